@@ -17,6 +17,7 @@ habitatConfig({
     oauth_client_id: "{{cfg.oauth.client_id}}",
     oauth_provider: "{{cfg.oauth.provider}}",
     oauth_redirect_url: "{{cfg.oauth.redirect_url}}",
+    oauth_scopes: {{#if bind.http.first.cfg.oauth_scopes}}{{toJson bind.http.first.cfg.oauth_scopes}}{{else}}[]{{/if}},
     oauth_signup_url: "{{cfg.oauth.signup_url}}",
     source_code_url: "{{cfg.source_code_url}}",
     status_url: "{{cfg.status_url}}",
@@ -10,6 +10,7 @@ pkg_build_deps=(core/protobuf-cpp core/protobuf-rust core/coreutils core/cacerts
 core/rust core/gcc core/git core/pkg-config)
 pkg_exports=(
   [port]=http.port
+  [oauth_scopes]=oauth.scopes
 )
 pkg_exposes=(port)
 pkg_binds=(
@@ -20,6 +20,7 @@ use std::{env,
           io,
           net::{IpAddr,
                 Ipv4Addr,
+                Ipv6Addr,
                 SocketAddr,
                 ToSocketAddrs},
           option::IntoIter,
@@ -31,7 +32,8 @@ use artifactory_client::config::ArtifactoryCfg;
 use github_api_client::config::GitHubCfg;
 use oauth_client::config::OAuth2Cfg;
 
-use crate::{db::config::DataStoreCfg,
+use crate::{bldr_core::http_client::HttpClientCfg,
+            db::config::DataStoreCfg,
             hab_core::{self,
                        config::ConfigFile,
                        package::target::{self,
@@ -52,30 +54,43 @@ pub trait GatewayCfg {
 #[derive(Clone, Debug, Deserialize)]
 #[serde(default)]
 pub struct Config {
-    pub api:         ApiCfg,
-    pub artifactory: ArtifactoryCfg,
-    pub github:      GitHubCfg,
-    pub http:        HttpCfg,
-    pub oauth:       OAuth2Cfg,
-    pub s3:          S3Cfg,
-    pub ui:          UiCfg,
-    pub memcache:    MemcacheCfg,
-    pub jobsrv:      JobsrvCfg,
-    pub datastore:   DataStoreCfg,
+    pub api:            ApiCfg,
+    pub artifactory:    ArtifactoryCfg,
+    pub github:         GitHubCfg,
+    pub http:           HttpCfg,
+    /// Timeouts, proxies and additional trust roots of outbound HTTP calls
+    pub http_client:    HttpClientCfg,
+    pub oauth:          OAuth2Cfg,
+    pub s3:             S3Cfg,
+    pub ui:             UiCfg,
+    pub memcache:       MemcacheCfg,
+    pub jobsrv:         JobsrvCfg,
+    pub datastore:      DataStoreCfg,
+    pub upstream:       UpstreamCfg,
+    /// Uploads kept while the artifact store is unavailable
+    pub upload_spool:   UploadSpoolCfg,
+    /// Downloaded artifacts kept on disk in front of the artifact store
+    pub download_cache: DownloadCacheCfg,
+    pub channels:       ChannelsCfg,
 }
 
 impl Default for Config {
     fn default() -> Self {
-        Config { api:         ApiCfg::default(),
-                 artifactory: ArtifactoryCfg::default(),
-                 github:      GitHubCfg::default(),
-                 http:        HttpCfg::default(),
-                 oauth:       OAuth2Cfg::default(),
-                 s3:          S3Cfg::default(),
-                 ui:          UiCfg::default(),
-                 memcache:    MemcacheCfg::default(),
-                 jobsrv:      JobsrvCfg::default(),
-                 datastore:   DataStoreCfg::default(), }
+        Config { api:            ApiCfg::default(),
+                 artifactory:    ArtifactoryCfg::default(),
+                 github:         GitHubCfg::default(),
+                 http:           HttpCfg::default(),
+                 http_client:    HttpClientCfg::default(),
+                 oauth:          OAuth2Cfg::default(),
+                 s3:             S3Cfg::default(),
+                 ui:             UiCfg::default(),
+                 memcache:       MemcacheCfg::default(),
+                 jobsrv:         JobsrvCfg::default(),
+                 datastore:      DataStoreCfg::default(),
+                 upstream:       UpstreamCfg::default(),
+                 upload_spool:   UploadSpoolCfg::default(),
+                 download_cache: DownloadCacheCfg::default(),
+                 channels:       ChannelsCfg::default(), }
     }
 }
 
@@ -129,26 +144,140 @@ impl Default for S3Cfg {
 #[derive(Debug, Clone, Deserialize)]
 #[serde(default)]
 pub struct ApiCfg {
-    pub data_path:        PathBuf,
-    pub log_path:         PathBuf,
-    pub key_path:         PathBuf,
-    pub targets:          Vec<PackageTarget>,
-    pub build_targets:    Vec<PackageTarget>,
-    pub features_enabled: String,
-    pub build_on_upload:  bool,
+    pub data_path:                 PathBuf,
+    pub log_path:                  PathBuf,
+    pub key_path:                  PathBuf,
+    pub targets:                   Vec<PackageTarget>,
+    pub build_targets:             Vec<PackageTarget>,
+    pub features_enabled:          String,
+    pub build_on_upload:           bool,
+    /// Maximum lifetime of a personal access token in days, 0 for unlimited
+    pub token_max_lifetime_days:   u32,
+    /// Reject personal access tokens requested without an expiration
+    pub token_require_expiration:  bool,
+    /// Days before expiry that token owners are notified, 0 to disable
+    pub token_expiry_notice_days:  u32,
+    /// Days an origin invitation stays open, 0 for no expiry
+    pub invitation_lifetime_days:  u32,
+    /// Page that accepts the token from an emailed invitation link
+    pub invitation_url:            String,
+    /// URL the API is reached at, which feed entries link to. Links are relative without one.
+    pub public_url:                String,
+    /// Seconds between refreshes of the public status summary
+    pub status_check_interval_sec: u64,
+    /// Consecutive failed checks before a subsystem is reported degraded
+    pub status_failure_threshold:  u32,
+    /// Origins ranked first in package search for anonymous users and accounts without a
+    /// preference of their own, highest priority first
+    pub search_origins:            Vec<String>,
+    /// Services allowed to introspect personal access tokens
+    pub introspection_clients:     Vec<IntrospectionClientCfg>,
+    /// Token introspection requests each service may make per minute
+    pub introspection_rate_limit:  u32,
+    /// Highest concurrency cap a job group may ask for, 0 for no ceiling
+    pub max_group_concurrency:     u32,
+    /// Days a rotated origin signing key is still accepted on uploaded packages
+    pub key_rotation_overlap_days: u32,
+    /// Days account security events are kept, 0 to keep them forever
+    pub security_retention_days:   u32,
+    /// Days after an account's first token use during which new addresses are learned
+    /// without a security event
+    pub known_ip_learning_days:    u32,
+    /// Reverse proxies whose `X-Forwarded-For` header is believed. Requests from anywhere
+    /// else are taken to come from their peer address.
+    pub trusted_proxies:           Vec<IpAddr>,
+    /// Token that lets an upload through its origin's upload policy in an emergency, empty
+    /// to allow no override
+    pub policy_override_token:     String,
+    /// Seconds a GitHub hook delivery is remembered, a delivery sent again within them is
+    /// ignored. 0 to handle every delivery.
+    pub github_delivery_ttl_secs:  u64,
+    /// Directory of the keys origins may encrypt their artifacts under, as `<key_id>.key`
+    /// files of 64 hex digits
+    pub artifact_key_dir:          PathBuf,
+    /// Seconds a request may take unless the client asks for less or more in the
+    /// `X-Request-Timeout` header. Bounds the jobsrv RPCs made for the request.
+    pub request_timeout_secs:      u64,
+    /// Most seconds a client may ask for in the `X-Request-Timeout` header
+    pub max_request_timeout_secs:  u64,
+    /// Accept install reports from hab clients on `/v1/telemetry/install`
+    pub telemetry_enabled:         bool,
+    /// Install reports each address may send per minute, 0 for no limit
+    pub telemetry_rate_limit:      u32,
+    /// Largest install report accepted, in bytes
+    pub telemetry_max_bytes:       usize,
+    /// Hours an account data export can be downloaded before it is deleted
+    pub export_retention_hours:    u32,
+    /// Seconds the download link of an account data export is valid for
+    pub export_link_secs:          u64,
+    /// Most inventory entries a mirror may send in one channel sync request, larger
+    /// inventories are sent in chunks
+    pub sync_diff_max_entries:     usize,
+    /// Channel sync requests each account, or each address when signed out, may make per
+    /// minute, 0 for no limit
+    pub sync_diff_rate_limit:      u32,
+    /// The origins the members of each OAuth provider group belong to, kept in step at sign in.
+    /// Origin membership is left to invitations when it is empty.
+    pub group_origins:             Vec<GroupOriginsCfg>,
+}
+
+/// The origins the members of a provider group are made members of
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct GroupOriginsCfg {
+    pub group:   String,
+    pub origins: Vec<String>,
+}
+
+/// A service credential accepted by the token introspection endpoint
+#[derive(Debug, Clone, Deserialize)]
+pub struct IntrospectionClientCfg {
+    /// Identifies the service in audit logs
+    pub name:   String,
+    pub secret: String,
 }
 
 impl Default for ApiCfg {
     fn default() -> Self {
-        ApiCfg { data_path:        PathBuf::from("/hab/svc/builder-api/data"),
-                 log_path:         env::temp_dir(),
-                 key_path:         PathBuf::from("/hab/svc/builder-api/files"),
-                 targets:          vec![target::X86_64_LINUX,
-                                        target::X86_64_LINUX_KERNEL2,
-                                        target::X86_64_WINDOWS,],
-                 build_targets:    vec![target::X86_64_LINUX, target::X86_64_WINDOWS],
-                 features_enabled: String::from("jobsrv"),
-                 build_on_upload:  true, }
+        ApiCfg { data_path:                 PathBuf::from("/hab/svc/builder-api/data"),
+                 log_path:                  env::temp_dir(),
+                 key_path:                  PathBuf::from("/hab/svc/builder-api/files"),
+                 targets:                   vec![target::X86_64_LINUX,
+                                                 target::X86_64_LINUX_KERNEL2,
+                                                 target::X86_64_WINDOWS,
+                                                 target::AARCH64_LINUX,],
+                 build_targets:             vec![target::X86_64_LINUX, target::X86_64_WINDOWS],
+                 features_enabled:          String::from("jobsrv"),
+                 build_on_upload:           true,
+                 token_max_lifetime_days:   0,
+                 token_require_expiration:  false,
+                 token_expiry_notice_days:  7,
+                 invitation_lifetime_days:  14,
+                 invitation_url:            String::from("https://bldr.habitat.sh/#/invitations"),
+                 public_url:                String::new(),
+                 status_check_interval_sec: 30,
+                 status_failure_threshold:  3,
+                 search_origins:            vec![],
+                 introspection_clients:     vec![],
+                 introspection_rate_limit:  600,
+                 max_group_concurrency:     100,
+                 key_rotation_overlap_days: 7,
+                 security_retention_days:   365,
+                 known_ip_learning_days:    7,
+                 trusted_proxies:           vec![IpAddr::V4(Ipv4Addr::LOCALHOST),
+                                                 IpAddr::V6(Ipv6Addr::LOCALHOST)],
+                 policy_override_token:     String::new(),
+                 github_delivery_ttl_secs:  86_400,
+                 artifact_key_dir:          PathBuf::from("/hab/svc/builder-api/artifact-keys"),
+                 request_timeout_secs:      60,
+                 max_request_timeout_secs:  300,
+                 telemetry_enabled:         true,
+                 telemetry_rate_limit:      60,
+                 telemetry_max_bytes:       1024,
+                 export_retention_hours:    72,
+                 export_link_secs:          900,
+                 sync_diff_max_entries:     1000,
+                 sync_diff_rate_limit:      60,
+                 group_origins:             vec![], }
     }
 }
 
@@ -251,6 +380,130 @@ impl fmt::Display for JobsrvCfg {
     }
 }
 
+/// Fetch-through of missing packages from another Builder
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UpstreamCfg {
+    /// Builder to fetch from, fetch-through is disabled when empty
+    pub url:                  String,
+    /// Origins whose missing packages are fetched
+    pub origins:              Vec<String>,
+    /// Channel whose head is fetched when a request names no release
+    pub channel:              String,
+    /// Seconds before an upstream request is abandoned
+    pub timeout_sec:          u64,
+    /// Seconds between refreshes of fetched channel heads, 0 to disable
+    pub refresh_interval_sec: u64,
+    /// Whether signing keys missing locally are imported from the upstream. When false, a
+    /// package is only fetched once the origin key that signed it has been uploaded here.
+    pub import_keys:          bool,
+    /// Whether requests without a session may trigger fetches
+    pub allow_anonymous:      bool,
+}
+
+impl Default for UpstreamCfg {
+    fn default() -> Self {
+        UpstreamCfg { url:                  String::new(),
+                      origins:              vec!["core".to_string()],
+                      channel:              String::from("stable"),
+                      timeout_sec:          60,
+                      refresh_interval_sec: 3600,
+                      import_keys:          false,
+                      allow_anonymous:      false, }
+    }
+}
+
+impl UpstreamCfg {
+    pub fn is_enabled(&self) -> bool { !self.url.is_empty() }
+
+    /// Whether missing packages in `origin` are fetched from the upstream
+    pub fn serves(&self, origin: &str) -> bool {
+        self.is_enabled() && self.origins.iter().any(|o| o == origin)
+    }
+
+    /// Whether a request that missed `origin` locally, with or without a session, fetches
+    /// from the upstream
+    pub fn fetches_for(&self, origin: &str, authenticated: bool) -> bool {
+        self.serves(origin) && (authenticated || self.allow_anonymous)
+    }
+}
+
+/// Spooling of uploads whose artifact store write failed, to be stored once the store is back
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UploadSpoolCfg {
+    pub enabled:             bool,
+    /// Directory of the spooled archives, it should survive a restart of the service
+    pub path:                PathBuf,
+    /// Uploads spooled at once, further ones are refused with a 503
+    pub max_entries:         usize,
+    /// Bytes of archives spooled at once, further uploads are refused with a 503
+    pub max_bytes:           u64,
+    /// Seconds before a spooled upload is stored again, doubling with each failure
+    pub retry_base_sec:      u64,
+    /// Seconds the delay between attempts grows to at most
+    pub retry_max_sec:       u64,
+    /// Attempts at an upload failing for another reason than the store being unavailable, after
+    /// which it is given up on
+    pub max_attempts:        u32,
+    /// Seconds between looks for spooled uploads due for another attempt
+    pub drain_interval_sec:  u64,
+    /// Seconds the status of a completed ingestion is kept
+    pub retain_complete_sec: u64,
+}
+
+impl Default for UploadSpoolCfg {
+    fn default() -> Self {
+        UploadSpoolCfg { enabled:             false,
+                         path:                PathBuf::from("/hab/svc/builder-api/data/spool"),
+                         max_entries:         1000,
+                         max_bytes:           10 * 1024 * 1024 * 1024,
+                         retry_base_sec:      30,
+                         retry_max_sec:       1800,
+                         max_attempts:        5,
+                         drain_interval_sec:  10,
+                         retain_complete_sec: 86_400, }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DownloadCacheCfg {
+    pub enabled:   bool,
+    /// Directory of the cached artifacts, named by their checksums
+    pub path:      PathBuf,
+    /// Bytes of artifacts cached at once, the least recently downloaded are evicted beyond it
+    pub max_bytes: u64,
+}
+
+impl Default for DownloadCacheCfg {
+    fn default() -> Self {
+        DownloadCacheCfg { enabled:   false,
+                           path:      PathBuf::from("/hab/svc/builder-api/data/download-cache"),
+                           max_bytes: 20 * 1024 * 1024 * 1024, }
+    }
+}
+
+/// Lifetimes of ephemeral channels, which are deleted along with their contents once they expire
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ChannelsCfg {
+    /// Days an ephemeral channel lives for when it is created or renewed without a TTL
+    pub ephemeral_ttl_days:     u32,
+    /// Days an ephemeral channel lives for at most, longer TTLs are capped to it
+    pub ephemeral_max_ttl_days: u32,
+    /// Seconds between looks for ephemeral channels that expired
+    pub expiry_interval_sec:    u64,
+}
+
+impl Default for ChannelsCfg {
+    fn default() -> Self {
+        ChannelsCfg { ephemeral_ttl_days:     14,
+                      ephemeral_max_ttl_days: 90,
+                      expiry_interval_sec:    3600, }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -263,10 +516,45 @@ mod tests {
         data_path = "/hab/svc/hab-depot/data"
         log_path = "/hab/svc/hab-depot/var/log"
         key_path = "/hab/svc/hab-depot/files"
-        targets = ["x86_64-linux", "x86_64-linux-kernel2", "x86_64-windows"]
-        build_targets = ["x86_64-linux"]
+        targets = ["x86_64-linux", "x86_64-linux-kernel2", "x86_64-windows", "aarch64-linux"]
+        build_targets = ["x86_64-linux", "aarch64-linux"]
         features_enabled = "foo, bar"
         build_on_upload = false
+        token_max_lifetime_days = 90
+        token_require_expiration = true
+        token_expiry_notice_days = 14
+        invitation_lifetime_days = 30
+        invitation_url = "https://bldr.example.com/#/invitations"
+        public_url = "https://bldr.example.com"
+        status_check_interval_sec = 60
+        status_failure_threshold = 5
+        search_origins = ["core", "acme"]
+        introspection_rate_limit = 120
+        max_group_concurrency = 25
+        key_rotation_overlap_days = 3
+        security_retention_days = 30
+        known_ip_learning_days = 2
+        trusted_proxies = ["10.0.0.2"]
+        policy_override_token = "7d1e0c4a9b3f"
+        github_delivery_ttl_secs = 3600
+        artifact_key_dir = "/hab/svc/builder-api/files/keys"
+        request_timeout_secs = 20
+        max_request_timeout_secs = 120
+        telemetry_enabled = false
+        telemetry_rate_limit = 10
+        telemetry_max_bytes = 512
+        export_retention_hours = 24
+        export_link_secs = 300
+        sync_diff_max_entries = 500
+        sync_diff_rate_limit = 30
+
+        [[api.introspection_clients]]
+        name = "artifact-mirror"
+        secret = "5f9e6c0b8a7d4e21"
+
+        [[api.group_origins]]
+        group = "builder-core"
+        origins = ["core", "core-testing"]
 
         [http]
         listen = "0:0:0:0:0:0:0:1"
@@ -286,6 +574,11 @@ mod tests {
         [oauth]
         client_id = "0c2f738a7d0bd300de10"
         client_secret = "438223113eeb6e7edf2d2f91a232b72de72b9bdf"
+        connect_timeout_ms = 2000
+        https_proxy = "http://proxy.corp:3128"
+        no_proxy = ["internal.corp"]
+        id_claim = "user.id"
+        username_claim = "user.login"
 
         [s3]
         backend = "minio"
@@ -302,6 +595,10 @@ mod tests {
         [github]
         api_url = "https://api.github.com"
 
+        [[github.installation_secrets]]
+        installation_id = 56789
+        secret = "3b9a0e1c"
+
         [jobsrv]
         host = "1.2.3.4"
         port = 1234
@@ -315,6 +612,35 @@ mod tests {
         connection_timeout_sec = 4800
         connection_test = true
         pool_size = 1
+
+        [upstream]
+        url = "https://bldr.habitat.sh"
+        origins = ["core", "chef"]
+        channel = "stable"
+        timeout_sec = 30
+        refresh_interval_sec = 600
+        import_keys = true
+
+        [upload_spool]
+        enabled = true
+        path = "/hab/svc/builder-api/data/spool"
+        max_entries = 50
+        max_bytes = 1073741824
+        retry_base_sec = 15
+        max_attempts = 3
+
+        [download_cache]
+        enabled = true
+        path = "/var/cache/builder-api"
+        max_bytes = 5368709120
+
+        [channels]
+        ephemeral_ttl_days = 7
+        ephemeral_max_ttl_days = 30
+
+        [http_client]
+        https_proxy = "http://proxy.corp:3128"
+        connect_timeout_sec = 5
         "#;
 
         let config = Config::from_raw(&content).unwrap();
@@ -325,16 +651,57 @@ mod tests {
         assert_eq!(config.api.key_path,
                    PathBuf::from("/hab/svc/hab-depot/files"));
 
-        assert_eq!(config.api.targets.len(), 3);
+        assert_eq!(config.api.targets.len(), 4);
         assert_eq!(config.api.targets[0], target::X86_64_LINUX);
         assert_eq!(config.api.targets[1], target::X86_64_LINUX_KERNEL2);
         assert_eq!(config.api.targets[2], target::X86_64_WINDOWS);
+        assert_eq!(config.api.targets[3], target::AARCH64_LINUX);
 
-        assert_eq!(config.api.build_targets.len(), 1);
+        assert_eq!(config.api.build_targets.len(), 2);
         assert_eq!(config.api.build_targets[0], target::X86_64_LINUX);
+        assert_eq!(config.api.build_targets[1], target::AARCH64_LINUX);
 
         assert_eq!(&config.api.features_enabled, "foo, bar");
         assert_eq!(config.api.build_on_upload, false);
+        assert_eq!(config.api.token_max_lifetime_days, 90);
+        assert_eq!(config.api.token_require_expiration, true);
+        assert_eq!(config.api.token_expiry_notice_days, 14);
+        assert_eq!(config.api.invitation_lifetime_days, 30);
+        assert_eq!(&config.api.invitation_url,
+                   "https://bldr.example.com/#/invitations");
+        assert_eq!(&config.api.public_url, "https://bldr.example.com");
+        assert_eq!(config.api.status_check_interval_sec, 60);
+        assert_eq!(config.api.status_failure_threshold, 5);
+        assert_eq!(config.api.search_origins,
+                   vec!["core".to_string(), "acme".to_string()]);
+        assert_eq!(config.api.introspection_rate_limit, 120);
+        assert_eq!(config.api.max_group_concurrency, 25);
+        assert_eq!(config.api.key_rotation_overlap_days, 3);
+        assert_eq!(config.api.security_retention_days, 30);
+        assert_eq!(config.api.known_ip_learning_days, 2);
+        assert_eq!(config.api.trusted_proxies,
+                   vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))]);
+        assert_eq!(config.api.policy_override_token, "7d1e0c4a9b3f");
+        assert_eq!(config.api.github_delivery_ttl_secs, 3600);
+        assert_eq!(config.api.artifact_key_dir,
+                   PathBuf::from("/hab/svc/builder-api/files/keys"));
+        assert_eq!(config.api.request_timeout_secs, 20);
+        assert_eq!(config.api.max_request_timeout_secs, 120);
+        assert_eq!(config.api.telemetry_enabled, false);
+        assert_eq!(config.api.telemetry_rate_limit, 10);
+        assert_eq!(config.api.telemetry_max_bytes, 512);
+        assert_eq!(config.api.export_retention_hours, 24);
+        assert_eq!(config.api.export_link_secs, 300);
+        assert_eq!(config.api.sync_diff_max_entries, 500);
+        assert_eq!(config.api.sync_diff_rate_limit, 30);
+        assert_eq!(config.api.introspection_clients.len(), 1);
+        assert_eq!(config.api.introspection_clients[0].name, "artifact-mirror");
+        assert_eq!(config.api.introspection_clients[0].secret,
+                   "5f9e6c0b8a7d4e21");
+        assert_eq!(config.api.group_origins,
+                   vec![GroupOriginsCfg { group:   "builder-core".to_string(),
+                                          origins: vec!["core".to_string(),
+                                                        "core-testing".to_string()], }]);
 
         assert_eq!(&format!("{}", config.http.listen), "::1");
 
@@ -351,8 +718,18 @@ mod tests {
         assert_eq!(config.oauth.client_id, "0c2f738a7d0bd300de10");
         assert_eq!(config.oauth.client_secret,
                    "438223113eeb6e7edf2d2f91a232b72de72b9bdf");
+        assert_eq!(config.oauth.request_timeout_ms, None);
+        assert_eq!(config.oauth.connect_timeout_ms, Some(2000));
+        assert_eq!(config.oauth.http_proxy, None);
+        assert_eq!(config.oauth.https_proxy,
+                   Some("http://proxy.corp:3128".to_string()));
+        assert_eq!(config.oauth.no_proxy, vec!["internal.corp".to_string()]);
+        assert_eq!(config.oauth.id_claim, Some("user.id".to_string()));
+        assert_eq!(config.oauth.username_claim, Some("user.login".to_string()));
 
         assert_eq!(config.github.api_url, "https://api.github.com");
+        assert_eq!(config.github.installation_secrets[0].installation_id, 56789);
+        assert_eq!(config.github.installation_secrets[0].secret, "3b9a0e1c");
 
         assert_eq!(config.ui.root, Some("/some/path".to_string()));
 
@@ -374,6 +751,43 @@ mod tests {
         assert_eq!(config.datastore.connection_timeout_sec, 4800);
         assert_eq!(config.datastore.connection_test, true);
         assert_eq!(config.datastore.pool_size, 1);
+
+        assert_eq!(config.upstream.url, "https://bldr.habitat.sh");
+        assert_eq!(config.upstream.origins,
+                   vec!["core".to_string(), "chef".to_string()]);
+        assert_eq!(config.upstream.channel, "stable");
+        assert_eq!(config.upstream.timeout_sec, 30);
+        assert_eq!(config.upstream.refresh_interval_sec, 600);
+        assert!(config.upstream.import_keys);
+        assert!(!config.upstream.allow_anonymous);
+        assert!(config.upstream.serves("chef"));
+        assert!(!config.upstream.serves("acme"));
+        assert!(config.upstream.fetches_for("chef", true));
+        assert!(!config.upstream.fetches_for("chef", false));
+        assert!(!config.upstream.fetches_for("acme", true));
+
+        assert!(config.upload_spool.enabled);
+        assert_eq!(config.upload_spool.path,
+                   PathBuf::from("/hab/svc/builder-api/data/spool"));
+        assert_eq!(config.upload_spool.max_entries, 50);
+        assert_eq!(config.upload_spool.max_bytes, 1_073_741_824);
+        assert_eq!(config.upload_spool.retry_base_sec, 15);
+        assert_eq!(config.upload_spool.retry_max_sec, 1800);
+        assert_eq!(config.upload_spool.max_attempts, 3);
+
+        assert!(config.download_cache.enabled);
+        assert_eq!(config.download_cache.path,
+                   PathBuf::from("/var/cache/builder-api"));
+        assert_eq!(config.download_cache.max_bytes, 5_368_709_120);
+
+        assert_eq!(config.channels.ephemeral_ttl_days, 7);
+        assert_eq!(config.channels.ephemeral_max_ttl_days, 30);
+        assert_eq!(config.channels.expiry_interval_sec, 3600);
+
+        assert_eq!(config.http_client.https_proxy,
+                   Some("http://proxy.corp:3128".to_string()));
+        assert_eq!(config.http_client.connect_timeout_sec, 5);
+        assert_eq!(config.http_client.timeout_sec, 60);
     }
 
     #[test]
@@ -385,5 +799,10 @@ mod tests {
 
         let config = Config::from_raw(&content).unwrap();
         assert_eq!(config.http.port, 9000);
+        assert!(!config.upstream.serves("core"));
+        assert!(!config.upload_spool.enabled);
+        assert!(!config.download_cache.enabled);
+        assert_eq!(config.channels.ephemeral_ttl_days, 14);
+        assert_eq!(config.channels.ephemeral_max_ttl_days, 90);
     }
 }
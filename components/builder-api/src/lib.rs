@@ -36,3 +36,5 @@ use rusoto_core as rusoto;
 
 pub mod config;
 pub mod server;
+
+pub const VERSION: &str = include_str!(concat!(env!("OUT_DIR"), "/VERSION"));
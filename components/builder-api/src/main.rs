@@ -39,9 +39,21 @@ fn main() {
     env_logger::init();
     let matches = app().get_matches();
     debug!("CLI matches: {:?}", matches);
-    match server::run(config_from_args(&matches)) {
-        Ok(_) => std::process::exit(0),
-        Err(e) => exit_with(e, 1),
+    let config = config_from_args(&matches);
+    match matches.subcommand() {
+        ("sunset", Some(args)) => {
+            let days = value_t!(args, "days", u32).unwrap_or_else(|e| e.exit());
+            match server::sunset_tokens(&config, days) {
+                Ok(count) => println!("Assigned an expiration to {} access tokens", count),
+                Err(e) => exit_with(e, 1),
+            }
+        }
+        _ => {
+            match server::run(config) {
+                Ok(_) => std::process::exit(0),
+                Err(e) => exit_with(e, 1),
+            }
+        }
     }
 }
 
@@ -59,6 +71,13 @@ fn app<'a, 'b>() -> clap::App<'a, 'b> {
                 "Filepath to store packages, keys, and other artifacts.")
             (@arg port: --port +takes_value "Listen port. [default: 9636]")
         )
+        (@subcommand sunset =>
+            (about: "Assign an expiration to personal access tokens issued without one")
+            (@arg config: -c --config +takes_value
+                "Filepath to configuration file. [default: /hab/svc/builder-api/config/config.toml]")
+            (@arg days: --days +takes_value +required
+                "Number of days from now until the tokens expire")
+        )
     )
 }
 
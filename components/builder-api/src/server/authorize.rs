@@ -76,6 +76,17 @@ pub fn authorize_session(req: &HttpRequest,
     Ok(session)
 }
 
+pub fn authorize_admin(req: &HttpRequest) -> Result<originsrv::Session> {
+    let session = authorize_session(req, None)?;
+    let flags = FeatureFlags::from_bits(session.get_flags()).unwrap(); // unwrap Ok
+
+    if flags.contains(FeatureFlags::ADMIN) {
+        Ok(session)
+    } else {
+        Err(Error::Authorization)
+    }
+}
+
 pub fn check_origin_owner(req: &HttpRequest, account_id: u64, origin: &str) -> Result<bool> {
     let conn = req_state(req).db.get_conn().map_err(Error::DbError)?;
 
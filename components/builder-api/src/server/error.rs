@@ -20,7 +20,9 @@ use std::{error,
           string};
 
 use actix_web::{self,
-                http::StatusCode,
+                body::Body,
+                http::{self,
+                       StatusCode},
                 HttpResponse,
                 ResponseError};
 use artifactory_client::error::ArtifactoryError;
@@ -34,11 +36,14 @@ use rusoto_s3;
 use serde_json;
 
 use crate::{bldr_core,
-            db,
-            hab_core};
+            db::{self,
+                 models::channel::ChannelPackagePin},
+            hab_core,
+            server::framework::headers};
 
 #[derive(Debug)]
 pub enum Error {
+    ArtifactEncryption(String),
     Artifactory(ArtifactoryError),
     Authentication,
     Authorization,
@@ -59,14 +64,20 @@ pub enum Error {
     MultipartUploadReq(RusotoError<rusoto_s3::CreateMultipartUploadError>),
     NotFound,
     OAuth(OAuthError),
+    ObjectDelete(RusotoError<rusoto_s3::DeleteObjectError>),
+    ObjectUpload(RusotoError<rusoto_s3::PutObjectError>),
+    PackagePinned(ChannelPackagePin),
     PackageDownload(RusotoError<rusoto_s3::GetObjectError>),
     PackageUpload(RusotoError<rusoto_s3::PutObjectError>),
     PartialUpload(RusotoError<rusoto_s3::UploadPartError>),
     PayloadError(actix_web::error::PayloadError),
     Protobuf(protobuf::ProtobufError),
     SerdeJson(serde_json::Error),
+    SpoolFull,
     System,
+    TokenExpired,
     Unprocessable,
+    Upstream(String),
     Utf8(string::FromUtf8Error),
 }
 
@@ -75,6 +86,7 @@ pub type Result<T> = result::Result<T, Error>;
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let msg = match *self {
+            Error::ArtifactEncryption(ref e) => format!("Artifact encryption failed: {}", e),
             Error::Artifactory(ref e) => format!("{}", e),
             Error::Authentication => "User is not authenticated".to_string(),
             Error::Authorization => "User is not authorized to perform operation".to_string(),
@@ -95,14 +107,23 @@ impl fmt::Display for Error {
             Error::MultipartUploadReq(ref e) => format!("{}", e),
             Error::NotFound => "Entity not found".to_string(),
             Error::OAuth(ref e) => format!("{}", e),
+            Error::ObjectDelete(ref e) => format!("{}", e),
+            Error::ObjectUpload(ref e) => format!("{}", e),
+            Error::PackagePinned(ref pin) => {
+                format!("{} is pinned in {} by {}",
+                        pin.ident, pin.channel, pin.pinned_by_name)
+            }
             Error::PackageDownload(ref e) => format!("{}", e),
             Error::PackageUpload(ref e) => format!("{}", e),
             Error::PartialUpload(ref e) => format!("{}", e),
             Error::PayloadError(ref e) => format!("{}", e),
             Error::Protobuf(ref e) => format!("{}", e),
             Error::SerdeJson(ref e) => format!("{}", e),
+            Error::SpoolFull => "The upload spool is full".to_string(),
             Error::System => "Internal error".to_string(),
+            Error::TokenExpired => "Access token has expired".to_string(),
             Error::Unprocessable => "Unprocessable entity".to_string(),
+            Error::Upstream(ref e) => format!("Upstream fetch failed: {}", e),
             Error::Utf8(ref e) => format!("{}", e),
         };
         write!(f, "{}", msg)
@@ -112,6 +133,7 @@ impl fmt::Display for Error {
 impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
+            Error::ArtifactEncryption(_) => "Artifact encryption failed",
             Error::Artifactory(ref err) => err.description(),
             Error::Authentication => "User is not authenticated",
             Error::Authorization => "User is not authorized to perform operation",
@@ -132,14 +154,20 @@ impl error::Error for Error {
             Error::MultipartUploadReq(ref err) => err.description(),
             Error::NotFound => "Entity not found",
             Error::OAuth(ref err) => err.description(),
+            Error::ObjectDelete(ref err) => err.description(),
+            Error::ObjectUpload(ref err) => err.description(),
+            Error::PackagePinned(_) => "Package is pinned in the channel",
             Error::PackageDownload(ref err) => err.description(),
             Error::PackageUpload(ref err) => err.description(),
             Error::PartialUpload(ref err) => err.description(),
             Error::PayloadError(_) => "Http request stream error",
             Error::Protobuf(ref err) => err.description(),
             Error::SerdeJson(ref err) => err.description(),
+            Error::SpoolFull => "The upload spool is full",
             Error::System => "Internal error",
+            Error::TokenExpired => "Access token has expired",
             Error::Unprocessable => "Unprocessable entity",
+            Error::Upstream(_) => "Upstream fetch failed",
             Error::Utf8(ref err) => err.description(),
         }
     }
@@ -148,6 +176,7 @@ impl error::Error for Error {
 impl ResponseError for Error {
     fn error_response(&self) -> HttpResponse {
         match self {
+            Error::ArtifactEncryption(_) => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
             Error::Artifactory(ref e) => HttpResponse::new(artifactory_err_to_http(&e)),
             Error::Authentication => HttpResponse::new(StatusCode::UNAUTHORIZED),
             Error::Authorization => HttpResponse::new(StatusCode::FORBIDDEN),
@@ -155,10 +184,16 @@ impl ResponseError for Error {
             Error::Conflict => HttpResponse::new(StatusCode::CONFLICT),
             Error::Github(_) => HttpResponse::new(StatusCode::FORBIDDEN),
             Error::NotFound => HttpResponse::new(StatusCode::NOT_FOUND),
+            Error::OAuth(OAuthError::Timeout(_)) => HttpResponse::new(StatusCode::GATEWAY_TIMEOUT),
             Error::OAuth(_) => HttpResponse::new(StatusCode::UNAUTHORIZED),
+            Error::PackagePinned(ref pin) => HttpResponse::Conflict().json(pin),
+            Error::BuilderCore(bldr_core::Error::DeadlineExceeded) => deadline_exceeded_response(),
             Error::DieselError(ref e) => HttpResponse::new(diesel_err_to_http(&e)),
+            Error::SpoolFull => spool_full_response(),
             Error::System => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
+            Error::TokenExpired => token_expired_response(),
             Error::Unprocessable => HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY),
+            Error::Upstream(_) => upstream_response(self),
 
             // Default
             _ => HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY),
@@ -169,6 +204,7 @@ impl ResponseError for Error {
 impl Into<HttpResponse> for Error {
     fn into(self) -> HttpResponse {
         match self {
+            Error::ArtifactEncryption(_) => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
             Error::Artifactory(ref e) => HttpResponse::new(artifactory_err_to_http(&e)),
             Error::Authentication => HttpResponse::new(StatusCode::UNAUTHORIZED),
             Error::Authorization => HttpResponse::new(StatusCode::FORBIDDEN),
@@ -176,11 +212,17 @@ impl Into<HttpResponse> for Error {
             Error::Conflict => HttpResponse::new(StatusCode::CONFLICT),
             Error::Github(_) => HttpResponse::new(StatusCode::FORBIDDEN),
             Error::NotFound => HttpResponse::new(StatusCode::NOT_FOUND),
+            Error::OAuth(OAuthError::Timeout(_)) => HttpResponse::new(StatusCode::GATEWAY_TIMEOUT),
             Error::OAuth(_) => HttpResponse::new(StatusCode::UNAUTHORIZED),
+            Error::PackagePinned(ref pin) => HttpResponse::Conflict().json(pin),
+            Error::BuilderCore(bldr_core::Error::DeadlineExceeded) => deadline_exceeded_response(),
             Error::BuilderCore(ref e) => HttpResponse::new(bldr_core_err_to_http(e)),
             Error::DieselError(ref e) => HttpResponse::new(diesel_err_to_http(e)),
+            Error::SpoolFull => spool_full_response(),
             Error::System => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
+            Error::TokenExpired => token_expired_response(),
             Error::Unprocessable => HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY),
+            Error::Upstream(_) => upstream_response(&self),
 
             // Default
             _ => HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY),
@@ -188,6 +230,38 @@ impl Into<HttpResponse> for Error {
     }
 }
 
+// Expired tokens are distinguished from revoked or invalid ones so that
+// clients know to request a new token rather than re-authenticate.
+fn token_expired_response() -> HttpResponse {
+    HttpResponse::Unauthorized().header(http::header::WWW_AUTHENTICATE,
+                                        headers::BEARER_TOKEN_EXPIRED)
+                                .finish()
+}
+
+// The request ran out of time waiting on another service. The body tells clients it is worth
+// retrying with a longer X-Request-Timeout.
+fn deadline_exceeded_response() -> HttpResponse {
+    let body = json!({
+        "error": "deadline_exceeded",
+        "message": "The request was not answered within its deadline"
+    });
+    HttpResponse::GatewayTimeout().json(body)
+}
+
+// The artifact store is unavailable and no more uploads can be kept until it is back
+fn spool_full_response() -> HttpResponse {
+    let body = json!({
+        "error": "spool_full",
+        "message": "The artifact store is unavailable and the upload spool is full, retry later"
+    });
+    HttpResponse::ServiceUnavailable().json(body)
+}
+
+// A package that could not be fetched through is missing, the reason tells clients why
+fn upstream_response(err: &Error) -> HttpResponse {
+    HttpResponse::with_body(StatusCode::NOT_FOUND, Body::from_message(err.to_string()))
+}
+
 fn artifactory_err_to_http(err: &ArtifactoryError) -> StatusCode {
     match err {
         ArtifactoryError::ApiError(code, _) => StatusCode::from_u16(code.as_u16()).unwrap(),
@@ -266,3 +340,38 @@ impl From<serde_json::Error> for Error {
 impl From<string::FromUtf8Error> for Error {
     fn from(err: string::FromUtf8Error) -> Error { Error::Utf8(err) }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener,
+              thread,
+              time::Duration};
+
+    // The error of a request to a provider that takes the connection and never answers
+    fn timed_out() -> reqwest::Error {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/oauth/token", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let mut held = Vec::new();
+            for stream in listener.incoming() {
+                held.push(stream);
+            }
+        });
+        let client = reqwest::Client::builder().timeout(Duration::from_millis(50))
+                                               .build()
+                                               .unwrap();
+        client.post(&url).send().unwrap_err()
+    }
+
+    #[test]
+    fn provider_timeouts_are_answered_with_a_gateway_timeout() {
+        let err = Error::OAuth(OAuthError::http_client(timed_out()));
+        assert_eq!(err.error_response().status(), StatusCode::GATEWAY_TIMEOUT);
+        let resp: HttpResponse = err.into();
+        assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let err = Error::OAuth(OAuthError::NotSupported);
+        assert_eq!(err.error_response().status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,149 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Request deadlines. A request has `request_timeout_secs` to be answered, or what the client
+//! asks for in the `X-Request-Timeout` header, up to `max_request_timeout_secs`. The jobsrv RPCs
+//! made for a request carry the time it has left, so that neither service keeps working on an
+//! answer the client stopped waiting for.
+
+use std::time::{Duration,
+                Instant};
+
+use actix_web::{dev::{Body,
+                      Service,
+                      ServiceRequest,
+                      ServiceResponse},
+                Error,
+                HttpRequest,
+                HttpResponse};
+use futures::future::{ok,
+                      Either,
+                      Future};
+
+use crate::{config::ApiCfg,
+            server::{framework::headers,
+                     AppState}};
+
+/// Shortest timeout a client may ask for
+const MIN_REQUEST_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// When a request has to be answered by
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    pub fn after(timeout: Duration) -> Self { Deadline(Instant::now() + timeout) }
+
+    /// The time left before the deadline, zero once it has passed
+    pub fn remaining(&self) -> Duration {
+        let now = Instant::now();
+        if self.0 > now {
+            self.0 - now
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+}
+
+/// The time the request has left, none outside of a request handled by `deadline_middleware`
+pub fn remaining(req: &HttpRequest) -> Option<Duration> {
+    req.extensions().get::<Deadline>().map(Deadline::remaining)
+}
+
+/// Sets the deadline of each request
+pub fn deadline_middleware<S>(mut req: ServiceRequest,
+                              srv: &mut S)
+                              -> impl Future<Item = ServiceResponse<Body>, Error = Error>
+    where S: Service<Request = ServiceRequest, Response = ServiceResponse<Body>, Error = Error>
+{
+    let asked = req.headers()
+                   .get(headers::XREQUESTTIMEOUT)
+                   .map(|value| value.to_str().unwrap_or_default());
+    let timeout = {
+        let state = req.app_data::<AppState>().expect("request state");
+        request_timeout(asked, &state.config.api)
+    };
+
+    match timeout {
+        Ok(timeout) => {
+            req.head_mut()
+               .extensions_mut()
+               .insert(Deadline::after(timeout));
+            Either::A(srv.call(req))
+        }
+        Err(msg) => Either::B(ok(req.into_response(HttpResponse::BadRequest().body(msg)))),
+    }
+}
+
+// The timeout of a request asking for `asked` seconds, if it asks for any. What it asks for is
+// kept within bounds, only a value that is not a number of seconds is refused.
+fn request_timeout(asked: Option<&str>, cfg: &ApiCfg) -> Result<Duration, String> {
+    let asked = match asked {
+        Some(asked) => asked,
+        None => return Ok(Duration::from_secs(cfg.request_timeout_secs)),
+    };
+    let secs = match asked.trim().parse::<f64>() {
+        Ok(secs) if secs.is_finite() && secs >= 0.0 => secs,
+        _ => return Err(format!("Invalid X-Request-Timeout {:?}, expected seconds", asked)),
+    };
+
+    let max = Duration::from_secs(cfg.max_request_timeout_secs);
+    if secs >= max.as_secs() as f64 {
+        return Ok(max);
+    }
+    let millis = (secs * 1000.0) as u64;
+    Ok(Duration::from_millis(millis).max(MIN_REQUEST_TIMEOUT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg() -> ApiCfg {
+        ApiCfg { request_timeout_secs: 60,
+                 max_request_timeout_secs: 300,
+                 ..Default::default() }
+    }
+
+    #[test]
+    fn configured_timeout_without_header() {
+        assert_eq!(request_timeout(None, &cfg()), Ok(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn asked_timeout_is_kept_within_bounds() {
+        assert_eq!(request_timeout(Some("10"), &cfg()),
+                   Ok(Duration::from_secs(10)));
+        assert_eq!(request_timeout(Some(" 2.5 "), &cfg()),
+                   Ok(Duration::from_millis(2500)));
+        assert_eq!(request_timeout(Some("3600"), &cfg()),
+                   Ok(Duration::from_secs(300)));
+        assert_eq!(request_timeout(Some("0"), &cfg()), Ok(MIN_REQUEST_TIMEOUT));
+    }
+
+    #[test]
+    fn timeout_that_is_not_seconds_is_refused() {
+        for asked in &["", "ten", "-5", "inf", "NaN", "10s"] {
+            assert!(request_timeout(Some(asked), &cfg()).is_err(), "{}", asked);
+        }
+    }
+
+    #[test]
+    fn remaining_time_runs_out() {
+        let deadline = Deadline::after(Duration::from_secs(10));
+        assert!(deadline.remaining() > Duration::from_secs(9));
+        assert_eq!(Deadline::after(Duration::from_secs(0)).remaining(),
+                   Duration::from_secs(0));
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The `~` origin shortcut. On the package, project and job scheduling routes, a `~` in place
+//! of the origin stands for the caller's default origin, set on their profile. The path is
+//! rewritten before routing, so handlers and their authorization checks only ever see the
+//! actual origin.
+
+use actix_web::{dev::{Body,
+                      Service,
+                      ServiceRequest,
+                      ServiceResponse},
+                http::Uri,
+                Error,
+                HttpResponse};
+use futures::future::{ok,
+                      Either,
+                      Future};
+
+use crate::{db::models::account::Account,
+            protocol::originsrv};
+
+use crate::server::{error,
+                    AppState};
+
+/// The origin segment that resolves to the caller's default origin
+pub const DEFAULT_ORIGIN: &str = "~";
+
+// Routes whose origin segment, the one following the prefix, may be `~`
+const ROUTES: &[&str] = &["/v1/depot/pkgs/",
+                          "/v1/depot/pkgs/schedule/",
+                          "/v1/projects/"];
+
+const NO_DEFAULT_ORIGIN: &str = "No default origin is set, set one with the default_origin field \
+                                 of your profile (PATCH /v1/profile)";
+
+/// Resolves `~` origin segments. Has to run after `authentication_middleware`, as the default
+/// origin is the session's.
+pub fn default_origin_middleware<S>(mut req: ServiceRequest,
+                                    srv: &mut S)
+                                    -> impl Future<Item = ServiceResponse<Body>, Error = Error>
+    where S: Service<Request = ServiceRequest, Response = ServiceResponse<Body>, Error = Error>
+{
+    if origin_segment(req.path()).is_none() {
+        return Either::A(srv.call(req));
+    }
+
+    let account_id = req.extensions()
+                        .get::<originsrv::Session>()
+                        .map(originsrv::Session::get_id);
+    let account_id = match account_id {
+        Some(account_id) => account_id,
+        None => return Either::B(ok(req.into_response(HttpResponse::Unauthorized().finish()))),
+    };
+
+    let origin = match default_origin(account_id, &req.app_data().expect("request state")) {
+        Ok(Some(origin)) => origin,
+        Ok(None) => {
+            let resp = HttpResponse::BadRequest().body(NO_DEFAULT_ORIGIN);
+            return Either::B(ok(req.into_response(resp)));
+        }
+        Err(err) => {
+            debug!("Unable to resolve default origin of account {}, err={}",
+                   account_id, err);
+            let resp: HttpResponse = err.into();
+            return Either::B(ok(req.into_response(resp)));
+        }
+    };
+
+    let path = match req.uri().query() {
+        Some(query) => format!("{}?{}", with_origin(req.path(), &origin), query),
+        None => with_origin(req.path(), &origin),
+    };
+    // Origin names are valid in a path, the rewritten one parses whenever the original did
+    let uri = path.parse::<Uri>().expect("path with default origin");
+    req.match_info_mut().get_mut().update(&uri);
+    req.head_mut().uri = uri;
+    Either::A(srv.call(req))
+}
+
+fn default_origin(account_id: u64, state: &AppState) -> error::Result<Option<String>> {
+    let conn = state.db.get_conn().map_err(error::Error::DbError)?;
+    let account = Account::get_by_id(account_id as i64, &*conn).map_err(error::Error::DieselError)?;
+    Ok(account.default_origin)
+}
+
+// The byte range of the origin segment of `path` if it is `~` on one of the routes taking it
+fn origin_segment(path: &str) -> Option<(usize, usize)> {
+    ROUTES.iter().find_map(|prefix| {
+                     if !path.starts_with(prefix) {
+                         return None;
+                     }
+                     let start = prefix.len();
+                     let end = path[start..].find('/').map_or(path.len(), |i| start + i);
+                     if &path[start..end] == DEFAULT_ORIGIN {
+                         Some((start, end))
+                     } else {
+                         None
+                     }
+                 })
+}
+
+fn with_origin(path: &str, origin: &str) -> String {
+    match origin_segment(path) {
+        Some((start, end)) => format!("{}{}{}", &path[..start], origin, &path[end..]),
+        None => path.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tilde_origin_segment_is_replaced() {
+        assert_eq!(with_origin("/v1/depot/pkgs/~", "acme"),
+                   "/v1/depot/pkgs/acme");
+        assert_eq!(with_origin("/v1/depot/pkgs/~/nginx/latest", "acme"),
+                   "/v1/depot/pkgs/acme/nginx/latest");
+        assert_eq!(with_origin("/v1/depot/pkgs/schedule/~/nginx", "acme"),
+                   "/v1/depot/pkgs/schedule/acme/nginx");
+        assert_eq!(with_origin("/v1/projects/~/nginx/jobs", "acme"),
+                   "/v1/projects/acme/nginx/jobs");
+    }
+
+    #[test]
+    fn other_paths_are_left_alone() {
+        for path in &["/v1/depot/pkgs/core/nginx",
+                      "/v1/depot/pkgs/search/~",
+                      "/v1/depot/pkgs/~acme",
+                      "/v1/projects",
+                      "/v1/depot/origins/~",
+                      "/v1/depot/channels/~/stable/pkgs"]
+        {
+            assert_eq!(origin_segment(path), None, "{}", path);
+            assert_eq!(&with_origin(path, "acme"), path);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Response field selection for the `?fields=` query parameter.
+//!
+//! Selection happens after serialization so handlers keep building their usual response types
+//! and only declare which top level fields a client may ask for. A field may be followed by a
+//! single nested name, e.g. `projects.name,projects.state`, which selects from an object or from
+//! each object in an array. Unknown names are ignored, and a request that selects nothing known
+//! returns the unfiltered response.
+
+use std::collections::{BTreeSet,
+                       HashMap};
+
+use serde_json::{self,
+                 Map,
+                 Value};
+
+#[derive(Deserialize)]
+pub struct Fields {
+    #[serde(default)]
+    pub fields: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Selection {
+    // Top level fields returned whole
+    whole:  BTreeSet<String>,
+    // Top level fields returned with only the listed nested fields
+    nested: HashMap<String, BTreeSet<String>>,
+}
+
+impl Selection {
+    fn parse(fields: &str, filterable: &[&str]) -> Option<Self> {
+        let mut selection = Selection::default();
+
+        for field in fields.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            let mut parts = field.splitn(2, '.');
+            let top = parts.next().unwrap_or_default();
+            if !filterable.contains(&top) {
+                continue;
+            }
+            match parts.next() {
+                Some(inner) if !inner.is_empty() && !inner.contains('.') => {
+                    selection.nested
+                             .entry(top.to_string())
+                             .or_insert_with(BTreeSet::new)
+                             .insert(inner.to_string());
+                }
+                Some(_) => (),
+                None => {
+                    selection.whole.insert(top.to_string());
+                }
+            }
+        }
+
+        if selection.whole.is_empty() && selection.nested.is_empty() {
+            None
+        } else {
+            Some(selection)
+        }
+    }
+
+    fn apply(&self, value: Value) -> Value {
+        match value {
+            Value::Object(map) => {
+                let mut filtered = Map::new();
+                for (key, val) in map {
+                    if self.whole.contains(&key) {
+                        filtered.insert(key, val);
+                    } else if let Some(inner) = self.nested.get(&key) {
+                        filtered.insert(key, select_keys(val, inner));
+                    }
+                }
+                Value::Object(filtered)
+            }
+            Value::Array(items) => Value::Array(items.into_iter().map(|v| self.apply(v)).collect()),
+            other => other,
+        }
+    }
+}
+
+fn select_keys(value: Value, keys: &BTreeSet<String>) -> Value {
+    match value {
+        Value::Object(map) => {
+            Value::Object(map.into_iter().filter(|(k, _)| keys.contains(k)).collect())
+        }
+        Value::Array(items) => {
+            Value::Array(items.into_iter().map(|v| select_keys(v, keys)).collect())
+        }
+        other => other,
+    }
+}
+
+/// Filters a serialized response down to the requested fields. Objects are filtered directly and
+/// arrays have each of their objects filtered.
+pub fn filter_value(value: Value, fields: Option<&str>, filterable: &[&str]) -> Value {
+    match fields.and_then(|f| Selection::parse(f, filterable)) {
+        Some(selection) => selection.apply(value),
+        None => value,
+    }
+}
+
+/// Filters the `data` entries of a paginated result, leaving the range and count fields intact.
+pub fn filter_results(mut value: Value, fields: Option<&str>, filterable: &[&str]) -> Value {
+    if let Some(data) = value.get_mut("data") {
+        let filtered = filter_value(data.take(), fields, filterable);
+        *data = filtered;
+    }
+    value
+}
+
+/// Applies `filter` to an already serialized JSON body. Bodies which can't be parsed are
+/// returned untouched.
+pub fn filter_body<F>(body: String, fields: Option<&str>, filterable: &[&str], filter: F) -> String
+    where F: Fn(Value, Option<&str>, &[&str]) -> Value
+{
+    if fields.is_none() {
+        return body;
+    }
+    match serde_json::from_str(&body) {
+        Ok(value) => serde_json::to_string(&filter(value, fields, filterable)).unwrap_or(body),
+        Err(err) => {
+            debug!("Unable to parse response body for field filtering, err={}",
+                   err);
+            body
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const GROUP_FIELDS: &[&str] = &["id", "state", "projects", "created_at", "project_name"];
+
+    fn group() -> Value {
+        json!({
+            "id": "1234",
+            "state": "Complete",
+            "created_at": "2019-07-01T00:00:00Z",
+            "project_name": "core/redis",
+            "target": "x86_64-linux",
+            "projects": [
+                { "name": "core/redis", "ident": "core/redis/4.0.14/20190701000000",
+                  "state": "Success", "job_id": "5678", "target": "x86_64-linux" },
+                { "name": "core/nginx", "ident": "", "state": "Failure",
+                  "job_id": "5679", "target": "x86_64-linux" }
+            ]
+        })
+    }
+
+    #[test]
+    fn unfiltered_by_default() {
+        assert_eq!(filter_value(group(), None, GROUP_FIELDS), group());
+    }
+
+    #[test]
+    fn top_level_fields() {
+        assert_eq!(filter_value(group(), Some("id,state"), GROUP_FIELDS),
+                   json!({ "id": "1234", "state": "Complete" }));
+    }
+
+    #[test]
+    fn nested_fields_in_arrays() {
+        assert_eq!(filter_value(group(),
+                                Some("state,projects.name,projects.state"),
+                                GROUP_FIELDS),
+                   json!({
+                       "state": "Complete",
+                       "projects": [
+                           { "name": "core/redis", "state": "Success" },
+                           { "name": "core/nginx", "state": "Failure" }
+                       ]
+                   }));
+    }
+
+    #[test]
+    fn whole_field_wins_over_nested() {
+        let filtered = filter_value(group(), Some("projects.name,projects"), GROUP_FIELDS);
+        assert_eq!(filtered, json!({ "projects": group()["projects"] }));
+    }
+
+    #[test]
+    fn unknown_fields_are_ignored() {
+        assert_eq!(filter_value(group(), Some("id,bogus,projects.bogus,a.b.c"), GROUP_FIELDS),
+                   json!({ "id": "1234", "projects": [{}, {}] }));
+        // Not declared filterable by the handler
+        assert_eq!(filter_value(group(), Some("id,target"), GROUP_FIELDS),
+                   json!({ "id": "1234" }));
+        assert_eq!(filter_value(group(), Some("bogus,,"), GROUP_FIELDS),
+                   group());
+    }
+
+    #[test]
+    fn paginated_results_keep_envelope() {
+        let results = json!({
+            "range_start": 0,
+            "range_end": 1,
+            "total_count": 2,
+            "data": [
+                { "origin": "core", "name": "redis", "version": "4.0.14",
+                  "release": "20190701000000", "channels": ["stable"], "platforms": [] },
+                { "origin": "core", "name": "nginx", "version": "1.17.0",
+                  "release": "20190701000001", "channels": [], "platforms": [] }
+            ]
+        });
+
+        assert_eq!(filter_results(results, Some("name,version"), &["name", "version"]),
+                   json!({
+                       "range_start": 0,
+                       "range_end": 1,
+                       "total_count": 2,
+                       "data": [
+                           { "name": "redis", "version": "4.0.14" },
+                           { "name": "nginx", "version": "1.17.0" }
+                       ]
+                   }));
+    }
+
+    #[test]
+    fn serialized_bodies() {
+        let body = serde_json::to_string(&group()).unwrap();
+        assert_eq!(filter_body(body.clone(), None, GROUP_FIELDS, filter_value),
+                   body);
+        assert_eq!(filter_body("not json".to_string(),
+                               Some("id"),
+                               GROUP_FIELDS,
+                               filter_value),
+                   "not json");
+        assert_eq!(filter_body(body, Some("project_name"), GROUP_FIELDS, filter_value),
+                   r#"{"project_name":"core/redis"}"#);
+    }
+}
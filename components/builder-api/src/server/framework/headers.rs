@@ -12,12 +12,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use sha2::{Digest,
+           Sha512};
+
 pub const NO_CACHE: &str = "private, no-cache, no-store";
 pub const CACHE: &str = "public, max-age=31536000"; // ONE_YEAR_IN_SECONDS
 
 pub const APPLICATION_JSON: &str = "application/json";
 
 pub const XFILENAME: &str = "x-filename"; // must be lowercase
+pub const XPACKAGEDEPRECATED: &str = "x-package-deprecated"; // must be lowercase
+pub const XREQUESTTIMEOUT: &str = "x-request-timeout"; // must be lowercase
 
 pub fn cache(cache: bool) -> &'static str {
     if cache {
@@ -28,4 +33,123 @@ pub fn cache(cache: bool) -> &'static str {
 }
 
 pub const XGITHUBEVENT: &str = "X-GitHub-Event";
-pub const XHUBSIGNATURE: &str = "X-Hub-Signature";
+pub const XGITHUBDELIVERY: &str = "X-GitHub-Delivery";
+pub const XHUBSIGNATURE256: &str = "X-Hub-Signature-256";
+pub const XBUILDERSIGNATURE: &str = "X-Builder-Signature";
+pub const XBUILDERPOLICYOVERRIDE: &str = "X-Builder-Policy-Override";
+
+pub const BEARER_TOKEN_EXPIRED: &str =
+    "Bearer error=\"invalid_token\", error_description=\"token_expired\"";
+
+/// The part of a response body a `Range` header asks for
+#[derive(Debug, PartialEq)]
+pub enum ByteRange {
+    Full,
+    /// The first and last byte, inclusive
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// The single byte range `range` asks for of a body of `len` bytes. Headers asking for several
+/// ranges, or that don't parse, are answered with the full body as RFC 7233 allows.
+pub fn byte_range(range: Option<&str>, len: u64) -> ByteRange {
+    let spec = match range.map(str::trim) {
+        Some(r) if r.starts_with("bytes=") && !r.contains(',') => r["bytes=".len()..].trim(),
+        _ => return ByteRange::Full,
+    };
+    let mut bounds = spec.splitn(2, '-');
+    let (first, last) = match (bounds.next(), bounds.next()) {
+        (Some(first), Some(last)) => (first.trim(), last.trim()),
+        _ => return ByteRange::Full,
+    };
+
+    let (first, last) = if first.is_empty() {
+        // The last `last` bytes
+        match last.parse::<u64>() {
+            Ok(0) => return ByteRange::Unsatisfiable,
+            Ok(suffix) => (len.saturating_sub(suffix), len.saturating_sub(1)),
+            Err(_) => return ByteRange::Full,
+        }
+    } else {
+        let first = match first.parse::<u64>() {
+            Ok(first) => first,
+            Err(_) => return ByteRange::Full,
+        };
+        let last = if last.is_empty() {
+            len.saturating_sub(1)
+        } else {
+            match last.parse::<u64>() {
+                Ok(last) if last >= first => last.min(len.saturating_sub(1)),
+                _ => return ByteRange::Full,
+            }
+        };
+        (first, last)
+    };
+
+    if len == 0 || first >= len {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial(first, last)
+    }
+}
+
+/// A strong ETag of a response body
+pub fn etag<B: AsRef<[u8]>>(body: B) -> String {
+    let mut hasher = Sha512::new();
+    hasher.input(body);
+    let digest = format!("{:02x}", hasher.result());
+    format!("\"{}\"", &digest[..32])
+}
+
+/// Whether an `If-None-Match` header names `etag`, weakly compared as RFC 7232 has it for GET
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.split(',')
+                 .map(|tag| tag.trim().trim_start_matches("W/"))
+                 .any(|tag| tag == "*" || tag == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn etags_match_any_listed_tag() {
+        let tag = etag("{}");
+        assert!(etag_matches(&tag, &tag));
+        assert!(etag_matches(&format!("\"xyz\", W/{}", tag), &tag));
+        assert!(etag_matches("*", &tag));
+        assert!(!etag_matches("\"xyz\"", &tag));
+        assert_ne!(etag("{}"), etag("[]"));
+    }
+
+    #[test]
+    fn ranges_are_bounded_by_the_body() {
+        assert_eq!(byte_range(Some("bytes=0-99"), 1000),
+                   ByteRange::Partial(0, 99));
+        assert_eq!(byte_range(Some("bytes=900-"), 1000),
+                   ByteRange::Partial(900, 999));
+        assert_eq!(byte_range(Some("bytes=900-5000"), 1000),
+                   ByteRange::Partial(900, 999));
+        assert_eq!(byte_range(Some("bytes=-100"), 1000),
+                   ByteRange::Partial(900, 999));
+        assert_eq!(byte_range(Some("bytes=-5000"), 1000),
+                   ByteRange::Partial(0, 999));
+    }
+
+    #[test]
+    fn ranges_past_the_body_are_unsatisfiable() {
+        assert_eq!(byte_range(Some("bytes=1000-"), 1000),
+                   ByteRange::Unsatisfiable);
+        assert_eq!(byte_range(Some("bytes=-0"), 1000), ByteRange::Unsatisfiable);
+        assert_eq!(byte_range(Some("bytes=0-"), 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn other_ranges_get_the_full_body() {
+        assert_eq!(byte_range(None, 1000), ByteRange::Full);
+        assert_eq!(byte_range(Some("bytes=0-1,5-9"), 1000), ByteRange::Full);
+        assert_eq!(byte_range(Some("bytes=9-5"), 1000), ByteRange::Full);
+        assert_eq!(byte_range(Some("items=0-9"), 1000), ByteRange::Full);
+        assert_eq!(byte_range(Some("bytes=a-"), 1000), ByteRange::Full);
+    }
+}
@@ -0,0 +1,423 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! JSON request bodies with structured validation errors.
+//!
+//! `ValidatedJson<T>` takes the place of actix's `Json<T>` extractor. A body that is not JSON,
+//! or that does not match `T`, is rejected with a 422 listing the path of the offending field
+//! (e.g. `settings.labels[1]`), the type expected there and a snippet of the value received.
+//! Fields `T` does not know are ignored and reported back in `Warning` response headers, added
+//! by `warnings_middleware`. Endpoints that would rather reject them take `StrictJson<T>`.
+
+use std::{fmt,
+          ops::Deref};
+
+use actix_web::{dev::{Body,
+                      Payload,
+                      Service,
+                      ServiceRequest,
+                      ServiceResponse},
+                http::{self,
+                       HeaderValue},
+                Error,
+                FromRequest,
+                HttpRequest,
+                HttpResponse,
+                ResponseError};
+use bytes::Bytes;
+use futures::Future;
+use serde::de::DeserializeOwned;
+use serde_ignored;
+use serde_json::{self,
+                 Value};
+use serde_path_to_error::{self,
+                          Segment};
+
+// Longest received value echoed back in an error
+const MAX_SNIPPET_LEN: usize = 64;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FieldError {
+    pub path:     String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub received: Option<String>,
+    pub message:  String,
+}
+
+/// The 422 body for a request body that could not be used
+#[derive(Debug, Serialize)]
+pub struct JsonBodyError {
+    pub errors: Vec<FieldError>,
+}
+
+impl fmt::Display for JsonBodyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let errors: Vec<String> = self.errors
+                                      .iter()
+                                      .map(|e| format!("{}: {}", e.path, e.message))
+                                      .collect();
+        write!(f, "Invalid JSON body, {}", errors.join(", "))
+    }
+}
+
+impl ResponseError for JsonBodyError {
+    fn error_response(&self) -> HttpResponse { HttpResponse::UnprocessableEntity().json(self) }
+}
+
+// Unknown fields of the request body, kept on the request until the response is sent
+struct JsonWarnings(Vec<String>);
+
+/// A JSON request body. Unknown fields are ignored with a warning.
+pub struct ValidatedJson<T>(pub T);
+
+/// A JSON request body. Unknown fields are rejected like any other invalid field.
+pub struct StrictJson<T>(pub T);
+
+impl<T> ValidatedJson<T> {
+    pub fn into_inner(self) -> T { self.0 }
+}
+
+impl<T> StrictJson<T> {
+    pub fn into_inner(self) -> T { self.0 }
+}
+
+impl<T> Deref for ValidatedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T { &self.0 }
+}
+
+impl<T> Deref for StrictJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T { &self.0 }
+}
+
+impl<T> FromRequest for ValidatedJson<T> where T: DeserializeOwned + 'static
+{
+    type Config = ();
+    type Error = Error;
+    type Future = Box<dyn Future<Item = Self, Error = Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        Box::new(extract(req, payload, false).map(ValidatedJson))
+    }
+}
+
+impl<T> FromRequest for StrictJson<T> where T: DeserializeOwned + 'static
+{
+    type Config = ();
+    type Error = Error;
+    type Future = Box<dyn Future<Item = Self, Error = Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        Box::new(extract(req, payload, true).map(StrictJson))
+    }
+}
+
+/// Adds a `Warning` header for each unknown field of the request body
+pub fn warnings_middleware<S>(req: ServiceRequest,
+                              srv: &mut S)
+                              -> impl Future<Item = ServiceResponse<Body>, Error = Error>
+    where S: Service<Request = ServiceRequest, Response = ServiceResponse<Body>, Error = Error>
+{
+    srv.call(req).map(|mut res| {
+                     let warnings = res.request()
+                                       .extensions()
+                                       .get::<JsonWarnings>()
+                                       .map(|warnings| warnings.0.clone())
+                                       .unwrap_or_default();
+                     for path in warnings {
+                         if let Ok(value) = HeaderValue::from_str(&warning_header(&path)) {
+                             res.headers_mut().append(http::header::WARNING, value);
+                         }
+                     }
+                     res
+                 })
+}
+
+fn extract<T>(req: &HttpRequest,
+              payload: &mut Payload,
+              strict: bool)
+              -> impl Future<Item = T, Error = Error>
+    where T: DeserializeOwned + 'static
+{
+    let req = req.clone();
+    Bytes::from_request(&req, payload).and_then(move |body| -> Result<T, Error> {
+                                          let (value, unknown) = parse(&body, strict)?;
+                                          if !unknown.is_empty() {
+                                              req.extensions_mut().insert(JsonWarnings(unknown));
+                                          }
+                                          Ok(value)
+                                      })
+}
+
+// A path into the request body
+#[derive(Clone, Debug)]
+enum Step {
+    Key(String),
+    Index(usize),
+}
+
+fn display_path(steps: &[Step]) -> String {
+    let mut path = String::new();
+    for step in steps {
+        match step {
+            Step::Key(key) => {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(key);
+            }
+            Step::Index(index) => path.push_str(&format!("[{}]", index)),
+        }
+    }
+    path
+}
+
+fn lookup<'a>(value: &'a Value, steps: &[Step]) -> Option<&'a Value> {
+    steps.iter().try_fold(value, |value, step| {
+                    match step {
+                        Step::Key(key) => value.get(key.as_str()),
+                        Step::Index(index) => value.get(*index),
+                    }
+                })
+}
+
+fn snippet(value: &Value) -> String {
+    let full = value.to_string();
+    if full.chars().count() <= MAX_SNIPPET_LEN {
+        full
+    } else {
+        let mut short: String = full.chars().take(MAX_SNIPPET_LEN).collect();
+        short.push_str("...");
+        short
+    }
+}
+
+fn ignored_steps(path: &serde_ignored::Path, steps: &mut Vec<Step>) {
+    match path {
+        serde_ignored::Path::Root => (),
+        serde_ignored::Path::Seq { parent, index } => {
+            ignored_steps(parent, steps);
+            steps.push(Step::Index(*index));
+        }
+        serde_ignored::Path::Map { parent, key } => {
+            ignored_steps(parent, steps);
+            steps.push(Step::Key(key.clone()));
+        }
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => ignored_steps(parent, steps),
+    }
+}
+
+// Header values cannot carry every character a JSON key may hold
+fn warning_header(path: &str) -> String {
+    let path: String = path.chars()
+                           .map(|c| {
+                               if c.is_ascii_graphic() && c != '"' && c != '\\' {
+                                   c
+                               } else {
+                                   '?'
+                               }
+                           })
+                           .collect();
+    format!("299 - \"unknown field {} ignored\"", path)
+}
+
+// Deserializes `body`, returning the paths of any fields `T` ignored
+fn parse<T>(body: &[u8], strict: bool) -> Result<(T, Vec<String>), JsonBodyError>
+    where T: DeserializeOwned
+{
+    let value: Value = match serde_json::from_slice(body) {
+        Ok(value) => value,
+        Err(err) => {
+            let error = FieldError { path:     String::new(),
+                                     expected: None,
+                                     received: None,
+                                     message:  err.to_string(), };
+            return Err(JsonBodyError { errors: vec![error], });
+        }
+    };
+
+    let mut unknown = Vec::new();
+    let result: Result<T, _> = {
+        let de = serde_ignored::Deserializer::new(&value, |path| {
+            let mut steps = Vec::new();
+            ignored_steps(&path, &mut steps);
+            unknown.push(steps);
+        });
+        serde_path_to_error::deserialize(de)
+    };
+
+    let parsed = match result {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            let mut steps = error_steps(err.path());
+            let message = err.into_inner().to_string();
+
+            // A missing field is reported at the object that lacks it
+            if let Some(field) = missing_field(&message) {
+                steps.push(Step::Key(field.to_string()));
+            }
+            let expected = message.rsplitn(2, ", expected ")
+                                  .next()
+                                  .filter(|expected| *expected != message)
+                                  .map(str::to_string);
+            let received = lookup(&value, &steps).map(snippet);
+            let error = FieldError { path: display_path(&steps),
+                                     expected,
+                                     received,
+                                     message };
+            return Err(JsonBodyError { errors: vec![error], });
+        }
+    };
+
+    if strict && !unknown.is_empty() {
+        let errors = unknown.iter()
+                            .map(|steps| {
+                                FieldError { path:     display_path(steps),
+                                             expected: None,
+                                             received: lookup(&value, steps).map(snippet),
+                                             message:  "unknown field".to_string(), }
+                            })
+                            .collect();
+        return Err(JsonBodyError { errors });
+    }
+
+    Ok((parsed, unknown.iter().map(|steps| display_path(steps)).collect()))
+}
+
+fn error_steps(path: &serde_path_to_error::Path) -> Vec<Step> {
+    path.iter()
+        .filter_map(|segment| {
+            match segment {
+                Segment::Seq { index } => Some(Step::Index(*index)),
+                Segment::Map { key } => Some(Step::Key(key.clone())),
+                Segment::Enum { variant } => Some(Step::Key(variant.clone())),
+                Segment::Unknown => None,
+            }
+        })
+        .collect()
+}
+
+fn missing_field(message: &str) -> Option<&str> {
+    if message.starts_with("missing field `") && message.ends_with('`') {
+        Some(&message["missing field `".len()..message.len() - 1])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Settings {
+        retries: u32,
+        #[serde(default)]
+        labels:  Vec<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Update {
+        name:     String,
+        settings: Settings,
+    }
+
+    fn error_body<T: DeserializeOwned>(body: &str, strict: bool) -> Value {
+        match parse::<T>(body.as_bytes(), strict) {
+            Ok(_) => panic!("{} should not parse", body),
+            Err(err) => serde_json::to_value(err).unwrap(),
+        }
+    }
+
+    #[test]
+    fn nested_field_error() {
+        let body = r#"{"name": "redis", "settings": {"retries": "three"}}"#;
+        assert_eq!(error_body::<Update>(body, false),
+                   json!({
+                       "errors": [{
+                           "path": "settings.retries",
+                           "expected": "u32",
+                           "received": "\"three\"",
+                           "message": "invalid type: string \"three\", expected u32"
+                       }]
+                   }));
+    }
+
+    #[test]
+    fn array_index_error() {
+        let body = r#"{"name": "redis", "settings": {"retries": 1, "labels": ["a", 5]}}"#;
+        assert_eq!(error_body::<Update>(body, false),
+                   json!({
+                       "errors": [{
+                           "path": "settings.labels[1]",
+                           "expected": "a string",
+                           "received": "5",
+                           "message": "invalid type: integer `5`, expected a string"
+                       }]
+                   }));
+    }
+
+    #[test]
+    fn missing_field_error() {
+        let body = r#"{"name": "redis", "settings": {}}"#;
+        assert_eq!(error_body::<Update>(body, false),
+                   json!({
+                       "errors": [{
+                           "path": "settings.retries",
+                           "message": "missing field `retries`"
+                       }]
+                   }));
+    }
+
+    #[test]
+    fn malformed_body_error() {
+        let body = error_body::<Update>(r#"{"name": "#, false);
+        assert_eq!(body["errors"][0]["path"], json!(""));
+        assert!(body["errors"][0]["message"].as_str()
+                                            .unwrap()
+                                            .starts_with("EOF while parsing"));
+    }
+
+    #[test]
+    fn unknown_fields_are_warnings() {
+        let body = r#"{"name": "redis", "colour": "red", "settings": {"retries": 1, "x": 2}}"#;
+        let (update, unknown) = parse::<Update>(body.as_bytes(), false).unwrap();
+        assert_eq!(update.name, "redis");
+        assert_eq!(update.settings.retries, 1);
+        assert_eq!(unknown,
+                   vec!["colour".to_string(), "settings.x".to_string()]);
+        assert_eq!(warning_header(&unknown[1]),
+                   "299 - \"unknown field settings.x ignored\"");
+    }
+
+    #[test]
+    fn unknown_fields_are_errors_when_strict() {
+        let body = r#"{"name": "redis", "settings": {"retries": 1, "x": [1, 2]}}"#;
+        assert_eq!(error_body::<Update>(body, true),
+                   json!({
+                       "errors": [{
+                           "path": "settings.x",
+                           "received": "[1,2]",
+                           "message": "unknown field"
+                       }]
+                   }));
+    }
+}
@@ -12,7 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::env;
+use std::{cmp,
+          env};
 
 use actix_web::{dev::{Body,
                       Service,
@@ -27,7 +28,12 @@ use futures::future::{ok,
                       Future};
 
 use base64;
-use oauth_client::types::OAuth2User;
+use chrono::{DateTime,
+             Utc};
+use diesel::pg::PgConnection;
+use oauth_client::{error::Error as OAuthError,
+                   types::{OAuth2Token,
+                           OAuth2User}};
 use protobuf;
 
 use crate::bldr_core::{self,
@@ -41,22 +47,33 @@ use crate::{db::models::account::*,
                        originsrv}};
 
 use crate::server::{error,
+                    framework::deadline,
                     helpers::req_state,
-                    services::metrics::Counter,
+                    services::{invitations,
+                               metrics::Counter,
+                               origin_groups,
+                               security_events},
                     AppState};
 
 lazy_static! {
     static ref SESSION_DURATION: u32 = 3 * 24 * 60 * 60;
 }
 
+// OAuth tokens are refreshed when they are this close to expiring, in seconds
+const OAUTH_REFRESH_MARGIN: i64 = 60;
+
+// Seconds the refresh of a session's token is left to the request that claimed it before another
+// request may claim it
+const OAUTH_REFRESH_CLAIM_SECS: u32 = 30;
+
 pub fn route_message<R, T>(req: &HttpRequest, msg: &R) -> error::Result<T>
     where R: protobuf::Message,
           T: protobuf::Message
 {
     Counter::RouteMessage.increment();
-    // Route via Protobuf over HTTP
+    // Route via Protobuf over HTTP, within the time the request has left
     req_state(req).jobsrv
-                  .rpc::<R, T>(msg)
+                  .rpc_within::<R, T>(msg, deadline::remaining(req))
                   .map_err(error::Error::BuilderCore)
 }
 
@@ -73,6 +90,10 @@ pub fn authentication_middleware<S>(mut req: ServiceRequest,
     };
 
     let hdr_components: Vec<&str> = hdr.split_whitespace().collect();
+    // Service credentials carry no session, the endpoints accepting them check them directly
+    if (hdr_components.len() == 2) && (hdr_components[0] == "Basic") {
+        return Either::A(srv.call(req));
+    }
     if (hdr_components.len() != 2) || (hdr_components[0] != "Bearer") {
         return Either::B(ok(req.into_response(HttpResponse::Unauthorized().finish())));
     }
@@ -80,9 +101,26 @@ pub fn authentication_middleware<S>(mut req: ServiceRequest,
 
     let session = match authenticate(&token, &req.app_data().expect("request state")) {
         Ok(session) => session,
+        Err(error::Error::TokenExpired) => {
+            let resp: HttpResponse = error::Error::TokenExpired.into();
+            return Either::B(ok(req.into_response(resp)));
+        }
         Err(_) => return Either::B(ok(req.into_response(HttpResponse::Unauthorized().finish()))),
     };
 
+    // Personal access tokens are watched for use from addresses they were not used from before
+    if bldr_core::access_token::is_access_token(token) && session.get_id() != BUILDER_ACCOUNT_ID {
+        let state = req.app_data::<AppState>().expect("request state");
+        let ip = security_events::client_ip(req.head(), &state.config.api.trusted_proxies);
+        if let Some(ip) = ip {
+            let account_id = session.get_id() as i64;
+            if let Err(err) = state.known_ips.check(account_id, &ip, &state.db) {
+                warn!("Unable to check token use by account {} from {}, err={}",
+                      account_id, ip, err);
+            }
+        }
+    }
+
     req.head_mut()
        .extensions_mut()
        .insert::<originsrv::Session>(session);
@@ -97,14 +135,17 @@ fn authenticate(token: &str, state: &AppState) -> error::Result<originsrv::Sessi
         return session_create_short_circuit(token, state);
     };
 
-    let mut memcache = state.memcache.borrow_mut();
-    match memcache.get_session(token) {
+    let cached = state.memcache.borrow_mut().get_session(token);
+    match cached {
         Some(session) => {
             trace!("Session {} Cache Hit!", token);
+            let session = refresh_oauth_token(token, session, state);
+            validate_oauth_token(token, &session, state)?;
             Ok(session)
         }
         None => {
             trace!("Session {} Cache Miss!", token);
+            let mut memcache = state.memcache.borrow_mut();
             if !bldr_core::access_token::is_access_token(token) {
                 // No token in cache and not a PAT - bail
                 return Err(error::Error::Authorization);
@@ -112,10 +153,13 @@ fn authenticate(token: &str, state: &AppState) -> error::Result<originsrv::Sessi
             // Pull the session out of the current token provided so we can validate
             // it against the db's tokens
             let mut session =
-                bldr_core::access_token::validate_access_token(&state.config.api.key_path,
-                                                               token).map_err(|_| {
-                                                                         error::Error::Authorization
-                                                                     })?;
+                match bldr_core::access_token::validate_access_token(&state.config.api.key_path,
+                                                                     token)
+                {
+                    Ok(session) => session,
+                    Err(bldr_core::Error::TokenExpired) => return Err(error::Error::TokenExpired),
+                    Err(_) => return Err(error::Error::Authorization),
+                };
 
             if session.get_id() == BUILDER_ACCOUNT_ID {
                 trace!("Builder token identified");
@@ -135,16 +179,32 @@ fn authenticate(token: &str, state: &AppState) -> error::Result<originsrv::Sessi
                         Some(access_token) => {
                             let new_token = access_token.token.clone();
                             if token.trim_end_matches('=') != new_token.trim_end_matches('=') {
-                                // Token is valid but revoked or otherwise expired
+                                // Token is valid but revoked or replaced
                                 return Err(error::Error::Authorization);
                             }
 
+                            // Tokens issued before expiration was introduced only carry
+                            // their sunset date in the database
+                            let ttl = match access_token.expires_at {
+                                Some(expires_at) => {
+                                    let expires = DateTime::<Utc>::from_utc(expires_at, Utc);
+                                    let now = Utc::now();
+                                    if bldr_core::access_token::is_expired(expires, now) {
+                                        return Err(error::Error::TokenExpired);
+                                    }
+                                    let remaining = (expires - now).num_seconds() as u32;
+                                    Some(cmp::min(remaining, state.config.memcache.ttl * 60))
+                                }
+                                None => None,
+                            };
+
                             let account = Account::get_by_id(session.get_id() as i64, &*conn)
                                 .map_err(error::Error::DieselError)?;
+                            record_authentication(account.id, &*conn);
                             session.set_name(account.name);
                             session.set_email(account.email);
 
-                            memcache.set_session(&new_token, &session, None);
+                            memcache.set_session(&new_token, &session, ttl);
                             Ok(session)
                         }
                         None => {
@@ -162,7 +222,109 @@ fn authenticate(token: &str, state: &AppState) -> error::Result<originsrv::Sessi
     }
 }
 
-pub fn session_create_oauth(oauth_token: &str,
+// Sessions signed in through a provider issuing short lived tokens trade their refresh token
+// for a new access token when it is about to expire, rather than making the user sign in again.
+// Only the request that claims the refresh makes it, the others carry on with the token they have
+// while it is made, as a provider rotating refresh tokens would refuse all but the first. A
+// refresh that fails leaves the session as it is, without the refresh token so it is not retried
+// on every request.
+fn refresh_oauth_token(token: &str,
+                       session: originsrv::Session,
+                       state: &AppState)
+                       -> originsrv::Session {
+    let now = Utc::now().timestamp();
+    if !needs_refresh(&session, now)
+       || !state.memcache
+                .borrow_mut()
+                .claim_session_refresh(token, OAUTH_REFRESH_CLAIM_SECS)
+    {
+        return session;
+    }
+
+    // The provider is called without holding the cache, which other requests of this worker use
+    let refreshed = state.oauth.refresh(session.get_oauth_refresh_token());
+    if let Err(ref err) = refreshed {
+        warn!("Unable to refresh OAuth token of account {}, err={}",
+              session.get_id(),
+              err);
+    }
+    let session = with_refreshed_token(session, refreshed.ok(), now);
+
+    let mut memcache = state.memcache.borrow_mut();
+    let ttl = session.get_expires_at() - now;
+    if ttl > 0 {
+        memcache.set_session(token, &session, Some(ttl as u32));
+    }
+    memcache.release_session_refresh(token);
+    session
+}
+
+// With `validate_sessions`, a session ends once the provider no longer takes its token, e.g.
+// because the user was deactivated there. A provider that can't be reached leaves the session as
+// it is, so that an outage of the provider doesn't sign everyone out.
+fn validate_oauth_token(token: &str,
+                        session: &originsrv::Session,
+                        state: &AppState)
+                        -> error::Result<()> {
+    if !state.oauth.config.validate_sessions || session.get_oauth_token().is_empty() {
+        return Ok(());
+    }
+    match state.oauth.user(session.get_oauth_token()) {
+        Ok(_) | Err(OAuthError::NotSupported) => Ok(()),
+        Err(OAuthError::HttpResponse(status, _)) if status.as_u16() == 401 => {
+            debug!("{} no longer takes the OAuth token of account {}",
+                   state.oauth.config.provider,
+                   session.get_id());
+            state.memcache.borrow_mut().delete_session_key(token);
+            Err(error::Error::Authorization)
+        }
+        Err(err) => {
+            warn!("Unable to validate the OAuth token of account {}, err={}",
+                  session.get_id(),
+                  err);
+            Ok(())
+        }
+    }
+}
+
+fn needs_refresh(session: &originsrv::Session, now: i64) -> bool {
+    session.has_oauth_refresh_token()
+    && session.has_oauth_expires_at()
+    && session.get_oauth_expires_at() - now <= OAUTH_REFRESH_MARGIN
+}
+
+// The session with the token a refresh gave, or without its refresh token when it failed
+fn with_refreshed_token(mut session: originsrv::Session,
+                        refreshed: Option<OAuth2Token>,
+                        now: i64)
+                        -> originsrv::Session {
+    match refreshed {
+        Some(refreshed) => {
+            debug!("Refreshed OAuth token of account {}", session.get_id());
+            session.set_oauth_token(refreshed.access_token);
+            if let Some(refresh_token) = refreshed.refresh_token {
+                session.set_oauth_refresh_token(refresh_token);
+            }
+            // Providers may grant refreshed tokens a lifetime of their own
+            let expires_in = match refreshed.expires_in {
+                Some(expires_in) => Some(expires_in),
+                None if session.has_oauth_expires_in() => Some(session.get_oauth_expires_in()),
+                None => None,
+            };
+            match expires_in {
+                Some(expires_in) => {
+                    session.set_oauth_expires_at(now + expires_in as i64);
+                    session.set_oauth_expires_in(expires_in);
+                }
+                None => session.clear_oauth_expires_at(),
+            }
+        }
+        None => session.clear_oauth_refresh_token(),
+    }
+    session
+}
+
+pub fn session_create_oauth(oauth_token: &OAuth2Token,
                             user: &OAuth2User,
                             provider: &str,
                             state: &AppState)
@@ -184,9 +346,24 @@ pub fn session_create_oauth(oauth_token: &str,
                                   &*conn)
     {
         Ok(account) => {
+            // Invitations sent to this address before the account existed become
+            // regular invitations the account can accept
+            if let Some(email) = invitations::claimable_email(user) {
+                if let Err(err) =
+                    invitations::claim_by_email(email, account.id, &account.name, &*conn)
+                {
+                    warn!("Unable to claim email invitations for {}, err={}",
+                          account.name, err);
+                }
+            }
+
+            sync_origin_groups(state, &account, user, &*conn);
+
+            record_authentication(account.id, &*conn);
+
             session_token.set_account_id(account.id as u64);
             session_token.set_extern_id(user.id.to_string());
-            session_token.set_token(oauth_token.to_string().into_bytes());
+            session_token.set_token(oauth_token.access_token.clone().into_bytes());
 
             match provider.parse::<originsrv::OAuthProvider>() {
                 Ok(p) => session_token.set_provider(p),
@@ -202,7 +379,17 @@ pub fn session_create_oauth(oauth_token: &str,
             session.set_name(account.name);
             session.set_token(encoded_token.clone());
             session.set_flags(FeatureFlags::empty().bits());
-            session.set_oauth_token(oauth_token.to_owned());
+            session.set_oauth_token(oauth_token.access_token.clone());
+
+            let now = Utc::now().timestamp();
+            session.set_expires_at(now + i64::from(*SESSION_DURATION));
+            if let Some(ref refresh_token) = oauth_token.refresh_token {
+                session.set_oauth_refresh_token(refresh_token.clone());
+            }
+            if let Some(expires_in) = oauth_token.expires_in {
+                session.set_oauth_expires_at(now + expires_in as i64);
+                session.set_oauth_expires_in(expires_in);
+            }
 
             debug!("issuing session, {:?}", session);
             state.memcache
@@ -217,32 +404,69 @@ pub fn session_create_oauth(oauth_token: &str,
     }
 }
 
+// Keeps the account's membership of the origins mapped to provider groups in step with its
+// groups. The cached memberships are updated too, for the change to apply at once.
+fn sync_origin_groups(state: &AppState, account: &Account, user: &OAuth2User, conn: &PgConnection) {
+    match origin_groups::sign_in(&state.config.api.group_origins, account, user, conn) {
+        Ok(changes) => {
+            let mut memcache = state.memcache.borrow_mut();
+            for origin in &changes.added {
+                memcache.set_origin_member(origin, account.id as u64, true);
+            }
+            for origin in &changes.removed {
+                memcache.set_origin_member(origin, account.id as u64, false);
+            }
+        }
+        Err(err) => {
+            warn!("Unable to sync the origins of the groups of {}, err={}",
+                  account.name, err)
+        }
+    }
+}
+
+// Sessions are cached, so an account is seen to authenticate when it signs in or when its token
+// is first used after its cached session expired
+fn record_authentication(account_id: i64, conn: &PgConnection) {
+    if let Err(err) = Account::record_authentication(account_id as u64, conn) {
+        warn!("Unable to record authentication of account {}, err={}",
+              account_id, err);
+    }
+}
+
 pub fn session_create_short_circuit(token: &str,
                                     state: &AppState)
                                     -> error::Result<originsrv::Session> {
     let (user, provider) = match token {
         "bobo" => {
-            (OAuth2User { id:       "0".to_string(),
-                          email:    Some("bobo@example.com".to_string()),
-                          username: "bobo".to_string(), },
+            (OAuth2User { id:             "0".to_string(),
+                          email:          Some("bobo@example.com".to_string()),
+                          email_verified: Some(true),
+                          groups:         None,
+                          username:       "bobo".to_string(), },
              "GitHub")
         }
         "mystique" => {
-            (OAuth2User { id:       "1".to_string(),
-                          email:    Some("mystique@example.com".to_string()),
-                          username: "mystique".to_string(), },
+            (OAuth2User { id:             "1".to_string(),
+                          email:          Some("mystique@example.com".to_string()),
+                          email_verified: Some(true),
+                          groups:         None,
+                          username:       "mystique".to_string(), },
              "GitHub")
         }
         "hank" => {
-            (OAuth2User { id:       "2".to_string(),
-                          email:    Some("hank@example.com".to_string()),
-                          username: "hank".to_string(), },
+            (OAuth2User { id:             "2".to_string(),
+                          email:          Some("hank@example.com".to_string()),
+                          email_verified: Some(true),
+                          groups:         None,
+                          username:       "hank".to_string(), },
              "GitHub")
         }
         "wesker" => {
-            (OAuth2User { id:       "3".to_string(),
-                          email:    Some("awesker@umbrella.corp".to_string()),
-                          username: "wesker".to_string(), },
+            (OAuth2User { id:             "3".to_string(),
+                          email:          Some("awesker@umbrella.corp".to_string()),
+                          email_verified: Some(true),
+                          groups:         None,
+                          username:       "wesker".to_string(), },
              "GitHub")
         }
         user => {
@@ -251,10 +475,72 @@ pub fn session_create_short_circuit(token: &str,
         }
     };
 
-    session_create_oauth(token, &user, provider, state)
+    let token = OAuth2Token { access_token:  token.to_string(),
+                              refresh_token: None,
+                              expires_in:    None, };
+    session_create_oauth(&token, &user, provider, state)
 }
 
 fn encode_token(token: &originsrv::SessionToken) -> String {
     let bytes = protocol::message::encode(token).unwrap(); // Unwrap is safe
     base64::encode(&bytes)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_571_600_000;
+
+    fn session(expires_at: i64) -> originsrv::Session {
+        let mut session = originsrv::Session::new();
+        session.set_oauth_token("at".to_string());
+        session.set_oauth_refresh_token("rt".to_string());
+        session.set_oauth_expires_at(expires_at);
+        session.set_oauth_expires_in(3600);
+        session
+    }
+
+    fn token(refresh_token: Option<&str>, expires_in: Option<u64>) -> OAuth2Token {
+        OAuth2Token { access_token: "at2".to_string(),
+                      refresh_token: refresh_token.map(str::to_string),
+                      expires_in }
+    }
+
+    #[test]
+    fn tokens_are_refreshed_when_about_to_expire() {
+        assert!(!needs_refresh(&session(NOW + OAUTH_REFRESH_MARGIN + 1), NOW));
+        assert!(needs_refresh(&session(NOW + OAUTH_REFRESH_MARGIN), NOW));
+        assert!(needs_refresh(&session(NOW - 10), NOW));
+
+        let mut unrefreshable = session(NOW);
+        unrefreshable.clear_oauth_refresh_token();
+        assert!(!needs_refresh(&unrefreshable, NOW));
+        let mut unexpiring = session(NOW);
+        unexpiring.clear_oauth_expires_at();
+        assert!(!needs_refresh(&unexpiring, NOW));
+    }
+
+    #[test]
+    fn refreshed_tokens_keep_the_lifetime_the_provider_gave() {
+        let refreshed =
+            with_refreshed_token(session(NOW), Some(token(Some("rt2"), Some(300))), NOW);
+        assert_eq!(refreshed.get_oauth_token(), "at2");
+        assert_eq!(refreshed.get_oauth_refresh_token(), "rt2");
+        assert_eq!(refreshed.get_oauth_expires_at(), NOW + 300);
+        assert_eq!(refreshed.get_oauth_expires_in(), 300);
+
+        // The provider said nothing, the lifetime of the first token stands
+        let refreshed = with_refreshed_token(session(NOW), Some(token(None, None)), NOW);
+        assert_eq!(refreshed.get_oauth_refresh_token(), "rt");
+        assert_eq!(refreshed.get_oauth_expires_at(), NOW + 3600);
+    }
+
+    #[test]
+    fn failed_refreshes_are_not_retried() {
+        let failed = with_refreshed_token(session(NOW), None, NOW);
+        assert_eq!(failed.get_oauth_token(), "at");
+        assert!(!failed.has_oauth_refresh_token());
+        assert!(!needs_refresh(&failed, NOW));
+    }
+}
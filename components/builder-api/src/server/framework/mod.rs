@@ -1,2 +1,6 @@
+pub mod deadline;
+pub mod default_origin;
+pub mod fields;
 pub mod headers;
+pub mod json;
 pub mod middleware;
@@ -175,4 +175,9 @@ pub fn trigger_from_request_model(req: &HttpRequest) -> PCT {
     PCT::Unknown
 }
 
+// A concurrency cap requested for a job group, 0 meaning none, within the operator's ceiling
+pub fn valid_max_concurrency(max_concurrency: u32, ceiling: u32) -> bool {
+    ceiling == 0 || max_concurrency <= ceiling
+}
+
 pub fn req_state(req: &HttpRequest) -> &AppState { req.app_data().expect("request state") }
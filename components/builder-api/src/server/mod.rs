@@ -20,9 +20,12 @@ pub mod resources;
 pub mod services;
 
 use std::{cell::RefCell,
+          cmp,
           collections::HashMap,
           iter::FromIterator,
-          sync::Arc};
+          sync::Arc,
+          thread,
+          time::Duration};
 
 use actix_web::{http::StatusCode,
                 middleware::Logger,
@@ -33,20 +36,51 @@ use actix_web::{http::StatusCode,
                 HttpServer,
                 Result};
 
-use crate::{bldr_core::rpc::RpcClient,
+use crate::{bldr_core::{build_info::BuildInfo,
+                        http_client::HttpClientFactory,
+                        rpc::RpcClient},
             db::{migration,
-                 DbPool}};
+                 DbPool},
+            VERSION};
 use github_api_client::GitHubClient;
 
 use artifactory_client::client::ArtifactoryClient;
-use oauth_client::client::OAuth2Client;
+use oauth_client::{client::OAuth2Client,
+                   error::Error as OAuthError};
 
-use self::framework::middleware::authentication_middleware;
+use self::framework::{deadline::deadline_middleware,
+                      default_origin::default_origin_middleware,
+                      json::warnings_middleware,
+                      middleware::authentication_middleware};
 
-use self::services::{memcache::MemcacheClient,
-                     s3::S3Handler};
+use self::services::{account_export,
+                     announcements,
+                     artifact_encryption::{self,
+                                           KeyProvider},
+                     build_info,
+                     delivery_log::DeliveryLog,
+                     download_cache::DownloadCache,
+                     ephemeral_channels,
+                     install_stats::{self,
+                                     InstallStats},
+                     key_rotation,
+                     memcache::MemcacheClient,
+                     rate_limit::RateLimiter,
+                     revocations::{self,
+                                   Revocations},
+                     s3::S3Handler,
+                     security_events::{self,
+                                       KnownIps},
+                     status::{self,
+                              SharedStatus},
+                     token_expiry,
+                     upload_spool::{self,
+                                    Spool},
+                     upstream::{self,
+                                Upstream}};
 
-use self::resources::{authenticate::Authenticate,
+use self::resources::{announcements::Announcements,
+                      authenticate::Authenticate,
                       channels::Channels,
                       ext::Ext,
                       jobs::Jobs,
@@ -55,11 +89,17 @@ use self::resources::{authenticate::Authenticate,
                       pkgs::Packages,
                       profile::Profile,
                       projects::Projects,
+                      status::Status,
+                      telemetry::Telemetry,
+                      token::Token,
                       user::User};
 
 use crate::config::{Config,
                     GatewayCfg};
 
+// Longest wait between attempts to discover the OAuth provider at startup
+const MAX_DISCOVERY_BACKOFF_SECS: u64 = 60;
+
 features! {
     pub mod feat {
         const List = 0b0000_0001,
@@ -71,30 +111,92 @@ features! {
 
 // Application state
 pub struct AppState {
-    config:      Config,
-    packages:    S3Handler,
-    github:      GitHubClient,
-    jobsrv:      RpcClient,
-    oauth:       OAuth2Client,
-    memcache:    RefCell<MemcacheClient>,
-    artifactory: ArtifactoryClient,
-    db:          DbPool,
+    config:                Config,
+    packages:              S3Handler,
+    github:                GitHubClient,
+    jobsrv:                RpcClient,
+    oauth:                 Arc<OAuth2Client>,
+    revocations:           Arc<Revocations>,
+    memcache:              RefCell<MemcacheClient>,
+    artifactory:           ArtifactoryClient,
+    db:                    DbPool,
+    status:                SharedStatus,
+    introspection_limiter: Arc<RateLimiter>,
+    sync_diff_limiter:     Arc<RateLimiter>,
+    upstream:              Arc<Upstream>,
+    known_ips:             Arc<KnownIps>,
+    github_deliveries:     Arc<DeliveryLog>,
+    artifact_keys:         Box<dyn KeyProvider>,
+    install_stats:         Arc<InstallStats>,
+    upload_spool:          Arc<Spool>,
+    download_cache:        Arc<DownloadCache>,
+    build_info:            BuildInfo,
 }
 
 impl AppState {
-    pub fn new(config: &Config, db: DbPool) -> error::Result<AppState> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(config: &Config,
+               db: DbPool,
+               status: SharedStatus,
+               introspection_limiter: Arc<RateLimiter>,
+               sync_diff_limiter: Arc<RateLimiter>,
+               upstream: Arc<Upstream>,
+               known_ips: Arc<KnownIps>,
+               github_deliveries: Arc<DeliveryLog>,
+               install_stats: Arc<InstallStats>,
+               upload_spool: Arc<Spool>,
+               download_cache: Arc<DownloadCache>,
+               build_info: BuildInfo,
+               oauth: Arc<OAuth2Client>,
+               revocations: Arc<Revocations>)
+               -> error::Result<AppState> {
         Ok(AppState { config: config.clone(),
                       packages: S3Handler::new(config.s3.clone()),
                       github: GitHubClient::new(config.github.clone())?,
                       jobsrv: RpcClient::new(&format!("{}", config.jobsrv)),
-                      oauth: OAuth2Client::new(config.oauth.clone())?,
+                      oauth,
+                      revocations,
                       memcache: RefCell::new(MemcacheClient::new(&config.memcache.clone())),
                       artifactory: ArtifactoryClient::new(config.artifactory.clone())?,
-                      db })
+                      db,
+                      status,
+                      introspection_limiter,
+                      sync_diff_limiter,
+                      upstream,
+                      known_ips,
+                      github_deliveries,
+                      artifact_keys: artifact_encryption::key_provider(&config.api),
+                      install_stats,
+                      upload_spool,
+                      download_cache,
+                      build_info })
+    }
+}
+
+// The OAuth client. An OpenID Connect provider that can't be discovered yet, e.g. as it starts
+// along with Builder, is retried with a backoff; settings the client refuses stop the service.
+fn oauth_client(config: &Config, http_clients: &HttpClientFactory) -> OAuth2Client {
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        match OAuth2Client::new(config.oauth.clone(), http_clients) {
+            Ok(client) => return client,
+            Err(err @ OAuthError::Discovery(_)) => {
+                warn!("Unable to discover the OAuth provider, retrying in {}s, err = {}",
+                      backoff.as_secs(),
+                      err);
+                thread::sleep(backoff);
+                backoff = cmp::min(backoff * 2, Duration::from_secs(MAX_DISCOVERY_BACKOFF_SECS));
+            }
+            Err(err) => {
+                error!("Unable to create the OAuth client, err = {}", err);
+                panic!("Cannot start without a valid OAuth client");
+            }
+        }
     }
 }
 
-fn enable_features(config: &Config) {
+// Returns the names of the features enabled
+fn enable_features(config: &Config) -> Vec<String> {
     let features: HashMap<_, _> = HashMap::from_iter(vec![("LIST", feat::List),
                                                           ("JOBSRV", feat::Jobsrv),
                                                           ("ARTIFACTORY", feat::Artifactory),
@@ -103,17 +205,23 @@ fn enable_features(config: &Config) {
                                  .features_enabled
                                  .split(',')
                                  .map(|f| f.trim().to_uppercase());
+    let mut enabled = Vec::new();
     for key in features_enabled {
         if features.contains_key(key.as_str()) {
             info!("Enabling feature: {}", key);
             feat::enable(features[key.as_str()]);
+            enabled.push(key);
         }
     }
+    enabled.sort();
+    enabled.dedup();
 
     if feat::is_enabled(feat::List) {
         println!("Listing possible feature flags: {:?}", features.keys());
         println!("Enable features by populating 'features_enabled' in config");
     }
+
+    enabled
 }
 
 /// Endpoint for determining availability of builder-api components.
@@ -121,8 +229,22 @@ fn enable_features(config: &Config) {
 /// Returns a status 200 on success. Any non-200 responses are an outage or a partial outage.
 pub fn status() -> HttpResponse { HttpResponse::new(StatusCode::OK) }
 
+/// What this builder-api was built from, see `BuildInfo`
+#[allow(clippy::needless_pass_by_value)]
+pub fn version(state: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(&state.build_info)
+}
+
+/// Assign a sunset date, `days` from now, to all personal access tokens issued
+/// without an expiration.
+pub fn sunset_tokens(config: &Config, days: u32) -> error::Result<usize> {
+    let db_pool = DbPool::new(&config.datastore);
+    migration::setup(&db_pool.get_conn().map_err(error::Error::DbError)?)?;
+    token_expiry::sunset_tokens(&db_pool, days)
+}
+
 pub fn run(config: Config) -> Result<()> {
-    enable_features(&config);
+    let build_info = build_info::current(enable_features(&config));
 
     let cfg = Arc::new(config.clone());
 
@@ -136,8 +258,97 @@ pub fn run(config: Config) -> Result<()> {
 
     migration::setup(&db_pool.get_conn().unwrap()).unwrap();
 
+    token_expiry::start(config.api.clone(), db_pool.clone());
+    key_rotation::start(config.api.clone(), db_pool.clone());
+    security_events::start(config.api.clone(), db_pool.clone());
+    account_export::start(config.clone(), db_pool.clone());
+    announcements::start(db_pool.clone());
+    ephemeral_channels::start(&config, db_pool.clone());
+
+    let status = status::start(&config, db_pool.clone());
+
+    // Shared by every worker so the limit holds across the whole process
+    let introspection_limiter =
+        Arc::new(RateLimiter::new(config.api.introspection_rate_limit, Duration::from_secs(60)));
+    let sync_diff_limiter =
+        Arc::new(RateLimiter::new(config.api.sync_diff_rate_limit, Duration::from_secs(60)));
+
+    // Every outbound client is built from these, so bad settings stop the service here
+    let http_clients = match HttpClientFactory::new("builder-api", VERSION, &config.http_client) {
+        Ok(http_clients) => http_clients,
+        Err(err) => {
+            error!("Invalid HTTP client settings, err = {}", err);
+            panic!("Cannot start without valid HTTP client settings");
+        }
+    };
+
+    // Shared by every worker so that an OpenID Connect provider is discovered once
+    let oauth = Arc::new(oauth_client(&config, &http_clients));
+
+    // Shared by every worker so that tokens are revoked one at a time, in one place
+    let revocations = Arc::new(Revocations::new());
+    revocations::start(revocations.clone(), oauth.clone());
+
+    // Shared by every worker so that concurrent misses fetch a package once
+    let upstream = match Upstream::new(&config, db_pool.clone(), &http_clients) {
+        Ok(upstream) => Arc::new(upstream),
+        Err(err) => {
+            error!("Unable to create upstream client, err = {}", err);
+            panic!("Cannot start without a valid upstream client");
+        }
+    };
+    upstream::start(upstream.clone());
+
+    // Shared by every worker so that an address is reported new once
+    let known_ips = Arc::new(KnownIps::new(config.api.known_ip_learning_days));
+
+    // Shared by every worker so that a redelivered hook is recognized whichever one takes it
+    let github_deliveries =
+        Arc::new(DeliveryLog::new(Duration::from_secs(config.api.github_delivery_ttl_secs)));
+
+    // Shared by every worker so that reports are written, and rate limited, in one place
+    let install_stats = Arc::new(InstallStats::new(&config.api));
+    install_stats::start(&config.api, install_stats.clone(), db_pool.clone());
+
+    // Shared by every worker so that the spool's capacity holds across the whole process
+    let upload_spool = match Spool::open(&config.upload_spool) {
+        Ok(spool) => Arc::new(spool),
+        Err(err) => {
+            error!("Unable to open the upload spool, err = {}", err);
+            panic!("Cannot start without the configured upload spool");
+        }
+    };
+    upload_spool::start(&config, upload_spool.clone(), db_pool.clone());
+
+    // Shared by every worker so that the cache's size holds across the whole process
+    let download_cache = match DownloadCache::open(&config.download_cache) {
+        Ok(cache) => Arc::new(cache),
+        Err(err) => {
+            error!("Unable to open the download cache, err = {}", err);
+            panic!("Cannot start without the configured download cache");
+        }
+    };
+
+    if feat::is_enabled(feat::Jobsrv) {
+        build_info::check_jobsrv(&build_info, &RpcClient::new(&format!("{}", config.jobsrv)));
+    }
+
     HttpServer::new(move || {
-        let app_state = match AppState::new(&config, db_pool.clone()) {
+        let app_state = match AppState::new(&config,
+                                            db_pool.clone(),
+                                            status.clone(),
+                                            introspection_limiter.clone(),
+                                            sync_diff_limiter.clone(),
+                                            upstream.clone(),
+                                            known_ips.clone(),
+                                            github_deliveries.clone(),
+                                            install_stats.clone(),
+                                            upload_spool.clone(),
+                                            download_cache.clone(),
+                                            build_info.clone(),
+                                            oauth.clone(),
+                                            revocations.clone())
+        {
             Ok(state) => state,
             Err(err) => {
                 error!("Unable to create application state, err = {}", err);
@@ -145,10 +356,15 @@ pub fn run(config: Config) -> Result<()> {
             }
         };
 
+        // Middleware registered last runs first, the default origin needs the session
         App::new().data(app_state)
+                  .wrap_fn(default_origin_middleware)
                   .wrap_fn(authentication_middleware)
+                  .wrap_fn(warnings_middleware)
+                  .wrap_fn(deadline_middleware)
                   .wrap(Logger::default().exclude("/v1/status"))
                   .service(web::scope("/v1")
+                      .configure(Announcements::register)
                       .configure(Authenticate::register)
                       .configure(Channels::register)
                       .configure(Ext::register)
@@ -158,10 +374,14 @@ pub fn run(config: Config) -> Result<()> {
                       .configure(Packages::register)
                       .configure(Profile::register)
                       .configure(Projects::register)
+                      .configure(Status::register)
+                      .configure(Telemetry::register)
+                      .configure(Token::register)
                       .configure(User::register)
                       .service(web::resource("/status")
                           .route(web::get().to(status))
-                          .route(web::head().to(status))))
+                          .route(web::head().to(status)))
+                      .service(web::resource("/version").route(web::get().to(version))))
     }).workers(cfg.handler_count())
       .keep_alive(cfg.http.keep_alive)
       .bind(cfg.http.clone())
@@ -0,0 +1,340 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use actix_web::{http::{self,
+                       StatusCode},
+                web::{self,
+                      Data,
+                      Json,
+                      Path,
+                      ServiceConfig},
+                HttpRequest,
+                HttpResponse};
+use chrono::Utc;
+use diesel::{pg::PgConnection,
+             Connection};
+
+use crate::{db::models::{announcement::{Announcement,
+                                        AnnouncementDismissal,
+                                        AnnouncementOperation},
+                         origin::AccountMembership},
+            protocol::originsrv};
+
+use crate::server::{authorize::{authorize_admin,
+                                authorize_session},
+                    error::{Error,
+                            Result},
+                    framework::headers,
+                    services::announcements::{self,
+                                              AnnouncementReq,
+                                              Notice,
+                                              Viewer},
+                    AppState};
+
+// The announcements differ by user, the UI revalidates them with the ETag on every poll
+const CACHE_CONTROL: &str = "private, no-cache";
+
+#[derive(Clone, Serialize, Debug)]
+pub struct AnnouncementsResp {
+    pub announcements: Vec<Notice>,
+}
+
+pub struct Announcements;
+
+impl Announcements {
+    // Route registration
+    //
+    pub fn register(cfg: &mut ServiceConfig) {
+        cfg.route("/announcements", web::get().to(get_announcements))
+           .route("/announcements/{id}/dismiss",
+                  web::post().to(dismiss_announcement))
+           .route("/admin/announcements", web::get().to(list_announcements))
+           .route("/admin/announcements", web::post().to(create_announcement))
+           .route("/admin/announcements/{id}",
+                  web::put().to(update_announcement))
+           .route("/admin/announcements/{id}",
+                  web::delete().to(expire_announcement));
+    }
+}
+
+// Route handlers - these functions can return any Responder trait
+//
+#[allow(clippy::needless_pass_by_value)]
+fn get_announcements(req: HttpRequest, state: Data<AppState>) -> HttpResponse {
+    let session = authorize_session(&req, None).ok();
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let announcements = match visible_announcements(session.as_ref(), &*conn) {
+        Ok(announcements) => announcements,
+        Err(err) => {
+            debug!("{}", err);
+            return err.into();
+        }
+    };
+
+    let body = match serde_json::to_string(&AnnouncementsResp { announcements }) {
+        Ok(body) => body,
+        Err(err) => {
+            debug!("{}", err);
+            return HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let etag = headers::etag(&body);
+    let fresh = req.headers()
+                   .get(http::header::IF_NONE_MATCH)
+                   .and_then(|value| value.to_str().ok())
+                   .map_or(false, |tags| headers::etag_matches(tags, &etag));
+
+    let mut response = if fresh {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response.header(http::header::CACHE_CONTROL, CACHE_CONTROL)
+            .header(http::header::ETAG, etag);
+    if fresh {
+        response.finish()
+    } else {
+        response.content_type(headers::APPLICATION_JSON).body(body)
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn dismiss_announcement(req: HttpRequest,
+                        path: Path<String>,
+                        state: Data<AppState>)
+                        -> HttpResponse {
+    let account_id = match authorize_session(&req, None) {
+        Ok(session) => session.get_id() as i64,
+        Err(err) => return err.into(),
+    };
+    let id = match path.into_inner().parse::<i64>() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::new(StatusCode::BAD_REQUEST),
+    };
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    // Only announcements that exist are dismissed
+    if let Err(err) = Announcement::get(id, &*conn).map_err(Error::DieselError) {
+        return err.into();
+    }
+
+    match AnnouncementDismissal::dismiss(account_id, id, &*conn).map_err(Error::DieselError) {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn list_announcements(req: HttpRequest, state: Data<AppState>) -> HttpResponse {
+    if let Err(err) = authorize_admin(&req) {
+        return err.into();
+    }
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    match Announcement::list(&*conn).map_err(Error::DieselError) {
+        Ok(announcements) => {
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(announcements)
+        }
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn create_announcement(req: HttpRequest,
+                       body: Json<AnnouncementReq>,
+                       state: Data<AppState>)
+                       -> HttpResponse {
+    let session = match authorize_admin(&req) {
+        Ok(session) => session,
+        Err(err) => return err.into(),
+    };
+
+    let new_announcement = match announcements::validate(&body,
+                                                         session.get_id() as i64,
+                                                         Utc::now().naive_utc())
+    {
+        Ok(new_announcement) => new_announcement,
+        Err(reason) => return HttpResponse::UnprocessableEntity().json(json!({ "error": reason })),
+    };
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let created = conn.transaction::<_, Error, _>(|| {
+                          let announcement = Announcement::create(&new_announcement, &*conn)?;
+                          audit(&announcement,
+                                AnnouncementOperation::Create,
+                                &session,
+                                &*conn)?;
+                          Ok(announcement)
+                      });
+
+    match created {
+        Ok(announcement) => HttpResponse::Created().json(announcement),
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn update_announcement(req: HttpRequest,
+                       path: Path<String>,
+                       body: Json<AnnouncementReq>,
+                       state: Data<AppState>)
+                       -> HttpResponse {
+    let session = match authorize_admin(&req) {
+        Ok(session) => session,
+        Err(err) => return err.into(),
+    };
+    let id = match path.into_inner().parse::<i64>() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::new(StatusCode::BAD_REQUEST),
+    };
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    // The announcement keeps its owner, the operator changing it is in the audit trail
+    let owner_id = match Announcement::get(id, &*conn) {
+        Ok(announcement) => announcement.owner_id,
+        Err(err) => return Error::DieselError(err).into(),
+    };
+    let changes = match announcements::validate(&body, owner_id, Utc::now().naive_utc()) {
+        Ok(changes) => changes,
+        Err(reason) => return HttpResponse::UnprocessableEntity().json(json!({ "error": reason })),
+    };
+
+    let updated = conn.transaction::<_, Error, _>(|| {
+                          let announcement = Announcement::update(id, &changes, &*conn)?;
+                          audit(&announcement,
+                                AnnouncementOperation::Update,
+                                &session,
+                                &*conn)?;
+                          Ok(announcement)
+                      });
+
+    match updated {
+        Ok(announcement) => HttpResponse::Ok().json(announcement),
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn expire_announcement(req: HttpRequest,
+                       path: Path<String>,
+                       state: Data<AppState>)
+                       -> HttpResponse {
+    let session = match authorize_admin(&req) {
+        Ok(session) => session,
+        Err(err) => return err.into(),
+    };
+    let id = match path.into_inner().parse::<i64>() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::new(StatusCode::BAD_REQUEST),
+    };
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    // Expiring an announcement that ended already changes nothing and isn't audited
+    let expired = conn.transaction::<_, Error, _>(|| {
+                          Announcement::get(id, &*conn)?;
+                          match Announcement::expire(id, &*conn)? {
+                              Some(announcement) => {
+                                  audit(&announcement,
+                                        AnnouncementOperation::Expire,
+                                        &session,
+                                        &*conn)
+                              }
+                              None => Ok(()),
+                          }
+                      });
+
+    match expired {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+// Internal - these functions should return Result<..>
+//
+fn visible_announcements(session: Option<&originsrv::Session>,
+                         conn: &PgConnection)
+                         -> Result<Vec<Notice>> {
+    let active = Announcement::active(Utc::now().naive_utc(), conn).map_err(Error::DieselError)?;
+    let viewer = match session {
+        Some(session) => {
+            let account_id = session.get_id() as i64;
+            let origins = AccountMembership::list(account_id, conn).map_err(Error::DieselError)?
+                                                                   .into_iter()
+                                                                   .map(|m| m.origin)
+                                                                   .collect();
+            let dismissed =
+                AnnouncementDismissal::list(account_id, conn).map_err(Error::DieselError)?
+                                                             .into_iter()
+                                                             .collect();
+            Some(Viewer { origins, dismissed })
+        }
+        None => None,
+    };
+    Ok(announcements::visible(active, viewer.as_ref()))
+}
+
+fn audit(announcement: &Announcement,
+         operation: AnnouncementOperation,
+         session: &originsrv::Session,
+         conn: &PgConnection)
+         -> Result<()> {
+    announcement.audit(operation, session.get_id() as i64, session.get_name(), conn)
+                .map_err(Error::DieselError)?;
+    info!("Announcement {} {:?} by {}",
+          announcement.id,
+          operation,
+          session.get_name());
+    Ok(())
+}
@@ -18,40 +18,85 @@ use actix_web::{http::StatusCode,
                 web::{self,
                       Data,
                       Path,
+                      Query,
                       ServiceConfig},
+                HttpRequest,
                 HttpResponse};
 
 use oauth_client::error::Error as OAuthError;
 
 use crate::{protocol::originsrv,
-            server::{error::{Error,
+            server::{authorize::authorize_session,
+                     error::{Error,
                              Result},
                      framework::middleware::{session_create_oauth,
                                              session_create_short_circuit},
                      AppState}};
 
+#[derive(Debug, Deserialize)]
+struct AuthenticateQuery {
+    /// The PKCE verifier of the challenge the authorize URL carried, if any
+    #[serde(default)]
+    code_verifier: Option<String>,
+    /// The nonce the authorize URL carried, if any, which the provider's ID token must repeat
+    #[serde(default)]
+    nonce:         Option<String>,
+}
+
 pub struct Authenticate {}
 
 impl Authenticate {
     // Route registration
     //
     pub fn register(cfg: &mut ServiceConfig) {
-        cfg.route("/authenticate/{code}", web::get().to(authenticate));
+        cfg.route("/authenticate/{code}", web::get().to(authenticate))
+           .route("/logout", web::post().to(logout));
     }
 }
 
 // Route handlers - these functions can return any Responder trait
 //
 #[allow(clippy::needless_pass_by_value)]
-fn authenticate(path: Path<String>, state: Data<AppState>) -> HttpResponse {
+fn authenticate(path: Path<String>,
+                query: Query<AuthenticateQuery>,
+                state: Data<AppState>)
+                -> HttpResponse {
     let code = path.into_inner();
     debug!("authenticate called, code = {}", code);
 
-    match do_authenticate(&code, &state) {
+    let verifier = query.code_verifier.as_ref().map(String::as_str);
+    let nonce = query.nonce.as_ref().map(String::as_str);
+    match do_authenticate(&code, verifier, nonce, &state) {
         Ok(session) => HttpResponse::Ok().json(session),
         Err(Error::OAuth(OAuthError::HttpResponse(_code, _response))) => {
             HttpResponse::new(StatusCode::UNAUTHORIZED)
         }
+        Err(Error::OAuth(OAuthError::TokenRequest(status, error, description))) => {
+            warn!("Oauth code exchange failed, status={}, error={}: {}",
+                  status, error, description);
+            // The provider's reason, e.g. a PKCE verifier it refused, for the sign in page to show
+            HttpResponse::Unauthorized().json(json!({ "code": error, "msg": description }))
+        }
+        Err(Error::OAuth(ref e @ OAuthError::InvalidVerifier(_))) => {
+            HttpResponse::BadRequest().json(json!({ "code": "invalid_verifier",
+                                                    "msg": format!("{}", e) }))
+        }
+        Err(Error::OAuth(ref e @ OAuthError::JwtValidation(_))) => {
+            // The ID token isn't the provider's, or not for Builder
+            warn!("Oauth ID token refused, {}", e);
+            HttpResponse::new(StatusCode::UNAUTHORIZED)
+        }
+        Err(Error::OAuth(ref e @ OAuthError::EmailRequired(_))) => {
+            warn!("Oauth sign in refused, {}", e);
+            let msg = format!("Builder requires a verified email address. Add one to your {} \
+                               account, or verify it there, then sign in again.",
+                              state.oauth.config.provider);
+            HttpResponse::Forbidden().json(json!({ "code": "email_required", "msg": msg }))
+        }
+        Err(Error::OAuth(ref e @ OAuthError::AccessDenied(_))) => {
+            warn!("Oauth sign in refused, {}", e);
+            HttpResponse::new(StatusCode::FORBIDDEN)
+        }
         Err(e) => {
             warn!("Oauth client error, {:?}", e);
             e.into()
@@ -59,15 +104,48 @@ fn authenticate(path: Path<String>, state: Data<AppState>) -> HttpResponse {
     }
 }
 
+// Ends the session, and revokes the provider's token so that it can't be used once the user
+// has logged out. The refresh token is revoked when there is one, as providers revoke the access
+// tokens granted with it. The token is revoked in the background, a provider failing to revoke
+// it doesn't keep the user logged in.
+#[allow(clippy::needless_pass_by_value)]
+fn logout(req: HttpRequest, state: Data<AppState>) -> HttpResponse {
+    let session = match authorize_session(&req, None) {
+        Ok(session) => session,
+        Err(err) => return err.into(),
+    };
+
+    state.memcache
+         .borrow_mut()
+         .delete_session_key(session.get_token());
+
+    let token = if session.has_oauth_refresh_token() {
+        session.get_oauth_refresh_token()
+    } else {
+        session.get_oauth_token()
+    };
+    if !token.is_empty() && !state.revocations.queue(token, session.get_name()) {
+        warn!("Too many revocations queued, leaving the {} token of {} to expire",
+              state.oauth.config.provider,
+              session.get_name());
+    }
+
+    HttpResponse::new(StatusCode::NO_CONTENT)
+}
+
 // Internal - these functions should return Result<..>
 //
-fn do_authenticate(code: &str, state: &AppState) -> Result<originsrv::Session> {
+fn do_authenticate(code: &str,
+                   verifier: Option<&str>,
+                   nonce: Option<&str>,
+                   state: &AppState)
+                   -> Result<originsrv::Session> {
     if env::var_os("HAB_FUNC_TEST").is_some() {
         return session_create_short_circuit(code, state);
     }
 
     let oauth = &state.oauth;
-    let (token, user) = oauth.authenticate(code)?;
+    let (token, user) = oauth.authenticate(code, verifier, nonce)?;
 
     session_create_oauth(&token, &user, &oauth.config.provider, state)
 }
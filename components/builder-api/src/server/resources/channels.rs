@@ -12,7 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::str::FromStr;
+use std::{collections::HashMap,
+          str::FromStr};
 
 use actix_web::{http::{self,
                        StatusCode},
@@ -23,33 +24,53 @@ use actix_web::{http::{self,
                       ServiceConfig},
                 HttpRequest,
                 HttpResponse};
+use chrono::{NaiveDateTime,
+             Utc};
 use diesel::{pg::PgConnection,
              result::{DatabaseErrorKind,
                       Error::{DatabaseError,
-                              NotFound}}};
+                              NotFound}},
+             Connection};
 use serde_json;
 
-use crate::{bldr_core::metrics::CounterMetric,
+use crate::{bldr_core::{channel_sync::{self,
+                                       SyncDiffRequest},
+                        metrics::CounterMetric,
+                        timestamp::rfc3339_opt},
             hab_core::{package::{PackageIdent,
                                  PackageTarget},
-                       ChannelIdent}};
+                       ChannelIdent},
+            protocol::{jobsrv,
+                       originsrv}};
 
 use crate::db::models::{channel::*,
                         package::{BuilderPackageIdent,
+                                  BuilderPackageTarget,
+                                  GetPackage,
                                   GetPackageGroup,
-                                  Package}};
+                                  Package,
+                                  PackageVerificationFailure,
+                                  FEED_ENTRIES}};
 
-use crate::server::{authorize::authorize_session,
+use crate::server::{authorize::{authorize_session,
+                                check_origin_owner},
                     error::{Error,
                             Result},
-                    framework::headers,
+                    framework::{headers,
+                                json::ValidatedJson,
+                                middleware::route_message},
                     helpers::{self,
                               req_state,
                               visibility_for_optional_session,
                               Pagination,
                               Target,
                               ToChannel},
-                    services::metrics::Counter,
+                    resources::pkgs::package_deprecation,
+                    services::{ephemeral_channels,
+                               feed::{self,
+                                      Feed},
+                               metrics::Counter,
+                               security_events},
                     AppState};
 
 // Query param containers
@@ -59,6 +80,43 @@ struct SandboxBool {
     sandbox: bool,
 }
 
+#[derive(Debug, Default, Clone, Deserialize)]
+struct ChannelCreateQuery {
+    #[serde(default)]
+    ephemeral: bool,
+    /// Days an ephemeral channel lives for, the configured default when absent
+    #[serde(default)]
+    ttl_days:  Option<u32>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+struct ChannelRenewQuery {
+    #[serde(default)]
+    ttl_days: Option<u32>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+struct ChannelUpdateQuery {
+    #[serde(default)]
+    cleanup: bool,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ChannelUpdateReq {
+    rolling: bool,
+}
+
+#[derive(Serialize)]
+struct ChannelUpdate {
+    #[serde(flatten)]
+    channel: Channel,
+    /// Releases the update demotes, or would demote on a dry run
+    demoted: Vec<String>,
+    dry_run: bool,
+}
+
 pub struct Channels;
 
 impl Channels {
@@ -68,8 +126,16 @@ impl Channels {
         cfg.route("/depot/channels/{origin}", web::get().to(get_channels))
            .route("/depot/channels/{origin}/{channel}",
                   web::post().to(create_channel))
+           .route("/depot/channels/{origin}/{channel}",
+                  web::patch().to(update_channel))
            .route("/depot/channels/{origin}/{channel}",
                   web::delete().to(delete_channel))
+           .route("/depot/channels/{origin}/{channel}/renew",
+                  web::post().to(renew_channel))
+           .route("/depot/channels/{origin}/{channel}/feed.atom",
+                  web::get().to(get_channel_feed))
+           .route("/depot/channels/{origin}/{channel}/sync_diff",
+                  web::post().to(sync_diff))
            .route("/depot/channels/{origin}/{channel}/pkgs",
                   web::get().to(get_packages_for_origin_channel))
            .route("/depot/channels/{origin}/{channel}/pkgs/{pkg}",
@@ -89,7 +155,11 @@ impl Channels {
            .route("/depot/channels/{origin}/{channel}/pkgs/{pkg}/{version}/{release}/promote",
                   web::put().to(promote_package))
            .route("/depot/channels/{origin}/{channel}/pkgs/{pkg}/{version}/{release}/demote",
-                  web::put().to(demote_package));
+                  web::put().to(demote_package))
+           .route("/depot/channels/{origin}/{channel}/pkgs/{pkg}/pin",
+                  web::put().to(pin_package))
+           .route("/depot/channels/{origin}/{channel}/pkgs/{pkg}/pin",
+                  web::delete().to(unpin_package));
     }
 }
 
@@ -113,10 +183,17 @@ fn get_channels(path: Path<String>,
             // currently the output looks like [{"name": "foo"}] when it probably should be ["foo"]
             #[derive(Serialize)]
             struct Temp {
-                name: String,
+                name:         String,
+                channel_type: ChannelType,
+                #[serde(with = "rfc3339_opt", skip_serializing_if = "Option::is_none")]
+                expires_at:   Option<NaiveDateTime>,
             }
-            let ident_list: Vec<Temp> = list.iter()
-                                            .map(|channel| Temp { name: channel.name.clone(), })
+            let ident_list: Vec<Temp> = list.into_iter()
+                                            .map(|channel| {
+                                                Temp { name:         channel.name,
+                                                       channel_type: channel.channel_type,
+                                                       expires_at:   channel.expires_at, }
+                                            })
                                             .collect();
             HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
                               .json(ident_list)
@@ -132,26 +209,57 @@ fn get_channels(path: Path<String>,
 #[allow(clippy::needless_pass_by_value)]
 fn create_channel(req: HttpRequest,
                   path: Path<(String, String)>,
+                  qcreate: Query<ChannelCreateQuery>,
                   state: Data<AppState>)
                   -> HttpResponse {
     let (origin, channel) = path.into_inner();
 
-    let session_id = match authorize_session(&req, Some(&origin)) {
-        Ok(session) => session.get_id(),
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
         Err(_) => return HttpResponse::new(StatusCode::UNAUTHORIZED),
     };
 
+    // Only ephemeral channels expire
+    if qcreate.ttl_days.is_some() && !qcreate.ephemeral {
+        return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let expires_at = if qcreate.ephemeral {
+        match ephemeral_channels::expires_at(&state.config.channels,
+                                             qcreate.ttl_days,
+                                             Utc::now().naive_utc())
+        {
+            Ok(expires_at) => Some(expires_at),
+            Err(reason) => {
+                return HttpResponse::UnprocessableEntity().json(json!({ "error": reason }))
+            }
+        }
+    } else {
+        None
+    };
+
     let conn = match state.db.get_conn().map_err(Error::DbError) {
         Ok(conn_ref) => conn_ref,
         Err(err) => return err.into(),
     };
 
-    match Channel::create(&CreateChannel { name:     &channel,
-                                           origin:   &origin,
-                                           owner_id: session_id as i64, },
-                          &*conn)
-    {
-        Ok(channel) => HttpResponse::Created().json(channel),
+    let new_channel = CreateChannel { name:     &channel,
+                                      origin:   &origin,
+                                      owner_id: session.get_id() as i64, };
+    let created = match expires_at {
+        Some(expires_at) => Channel::create_ephemeral(&new_channel, expires_at, &*conn),
+        None => Channel::create(&new_channel, &*conn),
+    };
+    match created {
+        Ok(channel) => {
+            if channel.channel_type == ChannelType::Ephemeral {
+                audit_channel(&channel,
+                              ChannelOperation::Create,
+                              Vec::new(),
+                              &session,
+                              &*conn);
+            }
+            HttpResponse::Created().json(channel)
+        }
         Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
             HttpResponse::Conflict().into()
         }
@@ -163,32 +271,191 @@ fn create_channel(req: HttpRequest,
 }
 
 #[allow(clippy::needless_pass_by_value)]
-fn delete_channel(req: HttpRequest,
+fn renew_channel(req: HttpRequest,
+                 path: Path<(String, String)>,
+                 qrenew: Query<ChannelRenewQuery>,
+                 state: Data<AppState>)
+                 -> HttpResponse {
+    let (origin, channel) = path.into_inner();
+    let channel = ChannelIdent::from(channel);
+
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
+        Err(_) => return HttpResponse::new(StatusCode::UNAUTHORIZED),
+    };
+
+    let expires_at = match ephemeral_channels::expires_at(&state.config.channels,
+                                                          qrenew.ttl_days,
+                                                          Utc::now().naive_utc())
+    {
+        Ok(expires_at) => expires_at,
+        Err(reason) => return HttpResponse::UnprocessableEntity().json(json!({ "error": reason })),
+    };
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let existing = match Channel::get(&origin, &channel, &*conn) {
+        Ok(existing) => existing,
+        Err(err) => return Error::DieselError(err).into(),
+    };
+
+    // Standard channels live until they are deleted
+    if existing.channel_type != ChannelType::Ephemeral {
+        return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    match Channel::renew(existing.id, expires_at, &*conn) {
+        Ok(renewed) => {
+            audit_channel(&renewed,
+                          ChannelOperation::Renew,
+                          Vec::new(),
+                          &session,
+                          &*conn);
+            HttpResponse::Ok().json(renewed)
+        }
+        Err(err) => {
+            debug!("Failed to renew channel {}, err={}", channel, err);
+            Error::DieselError(err).into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn update_channel(req: HttpRequest,
                   path: Path<(String, String)>,
+                  qupdate: Query<ChannelUpdateQuery>,
+                  body: ValidatedJson<ChannelUpdateReq>,
                   state: Data<AppState>)
                   -> HttpResponse {
     let (origin, channel) = path.into_inner();
     let channel = ChannelIdent::from(channel);
 
-    if let Err(_err) = authorize_session(&req, Some(&origin)) {
-        return HttpResponse::new(StatusCode::UNAUTHORIZED);
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
+        Err(_) => return HttpResponse::new(StatusCode::UNAUTHORIZED),
+    };
+
+    // Everything uploaded lands in unstable, it can't be held to a single release per package
+    if body.rolling && channel == ChannelIdent::unstable() {
+        return HttpResponse::new(StatusCode::FORBIDDEN);
+    }
+
+    // Only a channel being made rolling has superseded releases to clean up
+    if qupdate.cleanup && !body.rolling {
+        return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let existing = match Channel::get(&origin, &channel, &*conn) {
+        Ok(existing) => existing,
+        Err(err) => return Error::DieselError(err).into(),
+    };
+
+    let stale = if qupdate.cleanup {
+        match Channel::list_releases(existing.id, &*conn) {
+            Ok(releases) => stale_releases(releases),
+            Err(err) => return Error::DieselError(err).into(),
+        }
+    } else {
+        Vec::new()
+    };
+    let demoted: Vec<String> = stale.iter().map(|r| r.ident.to_string()).collect();
+
+    if qupdate.dry_run {
+        // A dry run fails the way the cleanup itself would
+        if let Err(err) = check_pins(&origin, &channel, &release_idents(&stale), false, &*conn) {
+            return err.into();
+        }
+        let mut channel = existing;
+        channel.rolling = body.rolling;
+        return HttpResponse::Ok().json(ChannelUpdate { channel,
+                                                       demoted,
+                                                       dry_run: true });
+    }
+
+    let updated = conn.transaction::<_, Error, _>(|| {
+                          let updated = Channel::set_rolling(existing.id, body.rolling, &*conn)?;
+                          demote_releases(&updated, &stale, &*conn)?;
+                          Ok(updated)
+                      });
+    match updated {
+        Ok(channel) => {
+            record_demotions(&req, &channel, &stale, &session, &*conn);
+            HttpResponse::Ok().json(ChannelUpdate { channel,
+                                                    demoted,
+                                                    dry_run: false })
+        }
+        Err(err) => {
+            debug!("Failed to update channel {}, err={}", channel, err);
+            err.into()
+        }
     }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn delete_channel(req: HttpRequest,
+                  path: Path<(String, String)>,
+                  state: Data<AppState>)
+                  -> HttpResponse {
+    let (origin, channel) = path.into_inner();
+    let channel = ChannelIdent::from(channel);
+
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
+        Err(_) => return HttpResponse::new(StatusCode::UNAUTHORIZED),
+    };
 
     if channel == ChannelIdent::stable() || channel == ChannelIdent::unstable() {
         return HttpResponse::new(StatusCode::FORBIDDEN);
     }
 
-    state.memcache
-         .borrow_mut()
-         .clear_cache_for_channel(&origin, &channel);
-
     let conn = match state.db.get_conn().map_err(Error::DbError) {
         Ok(conn_ref) => conn_ref,
         Err(err) => return err.into(),
     };
 
-    match Channel::delete(&origin, &channel, &*conn).map_err(Error::DieselError) {
-        Ok(_) => HttpResponse::new(StatusCode::OK),
+    // The channel is locked while its pins are checked and it is deleted, so that a release
+    // can't be pinned in between and demoted along with everything else
+    let deleted = conn.transaction::<_, Error, _>(|| {
+                          Channel::lock(&origin, &channel, &*conn)?;
+                          let pins = ChannelPackagePin::list(&origin, &channel, &*conn)?;
+                          if !pins.is_empty() {
+                              return Err(Error::PackagePinned(pins[0].clone()));
+                          }
+
+                          // The releases the channel held are kept in the audit trail of its
+                          // deletion
+                          let existing = match Channel::get(&origin, &channel, &*conn) {
+                              Ok(existing) => existing,
+                              Err(NotFound) => return Ok(None),
+                              Err(err) => return Err(err.into()),
+                          };
+                          let releases = Channel::list_releases(existing.id, &*conn)?;
+                          Channel::delete(&origin, &channel, &*conn)?;
+                          Ok(Some((existing, releases)))
+                      });
+
+    match deleted {
+        Ok(deleted) => {
+            state.memcache
+                 .borrow_mut()
+                 .clear_cache_for_channel(&origin, &channel);
+            if let Some((existing, releases)) = deleted {
+                audit_channel(&existing,
+                              ChannelOperation::Delete,
+                              releases.iter().map(|r| r.package_id).collect(),
+                              &session,
+                              &*conn);
+            }
+            HttpResponse::new(StatusCode::OK)
+        }
         Err(err) => {
             debug!("Failed to delete channel, err={}", err);
             err.into()
@@ -217,19 +484,15 @@ fn promote_channel_packages(req: HttpRequest,
     let ch_source = ChannelIdent::from(channel);
     let ch_target = ChannelIdent::from(to_channel.channel.as_ref());
 
-    match do_promote_or_demote_channel_packages(&req,
-                                                &ch_source,
-                                                &ch_target,
-                                                &origin,
-                                                true,
-                                                session.get_id() as i64)
+    match do_promote_or_demote_channel_packages(&req, &ch_source, &ch_target, &origin, true,
+                                                &session)
     {
         Ok(pkg_ids) => {
             match PackageGroupChannelAudit::audit(
                 PackageGroupChannelAudit {
                     origin: &origin,
                     channel: &ch_target.as_str(),
-                    package_ids: pkg_ids,
+                    package_ids: pkg_ids.clone(),
                     operation: PackageChannelOperation::Promote,
                     trigger: helpers::trigger_from_request_model(&req),
                     requester_id: session.get_id() as i64,
@@ -241,7 +504,16 @@ fn promote_channel_packages(req: HttpRequest,
                 Ok(_) => {}
                 Err(e) => debug!("Failed to save rank change to audit log: {}", e),
             };
-            HttpResponse::new(StatusCode::OK)
+
+            let mut response = HttpResponse::Ok();
+            if ch_target == ChannelIdent::stable() {
+                for warning in
+                    promoted_release_warnings(&req, &origin, &ch_source, &pkg_ids, &*conn)
+                {
+                    response.header(http::header::WARNING, warning);
+                }
+            }
+            response.finish()
         }
         Err(e) => {
             debug!("Failed to promote channel packages, err={}", e);
@@ -270,12 +542,8 @@ fn demote_channel_packages(req: HttpRequest,
     let ch_source = ChannelIdent::from(channel);
     let ch_target = ChannelIdent::from(to_channel.channel.as_ref());
 
-    match do_promote_or_demote_channel_packages(&req,
-                                                &ch_source,
-                                                &ch_target,
-                                                &origin,
-                                                false,
-                                                session.get_id() as i64)
+    match do_promote_or_demote_channel_packages(&req, &ch_source, &ch_target, &origin, false,
+                                                &session)
     {
         Ok(pkg_ids) => {
             match PackageGroupChannelAudit::audit(
@@ -308,7 +576,7 @@ fn do_promote_or_demote_channel_packages(req: &HttpRequest,
                                          ch_target: &ChannelIdent,
                                          origin: &str,
                                          promote: bool,
-                                         session_id: i64)
+                                         session: &originsrv::Session)
                                          -> Result<Vec<i64>> {
     Counter::AtomicChannelRequests.increment();
     let conn = req_state(req).db.get_conn().map_err(Error::DbError)?;
@@ -340,7 +608,7 @@ fn do_promote_or_demote_channel_packages(req: &HttpRequest,
                     &CreateChannel {
                         name:     &ch_target.as_str(),
                         origin:   &origin,
-                        owner_id: session_id,
+                        owner_id: session.get_id() as i64,
                     },
                 &*conn)?
             } else {
@@ -362,16 +630,20 @@ fn do_promote_or_demote_channel_packages(req: &HttpRequest,
         },
     &*conn)?;
 
+    let releases: Vec<(PackageIdent, PackageTarget)> =
+        op.iter().map(|p| (p.ident.0.clone(), p.target.0)).collect();
+
     let mut ids: Vec<i64> = op.iter().map(|x| x.id).collect();
 
     pkg_ids.append(&mut ids);
 
     if promote {
         debug!("Bulk promoting Pkg IDs: {:?}", &pkg_ids);
-        Channel::promote_packages(channel.id, &pkg_ids, &*conn)?;
+        let superseded = promote_packages(&channel, &pkg_ids, &releases, &*conn)?;
+        record_demotions(req, &channel, &superseded, session, &*conn);
     } else {
         debug!("Bulk demoting Pkg IDs: {:?}", &pkg_ids);
-        Channel::demote_packages(channel.id, &pkg_ids, &*conn)?;
+        demote_packages(&channel, &pkg_ids, &releases, &*conn)?;
     }
     Ok(pkg_ids)
 }
@@ -415,18 +687,30 @@ fn promote_package(req: HttpRequest,
         Err(err) => return err.into(),
     };
 
-    match OriginChannelPackage::promote(
-        OriginChannelPromote {
-            ident: BuilderPackageIdent(ident.clone()),
-            target,
-            origin: origin.clone(),
-            channel: channel.clone(),
-        },
-        &*conn,
-    )
-    .map_err(Error::DieselError)
+    let target_channel = match Channel::get(&origin, &channel, &*conn) {
+        Ok(target_channel) => target_channel,
+        Err(err) => {
+            debug!("Failed to find channel {}, err={}", channel, err);
+            return Error::DieselError(err).into();
+        }
+    };
+    let package = GetPackage { ident:      BuilderPackageIdent(ident.clone()),
+                               visibility: helpers::all_visibilities(),
+                               target:     BuilderPackageTarget(target), };
+    let package = match Package::get(package, &*conn) {
+        Ok(package) => package,
+        Err(err) => {
+            debug!("Failed to find {}, err={}", ident, err);
+            return Error::DieselError(err).into();
+        }
+    };
+
+    match promote_packages(&target_channel,
+                           &[package.id],
+                           &[(ident.clone(), target)],
+                           &*conn)
     {
-        Ok(_) => {
+        Ok(superseded) => {
             match PackageChannelAudit::audit(
                 &PackageChannelAudit {
                     package_ident: BuilderPackageIdent(ident.clone()),
@@ -436,17 +720,37 @@ fn promote_package(req: HttpRequest,
                     requester_id: session.get_id() as i64,
                     requester_name: &session.get_name(),
                     origin: &origin,
+                    package_target: Some(BuilderPackageTarget(target)),
                 },
                 &*conn,
             ) {
                 Ok(_) => {}
                 Err(err) => debug!("Failed to save rank change to audit log: {}", err),
             };
-            state
-                .memcache
-                .borrow_mut()
-                .clear_cache_for_package(&ident);
-            HttpResponse::new(StatusCode::OK)
+            record_demotions(&req, &target_channel, &superseded, &session, &*conn);
+            state.memcache.borrow_mut().clear_cache_for_package(&ident);
+
+            let mut response = HttpResponse::Ok();
+            if let Some(failure) = verification_failure(&ident, target, &*conn) {
+                warn!("Promoted {} to {} though its promotion was refused by post-processing, \
+                       key={}, reason={}",
+                      ident, channel, failure.key_name, failure.reason);
+                response.header(http::header::WARNING, verification_warning(&failure));
+            }
+            if channel == ChannelIdent::stable() {
+                let sources = Package::list_package_channels(&package.ident,
+                                                             target,
+                                                             helpers::all_visibilities(),
+                                                             &*conn).unwrap_or_else(|err| {
+                                                                        debug!("{}", err);
+                                                                        Vec::new()
+                                                                    });
+                let releases = [(package.id, ident.to_string())];
+                for warning in adhoc_warnings(&req, &sources, &releases, &*conn) {
+                    response.header(http::header::WARNING, warning);
+                }
+            }
+            response.finish()
         }
         Err(err) => {
             debug!("Failed to promote package, err={}", err);
@@ -455,6 +759,141 @@ fn promote_package(req: HttpRequest,
     }
 }
 
+// The warnings of a bulk promotion into stable from `source`, for its releases ad-hoc groups built
+fn promoted_release_warnings(req: &HttpRequest,
+                             origin: &str,
+                             source: &ChannelIdent,
+                             package_ids: &[i64],
+                             conn: &PgConnection)
+                             -> Vec<String> {
+    let source = match Channel::get(origin, source, conn) {
+        Ok(source) => source,
+        Err(err) => {
+            debug!("{}", err);
+            return Vec::new();
+        }
+    };
+    if source.channel_type != ChannelType::Ephemeral {
+        return Vec::new();
+    }
+    let releases: Vec<(i64, String)> = match Channel::list_releases(source.id, conn) {
+        Ok(releases) => {
+            releases.into_iter()
+                    .filter(|r| package_ids.contains(&r.package_id))
+                    .map(|r| (r.package_id, r.ident.to_string()))
+                    .collect()
+        }
+        Err(err) => {
+            debug!("{}", err);
+            return Vec::new();
+        }
+    };
+    adhoc_warnings(req, &[source], &releases, conn)
+}
+
+// The warnings of a promotion into stable of releases that ad-hoc groups promoted into one of
+// the ephemeral `sources`. A release whose group can't be looked up is promoted without one.
+fn adhoc_warnings(req: &HttpRequest,
+                  sources: &[Channel],
+                  releases: &[(i64, String)],
+                  conn: &PgConnection)
+                  -> Vec<String> {
+    let mut adhoc_groups: HashMap<i64, bool> = HashMap::new();
+    let mut warnings = Vec::new();
+
+    for source in sources.iter()
+                         .filter(|c| c.channel_type == ChannelType::Ephemeral)
+    {
+        let source_ident = ChannelIdent::from(source.name.as_str());
+        for (package_id, ident) in releases {
+            let group_id = match PackageGroupChannelAudit::latest_group(&source.origin,
+                                                                        &source_ident,
+                                                                        *package_id,
+                                                                        conn)
+            {
+                Ok(Some(group_id)) => group_id,
+                Ok(None) => continue,
+                Err(err) => {
+                    debug!("Failed to look up the group of {}, err={}", ident, err);
+                    continue;
+                }
+            };
+            let adhoc = *adhoc_groups.entry(group_id)
+                                     .or_insert_with(|| is_adhoc_group(req, group_id));
+            let warning = ephemeral_channels::adhoc_warning(ident, group_id as u64);
+            if adhoc && !warnings.contains(&warning) {
+                warn!("Promoted {} to stable from ephemeral channel {}, it was built by ad-hoc \
+                       group {}",
+                      ident, source.name, group_id);
+                warnings.push(warning);
+            }
+        }
+    }
+    warnings
+}
+
+fn is_adhoc_group(req: &HttpRequest, group_id: i64) -> bool {
+    let mut group_get = jobsrv::JobGroupGet::new();
+    group_get.set_group_id(group_id as u64);
+    match route_message::<jobsrv::JobGroupGet, jobsrv::JobGroup>(req, &group_get) {
+        Ok(group) => group.has_trigger() && ephemeral_channels::is_adhoc(group.get_trigger()),
+        Err(err) => {
+            debug!("Failed to look up group {}, err={}", group_id, err);
+            false
+        }
+    }
+}
+
+// Records a step in the lifecycle of an ephemeral channel, or the deletion of any channel
+fn audit_channel(channel: &Channel,
+                 operation: ChannelOperation,
+                 package_ids: Vec<i64>,
+                 session: &originsrv::Session,
+                 conn: &PgConnection) {
+    let audit = ChannelAudit { origin: &channel.origin,
+                               channel: &channel.name,
+                               channel_type: channel.channel_type,
+                               operation,
+                               expires_at: channel.expires_at,
+                               package_ids,
+                               requester_id: session.get_id() as i64,
+                               requester_name: session.get_name() };
+    if let Err(err) = ChannelAudit::audit(&audit, conn) {
+        debug!("Failed to save channel {:?} to audit log: {}",
+               operation, err);
+    }
+}
+
+// The failed signature verification that kept the package from being promoted after its build
+fn verification_failure(ident: &PackageIdent,
+                        target: PackageTarget,
+                        conn: &PgConnection)
+                        -> Option<PackageVerificationFailure> {
+    PackageVerificationFailure::get(&BuilderPackageIdent(ident.clone()),
+                                    BuilderPackageTarget(target),
+                                    conn).unwrap_or_else(|err| {
+                                             warn!("Failed to look up the verification of {}, \
+                                                    err={}",
+                                                   ident, err);
+                                             None
+                                         })
+}
+
+fn verification_warning(failure: &PackageVerificationFailure) -> String {
+    let reason: String = failure.reason
+                                .chars()
+                                .map(|c| {
+                                    if c.is_ascii_graphic() && c != '"' && c != '\\' {
+                                        c
+                                    } else {
+                                        ' '
+                                    }
+                                })
+                                .collect();
+    format!("299 - \"signature did not verify against an accepted key of the origin, key={}, {}\"",
+            failure.key_name, reason)
+}
+
 #[allow(clippy::needless_pass_by_value)]
 fn demote_package(req: HttpRequest,
                   path: Path<(String, String, String, String, String)>,
@@ -498,13 +937,17 @@ fn demote_package(req: HttpRequest,
         Err(err) => return err.into(),
     };
 
-    match OriginChannelPackage::demote(OriginChannelDemote { ident:
+    let demoted = conn.transaction::<_, Error, _>(|| {
+                          check_pins(&origin, &channel, &[(ident.clone(), target)], false, &*conn)?;
+                          let demote = OriginChannelDemote { ident:
                                                                  BuilderPackageIdent(ident.clone()),
                                                              target,
                                                              origin: origin.clone(),
-                                                             channel: channel.clone() },
-                                       &*conn).map_err(Error::DieselError)
-    {
+                                                             channel: channel.clone() };
+                          Ok(OriginChannelPackage::demote(demote, &*conn)?)
+                      });
+
+    match demoted {
         Ok(_) => {
             match PackageChannelAudit::audit(
                 &PackageChannelAudit {
@@ -515,6 +958,7 @@ fn demote_package(req: HttpRequest,
                     requester_id: session.get_id() as i64,
                     requester_name: &session.get_name(),
                     origin: &origin,
+                    package_target: Some(BuilderPackageTarget(target)),
                 },
                 &*conn,
             ) {
@@ -531,6 +975,162 @@ fn demote_package(req: HttpRequest,
     }
 }
 
+#[allow(clippy::needless_pass_by_value)]
+fn pin_package(req: HttpRequest,
+               path: Path<(String, String, String)>,
+               qtarget: Query<Target>,
+               state: Data<AppState>)
+               -> HttpResponse {
+    let (origin, channel, pkg) = path.into_inner();
+    let channel = ChannelIdent::from(channel);
+
+    // Everything uploaded lands in unstable, a pin there would only block uploads
+    if channel == ChannelIdent::unstable() {
+        return HttpResponse::new(StatusCode::FORBIDDEN);
+    }
+
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
+        Err(_) => return HttpResponse::new(StatusCode::UNAUTHORIZED),
+    };
+
+    // TODO: Deprecate target from headers
+    let target = match qtarget.target {
+        Some(ref t) => {
+            trace!("Query requested target = {}", t);
+            match PackageTarget::from_str(t) {
+                Ok(t) => t,
+                Err(err) => {
+                    debug!("Invalid target requested: {}, err = {:?}", t, err);
+                    return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+                }
+            }
+        }
+        None => helpers::target_from_headers(&req),
+    };
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let target = target.to_string();
+    let ident = BuilderPackageIdent(PackageIdent::new(origin.clone(), pkg.clone(), None, None));
+    let visibility = helpers::all_visibilities();
+    let latest = GetLatestPackage { ident:      &ident,
+                                    visibility: &visibility,
+                                    channel:    &channel,
+                                    target:     &target, };
+    // The channel is locked while the release is read and pinned, so a promotion can't slip a
+    // newer one in between
+    let pin = conn.transaction::<_, diesel::result::Error, _>(|| {
+                      Channel::lock(&origin, &channel, &*conn)?;
+                      let latest = Channel::get_latest_package(&latest, &*conn)?;
+                      let pinned = latest.ident.to_string();
+                      let new_pin = NewChannelPackagePin { origin:         &origin,
+                                                           channel:        channel.as_str(),
+                                                           name:           &pkg,
+                                                           target:         &target,
+                                                           ident:          &pinned,
+                                                           pinned_by_id:   session.get_id() as i64,
+                                                           pinned_by_name: session.get_name(), };
+                      ChannelPackagePin::create(&new_pin, &*conn)
+                  });
+    let pin = match pin {
+        Ok(pin) => pin,
+        Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            return match ChannelPackagePin::get(&origin, &channel, &pkg, &target, &*conn) {
+                Ok(pin) => Error::PackagePinned(pin).into(),
+                Err(err) => Error::DieselError(err).into(),
+            };
+        }
+        Err(err) => {
+            debug!("Failed to pin {}/{} in {}, err={}",
+                   origin, pkg, channel, err);
+            return Error::DieselError(err).into();
+        }
+    };
+
+    let audit = PackagePinAudit { origin:         &origin,
+                                  channel:        channel.as_str(),
+                                  name:           &pkg,
+                                  target:         &target,
+                                  ident:          &pin.ident,
+                                  operation:      PackagePinOperation::Pin,
+                                  requester_id:   session.get_id() as i64,
+                                  requester_name: session.get_name(), };
+    if let Err(err) = PackagePinAudit::audit(&audit, &*conn) {
+        debug!("Failed to save pin to audit log: {}", err);
+    }
+
+    HttpResponse::Created().json(pin)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn unpin_package(req: HttpRequest,
+                 path: Path<(String, String, String)>,
+                 qtarget: Query<Target>,
+                 state: Data<AppState>)
+                 -> HttpResponse {
+    let (origin, channel, pkg) = path.into_inner();
+    let channel = ChannelIdent::from(channel);
+
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
+        Err(_) => return HttpResponse::new(StatusCode::UNAUTHORIZED),
+    };
+
+    // Lifting a pin is left to the origin owners, the members it protects against can't
+    if !check_origin_owner(&req, session.get_id(), &origin).unwrap_or(false) {
+        return HttpResponse::new(StatusCode::FORBIDDEN);
+    }
+
+    // TODO: Deprecate target from headers
+    let target = match qtarget.target {
+        Some(ref t) => {
+            trace!("Query requested target = {}", t);
+            match PackageTarget::from_str(t) {
+                Ok(t) => t,
+                Err(err) => {
+                    debug!("Invalid target requested: {}, err = {:?}", t, err);
+                    return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+                }
+            }
+        }
+        None => helpers::target_from_headers(&req),
+    };
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let target = target.to_string();
+    let pin = match ChannelPackagePin::get(&origin, &channel, &pkg, &target, &*conn) {
+        Ok(pin) => pin,
+        Err(err) => return Error::DieselError(err).into(),
+    };
+
+    if let Err(err) = ChannelPackagePin::delete(&origin, &channel, &pkg, &target, &*conn) {
+        debug!("Failed to unpin {} in {}, err={}", pin.ident, channel, err);
+        return Error::DieselError(err).into();
+    }
+
+    let audit = PackagePinAudit { origin:         &origin,
+                                  channel:        channel.as_str(),
+                                  name:           &pkg,
+                                  target:         &target,
+                                  ident:          &pin.ident,
+                                  operation:      PackagePinOperation::Unpin,
+                                  requester_id:   session.get_id() as i64,
+                                  requester_name: session.get_name(), };
+    if let Err(err) = PackagePinAudit::audit(&audit, &*conn) {
+        debug!("Failed to save unpin to audit log: {}", err);
+    }
+
+    HttpResponse::NoContent().finish()
+}
+
 #[allow(clippy::needless_pass_by_value)]
 fn get_packages_for_origin_channel_package_version(req: HttpRequest,
                                                    path: Path<(String, String, String, String)>,
@@ -543,7 +1143,7 @@ fn get_packages_for_origin_channel_package_version(req: HttpRequest,
 
     match do_get_channel_packages(&req, &pagination, &ident, &channel) {
         Ok((packages, count)) => {
-            postprocess_channel_package_list(&req, &packages, count, &pagination)
+            postprocess_channel_package_list(&req, &channel, &packages, count, &pagination)
         }
         Err(Error::NotFound) => HttpResponse::new(StatusCode::NOT_FOUND),
         Err(err) => {
@@ -565,7 +1165,7 @@ fn get_packages_for_origin_channel_package(req: HttpRequest,
 
     match do_get_channel_packages(&req, &pagination, &ident, &channel) {
         Ok((packages, count)) => {
-            postprocess_channel_package_list(&req, &packages, count, &pagination)
+            postprocess_channel_package_list(&req, &channel, &packages, count, &pagination)
         }
         Err(Error::NotFound) => HttpResponse::new(StatusCode::NOT_FOUND),
         Err(err) => {
@@ -588,7 +1188,7 @@ fn get_packages_for_origin_channel(req: HttpRequest,
 
     match do_get_channel_packages(&req, &pagination, &ident, &channel) {
         Ok((packages, count)) => {
-            postprocess_channel_package_list(&req, &packages, count, &pagination)
+            postprocess_channel_package_list(&req, &channel, &packages, count, &pagination)
         }
         Err(Error::NotFound) => HttpResponse::new(StatusCode::NOT_FOUND),
         Err(err) => {
@@ -598,6 +1198,169 @@ fn get_packages_for_origin_channel(req: HttpRequest,
     }
 }
 
+// The latest promotions into a channel as an Atom feed, along with the steps in its lifecycle
+// such as its expiry. Promotions of private releases are only listed to origin members.
+#[allow(clippy::needless_pass_by_value)]
+fn get_channel_feed(req: HttpRequest,
+                    path: Path<(String, String)>,
+                    state: Data<AppState>)
+                    -> HttpResponse {
+    let (origin, channel) = path.into_inner();
+    let channel = ChannelIdent::from(channel);
+
+    let opt_session_id = match authorize_session(&req, None) {
+        Ok(session) => Some(session.get_id()),
+        Err(_) => None,
+    };
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let exists = match Channel::get(&origin, &channel, &*conn) {
+        Ok(_) => true,
+        Err(NotFound) => false,
+        Err(err) => {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    };
+
+    // The events of a channel that expired or was deleted are still served
+    let events = match ChannelAudit::list(&origin, &channel, &*conn) {
+        Ok(events) => events,
+        Err(err) => {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    };
+    if !exists && events.is_empty() {
+        return HttpResponse::new(StatusCode::NOT_FOUND);
+    }
+
+    let visibility = visibility_for_optional_session(&req, opt_session_id, &origin);
+    match Channel::list_promotions(&origin, &channel, &visibility, &*conn) {
+        Ok(promotions) => {
+            let base_url = feed::base_url(&state.config.api);
+            let urn = format!("urn:habitat:promotion:{}:{}", origin, channel);
+            let mut entries: Vec<feed::Entry> =
+                promotions.into_iter()
+                          .filter_map(|p| feed::promotion_entry(base_url, &urn, p))
+                          .chain(events.into_iter()
+                                       .filter_map(|e| feed::channel_event_entry(base_url, e)))
+                          .collect();
+            entries.sort_by(|a, b| b.updated.cmp(&a.updated));
+            entries.truncate(FEED_ENTRIES as usize);
+            let feed = Feed { id: feed::channel_feed_id(&origin, channel.as_str()),
+                              link: feed::self_link(&state.config.api, &req),
+                              title: format!("{}/{} promotions", origin, channel),
+                              entries };
+            feed::respond(&req, &feed)
+        }
+        Err(err) => {
+            debug!("{}", err);
+            Error::DieselError(err).into()
+        }
+    }
+}
+
+// The releases of one target a mirror is missing, holds with another checksum, or holds though
+// they left the channel, computed from the mirror's inventory in one pass. Mirrors with more
+// releases than `sync_diff_max_entries` send their inventory in chunks, see
+// `bldr_core::channel_sync`. Only the releases in a chunk's range are read for it, and only the
+// requests starting a sync, those with a hash or the first chunk, count against the rate limit
+// so that a large mirror is not held up half way through.
+#[allow(clippy::needless_pass_by_value)]
+fn sync_diff(req: HttpRequest,
+             path: Path<(String, String)>,
+             qtarget: Query<Target>,
+             body: ValidatedJson<SyncDiffRequest>,
+             state: Data<AppState>)
+             -> HttpResponse {
+    let (origin, channel) = path.into_inner();
+    let channel = ChannelIdent::from(channel);
+
+    let opt_session_id = match authorize_session(&req, None) {
+        Ok(session) => Some(session.get_id()),
+        Err(_) => None,
+    };
+
+    let caller = match opt_session_id {
+        Some(session_id) => session_id.to_string(),
+        None => {
+            security_events::client_ip(req.head(), &state.config.api.trusted_proxies)
+                .unwrap_or_default()
+        }
+    };
+    let starts_sync = body.inventory_hash.is_some() || body.after.is_none();
+    if starts_sync && !state.sync_diff_limiter.check(&caller) {
+        debug!("Channel sync of {} by {} rate limited", channel, caller);
+        return HttpResponse::new(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    if body.inventory.len() > state.config.api.sync_diff_max_entries {
+        return HttpResponse::new(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    if let Err(reason) = body.validate() {
+        return HttpResponse::BadRequest().body(reason);
+    }
+
+    let target = match qtarget.target {
+        Some(ref t) => {
+            match PackageTarget::from_str(t) {
+                Ok(t) => t,
+                Err(err) => {
+                    debug!("Invalid target requested: {}, err = {:?}", t, err);
+                    return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+                }
+            }
+        }
+        None => helpers::target_from_headers(&req),
+    };
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    match Channel::get(&origin, &channel, &*conn) {
+        Ok(_) => (),
+        Err(NotFound) => return HttpResponse::new(StatusCode::NOT_FOUND),
+        Err(err) => {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    }
+
+    let visibility = visibility_for_optional_session(&req, opt_session_id, &origin);
+    // The hash is of the whole channel, a chunk is compared against its range
+    let (after, until) = match body.inventory_hash {
+        Some(_) => (None, None),
+        None => (body.after.as_ref().map(String::as_str), body.until.as_ref().map(String::as_str)),
+    };
+    let lcc = ListChannelChecksums { visibility: &visibility,
+                                     channel: &channel,
+                                     origin: &origin,
+                                     target,
+                                     after,
+                                     until };
+    match Channel::list_checksums(&lcc, &*conn) {
+        Ok(releases) => {
+            let releases: Vec<(String, String)> =
+                releases.into_iter()
+                        .map(|release| (release.ident, release.checksum))
+                        .collect();
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(channel_sync::diff(&releases, &body))
+        }
+        Err(err) => {
+            debug!("{}", err);
+            Error::DieselError(err).into()
+        }
+    }
+}
+
 #[allow(clippy::needless_pass_by_value)]
 fn get_latest_package_for_origin_channel_package(req: HttpRequest,
                                                  path: Path<(String, String, String)>,
@@ -813,6 +1576,9 @@ fn do_get_channel_package(req: &HttpRequest,
 
     pkg_json["channels"] = json!(channels);
     pkg_json["is_a_service"] = json!(pkg.is_a_service());
+    if let Some(deprecation) = package_deprecation(&pkg.ident, &*conn)? {
+        pkg_json["deprecation"] = json!(deprecation);
+    }
 
     let json_body = serde_json::to_string(&pkg_json).unwrap();
 
@@ -856,9 +1622,182 @@ pub fn channels_for_package_ident(req: &HttpRequest,
     }
 }
 
+/// Fails with the pin in `channel` that promoting the given releases into it, or demoting them
+/// from it, would break. The channel stays locked until the end of the transaction, so a check
+/// made in the transaction of the change it guards can't be raced by a new pin.
+pub fn check_pins(origin: &str,
+                  channel: &ChannelIdent,
+                  releases: &[(PackageIdent, PackageTarget)],
+                  promote: bool,
+                  conn: &PgConnection)
+                  -> Result<()> {
+    Channel::lock(origin, channel, conn)?;
+    let pins = ChannelPackagePin::list(origin, channel, conn)?;
+    for (ident, target) in releases {
+        if let Some(pin) = pins.iter()
+                               .find(|pin| pin_blocks(pin, ident, *target, promote))
+        {
+            return Err(Error::PackagePinned(pin.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Promotes packages, the given releases, into a channel unless a pin there blocks it. A rolling
+/// channel holds a single release of each package, so promoting into one also demotes, in the
+/// same transaction, the releases the promoted ones replace. A pin on any of those fails the
+/// whole promotion. Returns the demoted releases.
+pub fn promote_packages(channel: &Channel,
+                        package_ids: &[i64],
+                        releases: &[(PackageIdent, PackageTarget)],
+                        conn: &PgConnection)
+                        -> Result<Vec<ChannelRelease>> {
+    let channel_ident = ChannelIdent::from(channel.name.as_str());
+    conn.transaction::<_, Error, _>(|| {
+            check_pins(&channel.origin, &channel_ident, releases, true, conn)?;
+            Channel::promote_packages(channel.id, package_ids, conn)?;
+            if !channel.rolling {
+                return Ok(Vec::new());
+            }
+            let releases = Channel::list_releases(channel.id, conn)?;
+            let superseded = superseded_releases(releases, package_ids);
+            demote_releases(channel, &superseded, conn)?;
+            Ok(superseded)
+        })
+}
+
+/// Demotes packages, the given releases, from a channel unless one of them is pinned there
+pub fn demote_packages(channel: &Channel,
+                       package_ids: &[i64],
+                       releases: &[(PackageIdent, PackageTarget)],
+                       conn: &PgConnection)
+                       -> Result<()> {
+    let channel_ident = ChannelIdent::from(channel.name.as_str());
+    conn.transaction::<_, Error, _>(|| {
+            check_pins(&channel.origin, &channel_ident, releases, false, conn)?;
+            Channel::demote_packages(channel.id, package_ids, conn)?;
+            Ok(())
+        })
+}
+
+/// Records releases demoted on the way, by a promotion into a rolling channel or by its
+/// cleanup, in the audit trail and the channel's events feed, and drops them from the cache.
+pub fn record_demotions(req: &HttpRequest,
+                        channel: &Channel,
+                        releases: &[ChannelRelease],
+                        session: &originsrv::Session,
+                        conn: &PgConnection) {
+    if !releases.is_empty() {
+        audit_channel(channel,
+                      ChannelOperation::Supersede,
+                      releases.iter().map(|r| r.package_id).collect(),
+                      session,
+                      conn);
+    }
+    for release in releases {
+        let audit = PackageChannelAudit { package_ident:  release.ident.clone(),
+                                          channel:        &channel.name,
+                                          operation:      PackageChannelOperation::Demote,
+                                          trigger:        helpers::trigger_from_request_model(req),
+                                          requester_id:   session.get_id() as i64,
+                                          requester_name: session.get_name(),
+                                          origin:         &channel.origin,
+                                          package_target: Some(release.target.clone()), };
+        if let Err(err) = PackageChannelAudit::audit(&audit, conn) {
+            debug!("Failed to save rank change to audit log: {}", err);
+        }
+        req_state(req).memcache
+                      .borrow_mut()
+                      .clear_cache_for_package(&release.ident);
+    }
+}
+
+// Demotes releases from the channel unless one of them is pinned there
+fn demote_releases(channel: &Channel,
+                   releases: &[ChannelRelease],
+                   conn: &PgConnection)
+                   -> Result<()> {
+    if releases.is_empty() {
+        return Ok(());
+    }
+    let channel_ident = ChannelIdent::from(channel.name.as_str());
+    check_pins(&channel.origin,
+               &channel_ident,
+               &release_idents(releases),
+               false,
+               conn)?;
+    let ids: Vec<i64> = releases.iter().map(|r| r.package_id).collect();
+    Channel::demote_packages(channel.id, &ids, conn)?;
+    Ok(())
+}
+
+fn release_idents(releases: &[ChannelRelease]) -> Vec<(PackageIdent, PackageTarget)> {
+    releases.iter()
+            .map(|r| (r.ident.0.clone(), r.target.0))
+            .collect()
+}
+
+// Releases of the promoted packages, for the same targets, other than the head each package
+// rolls to. That is the newest of the promoted releases, so a bulk promotion of several
+// releases of a package leaves only one in the channel.
+fn superseded_releases(releases: Vec<ChannelRelease>, promoted: &[i64]) -> Vec<ChannelRelease> {
+    let mut heads: HashMap<(String, PackageTarget), PackageIdent> = HashMap::new();
+    for release in releases.iter().filter(|r| promoted.contains(&r.package_id)) {
+        let key = (release.ident.name.clone(), release.target.0);
+        let head = heads.entry(key).or_insert_with(|| release.ident.0.clone());
+        if release.ident.0 > *head {
+            *head = release.ident.0.clone();
+        }
+    }
+    releases.into_iter()
+            .filter(|r| {
+                heads.get(&(r.ident.name.clone(), r.target.0))
+                     .map_or(false, |head| *head != r.ident.0)
+            })
+            .collect()
+}
+
+// Releases a channel sheds when it is cleaned up on becoming rolling: all but the newest of
+// each package and target
+fn stale_releases(releases: Vec<ChannelRelease>) -> Vec<ChannelRelease> {
+    let mut newest: HashMap<(String, PackageTarget), PackageIdent> = HashMap::new();
+    for release in &releases {
+        let key = (release.ident.name.clone(), release.target.0);
+        let entry = newest.entry(key).or_insert_with(|| release.ident.0.clone());
+        if release.ident.0 > *entry {
+            *entry = release.ident.0.clone();
+        }
+    }
+    releases.into_iter()
+            .filter(|r| newest.get(&(r.ident.name.clone(), r.target.0)) != Some(&r.ident.0))
+            .collect()
+}
+
+// A pin blocks demoting the pinned release and promoting any newer one. Older releases can
+// still be promoted, they don't change what the channel resolves to.
+fn pin_blocks(pin: &ChannelPackagePin,
+              ident: &PackageIdent,
+              target: PackageTarget,
+              promote: bool)
+              -> bool {
+    let pinned = match PackageIdent::from_str(&pin.ident) {
+        Ok(pinned) => pinned,
+        Err(_) => return false,
+    };
+    if pin.target != target.to_string() {
+        return false;
+    }
+    if promote {
+        *ident > pinned
+    } else {
+        *ident == pinned
+    }
+}
+
 // Helper
 
-fn postprocess_channel_package_list(_req: &HttpRequest,
+fn postprocess_channel_package_list(req: &HttpRequest,
+                                    channel: &ChannelIdent,
                                     packages: &[BuilderPackageIdent],
                                     count: i64,
                                     pagination: &Query<Pagination>)
@@ -873,8 +1812,13 @@ fn postprocess_channel_package_list(_req: &HttpRequest,
     debug!("postprocessing channel package list, start: {}, stop: {}, total_count: {}",
            start, stop, count);
 
+    let packages = match mark_pinned(req, channel, packages) {
+        Ok(packages) => packages,
+        Err(err) => return err.into(),
+    };
+
     let body =
-        helpers::package_results_json(packages, count as isize, start as isize, stop as isize);
+        helpers::package_results_json(&packages, count as isize, start as isize, stop as isize);
 
     let mut response = if count as isize > (stop as isize + 1) {
         HttpResponse::PartialContent()
@@ -886,3 +1830,71 @@ fn postprocess_channel_package_list(_req: &HttpRequest,
             .header(http::header::CACHE_CONTROL, headers::NO_CACHE)
             .body(body)
 }
+
+// Flags the packages pinned in the channel, for any target
+fn mark_pinned(req: &HttpRequest,
+               channel: &ChannelIdent,
+               packages: &[BuilderPackageIdent])
+               -> Result<Vec<serde_json::Value>> {
+    let origin = match packages.first() {
+        Some(package) => package.origin.clone(),
+        None => return Ok(Vec::new()),
+    };
+    let conn = req_state(req).db.get_conn().map_err(Error::DbError)?;
+    let pins = ChannelPackagePin::list(&origin, channel, &*conn)?;
+
+    Ok(packages.iter()
+               .map(|package| {
+                   let mut json = json!(package);
+                   if pins.iter().any(|pin| pin.ident == package.to_string()) {
+                       json["pinned"] = json!(true);
+                   }
+                   json
+               })
+               .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(package_id: i64, ident: &str, target: &str) -> ChannelRelease {
+        ChannelRelease { package_id,
+                         ident: BuilderPackageIdent(PackageIdent::from_str(ident).unwrap()),
+                         target: BuilderPackageTarget(PackageTarget::from_str(target).unwrap()) }
+    }
+
+    fn ids(releases: &[ChannelRelease]) -> Vec<i64> {
+        let mut ids: Vec<i64> = releases.iter().map(|r| r.package_id).collect();
+        ids.sort();
+        ids
+    }
+
+    fn channel_releases() -> Vec<ChannelRelease> {
+        vec![release(1, "core/zlib/1.2.11/20190115003728", "x86_64-linux"),
+             release(2, "core/zlib/1.2.11/20190301000000", "x86_64-linux"),
+             release(3, "core/zlib/1.2.11/20190401000000", "x86_64-linux"),
+             release(4, "core/zlib/1.2.11/20190115003728", "x86_64-windows"),
+             release(5, "core/openssl/1.0.2r/20190305210149", "x86_64-linux")]
+    }
+
+    #[test]
+    fn promotion_supersedes_other_releases_of_the_package_and_target() {
+        let superseded = superseded_releases(channel_releases(), &[2]);
+        assert_eq!(ids(&superseded), vec![1, 3]);
+    }
+
+    #[test]
+    fn bulk_promotion_keeps_the_newest_promoted_release() {
+        let superseded = superseded_releases(channel_releases(), &[1, 2, 5]);
+        assert_eq!(ids(&superseded), vec![1, 3]);
+
+        let superseded = superseded_releases(channel_releases(), &[1, 3, 4]);
+        assert_eq!(ids(&superseded), vec![1, 2]);
+    }
+
+    #[test]
+    fn cleanup_keeps_the_newest_release_of_each_package_and_target() {
+        assert_eq!(ids(&stale_releases(channel_releases())), vec![1, 2]);
+    }
+}
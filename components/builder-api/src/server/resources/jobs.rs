@@ -18,7 +18,6 @@ use std::{collections::HashMap,
 use actix_web::{http::{self,
                        StatusCode},
                 web::{self,
-                      Json,
                       Path,
                       Query,
                       ServiceConfig},
@@ -41,15 +40,20 @@ use crate::db::models::{channel::*,
                         projects::*};
 use diesel::result::Error::NotFound;
 
-use crate::server::{authorize::authorize_session,
+use crate::server::{authorize::{authorize_admin,
+                                authorize_session},
                     error::{Error,
                             Result},
                     framework::{headers,
+                                json::ValidatedJson,
                                 middleware::route_message},
                     helpers::{self,
                               req_state,
                               Target},
-                    resources::{channels::channels_for_package_ident,
+                    resources::{channels::{channels_for_package_ident,
+                                           demote_packages,
+                                           promote_packages,
+                                           record_demotions},
                                 pkgs::platforms_for_package_ident}};
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -64,6 +68,29 @@ pub struct GroupDemoteReq {
     pub idents: Vec<String>,
 }
 
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct GroupUpdateReq {
+    /// 0 removes the cap
+    pub max_concurrency: u32,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct FeatureFlagReq {
+    pub name:            String,
+    pub enabled:         bool,
+    /// Percentage of job groups the flag is on for when enabled
+    #[serde(default = "default_rollout_percent")]
+    pub rollout_percent: u32,
+}
+
+fn default_rollout_percent() -> u32 { 100 }
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct GroupApproveReq {
+    /// false rejects the group, canceling it
+    pub approved: bool,
+}
+
 #[derive(Deserialize)]
 pub struct JobLogPagination {
     #[serde(default)]
@@ -83,11 +110,21 @@ impl Jobs {
            .route("/jobs/group/{id}/demote/{channel}",
                   web::post().to(demote_job_group))
            .route("/jobs/group/{id}/cancel", web::post().to(cancel_job_group))
+           .route("/depot/groups/{id}", web::patch().to(update_job_group))
            .route("/rdeps/{origin}/{name}", web::get().to(get_rdeps))
            .route("/rdeps/{origin}/{name}/group",
                   web::get().to(get_rdeps_group))
+           .route("/jobs/workers", web::get().to(get_workers))
+           .route("/admin/graph/verify", web::post().to(verify_graph))
+           .route("/admin/graph/verify",
+                  web::get().to(get_graph_verify_status))
+           .route("/admin/flags", web::get().to(get_feature_flags))
+           .route("/admin/flags", web::put().to(set_feature_flag))
+           .route("/admin/groups/{id}/approve",
+                  web::post().to(approve_job_group))
            .route("/jobs/{id}", web::get().to(get_job))
-           .route("/jobs/{id}/log", web::get().to(get_job_log));
+           .route("/jobs/{id}/log", web::get().to(get_job_log))
+           .route("/jobs/{id}/events", web::get().to(get_job_events));
     }
 }
 
@@ -187,6 +224,125 @@ fn get_job(req: HttpRequest, path: Path<String>) -> HttpResponse {
     }
 }
 
+#[allow(clippy::needless_pass_by_value)]
+fn get_workers(req: HttpRequest) -> HttpResponse {
+    if let Err(err) = authorize_admin(&req) {
+        return err.into();
+    }
+
+    let workers_get = jobsrv::WorkerListGet::new();
+
+    match route_message::<jobsrv::WorkerListGet, jobsrv::WorkerListResponse>(&req, &workers_get) {
+        Ok(workers) => {
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(workers)
+        }
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn verify_graph(req: HttpRequest) -> HttpResponse {
+    if let Err(err) = authorize_admin(&req) {
+        return err.into();
+    }
+
+    let verify = jobsrv::JobGraphVerify::new();
+
+    match route_message::<jobsrv::JobGraphVerify, jobsrv::JobGraphVerifyStatus>(&req, &verify) {
+        Ok(status) => HttpResponse::Accepted().json(status),
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn get_graph_verify_status(req: HttpRequest) -> HttpResponse {
+    if let Err(err) = authorize_admin(&req) {
+        return err.into();
+    }
+
+    let status_get = jobsrv::JobGraphVerifyStatusGet::new();
+
+    match route_message::<jobsrv::JobGraphVerifyStatusGet, jobsrv::JobGraphVerifyStatus>(&req,
+                                                                                        &status_get)
+    {
+        Ok(status) => {
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(status)
+        }
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn get_feature_flags(req: HttpRequest) -> HttpResponse {
+    if let Err(err) = authorize_admin(&req) {
+        return err.into();
+    }
+
+    let list_get = jobsrv::JobFeatureFlagListGet::new();
+
+    match route_message::<jobsrv::JobFeatureFlagListGet, jobsrv::JobFeatureFlagList>(&req,
+                                                                                     &list_get)
+    {
+        Ok(list) => {
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(list)
+        }
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn set_feature_flag(req: HttpRequest, body: ValidatedJson<FeatureFlagReq>) -> HttpResponse {
+    match do_set_feature_flag(&req, &body) {
+        Ok(flag) => HttpResponse::Ok().json(flag),
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn approve_job_group(req: HttpRequest,
+                     path: Path<String>,
+                     body: ValidatedJson<GroupApproveReq>)
+                     -> HttpResponse {
+    let id_str = path.into_inner();
+
+    let group_id = match id_str.parse::<u64>() {
+        Ok(id) => id,
+        Err(e) => {
+            debug!("Error finding id. e = {:?}", e);
+            return HttpResponse::new(StatusCode::BAD_REQUEST);
+        }
+    };
+
+    match do_approve_job_group(&req, group_id, body.approved) {
+        Ok(group) => {
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(group)
+        }
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
 #[allow(clippy::needless_pass_by_value)]
 fn get_job_log(req: HttpRequest,
                path: Path<String>,
@@ -216,10 +372,31 @@ fn get_job_log(req: HttpRequest,
     }
 }
 
+#[allow(clippy::needless_pass_by_value)]
+fn get_job_events(req: HttpRequest, path: Path<String>) -> HttpResponse {
+    let id_str = path.into_inner();
+
+    let job_id = match id_str.parse::<u64>() {
+        Ok(id) => id,
+        Err(e) => {
+            debug!("Error finding id. e = {:?}", e);
+            return HttpResponse::new(StatusCode::BAD_REQUEST);
+        }
+    };
+
+    match do_get_job_events(&req, job_id) {
+        Ok(events) => HttpResponse::Ok().json(events),
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
 #[allow(clippy::needless_pass_by_value)]
 fn promote_job_group(req: HttpRequest,
                      path: Path<(String, String)>,
-                     body: Json<GroupPromoteReq>)
+                     body: ValidatedJson<GroupPromoteReq>)
                      -> HttpResponse {
     let (group_id, channel) = path.into_inner();
     let channel = ChannelIdent::from(channel);
@@ -236,7 +413,7 @@ fn promote_job_group(req: HttpRequest,
 #[allow(clippy::needless_pass_by_value)]
 fn demote_job_group(req: HttpRequest,
                     path: Path<(String, String)>,
-                    body: Json<GroupDemoteReq>)
+                    body: ValidatedJson<GroupDemoteReq>)
                     -> HttpResponse {
     let (group_id, channel) = path.into_inner();
     let channel = ChannelIdent::from(channel);
@@ -271,6 +448,33 @@ fn cancel_job_group(req: HttpRequest, path: Path<String>) -> HttpResponse {
     }
 }
 
+#[allow(clippy::needless_pass_by_value)]
+fn update_job_group(req: HttpRequest,
+                    path: Path<String>,
+                    body: ValidatedJson<GroupUpdateReq>)
+                    -> HttpResponse {
+    let id_str = path.into_inner();
+
+    let group_id = match id_str.parse::<u64>() {
+        Ok(id) => id,
+        Err(e) => {
+            debug!("Error finding id. e = {:?}", e);
+            return HttpResponse::new(StatusCode::BAD_REQUEST);
+        }
+    };
+
+    match do_update_job_group(&req, group_id, &body) {
+        Ok(group) => {
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(group)
+        }
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
 // Internal - these functions should return Result<..>
 //
 fn do_group_promotion_or_demotion(req: &HttpRequest,
@@ -304,6 +508,7 @@ fn do_group_promotion_or_demotion(req: &HttpRequest,
     };
 
     let mut package_ids = Vec::new();
+    let mut releases = Vec::new();
 
     for project in projects {
         req_state(req).memcache
@@ -320,13 +525,15 @@ fn do_group_promotion_or_demotion(req: &HttpRequest,
             &*conn,
         )?;
 
+        releases.push((op.ident.0.clone(), target));
         package_ids.push(op.id);
     }
 
     if promote {
-        Channel::promote_packages(channel.id, &package_ids, &*conn)?;
+        let superseded = promote_packages(&channel, &package_ids, &releases, &*conn)?;
+        record_demotions(req, &channel, &superseded, &session, &*conn);
     } else {
-        Channel::demote_packages(channel.id, &package_ids, &*conn)?;
+        demote_packages(&channel, &package_ids, &releases, &*conn)?;
     }
 
     Ok(package_ids)
@@ -374,7 +581,9 @@ fn promote_or_demote_job_group(req: &HttpRequest,
     // atomically commit the entire promotion/demotion at once, but that would require a cross-shard
     // tool that we don't currently have.
     for project in group.get_projects().iter() {
-        if project.get_state() == jobsrv::JobGroupProjectState::Success {
+        if project.get_state() == jobsrv::JobGroupProjectState::Success
+           || project.get_state() == jobsrv::JobGroupProjectState::Cached
+        {
             let ident_str = project.get_ident();
             if has_idents && !ident_map.contains_key(ident_str) {
                 continue;
@@ -503,6 +712,16 @@ fn do_get_job_log(req: &HttpRequest, job_id: u64, start: u64) -> Result<jobsrv::
     }
 }
 
+// The events of a job are shown to the members of its origin, like the job itself
+fn do_get_job_events(req: &HttpRequest, job_id: u64) -> Result<Vec<JobEvent>> {
+    let conn = req_state(req).db.get_conn().map_err(Error::DbError)?;
+
+    let job: jobsrv::Job = Job::get(job_id as i64, &*conn)?.into();
+    authorize_session(req, Some(&job.get_project().get_origin_name()))?;
+
+    Ok(JobEvent::list(job_id as i64, &*conn)?)
+}
+
 fn do_cancel_job_group(req: &HttpRequest, group_id: u64) -> Result<NetOk> {
     let mut jgg = jobsrv::JobGroupGet::new();
     jgg.set_group_id(group_id);
@@ -523,3 +742,69 @@ fn do_cancel_job_group(req: &HttpRequest, group_id: u64) -> Result<NetOk> {
 
     route_message::<jobsrv::JobGroupCancel, NetOk>(req, &jgc)
 }
+
+fn do_approve_job_group(req: &HttpRequest,
+                        group_id: u64,
+                        approved: bool)
+                        -> Result<jobsrv::JobGroup> {
+    let session = authorize_admin(req)?;
+
+    let mut jga = jobsrv::JobGroupApprove::new();
+    jga.set_group_id(group_id);
+    jga.set_approved(approved);
+    jga.set_requester_id(session.get_id());
+    jga.set_requester_name(session.get_name().to_string());
+
+    route_message::<jobsrv::JobGroupApprove, jobsrv::JobGroup>(req, &jga)
+}
+
+fn do_set_feature_flag(req: &HttpRequest, body: &FeatureFlagReq) -> Result<jobsrv::JobFeatureFlag> {
+    let session = authorize_admin(req)?;
+
+    if body.rollout_percent > 100 {
+        debug!("Rejecting feature flag {} with rollout_percent: {}",
+               body.name, body.rollout_percent);
+        return Err(Error::Unprocessable);
+    }
+
+    let mut set = jobsrv::JobFeatureFlagSet::new();
+    set.set_name(body.name.clone());
+    set.set_enabled(body.enabled);
+    set.set_rollout_percent(body.rollout_percent);
+    set.set_requester_id(session.get_id());
+    set.set_requester_name(session.get_name().to_string());
+
+    route_message::<jobsrv::JobFeatureFlagSet, jobsrv::JobFeatureFlag>(req, &set)
+}
+
+fn do_update_job_group(req: &HttpRequest,
+                       group_id: u64,
+                       body: &GroupUpdateReq)
+                       -> Result<jobsrv::JobGroup> {
+    let ceiling = req_state(req).config.api.max_group_concurrency;
+    if !helpers::valid_max_concurrency(body.max_concurrency, ceiling) {
+        debug!("Rejecting group update with max_concurrency: {}",
+               body.max_concurrency);
+        return Err(Error::Unprocessable);
+    }
+
+    let mut jgg = jobsrv::JobGroupGet::new();
+    jgg.set_group_id(group_id);
+    jgg.set_include_projects(false);
+
+    let group = route_message::<jobsrv::JobGroupGet, jobsrv::JobGroup>(req, &jgg)?;
+
+    let name_split: Vec<&str> = group.get_project_name().split('/').collect();
+    assert!(name_split.len() == 2);
+
+    let session = authorize_session(req, Some(&name_split[0]))?;
+
+    let mut jgu = jobsrv::JobGroupUpdate::new();
+    jgu.set_group_id(group_id);
+    jgu.set_max_concurrency(body.max_concurrency);
+    jgu.set_trigger(helpers::trigger_from_request(req));
+    jgu.set_requester_id(session.get_id());
+    jgu.set_requester_name(session.get_name().to_string());
+
+    route_message::<jobsrv::JobGroupUpdate, jobsrv::JobGroup>(req, &jgu)
+}
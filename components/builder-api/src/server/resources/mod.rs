@@ -1,3 +1,4 @@
+pub mod announcements;
 pub mod authenticate;
 pub mod channels;
 pub mod ext;
@@ -7,4 +8,7 @@ pub mod origins;
 pub mod pkgs;
 pub mod profile;
 pub mod projects;
+pub mod status;
+pub mod telemetry;
+pub mod token;
 pub mod user;
@@ -13,19 +13,37 @@
 // limitations under the License.
 
 use actix_web::{web::{self,
+                      Path,
+                      Query,
                       ServiceConfig},
                 HttpRequest,
                 HttpResponse};
 
-use crate::server::services::github;
+use crate::server::services::{github,
+                              webhook::{self,
+                                        WebhookTarget}};
 
 pub struct Notify;
 
 impl Notify {
     // Route registration
     //
-    pub fn register(cfg: &mut ServiceConfig) { cfg.route("/notify", web::post().to(notify)); }
+    pub fn register(cfg: &mut ServiceConfig) {
+        cfg.route("/notify", web::post().to(notify))
+           .route("/notify/projects/{origin}/{name}",
+                  web::post().to(notify_project));
+    }
 }
 
 #[allow(clippy::needless_pass_by_value)]
 pub fn notify(req: HttpRequest, body: String) -> HttpResponse { github::handle_event(req, body) }
+
+#[allow(clippy::needless_pass_by_value)]
+pub fn notify_project(req: HttpRequest,
+                      path: Path<(String, String)>,
+                      qtarget: Query<WebhookTarget>,
+                      body: String)
+                      -> HttpResponse {
+    let (origin, name) = path.into_inner();
+    webhook::handle_event(&req, &origin, &name, &qtarget.target, &body)
+}
@@ -29,28 +29,30 @@ use actix_web::{body::Body,
                 web::{self,
                       Bytes as ActixBytes,
                       Data,
-                      Json,
                       Path,
                       Query,
                       ServiceConfig},
                 HttpRequest,
                 HttpResponse};
 use bytes::Bytes;
+use chrono::Utc;
 use diesel::{pg::PgConnection,
-             result::Error::NotFound};
+             result::Error::NotFound,
+             Connection};
 use serde_json;
 
-use crate::{bldr_core,
+use crate::{bldr_core::{self,
+                        privilege::FeatureFlags},
             hab_core::{crypto::{keys::{box_key_pair::WrappedSealedBox,
                                        parse_key_str,
                                        parse_name_with_rev,
                                        PairType},
-                                BoxKeyPair,
-                                SigKeyPair},
+                                BoxKeyPair},
                        package::{ident,
                                  PackageIdent}}};
 
-use crate::protocol::originsrv::OriginKeyIdent;
+use crate::protocol::{jobsrv,
+                      originsrv::OriginKeyIdent};
 
 use crate::db::models::{account::*,
                         integration::*,
@@ -58,20 +60,35 @@ use crate::db::models::{account::*,
                         keys::*,
                         origin::*,
                         package::{BuilderPackageIdent,
+                                  BuilderPackageTarget,
                                   ListPackages,
                                   Package,
+                                  PackageEncryption,
                                   PackageVisibility},
                         secrets::*};
 
-use crate::server::{authorize::{authorize_session,
+use crate::server::{authorize::{authorize_admin,
+                                authorize_session,
                                 check_origin_owner},
                     error::{Error,
                             Result},
-                    framework::headers,
+                    framework::{headers,
+                                json::{StrictJson,
+                                       ValidatedJson},
+                                middleware::route_message},
                     helpers::{self,
                               req_state,
                               Pagination},
                     resources::pkgs::postprocess_package_list,
+                    services::{artifact_encryption::{self,
+                                                     KeyProvider},
+                               invitations,
+                               key_rotation::{self,
+                                              RotationRequester},
+                               member_activity,
+                               security_events,
+                               upload_policy::{self,
+                                               Violation}},
                     AppState};
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -91,8 +108,83 @@ pub struct CreateOriginHandlerReq {
 #[derive(Clone, Serialize, Deserialize)]
 pub struct UpdateOriginHandlerReq {
     pub default_package_visibility: Option<PackageVisibility>,
+    pub upload_policy:              Option<UploadPolicyReq>,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UploadPolicyReq {
+    #[serde(default)]
+    pub require_license:              bool,
+    #[serde(default)]
+    pub allowed_licenses:             Vec<String>,
+    #[serde(default)]
+    pub blocked_licenses:             Vec<String>,
+    #[serde(default)]
+    pub require_description:          bool,
+    #[serde(default)]
+    pub strict_dependency_validation: bool,
+}
+
+// A stored package the origin's upload policy would now reject
+#[derive(Serialize)]
+struct PolicyViolatingPackage {
+    ident:      BuilderPackageIdent,
+    target:     BuilderPackageTarget,
+    violations: Vec<Violation>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ArtifactEncryptionReq {
+    pub key_id: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EmailInvitationReq {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct MemberActivityQuery {
+    #[serde(default)]
+    pub stale_days: Option<u32>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KeyRotationReq {
+    pub enabled:       bool,
+    pub interval_days: u32,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FreezeWindowsReq {
+    pub windows: Vec<FreezeWindowReq>,
+}
+
+/// A weekly window sets the days and times, a one-off window `starts_at` and `ends_at`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FreezeWindowReq {
+    pub kind:       String,
+    pub timezone:   String,
+    #[serde(default)]
+    pub start_day:  String,
+    #[serde(default)]
+    pub start_time: String,
+    #[serde(default)]
+    pub end_day:    String,
+    #[serde(default)]
+    pub end_time:   String,
+    #[serde(default)]
+    pub starts_at:  String,
+    #[serde(default)]
+    pub ends_at:    String,
+}
+
+// Ten years, longer intervals are better expressed by disabling rotation
+const MAX_KEY_ROTATION_INTERVAL_DAYS: u32 = 3650;
+
+// Packages read at a time when reporting upload policy violations
+const POLICY_REPORT_BATCH_SIZE: i64 = 500;
+
 pub struct Origins {}
 
 impl Origins {
@@ -104,12 +196,28 @@ impl Origins {
            .route("/depot/origins/{origin}", web::put().to(update_origin))
            .route("/depot/origins/{origin}", web::delete().to(delete_origin))
            .route("/depot/origins", web::post().to(create_origin))
+           .route("/depot/origins/{origin}/upload_policy/violations",
+                  web::get().to(list_upload_policy_violations))
+           .route("/depot/origins/{origin}/freeze_windows",
+                  web::get().to(get_freeze_windows))
+           .route("/depot/origins/{origin}/freeze_windows",
+                  web::put().to(update_freeze_windows))
+           .route("/depot/origins/{origin}/artifact_encryption",
+                  web::get().to(get_artifact_encryption))
+           .route("/depot/origins/{origin}/artifact_encryption",
+                  web::put().to(update_artifact_encryption))
+           .route("/depot/origins/{origin}/artifact_encryption",
+                  web::delete().to(delete_artifact_encryption))
            .route("/depot/origins/{origin}/users",
                   web::get().to(list_origin_members))
            .route("/depot/origins/{origin}/users/{user}",
                   web::delete().to(origin_member_delete))
+           .route("/depot/origins/{origin}/members/activity",
+                  web::get().to(list_origin_member_activity))
            .route("/depot/origins/{origin}/invitations",
                   web::get().to(list_origin_invitations))
+           .route("/depot/origins/{origin}/invitations",
+                  web::post().to(invite_email_to_origin))
            .route("/depot/origins/{origin}/users/{username}/invitations",
                   web::post().to(invite_to_origin))
            .route("/depot/origins/{origin}/invitations/{invitation_id}",
@@ -118,11 +226,19 @@ impl Origins {
                   web::delete().to(rescind_invitation))
            .route("/depot/origins/{origin}/invitations/{invitation_id}/ignore",
                   web::put().to(ignore_invitation))
+           .route("/depot/origins/{origin}/invitations/{invitation_id}/resend",
+                  web::put().to(resend_invitation))
            .route("/depot/origins/{origin}/keys/latest",
                   web::get().to(download_latest_origin_key))
            .route("/depot/origins/{origin}/keys", web::post().to(create_keys))
            .route("/depot/origins/{origin}/keys",
                   web::get().to(list_origin_keys))
+           .route("/depot/origins/{origin}/keys/rotation",
+                  web::get().to(get_key_rotation))
+           .route("/depot/origins/{origin}/keys/rotation",
+                  web::put().to(update_key_rotation))
+           .route("/depot/origins/{origin}/keys/rotate",
+                  web::post().to(rotate_keys))
            .route("/depot/origins/{origin}/keys/{revision}",
                   web::post().to(upload_origin_key))
            .route("/depot/origins/{origin}/keys/{revision}",
@@ -163,12 +279,24 @@ fn get_origin(path: Path<String>, state: Data<AppState>) -> HttpResponse {
         Err(err) => return err.into(),
     };
 
-    match Origin::get(&origin_name, &*conn) {
-        Ok(origin) => {
+    let origin = match Origin::get(&origin_name, &*conn) {
+        Ok(origin) => origin,
+        Err(NotFound) => return HttpResponse::NotFound().into(),
+        Err(err) => {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    };
+
+    match OriginUploadPolicy::get(&origin_name, &*conn) {
+        Ok(policy) => {
+            let mut body = serde_json::to_value(origin).unwrap();
+            if let Some(policy) = policy {
+                body["upload_policy"] = json!(policy);
+            }
             HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
-                              .json(origin)
+                              .json(body)
         }
-        Err(NotFound) => HttpResponse::NotFound().into(),
         Err(err) => {
             debug!("{}", err);
             Error::DieselError(err).into()
@@ -178,7 +306,7 @@ fn get_origin(path: Path<String>, state: Data<AppState>) -> HttpResponse {
 
 #[allow(clippy::needless_pass_by_value)]
 fn create_origin(req: HttpRequest,
-                 body: Json<CreateOriginHandlerReq>,
+                 body: ValidatedJson<CreateOriginHandlerReq>,
                  state: Data<AppState>)
                  -> HttpResponse {
     let session = match authorize_session(&req, None) {
@@ -216,30 +344,381 @@ fn create_origin(req: HttpRequest,
 #[allow(clippy::needless_pass_by_value)]
 fn update_origin(req: HttpRequest,
                  path: Path<String>,
-                 body: Json<UpdateOriginHandlerReq>,
+                 body: ValidatedJson<UpdateOriginHandlerReq>,
                  state: Data<AppState>)
                  -> HttpResponse {
     let origin = path.into_inner();
 
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
+        Err(err) => return err.into(),
+    };
+
+    if let Some(ref policy) = body.upload_policy {
+        match check_origin_owner(&req, session.get_id(), &origin) {
+            Ok(true) => (),
+            Ok(false) => return HttpResponse::new(StatusCode::FORBIDDEN),
+            Err(err) => return err.into(),
+        }
+        if let Err(msg) = validate_upload_policy(policy) {
+            return HttpResponse::with_body(StatusCode::UNPROCESSABLE_ENTITY,
+                                           Body::from_message(msg));
+        }
+    }
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    // A body without an upload policy sets the visibility, public unless given, as it always has
+    if body.default_package_visibility.is_some() || body.upload_policy.is_none() {
+        let dpv = match body.default_package_visibility.clone() {
+            Some(viz) => viz,
+            None => PackageVisibility::Public,
+        };
+
+        if let Err(err) = Origin::update(&origin, dpv, &*conn).map_err(Error::DieselError) {
+            debug!("{}", err);
+            return err.into();
+        }
+    }
+
+    if let Some(ref policy) = body.upload_policy {
+        let new_policy = NewOriginUploadPolicy { origin: &origin,
+                                                 require_license: policy.require_license,
+                                                 allowed_licenses: &policy.allowed_licenses,
+                                                 blocked_licenses: &policy.blocked_licenses,
+                                                 require_description: policy.require_description,
+                                                 strict_dependency_validation:
+                                                     policy.strict_dependency_validation, };
+
+        if let Err(err) = OriginUploadPolicy::upsert(&new_policy, &*conn) {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    }
+
+    HttpResponse::NoContent().into()
+}
+
+fn validate_upload_policy(policy: &UploadPolicyReq) -> std::result::Result<(), String> {
+    let licenses = policy.allowed_licenses
+                         .iter()
+                         .chain(policy.blocked_licenses.iter());
+    for license in licenses {
+        if license.trim().is_empty() || license.chars().any(char::is_whitespace) {
+            return Err(format!("Invalid license identifier {:?}", license));
+        }
+    }
+
+    for license in &policy.allowed_licenses {
+        if policy.blocked_licenses
+                 .iter()
+                 .any(|blocked| blocked.eq_ignore_ascii_case(license))
+        {
+            return Err(format!("{} is both allowed and blocked", license));
+        }
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn list_upload_policy_violations(req: HttpRequest,
+                                 path: Path<String>,
+                                 state: Data<AppState>)
+                                 -> HttpResponse {
+    let origin = path.into_inner();
+
+    if let Err(err) = authorize_admin(&req) {
+        return err.into();
+    }
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let policy = match OriginUploadPolicy::get(&origin, &*conn) {
+        Ok(Some(policy)) => policy,
+        Ok(None) => return HttpResponse::NotFound().into(),
+        Err(err) => {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    };
+
+    match policy_violating_packages(&policy, &*conn) {
+        Ok(packages) => {
+            let body = json!({
+                "origin": origin,
+                "upload_policy": policy,
+                "packages": packages
+            });
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(body)
+        }
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+// Every package stored in the policy's origin that would fail it, read a batch at a time as
+// origins can hold many packages
+fn policy_violating_packages(policy: &OriginUploadPolicy,
+                             conn: &PgConnection)
+                             -> Result<Vec<PolicyViolatingPackage>> {
+    let mut packages = Vec::new();
+    let mut after_id = 0;
+    loop {
+        let batch =
+            Package::list_manifests(&policy.origin, after_id, POLICY_REPORT_BATCH_SIZE, conn)?;
+        for package in &batch {
+            let violations = upload_policy::violations(policy, &package.manifest);
+            if !violations.is_empty() {
+                packages.push(PolicyViolatingPackage { ident: package.ident.clone(),
+                                                       target: package.target.clone(),
+                                                       violations });
+            }
+        }
+
+        match batch.last() {
+            Some(last) if batch.len() as i64 == POLICY_REPORT_BATCH_SIZE => after_id = last.id,
+            _ => return Ok(packages),
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn get_artifact_encryption(req: HttpRequest,
+                           path: Path<String>,
+                           state: Data<AppState>)
+                           -> HttpResponse {
+    let origin = path.into_inner();
+
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
+        Err(err) => return err.into(),
+    };
+
+    match check_origin_owner(&req, session.get_id(), &origin) {
+        Ok(true) => (),
+        Ok(false) => return HttpResponse::new(StatusCode::FORBIDDEN),
+        Err(err) => return err.into(),
+    }
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    match OriginArtifactEncryption::get(&origin, &*conn) {
+        Ok(Some(encryption)) => {
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(encryption)
+        }
+        Ok(None) => HttpResponse::NotFound().into(),
+        Err(err) => {
+            debug!("{}", err);
+            Error::DieselError(err).into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn get_freeze_windows(req: HttpRequest, path: Path<String>) -> HttpResponse {
+    let origin = path.into_inner();
+
     if let Err(err) = authorize_session(&req, Some(&origin)) {
         return err.into();
     }
 
+    let mut request = jobsrv::JobFreezeWindowsGet::new();
+    request.set_origin(origin);
+
+    match route_message::<jobsrv::JobFreezeWindowsGet, jobsrv::JobFreezeWindows>(&req, &request) {
+        Ok(windows) => {
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(windows)
+        }
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+// Replaces the origin's build freeze windows. The job server checks the windows themselves.
+#[allow(clippy::needless_pass_by_value)]
+fn update_freeze_windows(req: HttpRequest,
+                         path: Path<String>,
+                         body: ValidatedJson<FreezeWindowsReq>)
+                         -> HttpResponse {
+    let origin = path.into_inner();
+
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
+        Err(err) => return err.into(),
+    };
+
+    if !check_origin_owner(&req, session.get_id(), &origin).unwrap_or(false) {
+        return HttpResponse::new(StatusCode::FORBIDDEN);
+    }
+
+    let mut windows = Vec::new();
+    for window in &body.windows {
+        let kind = match window.kind.parse::<jobsrv::JobFreezeWindowKind>() {
+            Ok(kind) => kind,
+            Err(_) => {
+                let msg = format!("Unknown freeze window kind {}", window.kind);
+                return HttpResponse::with_body(StatusCode::UNPROCESSABLE_ENTITY,
+                                               Body::from_message(msg));
+            }
+        };
+        let mut msg = jobsrv::JobFreezeWindow::new();
+        msg.set_kind(kind);
+        msg.set_timezone(window.timezone.clone());
+        msg.set_start_day(window.start_day.clone());
+        msg.set_start_time(window.start_time.clone());
+        msg.set_end_day(window.end_day.clone());
+        msg.set_end_time(window.end_time.clone());
+        msg.set_starts_at(window.starts_at.clone());
+        msg.set_ends_at(window.ends_at.clone());
+        windows.push(msg);
+    }
+
+    let mut request = jobsrv::JobFreezeWindowsSet::new();
+    request.set_origin(origin);
+    request.set_windows(protobuf::RepeatedField::from_vec(windows));
+    request.set_requester_id(session.get_id());
+    request.set_requester_name(session.get_name().to_string());
+
+    match route_message::<jobsrv::JobFreezeWindowsSet, jobsrv::JobFreezeWindows>(&req, &request) {
+        Ok(windows) => HttpResponse::Ok().json(windows),
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+// Sets the key the origin's artifacts are encrypted under. Changing the key re-wraps the data
+// keys of the artifacts already stored, the artifacts themselves are left as they are.
+#[allow(clippy::needless_pass_by_value)]
+fn update_artifact_encryption(req: HttpRequest,
+                              path: Path<String>,
+                              body: ValidatedJson<ArtifactEncryptionReq>,
+                              state: Data<AppState>)
+                              -> HttpResponse {
+    let origin = path.into_inner();
+
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
+        Err(err) => return err.into(),
+    };
+
+    match check_origin_owner(&req, session.get_id(), &origin) {
+        Ok(true) => (),
+        Ok(false) => return HttpResponse::new(StatusCode::FORBIDDEN),
+        Err(err) => return err.into(),
+    }
+
+    let key_id = &body.key_id;
+    let keys = &*state.artifact_keys;
+    if !artifact_encryption::valid_key_id(key_id) || !keys.has_key(key_id) {
+        let msg = format!("No artifact encryption key {}", key_id);
+        return HttpResponse::with_body(StatusCode::UNPROCESSABLE_ENTITY, Body::from_message(msg));
+    }
+
     let conn = match state.db.get_conn().map_err(Error::DbError) {
         Ok(conn_ref) => conn_ref,
         Err(err) => return err.into(),
     };
 
-    let dpv = match body.0.default_package_visibility {
-        Some(viz) => viz,
-        None => PackageVisibility::Public,
+    match Origin::get(&origin, &*conn) {
+        Ok(ref o) if o.default_package_visibility == PackageVisibility::Private => (),
+        Ok(_) => {
+            let msg = "Artifact encryption is only offered to private origins";
+            return HttpResponse::with_body(StatusCode::UNPROCESSABLE_ENTITY,
+                                           Body::from_message(msg));
+        }
+        Err(err) => return Error::DieselError(err).into(),
+    }
+
+    let rewrapped =
+        conn.transaction::<_, Error, _>(|| set_artifact_encryption(&origin, key_id, keys, &*conn));
+
+    match rewrapped {
+        Ok(rewrapped) => {
+            let body = json!({
+                "origin": origin,
+                "key_id": key_id,
+                "rewrapped": rewrapped
+            });
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(body)
+        }
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+// Re-wraps the data keys of the origin's artifacts with the key and sets it, returning how many
+// were re-wrapped
+fn set_artifact_encryption(origin: &str,
+                           key_id: &str,
+                           keys: &dyn KeyProvider,
+                           conn: &PgConnection)
+                           -> Result<usize> {
+    let stored = PackageEncryption::list_not_under_key(origin, key_id, conn)?;
+    for encryption in &stored {
+        let wrapped = artifact_encryption::rewrap(keys,
+                                                  &encryption.key_id,
+                                                  key_id,
+                                                  &encryption.wrapped_data_key)?;
+        encryption.rewrap(key_id, &wrapped, conn)?;
+    }
+
+    let new_encryption = NewOriginArtifactEncryption { origin, key_id };
+    OriginArtifactEncryption::upsert(&new_encryption, conn)?;
+    Ok(stored.len())
+}
+
+// Stops encrypting new uploads. Artifacts already encrypted stay so and are still decrypted on
+// download, so their keys must be kept.
+#[allow(clippy::needless_pass_by_value)]
+fn delete_artifact_encryption(req: HttpRequest,
+                              path: Path<String>,
+                              state: Data<AppState>)
+                              -> HttpResponse {
+    let origin = path.into_inner();
+
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
+        Err(err) => return err.into(),
+    };
+
+    match check_origin_owner(&req, session.get_id(), &origin) {
+        Ok(true) => (),
+        Ok(false) => return HttpResponse::new(StatusCode::FORBIDDEN),
+        Err(err) => return err.into(),
+    }
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
     };
 
-    match Origin::update(&origin, dpv, &*conn).map_err(Error::DieselError) {
+    match OriginArtifactEncryption::delete(&origin, &*conn) {
         Ok(_) => HttpResponse::NoContent().into(),
         Err(err) => {
             debug!("{}", err);
-            err.into()
+            Error::DieselError(err).into()
         }
     }
 }
@@ -282,15 +761,6 @@ fn create_keys(req: HttpRequest, path: Path<String>, state: Data<AppState>) -> H
         Err(err) => return err.into(),
     };
 
-    let pair = match SigKeyPair::generate_pair_for_origin(&origin).map_err(Error::HabitatCore) {
-        Ok(pair) => pair,
-        Err(err) => {
-            error!("Failed to generate origin key pair for {}, err={}",
-                   origin, err);
-            return err.into();
-        }
-    };
-
     let conn = match state.db.get_conn().map_err(Error::DbError) {
         Ok(conn_ref) => conn_ref,
         Err(err) => {
@@ -299,53 +769,126 @@ fn create_keys(req: HttpRequest, path: Path<String>, state: Data<AppState>) -> H
         }
     };
 
-    let pk_body = match pair.to_public_string().map_err(Error::HabitatCore) {
-        Ok(pk) => pk.into_bytes(),
+    match key_rotation::create_signing_keys(&origin, account_id as i64, &*conn) {
+        Ok(_) => HttpResponse::Created().finish(),
         Err(err) => {
-            error!("create_keys: Failed to get pk body, err={}", err);
-            return err.into();
+            error!("create_keys: Failed to create origin key pair for {}, err={}",
+                   origin, err);
+            err.into()
         }
-    };
+    }
+}
 
-    let new_pk = NewOriginPublicSigningKey { owner_id:  account_id as i64,
-                                             origin:    &origin,
-                                             full_name: &format!("{}-{}", &origin, &pair.rev),
-                                             name:      &origin,
-                                             revision:  &pair.rev,
-                                             body:      &pk_body, };
+#[allow(clippy::needless_pass_by_value)]
+fn get_key_rotation(req: HttpRequest, path: Path<String>, state: Data<AppState>) -> HttpResponse {
+    let origin = path.into_inner();
 
-    match OriginPublicSigningKey::create(&new_pk, &*conn).map_err(Error::DieselError) {
-        Ok(_) => (),
+    if let Err(err) = authorize_session(&req, Some(&origin)) {
+        return err.into();
+    }
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    match OriginKeyRotation::get(&origin, &*conn) {
+        Ok(policy) => {
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(policy)
+        }
+        Err(NotFound) => HttpResponse::NotFound().into(),
         Err(err) => {
-            error!("create_keys: Failed to create public key, err={}", err);
-            return err.into();
+            debug!("{}", err);
+            Error::DieselError(err).into()
         }
     }
+}
 
-    let sk_body = match pair.to_secret_string().map_err(Error::HabitatCore) {
-        Ok(sk) => sk.into_bytes(),
+#[allow(clippy::needless_pass_by_value)]
+fn update_key_rotation(req: HttpRequest,
+                       path: Path<String>,
+                       body: ValidatedJson<KeyRotationReq>,
+                       state: Data<AppState>)
+                       -> HttpResponse {
+    let origin = path.into_inner();
+
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
+        Err(err) => return err.into(),
+    };
+
+    if !check_origin_owner(&req, session.get_id(), &origin).unwrap_or(false) {
+        return HttpResponse::new(StatusCode::FORBIDDEN);
+    }
+
+    if body.interval_days == 0 || body.interval_days > MAX_KEY_ROTATION_INTERVAL_DAYS {
+        let msg = format!("interval_days must be between 1 and {}",
+                          MAX_KEY_ROTATION_INTERVAL_DAYS);
+        return HttpResponse::with_body(StatusCode::UNPROCESSABLE_ENTITY, Body::from_message(msg));
+    }
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let new_policy = NewOriginKeyRotation { origin:        &origin,
+                                            enabled:       body.enabled,
+                                            interval_days: body.interval_days as i32, };
+
+    match OriginKeyRotation::upsert(&new_policy, &*conn) {
+        Ok(policy) => {
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(policy)
+        }
         Err(err) => {
-            error!("create_keys: Failed to get sk body, err={}", err);
-            return err.into();
+            debug!("{}", err);
+            Error::DieselError(err).into()
         }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn rotate_keys(req: HttpRequest, path: Path<String>, state: Data<AppState>) -> HttpResponse {
+    let origin = path.into_inner();
+
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
+        Err(err) => return err.into(),
     };
 
-    let new_sk = NewOriginPrivateSigningKey { owner_id:  account_id as i64,
-                                              origin:    &origin,
-                                              full_name: &format!("{}-{}", &origin, &pair.rev),
-                                              name:      &origin,
-                                              revision:  &pair.rev,
-                                              body:      &sk_body, };
+    if !check_origin_owner(&req, session.get_id(), &origin).unwrap_or(false) {
+        return HttpResponse::new(StatusCode::FORBIDDEN);
+    }
 
-    match OriginPrivateSigningKey::create(&new_sk, &*conn).map_err(Error::DieselError) {
-        Ok(_) => (),
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let requester = RotationRequester { trigger:        KeyRotationTrigger::Manual,
+                                        requester_id:   session.get_id() as i64,
+                                        requester_name: session.get_name(), };
+
+    match key_rotation::rotate(&origin,
+                               &requester,
+                               state.config.api.key_rotation_overlap_days,
+                               &*conn)
+    {
+        Ok(revision) => {
+            let mut ident = OriginKeyIdent::new();
+            ident.set_location(format!("/origins/{}/keys/{}", &origin, &revision));
+            ident.set_origin(origin);
+            ident.set_revision(revision);
+            HttpResponse::Created().json(&ident)
+        }
         Err(err) => {
-            error!("create_keys: Failed to create private key, err={}", err);
-            return err.into();
+            error!("rotate_keys: Failed to rotate signing key for {}, err={}",
+                   origin, err);
+            err.into()
         }
     }
-
-    HttpResponse::Created().finish()
 }
 
 #[allow(clippy::needless_pass_by_value)]
@@ -367,6 +910,7 @@ fn list_origin_keys(path: Path<String>, state: Data<AppState>) -> HttpResponse {
                                                    &key.name, &key.revision));
                         ident.set_origin(key.name.to_string());
                         ident.set_revision(key.revision.to_string());
+                        ident.set_expired(key.is_expired());
                         ident
                     })
                     .collect();
@@ -506,9 +1050,10 @@ fn list_origin_secrets(req: HttpRequest,
     }
 }
 
+// Strict, so that a misspelt field is reported as such rather than as a missing value
 #[allow(clippy::needless_pass_by_value)]
 fn create_origin_secret(req: HttpRequest,
-                        body: Json<OriginSecretPayload>,
+                        body: StrictJson<OriginSecretPayload>,
                         path: Path<String>,
                         state: Data<AppState>)
                         -> HttpResponse {
@@ -842,6 +1387,7 @@ fn invite_to_origin(req: HttpRequest,
         Err(err) => return err.into(),
     };
 
+    let expires_at = invitations::expires_at(&state.config.api);
     let (recipient_id, recipient_name) =
         match Account::get(&user, &*conn).map_err(Error::DieselError) {
             Ok(account) => (account.id, account.name),
@@ -851,14 +1397,20 @@ fn invite_to_origin(req: HttpRequest,
             }
         };
 
-    let new_invitation = NewOriginInvitation { origin:       &origin,
-                                               account_id:   recipient_id,
+    let new_invitation = NewOriginInvitation { origin: &origin,
+                                               account_id: recipient_id,
                                                account_name: &recipient_name,
-                                               owner_id:     account_id as i64, };
+                                               owner_id: account_id as i64,
+                                               expires_at };
 
     // store invitations in the originsrv
     match OriginInvitation::create(&new_invitation, &*conn).map_err(Error::DieselError) {
-        Ok(invitation) => HttpResponse::Created().json(&invitation),
+        Ok(invitation) => {
+            if let Err(err) = invitations::send(&invitation, &state.config.api, &*conn) {
+                warn!("Unable to notify invitation {}, err={}", invitation.id, err);
+            }
+            HttpResponse::Created().json(&invitation)
+        }
         // TODO (SA): Check for error case where invitation already exists
         Err(err) => {
             debug!("{}", err);
@@ -867,6 +1419,107 @@ fn invite_to_origin(req: HttpRequest,
     }
 }
 
+#[allow(clippy::needless_pass_by_value)]
+fn invite_email_to_origin(req: HttpRequest,
+                          path: Path<String>,
+                          body: ValidatedJson<EmailInvitationReq>,
+                          state: Data<AppState>)
+                          -> HttpResponse {
+    let origin = path.into_inner();
+
+    let account_id = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session.get_id(),
+        Err(err) => return err.into(),
+    };
+
+    let email = match invitations::normalize_email(&body.email) {
+        Some(email) => email,
+        None => return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY),
+    };
+
+    debug!("Creating invitation for email {} origin {}",
+           &email, &origin);
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let expires_at = invitations::expires_at(&state.config.api);
+    let new_invitation = NewOriginEmailInvitation { origin: &origin,
+                                                    email: &email,
+                                                    owner_id: account_id as i64,
+                                                    expires_at };
+
+    match OriginInvitation::create_for_email(&new_invitation, &*conn).map_err(Error::DieselError) {
+        Ok(invitation) => {
+            if let Err(err) = invitations::send(&invitation, &state.config.api, &*conn) {
+                warn!("Unable to send invitation {} to {}, err={}",
+                      invitation.id, &email, err);
+            }
+            HttpResponse::Created().json(&invitation)
+        }
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn resend_invitation(req: HttpRequest,
+                     path: Path<(String, String)>,
+                     state: Data<AppState>)
+                     -> HttpResponse {
+    let (origin, invitation) = path.into_inner();
+
+    if let Err(err) = authorize_session(&req, Some(&origin)) {
+        return err.into();
+    }
+
+    let invitation_id = match invitation.parse::<u64>() {
+        Ok(invitation_id) => invitation_id,
+        Err(err) => {
+            debug!("{}", err);
+            return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    };
+
+    debug!("Resending invitation id {} for origin {}",
+           invitation_id, &origin);
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    match OriginInvitation::get(invitation_id, &*conn) {
+        Ok(ref invitation) if invitation.origin == origin => (),
+        Ok(_) => return HttpResponse::new(StatusCode::NOT_FOUND),
+        Err(err) => {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    }
+
+    let expires_at = invitations::expires_at(&state.config.api);
+    let invitation = match OriginInvitation::renew(invitation_id, expires_at, &*conn) {
+        Ok(invitation) => invitation,
+        Err(err) => {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    };
+
+    match invitations::send(&invitation, &state.config.api, &*conn) {
+        Ok(_) => HttpResponse::Ok().json(&invitation),
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
 #[allow(clippy::needless_pass_by_value)]
 fn accept_invitation(req: HttpRequest,
                      path: Path<(String, String)>,
@@ -892,8 +1545,30 @@ fn accept_invitation(req: HttpRequest,
         Err(err) => return err.into(),
     };
 
+    // Accepting deletes the invitation, along with who sent it
+    let invitation = match OriginInvitation::get(invitation_id, &*conn) {
+        Ok(invitation) => invitation,
+        Err(err) => {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    };
+
     match OriginInvitation::accept(invitation_id, false, &*conn).map_err(Error::DieselError) {
-        Ok(_) => HttpResponse::NoContent().finish(),
+        Ok(_) => {
+            let owner = Account::get_by_id(invitation.owner_id, &*conn).ok();
+            let mut event =
+                NewAccountSecurityEvent::new(account_id as i64,
+                                             AccountSecurityEventKind::OriginMemberAdded);
+            event.origin = Some(&invitation.origin);
+            event.actor_id = Some(invitation.owner_id);
+            event.actor_name = owner.as_ref().map(|o| o.name.as_str());
+            if let Err(err) = security_events::record(&event, &*conn) {
+                warn!("Unable to record membership of {} for account {}, err={}",
+                      invitation.origin, account_id, err);
+            }
+            HttpResponse::NoContent().finish()
+        }
         Err(err) => {
             debug!("{}", err);
             err.into()
@@ -1024,11 +1699,20 @@ fn list_origin_members(req: HttpRequest,
         Err(err) => return err.into(),
     };
 
+    let pending = match OriginInvitation::list_pending_email(&origin, &*conn) {
+        Ok(pending) => pending,
+        Err(err) => {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    };
+
     match OriginMember::list(&origin, &*conn).map_err(Error::DieselError) {
         Ok(users) => {
             let json = json!({
                 "origin": &origin,
-                "members": serde_json::to_value(users).unwrap()
+                "members": serde_json::to_value(users).unwrap(),
+                "pending_email_invitations": serde_json::to_value(pending).unwrap()
             });
 
             HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
@@ -1041,6 +1725,89 @@ fn list_origin_members(req: HttpRequest,
     }
 }
 
+#[allow(clippy::needless_pass_by_value)]
+fn list_origin_member_activity(req: HttpRequest,
+                               path: Path<String>,
+                               qactivity: Query<MemberActivityQuery>,
+                               state: Data<AppState>)
+                               -> HttpResponse {
+    let origin = path.into_inner();
+
+    let session = match authorize_session(&req, None) {
+        Ok(session) => session,
+        Err(err) => return err.into(),
+    };
+
+    let flags = FeatureFlags::from_bits_truncate(session.get_flags());
+    if !flags.contains(FeatureFlags::ADMIN) {
+        match check_origin_owner(&req, session.get_id(), &origin) {
+            Ok(true) => (),
+            Ok(false) => return HttpResponse::new(StatusCode::FORBIDDEN),
+            Err(err) => return err.into(),
+        }
+    }
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    if let Err(err) = Origin::get(&origin, &*conn) {
+        debug!("{}", err);
+        return Error::DieselError(err).into();
+    }
+
+    let members = match OriginMemberActivity::list(&origin, &*conn) {
+        Ok(members) => members,
+        Err(err) => {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    };
+
+    let mut request = jobsrv::JobGroupSubmittersGet::new();
+    request.set_origin(origin.clone());
+    let submitters =
+        match route_message::<jobsrv::JobGroupSubmittersGet, jobsrv::JobGroupSubmitters>(&req,
+                                                                                         &request)
+        {
+            Ok(submitters) => submitters,
+            Err(err) => {
+                debug!("{}", err);
+                return err.into();
+            }
+        };
+
+    let mut members = member_activity::collect(members, submitters.get_submitters());
+    if let Some(days) = qactivity.stale_days {
+        members = member_activity::stale(members, days, Utc::now().naive_utc());
+    }
+
+    if accepts_csv(&req) {
+        HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                          .content_type("text/csv; charset=utf-8")
+                          .body(member_activity::to_csv(&members))
+    } else {
+        let body = json!({
+            "origin": origin,
+            "members": members
+        });
+        HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                          .json(body)
+    }
+}
+
+// Whether the client asked for CSV rather than JSON
+fn accepts_csv(req: &HttpRequest) -> bool {
+    req.headers()
+       .get(http::header::ACCEPT)
+       .and_then(|accept| accept.to_str().ok())
+       .map_or(false, |accept| {
+           accept.split(',')
+                 .any(|media_type| media_type.trim().starts_with("text/csv"))
+       })
+}
+
 #[allow(clippy::needless_pass_by_value)]
 fn origin_member_delete(req: HttpRequest,
                         path: Path<(String, String)>,
@@ -12,23 +12,45 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{bldr_core::{error::Error::RpcError,
-                        metrics::CounterMetric},
-            db::models::{channel::Channel,
-                         origin::Origin,
+use crate::{bldr_core::{access_token::BUILDER_ACCOUNT_ID,
+                        error::Error::RpcError,
+                        metrics::CounterMetric,
+                        package_name,
+                        timestamp},
+            db::models::{account::Account,
+                         channel::Channel,
+                         install::InstallStat,
+                         keys::OriginPublicSigningKey,
+                         origin::{Origin,
+                                  OriginUploadPolicy,
+                                  UploadPolicyOverrideAudit},
                          package::{BuilderPackageIdent,
                                    BuilderPackageTarget,
                                    DeletePackage,
+                                   DependencyStatus,
                                    GetLatestPackage,
                                    GetPackage,
                                    ListPackages,
-                                   NewPackage,
+                                   NewPackageDeprecation,
+                                   NewPackageVerificationFailure,
                                    Package,
+                                   PackageBuildCache,
+                                   PackageDependencyReport,
+                                   PackageDeprecation,
+                                   PackageDeprecationAudit,
+                                   PackageDeprecationOperation,
+                                   PackageEncryption,
                                    PackageIdentWithChannelPlatform,
+                                   PackageUpstream,
+                                   PackageVerificationFailure,
                                    PackageVisibility,
-                                   SearchPackages},
-                         projects::Project},
-            hab_core::{package::{FromArchive,
+                                   SearchPackages,
+                                   MAX_TARGET_MATRIX_RELEASES,
+                                   TARGET_MATRIX_RELEASES}},
+            hab_core::{crypto::{self,
+                                artifact,
+                                keys::parse_name_with_rev},
+                       package::{ident,
                                  Identifiable,
                                  PackageArchive,
                                  PackageIdent,
@@ -37,18 +59,43 @@ use crate::{bldr_core::{error::Error::RpcError,
             protocol::{jobsrv,
                        net::NetOk,
                        originsrv},
-            server::{authorize::authorize_session,
+            server::{authorize::{authorize_session,
+                                 check_origin_owner},
                      error::{Error,
                              Result},
                      feat,
-                     framework::{headers,
+                     framework::{deadline,
+                                 fields::{filter_body,
+                                          filter_results,
+                                          filter_value,
+                                          Fields},
+                                 headers::{self,
+                                           ByteRange},
                                  middleware::route_message},
                      helpers::{self,
                                req_state,
                                Pagination,
                                Target},
-                     resources::channels::channels_for_package_ident,
-                     services::metrics::Counter,
+                     resources::channels::{channels_for_package_ident,
+                                           check_pins},
+                     services::{artifact_encryption::{self,
+                                                      EncryptedArtifact},
+                                dep_check::DependencyReport,
+                                dep_tree::{self,
+                                           PackageDeps},
+                                download_cache::DownloadCache,
+                                feed::{self,
+                                       Feed},
+                                ingest::{self,
+                                         Jobsrv,
+                                         StoredUpload},
+                                metrics::Counter,
+                                notify,
+                                target_matrix,
+                                upload_policy::{self,
+                                                Violation},
+                                upload_spool::{SpooledEncryption,
+                                               SpooledUpload}},
                      AppState}};
 use actix_web::{body::Body,
                 error,
@@ -60,32 +107,48 @@ use actix_web::{body::Body,
                        StatusCode},
                 web::{self,
                       Data,
+                      Json,
                       Path,
                       Query,
                       ServiceConfig},
                 HttpRequest,
                 HttpResponse};
 use bytes::Bytes;
-use diesel::result::Error::NotFound;
+use chrono::{Duration,
+             Utc};
+use diesel::{pg::PgConnection,
+             result::{Error::NotFound,
+                      QueryResult},
+             Connection};
 use futures::{future::ok as fut_ok,
-              sync::mpsc,
+              stream,
               Future,
               Stream};
 use percent_encoding;
 use protobuf;
+use serde::Serialize;
 use serde_json;
-use std::{fs::{self,
+use std::{cmp,
+          collections::HashMap,
+          fs::{self,
                remove_file,
                File},
-          io::{BufReader,
+          io::{self,
+               BufReader,
                BufWriter,
                Read,
+               Seek,
+               SeekFrom,
                Write},
-          path::PathBuf,
+          path::{Path as StdPath,
+                 PathBuf},
           str::FromStr};
 use tempfile::tempdir_in;
 use uuid::Uuid;
 
+// Downloads are read in chunks of this size
+const DOWNLOAD_CHUNK_SIZE: u64 = 64 * 1024;
+
 // Query param containers
 #[derive(Debug, Deserialize)]
 pub struct Upload {
@@ -97,6 +160,8 @@ pub struct Upload {
     builder: Option<String>,
     #[serde(default)]
     forced: bool,
+    #[serde(default)]
+    build_cache_key: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -109,10 +174,145 @@ pub struct Schedule {
     origin_only: Option<String>,
     #[serde(default)]
     package_only: Option<String>,
+    #[serde(default)]
+    no_cache: Option<String>,
+    #[serde(default)]
+    max_concurrency: Option<u32>,
+    #[serde(default)]
+    failure_policy: Option<String>,
+    #[serde(default)]
+    exclude: Option<String>,
+    #[serde(default)]
+    confirm_large_group: bool,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Deps {
+    #[serde(default)]
+    tree:  bool,
+    #[serde(default)]
+    depth: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Stats {
+    #[serde(default = "default_stats_days")]
+    days: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedQuery {
+    #[serde(default)]
+    channel: Option<String>,
+    #[serde(default)]
+    target:  Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TargetMatrixQuery {
+    #[serde(default = "default_matrix_releases")]
+    releases: i64,
+    #[serde(default)]
+    channel:  Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Search {
+    #[serde(default)]
+    origins: Option<String>,
+    #[serde(default)]
+    mode:    SearchMode,
+}
+
+// How a search matches package names. By default names differing from the query only in case
+// and punctuation match too; an exact search matches the words of the query alone.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    Normalized,
+    Exact,
 }
 
+impl Default for SearchMode {
+    fn default() -> Self { SearchMode::Normalized }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PackageDeprecationReq {
+    message: String,
+    #[serde(default)]
+    replacement: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerificationFailureReq {
+    key_name: String,
+    reason:   String,
+}
+
+// A search result, with the deprecation notice of its package if there is one
+#[derive(Serialize)]
+struct SearchResult {
+    #[serde(flatten)]
+    ident:       BuilderPackageIdent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deprecation: Option<PackageDeprecation>,
+}
+
+const MAX_DEPRECATION_MESSAGE_LEN: usize = 512;
+
+// Most days of install stats a request may ask for
+const MAX_STATS_DAYS: u32 = 365;
+
 fn default_target() -> String { "x86_64-linux".to_string() }
 
+fn default_stats_days() -> u32 { 30 }
+
+fn default_matrix_releases() -> i64 { TARGET_MATRIX_RELEASES }
+
+// Fields which may be selected with `?fields=`
+const PACKAGE_FIELDS: &[&str] = &["ident",
+                                  "name",
+                                  "origin",
+                                  "checksum",
+                                  "manifest",
+                                  "config",
+                                  "target",
+                                  "deps",
+                                  "tdeps",
+                                  "build_deps",
+                                  "build_tdeps",
+                                  "exposes",
+                                  "visibility",
+                                  "channels",
+                                  "is_a_service",
+                                  "release_at",
+                                  "deprecation",
+                                  "upstream",
+                                  "dependency_report",
+                                  "created_at",
+                                  "updated_at"];
+const PACKAGE_LIST_FIELDS: &[&str] = &["origin",
+                                       "name",
+                                       "version",
+                                       "release",
+                                       "release_at",
+                                       "channels",
+                                       "platforms"];
+const GROUP_FIELDS: &[&str] = &["id",
+                                "state",
+                                "projects",
+                                "created_at",
+                                "project_name",
+                                "target",
+                                "no_cache",
+                                "max_concurrency",
+                                "failure_policy",
+                                "in_flight",
+                                "frozen_until"];
+
 #[derive(Debug, Deserialize)]
 pub struct GetSchedule {
     #[serde(default)]
@@ -146,6 +346,16 @@ impl Packages {
                   web::get().to(get_latest_package_for_origin_package))
            .route("/depot/pkgs/{origin}/{pkg}/versions",
                   web::get().to(list_package_versions))
+           .route("/depot/pkgs/{origin}/{pkg}/stats",
+                  web::get().to(get_package_stats))
+           .route("/depot/pkgs/{origin}/{pkg}/targets",
+                  web::get().to(get_package_target_matrix))
+           .route("/depot/pkgs/{origin}/{pkg}/feed.atom",
+                  web::get().to(get_package_feed))
+           .route("/depot/pkgs/{origin}/{pkg}/deprecation",
+                  web::put().to(update_package_deprecation))
+           .route("/depot/pkgs/{origin}/{pkg}/deprecation",
+                  web::delete().to(delete_package_deprecation))
            .route("/depot/pkgs/{origin}/{pkg}/{version}",
                   web::get().to(get_packages_for_origin_package_version))
            .route("/depot/pkgs/{origin}/{pkg}/{version}/latest",
@@ -158,10 +368,19 @@ impl Packages {
                   web::delete().to(delete_package))
            .route("/depot/pkgs/{origin}/{pkg}/{version}/{release}/download",
                   web::get().to(download_package))
+           .route("/depot/pkgs/{origin}/{pkg}/{version}/{release}/download",
+                  web::head().to(download_package))
            .route("/depot/pkgs/{origin}/{pkg}/{version}/{release}/channels",
                   web::get().to(get_package_channels))
+           .route("/depot/pkgs/{origin}/{pkg}/{version}/{release}/deps",
+                  web::get().to(get_package_deps))
+           .route("/depot/pkgs/{origin}/{pkg}/{version}/{release}/verification_failure",
+                  web::post().to(report_verification_failure))
            .route("/depot/pkgs/{origin}/{pkg}/{version}/{release}/{visibility}",
-                  web::patch().to(package_privacy_toggle));
+                  web::patch().to(package_privacy_toggle))
+           .route("/depot/build_cache/{key}",
+                  web::get().to(get_build_cache_package))
+           .route("/depot/ingestions/{id}", web::get().to(get_ingestion));
     }
 }
 
@@ -171,14 +390,19 @@ impl Packages {
 #[allow(clippy::needless_pass_by_value)]
 fn get_packages_for_origin(req: HttpRequest,
                            path: Path<String>,
-                           pagination: Query<Pagination>)
+                           pagination: Query<Pagination>,
+                           qfields: Query<Fields>)
                            -> HttpResponse {
     let origin = path.into_inner();
     let ident = PackageIdent::new(origin, String::from(""), None, None);
 
     match do_get_packages(&req, &ident, &pagination) {
         Ok((packages, count)) => {
-            postprocess_extended_package_list(&req, &packages, count, &pagination)
+            postprocess_extended_package_list(&req,
+                                              &packages,
+                                              count,
+                                              &pagination,
+                                              qfields.fields.as_ref().map(String::as_str))
         }
         Err(err) => {
             debug!("{}", err);
@@ -190,7 +414,8 @@ fn get_packages_for_origin(req: HttpRequest,
 #[allow(clippy::needless_pass_by_value)]
 fn get_packages_for_origin_package(req: HttpRequest,
                                    path: Path<(String, String)>,
-                                   pagination: Query<Pagination>)
+                                   pagination: Query<Pagination>,
+                                   qfields: Query<Fields>)
                                    -> HttpResponse {
     let (origin, pkg) = path.into_inner();
 
@@ -198,7 +423,11 @@ fn get_packages_for_origin_package(req: HttpRequest,
 
     match do_get_packages(&req, &ident, &pagination) {
         Ok((packages, count)) => {
-            postprocess_extended_package_list(&req, &packages, count, &pagination)
+            postprocess_extended_package_list(&req,
+                                              &packages,
+                                              count,
+                                              &pagination,
+                                              qfields.fields.as_ref().map(String::as_str))
         }
         Err(err) => {
             debug!("{}", err);
@@ -210,7 +439,8 @@ fn get_packages_for_origin_package(req: HttpRequest,
 #[allow(clippy::needless_pass_by_value)]
 fn get_packages_for_origin_package_version(req: HttpRequest,
                                            path: Path<(String, String, String)>,
-                                           pagination: Query<Pagination>)
+                                           pagination: Query<Pagination>,
+                                           qfields: Query<Fields>)
                                            -> HttpResponse {
     let (origin, pkg, version) = path.into_inner();
 
@@ -218,7 +448,11 @@ fn get_packages_for_origin_package_version(req: HttpRequest,
 
     match do_get_packages(&req, &ident, &pagination) {
         Ok((packages, count)) => {
-            postprocess_extended_package_list(&req, &packages, count, &pagination)
+            postprocess_extended_package_list(&req,
+                                              &packages,
+                                              count,
+                                              &pagination,
+                                              qfields.fields.as_ref().map(String::as_str))
         }
         Err(err) => {
             debug!("{}", err);
@@ -230,7 +464,8 @@ fn get_packages_for_origin_package_version(req: HttpRequest,
 #[allow(clippy::needless_pass_by_value)]
 fn get_latest_package_for_origin_package(req: HttpRequest,
                                          path: Path<(String, String)>,
-                                         qtarget: Query<Target>)
+                                         qtarget: Query<Target>,
+                                         qfields: Query<Fields>)
                                          -> HttpResponse {
     let (origin, pkg) = path.into_inner();
 
@@ -238,10 +473,15 @@ fn get_latest_package_for_origin_package(req: HttpRequest,
 
     match do_get_package(&req, &qtarget, &ident) {
         Ok(json_body) => {
+            let json_body = filter_body(json_body,
+                                        qfields.fields.as_ref().map(String::as_str),
+                                        PACKAGE_FIELDS,
+                                        filter_value);
             HttpResponse::Ok().header(http::header::CONTENT_TYPE, headers::APPLICATION_JSON)
                               .header(http::header::CACHE_CONTROL, headers::cache(false))
                               .body(json_body)
         }
+        Err(Error::NotFound) => package_not_found(&req, &ident),
         Err(err) => {
             debug!("{}", err);
             err.into()
@@ -252,7 +492,8 @@ fn get_latest_package_for_origin_package(req: HttpRequest,
 #[allow(clippy::needless_pass_by_value)]
 fn get_latest_package_for_origin_package_version(req: HttpRequest,
                                                  path: Path<(String, String, String)>,
-                                                 qtarget: Query<Target>)
+                                                 qtarget: Query<Target>,
+                                                 qfields: Query<Fields>)
                                                  -> HttpResponse {
     let (origin, pkg, version) = path.into_inner();
 
@@ -260,10 +501,15 @@ fn get_latest_package_for_origin_package_version(req: HttpRequest,
 
     match do_get_package(&req, &qtarget, &ident) {
         Ok(json_body) => {
+            let json_body = filter_body(json_body,
+                                        qfields.fields.as_ref().map(String::as_str),
+                                        PACKAGE_FIELDS,
+                                        filter_value);
             HttpResponse::Ok().header(http::header::CONTENT_TYPE, headers::APPLICATION_JSON)
                               .header(http::header::CACHE_CONTROL, headers::cache(false))
                               .body(json_body)
         }
+        Err(Error::NotFound) => package_not_found(&req, &ident),
         Err(err) => {
             debug!("{}", err);
             err.into()
@@ -274,7 +520,8 @@ fn get_latest_package_for_origin_package_version(req: HttpRequest,
 #[allow(clippy::needless_pass_by_value)]
 fn get_package(req: HttpRequest,
                path: Path<(String, String, String, String)>,
-               qtarget: Query<Target>)
+               qtarget: Query<Target>,
+               qfields: Query<Fields>)
                -> HttpResponse {
     let (origin, pkg, version, release) = path.into_inner();
 
@@ -282,10 +529,42 @@ fn get_package(req: HttpRequest,
 
     match do_get_package(&req, &qtarget, &ident) {
         Ok(json_body) => {
+            let json_body = filter_body(json_body,
+                                        qfields.fields.as_ref().map(String::as_str),
+                                        PACKAGE_FIELDS,
+                                        filter_value);
             HttpResponse::Ok().header(http::header::CONTENT_TYPE, headers::APPLICATION_JSON)
                               .header(http::header::CACHE_CONTROL, headers::cache(true))
                               .body(json_body)
         }
+        Err(Error::NotFound) => package_not_found(&req, &ident),
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn get_package_deps(req: HttpRequest,
+                    path: Path<(String, String, String, String)>,
+                    qdeps: Query<Deps>)
+                    -> HttpResponse {
+    let (origin, pkg, version, release) = path.into_inner();
+
+    let ident = PackageIdent::new(origin, pkg, Some(version), Some(release));
+
+    let depth = qdeps.depth.unwrap_or(dep_tree::DEFAULT_DEPTH);
+    if depth == 0 || depth > dep_tree::MAX_DEPTH {
+        debug!("Rejecting dependency tree with depth: {}", depth);
+        return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    match do_get_package_deps(&req, &ident, qdeps.tree, depth) {
+        Ok(body) => {
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(body)
+        }
         Err(err) => {
             debug!("{}", err);
             err.into()
@@ -293,6 +572,77 @@ fn get_package(req: HttpRequest,
     }
 }
 
+// The status of an upload spooled while the artifact store was unavailable
+#[allow(clippy::needless_pass_by_value)]
+fn get_ingestion(req: HttpRequest, path: Path<String>, state: Data<AppState>) -> HttpResponse {
+    let id = path.into_inner();
+
+    let entry = match state.upload_spool.get(&id) {
+        Some(entry) => entry,
+        None => return HttpResponse::new(StatusCode::NOT_FOUND),
+    };
+
+    if let Err(err) = authorize_session(&req, Some(entry.origin())) {
+        return err.into();
+    }
+
+    HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                      .json(entry.ingestion())
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn get_build_cache_package(req: HttpRequest,
+                           path: Path<String>,
+                           qtarget: Query<Target>,
+                           state: Data<AppState>)
+                           -> HttpResponse {
+    let cache_key = path.into_inner();
+
+    if let Err(err) = authorize_session(&req, None) {
+        return err.into();
+    }
+
+    if !valid_build_cache_key(&cache_key) {
+        return HttpResponse::new(StatusCode::BAD_REQUEST);
+    }
+
+    let target = match qtarget.target {
+        Some(ref t) => {
+            match PackageTarget::from_str(t) {
+                Ok(t) => t,
+                Err(err) => {
+                    debug!("Invalid target requested: {}, err = {:?}", t, err);
+                    return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+                }
+            }
+        }
+        None => helpers::target_from_headers(&req),
+    };
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn) => conn,
+        Err(err) => return err.into(),
+    };
+
+    match PackageBuildCache::get_package(&cache_key, BuilderPackageTarget(target), &*conn) {
+        Ok(pkg) => {
+            // A cache hit must not reveal private packages outside of their origin
+            if pkg.visibility != PackageVisibility::Public
+               && authorize_session(&req, Some(&pkg.origin)).is_err()
+            {
+                return HttpResponse::new(StatusCode::NOT_FOUND);
+            }
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(pkg)
+        }
+        Err(NotFound) => HttpResponse::new(StatusCode::NOT_FOUND),
+        Err(err) => {
+            debug!("{}", err);
+            Error::DieselError(err).into()
+        }
+    }
+}
+
 #[allow(clippy::needless_pass_by_value)]
 fn delete_package(req: HttpRequest,
                   path: Path<(String, String, String, String)>,
@@ -328,10 +678,10 @@ fn delete_package(req: HttpRequest,
     };
 
     // Check whether package is in stable channel
-    match Package::list_package_channels(&BuilderPackageIdent(ident.clone()),
-                                         target,
-                                         helpers::all_visibilities(),
-                                         &*conn)
+    let channels = match Package::list_package_channels(&BuilderPackageIdent(ident.clone()),
+                                                        target,
+                                                        helpers::all_visibilities(),
+                                                        &*conn)
     {
         Ok(channels) => {
             if channels.iter()
@@ -340,12 +690,21 @@ fn delete_package(req: HttpRequest,
                 debug!("Deleting package in stable channel not allowed: {}", ident);
                 return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
             }
+            let channels: Vec<ChannelIdent> = channels.into_iter()
+                                                      .map(|c| ChannelIdent::from(c.name))
+                                                      .collect();
+            if let Err(err) = check_release_pins(&ident, target, &channels, &*conn) {
+                debug!("Deleting pinned package not allowed: {}, err={}",
+                       ident, err);
+                return err.into();
+            }
+            channels
         }
         Err(err) => {
             debug!("{}", err);
             return Error::DieselError(err).into();
         }
-    }
+    };
 
     // Check whether package project has any rdeps
     if feat::is_enabled(feat::Jobsrv) {
@@ -370,8 +729,7 @@ fn delete_package(req: HttpRequest,
         }
     }
 
-    // TODO (SA): Wrap in transaction, or better yet, eliminate need to do
-    // channel package deletion
+    // TODO (SA): Eliminate need to do channel package deletion
     let pkg = match Package::get(GetPackage { ident:      BuilderPackageIdent(ident.clone()),
                                               visibility: helpers::all_visibilities(),
                                               target:     BuilderPackageTarget(target), },
@@ -381,15 +739,16 @@ fn delete_package(req: HttpRequest,
         Err(err) => return err.into(),
     };
 
-    if let Err(err) = Channel::delete_channel_package(pkg.id, &*conn).map_err(Error::DieselError) {
-        debug!("{}", err);
-        return err.into();
-    }
+    // The pins are checked again along with the delete, one may have been set since
+    let deleted = conn.transaction::<_, Error, _>(|| {
+                          check_release_pins(&ident, target, &channels, &*conn)?;
+                          Channel::delete_channel_package(pkg.id, &*conn)?;
+                          let delete = DeletePackage { ident:  BuilderPackageIdent(ident.clone()),
+                                                       target: BuilderPackageTarget(target), };
+                          Ok(Package::delete(delete, &*conn)?)
+                      });
 
-    match Package::delete(DeletePackage { ident:  BuilderPackageIdent(ident.clone()),
-                                          target: BuilderPackageTarget(target), },
-                          &*conn).map_err(Error::DieselError)
-    {
+    match deleted {
         Ok(_) => {
             state.memcache.borrow_mut().clear_cache_for_package(&ident);
             HttpResponse::NoContent().finish()
@@ -401,6 +760,22 @@ fn delete_package(req: HttpRequest,
     }
 }
 
+// Fails with the pin, in any of the channels the release is in, that deleting it would break
+fn check_release_pins(ident: &PackageIdent,
+                      target: PackageTarget,
+                      channels: &[ChannelIdent],
+                      conn: &PgConnection)
+                      -> Result<()> {
+    for channel in channels {
+        check_pins(&ident.origin,
+                   channel,
+                   &[(ident.clone(), target)],
+                   false,
+                   conn)?;
+    }
+    Ok(())
+}
+
 // TODO : Convert to async
 #[allow(clippy::needless_pass_by_value)]
 fn download_package(req: HttpRequest,
@@ -408,6 +783,7 @@ fn download_package(req: HttpRequest,
                     qtarget: Query<Target>,
                     state: Data<AppState>)
                     -> HttpResponse {
+    Counter::DownloadRequests.increment();
     let (origin, name, version, release) = path.into_inner();
 
     let conn = match state.db.get_conn().map_err(Error::DbError) {
@@ -444,38 +820,133 @@ fn download_package(req: HttpRequest,
         return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
     }
 
-    match Package::get(GetPackage { ident:      BuilderPackageIdent(ident.clone()),
-                                    visibility: vis,
-                                    target:     BuilderPackageTarget(target), },
-                       &*conn)
-    {
-        Ok(package) => {
-            let dir = tempdir_in(&state.config.api.data_path).expect("Unable to create a tempdir!");
-            let file_path = dir.path().join(archive_name(&package.ident, target));
-            let temp_ident = ident.to_owned();
-
-            // TODO: Aggregate Artifactory/S3 into a provider model
-            if feat::is_enabled(feat::Artifactory) {
-                match state.artifactory.download(&file_path, &temp_ident, target) {
-                    Ok(archive) => download_response_for_archive(&archive, &file_path),
-                    Err(e) => {
-                        warn!("Failed to download package, ident={}, err={:?}",
-                              temp_ident, e);
-                        HttpResponse::new(StatusCode::NOT_FOUND)
-                    }
-                }
-            } else {
-                match state.packages.download(&file_path, &temp_ident, target) {
-                    Ok(archive) => download_response_for_archive(&archive, &file_path),
-                    Err(e) => {
-                        warn!("Failed to download package, ident={}, err={:?}",
-                              temp_ident, e);
-                        HttpResponse::new(StatusCode::NOT_FOUND)
-                    }
-                }
+    let get_package = || {
+        Package::get(GetPackage { ident:      BuilderPackageIdent(ident.clone()),
+                                  visibility: vis.clone(),
+                                  target:     BuilderPackageTarget(target), },
+                     &*conn)
+    };
+    let package = match get_package() {
+        Ok(package) => package,
+        Err(NotFound)
+            if state.upstream
+                    .fetches_for(&ident.origin, opt_session_id.is_some()) =>
+        {
+            if let Err(err) = state.upstream.fetch(&ident, target) {
+                return err.into();
+            }
+            state.memcache.borrow_mut().clear_cache_for_package(&ident);
+            match get_package() {
+                Ok(package) => package,
+                Err(err) => return Error::DieselError(err).into(),
+            }
+        }
+        Err(err) => return Error::DieselError(err).into(),
+    };
+
+    let filename = archive_name(&package.ident, target).to_string_lossy()
+                                                       .into_owned();
+
+    // The notice only informs the client, a failed lookup should not fail the download
+    let deprecation = match package_deprecation(&ident, &*conn) {
+        Ok(deprecation) => deprecation,
+        Err(err) => {
+            warn!("Unable to look up deprecation of {}, err={:?}", ident, err);
+            None
+        }
+    };
+
+    let encryption =
+        match PackageEncryption::get(&package.ident, BuilderPackageTarget(target), &*conn) {
+            Ok(encryption) => encryption,
+            Err(err) => return Error::DieselError(err).into(),
+        };
+
+    // Artifacts of origins that encrypt them are never kept decrypted on disk
+    let cacheable = encryption.is_none() && state.download_cache.enabled();
+    if cacheable {
+        if let Some(file) = state.download_cache.cached(&package.checksum) {
+            let archive = Archive::Cached(file, &state.download_cache);
+            return download_response(&req, filename, archive, deprecation.as_ref());
+        }
+    }
+
+    // A HEAD, or a range of an artifact stored as is, is answered from the artifact store
+    // without fetching the whole artifact. A range of an encrypted one needs it decrypted whole.
+    let head = req.method() == http::Method::HEAD;
+    let ranged = req.headers().contains_key(http::header::RANGE);
+    if !feat::is_enabled(feat::Artifactory) && (head || (ranged && encryption.is_none())) {
+        let len = match state.packages.size(&ident, target) {
+            Ok(len) if encryption.is_some() => artifact_encryption::plaintext_len(len),
+            Ok(len) => len,
+            Err(e) => {
+                warn!("Failed to look up package, ident={}, err={:?}", ident, e);
+                return HttpResponse::new(StatusCode::NOT_FOUND);
+            }
+        };
+        let archive = Archive::Stored(&state, &ident, target, len);
+        return download_response(&req, filename, archive, deprecation.as_ref());
+    }
+
+    let archive = if cacheable {
+        let cached = state.download_cache
+                          .open_artifact(&package.checksum, |path| {
+                              fetch_artifact(&state, path, &ident, target).map(|_| ())
+                          });
+        match cached {
+            Ok(file) => Archive::Cached(file, &state.download_cache),
+            Err(e) => {
+                warn!("Failed to download package, ident={}, err={:?}", ident, e);
+                return HttpResponse::new(StatusCode::NOT_FOUND);
+            }
+        }
+    } else {
+        let dir = tempdir_in(&state.config.api.data_path).expect("Unable to create a tempdir!");
+        let file_path = dir.path().join(&filename);
+        // An encrypted artifact is fetched beside the archive it decrypts to
+        let fetch_path = match encryption {
+            Some(_) => file_path.with_extension("enc"),
+            None => file_path.clone(),
+        };
+
+        if let Err(e) = fetch_artifact(&state, &fetch_path, &ident, target) {
+            warn!("Failed to download package, ident={}, err={:?}", ident, e);
+            return HttpResponse::new(StatusCode::NOT_FOUND);
+        }
+
+        if let Some(ref encryption) = encryption {
+            if let Err(err) = decrypt_download(&state, encryption, &fetch_path, &file_path) {
+                warn!("Failed to decrypt package, ident={}, err={:?}", ident, err);
+                return err.into();
+            }
+        }
+
+        // The reading outlives the temporary directory, the open file is kept
+        match File::open(&file_path) {
+            Ok(file) => Archive::File(file),
+            Err(err) => {
+                warn!("Unable to open file: {:?}", file_path);
+                return Error::IO(err).into();
             }
         }
-        Err(err) => Error::DieselError(err).into(),
+    };
+    download_response(&req, filename, archive, deprecation.as_ref())
+}
+
+// Fetches the artifact from the artifact store to `path`
+fn fetch_artifact(state: &AppState,
+                  path: &StdPath,
+                  ident: &PackageIdent,
+                  target: PackageTarget)
+                  -> Result<PackageArchive> {
+    let path = path.to_path_buf();
+    // TODO: Aggregate Artifactory/S3 into a provider model
+    if feat::is_enabled(feat::Artifactory) {
+        state.artifactory
+             .download(&path, ident, target)
+             .map_err(Error::Artifactory)
+    } else {
+        state.packages.download(&path, ident, target)
     }
 }
 
@@ -496,6 +967,22 @@ fn upload_package(req: HttpRequest,
         return Box::new(fut_ok(HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY)));
     }
 
+    if let Some(ref key) = qupload.build_cache_key {
+        if !valid_build_cache_key(key) {
+            debug!("Invalid build cache key for {}: {}", ident, key);
+            return Box::new(fut_ok(HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY)));
+        }
+        // Only the builds of workers may claim a cache key, or anyone able to upload to the
+        // origin could have their artifact served for another's build
+        match authorize_session(&req, None) {
+            Ok(session) if session.get_id() == BUILDER_ACCOUNT_ID => (),
+            _ => {
+                debug!("Build cache key for {} from a non-worker upload", ident);
+                return Box::new(fut_ok(HttpResponse::new(StatusCode::FORBIDDEN)));
+            }
+        }
+    }
+
     match do_upload_package_start(&req, &qupload, &ident) {
         Ok((temp_path, writer)) => {
             state.memcache.borrow_mut().clear_cache_for_package(&ident);
@@ -540,6 +1027,34 @@ fn schedule_job_group(req: HttpRequest,
         return HttpResponse::new(StatusCode::BAD_REQUEST);
     }
 
+    let max_concurrency = qschedule.max_concurrency.unwrap_or(0);
+    if !helpers::valid_max_concurrency(max_concurrency, state.config.api.max_group_concurrency) {
+        debug!("Rejecting build with max_concurrency: {}", max_concurrency);
+        return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let failure_policy = match qschedule.failure_policy {
+        Some(ref policy) => {
+            match policy.parse::<jobsrv::JobGroupFailurePolicy>() {
+                Ok(policy) => policy,
+                Err(_) => {
+                    debug!("Rejecting build with failure_policy: {}", policy);
+                    return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+                }
+            }
+        }
+        None => jobsrv::JobGroupFailurePolicy::ContinueOnFailure,
+    };
+
+    let root = PackageIdent::new(origin_name.clone(), package.clone(), None, None);
+    let exclude = match schedule_exclusions(&qschedule, &root) {
+        Some(exclude) => exclude,
+        None => {
+            debug!("Rejecting build with exclusions: {:?}", qschedule.exclude);
+            return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    };
+
     let mut request = jobsrv::JobGroupSpec::new();
     request.set_origin(origin_name);
     request.set_package(package);
@@ -559,15 +1074,22 @@ fn schedule_job_group(req: HttpRequest,
                                       .unwrap_or_else(|| "false".to_string())
                                       .parse()
                                       .unwrap_or(false));
+    request.set_no_cache(qschedule.no_cache
+                                  .clone()
+                                  .unwrap_or_else(|| "false".to_string())
+                                  .parse()
+                                  .unwrap_or(false));
+    request.set_max_concurrency(max_concurrency);
+    request.set_failure_policy(failure_policy);
+    request.set_exclude(protobuf::RepeatedField::from_vec(exclude));
+    request.set_confirm_large_group(qschedule.confirm_large_group);
+    request.set_dry_run(qschedule.dry_run);
     request.set_trigger(helpers::trigger_from_request(&req));
     request.set_requester_id(session.get_id());
     request.set_requester_name(session.get_name().to_string());
 
     match route_message::<jobsrv::JobGroupSpec, jobsrv::JobGroup>(&req, &request) {
-        Ok(group) => {
-            HttpResponse::Created().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
-                                   .json(group)
-        }
+        Ok(group) => schedule_response(request.get_dry_run(), &group),
         Err(err) => {
             debug!("{}", err);
             err.into()
@@ -575,10 +1097,33 @@ fn schedule_job_group(req: HttpRequest,
     }
 }
 
+// The answer to a schedule request, by what the jobsrv made of it
+fn schedule_response(dry_run: bool, group: &jobsrv::JobGroup) -> HttpResponse {
+    // Nothing was created, the group's size is returned for the request to confirm
+    if group.get_confirmation_required() {
+        let body = json!({ "project_name": group.get_project_name(),
+                           "target": group.get_target(),
+                           "project_count": group.get_project_count() });
+        return HttpResponse::Conflict().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                                       .json(body);
+    }
+
+    let mut resp = if dry_run {
+        HttpResponse::Ok()
+    } else if group.get_state() == jobsrv::JobGroupState::GroupPendingApproval {
+        HttpResponse::Accepted()
+    } else {
+        HttpResponse::Created()
+    };
+    resp.header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+        .json(group)
+}
+
 #[allow(clippy::needless_pass_by_value)]
 fn get_schedule(req: HttpRequest,
                 path: Path<String>,
-                qgetschedule: Query<GetSchedule>)
+                qgetschedule: Query<GetSchedule>,
+                qfields: Query<Fields>)
                 -> HttpResponse {
     let group_id_str = path.into_inner();
     let group_id = match group_id_str.parse::<u64>() {
@@ -592,8 +1137,16 @@ fn get_schedule(req: HttpRequest,
 
     match route_message::<jobsrv::JobGroupGet, jobsrv::JobGroup>(&req, &request) {
         Ok(group) => {
+            let body = match serde_json::to_value(group) {
+                Ok(value) => {
+                    filter_value(value,
+                                 qfields.fields.as_ref().map(String::as_str),
+                                 GROUP_FIELDS)
+                }
+                Err(err) => return Error::SerdeJson(err).into(),
+            };
             HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
-                              .json(group)
+                              .json(body)
         }
         Err(err) => {
             debug!("{}", err);
@@ -727,51 +1280,227 @@ fn list_package_versions(req: HttpRequest,
     }
 }
 
+// Daily installs reported by hab clients, all releases of the package together
 #[allow(clippy::needless_pass_by_value)]
-fn search_packages(req: HttpRequest,
-                   path: Path<String>,
-                   pagination: Query<Pagination>,
-                   state: Data<AppState>)
-                   -> HttpResponse {
-    Counter::SearchPackages.increment();
+fn get_package_stats(req: HttpRequest,
+                     path: Path<(String, String)>,
+                     qstats: Query<Stats>,
+                     state: Data<AppState>)
+                     -> HttpResponse {
+    let (origin, name) = path.into_inner();
 
-    let query = path.into_inner();
+    if let Err(err) = authorize_session(&req, Some(&origin)) {
+        return err.into();
+    }
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let days = qstats.days.min(MAX_STATS_DAYS);
+    let since = Utc::today().naive_utc() - Duration::days(i64::from(days));
+
+    match InstallStat::list(&origin, &name, since, &*conn).map_err(Error::DieselError) {
+        Ok(installs) => {
+            let body = json!({
+                "origin": origin,
+                "name": name,
+                "days": days,
+                "installs": installs,
+            });
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(body)
+        }
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+// Which of the latest releases of a package, or of those in a channel, exist for which targets.
+// Private releases are only listed to origin members.
+#[allow(clippy::needless_pass_by_value)]
+fn get_package_target_matrix(req: HttpRequest,
+                             path: Path<(String, String)>,
+                             qmatrix: Query<TargetMatrixQuery>,
+                             state: Data<AppState>)
+                             -> HttpResponse {
+    let (origin, name) = path.into_inner();
 
     let opt_session_id = match authorize_session(&req, None) {
-        Ok(session) => Some(session.get_id() as i64),
+        Ok(session) => Some(session.get_id()),
         Err(_) => None,
     };
 
+    let releases = qmatrix.releases.max(1).min(MAX_TARGET_MATRIX_RELEASES);
+    let channel = qmatrix.channel.clone().unwrap_or_default();
+
     let conn = match state.db.get_conn().map_err(Error::DbError) {
         Ok(conn_ref) => conn_ref,
         Err(err) => return err.into(),
     };
 
-    let (page, per_page) = helpers::extract_pagination_in_pages(&pagination);
-
-    // First, try to parse the query like it's a PackageIdent, since it seems reasonable to expect
-    // that many people will try searching using that kind of string, e.g. core/redis.  If that
-    // works, set the origin appropriately and do a regular search.  If that doesn't work, do a
-    // search across all origins, similar to how the "distinct" search works now, but returning all
-    // the details instead of just names.
-    let decoded_query = match percent_encoding::percent_decode(query.as_bytes()).decode_utf8() {
-        Ok(q) => q.to_string().trim_end_matches('/').replace("/", " & "),
+    let visibility = helpers::visibility_for_optional_session(&req, opt_session_id, &origin);
+    match Package::list_target_matrix(&origin, &name, &channel, releases, &visibility, &*conn) {
+        Ok(ref cells) if cells.is_empty() => HttpResponse::new(StatusCode::NOT_FOUND),
+        Ok(cells) => {
+            let matrix = target_matrix::build(&origin, &name, &state.config.api.targets, cells);
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(matrix)
+        }
         Err(err) => {
             debug!("{}", err);
-            return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+            Error::DieselError(err).into()
         }
-    };
+    }
+}
 
-    debug!("search_packages called with: {}", decoded_query);
+// The latest releases of a package as an Atom feed, of those in a channel or for a target when
+// asked. Private releases are only listed to origin members, like everywhere else.
+#[allow(clippy::needless_pass_by_value)]
+fn get_package_feed(req: HttpRequest,
+                    path: Path<(String, String)>,
+                    qfeed: Query<FeedQuery>,
+                    state: Data<AppState>)
+                    -> HttpResponse {
+    let (origin, name) = path.into_inner();
+
+    let opt_session_id = match authorize_session(&req, None) {
+        Ok(session) => Some(session.get_id()),
+        Err(_) => None,
+    };
+
+    let target = match qfeed.target {
+        Some(ref t) => {
+            match PackageTarget::from_str(t) {
+                Ok(t) => t.to_string(),
+                Err(err) => {
+                    debug!("Invalid target requested: {}, err = {:?}", t, err);
+                    return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+                }
+            }
+        }
+        None => String::new(),
+    };
+    let channel = qfeed.channel.clone().unwrap_or_default();
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let visibility = helpers::visibility_for_optional_session(&req, opt_session_id, &origin);
+    match Package::list_feed(&origin, &name, &channel, &target, &visibility, &*conn) {
+        Ok(ref releases) if releases.is_empty() => HttpResponse::new(StatusCode::NOT_FOUND),
+        Ok(releases) => {
+            let base_url = feed::base_url(&state.config.api);
+            let mut title = format!("{}/{} releases", origin, name);
+            if !channel.is_empty() {
+                title.push_str(&format!(" in {}", channel));
+            }
+            if !target.is_empty() {
+                title.push_str(&format!(" for {}", target));
+            }
+            let feed = Feed { id: feed::package_feed_id(&origin, &name, &channel, &target),
+                              link: feed::self_link(&state.config.api, &req),
+                              title,
+                              entries: releases.into_iter()
+                                               .filter_map(|r| feed::release_entry(base_url, r))
+                                               .collect() };
+            feed::respond(&req, &feed)
+        }
+        Err(err) => {
+            debug!("{}", err);
+            Error::DieselError(err).into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn search_packages(req: HttpRequest,
+                   path: Path<String>,
+                   pagination: Query<Pagination>,
+                   search: Query<Search>,
+                   state: Data<AppState>)
+                   -> HttpResponse {
+    Counter::SearchPackages.increment();
+
+    let query = path.into_inner();
+
+    let opt_session_id = match authorize_session(&req, None) {
+        Ok(session) => Some(session.get_id() as i64),
+        Err(_) => None,
+    };
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let (page, per_page) = helpers::extract_pagination_in_pages(&pagination);
+
+    let origins = match search_origins_filter(&search) {
+        Some(origins) => origins,
+        None => return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY),
+    };
+
+    let preferred_origins = match preferred_search_origins(opt_session_id, &state, &*conn) {
+        Ok(origins) => origins,
+        Err(err) => {
+            debug!("{}", err);
+            return err.into();
+        }
+    };
+
+    // First, try to parse the query like it's a PackageIdent, since it seems reasonable to expect
+    // that many people will try searching using that kind of string, e.g. core/redis.  If that
+    // works, set the origin appropriately and do a regular search.  If that doesn't work, do a
+    // search across all origins, similar to how the "distinct" search works now, but returning all
+    // the details instead of just names.
+    let (decoded_query, exact_name, query_origin) =
+        match percent_encoding::percent_decode(query.as_bytes()).decode_utf8() {
+            Ok(q) => {
+                let q = q.trim_end_matches('/');
+                let name = q.rsplit('/').next().unwrap_or_default().to_string();
+                let origin = q.find('/').map(|i| q[..i].to_string());
+                (q.replace("/", " & "), name, origin)
+            }
+            Err(err) => {
+                debug!("{}", err);
+                return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+            }
+        };
+
+    debug!("search_packages called with: {}", decoded_query);
+
+    // Names are also matched once normalized, within the origin of a query like core/libxml2
+    let normalized_name = match search.mode {
+        SearchMode::Normalized => {
+            Some(package_name::normalize(&exact_name)).filter(|name| !name.is_empty())
+        }
+        SearchMode::Exact => None,
+    };
 
     if pagination.distinct {
-        return match Package::search_distinct(SearchPackages { query:      decoded_query,
-                                                               page:       page as i64,
-                                                               limit:      per_page as i64,
-                                                               account_id: opt_session_id, },
+        return match Package::search_distinct(SearchPackages { query: decoded_query,
+                                                               page: page as i64,
+                                                               limit: per_page as i64,
+                                                               account_id: opt_session_id,
+                                                               origins,
+                                                               preferred_origins,
+                                                               exact_name,
+                                                               normalized_name,
+                                                               query_origin },
                                               &*conn)
         {
-            Ok((packages, count)) => postprocess_package_list(&req, &packages, count, &pagination),
+            Ok((packages, count)) => {
+                match search_results(packages, &*conn) {
+                    Ok(results) => postprocess_package_list(&req, &results, count, &pagination),
+                    Err(err) => err.into(),
+                }
+            }
             Err(err) => {
                 debug!("{}", err);
                 Error::DieselError(err).into()
@@ -779,13 +1508,23 @@ fn search_packages(req: HttpRequest,
         };
     }
 
-    match Package::search(SearchPackages { query:      decoded_query,
-                                           page:       page as i64,
-                                           limit:      per_page as i64,
-                                           account_id: opt_session_id, },
+    match Package::search(SearchPackages { query: decoded_query,
+                                           page: page as i64,
+                                           limit: per_page as i64,
+                                           account_id: opt_session_id,
+                                           origins,
+                                           preferred_origins,
+                                           exact_name,
+                                           normalized_name,
+                                           query_origin },
                           &*conn)
     {
-        Ok((packages, count)) => postprocess_package_list(&req, &packages, count, &pagination),
+        Ok((packages, count)) => {
+            match search_results(packages, &*conn) {
+                Ok(results) => postprocess_package_list(&req, &results, count, &pagination),
+                Err(err) => err.into(),
+            }
+        }
         Err(err) => {
             debug!("{}", err);
             Error::DieselError(err).into()
@@ -842,14 +1581,223 @@ fn package_privacy_toggle(req: HttpRequest,
     }
 }
 
+#[allow(clippy::needless_pass_by_value)]
+fn update_package_deprecation(req: HttpRequest,
+                              path: Path<(String, String)>,
+                              body: Json<PackageDeprecationReq>,
+                              state: Data<AppState>)
+                              -> HttpResponse {
+    let (origin, name) = path.into_inner();
+
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
+        Err(err) => return err.into(),
+    };
+
+    if !check_origin_owner(&req, session.get_id(), &origin).unwrap_or(false) {
+        return HttpResponse::new(StatusCode::FORBIDDEN);
+    }
+
+    let message = body.message.trim();
+    if message.is_empty()
+       || message.len() > MAX_DEPRECATION_MESSAGE_LEN
+       || message.chars().any(char::is_control)
+    {
+        let msg = format!("message must be between 1 and {} characters, without control \
+                           characters",
+                          MAX_DEPRECATION_MESSAGE_LEN);
+        return HttpResponse::with_body(StatusCode::UNPROCESSABLE_ENTITY, Body::from_message(msg));
+    }
+
+    let ident = PackageIdent::new(origin.clone(), name.clone(), None, None);
+    if let Some(ref replacement) = body.replacement {
+        if !valid_replacement(replacement, &ident) {
+            let msg = format!("replacement must be another package in origin/name form, got {}",
+                              replacement);
+            return HttpResponse::with_body(StatusCode::UNPROCESSABLE_ENTITY,
+                                           Body::from_message(msg));
+        }
+    }
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    match Package::list_package_versions(&BuilderPackageIdent(ident.clone()),
+                                         PackageVisibility::all(),
+                                         &*conn)
+    {
+        Ok(ref versions) if versions.is_empty() => return HttpResponse::new(StatusCode::NOT_FOUND),
+        Ok(_) => (),
+        Err(err) => {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    }
+
+    let replacement = body.replacement.as_ref().map(String::as_str);
+    let new_deprecation = NewPackageDeprecation { origin: &origin,
+                                                  name: &name,
+                                                  message,
+                                                  replacement };
+    let audit = PackageDeprecationAudit { origin: &origin,
+                                          name: &name,
+                                          operation: PackageDeprecationOperation::Deprecate,
+                                          message: Some(message),
+                                          replacement,
+                                          requester_id: session.get_id() as i64,
+                                          requester_name: session.get_name() };
+
+    match PackageDeprecation::set(&new_deprecation, &*conn) {
+        Ok(deprecation) => {
+            if let Err(err) = PackageDeprecationAudit::audit(&audit, &*conn) {
+                warn!("Failed to save deprecation audit record for {}, err={:?}",
+                      ident, err);
+            }
+            state.memcache.borrow_mut().clear_cache_for_package(&ident);
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(deprecation)
+        }
+        Err(err) => {
+            debug!("{}", err);
+            Error::DieselError(err).into()
+        }
+    }
+}
+
+// Reported by the post-processing of a worker that uploaded the package it built but refused to
+// promote it, as its signature did not verify against a key of the origin still accepted for
+// signing. The origin's owner is told, and later promotions of the package are warned about.
+#[allow(clippy::needless_pass_by_value)]
+fn report_verification_failure(req: HttpRequest,
+                               path: Path<(String, String, String, String)>,
+                               qtarget: Query<Target>,
+                               body: Json<VerificationFailureReq>,
+                               state: Data<AppState>)
+                               -> HttpResponse {
+    let (origin, pkg, version, release) = path.into_inner();
+
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
+        Err(err) => return err.into(),
+    };
+
+    if session.get_id() != BUILDER_ACCOUNT_ID {
+        return HttpResponse::new(StatusCode::FORBIDDEN);
+    }
+
+    let ident = PackageIdent::new(origin.clone(), pkg, Some(version), Some(release));
+
+    // TODO: Deprecate target from headers
+    let target = match qtarget.target {
+        Some(ref t) => {
+            match PackageTarget::from_str(t) {
+                Ok(t) => t,
+                Err(err) => {
+                    debug!("Invalid target requested: {}, err = {:?}", t, err);
+                    return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+                }
+            }
+        }
+        None => helpers::target_from_headers(&req),
+    };
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let owner_id = match Origin::get(&origin, &*conn) {
+        Ok(origin) => origin.owner_id,
+        Err(err) => {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    };
+
+    let failure = NewPackageVerificationFailure { ident:    BuilderPackageIdent(ident.clone()),
+                                                  target:   BuilderPackageTarget(target),
+                                                  origin:   &origin,
+                                                  key_name: &body.key_name,
+                                                  reason:   &body.reason, };
+    if let Err(err) = PackageVerificationFailure::upsert(&failure, &*conn) {
+        debug!("{}", err);
+        return Error::DieselError(err).into();
+    }
+
+    warn!("Promotion of {} ({}) refused by post-processing, key={}, reason={}",
+          ident, target, body.key_name, body.reason);
+    let message = format!("Package {} ({}) was built but not promoted: its signature did not \
+                           verify against a signing key of origin {} still accepted. {}",
+                          ident, target, origin, body.reason);
+    if let Err(err) = notify::notify_account(owner_id,
+                                             notify::PROMOTION_VERIFICATION_FAILED,
+                                             &message,
+                                             &*conn)
+    {
+        warn!("Failed to notify the owner of {} of the refused promotion of {}, err={}",
+              origin, ident, err);
+    }
+
+    HttpResponse::NoContent().finish()
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn delete_package_deprecation(req: HttpRequest,
+                              path: Path<(String, String)>,
+                              state: Data<AppState>)
+                              -> HttpResponse {
+    let (origin, name) = path.into_inner();
+
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
+        Err(err) => return err.into(),
+    };
+
+    if !check_origin_owner(&req, session.get_id(), &origin).unwrap_or(false) {
+        return HttpResponse::new(StatusCode::FORBIDDEN);
+    }
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    match PackageDeprecation::clear(&origin, &name, &*conn) {
+        Ok(0) => HttpResponse::new(StatusCode::NOT_FOUND),
+        Ok(_) => {
+            let operation = PackageDeprecationOperation::Undeprecate;
+            let audit = PackageDeprecationAudit { origin: &origin,
+                                                  name: &name,
+                                                  operation,
+                                                  message: None,
+                                                  replacement: None,
+                                                  requester_id: session.get_id() as i64,
+                                                  requester_name: session.get_name() };
+            if let Err(err) = PackageDeprecationAudit::audit(&audit, &*conn) {
+                warn!("Failed to save deprecation audit record for {}/{}, err={:?}",
+                      origin, name, err);
+            }
+            let ident = PackageIdent::new(origin.clone(), name.clone(), None, None);
+            state.memcache.borrow_mut().clear_cache_for_package(&ident);
+            HttpResponse::NoContent().finish()
+        }
+        Err(err) => {
+            debug!("{}", err);
+            Error::DieselError(err).into()
+        }
+    }
+}
+
 // Public helpers
 //
 
-pub fn postprocess_package_list(_req: &HttpRequest,
-                                packages: &[BuilderPackageIdent],
-                                count: i64,
-                                pagination: &Query<Pagination>)
-                                -> HttpResponse {
+pub fn postprocess_package_list<T: Serialize>(_req: &HttpRequest,
+                                              packages: &[T],
+                                              count: i64,
+                                              pagination: &Query<Pagination>)
+                                              -> HttpResponse {
     let (start, _) = helpers::extract_pagination(pagination);
     let pkg_count = packages.len() as isize;
     let stop = match pkg_count {
@@ -877,7 +1825,8 @@ pub fn postprocess_package_list(_req: &HttpRequest,
 pub fn postprocess_extended_package_list(_req: &HttpRequest,
                                          packages: &[PackageIdentWithChannelPlatform],
                                          count: i64,
-                                         pagination: &Query<Pagination>)
+                                         pagination: &Query<Pagination>,
+                                         fields: Option<&str>)
                                          -> HttpResponse {
     let (start, _) = helpers::extract_pagination(pagination);
     let pkg_count = packages.len() as isize;
@@ -891,6 +1840,7 @@ pub fn postprocess_extended_package_list(_req: &HttpRequest,
 
     let body =
         helpers::package_results_json(&packages, count as isize, start as isize, stop as isize);
+    let body = filter_body(body, fields, PACKAGE_LIST_FIELDS, filter_results);
 
     let mut response = if count as isize > (stop as isize + 1) {
         HttpResponse::PartialContent()
@@ -903,6 +1853,37 @@ pub fn postprocess_extended_package_list(_req: &HttpRequest,
             .body(body)
 }
 
+/// The deprecation notice set for the package name of `ident`, if any
+pub fn package_deprecation(ident: &PackageIdent,
+                           conn: &PgConnection)
+                           -> Result<Option<PackageDeprecation>> {
+    match PackageDeprecation::get(&ident.origin, &ident.name, conn) {
+        Ok(deprecation) => Ok(Some(deprecation)),
+        Err(NotFound) => Ok(None),
+        Err(err) => Err(Error::DieselError(err)),
+    }
+}
+
+pub fn package_upstream(package_id: i64, conn: &PgConnection) -> Result<Option<PackageUpstream>> {
+    match PackageUpstream::get(package_id, conn) {
+        Ok(upstream) => Ok(Some(upstream)),
+        Err(NotFound) => Ok(None),
+        Err(err) => Err(Error::DieselError(err)),
+    }
+}
+
+/// What the dependency check found when the package was uploaded, none for packages uploaded
+/// before there was one
+pub fn package_dependency_report(package_id: i64,
+                                 conn: &PgConnection)
+                                 -> Result<Option<PackageDependencyReport>> {
+    match PackageDependencyReport::get(package_id, conn) {
+        Ok(report) => Ok(Some(report)),
+        Err(NotFound) => Ok(None),
+        Err(err) => Err(Error::DieselError(err)),
+    }
+}
+
 // Internal - these functions should return Result<..>
 //
 fn do_get_packages(req: &HttpRequest,
@@ -1043,6 +2024,55 @@ fn do_upload_package_finish(req: &HttpRequest,
                                        Body::from_message("ds:up:3"));
     }
 
+    match signed_with_expired_key(req, temp_path) {
+        Ok(Some(key_name)) => {
+            debug!("Package signed with expired key {}", key_name);
+            let msg = format!("ds:up:7, signing key {} has expired", key_name);
+            return HttpResponse::with_body(StatusCode::UNPROCESSABLE_ENTITY,
+                                           Body::from_message(msg));
+        }
+        Ok(None) => (),
+        Err(err) => return err.into(),
+    }
+
+    match upload_policy_violations(req, ident, &mut archive) {
+        Ok(ref violations) if violations.is_empty() => (),
+        Ok(violations) => {
+            match override_upload_policy(req, ident, target_from_artifact, &violations) {
+                Ok(true) => (),
+                Ok(false) => {
+                    debug!("Package {} violates its origin's upload policy: {:?}",
+                           ident, violations);
+                    let body = json!({ "violations": violations });
+                    return HttpResponse::UnprocessableEntity().json(body);
+                }
+                Err(err) => return err.into(),
+            }
+        }
+        Err(err) => return err.into(),
+    }
+
+    let dependency_report = match dependency_report(req, ident, target_from_artifact, &mut archive)
+    {
+        Ok(report) => report,
+        Err(err) => return err.into(),
+    };
+    if !dependency_report.missing.is_empty() {
+        match rejects_missing_dependencies(req, ident, target_from_artifact, &dependency_report) {
+            Ok(true) => {
+                debug!("Package {} has missing dependencies: {:?}",
+                       ident, dependency_report.missing);
+                let body = json!({ "missing_dependencies": dependency_report.missing });
+                return HttpResponse::UnprocessableEntity().json(body);
+            }
+            Ok(false) => {
+                warn!("Package {} uploaded with missing dependencies: {:?}",
+                      ident, dependency_report.missing)
+            }
+            Err(err) => return err.into(),
+        }
+    }
+
     // Check with scheduler to ensure we don't have circular deps, if configured
     if feat::is_enabled(feat::Jobsrv) {
         match has_circular_deps(&req, ident, target_from_artifact, &mut archive) {
@@ -1065,40 +2095,58 @@ fn do_upload_package_finish(req: &HttpRequest,
         }
     }
 
+    let encrypted = match encrypt_for_storage(req, ident, &filename) {
+        Ok(encrypted) => encrypted,
+        Err(err) => {
+            warn!("Unable to encrypt archive {:?}, err={:?}", filename, err);
+            return err.into();
+        }
+    };
+    let stored_path = encrypted.as_ref().map_or(&filename, |e| &e.path);
+
     // TODO: Make upload async
     // TODO: Aggregate Artifactory/S3 into a provider model
-    if feat::is_enabled(feat::Artifactory) {
-        if let Err(err) = req_state(req).artifactory
-                                        .upload(&filename, &temp_ident, target_from_artifact)
-                                        .map_err(Error::Artifactory)
-        {
-            warn!("Unable to upload archive to artifactory!");
-            return err.into();
+    let stored = if feat::is_enabled(feat::Artifactory) {
+        req_state(req).artifactory
+                      .upload(stored_path, &temp_ident, target_from_artifact)
+                      .map(|_| ())
+                      .map_err(Error::Artifactory)
+    } else {
+        req_state(req).packages
+                      .upload(stored_path, &temp_ident, target_from_artifact)
+    };
+    if let Err(err) = stored {
+        warn!("Unable to upload archive to the artifact store, err={}",
+              err);
+        if req_state(req).upload_spool.accepts(&err) {
+            return spool_upload(req,
+                                qupload,
+                                ident,
+                                target_from_artifact,
+                                &checksum_from_artifact,
+                                &filename,
+                                encrypted.as_ref(),
+                                &dependency_report);
         }
-    } else if let Err(err) = req_state(req).packages
-                                           .upload(&filename, &temp_ident, target_from_artifact)
-    {
-        warn!("Unable to upload archive to s3!");
         return err.into();
     }
 
-    debug!("File added to Depot: {:?}", &filename);
+    debug!("File added to Depot: {:?}", stored_path);
 
-    let mut archive = PackageArchive::new(filename.clone());
-    let mut package = match NewPackage::from_archive(&mut archive) {
-        Ok(package) => package,
-        Err(e) => {
-            debug!("Error building package from archive: {:#?}", e);
-            return Error::HabitatCore(e).into();
+    if let Err(err) = record_encryption(req,
+                                        ident,
+                                        target_from_artifact,
+                                        &checksum_from_artifact,
+                                        encrypted.as_ref())
+    {
+        warn!("Unable to record encryption of {}, err={:?}", ident, err);
+        return err.into();
+    }
+    if let Some(ref encrypted) = encrypted {
+        if let Err(e) = remove_file(&encrypted.path) {
+            warn!("Failed to remove encrypted file after upload: {:?}, {}",
+                  &encrypted.path, e)
         }
-    };
-
-    if !ident.satisfies(&*package.ident) {
-        debug!("Ident mismatch, expected={:?}, got={:?}",
-               ident, package.ident);
-
-        return HttpResponse::with_body(StatusCode::UNPROCESSABLE_ENTITY,
-                                       Body::from_message("ds:up:6"));
     }
 
     let session = authorize_session(&req, None).unwrap(); // Unwrap Ok
@@ -1108,90 +2156,30 @@ fn do_upload_package_finish(req: &HttpRequest,
         Err(err) => return err.into(),
     };
 
-    package.owner_id = session.get_id() as i64;
-    package.origin = ident.clone().origin;
-
-    // First, try to fetch visibility settings from a project, if one exists
-    let project_name = format!("{}/{}", ident.origin.clone(), ident.name.clone());
-
-    package.visibility = match Project::get(&project_name, &*conn) {
-        // TED if this is in-fact optional in the db it should be an option in the model
-        Ok(proj) => proj.visibility,
-        Err(_) => {
-            match Origin::get(&ident.origin, &*conn) {
-                Ok(o) => o.default_package_visibility,
-                Err(err) => return Error::DieselError(err).into(),
-            }
-        }
+    let stored =
+        StoredUpload { ident,
+                       target: target_from_artifact,
+                       archive_path: &filename,
+                       owner_id: session.get_id(),
+                       owner_name: session.get_name(),
+                       build_cache_key: qupload.build_cache_key.as_ref().map(String::as_str),
+                       from_builder: qupload.builder.is_some(),
+                       dependency_report: Some(&dependency_report) };
+    let jobsrv = Jobsrv { client: &req_state(req).jobsrv,
+                          budget: deadline::remaining(req), };
+    let jobsrv = if feat::is_enabled(feat::Jobsrv) {
+        Some(&jobsrv)
+    } else {
+        None
     };
-
-    // Re-create origin package as needed (eg, checksum update)
-    match Package::create(&package, &*conn) {
-        Ok(pkg) => {
-            if feat::is_enabled(feat::Jobsrv) {
-                let mut job_graph_package = jobsrv::JobGraphPackageCreate::new();
-                job_graph_package.set_package(pkg.into());
-
-                match route_message::<jobsrv::JobGraphPackageCreate, originsrv::OriginPackage>(
-                    &req,
-                    &job_graph_package,
-                ) {
-                    Ok(_) => (),
-                    Err(Error::BuilderCore(RpcError(code, _)))
-                        if StatusCode::from_u16(code).unwrap() == StatusCode::NOT_FOUND =>
-                    {
-                        debug!(
-                            "Graph not found for package target: {}",
-                            target_from_artifact
-                        );
-                    }
-                    Err(err) => {
-                        debug!("Failed to create job graph package, err={:?}", err);
-                        return err.into();
-                    }
-                }
-            }
-        }
-        Err(NotFound) => {
-            debug!("Package::create returned NotFound (DB conflict handled)");
-        }
-        Err(err) => {
-            debug!("Failed to create package in DB, err: {:?}", err);
-            return Error::DieselError(err).into();
-        }
-    }
-
-    // Schedule re-build of dependent packages (if requested)
-    // Don't schedule builds if the upload is being done by the builder
-    if qupload.builder.is_none()
-       && feat::is_enabled(feat::Jobsrv)
-       && req_state(req).config.api.build_on_upload
-    {
-        let mut request = jobsrv::JobGroupSpec::new();
-        request.set_origin(ident.origin.to_string());
-        request.set_package(ident.name.to_string());
-        request.set_target(target_from_artifact.to_string());
-        request.set_deps_only(true);
-        request.set_origin_only(false);
-        request.set_package_only(false);
-        request.set_trigger(jobsrv::JobGroupTrigger::Upload);
-        request.set_requester_id(session.get_id());
-        request.set_requester_name(session.get_name().to_string());
-
-        match route_message::<jobsrv::JobGroupSpec, jobsrv::JobGroup>(&req, &request) {
-            Ok(group) => {
-                debug!("Scheduled reverse dependecy build for {}, group id: {}",
-                       ident,
-                       group.get_id())
-            }
-            Err(Error::BuilderCore(RpcError(code, _)))
-                if StatusCode::from_u16(code).unwrap() == StatusCode::NOT_FOUND =>
-            {
-                debug!("Unable to schedule build for {} (not found)", ident)
-            }
-            Err(err) => warn!("Unable to schedule build for {}, err: {:?}", ident, err),
+    let package_ident = match ingest::finish(&stored, &req_state(req).config, jobsrv, &*conn) {
+        Ok(package_ident) => package_ident,
+        Err(Error::Unprocessable) => {
+            return HttpResponse::with_body(StatusCode::UNPROCESSABLE_ENTITY,
+                                           Body::from_message("ds:up:6"));
         }
-    }
+        Err(err) => return err.into(),
+    };
 
     match remove_file(&filename) {
         Ok(_) => {
@@ -1205,7 +2193,58 @@ fn do_upload_package_finish(req: &HttpRequest,
     }
 
     HttpResponse::Created().header(http::header::LOCATION, format!("{}", req.uri()))
-                           .body(format!("/pkgs/{}/download", *package.ident))
+                           .body(format!("/pkgs/{}/download", package_ident))
+}
+
+// The artifact store is unavailable. The verified archive waits in the spool until the store is
+// back, and the client polls the ingestion to learn when it is stored.
+fn spool_upload(req: &HttpRequest,
+                qupload: &Query<Upload>,
+                ident: &PackageIdent,
+                target: PackageTarget,
+                checksum: &str,
+                archive_path: &PathBuf,
+                encrypted: Option<&EncryptedArtifact>,
+                dependency_report: &DependencyReport)
+                -> HttpResponse {
+    let session = authorize_session(&req, None).unwrap(); // Unwrap Ok
+    let encryption = encrypted.map(|e| {
+                         SpooledEncryption { key_id:           e.key_id.clone(),
+                                             wrapped_data_key: e.wrapped_data_key.clone(), }
+                     });
+    let upload = SpooledUpload { ident: ident.to_string(),
+                                 target: target.to_string(),
+                                 checksum: checksum.to_string(),
+                                 owner_id: session.get_id(),
+                                 owner_name: session.get_name().to_string(),
+                                 build_cache_key: qupload.build_cache_key.clone(),
+                                 from_builder: qupload.builder.is_some(),
+                                 encryption,
+                                 dependency_report: Some(dependency_report.clone()) };
+    let encrypted_path = encrypted.map(|e| e.path.as_path());
+
+    match req_state(req).upload_spool
+                        .spool(upload, archive_path, encrypted_path)
+    {
+        Ok(entry) => {
+            info!("Spooled upload of {} ({}), ingestion {}",
+                  ident, target, entry.id);
+            HttpResponse::Accepted().header(http::header::LOCATION,
+                                            format!("/v1/depot/ingestions/{}", entry.id))
+                                    .json(entry.ingestion())
+        }
+        Err(err) => {
+            warn!("Unable to spool upload of {}, err={}", ident, err);
+            for path in Some(archive_path.as_path()).into_iter()
+                                                    .chain(encrypted_path)
+            {
+                if let Err(e) = remove_file(path) {
+                    warn!("Failed to remove file of refused upload: {:?}, {}", path, e)
+                }
+            }
+            err.into()
+        }
+    }
 }
 
 fn do_upload_package_async(req: HttpRequest,
@@ -1235,6 +2274,93 @@ fn do_upload_package_async(req: HttpRequest,
     )
 }
 
+// Every package in the tree is looked up with the caller's visibility for its own origin
+fn do_get_package_deps(req: &HttpRequest,
+                       ident: &PackageIdent,
+                       tree: bool,
+                       depth: usize)
+                       -> Result<serde_json::Value> {
+    let opt_session_id = match authorize_session(req, None) {
+        Ok(session) => Some(session.get_id()),
+        Err(_) => None,
+    };
+
+    let conn = req_state(req).db.get_conn().map_err(Error::DbError)?;
+
+    let mut visibilities = HashMap::new();
+    let mut lookup = |dep: &PackageIdent| -> Result<Option<Package>> {
+        let visibility = visibilities.entry(dep.origin.clone())
+                                     .or_insert_with(|| {
+                                         helpers::visibility_for_optional_session(req,
+                                                                                  opt_session_id,
+                                                                                  &dep.origin)
+                                     })
+                                     .clone();
+        match Package::get_without_target(BuilderPackageIdent(dep.clone()), visibility, &*conn) {
+            Ok(pkg) => Ok(Some(pkg)),
+            Err(NotFound) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    };
+
+    let pkg = lookup(ident)?.ok_or(Error::NotFound)?;
+
+    if !tree {
+        return Ok(json!({
+            "ident": pkg.ident.to_string(),
+            "deps": idents_to_strings(&pkg.deps),
+            "tdeps": idents_to_strings(&pkg.tdeps),
+            "build_deps": idents_to_strings(&pkg.build_deps),
+            "build_tdeps": idents_to_strings(&pkg.build_tdeps),
+        }));
+    }
+
+    let root = dep_tree::build(&pkg.ident.to_string(),
+                               package_deps(&pkg),
+                               depth,
+                               |dep| -> Result<_> {
+                                   let dep = PackageIdent::from_str(dep)?;
+                                   Ok(lookup(&dep)?.as_ref().map(package_deps))
+                               })?;
+    Ok(serde_json::to_value(root)?)
+}
+
+fn package_deps(pkg: &Package) -> PackageDeps {
+    PackageDeps { deps:       idents_to_strings(&pkg.deps),
+                  build_deps: idents_to_strings(&pkg.build_deps), }
+}
+
+// The 404 of a package that does not exist, suggesting the names of the origin's packages near
+// the one asked for when there are any
+fn package_not_found(req: &HttpRequest, ident: &PackageIdent) -> HttpResponse {
+    match suggested_names(req, ident) {
+        Ok(ref names) if !names.is_empty() => {
+            HttpResponse::NotFound().json(json!({ "suggestions": names }))
+        }
+        Ok(_) => Error::NotFound.into(),
+        Err(err) => {
+            debug!("Unable to suggest package names, {}", err);
+            Error::NotFound.into()
+        }
+    }
+}
+
+fn suggested_names(req: &HttpRequest, ident: &PackageIdent) -> Result<Vec<String>> {
+    let opt_session_id = match authorize_session(req, None) {
+        Ok(session) => Some(session.get_id()),
+        Err(_) => None,
+    };
+    let visibility = helpers::visibility_for_optional_session(req, opt_session_id, &ident.origin);
+    let conn = req_state(req).db.get_conn().map_err(Error::DbError)?;
+
+    Package::suggest_names(&ident.origin, &ident.name, visibility, &*conn)
+        .map_err(Error::DieselError)
+}
+
+fn idents_to_strings(idents: &[BuilderPackageIdent]) -> Vec<String> {
+    idents.iter().map(|ident| ident.to_string()).collect()
+}
+
 fn do_get_package(req: &HttpRequest,
                   qtarget: &Query<Target>,
                   ident: &PackageIdent)
@@ -1292,58 +2418,30 @@ fn do_get_package(req: &HttpRequest,
         };
     }
 
-    let pkg = if ident.fully_qualified() {
-        match Package::get_without_target(BuilderPackageIdent(ident.clone()),
-                                          helpers::visibility_for_optional_session(req,
-                                                                                   opt_session_id,
-                                                                                   &ident.origin),
-                                          &*conn)
+    let pkg = match lookup_package(req, ident, target, opt_session_id, &*conn) {
+        Ok(pkg) => pkg,
+        Err(NotFound)
+            if req_state(req).upstream
+                             .fetches_for(&ident.origin, opt_session_id.is_some()) =>
         {
-            Ok(pkg) => pkg,
-            Err(NotFound) => {
-                let mut memcache = req_state(req).memcache.borrow_mut();
-                memcache.set_package(&ident,
-                                     None,
-                                     &ChannelIdent::unstable(),
-                                     &target,
-                                     opt_session_id);
-                return Err(Error::NotFound);
-            }
-
-            Err(err) => {
-                debug!("{:?}", err);
-                return Err(err.into());
-            }
+            req_state(req).upstream.fetch(ident, target)?;
+            req_state(req).memcache
+                          .borrow_mut()
+                          .clear_cache_for_package(ident);
+            lookup_package(req, ident, target, opt_session_id, &*conn)?
         }
-    } else {
-        match Package::get_latest(
-            GetLatestPackage {
-                ident: BuilderPackageIdent(ident.clone()),
-                target: BuilderPackageTarget(target),
-                visibility: helpers::visibility_for_optional_session(
-                    req,
-                    opt_session_id,
-                    &ident.origin,
-                ),
-            },
-            &*conn,
-        ) {
-            Ok(pkg) => pkg.into(),
-            Err(NotFound) => {
-                let mut memcache = req_state(req).memcache.borrow_mut();
-                memcache.set_package(
-                    &ident,
-                    None,
-                    &ChannelIdent::unstable(),
-                    &target,
-                    opt_session_id,
-                );
-                return Err(Error::NotFound);
-            }
-            Err(err) => {
-                debug!("{:?}", err);
-                return Err(err.into());
-            }
+        Err(NotFound) => {
+            let mut memcache = req_state(req).memcache.borrow_mut();
+            memcache.set_package(&ident,
+                                 None,
+                                 &ChannelIdent::unstable(),
+                                 &target,
+                                 opt_session_id);
+            return Err(Error::NotFound);
+        }
+        Err(err) => {
+            debug!("{:?}", err);
+            return Err(err.into());
         }
     };
 
@@ -1352,6 +2450,19 @@ fn do_get_package(req: &HttpRequest,
 
     pkg_json["channels"] = json!(channels);
     pkg_json["is_a_service"] = json!(pkg.is_a_service());
+    pkg_json["release_at"] = json!(pkg.ident
+                                      .release
+                                      .as_ref()
+                                      .and_then(|r| timestamp::release_to_rfc3339(r)));
+    if let Some(deprecation) = package_deprecation(&pkg.ident, &*conn)? {
+        pkg_json["deprecation"] = json!(deprecation);
+    }
+    if let Some(upstream) = package_upstream(pkg.id, &*conn)? {
+        pkg_json["upstream"] = json!(upstream);
+    }
+    if let Some(report) = package_dependency_report(pkg.id, &*conn)? {
+        pkg_json["dependency_report"] = json!(report);
+    }
 
     let json_body = serde_json::to_string(&pkg_json).unwrap();
 
@@ -1370,6 +2481,111 @@ fn do_get_package(req: &HttpRequest,
 // Internal helpers
 //
 
+// A fully qualified ident is looked up as is, any other resolves to its latest release
+fn lookup_package(req: &HttpRequest,
+                  ident: &PackageIdent,
+                  target: PackageTarget,
+                  opt_session_id: Option<u64>,
+                  conn: &PgConnection)
+                  -> QueryResult<Package> {
+    let visibility = helpers::visibility_for_optional_session(req, opt_session_id, &ident.origin);
+    if ident.fully_qualified() {
+        Package::get_without_target(BuilderPackageIdent(ident.clone()), visibility, conn)
+    } else {
+        let latest = GetLatestPackage { ident: BuilderPackageIdent(ident.clone()),
+                                        target: BuilderPackageTarget(target),
+                                        visibility };
+        Package::get_latest(latest, conn).map(Into::into)
+    }
+}
+
+// A replacement names another package by origin and name only
+fn valid_replacement(replacement: &str, ident: &PackageIdent) -> bool {
+    match PackageIdent::from_str(replacement) {
+        Ok(replacement) => {
+            replacement.valid() && replacement.version.is_none() && replacement != *ident
+        }
+        Err(_) => false,
+    }
+}
+
+// Parse the comma separated `exclude=` list of origin/name pairs, returning None if an entry
+// is not a bare package name or names the package being rebuilt
+fn schedule_exclusions(schedule: &Schedule, root: &PackageIdent) -> Option<Vec<String>> {
+    let mut exclude = Vec::new();
+    if let Some(ref list) = schedule.exclude {
+        for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            match PackageIdent::from_str(name) {
+                Ok(ref ident) if ident.valid() && ident.version.is_none() && ident != root => {
+                    exclude.push(ident.to_string())
+                }
+                _ => return None,
+            }
+        }
+    }
+    Some(exclude)
+}
+
+// Build cache keys are the hex encoded SHA-256 digests computed by the worker
+fn valid_build_cache_key(key: &str) -> bool {
+    key.len() == 64 && key.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// Pairs each search result with the deprecation notice of its package
+fn search_results(packages: Vec<BuilderPackageIdent>,
+                  conn: &PgConnection)
+                  -> Result<Vec<SearchResult>> {
+    let mut names: Vec<String> = packages.iter()
+                                         .map(|ident| format!("{}/{}", ident.origin, ident.name))
+                                         .collect();
+    names.sort();
+    names.dedup();
+
+    let deprecations: HashMap<String, PackageDeprecation> =
+        PackageDeprecation::list(&names, conn).map_err(Error::DieselError)?
+                                              .into_iter()
+                                              .map(|d| (d.short_name(), d))
+                                              .collect();
+
+    Ok(packages.into_iter()
+               .map(|ident| {
+                   let name = format!("{}/{}", ident.origin, ident.name);
+                   let deprecation = deprecations.get(&name).cloned();
+                   SearchResult { ident, deprecation }
+               })
+               .collect())
+}
+
+// Parse the comma separated `origins=` search filter, returning None if it names an invalid
+// origin. An absent or empty filter searches every origin.
+fn search_origins_filter(search: &Search) -> Option<Vec<String>> {
+    let mut origins = Vec::new();
+    if let Some(ref list) = search.origins {
+        for origin in list.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+            if !ident::is_valid_origin_name(origin) {
+                return None;
+            }
+            origins.push(origin.to_string());
+        }
+    }
+    Some(origins)
+}
+
+// The searching account's origin ranking preference, falling back to the operator default
+// for anonymous searches and accounts that have not set one
+fn preferred_search_origins(account_id: Option<i64>,
+                            state: &AppState,
+                            conn: &PgConnection)
+                            -> Result<Vec<String>> {
+    if let Some(account_id) = account_id {
+        let account = Account::get_by_id(account_id, conn).map_err(Error::DieselError)?;
+        if !account.search_origins.is_empty() {
+            return Ok(account.search_origins);
+        }
+    }
+    Ok(state.config.api.search_origins.clone())
+}
+
 // Return a formatted string representing the filename of an archive for the given package
 // identifier pieces.
 fn archive_name(ident: &PackageIdent, target: PackageTarget) -> PathBuf {
@@ -1380,29 +2596,293 @@ fn archive_name(ident: &PackageIdent, target: PackageTarget) -> PathBuf {
                                                         }))
 }
 
-fn download_response_for_archive(archive: &PackageArchive, file_path: &PathBuf) -> HttpResponse {
-    let filename = archive.file_name();
-    let file = match File::open(&file_path) {
-        Ok(f) => f,
+// Returns the name of the signing key when it has been rotated out and its overlap window has
+// closed. Keys Builder does not know about are left for later checks to reject.
+fn signed_with_expired_key(req: &HttpRequest, archive_path: &StdPath) -> Result<Option<String>> {
+    let header = artifact::get_artifact_header(archive_path)?;
+    let (origin, revision) = parse_name_with_rev(&header.key_name)?;
+
+    let conn = req_state(req).db.get_conn().map_err(Error::DbError)?;
+    match OriginPublicSigningKey::get(&origin, &revision, &*conn) {
+        Ok(key) if key.is_expired() => Ok(Some(header.key_name)),
+        Ok(_) | Err(NotFound) => Ok(None),
+        Err(err) => Err(Error::DieselError(err)),
+    }
+}
+
+// Encrypts the archive beside itself when its origin has artifact encryption set, none otherwise
+fn encrypt_for_storage(req: &HttpRequest,
+                       ident: &PackageIdent,
+                       archive_path: &PathBuf)
+                       -> Result<Option<EncryptedArtifact>> {
+    let conn = req_state(req).db.get_conn().map_err(Error::DbError)?;
+    let keys = &*req_state(req).artifact_keys;
+    artifact_encryption::encrypt_for_origin(keys, &ident.origin, archive_path, &*conn)
+}
+
+// Records how the stored artifact is encrypted
+fn record_encryption(req: &HttpRequest,
+                     ident: &PackageIdent,
+                     target: PackageTarget,
+                     checksum: &str,
+                     encrypted: Option<&EncryptedArtifact>)
+                     -> Result<()> {
+    let conn = req_state(req).db.get_conn().map_err(Error::DbError)?;
+    artifact_encryption::record(ident, target, checksum, encrypted, &*conn)
+}
+
+// Decrypts a downloaded artifact to `file_path`, refusing it unless it is the artifact that was
+// uploaded
+fn decrypt_download(state: &AppState,
+                    encryption: &PackageEncryption,
+                    fetched: &PathBuf,
+                    file_path: &PathBuf)
+                    -> Result<PackageArchive> {
+    artifact_encryption::decrypt_artifact(&*state.artifact_keys,
+                                          &encryption.key_id,
+                                          &encryption.wrapped_data_key,
+                                          fetched,
+                                          file_path)?;
+    let mut archive = PackageArchive::new(file_path);
+    if archive.checksum()? != encryption.plaintext_checksum {
+        let msg = format!("checksum of decrypted {} does not match", *encryption.ident);
+        return Err(Error::ArtifactEncryption(msg));
+    }
+    Ok(archive)
+}
+
+// The policies of the package's origin it fails, none when the origin has no upload policy
+fn upload_policy_violations(req: &HttpRequest,
+                            ident: &PackageIdent,
+                            archive: &mut PackageArchive)
+                            -> Result<Vec<Violation>> {
+    let conn = req_state(req).db.get_conn().map_err(Error::DbError)?;
+    match OriginUploadPolicy::get(&ident.origin, &*conn)? {
+        Some(policy) => {
+            let manifest = archive.manifest()?;
+            Ok(upload_policy::violations(&policy, &manifest))
+        }
+        None => Ok(vec![]),
+    }
+}
+
+// The runtime dependencies of the upload the depot does not have, or has deprecated, looked up
+// in one query
+fn dependency_report(req: &HttpRequest,
+                     ident: &PackageIdent,
+                     target: PackageTarget,
+                     archive: &mut PackageArchive)
+                     -> Result<DependencyReport> {
+    let deps = archive.deps()?
+                      .iter()
+                      .map(PackageIdent::to_string)
+                      .collect::<Vec<_>>();
+    if deps.is_empty() {
+        return Ok(DependencyReport::default());
+    }
+
+    let conn = req_state(req).db.get_conn().map_err(Error::DbError)?;
+    let statuses = DependencyStatus::list(&deps, &target.to_string(), &ident.origin, &*conn)?;
+    Ok(DependencyReport::new(statuses))
+}
+
+// Whether the package's origin rejects uploads with missing dependencies, and the upload doesn't
+// carry the operator's override token
+fn rejects_missing_dependencies(req: &HttpRequest,
+                                ident: &PackageIdent,
+                                target: PackageTarget,
+                                report: &DependencyReport)
+                                -> Result<bool> {
+    let conn = req_state(req).db.get_conn().map_err(Error::DbError)?;
+    let policy = OriginUploadPolicy::get(&ident.origin, &*conn)?;
+    if !policy.map_or(false, |p| p.strict_dependency_validation) {
+        return Ok(false);
+    }
+    Ok(!override_upload_policy(req,
+                               ident,
+                               target,
+                               &report.violations())?)
+}
+
+// Whether the upload carries the operator's override token. An overridden upload is let through
+// its violations and audited. The token comes in a header, so it stays out of access logs.
+fn override_upload_policy(req: &HttpRequest,
+                          ident: &PackageIdent,
+                          target: PackageTarget,
+                          violations: &[Violation])
+                          -> Result<bool> {
+    let token = &req_state(req).config.api.policy_override_token;
+    let given = req.headers()
+                   .get(headers::XBUILDERPOLICYOVERRIDE)
+                   .and_then(|value| value.to_str().ok());
+    match given {
+        Some(given) if !token.is_empty() && crypto::secure_eq(given, token) => (),
+        Some(_) => {
+            warn!("Invalid upload policy override token given for {}", ident);
+            return Ok(false);
+        }
+        None => return Ok(false),
+    }
+
+    let session = authorize_session(req, None)?;
+    let conn = req_state(req).db.get_conn().map_err(Error::DbError)?;
+    let violations = violations.iter()
+                               .map(Violation::to_string)
+                               .collect::<Vec<_>>();
+    let package_ident = ident.to_string();
+    let target = target.to_string();
+    let audit = UploadPolicyOverrideAudit { origin:         &ident.origin,
+                                            package_ident:  &package_ident,
+                                            target:         &target,
+                                            violations:     &violations,
+                                            requester_id:   session.get_id() as i64,
+                                            requester_name: session.get_name(), };
+    UploadPolicyOverrideAudit::audit(&audit, &*conn)?;
+    warn!("Upload policy overridden for {} by {}: {:?}",
+          ident,
+          session.get_name(),
+          violations);
+    Ok(true)
+}
+
+// Where the bytes of a download are read from
+enum Archive<'a> {
+    /// An archive fetched for this download
+    File(File),
+    /// An archive of the download cache, which counts the bytes it serves
+    Cached(File, &'a DownloadCache),
+    /// An artifact of the given length left in the artifact store, of which only the range asked
+    /// for is fetched
+    Stored(&'a AppState, &'a PackageIdent, PackageTarget, u64),
+}
+
+// Answers with the archive, or the byte range of it the request asks for. A HEAD request gets
+// the headers alone.
+fn download_response(req: &HttpRequest,
+                     filename: String,
+                     archive: Archive,
+                     deprecation: Option<&PackageDeprecation>)
+                     -> HttpResponse {
+    let len = match archive {
+        Archive::File(ref file) | Archive::Cached(ref file, _) => {
+            match file.metadata() {
+                Ok(metadata) => metadata.len(),
+                Err(err) => return Error::IO(err).into(),
+            }
+        }
+        Archive::Stored(_, _, _, len) => len,
+    };
+    let range = req.headers()
+                   .get(http::header::RANGE)
+                   .and_then(|range| range.to_str().ok());
+    let (mut response, start, end) = match headers::byte_range(range, len) {
+        ByteRange::Full => (HttpResponse::Ok(), 0, len),
+        ByteRange::Partial(first, last) => {
+            let mut response = HttpResponse::PartialContent();
+            response.header(http::header::CONTENT_RANGE,
+                            format!("bytes {}-{}/{}", first, last, len));
+            (response, first, last + 1)
+        }
+        ByteRange::Unsatisfiable => {
+            return HttpResponse::RangeNotSatisfiable().header(http::header::CONTENT_RANGE,
+                                                              format!("bytes */{}", len))
+                                                      .finish();
+        }
+    };
+
+    response.header(http::header::CONTENT_DISPOSITION,
+                    ContentDisposition { disposition: DispositionType::Attachment,
+                                         parameters:
+                                             vec![DispositionParam::Filename(filename.clone())], })
+            .header(http::header::HeaderName::from_static(headers::XFILENAME),
+                    filename)
+            .set(ContentType::octet_stream())
+            .header(http::header::CACHE_CONTROL, headers::cache(true))
+            .header(http::header::ACCEPT_RANGES, "bytes")
+            .header(http::header::CONTENT_LENGTH, end - start)
+            .no_chunking();
+
+    if let Some(deprecation) = deprecation {
+        response.header(http::header::HeaderName::from_static(headers::XPACKAGEDEPRECATED),
+                        deprecation_header(deprecation));
+    }
+
+    if req.method() == http::Method::HEAD || start == end {
+        return response.streaming(futures::stream::empty::<Bytes, error::Error>());
+    }
+
+    let file = match archive {
+        Archive::File(file) => file,
+        Archive::Cached(file, cache) => {
+            cache.served(end - start);
+            file
+        }
+        Archive::Stored(state, ident, target, _) => {
+            return match state.packages.download_range(ident, target, start, end - 1) {
+                Ok(bytes) => {
+                    response.streaming(stream::once::<Bytes, error::Error>(Ok(Bytes::from(bytes))))
+                }
+                Err(err) => {
+                    warn!("Failed to download package range, ident={}, err={:?}",
+                          ident, err);
+                    err.into()
+                }
+            };
+        }
+    };
+
+    // The archive is read as the client takes it, a client that goes away stops the reading
+    let body = match archive_chunks(file, start, end - start) {
+        Ok(body) => body,
         Err(err) => {
-            warn!("Unable to open file: {:?}", file_path);
+            warn!("Unable to seek archive for download, err={}", err);
             return Error::IO(err).into();
         }
     };
+    response.streaming(body)
+}
+
+// Reads `len` bytes of the archive from `start` a chunk at a time, each read done on the shared
+// blocking pool when the body asks for the next chunk
+fn archive_chunks(mut file: File,
+                  start: u64,
+                  len: u64)
+                  -> io::Result<impl Stream<Item = Bytes, Error = error::Error>> {
+    file.seek(SeekFrom::Start(start))?;
     let reader = BufReader::new(file);
-    let bytes: Vec<u8> = reader.bytes().map(|r| r.unwrap()).collect();
 
-    let (tx, rx_body) = mpsc::unbounded();
-    let _ = tx.unbounded_send(Bytes::from(bytes));
+    Ok(stream::unfold((reader, len), |(mut reader, left)| {
+        if left == 0 {
+            return None;
+        }
+        let read = web::block(move || {
+            let mut chunk = vec![0u8; cmp::min(left, DOWNLOAD_CHUNK_SIZE) as usize];
+            reader.read_exact(&mut chunk)?;
+            let left = left - chunk.len() as u64;
+            Ok::<_, io::Error>((Bytes::from(chunk), (reader, left)))
+        });
+        Some(read.map_err(|err| {
+                     warn!("Unable to read archive for download, err={}", err);
+                     error::ErrorInternalServerError(err)
+                 }))
+    }))
+}
 
-    HttpResponse::Ok().header(http::header::CONTENT_DISPOSITION,
-            ContentDisposition { disposition: DispositionType::Attachment,
-                                 parameters:  vec![DispositionParam::Filename(filename)], })
-    .header(http::header::HeaderName::from_static(headers::XFILENAME),
-            archive.file_name())
-    .set(ContentType::octet_stream())
-    .header(http::header::CACHE_CONTROL, headers::cache(true))
-    .streaming(rx_body.map_err(|_| error::ErrorBadRequest("bad request")))
+fn header_char(c: char) -> char {
+    if c.is_ascii_graphic() || c == ' ' {
+        c
+    } else {
+        '?'
+    }
+}
+
+// The message, with anything a header cannot carry replaced, followed by the replacement
+fn deprecation_header(deprecation: &PackageDeprecation) -> String {
+    let mut value: String = deprecation.message.chars().map(header_char).collect();
+    if let Some(ref replacement) = deprecation.replacement {
+        value.push_str(&format!("; replacement={}", replacement));
+    }
+    value
 }
 
 #[allow(clippy::needless_pass_by_value)]
@@ -1496,3 +2976,55 @@ pub fn platforms_for_package_ident(req: &HttpRequest,
         Err(err) => Err(Error::DieselError(err)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::ResponseBody;
+
+    fn body(resp: &HttpResponse) -> serde_json::Value {
+        match resp.body() {
+            ResponseBody::Body(Body::Bytes(bytes)) | ResponseBody::Other(Body::Bytes(bytes)) => {
+                serde_json::from_slice(bytes).unwrap()
+            }
+            _ => panic!("Expected a JSON body"),
+        }
+    }
+
+    fn group(state: jobsrv::JobGroupState) -> jobsrv::JobGroup {
+        let mut group = jobsrv::JobGroup::new();
+        group.set_id(1_130_187_394_418_761_728);
+        group.set_state(state);
+        group.set_project_name("core/glibc".to_string());
+        group.set_target("x86_64-linux".to_string());
+        group.set_project_count(11_042);
+        group
+    }
+
+    #[test]
+    fn groups_to_confirm_are_described_without_a_group() {
+        let mut unconfirmed = group(jobsrv::JobGroupState::GroupPending);
+        unconfirmed.set_id(0);
+        unconfirmed.set_confirmation_required(true);
+
+        let resp = schedule_response(false, &unconfirmed);
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        assert_eq!(body(&resp),
+                   json!({ "project_name": "core/glibc",
+                           "target": "x86_64-linux",
+                           "project_count": 11_042 }));
+    }
+
+    #[test]
+    fn groups_are_answered_by_their_state() {
+        let resp = schedule_response(false, &group(jobsrv::JobGroupState::GroupPendingApproval));
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        assert_eq!(body(&resp)["id"], "1130187394418761728");
+
+        let resp = schedule_response(false, &group(jobsrv::JobGroupState::GroupQueued));
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let resp = schedule_response(true, &group(jobsrv::JobGroupState::GroupPending));
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
@@ -21,26 +21,49 @@ use actix_web::{http::{self,
                       ServiceConfig},
                 HttpRequest,
                 HttpResponse};
+use chrono::{Duration,
+             Utc};
 use serde_json;
 
 use crate::{bldr_core,
+            config::ApiCfg,
+            hab_core::package::ident,
             protocol::originsrv};
 
-use crate::db::models::account::*;
+use crate::db::models::{account::*,
+                        origin::Origin};
 
 use crate::server::{authorize::authorize_session,
                     error::{Error,
                             Result},
                     framework::headers,
                     helpers::req_state,
+                    services::security_events,
                     AppState};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct UserUpdateReq {
     #[serde(default)]
-    pub email: String,
+    pub email:                  String,
+    /// Origins to rank first in package search, highest priority first
+    #[serde(default)]
+    pub search_origins:         Option<Vec<String>>,
+    /// Whether high severity security events are sent as notifications
+    #[serde(default)]
+    pub security_notifications: Option<bool>,
+    /// Origin a `~` in request paths resolves to, empty to clear it
+    #[serde(default)]
+    pub default_origin:         Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AccessTokenCreateReq {
+    #[serde(default)]
+    pub expires_in_days: Option<u32>,
 }
 
+const MAX_SEARCH_ORIGINS: usize = 20;
+
 pub struct Profile {}
 
 impl Profile {
@@ -111,12 +134,23 @@ fn get_access_tokens(req: HttpRequest) -> HttpResponse {
 }
 
 #[allow(clippy::needless_pass_by_value)]
-fn generate_access_token(req: HttpRequest, state: Data<AppState>) -> HttpResponse {
+fn generate_access_token(req: HttpRequest,
+                         body: Option<Json<AccessTokenCreateReq>>,
+                         state: Data<AppState>)
+                         -> HttpResponse {
     let account_id = match authorize_session(&req, None) {
         Ok(session) => session.get_id(),
         Err(err) => return err.into(),
     };
 
+    let requested_days = body.and_then(|b| b.expires_in_days);
+    let lifetime_days = match token_lifetime_days(requested_days, &state.config.api) {
+        Ok(days) => days,
+        Err(err) => return err.into(),
+    };
+    let lifetime = lifetime_days.map(|days| Duration::days(i64::from(days)));
+    let expires_at = lifetime.map(|l| (Utc::now() + l).naive_utc());
+
     let conn = match state.db.get_conn().map_err(Error::DbError) {
         Ok(conn_ref) => conn_ref,
         Err(err) => return err.into(),
@@ -141,10 +175,12 @@ fn generate_access_token(req: HttpRequest, state: Data<AppState>) -> HttpRespons
 
     let token = bldr_core::access_token::generate_user_token(&state.config.api.key_path,
                                                              account_id,
-                                                             flags).unwrap();
+                                                             flags,
+                                                             lifetime).unwrap();
 
     let new_token = NewAccountToken { account_id: account_id as i64,
-                                      token:      &token, };
+                                      token: &token,
+                                      expires_at };
 
     match AccountToken::create(&new_token, &*conn).map_err(Error::DieselError) {
         Ok(account_token) => {
@@ -152,6 +188,16 @@ fn generate_access_token(req: HttpRequest, state: Data<AppState>) -> HttpRespons
             for token in access_tokens {
                 memcache.delete_session_key(&token.token)
             }
+
+            let ip = security_events::client_ip(req.head(), &state.config.api.trusted_proxies);
+            let mut event = NewAccountSecurityEvent::new(account_id as i64,
+                                                         AccountSecurityEventKind::TokenCreated);
+            event.token_id = Some(account_token.id);
+            event.ip = ip.as_ref().map(String::as_str);
+            if let Err(err) = security_events::record(&event, &*conn) {
+                warn!("Unable to record token creation for account {}, err={}",
+                      account_id, err);
+            }
             HttpResponse::Ok().json(account_token)
         }
         Err(err) => {
@@ -215,20 +261,101 @@ fn update_account(req: HttpRequest,
         Err(_err) => return HttpResponse::new(StatusCode::UNAUTHORIZED),
     };
 
-    if body.email.is_empty() {
+    if body.email.is_empty()
+       && body.search_origins.is_none()
+       && body.security_notifications.is_none()
+       && body.default_origin.is_none()
+    {
         return HttpResponse::new(StatusCode::BAD_REQUEST);
     }
 
+    let search_origins = match body.search_origins {
+        Some(ref origins) => {
+            match search_origins(origins) {
+                Some(origins) => Some(origins),
+                None => return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY),
+            }
+        }
+        None => None,
+    };
+
     let conn = match state.db.get_conn().map_err(Error::DbError) {
         Ok(conn_ref) => conn_ref,
         Err(err) => return err.into(),
     };
 
-    match Account::update(account_id, &body.email, &*conn).map_err(Error::DieselError) {
-        Ok(_) => HttpResponse::new(StatusCode::OK),
-        Err(err) => {
+    // Only an origin the account belongs to can be its default, an empty one clears it
+    let default_origin = body.default_origin.as_ref().map(|o| o.trim());
+    if let Some(origin) = default_origin.filter(|o| !o.is_empty()) {
+        match Origin::check_membership(origin, account_id as i64, &*conn) {
+            Ok(true) => (),
+            Ok(false) => return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY),
+            Err(err) => return Error::DieselError(err).into(),
+        }
+    }
+
+    if !body.email.is_empty() {
+        if let Err(err) = Account::update(account_id, &body.email, &*conn) {
             debug!("{}", err);
-            err.into()
+            return Error::DieselError(err).into();
+        }
+    }
+
+    if let Some(origins) = search_origins {
+        if let Err(err) = Account::update_search_origins(account_id, &origins, &*conn) {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    }
+
+    if let Some(enabled) = body.security_notifications {
+        if let Err(err) = Account::update_security_notifications(account_id, enabled, &*conn) {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    }
+
+    if let Some(origin) = default_origin {
+        let origin = Some(origin).filter(|o| !o.is_empty());
+        if let Err(err) = Account::update_default_origin(account_id, origin, &*conn) {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
         }
     }
+
+    HttpResponse::new(StatusCode::OK)
+}
+
+// Validate a search ranking preference, dropping repeated origins. Returns None if the list
+// is too long or names an invalid origin.
+fn search_origins(origins: &[String]) -> Option<Vec<String>> {
+    if origins.len() > MAX_SEARCH_ORIGINS {
+        return None;
+    }
+
+    let mut result: Vec<String> = Vec::with_capacity(origins.len());
+    for origin in origins.iter().map(|o| o.trim()) {
+        if !ident::is_valid_origin_name(origin) {
+            return None;
+        }
+        if !result.iter().any(|o| o == origin) {
+            result.push(origin.to_string());
+        }
+    }
+    Some(result)
+}
+
+// Resolve the lifetime of a new token from the requested one and the operator limits.
+// Requests above the configured maximum, including non-expiring ones, are capped
+// rather than rejected.
+fn token_lifetime_days(requested: Option<u32>, config: &ApiCfg) -> Result<Option<u32>> {
+    let max = config.token_max_lifetime_days;
+    match requested {
+        Some(0) => Err(Error::BadRequest),
+        Some(days) if max > 0 && days > max => Ok(Some(max)),
+        Some(days) => Ok(Some(days)),
+        None if max > 0 => Ok(Some(max)),
+        None if config.token_require_expiration => Err(Error::BadRequest),
+        None => Ok(None),
+    }
 }
@@ -19,15 +19,20 @@ use actix_web::{http::{self,
                        StatusCode},
                 web::{self,
                       Data,
-                      Json,
                       Path,
                       Query,
                       ServiceConfig},
                 HttpRequest,
                 HttpResponse};
+use diesel::{pg::PgConnection,
+             result::{Error::NotFound,
+                      QueryResult}};
 use serde_json;
+use url::Url;
 
-use crate::protocol::jobsrv;
+use crate::{bldr_core::{self,
+                        vcs::is_ssh_url},
+            protocol::jobsrv};
 
 use crate::hab_core::package::{PackageIdent,
                                Plan};
@@ -37,39 +42,77 @@ use crate::db::models::{jobs::*,
                         package::{PackageVisibility,
                                   *},
                         project_integration::*,
-                        projects::*};
+                        projects::*,
+                        secrets::OriginSecret};
 
 use crate::server::{authorize::authorize_session,
-                    error::Error,
-                    framework::headers,
+                    error::{Error,
+                            Result},
+                    framework::{headers,
+                                json::ValidatedJson},
                     helpers::{self,
                               Pagination},
                     AppState};
 
+// Longest a webhook build may be held back waiting for further pushes
+const MAX_QUIET_PERIOD_SECS: u32 = 3600;
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct ProjectCreateReq {
     #[serde(default)]
-    pub origin: String,
+    pub origin:                 String,
+    #[serde(default)]
+    pub plan_path:              String,
+    #[serde(default)]
+    pub installation_id:        u32,
+    #[serde(default)]
+    pub repo_id:                u32,
+    #[serde(default)]
+    pub auto_build:             bool,
+    #[serde(default)]
+    pub required_worker_labels: Vec<String>,
+    // Set to `generic_git` for a repository cloned from `vcs_data` rather than through the
+    // GitHub App, in which case `package_name` names the plan's package
     #[serde(default)]
-    pub plan_path: String,
+    pub vcs_type:               String,
     #[serde(default)]
-    pub installation_id: u32,
+    pub vcs_data:               String,
     #[serde(default)]
-    pub repo_id: u32,
+    pub package_name:           String,
     #[serde(default)]
-    pub auto_build: bool,
+    pub vcs_credential:         Option<String>,
+    #[serde(default)]
+    pub webhook_secret:         Option<String>,
+    // Seconds a webhook build waits in the queue for further pushes, 0 to build every push
+    #[serde(default)]
+    pub quiet_period_secs:      u32,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct ProjectUpdateReq {
     #[serde(default)]
-    pub plan_path: String,
+    pub plan_path:              String,
+    #[serde(default)]
+    pub installation_id:        u32,
+    #[serde(default)]
+    pub repo_id:                u32,
+    #[serde(default)]
+    pub auto_build:             bool,
+    // Omitting the labels leaves the project's current labels in place
+    #[serde(default)]
+    pub required_worker_labels: Option<Vec<String>>,
     #[serde(default)]
-    pub installation_id: u32,
+    pub vcs_type:               String,
     #[serde(default)]
-    pub repo_id: u32,
+    pub vcs_data:               String,
     #[serde(default)]
-    pub auto_build: bool,
+    pub vcs_credential:         Option<String>,
+    // Omitting the secret leaves the project's current secret in place
+    #[serde(default)]
+    pub webhook_secret:         Option<String>,
+    // Omitting the quiet period leaves the project's current one in place
+    #[serde(default)]
+    pub quiet_period_secs:      Option<u32>,
 }
 
 pub struct Projects;
@@ -85,6 +128,12 @@ impl Projects {
            .route("/projects/{origin}/{name}",
                   web::delete().to(delete_project))
            .route("/projects/{origin}/{name}/jobs", web::get().to(get_jobs))
+           .route("/projects/{origin}/{name}/stats",
+                  web::get().to(get_build_stats))
+           .route("/projects/{origin}/{name}/settings/history",
+                  web::get().to(get_settings_history))
+           .route("/projects/{origin}/{name}/settings/rollback/{version}",
+                  web::post().to(rollback_settings))
            .route("/projects/{origin}/{name}/integrations/{integration}/default",
                   web::get().to(get_integration))
            .route("/projects/{origin}/{name}/integrations/{integration}/default",
@@ -102,10 +151,14 @@ impl Projects {
 // TODO: the project creation API needs to be simplified
 #[allow(clippy::needless_pass_by_value)]
 fn create_project(req: HttpRequest,
-                  body: Json<ProjectCreateReq>,
+                  body: ValidatedJson<ProjectCreateReq>,
                   state: Data<AppState>)
                   -> HttpResponse {
-    if body.origin.is_empty() || body.plan_path.is_empty() {
+    if body.origin.is_empty()
+       || body.plan_path.is_empty()
+       || !valid_worker_labels(&body.required_worker_labels)
+       || !valid_quiet_period(body.quiet_period_secs)
+    {
         return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
     }
 
@@ -128,19 +181,63 @@ fn create_project(req: HttpRequest,
         }
     };
 
+    // There is no repository to read the plan from for a generic git project, so the package
+    // name comes with the request
+    if body.vcs_type == "generic_git" {
+        let credential = body.vcs_credential.as_ref().map(String::as_str);
+        let encrypted_secret = match encrypt_webhook_secret(&state, body.webhook_secret.as_ref()) {
+            Ok(secret) => secret,
+            Err(err) => return err.into(),
+        };
+        let webhook_secret = encrypted_secret.as_ref().map(String::as_str);
+        if !valid_package_name(&body.package_name)
+           || !valid_generic_git(&origin.name, &body.vcs_data, credential, &*conn)
+        {
+            return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        let name = format!("{}/{}", &origin.name, body.package_name);
+        let new_project = NewProject { owner_id: account_id as i64,
+                                       origin: &origin.name,
+                                       package_name: &body.package_name,
+                                       name: &name,
+                                       plan_path: &body.plan_path,
+                                       vcs_type: "generic_git",
+                                       vcs_data: &body.vcs_data,
+                                       vcs_installation_id: None,
+                                       visibility: &origin.default_package_visibility,
+                                       auto_build: body.auto_build,
+                                       required_worker_labels: &body.required_worker_labels,
+                                       vcs_credential: credential,
+                                       webhook_secret,
+                                       quiet_period_secs: body.quiet_period_secs as i32 };
+
+        return match Project::create(&new_project, &*conn).map_err(Error::DieselError) {
+            Ok(project) => HttpResponse::Created().json(project),
+            Err(err) => {
+                debug!("{}", err);
+                err.into()
+            }
+        };
+    }
+
     // Test hook - bypass the github dance
     if env::var_os("HAB_FUNC_TEST").is_some() {
         let new_project =
-            NewProject { owner_id:            account_id as i64,
-                         origin:              &origin.name,
-                         package_name:        "testapp",
-                         name:                &format!("{}/{}", &origin.name, "testapp"),
-                         plan_path:           &body.plan_path,
-                         vcs_type:            "git",
-                         vcs_data:            "https://github.com/habitat-sh/testapp.git",
-                         vcs_installation_id: Some(i64::from(body.installation_id)),
-                         visibility:          &PackageVisibility::Public,
-                         auto_build:          body.auto_build, };
+            NewProject { owner_id:               account_id as i64,
+                         origin:                 &origin.name,
+                         package_name:           "testapp",
+                         name:                   &format!("{}/{}", &origin.name, "testapp"),
+                         plan_path:              &body.plan_path,
+                         vcs_type:               "git",
+                         vcs_data:               "https://github.com/habitat-sh/testapp.git",
+                         vcs_installation_id:    Some(i64::from(body.installation_id)),
+                         visibility:             &PackageVisibility::Public,
+                         auto_build:             body.auto_build,
+                         required_worker_labels: &body.required_worker_labels,
+                         vcs_credential:         None,
+                         webhook_secret:         None,
+                         quiet_period_secs:      body.quiet_period_secs as i32, };
 
         match Project::create(&new_project, &*conn).map_err(Error::DieselError) {
             Ok(project) => return HttpResponse::Created().json(project),
@@ -204,7 +301,11 @@ fn create_project(req: HttpRequest,
                                    vcs_data: &vcs_data,
                                    vcs_installation_id: Some(i64::from(body.installation_id)),
                                    visibility: &origin.default_package_visibility,
-                                   auto_build: body.auto_build };
+                                   auto_build: body.auto_build,
+                                   required_worker_labels: &body.required_worker_labels,
+                                   vcs_credential: None,
+                                   webhook_secret: None,
+                                   quiet_period_secs: body.quiet_period_secs as i32 };
 
     match Project::create(&new_project, &*conn).map_err(Error::DieselError) {
         Ok(project) => HttpResponse::Created().json(project),
@@ -272,20 +373,33 @@ fn delete_project(req: HttpRequest,
 #[allow(clippy::needless_pass_by_value)]
 fn update_project(req: HttpRequest,
                   path: Path<(String, String)>,
-                  body: Json<ProjectUpdateReq>,
+                  body: ValidatedJson<ProjectUpdateReq>,
                   state: Data<AppState>)
                   -> HttpResponse {
     let (origin, name) = path.into_inner();
 
-    let account_id = match authorize_session(&req, Some(&origin)) {
-        Ok(session) => session.get_id(),
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
         Err(err) => return err.into(),
     };
+    let account_id = session.get_id();
 
     if body.plan_path.is_empty() {
         return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
     }
 
+    if let Some(ref labels) = body.required_worker_labels {
+        if !valid_worker_labels(labels) {
+            return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+
+    if let Some(quiet_period) = body.quiet_period_secs {
+        if !valid_quiet_period(quiet_period) {
+            return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+
     let conn = match state.db.get_conn().map_err(Error::DbError) {
         Ok(conn_ref) => conn_ref,
         Err(err) => return err.into(),
@@ -304,21 +418,75 @@ fn update_project(req: HttpRequest,
         }
     };
 
+    let worker_labels = body.required_worker_labels
+                            .as_ref()
+                            .unwrap_or(&project.required_worker_labels);
+    let quiet_period = body.quiet_period_secs
+                           .map(|secs| secs as i32)
+                           .unwrap_or(project.quiet_period_secs);
+
+    if body.vcs_type == "generic_git" {
+        let credential = body.vcs_credential.as_ref().map(String::as_str);
+        let encrypted_secret = match encrypt_webhook_secret(&state, body.webhook_secret.as_ref()) {
+            Ok(secret) => secret,
+            Err(err) => return err.into(),
+        };
+        let webhook_secret = encrypted_secret.as_ref().map(String::as_str);
+        if !valid_generic_git(&origin, &body.vcs_data, credential, &*conn) {
+            return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        let update_project = UpdateProject { id: project.id,
+                                             origin: &project.origin,
+                                             owner_id: account_id as i64,
+                                             package_name: &project.package_name,
+                                             plan_path: &body.plan_path,
+                                             vcs_type: "generic_git",
+                                             vcs_data: &body.vcs_data,
+                                             vcs_installation_id: None,
+                                             visibility: &project.visibility,
+                                             auto_build: body.auto_build,
+                                             required_worker_labels: worker_labels,
+                                             vcs_credential: credential,
+                                             webhook_secret,
+                                             quiet_period_secs: quiet_period };
+
+        return match Project::update(&update_project,
+                                     account_id as i64,
+                                     session.get_name(),
+                                     &*conn).map_err(Error::DieselError)
+        {
+            Ok(_) => HttpResponse::NoContent().finish(),
+            Err(err) => {
+                debug!("{}", err);
+                err.into()
+            }
+        };
+    }
+
     // Test hook - bypass the github dance
     if env::var_os("HAB_FUNC_TEST").is_some() {
         let update_project =
-            UpdateProject { id:                  project.id,
-                            origin:              &project.origin,
-                            owner_id:            account_id as i64,
-                            package_name:        "testapp",
-                            plan_path:           &body.plan_path,
-                            vcs_type:            "git",
-                            vcs_data:            "https://github.com/habitat-sh/testapp.git",
-                            vcs_installation_id: Some(i64::from(body.installation_id)),
-                            visibility:          &PackageVisibility::Public,
-                            auto_build:          body.auto_build, };
-
-        match Project::update(&update_project, &*conn).map_err(Error::DieselError) {
+            UpdateProject { id:                     project.id,
+                            origin:                 &project.origin,
+                            owner_id:               account_id as i64,
+                            package_name:           "testapp",
+                            plan_path:              &body.plan_path,
+                            vcs_type:               "git",
+                            vcs_data:               "https://github.com/habitat-sh/testapp.git",
+                            vcs_installation_id:    Some(i64::from(body.installation_id)),
+                            visibility:             &PackageVisibility::Public,
+                            auto_build:             body.auto_build,
+                            required_worker_labels: worker_labels,
+                            vcs_credential:         None,
+                            webhook_secret:         None,
+                            quiet_period_secs:      quiet_period, };
+
+        match Project::update(&update_project,
+                              account_id as i64,
+                              session.get_name(),
+                              &*conn).map_err(Error::DieselError)
+        {
             Ok(_) => return HttpResponse::NoContent().finish(),
             Err(err) => {
                 debug!("{}", err);
@@ -375,18 +543,27 @@ fn update_project(req: HttpRequest,
         }
     };
 
-    let update_project = UpdateProject { id:                  project.id,
-                                         owner_id:            account_id as i64,
-                                         origin:              &project.origin,
-                                         package_name:        &plan.name.trim_matches('"'),
-                                         plan_path:           &body.plan_path,
-                                         vcs_type:            "git",
-                                         vcs_data:            &vcs_data,
-                                         vcs_installation_id: Some(i64::from(body.installation_id)),
-                                         visibility:          &project.visibility,
-                                         auto_build:          body.auto_build, };
-
-    match Project::update(&update_project, &*conn).map_err(Error::DieselError) {
+    let update_project = UpdateProject { id:                     project.id,
+                                         owner_id:               account_id as i64,
+                                         origin:                 &project.origin,
+                                         package_name:           &plan.name.trim_matches('"'),
+                                         plan_path:              &body.plan_path,
+                                         vcs_type:               "git",
+                                         vcs_data:               &vcs_data,
+                                         vcs_installation_id:
+                                             Some(i64::from(body.installation_id)),
+                                         visibility:             &project.visibility,
+                                         auto_build:             body.auto_build,
+                                         required_worker_labels: worker_labels,
+                                         vcs_credential:         None,
+                                         webhook_secret:         None,
+                                         quiet_period_secs:      quiet_period, };
+
+    match Project::update(&update_project,
+                          account_id as i64,
+                          session.get_name(),
+                          &*conn).map_err(Error::DieselError)
+    {
         Ok(_) => HttpResponse::NoContent().finish(),
         Err(err) => {
             debug!("{}", err);
@@ -483,6 +660,145 @@ fn get_jobs(req: HttpRequest,
     }
 }
 
+// Per target disk usage of the project's recent builds
+#[allow(clippy::needless_pass_by_value)]
+fn get_build_stats(req: HttpRequest,
+                   path: Path<(String, String)>,
+                   state: Data<AppState>)
+                   -> HttpResponse {
+    let (origin, name) = path.into_inner();
+
+    if let Err(err) = authorize_session(&req, Some(&origin)) {
+        return err.into();
+    }
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    match ProjectBuildStats::get(&format!("{}/{}", origin, name), &*conn).map_err(Error::DieselError)
+    {
+        Ok(stats) => {
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                              .json(stats)
+        }
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn get_settings_history(req: HttpRequest,
+                        path: Path<(String, String)>,
+                        pagination: Query<Pagination>,
+                        state: Data<AppState>)
+                        -> HttpResponse {
+    let (origin, name) = path.into_inner();
+
+    if let Err(err) = authorize_session(&req, Some(&origin)) {
+        return err.into();
+    }
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let project = match Project::get(&format!("{}/{}", origin, name), &*conn) {
+        Ok(project) => project,
+        Err(err) => {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    };
+
+    let (page, per_page) = helpers::extract_pagination_in_pages(&pagination);
+    assert!(page >= 1);
+
+    let lpsh = ListProjectSettingsHistory { project_id: project.id,
+                                            page:       page as i64,
+                                            limit:      per_page as i64, };
+
+    match settings_history_with_changes(&project, &lpsh, &*conn) {
+        Ok((entries, total_count)) => {
+            let start = (page - 1) * per_page;
+            let stop = match entries.len() {
+                0 => per_page - 1,
+                len => (start + (len as isize) - 1),
+            };
+
+            let body = helpers::package_results_json(&entries,
+                                                     total_count as isize,
+                                                     start as isize,
+                                                     stop as isize);
+
+            let mut response = if total_count as isize > (stop + 1) {
+                HttpResponse::PartialContent()
+            } else {
+                HttpResponse::Ok()
+            };
+
+            response.header(http::header::CONTENT_TYPE, headers::APPLICATION_JSON)
+                    .header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                    .body(body)
+        }
+        Err(err) => {
+            debug!("{}", err);
+            Error::DieselError(err).into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn rollback_settings(req: HttpRequest,
+                     path: Path<(String, String, String)>,
+                     state: Data<AppState>)
+                     -> HttpResponse {
+    let (origin, name, version) = path.into_inner();
+
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
+        Err(err) => return err.into(),
+    };
+
+    let version = match version.parse::<i32>() {
+        Ok(version) => version,
+        Err(err) => {
+            debug!("{:?}", err);
+            return HttpResponse::new(StatusCode::BAD_REQUEST);
+        }
+    };
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let project = match Project::get(&format!("{}/{}", origin, name), &*conn) {
+        Ok(project) => project,
+        Err(err) => {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    };
+
+    match Project::rollback(project.id,
+                            version,
+                            session.get_id() as i64,
+                            session.get_name(),
+                            &*conn)
+    {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(err) => {
+            debug!("{}", err);
+            Error::DieselError(err).into()
+        }
+    }
+}
+
 #[allow(clippy::needless_pass_by_value)]
 fn create_integration(req: HttpRequest,
                       path: Path<(String, String, String)>,
@@ -597,9 +913,10 @@ fn toggle_privacy(req: HttpRequest,
                   -> HttpResponse {
     let (origin, name, visibility) = path.into_inner();
 
-    if let Err(err) = authorize_session(&req, Some(&origin)) {
-        return err.into();
-    }
+    let session = match authorize_session(&req, Some(&origin)) {
+        Ok(session) => session,
+        Err(err) => return err.into(),
+    };
 
     // users aren't allowed to set projects to hidden manually
     if visibility.to_lowercase() == "hidden" {
@@ -630,18 +947,26 @@ fn toggle_privacy(req: HttpRequest,
 
     let package_name = project.package_name.clone();
 
-    let update_project = UpdateProject { id:                  project.id,
-                                         owner_id:            project.owner_id,
-                                         origin:              &project.origin,
-                                         package_name:        &package_name,
-                                         plan_path:           &project.plan_path,
-                                         vcs_type:            &project.vcs_type,
-                                         vcs_data:            &project.vcs_data,
-                                         vcs_installation_id: project.vcs_installation_id,
-                                         visibility:          &pv,
-                                         auto_build:          project.auto_build, };
-
-    if let Err(err) = Project::update(&update_project, &*conn).map_err(Error::DieselError) {
+    let update_project = UpdateProject { id:                     project.id,
+                                         owner_id:               project.owner_id,
+                                         origin:                 &project.origin,
+                                         package_name:           &package_name,
+                                         plan_path:              &project.plan_path,
+                                         vcs_type:               &project.vcs_type,
+                                         vcs_data:               &project.vcs_data,
+                                         vcs_installation_id:    project.vcs_installation_id,
+                                         visibility:             &pv,
+                                         auto_build:             project.auto_build,
+                                         required_worker_labels: &project.required_worker_labels,
+                                         vcs_credential:         None,
+                                         webhook_secret:         None,
+                                         quiet_period_secs:      project.quiet_period_secs, };
+
+    if let Err(err) = Project::update(&update_project,
+                                      session.get_id() as i64,
+                                      session.get_name(),
+                                      &*conn).map_err(Error::DieselError)
+    {
         debug!("{}", err);
         return err.into();
     }
@@ -682,3 +1007,85 @@ fn toggle_privacy(req: HttpRequest,
 
     HttpResponse::NoContent().finish()
 }
+
+// Labels are matched verbatim against the labels workers advertise, so a blank label could
+// never be satisfied
+fn valid_worker_labels(labels: &[String]) -> bool { labels.iter().all(|l| !l.trim().is_empty()) }
+
+fn valid_quiet_period(secs: u32) -> bool { secs <= MAX_QUIET_PERIOD_SECS }
+
+fn valid_package_name(name: &str) -> bool {
+    !name.is_empty()
+    && name.chars()
+           .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// Webhook secrets are kept encrypted with the builder key, as origin integrations are, and are
+// only decrypted to check a signature
+fn encrypt_webhook_secret(state: &AppState, secret: Option<&String>) -> Result<Option<String>> {
+    match secret {
+        Some(secret) => {
+            bldr_core::integrations::encrypt(&state.config.api.key_path, secret.as_bytes())
+                .map(Some)
+                .map_err(Error::BuilderCore)
+        }
+        None => Ok(None),
+    }
+}
+
+// A generic git repository is cloned over HTTPS, with an optional token, or over SSH with a
+// deploy key. The credential is named rather than given, and must be one of the origin's secrets.
+fn valid_generic_git(origin: &str,
+                     clone_url: &str,
+                     credential: Option<&str>,
+                     conn: &PgConnection)
+                     -> bool {
+    let ssh = is_ssh_url(clone_url);
+    let https = Url::parse(clone_url).map(|url| url.scheme() == "https")
+                                     .unwrap_or(false);
+    if !ssh && !https {
+        return false;
+    }
+
+    match credential {
+        Some(name) => OriginSecret::get(origin, name, conn).is_ok(),
+        None => !ssh,
+    }
+}
+
+#[derive(Serialize)]
+struct SettingsHistoryEntry {
+    #[serde(flatten)]
+    entry:   ProjectSettingsHistory,
+    changes: Vec<SettingChange>,
+}
+
+// Each history entry holds the settings as they were before an update, so the changes made by
+// that update are the diff against the next newer entry, or against the current settings for
+// the newest one.
+fn settings_history_with_changes(project: &Project,
+                                 lpsh: &ListProjectSettingsHistory,
+                                 conn: &PgConnection)
+                                 -> QueryResult<(Vec<SettingsHistoryEntry>, i64)> {
+    let (entries, total_count) = ProjectSettingsHistory::list(lpsh, conn)?;
+
+    let mut after = match entries.first() {
+        Some(newest) => {
+            match ProjectSettingsHistory::get(project.id, newest.version + 1, conn) {
+                Ok(next) => next.settings,
+                Err(NotFound) => serde_json::to_value(ProjectSettings::from(project)).unwrap(),
+                Err(err) => return Err(err),
+            }
+        }
+        None => return Ok((Vec::new(), total_count)),
+    };
+
+    let mut history = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let changes = ProjectSettingsHistory::diff(&entry.settings, &after);
+        after = entry.settings.clone();
+        history.push(SettingsHistoryEntry { entry, changes });
+    }
+
+    Ok((history, total_count))
+}
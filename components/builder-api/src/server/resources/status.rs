@@ -0,0 +1,165 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use actix_web::{http::{self,
+                       StatusCode},
+                web::{self,
+                      Data,
+                      Json,
+                      ServiceConfig},
+                HttpRequest,
+                HttpResponse};
+use chrono::Utc;
+
+use crate::{bldr_core::{build_info::BuildInfo,
+                        timestamp},
+            db::models::status::{NewStatusBanner,
+                                 StatusBanner,
+                                 StatusBannerSeverity}};
+
+use crate::server::{authorize::authorize_admin,
+                    error::Error,
+                    feat,
+                    services::build_info,
+                    AppState};
+
+const MAX_BANNER_LEN: usize = 500;
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct StatusBannerReq {
+    pub message:    String,
+    pub severity:   StatusBannerSeverity,
+    pub expires_at: String,
+}
+
+// The versions of the deployment's services, the jobsrv fields are absent when it is not
+// enabled or can't be reached
+#[derive(Clone, Serialize, Debug)]
+pub struct OverviewResp {
+    pub api:               BuildInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jobsrv:            Option<BuildInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jobsrv_compatible: Option<bool>,
+}
+
+pub struct Status;
+
+impl Status {
+    // Route registration
+    //
+    pub fn register(cfg: &mut ServiceConfig) {
+        cfg.route("/status/summary", web::get().to(get_summary))
+           .route("/status/summary", web::head().to(get_summary))
+           .route("/admin/overview", web::get().to(get_overview))
+           .route("/admin/status/banner", web::put().to(set_banner))
+           .route("/admin/status/banner", web::delete().to(clear_banner));
+    }
+}
+
+// Served from the summary cached by the status service, never computed here
+#[allow(clippy::needless_pass_by_value)]
+fn get_summary(state: Data<AppState>) -> HttpResponse {
+    let summary = match state.status.read() {
+        Ok(summary) => summary.clone(),
+        Err(err) => {
+            warn!("Unable to read status summary, err={}", err);
+            return HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    HttpResponse::Ok().header(http::header::CACHE_CONTROL,
+                              format!("public, max-age={}",
+                                      state.config.api.status_check_interval_sec))
+                      .json(summary)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn get_overview(req: HttpRequest, state: Data<AppState>) -> HttpResponse {
+    if let Err(err) = authorize_admin(&req) {
+        return err.into();
+    }
+
+    let jobsrv = if feat::is_enabled(feat::Jobsrv) {
+        build_info::jobsrv(&state.build_info, &state.jobsrv)
+    } else {
+        None
+    };
+    let (jobsrv, jobsrv_compatible) = match jobsrv {
+        Some((info, compatible)) => (Some(info), Some(compatible)),
+        None => (None, None),
+    };
+
+    HttpResponse::Ok().json(&OverviewResp { api: state.build_info.clone(),
+                                            jobsrv,
+                                            jobsrv_compatible })
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn set_banner(req: HttpRequest,
+              body: Json<StatusBannerReq>,
+              state: Data<AppState>)
+              -> HttpResponse {
+    let account_id = match authorize_admin(&req) {
+        Ok(session) => session.get_id(),
+        Err(err) => return err.into(),
+    };
+
+    let message = body.message.trim();
+    if message.is_empty() || message.len() > MAX_BANNER_LEN {
+        return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let expires_at = match timestamp::parse(&body.expires_at) {
+        Some(expires_at) if expires_at > Utc::now() => expires_at.naive_utc(),
+        _ => return HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY),
+    };
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let new_banner = NewStatusBanner { message,
+                                       severity: body.severity,
+                                       owner_id: account_id as i64,
+                                       expires_at };
+
+    match StatusBanner::create(&new_banner, &*conn).map_err(Error::DieselError) {
+        Ok(banner) => HttpResponse::Ok().json(&banner),
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn clear_banner(req: HttpRequest, state: Data<AppState>) -> HttpResponse {
+    if let Err(err) = authorize_admin(&req) {
+        return err.into();
+    }
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    match StatusBanner::clear(&*conn).map_err(Error::DieselError) {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
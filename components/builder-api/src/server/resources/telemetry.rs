@@ -0,0 +1,112 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use actix_web::{error::PayloadError,
+                http::{header,
+                       StatusCode},
+                web::{self,
+                      Data,
+                      ServiceConfig},
+                HttpRequest,
+                HttpResponse};
+use bytes::BytesMut;
+use chrono::Utc;
+use futures::{future::ok as fut_ok,
+              Future,
+              Stream};
+use serde_json;
+
+use crate::server::{error::Error,
+                    services::{install_stats::InstallReport,
+                               security_events},
+                    AppState};
+
+pub struct Telemetry;
+
+impl Telemetry {
+    // Route registration
+    //
+    pub fn register(cfg: &mut ServiceConfig) {
+        cfg.route("/telemetry/install", web::post().to(report_install));
+    }
+}
+
+// Install reports are queued and answered at once, the client does not wait for them to be
+// written and is not told whether the package is known. Bodies are read only up to the size
+// cap, and only once the caller is within its rate limit.
+#[allow(clippy::needless_pass_by_value)]
+fn report_install(req: HttpRequest,
+                  stream: web::Payload,
+                  state: Data<AppState>)
+                  -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    if !state.config.api.telemetry_enabled {
+        return Box::new(fut_ok(HttpResponse::new(StatusCode::NOT_FOUND)));
+    }
+
+    let max_bytes = state.config.api.telemetry_max_bytes;
+    if content_length(&req).map_or(false, |len| len > max_bytes) {
+        return Box::new(fut_ok(HttpResponse::new(StatusCode::PAYLOAD_TOO_LARGE)));
+    }
+
+    let caller = security_events::client_ip(req.head(), &state.config.api.trusted_proxies)
+        .unwrap_or_default();
+    if !state.install_stats.allow(&caller) {
+        debug!("Install report from {} rate limited", caller);
+        return Box::new(fut_ok(HttpResponse::new(StatusCode::TOO_MANY_REQUESTS)));
+    }
+
+    Box::new(stream.from_err()
+                   .fold(BytesMut::new(), move |mut body, chunk| {
+                       if body.len() + chunk.len() > max_bytes {
+                           return Err(Error::PayloadError(PayloadError::Overflow));
+                       }
+                       body.extend_from_slice(&chunk);
+                       Ok(body)
+                   })
+                   .then(move |body| {
+                       match body {
+                           Ok(body) => Ok(queue_report(&body, &state)),
+                           Err(Error::PayloadError(PayloadError::Overflow)) => {
+                               Ok(HttpResponse::new(StatusCode::PAYLOAD_TOO_LARGE))
+                           }
+                           Err(err) => Err(err),
+                       }
+                   }))
+}
+
+fn content_length(req: &HttpRequest) -> Option<usize> {
+    req.headers()
+       .get(header::CONTENT_LENGTH)
+       .and_then(|len| len.to_str().ok())
+       .and_then(|len| len.parse().ok())
+}
+
+fn queue_report(body: &[u8], state: &AppState) -> HttpResponse {
+    let report: InstallReport = match serde_json::from_slice(body) {
+        Ok(report) => report,
+        Err(err) => return HttpResponse::BadRequest().body(format!("{}", err)),
+    };
+    let (ident, target) = match report.validate() {
+        Ok(valid) => valid,
+        Err(reason) => return HttpResponse::BadRequest().body(reason),
+    };
+
+    let today = Utc::today().naive_utc();
+    if !state.install_stats
+             .queue(&ident, target, report.success, &report.site_id, today)
+    {
+        debug!("Install report of {} dropped, the queue is full", ident);
+    }
+    HttpResponse::new(StatusCode::ACCEPTED)
+}
@@ -0,0 +1,191 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Token introspection for services that accept Builder personal access tokens, loosely after
+//! RFC 7662. Services authenticate with an operator configured credential over HTTP Basic.
+//! Looking a token up here never touches the session cache, so it neither creates nor extends
+//! a session for the token's owner.
+
+use actix_web::{http::{self,
+                       StatusCode},
+                web::{self,
+                      Data,
+                      Json,
+                      ServiceConfig},
+                HttpRequest,
+                HttpResponse};
+use base64;
+use chrono::{DateTime,
+             Utc};
+
+use crate::{bldr_core::{self,
+                        access_token::BUILDER_ACCOUNT_ID,
+                        privilege::FeatureFlags},
+            config::IntrospectionClientCfg,
+            db::models::account::*};
+
+use crate::server::{error::{Error,
+                            Result},
+                    AppState};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct IntrospectReq {
+    pub token: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct IntrospectResp {
+    pub active:     bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username:   Option<String>,
+    /// Space separated, as in RFC 7662
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope:      Option<String>,
+    /// Seconds since the epoch, absent for tokens that do not expire
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp:        Option<i64>,
+}
+
+pub struct Token;
+
+impl Token {
+    // Route registration
+    //
+    pub fn register(cfg: &mut ServiceConfig) {
+        cfg.route("/token/introspect", web::post().to(introspect));
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn introspect(req: HttpRequest, body: Json<IntrospectReq>, state: Data<AppState>) -> HttpResponse {
+    let client = match authenticate_client(&req, &state.config.api.introspection_clients) {
+        Some(client) => client,
+        None => {
+            return HttpResponse::Unauthorized().header(http::header::WWW_AUTHENTICATE,
+                                                       "Basic realm=\"token-introspection\"")
+                                               .finish();
+        }
+    };
+
+    if !state.introspection_limiter.check(&client) {
+        warn!("Token introspection by {} rate limited", client);
+        return HttpResponse::new(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    match do_introspect(&body.token, &state) {
+        Ok(resp) => {
+            info!("Token introspection by {}, account_id={}, active={}",
+                  client,
+                  resp.account_id.as_ref().map_or("-", String::as_str),
+                  resp.active);
+            HttpResponse::Ok().header(http::header::CACHE_CONTROL, "no-store")
+                              .json(resp)
+        }
+        Err(err) => {
+            warn!("Token introspection by {} failed, err={}", client, err);
+            err.into()
+        }
+    }
+}
+
+// Returns the name of the service whose credential is presented in the Basic authorization
+fn authenticate_client(req: &HttpRequest, clients: &[IntrospectionClientCfg]) -> Option<String> {
+    let hdr = req.headers()
+                 .get(http::header::AUTHORIZATION)?
+                 .to_str()
+                 .ok()?;
+    let mut parts = hdr.split_whitespace();
+    if parts.next() != Some("Basic") {
+        return None;
+    }
+    let decoded = base64::decode(parts.next()?).ok()?;
+    let credentials = String::from_utf8(decoded).ok()?;
+    let mut credentials = credentials.splitn(2, ':');
+    let (name, secret) = (credentials.next()?, credentials.next()?);
+
+    clients.iter()
+           .find(|c| c.name == name && constant_time_eq(c.secret.as_bytes(), secret.as_bytes()))
+           .map(|c| c.name.clone())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Mirrors the checks of the authentication middleware, but revoked, replaced, expired and
+// undecipherable tokens are reported inactive rather than as errors
+fn do_introspect(token: &str, state: &AppState) -> Result<IntrospectResp> {
+    let inactive = IntrospectResp::default();
+    if !bldr_core::access_token::is_access_token(token) {
+        return Ok(inactive);
+    }
+
+    let session =
+        match bldr_core::access_token::validate_access_token(&state.config.api.key_path, token) {
+            Ok(session) => session,
+            Err(_) => return Ok(inactive),
+        };
+    // Builder's own service tokens are not personal access tokens
+    if session.get_id() == BUILDER_ACCOUNT_ID {
+        return Ok(inactive);
+    }
+
+    let conn = state.db.get_conn().map_err(Error::DbError)?;
+
+    let access_tokens = AccountToken::list(session.get_id(), &*conn).map_err(Error::DieselError)?;
+    let access_token = match access_tokens.first() {
+        Some(access_token)
+            if token.trim_end_matches('=') == access_token.token.trim_end_matches('=') =>
+        {
+            access_token
+        }
+        _ => return Ok(inactive),
+    };
+
+    if let Some(expires_at) = access_token.expires_at {
+        let expires = DateTime::<Utc>::from_utc(expires_at, Utc);
+        if bldr_core::access_token::is_expired(expires, Utc::now()) {
+            return Ok(inactive);
+        }
+    }
+
+    let account = match Account::get_by_id(session.get_id() as i64, &*conn) {
+        Ok(account) => account,
+        Err(diesel::result::Error::NotFound) => return Ok(inactive),
+        Err(err) => return Err(Error::DieselError(err)),
+    };
+
+    Ok(IntrospectResp { active:     true,
+                        account_id: Some(account.id.to_string()),
+                        username:   Some(account.name),
+                        scope:      Some(scope(session.get_flags())),
+                        exp:        access_token.expires_at.map(|e| e.timestamp()), })
+}
+
+fn scope(flags: u32) -> String {
+    let flags = FeatureFlags::from_bits_truncate(flags);
+    let mut scopes = vec!["user"];
+    if flags.contains(FeatureFlags::ADMIN) {
+        scopes.push("admin");
+    }
+    if flags.contains(FeatureFlags::EARLY_ACCESS) {
+        scopes.push("early_access");
+    }
+    if flags.contains(FeatureFlags::BUILD_WORKER) {
+        scopes.push("build_worker");
+    }
+    scopes.join(" ")
+}
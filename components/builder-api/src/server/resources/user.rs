@@ -12,17 +12,33 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use actix_web::{web::{self,
+use std::time::Duration;
+
+use actix_web::{http::{self,
+                       StatusCode},
+                web::{self,
                       Data,
+                      Path,
+                      Query,
                       ServiceConfig},
                 HttpRequest,
                 HttpResponse};
 
-use crate::db::models::{invitations::OriginInvitation,
-                        origin::Origin};
+use crate::{bldr_core::invitation_token,
+            db::models::{account::{AccountExport,
+                                   AccountExportState,
+                                   AccountSecurityEvent,
+                                   ListAccountSecurityEvents},
+                         invitations::OriginInvitation,
+                         notifications::Notification,
+                         origin::Origin}};
 
 use crate::server::{authorize::authorize_session,
                     error::Error,
+                    framework::headers,
+                    helpers::{self,
+                              Pagination},
+                    services::account_export,
                     AppState};
 
 pub struct User {}
@@ -31,13 +47,84 @@ impl User {
     // Route registration
     //
     pub fn register(cfg: &mut ServiceConfig) {
-        cfg.route("/user/invitations", web::get().to(get_invitations))
-           .route("/user/origins", web::get().to(get_origins));
+        cfg.route("/user/export", web::post().to(create_export))
+           .route("/user/export/{id}", web::get().to(get_export))
+           .route("/user/invitations", web::get().to(get_invitations))
+           .route("/user/invitations/claim/{token}",
+                  web::put().to(claim_invitation))
+           .route("/user/notifications", web::get().to(get_notifications))
+           .route("/user/origins", web::get().to(get_origins))
+           .route("/user/security_events", web::get().to(get_security_events));
     }
 }
 
+// An export with the link to download its archive once it is ready
+#[derive(Serialize)]
+struct ExportResponse {
+    #[serde(flatten)]
+    export:       AccountExport,
+    download_url: Option<String>,
+}
+
 // Route handlers - these functions can return any Responder trait
 //
+
+// Exports are assembled in the background, the response is the pending export to poll
+#[allow(clippy::needless_pass_by_value)]
+fn create_export(req: HttpRequest, state: Data<AppState>) -> HttpResponse {
+    let account_id = match authorize_session(&req, None) {
+        Ok(session) => session.get_id(),
+        Err(err) => return err.into(),
+    };
+
+    match account_export::request(account_id, &state.db) {
+        Ok(export) => HttpResponse::Accepted().json(export),
+        Err(err) => {
+            debug!("{}", err);
+            err.into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn get_export(req: HttpRequest, path: Path<String>, state: Data<AppState>) -> HttpResponse {
+    let account_id = match authorize_session(&req, None) {
+        Ok(session) => session.get_id() as i64,
+        Err(err) => return err.into(),
+    };
+
+    let export_id = match path.into_inner().parse::<i64>() {
+        Ok(id) => id,
+        Err(_) => return HttpResponse::new(StatusCode::BAD_REQUEST),
+    };
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let export = match AccountExport::get(export_id, account_id, &*conn) {
+        Ok(export) => export,
+        Err(err) => {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    };
+
+    // Links are minted on each request so that each is short lived
+    let download_url = match (export.state, &export.object_key) {
+        (AccountExportState::Ready, Some(key)) => {
+            let expires_in = Duration::from_secs(state.config.api.export_link_secs);
+            Some(state.packages.presigned_url(key, expires_in))
+        }
+        _ => None,
+    };
+
+    HttpResponse::Ok().header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                      .json(ExportResponse { export,
+                                             download_url })
+}
+
 #[allow(clippy::needless_pass_by_value)]
 fn get_invitations(req: HttpRequest, state: Data<AppState>) -> HttpResponse {
     let account_id = match authorize_session(&req, None) {
@@ -59,6 +146,76 @@ fn get_invitations(req: HttpRequest, state: Data<AppState>) -> HttpResponse {
     }
 }
 
+// Turns an invitation sent by email into one for the signed in account, which can then be
+// accepted or ignored like any other. Holding the emailed token is what proves the address.
+#[allow(clippy::needless_pass_by_value)]
+fn claim_invitation(req: HttpRequest, path: Path<String>, state: Data<AppState>) -> HttpResponse {
+    let token = path.into_inner();
+
+    let (account_id, account_name) = match authorize_session(&req, None) {
+        Ok(session) => (session.get_id() as i64, session.get_name().to_string()),
+        Err(err) => return err.into(),
+    };
+
+    let (invitation_id, email) =
+        match invitation_token::validate_invitation_token(&state.config.api.key_path, &token) {
+            Ok(claims) => claims,
+            Err(err) => {
+                debug!("Invalid invitation token, err={}", err);
+                return HttpResponse::new(StatusCode::NOT_FOUND);
+            }
+        };
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    match OriginInvitation::get(invitation_id, &*conn) {
+        Ok(ref invitation) if invitation.email.as_ref() != Some(&email) => {
+            return HttpResponse::new(StatusCode::NOT_FOUND);
+        }
+        // Following the link again after claiming it is harmless
+        Ok(ref invitation) if invitation.account_id == Some(account_id) => {
+            return HttpResponse::Ok().json(invitation);
+        }
+        Ok(_) => (),
+        Err(err) => {
+            debug!("{}", err);
+            return Error::DieselError(err).into();
+        }
+    }
+
+    match OriginInvitation::claim(invitation_id, account_id, &account_name, &*conn) {
+        Ok(invitation) => HttpResponse::Ok().json(invitation),
+        Err(err) => {
+            debug!("{}", err);
+            Error::DieselError(err).into()
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn get_notifications(req: HttpRequest, state: Data<AppState>) -> HttpResponse {
+    let account_id = match authorize_session(&req, None) {
+        Ok(session) => session.get_id(),
+        Err(err) => return err.into(),
+    };
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    match Notification::list(account_id, &*conn) {
+        Ok(response) => HttpResponse::Ok().json(response),
+        Err(err) => {
+            debug!("{}", err);
+            Error::DieselError(err).into()
+        }
+    }
+}
+
 #[allow(clippy::needless_pass_by_value)]
 fn get_origins(req: HttpRequest, state: Data<AppState>) -> HttpResponse {
     let account_id = match authorize_session(&req, None) {
@@ -79,3 +236,53 @@ fn get_origins(req: HttpRequest, state: Data<AppState>) -> HttpResponse {
         }
     }
 }
+
+#[allow(clippy::needless_pass_by_value)]
+fn get_security_events(req: HttpRequest,
+                       pagination: Query<Pagination>,
+                       state: Data<AppState>)
+                       -> HttpResponse {
+    let account_id = match authorize_session(&req, None) {
+        Ok(session) => session.get_id() as i64,
+        Err(err) => return err.into(),
+    };
+
+    let conn = match state.db.get_conn().map_err(Error::DbError) {
+        Ok(conn_ref) => conn_ref,
+        Err(err) => return err.into(),
+    };
+
+    let (page, per_page) = helpers::extract_pagination_in_pages(&pagination);
+    let lse = ListAccountSecurityEvents { account_id,
+                                          page: page as i64,
+                                          limit: per_page as i64 };
+
+    match AccountSecurityEvent::list(lse, &*conn) {
+        Ok((events, total_count)) => {
+            let start = (page - 1) * per_page;
+            let stop = match events.len() {
+                0 => per_page - 1,
+                len => (start + (len as isize) - 1),
+            };
+
+            let body = helpers::package_results_json(&events,
+                                                     total_count as isize,
+                                                     start as isize,
+                                                     stop as isize);
+
+            let mut response = if total_count as isize > (stop + 1) {
+                HttpResponse::PartialContent()
+            } else {
+                HttpResponse::Ok()
+            };
+
+            response.header(http::header::CONTENT_TYPE, headers::APPLICATION_JSON)
+                    .header(http::header::CACHE_CONTROL, headers::NO_CACHE)
+                    .body(body)
+        }
+        Err(err) => {
+            debug!("{}", err);
+            Error::DieselError(err).into()
+        }
+    }
+}
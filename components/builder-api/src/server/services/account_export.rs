@@ -0,0 +1,246 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exports of what Builder stores about an account, for its owner. An export is assembled in
+//! the background into a JSON archive kept in the artifact store, and the owner is notified
+//! when it is ready. Archives are deleted once they expire.
+
+use std::{thread,
+          time::{Duration as StdDuration,
+                 Instant}};
+
+use chrono::{Duration,
+             NaiveDateTime,
+             Utc};
+use diesel::{pg::PgConnection,
+             Connection};
+use serde_json;
+
+use crate::{bldr_core::timestamp::{rfc3339,
+                                   rfc3339_opt},
+            config::Config,
+            db::{models::{account::*,
+                          invitations::OriginInvitation,
+                          origin::AccountMembership},
+                 DbPool}};
+
+use crate::server::{error::{Error,
+                            Result},
+                    services::{notify,
+                               s3::S3Handler}};
+
+/// The version of the archive layout. It changes when a field is removed, renamed or changes
+/// meaning; fields may be added within a version.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+const CONTENT_TYPE: &str = "application/json";
+const ASSEMBLE_INTERVAL_SECS: u64 = 10;
+const PURGE_INTERVAL_SECS: u64 = 60 * 60;
+// Exports pending for longer are not being assembled, by a node that keeps failing on them for
+// instance, and would block new ones
+const STALE_AFTER_MINS: i64 = 60;
+
+/// Everything Builder stores about an account. Token values are never included.
+#[derive(Serialize)]
+pub struct Archive {
+    pub version:                  u32,
+    #[serde(with = "rfc3339")]
+    pub generated_at:             NaiveDateTime,
+    pub account:                  Account,
+    pub memberships:              Vec<AccountMembership>,
+    pub invitations:              Vec<OriginInvitation>,
+    pub tokens:                   Vec<TokenMetadata>,
+    pub security_events:          Vec<AccountSecurityEvent>,
+    pub activity:                 Vec<AccountActivity>,
+    pub notification_preferences: NotificationPreferences,
+}
+
+#[derive(Serialize)]
+pub struct TokenMetadata {
+    pub id:         String,
+    #[serde(with = "rfc3339_opt")]
+    pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+impl<'a> From<&'a AccountToken> for TokenMetadata {
+    fn from(token: &'a AccountToken) -> Self {
+        TokenMetadata { id:         token.id.to_string(),
+                        created_at: token.created_at,
+                        expires_at: token.expires_at, }
+    }
+}
+
+#[derive(Serialize)]
+pub struct NotificationPreferences {
+    pub security_events: bool,
+}
+
+pub fn start(config: Config, db: DbPool) {
+    thread::Builder::new().name("account-exports".to_string())
+                          .spawn(move || {
+                              let mut purged_at: Option<Instant> = None;
+                              loop {
+                                  if let Err(err) = assemble_pending(&config, &db) {
+                                      warn!("Unable to assemble account exports, err={}", err);
+                                  }
+                                  let purge_interval = StdDuration::from_secs(PURGE_INTERVAL_SECS);
+                                  if purged_at.map_or(true, |at| at.elapsed() >= purge_interval) {
+                                      if let Err(err) = purge(&config, &db) {
+                                          warn!("Unable to purge account exports, err={}", err);
+                                      }
+                                      purged_at = Some(Instant::now());
+                                  }
+                                  thread::sleep(StdDuration::from_secs(ASSEMBLE_INTERVAL_SECS));
+                              }
+                          })
+                          .expect("Unable to start account exports thread");
+}
+
+// Every API node runs the purge, so it is done by whichever node takes the purge lock. An
+// archive that cannot be deleted keeps its row, so that it is tried again on the next pass.
+fn purge(config: &Config, db: &DbPool) -> Result<()> {
+    let conn = db.get_conn().map_err(Error::DbError)?;
+    let s3 = S3Handler::new(config.s3.clone());
+    conn.transaction::<_, Error, _>(|| {
+            if !AccountExport::try_lock_purge(&*conn)? {
+                return Ok(());
+            }
+            let now = Utc::now().naive_utc();
+            let stale =
+                AccountExport::fail_stale(now - Duration::minutes(STALE_AFTER_MINS), &*conn)?;
+            if stale > 0 {
+                warn!("Failed {} account exports that never completed", stale);
+            }
+
+            for export in AccountExport::list_expired(now, &*conn)? {
+                if let Some(ref key) = export.object_key {
+                    if let Err(err) = s3.delete_object(key) {
+                        warn!("Unable to delete account export {}, err={}", export.id, err);
+                        continue;
+                    }
+                }
+                AccountExport::delete(export.id, &*conn)?;
+            }
+            Ok(())
+        })
+}
+
+/// Starts an export of the account's data, assembled in the background. An account may only
+/// have one export pending at a time, a second one is a conflict.
+pub fn request(account_id: u64, db: &DbPool) -> Result<AccountExport> {
+    let conn = db.get_conn().map_err(Error::DbError)?;
+    AccountExport::create(account_id as i64, &*conn).map_err(Error::DieselError)
+}
+
+fn assemble_pending(config: &Config, db: &DbPool) -> Result<()> {
+    let conn = db.get_conn().map_err(Error::DbError)?;
+    while assemble_next(config, &*conn)? {}
+    Ok(())
+}
+
+// Assembles the oldest pending export not being assembled by another node, returning whether
+// there was one. The export stays locked until it is ready or failed.
+fn assemble_next(config: &Config, conn: &PgConnection) -> Result<bool> {
+    let assembled = conn.transaction::<_, Error, _>(|| {
+                            let export = match AccountExport::next_pending(conn)? {
+                                Some(export) => export,
+                                None => return Ok(None),
+                            };
+                            // In a savepoint, so that the export can still be marked failed
+                            match conn.transaction(|| assemble(&export, config, conn)) {
+                                Ok(expires_at) => Ok(Some((export.account_id, Some(expires_at)))),
+                                Err(err) => {
+                                    warn!("Account export {} failed, err={}", export.id, err);
+                                    AccountExport::mark_failed(export.id, conn)?;
+                                    Ok(Some((export.account_id, None)))
+                                }
+                            }
+                        })?;
+
+    // The archive is ready whether or not its owner could be told
+    if let Some((account_id, Some(expires_at))) = assembled {
+        let message = format!("Your account data export is ready. It can be downloaded until {} \
+                               UTC.",
+                              expires_at.format("%Y-%m-%d %H:%M:%S"));
+        if let Err(err) = notify::notify_account(account_id, notify::ACCOUNT_EXPORT, &message, conn)
+        {
+            warn!("Unable to notify account {} of its export, err={}",
+                  account_id, err);
+        }
+    }
+    Ok(assembled.is_some())
+}
+
+// Stores the export's archive and marks it ready, returning when it expires
+fn assemble(export: &AccountExport, config: &Config, conn: &PgConnection) -> Result<NaiveDateTime> {
+    let archive = archive(export.account_id, conn)?;
+    let body = serde_json::to_vec_pretty(&archive).map_err(Error::SerdeJson)?;
+
+    let key = object_key(export.account_id as u64, export.id);
+    S3Handler::new(config.s3.clone()).put_object(&key, body, CONTENT_TYPE)?;
+
+    let retention = Duration::hours(i64::from(config.api.export_retention_hours));
+    let expires_at = (Utc::now() + retention).naive_utc();
+    AccountExport::mark_ready(export.id, &key, expires_at, conn).map_err(Error::DieselError)?;
+    Ok(expires_at)
+}
+
+fn archive(account_id: i64, conn: &PgConnection) -> Result<Archive> {
+    let account = Account::get_by_id(account_id, conn).map_err(Error::DieselError)?;
+    let tokens = AccountToken::list(account_id as u64, conn).map_err(Error::DieselError)?;
+    let notification_preferences =
+        NotificationPreferences { security_events: account.security_notifications, };
+
+    Ok(Archive { version: ARCHIVE_VERSION,
+                 generated_at: Utc::now().naive_utc(),
+                 account,
+                 memberships: AccountMembership::list(account_id, conn).map_err(Error::DieselError)?,
+                 invitations: OriginInvitation::list_all_by_account(account_id, conn)
+                     .map_err(Error::DieselError)?,
+                 tokens: tokens.iter().map(TokenMetadata::from).collect(),
+                 security_events: AccountSecurityEvent::list_involving(account_id, conn)
+                     .map_err(Error::DieselError)?,
+                 activity: AccountActivity::list(account_id, conn).map_err(Error::DieselError)?,
+                 notification_preferences })
+}
+
+fn object_key(account_id: u64, id: i64) -> String {
+    format!("account-exports/{}/{}.json", account_id, id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archives_are_stored_per_account() {
+        assert_eq!(object_key(1_123_456_789, 42),
+                   "account-exports/1123456789/42.json");
+    }
+
+    #[test]
+    fn token_values_are_not_exported() {
+        let token = AccountToken { id:                 42,
+                                   account_id:         1,
+                                   token:              "s3cr3t".to_string(),
+                                   created_at:         None,
+                                   expires_at:         None,
+                                   expiry_notified_at: None, };
+        let json = serde_json::to_string(&TokenMetadata::from(&token)).unwrap();
+        assert!(!json.contains("s3cr3t"));
+        assert!(json.contains("\"id\":\"42\""));
+    }
+}
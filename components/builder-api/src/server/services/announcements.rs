@@ -0,0 +1,295 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Announcements operators show in the web UI, such as maintenance windows. Each user is served
+//! the announcements showing now that are meant for them and that they haven't dismissed. The
+//! announcements that ended are pruned on a timer; their audit trail is kept.
+
+use std::{collections::{BTreeSet,
+                        HashSet},
+          thread,
+          time::Duration};
+
+use chrono::{NaiveDateTime,
+             Utc};
+
+use crate::{bldr_core::timestamp::{self,
+                                   rfc3339},
+            db::{models::{announcement::{Announcement,
+                                         AnnouncementAudience,
+                                         NewAnnouncement},
+                          status::StatusBannerSeverity},
+                 DbPool}};
+
+use crate::server::error::{Error,
+                           Result};
+
+const PRUNE_INTERVAL_SECS: u64 = 60 * 60;
+
+pub const MAX_MESSAGE_LEN: usize = 500;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct AnnouncementReq {
+    pub message:   String,
+    pub severity:  StatusBannerSeverity,
+    /// Now when absent
+    #[serde(default)]
+    pub starts_at: Option<String>,
+    pub ends_at:   String,
+    #[serde(default)]
+    pub audience:  AnnouncementAudience,
+    /// The origins whose members are shown the announcement, for the `origins` audience only
+    #[serde(default)]
+    pub origins:   Vec<String>,
+}
+
+/// An announcement as the web UI shows it, without who it is meant for
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Notice {
+    pub id:        String,
+    pub message:   String,
+    pub severity:  StatusBannerSeverity,
+    #[serde(with = "rfc3339")]
+    pub starts_at: NaiveDateTime,
+    #[serde(with = "rfc3339")]
+    pub ends_at:   NaiveDateTime,
+}
+
+impl From<Announcement> for Notice {
+    fn from(announcement: Announcement) -> Notice {
+        Notice { id:        announcement.id.to_string(),
+                 message:   announcement.message,
+                 severity:  announcement.severity,
+                 starts_at: announcement.starts_at,
+                 ends_at:   announcement.ends_at, }
+    }
+}
+
+/// The signed in user asking for announcements
+#[derive(Debug, Default)]
+pub struct Viewer {
+    /// The origins they are a member of
+    pub origins:   HashSet<String>,
+    /// The ids of the announcements they dismissed
+    pub dismissed: HashSet<i64>,
+}
+
+/// The announcement the request describes, owned by `owner_id`, or why it can't be made
+pub fn validate(req: &AnnouncementReq,
+                owner_id: i64,
+                now: NaiveDateTime)
+                -> std::result::Result<NewAnnouncement, &'static str> {
+    let message = req.message.trim();
+    if message.is_empty() || message.chars().count() > MAX_MESSAGE_LEN {
+        return Err("message must be between 1 and 500 characters");
+    }
+
+    let starts_at = match req.starts_at {
+        Some(ref starts_at) => {
+            timestamp::parse(starts_at).ok_or("starts_at is not an RFC 3339 time")?
+                                       .naive_utc()
+        }
+        None => now,
+    };
+    let ends_at = timestamp::parse(&req.ends_at).ok_or("ends_at is not an RFC 3339 time")?
+                                                .naive_utc();
+    if ends_at <= starts_at || ends_at <= now {
+        return Err("ends_at must be after starts_at and in the future");
+    }
+
+    let origins: BTreeSet<String> = req.origins
+                                       .iter()
+                                       .map(|origin| origin.trim().to_string())
+                                       .filter(|origin| !origin.is_empty())
+                                       .collect();
+    match (req.audience, origins.is_empty()) {
+        (AnnouncementAudience::Origins, true) => {
+            return Err("an announcement for origins must name at least one");
+        }
+        (AnnouncementAudience::Everyone, false) | (AnnouncementAudience::Authenticated, false) => {
+            return Err("origins are only named for the origins audience");
+        }
+        _ => (),
+    }
+
+    Ok(NewAnnouncement { message,
+                         severity: req.severity,
+                         audience: req.audience,
+                         origins: origins.into_iter().collect(),
+                         owner_id,
+                         starts_at,
+                         ends_at })
+}
+
+/// The announcements among `active` meant for the viewer, or for anonymous users when there is
+/// none, leaving out those the viewer dismissed
+pub fn visible(active: Vec<Announcement>, viewer: Option<&Viewer>) -> Vec<Notice> {
+    active.into_iter()
+          .filter(|announcement| is_meant_for(announcement, viewer))
+          .map(Notice::from)
+          .collect()
+}
+
+fn is_meant_for(announcement: &Announcement, viewer: Option<&Viewer>) -> bool {
+    let viewer = match viewer {
+        Some(viewer) => viewer,
+        None => return announcement.audience == AnnouncementAudience::Everyone,
+    };
+    if viewer.dismissed.contains(&announcement.id) {
+        return false;
+    }
+
+    match announcement.audience {
+        AnnouncementAudience::Everyone | AnnouncementAudience::Authenticated => true,
+        AnnouncementAudience::Origins => {
+            announcement.origins
+                        .iter()
+                        .any(|origin| viewer.origins.contains(origin))
+        }
+    }
+}
+
+/// Start pruning the announcements that ended
+pub fn start(db: DbPool) {
+    thread::Builder::new().name("announcements".to_string())
+                          .spawn(move || {
+                              loop {
+                                  if let Err(err) = prune(&db) {
+                                      warn!("Unable to prune announcements, err={}", err);
+                                  }
+                                  thread::sleep(Duration::from_secs(PRUNE_INTERVAL_SECS));
+                              }
+                          })
+                          .expect("Unable to start announcement pruning thread");
+}
+
+fn prune(db: &DbPool) -> Result<()> {
+    let conn = db.get_conn().map_err(Error::DbError)?;
+    let pruned = Announcement::prune(Utc::now().naive_utc(), &*conn).map_err(Error::DieselError)?;
+    if pruned > 0 {
+        debug!("Pruned {} announcements that ended", pruned);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime { NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap() }
+
+    fn req(audience: AnnouncementAudience, origins: &[&str]) -> AnnouncementReq {
+        AnnouncementReq { message: " Maintenance tonight ".to_string(),
+                          severity: StatusBannerSeverity::Warning,
+                          starts_at: None,
+                          ends_at: "2019-10-22T06:00:00Z".to_string(),
+                          audience,
+                          origins: origins.iter().map(|o| o.to_string()).collect() }
+    }
+
+    fn announcement(id: i64, audience: AnnouncementAudience, origins: &[&str]) -> Announcement {
+        Announcement { id,
+                       message: "Maintenance tonight".to_string(),
+                       severity: StatusBannerSeverity::Warning,
+                       audience,
+                       origins: origins.iter().map(|o| o.to_string()).collect(),
+                       owner_id: 1,
+                       starts_at: at("2019-10-21 12:00"),
+                       ends_at: at("2019-10-22 06:00"),
+                       created_at: None,
+                       updated_at: None }
+    }
+
+    fn viewer(origins: &[&str], dismissed: &[i64]) -> Viewer {
+        Viewer { origins:   origins.iter().map(|o| o.to_string()).collect(),
+                 dismissed: dismissed.iter().cloned().collect(), }
+    }
+
+    fn ids(notices: &[Notice]) -> Vec<&str> { notices.iter().map(|n| n.id.as_str()).collect() }
+
+    #[test]
+    fn announcements_start_now_unless_scheduled() {
+        let now = at("2019-10-21 12:00");
+        let immediate = req(AnnouncementAudience::Everyone, &[]);
+        let new = validate(&immediate, 7, now).unwrap();
+        assert_eq!(new.message, "Maintenance tonight");
+        assert_eq!(new.starts_at, now);
+        assert_eq!(new.ends_at, at("2019-10-22 06:00"));
+
+        let mut scheduled = req(AnnouncementAudience::Everyone, &[]);
+        scheduled.starts_at = Some("2019-10-21T22:00:00Z".to_string());
+        let new = validate(&scheduled, 7, now).unwrap();
+        assert_eq!(new.starts_at, at("2019-10-21 22:00"));
+    }
+
+    #[test]
+    fn messages_are_limited_in_characters() {
+        let now = at("2019-10-21 12:00");
+        let mut long = req(AnnouncementAudience::Everyone, &[]);
+        long.message = "é".repeat(MAX_MESSAGE_LEN);
+        assert!(validate(&long, 7, now).is_ok());
+
+        long.message.push('é');
+        assert!(validate(&long, 7, now).is_err());
+        long.message = "  ".to_string();
+        assert!(validate(&long, 7, now).is_err());
+    }
+
+    #[test]
+    fn announcements_must_end_in_the_future() {
+        let late = at("2019-10-22 07:00");
+        assert!(validate(&req(AnnouncementAudience::Everyone, &[]), 7, late).is_err());
+
+        let mut backwards = req(AnnouncementAudience::Everyone, &[]);
+        backwards.starts_at = Some("2019-10-23T00:00:00Z".to_string());
+        assert!(validate(&backwards, 7, at("2019-10-21 12:00")).is_err());
+    }
+
+    #[test]
+    fn origins_are_named_for_the_origins_audience_only() {
+        let now = at("2019-10-21 12:00");
+        assert!(validate(&req(AnnouncementAudience::Origins, &[]), 7, now).is_err());
+        assert!(validate(&req(AnnouncementAudience::Authenticated, &["core"]), 7, now).is_err());
+
+        let for_origins = req(AnnouncementAudience::Origins, &["core", " acme", "core"]);
+        let new = validate(&for_origins, 7, now).unwrap();
+        assert_eq!(new.origins, vec!["acme", "core"]);
+    }
+
+    #[test]
+    fn anonymous_users_see_announcements_for_everyone() {
+        let active = vec![announcement(1, AnnouncementAudience::Everyone, &[]),
+                          announcement(2, AnnouncementAudience::Authenticated, &[]),
+                          announcement(3, AnnouncementAudience::Origins, &["core"]),];
+        assert_eq!(ids(&visible(active, None)), vec!["1"]);
+    }
+
+    #[test]
+    fn origin_announcements_are_for_their_members() {
+        let active = vec![announcement(1, AnnouncementAudience::Everyone, &[]),
+                          announcement(2, AnnouncementAudience::Authenticated, &[]),
+                          announcement(3, AnnouncementAudience::Origins, &["core", "acme"]),
+                          announcement(4, AnnouncementAudience::Origins, &["private"]),];
+        let member = viewer(&["acme"], &[]);
+        assert_eq!(ids(&visible(active, Some(&member))), vec!["1", "2", "3"]);
+    }
+
+    #[test]
+    fn dismissed_announcements_are_left_out() {
+        let active = vec![announcement(1, AnnouncementAudience::Everyone, &[]),
+                          announcement(2, AnnouncementAudience::Authenticated, &[]),];
+        let dismissed = viewer(&[], &[1]);
+        assert_eq!(ids(&visible(active, Some(&dismissed))), vec!["2"]);
+    }
+}
@@ -0,0 +1,398 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Encryption at rest of the artifacts of origins that ask for it.
+//!
+//! Each artifact is encrypted with AES-256-GCM under a data key of its own. The data key is
+//! stored wrapped (encrypted) by the origin's key, which never leaves the key provider, so
+//! rotating the origin key re-wraps data keys without touching the stored artifacts.
+//!
+//! An encrypted artifact is stored as the nonce, the ciphertext and the authentication tag.
+
+use std::{cmp,
+          fs::{self,
+               File},
+          io::{BufReader,
+               BufWriter,
+               Read,
+               Write},
+          path::{Path,
+                 PathBuf}};
+
+use openssl::{rand::rand_bytes,
+              symm::{decrypt_aead,
+                     encrypt_aead,
+                     Cipher,
+                     Crypter,
+                     Mode}};
+
+use diesel::pg::PgConnection;
+
+use crate::{config::ApiCfg,
+            db::models::{origin::OriginArtifactEncryption,
+                         package::{BuilderPackageIdent,
+                                   BuilderPackageTarget,
+                                   NewPackageEncryption,
+                                   PackageEncryption}},
+            hab_core::package::{PackageIdent,
+                                PackageTarget},
+            server::error::{Error,
+                            Result}};
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// Holds the origin keys and wraps data keys with them
+pub trait KeyProvider {
+    /// Whether the provider holds a key with this id
+    fn has_key(&self, key_id: &str) -> bool;
+
+    /// Encrypts a data key with the key `key_id`
+    fn wrap(&self, key_id: &str, data_key: &[u8]) -> Result<String>;
+
+    /// Decrypts a data key wrapped by the key `key_id`
+    fn unwrap(&self, key_id: &str, wrapped: &str) -> Result<Vec<u8>>;
+}
+
+pub fn key_provider(config: &ApiCfg) -> Box<dyn KeyProvider> {
+    Box::new(FileKeyProvider::new(config.artifact_key_dir.clone()))
+}
+
+/// Keys kept in a directory as files named `<key_id>.key`, each holding 64 hex digits. Keys are
+/// read when used, so one can be added without a restart.
+pub struct FileKeyProvider {
+    key_dir: PathBuf,
+}
+
+impl FileKeyProvider {
+    pub fn new(key_dir: PathBuf) -> Self { FileKeyProvider { key_dir } }
+
+    fn key(&self, key_id: &str) -> Result<Vec<u8>> {
+        if !valid_key_id(key_id) {
+            return Err(encryption_error(format!("invalid key id {}", key_id)));
+        }
+
+        let path = self.key_dir.join(format!("{}.key", key_id));
+        let hex_key = fs::read_to_string(&path).map_err(Error::IO)?;
+        match hex::decode(hex_key.trim()) {
+            Ok(ref key) if key.len() == KEY_LEN => Ok(key.to_vec()),
+            _ => {
+                let msg = format!("key {} is not {} hex encoded bytes", key_id, KEY_LEN);
+                Err(encryption_error(msg))
+            }
+        }
+    }
+}
+
+impl KeyProvider for FileKeyProvider {
+    fn has_key(&self, key_id: &str) -> bool { self.key(key_id).is_ok() }
+
+    fn wrap(&self, key_id: &str, data_key: &[u8]) -> Result<String> {
+        Ok(base64::encode(&seal(&self.key(key_id)?, data_key)?))
+    }
+
+    fn unwrap(&self, key_id: &str, wrapped: &str) -> Result<Vec<u8>> {
+        let sealed = base64::decode(wrapped).map_err(|e| encryption_error(e.to_string()))?;
+        open(&self.key(key_id)?, &sealed)
+    }
+}
+
+/// Key ids name files, so they are limited to letters, digits, `-` and `_`
+pub fn valid_key_id(key_id: &str) -> bool {
+    !key_id.is_empty()
+    && key_id.len() <= 64
+    && key_id.chars()
+             .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Encrypts the artifact at `src` to `dst` with a new data key, returning the data key wrapped by
+/// the key `key_id`
+pub fn encrypt_artifact(keys: &dyn KeyProvider,
+                        key_id: &str,
+                        src: &Path,
+                        dst: &Path)
+                        -> Result<String> {
+    let mut data_key = [0u8; KEY_LEN];
+    rand_bytes(&mut data_key).map_err(|e| encryption_error(e.to_string()))?;
+    let wrapped = keys.wrap(key_id, &data_key)?;
+    encrypt_file(&data_key, src, dst)?;
+    Ok(wrapped)
+}
+
+/// The length of the artifact an encrypted one of `stored_len` bytes decrypts to, its nonce and
+/// tag taken off
+pub fn plaintext_len(stored_len: u64) -> u64 {
+    stored_len.saturating_sub((NONCE_LEN + TAG_LEN) as u64)
+}
+
+/// Decrypts the artifact at `src` to `dst`. Fails, leaving `dst` incomplete, if the artifact was
+/// altered.
+pub fn decrypt_artifact(keys: &dyn KeyProvider,
+                        key_id: &str,
+                        wrapped: &str,
+                        src: &Path,
+                        dst: &Path)
+                        -> Result<()> {
+    let data_key = keys.unwrap(key_id, wrapped)?;
+    decrypt_file(&data_key, src, dst)
+}
+
+/// An archive encrypted for storage and its data key wrapped by the origin's key
+pub struct EncryptedArtifact {
+    pub path:             PathBuf,
+    pub key_id:           String,
+    pub wrapped_data_key: String,
+}
+
+/// Encrypts the archive beside itself when `origin` has artifact encryption set, none otherwise.
+/// Every way an artifact enters the store goes through this, uploads and upstream fetches alike.
+pub fn encrypt_for_origin(keys: &dyn KeyProvider,
+                          origin: &str,
+                          archive_path: &Path,
+                          conn: &PgConnection)
+                          -> Result<Option<EncryptedArtifact>> {
+    let key_id = match OriginArtifactEncryption::get(origin, conn)? {
+        Some(encryption) => encryption.key_id,
+        None => return Ok(None),
+    };
+
+    let path = archive_path.with_extension("enc");
+    let wrapped_data_key = encrypt_artifact(keys, &key_id, archive_path, &path)?;
+    Ok(Some(EncryptedArtifact { path,
+                                key_id,
+                                wrapped_data_key }))
+}
+
+/// Records how the stored artifact is encrypted. An unencrypted artifact replacing an encrypted
+/// one forgets the encryption of the one it replaced.
+pub fn record(ident: &PackageIdent,
+              target: PackageTarget,
+              checksum: &str,
+              encrypted: Option<&EncryptedArtifact>,
+              conn: &PgConnection)
+              -> Result<()> {
+    match encrypted {
+        Some(encrypted) => {
+            let package_ident = BuilderPackageIdent(ident.clone());
+            let record = NewPackageEncryption { ident:              package_ident,
+                                                target:             BuilderPackageTarget(target),
+                                                origin:             &ident.origin,
+                                                key_id:             &encrypted.key_id,
+                                                wrapped_data_key:   &encrypted.wrapped_data_key,
+                                                plaintext_checksum: checksum, };
+            PackageEncryption::upsert(&record, conn)?;
+        }
+        None => {
+            PackageEncryption::delete(&BuilderPackageIdent(ident.clone()),
+                                      BuilderPackageTarget(target),
+                                      conn)?;
+        }
+    }
+    Ok(())
+}
+
+/// Re-wraps a data key wrapped by `from_key` with `to_key`
+pub fn rewrap(keys: &dyn KeyProvider,
+              from_key: &str,
+              to_key: &str,
+              wrapped: &str)
+              -> Result<String> {
+    keys.wrap(to_key, &keys.unwrap(from_key, wrapped)?)
+}
+
+fn seal(key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand_bytes(&mut nonce).map_err(|e| encryption_error(e.to_string()))?;
+    let mut tag = [0u8; TAG_LEN];
+    let cipher = Cipher::aes_256_gcm();
+    let ciphertext = encrypt_aead(cipher, key, Some(&nonce), &[], plaintext, &mut tag)
+                         .map_err(|e| encryption_error(e.to_string()))?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    sealed.extend_from_slice(&tag);
+    Ok(sealed)
+}
+
+fn open(key: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN + TAG_LEN {
+        return Err(encryption_error("wrapped key is truncated".to_string()));
+    }
+    let (nonce, rest) = sealed.split_at(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+    let cipher = Cipher::aes_256_gcm();
+    decrypt_aead(cipher, key, Some(nonce), &[], ciphertext, tag)
+        .map_err(|_| encryption_error("wrapped key failed authentication".to_string()))
+}
+
+fn encrypt_file(data_key: &[u8], src: &Path, dst: &Path) -> Result<()> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand_bytes(&mut nonce).map_err(|e| encryption_error(e.to_string()))?;
+    let cipher = Cipher::aes_256_gcm();
+    let mut crypter = Crypter::new(cipher, Mode::Encrypt, data_key, Some(&nonce))
+        .map_err(|e| encryption_error(e.to_string()))?;
+
+    let mut reader = BufReader::new(File::open(src).map_err(Error::IO)?);
+    let mut writer = BufWriter::new(File::create(dst).map_err(Error::IO)?);
+    writer.write_all(&nonce).map_err(Error::IO)?;
+
+    let mut chunk = vec![0u8; CHUNK_LEN];
+    let mut out = vec![0u8; CHUNK_LEN + cipher.block_size()];
+    loop {
+        let read = reader.read(&mut chunk).map_err(Error::IO)?;
+        if read == 0 {
+            break;
+        }
+        let count = crypter.update(&chunk[..read], &mut out)
+                           .map_err(|e| encryption_error(e.to_string()))?;
+        writer.write_all(&out[..count]).map_err(Error::IO)?;
+    }
+
+    let count = crypter.finalize(&mut out)
+                       .map_err(|e| encryption_error(e.to_string()))?;
+    writer.write_all(&out[..count]).map_err(Error::IO)?;
+    let mut tag = [0u8; TAG_LEN];
+    crypter.get_tag(&mut tag)
+           .map_err(|e| encryption_error(e.to_string()))?;
+    writer.write_all(&tag).map_err(Error::IO)?;
+    writer.flush().map_err(Error::IO)
+}
+
+fn decrypt_file(data_key: &[u8], src: &Path, dst: &Path) -> Result<()> {
+    let file = File::open(src).map_err(Error::IO)?;
+    let len = file.metadata().map_err(Error::IO)?.len();
+    if len < (NONCE_LEN + TAG_LEN) as u64 {
+        return Err(encryption_error("encrypted artifact is truncated".to_string()));
+    }
+
+    let mut reader = BufReader::new(file);
+    let mut nonce = [0u8; NONCE_LEN];
+    reader.read_exact(&mut nonce).map_err(Error::IO)?;
+    let cipher = Cipher::aes_256_gcm();
+    let mut crypter = Crypter::new(cipher, Mode::Decrypt, data_key, Some(&nonce))
+        .map_err(|e| encryption_error(e.to_string()))?;
+
+    let mut writer = BufWriter::new(File::create(dst).map_err(Error::IO)?);
+    let mut chunk = vec![0u8; CHUNK_LEN];
+    let mut out = vec![0u8; CHUNK_LEN + cipher.block_size()];
+    let mut remaining = len - (NONCE_LEN + TAG_LEN) as u64;
+    while remaining > 0 {
+        let want = cmp::min(remaining, CHUNK_LEN as u64) as usize;
+        reader.read_exact(&mut chunk[..want]).map_err(Error::IO)?;
+        let count = crypter.update(&chunk[..want], &mut out)
+                           .map_err(|e| encryption_error(e.to_string()))?;
+        writer.write_all(&out[..count]).map_err(Error::IO)?;
+        remaining -= want as u64;
+    }
+
+    let mut tag = [0u8; TAG_LEN];
+    reader.read_exact(&mut tag).map_err(Error::IO)?;
+    crypter.set_tag(&tag)
+           .map_err(|e| encryption_error(e.to_string()))?;
+    let failed = |_| encryption_error("artifact failed authentication".to_string());
+    let count = crypter.finalize(&mut out).map_err(failed)?;
+    writer.write_all(&out[..count]).map_err(Error::IO)?;
+    writer.flush().map_err(Error::IO)
+}
+
+fn encryption_error(msg: String) -> Error { Error::ArtifactEncryption(msg) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const KEY_A: &str = "8f3b2c1d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f9";
+    const KEY_B: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    fn provider(dir: &Path) -> FileKeyProvider {
+        fs::write(dir.join("acme-2019.key"), KEY_A).unwrap();
+        fs::write(dir.join("acme-2020.key"), format!("{}\n", KEY_B)).unwrap();
+        FileKeyProvider::new(dir.to_path_buf())
+    }
+
+    // Spans several chunks and ends part way through one
+    fn artifact() -> Vec<u8> {
+        (0..(CHUNK_LEN * 2 + 1234)).map(|i| (i % 251) as u8)
+                                   .collect()
+    }
+
+    #[test]
+    fn artifact_round_trips() {
+        let dir = tempdir().unwrap();
+        let keys = provider(dir.path());
+        let (plain, sealed, opened) =
+            (dir.path().join("a.hart"), dir.path().join("a.enc"), dir.path().join("b.hart"));
+        fs::write(&plain, artifact()).unwrap();
+
+        let wrapped = encrypt_artifact(&keys, "acme-2019", &plain, &sealed).unwrap();
+        let stored = fs::read(&sealed).unwrap();
+        assert_eq!(stored.len(), artifact().len() + NONCE_LEN + TAG_LEN);
+        assert_eq!(plaintext_len(stored.len() as u64), artifact().len() as u64);
+        assert_ne!(&stored[NONCE_LEN..NONCE_LEN + 64], &artifact()[..64]);
+
+        decrypt_artifact(&keys, "acme-2019", &wrapped, &sealed, &opened).unwrap();
+        assert_eq!(fs::read(&opened).unwrap(), artifact());
+    }
+
+    #[test]
+    fn altered_artifact_is_refused() {
+        let dir = tempdir().unwrap();
+        let keys = provider(dir.path());
+        let (plain, sealed, opened) =
+            (dir.path().join("a.hart"), dir.path().join("a.enc"), dir.path().join("b.hart"));
+        fs::write(&plain, artifact()).unwrap();
+        let wrapped = encrypt_artifact(&keys, "acme-2019", &plain, &sealed).unwrap();
+
+        let mut stored = fs::read(&sealed).unwrap();
+        stored[NONCE_LEN + 100] ^= 1;
+        fs::write(&sealed, &stored).unwrap();
+        assert!(decrypt_artifact(&keys, "acme-2019", &wrapped, &sealed, &opened).is_err());
+
+        fs::write(&sealed, &stored[..NONCE_LEN]).unwrap();
+        assert!(decrypt_artifact(&keys, "acme-2019", &wrapped, &sealed, &opened).is_err());
+    }
+
+    #[test]
+    fn rewrapped_key_decrypts_the_same_artifact() {
+        let dir = tempdir().unwrap();
+        let keys = provider(dir.path());
+        let (plain, sealed, opened) =
+            (dir.path().join("a.hart"), dir.path().join("a.enc"), dir.path().join("b.hart"));
+        fs::write(&plain, artifact()).unwrap();
+        let wrapped = encrypt_artifact(&keys, "acme-2019", &plain, &sealed).unwrap();
+
+        let rewrapped = rewrap(&keys, "acme-2019", "acme-2020", &wrapped).unwrap();
+        assert!(keys.unwrap("acme-2019", &rewrapped).is_err());
+        decrypt_artifact(&keys, "acme-2020", &rewrapped, &sealed, &opened).unwrap();
+        assert_eq!(fs::read(&opened).unwrap(), artifact());
+    }
+
+    #[test]
+    fn keys_must_exist_and_have_safe_ids() {
+        let dir = tempdir().unwrap();
+        let keys = provider(dir.path());
+        fs::write(dir.path().join("short.key"), "0123abcd").unwrap();
+
+        assert!(keys.has_key("acme-2019"));
+        assert!(!keys.has_key("acme-2021"));
+        assert!(!keys.has_key("short"));
+        assert!(!keys.has_key("../acme-2019"));
+        assert!(valid_key_id("acme_2019-a"));
+        assert!(!valid_key_id(""));
+        assert!(!valid_key_id("acme/2019"));
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! What this api and the jobsrv it talks to were built from, so that a deployment running
+//! services of incompatible protocol versions is noticed when it starts rather than when a
+//! message fails to parse.
+
+use crate::bldr_core::{build_info::BuildInfo,
+                       rpc::RpcClient};
+
+/// The build info of this api, `features` being the flags enabled in its config
+pub fn current(features: Vec<String>) -> BuildInfo {
+    builder_core::build_info!("builder-api", features)
+}
+
+/// The build info of the jobsrv and whether it speaks the api's protocol, none when it can't
+/// be reached
+pub fn jobsrv(api: &BuildInfo, jobsrv: &RpcClient) -> Option<(BuildInfo, bool)> {
+    match jobsrv.version() {
+        Ok(info) => {
+            let compatible = api.is_compatible(&info);
+            Some((info, compatible))
+        }
+        Err(err) => {
+            debug!("Unable to read jobsrv version, err = {}", err);
+            None
+        }
+    }
+}
+
+/// Warns when the jobsrv speaks another protocol version than the api
+pub fn check_jobsrv(api: &BuildInfo, jobsrv_client: &RpcClient) {
+    match jobsrv(api, jobsrv_client) {
+        Some((ref info, false)) => {
+            warn!("jobsrv {} ({}) speaks protocol version {}, builder-api speaks {}. Builds will \
+                   fail until both are upgraded.",
+                  info.version, info.git_sha, info.protocol_version, api.protocol_version)
+        }
+        Some((ref info, true)) => {
+            info!("jobsrv {} ({}) speaks protocol version {}",
+                  info.version, info.git_sha, info.protocol_version)
+        }
+        None => warn!("Unable to check the protocol version of the jobsrv"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    #[test]
+    fn the_build_is_described() {
+        let info = current(vec!["JOBSRV".to_string()]);
+        assert_eq!(info.component, "builder-api");
+        assert!(!info.version.is_empty());
+        assert_eq!(info.version, info.version.trim());
+        // Set by the plan, unknown in a plain cargo build
+        let hex = |sha: &str| sha.len() == 40 && sha.chars().all(|c| c.is_ascii_hexdigit());
+        assert!(info.git_sha == "unknown" || hex(&info.git_sha),
+                "{}",
+                info.git_sha);
+        assert!(DateTime::parse_from_rfc3339(&info.built_at).is_ok(),
+                "{}",
+                info.built_at);
+        assert_eq!(info.features, vec!["JOBSRV".to_string()]);
+    }
+}
@@ -0,0 +1,104 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recently handled webhook deliveries, shared by every worker thread, so a delivery sent again
+//! is acknowledged without scheduling its builds twice.
+
+use std::{collections::HashMap,
+          sync::Mutex,
+          time::{Duration,
+                 Instant}};
+
+pub struct DeliveryLog {
+    ttl:        Duration,
+    deliveries: Mutex<HashMap<String, Instant>>,
+}
+
+impl DeliveryLog {
+    /// Remembers deliveries for `ttl`. A ttl of 0 remembers nothing.
+    pub fn new(ttl: Duration) -> Self {
+        DeliveryLog { ttl,
+                      deliveries: Mutex::new(HashMap::new()) }
+    }
+
+    /// Records the delivery with id `delivery`, returning false when it was already recorded
+    /// within the ttl.
+    pub fn record(&self, delivery: &str) -> bool { self.record_at(delivery, Instant::now()) }
+
+    /// Forgets a delivery that could not be handled, so that sending it again is retried
+    pub fn forget(&self, delivery: &str) { self.lock().remove(delivery); }
+
+    fn record_at(&self, delivery: &str, now: Instant) -> bool {
+        if self.ttl == Duration::from_secs(0) {
+            return true;
+        }
+
+        let ttl = self.ttl;
+        let mut deliveries = self.lock();
+        deliveries.retain(|_, recorded| now.duration_since(*recorded) < ttl);
+        if deliveries.contains_key(delivery) {
+            return false;
+        }
+        deliveries.insert(delivery.to_string(), now);
+        true
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<HashMap<String, Instant>> {
+        match self.deliveries.lock() {
+            Ok(deliveries) => deliveries,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELIVERY: &str = "72d3162e-cc78-11e3-81ab-4c9367dc0958";
+
+    #[test]
+    fn duplicate_delivery_is_refused() {
+        let log = DeliveryLog::new(Duration::from_secs(3600));
+        let now = Instant::now();
+        assert!(log.record_at(DELIVERY, now));
+        assert!(!log.record_at(DELIVERY, now + Duration::from_secs(5)));
+        assert!(log.record_at("a-different-delivery", now + Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn delivery_is_forgotten_after_ttl() {
+        let log = DeliveryLog::new(Duration::from_secs(3600));
+        let now = Instant::now();
+        assert!(log.record_at(DELIVERY, now));
+        assert!(log.record_at(DELIVERY, now + Duration::from_secs(3600)));
+        assert_eq!(log.lock().len(), 1);
+    }
+
+    #[test]
+    fn forgotten_delivery_is_handled_again() {
+        let log = DeliveryLog::new(Duration::from_secs(3600));
+        assert!(log.record(DELIVERY));
+        log.forget(DELIVERY);
+        assert!(log.record(DELIVERY));
+    }
+
+    #[test]
+    fn zero_ttl_records_nothing() {
+        let log = DeliveryLog::new(Duration::from_secs(0));
+        let now = Instant::now();
+        assert!(log.record_at(DELIVERY, now));
+        assert!(log.record_at(DELIVERY, now));
+    }
+}
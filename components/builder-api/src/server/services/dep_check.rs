@@ -0,0 +1,111 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The runtime dependencies of an upload, checked against the packages the depot has. A
+//! dependency is missing when nothing in any channel satisfies it for the upload's target, and
+//! one pinned to a release of a deprecated package is flagged so consumers know to move off it.
+//! The depot has no per-release retraction, deprecation is the closest it comes.
+
+use std::str::FromStr;
+
+use crate::{db::models::package::DependencyStatus,
+            hab_core::package::{Identifiable,
+                                PackageIdent}};
+
+use super::upload_policy::Violation;
+
+/// What the check found, kept with the package. Spooled uploads keep it with the rest of what
+/// finishing them needs.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct DependencyReport {
+    pub missing:    Vec<String>,
+    pub deprecated: Vec<String>,
+}
+
+impl DependencyReport {
+    pub fn new(statuses: Vec<DependencyStatus>) -> Self {
+        let mut report = DependencyReport::default();
+        for status in statuses {
+            if !status.present {
+                report.missing.push(status.ident);
+            } else if status.deprecated && is_pinned(&status.ident) {
+                report.deprecated.push(status.ident);
+            }
+        }
+        report
+    }
+
+    /// The missing dependencies as violations of `strict_dependency_validation`, for an origin
+    /// that rejects them
+    pub fn violations(&self) -> Vec<Violation> {
+        self.missing
+            .iter()
+            .map(|ident| {
+                Violation { policy: "strict_dependency_validation",
+                            detail: format!("{} is not in the depot", ident), }
+            })
+            .collect()
+    }
+}
+
+fn is_pinned(ident: &str) -> bool {
+    PackageIdent::from_str(ident).map(|ident| ident.fully_qualified())
+                                 .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(ident: &str, present: bool, deprecated: bool) -> DependencyStatus {
+        DependencyStatus { ident: ident.to_string(),
+                           present,
+                           deprecated }
+    }
+
+    #[test]
+    fn all_present_is_a_clean_report() {
+        let report =
+            DependencyReport::new(vec![status("core/glibc/2.27/20190115002733", true, false),
+                                       status("core/zlib/1.2.11/20190115003728", true, false)]);
+        assert_eq!(report, DependencyReport::default());
+    }
+
+    #[test]
+    fn missing_dependencies_are_reported() {
+        let report =
+            DependencyReport::new(vec![status("core/glibc/2.27/20190115002733", true, false),
+                                       status("acme/private-lib/1.0.0/20191001000000",
+                                              false,
+                                              false)]);
+        assert_eq!(report.missing,
+                   vec!["acme/private-lib/1.0.0/20191001000000".to_string()]);
+        assert!(report.deprecated.is_empty());
+        assert_eq!(report.violations()[0].to_string(),
+                   "strict_dependency_validation: acme/private-lib/1.0.0/20191001000000 is not \
+                    in the depot");
+    }
+
+    #[test]
+    fn only_pins_to_deprecated_packages_are_flagged() {
+        let report =
+            DependencyReport::new(vec![status("core/openssl/1.0.2t/20191004170122", true, true),
+                                       status("core/openssl", true, true),
+                                       status("core/gone/1.0.0/20190101000000", false, true)]);
+        assert_eq!(report.deprecated,
+                   vec!["core/openssl/1.0.2t/20191004170122".to_string()]);
+        assert_eq!(report.missing,
+                   vec!["core/gone/1.0.0/20190101000000".to_string()]);
+    }
+}
@@ -0,0 +1,312 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Expands a package's dependencies into a nested tree. The root's children are its runtime
+//! and build dependencies; below that only runtime dependencies are followed, since those are
+//! what a dependency brings along. Each package is expanded at most once for each level it turns
+//! up at closer to the root, later occurrences refer back to the expansion with `$ref`, and a
+//! package that turns up among its own ancestors is marked as a cycle instead of being followed.
+
+use std::collections::HashMap;
+
+pub const DEFAULT_DEPTH: usize = 3;
+pub const MAX_DEPTH: usize = 6;
+
+/// The direct dependencies of a package
+pub struct PackageDeps {
+    pub deps:       Vec<String>,
+    pub build_deps: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum DepKind {
+    #[serde(rename = "runtime")]
+    Runtime,
+    #[serde(rename = "build")]
+    Build,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct DepNode {
+    /// Absent for packages the caller may not see
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ident:       Option<String>,
+    /// Absent for the root
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind:        Option<DepKind>,
+    /// The ident of the earlier node holding this package's full expansion
+    #[serde(rename = "$ref", skip_serializing_if = "Option::is_none")]
+    pub reference:   Option<String>,
+    #[serde(skip_serializing_if = "is_false")]
+    pub cycle:       bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub unavailable: bool,
+    /// The package has dependencies below the depth limit
+    #[serde(skip_serializing_if = "is_false")]
+    pub truncated:   bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children:    Vec<DepNode>,
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_false(b: &bool) -> bool { !b }
+
+/// Builds the tree below `root` down to `depth` levels. `lookup` returns the dependencies of a
+/// package, or `None` when it does not exist or the caller may not see it.
+pub fn build<F, E>(root: &str,
+                   root_deps: PackageDeps,
+                   depth: usize,
+                   lookup: F)
+                   -> Result<DepNode, E>
+    where F: FnMut(&str) -> Result<Option<PackageDeps>, E>
+{
+    let mut walker = Walker { lookup,
+                              depth,
+                              known: HashMap::new(),
+                              ancestors: vec![root.to_string()],
+                              expanded: HashMap::new() };
+
+    let mut node = DepNode { ident: Some(root.to_string()),
+                             ..Default::default() };
+    if depth == 0 {
+        node.truncated = !root_deps.deps.is_empty() || !root_deps.build_deps.is_empty();
+        return Ok(node);
+    }
+
+    let (mut children, _) = walker.children(&root_deps.deps, DepKind::Runtime, 1)?;
+    let (build_children, _) = walker.children(&root_deps.build_deps, DepKind::Build, 1)?;
+    children.extend(build_children);
+    node.children = children;
+    Ok(node)
+}
+
+struct Walker<F> {
+    lookup:    F,
+    depth:     usize,
+    // Runtime dependencies of every package looked up so far
+    known:     HashMap<String, Option<Vec<String>>>,
+    ancestors: Vec<String>,
+    // The shallowest level each package was expanded at, 0 for packages expanded in full
+    expanded:  HashMap<String, usize>,
+}
+
+impl<F, E> Walker<F> where F: FnMut(&str) -> Result<Option<PackageDeps>, E>
+{
+    // Returns the nodes and whether every one of them was expanded in full
+    fn children(&mut self,
+                idents: &[String],
+                kind: DepKind,
+                level: usize)
+                -> Result<(Vec<DepNode>, bool), E> {
+        let mut nodes = Vec::with_capacity(idents.len());
+        let mut complete = true;
+        for ident in idents {
+            let (node, node_complete) = self.node(ident, kind, level)?;
+            nodes.push(node);
+            complete &= node_complete;
+        }
+        Ok((nodes, complete))
+    }
+
+    fn node(&mut self, ident: &str, kind: DepKind, level: usize) -> Result<(DepNode, bool), E> {
+        let mut node = DepNode { ident: Some(ident.to_string()),
+                                 kind: Some(kind),
+                                 ..Default::default() };
+
+        if self.ancestors.iter().any(|a| a == ident) {
+            node.cycle = true;
+            return Ok((node, true));
+        }
+        // An earlier expansion at this level or above reaches at least as deep as this one
+        // would, so it stands in for it
+        if let Some(&expanded_at) = self.expanded.get(ident) {
+            if expanded_at <= level {
+                node.reference = Some(ident.to_string());
+                return Ok((node, expanded_at == 0));
+            }
+        }
+
+        let deps = match self.deps_of(ident)? {
+            Some(deps) => deps,
+            None => {
+                return Ok((DepNode { kind: Some(kind),
+                                     unavailable: true,
+                                     ..Default::default() },
+                           true));
+            }
+        };
+        if deps.is_empty() {
+            return Ok((node, true));
+        }
+        if level >= self.depth {
+            node.truncated = true;
+            return Ok((node, false));
+        }
+
+        self.ancestors.push(ident.to_string());
+        let result = self.children(&deps, DepKind::Runtime, level + 1);
+        self.ancestors.pop();
+        let (children, complete) = result?;
+
+        // A full expansion stands in for every later occurrence, a truncated one only for those
+        // as deep or deeper and is expanded again should the package turn up closer to the root
+        self.expanded
+            .insert(ident.to_string(), if complete { 0 } else { level });
+        node.children = children;
+        Ok((node, complete))
+    }
+
+    fn deps_of(&mut self, ident: &str) -> Result<Option<Vec<String>>, E> {
+        if let Some(deps) = self.known.get(ident) {
+            return Ok(deps.clone());
+        }
+        let deps = (self.lookup)(ident)?.map(|pkg| pkg.deps);
+        self.known.insert(ident.to_string(), deps.clone());
+        Ok(deps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(edges: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        edges.iter()
+             .map(|(pkg, deps)| (pkg.to_string(), deps.iter().map(|d| d.to_string()).collect()))
+             .collect()
+    }
+
+    fn tree(graph: &HashMap<String, Vec<String>>,
+            root_deps: &[&str],
+            root_build_deps: &[&str],
+            depth: usize)
+            -> DepNode {
+        let root_deps =
+            PackageDeps { deps:       root_deps.iter().map(|d| d.to_string()).collect(),
+                          build_deps: root_build_deps.iter().map(|d| d.to_string()).collect(), };
+        let result: Result<DepNode, ()> = build("root", root_deps, depth, |ident| {
+            Ok(graph.get(ident).map(|deps| {
+                                   PackageDeps { deps:       deps.clone(),
+                                                 build_deps: vec![], }
+                               }))
+        });
+        result.unwrap()
+    }
+
+    fn idents(nodes: &[DepNode]) -> Vec<&str> {
+        nodes.iter()
+             .map(|n| n.ident.as_ref().map_or("-", String::as_str))
+             .collect()
+    }
+
+    #[test]
+    fn runtime_and_build_children_of_root() {
+        let g = graph(&[("app", &["glibc"]), ("gcc", &["glibc"]), ("glibc", &[])]);
+        let t = tree(&g, &["app"], &["gcc"], 3);
+        assert_eq!(idents(&t.children), vec!["app", "gcc"]);
+        assert_eq!(t.children[0].kind, Some(DepKind::Runtime));
+        assert_eq!(t.children[1].kind, Some(DepKind::Build));
+        assert_eq!(idents(&t.children[0].children), vec!["glibc"]);
+    }
+
+    #[test]
+    fn shared_subtree_is_referenced_after_first_expansion() {
+        let g = graph(&[("a", &["openssl"]),
+                        ("b", &["openssl"]),
+                        ("openssl", &["glibc"]),
+                        ("glibc", &[])]);
+        let t = tree(&g, &["a", "b"], &[], 3);
+        let first = &t.children[0].children[0];
+        let second = &t.children[1].children[0];
+        assert_eq!(idents(&first.children), vec!["glibc"]);
+        assert_eq!(second.reference, Some("openssl".to_string()));
+        assert!(second.children.is_empty());
+    }
+
+    #[test]
+    fn truncated_expansion_is_not_referenced() {
+        let g = graph(&[("a", &["b"]), ("b", &["c"]), ("c", &["d"]), ("d", &[])]);
+        // "c" is first reached at the depth limit, then directly from the root
+        let t = tree(&g, &["a", "c"], &[], 3);
+        let deep = &t.children[0].children[0].children[0];
+        assert!(deep.truncated);
+        let shallow = &t.children[1];
+        assert_eq!(shallow.reference, None);
+        assert_eq!(idents(&shallow.children), vec!["d"]);
+    }
+
+    #[test]
+    fn truncated_expansion_is_referenced_at_its_depth_or_deeper() {
+        // Every package of a layer depends on both of the next, so without memoizing truncated
+        // expansions each layer is expanded twice as often as the one above
+        let g = graph(&[("a1", &["b1", "b2"]),
+                        ("a2", &["b1", "b2"]),
+                        ("b1", &["c1", "c2"]),
+                        ("b2", &["c1", "c2"]),
+                        ("c1", &["d1", "d2"]),
+                        ("c2", &["d1", "d2"]),
+                        ("d1", &["e1"]),
+                        ("d2", &["e1"]),
+                        ("e1", &["f1"]),
+                        ("f1", &[])]);
+        let t = tree(&g, &["a1", "a2"], &[], 3);
+
+        let a2 = &t.children[1];
+        assert_eq!(a2.reference, None);
+        assert_eq!(idents(&a2.children), vec!["b1", "b2"]);
+        assert!(a2.children
+                  .iter()
+                  .all(|b| b.reference.is_some() && b.children.is_empty()));
+
+        let b2 = &t.children[0].children[1];
+        assert_eq!(b2.reference, None);
+        assert!(b2.children.iter().all(|c| c.truncated));
+    }
+
+    #[test]
+    fn cycles_are_marked() {
+        let g = graph(&[("a", &["b"]), ("b", &["a"])]);
+        let t = tree(&g, &["a"], &[], 6);
+        let back = &t.children[0].children[0].children[0];
+        assert_eq!(back.ident, Some("a".to_string()));
+        assert!(back.cycle);
+        assert!(back.children.is_empty());
+    }
+
+    #[test]
+    fn unavailable_packages_are_not_named() {
+        let g = graph(&[("a", &["secret"])]);
+        let t = tree(&g, &["a"], &[], 3);
+        let hidden = &t.children[0].children[0];
+        assert!(hidden.unavailable);
+        assert_eq!(hidden.ident, None);
+    }
+
+    #[test]
+    fn lookups_are_made_once_per_package() {
+        let g = graph(&[("a", &["glibc"]), ("b", &["glibc"]), ("glibc", &[])]);
+        let mut calls = 0;
+        let root_deps = PackageDeps { deps:       vec!["a".to_string(), "b".to_string()],
+                                      build_deps: vec!["glibc".to_string()], };
+        let result: Result<DepNode, ()> = build("root", root_deps, 3, |ident| {
+            calls += 1;
+            Ok(g.get(ident).map(|deps| {
+                               PackageDeps { deps:       deps.clone(),
+                                             build_deps: vec![], }
+                           }))
+        });
+        result.unwrap();
+        assert_eq!(calls, 3);
+    }
+}
@@ -0,0 +1,515 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A read-through disk cache of downloaded artifacts, in front of the artifact store. Artifacts
+//! are kept under their checksum once first fetched, and the least recently downloaded ones are
+//! evicted when the cache outgrows its size. A fetched artifact is checked against its checksum
+//! once, before it is added; those found in the cache directory at startup are checked the first
+//! time they are read, and evicted and fetched again when they no longer match. Concurrent
+//! downloads of an artifact that isn't cached yet fetch it once, the others wait for it.
+
+use std::{collections::{HashMap,
+                        HashSet},
+          fs::{self,
+               File},
+          io,
+          path::{Path,
+                 PathBuf},
+          sync::{Condvar,
+                 Mutex,
+                 MutexGuard}};
+
+use crate::{bldr_core::metrics::{CounterMetric,
+                                 GaugeMetric},
+            config::DownloadCacheCfg,
+            hab_core::package::PackageArchive};
+
+use crate::server::{error::{Error,
+                            Result},
+                    services::metrics::{Counter,
+                                        Gauge}};
+
+// Suffix of an artifact being fetched into the cache
+const PARTIAL_EXT: &str = "part";
+
+struct Entry {
+    size:       u64,
+    last_used:  u64,
+    /// Tells the artifact apart from one fetched again under the same checksum
+    generation: u64,
+    /// Checked against its checksum, as fetched artifacts are before they are added
+    verified:   bool,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries:      HashMap<String, Entry>,
+    /// Checksums being fetched, downloads of them wait for the fetch
+    fetching:     HashSet<String>,
+    bytes:        u64,
+    /// Orders the entries by last use, and numbers their generations
+    clock:        u64,
+    hits:         u64,
+    misses:       u64,
+    bytes_served: u64,
+}
+
+impl CacheState {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+pub struct DownloadCache {
+    cfg:     DownloadCacheCfg,
+    state:   Mutex<CacheState>,
+    fetched: Condvar,
+}
+
+impl DownloadCache {
+    /// Opens the cache directory, keeping the artifacts already in it
+    pub fn open(cfg: &DownloadCacheCfg) -> Result<Self> {
+        let cache = DownloadCache { cfg:     cfg.clone(),
+                                    state:   Mutex::new(CacheState::default()),
+                                    fetched: Condvar::new(), };
+        if !cfg.enabled {
+            return Ok(cache);
+        }
+
+        fs::create_dir_all(&cfg.path)?;
+        let mut found = Vec::new();
+        for file in fs::read_dir(&cfg.path)? {
+            let file = file?;
+            let path = file.path();
+            if path.extension().is_some() {
+                // Left by a fetch the service stopped in the middle of
+                fs::remove_file(&path)?;
+                continue;
+            }
+            let metadata = file.metadata()?;
+            let name = file.file_name().to_string_lossy().into_owned();
+            found.push((metadata.modified()?, name, metadata.len()));
+        }
+
+        // The least recently written are the first evicted
+        found.sort();
+        {
+            let mut state = cache.lock();
+            for (_, checksum, size) in found {
+                let clock = state.tick();
+                state.bytes += size;
+                state.entries.insert(checksum,
+                                     Entry { size,
+                                             last_used: clock,
+                                             generation: clock,
+                                             verified: false });
+            }
+            info!("Download cache opened with {} artifacts, {} bytes",
+                  state.entries.len(),
+                  state.bytes);
+            cache.evict(&mut state, None);
+        }
+        Ok(cache)
+    }
+
+    pub fn enabled(&self) -> bool { self.cfg.enabled }
+
+    /// The cached artifact with `checksum`, counted as a hit, or none, counted as a miss
+    pub fn cached(&self, checksum: &str) -> Option<File> {
+        let file = self.open_entry(checksum);
+        let mut state = self.lock();
+        if file.is_some() {
+            state.hits += 1;
+        } else {
+            state.misses += 1;
+        }
+        self.report(&state);
+        file
+    }
+
+    /// Opens the artifact with `checksum`, calling `fetch` to write it to the path it is given
+    /// when it isn't cached. Downloads already fetching it are waited for instead.
+    pub fn open_artifact<F>(&self, checksum: &str, fetch: F) -> Result<File>
+        where F: FnOnce(&Path) -> Result<()>
+    {
+        loop {
+            let mut state = self.lock();
+            while state.fetching.contains(checksum) {
+                state = self.fetched
+                            .wait(state)
+                            .unwrap_or_else(|poisoned| poisoned.into_inner());
+            }
+            if !state.entries.contains_key(checksum) {
+                state.fetching.insert(checksum.to_string());
+                break;
+            }
+            drop(state);
+            // Fetched while this download waited
+            if let Some(file) = self.open_entry(checksum) {
+                return Ok(file);
+            }
+        }
+
+        let fetched = self.fetch(checksum, fetch);
+        let mut state = self.lock();
+        state.fetching.remove(checksum);
+        self.fetched.notify_all();
+        fetched
+    }
+
+    /// Counts `bytes` of a cached artifact as sent to a client
+    pub fn served(&self, bytes: u64) {
+        let mut state = self.lock();
+        state.bytes_served += bytes;
+        self.report(&state);
+    }
+
+    fn lock(&self) -> MutexGuard<CacheState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn path(&self, checksum: &str) -> PathBuf { self.cfg.path.join(checksum) }
+
+    // Opens the cached artifact, checking it first if it has not been. Concurrent reads are
+    // checked in parallel rather than one after the other.
+    fn open_entry(&self, checksum: &str) -> Option<File> {
+        let (generation, verified) = {
+            let mut state = self.lock();
+            let clock = state.tick();
+            let entry = state.entries.get_mut(checksum)?;
+            entry.last_used = clock;
+            (entry.generation, entry.verified)
+        };
+
+        let path = self.path(checksum);
+        let opened = if verified {
+            File::open(&path).map_err(Error::IO)
+        } else {
+            verified_file(&path, checksum)
+        };
+        match opened {
+            Ok(file) => {
+                if !verified {
+                    let mut state = self.lock();
+                    if let Some(entry) = state.entries.get_mut(checksum) {
+                        if entry.generation == generation {
+                            entry.verified = true;
+                        }
+                    }
+                }
+                Some(file)
+            }
+            Err(err) => {
+                warn!("Evicting cached artifact {}, err={}", checksum, err);
+                self.remove(checksum, generation);
+                None
+            }
+        }
+    }
+
+    // Removes the artifact when it is still the `generation` that was read. Another download
+    // may have removed it and fetched it again since, which is left alone.
+    fn remove(&self, checksum: &str, generation: u64) {
+        let mut state = self.lock();
+        let current = state.entries.get(checksum).map(|entry| entry.generation);
+        if current != Some(generation) {
+            return;
+        }
+        if let Some(entry) = state.entries.remove(checksum) {
+            state.bytes -= entry.size;
+            Counter::DownloadCacheEvictions.increment();
+            let path = self.path(checksum);
+            if let Err(err) = fs::remove_file(&path) {
+                warn!("Unable to remove cached artifact {:?}, err={}", path, err);
+            }
+        }
+        self.report(&state);
+    }
+
+    // Fetches the artifact, checks it and adds it to the cache. An artifact larger than the
+    // whole cache is served without being kept.
+    fn fetch<F>(&self, checksum: &str, fetch: F) -> Result<File>
+        where F: FnOnce(&Path) -> Result<()>
+    {
+        let partial = self.path(checksum).with_extension(PARTIAL_EXT);
+        let fetched = fetch(&partial).and_then(|_| verified_file(&partial, checksum));
+        let file = match fetched {
+            Ok(file) => file,
+            Err(err) => {
+                let _ = fs::remove_file(&partial);
+                return Err(err);
+            }
+        };
+        let size = file.metadata()?.len();
+
+        if size > self.cfg.max_bytes {
+            fs::remove_file(&partial)?;
+            return Ok(file);
+        }
+
+        fs::rename(&partial, self.path(checksum))?;
+        let mut state = self.lock();
+        let clock = state.tick();
+        state.bytes += size;
+        state.entries.insert(checksum.to_string(),
+                             Entry { size,
+                                     last_used: clock,
+                                     generation: clock,
+                                     verified: true });
+        self.evict(&mut state, Some(checksum));
+        self.report(&state);
+        Ok(file)
+    }
+
+    // Evicts the least recently used artifacts until the cache fits its size, never `keep`
+    fn evict(&self, state: &mut CacheState, keep: Option<&str>) {
+        while state.bytes > self.cfg.max_bytes {
+            let oldest = state.entries
+                              .iter()
+                              .filter(|(checksum, _)| Some(checksum.as_str()) != keep)
+                              .min_by_key(|(_, entry)| entry.last_used)
+                              .map(|(checksum, _)| checksum.clone());
+            let checksum = match oldest {
+                Some(checksum) => checksum,
+                None => return,
+            };
+            let entry = state.entries.remove(&checksum).unwrap();
+            state.bytes -= entry.size;
+            Counter::DownloadCacheEvictions.increment();
+            let path = self.path(&checksum);
+            // Downloads still reading the artifact keep it open until they are done
+            if let Err(err) = fs::remove_file(&path) {
+                warn!("Unable to remove cached artifact {:?}, err={}", path, err);
+            }
+        }
+    }
+
+    fn report(&self, state: &CacheState) {
+        let lookups = state.hits + state.misses;
+        if lookups > 0 {
+            Gauge::DownloadCacheHitRatio.set(state.hits as f64 / lookups as f64);
+        }
+        Gauge::DownloadCacheBytesServed.set(state.bytes_served as f64);
+        Gauge::DownloadCacheBytes.set(state.bytes as f64);
+    }
+}
+
+// The artifact at `path`, opened once its checksum is found to be `checksum`
+fn verified_file(path: &Path, checksum: &str) -> Result<File> {
+    let actual = PackageArchive::new(path).checksum()?;
+    if actual != checksum {
+        return Err(Error::IO(io::Error::new(io::ErrorKind::InvalidData,
+                                            format!("checksum is {}, \
+                                                     expected {}",
+                                                    actual, checksum))));
+    }
+    Ok(File::open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Read,
+              sync::{atomic::{AtomicUsize,
+                              Ordering},
+                     Arc},
+              thread,
+              time::Duration};
+    use tempfile::{tempdir,
+                   TempDir};
+
+    fn cfg(dir: &TempDir, max_bytes: u64) -> DownloadCacheCfg {
+        DownloadCacheCfg { enabled: true,
+                           path: dir.path().join("cache"),
+                           max_bytes }
+    }
+
+    // An artifact of `len` bytes of `byte`, and its checksum
+    fn artifact(dir: &TempDir, byte: u8, len: usize) -> (Vec<u8>, String) {
+        let content = vec![byte; len];
+        let path = dir.path().join(format!("artifact-{}", byte));
+        fs::write(&path, &content).unwrap();
+        let checksum = PackageArchive::new(&path).checksum().unwrap();
+        (content, checksum)
+    }
+
+    // Opens the artifact as a download does, fetching `content` when it isn't cached and
+    // counting the fetches
+    fn open(cache: &DownloadCache,
+            checksum: &str,
+            content: &[u8],
+            fetches: &AtomicUsize)
+            -> Result<Vec<u8>> {
+        let mut file = match cache.cached(checksum) {
+            Some(file) => file,
+            None => {
+                cache.open_artifact(checksum, |path| {
+                         fetches.fetch_add(1, Ordering::SeqCst);
+                         fs::write(path, content)?;
+                         Ok(())
+                     })?
+            }
+        };
+        let mut read = Vec::new();
+        file.read_to_end(&mut read).unwrap();
+        Ok(read)
+    }
+
+    #[test]
+    fn artifacts_are_fetched_once() {
+        let dir = tempdir().unwrap();
+        let cache = DownloadCache::open(&cfg(&dir, 1024)).unwrap();
+        let (content, checksum) = artifact(&dir, 1, 100);
+        let fetches = AtomicUsize::new(0);
+
+        assert_eq!(open(&cache, &checksum, &content, &fetches).unwrap(),
+                   content);
+        assert_eq!(open(&cache, &checksum, &content, &fetches).unwrap(),
+                   content);
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        cache.served(150);
+        let state = cache.lock();
+        assert_eq!((state.hits, state.misses, state.bytes_served), (1, 1, 150));
+
+        // Kept over a restart
+        drop(state);
+        let reopened = DownloadCache::open(&cfg(&dir, 1024)).unwrap();
+        assert_eq!(open(&reopened, &checksum, &content, &fetches).unwrap(),
+                   content);
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn the_least_recently_used_are_evicted() {
+        let dir = tempdir().unwrap();
+        let cache = DownloadCache::open(&cfg(&dir, 250)).unwrap();
+        let (first, first_sum) = artifact(&dir, 1, 100);
+        let (second, second_sum) = artifact(&dir, 2, 100);
+        let (third, third_sum) = artifact(&dir, 3, 100);
+        let fetches = AtomicUsize::new(0);
+
+        open(&cache, &first_sum, &first, &fetches).unwrap();
+        open(&cache, &second_sum, &second, &fetches).unwrap();
+        open(&cache, &first_sum, &first, &fetches).unwrap();
+        open(&cache, &third_sum, &third, &fetches).unwrap();
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+        assert!(!cache.path(&second_sum).exists());
+        assert_eq!(cache.lock().bytes, 200);
+
+        open(&cache, &first_sum, &first, &fetches).unwrap();
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn artifacts_larger_than_the_cache_are_not_kept() {
+        let dir = tempdir().unwrap();
+        let cache = DownloadCache::open(&cfg(&dir, 50)).unwrap();
+        let (content, checksum) = artifact(&dir, 1, 100);
+        let fetches = AtomicUsize::new(0);
+
+        assert_eq!(open(&cache, &checksum, &content, &fetches).unwrap(),
+                   content);
+        assert!(!cache.path(&checksum).exists());
+        assert_eq!(cache.lock().bytes, 0);
+    }
+
+    #[test]
+    fn corrupted_artifacts_found_at_startup_are_fetched_again() {
+        let dir = tempdir().unwrap();
+        let cache = DownloadCache::open(&cfg(&dir, 1024)).unwrap();
+        let (content, checksum) = artifact(&dir, 1, 100);
+        let fetches = AtomicUsize::new(0);
+
+        open(&cache, &checksum, &content, &fetches).unwrap();
+        fs::write(cache.path(&checksum), vec![9u8; 100]).unwrap();
+        let reopened = DownloadCache::open(&cfg(&dir, 1024)).unwrap();
+        assert_eq!(open(&reopened, &checksum, &content, &fetches).unwrap(),
+                   content);
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        // Checked once, then read as is
+        assert!(reopened.lock().entries[&checksum].verified);
+        assert_eq!(open(&reopened, &checksum, &content, &fetches).unwrap(),
+                   content);
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn artifacts_fetched_again_are_not_evicted_for_their_old_copy() {
+        let dir = tempdir().unwrap();
+        let cache = DownloadCache::open(&cfg(&dir, 1024)).unwrap();
+        let (content, checksum) = artifact(&dir, 1, 100);
+        let fetches = AtomicUsize::new(0);
+
+        open(&cache, &checksum, &content, &fetches).unwrap();
+        let old = cache.lock().entries[&checksum].generation;
+        cache.remove(&checksum, old);
+        open(&cache, &checksum, &content, &fetches).unwrap();
+
+        // A download that found the old copy corrupted leaves the new one
+        cache.remove(&checksum, old);
+        assert!(cache.lock().entries.contains_key(&checksum));
+        assert!(cache.path(&checksum).exists());
+    }
+
+    #[test]
+    fn fetches_that_do_not_match_the_checksum_fail() {
+        let dir = tempdir().unwrap();
+        let cache = DownloadCache::open(&cfg(&dir, 1024)).unwrap();
+        let (_, checksum) = artifact(&dir, 1, 100);
+        let fetches = AtomicUsize::new(0);
+
+        assert!(open(&cache, &checksum, &[2u8; 100], &fetches).is_err());
+        assert!(cache.lock().entries.is_empty());
+        assert_eq!(fs::read_dir(dir.path().join("cache")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn concurrent_downloads_fetch_once() {
+        let dir = tempdir().unwrap();
+        let cache = Arc::new(DownloadCache::open(&cfg(&dir, 1024)).unwrap());
+        let (content, checksum) = artifact(&dir, 1, 100);
+        let fetches = Arc::new(AtomicUsize::new(0));
+
+        let downloads: Vec<_> = (0..8).map(|_| {
+                                          let cache = cache.clone();
+                                          let content = content.clone();
+                                          let checksum = checksum.clone();
+                                          let fetches = fetches.clone();
+                                          thread::spawn(move || {
+                                              let mut file =
+                                                  cache.open_artifact(&checksum, |path| {
+                                                      fetches.fetch_add(1, Ordering::SeqCst);
+                                                      // Long enough for the others to wait on it
+                                                      thread::sleep(Duration::from_millis(100));
+                                                      fs::write(path, &content)?;
+                                                      Ok(())
+                                                  })
+                                                  .unwrap();
+                                              let mut read = Vec::new();
+                                              file.read_to_end(&mut read).unwrap();
+                                              read
+                                          })
+                                      })
+                                      .collect();
+        for download in downloads {
+            assert_eq!(download.join().unwrap(), content);
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+}
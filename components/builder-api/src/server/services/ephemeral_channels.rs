@@ -0,0 +1,214 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Ephemeral channels, such as one per feature branch, live for a TTL unless they are renewed.
+//! Once they expire their releases are demoted and the channel is deleted on a timer; the
+//! channel's events feed keeps a record of it.
+
+use std::{thread,
+          time::Duration as StdDuration};
+
+use chrono::{Duration,
+             NaiveDateTime,
+             Utc};
+use diesel::{pg::PgConnection,
+             Connection};
+
+use crate::{config::{ChannelsCfg,
+                     Config},
+            db::{models::channel::{Channel,
+                                   ChannelAudit,
+                                   ChannelOperation,
+                                   ChannelPackagePin,
+                                   ChannelRelease,
+                                   PackageChannelOperation,
+                                   PackageChannelTrigger,
+                                   PackageGroupChannelAudit},
+                 DbPool},
+            hab_core::ChannelIdent,
+            protocol::jobsrv::JobGroupTrigger};
+
+use crate::server::{error::{Error,
+                            Result},
+                    services::memcache::MemcacheClient};
+
+/// The requester of the operations Builder carries out on its own
+pub const SYSTEM_REQUESTER: &str = "builder";
+
+/// When a channel created or renewed `now` with a TTL of `ttl_days`, or the configured one when
+/// absent, expires. TTLs longer than the configured maximum are capped to it.
+pub fn expires_at(cfg: &ChannelsCfg,
+                  ttl_days: Option<u32>,
+                  now: NaiveDateTime)
+                  -> std::result::Result<NaiveDateTime, &'static str> {
+    let ttl_days = ttl_days.unwrap_or(cfg.ephemeral_ttl_days);
+    if ttl_days == 0 {
+        return Err("ttl_days must be at least 1");
+    }
+    Ok(now + Duration::days(i64::from(ttl_days.min(cfg.ephemeral_max_ttl_days))))
+}
+
+/// Whether a group was started by hand, from the hab client or the web UI, rather than by a
+/// push or an upload
+pub fn is_adhoc(trigger: JobGroupTrigger) -> bool {
+    match trigger {
+        JobGroupTrigger::HabClient | JobGroupTrigger::BuilderUI => true,
+        _ => false,
+    }
+}
+
+/// The Warning header of a promotion into stable of a release an ad-hoc group built
+pub fn adhoc_warning(ident: &str, group_id: u64) -> String {
+    format!("299 - \"{} was promoted from an ephemeral channel, it was built by ad-hoc group {}\"",
+            ident, group_id)
+}
+
+/// Start expiring the ephemeral channels
+pub fn start(config: &Config, db: DbPool) {
+    let config = config.clone();
+    let interval = StdDuration::from_secs(config.channels.expiry_interval_sec.max(1));
+
+    thread::Builder::new().name("ephemeral-channels".to_string())
+                          .spawn(move || {
+                              let mut memcache = MemcacheClient::new(&config.memcache);
+                              loop {
+                                  if let Err(err) = expire(&db, &mut memcache) {
+                                      warn!("Unable to expire channels, err={}", err);
+                                  }
+                                  thread::sleep(interval);
+                              }
+                          })
+                          .expect("Unable to start ephemeral channel expiry thread");
+}
+
+fn expire(db: &DbPool, memcache: &mut MemcacheClient) -> Result<()> {
+    let conn = db.get_conn().map_err(Error::DbError)?;
+    let now = Utc::now().naive_utc();
+    let expired = Channel::list_expired(now, &*conn)?;
+
+    for channel in expired {
+        let ident = ChannelIdent::from(channel.name.as_str());
+        match expire_channel(&channel, now, &*conn) {
+            Ok(Some(releases)) => {
+                info!("Channel {}/{} expired, demoted {} releases",
+                      channel.origin,
+                      channel.name,
+                      releases.len());
+                memcache.clear_cache_for_channel(&channel.origin, &ident);
+                for release in releases {
+                    memcache.clear_cache_for_package(&release.ident);
+                }
+            }
+            // Another node is expiring it, or it was renewed or deleted since it was listed
+            Ok(None) => (),
+            Err(err) => {
+                warn!("Unable to expire channel {}/{}, err={}",
+                      channel.origin, channel.name, err)
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Demotes the releases of the channel and deletes it along with its pins, in one transaction.
+/// Returns the demoted releases, or `None` when the channel is no longer expired by `now` or
+/// another connection holds it.
+pub fn expire_channel(channel: &Channel,
+                      now: NaiveDateTime,
+                      conn: &PgConnection)
+                      -> Result<Option<Vec<ChannelRelease>>> {
+    conn.transaction::<_, Error, _>(|| {
+            let channel = match Channel::lock_expired(channel.id, now, conn)? {
+                Some(channel) => channel,
+                None => return Ok(None),
+            };
+            let ident = ChannelIdent::from(channel.name.as_str());
+            let releases = Channel::list_releases(channel.id, conn)?;
+            let package_ids: Vec<i64> = releases.iter().map(|r| r.package_id).collect();
+
+            Channel::demote_packages(channel.id, &package_ids, conn)?;
+            ChannelPackagePin::delete_all(&channel.origin, &ident, conn)?;
+            Channel::delete(&channel.origin, &ident, conn)?;
+
+            ChannelAudit::audit(&ChannelAudit { origin:         &channel.origin,
+                                                channel:        &channel.name,
+                                                channel_type:   channel.channel_type,
+                                                operation:      ChannelOperation::Expire,
+                                                expires_at:     channel.expires_at,
+                                                package_ids:    package_ids.clone(),
+                                                requester_id:   0,
+                                                requester_name: SYSTEM_REQUESTER, },
+                                conn)?;
+            if !package_ids.is_empty() {
+                PackageGroupChannelAudit::audit(
+                    PackageGroupChannelAudit {
+                        origin: &channel.origin,
+                        channel: &channel.name,
+                        package_ids,
+                        operation: PackageChannelOperation::Demote,
+                        trigger: PackageChannelTrigger::Unknown,
+                        requester_id: 0,
+                        requester_name: SYSTEM_REQUESTER,
+                        group_id: 0,
+                    },
+                    conn,
+                )?;
+            }
+            Ok(Some(releases))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime { NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap() }
+
+    fn cfg() -> ChannelsCfg {
+        ChannelsCfg { ephemeral_ttl_days:     14,
+                      ephemeral_max_ttl_days: 30,
+                      expiry_interval_sec:    3600, }
+    }
+
+    #[test]
+    fn channels_live_for_the_configured_ttl_by_default() {
+        let now = at("2019-10-21 12:00");
+        assert_eq!(expires_at(&cfg(), None, now), Ok(at("2019-11-04 12:00")));
+        assert_eq!(expires_at(&cfg(), Some(1), now), Ok(at("2019-10-22 12:00")));
+    }
+
+    #[test]
+    fn ttls_are_capped_to_the_maximum() {
+        let now = at("2019-10-21 12:00");
+        assert_eq!(expires_at(&cfg(), Some(365), now),
+                   Ok(at("2019-11-20 12:00")));
+        assert!(expires_at(&cfg(), Some(0), now).is_err());
+    }
+
+    #[test]
+    fn groups_started_by_hand_are_adhoc() {
+        assert!(is_adhoc(JobGroupTrigger::HabClient));
+        assert!(is_adhoc(JobGroupTrigger::BuilderUI));
+        assert!(!is_adhoc(JobGroupTrigger::Webhook));
+        assert!(!is_adhoc(JobGroupTrigger::Upload));
+        assert!(!is_adhoc(JobGroupTrigger::Unknown));
+    }
+
+    #[test]
+    fn adhoc_warnings_name_the_group() {
+        assert_eq!(adhoc_warning("core/zlib/1.2.11/20190115003728", 1234),
+                   "299 - \"core/zlib/1.2.11/20190115003728 was promoted from an ephemeral \
+                    channel, it was built by ad-hoc group 1234\"");
+    }
+}
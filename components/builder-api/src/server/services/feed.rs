@@ -0,0 +1,435 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Atom feeds of the releases of a package and the promotions into a channel, along with the
+//! lifecycle of the channel. A feed carries an ETag and a Last-Modified time so that readers
+//! polling an unchanged feed are answered with a 304 and no body.
+
+use std::time::{Duration,
+                SystemTime,
+                UNIX_EPOCH};
+
+use actix_web::{http::{self,
+                       header::HttpDate,
+                       StatusCode},
+                HttpRequest,
+                HttpResponse};
+use chrono::NaiveDateTime;
+
+use crate::{bldr_core::timestamp,
+            config::ApiCfg,
+            db::models::{channel::{ChannelEvent,
+                                   ChannelOperation},
+                         package::FeedRelease}};
+
+use crate::server::framework::headers;
+
+pub const CONTENT_TYPE: &str = "application/atom+xml; charset=utf-8";
+
+// Readers may keep a feed but must revalidate it, no-store would keep them from sending the
+// ETag back
+const CACHE_CONTROL: &str = "private, no-cache";
+
+// The updated time of a feed without entries
+const EPOCH: &str = "1970-01-01T00:00:00Z";
+
+pub struct Feed {
+    pub id:      String,
+    pub link:    String,
+    pub title:   String,
+    pub entries: Vec<Entry>,
+}
+
+pub struct Entry {
+    pub id:      String,
+    pub title:   String,
+    pub link:    String,
+    pub summary: String,
+    pub updated: NaiveDateTime,
+}
+
+impl Feed {
+    /// When the feed last changed, the time of its latest entry
+    pub fn updated(&self) -> Option<NaiveDateTime> { self.entries.iter().map(|e| e.updated).max() }
+
+    pub fn to_xml(&self) -> String {
+        let updated = self.updated()
+                          .map_or_else(|| EPOCH.to_string(), |at| timestamp::naive_to_rfc3339(&at));
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+        xml.push_str(&format!("  <id>{}</id>\n", escape(&self.id)));
+        xml.push_str(&format!("  <title>{}</title>\n", escape(&self.title)));
+        xml.push_str(&format!("  <link rel=\"self\" href=\"{}\"/>\n", escape(&self.link)));
+        xml.push_str(&format!("  <updated>{}</updated>\n", updated));
+        xml.push_str("  <author><name>Habitat Builder</name></author>\n");
+        for entry in &self.entries {
+            xml.push_str("  <entry>\n");
+            xml.push_str(&format!("    <id>{}</id>\n", escape(&entry.id)));
+            xml.push_str(&format!("    <title>{}</title>\n", escape(&entry.title)));
+            xml.push_str(&format!("    <link href=\"{}\"/>\n", escape(&entry.link)));
+            xml.push_str(&format!("    <updated>{}</updated>\n",
+                                  timestamp::naive_to_rfc3339(&entry.updated)));
+            xml.push_str(&format!("    <summary>{}</summary>\n", escape(&entry.summary)));
+            xml.push_str("  </entry>\n");
+        }
+        xml.push_str("</feed>\n");
+        xml
+    }
+}
+
+/// An entry for a release of a package, identified by its fully qualified ident and target
+pub fn release_entry(base_url: &str, release: FeedRelease) -> Option<Entry> {
+    let updated = release.updated_at?;
+    Some(Entry { id: format!("urn:habitat:release:{}:{}", release.target, release.ident),
+                 title: release.ident.clone(),
+                 link: package_link(base_url, &release),
+                 summary: format!("{} was released for {}", release.ident, release.target),
+                 updated })
+}
+
+/// An entry for a promotion of a release into a channel. A release promoted, demoted and
+/// promoted again has an entry for each promotion.
+pub fn promotion_entry(base_url: &str, urn: &str, release: FeedRelease) -> Option<Entry> {
+    let updated = release.updated_at?;
+    let by = release.requester_name
+                    .as_ref()
+                    .map_or_else(String::new, |name| format!(" by {}", name));
+    let summary = format!("{} was promoted{}", release.ident, by);
+    Some(Entry { id: format!("{}:{}:{}:{}",
+                             urn,
+                             release.target,
+                             release.ident,
+                             updated.timestamp()),
+                 title: release.ident.clone(),
+                 link: package_link(base_url, &release),
+                 summary,
+                 updated })
+}
+
+/// An entry for a step in the lifecycle of a channel, such as its expiry
+pub fn channel_event_entry(base_url: &str, event: ChannelEvent) -> Option<Entry> {
+    let updated = event.created_at?;
+    let until = event.expires_at.map_or_else(String::new, |at| {
+                                    format!(" until {}", timestamp::naive_to_rfc3339(&at))
+                                });
+    let (operation, summary) = match event.operation {
+        ChannelOperation::Create => {
+            ("create",
+             format!("{} was created by {}{}",
+                     event.channel, event.requester_name, until))
+        }
+        ChannelOperation::Renew => {
+            ("renew",
+             format!("{} was renewed by {}{}",
+                     event.channel, event.requester_name, until))
+        }
+        ChannelOperation::Expire => {
+            ("expire",
+             format!("{} expired, {} releases were demoted",
+                     event.channel,
+                     event.package_ids.len()))
+        }
+        ChannelOperation::Delete => {
+            ("delete",
+             format!("{} was deleted by {}, {} releases were demoted",
+                     event.channel,
+                     event.requester_name,
+                     event.package_ids.len()))
+        }
+        ChannelOperation::Supersede => {
+            ("supersede",
+             format!("{} releases were superseded in {} by {} and demoted",
+                     event.package_ids.len(),
+                     event.channel,
+                     event.requester_name))
+        }
+    };
+    Some(Entry { id: format!("urn:habitat:channel:{}:{}:{}:{}",
+                             event.origin,
+                             event.channel,
+                             operation,
+                             updated.timestamp()),
+                 title: format!("{}/{} {}", event.origin, event.channel, operation),
+                 link: format!("{}/v1/depot/channels/{}/{}/pkgs",
+                               base_url, event.origin, event.channel),
+                 summary,
+                 updated })
+}
+
+fn package_link(base_url: &str, release: &FeedRelease) -> String {
+    format!("{}/v1/depot/pkgs/{}?target={}",
+            base_url, release.ident, release.target)
+}
+
+/// The id of the feed of a package's releases, the same whichever host it was read from
+pub fn package_feed_id(origin: &str, name: &str, channel: &str, target: &str) -> String {
+    let mut id = format!("urn:habitat:feed:package:{}:{}", origin, name);
+    if !channel.is_empty() {
+        id.push_str(&format!(":channel:{}", channel));
+    }
+    if !target.is_empty() {
+        id.push_str(&format!(":target:{}", target));
+    }
+    id
+}
+
+/// The id of the feed of a channel's promotions, the same whichever host it was read from
+pub fn channel_feed_id(origin: &str, channel: &str) -> String {
+    format!("urn:habitat:feed:channel:{}:{}", origin, channel)
+}
+
+/// The URL entries link to, the configured `public_url`. Links are relative without one, the
+/// request's Host is not trusted to name Builder.
+pub fn base_url(config: &ApiCfg) -> &str { config.public_url.trim_end_matches('/') }
+
+/// The link of the feed itself, its path under the base URL
+pub fn self_link(config: &ApiCfg, req: &HttpRequest) -> String {
+    format!("{}{}", base_url(config), req.uri())
+}
+
+/// The feed as an Atom document, or a 304 when the reader already has it
+pub fn respond(req: &HttpRequest, feed: &Feed) -> HttpResponse {
+    let body = feed.to_xml();
+    let etag = headers::etag(&body);
+    let updated = feed.updated();
+
+    let if_none_match = header_str(req, http::header::IF_NONE_MATCH);
+    let if_modified_since = header_str(req, http::header::IF_MODIFIED_SINCE);
+    let status = if is_fresh(if_none_match.as_ref().map(String::as_str),
+                             if_modified_since.as_ref().map(String::as_str),
+                             &etag,
+                             updated)
+    {
+        StatusCode::NOT_MODIFIED
+    } else {
+        StatusCode::OK
+    };
+
+    let mut response = HttpResponse::build(status);
+    response.header(http::header::CACHE_CONTROL, CACHE_CONTROL)
+            .header(http::header::ETAG, etag);
+    if let Some(updated) = updated {
+        response.header(http::header::LAST_MODIFIED, http_date(updated).to_string());
+    }
+    if status == StatusCode::NOT_MODIFIED {
+        response.finish()
+    } else {
+        response.content_type(CONTENT_TYPE).body(body)
+    }
+}
+
+fn header_str(req: &HttpRequest, name: http::header::HeaderName) -> Option<String> {
+    req.headers()
+       .get(name)
+       .and_then(|value| value.to_str().ok())
+       .map(str::to_string)
+}
+
+/// Whether the reader's copy is current. An If-None-Match is decided by the ETag alone, as
+/// RFC 7232 has it; otherwise an If-Modified-Since no earlier than the latest entry is current.
+fn is_fresh(if_none_match: Option<&str>,
+            if_modified_since: Option<&str>,
+            etag: &str,
+            updated: Option<NaiveDateTime>)
+            -> bool {
+    if let Some(tags) = if_none_match {
+        return headers::etag_matches(tags, etag);
+    }
+
+    match (if_modified_since.and_then(|since| since.parse::<HttpDate>().ok()), updated) {
+        (Some(since), Some(updated)) => SystemTime::from(since) >= http_date_time(updated),
+        _ => false,
+    }
+}
+
+// HTTP dates are to the second, the entry time is truncated so that it compares equal to the
+// Last-Modified it was sent as
+fn http_date_time(at: NaiveDateTime) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(at.timestamp().max(0) as u64)
+}
+
+fn http_date(at: NaiveDateTime) -> HttpDate { HttpDate::from(http_date_time(at)) }
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::models::channel::ChannelType,
+                server::framework::headers::etag};
+
+    const BASE_URL: &str = "https://bldr.example.com";
+    const ETAG: &str = "\"abc\"";
+    const UPDATED: &str = "Thu, 03 Oct 2019 12:00:00 GMT";
+
+    fn at(s: &str) -> NaiveDateTime { NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap() }
+
+    fn release(ident: &str, requester_name: Option<&str>) -> FeedRelease {
+        FeedRelease { ident:          ident.to_string(),
+                      target:         "x86_64-linux".to_string(),
+                      updated_at:     Some(at("2019-10-03 12:00")),
+                      requester_name: requester_name.map(str::to_string), }
+    }
+
+    fn feed(entries: Vec<Entry>) -> Feed {
+        Feed { id: package_feed_id("core", "zlib", "", ""),
+               link: format!("{}/v1/depot/pkgs/core/zlib/feed.atom", BASE_URL),
+               title: "core/zlib releases".to_string(),
+               entries }
+    }
+
+    #[test]
+    fn release_entries_are_identified_by_ident_and_target() {
+        let entry = release_entry(BASE_URL, release("core/zlib/1.2.11/20190115003728", None));
+        let entry = entry.unwrap();
+        assert_eq!(entry.id,
+                   "urn:habitat:release:x86_64-linux:core/zlib/1.2.11/20190115003728");
+        assert_eq!(entry.link,
+                   "https://bldr.example.com/v1/depot/pkgs/core/zlib/1.2.11/20190115003728?\
+                    target=x86_64-linux");
+    }
+
+    #[test]
+    fn releases_without_a_time_have_no_entry() {
+        let mut release = release("core/zlib/1.2.11/20190115003728", None);
+        release.updated_at = None;
+        assert!(release_entry(BASE_URL, release).is_none());
+    }
+
+    #[test]
+    fn idents_are_escaped() {
+        let entry = release_entry(BASE_URL, release("core/a&b<c>/1.0\"/'20190115003728", None));
+        let xml = feed(vec![entry.unwrap()]).to_xml();
+        let escaped = "core/a&amp;b&lt;c&gt;/1.0&quot;/&apos;20190115003728";
+        assert!(xml.contains(&format!("<title>{}</title>", escaped)));
+        assert!(xml.contains(&format!("pkgs/{}?target=x86_64-linux\"/>", escaped)));
+        assert!(!xml.contains("a&b"));
+    }
+
+    #[test]
+    fn messages_are_escaped() {
+        let entry = promotion_entry(BASE_URL,
+                                    "urn:habitat:promotion:core:stable",
+                                    release("core/zlib/1.2.11/20190115003728",
+                                            Some("<script>alert('&')</script>")));
+        let xml = feed(vec![entry.unwrap()]).to_xml();
+        let escaped = "&lt;script&gt;alert(&apos;&amp;&apos;)&lt;/script&gt;";
+        assert!(xml.contains(&format!("<summary>core/zlib/1.2.11/20190115003728 was promoted \
+                                       by {}</summary>",
+                                      escaped)));
+        assert!(!xml.contains("<script>"));
+    }
+
+    #[test]
+    fn channel_expiries_count_the_demoted_releases() {
+        let event = ChannelEvent { origin:         "core".to_string(),
+                                   channel:        "feat-1234".to_string(),
+                                   channel_type:   ChannelType::Ephemeral,
+                                   operation:      ChannelOperation::Expire,
+                                   expires_at:     Some(at("2019-10-03 12:00")),
+                                   package_ids:    vec![7, 8],
+                                   requester_id:   0,
+                                   requester_name: "builder".to_string(),
+                                   created_at:     Some(at("2019-10-03 12:05")), };
+        let entry = channel_event_entry(BASE_URL, event).unwrap();
+        assert_eq!(entry.id,
+                   format!("urn:habitat:channel:core:feat-1234:expire:{}",
+                           at("2019-10-03 12:05").timestamp()));
+        assert_eq!(entry.title, "core/feat-1234 expire");
+        assert_eq!(entry.summary, "feat-1234 expired, 2 releases were demoted");
+        assert_eq!(entry.link,
+                   "https://bldr.example.com/v1/depot/channels/core/feat-1234/pkgs");
+    }
+
+    #[test]
+    fn feeds_are_updated_at_their_latest_entry() {
+        let mut older = release("core/zlib/1.2.8/20180101000000", None);
+        older.updated_at = Some(at("2018-01-01 00:00"));
+        let entries = vec![release_entry(BASE_URL, release("core/zlib/1.2.11/20190115003728",
+                                                           None)).unwrap(),
+                           release_entry(BASE_URL, older).unwrap()];
+        let xml = feed(entries).to_xml();
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+                                 <feed xmlns=\"http://www.w3.org/2005/Atom\">\n"));
+        assert!(xml.contains("  <updated>2019-10-03T12:00:00.000000Z</updated>\n"));
+        assert_eq!(xml.matches("<entry>").count(), 2);
+
+        assert!(feed(vec![]).to_xml()
+                            .contains("  <updated>1970-01-01T00:00:00Z</updated>\n"));
+    }
+
+    #[test]
+    fn readers_with_the_etag_are_current() {
+        let updated = Some(at("2019-10-03 12:00"));
+        assert!(is_fresh(Some(ETAG), None, ETAG, updated));
+        assert!(is_fresh(Some("\"xyz\", W/\"abc\""), None, ETAG, updated));
+        assert!(is_fresh(Some("*"), None, ETAG, updated));
+        assert!(!is_fresh(Some("\"xyz\""), Some(UPDATED), ETAG, updated));
+    }
+
+    #[test]
+    fn readers_modified_since_the_latest_entry_are_current() {
+        let updated = Some(at("2019-10-03 12:00"));
+        assert!(is_fresh(None, Some(UPDATED), ETAG, updated));
+        assert!(!is_fresh(None, Some("Thu, 03 Oct 2019 11:59:59 GMT"), ETAG, updated));
+        assert!(!is_fresh(None, Some("not a date"), ETAG, updated));
+        assert!(!is_fresh(None, Some(UPDATED), ETAG, None));
+        assert!(!is_fresh(None, None, ETAG, updated));
+    }
+
+    #[test]
+    fn etags_follow_the_body() {
+        assert_eq!(etag("<feed/>"), etag("<feed/>"));
+        assert_ne!(etag("<feed/>"), etag("<feed></feed>"));
+        assert_eq!(etag("<feed/>").len(), 34);
+    }
+
+    #[test]
+    fn feeds_are_identified_by_what_they_list() {
+        assert_eq!(package_feed_id("core", "zlib", "", ""),
+                   "urn:habitat:feed:package:core:zlib");
+        assert_eq!(package_feed_id("core", "zlib", "stable", "x86_64-linux"),
+                   "urn:habitat:feed:package:core:zlib:channel:stable:target:x86_64-linux");
+        assert_eq!(channel_feed_id("core", "stable"),
+                   "urn:habitat:feed:channel:core:stable");
+
+        let xml = feed(vec![]).to_xml();
+        assert!(xml.contains("  <id>urn:habitat:feed:package:core:zlib</id>\n"));
+        assert!(xml.contains(&format!("  <link rel=\"self\" \
+                                       href=\"{}/v1/depot/pkgs/core/zlib/feed.atom\"/>\n",
+                                      BASE_URL)));
+    }
+
+    #[test]
+    fn links_are_relative_without_a_public_url() {
+        let config = ApiCfg::default();
+        assert_eq!(base_url(&config), "");
+        let config = ApiCfg { public_url: "https://bldr.example.com/".to_string(),
+                              ..Default::default() };
+        assert_eq!(base_url(&config), BASE_URL);
+    }
+}
@@ -36,11 +36,8 @@ use crate::{bldr_core::{build_config::{BuildCfg,
 
 use github_api_client::{types::GitHubWebhookPush,
                         AppToken,
-                        GitHubClient};
-use hex;
-use openssl::{hash::MessageDigest,
-              pkey::PKey,
-              sign::Signer};
+                        GitHubClient,
+                        InstallationId};
 use serde_json;
 
 use crate::db::models::{account::Account,
@@ -53,7 +50,8 @@ use crate::server::{authorize::authorize_session,
                     framework::{headers,
                                 middleware::route_message},
                     helpers::req_state,
-                    services::metrics::Counter,
+                    services::{metrics::Counter,
+                               webhook},
                     AppState};
 
 pub enum GitHubEvent {
@@ -108,32 +106,73 @@ pub fn handle_event(req: HttpRequest, body: String) -> HttpResponse {
     };
 
     // Authenticate the hook
-    let github = &req_state(&req).github;
-    let gh_signature = match req.headers().get(headers::XHUBSIGNATURE) {
-        Some(sig) => sig.clone(),
+    let state = req_state(&req);
+    let secret = state.github.webhook_secret_for(installation_id(&body));
+    let signature = req.headers()
+                       .get(headers::XHUBSIGNATURE256)
+                       .and_then(|sig| sig.to_str().ok());
+    if let Err(err) = verify_signature(signature, secret, &body) {
+        warn!("Rejecting GitHub hook, {}", err);
+        Counter::GitHubSignatureFailure.increment();
+        return HttpResponse::new(StatusCode::UNAUTHORIZED);
+    }
+
+    trace!("handle-notify, {}", body);
+
+    let delivery = match req.headers()
+                            .get(headers::XGITHUBDELIVERY)
+                            .and_then(|d| d.to_str().ok())
+    {
+        Some(delivery) => delivery.to_string(),
         None => {
-            warn!("Received a GitHub hook with no signature");
+            warn!("Received a GitHub hook with no delivery id");
             return Error::BadRequest.into();
         }
     };
 
-    trace!("handle-notify, {}", body);
-
-    let key = PKey::hmac(github.webhook_secret.as_bytes()).unwrap();
-    let mut signer = Signer::new(MessageDigest::sha1(), &key).unwrap();
-    signer.update(body.as_bytes()).unwrap();
-    let hmac = signer.sign_to_vec().unwrap();
-    let computed_signature = format!("sha1={}", &hex::encode(hmac));
-
-    if !crypto::secure_eq(&gh_signature, &computed_signature) {
-        warn!("Web hook signatures don't match. GH = {:?}, Our = {:?}",
-              gh_signature, computed_signature);
-        return Error::BadRequest.into();
+    // A delivery sent again is acknowledged, an error would only have GitHub retry it
+    if !state.github_deliveries.record(&delivery) {
+        debug!("Ignoring GitHub hook delivery {}, it was already handled",
+               delivery);
+        Counter::GitHubRedelivery.increment();
+        return HttpResponse::Ok().json(json!({ "ignored": "duplicate delivery" }));
     }
 
-    match event {
+    let resp = match event {
         GitHubEvent::Ping => HttpResponse::new(StatusCode::OK),
         GitHubEvent::Push => handle_push(&req, &body),
+    };
+    if resp.status().is_server_error() {
+        state.github_deliveries.forget(&delivery);
+    }
+    resp
+}
+
+// The App installation a hook was sent for. It decides the secret the hook is verified with,
+// so it is read from the body before the hook is authenticated.
+fn installation_id(body: &str) -> Option<InstallationId> {
+    let hook = serde_json::from_str::<serde_json::Value>(body).ok()?;
+    hook["installation"]["id"].as_u64()
+                              .map(|id| id as InstallationId)
+}
+
+// Checks the X-Hub-Signature-256 header of a hook: `sha256=` followed by the hex encoded
+// HMAC-SHA256 of the body, keyed with the webhook secret
+fn verify_signature(signature: Option<&str>,
+                    secret: &str,
+                    body: &str)
+                    -> std::result::Result<(), &'static str> {
+    let signature = match signature {
+        Some(signature) => signature,
+        None => return Err("it is not signed"),
+    };
+    if secret.is_empty() {
+        return Err("no webhook secret is configured");
+    }
+    if crypto::secure_eq(signature, webhook::signature(secret, body)) {
+        Ok(())
+    } else {
+        Err("its signature does not match")
     }
 }
 
@@ -221,6 +260,7 @@ fn handle_push(req: &HttpRequest, body: &str) -> HttpResponse {
 
     build_plans(&req,
                 &hook.repository.clone_url,
+                &hook.after,
                 &hook.pusher.name,
                 account_id,
                 &plans)
@@ -228,6 +268,7 @@ fn handle_push(req: &HttpRequest, body: &str) -> HttpResponse {
 
 fn build_plans(req: &HttpRequest,
                repo_url: &str,
+               vcs_ref: &str,
                pusher: &str,
                account_id: Option<u64>,
                plans: &[PlanWithTarget])
@@ -267,6 +308,7 @@ fn build_plans(req: &HttpRequest,
             request.set_package(plan.0.name.clone());
             request.set_target(plan.1.to_string());
             request.set_trigger(JobGroupTrigger::Webhook);
+            request.set_vcs_ref(vcs_ref.to_string());
             request.set_requester_name(pusher.to_string());
             if account_id.is_some() {
                 request.set_requester_id(account_id.unwrap());
@@ -412,6 +454,7 @@ fn read_plan_targets(github: &GitHubClient,
                     "plan.sh" => {
                         targets.insert(target::X86_64_LINUX);
                         targets.insert(target::X86_64_LINUX_KERNEL2);
+                        targets.insert(target::AARCH64_LINUX);
                     }
                     _ => (),
                 }
@@ -425,3 +468,54 @@ fn read_plan_targets(github: &GitHubClient,
 
     Ok(targets)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &str = r#"{"ref":"refs/heads/master","installation":{"id":56789}}"#;
+
+    #[test]
+    fn signed_hook_is_accepted() {
+        let signature = webhook::signature("secret", BODY);
+        assert_eq!(verify_signature(Some(&signature), "secret", BODY), Ok(()));
+    }
+
+    #[test]
+    fn unsigned_hook_is_refused() {
+        assert_eq!(verify_signature(None, "secret", BODY),
+                   Err("it is not signed"));
+    }
+
+    #[test]
+    fn mis_signed_hook_is_refused() {
+        let signature = webhook::signature("other", BODY);
+        assert_eq!(verify_signature(Some(&signature), "secret", BODY),
+                   Err("its signature does not match"));
+
+        let tampered = BODY.replace("master", "release");
+        let signature = webhook::signature("secret", &tampered);
+        assert_eq!(verify_signature(Some(&signature), "secret", BODY),
+                   Err("its signature does not match"));
+
+        // GitHub's legacy SHA-1 signature is not accepted in place of the SHA-256 one
+        let legacy = "sha1=d03207e4b030cf234e3447bac4d93add4c6643d8";
+        assert_eq!(verify_signature(Some(legacy), "secret", BODY),
+                   Err("its signature does not match"));
+    }
+
+    #[test]
+    fn nothing_verifies_without_a_secret() {
+        let signature = webhook::signature("", BODY);
+        assert_eq!(verify_signature(Some(&signature), "", BODY),
+                   Err("no webhook secret is configured"));
+    }
+
+    #[test]
+    fn installation_is_read_from_body() {
+        assert_eq!(installation_id(BODY), Some(56789));
+        let ping = r#"{"zen":"Keep it logically awesome."}"#;
+        assert_eq!(installation_id(ping), None);
+        assert_eq!(installation_id("not json"), None);
+    }
+}
@@ -0,0 +1,189 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The remainder of an upload once its archive is in the artifact store: the package row, its
+//! build cache key and dependency report, the build graph and the builds of its reverse
+//! dependencies. Uploads stored at once and those drained from the upload spool both finish
+//! here.
+
+use std::{path::Path,
+          time::Duration};
+
+use actix_web::http::StatusCode;
+use diesel::{pg::PgConnection,
+             result::Error::NotFound};
+
+use crate::{bldr_core::{error::Error::RpcError,
+                        rpc::RpcClient},
+            config::Config,
+            db::models::{origin::Origin,
+                         package::{NewPackage,
+                                   NewPackageBuildCache,
+                                   NewPackageDependencyReport,
+                                   Package,
+                                   PackageBuildCache,
+                                   PackageDependencyReport,
+                                   PackageVisibility},
+                         projects::Project},
+            hab_core::package::{FromArchive,
+                                PackageArchive,
+                                PackageIdent,
+                                PackageTarget},
+            protocol::{jobsrv,
+                       originsrv}};
+
+use crate::server::{error::{Error,
+                            Result},
+                    services::dep_check::DependencyReport};
+
+/// An upload whose archive is stored
+pub struct StoredUpload<'a> {
+    /// The ident the upload was made to
+    pub ident:             &'a PackageIdent,
+    pub target:            PackageTarget,
+    /// The plaintext archive, whether or not the stored one is encrypted
+    pub archive_path:      &'a Path,
+    pub owner_id:          u64,
+    pub owner_name:        &'a str,
+    pub build_cache_key:   Option<&'a str>,
+    /// Uploaded by a worker, which schedules no builds of reverse dependencies
+    pub from_builder:      bool,
+    pub dependency_report: Option<&'a DependencyReport>,
+}
+
+/// The jobsrv the package is added to the build graph of, and the time the calls have
+pub struct Jobsrv<'a> {
+    pub client: &'a RpcClient,
+    pub budget: Option<Duration>,
+}
+
+/// Records a stored upload in the depot, returning the ident of its archive.
+/// `Error::Unprocessable` when the archive is not of the ident it was uploaded to.
+pub fn finish(upload: &StoredUpload,
+              config: &Config,
+              jobsrv: Option<&Jobsrv>,
+              conn: &PgConnection)
+              -> Result<PackageIdent> {
+    let ident = upload.ident;
+    let mut archive = PackageArchive::new(upload.archive_path);
+    let mut package = NewPackage::from_archive(&mut archive).map_err(Error::HabitatCore)?;
+
+    if !ident.satisfies(&*package.ident) {
+        debug!("Ident mismatch, expected={:?}, got={:?}",
+               ident, package.ident);
+        return Err(Error::Unprocessable);
+    }
+
+    package.owner_id = upload.owner_id as i64;
+    package.origin = ident.origin.clone();
+    package.visibility = visibility(ident, conn)?;
+
+    // Re-create origin package as needed (eg, checksum update)
+    match Package::create(&package, conn) {
+        Ok(pkg) => {
+            if let Some(key) = upload.build_cache_key {
+                let entry = NewPackageBuildCache { cache_key:  key,
+                                                   target:     pkg.target.clone(),
+                                                   package_id: pkg.id, };
+                if let Err(err) = PackageBuildCache::create(&entry, conn) {
+                    warn!("Unable to record build cache key for {}, err={:?}",
+                          ident, err);
+                }
+            }
+
+            if let Some(report) = upload.dependency_report {
+                let report = NewPackageDependencyReport { package_id: pkg.id,
+                                                          missing:    &report.missing,
+                                                          deprecated: &report.deprecated, };
+                if let Err(err) = PackageDependencyReport::create(&report, conn) {
+                    warn!("Unable to record dependency report for {}, err={:?}",
+                          ident, err);
+                }
+            }
+
+            if let Some(jobsrv) = jobsrv {
+                let mut job_graph_package = jobsrv::JobGraphPackageCreate::new();
+                job_graph_package.set_package(pkg.into());
+
+                match jobsrv.client
+                            .rpc_within::<_, originsrv::OriginPackage>(&job_graph_package,
+                                                                       jobsrv.budget)
+                {
+                    Ok(_) => (),
+                    Err(RpcError(code, _)) if code == StatusCode::NOT_FOUND.as_u16() => {
+                        debug!("Graph not found for package target: {}", upload.target);
+                    }
+                    Err(err) => {
+                        debug!("Failed to create job graph package, err={:?}", err);
+                        return Err(Error::BuilderCore(err));
+                    }
+                }
+            }
+        }
+        Err(NotFound) => {
+            debug!("Package::create returned NotFound (DB conflict handled)");
+        }
+        Err(err) => {
+            debug!("Failed to create package in DB, err: {:?}", err);
+            return Err(Error::DieselError(err));
+        }
+    }
+
+    // Schedule re-build of dependent packages (if requested)
+    // Don't schedule builds if the upload is being done by the builder
+    if let Some(jobsrv) = jobsrv {
+        if !upload.from_builder && config.api.build_on_upload {
+            schedule_rdeps(upload, jobsrv);
+        }
+    }
+
+    Ok((*package.ident).clone())
+}
+
+fn schedule_rdeps(upload: &StoredUpload, jobsrv: &Jobsrv) {
+    let ident = upload.ident;
+    let mut request = jobsrv::JobGroupSpec::new();
+    request.set_origin(ident.origin.to_string());
+    request.set_package(ident.name.to_string());
+    request.set_target(upload.target.to_string());
+    request.set_deps_only(true);
+    request.set_origin_only(false);
+    request.set_package_only(false);
+    request.set_trigger(jobsrv::JobGroupTrigger::Upload);
+    request.set_requester_id(upload.owner_id);
+    request.set_requester_name(upload.owner_name.to_string());
+
+    match jobsrv.client
+                .rpc_within::<_, jobsrv::JobGroup>(&request, jobsrv.budget)
+    {
+        Ok(group) => {
+            debug!("Scheduled reverse dependecy build for {}, group id: {}",
+                   ident,
+                   group.get_id())
+        }
+        Err(RpcError(code, _)) if code == StatusCode::NOT_FOUND.as_u16() => {
+            debug!("Unable to schedule build for {} (not found)", ident)
+        }
+        Err(err) => warn!("Unable to schedule build for {}, err: {:?}", ident, err),
+    }
+}
+
+// That of the package's project when it has one, the origin's default otherwise
+fn visibility(ident: &PackageIdent, conn: &PgConnection) -> Result<PackageVisibility> {
+    let project_name = format!("{}/{}", ident.origin, ident.name);
+    match Project::get(&project_name, conn) {
+        Ok(project) => Ok(project.visibility),
+        Err(_) => Ok(Origin::get(&ident.origin, conn)?.default_package_visibility),
+    }
+}
@@ -0,0 +1,399 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Installs reported by hab clients. A report names the package, its target, whether the
+//! install worked and an opaque id the client derives for its site, nothing that identifies the
+//! host. Reports are tallied in memory and added to the daily rollup in the background, so that
+//! clients are answered without waiting on the database. Installs of packages this Builder does
+//! not know are counted under `unknown`.
+
+use std::{collections::{HashMap,
+                        HashSet},
+          mem,
+          str::FromStr,
+          sync::{Arc,
+                 Mutex,
+                 MutexGuard},
+          thread,
+          time::Duration as StdDuration};
+
+use chrono::{Duration,
+             NaiveDate,
+             Utc};
+use diesel::{pg::PgConnection,
+             result::Error::NotFound,
+             Connection};
+
+use crate::{config::ApiCfg,
+            db::{models::{install::{InstallStat,
+                                    NewInstallStat,
+                                    UNKNOWN_IDENT},
+                          package::{BuilderPackageIdent,
+                                    BuilderPackageTarget,
+                                    GetPackage,
+                                    Package,
+                                    PackageVisibility}},
+                 DbPool},
+            hab_core::package::{Identifiable,
+                                PackageIdent,
+                                PackageTarget}};
+
+use crate::server::{error::{Error,
+                            Result},
+                    services::rate_limit::RateLimiter};
+
+const FLUSH_INTERVAL_SECS: u64 = 60;
+
+/// Reports held between flushes, those beyond are dropped
+const MAX_PENDING_REPORTS: usize = 100_000;
+
+/// Days the ids of the sites that installed a package are kept to count them
+const SITE_RETENTION_DAYS: i64 = 30;
+
+const SITE_ID_MIN_LEN: usize = 16;
+const SITE_ID_MAX_LEN: usize = 64;
+
+/// An install as reported by a client. Fields beyond these are refused rather than ignored, so
+/// that nothing more is sent than is kept.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InstallReport {
+    pub ident:   String,
+    pub target:  String,
+    pub success: bool,
+    pub site_id: String,
+}
+
+impl InstallReport {
+    /// The package and target the report is about, or why the report is refused
+    pub fn validate(&self) -> std::result::Result<(PackageIdent, PackageTarget), String> {
+        let ident = match PackageIdent::from_str(&self.ident) {
+            Ok(ident) if ident.fully_qualified() => ident,
+            _ => return Err(format!("{:?} is not a fully qualified ident", self.ident)),
+        };
+        let target = match PackageTarget::from_str(&self.target) {
+            Ok(target) => target,
+            Err(_) => return Err(format!("{:?} is not a target", self.target)),
+        };
+        if !valid_site_id(&self.site_id) {
+            return Err(format!("A site id is {} to {} letters, digits, - or _",
+                               SITE_ID_MIN_LEN, SITE_ID_MAX_LEN));
+        }
+        Ok((ident, target))
+    }
+}
+
+// Opaque ids only, a hostname or address does not pass
+fn valid_site_id(site_id: &str) -> bool {
+    site_id.len() >= SITE_ID_MIN_LEN
+    && site_id.len() <= SITE_ID_MAX_LEN
+    && site_id.chars()
+              .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct TallyKey {
+    day:    NaiveDate,
+    ident:  String,
+    target: String,
+}
+
+#[derive(Debug, Default)]
+struct Tally {
+    successes: i64,
+    failures:  i64,
+    sites:     HashSet<String>,
+}
+
+#[derive(Default)]
+struct Pending {
+    tallies: HashMap<TallyKey, Tally>,
+    reports: usize,
+}
+
+/// The install reports waiting to be written, shared by every worker
+pub struct InstallStats {
+    limiter: RateLimiter,
+    pending: Mutex<Pending>,
+}
+
+impl InstallStats {
+    pub fn new(config: &ApiCfg) -> Self {
+        InstallStats { limiter: RateLimiter::new(config.telemetry_rate_limit,
+                                                 StdDuration::from_secs(60)),
+                       pending: Mutex::new(Pending::default()), }
+    }
+
+    /// Counts a report from `caller`, returning false when it is over the rate limit
+    pub fn allow(&self, caller: &str) -> bool { self.limiter.check(caller) }
+
+    /// Queues a report for the next flush, returning false when the queue is full and the
+    /// report was dropped
+    pub fn queue(&self,
+                 ident: &PackageIdent,
+                 target: PackageTarget,
+                 success: bool,
+                 site_id: &str,
+                 day: NaiveDate)
+                 -> bool {
+        let mut pending = self.lock();
+        if pending.reports >= MAX_PENDING_REPORTS {
+            return false;
+        }
+        pending.reports += 1;
+
+        let key = TallyKey { day,
+                             ident: ident.to_string(),
+                             target: target.to_string() };
+        let tally = pending.tallies.entry(key).or_insert_with(Tally::default);
+        if success {
+            tally.successes += 1;
+        } else {
+            tally.failures += 1;
+        }
+        tally.sites.insert(site_id.to_string());
+        true
+    }
+
+    fn take(&self) -> HashMap<TallyKey, Tally> {
+        let mut pending = self.lock();
+        pending.reports = 0;
+        mem::replace(&mut pending.tallies, HashMap::new())
+    }
+
+    fn lock(&self) -> MutexGuard<Pending> {
+        match self.pending.lock() {
+            Ok(pending) => pending,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    // Puts back tallies that could not be written, to be retried with the next flush
+    fn restore(&self, tallies: HashMap<TallyKey, Tally>) {
+        let mut pending = self.lock();
+        for (key, tally) in tallies {
+            pending.reports += (tally.successes + tally.failures) as usize;
+            let kept = pending.tallies.entry(key).or_insert_with(Tally::default);
+            kept.successes += tally.successes;
+            kept.failures += tally.failures;
+            kept.sites.extend(tally.sites);
+        }
+    }
+
+    // Adds the queued reports to the rollup. Each package is written in a transaction of its
+    // own, and those that fail are kept for the next flush.
+    fn flush(&self, db: &DbPool) -> Result<()> {
+        let tallies = self.take();
+        if tallies.is_empty() {
+            return Ok(());
+        }
+
+        let conn = match db.get_conn() {
+            Ok(conn) => conn,
+            Err(err) => {
+                self.restore(tallies);
+                return Err(Error::DbError(err));
+            }
+        };
+        let mut failed = HashMap::new();
+        for (key, tally) in tallies {
+            if let Err(err) = conn.transaction::<_, Error, _>(|| write(&key, &tally, &*conn)) {
+                warn!("Unable to record installs of {} ({}), err={}",
+                      key.ident, key.target, err);
+                failed.insert(key, tally);
+            }
+        }
+        if !failed.is_empty() {
+            debug!("Keeping installs of {} packages for the next flush",
+                   failed.len());
+            self.restore(failed);
+        }
+        Ok(())
+    }
+}
+
+fn write(key: &TallyKey, tally: &Tally, conn: &PgConnection) -> Result<()> {
+    let ident = PackageIdent::from_str(&key.ident)?;
+    let target = PackageTarget::from_str(&key.target)?;
+
+    let mut stat = NewInstallStat { day:       key.day,
+                                    ident:     &key.ident,
+                                    origin:    Some(ident.origin()),
+                                    name:      Some(ident.name()),
+                                    target:    &key.target,
+                                    successes: tally.successes,
+                                    failures:  tally.failures, };
+    if !is_known(&ident, target, conn)? {
+        stat.ident = UNKNOWN_IDENT;
+        stat.origin = None;
+        stat.name = None;
+        InstallStat::add(&stat, conn).map_err(Error::DieselError)?;
+        return Ok(());
+    }
+
+    InstallStat::add(&stat, conn).map_err(Error::DieselError)?;
+    let sites: Vec<&str> = tally.sites.iter().map(String::as_str).collect();
+    InstallStat::add_sites(key.day,
+                           ident.origin(),
+                           ident.name(),
+                           &key.target,
+                           &sites,
+                           conn).map_err(Error::DieselError)?;
+    Ok(())
+}
+
+fn is_known(ident: &PackageIdent, target: PackageTarget, conn: &PgConnection) -> Result<bool> {
+    let get = GetPackage { ident:      BuilderPackageIdent(ident.clone()),
+                           visibility: PackageVisibility::all(),
+                           target:     BuilderPackageTarget(target), };
+    match Package::get(get, conn) {
+        Ok(_) => Ok(true),
+        Err(NotFound) => Ok(false),
+        Err(err) => Err(Error::DieselError(err)),
+    }
+}
+
+/// Writes the queued reports every minute, and forgets the sites of old installs once a day
+pub fn start(config: &ApiCfg, stats: Arc<InstallStats>, db: DbPool) {
+    if !config.telemetry_enabled {
+        info!("Install telemetry disabled");
+        return;
+    }
+
+    thread::Builder::new().name("install-stats".to_string())
+                          .spawn(move || {
+                              let mut purged_on = None;
+                              loop {
+                                  thread::sleep(StdDuration::from_secs(FLUSH_INTERVAL_SECS));
+                                  if let Err(err) = stats.flush(&db) {
+                                      warn!("Unable to record installs, err={}", err);
+                                  }
+
+                                  let today = Utc::today().naive_utc();
+                                  if purged_on != Some(today) {
+                                      match purge_sites(today, &db) {
+                                          Ok(()) => purged_on = Some(today),
+                                          Err(err) => {
+                                              warn!("Unable to purge install sites, err={}", err)
+                                          }
+                                      }
+                                  }
+                              }
+                          })
+                          .expect("Unable to start install stats thread");
+}
+
+fn purge_sites(today: NaiveDate, db: &DbPool) -> Result<()> {
+    let conn = db.get_conn().map_err(Error::DbError)?;
+    let before = today - Duration::days(SITE_RETENTION_DAYS);
+    let purged = InstallStat::purge_sites(before, &*conn).map_err(Error::DieselError)?;
+    debug!("Purged {} install sites", purged);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZLIB: &str = "core/zlib/1.2.11/20190115003728";
+
+    fn report(ident: &str, target: &str, site_id: &str) -> InstallReport {
+        InstallReport { ident:   ident.to_string(),
+                        target:  target.to_string(),
+                        success: true,
+                        site_id: site_id.to_string(), }
+    }
+
+    fn stats() -> InstallStats { InstallStats::new(&ApiCfg::default()) }
+
+    #[test]
+    fn valid_report() {
+        let report = report(ZLIB, "x86_64-linux", "3f1e0c4a9b7d5e26");
+        let (ident, target) = report.validate().unwrap();
+        assert_eq!(ident.to_string(), ZLIB);
+        assert_eq!(target.to_string(), "x86_64-linux");
+    }
+
+    #[test]
+    fn refused_reports() {
+        let site = "3f1e0c4a9b7d5e26";
+        let refused = vec![report("core/zlib", "x86_64-linux", site),
+                           report(ZLIB, "mainframe", site),
+                           report(ZLIB, "x86_64-linux", "short"),
+                           report(ZLIB, "x86_64-linux", "build-01.example.com"),
+                           report(ZLIB, "x86_64-linux", "10.0.0.1:9636abcdef")];
+        for report in refused {
+            assert!(report.validate().is_err(), "{:?}", report);
+        }
+    }
+
+    #[test]
+    fn payload_with_more_fields_is_refused() {
+        let body = r#"{"ident": "core/zlib/1.2.11/20190115003728", "target": "x86_64-linux",
+                       "success": true, "site_id": "3f1e0c4a9b7d5e26", "hostname": "build-01"}"#;
+        assert!(serde_json::from_str::<InstallReport>(body).is_err());
+    }
+
+    #[test]
+    fn reports_are_tallied() {
+        let stats = stats();
+        let ident: PackageIdent = ZLIB.parse().unwrap();
+        let day = NaiveDate::from_ymd(2019, 10, 21);
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        assert!(stats.queue(&ident, target, true, "site-aaaaaaaaaaaaa", day));
+        assert!(stats.queue(&ident, target, true, "site-aaaaaaaaaaaaa", day));
+        assert!(stats.queue(&ident, target, false, "site-bbbbbbbbbbbbb", day));
+
+        let tallies = stats.take();
+        assert_eq!(tallies.len(), 1);
+        let tally = tallies.values().next().unwrap();
+        assert_eq!(tally.successes, 2);
+        assert_eq!(tally.failures, 1);
+        assert_eq!(tally.sites.len(), 2);
+        assert!(stats.take().is_empty());
+    }
+
+    #[test]
+    fn restored_tallies_are_merged_with_new_reports() {
+        let stats = stats();
+        let ident: PackageIdent = ZLIB.parse().unwrap();
+        let day = NaiveDate::from_ymd(2019, 10, 21);
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        assert!(stats.queue(&ident, target, true, "site-aaaaaaaaaaaaa", day));
+        let unwritten = stats.take();
+
+        assert!(stats.queue(&ident, target, false, "site-bbbbbbbbbbbbb", day));
+        stats.restore(unwritten);
+        assert_eq!(stats.lock().reports, 2);
+
+        let tallies = stats.take();
+        let tally = tallies.values().next().unwrap();
+        assert_eq!(tally.successes, 1);
+        assert_eq!(tally.failures, 1);
+        assert_eq!(tally.sites.len(), 2);
+    }
+
+    #[test]
+    fn reports_beyond_the_queue_are_dropped() {
+        let stats = stats();
+        stats.lock().reports = MAX_PENDING_REPORTS;
+        let ident: PackageIdent = ZLIB.parse().unwrap();
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        let day = NaiveDate::from_ymd(2019, 10, 21);
+        assert!(!stats.queue(&ident, target, true, "site-aaaaaaaaaaaaa", day));
+
+        stats.take();
+        assert!(stats.queue(&ident, target, true, "site-aaaaaaaaaaaaa", day));
+    }
+}
@@ -0,0 +1,149 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Delivery of origin invitations, including those addressed to an email that has no
+//! Builder account yet. Those stay pending until someone signs in with a matching provider
+//! email or follows the emailed link while signed in.
+
+use chrono::{Duration,
+             NaiveDateTime,
+             Utc};
+use diesel::pg::PgConnection;
+
+use oauth_client::types::OAuth2User;
+
+use crate::{bldr_core::invitation_token,
+            config::ApiCfg,
+            db::models::invitations::OriginInvitation};
+
+use crate::server::{error::{Error,
+                            Result},
+                    services::notify};
+
+/// Expiration of an invitation sent now.
+pub fn expires_at(config: &ApiCfg) -> Option<NaiveDateTime> {
+    match config.invitation_lifetime_days {
+        0 => None,
+        days => Some((Utc::now() + Duration::days(i64::from(days))).naive_utc()),
+    }
+}
+
+/// Lowercased address, or None if it does not look like one.
+pub fn normalize_email(email: &str) -> Option<String> {
+    let email = email.trim();
+    let mut parts = email.rsplitn(2, '@');
+    let domain = parts.next().unwrap_or("");
+    let local = parts.next().unwrap_or("");
+    if local.is_empty() || domain.is_empty() || email.chars().any(char::is_whitespace) {
+        return None;
+    }
+    Some(email.to_lowercase())
+}
+
+/// Tells the recipient about an invitation: through their account notifications, or by
+/// email with an acceptance link while the invitation is pending by email.
+pub fn send(invitation: &OriginInvitation, config: &ApiCfg, conn: &PgConnection) -> Result<()> {
+    let expiry = match invitation.expires_at {
+        Some(expires_at) => {
+            format!(" The invitation expires on {} UTC.",
+                    expires_at.format("%Y-%m-%d %H:%M:%S"))
+        }
+        None => String::new(),
+    };
+
+    match (invitation.account_id, &invitation.email) {
+        (Some(account_id), _) => {
+            let message = format!("You have been invited to join the {} origin.{}",
+                                  invitation.origin, expiry);
+            notify::notify_account(account_id, notify::ORIGIN_INVITATION, &message, conn)?;
+        }
+        (None, Some(email)) => {
+            let token = invitation_token::generate_invitation_token(&config.key_path,
+                                                                    invitation.id as u64,
+                                                                    email)?;
+            let message = format!("You have been invited to join the {} origin on Habitat \
+                                   Builder. Sign in and accept the invitation at {}/{}.{}",
+                                  invitation.origin,
+                                  config.invitation_url.trim_end_matches('/'),
+                                  token,
+                                  expiry);
+            notify::notify_email(email, notify::ORIGIN_INVITATION, &message, conn)?;
+        }
+        (None, None) => {
+            warn!("Invitation {} has no recipient to notify", invitation.id);
+        }
+    }
+    Ok(())
+}
+
+/// The email of a signed in user that may claim the invitations sent to it. Only an address
+/// the provider says it verified counts, or an account could claim the invitations of any
+/// address it could set on itself.
+pub fn claimable_email(user: &OAuth2User) -> Option<&str> {
+    match (&user.email, user.email_verified) {
+        (Some(email), Some(true)) => Some(email.as_str()),
+        _ => None,
+    }
+}
+
+/// Hands the invitations pending for a provider email to the account that signed in with it.
+pub fn claim_by_email(email: &str,
+                      account_id: i64,
+                      account_name: &str,
+                      conn: &PgConnection)
+                      -> Result<()> {
+    let email = match normalize_email(email) {
+        Some(email) => email,
+        None => return Ok(()),
+    };
+
+    let claimed = OriginInvitation::claim_by_email(&email, account_id, account_name, conn)
+        .map_err(Error::DieselError)?;
+    for invitation in claimed {
+        debug!("Invitation {} to {} claimed by {}",
+               invitation.id, invitation.origin, account_name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(email: Option<&str>, email_verified: Option<bool>) -> OAuth2User {
+        OAuth2User { id: "1".to_string(),
+                     username: "bobo".to_string(),
+                     email: email.map(str::to_string),
+                     email_verified,
+                     groups: None }
+    }
+
+    #[test]
+    fn only_verified_emails_claim_invitations() {
+        assert_eq!(claimable_email(&user(Some("bobo@example.com"), Some(true))),
+                   Some("bobo@example.com"));
+        assert_eq!(claimable_email(&user(Some("bobo@example.com"), Some(false))),
+                   None);
+        assert_eq!(claimable_email(&user(Some("bobo@example.com"), None)), None);
+        assert_eq!(claimable_email(&user(None, Some(true))), None);
+    }
+
+    #[test]
+    fn emails_are_normalized() {
+        assert_eq!(normalize_email(" Bobo@Example.com "),
+                   Some("bobo@example.com".to_string()));
+        assert_eq!(normalize_email("bobo"), None);
+        assert_eq!(normalize_email("@example.com"), None);
+    }
+}
@@ -0,0 +1,282 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Origin signing key generation and scheduled rotation. A rotation creates a new key revision
+//! and starts the overlap window of the revisions it replaces; until the window closes,
+//! packages signed with them are still accepted for upload.
+
+use std::{thread,
+          time::Duration as StdDuration};
+
+use chrono::{Duration,
+             NaiveDateTime,
+             Utc};
+use diesel::{pg::PgConnection,
+             Connection};
+
+use crate::{bldr_core::access_token::{BUILDER_ACCOUNT_ID,
+                                      BUILDER_ACCOUNT_NAME},
+            config::ApiCfg,
+            db::{models::{keys::*,
+                          origin::Origin},
+                 DbPool},
+            hab_core::crypto::SigKeyPair};
+
+use crate::server::{error::{Error,
+                            Result},
+                    services::notify};
+
+const CHECK_INTERVAL_SECS: u64 = 60 * 60;
+const REVISION_ATTEMPTS: usize = 5;
+const REVISION_WAIT_MILLIS: u64 = 500;
+
+pub fn start(config: ApiCfg, db: DbPool) {
+    thread::Builder::new().name("key-rotation".to_string())
+                          .spawn(move || {
+                              loop {
+                                  if let Err(err) = rotate_due(&config, &db) {
+                                      warn!("Unable to rotate origin keys, err={}", err);
+                                  }
+                                  thread::sleep(StdDuration::from_secs(CHECK_INTERVAL_SECS));
+                              }
+                          })
+                          .expect("Unable to start key rotation thread");
+}
+
+fn rotate_due(config: &ApiCfg, db: &DbPool) -> Result<()> {
+    let conn = db.get_conn().map_err(Error::DbError)?;
+
+    for policy in OriginKeyRotation::list_enabled(&*conn).map_err(Error::DieselError)? {
+        // One origin failing to rotate should not hold up the rest
+        if let Err(err) = rotate_if_due(&policy, config.key_rotation_overlap_days, &*conn) {
+            warn!("Unable to rotate signing key for {}, err={}",
+                  policy.origin, err);
+        }
+    }
+    Ok(())
+}
+
+// Every API node runs the schedule, so the origin's rotation lock is taken first and whether
+// the key is due is decided under it. A node that finds the lock held leaves the origin to the
+// node holding it.
+fn rotate_if_due(policy: &OriginKeyRotation, overlap_days: u32, conn: &PgConnection) -> Result<()> {
+    let requester = RotationRequester { trigger:        KeyRotationTrigger::Schedule,
+                                        requester_id:   BUILDER_ACCOUNT_ID as i64,
+                                        requester_name: BUILDER_ACCOUNT_NAME, };
+    let rotation = conn.transaction::<_, Error, _>(|| {
+                           if !OriginKeyRotation::try_lock(&policy.origin, conn)? {
+                               return Ok(None);
+                           }
+                           let created_at =
+                               match OriginPublicSigningKey::latest(&policy.origin, conn) {
+                                   Ok(key) => key.created_at,
+                                   Err(diesel::result::Error::NotFound) => return Ok(None),
+                                   Err(err) => return Err(Error::DieselError(err)),
+                               };
+                           if !is_due(created_at, policy.interval_days, Utc::now().naive_utc()) {
+                               return Ok(None);
+                           }
+                           rotate_locked(&policy.origin, &requester, overlap_days, conn).map(Some)
+                       })?;
+
+    if let Some(rotation) = rotation {
+        notify_rotation(&policy.origin, &rotation, conn)?;
+    }
+    Ok(())
+}
+
+// Whether a key created at `created_at` has outlived the rotation interval
+fn is_due(created_at: Option<NaiveDateTime>, interval_days: i32, now: NaiveDateTime) -> bool {
+    match created_at {
+        Some(created_at) => created_at + Duration::days(i64::from(interval_days)) <= now,
+        None => true,
+    }
+}
+
+// Whether `revision` sorts after the latest stored revision. Revisions are timestamps to the
+// second, so a pair generated in the same second as the latest one would collide with it.
+fn is_newer(revision: &str, latest: Option<&str>) -> bool {
+    latest.map_or(true, |latest| revision > latest)
+}
+
+// Generate a key pair for `origin` whose revision is newer than any stored one, waiting out
+// the rest of the second when the latest revision was created in the current one
+fn generate_pair(origin: &str, conn: &PgConnection) -> Result<SigKeyPair> {
+    let latest = match OriginPublicSigningKey::latest(origin, conn) {
+        Ok(key) => Some(key.revision),
+        Err(diesel::result::Error::NotFound) => None,
+        Err(err) => return Err(Error::DieselError(err)),
+    };
+
+    for _ in 0..REVISION_ATTEMPTS {
+        let pair = SigKeyPair::generate_pair_for_origin(origin).map_err(Error::HabitatCore)?;
+        if is_newer(&pair.rev, latest.as_ref().map(String::as_str)) {
+            return Ok(pair);
+        }
+        thread::sleep(StdDuration::from_millis(REVISION_WAIT_MILLIS));
+    }
+    warn!("Unable to generate a signing key for {} newer than revision {:?}",
+          origin, latest);
+    Err(Error::Conflict)
+}
+
+/// Generate a new signing key pair for `origin` and store both halves, returning the new
+/// revision.
+pub fn create_signing_keys(origin: &str, owner_id: i64, conn: &PgConnection) -> Result<String> {
+    conn.transaction::<_, Error, _>(|| {
+            OriginKeyRotation::lock(origin, conn)?;
+            store_signing_keys(origin, owner_id, conn)
+        })
+}
+
+// Stores a new key pair of `origin`; the caller holds the origin's rotation lock
+fn store_signing_keys(origin: &str, owner_id: i64, conn: &PgConnection) -> Result<String> {
+    let pair = generate_pair(origin, conn)?;
+    let full_name = format!("{}-{}", origin, pair.rev);
+
+    let pk_body = pair.to_public_string()
+                      .map_err(Error::HabitatCore)?
+                      .into_bytes();
+    let new_pk = NewOriginPublicSigningKey { owner_id,
+                                             origin,
+                                             full_name: &full_name,
+                                             name: origin,
+                                             revision: &pair.rev,
+                                             body: &pk_body };
+    OriginPublicSigningKey::create(&new_pk, conn).map_err(Error::DieselError)?;
+
+    let sk_body = pair.to_secret_string()
+                      .map_err(Error::HabitatCore)?
+                      .into_bytes();
+    let new_sk = NewOriginPrivateSigningKey { owner_id,
+                                              origin,
+                                              full_name: &full_name,
+                                              name: origin,
+                                              revision: &pair.rev,
+                                              body: &sk_body };
+    OriginPrivateSigningKey::create(&new_sk, conn).map_err(Error::DieselError)?;
+
+    Ok(pair.rev)
+}
+
+/// Who asked for a rotation, and why
+pub struct RotationRequester<'a> {
+    pub trigger:        KeyRotationTrigger,
+    pub requester_id:   i64,
+    pub requester_name: &'a str,
+}
+
+// The outcome of a rotation, for notifying the origin owner once it has committed
+struct Rotation {
+    owner_id:   i64,
+    previous:   Option<String>,
+    revision:   String,
+    expires_at: NaiveDateTime,
+}
+
+/// Replace the signing key of `origin` with a new revision. Earlier revisions stay valid for
+/// `overlap_days`, the rotation is audited and the origin owner is notified.
+pub fn rotate(origin: &str,
+              requester: &RotationRequester,
+              overlap_days: u32,
+              conn: &PgConnection)
+              -> Result<String> {
+    let rotation = conn.transaction::<_, Error, _>(|| {
+                           OriginKeyRotation::lock(origin, conn)?;
+                           rotate_locked(origin, requester, overlap_days, conn)
+                       })?;
+    notify_rotation(origin, &rotation, conn)?;
+    Ok(rotation.revision)
+}
+
+// Rotates the key of `origin`; the caller holds the origin's rotation lock
+fn rotate_locked(origin: &str,
+                 requester: &RotationRequester,
+                 overlap_days: u32,
+                 conn: &PgConnection)
+                 -> Result<Rotation> {
+    let owner_id = Origin::get(origin, conn).map_err(Error::DieselError)?
+                                            .owner_id;
+    let previous = match OriginPublicSigningKey::latest(origin, conn) {
+        Ok(key) => Some(key.revision),
+        Err(diesel::result::Error::NotFound) => None,
+        Err(err) => return Err(Error::DieselError(err)),
+    };
+    let expires_at = (Utc::now() + Duration::days(i64::from(overlap_days))).naive_utc();
+
+    let revision = store_signing_keys(origin, owner_id, conn)?;
+    OriginPublicSigningKey::expire_previous(origin, &revision, expires_at, conn)?;
+    let audit = OriginKeyRotationAudit { origin,
+                                         previous_revision: previous.as_ref()
+                                                                    .map(String::as_str),
+                                         revision: &revision,
+                                         trigger: requester.trigger,
+                                         requester_id: requester.requester_id,
+                                         requester_name: requester.requester_name };
+    OriginKeyRotationAudit::audit(&audit, conn)?;
+
+    info!("Rotated signing key for {} to {}, previous={:?}, trigger={:?}",
+          origin, revision, previous, requester.trigger);
+
+    Ok(Rotation { owner_id,
+                  previous,
+                  revision,
+                  expires_at })
+}
+
+fn notify_rotation(origin: &str, rotation: &Rotation, conn: &PgConnection) -> Result<()> {
+    let message = match rotation.previous {
+        Some(ref previous) => {
+            format!("The signing key of origin {} was rotated to revision {}. Packages signed \
+                     with revision {} are accepted for upload until {} UTC.",
+                    origin,
+                    rotation.revision,
+                    previous,
+                    rotation.expires_at.format("%Y-%m-%d %H:%M:%S"))
+        }
+        None => {
+            format!("A signing key was generated for origin {}, revision {}.",
+                    origin, rotation.revision)
+        }
+    };
+    notify::notify_account(rotation.owner_id, notify::KEY_ROTATED, &message, conn).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime { NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap() }
+
+    #[test]
+    fn key_is_due_once_interval_has_passed() {
+        let created_at = Some(at("2019-01-01 12:00"));
+        assert!(!is_due(created_at, 30, at("2019-01-31 11:59")));
+        assert!(is_due(created_at, 30, at("2019-01-31 12:00")));
+        assert!(is_due(created_at, 30, at("2019-06-01 00:00")));
+    }
+
+    #[test]
+    fn key_of_unknown_age_is_due() {
+        assert!(is_due(None, 365, at("2019-01-01 00:00")));
+    }
+
+    #[test]
+    fn revisions_from_the_same_second_are_not_newer() {
+        assert!(is_newer("20190101120001", None));
+        assert!(is_newer("20190101120001", Some("20190101120000")));
+        assert!(!is_newer("20190101120000", Some("20190101120000")));
+        assert!(!is_newer("20190101115959", Some("20190101120000")));
+    }
+}
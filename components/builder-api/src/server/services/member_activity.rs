@@ -0,0 +1,209 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! What the members of an origin last did in it, for access reviews. Each kind of activity is
+//! read for all members at once: sign ins, uploads and promotions from the database, group
+//! submissions from the job server.
+
+use std::collections::HashMap;
+
+use chrono::{Duration,
+             NaiveDateTime};
+
+use crate::{bldr_core::timestamp::{self,
+                                   rfc3339_opt},
+            db::models::origin::OriginMemberActivity,
+            protocol::jobsrv::JobGroupSubmitter};
+
+const CSV_COLUMNS: [&str; 9] = ["account_id",
+                                "account_name",
+                                "role",
+                                "member_since",
+                                "last_authenticated_at",
+                                "last_upload_at",
+                                "last_promotion_at",
+                                "last_job_submitted_at",
+                                "last_active_at"];
+
+#[derive(Debug, Serialize)]
+pub struct MemberActivity {
+    pub account_id: String,
+    pub account_name: String,
+    pub role: String,
+    #[serde(with = "rfc3339_opt")]
+    pub member_since: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
+    pub last_authenticated_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
+    pub last_upload_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
+    pub last_promotion_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
+    pub last_job_submitted_at: Option<NaiveDateTime>,
+    /// The latest of the activities above, none for a member who did none of them
+    #[serde(with = "rfc3339_opt")]
+    pub last_active_at: Option<NaiveDateTime>,
+}
+
+/// The activity of each member, joining what the job server knows of group submissions to what
+/// the database knows of the rest. Every member is listed, active or not.
+pub fn collect(members: Vec<OriginMemberActivity>,
+               submitters: &[JobGroupSubmitter])
+               -> Vec<MemberActivity> {
+    let mut submitted = HashMap::new();
+    for submitter in submitters {
+        if let Some(at) = timestamp::parse(submitter.get_last_submitted_at()) {
+            submitted.insert(submitter.get_requester_id() as i64, at.naive_utc());
+        }
+    }
+
+    members.into_iter()
+           .map(|m| {
+               let last_job_submitted_at = submitted.get(&m.account_id).cloned();
+               let last_active_at = [m.last_authenticated_at,
+                                     m.last_upload_at,
+                                     m.last_promotion_at,
+                                     last_job_submitted_at].iter()
+                                                           .filter_map(|at| *at)
+                                                           .max();
+               MemberActivity { account_id: m.account_id.to_string(),
+                                account_name: m.account_name,
+                                role: m.role,
+                                member_since: m.member_since,
+                                last_authenticated_at: m.last_authenticated_at,
+                                last_upload_at: m.last_upload_at,
+                                last_promotion_at: m.last_promotion_at,
+                                last_job_submitted_at,
+                                last_active_at }
+           })
+           .collect()
+}
+
+/// Keeps the members who did nothing in the `days` before `now`
+pub fn stale(members: Vec<MemberActivity>, days: u32, now: NaiveDateTime) -> Vec<MemberActivity> {
+    let since = now - Duration::days(i64::from(days));
+    members.into_iter()
+           .filter(|m| m.last_active_at.map_or(true, |at| at < since))
+           .collect()
+}
+
+/// The report as CSV, one row per member under a header row. Absent times are empty fields.
+pub fn to_csv(members: &[MemberActivity]) -> String {
+    let mut csv = CSV_COLUMNS.join(",");
+    csv.push_str("\r\n");
+
+    for m in members {
+        let times = [m.member_since,
+                     m.last_authenticated_at,
+                     m.last_upload_at,
+                     m.last_promotion_at,
+                     m.last_job_submitted_at,
+                     m.last_active_at];
+        let mut fields = vec![csv_field(&m.account_id),
+                              csv_field(&m.account_name),
+                              csv_field(&m.role)];
+        fields.extend(times.iter().map(|at| csv_time(*at)));
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+fn csv_time(at: Option<NaiveDateTime>) -> String {
+    at.map_or_else(String::new, |at| timestamp::naive_to_rfc3339(&at))
+}
+
+// A field quoted as RFC 4180 requires when it holds a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains(|c| c == ',' || c == '"' || c == '\r' || c == '\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime { NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap() }
+
+    fn member(id: i64, name: &str) -> OriginMemberActivity {
+        OriginMemberActivity { account_id:            id,
+                               account_name:          name.to_string(),
+                               role:                  "member".to_string(),
+                               member_since:          Some(at("2019-01-01 00:00")),
+                               last_authenticated_at: None,
+                               last_upload_at:        None,
+                               last_promotion_at:     None, }
+    }
+
+    fn submitter(id: u64, submitted_at: &str) -> JobGroupSubmitter {
+        let mut submitter = JobGroupSubmitter::new();
+        submitter.set_requester_id(id);
+        submitter.set_last_submitted_at(submitted_at.to_string());
+        submitter
+    }
+
+    #[test]
+    fn members_without_activity_are_listed() {
+        let mut active = member(1, "alice");
+        active.last_authenticated_at = Some(at("2019-10-01 09:00"));
+        active.last_upload_at = Some(at("2019-10-03 12:00"));
+        let members = vec![active, member(2, "bob")];
+        let submitters = vec![submitter(1, "2019-10-02T08:00:00Z"),
+                              submitter(3, "2019-10-05T08:00:00Z")];
+
+        let activity = collect(members, &submitters);
+        assert_eq!(activity.len(), 2);
+        assert_eq!(activity[0].last_job_submitted_at,
+                   Some(at("2019-10-02 08:00")));
+        assert_eq!(activity[0].last_active_at, Some(at("2019-10-03 12:00")));
+        assert_eq!(activity[1].account_name, "bob");
+        assert_eq!(activity[1].last_job_submitted_at, None);
+        assert_eq!(activity[1].last_active_at, None);
+    }
+
+    #[test]
+    fn stale_members_did_nothing_within_the_days() {
+        let mut recent = member(1, "alice");
+        recent.last_promotion_at = Some(at("2019-10-01 00:00"));
+        let mut old = member(2, "bob");
+        old.last_authenticated_at = Some(at("2019-06-01 00:00"));
+        let activity = collect(vec![recent, old, member(3, "carol")], &[]);
+
+        let names: Vec<String> = stale(activity, 90, at("2019-10-21 00:00")).into_iter()
+                                                                            .map(|m| m.account_name)
+                                                                            .collect();
+        assert_eq!(names, vec!["bob", "carol"]);
+    }
+
+    #[test]
+    fn csv_has_a_row_per_member() {
+        let mut active = member(1, "alice");
+        active.role = "owner".to_string();
+        active.last_upload_at = Some(at("2019-10-03 12:00"));
+        let activity = collect(vec![active, member(2, "bob,\"jr\"")], &[]);
+
+        let csv = to_csv(&activity);
+        let rows: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(rows[0], CSV_COLUMNS.join(","));
+        assert_eq!(rows[1],
+                   "1,alice,owner,2019-01-01T00:00:00.000000Z,,2019-10-03T12:00:00.000000Z,,,\
+                    2019-10-03T12:00:00.000000Z");
+        assert_eq!(rows[2],
+                   "2,\"bob,\"\"jr\"\"\",member,2019-01-01T00:00:00.000000Z,,,,,");
+        assert_eq!(rows[3], "");
+    }
+}
@@ -183,6 +183,24 @@ impl MemcacheClient {
         };
     }
 
+    /// Claims the refresh of the OAuth token of a session for `ttl` seconds, false when another
+    /// request already has
+    pub fn claim_session_refresh(&mut self, token: &str, ttl: u32) -> bool {
+        match self.cli.add(&refresh_key(token), 1, ttl) {
+            Ok(_) => true,
+            Err(e) => {
+                trace!("Refresh of session {} not claimed: {}", token, e);
+                false
+            }
+        }
+    }
+
+    pub fn release_session_refresh(&mut self, token: &str) {
+        if let Err(e) = self.cli.delete(&refresh_key(token)) {
+            warn!("Failed to release refresh of session {}: {}", token, e);
+        }
+    }
+
     pub fn set_origin_member(&mut self, origin: &str, account_id: u64, val: bool) {
         let key = format!("member:{}/{}", origin, account_id);
 
@@ -284,3 +302,5 @@ fn hash_key(key: &str) -> String {
     hasher.input(key);
     format!("{:02x}", hasher.result())
 }
+
+fn refresh_key(token: &str) -> String { format!("refresh:{}", hash_key(token)) }
@@ -22,6 +22,9 @@ pub enum Counter {
     GetPackage,
     GetChannelPackage,
     GitHubEvent,
+    GitHubSignatureFailure,
+    GitHubRedelivery,
+    WebhookEvent,
     RouteMessage,
     SearchPackages,
     UploadRequests,
@@ -30,6 +33,7 @@ pub enum Counter {
     DownloadRequests,
     UploadFailures,
     AtomicChannelRequests,
+    DownloadCacheEvictions,
 }
 
 impl metrics::CounterMetric for Counter {}
@@ -40,6 +44,9 @@ impl metrics::Metric for Counter {
             Counter::GetPackage => "get-package".into(),
             Counter::GetChannelPackage => "get-channel-package".into(),
             Counter::GitHubEvent => "github.event".into(),
+            Counter::GitHubSignatureFailure => "github.signature-failure".into(),
+            Counter::GitHubRedelivery => "github.redelivery".into(),
+            Counter::WebhookEvent => "webhook.event".into(),
             Counter::RouteMessage => "route-message".into(),
             Counter::SearchPackages => "search-packages".into(),
             Counter::UploadRequests => "upload-packages".into(),
@@ -48,6 +55,25 @@ impl metrics::Metric for Counter {
             Counter::DownloadRequests => "download-packages".into(),
             Counter::UploadFailures => "upload-failures".into(),
             Counter::AtomicChannelRequests => "channel-to-channel".into(),
+            Counter::DownloadCacheEvictions => "download-cache.evictions".into(),
+        }
+    }
+}
+
+pub enum Gauge {
+    DownloadCacheHitRatio,
+    DownloadCacheBytesServed,
+    DownloadCacheBytes,
+}
+
+impl metrics::GaugeMetric for Gauge {}
+
+impl metrics::Metric for Gauge {
+    fn id(&self) -> Cow<'static, str> {
+        match *self {
+            Gauge::DownloadCacheHitRatio => "download-cache.hit-ratio".into(),
+            Gauge::DownloadCacheBytesServed => "download-cache.bytes-served".into(),
+            Gauge::DownloadCacheBytes => "download-cache.bytes".into(),
         }
     }
 }
@@ -1,4 +1,31 @@
+pub mod account_export;
+pub mod announcements;
+pub mod artifact_encryption;
+pub mod build_info;
+pub mod delivery_log;
+pub mod dep_check;
+pub mod dep_tree;
+pub mod download_cache;
+pub mod ephemeral_channels;
+pub mod feed;
 pub mod github;
+pub mod ingest;
+pub mod install_stats;
+pub mod invitations;
+pub mod key_rotation;
+pub mod member_activity;
 pub mod memcache;
 pub mod metrics;
+pub mod notify;
+pub mod origin_groups;
+pub mod rate_limit;
+pub mod revocations;
 pub mod s3;
+pub mod security_events;
+pub mod status;
+pub mod target_matrix;
+pub mod token_expiry;
+pub mod upload_policy;
+pub mod upload_spool;
+pub mod upstream;
+pub mod webhook;
@@ -0,0 +1,58 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Notifications addressed to Builder accounts, or to email addresses that have no account
+//! yet. Email notifications are queued for delivery by the mail relay.
+
+use diesel::pg::PgConnection;
+
+use crate::db::models::notifications::{EmailNotification,
+                                       NewEmailNotification,
+                                       NewNotification,
+                                       Notification};
+
+use crate::server::error::{Error,
+                           Result};
+
+pub const TOKEN_EXPIRING: &str = "token-expiring";
+pub const ORIGIN_INVITATION: &str = "origin-invitation";
+pub const KEY_ROTATED: &str = "key-rotated";
+pub const SECURITY_EVENT: &str = "security-event";
+pub const PROMOTION_VERIFICATION_FAILED: &str = "promotion-verification-failed";
+pub const ACCOUNT_EXPORT: &str = "account-export";
+
+pub fn notify_account(account_id: i64,
+                      category: &str,
+                      message: &str,
+                      conn: &PgConnection)
+                      -> Result<Notification> {
+    debug!("Notifying account {}: [{}] {}",
+           account_id, category, message);
+    Notification::create(&NewNotification { account_id,
+                                            category,
+                                            message },
+                         conn).map_err(Error::DieselError)
+}
+
+pub fn notify_email(email: &str,
+                    category: &str,
+                    message: &str,
+                    conn: &PgConnection)
+                    -> Result<EmailNotification> {
+    debug!("Notifying {}: [{}] {}", email, category, message);
+    EmailNotification::create(&NewEmailNotification { email,
+                                                      category,
+                                                      message },
+                              conn).map_err(Error::DieselError)
+}
@@ -0,0 +1,207 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Origin membership kept in step with the groups the OAuth provider puts users in. The
+//! configured `group_origins` name the origins each group's members belong to; at sign in a
+//! user is added to the origins of their groups and removed from the other mapped origins.
+//! Origins no group maps to are left to invitations, and owners are never removed. Each change is
+//! recorded on the account's security events.
+
+use std::collections::{BTreeSet,
+                       HashSet};
+
+use diesel::{pg::PgConnection,
+             result::Error::NotFound,
+             Connection};
+use oauth_client::types::OAuth2User;
+
+use crate::{config::GroupOriginsCfg,
+            db::models::{account::{Account,
+                                   AccountSecurityEventKind,
+                                   NewAccountSecurityEvent},
+                         origin::{AccountMembership,
+                                  Origin,
+                                  OriginMember}}};
+
+use crate::server::{error::{Error,
+                            Result},
+                    services::security_events};
+
+/// The membership changes of a sign in
+#[derive(Debug, Default, PartialEq)]
+pub struct Changes {
+    pub added:   Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// What the user in `groups` must join and leave, given the origins they are a `member` of
+/// and those they `own`
+pub fn changes(mappings: &[GroupOriginsCfg],
+               groups: &[String],
+               member: &HashSet<String>,
+               own: &HashSet<String>)
+               -> Changes {
+    let mut mapped = BTreeSet::new();
+    let mut granted = BTreeSet::new();
+    for mapping in mappings {
+        mapped.extend(mapping.origins.iter());
+        if groups.contains(&mapping.group) {
+            granted.extend(mapping.origins.iter());
+        }
+    }
+
+    Changes { added:   granted.iter()
+                              .filter(|origin| !member.contains(**origin))
+                              .map(|origin| origin.to_string())
+                              .collect(),
+              removed: mapped.difference(&granted)
+                             .filter(|origin| member.contains(**origin) && !own.contains(**origin))
+                             .map(|origin| origin.to_string())
+                             .collect(), }
+}
+
+/// Keeps the membership of the account signed in as `user` in step with the user's groups.
+/// Nothing changes unless groups are mapped to origins and the provider read the user's groups.
+pub fn sign_in(mappings: &[GroupOriginsCfg],
+               account: &Account,
+               user: &OAuth2User,
+               conn: &PgConnection)
+               -> Result<Changes> {
+    match user.groups {
+        Some(ref groups) if !mappings.is_empty() => {
+            sync(mappings, account.id, &account.name, groups, conn)
+        }
+        _ => Ok(Changes::default()),
+    }
+}
+
+/// Adds the account to the origins of its groups and removes it from the other mapped origins.
+/// Origins that don't exist are skipped.
+pub fn sync(mappings: &[GroupOriginsCfg],
+            account_id: i64,
+            account_name: &str,
+            groups: &[String],
+            conn: &PgConnection)
+            -> Result<Changes> {
+    let memberships = AccountMembership::list(account_id, conn).map_err(Error::DieselError)?;
+    let member = memberships.iter().map(|m| m.origin.clone()).collect();
+    let own = memberships.iter()
+                         .filter(|m| m.role == "owner")
+                         .map(|m| m.origin.clone())
+                         .collect();
+
+    let mut changes = changes(mappings, groups, &member, &own);
+    changes.added.retain(|origin| {
+                     match Origin::get(origin, conn) {
+                         Ok(_) => true,
+                         Err(NotFound) => {
+                             warn!("Origin {} of a group mapping does not exist", origin);
+                             false
+                         }
+                         Err(err) => {
+                             warn!("Unable to read origin {}, err={}", origin, err);
+                             false
+                         }
+                     }
+                 });
+
+    for origin in &changes.added {
+        conn.transaction::<_, Error, _>(|| {
+                OriginMember::add(origin, account_id, conn)?;
+                record(account_id,
+                       AccountSecurityEventKind::OriginMemberAdded,
+                       origin,
+                       conn)
+            })?;
+        info!("Added {} to origin {} for their provider groups",
+              account_name, origin);
+    }
+    for origin in &changes.removed {
+        conn.transaction::<_, Error, _>(|| {
+                OriginMember::delete(origin, account_name, conn)?;
+                record(account_id,
+                       AccountSecurityEventKind::OriginMemberRemoved,
+                       origin,
+                       conn)
+            })?;
+        info!("Removed {} from origin {}, they left its provider groups",
+              account_name, origin);
+    }
+    Ok(changes)
+}
+
+// Records a change of the account's membership of `origin`. No one made it, so the event has no
+// actor.
+fn record(account_id: i64,
+          kind: AccountSecurityEventKind,
+          origin: &str,
+          conn: &PgConnection)
+          -> Result<()> {
+    let mut event = NewAccountSecurityEvent::new(account_id, kind);
+    event.origin = Some(origin);
+    security_events::record(&event, conn).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mappings() -> Vec<GroupOriginsCfg> {
+        vec![GroupOriginsCfg { group:   "builder-core".to_string(),
+                               origins: vec!["core".to_string(), "core-testing".to_string()], },
+             GroupOriginsCfg { group:   "acme-devs".to_string(),
+                               origins: vec!["acme".to_string()], },]
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> { values.iter().map(|v| v.to_string()).collect() }
+
+    fn set(values: &[&str]) -> HashSet<String> { strings(values).into_iter().collect() }
+
+    #[test]
+    fn the_origins_of_the_users_groups_are_joined() {
+        let changes = changes(&mappings(),
+                              &strings(&["Everyone", "builder-core"]),
+                              &set(&["core"]),
+                              &set(&[]));
+        assert_eq!(changes,
+                   Changes { added:   strings(&["core-testing"]),
+                             removed: vec![], });
+    }
+
+    #[test]
+    fn the_mapped_origins_of_groups_left_are_left() {
+        let changes = changes(&mappings(),
+                              &strings(&["acme-devs"]),
+                              &set(&["acme", "core", "core-testing", "personal"]),
+                              &set(&["core-testing"]));
+        // Origins no group maps to, and those the user owns, are kept
+        assert_eq!(changes,
+                   Changes { added:   vec![],
+                             removed: strings(&["core"]), });
+    }
+
+    #[test]
+    fn an_origin_of_any_of_the_users_groups_is_kept() {
+        let mut mappings = mappings();
+        mappings.push(GroupOriginsCfg { group:   "release".to_string(),
+                                        origins: vec!["core".to_string()], });
+        let changes = changes(&mappings,
+                              &strings(&["release"]),
+                              &set(&["core", "core-testing"]),
+                              &set(&[]));
+        assert_eq!(changes,
+                   Changes { added:   vec![],
+                             removed: strings(&["core-testing"]), });
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fixed window request limits, counted per caller and shared by every worker thread.
+
+use std::{collections::HashMap,
+          sync::Mutex,
+          time::{Duration,
+                 Instant}};
+
+pub struct RateLimiter {
+    limit:   u32,
+    window:  Duration,
+    callers: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    /// Allows each caller `limit` requests per `window`. A limit of 0 allows everything.
+    pub fn new(limit: u32, window: Duration) -> Self {
+        RateLimiter { limit,
+                      window,
+                      callers: Mutex::new(HashMap::new()) }
+    }
+
+    /// Counts a request from `caller`, returning false when it is over the limit.
+    pub fn check(&self, caller: &str) -> bool { self.check_at(caller, Instant::now()) }
+
+    fn check_at(&self, caller: &str, now: Instant) -> bool {
+        if self.limit == 0 {
+            return true;
+        }
+
+        let mut callers = match self.callers.lock() {
+            Ok(callers) => callers,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let window = self.window;
+        let (started, count) = callers.entry(caller.to_string()).or_insert((now, 0));
+        if now.duration_since(*started) >= window {
+            *started = now;
+            *count = 0;
+        }
+        if *count >= self.limit {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_up_to_limit_within_window() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+        assert!(limiter.check_at("mirror", now));
+        assert!(limiter.check_at("mirror", now + Duration::from_secs(1)));
+        assert!(!limiter.check_at("mirror", now + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn window_resets() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        let now = Instant::now();
+        assert!(limiter.check_at("mirror", now));
+        assert!(!limiter.check_at("mirror", now + Duration::from_secs(59)));
+        assert!(limiter.check_at("mirror", now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn callers_are_counted_separately() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        let now = Instant::now();
+        assert!(limiter.check_at("mirror", now));
+        assert!(limiter.check_at("scanner", now));
+        assert!(!limiter.check_at("mirror", now));
+    }
+
+    #[test]
+    fn zero_limit_is_unlimited() {
+        let limiter = RateLimiter::new(0, Duration::from_secs(60));
+        let now = Instant::now();
+        for _ in 0..1000 {
+            assert!(limiter.check_at("mirror", now));
+        }
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Revocations of the provider tokens of ended sessions. Logouts queue the token and it is
+//! revoked in the background, so that a provider that is slow or being retried does not hold up
+//! the response.
+
+use std::{collections::VecDeque,
+          sync::{Arc,
+                 Condvar,
+                 Mutex,
+                 MutexGuard,
+                 PoisonError},
+          thread};
+
+use oauth_client::client::OAuth2Client;
+
+/// Revocations queued beyond these are dropped, leaving their tokens to expire
+const MAX_PENDING: usize = 10_000;
+
+struct Revocation {
+    token:   String,
+    account: String,
+}
+
+/// The tokens waiting to be revoked, shared by every worker
+#[derive(Default)]
+pub struct Revocations {
+    pending: Mutex<VecDeque<Revocation>>,
+    queued:  Condvar,
+}
+
+impl Revocations {
+    pub fn new() -> Self { Revocations::default() }
+
+    /// Queues the token of `account` to be revoked, returning false when the queue is full
+    pub fn queue(&self, token: &str, account: &str) -> bool {
+        let mut pending = self.pending();
+        if pending.len() >= MAX_PENDING {
+            return false;
+        }
+        pending.push_back(Revocation { token:   token.to_string(),
+                                       account: account.to_string(), });
+        self.queued.notify_one();
+        true
+    }
+
+    // Waits for the next queued revocation
+    fn next(&self) -> Revocation {
+        let mut pending = self.pending();
+        loop {
+            if let Some(revocation) = pending.pop_front() {
+                return revocation;
+            }
+            pending = self.queued
+                          .wait(pending)
+                          .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    // A panic while the lock was held leaves the queue whole, so a poisoned lock is used as is
+    fn pending(&self) -> MutexGuard<VecDeque<Revocation>> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub fn start(revocations: Arc<Revocations>, oauth: Arc<OAuth2Client>) {
+    thread::Builder::new().name("oauth-revocations".to_string())
+                          .spawn(move || {
+                              loop {
+                                  let revocation = revocations.next();
+                                  // A provider failing to revoke it leaves the token to expire
+                                  if let Err(err) = oauth.revoke(&revocation.token) {
+                                      warn!("Failed to revoke the {} token of {}, err={}",
+                                            oauth.config.provider, revocation.account, err);
+                                  }
+                              }
+                          })
+                          .expect("Unable to start oauth revocations thread");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn revocations_are_taken_in_order_until_the_queue_is_full() {
+        let revocations = Revocations::new();
+        assert!(revocations.queue("t1", "bobo"));
+        assert!(revocations.queue("t2", "mystique"));
+        assert_eq!(revocations.next().token, "t1");
+        assert_eq!(revocations.next().account, "mystique");
+
+        for n in 0..MAX_PENDING {
+            assert!(revocations.queue(&n.to_string(), "bobo"));
+        }
+        assert!(!revocations.queue("dropped", "bobo"));
+    }
+}
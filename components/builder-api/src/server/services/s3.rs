@@ -26,22 +26,27 @@
 //! ID and a secret access key.
 use std::{fmt::Display,
           fs::File,
-          io::{BufRead,
+          io::{self,
+               BufRead,
                BufReader,
                Read,
                Write},
           path::PathBuf,
-          str::FromStr};
+          str::FromStr,
+          time::Duration};
 
 use futures::{Future,
               Stream};
 use time::PreciseTime;
 
-use rusoto_s3::{CompleteMultipartUploadRequest,
+use rusoto_s3::{util::{PreSignedRequest,
+                       PreSignedRequestOption},
+                CompleteMultipartUploadRequest,
                 CompletedMultipartUpload,
                 CompletedPart,
                 CreateBucketRequest,
                 CreateMultipartUploadRequest,
+                DeleteObjectRequest,
                 GetObjectRequest,
                 HeadObjectRequest,
                 PutObjectRequest,
@@ -58,7 +63,8 @@ use crate::{bldr_core::metrics::CounterMetric,
             hab_core::package::{PackageArchive,
                                 PackageIdent,
                                 PackageTarget},
-            rusoto::{credential::StaticProvider,
+            rusoto::{credential::{AwsCredentials,
+                                  StaticProvider},
                      Region},
             server::error::{Error,
                             Result}};
@@ -69,8 +75,10 @@ use crate::{bldr_core::metrics::CounterMetric,
 const MINLIMIT: usize = 10240 * 1024;
 
 pub struct S3Handler {
-    client: S3Client,
-    bucket: String,
+    client:      S3Client,
+    bucket:      String,
+    region:      Region,
+    credentials: AwsCredentials,
 }
 
 impl S3Handler {
@@ -87,22 +95,26 @@ impl S3Handler {
         };
         let aws_id = config.key_id;
         let aws_secret = config.secret_key;
+        // Kept to presign download links, which are signed locally
+        let credentials = AwsCredentials::new(aws_id.clone(), aws_secret.clone(), None, None);
         let cred_provider = StaticProvider::new_minimal(aws_id, aws_secret);
         let http_client = match HttpClient::new() {
             Ok(client) => client,
             Err(err) => panic!("Unable to create Rusoto http client, err = {}", err),
         };
-        let client = S3Client::new_with(http_client, cred_provider, region);
+        let client = S3Client::new_with(http_client, cred_provider, region.clone());
         let bucket = config.bucket_name;
 
-        S3Handler { client, bucket }
+        S3Handler { client,
+                    bucket,
+                    region,
+                    credentials }
     }
 
     // This function checks whether or not the
     // configured bucket exists in the configured
     // backend.
-    #[allow(dead_code)]
-    fn bucket_exists(&self) -> Result<bool> {
+    pub fn bucket_exists(&self) -> Result<bool> {
         let artifactbucket = self.bucket.to_owned();
         match self.client.list_buckets().sync() {
             Ok(bucket_list) => {
@@ -186,7 +198,6 @@ impl S3Handler {
                     ident: &PackageIdent,
                     target: PackageTarget)
                     -> Result<PackageArchive> {
-        Counter::DownloadRequests.increment();
         let mut request = GetObjectRequest::default();
         let key = s3_key(ident, target)?;
         request.bucket = self.bucket.to_owned();
@@ -212,6 +223,92 @@ impl S3Handler {
         }
     }
 
+    /// The size of the stored artifact, read without fetching it
+    pub fn size(&self, ident: &PackageIdent, target: PackageTarget) -> Result<u64> {
+        let mut request = HeadObjectRequest::default();
+        request.bucket = self.bucket.clone();
+        request.key = s3_key(ident, target)?;
+
+        match self.client.head_object(request).sync() {
+            Ok(object) => {
+                match object.content_length {
+                    Some(len) if len >= 0 => Ok(len as u64),
+                    _ => {
+                        let msg = format!("no length for {}", ident);
+                        Err(Error::IO(io::Error::new(io::ErrorKind::InvalidData, msg)))
+                    }
+                }
+            }
+            Err(e) => Err(Error::HeadObject(e)),
+        }
+    }
+
+    /// The bytes `first` to `last`, inclusive, of the stored artifact
+    pub fn download_range(&self,
+                          ident: &PackageIdent,
+                          target: PackageTarget,
+                          first: u64,
+                          last: u64)
+                          -> Result<Vec<u8>> {
+        let mut request = GetObjectRequest::default();
+        request.bucket = self.bucket.to_owned();
+        request.key = s3_key(ident, target)?;
+        request.range = Some(format!("bytes={}-{}", first, last));
+
+        let body = match self.client.get_object(request).sync() {
+            Ok(response) => response.body,
+            Err(e) => {
+                warn!("Failed to retrieve object range from S3, ident={}: {:?}",
+                      ident, e);
+                return Err(Error::PackageDownload(e));
+            }
+        };
+        match body {
+            Some(body) => body.concat2().wait().map_err(Error::IO),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Stores an object that is not a package, such as an account data export
+    pub fn put_object(&self, key: &str, body: Vec<u8>, content_type: &str) -> Result<()> {
+        let mut request = PutObjectRequest::default();
+        request.bucket = self.bucket.clone();
+        request.key = key.to_string();
+        request.content_type = Some(content_type.to_string());
+        request.body = Some(body.into());
+
+        match self.client.put_object(request).sync() {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                warn!("Failed to store object {}: {:?}", key, e);
+                Err(Error::ObjectUpload(e))
+            }
+        }
+    }
+
+    pub fn delete_object(&self, key: &str) -> Result<()> {
+        let mut request = DeleteObjectRequest::default();
+        request.bucket = self.bucket.clone();
+        request.key = key.to_string();
+
+        match self.client.delete_object(request).sync() {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                warn!("Failed to delete object {}: {:?}", key, e);
+                Err(Error::ObjectDelete(e))
+            }
+        }
+    }
+
+    /// A link that downloads an object without credentials until it expires
+    pub fn presigned_url(&self, key: &str, expires_in: Duration) -> String {
+        let mut request = GetObjectRequest::default();
+        request.bucket = self.bucket.clone();
+        request.key = key.to_string();
+        let option = PreSignedRequestOption { expires_in };
+        request.get_presigned_url(&self.region, &self.credentials, &option)
+    }
+
     fn single_upload<P: Into<PathBuf>>(&self, key: &str, hart: File, path_attr: &P) -> Result<()>
         where P: Display
     {
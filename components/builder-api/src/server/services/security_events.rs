@@ -0,0 +1,325 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Security relevant events on accounts, recorded where they happen and listed to the account
+//! owner. High severity events are also sent as notifications unless the owner turned them
+//! off. Events, and known addresses unused, past the retention period are purged in the
+//! background.
+
+use std::{collections::HashMap,
+          net::{IpAddr,
+                SocketAddr},
+          sync::Mutex,
+          thread,
+          time::{Duration as StdDuration,
+                 Instant}};
+
+use actix_web::dev::RequestHead;
+use chrono::{Duration,
+             NaiveDateTime,
+             Utc};
+use diesel::pg::PgConnection;
+
+use crate::{config::ApiCfg,
+            db::{models::account::*,
+                 DbPool}};
+
+use crate::server::{error::{Error,
+                            Result},
+                    services::notify};
+
+const PURGE_INTERVAL_SECS: u64 = 24 * 60 * 60;
+// Addresses remembered per account, the least recently used are forgotten past this
+const MAX_KNOWN_IPS: i64 = 50;
+// New address events recorded per account per day, so a leaked token used from many
+// addresses does not flood the account's events and notifications
+const MAX_NEW_IP_EVENTS_PER_DAY: i64 = 10;
+// Addresses confirmed known, cached before they are checked against the database again
+const MAX_CACHED_IPS: usize = 10_000;
+const CACHED_IP_TTL_SECS: u64 = 60 * 60;
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+pub fn start(config: ApiCfg, db: DbPool) {
+    if config.security_retention_days == 0 {
+        info!("Account security event retention disabled");
+        return;
+    }
+
+    thread::Builder::new().name("security-events".to_string())
+                          .spawn(move || {
+                              loop {
+                                  if let Err(err) = purge(&config, &db) {
+                                      warn!("Unable to purge account security events, err={}", err);
+                                  }
+                                  thread::sleep(StdDuration::from_secs(PURGE_INTERVAL_SECS));
+                              }
+                          })
+                          .expect("Unable to start security events thread");
+}
+
+fn purge(config: &ApiCfg, db: &DbPool) -> Result<()> {
+    let conn = db.get_conn().map_err(Error::DbError)?;
+    let retention = Duration::days(i64::from(config.security_retention_days));
+    let before = (Utc::now() - retention).naive_utc();
+    let purged = AccountSecurityEvent::purge(before, &*conn).map_err(Error::DieselError)?;
+    let forgotten = AccountKnownIp::purge(before, &*conn).map_err(Error::DieselError)?;
+    debug!("Purged {} account security events and {} known addresses",
+           purged, forgotten);
+    Ok(())
+}
+
+/// Record a security event and notify the account of it when it is of high severity.
+pub fn record(event: &NewAccountSecurityEvent,
+              conn: &PgConnection)
+              -> Result<AccountSecurityEvent> {
+    let event = AccountSecurityEvent::create(event, conn).map_err(Error::DieselError)?;
+    info!("Security event {:?} on account {}, ip={:?}, actor={:?}",
+          event.kind, event.account_id, event.ip, event.actor_name);
+
+    if let Some(message) = notice(&event) {
+        let account = Account::get_by_id(event.account_id, conn).map_err(Error::DieselError)?;
+        if account.security_notifications {
+            notify::notify_account(event.account_id, notify::SECURITY_EVENT, &message, conn)?;
+        }
+    }
+    Ok(event)
+}
+
+// The notification for a high severity event, None for the others
+fn notice(event: &AccountSecurityEvent) -> Option<String> {
+    let ip = event.ip
+                  .as_ref()
+                  .map_or("an unknown address", String::as_str);
+    match event.kind {
+        AccountSecurityEventKind::TokenCreated => {
+            Some(format!("A personal access token was created for your account from {}. If this \
+                          was not you, revoke it from your profile.",
+                         ip))
+        }
+        AccountSecurityEventKind::TokenNewIp => {
+            Some(format!("Your personal access token was used from a new address, {}. If this \
+                          was not you, revoke it from your profile.",
+                         ip))
+        }
+        AccountSecurityEventKind::OriginMemberAdded
+        | AccountSecurityEventKind::OriginMemberRemoved => None,
+    }
+}
+
+/// The address a request came from. The `X-Forwarded-For` header is only believed as far
+/// back as it was added by trusted proxies, any address before that could have been sent by
+/// the client itself.
+pub fn client_ip(head: &RequestHead, trusted_proxies: &[IpAddr]) -> Option<String> {
+    let peer = head.peer_addr?.ip();
+    let forwarded_for = head.headers()
+                            .get_all(X_FORWARDED_FOR)
+                            .filter_map(|value| value.to_str().ok())
+                            .flat_map(|value| value.split(','))
+                            .collect::<Vec<_>>();
+    Some(resolve(peer, &forwarded_for, trusted_proxies).to_string())
+}
+
+// Walks the forwarded addresses from the nearest hop back for as long as they were added by a
+// trusted proxy
+fn resolve(peer: IpAddr, forwarded_for: &[&str], trusted_proxies: &[IpAddr]) -> IpAddr {
+    let mut addr = peer;
+    for hop in forwarded_for.iter().rev() {
+        if !trusted_proxies.contains(&addr) {
+            break;
+        }
+        match parse_addr(hop.trim()) {
+            Some(hop) => addr = hop,
+            None => break,
+        }
+    }
+    addr
+}
+
+fn parse_addr(addr: &str) -> Option<IpAddr> {
+    addr.parse::<IpAddr>()
+        .ok()
+        .or_else(|| addr.parse::<SocketAddr>().ok().map(|a| a.ip()))
+}
+
+/// The addresses each account's personal access token has been used from, shared by every
+/// worker. An address confirmed known is cached for a while, after that the next use checks
+/// it against the database again and notes when it was last used there.
+pub struct KnownIps {
+    learning_days: u32,
+    confirmed:     Mutex<HashMap<(i64, String), Instant>>,
+}
+
+impl KnownIps {
+    pub fn new(learning_days: u32) -> Self {
+        KnownIps { learning_days,
+                   confirmed: Mutex::new(HashMap::new()) }
+    }
+
+    /// Note a use of the account's token from `ip`, recording a security event the first time
+    /// an address is seen. Addresses seen while the account is still being learned, that is
+    /// within the learning period of its first token use, are remembered silently.
+    pub fn check(&self, account_id: i64, ip: &str, db: &DbPool) -> Result<()> {
+        if self.is_confirmed(account_id, ip, Instant::now()) {
+            return Ok(());
+        }
+
+        let conn = db.get_conn().map_err(Error::DbError)?;
+        let known = AccountKnownIp::list(account_id, &*conn).map_err(Error::DieselError)?;
+        if known.iter().any(|k| k.ip == ip) {
+            AccountKnownIp::touch(account_id, ip, &*conn).map_err(Error::DieselError)?;
+            self.confirm(account_id, ip, Instant::now());
+            return Ok(());
+        }
+
+        let first_seen_at = known.iter().filter_map(|k| k.first_seen_at).min();
+        let learning = is_learning(first_seen_at, self.learning_days, Utc::now().naive_utc());
+        let added = AccountKnownIp::add(account_id, ip, &*conn).map_err(Error::DieselError)?;
+        AccountKnownIp::trim(account_id, MAX_KNOWN_IPS, &*conn).map_err(Error::DieselError)?;
+        self.confirm(account_id, ip, Instant::now());
+
+        if added && !learning {
+            record_new_ip(account_id, ip, &*conn)?;
+        }
+        Ok(())
+    }
+
+    fn is_confirmed(&self, account_id: i64, ip: &str, now: Instant) -> bool {
+        let confirmed = match self.confirmed.lock() {
+            Ok(confirmed) => confirmed,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        confirmed.get(&(account_id, ip.to_string()))
+                 .map_or(false, |at| is_fresh(*at, now))
+    }
+
+    fn confirm(&self, account_id: i64, ip: &str, now: Instant) {
+        let mut confirmed = match self.confirmed.lock() {
+            Ok(confirmed) => confirmed,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if confirmed.len() >= MAX_CACHED_IPS {
+            confirmed.retain(|_, at| is_fresh(*at, now));
+            if confirmed.len() >= MAX_CACHED_IPS {
+                confirmed.clear();
+            }
+        }
+        confirmed.insert((account_id, ip.to_string()), now);
+    }
+}
+
+fn is_fresh(confirmed_at: Instant, now: Instant) -> bool {
+    confirmed_at + StdDuration::from_secs(CACHED_IP_TTL_SECS) > now
+}
+
+fn record_new_ip(account_id: i64, ip: &str, conn: &PgConnection) -> Result<()> {
+    let kind = AccountSecurityEventKind::TokenNewIp;
+    let since = (Utc::now() - Duration::days(1)).naive_utc();
+    let recent = AccountSecurityEvent::count_since(account_id, kind, since, conn)
+        .map_err(Error::DieselError)?;
+    if recent >= MAX_NEW_IP_EVENTS_PER_DAY {
+        warn!("Not recording the use of account {}'s token from {}, {} new addresses were \
+               recorded within a day",
+              account_id, ip, recent);
+        return Ok(());
+    }
+
+    // Accounts hold a single token at a time
+    let tokens = AccountToken::list(account_id as u64, conn).map_err(Error::DieselError)?;
+    let mut event = NewAccountSecurityEvent::new(account_id, kind);
+    event.token_id = tokens.first().map(|t| t.id);
+    event.ip = Some(ip);
+    record(&event, conn)?;
+    Ok(())
+}
+
+// Whether an account whose token was first used at `first_seen_at` is still having its
+// addresses learned. An account with no known address is, so the first use sets a baseline.
+fn is_learning(first_seen_at: Option<NaiveDateTime>,
+               learning_days: u32,
+               now: NaiveDateTime)
+               -> bool {
+    match first_seen_at {
+        Some(first_seen_at) => first_seen_at + Duration::days(i64::from(learning_days)) > now,
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime { NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap() }
+
+    #[test]
+    fn first_address_is_learned() {
+        assert!(is_learning(None, 0, at("2019-09-02 12:00")));
+        assert!(is_learning(None, 7, at("2019-09-02 12:00")));
+    }
+
+    #[test]
+    fn addresses_are_learned_within_learning_period() {
+        let first_seen_at = Some(at("2019-09-02 12:00"));
+        assert!(is_learning(first_seen_at, 7, at("2019-09-09 11:59")));
+        assert!(!is_learning(first_seen_at, 7, at("2019-09-09 12:00")));
+        assert!(!is_learning(first_seen_at, 0, at("2019-09-02 12:00")));
+    }
+
+    fn ip(addr: &str) -> IpAddr { addr.parse().unwrap() }
+
+    #[test]
+    fn forwarded_addresses_are_ignored_from_untrusted_peers() {
+        let trusted = vec![ip("127.0.0.1")];
+        assert_eq!(resolve(ip("203.0.113.7"), &["198.51.100.1"], &trusted),
+                   ip("203.0.113.7"));
+        assert_eq!(resolve(ip("203.0.113.7"), &[], &trusted), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn forwarded_addresses_are_believed_back_to_the_first_untrusted_hop() {
+        let trusted = vec![ip("127.0.0.1"), ip("10.0.0.2")];
+        assert_eq!(resolve(ip("127.0.0.1"), &["198.51.100.1"], &trusted),
+                   ip("198.51.100.1"));
+        // The client prepended an address of its own, the proxies added the ones after it
+        assert_eq!(resolve(ip("127.0.0.1"),
+                           &["192.0.2.9", " 198.51.100.1", " 10.0.0.2"],
+                           &trusted),
+                   ip("198.51.100.1"));
+        assert_eq!(resolve(ip("127.0.0.1"), &["garbage"], &trusted),
+                   ip("127.0.0.1"));
+    }
+
+    #[test]
+    fn ports_are_stripped_from_forwarded_addresses() {
+        assert_eq!(parse_addr("203.0.113.7:52114"), Some(ip("203.0.113.7")));
+        assert_eq!(parse_addr("[2001:db8::1]:443"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_addr("2001:db8::1"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_addr("unknown"), None);
+    }
+
+    #[test]
+    fn confirmed_addresses_expire_and_are_capped() {
+        let known_ips = KnownIps::new(7);
+        let start = Instant::now();
+        known_ips.confirm(1, "203.0.113.7", start);
+        assert!(known_ips.is_confirmed(1, "203.0.113.7", start));
+        assert!(!known_ips.is_confirmed(2, "203.0.113.7", start));
+        let later = start + StdDuration::from_secs(CACHED_IP_TTL_SECS);
+        assert!(!known_ips.is_confirmed(1, "203.0.113.7", later));
+
+        for account_id in 0..(MAX_CACHED_IPS as i64 + 1) {
+            known_ips.confirm(account_id, "203.0.113.7", start);
+        }
+        assert!(known_ips.confirmed.lock().unwrap().len() <= MAX_CACHED_IPS);
+    }
+}
@@ -0,0 +1,349 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Coarse service health for the public status page. Subsystems are checked on a background
+//! timer and the summary is cached, so serving it never reaches a backend. Check failures are
+//! only logged; the summary itself carries no hostnames, versions or error details.
+
+use std::{net::{IpAddr,
+                Ipv4Addr,
+                Ipv6Addr},
+          sync::{Arc,
+                 RwLock},
+          thread,
+          time::Duration};
+
+use chrono::{NaiveDateTime,
+             Utc};
+use diesel::{self,
+             RunQueryDsl};
+use reqwest::{Client,
+              StatusCode};
+
+use crate::{bldr_core::{rpc::RpcClient,
+                        timestamp::rfc3339},
+            config::Config,
+            db::{models::status::{StatusBanner,
+                                  StatusBannerSeverity},
+                 DbPool}};
+
+use crate::server::{feat,
+                    services::s3::S3Handler};
+
+const PROBE_TIMEOUT_SECS: u64 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Health {
+    #[serde(rename = "up")]
+    Up,
+    #[serde(rename = "degraded")]
+    Degraded,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Subsystems {
+    pub api:              Health,
+    pub datastore:        Health,
+    /// Absent when builds are not enabled on this deployment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_scheduling: Option<Health>,
+    pub artifact_storage: Health,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Banner {
+    pub message:    String,
+    pub severity:   StatusBannerSeverity,
+    #[serde(with = "rfc3339")]
+    pub expires_at: NaiveDateTime,
+}
+
+impl From<StatusBanner> for Banner {
+    fn from(banner: StatusBanner) -> Banner {
+        Banner { message:    banner.message,
+                 severity:   banner.severity,
+                 expires_at: banner.expires_at, }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StatusSummary {
+    pub status:     Health,
+    pub subsystems: Subsystems,
+    pub banner:     Option<Banner>,
+    #[serde(with = "rfc3339")]
+    pub updated_at: NaiveDateTime,
+}
+
+impl StatusSummary {
+    // Until the first round of checks completes nothing has failed enough to be degraded
+    fn initial() -> Self {
+        let build_scheduling = if feat::is_enabled(feat::Jobsrv) {
+            Some(Health::Up)
+        } else {
+            None
+        };
+        StatusSummary { status:     Health::Up,
+                        subsystems: Subsystems { api: Health::Up,
+                                                 datastore: Health::Up,
+                                                 build_scheduling,
+                                                 artifact_storage: Health::Up },
+                        banner:     None,
+                        updated_at: Utc::now().naive_utc(), }
+    }
+}
+
+/// The most recently computed summary, shared between the checker and the request handlers
+pub type SharedStatus = Arc<RwLock<StatusSummary>>;
+
+/// Tracks consecutive failures of one subsystem so a single failed check does not flap the
+/// public status.
+struct Hysteresis {
+    threshold: u32,
+    failures:  u32,
+}
+
+impl Hysteresis {
+    fn new(threshold: u32) -> Self {
+        Hysteresis { threshold: threshold.max(1),
+                     failures:  0, }
+    }
+
+    fn record(&mut self, ok: bool) -> Health {
+        if ok {
+            self.failures = 0;
+        } else {
+            self.failures = self.failures.saturating_add(1);
+        }
+        if self.failures >= self.threshold {
+            Health::Degraded
+        } else {
+            Health::Up
+        }
+    }
+}
+
+struct Checker {
+    db:               DbPool,
+    http:             Client,
+    api_url:          String,
+    jobsrv:           Option<RpcClient>,
+    packages:         S3Handler,
+    api:              Hysteresis,
+    datastore:        Hysteresis,
+    build_scheduling: Hysteresis,
+    artifact_storage: Hysteresis,
+    banner:           Option<Banner>,
+}
+
+impl Checker {
+    fn new(config: &Config, db: DbPool) -> Self {
+        let threshold = config.api.status_failure_threshold;
+        let jobsrv = if feat::is_enabled(feat::Jobsrv) {
+            Some(RpcClient::new(&format!("{}", config.jobsrv)))
+        } else {
+            None
+        };
+        let http = match Client::builder().timeout(Duration::from_secs(PROBE_TIMEOUT_SECS))
+                                          .build()
+        {
+            Ok(client) => client,
+            Err(err) => panic!("Unable to create status check client, err = {}", err),
+        };
+
+        Checker { db,
+                  http,
+                  api_url: format!("http://{}/v1/status", probe_addr(config)),
+                  jobsrv,
+                  packages: S3Handler::new(config.s3.clone()),
+                  api: Hysteresis::new(threshold),
+                  datastore: Hysteresis::new(threshold),
+                  build_scheduling: Hysteresis::new(threshold),
+                  artifact_storage: Hysteresis::new(threshold),
+                  banner: None }
+    }
+
+    fn run(&mut self) -> StatusSummary {
+        let api = self.api.record(self.check_api());
+        let datastore = self.datastore.record(self.check_datastore());
+        let build_scheduling = match self.jobsrv {
+            Some(_) => Some(self.build_scheduling.record(self.check_build_scheduling())),
+            None => None,
+        };
+        let artifact_storage = self.artifact_storage.record(self.check_artifact_storage());
+
+        let subsystems = Subsystems { api,
+                                      datastore,
+                                      build_scheduling,
+                                      artifact_storage };
+        let status = if api == Health::Up
+                        && datastore == Health::Up
+                        && build_scheduling != Some(Health::Degraded)
+                        && artifact_storage == Health::Up
+        {
+            Health::Up
+        } else {
+            Health::Degraded
+        };
+
+        StatusSummary { status,
+                        subsystems,
+                        banner: self.current_banner(),
+                        updated_at: Utc::now().naive_utc() }
+    }
+
+    fn check_api(&self) -> bool {
+        match self.http.get(&self.api_url).send() {
+            Ok(res) => res.status() == StatusCode::OK,
+            Err(err) => {
+                warn!("Status check of api failed, err={}", err);
+                false
+            }
+        }
+    }
+
+    fn check_datastore(&self) -> bool {
+        let result = self.db
+                         .get_conn()
+                         .map_err(|e| e.to_string())
+                         .and_then(|conn| {
+                             diesel::sql_query("SELECT 1").execute(&*conn)
+                                                          .map_err(|e| e.to_string())
+                         });
+        match result {
+            Ok(_) => true,
+            Err(err) => {
+                warn!("Status check of datastore failed, err={}", err);
+                false
+            }
+        }
+    }
+
+    fn check_build_scheduling(&self) -> bool {
+        match self.jobsrv.as_ref().map(RpcClient::status) {
+            Some(Ok(())) | None => true,
+            Some(Err(err)) => {
+                warn!("Status check of jobsrv failed, err={}", err);
+                false
+            }
+        }
+    }
+
+    fn check_artifact_storage(&self) -> bool {
+        match self.packages.bucket_exists() {
+            Ok(true) => true,
+            Ok(false) => {
+                warn!("Status check of artifact storage failed, bucket not found");
+                false
+            }
+            Err(err) => {
+                warn!("Status check of artifact storage failed, err={}", err);
+                false
+            }
+        }
+    }
+
+    // A banner that cannot be refreshed stays up until its own expiry
+    fn current_banner(&mut self) -> Option<Banner> {
+        let fetched =
+            self.db
+                .get_conn()
+                .map_err(|e| e.to_string())
+                .and_then(|conn| StatusBanner::current(&*conn).map_err(|e| e.to_string()));
+        match fetched {
+            Ok(banner) => self.banner = banner.map(Banner::from),
+            Err(err) => debug!("Unable to refresh status banner, err={}", err),
+        }
+
+        let now = Utc::now().naive_utc();
+        if self.banner.as_ref().map_or(false, |b| b.expires_at <= now) {
+            self.banner = None;
+        }
+        self.banner.clone()
+    }
+}
+
+// The api's own status endpoint, reached over loopback when listening on all interfaces
+fn probe_addr(config: &Config) -> String {
+    let ip = match config.http.listen {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    match ip {
+        IpAddr::V4(ip) => format!("{}:{}", ip, config.http.port),
+        IpAddr::V6(ip) => format!("[{}]:{}", ip, config.http.port),
+    }
+}
+
+/// Start the background checker and return the summary it keeps up to date.
+pub fn start(config: &Config, db: DbPool) -> SharedStatus {
+    let status = Arc::new(RwLock::new(StatusSummary::initial()));
+    let interval = Duration::from_secs(config.api.status_check_interval_sec.max(1));
+    let mut checker = Checker::new(config, db);
+    let shared = status.clone();
+
+    thread::Builder::new().name("status-check".to_string())
+                          .spawn(move || {
+                              loop {
+                                  thread::sleep(interval);
+                                  let summary = checker.run();
+                                  match shared.write() {
+                                      Ok(mut current) => *current = summary,
+                                      Err(err) => warn!("Unable to update status, err={}", err),
+                                  }
+                              }
+                          })
+                          .expect("Unable to start status check thread");
+
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_failure_below_threshold_stays_up() {
+        let mut check = Hysteresis::new(3);
+        assert_eq!(check.record(false), Health::Up);
+        assert_eq!(check.record(false), Health::Up);
+        assert_eq!(check.record(true), Health::Up);
+        assert_eq!(check.record(false), Health::Up);
+    }
+
+    #[test]
+    fn consecutive_failures_degrade() {
+        let mut check = Hysteresis::new(3);
+        check.record(false);
+        check.record(false);
+        assert_eq!(check.record(false), Health::Degraded);
+        assert_eq!(check.record(false), Health::Degraded);
+    }
+
+    #[test]
+    fn success_recovers() {
+        let mut check = Hysteresis::new(2);
+        check.record(false);
+        assert_eq!(check.record(false), Health::Degraded);
+        assert_eq!(check.record(true), Health::Up);
+    }
+
+    #[test]
+    fn zero_threshold_behaves_as_one() {
+        let mut check = Hysteresis::new(0);
+        assert_eq!(check.record(true), Health::Up);
+        assert_eq!(check.record(false), Health::Degraded);
+    }
+}
@@ -0,0 +1,185 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Which of the latest releases of a package were built for which targets. A release missing
+//! for a target usually means a platform was skipped, so every supported target gets a column,
+//! including those the package was never built for.
+
+use std::collections::BTreeMap;
+
+use crate::{db::models::package::TargetMatrixCell,
+            hab_core::package::PackageTarget};
+
+#[derive(Debug, Serialize)]
+pub struct TargetMatrix {
+    pub origin:   String,
+    pub name:     String,
+    /// The columns of the matrix, the supported targets first
+    pub targets:  Vec<String>,
+    /// Newest first
+    pub releases: Vec<MatrixRelease>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MatrixRelease {
+    pub version: String,
+    pub release: String,
+    pub targets: BTreeMap<String, TargetPresence>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TargetPresence {
+    pub present:  bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub channels: Vec<String>,
+}
+
+/// Lays the cells of a package's target matrix out by release. `cells` are in release order,
+/// as the query returns them.
+pub fn build(origin: &str,
+             name: &str,
+             supported: &[PackageTarget],
+             cells: Vec<TargetMatrixCell>)
+             -> TargetMatrix {
+    let mut targets: Vec<String> = supported.iter().map(ToString::to_string).collect();
+    // Targets no longer supported still show up when a listed release was built for them
+    let mut unsupported: Vec<String> = cells.iter()
+                                            .map(|cell| cell.target.clone())
+                                            .filter(|target| !targets.contains(target))
+                                            .collect();
+    unsupported.sort();
+    unsupported.dedup();
+    targets.extend(unsupported);
+
+    let mut releases: Vec<MatrixRelease> = Vec::new();
+    for cell in cells {
+        let is_last = releases.last()
+                              .map(|r| r.version == cell.version && r.release == cell.release)
+                              .unwrap_or(false);
+        if !is_last {
+            let absent = targets.iter()
+                                .map(|target| {
+                                    (target.clone(),
+                                     TargetPresence { present:  false,
+                                                      channels: Vec::new(), })
+                                })
+                                .collect();
+            releases.push(MatrixRelease { version: cell.version.clone(),
+                                          release: cell.release.clone(),
+                                          targets: absent, });
+        }
+        let release = releases.last_mut().unwrap(); // Unwrap Ok, pushed above
+        release.targets.insert(cell.target,
+                               TargetPresence { present:  true,
+                                                channels: cell.channels, });
+    }
+
+    TargetMatrix { origin: origin.to_string(),
+                   name: name.to_string(),
+                   targets,
+                   releases }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hab_core::package::target;
+
+    fn cell(version: &str, release: &str, target: &str, channels: &[&str]) -> TargetMatrixCell {
+        TargetMatrixCell { version:  version.to_string(),
+                           release:  release.to_string(),
+                           target:   target.to_string(),
+                           channels: channels.iter().map(|c| c.to_string()).collect(), }
+    }
+
+    fn supported() -> Vec<PackageTarget> {
+        vec![target::X86_64_LINUX,
+             target::X86_64_LINUX_KERNEL2,
+             target::X86_64_WINDOWS]
+    }
+
+    fn present(channels: &[&str]) -> TargetPresence {
+        TargetPresence { present:  true,
+                         channels: channels.iter().map(|c| c.to_string()).collect(), }
+    }
+
+    fn absent() -> TargetPresence {
+        TargetPresence { present:  false,
+                         channels: Vec::new(), }
+    }
+
+    #[test]
+    fn a_release_for_only_one_of_three_targets() {
+        let (new, old) = ("20191021120000", "20191001120000");
+        let cells = vec![cell("1.1.0", new, "x86_64-linux", &["stable", "unstable"]),
+                         cell("1.1.0", new, "x86_64-linux-kernel2", &["unstable"]),
+                         cell("1.1.0", new, "x86_64-windows", &["unstable"]),
+                         cell("1.0.0", old, "x86_64-linux", &["stable"])];
+
+        let matrix = build("core", "redis", &supported(), cells);
+        assert_eq!(matrix.targets,
+                   vec!["x86_64-linux", "x86_64-linux-kernel2", "x86_64-windows"]);
+        assert_eq!(matrix.releases.len(), 2);
+
+        let latest = &matrix.releases[0];
+        assert_eq!(latest.release, new);
+        assert_eq!(latest.targets["x86_64-linux"],
+                   present(&["stable", "unstable"]));
+        assert_eq!(latest.targets["x86_64-windows"], present(&["unstable"]));
+
+        let older = &matrix.releases[1];
+        assert_eq!((older.version.as_str(), older.release.as_str()),
+                   ("1.0.0", old));
+        assert_eq!(older.targets["x86_64-linux"], present(&["stable"]));
+        assert_eq!(older.targets["x86_64-linux-kernel2"], absent());
+        assert_eq!(older.targets["x86_64-windows"], absent());
+    }
+
+    #[test]
+    fn a_target_never_built_has_an_empty_column() {
+        let cells = vec![cell("2.0.0", "20191021120000", "x86_64-linux", &[]),
+                         cell("1.9.0", "20191015120000", "x86_64-linux", &["stable"])];
+
+        let matrix = build("core", "glibc", &supported(), cells);
+        assert_eq!(matrix.targets.len(), 3);
+        for release in &matrix.releases {
+            assert_eq!(release.targets.len(), 3);
+            assert!(release.targets["x86_64-linux"].present);
+            assert_eq!(release.targets["x86_64-windows"], absent());
+        }
+        assert_eq!(matrix.releases[0].targets["x86_64-linux"], present(&[]));
+
+        let json = serde_json::to_value(&matrix).unwrap();
+        assert_eq!(json["releases"][0]["targets"]["x86_64-windows"],
+                   json!({ "present": false }));
+    }
+
+    #[test]
+    fn targets_no_longer_supported_are_listed_last() {
+        let cells = vec![cell("1.0.0", "20191001120000", "aarch64-linux", &[]),
+                         cell("1.0.0", "20191001120000", "x86_64-linux", &[])];
+
+        let matrix = build("core", "zlib", &[target::X86_64_LINUX], cells);
+        assert_eq!(matrix.targets, vec!["x86_64-linux", "aarch64-linux"]);
+        assert_eq!(matrix.releases.len(), 1);
+        assert!(matrix.releases[0].targets["aarch64-linux"].present);
+    }
+
+    #[test]
+    fn no_releases_is_an_empty_matrix() {
+        let matrix = build("core", "nothing", &supported(), Vec::new());
+        assert_eq!(matrix.targets.len(), 3);
+        assert!(matrix.releases.is_empty());
+    }
+}
@@ -0,0 +1,85 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Background notification of expiring personal access tokens, and the
+//! operator command to assign a sunset date to non-expiring ones.
+
+use std::{thread,
+          time::Duration as StdDuration};
+
+use chrono::{Duration,
+             NaiveDateTime,
+             Utc};
+use diesel::Connection;
+
+use crate::{config::ApiCfg,
+            db::{models::account::AccountToken,
+                 DbPool}};
+
+use crate::server::{error::{Error,
+                            Result},
+                    services::notify};
+
+const CHECK_INTERVAL_SECS: u64 = 60 * 60;
+
+pub fn start(config: ApiCfg, db: DbPool) {
+    if config.token_expiry_notice_days == 0 {
+        info!("Access token expiry notifications disabled");
+        return;
+    }
+
+    thread::Builder::new().name("token-expiry".to_string())
+                          .spawn(move || {
+                              loop {
+                                  if let Err(err) = notify_expiring(&config, &db) {
+                                      warn!("Unable to notify expiring access tokens, err={}", err);
+                                  }
+                                  thread::sleep(StdDuration::from_secs(CHECK_INTERVAL_SECS));
+                              }
+                          })
+                          .expect("Unable to start token expiry thread");
+}
+
+fn notify_expiring(config: &ApiCfg, db: &DbPool) -> Result<()> {
+    let conn = db.get_conn().map_err(Error::DbError)?;
+    let notice = Duration::days(i64::from(config.token_expiry_notice_days));
+    let before = (Utc::now() + notice).naive_utc();
+
+    for token in AccountToken::list_expiring(before, &*conn).map_err(Error::DieselError)? {
+        let expires_at = token.expires_at.expect("expiring token has an expiry");
+        let message = format!("Your personal access token expires on {} UTC. Generate a new \
+                               token before then to avoid interruption.",
+                              expires_at.format("%Y-%m-%d %H:%M:%S"));
+        // Every node runs this task, the notice goes out from the one that claims it. A notice
+        // that fails to go out releases its claim.
+        conn.transaction::<_, Error, _>(|| {
+                if AccountToken::claim_expiry_notice(token.id, &*conn)? {
+                    notify::notify_account(token.account_id,
+                                           notify::TOKEN_EXPIRING,
+                                           &message,
+                                           &*conn)?;
+                }
+                Ok(())
+            })?;
+    }
+    Ok(())
+}
+
+/// Assign an expiration to all personal access tokens that were issued without one.
+/// Returns the number of tokens updated.
+pub fn sunset_tokens(db: &DbPool, days: u32) -> Result<usize> {
+    let conn = db.get_conn().map_err(Error::DbError)?;
+    let sunset: NaiveDateTime = (Utc::now() + Duration::days(i64::from(days))).naive_utc();
+    AccountToken::sunset_non_expiring(sunset, &*conn).map_err(Error::DieselError)
+}
@@ -0,0 +1,168 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Origin upload policies, checked against the license and description a package's plan
+//! declared. Both are read from the package MANIFEST, which has the plan's description under
+//! its title and the licenses on its `__License__` line.
+
+use std::fmt;
+
+use crate::db::models::origin::OriginUploadPolicy;
+
+const LICENSE_PREFIX: &str = "* __License__:";
+
+/// A policy a package fails
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Violation {
+    pub policy: &'static str,
+    pub detail: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.policy, self.detail)
+    }
+}
+
+/// Every policy the package with `manifest` fails, empty if it may be uploaded
+pub fn violations(policy: &OriginUploadPolicy, manifest: &str) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let licenses = licenses(manifest);
+
+    if policy.require_license && licenses.is_empty() {
+        violations.push(Violation { policy: "require_license",
+                                    detail: "the plan declares no license".to_string(), });
+    }
+
+    if !policy.allowed_licenses.is_empty() {
+        for license in licenses.iter()
+                               .filter(|l| !contains(&policy.allowed_licenses, l))
+        {
+            let detail = format!("{} is not an allowed license", license);
+            violations.push(Violation { policy: "allowed_licenses",
+                                        detail });
+        }
+    }
+
+    for license in licenses.iter()
+                           .filter(|l| contains(&policy.blocked_licenses, l))
+    {
+        violations.push(Violation { policy: "blocked_licenses",
+                                    detail: format!("{} is a blocked license", license), });
+    }
+
+    if policy.require_description && description(manifest).is_empty() {
+        violations.push(Violation { policy: "require_description",
+                                    detail: "the plan has no description".to_string(), });
+    }
+
+    violations
+}
+
+// License identifiers are compared ignoring case, as SPDX identifiers are
+fn contains(licenses: &[String], license: &str) -> bool {
+    licenses.iter().any(|l| l.eq_ignore_ascii_case(license))
+}
+
+fn licenses(manifest: &str) -> Vec<String> {
+    manifest.lines()
+            .map(str::trim)
+            .find(|line| line.starts_with(LICENSE_PREFIX))
+            .map_or_else(Vec::new, |line| {
+                line[LICENSE_PREFIX.len()..].split_whitespace()
+                                            .map(|l| l.trim_matches(&['\'', '"', ','][..]))
+                                            .filter(|l| !l.is_empty())
+                                            .map(str::to_string)
+                                            .collect()
+            })
+}
+
+// The lines between the manifest's title and its list of package details
+fn description(manifest: &str) -> String {
+    manifest.lines()
+            .map(str::trim)
+            .skip_while(|line| !line.starts_with("# "))
+            .skip(1)
+            .take_while(|line| !line.starts_with("* __"))
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(description: &str, licenses: &str) -> String {
+        format!("# core / nginx\n{}\n\n* __Maintainer__: The Habitat Maintainers\n* __Version__: \
+                 1.17.4\n* __License__: {}\n* __Source__: [nginx.tar.gz](nginx.tar.gz)\n",
+                description, licenses)
+    }
+
+    fn policy() -> OriginUploadPolicy {
+        OriginUploadPolicy { origin: "core".to_string(),
+                             require_license: false,
+                             allowed_licenses: vec![],
+                             blocked_licenses: vec![],
+                             require_description: false,
+                             created_at: None,
+                             updated_at: None,
+                             strict_dependency_validation: false, }
+    }
+
+    #[test]
+    fn licenses_and_description_are_read_from_manifest() {
+        let nginx = manifest("NGINX web server.", "'BSD-2-Clause' MIT");
+        assert_eq!(licenses(&nginx), vec!["BSD-2-Clause", "MIT"]);
+        assert_eq!(description(&nginx), "NGINX web server.");
+
+        let bare = manifest("", "");
+        assert!(licenses(&bare).is_empty());
+        assert_eq!(description(&bare), "");
+        assert!(licenses("").is_empty());
+    }
+
+    #[test]
+    fn empty_policy_allows_anything() {
+        assert!(violations(&policy(), &manifest("", "")).is_empty());
+    }
+
+    #[test]
+    fn required_license_and_description() {
+        let mut policy = policy();
+        policy.require_license = true;
+        policy.require_description = true;
+
+        let failed = violations(&policy, &manifest("", ""));
+        let policies = failed.iter().map(|v| v.policy).collect::<Vec<_>>();
+        assert_eq!(policies, vec!["require_license", "require_description"]);
+        assert!(violations(&policy, &manifest("NGINX web server.", "MIT")).is_empty());
+    }
+
+    #[test]
+    fn each_disallowed_license_is_a_violation() {
+        let mut policy = policy();
+        policy.allowed_licenses = vec!["mit".to_string(), "Apache-2.0".to_string()];
+        policy.blocked_licenses = vec!["GPL-3.0".to_string()];
+
+        assert!(violations(&policy, &manifest("", "MIT apache-2.0")).is_empty());
+        assert_eq!(violations(&policy, &manifest("", "MIT GPL-3.0 BSD-3-Clause")),
+                   vec![Violation { policy: "allowed_licenses",
+                                    detail: "GPL-3.0 is not an allowed license".to_string(), },
+                        Violation { policy: "allowed_licenses",
+                                    detail: "BSD-3-Clause is not an allowed license".to_string(), },
+                        Violation { policy: "blocked_licenses",
+                                    detail: "GPL-3.0 is a blocked license".to_string(), }]);
+    }
+}
@@ -0,0 +1,769 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Uploads accepted while the artifact store is unavailable. An upload whose store write fails
+//! with an error worth retrying keeps its verified archive in the spool directory, along with
+//! what is needed to finish ingesting it, and the client is answered with the id of the
+//! ingestion. A background drainer retries the store write with a growing delay and completes
+//! the ingestion once it succeeds. Entries are files, so they survive a restart of the service.
+
+use std::{collections::HashMap,
+          fs,
+          io,
+          path::{Path,
+                 PathBuf},
+          str::FromStr,
+          sync::{Arc,
+                 Mutex},
+          thread,
+          time::Duration as StdDuration};
+
+use chrono::{DateTime,
+             Duration,
+             Utc};
+use rusoto_core::RusotoError;
+use serde_json;
+use uuid::Uuid;
+
+use artifactory_client::{client::ArtifactoryClient,
+                         error::ArtifactoryError};
+
+use crate::{bldr_core::rpc::RpcClient,
+            config::{Config,
+                     UploadSpoolCfg},
+            db::DbPool,
+            hab_core::package::{PackageIdent,
+                                PackageTarget}};
+
+use crate::server::{error::{Error,
+                            Result},
+                    feat,
+                    services::{artifact_encryption::{self,
+                                                     EncryptedArtifact},
+                               dep_check::DependencyReport,
+                               ingest::{self,
+                                        Jobsrv,
+                                        StoredUpload},
+                               s3::S3Handler}};
+
+/// Where an ingestion is at, as reported to the client that uploaded it
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IngestionState {
+    /// Accepted, not yet tried again
+    Spooled,
+    /// At least one more store write failed, another is scheduled
+    Retrying,
+    /// Stored and ingested like any other upload
+    Complete,
+    /// Given up on after `max_attempts` failures that retrying won't fix. The archive is kept in
+    /// the spool's `failed` directory for an operator to look at.
+    Failed,
+}
+
+/// How the spooled artifact is encrypted, when its origin encrypts them
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SpooledEncryption {
+    pub key_id:           String,
+    pub wrapped_data_key: String,
+}
+
+/// What finishing an upload needs besides its archive
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SpooledUpload {
+    pub ident:             String,
+    pub target:            String,
+    pub checksum:          String,
+    pub owner_id:          u64,
+    pub owner_name:        String,
+    pub build_cache_key:   Option<String>,
+    /// Uploaded by a worker, which schedules no builds of reverse dependencies
+    pub from_builder:      bool,
+    pub encryption:        Option<SpooledEncryption>,
+    /// What the dependency check found on upload, none for entries spooled before it was kept
+    #[serde(default)]
+    pub dependency_report: Option<DependencyReport>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SpoolEntry {
+    pub id:              String,
+    pub upload:          SpooledUpload,
+    pub state:           IngestionState,
+    pub attempts:        u32,
+    /// Bytes the entry's files take in the spool
+    pub size:            u64,
+    pub created_at:      DateTime<Utc>,
+    pub updated_at:      DateTime<Utc>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error:      Option<String>,
+}
+
+/// The status of an ingestion, without the details of the upload
+#[derive(Debug, Serialize)]
+pub struct Ingestion {
+    pub id:              String,
+    pub ident:           String,
+    pub target:          String,
+    pub state:           IngestionState,
+    pub attempts:        u32,
+    pub created_at:      DateTime<Utc>,
+    pub updated_at:      DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error:      Option<String>,
+}
+
+impl SpoolEntry {
+    pub fn origin(&self) -> &str { self.upload.ident.split('/').next().unwrap_or("") }
+
+    pub fn is_pending(&self) -> bool {
+        match self.state {
+            IngestionState::Spooled | IngestionState::Retrying => true,
+            IngestionState::Complete | IngestionState::Failed => false,
+        }
+    }
+
+    pub fn ingestion(&self) -> Ingestion {
+        let next_attempt_at = if self.is_pending() {
+            Some(self.next_attempt_at)
+        } else {
+            None
+        };
+        Ingestion { id: self.id.clone(),
+                    ident: self.upload.ident.clone(),
+                    target: self.upload.target.clone(),
+                    state: self.state,
+                    attempts: self.attempts,
+                    created_at: self.created_at,
+                    updated_at: self.updated_at,
+                    next_attempt_at,
+                    last_error: self.last_error.clone() }
+    }
+}
+
+// Where the archives of the entries given up on are kept
+const FAILED_DIR: &str = "failed";
+
+/// Whether a failed store write is worth retrying. Outages of the store are, a request the
+/// store refuses isn't.
+pub fn is_retryable(err: &Error) -> bool {
+    match err {
+        Error::PackageUpload(ref e) => rusoto_retryable(e),
+        Error::PartialUpload(ref e) => rusoto_retryable(e),
+        Error::MultipartUploadReq(ref e) => rusoto_retryable(e),
+        Error::MultipartCompletion(ref e) => rusoto_retryable(e),
+        Error::HeadObject(ref e) => rusoto_retryable(e),
+        Error::Artifactory(ArtifactoryError::HttpClient(_)) => true,
+        Error::Artifactory(ArtifactoryError::ApiError(status, _)) => {
+            status.is_server_error() || status.as_u16() == 429
+        }
+        _ => false,
+    }
+}
+
+fn rusoto_retryable<E>(err: &RusotoError<E>) -> bool {
+    match err {
+        RusotoError::HttpDispatch(_) => true,
+        RusotoError::Unknown(ref res) => res.status.is_server_error() || res.status.as_u16() == 429,
+        _ => false,
+    }
+}
+
+/// The delay before the store write of an entry that failed `attempts` times is tried again,
+/// doubling from `retry_base_sec` up to `retry_max_sec`
+pub fn retry_delay(cfg: &UploadSpoolCfg, attempts: u32) -> Duration {
+    let factor = 1u64.checked_shl(attempts.saturating_sub(1).min(32))
+                     .unwrap_or(u64::max_value());
+    let secs = cfg.retry_base_sec
+                  .saturating_mul(factor)
+                  .min(cfg.retry_max_sec);
+    Duration::seconds(secs as i64)
+}
+
+/// The spooled uploads, kept both in memory and as files in the spool directory
+pub struct Spool {
+    cfg:     UploadSpoolCfg,
+    entries: Mutex<HashMap<String, SpoolEntry>>,
+}
+
+impl Spool {
+    /// Opens the spool directory, taking up the entries a previous run left there
+    pub fn open(cfg: &UploadSpoolCfg) -> Result<Self> {
+        let spool = Spool { cfg:     cfg.clone(),
+                            entries: Mutex::new(HashMap::new()), };
+        if !cfg.enabled {
+            return Ok(spool);
+        }
+
+        fs::create_dir_all(&cfg.path)?;
+        let mut entries = HashMap::new();
+        for file in fs::read_dir(&cfg.path)? {
+            let path = file?.path();
+            if path.extension().map_or(true, |ext| ext != "json") {
+                continue;
+            }
+            let entry = match read_entry(&path) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    warn!("Unable to read spool entry {:?}, err={}", path, err);
+                    continue;
+                }
+            };
+            if entry.is_pending() && !spool.archive_path(&entry.id).exists() {
+                warn!("Spooled archive of ingestion {} ({}) is missing, dropping it",
+                      entry.id, entry.upload.ident);
+                continue;
+            }
+            entries.insert(entry.id.clone(), entry);
+        }
+        info!("Upload spool opened with {} pending ingestions",
+              entries.values().filter(|e| e.is_pending()).count());
+        *spool.entries.lock().unwrap() = entries;
+        Ok(spool)
+    }
+
+    /// Whether a failed store write of an upload is answered by spooling it
+    pub fn accepts(&self, err: &Error) -> bool { self.cfg.enabled && is_retryable(err) }
+
+    /// Moves the verified archive of `upload`, and its encrypted copy when there is one, into
+    /// the spool. `Error::SpoolFull` when the spool has no room for them.
+    pub fn spool(&self,
+                 upload: SpooledUpload,
+                 archive: &Path,
+                 encrypted: Option<&Path>)
+                 -> Result<SpoolEntry> {
+        let mut size = fs::metadata(archive)?.len();
+        if let Some(encrypted) = encrypted {
+            size += fs::metadata(encrypted)?.len();
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+
+        // The client uploading again while its first upload is spooled is told of that one
+        if let Some(entry) = entries.values().find(|e| {
+                                                 e.is_pending()
+                                                 && e.upload.ident == upload.ident
+                                                 && e.upload.target == upload.target
+                                             })
+        {
+            for path in Some(archive).into_iter().chain(encrypted) {
+                remove_if_exists(path)?;
+            }
+            return Ok(entry.clone());
+        }
+
+        let (count, bytes) = entries.values()
+                                    .filter(|e| e.is_pending())
+                                    .fold((0, 0), |(count, bytes), e| (count + 1, bytes + e.size));
+        if count >= self.cfg.max_entries || bytes + size > self.cfg.max_bytes {
+            return Err(Error::SpoolFull);
+        }
+
+        let id = Uuid::new_v4().to_string();
+        move_file(archive, &self.archive_path(&id))?;
+        if let Some(encrypted) = encrypted {
+            move_file(encrypted, &self.encrypted_path(&id))?;
+        }
+
+        let now = Utc::now();
+        let entry = SpoolEntry { id: id.clone(),
+                                 upload,
+                                 state: IngestionState::Spooled,
+                                 attempts: 0,
+                                 size,
+                                 created_at: now,
+                                 updated_at: now,
+                                 next_attempt_at: now + retry_delay(&self.cfg, 1),
+                                 last_error: None };
+        self.persist(&entry)?;
+        entries.insert(id, entry.clone());
+        Ok(entry)
+    }
+
+    pub fn get(&self, id: &str) -> Option<SpoolEntry> {
+        self.entries.lock().unwrap().get(id).cloned()
+    }
+
+    /// The pending entries whose next attempt is due, oldest first
+    pub fn due(&self, now: DateTime<Utc>) -> Vec<SpoolEntry> {
+        let mut due: Vec<SpoolEntry> = self.entries
+                                           .lock()
+                                           .unwrap()
+                                           .values()
+                                           .filter(|e| e.is_pending() && e.next_attempt_at <= now)
+                                           .cloned()
+                                           .collect();
+        due.sort_by_key(|e| e.created_at);
+        due
+    }
+
+    /// Records another failed attempt at storing the entry and schedules the next one
+    pub fn retry(&self, id: &str, err: &Error) -> Result<()> {
+        let now = Utc::now();
+        self.update(id, |entry| {
+                entry.state = IngestionState::Retrying;
+                entry.attempts += 1;
+                entry.updated_at = now;
+                entry.next_attempt_at = now + retry_delay(&self.cfg, entry.attempts + 1);
+                entry.last_error = Some(err.to_string());
+            })
+    }
+
+    /// Gives up on the entry, moving its files to the `failed` directory. Its status is kept for
+    /// `retain_complete_sec`.
+    pub fn fail(&self, id: &str, err: &Error) -> Result<()> {
+        let now = Utc::now();
+        self.update(id, |entry| {
+                entry.state = IngestionState::Failed;
+                entry.attempts += 1;
+                entry.updated_at = now;
+                entry.last_error = Some(err.to_string());
+            })?;
+        let failed = self.cfg.path.join(FAILED_DIR);
+        fs::create_dir_all(&failed)?;
+        for path in &[self.archive_path(id), self.encrypted_path(id)] {
+            if path.exists() {
+                move_file(path, &failed.join(path.file_name().unwrap()))?; // Unwrap Ok
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether an entry that failed with `err` is given up on rather than retried. Store outages
+    /// are retried until the store is back, other errors `max_attempts` times.
+    pub fn gives_up(&self, entry: &SpoolEntry, err: &Error) -> bool {
+        !is_retryable(err) && entry.attempts + 1 >= self.cfg.max_attempts
+    }
+
+    /// Marks the entry ingested and frees its room in the spool. Its status is kept for
+    /// `retain_complete_sec`.
+    pub fn complete(&self, id: &str) -> Result<()> {
+        let now = Utc::now();
+        self.update(id, |entry| {
+                entry.state = IngestionState::Complete;
+                entry.attempts += 1;
+                entry.updated_at = now;
+                entry.last_error = None;
+            })?;
+        for path in &[self.archive_path(id), self.encrypted_path(id)] {
+            if let Err(err) = remove_if_exists(path) {
+                warn!("Unable to remove spooled file {:?}, err={}", path, err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets the ingestions completed longer than `retain_complete_sec` ago
+    pub fn prune(&self, now: DateTime<Utc>) {
+        let retain = Duration::seconds(self.cfg.retain_complete_sec as i64);
+        let mut entries = self.entries.lock().unwrap();
+        let expired: Vec<String> =
+            entries.values()
+                   .filter(|e| !e.is_pending() && e.updated_at + retain < now)
+                   .map(|e| e.id.clone())
+                   .collect();
+        for id in expired {
+            if let Err(err) = remove_if_exists(&self.entry_path(&id)) {
+                warn!("Unable to remove spool entry {}, err={}", id, err);
+                continue;
+            }
+            entries.remove(&id);
+        }
+    }
+
+    pub fn archive_path(&self, id: &str) -> PathBuf { self.cfg.path.join(format!("{}.hart", id)) }
+
+    pub fn encrypted_path(&self, id: &str) -> PathBuf { self.cfg.path.join(format!("{}.enc", id)) }
+
+    fn entry_path(&self, id: &str) -> PathBuf { self.cfg.path.join(format!("{}.json", id)) }
+
+    fn update<F>(&self, id: &str, change: F) -> Result<()>
+        where F: FnOnce(&mut SpoolEntry)
+    {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(id).ok_or(Error::NotFound)?;
+        change(entry);
+        self.persist(entry)
+    }
+
+    // Written beside the entry and renamed over it, so a crash never leaves half an entry
+    fn persist(&self, entry: &SpoolEntry) -> Result<()> {
+        let path = self.entry_path(&entry.id);
+        let partial = path.with_extension("json.part");
+        fs::write(&partial, serde_json::to_vec(entry)?)?;
+        fs::rename(&partial, &path)?;
+        Ok(())
+    }
+}
+
+fn read_entry(path: &Path) -> Result<SpoolEntry> {
+    let body = fs::read(path)?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+// The spool may be on another filesystem than the data path
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Finishes spooled uploads, the remainder of the upload after its store write
+struct Drainer {
+    config:      Config,
+    spool:       Arc<Spool>,
+    db:          DbPool,
+    packages:    S3Handler,
+    artifactory: Option<ArtifactoryClient>,
+    jobsrv:      Option<RpcClient>,
+}
+
+impl Drainer {
+    fn drain(&mut self) {
+        for entry in self.spool.due(Utc::now()) {
+            let result = match self.ingest(&entry) {
+                Ok(()) => {
+                    info!("Ingested spooled upload {} ({}), ingestion {}",
+                          entry.upload.ident, entry.upload.target, entry.id);
+                    self.spool.complete(&entry.id)
+                }
+                Err(err) if self.spool.gives_up(&entry, &err) => {
+                    error!("Giving up on spooled upload {} ({}), ingestion {}, err={}",
+                           entry.upload.ident, entry.upload.target, entry.id, err);
+                    self.spool.fail(&entry.id, &err)
+                }
+                Err(err) => {
+                    warn!("Unable to ingest spooled upload {} ({}), ingestion {}, err={}",
+                          entry.upload.ident, entry.upload.target, entry.id, err);
+                    self.spool.retry(&entry.id, &err)
+                }
+            };
+            if let Err(err) = result {
+                warn!("Unable to update spool entry {}, err={}", entry.id, err);
+            }
+        }
+        self.spool.prune(Utc::now());
+    }
+
+    // Made on first use, so that a misconfigured Artifactory fails the attempts rather than the
+    // service's start
+    fn artifactory(&mut self) -> Result<&ArtifactoryClient> {
+        if self.artifactory.is_none() {
+            let client = ArtifactoryClient::new(self.config.artifactory.clone())
+                .map_err(Error::Artifactory)?;
+            self.artifactory = Some(client);
+        }
+        Ok(self.artifactory.as_ref().unwrap()) // Unwrap Ok
+    }
+
+    fn ingest(&mut self, entry: &SpoolEntry) -> Result<()> {
+        let upload = &entry.upload;
+        let ident = PackageIdent::from_str(&upload.ident)?;
+        let target = PackageTarget::from_str(&upload.target)?;
+        let archive_path = self.spool.archive_path(&entry.id);
+        let encrypted = match upload.encryption {
+            Some(ref encryption) => {
+                Some(EncryptedArtifact { path:             self.spool.encrypted_path(&entry.id),
+                                         key_id:           encryption.key_id.clone(),
+                                         wrapped_data_key: encryption.wrapped_data_key.clone(), })
+            }
+            None => None,
+        };
+        let stored_path = encrypted.as_ref().map_or(&archive_path, |e| &e.path);
+
+        if feat::is_enabled(feat::Artifactory) {
+            self.artifactory()?
+                .upload(stored_path, &ident, target)
+                .map_err(Error::Artifactory)?;
+        } else {
+            self.packages.upload(stored_path, &ident, target)?;
+        }
+
+        let conn = self.db.get_conn().map_err(Error::DbError)?;
+        artifact_encryption::record(&ident, target, &upload.checksum, encrypted.as_ref(), &*conn)?;
+
+        let stored =
+            StoredUpload { ident: &ident,
+                           target,
+                           archive_path: &archive_path,
+                           owner_id: upload.owner_id,
+                           owner_name: &upload.owner_name,
+                           build_cache_key: upload.build_cache_key.as_ref().map(String::as_str),
+                           from_builder: upload.from_builder,
+                           dependency_report: upload.dependency_report.as_ref() };
+        let jobsrv = self.jobsrv.as_ref().map(|client| {
+                                             Jobsrv { client,
+                                                      budget: None }
+                                         });
+        ingest::finish(&stored, &self.config, jobsrv.as_ref(), &*conn)?;
+        Ok(())
+    }
+}
+
+/// Drain the spool in the background
+pub fn start(config: &Config, spool: Arc<Spool>, db: DbPool) {
+    if !config.upload_spool.enabled {
+        info!("Upload spool disabled");
+        return;
+    }
+
+    let jobsrv = if feat::is_enabled(feat::Jobsrv) {
+        Some(RpcClient::new(&format!("{}", config.jobsrv)))
+    } else {
+        None
+    };
+    let mut drainer = Drainer { config: config.clone(),
+                                spool,
+                                db,
+                                packages: S3Handler::new(config.s3.clone()),
+                                artifactory: None,
+                                jobsrv };
+    let interval = StdDuration::from_secs(config.upload_spool.drain_interval_sec.max(1));
+
+    thread::Builder::new().name("upload-spool".to_string())
+                          .spawn(move || {
+                              loop {
+                                  thread::sleep(interval);
+                                  drainer.drain();
+                              }
+                          })
+                          .expect("Unable to start upload spool thread");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::{tempdir,
+                   TempDir};
+
+    fn cfg(dir: &TempDir) -> UploadSpoolCfg {
+        UploadSpoolCfg { enabled: true,
+                         path: dir.path().join("spool"),
+                         max_entries: 2,
+                         max_bytes: 1024,
+                         retry_base_sec: 30,
+                         retry_max_sec: 600,
+                         max_attempts: 3,
+                         ..UploadSpoolCfg::default() }
+    }
+
+    fn upload() -> SpooledUpload {
+        SpooledUpload { ident:             "core/redis/5.0.5/20191021120000".to_string(),
+                        target:            "x86_64-linux".to_string(),
+                        checksum:          "0c7a5e1d".to_string(),
+                        owner_id:          42,
+                        owner_name:        "bobo".to_string(),
+                        build_cache_key:   None,
+                        from_builder:      false,
+                        encryption:        None,
+                        dependency_report: None, }
+    }
+
+    fn archive(dir: &TempDir, name: &str, len: usize) -> PathBuf {
+        let path = dir.path().join(name);
+        fs::write(&path, vec![7u8; len]).unwrap();
+        path
+    }
+
+    #[test]
+    fn spooled_uploads_survive_a_restart() {
+        let dir = tempdir().unwrap();
+        let spool = Spool::open(&cfg(&dir)).unwrap();
+        let uploaded = archive(&dir, "upload.tmp", 100);
+        let entry = spool.spool(upload(), &uploaded, None).unwrap();
+        assert!(!uploaded.exists());
+        assert_eq!(entry.state, IngestionState::Spooled);
+        assert_eq!(entry.size, 100);
+        assert_eq!(entry.origin(), "core");
+
+        let reopened = Spool::open(&cfg(&dir)).unwrap();
+        let entry = reopened.get(&entry.id).unwrap();
+        assert_eq!(entry.state, IngestionState::Spooled);
+        assert_eq!(entry.upload.ident, "core/redis/5.0.5/20191021120000");
+        assert_eq!(fs::read(reopened.archive_path(&entry.id)).unwrap().len(),
+                   100);
+    }
+
+    #[test]
+    fn the_dependency_report_is_kept_with_the_upload() {
+        let dir = tempdir().unwrap();
+        let spool = Spool::open(&cfg(&dir)).unwrap();
+        let report = DependencyReport { missing:    vec!["core/gone".to_string()],
+                                        deprecated: vec![], };
+        let upload = SpooledUpload { dependency_report: Some(report.clone()),
+                                     ..upload() };
+        let entry = spool.spool(upload, &archive(&dir, "upload.tmp", 10), None)
+                         .unwrap();
+
+        let reopened = Spool::open(&cfg(&dir)).unwrap();
+        let entry = reopened.get(&entry.id).unwrap();
+        assert_eq!(entry.upload.dependency_report, Some(report));
+
+        // Entries spooled before the report was kept have none
+        let mut json = serde_json::to_value(&upload()).unwrap();
+        json.as_object_mut().unwrap().remove("dependency_report");
+        let upload = serde_json::from_value::<SpooledUpload>(json).unwrap();
+        assert_eq!(upload.dependency_report, None);
+    }
+
+    #[test]
+    fn a_full_spool_refuses_uploads() {
+        let dir = tempdir().unwrap();
+        let spool = Spool::open(&cfg(&dir)).unwrap();
+        match spool.spool(upload(), &archive(&dir, "big.tmp", 2048), None) {
+            Err(Error::SpoolFull) => (),
+            other => panic!("Expected SpoolFull, got {:?}", other.map(|e| e.id)),
+        }
+
+        let first = spool.spool(upload(), &archive(&dir, "a.tmp", 10), None)
+                         .unwrap();
+        spool.spool(upload(), &archive(&dir, "b.tmp", 10), None)
+             .unwrap();
+        match spool.spool(upload(), &archive(&dir, "c.tmp", 10), None) {
+            Err(Error::SpoolFull) => (),
+            other => panic!("Expected SpoolFull, got {:?}", other.map(|e| e.id)),
+        }
+
+        // A completed ingestion no longer takes room
+        spool.complete(&first.id).unwrap();
+        assert!(!spool.archive_path(&first.id).exists());
+        spool.spool(upload(), &archive(&dir, "c.tmp", 10), None)
+             .unwrap();
+    }
+
+    #[test]
+    fn failed_attempts_are_retried_later() {
+        let dir = tempdir().unwrap();
+        let spool = Spool::open(&cfg(&dir)).unwrap();
+        let entry = spool.spool(upload(), &archive(&dir, "upload.tmp", 10), None)
+                         .unwrap();
+        assert!(spool.due(Utc::now()).is_empty());
+        assert_eq!(spool.due(entry.next_attempt_at).len(), 1);
+
+        spool.retry(&entry.id, &Error::System).unwrap();
+        let retried = spool.get(&entry.id).unwrap();
+        assert_eq!(retried.state, IngestionState::Retrying);
+        assert_eq!(retried.attempts, 1);
+        assert_eq!(retried.last_error, Some("Internal error".to_string()));
+        assert!(retried.next_attempt_at >= retried.updated_at + Duration::seconds(60));
+
+        let status = serde_json::to_value(retried.ingestion()).unwrap();
+        assert_eq!(status["state"], "retrying");
+        assert!(status.get("owner_id").is_none());
+    }
+
+    #[test]
+    fn completed_ingestions_are_pruned_after_their_retention() {
+        let dir = tempdir().unwrap();
+        let spool = Spool::open(&cfg(&dir)).unwrap();
+        let entry = spool.spool(upload(), &archive(&dir, "upload.tmp", 10), None)
+                         .unwrap();
+        spool.complete(&entry.id).unwrap();
+
+        let status = serde_json::to_value(spool.get(&entry.id).unwrap().ingestion()).unwrap();
+        assert_eq!(status["state"], "complete");
+        assert!(status.get("next_attempt_at").is_none());
+
+        spool.prune(Utc::now());
+        assert!(spool.get(&entry.id).is_some());
+        spool.prune(Utc::now() + Duration::days(2));
+        assert!(spool.get(&entry.id).is_none());
+        assert!(Spool::open(&cfg(&dir)).unwrap().get(&entry.id).is_none());
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_its_maximum() {
+        let dir = tempdir().unwrap();
+        let cfg = cfg(&dir);
+        assert_eq!(retry_delay(&cfg, 1), Duration::seconds(30));
+        assert_eq!(retry_delay(&cfg, 2), Duration::seconds(60));
+        assert_eq!(retry_delay(&cfg, 4), Duration::seconds(240));
+        assert_eq!(retry_delay(&cfg, 6), Duration::seconds(600));
+        assert_eq!(retry_delay(&cfg, 100), Duration::seconds(600));
+    }
+
+    #[test]
+    fn only_store_outages_are_spooled() {
+        let dir = tempdir().unwrap();
+        let spool = Spool::open(&cfg(&dir)).unwrap();
+        assert!(!spool.accepts(&Error::Unprocessable));
+        assert!(!spool.accepts(&Error::IO(io::Error::from(io::ErrorKind::NotFound))));
+    }
+
+    #[test]
+    fn uploading_again_while_spooled_is_told_of_the_first_upload() {
+        let dir = tempdir().unwrap();
+        let spool = Spool::open(&cfg(&dir)).unwrap();
+        let first = spool.spool(upload(), &archive(&dir, "a.tmp", 10), None)
+                         .unwrap();
+
+        let again = archive(&dir, "b.tmp", 10);
+        let second = spool.spool(upload(), &again, None).unwrap();
+        assert_eq!(second.id, first.id);
+        assert!(!again.exists());
+        assert_eq!(spool.due(first.next_attempt_at).len(), 1);
+
+        // Another target of the same release is another upload
+        let other = SpooledUpload { target: "x86_64-windows".to_string(),
+                                    ..upload() };
+        let third = spool.spool(other, &archive(&dir, "c.tmp", 10), None)
+                         .unwrap();
+        assert_ne!(third.id, first.id);
+    }
+
+    #[test]
+    fn only_failures_other_than_store_outages_are_given_up_on() {
+        let dir = tempdir().unwrap();
+        let spool = Spool::open(&cfg(&dir)).unwrap();
+        let entry = spool.spool(upload(), &archive(&dir, "upload.tmp", 10), None)
+                         .unwrap();
+        assert!(!spool.gives_up(&entry, &Error::Unprocessable));
+
+        spool.retry(&entry.id, &Error::Unprocessable).unwrap();
+        spool.retry(&entry.id, &Error::Unprocessable).unwrap();
+        let entry = spool.get(&entry.id).unwrap();
+        assert!(spool.gives_up(&entry, &Error::Unprocessable));
+        let outage = Error::Artifactory(ArtifactoryError::ApiError(
+            reqwest::StatusCode::SERVICE_UNAVAILABLE,
+            HashMap::new(),
+        ));
+        assert!(!spool.gives_up(&entry, &outage));
+
+        spool.fail(&entry.id, &Error::Unprocessable).unwrap();
+        let failed = spool.get(&entry.id).unwrap();
+        assert_eq!(failed.state, IngestionState::Failed);
+        assert!(!failed.is_pending());
+        assert!(spool.due(failed.next_attempt_at).is_empty());
+        assert!(!spool.archive_path(&entry.id).exists());
+        assert!(dir.path()
+                   .join("spool/failed")
+                   .join(format!("{}.hart", entry.id))
+                   .exists());
+
+        let reopened = Spool::open(&cfg(&dir)).unwrap();
+        assert_eq!(reopened.get(&entry.id).unwrap().state,
+                   IngestionState::Failed);
+    }
+}
@@ -0,0 +1,523 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fetch-through of packages from an upstream Builder. A package missing locally in one of the
+//! configured origins is looked up upstream, downloaded, checked against the upstream checksum
+//! and ingested as if it had been uploaded, so that the request which missed can be served.
+//! The upstream is not trusted to vouch for its own packages: an archive is only ingested once
+//! its signature verifies against an origin key already present here, unless key imports are
+//! enabled, and fetches are only made on behalf of signed-in requests unless configured so.
+//! Interrupted downloads resume where they stopped, and a background task keeps the channel
+//! heads of fetched packages current.
+
+use std::{collections::HashMap,
+          fs::{self,
+               File,
+               OpenOptions},
+          path::{Path,
+                 PathBuf},
+          sync::{Arc,
+                 Mutex},
+          thread,
+          time::Duration};
+
+use diesel::{pg::PgConnection,
+             result::Error::NotFound};
+use reqwest::{header::{self,
+                       HeaderMap},
+              StatusCode};
+
+use artifactory_client::client::ArtifactoryClient;
+
+use crate::{bldr_core::{http_client::{HttpClient,
+                                      HttpClientFactory},
+                        rpc::RpcClient},
+            config::Config,
+            db::{models::{channel::Channel,
+                          keys::{NewOriginPublicSigningKey,
+                                 OriginPublicSigningKey},
+                          origin::Origin,
+                          package::{BuilderPackageIdent,
+                                    BuilderPackageTarget,
+                                    GetPackage,
+                                    NewPackage,
+                                    NewPackageUpstream,
+                                    Package,
+                                    PackageUpstream,
+                                    PackageVisibility}},
+                 DbPool},
+            hab_core::{crypto::{artifact,
+                                keys::parse_name_with_rev},
+                       package::{FromArchive,
+                                 PackageArchive,
+                                 PackageIdent,
+                                 PackageTarget},
+                       ChannelIdent},
+            protocol::{jobsrv,
+                       originsrv}};
+
+use crate::server::{error::{Error,
+                            Result},
+                    feat,
+                    resources::channels::promote_packages,
+                    services::{artifact_encryption::{self,
+                                                     KeyProvider},
+                               s3::S3Handler}};
+
+/// The parts of an upstream package's metadata needed to fetch it
+#[derive(Debug, Deserialize)]
+struct UpstreamPackage {
+    ident:    PackageIdent,
+    checksum: String,
+}
+
+pub struct Upstream {
+    config:        Config,
+    db:            DbPool,
+    // None while the upstream is disabled, there is no URL to build it for
+    http:          Option<HttpClient>,
+    packages:      S3Handler,
+    artifactory:   ArtifactoryClient,
+    // Fetched artifacts are encrypted under their origin's key like uploads are
+    artifact_keys: Box<dyn KeyProvider>,
+    jobsrv:        Option<RpcClient>,
+    // One lock per package being fetched, so concurrent misses download it once
+    in_flight:     Mutex<HashMap<String, Arc<Mutex<()>>>>,
+}
+
+impl Upstream {
+    pub fn new(config: &Config, db: DbPool, http_clients: &HttpClientFactory) -> Result<Self> {
+        let http = if config.upstream.is_enabled() {
+            let http_clients = http_clients.with_timeout(config.upstream.timeout_sec)?;
+            Some(http_clients.client(&config.upstream.url, HeaderMap::new())?)
+        } else {
+            None
+        };
+        let jobsrv = if feat::is_enabled(feat::Jobsrv) {
+            Some(RpcClient::new(&format!("{}", config.jobsrv)))
+        } else {
+            None
+        };
+
+        Ok(Upstream { config: config.clone(),
+                      db,
+                      http,
+                      packages: S3Handler::new(config.s3.clone()),
+                      artifactory: ArtifactoryClient::new(config.artifactory.clone())?,
+                      artifact_keys: artifact_encryption::key_provider(&config.api),
+                      jobsrv,
+                      in_flight: Mutex::new(HashMap::new()) })
+    }
+
+    /// Whether missing packages in `origin` are fetched from the upstream
+    pub fn serves(&self, origin: &str) -> bool { self.config.upstream.serves(origin) }
+
+    /// Whether a request missing a package in `origin` fetches it from the upstream
+    pub fn fetches_for(&self, origin: &str, authenticated: bool) -> bool {
+        self.config.upstream.fetches_for(origin, authenticated)
+    }
+
+    /// Fetch `ident` from the upstream and ingest it, unless it is already present. An ident
+    /// without a release resolves to the head of the configured channel. Every failure is
+    /// reported as `Error::Upstream` so that clients get a reason rather than a bare 404.
+    pub fn fetch(&self, ident: &PackageIdent, target: PackageTarget) -> Result<()> {
+        let err = match self.fetch_package(ident, target) {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        warn!("Unable to fetch {} ({}) from upstream, err={}",
+              ident, target, err);
+        match err {
+            Error::Upstream(_) => Err(err),
+            err => Err(Error::Upstream(err.to_string())),
+        }
+    }
+
+    fn fetch_package(&self, ident: &PackageIdent, target: PackageTarget) -> Result<()> {
+        let upstream = self.metadata(ident, target)?;
+        let key = format!("{}/{}", upstream.ident, target);
+        let lock = self.lock_for(&key);
+
+        let result = {
+            let _guard = lock.lock().unwrap();
+            self.fetch_locked(ident, &upstream, target)
+        };
+
+        self.release(&key, lock);
+        result
+    }
+
+    fn fetch_locked(&self,
+                    ident: &PackageIdent,
+                    upstream: &UpstreamPackage,
+                    target: PackageTarget)
+                    -> Result<()> {
+        let conn = self.db.get_conn().map_err(Error::DbError)?;
+        let local = GetPackage { ident:      BuilderPackageIdent(upstream.ident.clone()),
+                                 visibility: PackageVisibility::all(),
+                                 target:     BuilderPackageTarget(target), };
+        // A concurrent request may have finished this fetch while we waited
+        match Package::get(local, &*conn) {
+            Ok(_) => (),
+            Err(NotFound) => self.ingest(upstream, target, &*conn)?,
+            Err(err) => return Err(Error::DieselError(err)),
+        }
+
+        if ident.fully_qualified() {
+            Ok(())
+        } else {
+            self.promote(&upstream.ident, target, &*conn)
+        }
+    }
+
+    /// Fetch the current channel head of every package previously fetched from the upstream
+    pub fn refresh(&self) -> Result<()> {
+        let conn = self.db.get_conn().map_err(Error::DbError)?;
+        let names = PackageUpstream::list_names(&self.config.upstream.url, &*conn)
+            .map_err(Error::DieselError)?;
+
+        for (origin, name, target) in names {
+            if !self.serves(&origin) {
+                continue;
+            }
+            let ident = PackageIdent::new(origin, name, None, None);
+            // Failures are logged by fetch, one package should not hold up the rest
+            let _ = self.fetch(&ident, target.0);
+        }
+        Ok(())
+    }
+
+    fn lock_for(&self, key: &str) -> Arc<Mutex<()>> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        in_flight.entry(key.to_string())
+                 .or_insert_with(|| Arc::new(Mutex::new(())))
+                 .clone()
+    }
+
+    // Forget the lock once no other request is waiting on it
+    fn release(&self, key: &str, lock: Arc<Mutex<()>>) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if Arc::strong_count(&lock) <= 2 {
+            in_flight.remove(key);
+        }
+    }
+
+    fn http(&self) -> Result<&HttpClient> {
+        self.http
+            .as_ref()
+            .ok_or_else(|| Error::Upstream("no upstream is configured".to_string()))
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/v1/depot/{}",
+                self.config.upstream.url.trim_end_matches('/'),
+                path)
+    }
+
+    fn metadata(&self, ident: &PackageIdent, target: PackageTarget) -> Result<UpstreamPackage> {
+        let channel = &self.config.upstream.channel;
+        let path = match (&ident.version, ident.fully_qualified()) {
+            (_, true) => format!("pkgs/{}", ident),
+            (Some(version), false) => {
+                format!("channels/{}/{}/pkgs/{}/{}/latest",
+                        ident.origin, channel, ident.name, version)
+            }
+            (None, false) => {
+                format!("channels/{}/{}/pkgs/{}/latest",
+                        ident.origin, channel, ident.name)
+            }
+        };
+
+        let mut res = self.http()?
+                          .get(&self.url(&path))
+                          .query(&[("target", target.to_string())])
+                          .send()
+                          .map_err(Error::HttpClient)?;
+        match res.status() {
+            StatusCode::OK => res.json().map_err(Error::HttpClient),
+            StatusCode::NOT_FOUND => {
+                Err(Error::Upstream(format!("{} ({}) not found upstream", ident, target)))
+            }
+            status => {
+                let reason = format!("upstream lookup of {} returned {}", ident, status);
+                Err(Error::Upstream(reason))
+            }
+        }
+    }
+
+    fn ingest(&self,
+              upstream: &UpstreamPackage,
+              target: PackageTarget,
+              conn: &PgConnection)
+              -> Result<()> {
+        let ident = &upstream.ident;
+        let owner_id = match Origin::get(&ident.origin, conn) {
+            Ok(origin) => origin.owner_id,
+            Err(NotFound) => {
+                let reason = format!("origin {} does not exist on this Builder", ident.origin);
+                return Err(Error::Upstream(reason));
+            }
+            Err(err) => return Err(Error::DieselError(err)),
+        };
+
+        let archive_name = ident.archive_name_with_target(target)?;
+        let data_path = &self.config.api.data_path;
+        let partial_path = data_path.join(format!("{}.part", archive_name));
+        let archive_path = data_path.join(&archive_name);
+
+        self.download(ident, target, &partial_path)?;
+        self.verify(upstream, target, &partial_path)?;
+        fs::rename(&partial_path, &archive_path)?;
+
+        let result = self.store(upstream, target, owner_id, &archive_path, conn);
+        if let Err(err) = fs::remove_file(&archive_path) {
+            warn!("Unable to remove fetched archive {:?}, err={}",
+                  archive_path, err);
+        }
+        result
+    }
+
+    // Download the archive to `path`, resuming from whatever an earlier attempt left there
+    fn download(&self, ident: &PackageIdent, target: PackageTarget, path: &PathBuf) -> Result<()> {
+        let offset = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+
+        let mut request = self.http()?
+                              .get(&self.url(&format!("pkgs/{}/download", ident)))
+                              .query(&[("target", target.to_string())]);
+        if offset > 0 {
+            debug!("Resuming upstream download of {} at {} bytes",
+                   ident, offset);
+            request = request.header(header::RANGE, format!("bytes={}-", offset));
+        }
+
+        let mut res = request.send().map_err(Error::HttpClient)?;
+        let mut file = match res.status() {
+            StatusCode::PARTIAL_CONTENT => OpenOptions::new().append(true).open(path)?,
+            // The upstream ignored the range, start over
+            StatusCode::OK => File::create(path)?,
+            // Everything was already downloaded by an earlier attempt
+            StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => return Ok(()),
+            status => {
+                let reason = format!("upstream download of {} returned {}", ident, status);
+                return Err(Error::Upstream(reason));
+            }
+        };
+        res.copy_to(&mut file).map_err(Error::HttpClient)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    // A corrupt download is discarded so the next attempt starts over
+    fn verify(&self,
+              upstream: &UpstreamPackage,
+              target: PackageTarget,
+              path: &PathBuf)
+              -> Result<()> {
+        let mut archive = PackageArchive::new(path);
+        let checksum = archive.checksum()?;
+        let reason = if checksum != upstream.checksum {
+            format!("checksum mismatch for {}, expected={}, got={}",
+                    upstream.ident, upstream.checksum, checksum)
+        } else if archive.ident()? != upstream.ident {
+            format!("the archive downloaded for {} is not that package",
+                    upstream.ident)
+        } else if archive.target()? != target {
+            format!("{} is not built for {}", upstream.ident, target)
+        } else {
+            return Ok(());
+        };
+        fs::remove_file(path)?;
+        Err(Error::Upstream(reason))
+    }
+
+    fn store(&self,
+             upstream: &UpstreamPackage,
+             target: PackageTarget,
+             owner_id: i64,
+             archive_path: &PathBuf,
+             conn: &PgConnection)
+             -> Result<()> {
+        let ident = &upstream.ident;
+        let key = self.signing_key(archive_path, owner_id, conn)?;
+        self.verify_signature(archive_path, &key)?;
+
+        // Fetched artifacts are stored as uploaded ones are, encrypted when the origin asks
+        let encrypted = artifact_encryption::encrypt_for_origin(&*self.artifact_keys,
+                                                                &ident.origin,
+                                                                archive_path,
+                                                                conn)?;
+        let stored_path = encrypted.as_ref().map_or(archive_path, |e| &e.path);
+        let stored = if feat::is_enabled(feat::Artifactory) {
+            self.artifactory
+                .upload(stored_path, ident, target)
+                .map(|_| ())
+                .map_err(Error::Artifactory)
+        } else {
+            self.packages.upload(stored_path, ident, target)
+        };
+        if let Some(ref encrypted) = encrypted {
+            if let Err(err) = fs::remove_file(&encrypted.path) {
+                warn!("Unable to remove encrypted archive {:?}, err={}",
+                      encrypted.path, err);
+            }
+        }
+        stored?;
+        artifact_encryption::record(ident, target, &upstream.checksum, encrypted.as_ref(), conn)?;
+
+        let mut archive = PackageArchive::new(archive_path.clone());
+        let mut package = NewPackage::from_archive(&mut archive)?;
+        package.owner_id = owner_id;
+        package.origin = ident.origin.clone();
+        package.visibility = PackageVisibility::Public;
+
+        let pkg = Package::create(&package, conn).map_err(Error::DieselError)?;
+        let provenance = NewPackageUpstream { package_id:   pkg.id,
+                                              origin:       &ident.origin,
+                                              name:         &ident.name,
+                                              target:       BuilderPackageTarget(target),
+                                              upstream_url: &self.config.upstream.url,
+                                              channel:      &self.config.upstream.channel, };
+        PackageUpstream::create(&provenance, conn).map_err(Error::DieselError)?;
+        info!("Fetched {} ({}) from upstream {}",
+              ident, target, self.config.upstream.url);
+
+        if let Some(ref client) = self.jobsrv {
+            let mut job_graph_package = jobsrv::JobGraphPackageCreate::new();
+            job_graph_package.set_package(pkg.into());
+            if let Err(err) = client.rpc::<_, originsrv::OriginPackage>(&job_graph_package) {
+                warn!("Unable to add fetched package {} to the build graph, err={}",
+                      ident, err);
+            }
+        }
+        Ok(())
+    }
+
+    // The public key that signed the archive, which must already be present here unless keys
+    // are imported from the upstream
+    fn signing_key(&self,
+                   archive_path: &PathBuf,
+                   owner_id: i64,
+                   conn: &PgConnection)
+                   -> Result<OriginPublicSigningKey> {
+        let key_name = artifact::get_artifact_header(archive_path)?.key_name;
+        let (origin, revision) = parse_name_with_rev(&key_name)?;
+        match OriginPublicSigningKey::get(&origin, &revision, conn) {
+            Ok(key) => return Ok(key),
+            Err(NotFound) => (),
+            Err(err) => return Err(Error::DieselError(err)),
+        }
+        if !self.config.upstream.import_keys {
+            let reason = format!("signing key {} is not trusted here, upload it before fetching \
+                                  packages signed with it",
+                                 key_name);
+            return Err(Error::Upstream(reason));
+        }
+
+        let mut res = self.http()?
+                          .get(&self.url(&format!("origins/{}/keys/{}", origin, revision)))
+                          .send()
+                          .map_err(Error::HttpClient)?;
+        if res.status() != StatusCode::OK {
+            return Err(Error::Upstream(format!("upstream key {} returned {}",
+                                               key_name,
+                                               res.status())));
+        }
+        let body = res.text().map_err(Error::HttpClient)?.into_bytes();
+
+        let new_pk = NewOriginPublicSigningKey { owner_id,
+                                                 origin: &origin,
+                                                 full_name: &key_name,
+                                                 name: &origin,
+                                                 revision: &revision,
+                                                 body: &body };
+        OriginPublicSigningKey::create(&new_pk, conn).map_err(Error::DieselError)
+    }
+
+    // The checksum only proves the archive is what the upstream served, the signature proves
+    // who built it
+    fn verify_signature(&self, archive_path: &PathBuf, key: &OriginPublicSigningKey) -> Result<()> {
+        let key_dir = key_dir(&self.config.api.data_path);
+        fs::create_dir_all(&key_dir)?;
+        fs::write(key_dir.join(format!("{}.pub", key.full_name)), &key.body)?;
+
+        match artifact::verify(archive_path, &key_dir) {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                let reason = format!("signature of {} does not verify against {}, {}",
+                                     archive_path.display(),
+                                     key.full_name,
+                                     err);
+                Err(Error::Upstream(reason))
+            }
+        }
+    }
+
+    // Channel heads follow the upstream, a channel missing locally is left alone and so is a
+    // package pinned in it
+    fn promote(&self,
+               ident: &PackageIdent,
+               target: PackageTarget,
+               conn: &PgConnection)
+               -> Result<()> {
+        let channel = ChannelIdent::from(self.config.upstream.channel.as_str());
+        let channel = match Channel::get(&ident.origin, &channel, conn) {
+            Ok(channel) => channel,
+            Err(NotFound) => {
+                warn!("Channel {} does not exist in origin {}, {} was not promoted",
+                      self.config.upstream.channel, ident.origin, ident);
+                return Ok(());
+            }
+            Err(err) => return Err(Error::DieselError(err)),
+        };
+        let package = GetPackage { ident:      BuilderPackageIdent(ident.clone()),
+                                   visibility: PackageVisibility::all(),
+                                   target:     BuilderPackageTarget(target), };
+        let package = Package::get(package, conn).map_err(Error::DieselError)?;
+
+        // A pin on the package, or in a rolling channel on the head the new one replaces, leaves
+        // the channel as it is
+        match promote_packages(&channel, &[package.id], &[(ident.clone(), target)], conn) {
+            Ok(_) => Ok(()),
+            Err(Error::PackagePinned(pin)) => {
+                info!("Not promoting {} from upstream, {} is pinned in {}",
+                      ident, pin.ident, pin.channel);
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+// Where the keys fetched archives are verified against are written out
+fn key_dir(data_path: &Path) -> PathBuf { data_path.join("upstream-keys") }
+
+/// Refresh the channel heads of fetched packages in the background
+pub fn start(upstream: Arc<Upstream>) {
+    let interval = upstream.config.upstream.refresh_interval_sec;
+    if !upstream.config.upstream.is_enabled() || interval == 0 {
+        info!("Upstream channel refresh disabled");
+        return;
+    }
+
+    thread::Builder::new().name("upstream-refresh".to_string())
+                          .spawn(move || {
+                              loop {
+                                  thread::sleep(Duration::from_secs(interval));
+                                  if let Err(err) = upstream.refresh() {
+                                      warn!("Unable to refresh upstream packages, err={}", err);
+                                  }
+                              }
+                          })
+                          .expect("Unable to start upstream refresh thread");
+}
@@ -0,0 +1,170 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Webhooks for generic git projects. Any hosting service can call one: the body is never
+//! parsed, the request only has to be signed with the secret set on the project, the same way
+//! GitHub signs its hooks. The secret is stored encrypted with the builder key.
+
+use std::str::FromStr;
+
+use actix_web::{HttpRequest,
+                HttpResponse};
+use hex;
+use openssl::{hash::MessageDigest,
+              pkey::PKey,
+              sign::Signer};
+
+use crate::{bldr_core::{self,
+                        metrics::CounterMetric},
+            hab_core::{crypto,
+                       package::PackageTarget},
+            protocol::jobsrv::{JobGroup,
+                               JobGroupSpec,
+                               JobGroupTrigger}};
+
+use crate::db::models::projects::Project;
+
+use crate::server::{error::Error,
+                    feat,
+                    framework::{headers,
+                                middleware::route_message},
+                    helpers::req_state,
+                    services::metrics::Counter};
+
+#[derive(Deserialize)]
+pub struct WebhookTarget {
+    #[serde(default = "default_target")]
+    pub target: String,
+}
+
+fn default_target() -> String { "x86_64-linux".to_string() }
+
+pub fn handle_event(req: &HttpRequest,
+                    origin: &str,
+                    name: &str,
+                    target: &str,
+                    body: &str)
+                    -> HttpResponse {
+    Counter::WebhookEvent.increment();
+
+    let hook_signature = match req.headers().get(headers::XBUILDERSIGNATURE) {
+        Some(sig) => sig.clone(),
+        None => {
+            warn!("Received a webhook for {}/{} with no signature",
+                  origin, name);
+            return Error::BadRequest.into();
+        }
+    };
+
+    let conn = match req_state(req).db.get_conn() {
+        Ok(conn_ref) => conn_ref,
+        Err(e) => return Error::DbError(e).into(),
+    };
+
+    let project = match Project::get(&format!("{}/{}", origin, name), &*conn) {
+        Ok(project) => project,
+        Err(err) => {
+            debug!("Failed to fetch project for webhook: {}/{}, {:?}",
+                   origin, name, err);
+            return Error::DieselError(err).into();
+        }
+    };
+
+    // Projects without a secret, GitHub ones included, can't be triggered from here
+    let secret = match project.webhook_secret {
+        Some(ref secret) if project.vcs_type == "generic_git" => secret,
+        _ => {
+            warn!("Received a webhook for {} which has no webhook secret",
+                  project.name);
+            return Error::NotFound.into();
+        }
+    };
+
+    let secret = match bldr_core::integrations::decrypt(&req_state(req).config.api.key_path,
+                                                        secret).map(String::from_utf8)
+    {
+        Ok(Ok(secret)) => secret,
+        _ => {
+            warn!("Unable to decrypt the webhook secret of {}", project.name);
+            return Error::BadRequest.into();
+        }
+    };
+
+    if !crypto::secure_eq(&hook_signature, &signature(&secret, body)) {
+        warn!("Webhook signature for {} doesn't match", project.name);
+        return Error::BadRequest.into();
+    }
+
+    let target = match PackageTarget::from_str(target) {
+        Ok(target) => target,
+        Err(_) => {
+            debug!("Invalid target received: {}", target);
+            return Error::BadRequest.into();
+        }
+    };
+
+    if !req_state(req).config.api.build_targets.contains(&target) {
+        debug!("Rejecting build with target: {}", target);
+        return Error::BadRequest.into();
+    }
+
+    if !feat::is_enabled(feat::Jobsrv) {
+        debug!("Skipping scheduling build for {} (jobsrv disabled)",
+               project.name);
+        return HttpResponse::NoContent().finish();
+    }
+
+    let mut request = JobGroupSpec::new();
+    request.set_origin(project.origin.clone());
+    request.set_package(project.package_name.clone());
+    request.set_target(target.to_string());
+    request.set_trigger(JobGroupTrigger::Webhook);
+
+    match route_message::<JobGroupSpec, JobGroup>(req, &request) {
+        Ok(group) => {
+            debug!("JobGroup created, {:?}", group);
+            HttpResponse::Created().json(group)
+        }
+        Err(err) => {
+            debug!("Failed to create group, {:?}", err);
+            err.into()
+        }
+    }
+}
+
+/// The signature a webhook body carries, an HMAC of it keyed with the secret. GitHub signs its
+/// hooks the same way.
+pub fn signature(secret: &str, body: &str) -> String {
+    let key = PKey::hmac(secret.as_bytes()).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+    signer.update(body.as_bytes()).unwrap();
+    let hmac = signer.sign_to_vec().unwrap();
+    format!("sha256={}", &hex::encode(hmac))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_is_hmac_sha256_of_body() {
+        assert_eq!(signature("key", "The quick brown fox jumps over the lazy dog"),
+                   "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8");
+    }
+
+    #[test]
+    fn signature_depends_on_secret() {
+        assert_ne!(signature("secret", "{}"), signature("other", "{}"));
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Expiry of ephemeral channels against a live database. See
+//! `components/builder-db/tests/db/README.md` for running them.
+
+#[path = "../../builder-db/tests/common/mod.rs"]
+mod common;
+
+use std::str::FromStr;
+
+use chrono::{Duration,
+             NaiveDate,
+             NaiveDateTime};
+use diesel::{pg::PgConnection,
+             result::Error::NotFound};
+
+use habitat_builder_api::server::services::ephemeral_channels;
+use habitat_builder_db::models::{channel::{Channel,
+                                           ChannelAudit,
+                                           ChannelEvent,
+                                           ChannelOperation,
+                                           ChannelPackagePin,
+                                           ChannelRelease,
+                                           CreateChannel,
+                                           NewChannelPackagePin},
+                                 origin::{NewOrigin,
+                                          Origin},
+                                 package::{BuilderPackageIdent,
+                                           BuilderPackageTarget,
+                                           NewPackage,
+                                           Package,
+                                           PackageVisibility}};
+use habitat_core::{package::{PackageIdent,
+                             PackageTarget},
+                   ChannelIdent};
+
+const OWNER_ID: i64 = 1;
+const ZLIB: &str = "core/zlib/1.2.11/20190115003728";
+
+fn setup() -> (PgConnection, Package) {
+    let conn = common::connection();
+
+    Origin::create(&NewOrigin { name: "core",
+                                owner_id: OWNER_ID,
+                                default_package_visibility: &PackageVisibility::Public, },
+                   &conn).unwrap();
+    let package = Package::create(&new_package(ZLIB), &conn).unwrap();
+    (conn, package)
+}
+
+fn new_package(ident: &str) -> NewPackage {
+    let ident = PackageIdent::from_str(ident).unwrap();
+    let target = PackageTarget::from_str("x86_64-linux").unwrap();
+    NewPackage { origin:          ident.origin.clone(),
+                 owner_id:        OWNER_ID,
+                 name:            ident.name.clone(),
+                 normalized_name: ident.name.clone(),
+                 ident_array:     ident.to_string().split('/').map(str::to_string).collect(),
+                 ident:           BuilderPackageIdent(ident),
+                 checksum:        String::from("checksum"),
+                 manifest:        String::new(),
+                 config:          String::new(),
+                 target:          BuilderPackageTarget(target),
+                 deps:            vec![],
+                 tdeps:           vec![],
+                 build_deps:      vec![],
+                 build_tdeps:     vec![],
+                 exposes:         vec![],
+                 visibility:      PackageVisibility::Public, }
+}
+
+// Whole seconds, so that times read back compare equal
+fn now() -> NaiveDateTime { NaiveDate::from_ymd(2019, 10, 21).and_hms(12, 0, 0) }
+
+// An ephemeral channel expiring `hours` from `now`, holding `package` pinned
+fn ephemeral(conn: &PgConnection, name: &str, hours: i64, package: &Package) -> Channel {
+    let channel = Channel::create_ephemeral(&CreateChannel { name,
+                                                             owner_id: OWNER_ID,
+                                                             origin: "core" },
+                                            now() + Duration::hours(hours),
+                                            conn).unwrap();
+    Channel::promote_packages(channel.id, &[package.id], conn).unwrap();
+    let pin = NewChannelPackagePin { origin:         "core",
+                                     channel:        name,
+                                     name:           "zlib",
+                                     target:         "x86_64-linux",
+                                     ident:          ZLIB,
+                                     pinned_by_id:   OWNER_ID,
+                                     pinned_by_name: "bobo", };
+    ChannelPackagePin::create(&pin, conn).unwrap();
+    channel
+}
+
+fn expire(channel: &Channel, conn: &PgConnection) -> Option<Vec<ChannelRelease>> {
+    ephemeral_channels::expire_channel(channel, now(), conn).unwrap()
+}
+
+fn events(conn: &PgConnection) -> Vec<ChannelEvent> {
+    ChannelAudit::list("core", &ChannelIdent::from("feature-a"), conn).unwrap()
+}
+
+#[test]
+#[ignore]
+fn expired_channels_are_deleted_with_their_contents() {
+    let (conn, package) = setup();
+    let channel = ephemeral(&conn, "feature-a", -1, &package);
+    let ident = ChannelIdent::from("feature-a");
+
+    let releases = expire(&channel, &conn).unwrap();
+    assert_eq!(releases.iter().map(|r| r.package_id).collect::<Vec<_>>(),
+               vec![package.id]);
+    assert_eq!(Channel::get("core", &ident, &conn).unwrap_err(), NotFound);
+    assert!(Channel::list_releases(channel.id, &conn).unwrap()
+                                                     .is_empty());
+    assert!(ChannelPackagePin::list("core", &ident, &conn).unwrap()
+                                                          .is_empty());
+
+    // The feed keeps the expiry, with what the channel held
+    let events = events(&conn);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].operation, ChannelOperation::Expire);
+    assert_eq!(events[0].package_ids, vec![package.id]);
+    assert_eq!(events[0].requester_name,
+               ephemeral_channels::SYSTEM_REQUESTER);
+}
+
+#[test]
+#[ignore]
+fn channels_are_expired_once() {
+    let (conn, package) = setup();
+    let channel = ephemeral(&conn, "feature-a", -1, &package);
+
+    assert!(expire(&channel, &conn).is_some());
+    // As when another node expired it after both listed it
+    assert!(expire(&channel, &conn).is_none());
+    assert_eq!(events(&conn).len(), 1);
+}
+
+#[test]
+#[ignore]
+fn channels_renewed_since_they_were_listed_are_kept() {
+    let (conn, package) = setup();
+    let channel = ephemeral(&conn, "feature-a", -1, &package);
+    let ident = ChannelIdent::from("feature-a");
+    Channel::renew(channel.id, now() + Duration::days(14), &conn).unwrap();
+
+    assert!(expire(&channel, &conn).is_none());
+    assert!(Channel::get("core", &ident, &conn).is_ok());
+    assert_eq!(Channel::list_releases(channel.id, &conn).unwrap().len(), 1);
+    assert!(events(&conn).is_empty());
+}
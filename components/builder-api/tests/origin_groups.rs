@@ -0,0 +1,153 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Origin membership synced from provider groups at sign in, against a live database. See
+//! `components/builder-db/tests/db/README.md` for running them.
+
+#[path = "../../builder-db/tests/common/mod.rs"]
+mod common;
+
+use diesel::pg::PgConnection;
+
+use habitat_builder_api::{config::GroupOriginsCfg,
+                          server::services::origin_groups::{self,
+                                                            Changes}};
+use habitat_builder_db::models::{account::{Account,
+                                           AccountSecurityEvent,
+                                           AccountSecurityEventKind,
+                                           ListAccountSecurityEvents,
+                                           NewAccount},
+                                 origin::{AccountMembership,
+                                          NewOrigin,
+                                          Origin,
+                                          OriginMember},
+                                 package::PackageVisibility};
+use oauth_client::types::OAuth2User;
+
+// bobo owns every origin, mystique is a member of acme and personal
+fn setup() -> (PgConnection, Account, Account) {
+    let conn = common::connection();
+
+    let owner = Account::create(&NewAccount { email: "bobo@example.com",
+                                              name:  "bobo", },
+                                &conn).unwrap();
+    let member = Account::create(&NewAccount { email: "mystique@example.com",
+                                               name:  "mystique", },
+                                 &conn).unwrap();
+    for origin in &["acme", "core", "core-testing", "personal"] {
+        Origin::create(&NewOrigin { name: origin,
+                                    owner_id: owner.id,
+                                    default_package_visibility: &PackageVisibility::Public, },
+                       &conn).unwrap();
+    }
+    OriginMember::add("acme", member.id, &conn).unwrap();
+    OriginMember::add("personal", member.id, &conn).unwrap();
+    (conn, owner, member)
+}
+
+fn mappings() -> Vec<GroupOriginsCfg> {
+    vec![GroupOriginsCfg { group:   "builder-core".to_string(),
+                           origins: vec!["core".to_string(), "core-testing".to_string()], },
+         GroupOriginsCfg { group:   "acme-devs".to_string(),
+                           origins: vec!["acme".to_string(), "ghost".to_string()], },]
+}
+
+// The user `account` signs in as, in `groups`
+fn user(account: &Account, groups: &[&str]) -> OAuth2User {
+    OAuth2User { id:             account.id.to_string(),
+                 username:       account.name.clone(),
+                 email:          None,
+                 email_verified: None,
+                 groups:         Some(strings(groups)), }
+}
+
+fn strings(values: &[&str]) -> Vec<String> { values.iter().map(|v| v.to_string()).collect() }
+
+fn origins(account: &Account, conn: &PgConnection) -> Vec<String> {
+    let mut origins: Vec<String> = AccountMembership::list(account.id, conn).unwrap()
+                                                                            .into_iter()
+                                                                            .map(|m| m.origin)
+                                                                            .collect();
+    origins.sort();
+    origins
+}
+
+fn events(account: &Account, conn: &PgConnection) -> Vec<(AccountSecurityEventKind, String)> {
+    let list = ListAccountSecurityEvents { account_id: account.id,
+                                           page:       1,
+                                           limit:      50, };
+    let mut events: Vec<(AccountSecurityEventKind, String)> =
+        AccountSecurityEvent::list(list, conn).unwrap()
+                                              .0
+                                              .into_iter()
+                                              .map(|e| (e.kind, e.origin.unwrap_or_default()))
+                                              .collect();
+    events.sort_by(|a, b| a.1.cmp(&b.1));
+    events
+}
+
+#[test]
+#[ignore]
+fn signing_in_joins_and_leaves_the_mapped_origins() {
+    let (conn, _, member) = setup();
+    let changes = origin_groups::sign_in(&mappings(),
+                                         &member,
+                                         &user(&member, &["builder-core"]),
+                                         &conn).unwrap();
+
+    assert_eq!(changes,
+               Changes { added:   strings(&["core", "core-testing"]),
+                         removed: strings(&["acme"]), });
+    // Origins no group maps to are left to invitations
+    assert_eq!(origins(&member, &conn),
+               strings(&["core", "core-testing", "personal"]));
+    assert_eq!(events(&member, &conn),
+               vec![(AccountSecurityEventKind::OriginMemberRemoved, "acme".to_string()),
+                    (AccountSecurityEventKind::OriginMemberAdded, "core".to_string()),
+                    (AccountSecurityEventKind::OriginMemberAdded, "core-testing".to_string()),]);
+}
+
+#[test]
+#[ignore]
+fn owners_and_missing_origins_are_left_alone() {
+    let (conn, owner, _) = setup();
+    let changes =
+        origin_groups::sign_in(&mappings(), &owner, &user(&owner, &["acme-devs"]), &conn).unwrap();
+    assert_eq!(changes, Changes::default());
+
+    let changes = origin_groups::sign_in(&mappings(), &owner, &user(&owner, &[]), &conn).unwrap();
+    assert_eq!(changes, Changes::default());
+    assert_eq!(origins(&owner, &conn),
+               strings(&["acme", "core", "core-testing", "personal"]));
+    assert!(events(&owner, &conn).is_empty());
+}
+
+#[test]
+#[ignore]
+fn signing_in_without_groups_changes_nothing() {
+    let (conn, _, member) = setup();
+    // The provider doesn't read the user's groups
+    let changes = origin_groups::sign_in(&mappings(),
+                                         &member,
+                                         &OAuth2User { groups: None,
+                                                       ..user(&member, &[]) },
+                                         &conn).unwrap();
+    assert_eq!(changes, Changes::default());
+    // No group is mapped
+    let changes = origin_groups::sign_in(&[], &member, &user(&member, &[]), &conn).unwrap();
+    assert_eq!(changes, Changes::default());
+
+    assert_eq!(origins(&member, &conn), strings(&["acme", "personal"]));
+    assert!(events(&member, &conn).is_empty());
+}
@@ -15,6 +15,7 @@
 use std::path::PathBuf;
 
 use chrono::{self,
+             DateTime,
              Duration,
              LocalResult::Single,
              TimeZone,
@@ -47,11 +48,16 @@ pub fn generate_bldr_token(key_dir: &PathBuf) -> Result<String> {
                           Duration::hours(BUILDER_TOKEN_LIFETIME_HOURS))
 }
 
-pub fn generate_user_token(key_dir: &PathBuf, account_id: u64, privileges: u32) -> Result<String> {
+pub fn generate_user_token(key_dir: &PathBuf,
+                           account_id: u64,
+                           privileges: u32,
+                           lifetime: Option<Duration>)
+                           -> Result<String> {
+    // User tokens without a lifetime never expire, and can only be revoked
     generate_access_token(key_dir,
                           account_id,
                           privileges,
-                          Duration::max_value() /* User tokens never expire, can only be revoked */)
+                          lifetime.unwrap_or_else(Duration::max_value))
 }
 
 pub fn generate_access_token(key_dir: &PathBuf,
@@ -95,7 +101,7 @@ pub fn validate_access_token(key_dir: &PathBuf, token: &str) -> Result<originsrv
 
     match Utc.timestamp_opt(payload.get_expires(), 0 /* nanoseconds */) {
         Single(expires) => {
-            if expires < Utc::now() {
+            if is_expired(expires, Utc::now()) {
                 Err(Error::TokenExpired)
             } else {
                 Ok(payload.into())
@@ -104,3 +110,25 @@ pub fn validate_access_token(key_dir: &PathBuf, token: &str) -> Result<originsrv
         _ => Err(Error::TokenInvalid),
     }
 }
+
+/// A token is considered expired starting at the second it expires.
+pub fn is_expired(expires: DateTime<Utc>, now: DateTime<Utc>) -> bool { now >= expires }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn token_valid_before_expiry() {
+        let expires = Utc.timestamp(1_561_939_200, 0);
+        let now = expires - Duration::seconds(1);
+        assert!(!is_expired(expires, now));
+    }
+
+    #[test]
+    fn token_expired_at_expiry_second() {
+        let expires = Utc.timestamp(1_561_939_200, 0);
+        assert!(is_expired(expires, expires));
+        assert!(is_expired(expires, expires + Duration::seconds(1)));
+    }
+}
@@ -25,14 +25,20 @@ use rand::{distributions::Alphanumeric,
            thread_rng,
            Rng};
 
-use reqwest::{header::HeaderMap,
+use reqwest::{header::{HeaderMap,
+                       CONTENT_TYPE},
               Body,
               Response,
               StatusCode};
 
 use serde_json;
 
-use crate::{error::{Error,
+use crate::{build_info::BuildInfo,
+            channel_sync::{self,
+                           InventoryEntry,
+                           SyncDiff,
+                           SyncDiffRequest},
+            error::{Error,
                     Result},
             hab_core::{package::{self,
                                  Identifiable,
@@ -41,8 +47,8 @@ use crate::{error::{Error,
                        ChannelIdent}};
 
 use crate::http_client::{HttpClient,
+                         HttpClientFactory,
                          ACCEPT_APPLICATION_JSON,
-                         USER_AGENT_BLDR,
                          XFILENAME};
 
 #[derive(Clone, Deserialize)]
@@ -76,6 +82,22 @@ pub struct Package {
     pub config:      String,
 }
 
+/// A public signing key of an origin as listed by the depot
+#[derive(Clone, Deserialize)]
+pub struct OriginKey {
+    pub origin:   String,
+    pub revision: String,
+    /// Rotated out and past its overlap window, no longer accepted for signing
+    #[serde(default)]
+    pub expired:  bool,
+}
+
+#[derive(Serialize)]
+struct VerificationFailure<'a> {
+    key_name: &'a str,
+    reason:   &'a str,
+}
+
 #[derive(Clone)]
 pub struct ApiClient {
     inner:   HttpClient,
@@ -83,14 +105,35 @@ pub struct ApiClient {
 }
 
 impl ApiClient {
-    pub fn new(url: &str) -> Result<Self> {
-        let header_values = vec![USER_AGENT_BLDR.clone(), ACCEPT_APPLICATION_JSON.clone()];
+    pub fn new(url: &str) -> Result<Self> { Self::with_factory(url, &HttpClientFactory::default()) }
+
+    pub fn with_factory(url: &str, http: &HttpClientFactory) -> Result<Self> {
+        let header_values = vec![ACCEPT_APPLICATION_JSON.clone()];
         let headers = HeaderMap::from_iter(header_values.into_iter());
 
-        Ok(ApiClient { inner: HttpClient::new(url, headers)?,
+        Ok(ApiClient { inner: http.client(url, headers)?,
                        url:   url.to_owned(), })
     }
 
+    /// What the depot's API server was built from
+    pub fn server_version(&self) -> Result<BuildInfo> {
+        let url_path = format!("{}/v1/version", self.url);
+        let mut resp = self.inner
+                           .get(&url_path)
+                           .send()
+                           .map_err(Error::HttpClient)?;
+
+        let mut body = String::new();
+        resp.read_to_string(&mut body).map_err(Error::IO)?;
+        debug!("Body: {:?}", body);
+
+        if resp.status() != StatusCode::OK {
+            return Err(err_from_response(resp));
+        }
+
+        serde_json::from_str::<BuildInfo>(&body).map_err(Error::Serialization)
+    }
+
     pub fn show_package<I>(&self,
                            package: &I,
                            channel: &ChannelIdent,
@@ -130,6 +173,40 @@ impl ApiClient {
         Ok(package)
     }
 
+    /// Returns the package recorded for a build cache key, or `None` if nothing has been built
+    /// for the key yet.
+    pub fn show_build_cache_package(&self,
+                                    key: &str,
+                                    target: &str,
+                                    token: Option<&str>)
+                                    -> Result<Option<Package>> {
+        let url_path = format!("{}/v1/{}", self.url, build_cache_path(key));
+        let mut query = HashMap::new();
+        query.insert("target", target);
+
+        let mut request = self.inner.get(&url_path).query(&query);
+
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+
+        let mut resp = request.send().map_err(Error::HttpClient)?;
+
+        match resp.status() {
+            StatusCode::OK => (),
+            StatusCode::NOT_FOUND => return Ok(None),
+            _ => return Err(err_from_response(resp)),
+        }
+
+        let mut body = String::new();
+        resp.read_to_string(&mut body).map_err(Error::IO)?;
+        debug!("Body: {:?}", body);
+
+        let package: Package =
+            serde_json::from_str::<Package>(&body).map_err(Error::Serialization)?;
+        Ok(Some(package))
+    }
+
     pub fn fetch_package<I, P>(&self,
                                ident: &I,
                                target: &str,
@@ -198,7 +275,11 @@ impl ApiClient {
         Ok(dst_file_path)
     }
 
-    pub fn x_put_package(&self, pa: &mut PackageArchive, token: &str) -> Result<()> {
+    pub fn x_put_package(&self,
+                         pa: &mut PackageArchive,
+                         build_cache_key: Option<&str>,
+                         token: &str)
+                         -> Result<()> {
         let checksum = pa.checksum()?;
         let ident = pa.ident()?;
         let target = pa.target()?;
@@ -211,6 +292,9 @@ impl ApiClient {
         qparams.insert("checksum", &checksum);
         qparams.insert("target", &target);
         qparams.insert("builder", "");
+        if let Some(key) = build_cache_key {
+            qparams.insert("build_cache_key", key);
+        }
 
         debug!("Reading from {}", &pa.path.display());
 
@@ -246,6 +330,81 @@ impl ApiClient {
                       Some(token))
     }
 
+    pub fn fetch_origin_public_key<P>(&self,
+                                      origin: &str,
+                                      revision: &str,
+                                      token: Option<&str>,
+                                      dst_path: P)
+                                      -> Result<PathBuf>
+        where P: AsRef<Path>
+    {
+        self.download(&origin_keys_revision(origin, revision),
+                      &HashMap::new(),
+                      dst_path.as_ref(),
+                      token)
+    }
+
+    /// Lists the public signing keys of `origin`, including the expired ones
+    pub fn list_origin_keys(&self, origin: &str, token: Option<&str>) -> Result<Vec<OriginKey>> {
+        let url_path = format!("{}/v1/{}", self.url, origin_keys(origin));
+
+        let mut request = self.inner.get(&url_path);
+
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+
+        let mut resp = request.send().map_err(Error::HttpClient)?;
+
+        if resp.status() != StatusCode::OK {
+            return Err(err_from_response(resp));
+        }
+
+        let mut body = String::new();
+        resp.read_to_string(&mut body).map_err(Error::IO)?;
+        debug!("Body: {:?}", body);
+
+        serde_json::from_str::<Vec<OriginKey>>(&body).map_err(Error::Serialization)
+    }
+
+    /// Records that the package was uploaded but not promoted, as its signature did not verify
+    /// against a key of its origin still accepted for signing
+    pub fn report_verification_failure<I>(&self,
+                                          (ident, target): (&I, PackageTarget),
+                                          key_name: &str,
+                                          reason: &str,
+                                          token: &str)
+                                          -> Result<()>
+        where I: Identifiable
+    {
+        let url_path = format!("{}/v1/{}/verification_failure",
+                               self.url,
+                               package_path(ident));
+        debug!("Reporting verification failure of {}, target {}",
+               ident, target);
+
+        let mut qparams: HashMap<&str, &str> = HashMap::new();
+        qparams.insert("target", &target);
+
+        let body = serde_json::to_string(&VerificationFailure { key_name, reason })
+            .map_err(Error::Serialization)?;
+
+        let resp = self.inner
+                       .post(&url_path)
+                       .query(&qparams)
+                       .bearer_auth(token)
+                       .header(CONTENT_TYPE, "application/json")
+                       .body(body)
+                       .send()
+                       .map_err(Error::HttpClient)?;
+
+        if resp.status() != StatusCode::NO_CONTENT {
+            return Err(err_from_response(resp));
+        }
+
+        Ok(())
+    }
+
     pub fn create_channel(&self, origin: &str, channel: &ChannelIdent, token: &str) -> Result<()> {
         let url_path = format!("{}/v1/depot/channels/{}/{}", self.url, origin, channel);
         debug!("Creating channel, path: {:?}", url_path);
@@ -265,6 +424,101 @@ impl ApiClient {
         Ok(())
     }
 
+    /// Compares a mirror's `inventory` of `target` releases against the channel. The hash of
+    /// the inventory is sent first, and the inventory itself only when it does not match the
+    /// channel's, sorted and in chunks of at most `chunk_size` entries. The differences of the
+    /// chunks are returned together.
+    pub fn sync_channel(&self,
+                        origin: &str,
+                        channel: &ChannelIdent,
+                        target: PackageTarget,
+                        inventory: &[InventoryEntry],
+                        chunk_size: usize,
+                        token: Option<&str>)
+                        -> Result<SyncDiff> {
+        let hash = channel_sync::inventory_hash(inventory.iter()
+                                                         .map(|e| {
+                                                             (e.ident.as_str(), e.checksum.as_str())
+                                                         }));
+        let req = SyncDiffRequest { inventory_hash: Some(hash),
+                                    ..Default::default() };
+        let first = self.sync_diff(origin, channel, target, &req, token)?;
+        if first.unchanged {
+            return Ok(first);
+        }
+
+        let mut inventory = inventory.to_vec();
+        inventory.sort_by(|a, b| a.ident.cmp(&b.ident));
+        let chunks: Vec<&[InventoryEntry]> = if inventory.is_empty() {
+            vec![&inventory[..]]
+        } else {
+            inventory.chunks(chunk_size.max(1)).collect()
+        };
+
+        let mut result = SyncDiff { inventory_hash: first.inventory_hash,
+                                    ..Default::default() };
+        let mut after = None;
+        for (n, chunk) in chunks.iter().enumerate() {
+            let until = if n + 1 < chunks.len() {
+                chunk.last().map(|e| e.ident.clone())
+            } else {
+                None
+            };
+            let req = SyncDiffRequest { inventory: chunk.to_vec(),
+                                        inventory_hash: None,
+                                        after,
+                                        until: until.clone() };
+            let diff = self.sync_diff(origin, channel, target, &req, token)?;
+            result.missing.extend(diff.missing);
+            result.mismatched.extend(diff.mismatched);
+            result.stale.extend(diff.stale);
+            after = until;
+        }
+        result.unchanged =
+            result.missing.is_empty() && result.mismatched.is_empty() && result.stale.is_empty();
+        Ok(result)
+    }
+
+    fn sync_diff(&self,
+                 origin: &str,
+                 channel: &ChannelIdent,
+                 target: PackageTarget,
+                 req: &SyncDiffRequest,
+                 token: Option<&str>)
+                 -> Result<SyncDiff> {
+        let url_path = format!("{}/v1/{}", self.url, channel_sync_diff(origin, channel));
+        debug!("Syncing channel, path: {:?}, entries: {}",
+               url_path,
+               req.inventory.len());
+
+        let mut qparams: HashMap<&str, &str> = HashMap::new();
+        qparams.insert("target", &target);
+
+        let body = serde_json::to_string(req).map_err(Error::Serialization)?;
+
+        let mut request = self.inner
+                              .post(&url_path)
+                              .query(&qparams)
+                              .header(CONTENT_TYPE, "application/json")
+                              .body(body);
+
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+
+        let mut resp = request.send().map_err(Error::HttpClient)?;
+
+        if resp.status() != StatusCode::OK {
+            return Err(err_from_response(resp));
+        }
+
+        let mut body = String::new();
+        resp.read_to_string(&mut body).map_err(Error::IO)?;
+        debug!("Body: {:?}", body);
+
+        serde_json::from_str::<SyncDiff>(&body).map_err(Error::Serialization)
+    }
+
     // TODO: make channel type hab_core::ChannelIdent
     pub fn promote_package<I>(&self,
                               (ident, target): (&I, PackageTarget),
@@ -326,10 +580,18 @@ fn package_path<I>(package: &I) -> String
     format!("depot/pkgs/{}", package)
 }
 
+fn build_cache_path(key: &str) -> String { format!("depot/build_cache/{}", key) }
+
 fn origin_secret_keys_latest(origin: &str) -> String {
     format!("depot/origins/{}/secret_keys/latest", origin)
 }
 
+fn origin_keys(origin: &str) -> String { format!("depot/origins/{}/keys", origin) }
+
+fn origin_keys_revision(origin: &str, revision: &str) -> String {
+    format!("depot/origins/{}/keys/{}", origin, revision)
+}
+
 fn channel_package_promote<I>(channel: &ChannelIdent, package: &I) -> String
     where I: Identifiable
 {
@@ -341,8 +603,120 @@ fn channel_package_promote<I>(channel: &ChannelIdent, package: &I) -> String
             package.release().unwrap())
 }
 
+fn channel_sync_diff(origin: &str, channel: &ChannelIdent) -> String {
+    format!("depot/channels/{}/{}/sync_diff", origin, channel)
+}
+
 fn err_from_response(mut response: Response) -> Error {
     let mut s = String::new();
     response.read_to_string(&mut s).map_err(Error::IO).unwrap();
     Error::ApiError(response.status(), s)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel_sync::tests::{channel,
+                                     entry};
+    use std::{io::{BufRead,
+                   BufReader,
+                   Write},
+              net::TcpListener,
+              sync::{Arc,
+                     Mutex},
+              thread};
+
+    // A depot holding `channel` that answers sync_diff requests and keeps their bodies
+    fn depot(channel: Vec<(String, String)>) -> (String, Arc<Mutex<Vec<SyncDiffRequest>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    let line = line.to_lowercase();
+                    if line.starts_with("content-length:") {
+                        content_length = line[15..].trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0u8; content_length];
+                reader.read_exact(&mut body).unwrap();
+
+                let req: SyncDiffRequest = serde_json::from_slice(&body).unwrap();
+                let diff = channel_sync::diff(&channel, &req);
+                received.lock().unwrap().push(req);
+                let body = serde_json::to_string(&diff).unwrap();
+                let _ = write!(stream,
+                               "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: \
+                                close\r\n\r\n{}",
+                               body.len(),
+                               body);
+            }
+        });
+        (url, requests)
+    }
+
+    fn sync(url: &str, inventory: &[InventoryEntry], chunk_size: usize) -> SyncDiff {
+        let client = ApiClient::new(url).unwrap();
+        client.sync_channel("core",
+                            &ChannelIdent::stable(),
+                            PackageTarget::active_target(),
+                            inventory,
+                            chunk_size,
+                            None)
+              .unwrap()
+    }
+
+    #[test]
+    fn an_unchanged_mirror_only_sends_its_hash() {
+        let (url, requests) = depot(channel());
+        let inventory: Vec<InventoryEntry> =
+            channel().iter().rev().map(|(i, c)| entry(i, c)).collect();
+
+        let diff = sync(&url, &inventory, 2);
+        assert!(diff.unchanged);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].inventory.is_empty());
+    }
+
+    #[test]
+    fn a_changed_mirror_sends_its_inventory_in_chunks() {
+        let (url, requests) = depot(channel());
+        let inventory = vec![entry("core/zlib/1.2.11/20190115000000", "cc"),
+                             entry("core/openssl/1.0.2s/20190601000000", "dd"),
+                             entry("core/gzip/1.10/20190101000000", "xx"),];
+
+        let diff = sync(&url, &inventory, 2);
+        assert!(!diff.unchanged);
+        assert_eq!(diff.missing, vec!["core/openssl/1.0.2t/20190911000000"]);
+        assert_eq!(diff.mismatched, vec!["core/gzip/1.10/20190101000000"]);
+        assert_eq!(diff.stale, vec!["core/openssl/1.0.2s/20190601000000"]);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1].inventory.len(), 2);
+        assert_eq!(requests[1].after, None);
+        assert_eq!(requests[1].until,
+                   Some("core/openssl/1.0.2s/20190601000000".to_string()));
+        assert_eq!(requests[2].after, requests[1].until);
+        assert_eq!(requests[2].until, None);
+    }
+
+    #[test]
+    fn an_empty_mirror_is_missing_the_whole_channel() {
+        let (url, requests) = depot(channel());
+        let diff = sync(&url, &[], 2);
+        assert_eq!(diff.missing.len(), 3);
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,118 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! What a service was built from, served on its `/version` endpoint so the services of a
+//! mixed-version deployment can be told apart. The commit and build time come from the files
+//! the component's build script writes, see `build_info!`.
+
+use chrono::NaiveDateTime;
+
+use crate::{protocol,
+            timestamp};
+
+const UNKNOWN: &str = "unknown";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    pub component:        String,
+    /// The version the build script wrote, the plan's version and release in a plan build
+    pub version:          String,
+    pub git_sha:          String,
+    pub built_at:         String,
+    /// The feature flags enabled in the service's config
+    pub features:         Vec<String>,
+    pub protocol_version: u32,
+}
+
+impl BuildInfo {
+    /// `version`, `git_sha` and `build_timestamp`, in seconds since the epoch, are as the build
+    /// script wrote them
+    pub fn new(component: &str,
+               version: &str,
+               git_sha: &str,
+               build_timestamp: &str,
+               features: Vec<String>)
+               -> Self {
+        let git_sha = match git_sha.trim() {
+            "" => UNKNOWN,
+            sha => sha,
+        };
+        BuildInfo { component: component.to_string(),
+                    version: version.trim().to_string(),
+                    git_sha: git_sha.to_string(),
+                    built_at: built_at(build_timestamp),
+                    features,
+                    protocol_version: protocol::PROTOCOL_VERSION }
+    }
+
+    /// Whether the two services can exchange messages
+    pub fn is_compatible(&self, other: &BuildInfo) -> bool {
+        self.protocol_version == other.protocol_version
+    }
+}
+
+fn built_at(build_timestamp: &str) -> String {
+    build_timestamp.trim()
+                   .parse::<i64>()
+                   .ok()
+                   .and_then(|secs| NaiveDateTime::from_timestamp_opt(secs, 0))
+                   .map_or_else(|| UNKNOWN.to_string(),
+                                |dt| timestamp::naive_to_rfc3339(&dt))
+}
+
+/// The `BuildInfo` of the calling crate, which must build with `build-builder.rs`
+#[macro_export]
+macro_rules! build_info {
+    ($component:expr, $features:expr) => {
+        $crate::build_info::BuildInfo::new($component,
+                                           include_str!(concat!(env!("OUT_DIR"), "/VERSION")),
+                                           include_str!(concat!(env!("OUT_DIR"), "/GIT_SHA")),
+                                           include_str!(concat!(env!("OUT_DIR"),
+                                                                "/BUILD_TIMESTAMP")),
+                                           $features)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_script_output_is_read() {
+        let info = BuildInfo::new("builder-api",
+                                  "8413/20191021000000\n",
+                                  "2f1c3e7a9b\n",
+                                  "1571616000",
+                                  vec!["JOBSRV".to_string()]);
+        assert_eq!(info.version, "8413/20191021000000");
+        assert_eq!(info.git_sha, "2f1c3e7a9b");
+        assert_eq!(info.built_at, "2019-10-21T00:00:00.000000Z");
+    }
+
+    #[test]
+    fn missing_build_script_output_is_unknown() {
+        let info = BuildInfo::new("builder-jobsrv", "0.0.0", "", "not a time", vec![]);
+        assert_eq!(info.git_sha, "unknown");
+        assert_eq!(info.built_at, "unknown");
+    }
+
+    #[test]
+    fn protocol_versions_must_match() {
+        let api = BuildInfo::new("builder-api", "0.0.0", "a", "0", vec![]);
+        let mut jobsrv = BuildInfo::new("builder-jobsrv", "0.0.0", "b", "0", vec![]);
+        assert!(api.is_compatible(&jobsrv));
+        jobsrv.protocol_version += 1;
+        assert!(!api.is_compatible(&jobsrv));
+    }
+}
@@ -0,0 +1,262 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The differential sync of a channel to a mirror. The mirror sends the releases it holds of
+//! one target, and the depot answers with the releases it is missing, those whose checksums
+//! differ from the depot's, and those it holds that are no longer in the channel.
+//!
+//! A mirror first sends only the hash of its inventory; when it matches the channel's hash
+//! nothing has changed. Otherwise it sends its inventory, sorted by ident and split into chunks
+//! of at most the depot's `sync_diff_max_entries` entries. Each chunk names the range of idents
+//! it covers: those after the last ident of the previous chunk, `after`, up to and including
+//! its own last ident, `until`. The first chunk has no `after` and the last no `until`, so the
+//! chunks cover every ident the channel may hold.
+
+use std::collections::{HashMap,
+                       HashSet};
+
+use sha2::{Digest,
+           Sha256};
+
+/// A release a mirror holds, its fully qualified ident and checksum
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InventoryEntry {
+    pub ident:    String,
+    pub checksum: String,
+}
+
+/// The body of a `sync_diff` request
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SyncDiffRequest {
+    /// The releases of the chunk
+    #[serde(default)]
+    pub inventory:      Vec<InventoryEntry>,
+    /// The hash of the mirror's whole inventory. A request with a hash is only answered with
+    /// whether it matches the channel's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inventory_hash: Option<String>,
+    /// The chunk covers the idents after this one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after:          Option<String>,
+    /// The chunk covers the idents up to and including this one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until:          Option<String>,
+}
+
+/// What a mirror must change to hold the same releases as the channel
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncDiff {
+    /// The mirror holds the channel's releases, or those of the chunk's range
+    pub unchanged:      bool,
+    /// Releases in the channel the mirror lacks
+    pub missing:        Vec<String>,
+    /// Releases the mirror holds with another checksum than the depot's
+    pub mismatched:     Vec<String>,
+    /// Releases the mirror holds that are no longer in the channel
+    pub stale:          Vec<String>,
+    /// The hash of the channel's releases, the mirror's once it is in sync. Only a request with
+    /// a hash is answered with it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inventory_hash: Option<String>,
+}
+
+/// The hash of an inventory, the hex SHA-256 of its `<ident> <checksum>` lines sorted by ident
+pub fn inventory_hash<'a, I>(entries: I) -> String
+    where I: IntoIterator<Item = (&'a str, &'a str)>
+{
+    let mut entries: Vec<(&str, &str)> = entries.into_iter().collect();
+    entries.sort();
+
+    let mut hasher = Sha256::new();
+    for (ident, checksum) in entries {
+        hasher.input(ident.as_bytes());
+        hasher.input(b" ");
+        hasher.input(checksum.as_bytes());
+        hasher.input(b"\n");
+    }
+    hex::encode(hasher.result())
+}
+
+impl SyncDiffRequest {
+    /// Whether `ident` falls in the range of idents the chunk covers
+    pub fn covers(&self, ident: &str) -> bool {
+        self.after
+            .as_ref()
+            .map_or(true, |after| ident > after.as_str())
+        && self.until
+               .as_ref()
+               .map_or(true, |until| ident <= until.as_str())
+    }
+
+    /// Checks that every entry falls in the chunk's range and that no ident is listed twice
+    pub fn validate(&self) -> Result<(), String> {
+        if let (Some(after), Some(until)) = (&self.after, &self.until) {
+            if after >= until {
+                return Err(format!("after {} is not before until {}", after, until));
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for entry in &self.inventory {
+            if !self.covers(&entry.ident) {
+                return Err(format!("{} is outside the range of the chunk", entry.ident));
+            }
+            if !seen.insert(entry.ident.as_str()) {
+                return Err(format!("{} is listed more than once", entry.ident));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares a request against the `channel`'s releases, pairs of ident and checksum. A request
+/// with a hash is compared against every release of the channel, a chunk only against those in
+/// its range, so the releases outside it need not be read.
+pub fn diff(channel: &[(String, String)], req: &SyncDiffRequest) -> SyncDiff {
+    if let Some(ref hash) = req.inventory_hash {
+        let channel_hash =
+            inventory_hash(channel.iter()
+                                  .map(|(ident, checksum)| (ident.as_str(), checksum.as_str())));
+        return SyncDiff { unchanged: *hash == channel_hash,
+                          inventory_hash: Some(channel_hash),
+                          ..Default::default() };
+    }
+
+    let mut held: HashMap<&str, &str> = req.inventory
+                                           .iter()
+                                           .map(|e| (e.ident.as_str(), e.checksum.as_str()))
+                                           .collect();
+    let mut missing = Vec::new();
+    let mut mismatched = Vec::new();
+    for (ident, checksum) in channel.iter().filter(|(ident, _)| req.covers(ident)) {
+        match held.remove(ident.as_str()) {
+            Some(held_checksum) if held_checksum == checksum => (),
+            Some(_) => mismatched.push(ident.clone()),
+            None => missing.push(ident.clone()),
+        }
+    }
+    let mut stale: Vec<String> = held.keys().map(|ident| ident.to_string()).collect();
+
+    missing.sort();
+    mismatched.sort();
+    stale.sort();
+
+    SyncDiff { unchanged: missing.is_empty() && mismatched.is_empty() && stale.is_empty(),
+               missing,
+               mismatched,
+               stale,
+               inventory_hash: None }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn entry(ident: &str, checksum: &str) -> InventoryEntry {
+        InventoryEntry { ident:    ident.to_string(),
+                         checksum: checksum.to_string(), }
+    }
+
+    pub(crate) fn channel() -> Vec<(String, String)> {
+        vec![("core/gzip/1.10/20190101000000", "aa"),
+             ("core/openssl/1.0.2t/20190911000000", "bb"),
+             ("core/zlib/1.2.11/20190115000000", "cc"),].into_iter()
+                                                        .map(|(ident, checksum)| {
+                                                            (ident.to_string(),
+                                                             checksum.to_string())
+                                                        })
+                                                        .collect()
+    }
+
+    fn channel_hash() -> String {
+        let channel = channel();
+        inventory_hash(channel.iter().map(|(i, c)| (i.as_str(), c.as_str())))
+    }
+
+    #[test]
+    fn inventory_hash_ignores_order() {
+        let sorted = inventory_hash(vec![("core/a/1/1", "aa"), ("core/b/1/1", "bb")]);
+        let unsorted = inventory_hash(vec![("core/b/1/1", "bb"), ("core/a/1/1", "aa")]);
+        assert_eq!(sorted, unsorted);
+        assert_ne!(sorted,
+                   inventory_hash(vec![("core/a/1/1", "aa"), ("core/b/1/1", "bc")]));
+        assert_eq!(sorted.len(), 64);
+    }
+
+    #[test]
+    fn a_matching_hash_is_unchanged() {
+        let req = SyncDiffRequest { inventory_hash: Some(channel_hash()),
+                                    ..Default::default() };
+        let diff = diff(&channel(), &req);
+        assert!(diff.unchanged);
+        assert_eq!(diff.inventory_hash, Some(channel_hash()));
+
+        let req = SyncDiffRequest { inventory_hash: Some("0".repeat(64)),
+                                    ..Default::default() };
+        let diff = super::diff(&channel(), &req);
+        assert!(!diff.unchanged);
+        assert!(diff.missing.is_empty());
+    }
+
+    #[test]
+    fn missing_mismatched_and_stale_releases_are_listed() {
+        let req =
+            SyncDiffRequest { inventory: vec![entry("core/gzip/1.10/20190101000000", "aa"),
+                                              entry("core/openssl/1.0.2t/20190911000000", "xx"),
+                                              entry("core/openssl/1.0.2s/20190601000000", "dd"),],
+                              ..Default::default() };
+        let diff = diff(&channel(), &req);
+        assert!(!diff.unchanged);
+        assert_eq!(diff.missing, vec!["core/zlib/1.2.11/20190115000000"]);
+        assert_eq!(diff.mismatched, vec!["core/openssl/1.0.2t/20190911000000"]);
+        assert_eq!(diff.stale, vec!["core/openssl/1.0.2s/20190601000000"]);
+    }
+
+    #[test]
+    fn chunks_only_compare_their_range() {
+        let first = SyncDiffRequest { inventory: vec![entry("core/gzip/1.10/20190101000000",
+                                                            "aa")],
+                                      until: Some("core/gzip/1.10/20190101000000".to_string()),
+                                      ..Default::default() };
+        assert!(diff(&channel(), &first).unchanged);
+
+        let last = SyncDiffRequest { inventory: vec![entry("core/zlib/1.2.11/20190115000000",
+                                                           "cc")],
+                                     after: Some("core/gzip/1.10/20190101000000".to_string()),
+                                     ..Default::default() };
+        let diff = diff(&channel(), &last);
+        assert_eq!(diff.missing, vec!["core/openssl/1.0.2t/20190911000000"]);
+        assert!(diff.stale.is_empty());
+    }
+
+    #[test]
+    fn entries_outside_the_chunk_or_listed_twice_are_invalid() {
+        let req = SyncDiffRequest { inventory: vec![entry("core/zlib/1.2.11/20190115000000",
+                                                          "cc")],
+                                    until: Some("core/gzip/1.10/20190101000000".to_string()),
+                                    ..Default::default() };
+        assert!(req.validate().is_err());
+
+        let req =
+            SyncDiffRequest { inventory: vec![entry("core/gzip/1.10/20190101000000", "aa"),
+                                              entry("core/gzip/1.10/20190101000000", "aa")],
+                              ..Default::default() };
+        assert!(req.validate().is_err());
+
+        let req = SyncDiffRequest { after: Some("core/b".to_string()),
+                                    until: Some("core/a".to_string()),
+                                    ..Default::default() };
+        assert!(req.validate().is_err());
+    }
+}
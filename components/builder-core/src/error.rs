@@ -31,14 +31,18 @@ use crate::{hab_core,
 pub enum Error {
     ApiError(reqwest::StatusCode, String),
     RpcError(u16, String),
+    CaBundle(String),
     HttpClient(reqwest::Error),
     IO(io::Error),
     Base64Error(base64::DecodeError),
     ChronoError(chrono::format::ParseError),
+    DeadlineExceeded,
     DecryptError(String),
     EncryptError(String),
     FromUtf8Error(string::FromUtf8Error),
     HabitatCore(hab_core::Error),
+    InvalidHttpClient(String),
+    InvalidProxy(String),
     Protobuf(protobuf::ProtobufError),
     Protocol(protocol::ProtocolError),
     Serialization(serde_json::Error),
@@ -57,14 +61,18 @@ impl fmt::Display for Error {
                         code, response)
             }
             Error::RpcError(ref code, ref e) => format!("{} {}", code, e),
+            Error::CaBundle(ref e) => format!("Unable to load CA bundle {}", e),
             Error::HttpClient(ref e) => format!("{}", e),
             Error::IO(ref e) => format!("{}", e),
             Error::Base64Error(ref e) => format!("{}", e),
             Error::ChronoError(ref e) => format!("{}", e),
+            Error::DeadlineExceeded => "Request deadline exceeded".to_string(),
             Error::DecryptError(ref e) => e.to_string(),
             Error::EncryptError(ref e) => e.to_string(),
             Error::FromUtf8Error(ref e) => format!("{}", e),
             Error::HabitatCore(ref e) => format!("{}", e),
+            Error::InvalidHttpClient(ref e) => format!("Invalid HTTP client settings, {}", e),
+            Error::InvalidProxy(ref e) => format!("Invalid proxy URL, {}", e),
             Error::Protobuf(ref e) => format!("{}", e),
             Error::Protocol(ref e) => format!("{}", e),
             Error::Serialization(ref e) => format!("{}", e),
@@ -81,14 +89,18 @@ impl error::Error for Error {
         match *self {
             Error::ApiError(..) => "Response returned a non-200 status code.",
             Error::RpcError(..) => "Response returned a non-200 status code.",
+            Error::CaBundle(_) => "Unable to load CA bundle",
             Error::HttpClient(ref err) => err.description(),
             Error::IO(ref err) => err.description(),
             Error::Base64Error(ref e) => e.description(),
             Error::ChronoError(ref e) => e.description(),
+            Error::DeadlineExceeded => "Request deadline exceeded",
             Error::DecryptError(_) => "Error decrypting integration",
             Error::EncryptError(_) => "Error encrypting integration",
             Error::FromUtf8Error(ref e) => e.description(),
             Error::HabitatCore(ref err) => err.description(),
+            Error::InvalidHttpClient(_) => "Invalid HTTP client settings",
+            Error::InvalidProxy(_) => "Invalid proxy URL",
             Error::Protobuf(ref err) => err.description(),
             Error::Protocol(ref err) => err.description(),
             Error::Serialization(ref err) => err.description(),
@@ -0,0 +1,172 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Runtime feature flags, flipped by operators without a restart and optionally rolled out to a
+//! percentage of the keys they are evaluated for (e.g. job group ids). The flags are held in
+//! memory and replaced wholesale by whatever loads them from their store, so an evaluation only
+//! takes an uncontended read lock.
+
+use std::{collections::HashMap,
+          sync::{Arc,
+                 RwLock}};
+
+/// A flag known to the code evaluating it, with the state it has until an operator sets it
+#[derive(Clone, Copy, Debug)]
+pub struct Flag {
+    pub name:    &'static str,
+    pub default: bool,
+}
+
+/// The state an operator set a flag to
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlagState {
+    pub enabled:         bool,
+    /// Percentage of keys the flag is on for when enabled, 100 for all of them
+    pub rollout_percent: u8,
+}
+
+impl FlagState {
+    /// Whether the flag is on for `key`. Each key lands in a bucket from 0 to 99 that depends
+    /// on the flag's name as well, so raising the percentage only adds keys and two flags at the
+    /// same percentage don't pick the same keys.
+    pub fn is_enabled_for(&self, name: &str, key: u64) -> bool {
+        self.enabled && bucket(name, key) < u64::from(self.rollout_percent.min(100))
+    }
+}
+
+/// The flags of a service, shared by every thread evaluating them
+#[derive(Clone, Default)]
+pub struct FeatureFlags {
+    flags: Arc<RwLock<HashMap<String, FlagState>>>,
+}
+
+impl FeatureFlags {
+    pub fn new() -> Self { FeatureFlags::default() }
+
+    /// Whether `flag` is on for everything it is evaluated for
+    pub fn is_enabled(&self, flag: Flag) -> bool {
+        match self.flags.read().unwrap().get(flag.name) {
+            Some(state) => state.enabled && state.rollout_percent >= 100,
+            None => flag.default,
+        }
+    }
+
+    /// Whether `flag` is on for `key`, e.g. the id of the job group a decision is made for
+    pub fn is_enabled_for(&self, flag: Flag, key: u64) -> bool {
+        match self.flags.read().unwrap().get(flag.name) {
+            Some(state) => state.is_enabled_for(flag.name, key),
+            None => flag.default,
+        }
+    }
+
+    /// Replaces every flag with the ones last loaded from their store
+    pub fn replace(&self, flags: HashMap<String, FlagState>) {
+        *self.flags.write().unwrap() = flags;
+    }
+
+    /// Sets a single flag, e.g. right after it was changed in the store
+    pub fn set(&self, name: &str, state: FlagState) {
+        self.flags.write().unwrap().insert(name.to_string(), state);
+    }
+
+    pub fn get(&self, name: &str) -> Option<FlagState> {
+        self.flags.read().unwrap().get(name).cloned()
+    }
+}
+
+// FNV-1a of the flag name and the key, reduced to 0..100
+fn bucket(name: &str, key: u64) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    let hash = name.as_bytes()
+                   .iter()
+                   .chain(key.to_le_bytes().iter())
+                   .fold(OFFSET_BASIS, |hash, byte| {
+                       (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+                   });
+    hash % 100
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ON_BY_DEFAULT: Flag = Flag { name:    "on_by_default",
+                                       default: true, };
+    const OFF_BY_DEFAULT: Flag = Flag { name:    "off_by_default",
+                                        default: false, };
+
+    fn state(enabled: bool, rollout_percent: u8) -> FlagState {
+        FlagState { enabled,
+                    rollout_percent }
+    }
+
+    #[test]
+    fn unset_flags_have_their_default() {
+        let flags = FeatureFlags::new();
+        assert!(flags.is_enabled(ON_BY_DEFAULT));
+        assert!(flags.is_enabled_for(ON_BY_DEFAULT, 42));
+        assert!(!flags.is_enabled(OFF_BY_DEFAULT));
+        assert!(!flags.is_enabled_for(OFF_BY_DEFAULT, 42));
+    }
+
+    #[test]
+    fn set_flags_override_their_default() {
+        let flags = FeatureFlags::new();
+        flags.set(ON_BY_DEFAULT.name, state(false, 100));
+        flags.set(OFF_BY_DEFAULT.name, state(true, 100));
+        assert!(!flags.is_enabled(ON_BY_DEFAULT));
+        assert!(!flags.is_enabled_for(ON_BY_DEFAULT, 42));
+        assert!(flags.is_enabled(OFF_BY_DEFAULT));
+        assert!(flags.is_enabled_for(OFF_BY_DEFAULT, 42));
+
+        let mut loaded = HashMap::new();
+        loaded.insert(OFF_BY_DEFAULT.name.to_string(), state(false, 100));
+        flags.replace(loaded);
+        assert!(flags.is_enabled(ON_BY_DEFAULT));
+        assert!(!flags.is_enabled(OFF_BY_DEFAULT));
+        assert_eq!(flags.get(OFF_BY_DEFAULT.name), Some(state(false, 100)));
+        assert_eq!(flags.get(ON_BY_DEFAULT.name), None);
+    }
+
+    #[test]
+    fn rollout_covers_its_percentage_of_keys() {
+        let flags = FeatureFlags::new();
+        flags.set(OFF_BY_DEFAULT.name, state(true, 25));
+        let on = (0..10_000).filter(|key| flags.is_enabled_for(OFF_BY_DEFAULT, *key))
+                            .count();
+        assert!(on > 2_000 && on < 3_000, "{} of 10000 keys", on);
+        // A partial rollout isn't on for everything
+        assert!(!flags.is_enabled(OFF_BY_DEFAULT));
+
+        flags.set(OFF_BY_DEFAULT.name, state(true, 0));
+        assert!((0..1_000).all(|key| !flags.is_enabled_for(OFF_BY_DEFAULT, key)));
+
+        flags.set(OFF_BY_DEFAULT.name, state(false, 100));
+        assert!((0..1_000).all(|key| !flags.is_enabled_for(OFF_BY_DEFAULT, key)));
+    }
+
+    #[test]
+    fn raising_the_rollout_keeps_the_keys_already_on() {
+        let (low, high) = (state(true, 10), state(true, 60));
+        for key in 0..1_000 {
+            if low.is_enabled_for("flag", key) {
+                assert!(high.is_enabled_for("flag", key), "key {}", key);
+            }
+        }
+        assert_eq!(state(true, 30).is_enabled_for("flag", 7),
+                   state(true, 30).is_enabled_for("flag", 7));
+    }
+}
@@ -0,0 +1,86 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tokens carried by the acceptance link of an origin invitation sent by email. They are
+//! sealed with the bldr key pair, so only Builder can mint one for a given invitation, and
+//! encoded URL safe so they can be used as a path segment as is.
+
+use std::path::Path;
+
+use base64;
+
+use crate::{error::{Error,
+                    Result},
+            integrations::{decrypt,
+                           encrypt}};
+
+pub fn generate_invitation_token<A>(key_dir: A, invitation_id: u64, email: &str) -> Result<String>
+    where A: AsRef<Path>
+{
+    let ciphertext = encrypt(key_dir, encode_payload(invitation_id, email).as_bytes())?;
+    let bytes = base64::decode(&ciphertext).map_err(Error::Base64Error)?;
+    Ok(base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD))
+}
+
+/// Returns the invitation id and address a token was issued for.
+pub fn validate_invitation_token<A>(key_dir: A, token: &str) -> Result<(u64, String)>
+    where A: AsRef<Path>
+{
+    let bytes =
+        base64::decode_config(token, base64::URL_SAFE_NO_PAD).map_err(|_| Error::TokenInvalid)?;
+    let bytes = decrypt(key_dir, &base64::encode(&bytes))?;
+    let payload = String::from_utf8(bytes).map_err(|_| Error::TokenInvalid)?;
+    decode_payload(&payload).ok_or(Error::TokenInvalid)
+}
+
+fn encode_payload(invitation_id: u64, email: &str) -> String {
+    format!("{}:{}", invitation_id, email)
+}
+
+fn decode_payload(payload: &str) -> Option<(u64, String)> {
+    let mut parts = payload.splitn(2, ':');
+    let id = parts.next()?.parse::<u64>().ok()?;
+    let email = parts.next()?;
+    if email.is_empty() {
+        return None;
+    }
+    Some((id, email.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn payload_round_trips() {
+        let payload = encode_payload(1_123_456_789, "dev@example.com");
+        assert_eq!(decode_payload(&payload),
+                   Some((1_123_456_789, "dev@example.com".to_string())));
+    }
+
+    #[test]
+    fn payload_keeps_colons_in_address() {
+        let payload = encode_payload(42, "\"a:b\"@example.com");
+        assert_eq!(decode_payload(&payload),
+                   Some((42, "\"a:b\"@example.com".to_string())));
+    }
+
+    #[test]
+    fn malformed_payload_is_rejected() {
+        assert_eq!(decode_payload(""), None);
+        assert_eq!(decode_payload("42"), None);
+        assert_eq!(decode_payload("42:"), None);
+        assert_eq!(decode_payload("abc:dev@example.com"), None);
+    }
+}
@@ -33,18 +33,25 @@ use habitat_core as hab_core;
 pub mod access_token;
 pub mod api_client;
 pub mod build_config;
+pub mod build_info;
+pub mod channel_sync;
 pub mod error;
+pub mod feature_flags;
 pub mod http_client;
 pub mod integrations;
+pub mod invitation_token;
 pub mod job;
 pub mod keys;
 pub mod logger;
 pub mod metrics;
 pub mod package_graph;
+pub mod package_name;
 pub mod privilege;
 pub mod rdeps;
 pub mod rpc;
 pub mod socket;
 pub mod target_graph;
+pub mod timestamp;
+pub mod vcs;
 
 pub use crate::error::Error;
@@ -13,13 +13,19 @@
 // limitations under the License.
 
 use petgraph::{algo::{connected_components,
+                      has_path_connecting,
                       is_cyclic_directed},
                graph::NodeIndex,
+               visit::{Bfs,
+                       Walker},
                Direction,
                Graph};
 use std::{cmp::Ordering,
-          collections::{BinaryHeap,
-                        HashMap},
+          collections::{BTreeSet,
+                        BinaryHeap,
+                        HashMap,
+                        HashSet,
+                        VecDeque},
           str::FromStr};
 
 use crate::{hab_core::package::PackageIdent,
@@ -34,6 +40,22 @@ pub struct Stats {
     pub is_cyclic:      bool,
 }
 
+/// How the graph differs from a package's stored metadata
+#[derive(Debug, PartialEq)]
+pub enum Divergence {
+    /// The graph has no release of the package
+    MissingNode,
+    /// The graph holds an older release as the latest, the ident held is given
+    StaleLatest(String),
+    /// The graph holds a newer release than the one compared against
+    NewerLatest(String),
+    /// The dependency edges differ, by short name
+    Edges {
+        missing: Vec<String>,
+        extra:   Vec<String>,
+    },
+}
+
 #[derive(Eq)]
 struct HeapEntry {
     pkg_index:  usize,
@@ -58,6 +80,17 @@ fn short_name(name: &str) -> String {
     format!("{}/{}", parts[0], parts[1])
 }
 
+// The dependencies a package gets edges from
+fn dep_idents(package: &originsrv::OriginPackage,
+              use_build_deps: bool)
+              -> Vec<&originsrv::OriginPackageIdent> {
+    let mut deps = package.get_deps().iter().collect::<Vec<_>>();
+    if use_build_deps {
+        deps.extend(package.get_build_deps());
+    }
+    deps
+}
+
 #[derive(Default)]
 pub struct PackageGraph {
     package_max:   usize,
@@ -129,19 +162,8 @@ impl PackageGraph {
         // Check to see if extension would create a circular dependency
         let mut circular_dep = false;
         let mut dep_nodes = Vec::new();
-        let mut deps;
-        let build_deps;
 
-        if use_build_deps {
-            deps = package.get_deps().iter().collect::<Vec<_>>();
-            build_deps = package.get_build_deps();
-
-            deps.extend(build_deps);
-        } else {
-            deps = package.get_deps().iter().collect::<Vec<_>>();
-        }
-
-        for dep in deps {
+        for dep in dep_idents(package, use_build_deps) {
             let dep_name = format!("{}", dep);
             let dep_short_name = short_name(&dep_name);
 
@@ -213,18 +235,7 @@ impl PackageGraph {
         };
 
         if add_deps {
-            let mut deps;
-            let build_deps;
-
-            if use_build_deps {
-                deps = package.get_deps().iter().collect::<Vec<_>>();
-                build_deps = package.get_build_deps();
-                deps.extend(build_deps);
-            } else {
-                deps = package.get_deps().iter().collect::<Vec<_>>();
-            }
-
-            for dep in deps {
+            for dep in dep_idents(package, use_build_deps) {
                 let depname = format!("{}", dep);
 
                 let (_, dep_node) = self.generate_id(&depname);
@@ -243,6 +254,60 @@ impl PackageGraph {
         (self.graph.node_count(), self.graph.edge_count())
     }
 
+    /// Compares the graph against the stored metadata of `package`, which should be the latest
+    /// release. Dependencies that `extend` would have left out to keep the graph acyclic are not
+    /// reported missing.
+    pub fn diff(&self,
+                package: &originsrv::OriginPackage,
+                use_build_deps: bool)
+                -> Option<Divergence> {
+        let name = format!("{}", package.get_ident());
+        let pkg_ident = PackageIdent::from_str(&name).unwrap();
+        let pkg_short_name = short_name(&name);
+
+        let latest = match self.latest_map.get(&pkg_short_name) {
+            Some(latest) => latest,
+            None => return Some(Divergence::MissingNode),
+        };
+        if *latest < pkg_ident {
+            return Some(Divergence::StaleLatest(format!("{}", latest)));
+        }
+        if *latest > pkg_ident {
+            return Some(Divergence::NewerLatest(format!("{}", latest)));
+        }
+
+        let (_, pkg_node) = self.package_map[&pkg_short_name];
+        let actual: BTreeSet<String> = self.graph
+                                           .neighbors_directed(pkg_node, Direction::Incoming)
+                                           .map(|n| self.package_names[self.graph[n]].clone())
+                                           .collect();
+
+        let mut missing = Vec::new();
+        let mut expected = BTreeSet::new();
+        for dep in dep_idents(package, use_build_deps) {
+            let dep_short_name = short_name(&format!("{}", dep));
+            if actual.contains(&dep_short_name) {
+                expected.insert(dep_short_name);
+                continue;
+            }
+            let cyclic = match self.package_map.get(&dep_short_name) {
+                Some(&(_, dep_node)) => has_path_connecting(&self.graph, pkg_node, dep_node, None),
+                None => false,
+            };
+            if !cyclic && expected.insert(dep_short_name.clone()) {
+                missing.push(dep_short_name);
+            }
+        }
+        missing.sort();
+        let extra: Vec<String> = actual.difference(&expected).cloned().collect();
+
+        if missing.is_empty() && extra.is_empty() {
+            None
+        } else {
+            Some(Divergence::Edges { missing, extra })
+        }
+    }
+
     pub fn rdeps(&self, name: &str) -> Option<Vec<(String, String)>> {
         let mut v: Vec<(String, String)> = Vec::new();
 
@@ -265,6 +330,49 @@ impl PackageGraph {
         Some(v)
     }
 
+    /// The direct dependencies of a package, in 'origin/name' format
+    pub fn deps(&self, name: &str) -> Option<Vec<String>> {
+        let &(_, pkg_node) = self.package_map.get(name)?;
+        let mut deps: Vec<String> = self.graph
+                                        .neighbors_directed(pkg_node, Direction::Incoming)
+                                        .map(|n| self.package_names[self.graph[n]].clone())
+                                        .collect();
+        deps.sort();
+        Some(deps)
+    }
+
+    /// The reverse dependencies of `name` left out of a rebuild that excludes the `excluded`
+    /// packages: those of them that are reverse dependencies, and every dependent that can only
+    /// be reached through them. Names are in 'origin/name' format, sorted.
+    pub fn excluded_rdeps(&self, name: &str, excluded: &[String]) -> Option<Vec<String>> {
+        let &(_, root) = self.package_map.get(name)?;
+        let excluded: HashSet<NodeIndex> = excluded.iter()
+                                                   .filter_map(|e| self.package_map.get(e))
+                                                   .map(|&(_, node)| node)
+                                                   .collect();
+
+        // Everything still reachable from the root without passing through an excluded package
+        let mut reached = HashSet::new();
+        let mut queue = VecDeque::new();
+        reached.insert(root);
+        queue.push_back(root);
+        while let Some(node) = queue.pop_front() {
+            for dependent in self.graph.neighbors_directed(node, Direction::Outgoing) {
+                if !excluded.contains(&dependent) && reached.insert(dependent) {
+                    queue.push_back(dependent);
+                }
+            }
+        }
+
+        let mut omitted: Vec<String> =
+            Bfs::new(&self.graph, root).iter(&self.graph)
+                                       .filter(|n| !reached.contains(n))
+                                       .map(|n| self.package_names[self.graph[n]].clone())
+                                       .collect();
+        omitted.sort();
+        Some(omitted)
+    }
+
     // Mostly for debugging
     pub fn rdeps_dump(&self) {
         debug!("Reverse dependencies:");
@@ -413,4 +521,106 @@ mod test {
 
         let (..) = graph.extend(&package2, true);
     }
+
+    fn package(ident: &str, deps: &[&str]) -> originsrv::OriginPackage {
+        let mut package = originsrv::OriginPackage::new();
+        package.set_ident(originsrv::OriginPackageIdent::from_str(ident).unwrap());
+        let mut package_deps = RepeatedField::new();
+        for dep in deps {
+            package_deps.push(originsrv::OriginPackageIdent::from_str(dep).unwrap());
+        }
+        package.set_deps(package_deps);
+        package
+    }
+
+    #[test]
+    fn diff_of_graph_in_sync() {
+        let mut graph = PackageGraph::new();
+        let packages = vec![package("foo/bar/1/2", &["foo/baz/1/2"]),
+                            package("foo/baz/1/2", &[])];
+        graph.build(packages.clone().into_iter(), true);
+
+        for p in &packages {
+            assert_eq!(graph.diff(p, true), None);
+        }
+    }
+
+    #[test]
+    fn diff_reports_edge_changes_and_extend_repairs_them() {
+        let mut graph = PackageGraph::new();
+        graph.extend(&package("foo/bar/1/2", &["foo/baz/1/2"]), true);
+
+        let stored = package("foo/bar/1/2", &["foo/qux/1/2"]);
+        assert_eq!(graph.diff(&stored, true),
+                   Some(Divergence::Edges { missing: vec!["foo/qux".to_string()],
+                                            extra:   vec!["foo/baz".to_string()], }));
+
+        graph.extend(&stored, true);
+        assert_eq!(graph.diff(&stored, true), None);
+    }
+
+    #[test]
+    fn diff_compares_latest_release() {
+        let mut graph = PackageGraph::new();
+        graph.extend(&package("foo/bar/1/2", &[]), true);
+
+        assert_eq!(graph.diff(&package("foo/bar/1/3", &[]), true),
+                   Some(Divergence::StaleLatest("foo/bar/1/2".to_string())));
+        assert_eq!(graph.diff(&package("foo/bar/1/1", &[]), true),
+                   Some(Divergence::NewerLatest("foo/bar/1/2".to_string())));
+        assert_eq!(graph.diff(&package("foo/other/1/1", &[]), true),
+                   Some(Divergence::MissingNode));
+    }
+
+    #[test]
+    fn diff_ignores_edges_left_out_to_avoid_cycles() {
+        let mut graph = PackageGraph::new();
+        let packages = vec![package("foo/bar/1/2", &["foo/baz/1/2"]),
+                            package("foo/baz/1/2", &["foo/bar/1/2"])];
+        graph.build(packages.clone().into_iter(), true);
+
+        assert_eq!(graph.diff(&packages[1], true), None);
+    }
+
+    #[test]
+    fn deps_are_direct_dependencies() {
+        let mut graph = PackageGraph::new();
+        let packages = vec![package("foo/bar/1/2", &["foo/qux/1/2", "foo/baz/1/2"]),
+                            package("foo/baz/1/2", &["foo/qux/1/2"])];
+        graph.build(packages.into_iter(), true);
+
+        assert_eq!(graph.deps("foo/bar"),
+                   Some(vec!["foo/baz".to_string(), "foo/qux".to_string()]));
+        assert_eq!(graph.deps("foo/qux"), Some(vec![]));
+        assert_eq!(graph.deps("foo/other"), None);
+    }
+
+    #[test]
+    fn excluded_rdeps_keeps_dependents_reachable_another_way() {
+        let mut graph = PackageGraph::new();
+        let packages = vec![package("foo/a/1/1", &[]),
+                            package("foo/b/1/1", &["foo/a/1/1"]),
+                            package("foo/c/1/1", &["foo/a/1/1"]),
+                            package("foo/d/1/1", &["foo/b/1/1", "foo/c/1/1"]),
+                            package("foo/e/1/1", &["foo/b/1/1"]),
+                            package("foo/f/1/1", &["foo/e/1/1"])];
+        graph.build(packages.into_iter(), true);
+
+        let excluded = vec!["foo/b".to_string()];
+        assert_eq!(graph.excluded_rdeps("foo/a", &excluded),
+                   Some(vec!["foo/b".to_string(),
+                             "foo/e".to_string(),
+                             "foo/f".to_string()]));
+
+        let excluded = vec!["foo/b".to_string(), "foo/c".to_string()];
+        assert_eq!(graph.excluded_rdeps("foo/a", &excluded),
+                   Some(vec!["foo/b".to_string(),
+                             "foo/c".to_string(),
+                             "foo/d".to_string(),
+                             "foo/e".to_string(),
+                             "foo/f".to_string()]));
+
+        assert_eq!(graph.excluded_rdeps("foo/a", &[]), Some(vec![]));
+        assert_eq!(graph.excluded_rdeps("foo/other", &excluded), None);
+    }
 }
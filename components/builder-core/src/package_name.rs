@@ -0,0 +1,163 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Package name matching that ignores case and punctuation, so that `libXML2` and `lib-xml2`
+//! both find core/libxml2. The depot keeps the normalized name of every package for search; the
+//! web UI and CLI apply the same rules to match what the depot matches.
+
+/// Most suggestions returned for a package that does not exist
+pub const MAX_SUGGESTIONS: usize = 5;
+
+/// The normalized form of a package name: ASCII letters lowercased and digits kept, every other
+/// character dropped. Normalizing a normalized name leaves it unchanged. Distinct names can
+/// normalize to the same form, e.g. `lib-xml2` and `libxml2`; matches return all of them.
+pub fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// The names among `candidates` to suggest for `name`, nearest first once normalized and
+/// alphabetically among equally near ones, at most `MAX_SUGGESTIONS`. A candidate is near when
+/// its normalized name contains or is contained in that of `name`, or is one edit from it for
+/// every four characters of it, at least one. `name` itself is never suggested.
+pub fn suggestions<'a, I>(name: &str, candidates: I) -> Vec<String>
+    where I: IntoIterator<Item = &'a str>
+{
+    let wanted = normalize(name);
+    if wanted.is_empty() {
+        return Vec::new();
+    }
+    let max_edits = max_edits(&wanted);
+
+    let mut near: Vec<(usize, &str)> =
+        candidates.into_iter()
+                  .filter(|candidate| *candidate != name)
+                  .filter_map(|candidate| {
+                      edits_if_near(&wanted, candidate, max_edits).map(|edits| (edits, candidate))
+                  })
+                  .collect();
+    near.sort();
+    near.dedup();
+    near.into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate.to_string())
+        .collect()
+}
+
+/// The most edits a name near the normalized `wanted` is from it, one for every four characters
+/// of it and at least one
+pub fn max_edits(wanted: &str) -> usize { (wanted.len() / 4).max(1) }
+
+// The edits from `wanted` to the normalized `candidate`, if it is near
+fn edits_if_near(wanted: &str, candidate: &str, max_edits: usize) -> Option<usize> {
+    let normalized = normalize(candidate);
+    let edits = distance(wanted, &normalized);
+    let contained =
+        !normalized.is_empty() && (normalized.contains(wanted) || wanted.contains(&normalized));
+    if contained || edits <= max_edits {
+        Some(edits)
+    } else {
+        None
+    }
+}
+
+// The Levenshtein distance between two normalized names
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn case_and_punctuation_are_ignored() {
+        assert_eq!(normalize("libXML2"), "libxml2");
+        assert_eq!(normalize("lib-xml2"), "libxml2");
+        assert_eq!(normalize("Lib_XML.2+"), "libxml2");
+        assert_eq!(normalize("--"), "");
+    }
+
+    #[test]
+    fn nearest_names_are_suggested_first() {
+        let names = vec!["libxml2", "libxslt", "libxml2-python", "zlib", "lib-xml2"];
+        assert_eq!(suggestions("libXML", names.iter().cloned()),
+                   vec!["lib-xml2", "libxml2", "libxml2-python"]);
+        assert_eq!(suggestions("libxml2", names.iter().cloned()),
+                   vec!["lib-xml2", "libxml2-python"]);
+        assert_eq!(suggestions("libxsl", names.iter().cloned()),
+                   vec!["libxslt"]);
+        assert!(suggestions("---", names.iter().cloned()).is_empty());
+    }
+
+    #[test]
+    fn suggestions_are_capped() {
+        let names: Vec<String> = (0..10).map(|n| format!("redis{}", n)).collect();
+        let found = suggestions("redis", names.iter().map(String::as_str));
+        assert_eq!(found,
+                   vec!["redis0", "redis1", "redis2", "redis3", "redis4"]);
+    }
+
+    #[test]
+    fn distance_counts_edits() {
+        assert_eq!(distance("libxml2", "libxml2"), 0);
+        assert_eq!(distance("libxml", "libxml2"), 1);
+        assert_eq!(distance("redis", "rebis"), 1);
+        assert_eq!(distance("", "zlib"), 4);
+        assert_eq!(distance("kitten", "sitting"), 3);
+    }
+
+    proptest! {
+        #[test]
+        fn normalizing_is_stable(name in "\\PC*") {
+            let normalized = normalize(&name);
+            prop_assert_eq!(normalize(&normalized), normalized.clone());
+            prop_assert!(normalized.chars()
+                                   .all(|c| c.is_ascii_digit() || c.is_ascii_lowercase()));
+        }
+
+        #[test]
+        fn case_and_separators_never_change_the_normalized_name(
+            name in "[a-zA-Z0-9]{1,20}",
+            separator in "[-_.+ ]"
+        ) {
+            let split = format!("{}{}{}", &name[..1], separator, &name[1..]);
+            prop_assert_eq!(normalize(&name.to_uppercase()), normalize(&name));
+            prop_assert_eq!(normalize(&split), normalize(&name));
+        }
+
+        #[test]
+        fn names_normalizing_identically_are_all_suggested(name in "[a-z][a-z0-9]{2,12}") {
+            let dashed = format!("{}-{}", &name[..1], &name[1..]);
+            let upper = name.to_uppercase();
+            let names = vec![name.as_str(), dashed.as_str(), upper.as_str()];
+            let found = suggestions(&format!("{}_", name), names.iter().cloned());
+            prop_assert_eq!(found.len(), 3);
+        }
+    }
+}
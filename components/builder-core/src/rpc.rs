@@ -12,8 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{io::Read,
-          iter::FromIterator};
+use std::{io::{self,
+               Read},
+          iter::FromIterator,
+          time::Duration};
 
 use reqwest::{header::HeaderMap,
               Client,
@@ -22,7 +24,8 @@ use reqwest::{header::HeaderMap,
 use protobuf;
 use serde_json;
 
-use crate::{error::{Error,
+use crate::{build_info::BuildInfo,
+            error::{Error,
                     Result},
             http_client::{ACCEPT_APPLICATION_JSON,
                           CONTENT_TYPE_APPLICATION_JSON,
@@ -35,10 +38,18 @@ pub struct RpcMessage {
     pub id: String,
     #[serde(default)]
     pub body: Vec<u8>,
+    /// Milliseconds left of the caller's deadline when the message was sent, none when the
+    /// caller has no deadline
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline_ms: Option<u64>,
 }
 
 impl RpcMessage {
-    pub fn new(id: String, body: Vec<u8>) -> Self { RpcMessage { id, body } }
+    pub fn new(id: String, body: Vec<u8>) -> Self {
+        RpcMessage { id,
+                     body,
+                     deadline_ms: None }
+    }
 
     pub fn make<T>(msg: &T) -> Result<RpcMessage>
         where T: protobuf::Message
@@ -58,8 +69,10 @@ impl RpcMessage {
 
 // RPC client
 pub struct RpcClient {
-    cli:      Client,
-    endpoint: String,
+    cli:              Client,
+    endpoint:         String,
+    status_endpoint:  String,
+    version_endpoint: String,
 }
 
 impl RpcClient {
@@ -77,21 +90,66 @@ impl RpcClient {
         };
 
         RpcClient { cli,
-                    endpoint: format!("{}/rpc", url) }
+                    endpoint: format!("{}/rpc", url),
+                    status_endpoint: format!("{}/status", url),
+                    version_endpoint: format!("{}/version", url) }
+    }
+
+    /// Check the availability of the remote service. Any response other than a 200 is an error.
+    pub fn status(&self) -> Result<()> {
+        let res = self.cli
+                      .get(&self.status_endpoint)
+                      .send()
+                      .map_err(Error::HttpClient)?;
+        match res.status() {
+            StatusCode::OK => Ok(()),
+            status => Err(Error::RpcError(status.as_u16(), String::new())),
+        }
+    }
+
+    /// What the remote service was built from
+    pub fn version(&self) -> Result<BuildInfo> {
+        let mut res = self.cli
+                          .get(&self.version_endpoint)
+                          .send()
+                          .map_err(Error::HttpClient)?;
+        let mut s = String::new();
+        res.read_to_string(&mut s).map_err(Error::IO)?;
+        match res.status() {
+            StatusCode::OK => Ok(serde_json::from_str(&s)?),
+            status => Err(Error::RpcError(status.as_u16(), s)),
+        }
     }
 
     pub fn rpc<R, T>(&self, req: &R) -> Result<T>
         where R: protobuf::Message,
               T: protobuf::Message
     {
-        let id = req.descriptor().name().to_owned();
-        let body = req.write_to_bytes()?;
-        let msg = RpcMessage { id, body };
+        self.rpc_within(req, None)
+    }
+
+    /// Sends an RPC that has to be answered within `budget`. The remote service is given the
+    /// budget to bound its work by, and the call fails with `DeadlineExceeded` once it runs out.
+    pub fn rpc_within<R, T>(&self, req: &R, budget: Option<Duration>) -> Result<T>
+        where R: protobuf::Message,
+              T: protobuf::Message
+    {
+        let mut msg = RpcMessage::make(req)?;
+        let mut request = self.cli.post(&self.endpoint);
+        if let Some(budget) = budget {
+            let deadline_ms = duration_ms(budget);
+            if deadline_ms == 0 {
+                return Err(Error::DeadlineExceeded);
+            }
+            msg.deadline_ms = Some(deadline_ms);
+            request = request.timeout(budget);
+        }
         debug!("Sending RPC Message: {}", msg.id);
 
         let json = serde_json::to_string(&msg)?;
-        let mut res = match self.cli.post(&self.endpoint).body(json).send() {
+        let mut res = match request.body(json).send() {
             Ok(res) => res,
+            Err(ref err) if err.is_timeout() => return Err(Error::DeadlineExceeded),
             Err(err) => {
                 debug!("Got http error: {}", err);
                 return Err(Error::HttpClient(err));
@@ -100,7 +158,13 @@ impl RpcClient {
         debug!("Got RPC response status: {}", res.status());
 
         let mut s = String::new();
-        res.read_to_string(&mut s).map_err(Error::IO)?;
+        match res.read_to_string(&mut s) {
+            Ok(_) => (),
+            Err(ref err) if err.kind() == io::ErrorKind::TimedOut => {
+                return Err(Error::DeadlineExceeded)
+            }
+            Err(err) => return Err(Error::IO(err)),
+        }
         trace!("Got http response body: {}", s);
 
         match res.status() {
@@ -111,7 +175,44 @@ impl RpcClient {
                 let resp_msg = protobuf::parse_from_bytes::<T>(&resp_json.body)?;
                 Ok(resp_msg)
             }
+            StatusCode::GATEWAY_TIMEOUT if budget.is_some() => Err(Error::DeadlineExceeded),
             status => Err(Error::RpcError(status.as_u16(), s)),
         }
     }
 }
+
+// Whole milliseconds, a budget under one having run out
+fn duration_ms(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::jobsrv;
+
+    #[test]
+    fn deadline_is_carried_only_when_set() {
+        let mut msg = RpcMessage::new("JobGet".to_string(), vec![1, 2]);
+        assert!(!serde_json::to_string(&msg).unwrap().contains("deadline_ms"));
+
+        msg.deadline_ms = Some(9500);
+        let json = serde_json::to_string(&msg).unwrap();
+        let parsed: RpcMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.deadline_ms, Some(9500));
+
+        let parsed: RpcMessage = serde_json::from_str(r#"{"id": "JobGet", "body": []}"#).unwrap();
+        assert_eq!(parsed.deadline_ms, None);
+    }
+
+    #[test]
+    fn exhausted_budget_fails_without_sending() {
+        let client = RpcClient::new("http://127.0.0.1:1");
+        let result = client.rpc_within::<_, jobsrv::Job>(&jobsrv::JobGet::new(),
+                                                         Some(Duration::from_micros(500)));
+        match result {
+            Err(Error::DeadlineExceeded) => (),
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -17,7 +17,8 @@ use std::{collections::HashMap,
           str::FromStr};
 
 use crate::{hab_core::package::PackageTarget,
-            package_graph::PackageGraph,
+            package_graph::{Divergence,
+                            PackageGraph},
             protocol::originsrv};
 
 pub struct TargetGraphStats {
@@ -36,7 +37,11 @@ impl TargetGraph {
         let mut graphs = HashMap::new();
 
         // We only support the following targets currently
-        for target_str in &["x86_64-linux", "x86_64-linux-kernel2", "x86_64-windows"] {
+        for target_str in &["x86_64-linux",
+                            "x86_64-linux-kernel2",
+                            "x86_64-windows",
+                            "aarch64-linux"]
+        {
             graphs.insert(PackageTarget::from_str(target_str).unwrap(),
                           PackageGraph::new());
         }
@@ -70,9 +75,7 @@ impl TargetGraph {
         where T: Iterator<Item = originsrv::OriginPackage>
     {
         for p in packages {
-            if let Some(ref mut graph) = self.graph_mut(p.get_target()) {
-                graph.extend(&p, use_build_deps);
-            }
+            self.extend(&p, use_build_deps);
         }
 
         let mut target_stats = Vec::new();
@@ -86,4 +89,52 @@ impl TargetGraph {
 
         target_stats
     }
+
+    /// Adds `package` to the graph of its target, replacing the edges of an older release.
+    /// Returns the node and edge counts of that graph, or `None` for an unsupported target.
+    pub fn extend(&mut self,
+                  package: &originsrv::OriginPackage,
+                  use_build_deps: bool)
+                  -> Option<(usize, usize)> {
+        self.graph_mut(package.get_target())
+            .map(|graph| graph.extend(package, use_build_deps))
+    }
+
+    /// Compares the graph of the package's target against its stored metadata, see
+    /// `PackageGraph::diff`. Packages of unsupported targets have nothing to compare.
+    pub fn diff(&self,
+                package: &originsrv::OriginPackage,
+                use_build_deps: bool)
+                -> Option<Divergence> {
+        self.graph(package.get_target())
+            .and_then(|graph| graph.diff(package, use_build_deps))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn package(ident: &str, target: &str) -> originsrv::OriginPackage {
+        let mut package = originsrv::OriginPackage::new();
+        package.set_ident(originsrv::OriginPackageIdent::from_str(ident).unwrap());
+        package.set_target(target.to_string());
+        package
+    }
+
+    #[test]
+    fn packages_go_to_the_graph_of_their_target() {
+        let mut graph = TargetGraph::new();
+        assert_eq!(graph.extend(&package("core/redis/4.0.14/1", "aarch64-linux"), true),
+                   Some((1, 0)));
+        assert_eq!(graph.extend(&package("core/nginx/1.17.4/1", "x86_64-linux"), true),
+                   Some((1, 0)));
+        assert_eq!(graph.extend(&package("core/redis/4.0.14/1", "x86_64-darwin"), true),
+                   None);
+
+        let node_count = |target| graph.graph(target).unwrap().stats().node_count;
+        assert_eq!(node_count("aarch64-linux"), 1);
+        assert_eq!(node_count("x86_64-linux"), 1);
+        assert_eq!(node_count("x86_64-linux-kernel2"), 0);
+    }
 }
@@ -0,0 +1,166 @@
+// Copyright (c) 2017 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Canonical timestamp formatting for everything Builder hands back to clients.
+//!
+//! Timestamps are rendered as RFC 3339 in UTC with a `Z` suffix and microsecond precision, which
+//! matches what Postgres stores. Model fields opt in with `#[serde(with = "rfc3339")]` (or
+//! `rfc3339_opt` for nullable columns); deserialization also accepts the older naive format so
+//! values cached before the switch still load.
+
+use chrono::{DateTime,
+             NaiveDateTime,
+             SecondsFormat,
+             TimeZone,
+             Utc};
+
+/// Format of the timestamp embedded in package releases and key revisions
+pub const RELEASE_FORMAT: &str = "%Y%m%d%H%M%S";
+
+// Format serde has historically used for naive timestamps
+const NAIVE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+pub fn to_rfc3339(dt: &DateTime<Utc>) -> String { dt.to_rfc3339_opts(SecondsFormat::Micros, true) }
+
+pub fn naive_to_rfc3339(dt: &NaiveDateTime) -> String {
+    to_rfc3339(&DateTime::<Utc>::from_utc(*dt, Utc))
+}
+
+/// Parses an RFC 3339 timestamp, or a naive timestamp which is assumed to be in UTC
+pub fn parse(value: &str) -> Option<DateTime<Utc>> {
+    match DateTime::parse_from_rfc3339(value) {
+        Ok(dt) => Some(dt.with_timezone(&Utc)),
+        Err(_) => {
+            NaiveDateTime::parse_from_str(value, NAIVE_FORMAT).ok()
+                                                              .map(|dt| Utc.from_utc_datetime(&dt))
+        }
+    }
+}
+
+/// Converts a package release or key revision, e.g. `20190701123456`, into RFC 3339. Returns
+/// `None` for values which are not timestamps.
+pub fn release_to_rfc3339(release: &str) -> Option<String> {
+    NaiveDateTime::parse_from_str(release, RELEASE_FORMAT).ok()
+                                                          .map(|dt| naive_to_rfc3339(&dt))
+}
+
+pub mod rfc3339 {
+    use chrono::NaiveDateTime;
+    use serde::{self,
+                Deserialize,
+                Deserializer,
+                Serializer};
+
+    pub fn serialize<S>(dt: &NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_str(&super::naive_to_rfc3339(dt))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<NaiveDateTime, D::Error>
+        where D: Deserializer<'de>
+    {
+        let s = String::deserialize(deserializer)?;
+        super::parse(&s).map(|dt| dt.naive_utc())
+                        .ok_or_else(|| serde::de::Error::custom(format!("invalid timestamp {}", s)))
+    }
+}
+
+pub mod rfc3339_opt {
+    use chrono::NaiveDateTime;
+    use serde::{self,
+                Deserialize,
+                Deserializer,
+                Serializer};
+
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn serialize<S>(dt: &Option<NaiveDateTime>, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        match dt {
+            Some(dt) => serializer.serialize_some(&super::naive_to_rfc3339(dt)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<NaiveDateTime>, D::Error>
+        where D: Deserializer<'de>
+    {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(s) => {
+                super::parse(&s).map(|dt| Some(dt.naive_utc()))
+                                .ok_or_else(|| {
+                                    serde::de::Error::custom(format!("invalid timestamp {}", s))
+                                })
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json;
+
+    #[derive(Serialize, Deserialize)]
+    struct Stamped {
+        #[serde(with = "rfc3339")]
+        created_at: NaiveDateTime,
+        #[serde(with = "rfc3339_opt")]
+        updated_at: Option<NaiveDateTime>,
+    }
+
+    fn stamp() -> NaiveDateTime {
+        NaiveDateTime::parse_from_str("20190701123456", RELEASE_FORMAT).unwrap()
+    }
+
+    #[test]
+    fn formats_utc_with_z_suffix() {
+        assert_eq!(naive_to_rfc3339(&stamp()), "2019-07-01T12:34:56.000000Z");
+        assert_eq!(to_rfc3339(&Utc.from_utc_datetime(&stamp())),
+                   "2019-07-01T12:34:56.000000Z");
+    }
+
+    #[test]
+    fn parses_offsets_and_legacy_naive_values() {
+        let expected = Utc.from_utc_datetime(&stamp());
+        assert_eq!(parse("2019-07-01T12:34:56Z"), Some(expected));
+        assert_eq!(parse("2019-07-01T14:34:56+02:00"), Some(expected));
+        assert_eq!(parse("2019-07-01T12:34:56"), Some(expected));
+        assert_eq!(parse("yesterday"), None);
+    }
+
+    #[test]
+    fn release_conversion() {
+        assert_eq!(release_to_rfc3339("20190701123456"),
+                   Some("2019-07-01T12:34:56.000000Z".to_string()));
+        assert_eq!(release_to_rfc3339("1.2.3"), None);
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let stamped = Stamped { created_at: stamp(),
+                                updated_at: None, };
+        let json = serde_json::to_string(&stamped).unwrap();
+        assert_eq!(json,
+                   r#"{"created_at":"2019-07-01T12:34:56.000000Z","updated_at":null}"#);
+
+        let legacy: Stamped =
+            serde_json::from_str(r#"{"created_at":"2019-07-01T12:34:56","updated_at":"2019-07-01T12:34:56.5"}"#).unwrap();
+        assert_eq!(legacy.created_at, stamp());
+        assert_eq!(naive_to_rfc3339(&legacy.updated_at.unwrap()),
+                   "2019-07-01T12:34:56.500000Z");
+    }
+}
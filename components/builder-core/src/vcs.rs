@@ -0,0 +1,42 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers shared by the API, which checks the clone URL of a project, and the worker, which
+//! clones it.
+
+/// Whether `url` is cloned over SSH, either as an `ssh://` URL or in the scp-like
+/// `user@host:path` form.
+pub fn is_ssh_url(url: &str) -> bool {
+    if url.starts_with("ssh://") {
+        return true;
+    }
+    match (url.find('@'), url.find(':')) {
+        (Some(at), Some(colon)) => at < colon && !url.contains("://"),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ssh_urls_are_recognized() {
+        assert!(is_ssh_url("ssh://git@git.example.com/core/testapp.git"));
+        assert!(is_ssh_url("git@git.example.com:core/testapp.git"));
+        assert!(!is_ssh_url("https://git.example.com/core/testapp.git"));
+        assert!(!is_ssh_url("https://builder@git.example.com/core/testapp.git"));
+        assert!(!is_ssh_url("/srv/git/testapp.git"));
+    }
+}
@@ -15,17 +15,39 @@
 use std::{thread,
           time::Duration};
 
-use diesel::{pg::PgConnection,
-             r2d2::{ConnectionManager,
+use diesel::{connection::SimpleConnection,
+             pg::PgConnection,
+             r2d2::{self,
+                    ConnectionManager,
+                    ManageConnection,
                     Pool,
                     PooledConnection}};
 
 use crate::{config::DataStoreCfg,
             error::Result};
 
-type PgPool = Pool<ConnectionManager<PgConnection>>;
+type PgPool = Pool<SessionManager>;
 
-type PgPooledConnection = PooledConnection<ConnectionManager<PgConnection>>;
+pub type PgPooledConnection = PooledConnection<SessionManager>;
+
+/// Manages Postgres connections whose session settings are put back to their defaults as they
+/// are checked out. Statements bounded by a deadline set a `statement_timeout` on the session, a
+/// connection that cannot be reset is discarded rather than handed out with it.
+pub struct SessionManager(ConnectionManager<PgConnection>);
+
+impl ManageConnection for SessionManager {
+    type Connection = PgConnection;
+    type Error = r2d2::Error;
+
+    fn connect(&self) -> std::result::Result<PgConnection, r2d2::Error> { self.0.connect() }
+
+    fn is_valid(&self, conn: &mut PgConnection) -> std::result::Result<(), r2d2::Error> {
+        conn.batch_execute("RESET statement_timeout")
+            .map_err(r2d2::Error::QueryError)
+    }
+
+    fn has_broken(&self, conn: &mut PgConnection) -> bool { self.0.has_broken(conn) }
+}
 
 #[derive(Clone)]
 pub struct DbPool(pub PgPool);
@@ -34,10 +56,13 @@ impl DbPool {
     pub fn new(config: &DataStoreCfg) -> Self {
         debug!("Creating new DbPool, config: {:?}", config);
         loop {
-            let manager = ConnectionManager::<PgConnection>::new(config.to_string());
+            let manager = SessionManager(ConnectionManager::new(config.to_string()));
             match Pool::builder()
                 .max_size(config.pool_size)
                 .connection_timeout(Duration::from_secs(config.connection_timeout_sec))
+                // Connections severed by a failover, or left with a statement timeout, are
+                // discarded instead of handed out
+                .test_on_check_out(true)
                 .build(manager)
             {
                 Ok(pool) => return DbPool(pool),
@@ -56,4 +81,13 @@ impl DbPool {
             Err(e) => Err(e.into()),
         }
     }
+
+    /// Checks out a connection, waiting at most `timeout` rather than the configured
+    /// connection timeout
+    pub fn get_conn_within(&self, timeout: Duration) -> Result<PgPooledConnection> {
+        match self.0.get_timeout(timeout) {
+            Ok(conn) => Ok(conn),
+            Err(e) => Err(e.into()),
+        }
+    }
 }
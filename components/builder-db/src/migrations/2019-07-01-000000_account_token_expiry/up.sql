@@ -0,0 +1,14 @@
+ALTER TABLE account_tokens ADD COLUMN expires_at timestamp with time zone;
+ALTER TABLE account_tokens ADD COLUMN expiry_notified_at timestamp with time zone;
+
+CREATE SEQUENCE IF NOT EXISTS notifications_id_seq;
+
+CREATE TABLE IF NOT EXISTS notifications (
+    id bigint DEFAULT next_id_v1('notifications_id_seq') PRIMARY KEY NOT NULL,
+    account_id bigint NOT NULL,
+    category text NOT NULL,
+    message text NOT NULL,
+    created_at timestamp with time zone DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS notifications_account_id ON notifications(account_id);
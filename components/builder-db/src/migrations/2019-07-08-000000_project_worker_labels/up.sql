@@ -0,0 +1 @@
+ALTER TABLE origin_projects ADD COLUMN required_worker_labels text[] NOT NULL DEFAULT '{}';
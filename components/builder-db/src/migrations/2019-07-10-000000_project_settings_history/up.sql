@@ -0,0 +1,12 @@
+CREATE SEQUENCE IF NOT EXISTS project_settings_history_id_seq;
+
+CREATE TABLE IF NOT EXISTS project_settings_history (
+    id bigint DEFAULT next_id_v1('project_settings_history_id_seq') PRIMARY KEY NOT NULL,
+    project_id bigint NOT NULL REFERENCES origin_projects(id) ON DELETE CASCADE,
+    version integer NOT NULL,
+    settings jsonb NOT NULL,
+    actor_id bigint NOT NULL,
+    actor_name text NOT NULL,
+    created_at timestamp with time zone DEFAULT now(),
+    UNIQUE (project_id, version)
+);
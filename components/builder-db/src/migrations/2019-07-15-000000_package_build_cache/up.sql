@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS origin_package_build_cache (
+    cache_key text NOT NULL,
+    target text NOT NULL,
+    package_id bigint NOT NULL REFERENCES origin_packages(id) ON DELETE CASCADE,
+    created_at timestamp with time zone DEFAULT now(),
+    updated_at timestamp with time zone DEFAULT now(),
+    PRIMARY KEY (cache_key, target)
+);
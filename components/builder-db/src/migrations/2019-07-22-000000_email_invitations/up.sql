@@ -0,0 +1,19 @@
+ALTER TABLE origin_invitations ADD COLUMN email text;
+ALTER TABLE origin_invitations ADD COLUMN expires_at timestamp with time zone;
+
+-- At most one pending invitation per address and origin, regardless of case
+CREATE UNIQUE INDEX IF NOT EXISTS origin_invitations_pending_email
+    ON origin_invitations(origin, lower(email)) WHERE account_id IS NULL;
+
+CREATE SEQUENCE IF NOT EXISTS email_notifications_id_seq;
+
+CREATE TABLE IF NOT EXISTS email_notifications (
+    id bigint DEFAULT next_id_v1('email_notifications_id_seq') PRIMARY KEY NOT NULL,
+    email text NOT NULL,
+    category text NOT NULL,
+    message text NOT NULL,
+    created_at timestamp with time zone DEFAULT now(),
+    sent_at timestamp with time zone
+);
+
+CREATE INDEX IF NOT EXISTS email_notifications_unsent ON email_notifications(created_at) WHERE sent_at IS NULL;
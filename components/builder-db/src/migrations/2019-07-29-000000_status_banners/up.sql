@@ -0,0 +1,14 @@
+CREATE TYPE status_banner_severity AS ENUM ('info', 'warning', 'critical');
+
+CREATE SEQUENCE IF NOT EXISTS status_banners_id_seq;
+
+CREATE TABLE IF NOT EXISTS status_banners (
+    id bigint DEFAULT next_id_v1('status_banners_id_seq') PRIMARY KEY NOT NULL,
+    message text NOT NULL,
+    severity status_banner_severity NOT NULL,
+    owner_id bigint NOT NULL,
+    created_at timestamp with time zone DEFAULT now(),
+    expires_at timestamp with time zone NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS status_banners_expires_at ON status_banners(expires_at);
@@ -0,0 +1,2 @@
+-- Origins whose packages a user wants ranked first in search, highest priority first
+ALTER TABLE accounts ADD COLUMN IF NOT EXISTS search_origins text[] NOT NULL DEFAULT '{}';
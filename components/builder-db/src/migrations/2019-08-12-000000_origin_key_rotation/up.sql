@@ -0,0 +1,23 @@
+ALTER TABLE origin_public_keys ADD COLUMN expires_at timestamp with time zone;
+
+CREATE TABLE IF NOT EXISTS origin_key_rotation (
+    origin text PRIMARY KEY REFERENCES origins(name) ON DELETE CASCADE,
+    enabled boolean NOT NULL DEFAULT false,
+    interval_days integer NOT NULL,
+    created_at timestamp with time zone DEFAULT now(),
+    updated_at timestamp with time zone DEFAULT now()
+);
+
+CREATE TYPE key_rotation_trigger AS ENUM ('schedule', 'manual');
+
+CREATE TABLE IF NOT EXISTS audit_origin_key_rotation (
+    origin text NOT NULL,
+    previous_revision text,
+    revision text NOT NULL,
+    trigger key_rotation_trigger NOT NULL,
+    requester_id bigint NOT NULL,
+    requester_name text NOT NULL,
+    created_at timestamp with time zone DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS audit_origin_key_rotation_origin ON audit_origin_key_rotation(origin);
@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS origin_package_deprecations (
+    origin text NOT NULL REFERENCES origins(name) ON DELETE CASCADE,
+    name text NOT NULL,
+    message text NOT NULL,
+    replacement text,
+    created_at timestamp with time zone DEFAULT now(),
+    updated_at timestamp with time zone DEFAULT now(),
+    PRIMARY KEY (origin, name)
+);
+
+CREATE TYPE package_deprecation_operation AS ENUM ('deprecate', 'undeprecate');
+
+CREATE TABLE IF NOT EXISTS audit_package_deprecation (
+    origin text NOT NULL,
+    name text NOT NULL,
+    operation package_deprecation_operation NOT NULL,
+    message text,
+    replacement text,
+    requester_id bigint NOT NULL,
+    requester_name text NOT NULL,
+    created_at timestamp with time zone DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS audit_package_deprecation_origin_name ON audit_package_deprecation(origin, name);
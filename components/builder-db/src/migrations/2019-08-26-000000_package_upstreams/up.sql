@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS origin_package_upstreams (
+    package_id bigint PRIMARY KEY REFERENCES origin_packages(id) ON DELETE CASCADE,
+    origin text NOT NULL,
+    name text NOT NULL,
+    target text NOT NULL,
+    upstream_url text NOT NULL,
+    channel text NOT NULL,
+    fetched_at timestamp with time zone DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS origin_package_upstreams_upstream_url ON origin_package_upstreams(upstream_url);
@@ -0,0 +1,26 @@
+ALTER TABLE accounts ADD COLUMN security_notifications boolean NOT NULL DEFAULT true;
+
+CREATE TYPE account_security_event_kind AS ENUM ('token_created', 'token_new_ip', 'origin_member_added');
+
+CREATE SEQUENCE IF NOT EXISTS account_security_events_id_seq;
+
+CREATE TABLE IF NOT EXISTS account_security_events (
+    id bigint DEFAULT next_id_v1('account_security_events_id_seq') PRIMARY KEY NOT NULL,
+    account_id bigint NOT NULL,
+    kind account_security_event_kind NOT NULL,
+    token_id bigint,
+    ip text,
+    origin text,
+    actor_id bigint,
+    actor_name text,
+    created_at timestamp with time zone DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS account_security_events_account_id ON account_security_events(account_id, created_at);
+
+CREATE TABLE IF NOT EXISTS account_known_ips (
+    account_id bigint NOT NULL,
+    ip text NOT NULL,
+    first_seen_at timestamp with time zone DEFAULT now(),
+    PRIMARY KEY (account_id, ip)
+);
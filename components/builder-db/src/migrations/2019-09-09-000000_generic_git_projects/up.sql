@@ -0,0 +1,2 @@
+ALTER TABLE origin_projects ADD COLUMN vcs_credential text;
+ALTER TABLE origin_projects ADD COLUMN webhook_secret text;
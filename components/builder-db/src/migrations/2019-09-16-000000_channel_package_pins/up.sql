@@ -0,0 +1,27 @@
+CREATE TABLE IF NOT EXISTS origin_channel_package_pins (
+    origin text NOT NULL REFERENCES origins(name) ON DELETE CASCADE,
+    channel text NOT NULL,
+    name text NOT NULL,
+    target text NOT NULL,
+    ident text NOT NULL,
+    pinned_by_id bigint NOT NULL,
+    pinned_by_name text NOT NULL,
+    created_at timestamp with time zone DEFAULT now(),
+    PRIMARY KEY (origin, channel, name, target)
+);
+
+CREATE TYPE package_pin_operation AS ENUM ('pin', 'unpin');
+
+CREATE TABLE IF NOT EXISTS audit_package_pin (
+    origin text NOT NULL,
+    channel text NOT NULL,
+    name text NOT NULL,
+    target text NOT NULL,
+    ident text NOT NULL,
+    operation package_pin_operation NOT NULL,
+    requester_id bigint NOT NULL,
+    requester_name text NOT NULL,
+    created_at timestamp with time zone DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS audit_package_pin_origin_channel ON audit_package_pin(origin, channel);
@@ -0,0 +1 @@
+ALTER TABLE origin_channels ADD COLUMN rolling bool NOT NULL DEFAULT false;
@@ -0,0 +1,11 @@
+ALTER TABLE accounts ADD COLUMN default_origin text;
+
+CREATE OR REPLACE FUNCTION clear_account_default_origin() RETURNS trigger AS $$
+    BEGIN
+        UPDATE accounts SET default_origin = NULL
+            WHERE id = OLD.account_id AND default_origin = OLD.origin;
+        RETURN OLD;
+    END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER origin_members_default_origin AFTER DELETE ON origin_members FOR EACH ROW EXECUTE PROCEDURE clear_account_default_origin();
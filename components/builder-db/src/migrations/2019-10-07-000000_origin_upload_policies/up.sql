@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS origin_upload_policies (
+    origin text PRIMARY KEY REFERENCES origins(name) ON DELETE CASCADE,
+    require_license boolean NOT NULL DEFAULT false,
+    allowed_licenses text[] NOT NULL DEFAULT '{}',
+    blocked_licenses text[] NOT NULL DEFAULT '{}',
+    require_description boolean NOT NULL DEFAULT false,
+    created_at timestamp with time zone DEFAULT now(),
+    updated_at timestamp with time zone DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS audit_upload_policy_override (
+    origin text NOT NULL,
+    package_ident text NOT NULL,
+    target text NOT NULL,
+    violations text[] NOT NULL,
+    requester_id bigint NOT NULL,
+    requester_name text NOT NULL,
+    created_at timestamp with time zone DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS audit_upload_policy_override_origin ON audit_upload_policy_override(origin);
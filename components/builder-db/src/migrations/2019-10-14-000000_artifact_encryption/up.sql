@@ -0,0 +1,22 @@
+CREATE TABLE IF NOT EXISTS origin_artifact_encryption (
+    origin text PRIMARY KEY REFERENCES origins(name) ON DELETE CASCADE,
+    key_id text NOT NULL,
+    created_at timestamp with time zone DEFAULT now(),
+    updated_at timestamp with time zone DEFAULT now()
+);
+
+-- One row per encrypted artifact. The data key the artifact is encrypted with is kept wrapped
+-- by the origin key named in key_id, so rotating the origin key only re-wraps these rows.
+CREATE TABLE IF NOT EXISTS origin_package_encryption (
+    ident text NOT NULL,
+    target text NOT NULL,
+    origin text NOT NULL,
+    key_id text NOT NULL,
+    wrapped_data_key text NOT NULL,
+    plaintext_checksum text NOT NULL,
+    created_at timestamp with time zone DEFAULT now(),
+    updated_at timestamp with time zone DEFAULT now(),
+    PRIMARY KEY (ident, target)
+);
+
+CREATE INDEX IF NOT EXISTS origin_package_encryption_origin ON origin_package_encryption(origin);
@@ -0,0 +1,5 @@
+ALTER TABLE accounts ADD COLUMN last_authenticated_at timestamp with time zone;
+
+CREATE INDEX IF NOT EXISTS origin_packages_origin_owner_id ON origin_packages(origin, owner_id);
+CREATE INDEX IF NOT EXISTS audit_package_origin_requester_id ON audit_package(origin, requester_id);
+CREATE INDEX IF NOT EXISTS audit_package_group_origin_requester_id ON audit_package_group(origin, requester_id);
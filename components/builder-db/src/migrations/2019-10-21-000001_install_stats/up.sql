@@ -0,0 +1,27 @@
+-- Installs reported by hab clients, rolled up per package, target and day. Reports for packages
+-- this Builder does not know are counted under the ident 'unknown', without an origin or name.
+CREATE TABLE IF NOT EXISTS install_stats (
+    day date NOT NULL,
+    ident text NOT NULL,
+    origin text,
+    name text,
+    target text NOT NULL,
+    successes bigint NOT NULL DEFAULT 0,
+    failures bigint NOT NULL DEFAULT 0,
+    PRIMARY KEY (day, ident, target)
+);
+
+CREATE INDEX IF NOT EXISTS install_stats_origin_name ON install_stats(origin, name, day);
+
+-- The sites that reported installing a package on a day, by the opaque id their clients send, so
+-- that each is counted once. Kept for a limited time only.
+CREATE TABLE IF NOT EXISTS install_sites (
+    day date NOT NULL,
+    origin text NOT NULL,
+    name text NOT NULL,
+    target text NOT NULL,
+    site_id text NOT NULL,
+    PRIMARY KEY (origin, name, target, day, site_id)
+);
+
+CREATE INDEX IF NOT EXISTS install_sites_day ON install_sites(day);
@@ -0,0 +1,11 @@
+-- Packages a worker built and uploaded but refused to promote, as their signature did not
+-- verify against a key of the origin still accepted for signing
+CREATE TABLE IF NOT EXISTS origin_package_verification_failures (
+    ident text NOT NULL,
+    target text NOT NULL,
+    origin text NOT NULL,
+    key_name text NOT NULL,
+    reason text NOT NULL,
+    created_at timestamp with time zone DEFAULT now(),
+    PRIMARY KEY (ident, target)
+);
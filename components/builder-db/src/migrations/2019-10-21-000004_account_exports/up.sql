@@ -0,0 +1,19 @@
+CREATE TYPE account_export_state AS ENUM ('pending', 'ready', 'failed');
+
+CREATE SEQUENCE IF NOT EXISTS account_exports_id_seq;
+
+-- Exports of an account's data asked for by its owner. The archive is kept in the artifact store
+-- under object_key until expires_at.
+CREATE TABLE IF NOT EXISTS account_exports (
+    id bigint DEFAULT next_id_v1('account_exports_id_seq') PRIMARY KEY NOT NULL,
+    account_id bigint NOT NULL,
+    state account_export_state NOT NULL DEFAULT 'pending',
+    object_key text,
+    created_at timestamp with time zone DEFAULT now(),
+    completed_at timestamp with time zone,
+    expires_at timestamp with time zone
+);
+
+-- An account has at most one export being assembled
+CREATE UNIQUE INDEX IF NOT EXISTS account_exports_pending ON account_exports(account_id) WHERE state = 'pending';
+CREATE INDEX IF NOT EXISTS account_exports_expires_at ON account_exports(expires_at);
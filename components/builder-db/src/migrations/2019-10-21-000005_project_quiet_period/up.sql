@@ -0,0 +1 @@
+ALTER TABLE origin_projects ADD COLUMN quiet_period_secs integer NOT NULL DEFAULT 0;
@@ -0,0 +1,8 @@
+ALTER TABLE origin_upload_policies ADD COLUMN IF NOT EXISTS strict_dependency_validation boolean NOT NULL DEFAULT false;
+
+CREATE TABLE IF NOT EXISTS origin_package_dependency_reports (
+    package_id bigint PRIMARY KEY REFERENCES origin_packages(id) ON DELETE CASCADE,
+    missing text[] NOT NULL DEFAULT '{}',
+    deprecated text[] NOT NULL DEFAULT '{}',
+    checked_at timestamp with time zone DEFAULT now()
+);
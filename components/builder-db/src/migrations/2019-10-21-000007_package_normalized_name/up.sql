@@ -0,0 +1,10 @@
+ALTER TABLE origin_packages ADD COLUMN IF NOT EXISTS normalized_name text;
+
+-- The rules of builder_core::package_name::normalize: ASCII letters lowercased, digits kept and
+-- every other character dropped
+UPDATE origin_packages SET normalized_name = lower(regexp_replace(name, '[^A-Za-z0-9]', '', 'g'))
+    WHERE normalized_name IS NULL;
+
+ALTER TABLE origin_packages ALTER COLUMN normalized_name SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS origin_packages_origin_normalized_name ON origin_packages(origin, normalized_name);
@@ -0,0 +1,46 @@
+CREATE TYPE announcement_audience AS ENUM ('everyone', 'authenticated', 'origins');
+CREATE TYPE announcement_operation AS ENUM ('create', 'update', 'expire');
+
+CREATE SEQUENCE IF NOT EXISTS announcements_id_seq;
+
+-- Operator messages shown in the web UI between starts_at and ends_at, to everyone, to signed in
+-- users, or to the members of the listed origins
+CREATE TABLE IF NOT EXISTS announcements (
+    id bigint DEFAULT next_id_v1('announcements_id_seq') PRIMARY KEY NOT NULL,
+    message text NOT NULL,
+    severity status_banner_severity NOT NULL,
+    audience announcement_audience NOT NULL DEFAULT 'everyone',
+    origins text[] NOT NULL DEFAULT '{}',
+    owner_id bigint NOT NULL,
+    starts_at timestamp with time zone NOT NULL,
+    ends_at timestamp with time zone NOT NULL,
+    created_at timestamp with time zone DEFAULT now(),
+    updated_at timestamp with time zone DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS announcements_ends_at ON announcements(ends_at);
+
+-- The announcements each user dismissed, gone with the announcement once it is pruned
+CREATE TABLE IF NOT EXISTS announcement_dismissals (
+    account_id bigint NOT NULL,
+    announcement_id bigint NOT NULL REFERENCES announcements(id) ON DELETE CASCADE,
+    created_at timestamp with time zone DEFAULT now(),
+    PRIMARY KEY (account_id, announcement_id)
+);
+
+-- Every change to an announcement with the announcement as it was left, kept after it is pruned
+CREATE TABLE IF NOT EXISTS audit_announcement (
+    announcement_id bigint NOT NULL,
+    operation announcement_operation NOT NULL,
+    message text NOT NULL,
+    severity status_banner_severity NOT NULL,
+    audience announcement_audience NOT NULL,
+    origins text[] NOT NULL,
+    starts_at timestamp with time zone NOT NULL,
+    ends_at timestamp with time zone NOT NULL,
+    requester_id bigint NOT NULL,
+    requester_name text NOT NULL,
+    created_at timestamp with time zone DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS audit_announcement_announcement_id ON audit_announcement(announcement_id);
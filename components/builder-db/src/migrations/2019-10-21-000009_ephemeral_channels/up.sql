@@ -0,0 +1,25 @@
+CREATE TYPE origin_channel_type AS ENUM ('standard', 'ephemeral');
+CREATE TYPE channel_operation AS ENUM ('create', 'renew', 'expire', 'delete');
+
+-- Ephemeral channels are deleted with their contents once expires_at has passed, unless they
+-- are renewed first
+ALTER TABLE origin_channels ADD COLUMN IF NOT EXISTS channel_type origin_channel_type NOT NULL DEFAULT 'standard';
+ALTER TABLE origin_channels ADD COLUMN IF NOT EXISTS expires_at timestamp with time zone;
+
+CREATE INDEX IF NOT EXISTS origin_channels_expires_at ON origin_channels(expires_at) WHERE channel_type = 'ephemeral';
+
+-- The lifecycle of channels, kept after they are deleted. package_ids are the releases the
+-- channel held when it expired or was deleted.
+CREATE TABLE IF NOT EXISTS audit_channel (
+    origin text NOT NULL,
+    channel text NOT NULL,
+    channel_type origin_channel_type NOT NULL,
+    operation channel_operation NOT NULL,
+    expires_at timestamp with time zone,
+    package_ids bigint[] NOT NULL DEFAULT '{}',
+    requester_id bigint NOT NULL,
+    requester_name text NOT NULL,
+    created_at timestamp with time zone DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS audit_channel_origin_channel ON audit_channel(origin, channel, created_at);
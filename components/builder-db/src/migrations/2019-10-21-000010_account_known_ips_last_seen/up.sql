@@ -0,0 +1,6 @@
+-- Known addresses are forgotten once they go unused for the security event retention period,
+-- and only the most recently used ones are kept per account
+ALTER TABLE account_known_ips ADD COLUMN IF NOT EXISTS last_seen_at timestamp with time zone DEFAULT now();
+
+CREATE INDEX IF NOT EXISTS account_known_ips_last_seen_at ON account_known_ips(last_seen_at);
+CREATE INDEX IF NOT EXISTS account_security_events_account_kind ON account_security_events(account_id, kind, created_at);
@@ -0,0 +1,6 @@
+-- Releases a promotion into a rolling channel demotes are part of the channel's lifecycle. The
+-- type is swapped rather than altered, ADD VALUE can't run in the migration's transaction.
+ALTER TYPE channel_operation RENAME TO channel_operation_old;
+CREATE TYPE channel_operation AS ENUM ('create', 'renew', 'expire', 'delete', 'supersede');
+ALTER TABLE audit_channel ALTER COLUMN operation TYPE channel_operation USING operation::text::channel_operation;
+DROP TYPE channel_operation_old;
@@ -0,0 +1,3 @@
+-- The target of the release a promotion or demotion was of. Audits made before it was recorded
+-- have none.
+ALTER TABLE audit_package ADD COLUMN IF NOT EXISTS package_target text;
@@ -0,0 +1,2 @@
+-- The dependency check of an upload looks its dependencies up by origin, name and target
+CREATE INDEX IF NOT EXISTS origin_packages_origin_name_target ON origin_packages(origin, name, target);
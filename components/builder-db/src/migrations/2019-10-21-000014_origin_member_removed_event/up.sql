@@ -0,0 +1,6 @@
+-- Sign ins remove members from the origins of provider groups they left. The type is swapped
+-- rather than altered, ADD VALUE can't run in the migration's transaction.
+ALTER TYPE account_security_event_kind RENAME TO account_security_event_kind_old;
+CREATE TYPE account_security_event_kind AS ENUM ('token_created', 'token_new_ip', 'origin_member_added', 'origin_member_removed');
+ALTER TABLE account_security_events ALTER COLUMN kind TYPE account_security_event_kind USING kind::text::account_security_event_kind;
+DROP TYPE account_security_event_kind_old;
@@ -0,0 +1,5 @@
+-- Searches match normalized names by prefix, which a btree index only serves with the pattern
+-- operator class unless the database collation is C
+DROP INDEX IF EXISTS origin_packages_origin_normalized_name;
+CREATE INDEX IF NOT EXISTS origin_packages_origin_normalized_name ON origin_packages(origin, normalized_name text_pattern_ops);
+CREATE INDEX IF NOT EXISTS origin_packages_normalized_name ON origin_packages(normalized_name text_pattern_ops);
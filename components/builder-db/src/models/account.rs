@@ -1,15 +1,29 @@
-use super::db_id_format;
-use chrono::NaiveDateTime;
+use super::{db_id_format,
+            db_optional_id_format,
+            pagination::Paginate};
+use crate::bldr_core::timestamp::rfc3339_opt;
+use chrono::{NaiveDateTime,
+             Utc};
 use diesel::{self,
              pg::PgConnection,
-             result::QueryResult,
+             result::{OptionalExtension,
+                      QueryResult},
+             sql_types::{BigInt,
+                         Bool,
+                         Nullable,
+                         Text,
+                         Timestamptz},
+             BoolExpressionMethods,
              ExpressionMethods,
              QueryDsl,
              RunQueryDsl};
 
 use crate::{bldr_core::metrics::CounterMetric,
             metrics::Counter,
-            schema::account::{account_tokens,
+            schema::account::{account_exports,
+                              account_known_ips,
+                              account_security_events,
+                              account_tokens,
                               accounts}};
 
 #[derive(Debug, Identifiable, Serialize, Queryable)]
@@ -18,8 +32,15 @@ pub struct Account {
     pub id: i64,
     pub email: String,
     pub name: String,
+    #[serde(with = "rfc3339_opt")]
     pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
     pub updated_at: Option<NaiveDateTime>,
+    pub search_origins: Vec<String>,
+    pub security_notifications: bool,
+    pub default_origin: Option<String>,
+    #[serde(with = "rfc3339_opt")]
+    pub last_authenticated_at: Option<NaiveDateTime>,
 }
 
 #[derive(Identifiable, Debug, Serialize, Queryable)]
@@ -30,7 +51,12 @@ pub struct AccountToken {
     #[serde(with = "db_id_format")]
     pub account_id: i64,
     pub token: String,
+    #[serde(with = "rfc3339_opt")]
     pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
+    pub expires_at: Option<NaiveDateTime>,
+    #[serde(skip_serializing)]
+    pub expiry_notified_at: Option<NaiveDateTime>,
 }
 
 #[derive(Insertable)]
@@ -78,6 +104,44 @@ impl Account {
         diesel::update(accounts::table.find(id as i64)).set(accounts::email.eq(email))
                                                        .execute(conn)
     }
+
+    pub fn update_search_origins(id: u64,
+                                 origins: &[String],
+                                 conn: &PgConnection)
+                                 -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::update(accounts::table.find(id as i64)).set(accounts::search_origins.eq(origins))
+                                                       .execute(conn)
+    }
+
+    pub fn update_security_notifications(id: u64,
+                                         enabled: bool,
+                                         conn: &PgConnection)
+                                         -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::update(accounts::table.find(id as i64))
+            .set(accounts::security_notifications.eq(enabled))
+            .execute(conn)
+    }
+
+    /// Sets the origin a `~` in a request path resolves to, None to clear it. It is cleared on
+    /// its own when the account leaves the origin.
+    pub fn update_default_origin(id: u64,
+                                 origin: Option<&str>,
+                                 conn: &PgConnection)
+                                 -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::update(accounts::table.find(id as i64)).set(accounts::default_origin.eq(origin))
+                                                       .execute(conn)
+    }
+
+    /// Records that the account signed in or presented its token to a new session
+    pub fn record_authentication(id: u64, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::update(accounts::table.find(id as i64))
+            .set(accounts::last_authenticated_at.eq(Utc::now().naive_utc()))
+            .execute(conn)
+    }
 }
 
 #[derive(Insertable)]
@@ -85,6 +149,7 @@ impl Account {
 pub struct NewAccountToken<'a> {
     pub account_id: i64,
     pub token:      &'a str,
+    pub expires_at: Option<NaiveDateTime>,
 }
 
 impl AccountToken {
@@ -99,7 +164,10 @@ impl AccountToken {
         diesel::insert_into(account_tokens::table).values(req)
                                                   .on_conflict(account_tokens::account_id)
                                                   .do_update()
-                                                  .set(account_tokens::token.eq(req.token))
+                                                  .set((account_tokens::token.eq(req.token),
+                                                        account_tokens::expires_at.eq(req.expires_at),
+                                                        account_tokens::expiry_notified_at
+                                                            .eq(None::<NaiveDateTime>)))
                                                   .get_result(conn)
     }
 
@@ -107,4 +175,383 @@ impl AccountToken {
         Counter::DBCall.increment();
         diesel::delete(account_tokens::table.find(id as i64)).execute(conn)
     }
+
+    /// Tokens expiring before the given time whose owners have not yet been notified.
+    pub fn list_expiring(before: NaiveDateTime,
+                         conn: &PgConnection)
+                         -> QueryResult<Vec<AccountToken>> {
+        Counter::DBCall.increment();
+        account_tokens::table.filter(account_tokens::expires_at.le(before))
+                             .filter(account_tokens::expires_at.gt(Utc::now().naive_utc()))
+                             .filter(account_tokens::expiry_notified_at.is_null())
+                             .get_results(conn)
+    }
+
+    /// Claims the expiry notice of the token, false when it was already sent. The claim holds the
+    /// row until the end of the current transaction, so of the nodes notifying at once only one
+    /// claims it.
+    pub fn claim_expiry_notice(id: i64, conn: &PgConnection) -> QueryResult<bool> {
+        Counter::DBCall.increment();
+        diesel::update(account_tokens::table.find(id)
+                                            .filter(account_tokens::expiry_notified_at.is_null()))
+            .set(account_tokens::expiry_notified_at.eq(Utc::now().naive_utc()))
+            .execute(conn)
+            .map(|updated| updated == 1)
+    }
+
+    /// Assigns a sunset date to all grandfathered, non-expiring tokens.
+    pub fn sunset_non_expiring(expires_at: NaiveDateTime,
+                               conn: &PgConnection)
+                               -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::update(account_tokens::table.filter(account_tokens::expires_at.is_null()))
+            .set(account_tokens::expires_at.eq(expires_at))
+            .execute(conn)
+    }
+}
+
+#[derive(DbEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AccountSecurityEventKind {
+    #[serde(rename = "token_created")]
+    TokenCreated,
+    #[serde(rename = "token_new_ip")]
+    TokenNewIp,
+    #[serde(rename = "origin_member_added")]
+    OriginMemberAdded,
+    #[serde(rename = "origin_member_removed")]
+    OriginMemberRemoved,
+}
+
+/// A security relevant change or use of an account. Tokens are referred to by id only.
+#[derive(Debug, Serialize, Queryable)]
+pub struct AccountSecurityEvent {
+    #[serde(with = "db_id_format")]
+    pub id: i64,
+    #[serde(with = "db_id_format")]
+    pub account_id: i64,
+    pub kind: AccountSecurityEventKind,
+    #[serde(with = "db_optional_id_format")]
+    pub token_id: Option<i64>,
+    pub ip: Option<String>,
+    pub origin: Option<String>,
+    #[serde(with = "db_optional_id_format")]
+    pub actor_id: Option<i64>,
+    pub actor_name: Option<String>,
+    #[serde(with = "rfc3339_opt")]
+    pub created_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "account_security_events"]
+pub struct NewAccountSecurityEvent<'a> {
+    pub account_id: i64,
+    pub kind:       AccountSecurityEventKind,
+    pub token_id:   Option<i64>,
+    pub ip:         Option<&'a str>,
+    pub origin:     Option<&'a str>,
+    pub actor_id:   Option<i64>,
+    pub actor_name: Option<&'a str>,
+}
+
+impl<'a> NewAccountSecurityEvent<'a> {
+    pub fn new(account_id: i64, kind: AccountSecurityEventKind) -> Self {
+        NewAccountSecurityEvent { account_id,
+                                  kind,
+                                  token_id: None,
+                                  ip: None,
+                                  origin: None,
+                                  actor_id: None,
+                                  actor_name: None }
+    }
+}
+
+pub struct ListAccountSecurityEvents {
+    pub account_id: i64,
+    pub page:       i64,
+    pub limit:      i64,
+}
+
+impl AccountSecurityEvent {
+    pub fn create(event: &NewAccountSecurityEvent,
+                  conn: &PgConnection)
+                  -> QueryResult<AccountSecurityEvent> {
+        Counter::DBCall.increment();
+        diesel::insert_into(account_security_events::table).values(event)
+                                                           .get_result(conn)
+    }
+
+    /// Events of an account, newest first
+    pub fn list(lse: ListAccountSecurityEvents,
+                conn: &PgConnection)
+                -> QueryResult<(Vec<AccountSecurityEvent>, i64)> {
+        Counter::DBCall.increment();
+        account_security_events::table
+            .filter(account_security_events::account_id.eq(lse.account_id))
+            .order(account_security_events::created_at.desc())
+            .paginate(lse.page)
+            .per_page(lse.limit)
+            .load_and_count_records(conn)
+    }
+
+    /// Every event on an account, and on other accounts where it was the actor, newest first
+    pub fn list_involving(account_id: i64,
+                          conn: &PgConnection)
+                          -> QueryResult<Vec<AccountSecurityEvent>> {
+        Counter::DBCall.increment();
+        account_security_events::table
+            .filter(account_security_events::account_id.eq(account_id)
+                        .or(account_security_events::actor_id.eq(account_id)))
+            .order(account_security_events::created_at.desc())
+            .get_results(conn)
+    }
+
+    /// The number of events of a kind recorded on an account since the given time
+    pub fn count_since(account_id: i64,
+                       kind: AccountSecurityEventKind,
+                       since: NaiveDateTime,
+                       conn: &PgConnection)
+                       -> QueryResult<i64> {
+        Counter::DBCall.increment();
+        account_security_events::table
+            .filter(account_security_events::account_id.eq(account_id))
+            .filter(account_security_events::kind.eq(kind))
+            .filter(account_security_events::created_at.ge(since))
+            .count()
+            .get_result(conn)
+    }
+
+    /// Deletes events recorded before the given time, returning the number deleted.
+    pub fn purge(before: NaiveDateTime, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::delete(account_security_events::table.filter(
+            account_security_events::created_at.lt(before),
+        ))
+        .execute(conn)
+    }
+}
+
+/// An address an account's personal access token has been used from
+#[derive(Debug, Queryable)]
+pub struct AccountKnownIp {
+    pub account_id:    i64,
+    pub ip:            String,
+    pub first_seen_at: Option<NaiveDateTime>,
+    pub last_seen_at:  Option<NaiveDateTime>,
+}
+
+impl AccountKnownIp {
+    pub fn list(account_id: i64, conn: &PgConnection) -> QueryResult<Vec<AccountKnownIp>> {
+        Counter::DBCall.increment();
+        account_known_ips::table.filter(account_known_ips::account_id.eq(account_id))
+                                .get_results(conn)
+    }
+
+    /// Remembers an address, returning false if it was already known.
+    pub fn add(account_id: i64, ip: &str, conn: &PgConnection) -> QueryResult<bool> {
+        Counter::DBCall.increment();
+        diesel::insert_into(account_known_ips::table)
+            .values((account_known_ips::account_id.eq(account_id),
+                     account_known_ips::ip.eq(ip)))
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .map(|inserted| inserted > 0)
+    }
+
+    /// Notes that a known address was used again
+    pub fn touch(account_id: i64, ip: &str, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::update(account_known_ips::table.find((account_id, ip)))
+            .set(account_known_ips::last_seen_at.eq(Utc::now().naive_utc()))
+            .execute(conn)
+    }
+
+    /// Forgets all but the `keep` most recently used addresses of an account, returning the
+    /// number forgotten.
+    pub fn trim(account_id: i64, keep: i64, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        let kept = account_known_ips::table.select(account_known_ips::ip)
+                                           .filter(account_known_ips::account_id.eq(account_id))
+                                           .order(account_known_ips::last_seen_at.desc())
+                                           .limit(keep);
+        diesel::delete(account_known_ips::table.filter(account_known_ips::account_id.eq(account_id))
+                                               .filter(account_known_ips::ip.ne_all(kept)))
+            .execute(conn)
+    }
+
+    /// Forgets the addresses not used since the given time, returning the number forgotten.
+    pub fn purge(before: NaiveDateTime, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::delete(account_known_ips::table.filter(account_known_ips::last_seen_at.lt(before)))
+            .execute(conn)
+    }
+}
+
+// The changes an account made to origins, packages and channels, from the audit tables
+const ACCOUNT_ACTIVITY: &str = "
+SELECT 'package' AS kind, origin, package_ident AS subject, channel, operation::text AS operation,
+       created_at
+FROM audit_package
+WHERE requester_id = $1
+UNION ALL
+SELECT 'package_group', origin, group_id::text, channel, operation::text, created_at
+FROM audit_package_group
+WHERE requester_id = $1
+UNION ALL
+SELECT 'package_deprecation', origin, name, NULL, operation::text, created_at
+FROM audit_package_deprecation
+WHERE requester_id = $1
+UNION ALL
+SELECT 'origin_key_rotation', origin, revision, NULL, trigger::text, created_at
+FROM audit_origin_key_rotation
+WHERE requester_id = $1
+ORDER BY created_at DESC";
+
+/// A change an account made, as recorded in the audit tables
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct AccountActivity {
+    /// `package`, `package_group`, `package_deprecation` or `origin_key_rotation`
+    #[sql_type = "Text"]
+    pub kind: String,
+    #[sql_type = "Nullable<Text>"]
+    pub origin: Option<String>,
+    /// The package ident, group id, package name or key revision changed
+    #[sql_type = "Text"]
+    pub subject: String,
+    #[sql_type = "Nullable<Text>"]
+    pub channel: Option<String>,
+    #[sql_type = "Text"]
+    pub operation: String,
+    #[sql_type = "Nullable<Timestamptz>"]
+    #[serde(with = "rfc3339_opt")]
+    pub created_at: Option<NaiveDateTime>,
+}
+
+impl AccountActivity {
+    pub fn list(account_id: i64, conn: &PgConnection) -> QueryResult<Vec<AccountActivity>> {
+        Counter::DBCall.increment();
+        diesel::sql_query(ACCOUNT_ACTIVITY).bind::<BigInt, _>(account_id)
+                                           .load(conn)
+    }
+}
+
+#[derive(DbEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AccountExportState {
+    #[serde(rename = "pending")]
+    Pending,
+    #[serde(rename = "ready")]
+    Ready,
+    #[serde(rename = "failed")]
+    Failed,
+}
+
+#[derive(QueryableByName)]
+struct PurgeLock {
+    #[sql_type = "Bool"]
+    locked: bool,
+}
+
+/// An export of an account's data. The archive is in the artifact store under `object_key`
+/// once the export is ready, until it expires.
+#[derive(Debug, Serialize, Queryable)]
+pub struct AccountExport {
+    #[serde(with = "db_id_format")]
+    pub id: i64,
+    #[serde(with = "db_id_format")]
+    pub account_id: i64,
+    pub state: AccountExportState,
+    #[serde(skip_serializing)]
+    pub object_key: Option<String>,
+    #[serde(with = "rfc3339_opt")]
+    pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
+    pub completed_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+impl AccountExport {
+    /// Starts an export. Fails with a unique violation while the account has another pending.
+    pub fn create(account_id: i64, conn: &PgConnection) -> QueryResult<AccountExport> {
+        Counter::DBCall.increment();
+        diesel::insert_into(account_exports::table)
+            .values(account_exports::account_id.eq(account_id))
+            .get_result(conn)
+    }
+
+    pub fn get(id: i64, account_id: i64, conn: &PgConnection) -> QueryResult<AccountExport> {
+        Counter::DBCall.increment();
+        account_exports::table.find(id)
+                              .filter(account_exports::account_id.eq(account_id))
+                              .get_result(conn)
+    }
+
+    pub fn mark_ready(id: i64,
+                      object_key: &str,
+                      expires_at: NaiveDateTime,
+                      conn: &PgConnection)
+                      -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::update(account_exports::table.find(id))
+            .set((account_exports::state.eq(AccountExportState::Ready),
+                  account_exports::object_key.eq(object_key),
+                  account_exports::completed_at.eq(Utc::now().naive_utc()),
+                  account_exports::expires_at.eq(expires_at)))
+            .execute(conn)
+    }
+
+    pub fn mark_failed(id: i64, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::update(account_exports::table.find(id))
+            .set((account_exports::state.eq(AccountExportState::Failed),
+                  account_exports::completed_at.eq(Utc::now().naive_utc())))
+            .execute(conn)
+    }
+
+    /// Fails the exports still pending that were started before the given time, returning the
+    /// number failed. They were lost, to a restart for instance, and would block new exports.
+    pub fn fail_stale(before: NaiveDateTime, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::update(account_exports::table
+                           .filter(account_exports::state.eq(AccountExportState::Pending))
+                           .filter(account_exports::created_at.lt(before)))
+            .set((account_exports::state.eq(AccountExportState::Failed),
+                  account_exports::completed_at.eq(Utc::now().naive_utc())))
+            .execute(conn)
+    }
+
+    /// The oldest pending export, locked until the end of the current transaction. Exports
+    /// locked by another connection are skipped.
+    pub fn next_pending(conn: &PgConnection) -> QueryResult<Option<AccountExport>> {
+        Counter::DBCall.increment();
+        account_exports::table.filter(account_exports::state.eq(AccountExportState::Pending))
+                              .order(account_exports::created_at.asc())
+                              .for_update()
+                              .skip_locked()
+                              .first(conn)
+                              .optional()
+    }
+
+    /// Take the purge lock until the end of the current transaction, unless another connection
+    /// holds it.
+    pub fn try_lock_purge(conn: &PgConnection) -> QueryResult<bool> {
+        Counter::DBCall.increment();
+        diesel::sql_query("SELECT pg_try_advisory_xact_lock(hashtext($1)) AS locked")
+            .bind::<Text, _>("account_exports/purge")
+            .get_result::<PurgeLock>(conn)
+            .map(|lock| lock.locked)
+    }
+
+    /// Exports whose archive expired before the given time
+    pub fn list_expired(before: NaiveDateTime,
+                        conn: &PgConnection)
+                        -> QueryResult<Vec<AccountExport>> {
+        Counter::DBCall.increment();
+        account_exports::table.filter(account_exports::expires_at.lt(before))
+                              .get_results(conn)
+    }
+
+    pub fn delete(id: i64, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::delete(account_exports::table.find(id)).execute(conn)
+    }
 }
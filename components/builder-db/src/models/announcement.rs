@@ -0,0 +1,201 @@
+use super::{db_id_format,
+            status::StatusBannerSeverity};
+use crate::bldr_core::timestamp::{rfc3339,
+                                  rfc3339_opt};
+use chrono::{NaiveDateTime,
+             Utc};
+use diesel::{self,
+             pg::PgConnection,
+             result::QueryResult,
+             ExpressionMethods,
+             OptionalExtension,
+             QueryDsl,
+             RunQueryDsl};
+
+use crate::{bldr_core::metrics::CounterMetric,
+            metrics::Counter,
+            schema::{announcement::{announcement_dismissals,
+                                    announcements},
+                     audit::audit_announcement}};
+
+/// Who an announcement is shown to
+#[derive(DbEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AnnouncementAudience {
+    #[serde(rename = "everyone")]
+    Everyone,
+    /// Signed in users
+    #[serde(rename = "authenticated")]
+    Authenticated,
+    /// Members of the announcement's origins
+    #[serde(rename = "origins")]
+    Origins,
+}
+
+impl Default for AnnouncementAudience {
+    fn default() -> Self { AnnouncementAudience::Everyone }
+}
+
+#[derive(DbEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AnnouncementOperation {
+    #[serde(rename = "create")]
+    Create,
+    #[serde(rename = "update")]
+    Update,
+    #[serde(rename = "expire")]
+    Expire,
+}
+
+/// An operator message shown in the web UI between its start and end
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+pub struct Announcement {
+    #[serde(with = "db_id_format")]
+    pub id:         i64,
+    pub message:    String,
+    pub severity:   StatusBannerSeverity,
+    pub audience:   AnnouncementAudience,
+    pub origins:    Vec<String>,
+    #[serde(with = "db_id_format")]
+    pub owner_id:   i64,
+    #[serde(with = "rfc3339")]
+    pub starts_at:  NaiveDateTime,
+    #[serde(with = "rfc3339")]
+    pub ends_at:    NaiveDateTime,
+    #[serde(with = "rfc3339_opt")]
+    pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "announcements"]
+pub struct NewAnnouncement<'a> {
+    pub message:   &'a str,
+    pub severity:  StatusBannerSeverity,
+    pub audience:  AnnouncementAudience,
+    pub origins:   Vec<String>,
+    pub owner_id:  i64,
+    pub starts_at: NaiveDateTime,
+    pub ends_at:   NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "audit_announcement"]
+pub struct AnnouncementAudit<'a> {
+    pub announcement_id: i64,
+    pub operation:       AnnouncementOperation,
+    pub message:         &'a str,
+    pub severity:        StatusBannerSeverity,
+    pub audience:        AnnouncementAudience,
+    pub origins:         &'a [String],
+    pub starts_at:       NaiveDateTime,
+    pub ends_at:         NaiveDateTime,
+    pub requester_id:    i64,
+    pub requester_name:  &'a str,
+}
+
+impl Announcement {
+    pub fn create(req: &NewAnnouncement, conn: &PgConnection) -> QueryResult<Announcement> {
+        Counter::DBCall.increment();
+        diesel::insert_into(announcements::table).values(req)
+                                                 .get_result(conn)
+    }
+
+    pub fn get(id: i64, conn: &PgConnection) -> QueryResult<Announcement> {
+        Counter::DBCall.increment();
+        announcements::table.find(id).get_result(conn)
+    }
+
+    /// Every announcement not yet pruned, the latest to start first
+    pub fn list(conn: &PgConnection) -> QueryResult<Vec<Announcement>> {
+        Counter::DBCall.increment();
+        announcements::table.order((announcements::starts_at.desc(), announcements::id.desc()))
+                            .get_results(conn)
+    }
+
+    /// The announcements showing at `now`, whatever their audience, the latest to start first
+    pub fn active(now: NaiveDateTime, conn: &PgConnection) -> QueryResult<Vec<Announcement>> {
+        Counter::DBCall.increment();
+        announcements::table.filter(announcements::starts_at.le(now))
+                            .filter(announcements::ends_at.gt(now))
+                            .order((announcements::starts_at.desc(), announcements::id.desc()))
+                            .get_results(conn)
+    }
+
+    /// Replace everything but the owner of the announcement
+    pub fn update(id: i64,
+                  req: &NewAnnouncement,
+                  conn: &PgConnection)
+                  -> QueryResult<Announcement> {
+        Counter::DBCall.increment();
+        diesel::update(announcements::table.find(id))
+            .set((announcements::message.eq(req.message),
+                  announcements::severity.eq(req.severity),
+                  announcements::audience.eq(req.audience),
+                  announcements::origins.eq(&req.origins),
+                  announcements::starts_at.eq(req.starts_at),
+                  announcements::ends_at.eq(req.ends_at),
+                  announcements::updated_at.eq(Utc::now().naive_utc())))
+            .get_result(conn)
+    }
+
+    /// End the announcement now, or `None` if it has ended already or doesn't exist
+    pub fn expire(id: i64, conn: &PgConnection) -> QueryResult<Option<Announcement>> {
+        Counter::DBCall.increment();
+        let now = Utc::now().naive_utc();
+        diesel::update(announcements::table.find(id).filter(announcements::ends_at.gt(now)))
+            .set((announcements::ends_at.eq(now), announcements::updated_at.eq(now)))
+            .get_result(conn)
+            .optional()
+    }
+
+    /// Delete the announcements that ended before `now`, along with their dismissals
+    pub fn prune(now: NaiveDateTime, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::delete(announcements::table.filter(announcements::ends_at.le(now))).execute(conn)
+    }
+
+    /// Record the announcement as the operation left it
+    pub fn audit(&self,
+                 operation: AnnouncementOperation,
+                 requester_id: i64,
+                 requester_name: &str,
+                 conn: &PgConnection)
+                 -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        let audit = AnnouncementAudit { announcement_id: self.id,
+                                        operation,
+                                        message: &self.message,
+                                        severity: self.severity,
+                                        audience: self.audience,
+                                        origins: &self.origins,
+                                        starts_at: self.starts_at,
+                                        ends_at: self.ends_at,
+                                        requester_id,
+                                        requester_name };
+        diesel::insert_into(audit_announcement::table).values(&audit)
+                                                      .execute(conn)
+    }
+}
+
+pub struct AnnouncementDismissal;
+
+impl AnnouncementDismissal {
+    /// Remember that the account dismissed the announcement, dismissing it again is a no-op
+    pub fn dismiss(account_id: i64, announcement_id: i64, conn: &PgConnection) -> QueryResult<()> {
+        Counter::DBCall.increment();
+        diesel::insert_into(announcement_dismissals::table)
+            .values((announcement_dismissals::account_id.eq(account_id),
+                     announcement_dismissals::announcement_id.eq(announcement_id)))
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        Ok(())
+    }
+
+    /// The ids of the announcements the account dismissed
+    pub fn list(account_id: i64, conn: &PgConnection) -> QueryResult<Vec<i64>> {
+        Counter::DBCall.increment();
+        announcement_dismissals::table.filter(announcement_dismissals::account_id.eq(account_id))
+                                      .select(announcement_dismissals::announcement_id)
+                                      .get_results(conn)
+    }
+}
@@ -1,12 +1,19 @@
 use super::db_id_format;
-use chrono::NaiveDateTime;
+use crate::bldr_core::timestamp::rfc3339_opt;
+use chrono::{NaiveDateTime,
+             Utc};
 use time::PreciseTime;
 
 use diesel::{self,
              dsl::sql,
              pg::{expression::dsl::any,
                   PgConnection},
-             result::QueryResult,
+             result::{OptionalExtension,
+                      QueryResult},
+             sql_types::{Array,
+                         BigInt,
+                         Nullable,
+                         Text},
              ExpressionMethods,
              NullableExpressionMethods,
              PgArrayExpressionMethods,
@@ -16,13 +23,19 @@ use diesel::{self,
              TextExpressionMethods};
 
 use crate::{models::{package::{BuilderPackageIdent,
+                               BuilderPackageTarget,
+                               FeedRelease,
                                PackageVisibility,
-                               PackageWithVersionArray},
+                               PackageWithVersionArray,
+                               FEED_ENTRIES},
                      pagination::Paginate},
             protocol::jobsrv::JobGroupTrigger,
-            schema::{audit::{audit_package,
-                             audit_package_group},
-                     channel::{origin_channel_packages,
+            schema::{audit::{audit_channel,
+                             audit_package,
+                             audit_package_group,
+                             audit_package_pin},
+                     channel::{origin_channel_package_pins,
+                               origin_channel_packages,
                                origin_channels},
                      origin::origins,
                      package::{origin_packages,
@@ -35,16 +48,51 @@ use crate::{bldr_core::metrics::{CounterMetric,
             metrics::{Counter,
                       Histogram}};
 
+/// Whether a channel lives until it is deleted, or only until it expires
+#[derive(DbEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[PgType = "origin_channel_type"]
+pub enum ChannelType {
+    #[serde(rename = "standard")]
+    Standard,
+    /// Deleted along with its contents once it expires, unless it is renewed first
+    #[serde(rename = "ephemeral")]
+    Ephemeral,
+}
+
+impl Default for ChannelType {
+    fn default() -> Self { ChannelType::Standard }
+}
+
+#[derive(DbEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChannelOperation {
+    #[serde(rename = "create")]
+    Create,
+    #[serde(rename = "renew")]
+    Renew,
+    #[serde(rename = "expire")]
+    Expire,
+    #[serde(rename = "delete")]
+    Delete,
+    #[serde(rename = "supersede")]
+    Supersede,
+}
+
 #[derive(AsExpression, Debug, Serialize, Deserialize, Queryable)]
 pub struct Channel {
     #[serde(with = "db_id_format")]
-    pub id: i64,
+    pub id:           i64,
     #[serde(with = "db_id_format")]
-    pub owner_id: i64,
-    pub name: String,
-    pub created_at: Option<NaiveDateTime>,
-    pub updated_at: Option<NaiveDateTime>,
-    pub origin: String,
+    pub owner_id:     i64,
+    pub name:         String,
+    #[serde(with = "rfc3339_opt")]
+    pub created_at:   Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
+    pub updated_at:   Option<NaiveDateTime>,
+    pub origin:       String,
+    pub rolling:      bool,
+    pub channel_type: ChannelType,
+    #[serde(with = "rfc3339_opt")]
+    pub expires_at:   Option<NaiveDateTime>,
 }
 
 #[derive(Insertable)]
@@ -73,12 +121,74 @@ pub struct ListChannelPackages<'a> {
     pub limit:      i64,
 }
 
+// A promotion audited without its target, before the target was recorded, is listed once for
+// the first target of the ident
+const CHANNEL_PROMOTION_FEED: &str = "
+SELECT ident, target, updated_at, requester_name
+FROM ((SELECT DISTINCT ON (a.package_ident, a.package_target, a.created_at)
+              p.id, p.ident, p.target, p.visibility, a.created_at AS updated_at, a.requester_name
+       FROM audit_package AS a
+       INNER JOIN origin_packages AS p
+         ON p.ident = a.package_ident
+         AND (a.package_target IS NULL OR p.target = a.package_target)
+       WHERE a.origin = $1 AND a.channel = $2 AND a.operation = 'promote'
+       ORDER BY a.package_ident, a.package_target, a.created_at, p.id)
+      UNION ALL
+      SELECT p.id, p.ident, p.target, p.visibility, g.created_at, g.requester_name
+      FROM audit_package_group AS g
+      INNER JOIN origin_packages AS p ON p.id = ANY(g.package_ids)
+      WHERE g.origin = $1 AND g.channel = $2 AND g.operation = 'promote') AS promoted
+WHERE visibility::text = ANY($3)
+ORDER BY updated_at DESC, id DESC
+LIMIT $4";
+
+// The releases of one target in a channel with their checksums, optionally only those in the
+// range of idents after $5 up to and including $6. Idents are compared bytewise, the way mirrors
+// sort their inventory.
+const CHANNEL_CHECKSUMS: &str = "
+SELECT p.ident, p.checksum
+FROM origin_packages AS p
+INNER JOIN origin_channel_packages AS cp ON cp.package_id = p.id
+INNER JOIN origin_channels AS c ON c.id = cp.channel_id
+WHERE c.origin = $1 AND c.name = $2 AND p.target = $3 AND p.visibility::text = ANY($4)
+  AND ($5::text IS NULL OR p.ident COLLATE \"C\" > $5)
+  AND ($6::text IS NULL OR p.ident COLLATE \"C\" <= $6)
+ORDER BY p.ident COLLATE \"C\"";
+
+/// A release of a channel with its checksum, as compared against a mirror's inventory
+#[derive(Debug, QueryableByName)]
+pub struct ChannelChecksum {
+    #[sql_type = "Text"]
+    pub ident:    String,
+    #[sql_type = "Text"]
+    pub checksum: String,
+}
+
+/// A release held in a channel
+#[derive(Debug, Clone, Queryable)]
+pub struct ChannelRelease {
+    pub package_id: i64,
+    pub ident:      BuilderPackageIdent,
+    pub target:     BuilderPackageTarget,
+}
+
 pub struct ListAllChannelPackages<'a> {
     pub visibility: &'a Vec<PackageVisibility>,
     pub channel:    &'a ChannelIdent,
     pub origin:     &'a str,
 }
 
+pub struct ListChannelChecksums<'a> {
+    pub visibility: &'a [PackageVisibility],
+    pub channel:    &'a ChannelIdent,
+    pub origin:     &'a str,
+    pub target:     PackageTarget,
+    /// Only releases whose ident sorts after this one
+    pub after:      Option<&'a str>,
+    /// Only releases whose ident sorts up to and including this one
+    pub until:      Option<&'a str>,
+}
+
 impl Channel {
     pub fn list(origin: &str,
                 include_sandbox_channels: bool,
@@ -101,12 +211,102 @@ impl Channel {
                               .get_result(conn)
     }
 
+    /// Locks the channel until the end of the transaction, so that what it holds and its pins
+    /// change one request at a time. A missing channel locks nothing.
+    pub fn lock(origin: &str, channel: &ChannelIdent, conn: &PgConnection) -> QueryResult<()> {
+        Counter::DBCall.increment();
+        origin_channels::table.select(origin_channels::id)
+                              .filter(origin_channels::origin.eq(origin))
+                              .filter(origin_channels::name.eq(channel.as_str()))
+                              .for_update()
+                              .load::<i64>(conn)
+                              .map(|_| ())
+    }
+
+    /// The latest promotions to a channel, at most `FEED_ENTRIES`, whether or not the releases
+    /// are still in it
+    pub fn list_promotions(origin: &str,
+                           channel: &ChannelIdent,
+                           visibility: &[PackageVisibility],
+                           conn: &PgConnection)
+                           -> QueryResult<Vec<FeedRelease>> {
+        Counter::DBCall.increment();
+        let visibility: Vec<String> = visibility.iter().map(ToString::to_string).collect();
+        diesel::sql_query(CHANNEL_PROMOTION_FEED).bind::<Text, _>(origin)
+                                                 .bind::<Text, _>(channel.as_str())
+                                                 .bind::<Array<Text>, _>(visibility)
+                                                 .bind::<BigInt, _>(FEED_ENTRIES)
+                                                 .load(conn)
+    }
+
     pub fn create(channel: &CreateChannel, conn: &PgConnection) -> QueryResult<Channel> {
         Counter::DBCall.increment();
         diesel::insert_into(origin_channels::table).values(channel)
                                                    .get_result(conn)
     }
 
+    /// Creates an ephemeral channel, deleted with its contents once `expires_at` has passed
+    pub fn create_ephemeral(channel: &CreateChannel,
+                            expires_at: NaiveDateTime,
+                            conn: &PgConnection)
+                            -> QueryResult<Channel> {
+        Counter::DBCall.increment();
+        diesel::insert_into(origin_channels::table)
+            .values((origin_channels::name.eq(channel.name),
+                     origin_channels::owner_id.eq(channel.owner_id),
+                     origin_channels::origin.eq(channel.origin),
+                     origin_channels::channel_type.eq(ChannelType::Ephemeral),
+                     origin_channels::expires_at.eq(expires_at)))
+            .get_result(conn)
+    }
+
+    /// Moves the expiry of an ephemeral channel to `expires_at`
+    pub fn renew(channel_id: i64,
+                 expires_at: NaiveDateTime,
+                 conn: &PgConnection)
+                 -> QueryResult<Channel> {
+        Counter::DBCall.increment();
+        diesel::update(origin_channels::table.find(channel_id))
+            .set((origin_channels::expires_at.eq(expires_at),
+                  origin_channels::updated_at.eq(Utc::now().naive_utc())))
+            .get_result(conn)
+    }
+
+    /// The ephemeral channels of every origin that expired by `now`
+    pub fn list_expired(now: NaiveDateTime, conn: &PgConnection) -> QueryResult<Vec<Channel>> {
+        Counter::DBCall.increment();
+        origin_channels::table.filter(origin_channels::channel_type.eq(ChannelType::Ephemeral))
+                              .filter(origin_channels::expires_at.le(now))
+                              .order(origin_channels::expires_at.asc())
+                              .get_results(conn)
+    }
+
+    /// The ephemeral channel `channel_id` if it expired by `now`, locked until the end of the
+    /// current transaction. A channel locked by another connection, such as another node
+    /// expiring it, is skipped.
+    pub fn lock_expired(channel_id: i64,
+                        now: NaiveDateTime,
+                        conn: &PgConnection)
+                        -> QueryResult<Option<Channel>> {
+        Counter::DBCall.increment();
+        origin_channels::table.find(channel_id)
+                              .filter(origin_channels::channel_type.eq(ChannelType::Ephemeral))
+                              .filter(origin_channels::expires_at.le(now))
+                              .for_update()
+                              .skip_locked()
+                              .first(conn)
+                              .optional()
+    }
+
+    /// Makes the channel rolling, or stops it being rolling. A rolling channel holds a single
+    /// release of each package.
+    pub fn set_rolling(channel_id: i64, rolling: bool, conn: &PgConnection) -> QueryResult<Channel> {
+        Counter::DBCall.increment();
+        diesel::update(origin_channels::table.find(channel_id))
+            .set(origin_channels::rolling.eq(rolling))
+            .get_result(conn)
+    }
+
     pub fn delete(origin: &str, channel: &ChannelIdent, conn: &PgConnection) -> QueryResult<usize> {
         Counter::DBCall.increment();
         diesel::delete(
@@ -172,7 +372,7 @@ impl Channel {
             .filter(origins::name.eq(lcp.origin))
             .filter(origin_channels::name.eq(lcp.channel.as_str()))
             .select(origin_packages::ident)
-            .order(origin_packages::ident.asc())
+            .order((origin_packages::ident.asc(), origin_packages::target.asc()))
             .paginate(lcp.page)
             .per_page(lcp.limit)
             .load_and_count_records(conn)
@@ -193,7 +393,7 @@ impl Channel {
             .filter(origins::name.eq(lacp.origin))
             .filter(origin_channels::name.eq(lacp.channel.as_str()))
             .select(origin_packages::ident)
-            .order(origin_packages::ident.asc())
+            .order((origin_packages::ident.asc(), origin_packages::target.asc()))
             .get_results(conn);
 
         let end_time = PreciseTime::now();
@@ -203,6 +403,42 @@ impl Channel {
         result
     }
 
+    /// The releases of one target in the channel with their checksums, those in the range of
+    /// idents `lcc` names, sorted by ident
+    pub fn list_checksums(lcc: &ListChannelChecksums,
+                          conn: &PgConnection)
+                          -> QueryResult<Vec<ChannelChecksum>> {
+        Counter::DBCall.increment();
+        let start_time = PreciseTime::now();
+
+        let visibility: Vec<String> = lcc.visibility.iter().map(ToString::to_string).collect();
+        let result = diesel::sql_query(CHANNEL_CHECKSUMS).bind::<Text, _>(lcc.origin)
+                                                         .bind::<Text, _>(lcc.channel.as_str())
+                                                         .bind::<Text, _>(lcc.target.to_string())
+                                                         .bind::<Array<Text>, _>(visibility)
+                                                         .bind::<Nullable<Text>, _>(lcc.after)
+                                                         .bind::<Nullable<Text>, _>(lcc.until)
+                                                         .load(conn);
+
+        let end_time = PreciseTime::now();
+        trace!("DBCall channel::list_checksums time: {} ms",
+               start_time.to(end_time).num_milliseconds());
+        Histogram::DbCallTime.set(start_time.to(end_time).num_milliseconds() as f64);
+        result
+    }
+
+    /// Every release in the channel, whatever its visibility
+    pub fn list_releases(channel_id: i64, conn: &PgConnection) -> QueryResult<Vec<ChannelRelease>> {
+        Counter::DBCall.increment();
+        origin_packages::table.inner_join(origin_channel_packages::table)
+                              .filter(origin_channel_packages::channel_id.eq(channel_id))
+                              .select((origin_packages::id,
+                                       origin_packages::ident,
+                                       origin_packages::target))
+                              .order(origin_packages::ident.asc())
+                              .get_results(conn)
+    }
+
     pub fn promote_packages(channel_id: i64,
                             package_ids: &[i64],
                             conn: &PgConnection)
@@ -233,6 +469,57 @@ impl Channel {
     }
 }
 
+#[derive(Debug, Insertable)]
+#[table_name = "audit_channel"]
+pub struct ChannelAudit<'a> {
+    pub origin:         &'a str,
+    pub channel:        &'a str,
+    pub channel_type:   ChannelType,
+    pub operation:      ChannelOperation,
+    pub expires_at:     Option<NaiveDateTime>,
+    pub package_ids:    Vec<i64>,
+    pub requester_id:   i64,
+    pub requester_name: &'a str,
+}
+
+/// A step in the lifecycle of a channel, as shown in its events feed
+#[derive(Debug, Clone, Serialize, Queryable)]
+pub struct ChannelEvent {
+    pub origin:         String,
+    pub channel:        String,
+    pub channel_type:   ChannelType,
+    pub operation:      ChannelOperation,
+    #[serde(with = "rfc3339_opt")]
+    pub expires_at:     Option<NaiveDateTime>,
+    pub package_ids:    Vec<i64>,
+    #[serde(with = "db_id_format")]
+    pub requester_id:   i64,
+    pub requester_name: String,
+    #[serde(with = "rfc3339_opt")]
+    pub created_at:     Option<NaiveDateTime>,
+}
+
+impl<'a> ChannelAudit<'a> {
+    pub fn audit(req: &ChannelAudit, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::insert_into(audit_channel::table).values(req)
+                                                 .execute(conn)
+    }
+
+    /// The latest events of a channel, at most `FEED_ENTRIES`, kept after the channel is gone
+    pub fn list(origin: &str,
+                channel: &ChannelIdent,
+                conn: &PgConnection)
+                -> QueryResult<Vec<ChannelEvent>> {
+        Counter::DBCall.increment();
+        audit_channel::table.filter(audit_channel::origin.eq(origin))
+                            .filter(audit_channel::channel.eq(channel.as_str()))
+                            .order(audit_channel::created_at.desc())
+                            .limit(FEED_ENTRIES)
+                            .get_results(conn)
+    }
+}
+
 #[derive(DbEnum, Debug, Clone, Serialize, Deserialize)]
 pub enum PackageChannelTrigger {
     Unknown,
@@ -267,6 +554,7 @@ pub struct PackageChannelAudit<'a> {
     pub requester_id:   i64,
     pub requester_name: &'a str,
     pub origin:         &'a str,
+    pub package_target: Option<BuilderPackageTarget>,
 }
 
 impl<'a> PackageChannelAudit<'a> {
@@ -297,6 +585,143 @@ impl<'a> PackageGroupChannelAudit<'a> {
         diesel::insert_into(audit_package_group::table).values(req)
                                                        .execute(conn)
     }
+
+    /// The group whose promotion most recently brought the release into the channel, if it
+    /// was promoted with its group
+    pub fn latest_group(origin: &str,
+                        channel: &ChannelIdent,
+                        package_id: i64,
+                        conn: &PgConnection)
+                        -> QueryResult<Option<i64>> {
+        Counter::DBCall.increment();
+        audit_package_group::table
+            .filter(audit_package_group::origin.eq(origin))
+            .filter(audit_package_group::channel.eq(channel.as_str()))
+            .filter(audit_package_group::operation.eq(PackageChannelOperation::Promote))
+            .filter(audit_package_group::group_id.ne(0))
+            .filter(audit_package_group::package_ids.contains(vec![package_id]))
+            .order(audit_package_group::created_at.desc())
+            .select(audit_package_group::group_id)
+            .first(conn)
+            .optional()
+    }
+}
+
+/// A release pinned in a channel. While it is pinned the release can't be demoted from the
+/// channel, and no newer release of the package can be promoted into it.
+#[derive(Debug, Serialize, Deserialize, Queryable, Clone)]
+pub struct ChannelPackagePin {
+    pub origin: String,
+    pub channel: String,
+    pub name: String,
+    pub target: String,
+    pub ident: String,
+    #[serde(with = "db_id_format")]
+    pub pinned_by_id: i64,
+    pub pinned_by_name: String,
+    #[serde(with = "rfc3339_opt")]
+    pub created_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "origin_channel_package_pins"]
+pub struct NewChannelPackagePin<'a> {
+    pub origin:         &'a str,
+    pub channel:        &'a str,
+    pub name:           &'a str,
+    pub target:         &'a str,
+    pub ident:          &'a str,
+    pub pinned_by_id:   i64,
+    pub pinned_by_name: &'a str,
+}
+
+#[derive(DbEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PackagePinOperation {
+    #[serde(rename = "pin")]
+    Pin,
+    #[serde(rename = "unpin")]
+    Unpin,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "audit_package_pin"]
+pub struct PackagePinAudit<'a> {
+    pub origin:         &'a str,
+    pub channel:        &'a str,
+    pub name:           &'a str,
+    pub target:         &'a str,
+    pub ident:          &'a str,
+    pub operation:      PackagePinOperation,
+    pub requester_id:   i64,
+    pub requester_name: &'a str,
+}
+
+impl ChannelPackagePin {
+    pub fn get(origin: &str,
+               channel: &ChannelIdent,
+               name: &str,
+               target: &str,
+               conn: &PgConnection)
+               -> QueryResult<ChannelPackagePin> {
+        Counter::DBCall.increment();
+        origin_channel_package_pins::table.find((origin, channel.as_str(), name, target))
+                                          .get_result(conn)
+    }
+
+    /// Pins in a channel, for every package and target
+    pub fn list(origin: &str,
+                channel: &ChannelIdent,
+                conn: &PgConnection)
+                -> QueryResult<Vec<ChannelPackagePin>> {
+        Counter::DBCall.increment();
+        origin_channel_package_pins::table
+            .filter(origin_channel_package_pins::origin.eq(origin))
+            .filter(origin_channel_package_pins::channel.eq(channel.as_str()))
+            .order(origin_channel_package_pins::name.asc())
+            .get_results(conn)
+    }
+
+    /// Pins a release, failing with a unique violation if the package is already pinned
+    pub fn create(req: &NewChannelPackagePin,
+                  conn: &PgConnection)
+                  -> QueryResult<ChannelPackagePin> {
+        Counter::DBCall.increment();
+        diesel::insert_into(origin_channel_package_pins::table).values(req)
+                                                               .get_result(conn)
+    }
+
+    pub fn delete(origin: &str,
+                  channel: &ChannelIdent,
+                  name: &str,
+                  target: &str,
+                  conn: &PgConnection)
+                  -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        let key = (origin, channel.as_str(), name, target);
+        diesel::delete(origin_channel_package_pins::table.find(key)).execute(conn)
+    }
+
+    /// Unpins every release of the channel, as it is deleted
+    pub fn delete_all(origin: &str,
+                      channel: &ChannelIdent,
+                      conn: &PgConnection)
+                      -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::delete(
+            origin_channel_package_pins::table
+                .filter(origin_channel_package_pins::origin.eq(origin))
+                .filter(origin_channel_package_pins::channel.eq(channel.as_str())),
+        )
+        .execute(conn)
+    }
+}
+
+impl<'a> PackagePinAudit<'a> {
+    pub fn audit(req: &PackagePinAudit, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::insert_into(audit_package_pin::table).values(req)
+                                                     .execute(conn)
+    }
 }
 
 #[derive(Debug, Serialize, Queryable)]
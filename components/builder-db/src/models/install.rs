@@ -0,0 +1,122 @@
+use chrono::NaiveDate;
+use diesel::{self,
+             pg::{upsert::excluded,
+                  PgConnection},
+             result::QueryResult,
+             sql_types::{BigInt,
+                         Date,
+                         Text},
+             ExpressionMethods,
+             QueryDsl,
+             RunQueryDsl};
+
+use crate::{bldr_core::metrics::CounterMetric,
+            metrics::Counter,
+            schema::install::{install_sites,
+                              install_stats}};
+
+/// The ident installs of packages this Builder does not know are counted under
+pub const UNKNOWN_IDENT: &str = "unknown";
+
+const PACKAGE_INSTALL_STATS: &str = "
+SELECT s.day,
+       s.target,
+       sum(s.successes)::bigint AS successes,
+       sum(s.failures)::bigint AS failures,
+       (SELECT count(*)
+        FROM install_sites AS i
+        WHERE i.origin = s.origin AND i.name = s.name AND i.target = s.target
+          AND i.day = s.day) AS sites
+FROM install_stats AS s
+WHERE s.origin = $1 AND s.name = $2 AND s.day >= $3
+GROUP BY s.origin, s.name, s.day, s.target
+ORDER BY s.day DESC, s.target";
+
+/// Installs of a package or an unknown ident, reported on a day, to add to its rollup
+#[derive(Debug, Insertable)]
+#[table_name = "install_stats"]
+pub struct NewInstallStat<'a> {
+    pub day:       NaiveDate,
+    pub ident:     &'a str,
+    pub origin:    Option<&'a str>,
+    pub name:      Option<&'a str>,
+    pub target:    &'a str,
+    pub successes: i64,
+    pub failures:  i64,
+}
+
+/// The installs of a package on one target and day, all its releases together. Sites are the
+/// distinct sites that reported them, unknown once their ids have been purged.
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct PackageInstallStats {
+    #[sql_type = "Date"]
+    pub day:       NaiveDate,
+    #[sql_type = "Text"]
+    pub target:    String,
+    #[sql_type = "BigInt"]
+    pub successes: i64,
+    #[sql_type = "BigInt"]
+    pub failures:  i64,
+    #[sql_type = "BigInt"]
+    pub sites:     i64,
+}
+
+pub struct InstallStat;
+
+impl InstallStat {
+    /// Adds the installs to those already counted for the ident, target and day
+    pub fn add(stat: &NewInstallStat, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::insert_into(install_stats::table)
+            .values(stat)
+            .on_conflict((install_stats::day, install_stats::ident, install_stats::target))
+            .do_update()
+            .set((
+                install_stats::successes.eq(install_stats::successes + excluded(install_stats::successes)),
+                install_stats::failures.eq(install_stats::failures + excluded(install_stats::failures)),
+            ))
+            .execute(conn)
+    }
+
+    /// Records the sites that installed a package on a day, each site once
+    pub fn add_sites(day: NaiveDate,
+                     origin: &str,
+                     name: &str,
+                     target: &str,
+                     sites: &[&str],
+                     conn: &PgConnection)
+                     -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        let rows: Vec<_> = sites.iter()
+                                .map(|site| {
+                                    (install_sites::day.eq(day),
+                                     install_sites::origin.eq(origin),
+                                     install_sites::name.eq(name),
+                                     install_sites::target.eq(target),
+                                     install_sites::site_id.eq(*site))
+                                })
+                                .collect();
+        diesel::insert_into(install_sites::table).values(&rows)
+                                                 .on_conflict_do_nothing()
+                                                 .execute(conn)
+    }
+
+    /// Forgets the sites that reported installs before `day`
+    pub fn purge_sites(day: NaiveDate, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::delete(install_sites::table.filter(install_sites::day.lt(day))).execute(conn)
+    }
+
+    /// The daily installs of a package since `since`, latest first
+    pub fn list(origin: &str,
+                name: &str,
+                since: NaiveDate,
+                conn: &PgConnection)
+                -> QueryResult<Vec<PackageInstallStats>> {
+        Counter::DBCall.increment();
+        diesel::sql_query(PACKAGE_INSTALL_STATS).bind::<Text, _>(origin)
+                                                .bind::<Text, _>(name)
+                                                .bind::<Date, _>(since)
+                                                .load(conn)
+    }
+}
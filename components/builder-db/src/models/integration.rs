@@ -1,4 +1,5 @@
 use super::db_id_format;
+use crate::bldr_core::timestamp::rfc3339_opt;
 use chrono::NaiveDateTime;
 use diesel::{self,
              pg::PgConnection,
@@ -19,7 +20,9 @@ pub struct OriginIntegration {
     pub integration: String,
     pub name: String,
     pub body: String,
+    #[serde(with = "rfc3339_opt")]
     pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
     pub updated_at: Option<NaiveDateTime>,
 }
 
@@ -1,8 +1,16 @@
-use super::db_id_format;
-use chrono::NaiveDateTime;
+use super::{db_id_format,
+            db_optional_id_format};
+use crate::bldr_core::timestamp::rfc3339_opt;
+use chrono::{NaiveDateTime,
+             Utc};
 use diesel::{self,
+             dsl::sql,
+             expression::SqlLiteral,
              pg::PgConnection,
-             result::QueryResult,
+             result::{Error,
+                      QueryResult},
+             sql_types::Bool,
+             Connection,
              ExpressionMethods,
              QueryDsl,
              RunQueryDsl};
@@ -18,14 +26,20 @@ pub struct OriginInvitation {
     #[serde(with = "db_id_format")]
     pub id: i64,
     pub origin: String,
-    #[serde(with = "db_id_format")]
-    pub account_id: i64,
-    pub account_name: String,
+    #[serde(with = "db_optional_id_format")]
+    pub account_id: Option<i64>,
+    pub account_name: Option<String>,
     #[serde(with = "db_id_format")]
     pub owner_id: i64,
     pub ignored: bool,
+    #[serde(with = "rfc3339_opt")]
     pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
     pub updated_at: Option<NaiveDateTime>,
+    /// Address an invitation was sent to. Pending until claimed by an account.
+    pub email: Option<String>,
+    #[serde(with = "rfc3339_opt")]
+    pub expires_at: Option<NaiveDateTime>,
 }
 
 #[derive(Insertable)]
@@ -35,6 +49,16 @@ pub struct NewOriginInvitation<'a> {
     pub account_id:   i64,
     pub account_name: &'a str,
     pub owner_id:     i64,
+    pub expires_at:   Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "origin_invitations"]
+pub struct NewOriginEmailInvitation<'a> {
+    pub origin:     &'a str,
+    pub email:      &'a str,
+    pub owner_id:   i64,
+    pub expires_at: Option<NaiveDateTime>,
 }
 
 impl OriginInvitation {
@@ -44,9 +68,37 @@ impl OriginInvitation {
                                                       .get_result(conn)
     }
 
+    /// Invites an address that has no account yet. Inviting the same address to the same
+    /// origin again refreshes the pending invitation instead of adding a second one.
+    pub fn create_for_email(req: &NewOriginEmailInvitation,
+                            conn: &PgConnection)
+                            -> QueryResult<OriginInvitation> {
+        Counter::DBCall.increment();
+        let existing = origin_invitations::table.filter(origin_invitations::origin.eq(req.origin))
+                                                .filter(origin_invitations::email.eq(req.email))
+                                                .filter(origin_invitations::account_id.is_null())
+                                                .select(origin_invitations::id)
+                                                .first::<i64>(conn);
+        match existing {
+            Ok(id) => Self::renew(id as u64, req.expires_at, conn),
+            Err(Error::NotFound) => {
+                diesel::insert_into(origin_invitations::table).values(req)
+                                                              .get_result(conn)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn get(invite_id: u64, conn: &PgConnection) -> QueryResult<OriginInvitation> {
+        Counter::DBCall.increment();
+        origin_invitations::table.find(invite_id as i64)
+                                 .get_result(conn)
+    }
+
     pub fn list_by_origin(origin: &str, conn: &PgConnection) -> QueryResult<Vec<OriginInvitation>> {
         Counter::DBCall.increment();
         origin_invitations::table.filter(origin_invitations::origin.eq(origin))
+                                 .filter(not_expired())
                                  .get_results(conn)
     }
 
@@ -56,6 +108,29 @@ impl OriginInvitation {
         Counter::DBCall.increment();
         origin_invitations::table.filter(origin_invitations::account_id.eq(owner_id as i64))
                                  .filter(origin_invitations::ignored.eq(false))
+                                 .filter(not_expired())
+                                 .get_results(conn)
+    }
+
+    /// Every invitation an account received, ignored and expired ones included
+    pub fn list_all_by_account(account_id: i64,
+                               conn: &PgConnection)
+                               -> QueryResult<Vec<OriginInvitation>> {
+        Counter::DBCall.increment();
+        origin_invitations::table.filter(origin_invitations::account_id.eq(account_id))
+                                 .order(origin_invitations::created_at.desc())
+                                 .get_results(conn)
+    }
+
+    /// Invitations still waiting for the invited address to sign in.
+    pub fn list_pending_email(origin: &str,
+                              conn: &PgConnection)
+                              -> QueryResult<Vec<OriginInvitation>> {
+        Counter::DBCall.increment();
+        origin_invitations::table.filter(origin_invitations::origin.eq(origin))
+                                 .filter(origin_invitations::account_id.is_null())
+                                 .filter(not_expired())
+                                 .order(origin_invitations::email.asc())
                                  .get_results(conn)
     }
 
@@ -67,15 +142,74 @@ impl OriginInvitation {
                                              .execute(conn);
         }
 
+        // Invitations still pending by email must be claimed by an account first
+        let (account_id, origin) =
+            invitation.filter(not_expired())
+                      .select((origin_invitations::account_id, origin_invitations::origin))
+                      .get_result::<(Option<i64>, String)>(conn)?;
+        let account_id = account_id.ok_or(Error::NotFound)?;
+
         diesel::insert_into(origin_members::table)
-            .values(invitation.select((origin_invitations::account_id, origin_invitations::origin)))
-            .into_columns((origin_members::account_id, origin_members::origin))
+            .values((origin_members::account_id.eq(account_id),
+                     origin_members::origin.eq(origin)))
             .on_conflict_do_nothing()
             .execute(conn)?;
 
         diesel::delete(invitation).execute(conn)
     }
 
+    /// Converts a pending email invitation into one addressed to the given account.
+    pub fn claim(invite_id: u64,
+                 account_id: i64,
+                 account_name: &str,
+                 conn: &PgConnection)
+                 -> QueryResult<OriginInvitation> {
+        Counter::DBCall.increment();
+        diesel::update(origin_invitations::table.find(invite_id as i64)
+                                                .filter(origin_invitations::account_id.is_null())
+                                                .filter(not_expired()))
+            .set((origin_invitations::account_id.eq(account_id),
+                  origin_invitations::account_name.eq(account_name),
+                  origin_invitations::updated_at.eq(Utc::now().naive_utc())))
+            .get_result(conn)
+    }
+
+    /// Converts every pending invitation sent to `email` into one addressed to the given
+    /// account.
+    pub fn claim_by_email(email: &str,
+                          account_id: i64,
+                          account_name: &str,
+                          conn: &PgConnection)
+                          -> QueryResult<Vec<OriginInvitation>> {
+        Counter::DBCall.increment();
+        conn.transaction(|| {
+                let pending: Vec<i64> =
+                    origin_invitations::table.filter(origin_invitations::email.eq(email))
+                                             .filter(origin_invitations::account_id.is_null())
+                                             .filter(not_expired())
+                                             .select(origin_invitations::id)
+                                             .get_results(conn)?;
+                let mut claimed = Vec::new();
+                for id in pending {
+                    claimed.push(Self::claim(id as u64, account_id, account_name, conn)?);
+                }
+                Ok(claimed)
+            })
+    }
+
+    /// Pushes out the expiration of an invitation that is being sent again.
+    pub fn renew(invite_id: u64,
+                 expires_at: Option<NaiveDateTime>,
+                 conn: &PgConnection)
+                 -> QueryResult<OriginInvitation> {
+        Counter::DBCall.increment();
+        diesel::update(origin_invitations::table.find(invite_id as i64))
+            .set((origin_invitations::expires_at.eq(expires_at),
+                  origin_invitations::ignored.eq(false),
+                  origin_invitations::updated_at.eq(Utc::now().naive_utc())))
+            .get_result(conn)
+    }
+
     pub fn ignore(invite_id: u64, conn: &PgConnection) -> QueryResult<usize> {
         Counter::DBCall.increment();
         diesel::update(origin_invitations::table.find(invite_id as i64))
@@ -88,3 +222,8 @@ impl OriginInvitation {
         diesel::delete(origin_invitations::table.find(invite_id as i64)).execute(conn)
     }
 }
+
+// Invitations created before expiration was introduced never expire
+fn not_expired() -> SqlLiteral<Bool> {
+    sql::<Bool>("(origin_invitations.expires_at IS NULL OR origin_invitations.expires_at > now())")
+}
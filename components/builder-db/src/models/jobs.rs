@@ -3,11 +3,15 @@ use chrono::prelude::*;
 use diesel::{dsl::count_star,
              pg::PgConnection,
              result::QueryResult,
+             sql_types::{BigInt,
+                         Nullable,
+                         Text},
              BoolExpressionMethods,
              ExpressionMethods,
              QueryDsl,
              RunQueryDsl};
-use protobuf::ProtobufEnum;
+use protobuf::{ProtobufEnum,
+               RepeatedField};
 
 use crate::protocol::{jobsrv,
                       net,
@@ -16,9 +20,11 @@ use crate::protocol::{jobsrv,
 use crate::{models::pagination::Paginate,
             schema::jobs::{busy_workers,
                            groups,
+                           job_events,
                            jobs}};
 
-use crate::{bldr_core::metrics::CounterMetric,
+use crate::{bldr_core::{metrics::CounterMetric,
+                        timestamp},
             hab_core::package::PackageTarget,
             metrics::Counter};
 
@@ -51,6 +57,18 @@ pub struct Job {
     pub sync_count: i32,
     pub worker: Option<String>,
     pub target: String,
+    pub required_worker_labels: Vec<String>,
+    pub no_cache: bool,
+    pub peak_workspace_bytes: Option<i64>,
+    pub artifact_bytes: Option<i64>,
+    pub failure_excerpt: Option<String>,
+    pub progress_phase: Option<String>,
+    pub progress_step: Option<String>,
+    pub progress_phase_secs: Option<i64>,
+    pub progress_updated_at: Option<DateTime<Utc>>,
+    pub vcs_ref: Option<String>,
+    pub leased_at: Option<DateTime<Utc>>,
+    pub lease_expires_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Insertable)]
@@ -106,6 +124,48 @@ impl Job {
     }
 }
 
+/// How many of a project's most recent measured jobs per target feed its build stats
+pub const BUILD_STATS_SAMPLE_JOBS: i64 = 100;
+
+const PROJECT_BUILD_STATS: &str = "
+SELECT target,
+       count(*) AS sampled_jobs,
+       percentile_disc(0.95) WITHIN GROUP (ORDER BY peak_workspace_bytes) AS p95_peak_workspace_bytes,
+       max(peak_workspace_bytes) AS max_peak_workspace_bytes,
+       percentile_disc(0.95) WITHIN GROUP (ORDER BY artifact_bytes) AS p95_artifact_bytes
+FROM (SELECT target, peak_workspace_bytes, artifact_bytes,
+             row_number() OVER (PARTITION BY target ORDER BY id DESC) AS recent
+      FROM jobs
+      WHERE project_name = $1 AND peak_workspace_bytes IS NOT NULL) AS measured
+WHERE recent <= $2
+GROUP BY target
+ORDER BY target";
+
+/// Disk usage of a project's recent builds on one target, from the jobs whose worker reported
+/// it. Byte figures are absent when none of the sampled jobs reported them.
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct ProjectBuildStats {
+    #[sql_type = "Text"]
+    pub target: String,
+    #[sql_type = "BigInt"]
+    pub sampled_jobs: i64,
+    #[sql_type = "Nullable<BigInt>"]
+    pub p95_peak_workspace_bytes: Option<i64>,
+    #[sql_type = "Nullable<BigInt>"]
+    pub max_peak_workspace_bytes: Option<i64>,
+    #[sql_type = "Nullable<BigInt>"]
+    pub p95_artifact_bytes: Option<i64>,
+}
+
+impl ProjectBuildStats {
+    pub fn get(project_name: &str, conn: &PgConnection) -> QueryResult<Vec<ProjectBuildStats>> {
+        Counter::DBCall.increment();
+        diesel::sql_query(PROJECT_BUILD_STATS).bind::<Text, _>(project_name)
+                                              .bind::<BigInt, _>(BUILD_STATS_SAMPLE_JOBS)
+                                              .load(conn)
+    }
+}
+
 impl Into<jobsrv::Job> for Job {
     fn into(self) -> jobsrv::Job {
         let mut job = jobsrv::Job::new();
@@ -115,13 +175,13 @@ impl Into<jobsrv::Job> for Job {
         let job_state: jobsrv::JobState = self.job_state.parse().unwrap();
         job.set_state(job_state);
 
-        job.set_created_at(self.created_at.unwrap().to_rfc3339());
+        job.set_created_at(timestamp::to_rfc3339(&self.created_at.unwrap()));
 
         if let Some(start) = self.build_started_at {
-            job.set_build_started_at(start.to_rfc3339());
+            job.set_build_started_at(timestamp::to_rfc3339(&start));
         }
         if let Some(stop) = self.build_finished_at {
-            job.set_build_finished_at(stop.to_rfc3339());
+            job.set_build_finished_at(timestamp::to_rfc3339(&stop));
         }
 
         if let Some(ident_str) = self.package_ident {
@@ -153,8 +213,19 @@ impl Into<jobsrv::Job> for Job {
                     }
                 }
             }
+            "generic_git" => {
+                let mut vcsa: Vec<Option<String>> = self.vcs_arguments;
+                project.set_vcs_type(String::from("generic_git"));
+                project.set_vcs_data(vcsa.remove(0).expect("expected vcs data"));
+                if !vcsa.is_empty() {
+                    if let Some(credential) = vcsa.remove(0) {
+                        project.set_vcs_credential(credential);
+                    }
+                }
+            }
             e => error!("Unknown VCS, {}", e),
         }
+        project.set_required_worker_labels(RepeatedField::from_vec(self.required_worker_labels));
         job.set_project(project);
 
         if let Some(err_msg) = self.net_error_msg {
@@ -178,6 +249,45 @@ impl Into<jobsrv::Job> for Job {
         };
 
         job.set_target(self.target.clone());
+        job.set_no_cache(self.no_cache);
+
+        if let Some(vcs_ref) = self.vcs_ref {
+            job.set_vcs_ref(vcs_ref);
+        }
+
+        if let Some(bytes) = self.peak_workspace_bytes {
+            job.set_peak_workspace_bytes(bytes as u64);
+        }
+
+        if let Some(bytes) = self.artifact_bytes {
+            job.set_artifact_bytes(bytes as u64);
+        }
+
+        if job.get_state() == jobsrv::JobState::Failed {
+            if let Some(excerpt) = self.failure_excerpt {
+                job.set_failure_excerpt(excerpt);
+            }
+        }
+
+        let running = job.get_state() == jobsrv::JobState::Dispatched
+                      || job.get_state() == jobsrv::JobState::Processing;
+        if running {
+            if let Some(phase) = self.progress_phase {
+                let mut progress = jobsrv::JobProgress::new();
+                progress.set_phase(phase);
+                if let Some(step) = self.progress_step {
+                    progress.set_step(step);
+                }
+                if let Some(secs) = self.progress_phase_secs {
+                    progress.set_phase_elapsed_secs(secs as u64);
+                }
+                if let Some(updated_at) = self.progress_updated_at {
+                    progress.set_updated_at(timestamp::to_rfc3339(&updated_at));
+                }
+                job.set_progress(progress);
+            }
+        }
+
         job
     }
 }
@@ -192,6 +302,13 @@ pub struct Group {
     pub target: String,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
+    pub no_cache: bool,
+    pub max_concurrency: i32,
+    pub failure_policy: String,
+    pub frozen_until: Option<DateTime<Utc>>,
+    pub vcs_ref: Option<String>,
+    pub superseded_refs: Vec<String>,
+    pub hold_until: Option<DateTime<Utc>>,
 }
 
 impl Group {
@@ -203,6 +320,37 @@ impl Group {
                      .get_result(conn)
     }
 
+    /// The group of the project held back by a build freeze, if any
+    pub fn get_deferred(project_name: &str,
+                        target: &str,
+                        conn: &PgConnection)
+                        -> QueryResult<Group> {
+        Counter::DBCall.increment();
+        groups::table.filter(groups::project_name.eq(project_name))
+                     .filter(groups::group_state.eq("DeferredByFreeze"))
+                     .filter(groups::target.eq(target))
+                     .get_result(conn)
+    }
+
+    /// The group of the project awaiting an operator's approval, if any
+    pub fn get_pending_approval(project_name: &str,
+                                target: &str,
+                                conn: &PgConnection)
+                                -> QueryResult<Group> {
+        Counter::DBCall.increment();
+        groups::table.filter(groups::project_name.eq(project_name))
+                     .filter(groups::group_state.eq("PendingApproval"))
+                     .filter(groups::target.eq(target))
+                     .get_result(conn)
+    }
+
+    pub fn get_all_deferred(target: PackageTarget, conn: &PgConnection) -> QueryResult<Vec<Group>> {
+        Counter::DBCall.increment();
+        groups::table.filter(groups::group_state.eq("DeferredByFreeze"))
+                     .filter(groups::target.eq(target.to_string()))
+                     .get_results(conn)
+    }
+
     pub fn get_all_queued(target: PackageTarget, conn: &PgConnection) -> QueryResult<Vec<Group>> {
         Counter::DBCall.increment();
         groups::table.filter(groups::group_state.eq("Queued"))
@@ -219,10 +367,15 @@ impl Group {
                      .get_results(conn)
     }
 
-    pub fn get_pending(target: PackageTarget, conn: &PgConnection) -> QueryResult<Group> {
+    /// The oldest pending group of the target, other than the `skipped` ones
+    pub fn get_pending(target: PackageTarget,
+                       skipped: &[i64],
+                       conn: &PgConnection)
+                       -> QueryResult<Group> {
         Counter::DBCall.increment();
         groups::table.filter(groups::group_state.eq("Pending"))
                      .filter(groups::target.eq(target.to_string()))
+                     .filter(groups::id.ne_all(skipped))
                      .order(groups::created_at.asc())
                      .get_result(conn)
     }
@@ -248,9 +401,21 @@ impl Into<jobsrv::JobGroup> for Group {
 
         let group_state = self.group_state.parse::<jobsrv::JobGroupState>().unwrap();
         group.set_state(group_state);
-        group.set_created_at(self.created_at.unwrap().to_rfc3339());
+        group.set_created_at(timestamp::to_rfc3339(&self.created_at.unwrap()));
         group.set_project_name(self.project_name);
         group.set_target(self.target);
+        group.set_no_cache(self.no_cache);
+        group.set_max_concurrency(self.max_concurrency as u32);
+        group.set_failure_policy(self.failure_policy
+                                     .parse()
+                                     .unwrap_or(jobsrv::JobGroupFailurePolicy::ContinueOnFailure));
+        if let Some(frozen_until) = self.frozen_until {
+            group.set_frozen_until(timestamp::to_rfc3339(&frozen_until));
+        }
+        if let Some(vcs_ref) = self.vcs_ref {
+            group.set_vcs_ref(vcs_ref);
+        }
+        group.set_superseded_refs(RepeatedField::from_vec(self.superseded_refs));
 
         group
     }
@@ -301,3 +466,27 @@ impl BusyWorker {
                                           .filter(busy_workers::job_id.eq(job_id))).execute(conn)
     }
 }
+
+/// What happened to a job besides its state changes, such as the renewals of its lease. Events
+/// are kept for the job server's `event_retention_days`.
+#[derive(Debug, Serialize, Deserialize, QueryableByName, Queryable)]
+#[table_name = "job_events"]
+pub struct JobEvent {
+    #[serde(with = "db_id_format")]
+    pub id:         i64,
+    #[serde(with = "db_id_format")]
+    pub job_id:     i64,
+    pub event:      String,
+    pub detail:     Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl JobEvent {
+    /// The events of a job, oldest first
+    pub fn list(job_id: i64, conn: &PgConnection) -> QueryResult<Vec<JobEvent>> {
+        Counter::DBCall.increment();
+        job_events::table.filter(job_events::job_id.eq(job_id))
+                         .order((job_events::created_at.asc(), job_events::id.asc()))
+                         .get_results(conn)
+    }
+}
@@ -1,15 +1,20 @@
 use super::db_id_format;
-use chrono::NaiveDateTime;
+use crate::bldr_core::timestamp::rfc3339_opt;
+use chrono::{NaiveDateTime,
+             Utc};
 use diesel::{self,
              pg::PgConnection,
              result::QueryResult,
+             sql_types::{Bool,
+                         Text},
              ExpressionMethods,
              QueryDsl,
              RunQueryDsl};
 
 use crate::{bldr_core::metrics::CounterMetric,
             metrics::Counter,
-            schema::key::*};
+            schema::{audit::audit_origin_key_rotation,
+                     key::*}};
 
 #[derive(Debug, Serialize, Deserialize, QueryableByName, Queryable)]
 #[table_name = "origin_public_encryption_keys"]
@@ -22,7 +27,9 @@ pub struct OriginPublicEncryptionKey {
     pub revision: String,
     pub full_name: String,
     pub body: Vec<u8>,
+    #[serde(with = "rfc3339_opt")]
     pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
     pub updated_at: Option<NaiveDateTime>,
     pub origin: String,
 }
@@ -38,7 +45,9 @@ pub struct OriginPrivateEncryptionKey {
     pub revision: String,
     pub full_name: String,
     pub body: Vec<u8>,
+    #[serde(with = "rfc3339_opt")]
     pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
     pub updated_at: Option<NaiveDateTime>,
     pub origin: String,
 }
@@ -54,7 +63,9 @@ pub struct OriginPrivateSigningKey {
     pub revision: String,
     pub full_name: String,
     pub body: Vec<u8>,
+    #[serde(with = "rfc3339_opt")]
     pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
     pub updated_at: Option<NaiveDateTime>,
     pub origin: String,
 }
@@ -70,9 +81,60 @@ pub struct OriginPublicSigningKey {
     pub revision: String,
     pub full_name: String,
     pub body: Vec<u8>,
+    #[serde(with = "rfc3339_opt")]
     pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
     pub updated_at: Option<NaiveDateTime>,
     pub origin: String,
+    /// Set once a newer revision replaces this one by rotation. Packages signed with this
+    /// revision are no longer accepted for upload after this time.
+    #[serde(with = "rfc3339_opt")]
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+#[derive(DbEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyRotationTrigger {
+    #[serde(rename = "schedule")]
+    Schedule,
+    #[serde(rename = "manual")]
+    Manual,
+}
+
+/// An origin's policy for rotating its signing key
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+pub struct OriginKeyRotation {
+    pub origin: String,
+    pub enabled: bool,
+    pub interval_days: i32,
+    #[serde(with = "rfc3339_opt")]
+    pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+#[derive(QueryableByName)]
+struct RotationLock {
+    #[sql_type = "Bool"]
+    locked: bool,
+}
+
+#[derive(Insertable)]
+#[table_name = "origin_key_rotation"]
+pub struct NewOriginKeyRotation<'a> {
+    pub origin:        &'a str,
+    pub enabled:       bool,
+    pub interval_days: i32,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "audit_origin_key_rotation"]
+pub struct OriginKeyRotationAudit<'a> {
+    pub origin:            &'a str,
+    pub previous_revision: Option<&'a str>,
+    pub revision:          &'a str,
+    pub trigger:           KeyRotationTrigger,
+    pub requester_id:      i64,
+    pub requester_name:    &'a str,
 }
 
 #[derive(Insertable)]
@@ -214,6 +276,26 @@ impl OriginPublicSigningKey {
                                  .order(origin_public_keys::revision.desc())
                                  .get_results(conn)
     }
+
+    /// Start the overlap window of every revision other than `revision` that has not been
+    /// superseded yet, so that they expire at `at`.
+    pub fn expire_previous(origin: &str,
+                           revision: &str,
+                           at: NaiveDateTime,
+                           conn: &PgConnection)
+                           -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::update(origin_public_keys::table.filter(origin_public_keys::origin.eq(origin))
+                                                .filter(origin_public_keys::revision.ne(revision))
+                                                .filter(origin_public_keys::expires_at.is_null()))
+            .set(origin_public_keys::expires_at.eq(at))
+            .execute(conn)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map_or(false, |expires_at| expires_at <= Utc::now().naive_utc())
+    }
 }
 
 impl OriginPrivateSigningKey {
@@ -234,3 +316,60 @@ impl OriginPrivateSigningKey {
                                                       .get_result(conn)
     }
 }
+
+impl OriginKeyRotation {
+    pub fn get(origin: &str, conn: &PgConnection) -> QueryResult<OriginKeyRotation> {
+        Counter::DBCall.increment();
+        origin_key_rotation::table.find(origin).get_result(conn)
+    }
+
+    pub fn upsert(req: &NewOriginKeyRotation,
+                  conn: &PgConnection)
+                  -> QueryResult<OriginKeyRotation> {
+        Counter::DBCall.increment();
+        diesel::insert_into(origin_key_rotation::table)
+            .values(req)
+            .on_conflict(origin_key_rotation::origin)
+            .do_update()
+            .set((origin_key_rotation::enabled.eq(req.enabled),
+                  origin_key_rotation::interval_days.eq(req.interval_days),
+                  origin_key_rotation::updated_at.eq(Utc::now().naive_utc())))
+            .get_result(conn)
+    }
+
+    pub fn list_enabled(conn: &PgConnection) -> QueryResult<Vec<OriginKeyRotation>> {
+        Counter::DBCall.increment();
+        origin_key_rotation::table.filter(origin_key_rotation::enabled.eq(true))
+                                  .get_results(conn)
+    }
+
+    /// Take the key rotation lock of `origin` until the end of the current transaction, waiting
+    /// for a rotation that holds it on another connection to finish.
+    pub fn lock(origin: &str, conn: &PgConnection) -> QueryResult<()> {
+        Counter::DBCall.increment();
+        diesel::sql_query("SELECT pg_advisory_xact_lock(hashtext($1))")
+            .bind::<Text, _>(lock_key(origin))
+            .execute(conn)
+            .map(|_| ())
+    }
+
+    /// Take the key rotation lock of `origin` until the end of the current transaction, unless
+    /// another connection holds it.
+    pub fn try_lock(origin: &str, conn: &PgConnection) -> QueryResult<bool> {
+        Counter::DBCall.increment();
+        diesel::sql_query("SELECT pg_try_advisory_xact_lock(hashtext($1)) AS locked")
+            .bind::<Text, _>(lock_key(origin))
+            .get_result::<RotationLock>(conn)
+            .map(|lock| lock.locked)
+    }
+}
+
+fn lock_key(origin: &str) -> String { format!("origin_key_rotation/{}", origin) }
+
+impl<'a> OriginKeyRotationAudit<'a> {
+    pub fn audit(req: &OriginKeyRotationAudit, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::insert_into(audit_origin_key_rotation::table).values(req)
+                                                             .execute(conn)
+    }
+}
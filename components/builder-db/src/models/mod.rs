@@ -3,17 +3,21 @@
 #![allow(proc_macro_derive_resolution_fallback)]
 
 pub mod account;
+pub mod announcement;
 pub mod channel;
+pub mod install;
 pub mod integration;
 pub mod invitations;
 pub mod jobs;
 pub mod keys;
+pub mod notifications;
 pub mod origin;
 pub mod package;
 pub mod pagination;
 pub mod project_integration;
 pub mod projects;
 pub mod secrets;
+pub mod status;
 
 mod db_id_format {
     use serde::{self,
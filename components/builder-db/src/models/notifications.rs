@@ -0,0 +1,81 @@
+use super::db_id_format;
+use crate::bldr_core::timestamp::rfc3339_opt;
+use chrono::NaiveDateTime;
+use diesel::{self,
+             pg::PgConnection,
+             result::QueryResult,
+             ExpressionMethods,
+             QueryDsl,
+             RunQueryDsl};
+
+use crate::{bldr_core::metrics::CounterMetric,
+            metrics::Counter,
+            schema::notification::{email_notifications,
+                                   notifications}};
+
+#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable)]
+pub struct Notification {
+    #[serde(with = "db_id_format")]
+    pub id: i64,
+    #[serde(with = "db_id_format")]
+    pub account_id: i64,
+    pub category: String,
+    pub message: String,
+    #[serde(with = "rfc3339_opt")]
+    pub created_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "notifications"]
+pub struct NewNotification<'a> {
+    pub account_id: i64,
+    pub category:   &'a str,
+    pub message:    &'a str,
+}
+
+impl Notification {
+    pub fn create(req: &NewNotification, conn: &PgConnection) -> QueryResult<Notification> {
+        Counter::DBCall.increment();
+        diesel::insert_into(notifications::table).values(req)
+                                                 .get_result(conn)
+    }
+
+    pub fn list(account_id: u64, conn: &PgConnection) -> QueryResult<Vec<Notification>> {
+        Counter::DBCall.increment();
+        notifications::table.filter(notifications::account_id.eq(account_id as i64))
+                            .order(notifications::created_at.desc())
+                            .get_results(conn)
+    }
+}
+
+/// A message for an address that has no account to notify yet, queued for the mail relay.
+#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable)]
+pub struct EmailNotification {
+    #[serde(with = "db_id_format")]
+    pub id: i64,
+    pub email: String,
+    pub category: String,
+    pub message: String,
+    #[serde(with = "rfc3339_opt")]
+    pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
+    pub sent_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "email_notifications"]
+pub struct NewEmailNotification<'a> {
+    pub email:    &'a str,
+    pub category: &'a str,
+    pub message:  &'a str,
+}
+
+impl EmailNotification {
+    pub fn create(req: &NewEmailNotification,
+                  conn: &PgConnection)
+                  -> QueryResult<EmailNotification> {
+        Counter::DBCall.increment();
+        diesel::insert_into(email_notifications::table).values(req)
+                                                       .get_result(conn)
+    }
+}
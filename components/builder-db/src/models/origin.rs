@@ -1,11 +1,17 @@
 use super::db_id_format;
-use chrono::NaiveDateTime;
+use crate::bldr_core::timestamp::rfc3339_opt;
+use chrono::{NaiveDateTime,
+             Utc};
 
 use diesel::{self,
              pg::PgConnection,
              prelude::*,
              result::{Error,
                       QueryResult},
+             sql_types::{BigInt,
+                         Nullable,
+                         Text,
+                         Timestamptz},
              ExpressionMethods,
              QueryDsl,
              RunQueryDsl};
@@ -15,12 +21,15 @@ use crate::{models::{channel::{Channel,
                      package::PackageVisibility},
             protocol::originsrv};
 
-use crate::schema::{channel::origin_channels,
+use crate::schema::{audit::audit_upload_policy_override,
+                    channel::origin_channels,
                     integration::origin_integrations,
                     key::{origin_public_keys,
                           origin_secret_keys},
                     member::origin_members,
-                    origin::{origins,
+                    origin::{origin_artifact_encryption,
+                             origin_upload_policies,
+                             origins,
                              origins_with_secret_key,
                              origins_with_stats}};
 
@@ -34,7 +43,9 @@ pub struct Origin {
     #[serde(with = "db_id_format")]
     pub owner_id: i64,
     pub name: String,
+    #[serde(with = "rfc3339_opt")]
     pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
     pub updated_at: Option<NaiveDateTime>,
     pub default_package_visibility: PackageVisibility,
 }
@@ -53,7 +64,9 @@ pub struct OriginWithStats {
     #[serde(with = "db_id_format")]
     pub owner_id: i64,
     pub name: String,
+    #[serde(with = "rfc3339_opt")]
     pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
     pub updated_at: Option<NaiveDateTime>,
     pub default_package_visibility: PackageVisibility,
     pub package_count: i64,
@@ -65,7 +78,9 @@ pub struct OriginMember {
     #[serde(with = "db_id_format")]
     pub account_id: i64,
     pub origin: String,
+    #[serde(with = "rfc3339_opt")]
     pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
     pub updated_at: Option<NaiveDateTime>,
 }
 
@@ -195,6 +210,219 @@ impl OriginMember {
     }
 }
 
+// The origins an account is a member of, with its role in each
+const ACCOUNT_MEMBERSHIPS: &str = "
+SELECT origin_members.origin,
+       CASE WHEN origins.owner_id = origin_members.account_id THEN 'owner' ELSE 'member' END
+           AS role,
+       origin_members.created_at AS member_since
+FROM origin_members
+INNER JOIN origins ON origins.name = origin_members.origin
+WHERE origin_members.account_id = $1
+ORDER BY origin_members.origin";
+
+/// An origin an account is a member of
+#[derive(Debug, Serialize, QueryableByName)]
+pub struct AccountMembership {
+    #[sql_type = "Text"]
+    pub origin: String,
+    /// `owner` or `member`
+    #[sql_type = "Text"]
+    pub role: String,
+    #[sql_type = "Nullable<Timestamptz>"]
+    #[serde(with = "rfc3339_opt")]
+    pub member_since: Option<NaiveDateTime>,
+}
+
+impl AccountMembership {
+    pub fn list(account_id: i64, conn: &PgConnection) -> QueryResult<Vec<AccountMembership>> {
+        Counter::DBCall.increment();
+        diesel::sql_query(ACCOUNT_MEMBERSHIPS).bind::<BigInt, _>(account_id)
+                                              .load(conn)
+    }
+}
+
+// Each member of an origin with the latest of their activities recorded in this database, one
+// aggregate per activity so that members who did nothing are listed all the same
+const ORIGIN_MEMBER_ACTIVITY: &str = "
+SELECT accounts.id AS account_id,
+       accounts.name AS account_name,
+       CASE WHEN origins.owner_id = accounts.id THEN 'owner' ELSE 'member' END AS role,
+       origin_members.created_at AS member_since,
+       accounts.last_authenticated_at,
+       uploads.last_upload_at,
+       promotions.last_promotion_at
+FROM origin_members
+INNER JOIN accounts ON accounts.id = origin_members.account_id
+INNER JOIN origins ON origins.name = origin_members.origin
+LEFT JOIN (SELECT owner_id, max(created_at) AS last_upload_at
+           FROM origin_packages
+           WHERE origin = $1
+           GROUP BY owner_id) AS uploads ON uploads.owner_id = accounts.id
+LEFT JOIN (SELECT requester_id, max(created_at) AS last_promotion_at
+           FROM (SELECT requester_id, created_at
+                 FROM audit_package
+                 WHERE origin = $1 AND operation = 'promote'
+                 UNION ALL
+                 SELECT requester_id, created_at
+                 FROM audit_package_group
+                 WHERE origin = $1 AND operation = 'promote') AS promoted
+           GROUP BY requester_id) AS promotions ON promotions.requester_id = accounts.id
+WHERE origin_members.origin = $1
+ORDER BY accounts.name";
+
+/// What a member of an origin last did in it, for access reviews. Activities the member never
+/// did are absent.
+#[derive(Debug, QueryableByName)]
+pub struct OriginMemberActivity {
+    #[sql_type = "BigInt"]
+    pub account_id: i64,
+    #[sql_type = "Text"]
+    pub account_name: String,
+    /// `owner` or `member`
+    #[sql_type = "Text"]
+    pub role: String,
+    #[sql_type = "Nullable<Timestamptz>"]
+    pub member_since: Option<NaiveDateTime>,
+    #[sql_type = "Nullable<Timestamptz>"]
+    pub last_authenticated_at: Option<NaiveDateTime>,
+    #[sql_type = "Nullable<Timestamptz>"]
+    pub last_upload_at: Option<NaiveDateTime>,
+    #[sql_type = "Nullable<Timestamptz>"]
+    pub last_promotion_at: Option<NaiveDateTime>,
+}
+
+impl OriginMemberActivity {
+    pub fn list(origin: &str, conn: &PgConnection) -> QueryResult<Vec<OriginMemberActivity>> {
+        Counter::DBCall.increment();
+        diesel::sql_query(ORIGIN_MEMBER_ACTIVITY).bind::<Text, _>(origin)
+                                                 .load(conn)
+    }
+}
+
+/// Checks uploads to an origin must pass. Packages already stored are not affected.
+#[derive(Debug, Serialize, Deserialize, Queryable, Clone)]
+pub struct OriginUploadPolicy {
+    #[serde(skip)]
+    pub origin: String,
+    pub require_license: bool,
+    pub allowed_licenses: Vec<String>,
+    pub blocked_licenses: Vec<String>,
+    pub require_description: bool,
+    #[serde(with = "rfc3339_opt")]
+    pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
+    pub updated_at: Option<NaiveDateTime>,
+    /// Rejects packages with runtime dependencies the depot does not have, rather than only
+    /// reporting them
+    pub strict_dependency_validation: bool,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "origin_upload_policies"]
+pub struct NewOriginUploadPolicy<'a> {
+    pub origin: &'a str,
+    pub require_license: bool,
+    pub allowed_licenses: &'a [String],
+    pub blocked_licenses: &'a [String],
+    pub require_description: bool,
+    pub strict_dependency_validation: bool,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "audit_upload_policy_override"]
+pub struct UploadPolicyOverrideAudit<'a> {
+    pub origin:         &'a str,
+    pub package_ident:  &'a str,
+    pub target:         &'a str,
+    pub violations:     &'a [String],
+    pub requester_id:   i64,
+    pub requester_name: &'a str,
+}
+
+impl OriginUploadPolicy {
+    pub fn get(origin: &str, conn: &PgConnection) -> QueryResult<Option<OriginUploadPolicy>> {
+        Counter::DBCall.increment();
+        origin_upload_policies::table.find(origin)
+                                     .get_result(conn)
+                                     .optional()
+    }
+
+    /// Sets the origin's policy, replacing any earlier one
+    pub fn upsert(req: &NewOriginUploadPolicy,
+                  conn: &PgConnection)
+                  -> QueryResult<OriginUploadPolicy> {
+        Counter::DBCall.increment();
+        diesel::insert_into(origin_upload_policies::table)
+            .values(req)
+            .on_conflict(origin_upload_policies::origin)
+            .do_update()
+            .set((origin_upload_policies::require_license.eq(req.require_license),
+                  origin_upload_policies::allowed_licenses.eq(req.allowed_licenses),
+                  origin_upload_policies::blocked_licenses.eq(req.blocked_licenses),
+                  origin_upload_policies::require_description.eq(req.require_description),
+                  origin_upload_policies::strict_dependency_validation
+                      .eq(req.strict_dependency_validation),
+                  origin_upload_policies::updated_at.eq(Utc::now().naive_utc())))
+            .get_result(conn)
+    }
+}
+
+/// The origin key that artifacts uploaded to an origin are encrypted under. The key itself is
+/// held by the API server's key provider, only its id is stored.
+#[derive(Debug, Serialize, Deserialize, Queryable, Clone)]
+pub struct OriginArtifactEncryption {
+    pub origin: String,
+    pub key_id: String,
+    #[serde(with = "rfc3339_opt")]
+    pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "origin_artifact_encryption"]
+pub struct NewOriginArtifactEncryption<'a> {
+    pub origin: &'a str,
+    pub key_id: &'a str,
+}
+
+impl OriginArtifactEncryption {
+    pub fn get(origin: &str, conn: &PgConnection) -> QueryResult<Option<OriginArtifactEncryption>> {
+        Counter::DBCall.increment();
+        origin_artifact_encryption::table.find(origin)
+                                         .get_result(conn)
+                                         .optional()
+    }
+
+    /// Sets the origin's key, replacing any earlier one
+    pub fn upsert(req: &NewOriginArtifactEncryption,
+                  conn: &PgConnection)
+                  -> QueryResult<OriginArtifactEncryption> {
+        Counter::DBCall.increment();
+        diesel::insert_into(origin_artifact_encryption::table)
+            .values(req)
+            .on_conflict(origin_artifact_encryption::origin)
+            .do_update()
+            .set((origin_artifact_encryption::key_id.eq(req.key_id),
+                  origin_artifact_encryption::updated_at.eq(Utc::now().naive_utc())))
+            .get_result(conn)
+    }
+
+    pub fn delete(origin: &str, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::delete(origin_artifact_encryption::table.find(origin)).execute(conn)
+    }
+}
+
+impl<'a> UploadPolicyOverrideAudit<'a> {
+    pub fn audit(req: &UploadPolicyOverrideAudit, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::insert_into(audit_upload_policy_override::table).values(req)
+                                                                .execute(conn)
+    }
+}
+
 impl Into<originsrv::Origin> for Origin {
     fn into(self) -> originsrv::Origin {
         let mut orig = originsrv::Origin::new();
@@ -4,7 +4,8 @@ use std::{fmt,
           str::{self,
                 FromStr}};
 
-use chrono::NaiveDateTime;
+use chrono::{NaiveDateTime,
+             Utc};
 use protobuf;
 use time::PreciseTime;
 
@@ -24,14 +25,23 @@ use diesel::{self,
                          IsNull,
                          Output,
                          ToSql},
-             sql_types::Text,
+             sql_types::{Array,
+                         BigInt,
+                         Bool,
+                         Integer,
+                         Nullable,
+                         Text,
+                         Timestamptz},
              PgArrayExpressionMethods,
              RunQueryDsl};
 use diesel_full_text_search::{to_tsquery,
                               TsQueryExtensions};
 
 use super::db_id_format;
-use crate::{hab_core::{self,
+use crate::{bldr_core::{package_name,
+                        timestamp::{self,
+                                    rfc3339_opt}},
+            hab_core::{self,
                        package::{FromArchive,
                                  Identifiable,
                                  PackageArchive,
@@ -43,10 +53,17 @@ use crate::{hab_core::{self,
                                OriginChannelPromote},
                      pagination::*}};
 
-use crate::schema::{channel::{origin_channel_packages,
+use crate::schema::{audit::audit_package_deprecation,
+                    channel::{origin_channel_packages,
                               origin_channels},
                     origin::origins,
-                    package::{origin_package_versions,
+                    package::{origin_package_build_cache,
+                              origin_package_dependency_reports,
+                              origin_package_deprecations,
+                              origin_package_encryption,
+                              origin_package_upstreams,
+                              origin_package_verification_failures,
+                              origin_package_versions,
                               origin_packages,
                               origin_packages_with_version_array,
                               packages_with_channel_platform}};
@@ -85,7 +102,9 @@ pub struct Package {
     pub build_tdeps: Vec<BuilderPackageIdent>,
     pub exposes: Vec<i32>,
     pub visibility: PackageVisibility,
+    #[serde(with = "rfc3339_opt")]
     pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
     pub updated_at: Option<NaiveDateTime>,
     pub origin: String,
 }
@@ -113,7 +132,9 @@ pub struct PackageWithVersionArray {
     pub deps: Vec<BuilderPackageIdent>,
     pub tdeps: Vec<BuilderPackageIdent>,
     pub exposes: Vec<i32>,
+    #[serde(with = "rfc3339_opt")]
     pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
     pub updated_at: Option<NaiveDateTime>,
     pub visibility: PackageVisibility,
     pub origin: String,
@@ -149,7 +170,9 @@ pub struct PackageWithChannelPlatform {
     pub build_tdeps: Vec<BuilderPackageIdent>,
     pub exposes: Vec<i32>,
     pub visibility: PackageVisibility,
+    #[serde(with = "rfc3339_opt")]
     pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
     pub updated_at: Option<NaiveDateTime>,
     pub origin: String,
     pub channels: Vec<String>,
@@ -158,12 +181,15 @@ pub struct PackageWithChannelPlatform {
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct PackageIdentWithChannelPlatform {
-    pub origin:    String,
-    pub name:      String,
-    pub version:   Option<String>,
-    pub release:   Option<String>,
-    pub channels:  Vec<String>,
-    pub platforms: Vec<String>,
+    pub origin:     String,
+    pub name:       String,
+    pub version:    Option<String>,
+    pub release:    Option<String>,
+    // RFC 3339 form of `release`, which the hab client still reads in its original format
+    #[serde(default)]
+    pub release_at: Option<String>,
+    pub channels:   Vec<String>,
+    pub platforms:  Vec<String>,
 }
 
 /// We literally never want to select `ident_vector`
@@ -260,6 +286,8 @@ pub struct NewPackage {
     #[serde(with = "db_id_format")]
     pub owner_id: i64,
     pub name: String,
+    /// The name as `package_name::normalize` makes it, matched by searches
+    pub normalized_name: String,
     pub ident: BuilderPackageIdent,
     pub ident_array: Vec<String>,
     pub checksum: String,
@@ -314,11 +342,100 @@ pub struct ListPackages {
 }
 
 pub struct SearchPackages {
-    pub query:      String,
-    pub account_id: Option<i64>,
-    pub page:       i64,
-    pub limit:      i64,
+    pub query:             String,
+    pub account_id:        Option<i64>,
+    pub page:              i64,
+    pub limit:             i64,
+    /// Restrict results to these origins, or search all origins when empty
+    pub origins:           Vec<String>,
+    /// Origins whose exact matches rank first, highest priority first
+    pub preferred_origins: Vec<String>,
+    /// The package name an exact match must have
+    pub exact_name:        String,
+    /// Also match packages whose normalized name starts with this, None to match the query alone
+    pub normalized_name:   Option<String>,
+    /// The origin the query names, which packages matched by normalized name must be in
+    pub query_origin:      Option<String>,
+}
+/// Most entries a package or channel feed holds
+pub const FEED_ENTRIES: i64 = 50;
+/// Most package names read to suggest names for one that does not exist
+pub const MAX_SUGGESTION_CANDIDATES: i64 = 500;
+
+const PACKAGE_RELEASE_FEED: &str = "
+SELECT p.ident, p.target, p.created_at AS updated_at, NULL::text AS requester_name
+FROM origin_packages AS p
+WHERE p.origin = $1 AND p.name = $2 AND p.visibility::text = ANY($3)
+  AND ($4 = '' OR p.target = $4)
+  AND ($5 = '' OR EXISTS (SELECT 1
+                          FROM origin_channel_packages AS ocp
+                          INNER JOIN origin_channels AS oc ON oc.id = ocp.channel_id
+                          WHERE ocp.package_id = p.id AND oc.origin = $1 AND oc.name = $5))
+ORDER BY p.created_at DESC, p.id DESC
+LIMIT $6";
+
+/// A release in a feed, when it was uploaded or, in a channel feed, promoted and by whom
+#[derive(Debug, QueryableByName)]
+pub struct FeedRelease {
+    #[sql_type = "Text"]
+    pub ident:          String,
+    #[sql_type = "Text"]
+    pub target:         String,
+    #[sql_type = "Nullable<Timestamptz>"]
+    pub updated_at:     Option<NaiveDateTime>,
+    #[sql_type = "Nullable<Text>"]
+    pub requester_name: Option<String>,
+}
+
+/// Releases of a package in its target matrix, unless asked for fewer
+pub const TARGET_MATRIX_RELEASES: i64 = 5;
+pub const MAX_TARGET_MATRIX_RELEASES: i64 = 20;
+
+// The most recent releases of a package, by version and release across targets, then which
+// targets each was built for and the channels it is in there. The channel, when not empty,
+// restricts both to the packages in it.
+const PACKAGE_TARGET_MATRIX: &str = "
+WITH releases AS (
+    SELECT p.ident_array[3] AS version, p.ident_array[4] AS release,
+           max(p.created_at) AS created_at
+    FROM origin_packages AS p
+    WHERE p.origin = $1 AND p.name = $2 AND p.visibility::text = ANY($3)
+      AND ($4 = '' OR EXISTS (SELECT 1
+                              FROM origin_channel_packages AS ocp
+                              INNER JOIN origin_channels AS oc ON oc.id = ocp.channel_id
+                              WHERE ocp.package_id = p.id AND oc.origin = $1 AND oc.name = $4))
+    GROUP BY p.ident_array[3], p.ident_array[4]
+    ORDER BY max(p.created_at) DESC, p.ident_array[4] DESC
+    LIMIT $5)
+SELECT r.version, r.release, p.target,
+       array_remove(array_agg(oc.name ORDER BY oc.name), NULL) AS channels
+FROM releases AS r
+INNER JOIN origin_packages AS p
+    ON p.origin = $1 AND p.name = $2
+   AND p.ident_array[3] = r.version AND p.ident_array[4] = r.release
+LEFT JOIN origin_channel_packages AS ocp ON ocp.package_id = p.id
+LEFT JOIN origin_channels AS oc ON oc.id = ocp.channel_id
+WHERE p.visibility::text = ANY($3)
+  AND ($4 = '' OR EXISTS (SELECT 1
+                          FROM origin_channel_packages AS ocp
+                          INNER JOIN origin_channels AS oc ON oc.id = ocp.channel_id
+                          WHERE ocp.package_id = p.id AND oc.origin = $1 AND oc.name = $4))
+GROUP BY r.version, r.release, r.created_at, p.target
+ORDER BY r.created_at DESC, r.release DESC, p.target";
+
+/// A release of a package built for a target, and the channels it is in
+#[derive(Debug, QueryableByName)]
+pub struct TargetMatrixCell {
+    #[sql_type = "Text"]
+    pub version:  String,
+    #[sql_type = "Text"]
+    pub release:  String,
+    #[sql_type = "Text"]
+    pub target:   String,
+    #[sql_type = "Array<Text>"]
+    pub channels: Vec<String>,
 }
+
 #[derive(Debug, Serialize, Deserialize, Queryable)]
 pub struct OriginPackageVersions {
     pub origin: String,
@@ -395,6 +512,15 @@ impl PackageWithVersionArray {
     }
 }
 
+/// A stored package's manifest, for checks over all of an origin's packages
+#[derive(Debug, Queryable)]
+pub struct PackageManifest {
+    pub id:       i64,
+    pub ident:    BuilderPackageIdent,
+    pub target:   BuilderPackageTarget,
+    pub manifest: String,
+}
+
 impl Package {
     pub fn get_without_target(ident: BuilderPackageIdent,
                               visibility: Vec<PackageVisibility>,
@@ -483,6 +609,14 @@ impl Package {
         result
     }
 
+    /// Up to `limit` packages picked at random, of any release
+    pub fn sample(limit: i64, conn: &PgConnection) -> QueryResult<Vec<PackageWithVersionArray>> {
+        Counter::DBCall.increment();
+        origin_packages_with_version_array::table.order(sql::<PackageWithVersionArray>("random()"))
+                                                 .limit(limit)
+                                                 .get_results(conn)
+    }
+
     pub fn create(package: &NewPackage, conn: &PgConnection) -> QueryResult<Package> {
         Counter::DBCall.increment();
         let pkg = diesel::insert_into(origin_packages::table)
@@ -494,6 +628,7 @@ impl Package {
                 origin_packages::origin.eq(excluded(origin_packages::origin)),
                 origin_packages::owner_id.eq(excluded(origin_packages::owner_id)),
                 origin_packages::name.eq(excluded(origin_packages::name)),
+                origin_packages::normalized_name.eq(excluded(origin_packages::normalized_name)),
                 origin_packages::ident.eq(excluded(origin_packages::ident)),
                 origin_packages::checksum.eq(excluded(origin_packages::checksum)),
                 origin_packages::manifest.eq(excluded(origin_packages::manifest)),
@@ -543,7 +678,8 @@ impl Package {
         let (mut pkgs, _) : (std::vec::Vec<PackageWithChannelPlatform>, i64) = packages_with_channel_platform::table
             .filter(packages_with_channel_platform::ident_array.contains(pl.ident.clone().parts()))
             .filter(packages_with_channel_platform::visibility.eq(any(pl.visibility)))
-            .order(packages_with_channel_platform::ident.desc())
+            .order((packages_with_channel_platform::ident.desc(),
+                    packages_with_channel_platform::target.asc()))
             .paginate(pl.page)
             .per_page(pl.limit)
             .load_and_count_records(conn)?;
@@ -588,8 +724,7 @@ impl Package {
                               .filter(origins::name.eq(&pl.ident.origin))
                               .filter(origin_packages::visibility.eq(any(pl.visibility)))
                               .filter(sql("TRUE GROUP BY origin_packages.name, origins.name"))
-                              .order(origins::name.asc())
-                              .order(origin_packages::name.asc())
+                              .order((origins::name.asc(), origin_packages::name.asc()))
                               .paginate(pl.page)
                               .per_page(pl.limit)
                               .load_and_count_records(conn)
@@ -621,11 +756,70 @@ impl Package {
             .filter(origin_package_versions::name.eq(ident.name()))
             .filter(origin_package_versions::visibility.eq(any(visibility)))
             .order(sql::<OriginPackageVersions>(
-                "string_to_array(version_array[1],'.')::numeric[]desc, version_array[2] desc",
+                "string_to_array(version_array[1],'.')::numeric[]desc, version_array[2] desc, \
+                 version desc, visibility",
             ))
             .get_results(conn)
     }
 
+    /// The latest releases of a package, at most `FEED_ENTRIES`. An empty channel or target
+    /// matches every release.
+    pub fn list_feed(origin: &str,
+                     name: &str,
+                     channel: &str,
+                     target: &str,
+                     visibility: &[PackageVisibility],
+                     conn: &PgConnection)
+                     -> QueryResult<Vec<FeedRelease>> {
+        Counter::DBCall.increment();
+        let visibility: Vec<String> = visibility.iter().map(ToString::to_string).collect();
+        diesel::sql_query(PACKAGE_RELEASE_FEED).bind::<Text, _>(origin)
+                                               .bind::<Text, _>(name)
+                                               .bind::<Array<Text>, _>(visibility)
+                                               .bind::<Text, _>(target)
+                                               .bind::<Text, _>(channel)
+                                               .bind::<BigInt, _>(FEED_ENTRIES)
+                                               .load(conn)
+    }
+
+    /// The targets each of the latest `releases` releases of a package was built for, newest
+    /// release first. An empty channel matches every release.
+    pub fn list_target_matrix(origin: &str,
+                              name: &str,
+                              channel: &str,
+                              releases: i64,
+                              visibility: &[PackageVisibility],
+                              conn: &PgConnection)
+                              -> QueryResult<Vec<TargetMatrixCell>> {
+        Counter::DBCall.increment();
+        let visibility: Vec<String> = visibility.iter().map(ToString::to_string).collect();
+        diesel::sql_query(PACKAGE_TARGET_MATRIX).bind::<Text, _>(origin)
+                                                .bind::<Text, _>(name)
+                                                .bind::<Array<Text>, _>(visibility)
+                                                .bind::<Text, _>(channel)
+                                                .bind::<BigInt, _>(releases)
+                                                .load(conn)
+    }
+
+    /// The id, identifier, target and manifest of up to `limit` of an origin's packages, in id
+    /// order after `after_id`, to go through all of them a batch at a time
+    pub fn list_manifests(origin: &str,
+                          after_id: i64,
+                          limit: i64,
+                          conn: &PgConnection)
+                          -> QueryResult<Vec<PackageManifest>> {
+        Counter::DBCall.increment();
+        origin_packages::table.select((origin_packages::id,
+                                       origin_packages::ident,
+                                       origin_packages::target,
+                                       origin_packages::manifest))
+                              .filter(origin_packages::origin.eq(origin))
+                              .filter(origin_packages::id.gt(after_id))
+                              .order(origin_packages::id.asc())
+                              .limit(limit)
+                              .get_results(conn)
+    }
+
     pub fn count_origin_packages(origin: &str, conn: &PgConnection) -> QueryResult<i64> {
         Counter::DBCall.increment();
         origin_packages::table.select(count(origin_packages::id))
@@ -640,10 +834,40 @@ impl Package {
         let mut query = origin_packages::table
             .inner_join(origins::table)
             .select(origin_packages::ident)
-            .filter(to_tsquery(sp.query).matches(origin_packages::ident_vector))
-            .order(origin_packages::ident.asc())
+            .order((
+                // Exact name matches in the searcher's preferred origins come first, weighted
+                // by each origin's position in the list
+                sql::<Nullable<BigInt>>("(SELECT preferred.weight FROM unnest(")
+                    .bind::<Array<Text>, _>(sp.preferred_origins)
+                    .sql(") WITH ORDINALITY AS preferred(origin, weight) \
+                          WHERE preferred.origin = origins.name \
+                          AND origin_packages.name = ")
+                    .bind::<Text, _>(sp.exact_name)
+                    .sql(") ASC NULLS LAST"),
+                origin_packages::ident.asc(),
+                origin_packages::target.asc(),
+            ))
             .into_boxed();
 
+        // Names like libXML2 or lib-xml2 match libxml2 by their normalized name
+        let matches = to_tsquery(sp.query).matches(origin_packages::ident_vector);
+        query = match (sp.normalized_name, sp.query_origin) {
+            (Some(name), Some(origin)) => {
+                query.filter(matches.or(origin_packages::normalized_name
+                                            .like(format!("{}%", name))
+                                            .and(origins::name.eq(origin))))
+            }
+            (Some(name), None) => {
+                query.filter(matches.or(origin_packages::normalized_name
+                                            .like(format!("{}%", name))))
+            }
+            (None, _) => query.filter(matches),
+        };
+
+        if !sp.origins.is_empty() {
+            query = query.filter(origins::name.eq(any(sp.origins)));
+        }
+
         if let Some(session_id) = sp.account_id {
             query = query.filter(
                 origin_packages::visibility
@@ -669,10 +893,38 @@ impl Package {
         let mut query = origin_packages::table
             .inner_join(origins::table)
             .select(sql("concat_ws('/', origins.name, origin_packages.name)"))
-            .filter(to_tsquery(sp.query).matches(origin_packages::ident_vector))
-            .order(origin_packages::name.asc())
+            .order((
+                sql::<Nullable<BigInt>>("(SELECT preferred.weight FROM unnest(")
+                    .bind::<Array<Text>, _>(sp.preferred_origins)
+                    .sql(") WITH ORDINALITY AS preferred(origin, weight) \
+                          WHERE preferred.origin = origins.name \
+                          AND origin_packages.name = ")
+                    .bind::<Text, _>(sp.exact_name)
+                    .sql(") ASC NULLS LAST"),
+                origin_packages::name.asc(),
+                origins::name.asc(),
+            ))
             .into_boxed();
 
+        // Names like libXML2 or lib-xml2 match libxml2 by their normalized name
+        let matches = to_tsquery(sp.query).matches(origin_packages::ident_vector);
+        query = match (sp.normalized_name, sp.query_origin) {
+            (Some(name), Some(origin)) => {
+                query.filter(matches.or(origin_packages::normalized_name
+                                            .like(format!("{}%", name))
+                                            .and(origins::name.eq(origin))))
+            }
+            (Some(name), None) => {
+                query.filter(matches.or(origin_packages::normalized_name
+                                            .like(format!("{}%", name))))
+            }
+            (None, _) => query.filter(matches),
+        };
+
+        if !sp.origins.is_empty() {
+            query = query.filter(origins::name.eq(any(sp.origins)));
+        }
+
         if let Some(session_id) = sp.account_id {
             query = query.filter(
                 origin_packages::visibility
@@ -691,6 +943,56 @@ impl Package {
              .load_and_count_records(conn)
     }
 
+    /// The names of the origin's packages near `name` once normalized, as
+    /// `package_name::suggestions` finds them. There are none when the origin has packages
+    /// named `name`. Only the names that can be near are read, at most
+    /// `MAX_SUGGESTION_CANDIDATES` of them.
+    pub fn suggest_names(origin: &str,
+                         name: &str,
+                         visibilities: Vec<PackageVisibility>,
+                         conn: &PgConnection)
+                         -> QueryResult<Vec<String>> {
+        let wanted = package_name::normalize(name);
+        if wanted.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        Counter::DBCall.increment();
+        let named: bool = diesel::select(diesel::dsl::exists(
+            origin_packages::table
+                .filter(origin_packages::origin.eq(origin))
+                .filter(origin_packages::name.eq(name)),
+        ))
+        .get_result(conn)?;
+        if named {
+            return Ok(Vec::new());
+        }
+
+        // Names within the edits allowed of the wanted length, or containing or contained in it
+        let max_edits = package_name::max_edits(&wanted) as i32;
+        let len = wanted.len() as i32;
+        let near = sql::<Bool>("(length(origin_packages.normalized_name) BETWEEN ")
+            .bind::<Integer, _>(len - max_edits)
+            .sql(" AND ")
+            .bind::<Integer, _>(len + max_edits)
+            .sql(" OR strpos(origin_packages.normalized_name, ")
+            .bind::<Text, _>(wanted.clone())
+            .sql(") > 0 OR strpos(")
+            .bind::<Text, _>(wanted)
+            .sql(", origin_packages.normalized_name) > 0)");
+
+        Counter::DBCall.increment();
+        let names: Vec<String> =
+            origin_packages::table.select(origin_packages::name)
+                                  .distinct()
+                                  .filter(origin_packages::origin.eq(origin))
+                                  .filter(origin_packages::visibility.eq(any(visibilities)))
+                                  .filter(near)
+                                  .limit(MAX_SUGGESTION_CANDIDATES)
+                                  .get_results(conn)?;
+        Ok(package_name::suggestions(name, names.iter().map(String::as_str)))
+    }
+
     pub fn all() -> All { origin_packages::table.select(ALL_COLUMNS) }
 
     pub fn list_package_platforms(ident: &BuilderPackageIdent,
@@ -725,6 +1027,424 @@ impl PackageWithChannelPlatform {
     }
 }
 
+#[derive(Debug, Insertable)]
+#[table_name = "origin_package_build_cache"]
+pub struct NewPackageBuildCache<'a> {
+    pub cache_key:  &'a str,
+    pub target:     BuilderPackageTarget,
+    pub package_id: i64,
+}
+
+/// Maps worker build cache keys to the package each key last produced.
+pub struct PackageBuildCache;
+
+impl PackageBuildCache {
+    /// Records the package built for a cache key. A later build for the same key (for example
+    /// one forced with `no_cache`) replaces the earlier entry.
+    pub fn create(entry: &NewPackageBuildCache, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::insert_into(origin_package_build_cache::table)
+            .values(entry)
+            .on_conflict((origin_package_build_cache::cache_key, origin_package_build_cache::target))
+            .do_update()
+            .set((
+                origin_package_build_cache::package_id
+                    .eq(excluded(origin_package_build_cache::package_id)),
+                origin_package_build_cache::updated_at
+                    .eq(excluded(origin_package_build_cache::updated_at)),
+            ))
+            .execute(conn)
+    }
+
+    pub fn get_package(cache_key: &str,
+                       target: BuilderPackageTarget,
+                       conn: &PgConnection)
+                       -> QueryResult<Package> {
+        Counter::DBCall.increment();
+        origin_package_build_cache::table
+            .inner_join(origin_packages::table)
+            .filter(origin_package_build_cache::cache_key.eq(cache_key))
+            .filter(origin_package_build_cache::target.eq(target))
+            .select(ALL_COLUMNS)
+            .get_result(conn)
+    }
+}
+
+/// A notice that a package should no longer be used, shown to consumers without changing how
+/// the package resolves.
+#[derive(Debug, Serialize, Deserialize, Queryable, Clone)]
+pub struct PackageDeprecation {
+    pub origin: String,
+    pub name: String,
+    pub message: String,
+    pub replacement: Option<String>,
+    #[serde(with = "rfc3339_opt")]
+    pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "origin_package_deprecations"]
+pub struct NewPackageDeprecation<'a> {
+    pub origin:      &'a str,
+    pub name:        &'a str,
+    pub message:     &'a str,
+    pub replacement: Option<&'a str>,
+}
+
+#[derive(DbEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PackageDeprecationOperation {
+    #[serde(rename = "deprecate")]
+    Deprecate,
+    #[serde(rename = "undeprecate")]
+    Undeprecate,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "audit_package_deprecation"]
+pub struct PackageDeprecationAudit<'a> {
+    pub origin:         &'a str,
+    pub name:           &'a str,
+    pub operation:      PackageDeprecationOperation,
+    pub message:        Option<&'a str>,
+    pub replacement:    Option<&'a str>,
+    pub requester_id:   i64,
+    pub requester_name: &'a str,
+}
+
+impl PackageDeprecation {
+    pub fn get(origin: &str, name: &str, conn: &PgConnection) -> QueryResult<PackageDeprecation> {
+        Counter::DBCall.increment();
+        origin_package_deprecations::table.find((origin, name))
+                                          .get_result(conn)
+    }
+
+    /// Deprecations for packages given in `origin/name` form
+    pub fn list(names: &[String], conn: &PgConnection) -> QueryResult<Vec<PackageDeprecation>> {
+        Counter::DBCall.increment();
+        origin_package_deprecations::table
+            .filter(origin_package_deprecations::origin.concat("/")
+                                                       .concat(origin_package_deprecations::name)
+                                                       .eq(any(names)))
+            .get_results(conn)
+    }
+
+    /// Deprecates a package name, replacing any notice already set
+    pub fn set(req: &NewPackageDeprecation,
+               conn: &PgConnection)
+               -> QueryResult<PackageDeprecation> {
+        Counter::DBCall.increment();
+        diesel::insert_into(origin_package_deprecations::table)
+            .values(req)
+            .on_conflict((origin_package_deprecations::origin, origin_package_deprecations::name))
+            .do_update()
+            .set((origin_package_deprecations::message.eq(req.message),
+                  origin_package_deprecations::replacement.eq(req.replacement),
+                  origin_package_deprecations::updated_at.eq(Utc::now().naive_utc())))
+            .get_result(conn)
+    }
+
+    pub fn clear(origin: &str, name: &str, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::delete(origin_package_deprecations::table.find((origin, name))).execute(conn)
+    }
+
+    pub fn short_name(&self) -> String { format!("{}/{}", self.origin, self.name) }
+}
+
+impl<'a> PackageDeprecationAudit<'a> {
+    pub fn audit(req: &PackageDeprecationAudit, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::insert_into(audit_package_deprecation::table).values(req)
+                                                             .execute(conn)
+    }
+}
+
+/// How a stored artifact is encrypted. The checksum is that of the artifact before
+/// encryption, as served on download.
+#[derive(Debug, Queryable, Clone)]
+pub struct PackageEncryption {
+    pub ident:              BuilderPackageIdent,
+    pub target:             BuilderPackageTarget,
+    pub origin:             String,
+    pub key_id:             String,
+    pub wrapped_data_key:   String,
+    pub plaintext_checksum: String,
+    pub created_at:         Option<NaiveDateTime>,
+    pub updated_at:         Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "origin_package_encryption"]
+pub struct NewPackageEncryption<'a> {
+    pub ident:              BuilderPackageIdent,
+    pub target:             BuilderPackageTarget,
+    pub origin:             &'a str,
+    pub key_id:             &'a str,
+    pub wrapped_data_key:   &'a str,
+    pub plaintext_checksum: &'a str,
+}
+
+impl PackageEncryption {
+    pub fn get(ident: &BuilderPackageIdent,
+               target: BuilderPackageTarget,
+               conn: &PgConnection)
+               -> QueryResult<Option<PackageEncryption>> {
+        Counter::DBCall.increment();
+        origin_package_encryption::table.find((ident.to_string(), target.to_string()))
+                                        .get_result(conn)
+                                        .optional()
+    }
+
+    /// Records how an uploaded artifact is encrypted, replacing the record of an artifact it
+    /// overwrote
+    pub fn upsert(req: &NewPackageEncryption, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::insert_into(origin_package_encryption::table)
+            .values(req)
+            .on_conflict((origin_package_encryption::ident, origin_package_encryption::target))
+            .do_update()
+            .set((origin_package_encryption::key_id.eq(req.key_id),
+                  origin_package_encryption::wrapped_data_key.eq(req.wrapped_data_key),
+                  origin_package_encryption::plaintext_checksum.eq(req.plaintext_checksum),
+                  origin_package_encryption::updated_at.eq(Utc::now().naive_utc())))
+            .execute(conn)
+    }
+
+    /// Forgets the encryption of an artifact overwritten by an unencrypted one
+    pub fn delete(ident: &BuilderPackageIdent,
+                  target: BuilderPackageTarget,
+                  conn: &PgConnection)
+                  -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::delete(origin_package_encryption::table.find((ident.to_string(),
+                                                              target.to_string()))).execute(conn)
+    }
+
+    /// The origin's encrypted artifacts whose data key is not wrapped by `key_id`
+    pub fn list_not_under_key(origin: &str,
+                              key_id: &str,
+                              conn: &PgConnection)
+                              -> QueryResult<Vec<PackageEncryption>> {
+        Counter::DBCall.increment();
+        origin_package_encryption::table.filter(origin_package_encryption::origin.eq(origin))
+                                        .filter(origin_package_encryption::key_id.ne(key_id))
+                                        .get_results(conn)
+    }
+
+    /// Replaces the wrapped data key of an artifact after it was re-wrapped by `key_id`
+    pub fn rewrap(&self,
+                  key_id: &str,
+                  wrapped_data_key: &str,
+                  conn: &PgConnection)
+                  -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::update(origin_package_encryption::table.find((self.ident.to_string(),
+                                                              self.target.to_string())))
+            .set((origin_package_encryption::key_id.eq(key_id),
+                  origin_package_encryption::wrapped_data_key.eq(wrapped_data_key),
+                  origin_package_encryption::updated_at.eq(Utc::now().naive_utc())))
+            .execute(conn)
+    }
+}
+
+/// Why a worker refused to promote a package it built: its signature did not verify against a
+/// key of the origin still accepted for signing
+#[derive(Debug, Queryable, Clone)]
+pub struct PackageVerificationFailure {
+    pub ident:      BuilderPackageIdent,
+    pub target:     BuilderPackageTarget,
+    pub origin:     String,
+    pub key_name:   String,
+    pub reason:     String,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "origin_package_verification_failures"]
+pub struct NewPackageVerificationFailure<'a> {
+    pub ident:    BuilderPackageIdent,
+    pub target:   BuilderPackageTarget,
+    pub origin:   &'a str,
+    pub key_name: &'a str,
+    pub reason:   &'a str,
+}
+
+impl PackageVerificationFailure {
+    pub fn get(ident: &BuilderPackageIdent,
+               target: BuilderPackageTarget,
+               conn: &PgConnection)
+               -> QueryResult<Option<PackageVerificationFailure>> {
+        Counter::DBCall.increment();
+        origin_package_verification_failures::table.find((ident.to_string(), target.to_string()))
+                                                   .get_result(conn)
+                                                   .optional()
+    }
+
+    /// Records a failure, replacing the one of an earlier build of the same package
+    pub fn upsert(req: &NewPackageVerificationFailure, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::insert_into(origin_package_verification_failures::table)
+            .values(req)
+            .on_conflict((origin_package_verification_failures::ident,
+                          origin_package_verification_failures::target))
+            .do_update()
+            .set((origin_package_verification_failures::key_name.eq(req.key_name),
+                  origin_package_verification_failures::reason.eq(req.reason),
+                  origin_package_verification_failures::created_at.eq(Utc::now().naive_utc())))
+            .execute(conn)
+    }
+}
+
+/// Where a package fetched through from an upstream Builder came from
+#[derive(Debug, Serialize, Queryable, Clone)]
+pub struct PackageUpstream {
+    #[serde(skip)]
+    pub package_id: i64,
+    pub origin: String,
+    pub name: String,
+    pub target: BuilderPackageTarget,
+    pub upstream_url: String,
+    pub channel: String,
+    #[serde(with = "rfc3339_opt")]
+    pub fetched_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "origin_package_upstreams"]
+pub struct NewPackageUpstream<'a> {
+    pub package_id:   i64,
+    pub origin:       &'a str,
+    pub name:         &'a str,
+    pub target:       BuilderPackageTarget,
+    pub upstream_url: &'a str,
+    pub channel:      &'a str,
+}
+
+impl PackageUpstream {
+    /// Records the provenance of a fetched package, replacing any earlier record
+    pub fn create(req: &NewPackageUpstream, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::insert_into(origin_package_upstreams::table)
+            .values(req)
+            .on_conflict(origin_package_upstreams::package_id)
+            .do_update()
+            .set((origin_package_upstreams::upstream_url.eq(req.upstream_url),
+                  origin_package_upstreams::channel.eq(req.channel),
+                  origin_package_upstreams::fetched_at.eq(Utc::now().naive_utc())))
+            .execute(conn)
+    }
+
+    pub fn get(package_id: i64, conn: &PgConnection) -> QueryResult<PackageUpstream> {
+        Counter::DBCall.increment();
+        origin_package_upstreams::table.find(package_id)
+                                       .get_result(conn)
+    }
+
+    /// The origin, name and target of every package fetched from `upstream_url`
+    pub fn list_names(upstream_url: &str,
+                      conn: &PgConnection)
+                      -> QueryResult<Vec<(String, String, BuilderPackageTarget)>> {
+        Counter::DBCall.increment();
+        origin_package_upstreams::table
+            .filter(origin_package_upstreams::upstream_url.eq(upstream_url))
+            .select((origin_package_upstreams::origin,
+                     origin_package_upstreams::name,
+                     origin_package_upstreams::target))
+            .distinct()
+            .get_results(conn)
+    }
+}
+
+/// Whether a package an upload depends on is in the depot, and whether its name is deprecated
+#[derive(Debug, QueryableByName)]
+pub struct DependencyStatus {
+    #[sql_type = "Text"]
+    pub ident:      String,
+    #[sql_type = "Bool"]
+    pub present:    bool,
+    #[sql_type = "Bool"]
+    pub deprecated: bool,
+}
+
+// A dependency is present when a package of the target it satisfies exists in any channel, and
+// is public or belongs to the origin uploading. It is matched on the origin and name columns,
+// then on the version and release when the dependency names them.
+const DEPENDENCY_STATUS: &str = "
+SELECT deps.ident,
+       EXISTS (SELECT 1 FROM origin_packages
+               WHERE origin_packages.origin = split_part(deps.ident, '/', 1)
+               AND origin_packages.name = split_part(deps.ident, '/', 2)
+               AND origin_packages.target = $2
+               AND (origin_packages.visibility = 'public' OR origin_packages.origin = $3)
+               AND (split_part(deps.ident, '/', 3) = ''
+                    OR origin_packages.ident_array[3] = split_part(deps.ident, '/', 3))
+               AND (split_part(deps.ident, '/', 4) = ''
+                    OR origin_packages.ident_array[4] = split_part(deps.ident, '/', 4)))
+                    AS present,
+       EXISTS (SELECT 1 FROM origin_package_deprecations
+               WHERE origin_package_deprecations.origin = split_part(deps.ident, '/', 1)
+               AND origin_package_deprecations.name = split_part(deps.ident, '/', 2)) AS deprecated
+FROM unnest($1::text[]) AS deps(ident)
+";
+
+impl DependencyStatus {
+    /// The status of each of `deps` for a package of `origin` built for `target`, in one query
+    pub fn list(deps: &[String],
+                target: &str,
+                origin: &str,
+                conn: &PgConnection)
+                -> QueryResult<Vec<DependencyStatus>> {
+        Counter::DBCall.increment();
+        diesel::sql_query(DEPENDENCY_STATUS).bind::<Array<Text>, _>(deps)
+                                            .bind::<Text, _>(target)
+                                            .bind::<Text, _>(origin)
+                                            .load(conn)
+    }
+}
+
+/// The runtime dependencies of a package that were not in the depot, or deprecated, when it
+/// was uploaded
+#[derive(Debug, Serialize, Queryable, Clone)]
+pub struct PackageDependencyReport {
+    #[serde(skip)]
+    pub package_id: i64,
+    pub missing:    Vec<String>,
+    pub deprecated: Vec<String>,
+    #[serde(with = "rfc3339_opt")]
+    pub checked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "origin_package_dependency_reports"]
+pub struct NewPackageDependencyReport<'a> {
+    pub package_id: i64,
+    pub missing:    &'a [String],
+    pub deprecated: &'a [String],
+}
+
+impl PackageDependencyReport {
+    /// Records the report of an upload, replacing the one of an earlier upload of the package
+    pub fn create(req: &NewPackageDependencyReport, conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        diesel::insert_into(origin_package_dependency_reports::table)
+            .values(req)
+            .on_conflict(origin_package_dependency_reports::package_id)
+            .do_update()
+            .set((origin_package_dependency_reports::missing.eq(req.missing),
+                  origin_package_dependency_reports::deprecated.eq(req.deprecated),
+                  origin_package_dependency_reports::checked_at.eq(Utc::now().naive_utc())))
+            .execute(conn)
+    }
+
+    pub fn get(package_id: i64, conn: &PgConnection) -> QueryResult<PackageDependencyReport> {
+        Counter::DBCall.increment();
+        origin_package_dependency_reports::table.find(package_id)
+                                                .get_result(conn)
+    }
+}
+
 fn searchable_ident(ident: &BuilderPackageIdent) -> Vec<String> {
     // https://github.com/rust-lang/rust-clippy/issues/3071U
     #[allow(clippy::redundant_closure)]
@@ -878,6 +1598,7 @@ impl FromArchive for NewPackage {
                         config,
                         checksum: archive.checksum()?,
                         name: ident.name.to_string(),
+                        normalized_name: package_name::normalize(&ident.name),
                         owner_id: 999_999_999_999,
                         visibility: PackageVisibility::Public })
     }
@@ -1000,6 +1721,7 @@ impl Into<PackageIdentWithChannelPlatform> for PackageWithChannelPlatform {
                                           name: self.ident.name.clone(),
                                           version: self.ident.version.clone(),
                                           release: self.ident.release.clone(),
+                                          release_at: release_at(&self.ident.release),
                                           channels: self.channels,
                                           platforms }
     }
@@ -1007,11 +1729,17 @@ impl Into<PackageIdentWithChannelPlatform> for PackageWithChannelPlatform {
 
 impl Into<PackageIdentWithChannelPlatform> for BuilderPackageIdent {
     fn into(self) -> PackageIdentWithChannelPlatform {
-        PackageIdentWithChannelPlatform { origin:    self.origin.clone(),
-                                          name:      self.name.clone(),
-                                          version:   self.version.clone(),
-                                          release:   self.release.clone(),
-                                          channels:  Vec::new(),
-                                          platforms: Vec::new(), }
+        PackageIdentWithChannelPlatform { origin:     self.origin.clone(),
+                                          name:       self.name.clone(),
+                                          version:    self.version.clone(),
+                                          release:    self.release.clone(),
+                                          release_at: release_at(&self.release),
+                                          channels:   Vec::new(),
+                                          platforms:  Vec::new(), }
     }
 }
+
+fn release_at(release: &Option<String>) -> Option<String> {
+    release.as_ref()
+           .and_then(|r| timestamp::release_to_rfc3339(r))
+}
@@ -1,4 +1,5 @@
 use super::db_id_format;
+use crate::bldr_core::timestamp::rfc3339_opt;
 use chrono::NaiveDateTime;
 use diesel::{self,
              pg::PgConnection,
@@ -33,7 +34,9 @@ pub struct ProjectIntegration {
     pub integration_id: i64,
     pub origin: String,
     pub body: String,
+    #[serde(with = "rfc3339_opt")]
     pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
     pub updated_at: Option<NaiveDateTime>,
 }
 
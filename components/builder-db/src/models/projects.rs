@@ -1,16 +1,25 @@
 use super::{db_id_format,
             db_optional_id_format};
+use crate::bldr_core::timestamp::rfc3339_opt;
 use chrono::NaiveDateTime;
 use diesel::{self,
+             dsl::max,
              pg::PgConnection,
-             result::QueryResult,
+             result::{Error,
+                      QueryResult},
+             Connection,
              ExpressionMethods,
              QueryDsl,
              RunQueryDsl};
+use protobuf::RepeatedField;
+use serde_json::{self,
+                 Value};
 
-use crate::{models::package::PackageVisibility,
+use crate::{models::{package::PackageVisibility,
+                     pagination::Paginate},
             protocol::originsrv,
-            schema::project::origin_projects};
+            schema::project::{origin_projects,
+                              project_settings_history}};
 
 use crate::{bldr_core::metrics::CounterMetric,
             metrics::Counter};
@@ -19,51 +28,66 @@ use crate::{bldr_core::metrics::CounterMetric,
 #[table_name = "origin_projects"]
 pub struct Project {
     #[serde(with = "db_id_format")]
-    pub id: i64,
-    pub origin: String,
+    pub id:                     i64,
+    pub origin:                 String,
     #[serde(with = "db_id_format")]
-    pub owner_id: i64,
-    pub package_name: String,
-    pub name: String,
-    pub plan_path: String,
-    pub visibility: PackageVisibility,
-    pub vcs_type: String,
-    pub vcs_data: String,
+    pub owner_id:               i64,
+    pub package_name:           String,
+    pub name:                   String,
+    pub plan_path:              String,
+    pub visibility:             PackageVisibility,
+    pub vcs_type:               String,
+    pub vcs_data:               String,
     #[serde(with = "db_optional_id_format")]
-    pub vcs_installation_id: Option<i64>,
-    pub auto_build: bool,
-    pub created_at: Option<NaiveDateTime>,
-    pub updated_at: Option<NaiveDateTime>,
+    pub vcs_installation_id:    Option<i64>,
+    pub auto_build:             bool,
+    #[serde(with = "rfc3339_opt")]
+    pub created_at:             Option<NaiveDateTime>,
+    #[serde(with = "rfc3339_opt")]
+    pub updated_at:             Option<NaiveDateTime>,
+    pub required_worker_labels: Vec<String>,
+    pub vcs_credential:         Option<String>,
+    #[serde(skip_serializing)]
+    pub webhook_secret:         Option<String>,
+    pub quiet_period_secs:      i32,
 }
 
 #[derive(Insertable)]
 #[table_name = "origin_projects"]
 pub struct NewProject<'a> {
-    pub owner_id:            i64,
-    pub origin:              &'a str,
-    pub name:                &'a str,
-    pub package_name:        &'a str,
-    pub plan_path:           &'a str,
-    pub vcs_type:            &'a str,
-    pub vcs_data:            &'a str,
-    pub vcs_installation_id: Option<i64>,
-    pub visibility:          &'a PackageVisibility,
-    pub auto_build:          bool,
+    pub owner_id:               i64,
+    pub origin:                 &'a str,
+    pub name:                   &'a str,
+    pub package_name:           &'a str,
+    pub plan_path:              &'a str,
+    pub vcs_type:               &'a str,
+    pub vcs_data:               &'a str,
+    pub vcs_installation_id:    Option<i64>,
+    pub visibility:             &'a PackageVisibility,
+    pub auto_build:             bool,
+    pub required_worker_labels: &'a [String],
+    pub vcs_credential:         Option<&'a str>,
+    pub webhook_secret:         Option<&'a str>,
+    pub quiet_period_secs:      i32,
 }
 
 #[derive(AsChangeset)]
 #[table_name = "origin_projects"]
 pub struct UpdateProject<'a> {
-    pub id:                  i64,
-    pub owner_id:            i64,
-    pub origin:              &'a str,
-    pub package_name:        &'a str,
-    pub plan_path:           &'a str,
-    pub vcs_type:            &'a str,
-    pub vcs_data:            &'a str,
-    pub vcs_installation_id: Option<i64>,
-    pub visibility:          &'a PackageVisibility,
-    pub auto_build:          bool,
+    pub id:                     i64,
+    pub owner_id:               i64,
+    pub origin:                 &'a str,
+    pub package_name:           &'a str,
+    pub plan_path:              &'a str,
+    pub vcs_type:               &'a str,
+    pub vcs_data:               &'a str,
+    pub vcs_installation_id:    Option<i64>,
+    pub visibility:             &'a PackageVisibility,
+    pub auto_build:             bool,
+    pub required_worker_labels: &'a [String],
+    pub vcs_credential:         Option<&'a str>,
+    pub webhook_secret:         Option<&'a str>,
+    pub quiet_period_secs:      i32,
 }
 
 impl Project {
@@ -84,10 +108,48 @@ impl Project {
                                                    .get_result(conn)
     }
 
-    pub fn update(project: &UpdateProject, conn: &PgConnection) -> QueryResult<usize> {
+    pub fn update(project: &UpdateProject,
+                  actor_id: i64,
+                  actor_name: &str,
+                  conn: &PgConnection)
+                  -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        conn.transaction::<_, Error, _>(|| {
+                let current = Self::lock(project.id, conn)?;
+                ProjectSettingsHistory::record(&current, actor_id, actor_name, conn)?;
+                diesel::update(origin_projects::table.find(project.id)).set(project)
+                                                                       .execute(conn)
+            })
+    }
+
+    /// Restores the settings saved as `version`, recording the settings it replaces as a new
+    /// history entry.
+    pub fn rollback(id: i64,
+                    version: i32,
+                    actor_id: i64,
+                    actor_name: &str,
+                    conn: &PgConnection)
+                    -> QueryResult<usize> {
         Counter::DBCall.increment();
-        diesel::update(origin_projects::table.find(project.id)).set(project)
+        conn.transaction::<_, Error, _>(|| {
+                let current = Self::lock(id, conn)?;
+                let entry = ProjectSettingsHistory::get(id, version, conn)?;
+                let settings: ProjectSettings = match serde_json::from_value(entry.settings) {
+                    Ok(settings) => settings,
+                    Err(e) => return Err(Error::DeserializationError(Box::new(e))),
+                };
+                ProjectSettingsHistory::record(&current, actor_id, actor_name, conn)?;
+                diesel::update(origin_projects::table.find(id)).set(&settings)
                                                                .execute(conn)
+            })
+    }
+
+    // Takes a row lock on the project for the rest of the transaction so that concurrent
+    // updates write their history entries one after the other
+    fn lock(id: i64, conn: &PgConnection) -> QueryResult<Project> {
+        origin_projects::table.find(id)
+                              .for_update()
+                              .get_result(conn)
     }
 
     pub fn list(origin: &str, conn: &PgConnection) -> QueryResult<Vec<Project>> {
@@ -97,6 +159,165 @@ impl Project {
     }
 }
 
+/// The versioned subset of a project's settings. Integrations and the webhook secret are
+/// deliberately left out since they carry credentials.
+#[derive(AsChangeset, Clone, Debug, Serialize, Deserialize)]
+#[table_name = "origin_projects"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct ProjectSettings {
+    pub plan_path:              String,
+    pub vcs_type:               String,
+    pub vcs_data:               String,
+    pub vcs_installation_id:    Option<i64>,
+    pub visibility:             PackageVisibility,
+    pub auto_build:             bool,
+    #[serde(default)]
+    pub required_worker_labels: Vec<String>,
+    #[serde(default)]
+    pub vcs_credential:         Option<String>,
+    #[serde(default)]
+    pub quiet_period_secs:      i32,
+}
+
+impl<'a> From<&'a Project> for ProjectSettings {
+    fn from(project: &'a Project) -> Self {
+        ProjectSettings { plan_path:              project.plan_path.clone(),
+                          vcs_type:               project.vcs_type.clone(),
+                          vcs_data:               project.vcs_data.clone(),
+                          vcs_installation_id:    project.vcs_installation_id,
+                          visibility:             project.visibility.clone(),
+                          auto_build:             project.auto_build,
+                          required_worker_labels: project.required_worker_labels.clone(),
+                          vcs_credential:         project.vcs_credential.clone(),
+                          quiet_period_secs:      project.quiet_period_secs, }
+    }
+}
+
+/// Number of settings versions kept per project; older entries are pruned on update
+pub const SETTINGS_HISTORY_RETENTION: i32 = 100;
+
+#[derive(Debug, Serialize, Deserialize, Queryable)]
+pub struct ProjectSettingsHistory {
+    #[serde(with = "db_id_format")]
+    pub id:         i64,
+    #[serde(with = "db_id_format")]
+    pub project_id: i64,
+    pub version:    i32,
+    pub settings:   Value,
+    #[serde(with = "db_id_format")]
+    pub actor_id:   i64,
+    pub actor_name: String,
+    #[serde(with = "rfc3339_opt")]
+    pub created_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "project_settings_history"]
+pub struct NewProjectSettingsHistory<'a> {
+    pub project_id: i64,
+    pub version:    i32,
+    pub settings:   Value,
+    pub actor_id:   i64,
+    pub actor_name: &'a str,
+}
+
+pub struct ListProjectSettingsHistory {
+    pub project_id: i64,
+    pub page:       i64,
+    pub limit:      i64,
+}
+
+/// A single field that differs between two versions of a project's settings
+#[derive(Debug, Serialize)]
+pub struct SettingChange {
+    pub field: String,
+    pub from:  Value,
+    pub to:    Value,
+}
+
+impl ProjectSettingsHistory {
+    pub fn get(project_id: i64, version: i32, conn: &PgConnection) -> QueryResult<Self> {
+        Counter::DBCall.increment();
+        project_settings_history::table.filter(project_settings_history::project_id.eq(project_id))
+                                       .filter(project_settings_history::version.eq(version))
+                                       .get_result(conn)
+    }
+
+    /// Lists the history of a project, newest version first
+    pub fn list(lpsh: &ListProjectSettingsHistory,
+                conn: &PgConnection)
+                -> QueryResult<(Vec<Self>, i64)> {
+        Counter::DBCall.increment();
+        project_settings_history::table
+            .filter(project_settings_history::project_id.eq(lpsh.project_id))
+            .order(project_settings_history::version.desc())
+            .paginate(lpsh.page)
+            .per_page(lpsh.limit)
+            .load_and_count_records(conn)
+    }
+
+    // Must run inside a transaction holding the project's row lock (see `Project::lock`) so
+    // version numbers are handed out in the order updates are applied
+    fn record(project: &Project,
+              actor_id: i64,
+              actor_name: &str,
+              conn: &PgConnection)
+              -> QueryResult<()> {
+        let latest: Option<i32> =
+            project_settings_history::table
+                .filter(project_settings_history::project_id.eq(project.id))
+                .select(max(project_settings_history::version))
+                .first(conn)?;
+        let version = latest.unwrap_or(0) + 1;
+
+        let settings = serde_json::to_value(ProjectSettings::from(project))
+            .map_err(|e| Error::SerializationError(Box::new(e)))?;
+
+        diesel::insert_into(project_settings_history::table)
+            .values(&NewProjectSettingsHistory { project_id: project.id,
+                                                 version,
+                                                 settings,
+                                                 actor_id,
+                                                 actor_name })
+            .execute(conn)?;
+
+        diesel::delete(
+            project_settings_history::table
+                .filter(project_settings_history::project_id.eq(project.id))
+                .filter(project_settings_history::version.le(version - SETTINGS_HISTORY_RETENTION)),
+        )
+        .execute(conn)?;
+
+        Ok(())
+    }
+
+    /// Field-level differences between two settings blobs. Fields missing from either side
+    /// (e.g. settings added after an older entry was written) are reported as null.
+    pub fn diff(before: &Value, after: &Value) -> Vec<SettingChange> {
+        let empty = serde_json::Map::new();
+        let before = before.as_object().unwrap_or(&empty);
+        let after = after.as_object().unwrap_or(&empty);
+
+        let mut fields: Vec<&String> = before.keys().chain(after.keys()).collect();
+        fields.sort();
+        fields.dedup();
+
+        fields.into_iter()
+              .filter_map(|field| {
+                  let from = before.get(field).cloned().unwrap_or(Value::Null);
+                  let to = after.get(field).cloned().unwrap_or(Value::Null);
+                  if from == to {
+                      None
+                  } else {
+                      Some(SettingChange { field: field.to_string(),
+                                           from,
+                                           to })
+                  }
+              })
+              .collect()
+    }
+}
+
 impl Into<originsrv::OriginProject> for Project {
     fn into(self) -> originsrv::OriginProject {
         let mut proj = originsrv::OriginProject::new();
@@ -111,7 +332,11 @@ impl Into<originsrv::OriginProject> for Project {
         if let Some(install_id) = self.vcs_installation_id {
             proj.set_vcs_installation_id(install_id as u32);
         }
+        if let Some(credential) = self.vcs_credential {
+            proj.set_vcs_credential(credential);
+        }
         proj.set_auto_build(self.auto_build);
+        proj.set_required_worker_labels(RepeatedField::from_vec(self.required_worker_labels));
         proj
     }
 }
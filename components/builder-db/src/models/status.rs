@@ -0,0 +1,77 @@
+use super::db_id_format;
+use crate::bldr_core::timestamp::{rfc3339,
+                                  rfc3339_opt};
+use chrono::{NaiveDateTime,
+             Utc};
+use diesel::{self,
+             pg::PgConnection,
+             result::QueryResult,
+             ExpressionMethods,
+             QueryDsl,
+             RunQueryDsl};
+
+use crate::{bldr_core::metrics::CounterMetric,
+            metrics::Counter,
+            schema::status::status_banners};
+
+#[derive(DbEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StatusBannerSeverity {
+    #[serde(rename = "info")]
+    Info,
+    #[serde(rename = "warning")]
+    Warning,
+    #[serde(rename = "critical")]
+    Critical,
+}
+
+/// An operator message shown on the public status page until it expires.
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+pub struct StatusBanner {
+    #[serde(with = "db_id_format")]
+    pub id: i64,
+    pub message: String,
+    pub severity: StatusBannerSeverity,
+    #[serde(with = "db_id_format")]
+    pub owner_id: i64,
+    #[serde(with = "rfc3339_opt")]
+    pub created_at: Option<NaiveDateTime>,
+    #[serde(with = "rfc3339")]
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "status_banners"]
+pub struct NewStatusBanner<'a> {
+    pub message:    &'a str,
+    pub severity:   StatusBannerSeverity,
+    pub owner_id:   i64,
+    pub expires_at: NaiveDateTime,
+}
+
+impl StatusBanner {
+    pub fn create(req: &NewStatusBanner, conn: &PgConnection) -> QueryResult<StatusBanner> {
+        Counter::DBCall.increment();
+        diesel::insert_into(status_banners::table).values(req)
+                                                  .get_result(conn)
+    }
+
+    /// The most recently set banner that has not yet expired, if any
+    pub fn current(conn: &PgConnection) -> QueryResult<Option<StatusBanner>> {
+        Counter::DBCall.increment();
+        let mut banners: Vec<StatusBanner> =
+            status_banners::table.filter(status_banners::expires_at.gt(Utc::now().naive_utc()))
+                                 .order(status_banners::created_at.desc())
+                                 .limit(1)
+                                 .get_results(conn)?;
+        Ok(banners.pop())
+    }
+
+    /// Expire every active banner immediately
+    pub fn clear(conn: &PgConnection) -> QueryResult<usize> {
+        Counter::DBCall.increment();
+        let now = Utc::now().naive_utc();
+        diesel::update(status_banners::table.filter(status_banners::expires_at.gt(now)))
+            .set(status_banners::expires_at.eq(now))
+            .execute(conn)
+    }
+}
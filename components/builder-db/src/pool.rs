@@ -18,9 +18,10 @@ use std::{fmt,
           thread,
           time::Duration};
 
-use r2d2;
-use r2d2_postgres::{self,
-                    PostgresConnectionManager,
+use postgres;
+use r2d2::{self,
+           ManageConnection};
+use r2d2_postgres::{PostgresConnectionManager,
                     TlsMode};
 
 use crate::{config::DataStoreCfg,
@@ -29,7 +30,30 @@ use crate::{config::DataStoreCfg,
 
 #[derive(Clone)]
 pub struct Pool {
-    inner: r2d2::Pool<PostgresConnectionManager>,
+    inner: r2d2::Pool<ConnectionManager>,
+}
+
+/// Manages Postgres connections whose session settings are put back to their defaults as they
+/// are checked out. Statements bounded by a deadline set a `statement_timeout` on the session, a
+/// connection that cannot be reset is discarded rather than handed out with it.
+#[derive(Debug)]
+pub struct ConnectionManager(PostgresConnectionManager);
+
+impl ManageConnection for ConnectionManager {
+    type Connection = postgres::Connection;
+    type Error = postgres::Error;
+
+    fn connect(&self) -> std::result::Result<postgres::Connection, postgres::Error> {
+        self.0.connect()
+    }
+
+    fn is_valid(&self,
+                conn: &mut postgres::Connection)
+                -> std::result::Result<(), postgres::Error> {
+        conn.batch_execute("RESET statement_timeout")
+    }
+
+    fn has_broken(&self, conn: &mut postgres::Connection) -> bool { self.0.has_broken(conn) }
 }
 
 impl fmt::Debug for Pool {
@@ -45,9 +69,13 @@ impl Pool {
             let manager =
                 PostgresConnectionManager::new(config, TlsMode::None).expect("Failed to connect \
                                                                               to Postgres");
+            let manager = ConnectionManager(manager);
             match r2d2::Pool::builder()
                 .max_size(config.pool_size)
                 .connection_timeout(Duration::from_secs(config.connection_timeout_sec))
+                // Connections severed by a failover, or left with a statement timeout, are
+                // discarded instead of handed out
+                .test_on_check_out(true)
                 .build(manager)
             {
                 Ok(pool) => return Pool { inner: pool },
@@ -60,18 +88,29 @@ impl Pool {
         }
     }
 
-    pub fn get(&self) -> Result<r2d2::PooledConnection<r2d2_postgres::PostgresConnectionManager>> {
+    pub fn get(&self) -> Result<r2d2::PooledConnection<ConnectionManager>> {
         let conn = self.inner.get().map_err(Error::ConnectionTimeout)?;
         Ok(conn)
     }
+
+    /// Checks out a connection, waiting at most `timeout` rather than the configured
+    /// connection timeout
+    pub fn get_within(&self,
+                      timeout: Duration)
+                      -> Result<r2d2::PooledConnection<ConnectionManager>> {
+        let conn = self.inner
+                       .get_timeout(timeout)
+                       .map_err(Error::ConnectionTimeout)?;
+        Ok(conn)
+    }
 }
 
 impl Deref for Pool {
-    type Target = r2d2::Pool<PostgresConnectionManager>;
+    type Target = r2d2::Pool<ConnectionManager>;
 
-    fn deref(&self) -> &r2d2::Pool<PostgresConnectionManager> { &self.inner }
+    fn deref(&self) -> &r2d2::Pool<ConnectionManager> { &self.inner }
 }
 
 impl DerefMut for Pool {
-    fn deref_mut(&mut self) -> &mut r2d2::Pool<PostgresConnectionManager> { &mut self.inner }
+    fn deref_mut(&mut self) -> &mut r2d2::Pool<ConnectionManager> { &mut self.inner }
 }
@@ -5,6 +5,10 @@ table! {
         name -> Text,
         created_at -> Nullable<Timestamptz>,
         updated_at -> Nullable<Timestamptz>,
+        search_origins -> Array<Text>,
+        security_notifications -> Bool,
+        default_origin -> Nullable<Text>,
+        last_authenticated_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -14,5 +18,46 @@ table! {
         account_id -> BigInt,
         token -> Text,
         created_at -> Nullable<Timestamptz>,
+        expires_at -> Nullable<Timestamptz>,
+        expiry_notified_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use crate::models::account::AccountSecurityEventKindMapping;
+    use diesel::sql_types::{BigInt, Text, Nullable, Timestamptz};
+    account_security_events (id) {
+        id -> BigInt,
+        account_id -> BigInt,
+        kind -> AccountSecurityEventKindMapping,
+        token_id -> Nullable<BigInt>,
+        ip -> Nullable<Text>,
+        origin -> Nullable<Text>,
+        actor_id -> Nullable<BigInt>,
+        actor_name -> Nullable<Text>,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    account_known_ips (account_id, ip) {
+        account_id -> BigInt,
+        ip -> Text,
+        first_seen_at -> Nullable<Timestamptz>,
+        last_seen_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use crate::models::account::AccountExportStateMapping;
+    use diesel::sql_types::{BigInt, Text, Nullable, Timestamptz};
+    account_exports (id) {
+        id -> BigInt,
+        account_id -> BigInt,
+        state -> AccountExportStateMapping,
+        object_key -> Nullable<Text>,
+        created_at -> Nullable<Timestamptz>,
+        completed_at -> Nullable<Timestamptz>,
+        expires_at -> Nullable<Timestamptz>,
     }
 }
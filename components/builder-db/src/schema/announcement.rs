@@ -0,0 +1,29 @@
+table! {
+    use crate::models::{announcement::AnnouncementAudienceMapping,
+                        status::StatusBannerSeverityMapping};
+    use diesel::sql_types::{Array, BigInt, Text, Nullable, Timestamptz};
+    announcements (id) {
+        id -> BigInt,
+        message -> Text,
+        severity -> StatusBannerSeverityMapping,
+        audience -> AnnouncementAudienceMapping,
+        origins -> Array<Text>,
+        owner_id -> BigInt,
+        starts_at -> Timestamptz,
+        ends_at -> Timestamptz,
+        created_at -> Nullable<Timestamptz>,
+        updated_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::{BigInt, Nullable, Timestamptz};
+    announcement_dismissals (account_id, announcement_id) {
+        account_id -> BigInt,
+        announcement_id -> BigInt,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
+joinable!(announcement_dismissals -> announcements (announcement_id));
+allow_tables_to_appear_in_same_query!(announcements, announcement_dismissals);
@@ -10,6 +10,7 @@ table! {
         requester_name -> Text,
         created_at -> Nullable<Timestamptz>,
         origin -> Text,
+        package_target -> Nullable<Text>,
     }
 }
 
@@ -28,3 +29,96 @@ table! {
         origin -> Text,
     }
 }
+
+table! {
+    use crate::models::keys::KeyRotationTriggerMapping;
+    use diesel::sql_types::{BigInt, Text, Nullable, Timestamptz};
+    audit_origin_key_rotation (origin, revision) {
+        origin -> Text,
+        previous_revision -> Nullable<Text>,
+        revision -> Text,
+        trigger -> KeyRotationTriggerMapping,
+        requester_id -> BigInt,
+        requester_name -> Text,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use crate::models::package::PackageDeprecationOperationMapping;
+    use diesel::sql_types::{BigInt, Text, Nullable, Timestamptz};
+    audit_package_deprecation (origin, name) {
+        origin -> Text,
+        name -> Text,
+        operation -> PackageDeprecationOperationMapping,
+        message -> Nullable<Text>,
+        replacement -> Nullable<Text>,
+        requester_id -> BigInt,
+        requester_name -> Text,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use crate::models::channel::PackagePinOperationMapping;
+    use diesel::sql_types::{BigInt, Text, Nullable, Timestamptz};
+    audit_package_pin (origin, channel, name, target) {
+        origin -> Text,
+        channel -> Text,
+        name -> Text,
+        target -> Text,
+        ident -> Text,
+        operation -> PackagePinOperationMapping,
+        requester_id -> BigInt,
+        requester_name -> Text,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::{Array, BigInt, Text, Nullable, Timestamptz};
+    audit_upload_policy_override (origin, package_ident, target) {
+        origin -> Text,
+        package_ident -> Text,
+        target -> Text,
+        violations -> Array<Text>,
+        requester_id -> BigInt,
+        requester_name -> Text,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use crate::models::{announcement::{AnnouncementAudienceMapping, AnnouncementOperationMapping},
+                        status::StatusBannerSeverityMapping};
+    use diesel::sql_types::{Array, BigInt, Text, Nullable, Timestamptz};
+    audit_announcement (announcement_id, created_at) {
+        announcement_id -> BigInt,
+        operation -> AnnouncementOperationMapping,
+        message -> Text,
+        severity -> StatusBannerSeverityMapping,
+        audience -> AnnouncementAudienceMapping,
+        origins -> Array<Text>,
+        starts_at -> Timestamptz,
+        ends_at -> Timestamptz,
+        requester_id -> BigInt,
+        requester_name -> Text,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use crate::models::channel::{ChannelOperationMapping, ChannelTypeMapping};
+    use diesel::sql_types::{Array, BigInt, Text, Nullable, Timestamptz};
+    audit_channel (origin, channel) {
+        origin -> Text,
+        channel -> Text,
+        channel_type -> ChannelTypeMapping,
+        operation -> ChannelOperationMapping,
+        expires_at -> Nullable<Timestamptz>,
+        package_ids -> Array<BigInt>,
+        requester_id -> BigInt,
+        requester_name -> Text,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
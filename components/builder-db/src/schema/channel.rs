@@ -1,4 +1,6 @@
 table! {
+    use crate::models::channel::ChannelTypeMapping;
+    use diesel::sql_types::{BigInt, Bool, Text, Nullable, Timestamptz};
     origin_channels (id) {
         id -> BigInt,
         owner_id -> BigInt,
@@ -6,6 +8,9 @@ table! {
         created_at -> Nullable<Timestamptz>,
         updated_at -> Nullable<Timestamptz>,
         origin -> Text,
+        rolling -> Bool,
+        channel_type -> ChannelTypeMapping,
+        expires_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -32,3 +37,17 @@ allow_tables_to_appear_in_same_query!(origin_channels,
                                       origin_packages,
                                       origin_packages_with_version_array,
                                       origins);
+
+table! {
+    use diesel::sql_types::{BigInt, Text, Nullable, Timestamptz};
+    origin_channel_package_pins (origin, channel, name, target) {
+        origin -> Text,
+        channel -> Text,
+        name -> Text,
+        target -> Text,
+        ident -> Text,
+        pinned_by_id -> BigInt,
+        pinned_by_name -> Text,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
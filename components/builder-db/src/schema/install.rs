@@ -0,0 +1,21 @@
+table! {
+    install_stats (day, ident, target) {
+        day -> Date,
+        ident -> Text,
+        origin -> Nullable<Text>,
+        name -> Nullable<Text>,
+        target -> Text,
+        successes -> BigInt,
+        failures -> BigInt,
+    }
+}
+
+table! {
+    install_sites (origin, name, target, day, site_id) {
+        day -> Date,
+        origin -> Text,
+        name -> Text,
+        target -> Text,
+        site_id -> Text,
+    }
+}
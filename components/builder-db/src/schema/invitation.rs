@@ -2,11 +2,13 @@ table! {
     origin_invitations {
         id -> BigInt,
         origin -> Text,
-        account_id -> BigInt,
-        account_name -> Text,
+        account_id -> Nullable<BigInt>,
+        account_name -> Nullable<Text>,
         owner_id -> BigInt,
         ignored -> Bool,
         created_at -> Nullable<Timestamptz>,
         updated_at -> Nullable<Timestamptz>,
+        email -> Nullable<Text>,
+        expires_at -> Nullable<Timestamptz>,
     }
 }
@@ -24,11 +24,23 @@ table! {
         sync_count -> Integer,
         worker -> Nullable<Text>,
         target -> Text,
+        required_worker_labels -> Array<Text>,
+        no_cache -> Bool,
+        peak_workspace_bytes -> Nullable<BigInt>,
+        artifact_bytes -> Nullable<BigInt>,
+        failure_excerpt -> Nullable<Text>,
+        progress_phase -> Nullable<Text>,
+        progress_step -> Nullable<Text>,
+        progress_phase_secs -> Nullable<BigInt>,
+        progress_updated_at -> Nullable<Timestamptz>,
+        vcs_ref -> Nullable<Text>,
+        leased_at -> Nullable<Timestamptz>,
+        lease_expires_at -> Nullable<Timestamptz>,
     }
 }
 
 table! {
-    use diesel::sql_types::{BigInt, Text, Nullable, Timestamptz};
+    use diesel::sql_types::{Array, BigInt, Bool, Integer, Text, Nullable, Timestamptz};
 
     groups (id) {
         id -> BigInt,
@@ -37,6 +49,13 @@ table! {
         target -> Text,
         created_at -> Nullable<Timestamptz>,
         updated_at -> Nullable<Timestamptz>,
+        no_cache -> Bool,
+        max_concurrency -> Integer,
+        failure_policy -> Text,
+        frozen_until -> Nullable<Timestamptz>,
+        vcs_ref -> Nullable<Text>,
+        superseded_refs -> Array<Text>,
+        hold_until -> Nullable<Timestamptz>,
     }
 }
 
@@ -67,3 +86,15 @@ table! {
         updated_at -> Nullable<Timestamptz>,
     }
 }
+
+table! {
+    use diesel::sql_types::{BigInt, Text, Nullable, Timestamptz};
+
+    job_events (id) {
+        id -> BigInt,
+        job_id -> BigInt,
+        event -> Text,
+        detail -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
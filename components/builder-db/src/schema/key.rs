@@ -9,6 +9,7 @@ table! {
         created_at -> Nullable<Timestamptz>,
         updated_at -> Nullable<Timestamptz>,
         origin -> Text,
+        expires_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -53,3 +54,13 @@ table! {
         origin -> Text,
     }
 }
+
+table! {
+    origin_key_rotation(origin) {
+        origin -> Text,
+        enabled -> Bool,
+        interval_days -> Integer,
+        created_at -> Nullable<Timestamptz>,
+        updated_at -> Nullable<Timestamptz>,
+    }
+}
@@ -3,15 +3,19 @@
 #![allow(proc_macro_derive_resolution_fallback)]
 
 pub mod account;
+pub mod announcement;
 pub mod audit;
 pub mod channel;
+pub mod install;
 pub mod integration;
 pub mod invitation;
 pub mod jobs;
 pub mod key;
 pub mod member;
+pub mod notification;
 pub mod origin;
 pub mod package;
 pub mod project;
 pub mod project_integration;
 pub mod secrets;
+pub mod status;
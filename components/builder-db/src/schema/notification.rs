@@ -0,0 +1,20 @@
+table! {
+    notifications (id) {
+        id -> BigInt,
+        account_id -> BigInt,
+        category -> Text,
+        message -> Text,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    email_notifications (id) {
+        id -> BigInt,
+        email -> Text,
+        category -> Text,
+        message -> Text,
+        created_at -> Nullable<Timestamptz>,
+        sent_at -> Nullable<Timestamptz>,
+    }
+}
@@ -33,3 +33,27 @@ table! {
         package_count -> BigInt,
     }
 }
+
+table! {
+    use diesel::sql_types::{Array, Bool, Text, Nullable, Timestamptz};
+    origin_upload_policies (origin) {
+        origin -> Text,
+        require_license -> Bool,
+        allowed_licenses -> Array<Text>,
+        blocked_licenses -> Array<Text>,
+        require_description -> Bool,
+        created_at -> Nullable<Timestamptz>,
+        updated_at -> Nullable<Timestamptz>,
+        strict_dependency_validation -> Bool,
+    }
+}
+
+table! {
+    use diesel::sql_types::{Text, Nullable, Timestamptz};
+    origin_artifact_encryption (origin) {
+        origin -> Text,
+        key_id -> Text,
+        created_at -> Nullable<Timestamptz>,
+        updated_at -> Nullable<Timestamptz>,
+    }
+}
@@ -75,6 +75,7 @@ table! {
         updated_at -> Nullable<Timestamptz>,
         origin -> Text,
         ident_vector -> TsVector,
+        normalized_name -> Text,
     }
 }
 
@@ -92,8 +93,85 @@ table! {
     }
 }
 
+table! {
+    use diesel::sql_types::{BigInt, Text, Nullable, Timestamptz};
+    origin_package_build_cache (cache_key, target) {
+        cache_key -> Text,
+        target -> Text,
+        package_id -> BigInt,
+        created_at -> Nullable<Timestamptz>,
+        updated_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::{Text, Nullable, Timestamptz};
+    origin_package_deprecations (origin, name) {
+        origin -> Text,
+        name -> Text,
+        message -> Text,
+        replacement -> Nullable<Text>,
+        created_at -> Nullable<Timestamptz>,
+        updated_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::{BigInt, Text, Nullable, Timestamptz};
+    origin_package_upstreams (package_id) {
+        package_id -> BigInt,
+        origin -> Text,
+        name -> Text,
+        target -> Text,
+        upstream_url -> Text,
+        channel -> Text,
+        fetched_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::{Array, BigInt, Text, Nullable, Timestamptz};
+    origin_package_dependency_reports (package_id) {
+        package_id -> BigInt,
+        missing -> Array<Text>,
+        deprecated -> Array<Text>,
+        checked_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::{Text, Nullable, Timestamptz};
+    origin_package_encryption (ident, target) {
+        ident -> Text,
+        target -> Text,
+        origin -> Text,
+        key_id -> Text,
+        wrapped_data_key -> Text,
+        plaintext_checksum -> Text,
+        created_at -> Nullable<Timestamptz>,
+        updated_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::{Text, Nullable, Timestamptz};
+    origin_package_verification_failures (ident, target) {
+        ident -> Text,
+        target -> Text,
+        origin -> Text,
+        key_name -> Text,
+        reason -> Text,
+        created_at -> Nullable<Timestamptz>,
+    }
+}
+
 use super::origin::{origins,
                     origins_with_stats};
 
 joinable!(origin_packages -> origins (origin));
 joinable!(origin_packages -> origins_with_stats (origin));
+joinable!(origin_package_build_cache -> origin_packages (package_id));
+joinable!(origin_package_upstreams -> origin_packages (package_id));
+joinable!(origin_package_dependency_reports -> origin_packages (package_id));
+
+allow_tables_to_appear_in_same_query!(origin_package_build_cache, origin_packages);
@@ -1,5 +1,5 @@
 table! {
-    use diesel::sql_types::{Bool, BigInt, Text, Nullable, Timestamptz};
+    use diesel::sql_types::{Array, Bool, BigInt, Integer, Text, Nullable, Timestamptz};
     use crate::models::package::PackageVisibilityMapping;
 
     origin_projects (id) {
@@ -16,5 +16,23 @@ table! {
         auto_build -> Bool,
         created_at -> Nullable<Timestamptz>,
         updated_at -> Nullable<Timestamptz>,
+        required_worker_labels -> Array<Text>,
+        vcs_credential -> Nullable<Text>,
+        webhook_secret -> Nullable<Text>,
+        quiet_period_secs -> Integer,
+    }
+}
+
+table! {
+    use diesel::sql_types::{BigInt, Integer, Jsonb, Text, Nullable, Timestamptz};
+
+    project_settings_history (id) {
+        id -> BigInt,
+        project_id -> BigInt,
+        version -> Integer,
+        settings -> Jsonb,
+        actor_id -> BigInt,
+        actor_name -> Text,
+        created_at -> Nullable<Timestamptz>,
     }
 }
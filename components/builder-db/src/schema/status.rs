@@ -0,0 +1,12 @@
+table! {
+    use crate::models::status::StatusBannerSeverityMapping;
+    use diesel::sql_types::{BigInt, Text, Nullable, Timestamptz};
+    status_banners (id) {
+        id -> BigInt,
+        message -> Text,
+        severity -> StatusBannerSeverityMapping,
+        owner_id -> BigInt,
+        created_at -> Nullable<Timestamptz>,
+        expires_at -> Timestamptz,
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Account exports and the activity they archive, against a live database. See `tests/db/README.md`
+//! for running them.
+
+mod common;
+
+use chrono::{Duration,
+             Utc};
+use diesel::{pg::PgConnection,
+             result::{DatabaseErrorKind,
+                      Error},
+             sql_types::BigInt,
+             RunQueryDsl};
+
+use habitat_builder_db::models::account::{Account,
+                                          AccountActivity,
+                                          AccountExport,
+                                          AccountExportState,
+                                          NewAccount};
+
+fn setup() -> (PgConnection, Account) {
+    let conn = common::connection();
+
+    let account = Account::create(&NewAccount { email: "bobo@example.com",
+                                                name:  "bobo", },
+                                  &conn).unwrap();
+    (conn, account)
+}
+
+#[test]
+#[ignore]
+fn accounts_have_one_export_pending_at_a_time() {
+    let (conn, account) = setup();
+    let export = AccountExport::create(account.id, &conn).unwrap();
+    assert_eq!(export.state, AccountExportState::Pending);
+
+    match AccountExport::create(account.id, &conn) {
+        Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => (),
+        other => panic!("Expected a unique violation, got {:?}", other),
+    }
+
+    let expires_at = (Utc::now() + Duration::hours(1)).naive_utc();
+    AccountExport::mark_ready(export.id, "account-exports/1/1.json", expires_at, &conn).unwrap();
+    AccountExport::create(account.id, &conn).unwrap();
+}
+
+#[test]
+#[ignore]
+fn exports_are_taken_oldest_first_and_only_while_pending() {
+    let (conn, account) = setup();
+    let export = AccountExport::create(account.id, &conn).unwrap();
+
+    let next = AccountExport::next_pending(&conn).unwrap().unwrap();
+    assert_eq!(next.id, export.id);
+
+    AccountExport::mark_failed(export.id, &conn).unwrap();
+    assert!(AccountExport::next_pending(&conn).unwrap().is_none());
+}
+
+#[test]
+#[ignore]
+fn stale_and_expired_exports_are_found() {
+    let (conn, account) = setup();
+    let export = AccountExport::create(account.id, &conn).unwrap();
+    let now = Utc::now().naive_utc();
+
+    assert_eq!(AccountExport::fail_stale(now - Duration::minutes(60), &conn).unwrap(),
+               0);
+    assert_eq!(AccountExport::fail_stale(now + Duration::minutes(1), &conn).unwrap(),
+               1);
+    let failed = AccountExport::get(export.id, account.id, &conn).unwrap();
+    assert_eq!(failed.state, AccountExportState::Failed);
+
+    let ready = AccountExport::create(account.id, &conn).unwrap();
+    AccountExport::mark_ready(ready.id, "account-exports/1/2.json", now, &conn).unwrap();
+    let expired = AccountExport::list_expired(now + Duration::minutes(1), &conn).unwrap();
+    let ids: Vec<i64> = expired.iter().map(|e| e.id).collect();
+    assert_eq!(ids, vec![ready.id]);
+    assert!(AccountExport::list_expired(now - Duration::minutes(1), &conn).unwrap()
+                                                                          .is_empty());
+
+    AccountExport::delete(ready.id, &conn).unwrap();
+    assert!(AccountExport::get(ready.id, account.id, &conn).is_err());
+}
+
+#[test]
+#[ignore]
+fn exports_are_only_read_by_their_account() {
+    let (conn, account) = setup();
+    let other = Account::create(&NewAccount { email: "mystique@example.com",
+                                              name:  "mystique", },
+                                &conn).unwrap();
+    let export = AccountExport::create(account.id, &conn).unwrap();
+    assert!(AccountExport::get(export.id, other.id, &conn).is_err());
+}
+
+#[test]
+#[ignore]
+fn activity_of_groups_without_an_origin_is_listed() {
+    let (conn, account) = setup();
+    diesel::sql_query("INSERT INTO audit_package_group (channel, package_ids, operation, \
+                       trigger, requester_id, requester_name, group_id) VALUES ('stable', '{}', \
+                       'promote', 'builder_ui', $1, 'bobo', 42)").bind::<BigInt, _>(account.id)
+                                                                 .execute(&conn)
+                                                                 .unwrap();
+
+    let activity = AccountActivity::list(account.id, &conn).unwrap();
+    assert_eq!(activity.len(), 1);
+    assert_eq!(activity[0].kind, "package_group");
+    assert_eq!(activity[0].origin, None);
+    assert_eq!(activity[0].subject, "42");
+}
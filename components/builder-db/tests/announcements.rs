@@ -0,0 +1,118 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Announcements and their dismissals against a live database. See `tests/db/README.md` for running
+//! them.
+
+mod common;
+
+use chrono::{Duration,
+             NaiveDateTime,
+             Utc};
+use diesel::{pg::PgConnection,
+             result::Error::NotFound};
+
+use habitat_builder_db::models::{announcement::{Announcement,
+                                                AnnouncementAudience,
+                                                AnnouncementDismissal,
+                                                NewAnnouncement},
+                                 status::StatusBannerSeverity};
+
+const OWNER_ID: i64 = 1;
+const ACCOUNT_ID: i64 = 7;
+
+// An announcement showing from `starts` to `ends` hours after `now`
+fn announce(conn: &PgConnection, now: NaiveDateTime, starts: i64, ends: i64) -> Announcement {
+    Announcement::create(&NewAnnouncement { message:   "Maintenance tonight",
+                                            severity:  StatusBannerSeverity::Info,
+                                            audience:  AnnouncementAudience::Everyone,
+                                            origins:   vec![],
+                                            owner_id:  OWNER_ID,
+                                            starts_at: now + Duration::hours(starts),
+                                            ends_at:   now + Duration::hours(ends), },
+                         conn).unwrap()
+}
+
+fn ids(announcements: &[Announcement]) -> Vec<i64> { announcements.iter().map(|a| a.id).collect() }
+
+#[test]
+#[ignore]
+fn active_announcements_are_those_showing_now() {
+    let conn = common::connection();
+    let now = Utc::now().naive_utc();
+    announce(&conn, now, -48, -24);
+    let earlier = announce(&conn, now, -2, 1);
+    let later = announce(&conn, now, -1, 1);
+    announce(&conn, now, 1, 2);
+    // An announcement stops showing the moment it ends
+    announce(&conn, now, -1, 0);
+
+    let active = Announcement::active(now, &conn).unwrap();
+    assert_eq!(ids(&active), vec![later.id, earlier.id]);
+    assert_eq!(Announcement::list(&conn).unwrap().len(), 5);
+}
+
+#[test]
+#[ignore]
+fn announcements_are_expired_once() {
+    let conn = common::connection();
+    let now = Utc::now().naive_utc();
+    let showing = announce(&conn, now, -1, 1);
+    let ended = announce(&conn, now, -48, -24);
+
+    let expired = Announcement::expire(showing.id, &conn).unwrap().unwrap();
+    assert!(expired.ends_at < showing.ends_at);
+    assert!(Announcement::active(Utc::now().naive_utc(), &conn).unwrap()
+                                                               .is_empty());
+
+    // Nothing changes for those that ended already
+    assert!(Announcement::expire(showing.id, &conn).unwrap().is_none());
+    assert!(Announcement::expire(ended.id, &conn).unwrap().is_none());
+    assert_eq!(Announcement::get(ended.id, &conn).unwrap().ends_at,
+               ended.ends_at);
+}
+
+#[test]
+#[ignore]
+fn pruning_deletes_ended_announcements_and_their_dismissals() {
+    let conn = common::connection();
+    let now = Utc::now().naive_utc();
+    let ended = announce(&conn, now, -48, -24);
+    let showing = announce(&conn, now, -1, 1);
+    AnnouncementDismissal::dismiss(ACCOUNT_ID, ended.id, &conn).unwrap();
+    AnnouncementDismissal::dismiss(ACCOUNT_ID, showing.id, &conn).unwrap();
+
+    assert_eq!(Announcement::prune(now, &conn).unwrap(), 1);
+    assert_eq!(Announcement::get(ended.id, &conn).unwrap_err(), NotFound);
+    assert_eq!(ids(&Announcement::list(&conn).unwrap()), vec![showing.id]);
+    assert_eq!(AnnouncementDismissal::list(ACCOUNT_ID, &conn).unwrap(),
+               vec![showing.id]);
+    assert_eq!(Announcement::prune(now, &conn).unwrap(), 0);
+}
+
+#[test]
+#[ignore]
+fn dismissals_are_per_account() {
+    let conn = common::connection();
+    let now = Utc::now().naive_utc();
+    let showing = announce(&conn, now, -1, 1);
+
+    AnnouncementDismissal::dismiss(ACCOUNT_ID, showing.id, &conn).unwrap();
+    // Dismissing it again is a no-op
+    AnnouncementDismissal::dismiss(ACCOUNT_ID, showing.id, &conn).unwrap();
+    assert_eq!(AnnouncementDismissal::list(ACCOUNT_ID, &conn).unwrap(),
+               vec![showing.id]);
+    assert!(AnnouncementDismissal::list(OWNER_ID, &conn).unwrap()
+                                                        .is_empty());
+}
@@ -0,0 +1,113 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The promotions feed of a channel against a live database. See `tests/db/README.md` for running
+//! them.
+
+mod common;
+
+use std::str::FromStr;
+
+use diesel::pg::PgConnection;
+
+use habitat_builder_db::models::{channel::{Channel,
+                                           PackageChannelAudit,
+                                           PackageChannelOperation,
+                                           PackageChannelTrigger},
+                                 origin::{NewOrigin,
+                                          Origin},
+                                 package::{BuilderPackageIdent,
+                                           BuilderPackageTarget,
+                                           NewPackage,
+                                           Package,
+                                           PackageVisibility}};
+use habitat_core::{package::{PackageIdent,
+                             PackageTarget},
+                   ChannelIdent};
+
+const OWNER_ID: i64 = 1;
+const ZLIB: &str = "core/zlib/1.2.11/20190115003728";
+const TARGETS: &[&str] = &["x86_64-linux", "x86_64-windows"];
+
+fn setup() -> PgConnection {
+    let conn = common::connection();
+
+    Origin::create(&NewOrigin { name: "core",
+                                owner_id: OWNER_ID,
+                                default_package_visibility: &PackageVisibility::Public, },
+                   &conn).unwrap();
+    for target in TARGETS {
+        Package::create(&new_package(ZLIB, target), &conn).unwrap();
+    }
+    conn
+}
+
+fn new_package(ident: &str, target: &str) -> NewPackage {
+    let ident = PackageIdent::from_str(ident).unwrap();
+    NewPackage { origin:          ident.origin.clone(),
+                 owner_id:        OWNER_ID,
+                 name:            ident.name.clone(),
+                 normalized_name: ident.name.clone(),
+                 ident_array:     ident.to_string().split('/').map(str::to_string).collect(),
+                 ident:           BuilderPackageIdent(ident),
+                 checksum:        String::from("checksum"),
+                 manifest:        String::new(),
+                 config:          String::new(),
+                 target:          BuilderPackageTarget(PackageTarget::from_str(target).unwrap()),
+                 deps:            vec![],
+                 tdeps:           vec![],
+                 build_deps:      vec![],
+                 build_tdeps:     vec![],
+                 exposes:         vec![],
+                 visibility:      PackageVisibility::Public, }
+}
+
+fn promote(conn: &PgConnection, target: Option<&str>) {
+    let target = target.map(|t| BuilderPackageTarget(PackageTarget::from_str(t).unwrap()));
+    let audit = PackageChannelAudit { package_ident:  BuilderPackageIdent(ZLIB.parse().unwrap()),
+                                      channel:        "stable",
+                                      operation:      PackageChannelOperation::Promote,
+                                      trigger:        PackageChannelTrigger::BuilderUi,
+                                      requester_id:   OWNER_ID,
+                                      requester_name: "bobo",
+                                      origin:         "core",
+                                      package_target: target, };
+    PackageChannelAudit::audit(&audit, conn).unwrap();
+}
+
+fn promoted_targets(conn: &PgConnection) -> Vec<String> {
+    Channel::list_promotions("core",
+                             &ChannelIdent::from("stable"),
+                             &[PackageVisibility::Public],
+                             conn).unwrap()
+                                  .into_iter()
+                                  .map(|release| release.target)
+                                  .collect()
+}
+
+#[test]
+#[ignore]
+fn promotions_are_listed_for_their_target() {
+    let conn = setup();
+    promote(&conn, Some("x86_64-windows"));
+    assert_eq!(promoted_targets(&conn), vec!["x86_64-windows"]);
+}
+
+#[test]
+#[ignore]
+fn promotions_without_a_target_are_listed_once() {
+    let conn = setup();
+    promote(&conn, None);
+    assert_eq!(promoted_targets(&conn), vec!["x86_64-linux"]);
+}
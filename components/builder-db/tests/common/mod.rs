@@ -0,0 +1,33 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Setup shared by the database tests of builder-db and builder-api. See `tests/db/README.md`
+//! for starting the test database and running them.
+
+use diesel::{pg::PgConnection,
+             Connection};
+
+use habitat_builder_db::{config::DataStoreCfg,
+                         migration};
+
+/// A connection to the test database, migrated inside a test transaction that is rolled back
+/// when it is dropped.
+pub fn connection() -> PgConnection {
+    let cfg = DataStoreCfg { password: Some("hab".to_string()),
+                             ..Default::default() };
+    let conn = PgConnection::establish(&cfg.to_string()).expect("test database is running");
+    conn.begin_test_transaction().unwrap();
+    migration::setup(&conn).unwrap();
+    conn
+}
@@ -0,0 +1,82 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Account default origins against a live database. See `tests/db/README.md` for running them.
+
+mod common;
+
+use diesel::pg::PgConnection;
+
+use habitat_builder_db::models::{account::{Account,
+                                           NewAccount},
+                                 origin::{NewOrigin,
+                                          Origin,
+                                          OriginMember},
+                                 package::PackageVisibility};
+
+fn setup() -> (PgConnection, Account) {
+    let conn = common::connection();
+
+    let account = Account::create(&NewAccount { email: "bobo@example.com",
+                                                name:  "bobo", },
+                                  &conn).unwrap();
+    for origin in &["acme", "core"] {
+        Origin::create(&NewOrigin { name: origin,
+                                    owner_id: account.id,
+                                    default_package_visibility: &PackageVisibility::Public, },
+                       &conn).unwrap();
+    }
+    (conn, account)
+}
+
+fn default_origin(account: &Account, conn: &PgConnection) -> Option<String> {
+    Account::get_by_id(account.id, conn).unwrap().default_origin
+}
+
+#[test]
+#[ignore]
+fn default_origin_is_set_and_cleared() {
+    let (conn, account) = setup();
+    assert_eq!(default_origin(&account, &conn), None);
+
+    Account::update_default_origin(account.id as u64, Some("acme"), &conn).unwrap();
+    assert_eq!(default_origin(&account, &conn), Some("acme".to_string()));
+
+    Account::update_default_origin(account.id as u64, None, &conn).unwrap();
+    assert_eq!(default_origin(&account, &conn), None);
+}
+
+#[test]
+#[ignore]
+fn leaving_the_default_origin_clears_it() {
+    let (conn, account) = setup();
+    Account::update_default_origin(account.id as u64, Some("acme"), &conn).unwrap();
+
+    // Leaving another origin keeps it
+    OriginMember::delete("core", "bobo", &conn).unwrap();
+    assert_eq!(default_origin(&account, &conn), Some("acme".to_string()));
+
+    OriginMember::delete("acme", "bobo", &conn).unwrap();
+    assert_eq!(default_origin(&account, &conn), None);
+}
+
+#[test]
+#[ignore]
+fn deleting_the_default_origin_clears_it() {
+    let (conn, account) = setup();
+    Account::update_default_origin(account.id as u64, Some("acme"), &conn).unwrap();
+
+    Origin::delete("acme", &conn).unwrap();
+    assert_eq!(default_origin(&account, &conn), None);
+}
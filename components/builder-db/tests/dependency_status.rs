@@ -0,0 +1,149 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The dependency check of uploads against a live database. See `tests/db/README.md` for running
+//! them.
+
+mod common;
+
+use std::str::FromStr;
+
+use diesel::pg::PgConnection;
+
+use habitat_builder_db::models::{origin::{NewOrigin,
+                                          Origin},
+                                 package::{BuilderPackageIdent,
+                                           BuilderPackageTarget,
+                                           DependencyStatus,
+                                           NewPackage,
+                                           NewPackageDeprecation,
+                                           Package,
+                                           PackageDeprecation,
+                                           PackageVisibility}};
+use habitat_core::package::{PackageIdent,
+                            PackageTarget};
+
+const OWNER_ID: i64 = 1;
+
+fn setup() -> PgConnection {
+    let conn = common::connection();
+
+    for origin in &["acme", "core"] {
+        Origin::create(&NewOrigin { name: origin,
+                                    owner_id: OWNER_ID,
+                                    default_package_visibility: &PackageVisibility::Public, },
+                       &conn).unwrap();
+    }
+    create(&conn,
+           "core/glibc/2.27/20190115002733",
+           PackageVisibility::Public);
+    create(&conn,
+           "core/openssl/1.0.2t/20191004170122",
+           PackageVisibility::Public);
+    create(&conn,
+           "acme/secret/1.0.0/20191001000000",
+           PackageVisibility::Private);
+    conn
+}
+
+fn create(conn: &PgConnection, ident: &str, visibility: PackageVisibility) {
+    let ident = PackageIdent::from_str(ident).unwrap();
+    let package =
+        NewPackage { origin: ident.origin.clone(),
+                     owner_id: OWNER_ID,
+                     name: ident.name.clone(),
+                     normalized_name: ident.name.clone(),
+                     ident_array: ident.to_string().split('/').map(str::to_string).collect(),
+                     ident: BuilderPackageIdent(ident),
+                     checksum: String::from("checksum"),
+                     manifest: String::new(),
+                     config: String::new(),
+                     target:
+                         BuilderPackageTarget(PackageTarget::from_str("x86_64-linux").unwrap()),
+                     deps: vec![],
+                     tdeps: vec![],
+                     build_deps: vec![],
+                     build_tdeps: vec![],
+                     exposes: vec![],
+                     visibility };
+    Package::create(&package, conn).unwrap();
+}
+
+// The idents of `deps` the depot has, as seen by an upload of `origin`
+fn present(conn: &PgConnection, origin: &str, deps: &[&str]) -> Vec<String> {
+    let deps: Vec<String> = deps.iter().map(|d| d.to_string()).collect();
+    DependencyStatus::list(&deps, "x86_64-linux", origin, conn).unwrap()
+                                                               .into_iter()
+                                                               .filter(|s| s.present)
+                                                               .map(|s| s.ident)
+                                                               .collect()
+}
+
+#[test]
+#[ignore]
+fn dependencies_are_matched_on_as_much_of_the_ident_as_they_name() {
+    let conn = setup();
+    let found = present(&conn,
+                        "core",
+                        &["core/glibc",
+                          "core/glibc/2.27",
+                          "core/glibc/2.27/20190115002733",
+                          "core/glibc/2.28",
+                          "core/glibc/2.27/20190101000000",
+                          "core/glib",
+                          "core/glibc2"]);
+    assert_eq!(found,
+               vec!["core/glibc",
+                    "core/glibc/2.27",
+                    "core/glibc/2.27/20190115002733"]);
+}
+
+#[test]
+#[ignore]
+fn private_packages_are_only_present_to_their_origin() {
+    let conn = setup();
+    assert!(present(&conn, "core", &["acme/secret"]).is_empty());
+    assert_eq!(present(&conn, "acme", &["acme/secret"]),
+               vec!["acme/secret"]);
+}
+
+#[test]
+#[ignore]
+fn packages_of_other_targets_are_missing() {
+    let conn = setup();
+    let deps = vec!["core/glibc".to_string()];
+    let statuses = DependencyStatus::list(&deps, "x86_64-windows", "core", &conn).unwrap();
+    assert_eq!(statuses.len(), 1);
+    assert!(!statuses[0].present);
+}
+
+#[test]
+#[ignore]
+fn deprecated_names_are_flagged() {
+    let conn = setup();
+    PackageDeprecation::set(&NewPackageDeprecation { origin:      "core",
+                                                     name:        "openssl",
+                                                     message:     "Unmaintained",
+                                                     replacement: None, },
+                            &conn).unwrap();
+    let deps = vec!["core/openssl/1.0.2t/20191004170122".to_string(),
+                    "core/glibc".to_string()];
+    let deprecated: Vec<String> =
+        DependencyStatus::list(&deps, "x86_64-linux", "core", &conn).unwrap()
+                                                                    .into_iter()
+                                                                    .filter(|s| s.deprecated)
+                                                                    .map(|s| s.ident)
+                                                                    .collect();
+    assert_eq!(deprecated, vec!["core/openssl/1.0.2t/20191004170122"]);
+}
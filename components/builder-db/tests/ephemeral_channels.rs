@@ -0,0 +1,119 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Expiry of ephemeral channels against a live database. See `tests/db/README.md` for running them.
+
+mod common;
+
+use chrono::{Duration,
+             NaiveDate,
+             NaiveDateTime};
+use diesel::pg::PgConnection;
+
+use habitat_builder_db::models::{channel::{Channel,
+                                           ChannelType,
+                                           CreateChannel},
+                                 origin::{NewOrigin,
+                                          Origin},
+                                 package::PackageVisibility};
+
+const OWNER_ID: i64 = 1;
+
+fn setup() -> PgConnection {
+    let conn = common::connection();
+
+    for origin in &["acme", "core"] {
+        Origin::create(&NewOrigin { name: origin,
+                                    owner_id: OWNER_ID,
+                                    default_package_visibility: &PackageVisibility::Public, },
+                       &conn).unwrap();
+    }
+    conn
+}
+
+// Whole seconds, so that times read back compare equal
+fn now() -> NaiveDateTime { NaiveDate::from_ymd(2019, 10, 21).and_hms(12, 0, 0) }
+
+// An ephemeral channel of `origin` expiring `hours` from `now`, in the past when negative
+fn ephemeral(conn: &PgConnection,
+             origin: &str,
+             name: &str,
+             now: NaiveDateTime,
+             hours: i64)
+             -> Channel {
+    Channel::create_ephemeral(&CreateChannel { name,
+                                               owner_id: OWNER_ID,
+                                               origin },
+                              now + Duration::hours(hours),
+                              conn).unwrap()
+}
+
+fn names(channels: &[Channel]) -> Vec<String> {
+    channels.iter()
+            .map(|c| format!("{}/{}", c.origin, c.name))
+            .collect()
+}
+
+#[test]
+#[ignore]
+fn expired_channels_of_every_origin_are_listed_oldest_first() {
+    let conn = setup();
+    let now = now();
+    ephemeral(&conn, "core", "feature-a", now, -1);
+    ephemeral(&conn, "acme", "feature-b", now, -2);
+    ephemeral(&conn, "core", "feature-c", now, 24);
+    let standard = Channel::create(&CreateChannel { name:     "beta",
+                                                    owner_id: OWNER_ID,
+                                                    origin:   "core", },
+                                   &conn).unwrap();
+    assert_eq!(standard.channel_type, ChannelType::Standard);
+
+    let expired = Channel::list_expired(now, &conn).unwrap();
+    assert_eq!(names(&expired), vec!["acme/feature-b", "core/feature-a"]);
+    assert!(expired.iter()
+                   .all(|c| c.channel_type == ChannelType::Ephemeral));
+}
+
+#[test]
+#[ignore]
+fn renewed_channels_are_no_longer_expired() {
+    let conn = setup();
+    let now = now();
+    let channel = ephemeral(&conn, "core", "feature-a", now, -1);
+
+    let renewed = Channel::renew(channel.id, now + Duration::days(14), &conn).unwrap();
+    assert_eq!(renewed.expires_at, Some(now + Duration::days(14)));
+    assert!(Channel::list_expired(now, &conn).unwrap().is_empty());
+    assert!(Channel::lock_expired(channel.id, now, &conn).unwrap()
+                                                         .is_none());
+}
+
+#[test]
+#[ignore]
+fn only_expired_ephemeral_channels_are_locked_for_expiry() {
+    let conn = setup();
+    let now = now();
+    let expired = ephemeral(&conn, "core", "feature-a", now, -1);
+    let live = ephemeral(&conn, "core", "feature-b", now, 1);
+    let standard = Channel::create(&CreateChannel { name:     "beta",
+                                                    owner_id: OWNER_ID,
+                                                    origin:   "core", },
+                                   &conn).unwrap();
+
+    let locked = |channel: &Channel| Channel::lock_expired(channel.id, now, &conn).unwrap();
+    assert_eq!(locked(&expired).map(|c| c.name),
+               Some("feature-a".to_string()));
+    assert!(locked(&live).is_none());
+    assert!(locked(&standard).is_none());
+}
@@ -0,0 +1,99 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Origin member activity against a live database. See `tests/db/README.md` for running them.
+
+mod common;
+
+use std::str::FromStr;
+
+use diesel::pg::PgConnection;
+
+use habitat_builder_db::models::{account::{Account,
+                                           NewAccount},
+                                 channel::{PackageChannelAudit,
+                                           PackageChannelOperation,
+                                           PackageChannelTrigger},
+                                 origin::{NewOrigin,
+                                          Origin,
+                                          OriginMember,
+                                          OriginMemberActivity},
+                                 package::{BuilderPackageIdent,
+                                           PackageVisibility}};
+use habitat_core::package::PackageIdent;
+
+fn setup() -> (PgConnection, Account, Account) {
+    let conn = common::connection();
+
+    let owner = Account::create(&NewAccount { email: "bobo@example.com",
+                                              name:  "bobo", },
+                                &conn).unwrap();
+    let member = Account::create(&NewAccount { email: "mystique@example.com",
+                                               name:  "mystique", },
+                                 &conn).unwrap();
+    for origin in &["acme", "core"] {
+        Origin::create(&NewOrigin { name: origin,
+                                    owner_id: owner.id,
+                                    default_package_visibility: &PackageVisibility::Public, },
+                       &conn).unwrap();
+    }
+    OriginMember::add("acme", member.id, &conn).unwrap();
+    (conn, owner, member)
+}
+
+fn promote(conn: &PgConnection, origin: &str, requester: &Account) {
+    let ident = PackageIdent::from_str(&format!("{}/redis/4.0.14/20190319155852", origin)).unwrap();
+    let audit = PackageChannelAudit { package_ident: BuilderPackageIdent(ident),
+                                      channel: "stable",
+                                      operation: PackageChannelOperation::Promote,
+                                      trigger: PackageChannelTrigger::BuilderUi,
+                                      requester_id: requester.id,
+                                      requester_name: &requester.name,
+                                      origin,
+                                      package_target: None };
+    PackageChannelAudit::audit(&audit, conn).unwrap();
+}
+
+#[test]
+#[ignore]
+fn members_without_activity_are_listed() {
+    let (conn, ..) = setup();
+    let activity = OriginMemberActivity::list("acme", &conn).unwrap();
+
+    let names: Vec<&str> = activity.iter().map(|m| m.account_name.as_str()).collect();
+    assert_eq!(names, vec!["bobo", "mystique"]);
+    assert_eq!(activity[0].role, "owner");
+    assert_eq!(activity[1].role, "member");
+    assert!(activity.iter().all(|m| {
+                               m.last_authenticated_at.is_none()
+                               && m.last_upload_at.is_none()
+                               && m.last_promotion_at.is_none()
+                           }));
+}
+
+#[test]
+#[ignore]
+fn activity_is_that_in_the_origin() {
+    let (conn, owner, member) = setup();
+    Account::record_authentication(member.id as u64, &conn).unwrap();
+    promote(&conn, "acme", &member);
+    promote(&conn, "core", &owner);
+
+    let activity = OriginMemberActivity::list("acme", &conn).unwrap();
+    assert_eq!(activity[0].account_name, "bobo");
+    assert_eq!(activity[0].last_promotion_at, None);
+    assert_eq!(activity[1].account_name, "mystique");
+    assert!(activity[1].last_authenticated_at.is_some());
+    assert!(activity[1].last_promotion_at.is_some());
+}
@@ -0,0 +1,118 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Package deprecation notices against a live database. See `tests/db/README.md` for running them.
+
+mod common;
+
+use diesel::{pg::PgConnection,
+             result::Error::NotFound};
+
+use habitat_builder_db::models::{origin::{NewOrigin,
+                                          Origin},
+                                 package::{NewPackageDeprecation,
+                                           PackageDeprecation,
+                                           PackageDeprecationAudit,
+                                           PackageDeprecationOperation,
+                                           PackageVisibility}};
+
+const OWNER_ID: i64 = 1;
+
+fn setup() -> PgConnection {
+    let conn = common::connection();
+
+    for origin in &["acme", "core"] {
+        Origin::create(&NewOrigin { name: origin,
+                                    owner_id: OWNER_ID,
+                                    default_package_visibility: &PackageVisibility::Public, },
+                       &conn).unwrap();
+    }
+    conn
+}
+
+fn deprecate(conn: &PgConnection,
+             origin: &str,
+             name: &str,
+             message: &str,
+             replacement: Option<&str>)
+             -> PackageDeprecation {
+    PackageDeprecation::set(&NewPackageDeprecation { origin,
+                                                     name,
+                                                     message,
+                                                     replacement },
+                            conn).unwrap()
+}
+
+#[test]
+#[ignore]
+fn setting_a_deprecation_replaces_the_notice() {
+    let conn = setup();
+    assert_eq!(PackageDeprecation::get("core", "nginx", &conn).unwrap_err(),
+               NotFound);
+
+    deprecate(&conn, "core", "nginx", "Unmaintained", None);
+    let updated = deprecate(&conn, "core", "nginx", "Moved", Some("core/nginx2"));
+    assert_eq!(updated.message, "Moved");
+    assert_eq!(updated.replacement, Some("core/nginx2".to_string()));
+
+    let stored = PackageDeprecation::get("core", "nginx", &conn).unwrap();
+    assert_eq!(stored.message, "Moved");
+    assert_eq!(stored.short_name(), "core/nginx");
+}
+
+#[test]
+#[ignore]
+fn deprecations_are_listed_by_origin_and_name() {
+    let conn = setup();
+    deprecate(&conn, "core", "nginx", "Unmaintained", None);
+    deprecate(&conn, "acme", "redis", "Unmaintained", None);
+
+    let names = vec!["core/nginx".to_string(),
+                     "core/redis".to_string(),
+                     "acme/nginx".to_string()];
+    let listed: Vec<String> = PackageDeprecation::list(&names, &conn).unwrap()
+                                                                     .iter()
+                                                                     .map(|d| d.short_name())
+                                                                     .collect();
+    assert_eq!(listed, vec!["core/nginx"]);
+    assert!(PackageDeprecation::list(&[], &conn).unwrap().is_empty());
+}
+
+#[test]
+#[ignore]
+fn clearing_a_deprecation_removes_it_once() {
+    let conn = setup();
+    deprecate(&conn, "core", "nginx", "Unmaintained", None);
+
+    assert_eq!(PackageDeprecation::clear("core", "nginx", &conn).unwrap(),
+               1);
+    assert_eq!(PackageDeprecation::clear("core", "nginx", &conn).unwrap(),
+               0);
+    assert_eq!(PackageDeprecation::get("core", "nginx", &conn).unwrap_err(),
+               NotFound);
+}
+
+#[test]
+#[ignore]
+fn deprecations_are_audited() {
+    let conn = setup();
+    let audit = PackageDeprecationAudit { origin:         "core",
+                                          name:           "nginx",
+                                          operation:      PackageDeprecationOperation::Deprecate,
+                                          message:        Some("Unmaintained"),
+                                          replacement:    None,
+                                          requester_id:   OWNER_ID,
+                                          requester_name: "bobo", };
+    assert_eq!(PackageDeprecationAudit::audit(&audit, &conn).unwrap(), 1);
+}
@@ -0,0 +1,221 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Package search ranking against a live database. See `tests/db/README.md` for running them.
+
+mod common;
+
+use std::str::FromStr;
+
+use diesel::pg::PgConnection;
+
+use builder_core::package_name;
+use habitat_builder_db::models::{origin::{NewOrigin,
+                                          Origin},
+                                 package::{BuilderPackageIdent,
+                                           BuilderPackageTarget,
+                                           NewPackage,
+                                           Package,
+                                           PackageVisibility,
+                                           SearchPackages}};
+use habitat_core::package::{PackageIdent,
+                            PackageTarget};
+
+const OWNER_ID: i64 = 1;
+
+// Every package below matches a search for "nginx": all but nginx/tools by name, and that
+// one by its origin. The two core packages of libxml2 normalize to the same name.
+const FIXTURE: &[&str] = &["aaa/nginx/1.15.6/20190101000000",
+                           "acme/nginx/1.15.6/20190101000000",
+                           "core/nginx/1.15.6/20190101000000",
+                           "nginx/tools/0.1.0/20190101000000",
+                           "zeta/nginx/1.15.6/20190101000000",
+                           "core/redis/4.0.14/20190101000000",
+                           "core/libxml2/2.9.9/20190101000000",
+                           "core/lib-xml2/2.9.9/20190101000000",
+                           "acme/libxslt/1.1.33/20190101000000"];
+
+fn setup() -> PgConnection {
+    let conn = common::connection();
+
+    for origin in &["aaa", "acme", "core", "nginx", "zeta"] {
+        Origin::create(&NewOrigin { name: origin,
+                                    owner_id: OWNER_ID,
+                                    default_package_visibility: &PackageVisibility::Public, },
+                       &conn).unwrap();
+    }
+    for ident in FIXTURE {
+        Package::create(&new_package(ident), &conn).unwrap();
+    }
+    conn
+}
+
+fn new_package(ident: &str) -> NewPackage {
+    let ident = PackageIdent::from_str(ident).unwrap();
+    NewPackage { origin:          ident.origin.clone(),
+                 owner_id:        OWNER_ID,
+                 name:            ident.name.clone(),
+                 normalized_name: package_name::normalize(&ident.name),
+                 ident_array:     ident.to_string().split('/').map(str::to_string).collect(),
+                 ident:           BuilderPackageIdent(ident),
+                 checksum:        String::from("checksum"),
+                 manifest:        String::new(),
+                 config:          String::new(),
+                 target:
+                     BuilderPackageTarget(PackageTarget::from_str("x86_64-linux").unwrap()),
+                 deps:            vec![],
+                 tdeps:           vec![],
+                 build_deps:      vec![],
+                 build_tdeps:     vec![],
+                 exposes:         vec![],
+                 visibility:      PackageVisibility::Public, }
+}
+
+fn search(conn: &PgConnection,
+          preferred: &[&str],
+          origins: &[&str],
+          distinct: bool)
+          -> Vec<String> {
+    let sp = SearchPackages { query:             String::from("nginx"),
+                              account_id:        None,
+                              page:              1,
+                              limit:             50,
+                              origins:           origins.iter().map(|o| o.to_string()).collect(),
+                              preferred_origins: preferred.iter().map(|o| o.to_string()).collect(),
+                              exact_name:        String::from("nginx"),
+                              normalized_name:   None,
+                              query_origin:      None, };
+    let result = if distinct {
+        Package::search_distinct(sp, conn)
+    } else {
+        Package::search(sp, conn)
+    };
+    let (packages, _) = result.unwrap();
+    packages.iter()
+            .map(|p| format!("{}/{}", p.origin, p.name))
+            .collect()
+}
+
+#[test]
+#[ignore]
+fn without_preference_results_are_alphabetical() {
+    let conn = setup();
+    assert_eq!(search(&conn, &[], &[], false),
+               vec!["aaa/nginx",
+                    "acme/nginx",
+                    "core/nginx",
+                    "nginx/tools",
+                    "zeta/nginx"]);
+}
+
+#[test]
+#[ignore]
+fn preferred_origins_rank_first_in_priority_order() {
+    let conn = setup();
+    assert_eq!(search(&conn, &["core", "acme"], &[], false),
+               vec!["core/nginx",
+                    "acme/nginx",
+                    "aaa/nginx",
+                    "nginx/tools",
+                    "zeta/nginx"]);
+    assert_eq!(search(&conn, &["acme", "core"], &[], false),
+               vec!["acme/nginx",
+                    "core/nginx",
+                    "aaa/nginx",
+                    "nginx/tools",
+                    "zeta/nginx"]);
+}
+
+#[test]
+#[ignore]
+fn only_exact_name_matches_are_boosted() {
+    let conn = setup();
+    assert_eq!(search(&conn, &["nginx", "zeta"], &[], false),
+               vec!["zeta/nginx",
+                    "aaa/nginx",
+                    "acme/nginx",
+                    "core/nginx",
+                    "nginx/tools"]);
+}
+
+#[test]
+#[ignore]
+fn origins_filter_scopes_results() {
+    let conn = setup();
+    assert_eq!(search(&conn, &["zeta"], &["acme", "zeta"], false),
+               vec!["zeta/nginx", "acme/nginx"]);
+    assert_eq!(search(&conn, &[], &["core"], false), vec!["core/nginx"]);
+}
+
+#[test]
+#[ignore]
+fn distinct_search_is_ranked() {
+    let conn = setup();
+    // Past the preferred origins distinct results are ordered by name, then origin
+    assert_eq!(search(&conn, &["core", "acme"], &[], true),
+               vec!["core/nginx",
+                    "acme/nginx",
+                    "aaa/nginx",
+                    "zeta/nginx",
+                    "nginx/tools"]);
+}
+
+// A default search for `query`, of the normalized `name` in the `origin` it names
+fn search_normalized(conn: &PgConnection,
+                     query: &str,
+                     name: &str,
+                     origin: Option<&str>)
+                     -> Vec<String> {
+    let sp = SearchPackages { query:             query.to_string(),
+                              account_id:        None,
+                              page:              1,
+                              limit:             50,
+                              origins:           vec![],
+                              preferred_origins: vec![],
+                              exact_name:        name.to_string(),
+                              normalized_name:   Some(name.to_string()),
+                              query_origin:      origin.map(str::to_string), };
+    let (packages, _) = Package::search(sp, conn).unwrap();
+    packages.iter()
+            .map(|p| format!("{}/{}", p.origin, p.name))
+            .collect()
+}
+
+#[test]
+#[ignore]
+fn names_match_whatever_their_case_and_punctuation() {
+    let conn = setup();
+    // Both packages normalizing to the name are found
+    assert_eq!(search_normalized(&conn, "libXML2", "libxml2", None),
+               vec!["core/lib-xml2", "core/libxml2"]);
+    assert_eq!(search_normalized(&conn, "core & lib_xml", "libxml", Some("core")),
+               vec!["core/lib-xml2", "core/libxml2"]);
+    assert!(search_normalized(&conn, "acme & libxml2", "libxml2", Some("acme")).is_empty());
+    // Normalized names match from their start
+    assert!(search_normalized(&conn, "ibxml", "ibxml", None).is_empty());
+}
+
+#[test]
+#[ignore]
+fn near_names_of_the_origin_are_suggested() {
+    let conn = setup();
+    let suggest = |origin, name| {
+        Package::suggest_names(origin, name, vec![PackageVisibility::Public], &conn).unwrap()
+    };
+    assert_eq!(suggest("core", "libXML"), vec!["lib-xml2", "libxml2"]);
+    assert_eq!(suggest("core", "ngnix"), Vec::<String>::new());
+    assert_eq!(suggest("core", "nginxx"), vec!["nginx"]);
+    assert_eq!(suggest("core", "nginx"), Vec::<String>::new());
+    assert_eq!(suggest("acme", "libxml2"), Vec::<String>::new());
+}
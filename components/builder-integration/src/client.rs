@@ -0,0 +1,177 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A client for the parts of the public api the tests drive. Every request is made as one of
+//! the users the api authenticates without GitHub when `HAB_FUNC_TEST` is set.
+
+use std::{fs,
+          path::Path};
+
+use reqwest::{self,
+              Method,
+              RequestBuilder,
+              Response};
+use serde_json::{self,
+                 Value};
+use sha2::{Digest,
+           Sha256};
+
+use crate::{error::{Error,
+                    Result},
+            fixtures::{Package,
+                       TARGET}};
+
+pub struct ApiClient {
+    inner: reqwest::Client,
+    url:   String,
+    token: String,
+}
+
+impl ApiClient {
+    pub fn new(url: &str, token: &str) -> Self {
+        ApiClient { inner: reqwest::Client::new(),
+                    url:   url.to_string(),
+                    token: token.to_string(), }
+    }
+
+    pub fn create_origin(&self, origin: &str) -> Result<()> {
+        let body = json!({ "name": origin });
+        self.send(self.request(Method::POST, "/depot/origins").json(&body))?;
+        Ok(())
+    }
+
+    pub fn upload_origin_key(&self, origin: &str, revision: &str, key: &Path) -> Result<()> {
+        let path = format!("/depot/origins/{}/keys/{}", origin, revision);
+        self.send(self.request(Method::POST, &path).body(fs::read(key)?))?;
+        Ok(())
+    }
+
+    pub fn upload_origin_secret_key(&self, origin: &str, revision: &str, key: &Path) -> Result<()> {
+        let path = format!("/depot/origins/{}/secret_keys/{}", origin, revision);
+        self.send(self.request(Method::POST, &path).body(fs::read(key)?))?;
+        Ok(())
+    }
+
+    /// Uploads a package's hart, with the checksum the api verifies it against.
+    pub fn upload_package(&self, package: &Package) -> Result<()> {
+        let body = fs::read(package.path())?;
+        let checksum = hex::encode(Sha256::digest(&body));
+        let path = format!("/depot/pkgs/{}", package.ident());
+        let req = self.request(Method::POST, &path)
+                      .query(&[("checksum", checksum.as_str()), ("target", TARGET)])
+                      .body(body);
+        self.send(req)?;
+        Ok(())
+    }
+
+    /// Creates the origin's project. The api stubs out the GitHub lookup under test and always
+    /// names the project `<origin>/testapp`.
+    pub fn create_project(&self, origin: &str) -> Result<()> {
+        let body = json!({
+            "origin": origin,
+            "plan_path": "plan.sh",
+            "installation_id": 56940,
+            "repo_id": 114_932_712,
+            "auto_build": true
+        });
+        self.send(self.request(Method::POST, "/projects").json(&body))?;
+        Ok(())
+    }
+
+    /// Sets how long builds triggered by a push wait for further pushes to the origin's project
+    pub fn set_quiet_period(&self, origin: &str, secs: u32) -> Result<()> {
+        let body = json!({
+            "plan_path": "plan.sh",
+            "installation_id": 56940,
+            "repo_id": 114_932_712,
+            "auto_build": true,
+            "quiet_period_secs": secs
+        });
+        let path = format!("/projects/{}/testapp", origin);
+        self.send(self.request(Method::PUT, &path).json(&body))?;
+        Ok(())
+    }
+
+    /// Schedules a build of the package, returning the id of the new job group.
+    pub fn schedule(&self, origin: &str, name: &str) -> Result<u64> {
+        let path = format!("/depot/pkgs/schedule/{}/{}", origin, name);
+        let group = self.send_json(self.request(Method::POST, &path))?;
+        group_id(&group)
+    }
+
+    pub fn group(&self, group_id: u64) -> Result<Value> {
+        let path = format!("/depot/pkgs/schedule/{}", group_id);
+        self.send_json(self.request(Method::GET, &path))
+    }
+
+    /// Promotes every package the group built to the channel.
+    pub fn promote_group(&self, group_id: u64, channel: &str) -> Result<()> {
+        let path = format!("/jobs/group/{}/promote/{}", group_id, channel);
+        let body = json!({ "idents": [] });
+        self.send(self.request(Method::POST, &path).json(&body))?;
+        Ok(())
+    }
+
+    pub fn promote_package(&self, package: &Package, channel: &str) -> Result<()> {
+        let path = format!("/depot/channels/{}/{}/pkgs/{}/{}/{}/promote",
+                           package.origin, channel, package.name, package.version, package.release);
+        let req = self.request(Method::PUT, &path)
+                      .query(&[("target", TARGET)]);
+        self.send(req)?;
+        Ok(())
+    }
+
+    /// The body of a GET, as sent, for tests that compare responses
+    pub fn get_text(&self, path: &str) -> Result<String> {
+        let mut res = self.send(self.request(Method::GET, path))?;
+        Ok(res.text()?)
+    }
+
+    pub fn cancel_group(&self, group_id: u64) -> Result<()> {
+        let path = format!("/jobs/group/{}/cancel", group_id);
+        self.send(self.request(Method::POST, &path))?;
+        Ok(())
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.inner
+            .request(method, &format!("{}{}", self.url, path))
+            .bearer_auth(&self.token)
+    }
+
+    fn send(&self, req: RequestBuilder) -> Result<Response> {
+        let mut res = req.send()?;
+        if res.status().is_success() {
+            Ok(res)
+        } else {
+            let body = res.text().unwrap_or_default();
+            Err(Error::HttpResponse(res.status(), body))
+        }
+    }
+
+    fn send_json(&self, req: RequestBuilder) -> Result<Value> {
+        let mut res = self.send(req)?;
+        Ok(res.json()?)
+    }
+}
+
+// Group ids are serialized as strings so that they survive JavaScript clients
+fn group_id(group: &Value) -> Result<u64> {
+    group["id"].as_str()
+               .and_then(|id| id.parse().ok())
+               .ok_or_else(|| {
+                   Error::HttpResponse(reqwest::StatusCode::OK,
+                                       serde_json::to_string(group).unwrap_or_default())
+               })
+}
@@ -0,0 +1,112 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{error,
+          fmt,
+          io};
+
+use postgres;
+use reqwest;
+use zmq;
+
+use crate::{bldr_core,
+            protocol};
+
+#[derive(Debug)]
+pub enum Error {
+    Compose(String),
+    HttpClient(reqwest::Error),
+    HttpResponse(reqwest::StatusCode, String),
+    IO(io::Error),
+    MissingBinary(String),
+    Postgres(postgres::Error),
+    Protocol(protocol::ProtocolError),
+    Rpc(bldr_core::error::Error),
+    S3(String),
+    Startup(String),
+    Timeout(String),
+    Zmq(zmq::Error),
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match *self {
+            Error::Compose(ref e) => format!("docker-compose failed, {}", e),
+            Error::HttpClient(ref e) => format!("{}", e),
+            Error::HttpResponse(ref code, ref response) => {
+                format!("Received a non-2xx response, status={}, response={}",
+                        code, response)
+            }
+            Error::IO(ref e) => format!("{}", e),
+            Error::MissingBinary(ref e) => {
+                format!("Unable to find {}, build it first or point the matching BLDR_*_BIN \
+                         variable at it",
+                        e)
+            }
+            Error::Postgres(ref e) => format!("{}", e),
+            Error::Protocol(ref e) => format!("{}", e),
+            Error::Rpc(ref e) => format!("jobsrv request failed, {}", e),
+            Error::S3(ref e) => format!("Object store request failed, {}", e),
+            Error::Startup(ref e) => format!("Unable to start the servers, {}", e),
+            Error::Timeout(ref e) => format!("Timed out waiting for {}", e),
+            Error::Zmq(ref e) => format!("{}", e),
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Compose(_) => "docker-compose failed.",
+            Error::HttpClient(ref err) => err.description(),
+            Error::HttpResponse(..) => "Non-2xx HTTP response.",
+            Error::IO(ref err) => err.description(),
+            Error::MissingBinary(_) => "Service binary not found.",
+            Error::Postgres(ref err) => err.description(),
+            Error::Protocol(ref err) => err.description(),
+            Error::Rpc(ref err) => err.description(),
+            Error::S3(_) => "Object store request failed.",
+            Error::Startup(_) => "Unable to start the servers.",
+            Error::Timeout(_) => "Timed out.",
+            Error::Zmq(ref err) => err.description(),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error { Error::IO(err) }
+}
+
+impl From<postgres::Error> for Error {
+    fn from(err: postgres::Error) -> Error { Error::Postgres(err) }
+}
+
+impl From<bldr_core::error::Error> for Error {
+    fn from(err: bldr_core::error::Error) -> Error { Error::Rpc(err) }
+}
+
+impl From<protocol::ProtocolError> for Error {
+    fn from(err: protocol::ProtocolError) -> Error { Error::Protocol(err) }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Error { Error::HttpClient(err) }
+}
+
+impl From<zmq::Error> for Error {
+    fn from(err: zmq::Error) -> Error { Error::Zmq(err) }
+}
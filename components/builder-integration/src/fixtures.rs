@@ -0,0 +1,91 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fixtures shared with the api's functional tests: the `neurosis` origin's signing keys and
+//! harts of its `testapp` package, signed with them.
+
+use std::path::{Path,
+                PathBuf};
+
+use crate::{client::ApiClient,
+            error::Result};
+
+/// The origin's owner, one of the users the api authenticates by name under test
+pub const OWNER: &str = "bobo";
+pub const ORIGIN: &str = "neurosis";
+pub const ORIGIN_KEY_REVISION: &str = "20171211220037";
+pub const PROJECT: &str = "testapp";
+pub const TARGET: &str = "x86_64-linux";
+
+/// The release uploaded when the origin is seeded, which puts the package in the build graph
+pub const SEED_PACKAGE: Package = Package { origin:  ORIGIN,
+                                            name:    PROJECT,
+                                            version: "0.1.3",
+                                            release: "20171205003213", };
+
+/// The release a fake worker uploads when it builds the package
+pub const BUILT_PACKAGE: Package = Package { origin:  ORIGIN,
+                                             name:    PROJECT,
+                                             version: "0.1.3",
+                                             release: "20171206004121", };
+
+#[derive(Clone, Copy, Debug)]
+pub struct Package {
+    pub origin:  &'static str,
+    pub name:    &'static str,
+    pub version: &'static str,
+    pub release: &'static str,
+}
+
+impl Package {
+    pub fn ident(&self) -> String {
+        format!("{}/{}/{}/{}",
+                self.origin, self.name, self.version, self.release)
+    }
+
+    pub fn archive_name(&self) -> String {
+        format!("{}-{}-{}-{}-{}.hart",
+                self.origin, self.name, self.version, self.release, TARGET)
+    }
+
+    pub fn path(&self) -> PathBuf { fixture(&self.archive_name()) }
+
+    /// The object store key the api keeps the package's hart under
+    pub fn s3_key(&self) -> String {
+        format!("{}/{}/{}",
+                self.ident(),
+                TARGET.replace('-', "/"),
+                self.archive_name())
+    }
+}
+
+pub fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../../test/builder-api/fixtures")
+                                         .join(name)
+}
+
+/// Creates the origin with its signing keys, uploads the seed package and creates the
+/// project that builds it.
+pub fn seed_origin(client: &ApiClient) -> Result<()> {
+    let key = format!("{}-{}", ORIGIN, ORIGIN_KEY_REVISION);
+    client.create_origin(ORIGIN)?;
+    client.upload_origin_key(ORIGIN,
+                             ORIGIN_KEY_REVISION,
+                             &fixture(&format!("{}.pub", key)))?;
+    client.upload_origin_secret_key(ORIGIN,
+                                    ORIGIN_KEY_REVISION,
+                                    &fixture(&format!("{}.sig.key", key)))?;
+    client.upload_package(&SEED_PACKAGE)?;
+    client.create_project(ORIGIN)
+}
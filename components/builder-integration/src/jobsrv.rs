@@ -0,0 +1,43 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Requests sent straight to the jobsrv, standing in for what the api only passes on from
+//! services the tests can't run, such as the push hooks of the GitHub App.
+
+use crate::{bldr_core::rpc::RpcClient,
+            error::Result,
+            fixtures::TARGET,
+            protocol::jobsrv::{JobGroup,
+                               JobGroupSpec,
+                               JobGroupTrigger}};
+
+pub struct JobsrvClient {
+    inner: RpcClient,
+}
+
+impl JobsrvClient {
+    pub fn new(url: &str) -> Self { JobsrvClient { inner: RpcClient::new(url), } }
+
+    /// Schedules a build of the package the way the api does for a push of `sha`, returning
+    /// the group the push went to.
+    pub fn push(&self, origin: &str, name: &str, sha: &str) -> Result<JobGroup> {
+        let mut request = JobGroupSpec::new();
+        request.set_origin(origin.to_string());
+        request.set_package(name.to_string());
+        request.set_target(TARGET.to_string());
+        request.set_trigger(JobGroupTrigger::Webhook);
+        request.set_vcs_ref(sha.to_string());
+        Ok(self.inner.rpc::<JobGroupSpec, JobGroup>(&request)?)
+    }
+}
@@ -0,0 +1,118 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! End-to-end tests of the api and jobsrv. A `TestEnv` runs the real servers against Postgres,
+//! MinIO and memcached started with docker-compose, and fake workers stand in for
+//! builder-worker, so a test can drive a build from scheduling to promotion through the
+//! public api and then check what ended up in the database and the object store.
+//!
+//! The tests are behind the `integration` feature. They need docker-compose on the path and
+//! the `bldr-api` and `bldr-jobsrv` binaries, taken from the workspace's debug build unless
+//! `BLDR_API_BIN` and `BLDR_JOBSRV_BIN` say otherwise:
+//!
+//! ```text
+//! cargo build -p habitat_builder_api -p habitat_builder_jobsrv
+//! cargo test -p habitat_builder_integration --features integration
+//! ```
+//!
+//! Server logs are kept in a temporary directory when a test fails.
+
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_json;
+
+use builder_core as bldr_core;
+use habitat_builder_protocol as protocol;
+
+pub mod client;
+pub mod error;
+pub mod fixtures;
+pub mod jobsrv;
+pub mod servers;
+pub mod services;
+pub mod state;
+pub mod worker;
+
+use std::{thread,
+          time::{Duration,
+                 Instant}};
+
+use env_logger;
+
+pub use crate::error::{Error,
+                       Result};
+
+use crate::{client::ApiClient,
+            fixtures::OWNER,
+            jobsrv::JobsrvClient,
+            servers::Servers,
+            services::Services,
+            state::{Database,
+                    ObjectStore},
+            worker::{FakeWorker,
+                     Outcome}};
+
+/// The bucket the api stores harts in
+pub const BUCKET: &str = "habitat-builder-artifact-store.test";
+
+const POLL_INTERVAL_MS: u64 = 250;
+
+pub struct TestEnv {
+    // Declared first so the servers stop before the services they use go away
+    servers:  Servers,
+    services: Services,
+}
+
+impl TestEnv {
+    pub fn start() -> Result<Self> {
+        let _ = env_logger::try_init();
+        let services = Services::start()?;
+        ObjectStore::new(&services).create_bucket()?;
+        let servers = Servers::start(&services)?;
+        Ok(TestEnv { servers, services })
+    }
+
+    /// A client authenticated as `user`, one of the names the api accepts as a token under
+    /// test
+    pub fn client(&self, user: &str) -> ApiClient { ApiClient::new(&self.servers.api_url, user) }
+
+    /// A client for the jobsrv's RPC endpoint, for requests that reach it from outside the api
+    pub fn jobsrv(&self) -> JobsrvClient { JobsrvClient::new(&self.servers.jobsrv_url) }
+
+    pub fn database(&self) -> Result<Database> { Database::connect(&self.services) }
+
+    pub fn object_store(&self) -> ObjectStore { ObjectStore::new(&self.services) }
+
+    /// Starts a fake worker that uploads what it builds as the origin owner
+    pub fn worker(&self, script: Vec<Outcome>) -> Result<FakeWorker> {
+        FakeWorker::start(&self.servers, self.client(OWNER), script)
+    }
+}
+
+/// Calls `check` until it returns something or `timeout` passes.
+pub fn wait_for<F, T>(what: &str, timeout: Duration, mut check: F) -> Result<T>
+    where F: FnMut() -> Option<T>
+{
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(value) = check() {
+            return Ok(value);
+        }
+        if Instant::now() >= deadline {
+            return Err(Error::Timeout(what.to_string()));
+        }
+        thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+    }
+}
@@ -0,0 +1,272 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The api and jobsrv under test, started from the workspace's build output with configs
+//! written to a temporary directory. Every listener gets a free port so that runs don't
+//! collide with each other or with a local Builder.
+
+use std::{env,
+          fs::{self,
+               File},
+          io::Write,
+          net::TcpListener,
+          path::{Path,
+                 PathBuf},
+          process::{Child,
+                    Command},
+          thread,
+          time::Duration};
+
+use reqwest;
+use tempfile;
+
+use crate::{error::{Error,
+                    Result},
+            services::Services,
+            wait_for};
+
+const STARTUP_TIMEOUT_SECS: u64 = 120;
+
+pub struct Servers {
+    pub api_url:     String,
+    pub jobsrv_url:  String,
+    pub worker_addr: String,
+    pub hb_addr:     String,
+    dir:             PathBuf,
+    api:             Option<Child>,
+    jobsrv:          Option<Child>,
+}
+
+impl Servers {
+    /// Migrates the database and starts the api, then the jobsrv, returning once both answer
+    /// on their status endpoints. The jobsrv loads its build graph from the packages table when
+    /// it starts, so it has to come up after the api has run its own migrations.
+    pub fn start(services: &Services) -> Result<Self> {
+        let dir = tempfile::Builder::new().prefix("bldr-it-")
+                                          .tempdir()?
+                                          .into_path();
+        let api_port = free_port()?;
+        let jobsrv_http_port = free_port()?;
+        let worker_port = free_port()?;
+        let hb_port = free_port()?;
+        let log_port = free_port()?;
+        // Dropping a half started `Servers` kills whatever made it that far
+        let mut servers = Servers { api_url: format!("http://127.0.0.1:{}/v1", api_port),
+                                    jobsrv_url: format!("http://127.0.0.1:{}", jobsrv_http_port),
+                                    worker_addr: format!("tcp://127.0.0.1:{}", worker_port),
+                                    hb_addr: format!("tcp://127.0.0.1:{}", hb_port),
+                                    dir,
+                                    api: None,
+                                    jobsrv: None };
+        let dir = servers.dir.clone();
+        for sub in &["api-data", "keys", "jobsrv-logs", "archive"] {
+            fs::create_dir(dir.join(sub))?;
+        }
+
+        let jobsrv_cfg = write_config(&dir,
+                                      "jobsrv.toml",
+                                      &jobsrv_config(&dir,
+                                                     services,
+                                                     jobsrv_http_port,
+                                                     worker_port,
+                                                     hb_port,
+                                                     log_port))?;
+        let api_cfg = write_config(&dir,
+                                   "api.toml",
+                                   &api_config(&dir, services, jobsrv_http_port))?;
+        let jobsrv_bin = binary("BLDR_JOBSRV_BIN", "bldr-jobsrv")?;
+        let api_bin = binary("BLDR_API_BIN", "bldr-api")?;
+
+        let status = Command::new(&jobsrv_bin).args(&["migrate", "-c", &path_str(&jobsrv_cfg)])
+                                              .stdout(log_file(&dir, "jobsrv-migrate")?)
+                                              .stderr(log_file(&dir, "jobsrv-migrate.err")?)
+                                              .status()?;
+        if !status.success() {
+            return Err(Error::Startup(format!("jobsrv migrations exited with {}", status)));
+        }
+
+        let api_args = ["start",
+                        "-c",
+                        &path_str(&api_cfg),
+                        "--port",
+                        &api_port.to_string()];
+        servers.api = Some(spawn(&api_bin, &api_args, &dir, "api")?);
+        wait_for_status("api", &format!("{}/status", servers.api_url))?;
+
+        let jobsrv_args = ["start", "-c", &path_str(&jobsrv_cfg)];
+        servers.jobsrv = Some(spawn(&jobsrv_bin, &jobsrv_args, &dir, "jobsrv")?);
+        wait_for_status("jobsrv", &format!("{}/status", servers.jobsrv_url))?;
+        Ok(servers)
+    }
+}
+
+impl Drop for Servers {
+    fn drop(&mut self) {
+        for child in self.jobsrv.iter_mut().chain(self.api.iter_mut()) {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        // The server logs are all there is to go on when a test fails
+        if thread::panicking() {
+            error!("Server logs kept in {}", self.dir.display());
+        } else {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+fn jobsrv_config(dir: &Path,
+                 services: &Services,
+                 http_port: u16,
+                 worker_port: u16,
+                 hb_port: u16,
+                 log_port: u16)
+                 -> String {
+    format!(
+            r#"log_dir = "{dir}/jobsrv-logs"
+key_dir = "{dir}/keys"
+log_path = "{dir}/jobsrv-logs"
+build_targets = ["x86_64-linux"]
+
+[http]
+listen = "127.0.0.1"
+port = {http_port}
+
+[net]
+worker_command_listen = "127.0.0.1"
+worker_command_port = {worker_port}
+worker_heartbeat_listen = "127.0.0.1"
+worker_heartbeat_port = {hb_port}
+log_ingestion_listen = "127.0.0.1"
+log_ingestion_port = {log_port}
+
+[archive]
+backend = "local"
+local_dir = "{dir}/archive"
+
+{datastore}"#,
+            dir = path_str(dir),
+            http_port = http_port,
+            worker_port = worker_port,
+            hb_port = hb_port,
+            log_port = log_port,
+            datastore = datastore_config(services)
+    )
+}
+
+fn api_config(dir: &Path, services: &Services, jobsrv_port: u16) -> String {
+    format!(
+            r#"[api]
+data_path = "{dir}/api-data"
+log_path = "{dir}"
+key_path = "{dir}/keys"
+features_enabled = "jobsrv"
+build_on_upload = false
+build_targets = ["x86_64-linux"]
+
+[http]
+listen = "127.0.0.1"
+
+[s3]
+key_id = "depot"
+secret_key = "password"
+bucket_name = "{bucket}"
+backend = "minio"
+endpoint = "http://{minio}"
+
+[memcache]
+ttl = 15
+hosts = [{{ host = "127.0.0.1", port = {memcached_port} }}]
+
+[jobsrv]
+host = "127.0.0.1"
+port = {jobsrv_port}
+
+{datastore}"#,
+            dir = path_str(dir),
+            bucket = crate::BUCKET,
+            minio = services.minio,
+            memcached_port = services.memcached.port(),
+            jobsrv_port = jobsrv_port,
+            datastore = datastore_config(services)
+    )
+}
+
+fn datastore_config(services: &Services) -> String {
+    format!(
+            r#"[datastore]
+host = "{}"
+port = {}
+user = "hab"
+password = "hab"
+database = "builder"
+"#,
+            services.postgres.ip(),
+            services.postgres.port()
+    )
+}
+
+fn write_config(dir: &Path, name: &str, content: &str) -> Result<PathBuf> {
+    let path = dir.join(name);
+    File::create(&path)?.write_all(content.as_bytes())?;
+    Ok(path)
+}
+
+// The binary named by `var`, defaulting to the one in the workspace's debug build output
+fn binary(var: &str, name: &str) -> Result<PathBuf> {
+    let path = match env::var_os(var) {
+        Some(path) => PathBuf::from(path),
+        None => {
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../../target/debug")
+                                                 .join(name)
+        }
+    };
+    if path.is_file() {
+        Ok(path)
+    } else {
+        Err(Error::MissingBinary(path.display().to_string()))
+    }
+}
+
+fn spawn(bin: &Path, args: &[&str], dir: &Path, name: &str) -> Result<Child> {
+    let child = Command::new(bin).args(args)
+                                 .env("HAB_FUNC_TEST", "1")
+                                 .env("RUST_LOG", "info")
+                                 .stdout(log_file(dir, name)?)
+                                 .stderr(log_file(dir, &format!("{}.err", name))?)
+                                 .spawn()?;
+    Ok(child)
+}
+
+fn log_file(dir: &Path, name: &str) -> Result<File> {
+    Ok(File::create(dir.join(format!("{}.log", name)))?)
+}
+
+fn wait_for_status(name: &str, url: &str) -> Result<()> {
+    let client = reqwest::Client::new();
+    wait_for(&format!("{} to start", name),
+             Duration::from_secs(STARTUP_TIMEOUT_SECS),
+             || {
+                 match client.get(url).send() {
+                     Ok(ref res) if res.status().is_success() => Some(()),
+                     _ => None,
+                 }
+             })
+}
+
+// Binding port 0 has the kernel pick a port that is free right now; it is released again
+// straight away for the server to bind
+fn free_port() -> Result<u16> { Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?.port()) }
+
+fn path_str(path: &Path) -> String { path.display().to_string() }
@@ -0,0 +1,128 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The backing services of the api and jobsrv: Postgres, MinIO and memcached, run with
+//! docker-compose. Each `Services` gets a compose project of its own so concurrent runs don't
+//! share containers, and everything, volumes included, is removed when it is dropped.
+
+use std::{net::{SocketAddr,
+                TcpStream},
+          process::{self,
+                    Command},
+          sync::atomic::{AtomicUsize,
+                         Ordering},
+          time::Duration};
+
+use postgres::{Connection,
+               TlsMode};
+use reqwest;
+
+use crate::{error::{Error,
+                    Result},
+            wait_for};
+
+const COMPOSE_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/docker-compose.yml");
+const STARTUP_TIMEOUT_SECS: u64 = 120;
+
+static NEXT_PROJECT: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Services {
+    pub postgres:  SocketAddr,
+    pub minio:     SocketAddr,
+    pub memcached: SocketAddr,
+    project:       String,
+}
+
+impl Services {
+    /// Brings the services up and waits for each of them to accept requests.
+    pub fn start() -> Result<Self> {
+        let project = format!("bldr-it-{}-{}",
+                              process::id(),
+                              NEXT_PROJECT.fetch_add(1, Ordering::SeqCst));
+        compose(&project, &["up", "-d"])?;
+
+        match Self::connect(&project) {
+            Ok(services) => Ok(services),
+            Err(err) => {
+                let _ = compose(&project, &["down", "-v"]);
+                Err(err)
+            }
+        }
+    }
+
+    fn connect(project: &str) -> Result<Self> {
+        let services = Services { postgres:  port(project, "postgres", 5432)?,
+                                  minio:     port(project, "minio", 9000)?,
+                                  memcached: port(project, "memcached", 11211)?,
+                                  project:   project.to_string(), };
+        let timeout = Duration::from_secs(STARTUP_TIMEOUT_SECS);
+
+        let url = services.postgres_url();
+        wait_for("postgres", timeout, || {
+            Connection::connect(url.as_str(), TlsMode::None).ok()
+        })?;
+
+        let client = reqwest::Client::new();
+        let health = format!("http://{}/minio/health/live", services.minio);
+        wait_for("minio", timeout, || {
+            match client.get(&health).send() {
+                Ok(ref res) if res.status().is_success() => Some(()),
+                _ => None,
+            }
+        })?;
+
+        let memcached = services.memcached;
+        wait_for("memcached", timeout, || TcpStream::connect(memcached).ok())?;
+        Ok(services)
+    }
+
+    pub fn postgres_url(&self) -> String { format!("postgres://hab:hab@{}/builder", self.postgres) }
+}
+
+impl Drop for Services {
+    fn drop(&mut self) {
+        if let Err(err) = compose(&self.project, &["down", "-v"]) {
+            warn!("Unable to remove compose project {}, {}", self.project, err);
+        }
+    }
+}
+
+fn compose(project: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new("docker-compose").args(&["-f", COMPOSE_FILE, "-p", project])
+                                               .args(args)
+                                               .output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(Error::Compose(format!("{} exited with {}, {}",
+                                   args.join(" "),
+                                   output.status,
+                                   String::from_utf8_lossy(&output.stderr).trim())))
+    }
+}
+
+// The host address a service's container port is published on. Docker reports the wildcard
+// address, which is reached through loopback.
+fn port(project: &str, service: &str, container_port: u16) -> Result<SocketAddr> {
+    let output = compose(project, &["port", service, &container_port.to_string()])?;
+    let port = output.trim()
+                     .rsplit(':')
+                     .next()
+                     .and_then(|p| p.parse::<u16>().ok())
+                     .ok_or_else(|| {
+                         Error::Compose(format!("no published port for {}, got {:?}",
+                                                service, output))
+                     })?;
+    Ok(SocketAddr::from(([127, 0, 0, 1], port)))
+}
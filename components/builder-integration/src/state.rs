@@ -0,0 +1,148 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Read-only views of the end state the tests assert on: the rows the api and jobsrv keep in
+//! Postgres and the harts the api keeps in the object store.
+
+use postgres::{Connection,
+               TlsMode};
+use rusoto_core::{credential::StaticProvider,
+                  HttpClient,
+                  Region,
+                  RusotoError};
+use rusoto_s3::{CreateBucketRequest,
+                HeadObjectError,
+                HeadObjectRequest,
+                S3Client,
+                S3};
+
+use crate::{error::{Error,
+                    Result},
+            services::Services,
+            BUCKET};
+
+pub struct Database {
+    conn: Connection,
+}
+
+#[derive(Debug)]
+pub struct GroupRefs {
+    pub id:              u64,
+    pub state:           String,
+    pub vcs_ref:         Option<String>,
+    pub superseded_refs: Vec<String>,
+}
+
+impl Database {
+    pub fn connect(services: &Services) -> Result<Self> {
+        let conn = Connection::connect(services.postgres_url().as_str(), TlsMode::None)?;
+        Ok(Database { conn })
+    }
+
+    pub fn group_state(&self, group_id: u64) -> Result<Option<String>> {
+        let rows = self.conn
+                       .query("SELECT group_state FROM groups WHERE id = $1",
+                              &[&(group_id as i64)])?;
+        Ok(rows.iter().next().map(|row| row.get(0)))
+    }
+
+    /// The groups of a project with the commits they coalesced, oldest first
+    pub fn group_refs(&self, project_name: &str) -> Result<Vec<GroupRefs>> {
+        let rows = self.conn
+                       .query("SELECT id, group_state, vcs_ref, superseded_refs FROM groups
+                                    WHERE project_name = $1 ORDER BY id",
+                              &[&project_name])?;
+        Ok(rows.iter()
+               .map(|row| {
+                   let id: i64 = row.get(0);
+                   GroupRefs { id:              id as u64,
+                               state:           row.get(1),
+                               vcs_ref:         row.get(2),
+                               superseded_refs: row.get(3), }
+               })
+               .collect())
+    }
+
+    /// Commits the project's jobs were built at, oldest first
+    pub fn job_refs(&self, group_id: u64) -> Result<Vec<Option<String>>> {
+        let rows = self.conn
+                       .query("SELECT vcs_ref FROM jobs WHERE owner_id = $1 ORDER BY id",
+                              &[&(group_id as i64)])?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    /// States of the group's jobs, oldest first
+    pub fn job_states(&self, group_id: u64) -> Result<Vec<String>> {
+        let rows = self.conn
+                       .query("SELECT job_state FROM jobs WHERE owner_id = $1 ORDER BY id",
+                              &[&(group_id as i64)])?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    /// Idents of the packages in an origin's channel, sorted
+    pub fn channel_packages(&self, origin: &str, channel: &str) -> Result<Vec<String>> {
+        let rows = self.conn
+                       .query("SELECT op.ident FROM origin_channel_packages AS ocp
+                                    INNER JOIN origin_channels AS oc ON oc.id = ocp.channel_id
+                                    INNER JOIN origin_packages AS op ON op.id = ocp.package_id
+                                    WHERE oc.origin = $1 AND oc.name = $2
+                                    ORDER BY op.ident",
+                              &[&origin, &channel])?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    pub fn package_exists(&self, ident: &str) -> Result<bool> {
+        let rows = self.conn
+                       .query("SELECT 1 FROM origin_packages WHERE ident = $1", &[&ident])?;
+        Ok(!rows.is_empty())
+    }
+}
+
+pub struct ObjectStore {
+    client: S3Client,
+}
+
+impl ObjectStore {
+    pub fn new(services: &Services) -> Self {
+        let region = Region::Custom { name:     "minio_s3".to_owned(),
+                                      endpoint: format!("http://{}", services.minio), };
+        let credentials = StaticProvider::new_minimal("depot".to_string(), "password".to_string());
+        let http_client = HttpClient::new().expect("Unable to create Rusoto http client");
+        ObjectStore { client: S3Client::new_with(http_client, credentials, region), }
+    }
+
+    /// Creates the bucket the api stores harts in, which it expects to exist
+    pub fn create_bucket(&self) -> Result<()> {
+        let request = CreateBucketRequest { bucket: BUCKET.to_string(),
+                                            ..Default::default() };
+        self.client
+            .create_bucket(request)
+            .sync()
+            .map_err(|err| Error::S3(err.to_string()))?;
+        Ok(())
+    }
+
+    pub fn contains(&self, key: &str) -> Result<bool> {
+        let request = HeadObjectRequest { bucket: BUCKET.to_string(),
+                                          key: key.to_string(),
+                                          ..Default::default() };
+        match self.client.head_object(request).sync() {
+            Ok(_) => Ok(true),
+            Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => Ok(false),
+            // HEAD responses have no body to name the error, so a missing key is only a 404
+            Err(RusotoError::Unknown(ref res)) if res.status.as_u16() == 404 => Ok(false),
+            Err(err) => Err(Error::S3(err.to_string())),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A stand-in for builder-worker that speaks the real worker protocol to the jobsrv but runs
+//! no builds. Each job it is handed takes the next outcome off its script: succeeding uploads
+//! a prebuilt hart through the api, the way a worker publishes what it built.
+
+use std::{collections::VecDeque,
+          sync::{atomic::{AtomicBool,
+                          AtomicUsize,
+                          Ordering},
+                 Arc,
+                 Mutex},
+          thread::{self,
+                   JoinHandle},
+          time::{Duration,
+                 Instant}};
+
+use zmq;
+
+use crate::{bldr_core::socket::DEFAULT_CONTEXT,
+            protocol::{jobsrv,
+                       message,
+                       originsrv::OriginPackageIdent}};
+
+use crate::{client::ApiClient,
+            error::Result,
+            fixtures::{Package,
+                       TARGET},
+            servers::Servers};
+
+// Well within the jobsrv's 33 second worker timeout
+const HEARTBEAT_INTERVAL_MS: u64 = 500;
+const POLL_TIMEOUT_MS: i64 = 100;
+
+static NEXT_WORKER: AtomicUsize = AtomicUsize::new(0);
+
+/// What the worker does with a job it is handed
+#[derive(Clone, Copy, Debug)]
+pub enum Outcome {
+    /// Upload the package and report the job complete
+    Succeed(Package),
+    /// Report the job failed
+    Fail,
+    /// Keep the job running until the jobsrv cancels it
+    Hold,
+}
+
+pub struct FakeWorker {
+    ident:  String,
+    jobs:   Arc<Mutex<Vec<u64>>>,
+    stop:   Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl FakeWorker {
+    /// Registers a worker with the jobsrv, which will hand it jobs once its first heartbeat
+    /// is in. Jobs beyond the end of the script fail.
+    pub fn start(servers: &Servers, client: ApiClient, script: Vec<Outcome>) -> Result<Self> {
+        let ident = format!("fake-worker-{}", NEXT_WORKER.fetch_add(1, Ordering::SeqCst));
+        let jobs = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let dealer = (**DEFAULT_CONTEXT).as_mut().socket(zmq::DEALER)?;
+        dealer.set_identity(ident.as_bytes())?;
+        dealer.set_linger(0)?;
+        dealer.connect(&servers.worker_addr)?;
+        let heartbeat = (**DEFAULT_CONTEXT).as_mut().socket(zmq::PUB)?;
+        heartbeat.set_linger(0)?;
+        heartbeat.connect(&servers.hb_addr)?;
+
+        let mut runner = Runner { dealer,
+                                  heartbeat,
+                                  client,
+                                  script: script.into_iter().collect(),
+                                  state: heartbeat_for(&ident),
+                                  jobs: jobs.clone(),
+                                  stop: stop.clone() };
+        let run = move || {
+            if let Err(err) = runner.run() {
+                error!("Fake worker stopped, {}", err);
+            }
+        };
+        let handle = thread::Builder::new().name(ident.clone()).spawn(run)?;
+        Ok(FakeWorker { ident,
+                        jobs,
+                        stop,
+                        handle: Some(handle) })
+    }
+
+    pub fn ident(&self) -> &str { &self.ident }
+
+    /// Ids of the jobs the worker was handed, in the order it got them
+    pub fn jobs(&self) -> Vec<u64> { self.jobs.lock().unwrap().clone() }
+}
+
+impl Drop for FakeWorker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+struct Runner {
+    dealer:    zmq::Socket,
+    heartbeat: zmq::Socket,
+    client:    ApiClient,
+    script:    VecDeque<Outcome>,
+    state:     jobsrv::Heartbeat,
+    jobs:      Arc<Mutex<Vec<u64>>>,
+    stop:      Arc<AtomicBool>,
+}
+
+impl Runner {
+    fn run(&mut self) -> Result<()> {
+        let interval = Duration::from_millis(HEARTBEAT_INTERVAL_MS);
+        let mut last_beat: Option<Instant> = None;
+
+        while !self.stop.load(Ordering::SeqCst) {
+            if last_beat.map_or(true, |beat| beat.elapsed() >= interval) {
+                self.heartbeat.send(&message::encode(&self.state)?, 0)?;
+                last_beat = Some(Instant::now());
+            }
+            {
+                let mut items = [self.dealer.as_poll_item(1)];
+                if zmq::poll(&mut items, POLL_TIMEOUT_MS)? == 0 {
+                    continue;
+                }
+            }
+
+            // The jobsrv sends an empty delimiter, then the command and the job
+            let frames = self.dealer.recv_multipart(0)?;
+            if frames.len() != 3 {
+                warn!("Fake worker got a message of {} frames", frames.len());
+                continue;
+            }
+            let command = message::decode::<jobsrv::WorkerCommand>(&frames[1])?;
+            let job = message::decode::<jobsrv::Job>(&frames[2])?;
+            match command.get_op() {
+                jobsrv::WorkerOperation::StartJob => self.start_job(job)?,
+                jobsrv::WorkerOperation::CancelJob => self.cancel_job(job)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn start_job(&mut self, mut job: jobsrv::Job) -> Result<()> {
+        self.jobs.lock().unwrap().push(job.get_id());
+        self.reply(&job)?;
+        self.state.set_state(jobsrv::WorkerState::Busy);
+
+        match self.script.pop_front().unwrap_or(Outcome::Fail) {
+            Outcome::Succeed(package) => {
+                self.client.upload_package(&package)?;
+                job.set_package_ident(package_ident(&package));
+                job.set_state(jobsrv::JobState::Complete);
+                self.finish(&job)
+            }
+            Outcome::Fail => {
+                job.set_state(jobsrv::JobState::Failed);
+                self.finish(&job)
+            }
+            Outcome::Hold => Ok(()),
+        }
+    }
+
+    // Acknowledges the cancel, then reports it done, as the worker does once its build
+    // process is gone
+    fn cancel_job(&mut self, mut job: jobsrv::Job) -> Result<()> {
+        job.set_state(jobsrv::JobState::CancelProcessing);
+        self.reply(&job)?;
+        job.set_state(jobsrv::JobState::CancelComplete);
+        self.finish(&job)
+    }
+
+    fn finish(&mut self, job: &jobsrv::Job) -> Result<()> {
+        self.reply(job)?;
+        self.state.set_state(jobsrv::WorkerState::Ready);
+        Ok(())
+    }
+
+    fn reply(&self, job: &jobsrv::Job) -> Result<()> {
+        self.dealer.send(&message::encode(job)?, 0)?;
+        Ok(())
+    }
+}
+
+fn heartbeat_for(ident: &str) -> jobsrv::Heartbeat {
+    let mut heartbeat = jobsrv::Heartbeat::new();
+    heartbeat.set_endpoint(ident.to_string());
+    heartbeat.set_os(jobsrv::Os::Linux);
+    heartbeat.set_state(jobsrv::WorkerState::Ready);
+    heartbeat.set_target(TARGET.to_string());
+    heartbeat
+}
+
+fn package_ident(package: &Package) -> OriginPackageIdent {
+    let mut ident = OriginPackageIdent::new();
+    ident.set_origin(package.origin.to_string());
+    ident.set_name(package.name.to_string());
+    ident.set_version(package.version.to_string());
+    ident.set_release(package.release.to_string());
+    ident
+}
@@ -0,0 +1,148 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pushes to a project with a quiet period are coalesced into one build at the latest commit,
+//! and pushes arriving once that build has left the queue into a single follow-up.
+
+#![cfg(feature = "integration")]
+
+use std::{collections::HashMap,
+          sync::{Arc,
+                 Mutex},
+          thread,
+          time::{Duration,
+                 Instant}};
+
+use habitat_builder_integration::{fixtures::{self,
+                                             BUILT_PACKAGE,
+                                             ORIGIN,
+                                             OWNER,
+                                             PROJECT},
+                                  jobsrv::JobsrvClient,
+                                  state::Database,
+                                  wait_for,
+                                  worker::Outcome,
+                                  TestEnv};
+
+const BUILD_TIMEOUT_SECS: u64 = 120;
+const PUSH_INTERVAL_MS: u64 = 20;
+
+fn sha(n: u64) -> String { format!("{:040x}", n) }
+
+fn project_name() -> String { format!("{}/{}", ORIGIN, PROJECT) }
+
+// Pushes commits `first`, `first + step`, ... until `until`, noting the group each landed on
+fn push_until(jobsrv: &JobsrvClient,
+              first: u64,
+              step: u64,
+              until: Instant,
+              landed: &Mutex<HashMap<String, u64>>) {
+    let mut n = first;
+    while Instant::now() < until {
+        let commit = sha(n);
+        let group = jobsrv.push(ORIGIN, PROJECT, &commit).unwrap();
+        assert_eq!(group.get_vcs_ref(), commit);
+        landed.lock().unwrap().insert(commit, group.get_id());
+        n += step;
+        thread::sleep(Duration::from_millis(PUSH_INTERVAL_MS));
+    }
+}
+
+fn wait_for_group(db: &Database, group_id: u64, state: &str) {
+    let what = format!("group {} to be {}", group_id, state);
+    wait_for(&what, Duration::from_secs(BUILD_TIMEOUT_SECS), || {
+        match db.group_state(group_id) {
+            Ok(Some(ref current)) if current == state => Some(()),
+            _ => None,
+        }
+    }).unwrap();
+}
+
+#[test]
+fn pushes_in_the_quiet_period_build_once_at_the_latest_commit() {
+    let env = TestEnv::start().unwrap();
+    let client = env.client(OWNER);
+    fixtures::seed_origin(&client).unwrap();
+    client.set_quiet_period(ORIGIN, 5).unwrap();
+    let jobsrv = env.jobsrv();
+    let db = env.database().unwrap();
+
+    let first = jobsrv.push(ORIGIN, PROJECT, &sha(1)).unwrap();
+    let second = jobsrv.push(ORIGIN, PROJECT, &sha(2)).unwrap();
+    let third = jobsrv.push(ORIGIN, PROJECT, &sha(3)).unwrap();
+    assert_eq!(second.get_id(), first.get_id());
+    assert_eq!(third.get_id(), first.get_id());
+    assert_eq!(third.get_vcs_ref(), sha(3));
+    assert_eq!(third.get_superseded_refs().to_vec(), vec![sha(1), sha(2)]);
+    assert_eq!(db.group_state(first.get_id()).unwrap(),
+               Some("Queued".to_string()));
+
+    let worker = env.worker(vec![Outcome::Succeed(BUILT_PACKAGE)]).unwrap();
+    wait_for_group(&db, first.get_id(), "Complete");
+
+    assert_eq!(worker.jobs().len(), 1);
+    assert_eq!(db.job_refs(first.get_id()).unwrap(), vec![Some(sha(3))]);
+    let groups = db.group_refs(&project_name()).unwrap();
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0].superseded_refs, vec![sha(1), sha(2)]);
+}
+
+// Pushes keep coming from several clients while the first group's quiet period runs out, so some
+// arrive just as the scheduler releases it. Each must land on exactly one group: the released one
+// if it got there first, otherwise the single follow-up. No worker runs, so the released group
+// stays active and the follow-up stays queued.
+#[test]
+fn pushes_racing_the_release_queue_one_follow_up() {
+    const PUSHERS: u64 = 4;
+    const QUIET_PERIOD_SECS: u32 = 1;
+
+    let env = TestEnv::start().unwrap();
+    let client = env.client(OWNER);
+    fixtures::seed_origin(&client).unwrap();
+    client.set_quiet_period(ORIGIN, QUIET_PERIOD_SECS).unwrap();
+    let db = env.database().unwrap();
+
+    let landed = Arc::new(Mutex::new(HashMap::new()));
+    let until = Instant::now() + Duration::from_secs(u64::from(QUIET_PERIOD_SECS) * 3);
+    let pushers: Vec<_> =
+        (0..PUSHERS).map(|pusher| {
+                        let jobsrv = env.jobsrv();
+                        let landed = landed.clone();
+                        thread::spawn(move || push_until(&jobsrv, pusher, PUSHERS, until, &landed))
+                    })
+                    .collect();
+    for pusher in pushers {
+        pusher.join().unwrap();
+    }
+
+    let landed = landed.lock().unwrap();
+    let groups = db.group_refs(&project_name()).unwrap();
+    assert_eq!(groups.len(),
+               2,
+               "expected a follow-up group, got {:?}",
+               groups);
+    assert_ne!(groups[0].state, "Queued");
+    assert_eq!(groups[1].state, "Queued");
+
+    let mut recorded = HashMap::new();
+    for group in groups.iter() {
+        let refs = group.superseded_refs.iter().chain(group.vcs_ref.iter());
+        for commit in refs {
+            assert!(recorded.insert(commit.clone(), group.id).is_none(),
+                    "{} is recorded on more than one group",
+                    commit);
+        }
+    }
+    assert_eq!(recorded, *landed);
+}
@@ -0,0 +1,113 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Builds driven through the public api from scheduling to promotion, with a fake worker
+//! standing in for builder-worker.
+
+#![cfg(feature = "integration")]
+
+use std::time::Duration;
+
+use habitat_builder_integration::{fixtures::{self,
+                                             BUILT_PACKAGE,
+                                             ORIGIN,
+                                             OWNER,
+                                             PROJECT,
+                                             SEED_PACKAGE},
+                                  state::Database,
+                                  wait_for,
+                                  worker::Outcome,
+                                  TestEnv};
+
+const BUILD_TIMEOUT_SECS: u64 = 120;
+
+fn wait_for_group(db: &Database, group_id: u64, state: &str) {
+    let what = format!("group {} to be {}", group_id, state);
+    wait_for(&what, Duration::from_secs(BUILD_TIMEOUT_SECS), || {
+        match db.group_state(group_id) {
+            Ok(Some(ref current)) if current == state => Some(()),
+            _ => None,
+        }
+    }).unwrap();
+}
+
+#[test]
+fn scheduled_build_is_uploaded_and_promoted() {
+    let env = TestEnv::start().unwrap();
+    let client = env.client(OWNER);
+    fixtures::seed_origin(&client).unwrap();
+    let worker = env.worker(vec![Outcome::Succeed(BUILT_PACKAGE)]).unwrap();
+    let db = env.database().unwrap();
+    let store = env.object_store();
+
+    let group_id = client.schedule(ORIGIN, PROJECT).unwrap();
+    wait_for_group(&db, group_id, "Complete");
+
+    assert_eq!(worker.jobs().len(), 1);
+    assert_eq!(db.job_states(group_id).unwrap(), vec!["Complete"]);
+    assert!(db.package_exists(&BUILT_PACKAGE.ident()).unwrap());
+    assert!(store.contains(&BUILT_PACKAGE.s3_key()).unwrap());
+    assert_eq!(db.channel_packages(ORIGIN, "unstable").unwrap(),
+               vec![SEED_PACKAGE.ident(), BUILT_PACKAGE.ident()]);
+
+    client.promote_group(group_id, "stable").unwrap();
+    assert_eq!(db.channel_packages(ORIGIN, "stable").unwrap(),
+               vec![BUILT_PACKAGE.ident()]);
+}
+
+#[test]
+fn canceled_build_uploads_nothing() {
+    let env = TestEnv::start().unwrap();
+    let client = env.client(OWNER);
+    fixtures::seed_origin(&client).unwrap();
+    let worker = env.worker(vec![Outcome::Hold]).unwrap();
+    let db = env.database().unwrap();
+    let store = env.object_store();
+
+    let group_id = client.schedule(ORIGIN, PROJECT).unwrap();
+    wait_for("the job to be dispatched",
+             Duration::from_secs(BUILD_TIMEOUT_SECS),
+             || worker.jobs().first().cloned()).unwrap();
+    client.cancel_group(group_id).unwrap();
+    wait_for_group(&db, group_id, "Canceled");
+
+    assert_eq!(db.job_states(group_id).unwrap(), vec!["CancelComplete"]);
+    assert!(!db.package_exists(&BUILT_PACKAGE.ident()).unwrap());
+    assert!(!store.contains(&BUILT_PACKAGE.s3_key()).unwrap());
+}
+
+#[test]
+fn failed_build_succeeds_when_rescheduled() {
+    let env = TestEnv::start().unwrap();
+    let client = env.client(OWNER);
+    fixtures::seed_origin(&client).unwrap();
+    let worker = env.worker(vec![Outcome::Fail, Outcome::Succeed(BUILT_PACKAGE)])
+                    .unwrap();
+    let db = env.database().unwrap();
+    let store = env.object_store();
+
+    let failed_id = client.schedule(ORIGIN, PROJECT).unwrap();
+    wait_for_group(&db, failed_id, "Failed");
+    assert_eq!(db.job_states(failed_id).unwrap(), vec!["Failed"]);
+    assert!(!db.package_exists(&BUILT_PACKAGE.ident()).unwrap());
+
+    let retry_id = client.schedule(ORIGIN, PROJECT).unwrap();
+    wait_for_group(&db, retry_id, "Complete");
+
+    assert_ne!(failed_id, retry_id);
+    assert_eq!(worker.jobs().len(), 2);
+    assert_eq!(db.job_states(retry_id).unwrap(), vec!["Complete"]);
+    assert!(db.package_exists(&BUILT_PACKAGE.ident()).unwrap());
+    assert!(store.contains(&BUILT_PACKAGE.s3_key()).unwrap());
+}
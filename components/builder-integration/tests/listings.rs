@@ -0,0 +1,70 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Listings answer byte for byte the same while nothing changes, so that tools can diff
+//! successive responses to detect changes.
+
+#![cfg(feature = "integration")]
+
+use habitat_builder_integration::{fixtures::{self,
+                                             Package,
+                                             BUILT_PACKAGE,
+                                             ORIGIN,
+                                             OWNER,
+                                             PROJECT,
+                                             SEED_PACKAGE},
+                                  TestEnv};
+
+// Releases of several versions and packages, in more than one channel, so that listings have
+// rows to order within a package and channels to order within a release
+const RELEASES: [Package; 3] = [BUILT_PACKAGE,
+                                Package { origin:  ORIGIN,
+                                          name:    PROJECT,
+                                          version: "0.1.4",
+                                          release: "20171206004139", },
+                                Package { origin:  ORIGIN,
+                                          name:    "testapp3",
+                                          version: "0.1.0",
+                                          release: "20190327162559", }];
+
+fn listings() -> Vec<String> {
+    vec![format!("/depot/pkgs/{}", ORIGIN),
+         format!("/depot/pkgs/{}?distinct=true", ORIGIN),
+         format!("/depot/pkgs/{}/{}", ORIGIN, PROJECT),
+         format!("/depot/pkgs/{}/{}/versions", ORIGIN, PROJECT),
+         format!("/depot/pkgs/{}/{}/0.1.3", ORIGIN, PROJECT),
+         format!("/depot/pkgs/search/{}", PROJECT),
+         format!("/depot/pkgs/search/{}?distinct=true", PROJECT),
+         format!("/depot/channels/{}", ORIGIN),
+         format!("/depot/channels/{}/unstable/pkgs", ORIGIN),
+         format!("/depot/channels/{}/stable/pkgs/{}", ORIGIN, PROJECT),]
+}
+
+#[test]
+fn listings_are_the_same_on_every_call() {
+    let env = TestEnv::start().unwrap();
+    let client = env.client(OWNER);
+    fixtures::seed_origin(&client).unwrap();
+    for package in RELEASES.iter() {
+        client.upload_package(package).unwrap();
+    }
+    client.promote_package(&SEED_PACKAGE, "stable").unwrap();
+    client.promote_package(&RELEASES[1], "stable").unwrap();
+
+    for path in listings() {
+        let first = client.get_text(&path).unwrap();
+        let second = client.get_text(&path).unwrap();
+        assert_eq!(first, second, "{} differs between calls", path);
+    }
+}
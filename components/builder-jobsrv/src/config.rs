@@ -14,7 +14,8 @@
 
 //! Configuration for a Habitat JobSrv service
 
-use std::{collections::HashSet,
+use std::{collections::{HashMap,
+                        HashSet},
           env,
           io,
           iter::FromIterator,
@@ -51,12 +52,26 @@ pub struct Config {
     pub key_dir: PathBuf,
     /// Path to scheduler event logs
     pub log_path: PathBuf,
-    /// Max time (in minutes) allowed for a build job
+    /// Max time (in minutes) allowed for a build job, on workers that don't renew job leases
     pub job_timeout: u64,
+    /// Leases of the jobs of workers that renew them
+    pub job_leases: JobLeaseCfg,
     /// Supported build targets
     pub build_targets: HashSet<PackageTarget>,
     /// Feature flag toggles
     pub features_enabled: String,
+    /// Consistency checking of the build graph against the packages table
+    pub graph_check: GraphCheckCfg,
+    /// Restarts of the scheduler, worker manager and other background threads
+    pub supervisor: SupervisorCfg,
+    /// Log excerpt stored with failed jobs
+    pub failure_excerpt: FailureExcerptCfg,
+    /// Reloading of the runtime feature flags
+    pub feature_flags: FeatureFlagCfg,
+    /// Confirmation and operator approval of groups with many projects
+    pub large_groups: LargeGroupCfg,
+    /// How many jobs of an origin may be in flight at once
+    pub origin_limits: OriginLimitsCfg,
 }
 
 impl Default for Config {
@@ -71,9 +86,16 @@ impl Default for Config {
                  key_dir: PathBuf::from("/hab/svc/hab-depot/files"),
                  log_path: PathBuf::from("/tmp"),
                  job_timeout: 60,
+                 job_leases: JobLeaseCfg::default(),
                  build_targets: HashSet::from_iter(vec![target::X86_64_LINUX,
                                                         target::X86_64_WINDOWS]),
-                 features_enabled: String::from("builddeps") }
+                 features_enabled: String::from("builddeps"),
+                 graph_check: GraphCheckCfg::default(),
+                 supervisor: SupervisorCfg::default(),
+                 failure_excerpt: FailureExcerptCfg::default(),
+                 feature_flags: FeatureFlagCfg::default(),
+                 large_groups: LargeGroupCfg::default(),
+                 origin_limits: OriginLimitsCfg::default() }
     }
 }
 
@@ -176,6 +198,141 @@ impl ToSocketAddrs for HttpCfg {
     }
 }
 
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct GraphCheckCfg {
+    /// Seconds between checks of a random sample of packages, 0 to disable
+    pub interval_sec: u64,
+    /// Packages checked per sample
+    pub sample_size:  u32,
+}
+
+impl Default for GraphCheckCfg {
+    fn default() -> Self {
+        GraphCheckCfg { interval_sec: 900,
+                        sample_size:  50, }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SupervisorCfg {
+    /// Restarts allowed within the window before the thread is reported as failed
+    pub max_restarts: u32,
+    /// Seconds over which restarts are counted
+    pub window_sec: u64,
+    /// Delay before the first restart, doubled for each further restart in the window
+    pub backoff_initial_ms: u64,
+    /// Longest delay between restarts
+    pub backoff_max_ms: u64,
+}
+
+impl Default for SupervisorCfg {
+    fn default() -> Self {
+        SupervisorCfg { max_restarts:       5,
+                        window_sec:         600,
+                        backoff_initial_ms: 1_000,
+                        backoff_max_ms:     60_000, }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct FailureExcerptCfg {
+    /// Lines from the end of the log always in the excerpt
+    pub tail_lines: usize,
+    /// Largest excerpt stored, the earliest lines are dropped to fit
+    pub max_bytes:  usize,
+    /// Lines containing any of these are in the excerpt wherever they are in the log
+    pub patterns:   Vec<String>,
+}
+
+impl Default for FailureExcerptCfg {
+    fn default() -> Self {
+        FailureExcerptCfg { tail_lines: 50,
+                            max_bytes:  8192,
+                            patterns:   vec!["ERROR".to_string(),
+                                             "error:".to_string(),
+                                             "undefined reference".to_string(),
+                                             "Exiting on error".to_string(),
+                                             "exited with status".to_string(),
+                                             "exit code".to_string()], }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct FeatureFlagCfg {
+    /// Seconds between reloads of the flags from the database
+    pub refresh_sec: u64,
+}
+
+impl Default for FeatureFlagCfg {
+    fn default() -> Self { FeatureFlagCfg { refresh_sec: 30 } }
+}
+
+/// Groups with more projects than `confirm_threshold` are only created when the request
+/// confirms them, and those with more than `approval_threshold` wait for an operator to approve
+/// them. A threshold of 0 turns its check off.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct LargeGroupCfg {
+    pub confirm_threshold:     usize,
+    pub approval_threshold:    usize,
+    /// Hours a group awaits approval before it is canceled
+    pub approval_window_hours: u64,
+    /// Addresses emailed when a group is created awaiting approval
+    pub approval_emails:       Vec<String>,
+}
+
+impl Default for LargeGroupCfg {
+    fn default() -> Self {
+        LargeGroupCfg { confirm_threshold:     500,
+                        approval_threshold:    2000,
+                        approval_window_hours: 72,
+                        approval_emails:       vec![], }
+    }
+}
+
+/// The jobs of an origin that may be pending, dispatched or building at once, across all of its
+/// groups, 0 for no limit. A group's own `max_concurrency` applies as well, whichever is lower.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct OriginLimitsCfg {
+    pub max_concurrency: u32,
+    /// Limits of particular origins, in place of `max_concurrency`
+    pub overrides:       HashMap<String, u32>,
+}
+
+impl OriginLimitsCfg {
+    pub fn max_concurrency(&self, origin: &str) -> u32 {
+        self.overrides
+            .get(origin)
+            .cloned()
+            .unwrap_or(self.max_concurrency)
+    }
+}
+
+/// A job dispatched to a worker renewing leases holds a lease of `lease_minutes`, which the
+/// worker's heartbeats renew while it builds the job. The job is canceled once its lease expires,
+/// or once it has run for `max_lifetime_minutes` however often it was renewed.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct JobLeaseCfg {
+    pub lease_minutes:        u64,
+    pub max_lifetime_minutes: u64,
+    /// Days the job events of leases are kept, 0 to keep them forever
+    pub event_retention_days: u64,
+}
+
+impl Default for JobLeaseCfg {
+    fn default() -> Self {
+        JobLeaseCfg { lease_minutes:        10,
+                      max_lifetime_minutes: 480,
+                      event_retention_days: 30, }
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////
 // Archive Configuration
 
@@ -239,6 +396,38 @@ mod tests {
         bucket = "bukkit"
         endpoint = "http://minio.mycompany.com:9000"
 
+        [graph_check]
+        interval_sec = 60
+        sample_size = 500
+
+        [supervisor]
+        max_restarts = 3
+        window_sec = 300
+        backoff_initial_ms = 500
+        backoff_max_ms = 10000
+
+        [failure_excerpt]
+        tail_lines = 20
+        max_bytes = 4096
+        patterns = ["FATAL", "panicked at"]
+
+        [feature_flags]
+        refresh_sec = 5
+
+        [large_groups]
+        confirm_threshold = 100
+        approval_threshold = 0
+        approval_emails = ["builder-ops@example.com"]
+
+        [job_leases]
+        lease_minutes = 5
+
+        [origin_limits]
+        max_concurrency = 50
+
+        [origin_limits.overrides]
+        core = 200
+
         [datastore]
         host = "1.1.1.1"
         port = 9000
@@ -284,5 +473,33 @@ mod tests {
                    Some("http://minio.mycompany.com:9000".to_string()));
         assert_eq!(config.archive.region, "us-east-1");
         assert_eq!(config.archive.local_dir, None);
+
+        assert_eq!(config.graph_check.interval_sec, 60);
+        assert_eq!(config.graph_check.sample_size, 500);
+
+        assert_eq!(config.supervisor.max_restarts, 3);
+        assert_eq!(config.supervisor.window_sec, 300);
+        assert_eq!(config.supervisor.backoff_initial_ms, 500);
+        assert_eq!(config.supervisor.backoff_max_ms, 10000);
+
+        assert_eq!(config.failure_excerpt.tail_lines, 20);
+        assert_eq!(config.failure_excerpt.max_bytes, 4096);
+        assert_eq!(config.failure_excerpt.patterns,
+                   vec!["FATAL", "panicked at"]);
+
+        assert_eq!(config.feature_flags.refresh_sec, 5);
+
+        assert_eq!(config.large_groups.confirm_threshold, 100);
+        assert_eq!(config.large_groups.approval_threshold, 0);
+        assert_eq!(config.large_groups.approval_window_hours, 72);
+        assert_eq!(config.large_groups.approval_emails,
+                   vec!["builder-ops@example.com".to_string()]);
+
+        assert_eq!(config.job_leases.lease_minutes, 5);
+        assert_eq!(config.job_leases.max_lifetime_minutes, 480);
+        assert_eq!(config.job_leases.event_retention_days, 30);
+
+        assert_eq!(config.origin_limits.max_concurrency("acme"), 50);
+        assert_eq!(config.origin_limits.max_concurrency("core"), 200);
     }
 }
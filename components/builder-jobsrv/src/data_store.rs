@@ -16,7 +16,9 @@
 
 embed_migrations!("src/migrations");
 
-use std::{io,
+use std::{collections::HashMap,
+          io,
+          ops::Deref,
           sync::Arc};
 
 use chrono::{DateTime,
@@ -28,10 +30,15 @@ use protobuf::{self,
                ProtobufEnum,
                RepeatedField};
 
-use crate::db::{config::DataStoreCfg,
-                migration::setup_ids,
-                pool::Pool,
-                DbPool};
+use crate::bldr_core::timestamp;
+
+use crate::{db::{self,
+                 config::DataStoreCfg,
+                 migration::setup_ids,
+                 pool::Pool,
+                 DbPool},
+            db_health::DbHealth,
+            deadline};
 
 use crate::protocol::{jobsrv,
                       net::{ErrCode,
@@ -46,6 +53,7 @@ use crate::error::{Error,
 pub struct DataStore {
     pool:        Pool,
     diesel_pool: DbPool,
+    health:      DbHealth,
 }
 
 impl DataStore {
@@ -56,14 +64,22 @@ impl DataStore {
     pub fn new(cfg: &DataStoreCfg) -> Self {
         let pool = Pool::new(cfg);
         let diesel_pool = DbPool::new(&cfg);
-        DataStore { pool, diesel_pool }
+        DataStore { pool,
+                    diesel_pool,
+                    health: DbHealth::default() }
     }
 
     /// Create a new DataStore from a pre-existing pool; useful for testing the database.
     pub fn from_pool(pool: Pool, diesel_pool: DbPool, _: Vec<u32>, _: Arc<String>) -> Self {
-        DataStore { pool, diesel_pool }
+        DataStore { pool,
+                    diesel_pool,
+                    health: DbHealth::default() }
     }
 
+    /// Whether a datastore connection was lost recently, e.g. during a Postgres failover.
+    /// New work should not be claimed while this is the case.
+    pub fn is_degraded(&self) -> bool { self.health.is_degraded() }
+
     /// Setup the datastore.
     ///
     /// This includes all the schema and data migrations, along with stored procedures for data
@@ -86,41 +102,53 @@ impl DataStore {
     /// * If the job cannot be created
     /// * If the job has an unknown VCS type
     pub fn create_job(&self, job: &jobsrv::Job) -> Result<jobsrv::Job> {
-        let conn = self.pool.get()?;
-
-        let channel = if job.has_channel() {
-            Some(job.get_channel())
-        } else {
-            None
-        };
-
-        if job.get_project().get_vcs_type() == "git" {
-            let project = job.get_project();
-            let install_id: Option<String> = {
-                if project.has_vcs_installation_id() {
-                    Some(project.get_vcs_installation_id().to_string())
+        self.write(|conn| {
+                let channel = if job.has_channel() {
+                    Some(job.get_channel())
                 } else {
                     None
-                }
-            };
-
-            let rows = conn.query("SELECT * FROM insert_job_v3($1, $2, $3, $4, $5, $6, $7, $8, \
-                                   $9)",
-                                  &[&(job.get_owner_id() as i64),
-                                    &(project.get_id() as i64),
-                                    &project.get_name(),
-                                    &(project.get_owner_id() as i64),
-                                    &project.get_plan_path(),
-                                    &project.get_vcs_type(),
-                                    &vec![Some(project.get_vcs_data().to_string()), install_id],
-                                    &channel,
-                                    &job.get_target()])
-                           .map_err(Error::JobCreate)?;
-            let job = row_to_job(&rows.get(0))?;
-            Ok(job)
-        } else {
-            Err(Error::UnknownVCS)
-        }
+                };
+
+                let project = job.get_project();
+                // The second VCS argument is the GitHub App installation of a `git` project, or
+                // the name of the secret holding the credential of a `generic_git` one. The
+                // credential itself never reaches the jobs table.
+                let vcs_argument: Option<String> = match project.get_vcs_type() {
+                    "git" if project.has_vcs_installation_id() => {
+                        Some(project.get_vcs_installation_id().to_string())
+                    }
+                    "generic_git" if project.has_vcs_credential() => {
+                        Some(project.get_vcs_credential().to_string())
+                    }
+                    "git" | "generic_git" => None,
+                    _ => return Err(Error::UnknownVCS),
+                };
+
+                let vcs_ref = if job.has_vcs_ref() {
+                    Some(job.get_vcs_ref())
+                } else {
+                    None
+                };
+
+                let rows = conn.query("SELECT * FROM insert_job_v6($1, $2, $3, $4, $5, $6, $7, \
+                                       $8, $9, $10, $11, $12)",
+                                      &[&(job.get_owner_id() as i64),
+                                        &(project.get_id() as i64),
+                                        &project.get_name(),
+                                        &(project.get_owner_id() as i64),
+                                        &project.get_plan_path(),
+                                        &project.get_vcs_type(),
+                                        &vec![Some(project.get_vcs_data().to_string()),
+                                              vcs_argument],
+                                        &channel,
+                                        &job.get_target(),
+                                        &project.get_required_worker_labels().to_vec(),
+                                        &job.get_no_cache(),
+                                        &vcs_ref])
+                               .map_err(Error::JobCreate)?;
+                let job = row_to_job(&rows.get(0))?;
+                Ok(job)
+            })
     }
 
     /// Get a job from the database. If the job does not exist, but the database was active, we'll
@@ -131,21 +159,23 @@ impl DataStore {
     /// * If a connection cannot be gotten from the pool
     /// * If the job cannot be selected from the database
     pub fn get_job(&self, get_job: &jobsrv::JobGet) -> Result<Option<jobsrv::Job>> {
-        let conn = self.pool.get()?;
-        let rows = &conn.query("SELECT * FROM get_job_v1($1)",
-                               &[&(get_job.get_id() as i64)])
-                        .map_err(Error::JobGet)?;
-
-        if !rows.is_empty() {
-            let row = rows.get(0);
-            let job = row_to_job(&row)?;
-            Ok(Some(job))
-        } else {
-            Ok(None)
-        }
+        self.read(|conn| {
+                let rows = &conn.query("SELECT * FROM get_job_v1($1)",
+                                       &[&(get_job.get_id() as i64)])
+                                .map_err(Error::JobGet)?;
+
+                if !rows.is_empty() {
+                    let row = rows.get(0);
+                    let job = row_to_job(&row)?;
+                    Ok(Some(job))
+                } else {
+                    Ok(None)
+                }
+            })
     }
 
-    /// Get the next pending job from the list of pending jobs
+    /// Get the next pending job from the list of pending jobs whose required worker labels
+    /// are all in `labels`
     /// Atomically set the job state to Dispatching, and set the worker id
     ///
     /// # Errors
@@ -153,19 +183,24 @@ impl DataStore {
     /// * If a connection cannot be gotten from the pool
     /// * If the pending jobs cannot be selected from the database
     /// * If the row returned cannot be translated into a Job
-    pub fn next_pending_job(&self, worker: &str, target: &str) -> Result<Option<jobsrv::Job>> {
-        let conn = self.pool.get()?;
-        let rows = &conn.query("SELECT * FROM next_pending_job_v2($1, $2)",
-                               &[&worker, &target])
-                        .map_err(Error::JobPending)?;
-
-        if !rows.is_empty() {
-            let row = rows.get(0);
-            let job = row_to_job(&row)?;
-            Ok(Some(job))
-        } else {
-            Ok(None)
-        }
+    pub fn next_pending_job(&self,
+                            worker: &str,
+                            target: &str,
+                            labels: &[String])
+                            -> Result<Option<jobsrv::Job>> {
+        self.write(|conn| {
+                let rows = &conn.query("SELECT * FROM next_pending_job_v3($1, $2, $3)",
+                                       &[&worker, &target, &labels.to_vec()])
+                                .map_err(Error::JobPending)?;
+
+                if !rows.is_empty() {
+                    let row = rows.get(0);
+                    let job = row_to_job(&row)?;
+                    Ok(Some(job))
+                } else {
+                    Ok(None)
+                }
+            })
     }
 
     /// Get a list of cancel-pending jobs
@@ -176,15 +211,16 @@ impl DataStore {
     /// * If the cancel pending jobs cannot be selected from the database
     /// * If the row returned cannot be translated into a Job
     pub fn get_cancel_pending_jobs(&self) -> Result<Vec<jobsrv::Job>> {
-        let mut jobs = Vec::new();
-        let conn = self.pool.get()?;
-        let rows = &conn.query("SELECT * FROM get_cancel_pending_jobs_v1()", &[])
-                        .map_err(Error::JobPending)?;
-        for row in rows {
-            let job = row_to_job(&row)?;
-            jobs.push(job);
-        }
-        Ok(jobs)
+        self.read(|conn| {
+                let mut jobs = Vec::new();
+                let rows = &conn.query("SELECT * FROM get_cancel_pending_jobs_v1()", &[])
+                                .map_err(Error::JobPending)?;
+                for row in rows {
+                    let job = row_to_job(&row)?;
+                    jobs.push(job);
+                }
+                Ok(jobs)
+            })
     }
 
     /// Get a list of Dispatched jobs
@@ -195,15 +231,33 @@ impl DataStore {
     /// * If the cancel pending jobs cannot be selected from the database
     /// * If the row returned cannot be translated into a Job
     pub fn get_dispatched_jobs(&self) -> Result<Vec<jobsrv::Job>> {
-        let mut jobs = Vec::new();
-        let conn = self.pool.get()?;
-        let rows = &conn.query("SELECT * FROM get_dispatched_jobs_v1()", &[])
-                        .map_err(Error::JobGet)?;
-        for row in rows {
-            let job = row_to_job(&row)?;
-            jobs.push(job);
-        }
-        Ok(jobs)
+        self.read(|conn| {
+                let mut jobs = Vec::new();
+                let rows = &conn.query("SELECT * FROM get_dispatched_jobs_v1()", &[])
+                                .map_err(Error::JobGet)?;
+                for row in rows {
+                    let job = row_to_job(&row)?;
+                    jobs.push(job);
+                }
+                Ok(jobs)
+            })
+    }
+
+    /// Get the distinct sets of worker labels required by Pending jobs for a target
+    ///
+    /// # Errors
+    ///
+    /// * If a connection cannot be gotten from the pool
+    /// * If the label sets cannot be selected from the database
+    pub fn get_pending_worker_labels(&self, target: &str) -> Result<Vec<Vec<String>>> {
+        self.read(|conn| {
+                let rows = &conn.query("SELECT * FROM get_pending_worker_labels_v1($1)",
+                                       &[&target])
+                                .map_err(Error::JobPending)?;
+                Ok(rows.iter()
+                       .map(|row| row.get("required_worker_labels"))
+                       .collect())
+            })
     }
 
     /// Count the number of jobs in a given state
@@ -212,12 +266,31 @@ impl DataStore {
     ///
     /// * If a connection cannot be gotten from the pool
     pub fn count_jobs(&self, job_state: jobsrv::JobState) -> Result<i64> {
-        let conn = self.pool.get()?;
-        let rows = &conn.query("SELECT * FROM count_jobs_v1($1)", &[&job_state.to_string()])
-                        .map_err(Error::JobGet)?;
-        assert!(rows.len() == 1);
-        let count: i64 = rows.get(0).get("count_jobs_v1");
-        Ok(count)
+        self.read(|conn| {
+                let rows = &conn.query("SELECT * FROM count_jobs_v1($1)",
+                                       &[&job_state.to_string()])
+                                .map_err(Error::JobGet)?;
+                assert!(rows.len() == 1);
+                let count: i64 = rows.get(0).get("count_jobs_v1");
+                Ok(count)
+            })
+    }
+
+    /// Count the jobs of an origin that are pending, dispatched or being built, which count
+    /// against its concurrency limit
+    ///
+    /// # Errors
+    ///
+    /// * If a connection cannot be gotten from the pool
+    pub fn count_origin_jobs_in_flight(&self, origin: &str) -> Result<i64> {
+        self.read(|conn| {
+                let rows = &conn.query("SELECT * FROM count_origin_jobs_in_flight_v1($1)",
+                                       &[&origin])
+                                .map_err(Error::JobGet)?;
+                assert!(rows.len() == 1);
+                let count: i64 = rows.get(0).get("count_origin_jobs_in_flight_v1");
+                Ok(count)
+            })
     }
 
     /// Updates a job. Currently, this entails updating the state,
@@ -229,61 +302,178 @@ impl DataStore {
     /// * If a connection cannot be gotten from the pool
     /// * If the job cannot be updated in the database
     pub fn update_job(&self, job: &jobsrv::Job) -> Result<()> {
-        let conn = self.pool.get()?;
-        let job_id = job.get_id() as i64;
-        let job_state = job.get_state().to_string();
-
-        // Note: the following fields may all be NULL. As currently
-        // coded, if they are NULL, then the corresponding fields in
-        // the database will also be updated to be NULL. This should
-        // be OK, though, because they shouldn't be changing anyway.
-        let build_started_at = if job.has_build_started_at() {
-            Some(job.get_build_started_at().parse::<DateTime<Utc>>().unwrap())
-        } else {
-            None
-        };
+        self.write(|conn| {
+                let job_id = job.get_id() as i64;
+                let job_state = job.get_state().to_string();
+
+                // Note: the following fields may all be NULL. As currently
+                // coded, if they are NULL, then the corresponding fields in
+                // the database will also be updated to be NULL. This should
+                // be OK, though, because they shouldn't be changing anyway.
+                let build_started_at = if job.has_build_started_at() {
+                    Some(job.get_build_started_at().parse::<DateTime<Utc>>().unwrap())
+                } else {
+                    None
+                };
 
-        let build_finished_at = if job.has_build_finished_at() {
-            Some(job.get_build_finished_at()
-                    .parse::<DateTime<Utc>>()
-                    .unwrap())
-        } else {
-            None
-        };
+                let build_finished_at = if job.has_build_finished_at() {
+                    Some(job.get_build_finished_at()
+                            .parse::<DateTime<Utc>>()
+                            .unwrap())
+                } else {
+                    None
+                };
 
-        let ident = if job.has_package_ident() {
-            Some(job.get_package_ident().to_string())
-        } else {
-            None
-        };
+                let ident = if job.has_package_ident() {
+                    Some(job.get_package_ident().to_string())
+                } else {
+                    None
+                };
 
-        let (err_code, err_msg) = if job.has_error() {
-            (Some(job.get_error().get_code() as i32), Some(job.get_error().get_msg()))
-        } else {
-            (None, None)
-        };
+                let (err_code, err_msg) = if job.has_error() {
+                    (Some(job.get_error().get_code() as i32), Some(job.get_error().get_msg()))
+                } else {
+                    (None, None)
+                };
 
-        conn.execute("SELECT update_job_v3($1, $2, $3, $4, $5, $6, $7)",
-                     &[&job_id,
-                       &job_state,
-                       &build_started_at,
-                       &build_finished_at,
-                       &ident,
-                       &err_code,
-                       &err_msg])
-            .map_err(Error::JobSetState)?;
+                // Disk usage is only reported with the finished build; NULL keeps what is stored
+                let peak_workspace_bytes = if job.has_peak_workspace_bytes() {
+                    Some(job.get_peak_workspace_bytes() as i64)
+                } else {
+                    None
+                };
 
-        Ok(())
+                let artifact_bytes = if job.has_artifact_bytes() {
+                    Some(job.get_artifact_bytes() as i64)
+                } else {
+                    None
+                };
+
+                conn.execute("SELECT update_job_v6($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                             &[&job_id,
+                               &job_state,
+                               &build_started_at,
+                               &build_finished_at,
+                               &ident,
+                               &err_code,
+                               &err_msg,
+                               &peak_workspace_bytes,
+                               &artifact_bytes])
+                    .map_err(Error::JobSetState)?;
+
+                Ok(())
+            })
     }
 
     /// Marks a given job's logs as having been archived. The location
     /// and mechanism for retrieval are dependent on the configured archiving
     /// mechanism.
     pub fn mark_as_archived(&self, job_id: u64) -> Result<()> {
-        let conn = self.pool.get()?;
-        conn.execute("SELECT mark_as_archived_v1($1)", &[&(job_id as i64)])
-            .map_err(Error::JobMarkArchived)?;
-        Ok(())
+        self.write(|conn| {
+                conn.execute("SELECT mark_as_archived_v1($1)", &[&(job_id as i64)])
+                    .map_err(Error::JobMarkArchived)?;
+                Ok(())
+            })
+    }
+
+    /// Stores the excerpt of a job's log shown when the job failed. Nothing is stored for a
+    /// job that already ended otherwise.
+    pub fn set_job_failure_excerpt(&self, job_id: u64, excerpt: &str) -> Result<()> {
+        self.write(|conn| {
+                conn.execute("SELECT set_job_failure_excerpt_v1($1, $2)",
+                             &[&(job_id as i64), &excerpt])
+                    .map_err(Error::JobFailureExcerpt)?;
+                Ok(())
+            })
+    }
+
+    /// The failure excerpts of a group's failed jobs, by job id
+    pub fn get_group_failure_excerpts(&self, group_id: u64) -> Result<HashMap<u64, String>> {
+        self.read(|conn| {
+                let rows = conn.query("SELECT * FROM get_group_failure_excerpts_v1($1)",
+                                      &[&(group_id as i64)])
+                               .map_err(Error::JobGroupGet)?;
+
+                let mut excerpts = HashMap::new();
+                for row in rows.iter() {
+                    let job_id: i64 = row.get("job_id");
+                    excerpts.insert(job_id as u64, row.get("failure_excerpt"));
+                }
+                Ok(excerpts)
+            })
+    }
+
+    /// Stores the latest progress of a running job in place of the previous one. Progress
+    /// arriving within 15 seconds of the stored one, or for a job no longer running, is dropped.
+    pub fn set_job_progress(&self, progress: &jobsrv::JobProgress) -> Result<()> {
+        let step = if progress.has_step() {
+            Some(progress.get_step())
+        } else {
+            None
+        };
+
+        self.write(|conn| {
+                conn.execute("SELECT set_job_progress_v1($1, $2, $3, $4)",
+                             &[&(progress.get_job_id() as i64),
+                               &progress.get_phase(),
+                               &step,
+                               &(progress.get_phase_elapsed_secs() as i64)])
+                    .map_err(Error::JobProgress)?;
+                Ok(())
+            })
+    }
+
+    /// Records the lease of a running job, adding `event` to the job's events
+    pub fn set_job_lease(&self,
+                         job_id: u64,
+                         leased_at: DateTime<Utc>,
+                         expires_at: DateTime<Utc>,
+                         event: &str)
+                         -> Result<()> {
+        self.write(|conn| {
+                conn.execute("SELECT set_job_lease_v1($1, $2, $3, $4)",
+                             &[&(job_id as i64), &leased_at, &expires_at, &event])
+                    .map_err(Error::JobLease)?;
+                Ok(())
+            })
+    }
+
+    pub fn add_job_event(&self, job_id: u64, event: &str, detail: &str) -> Result<()> {
+        self.write(|conn| {
+                conn.execute("SELECT add_job_event_v1($1, $2, $3)",
+                             &[&(job_id as i64), &event, &detail])
+                    .map_err(Error::JobLease)?;
+                Ok(())
+            })
+    }
+
+    /// Deletes the job events older than `before`, returning how many there were
+    pub fn prune_job_events(&self, before: DateTime<Utc>) -> Result<u64> {
+        self.write(|conn| {
+                let rows = conn.query("SELECT prune_job_events_v1($1)", &[&before])
+                               .map_err(Error::JobEventsPrune)?;
+                Ok(rows.iter()
+                       .next()
+                       .map_or(0, |row| row.get::<usize, i64>(0) as u64))
+            })
+    }
+
+    /// The progress of a group's running jobs, by job id
+    pub fn get_group_progress(&self, group_id: u64) -> Result<HashMap<u64, jobsrv::JobProgress>> {
+        self.read(|conn| {
+                let rows = conn.query("SELECT * FROM get_group_progress_v1($1)",
+                                      &[&(group_id as i64)])
+                               .map_err(Error::JobGroupGet)?;
+
+                let mut progress = HashMap::new();
+                for row in rows.iter() {
+                    let job_id: i64 = row.get("job_id");
+                    if let Some(p) = row_to_job_progress(&row) {
+                        progress.insert(job_id as u64, p);
+                    }
+                }
+                Ok(progress)
+            })
     }
 
     /// Create or update a busy worker
@@ -293,15 +483,15 @@ impl DataStore {
     /// * If the pool has no connections available
     /// * If the busy worker cannot be created
     pub fn upsert_busy_worker(&self, bw: &jobsrv::BusyWorker) -> Result<()> {
-        let conn = self.pool.get()?;
-
-        conn.execute("SELECT FROM upsert_busy_worker_v1($1, $2, $3)",
-                     &[&bw.get_ident(),
-                       &(bw.get_job_id() as i64),
-                       &bw.get_quarantined()])
-            .map_err(Error::BusyWorkerUpsert)?;
-
-        Ok(())
+        self.write(|conn| {
+                conn.execute("SELECT FROM upsert_busy_worker_v1($1, $2, $3)",
+                             &[&bw.get_ident(),
+                               &(bw.get_job_id() as i64),
+                               &bw.get_quarantined()])
+                    .map_err(Error::BusyWorkerUpsert)?;
+
+                Ok(())
+            })
     }
 
     /// Delete a busy worker
@@ -311,13 +501,13 @@ impl DataStore {
     /// * If the pool has no connections available
     /// * If the busy worker cannot be created
     pub fn delete_busy_worker(&self, bw: &jobsrv::BusyWorker) -> Result<()> {
-        let conn = self.pool.get()?;
-
-        conn.execute("SELECT FROM delete_busy_worker_v1($1, $2)",
-                     &[&bw.get_ident(), &(bw.get_job_id() as i64)])
-            .map_err(Error::BusyWorkerDelete)?;
+        self.write(|conn| {
+                conn.execute("SELECT FROM delete_busy_worker_v1($1, $2)",
+                             &[&bw.get_ident(), &(bw.get_job_id() as i64)])
+                    .map_err(Error::BusyWorkerDelete)?;
 
-        Ok(())
+                Ok(())
+            })
     }
 
     /// Get a list of busy workers
@@ -327,184 +517,430 @@ impl DataStore {
     /// * If the pool has no connections available
     /// * If the busy workers cannot be created
     pub fn get_busy_workers(&self) -> Result<Vec<jobsrv::BusyWorker>> {
-        let conn = self.pool.get()?;
-
-        let rows = conn.query("SELECT * FROM get_busy_workers_v1()", &[])
-                       .map_err(Error::BusyWorkersGet)?;
-
-        let mut workers = Vec::new();
-        for row in rows.iter() {
-            let bw = row_to_busy_worker(&row)?;
-            workers.push(bw);
-        }
+        self.read(|conn| {
+                let rows = conn.query("SELECT * FROM get_busy_workers_v1()", &[])
+                               .map_err(Error::BusyWorkersGet)?;
+
+                let mut workers = Vec::new();
+                for row in rows.iter() {
+                    let bw = row_to_busy_worker(&row)?;
+                    workers.push(bw);
+                }
 
-        Ok(workers)
+                Ok(workers)
+            })
     }
 
     pub fn is_job_group_active(&self, project_name: &str) -> Result<bool> {
-        let conn = self.pool.get()?;
-
-        let rows = &conn.query("SELECT * FROM check_active_group_v1($1)", &[&project_name])
-                        .map_err(Error::JobGroupGet)?;
+        self.read(|conn| {
+                let rows = &conn.query("SELECT * FROM check_active_group_v1($1)", &[&project_name])
+                                .map_err(Error::JobGroupGet)?;
 
-        // If we get any rows back, we found one or more active groups
-        Ok(!rows.is_empty())
+                // If we get any rows back, we found one or more active groups
+                Ok(!rows.is_empty())
+            })
     }
 
     pub fn get_queued_job_group(&self, project_name: &str) -> Result<Option<jobsrv::JobGroup>> {
-        let conn = self.pool.get()?;
+        self.read(|conn| {
+                let rows = &conn.query("SELECT * FROM get_queued_group_v1($1)", &[&project_name])
+                                .map_err(Error::JobGroupGet)?;
 
-        let rows = &conn.query("SELECT * FROM get_queued_group_v1($1)", &[&project_name])
-                        .map_err(Error::JobGroupGet)?;
+                if rows.is_empty() {
+                    debug!("JobGroup {} not queued (not found)", project_name);
+                    return Ok(None);
+                }
 
-        if rows.is_empty() {
-            debug!("JobGroup {} not queued (not found)", project_name);
-            return Ok(None);
-        }
+                assert!(rows.len() == 1); // should never have more than one
 
-        assert!(rows.len() == 1); // should never have more than one
+                let mut group = self.row_to_job_group(&rows.get(0))?;
+                let group_id = group.get_id();
 
-        let mut group = self.row_to_job_group(&rows.get(0))?;
-        let group_id = group.get_id();
+                let project_rows = &conn.query("SELECT * FROM get_group_projects_for_group_v1($1)",
+                                               &[&(group_id as i64)])
+                                        .map_err(Error::JobGroupGet)?;
 
-        let project_rows = &conn.query("SELECT * FROM get_group_projects_for_group_v1($1)",
-                                       &[&(group_id as i64)])
-                                .map_err(Error::JobGroupGet)?;
+                assert!(!project_rows.is_empty()); // should at least have one
+                let projects = self.rows_to_job_group_projects(&project_rows)?;
 
-        assert!(!project_rows.is_empty()); // should at least have one
-        let projects = self.rows_to_job_group_projects(&project_rows)?;
-
-        group.set_projects(projects);
-        Ok(Some(group))
+                group.set_projects(projects);
+                Ok(Some(group))
+            })
     }
 
     pub fn get_queued_job_groups(&self) -> Result<RepeatedField<jobsrv::JobGroup>> {
-        let mut groups = RepeatedField::new();
-
-        let conn = self.pool.get()?;
+        self.read(|conn| {
+                let mut groups = RepeatedField::new();
 
-        let rows = &conn.query("SELECT * FROM get_queued_groups_v1()", &[])
-                        .map_err(Error::JobGroupGet)?;
+                let rows = &conn.query("SELECT * FROM get_queued_groups_v1()", &[])
+                                .map_err(Error::JobGroupGet)?;
 
-        for row in rows {
-            let group = self.row_to_job_group(&row)?;
-            groups.push(group);
-        }
-        Ok(groups)
+                for row in rows {
+                    let group = self.row_to_job_group(&row)?;
+                    groups.push(group);
+                }
+                Ok(groups)
+            })
     }
 
+    /// Creates a group in `group_state`, `GroupDeferredByFreeze` along with the time it is
+    /// released at or `GroupPendingApproval`
     pub fn create_job_group(&self,
                             msg: &jobsrv::JobGroupSpec,
-                            project_tuples: Vec<(String, String)>)
+                            project_tuples: Vec<(String, String)>,
+                            group_state: jobsrv::JobGroupState,
+                            frozen_until: Option<DateTime<Utc>>)
                             -> Result<jobsrv::JobGroup> {
-        let conn = self.pool.get()?;
-
-        assert!(!project_tuples.is_empty());
+        self.write(|conn| {
+                assert!(!project_tuples.is_empty());
+
+                let root_project = format!("{}/{}", msg.get_origin(), msg.get_package());
+
+                let (project_names, project_idents): (Vec<String>, Vec<String>) =
+                    project_tuples.iter().cloned().unzip();
+
+                let rows = conn.query("SELECT * FROM insert_group_v7($1, $2, $3, $4, $5, $6, $7, \
+                                       $8, $9)",
+                                      &[&root_project,
+                                        &project_names,
+                                        &project_idents,
+                                        &msg.get_target(),
+                                        &msg.get_no_cache(),
+                                        &(msg.get_max_concurrency() as i32),
+                                        &msg.get_failure_policy().to_string(),
+                                        &group_state.to_string(),
+                                        &frozen_until])
+                               .map_err(Error::JobGroupCreate)?;
+
+                let mut group = self.row_to_job_group(&rows.get(0))?;
+                let mut projects = RepeatedField::new();
+
+                for (name, ident) in project_tuples {
+                    let mut project = jobsrv::JobGroupProject::new();
+                    project.set_name(name);
+                    project.set_ident(ident);
+                    project.set_state(jobsrv::JobGroupProjectState::NotStarted);
+                    project.set_target(msg.get_target().to_string());
+                    projects.push(project);
+                }
 
-        let root_project = format!("{}/{}", msg.get_origin(), msg.get_package());
+                group.set_projects(projects);
 
-        let (project_names, project_idents): (Vec<String>, Vec<String>) =
-            project_tuples.iter().cloned().unzip();
+                debug!("JobGroup created: {:?}", group);
 
-        let rows = conn.query("SELECT * FROM insert_group_v3($1, $2, $3, $4)",
-                              &[&root_project,
-                                &project_names,
-                                &project_idents,
-                                &msg.get_target()])
-                       .map_err(Error::JobGroupCreate)?;
+                Ok(group)
+            })
+    }
 
-        let mut group = self.row_to_job_group(&rows.get(0))?;
-        let mut projects = RepeatedField::new();
+    /// Queues a group for the request, held for `quiet_period` seconds, or folds the request
+    /// into the group already queued for the project and target. A folded request moves the
+    /// group to the request's ref; the projects and quiet period stay those of the queued group.
+    pub fn queue_job_group(&self,
+                           msg: &jobsrv::JobGroupSpec,
+                           project_tuples: Vec<(String, String)>,
+                           quiet_period: u32)
+                           -> Result<jobsrv::JobGroup> {
+        self.write(|conn| {
+                assert!(!project_tuples.is_empty());
+
+                let root_project = format!("{}/{}", msg.get_origin(), msg.get_package());
+
+                let (project_names, project_idents): (Vec<String>, Vec<String>) =
+                    project_tuples.iter().cloned().unzip();
+                let vcs_ref = if msg.has_vcs_ref() {
+                    Some(msg.get_vcs_ref())
+                } else {
+                    None
+                };
+
+                let rows = conn.query("SELECT * FROM queue_group_v1($1, $2, $3, $4, $5, $6, $7, \
+                                       $8, $9)",
+                                      &[&root_project,
+                                        &project_names,
+                                        &project_idents,
+                                        &msg.get_target(),
+                                        &msg.get_no_cache(),
+                                        &(msg.get_max_concurrency() as i32),
+                                        &msg.get_failure_policy().to_string(),
+                                        &vcs_ref,
+                                        &(quiet_period as i32)])
+                               .map_err(Error::JobGroupCreate)?;
+
+                let mut group = self.row_to_job_group(&rows.get(0))?;
+
+                let project_rows = &conn.query("SELECT * FROM get_group_projects_for_group_v1($1)",
+                                               &[&(group.get_id() as i64)])
+                                        .map_err(Error::JobGroupGet)?;
+                let projects = self.rows_to_job_group_projects(&project_rows)?;
+                group.set_projects(projects);
+
+                debug!("JobGroup queued: {:?}", group);
+
+                Ok(group)
+            })
+    }
 
-        for (name, ident) in project_tuples {
-            let mut project = jobsrv::JobGroupProject::new();
-            project.set_name(name);
-            project.set_ident(ident);
-            project.set_state(jobsrv::JobGroupProjectState::NotStarted);
-            project.set_target(msg.get_target().to_string());
-            projects.push(project);
-        }
+    /// Moves a queued group to pending unless its quiet period is still running, or it left the
+    /// queue in the meantime. Returns whether the group was released.
+    pub fn release_job_group(&self, group_id: u64) -> Result<bool> {
+        self.write(|conn| {
+                let rows = conn.query("SELECT * FROM release_group_v1($1)", &[&(group_id as i64)])
+                               .map_err(Error::JobGroupSetState)?;
+                Ok(!rows.is_empty())
+            })
+    }
 
-        group.set_projects(projects);
+    /// Changes how many of the group's jobs may be dispatched at once. Returns `None` when the
+    /// group does not exist.
+    pub fn set_job_group_max_concurrency(&self,
+                                         group_id: u64,
+                                         max_concurrency: u32)
+                                         -> Result<Option<jobsrv::JobGroup>> {
+        self.write(|conn| {
+                let rows = conn.query("SELECT * FROM set_group_max_concurrency_v1($1, $2)",
+                                      &[&(group_id as i64), &(max_concurrency as i32)])
+                               .map_err(Error::JobGroupUpdate)?;
+
+                if rows.is_empty() {
+                    return Ok(None);
+                }
+                Ok(Some(self.row_to_job_group(&rows.get(0))?))
+            })
+    }
 
-        debug!("JobGroup created: {:?}", group);
+    /// Queues a group awaiting approval when `approved`, or defers it until `frozen_until` when
+    /// that is set, and cancels it otherwise. Returns `None` when no such group awaits approval.
+    pub fn review_job_group(&self,
+                            group_id: u64,
+                            approved: bool,
+                            frozen_until: Option<DateTime<Utc>>)
+                            -> Result<Option<jobsrv::JobGroup>> {
+        self.write(|conn| {
+                let rows = conn.query("SELECT * FROM review_group_v2($1, $2, $3)",
+                                      &[&(group_id as i64), &approved, &frozen_until])
+                               .map_err(Error::JobGroupSetState)?;
+
+                if rows.is_empty() {
+                    return Ok(None);
+                }
+                Ok(Some(self.row_to_job_group(&rows.get(0))?))
+            })
+    }
 
-        Ok(group)
+    /// Cancels the groups that have awaited approval since before `before`, returning their ids
+    pub fn expire_job_groups_pending_approval(&self, before: DateTime<Utc>) -> Result<Vec<u64>> {
+        self.write(|conn| {
+                let rows = conn.query("SELECT * FROM expire_groups_pending_approval_v1($1)",
+                                      &[&before])
+                               .map_err(Error::JobGroupSetState)?;
+                Ok(rows.iter()
+                       .map(|row| row.get::<usize, i64>(0) as u64)
+                       .collect())
+            })
     }
 
     pub fn cancel_job_group(&self, group_id: u64) -> Result<()> {
-        let conn = self.pool.get()?;
-        conn.query("SELECT cancel_group_v1($1)", &[&(group_id as i64)])
-            .map_err(Error::JobGroupCancel)?;
+        self.write(|conn| {
+                conn.query("SELECT cancel_group_v1($1)", &[&(group_id as i64)])
+                    .map_err(Error::JobGroupCancel)?;
 
-        Ok(())
+                Ok(())
+            })
     }
 
     pub fn create_audit_entry(&self, msg: &jobsrv::JobGroupAudit) -> Result<()> {
-        let conn = self.pool.get()?;
-        conn.query("SELECT add_audit_jobs_entry_v1($1, $2, $3, $4, $5)",
-                   &[&(msg.get_group_id() as i64),
-                     &(msg.get_operation() as i16),
-                     &(msg.get_trigger() as i16),
-                     &(msg.get_requester_id() as i64),
-                     &msg.get_requester_name().to_string()])
-            .map_err(Error::JobGroupAudit)?;
-
-        Ok(())
+        self.write(|conn| {
+                conn.query("SELECT add_audit_jobs_entry_v1($1, $2, $3, $4, $5)",
+                           &[&(msg.get_group_id() as i64),
+                             &(msg.get_operation() as i16),
+                             &(msg.get_trigger() as i16),
+                             &(msg.get_requester_id() as i64),
+                             &msg.get_requester_name().to_string()])
+                    .map_err(Error::JobGroupAudit)?;
+
+                Ok(())
+            })
     }
 
     pub fn get_job_group_origin(&self,
                                 msg: &jobsrv::JobGroupOriginGet)
                                 -> Result<jobsrv::JobGroupOriginResponse> {
-        let origin = msg.get_origin();
-        let limit = msg.get_limit();
+        self.read(|conn| {
+                let origin = msg.get_origin();
+                let limit = msg.get_limit();
 
-        let conn = self.pool.get()?;
-        let rows = &conn.query("SELECT * FROM get_job_groups_for_origin_v2($1, $2)",
-                               &[&origin, &(limit as i32)])
-                        .map_err(Error::JobGroupOriginGet)?;
+                let rows = &conn.query("SELECT * FROM get_job_groups_for_origin_v2($1, $2)",
+                                       &[&origin, &(limit as i32)])
+                                .map_err(Error::JobGroupOriginGet)?;
 
-        let mut response = jobsrv::JobGroupOriginResponse::new();
-        let mut job_groups = RepeatedField::new();
+                let mut response = jobsrv::JobGroupOriginResponse::new();
+                let mut job_groups = RepeatedField::new();
 
-        for row in rows {
-            let group = self.row_to_job_group(&row)?;
-            job_groups.push(group);
-        }
+                for row in rows {
+                    let group = self.row_to_job_group(&row)?;
+                    job_groups.push(group);
+                }
 
-        response.set_job_groups(job_groups);
-        Ok(response)
+                response.set_job_groups(job_groups);
+                Ok(response)
+            })
     }
 
-    pub fn get_job_group(&self, msg: &jobsrv::JobGroupGet) -> Result<Option<jobsrv::JobGroup>> {
-        let group_id = msg.get_group_id();
-        let include_projects = msg.get_include_projects();
+    /// When each account last submitted a group for a project of the origin
+    pub fn get_job_group_submitters(&self,
+                                    msg: &jobsrv::JobGroupSubmittersGet)
+                                    -> Result<jobsrv::JobGroupSubmitters> {
+        self.read(|conn| {
+                let rows = conn.query("SELECT * FROM get_origin_group_submitters_v1($1)",
+                                      &[&msg.get_origin()])
+                               .map_err(Error::JobGroupSubmitters)?;
+
+                let mut submitters = RepeatedField::new();
+                for row in rows.iter() {
+                    let requester_id: i64 = row.get("requester_id");
+                    let submitted_at: DateTime<Utc> = row.get("last_submitted_at");
+
+                    let mut submitter = jobsrv::JobGroupSubmitter::new();
+                    submitter.set_requester_id(requester_id as u64);
+                    submitter.set_last_submitted_at(timestamp::to_rfc3339(&submitted_at));
+                    submitters.push(submitter);
+                }
 
-        let conn = self.pool.get()?;
-        let rows = &conn.query("SELECT * FROM get_group_v1($1)", &[&(group_id as i64)])
-                        .map_err(Error::JobGroupGet)?;
+                let mut response = jobsrv::JobGroupSubmitters::new();
+                response.set_submitters(submitters);
+                Ok(response)
+            })
+    }
 
-        if rows.is_empty() {
-            warn!("JobGroup id {} not found", group_id);
-            return Ok(None);
-        }
+    /// The feature flags operators have set, flags never set are not included
+    pub fn get_feature_flags(&self) -> Result<Vec<jobsrv::JobFeatureFlag>> {
+        self.read(|conn| {
+                let rows = conn.query("SELECT * FROM get_feature_flags_v1()", &[])
+                               .map_err(Error::FeatureFlagsGet)?;
+                Ok(rows.iter().map(|row| row_to_feature_flag(&row)).collect())
+            })
+    }
 
-        assert!(rows.len() == 1); // should never have more than one
+    /// Sets a feature flag, auditing the change with its requester and the flag's former state
+    pub fn set_feature_flag(&self,
+                            msg: &jobsrv::JobFeatureFlagSet)
+                            -> Result<jobsrv::JobFeatureFlag> {
+        self.write(|conn| {
+                let rows = conn.query("SELECT * FROM set_feature_flag_v1($1, $2, $3, $4, $5)",
+                                      &[&msg.get_name(),
+                                        &msg.get_enabled(),
+                                        &(msg.get_rollout_percent() as i16),
+                                        &(msg.get_requester_id() as i64),
+                                        &msg.get_requester_name()])
+                               .map_err(Error::FeatureFlagSet)?;
+                Ok(row_to_feature_flag(&rows.get(0)))
+            })
+    }
 
-        let mut group = self.row_to_job_group(&rows.get(0))?;
+    /// The build freeze windows of an origin
+    pub fn get_origin_freeze_windows(&self, origin: &str) -> Result<jobsrv::JobFreezeWindows> {
+        self.read(|conn| {
+                let rows = conn.query("SELECT * FROM get_origin_freeze_windows_v1($1)", &[&origin])
+                               .map_err(Error::FreezeWindowsGet)?;
+                rows_to_freeze_windows(origin, &rows)
+            })
+    }
 
-        if include_projects {
-            let project_rows = &conn.query("SELECT * FROM get_group_projects_for_group_v1($1)",
-                                           &[&(group_id as i64)])
-                                    .map_err(Error::JobGroupGet)?;
+    /// Replaces every build freeze window of the origin with those of `msg`
+    pub fn set_origin_freeze_windows(&self,
+                                     msg: &jobsrv::JobFreezeWindowsSet)
+                                     -> Result<jobsrv::JobFreezeWindows> {
+        let windows = msg.get_windows();
+        let column = |value: fn(&jobsrv::JobFreezeWindow) -> &str| -> Vec<Option<String>> {
+            windows.iter()
+                   .map(|w| Some(value(w)).filter(|v| !v.is_empty()).map(str::to_string))
+                   .collect()
+        };
+        let kinds: Vec<String> = windows.iter().map(|w| w.get_kind().to_string()).collect();
+        let timezones: Vec<String> = windows.iter()
+                                            .map(|w| w.get_timezone().to_string())
+                                            .collect();
+
+        self.write(|conn| {
+                let rows = conn.query("SELECT * FROM set_origin_freeze_windows_v1($1, $2, $3, \
+                                       $4, $5, $6, $7, $8, $9, $10)",
+                                      &[&msg.get_origin(),
+                                        &kinds,
+                                        &timezones,
+                                        &column(jobsrv::JobFreezeWindow::get_start_day),
+                                        &column(jobsrv::JobFreezeWindow::get_start_time),
+                                        &column(jobsrv::JobFreezeWindow::get_end_day),
+                                        &column(jobsrv::JobFreezeWindow::get_end_time),
+                                        &column(jobsrv::JobFreezeWindow::get_starts_at),
+                                        &column(jobsrv::JobFreezeWindow::get_ends_at),
+                                        &msg.get_requester_name()])
+                               .map_err(Error::FreezeWindowsSet)?;
+                rows_to_freeze_windows(msg.get_origin(), &rows)
+            })
+    }
 
-            assert!(!project_rows.is_empty()); // should at least have one
-            let projects = self.rows_to_job_group_projects(&project_rows)?;
+    /// Moves a group deferred by a build freeze on to its new release time, or queues it when
+    /// the freeze is over. Groups no longer deferred are left alone.
+    pub fn set_job_group_frozen_until(&self,
+                                      group_id: u64,
+                                      frozen_until: Option<DateTime<Utc>>)
+                                      -> Result<()> {
+        self.write(|conn| {
+                conn.execute("SELECT set_group_frozen_until_v1($1, $2)",
+                             &[&(group_id as i64), &frozen_until])
+                    .map_err(Error::JobGroupSetState)?;
+                Ok(())
+            })
+    }
 
-            group.set_projects(projects);
-        }
+    pub fn get_job_group(&self, msg: &jobsrv::JobGroupGet) -> Result<Option<jobsrv::JobGroup>> {
+        self.read(|conn| {
+                let group_id = msg.get_group_id();
+                let include_projects = msg.get_include_projects();
 
-        Ok(Some(group))
+                let rows = &conn.query("SELECT * FROM get_group_v1($1)", &[&(group_id as i64)])
+                                .map_err(Error::JobGroupGet)?;
+
+                if rows.is_empty() {
+                    warn!("JobGroup id {} not found", group_id);
+                    return Ok(None);
+                }
+
+                assert!(rows.len() == 1); // should never have more than one
+
+                let mut group = self.row_to_job_group(&rows.get(0))?;
+
+                let trigger_rows = &conn.query("SELECT * FROM get_group_trigger_v1($1)",
+                                               &[&(group_id as i64)])
+                                        .map_err(Error::JobGroupGet)?;
+                if !trigger_rows.is_empty() {
+                    let trigger: i16 = trigger_rows.get(0).get(0);
+                    if let Some(trigger) = jobsrv::JobGroupTrigger::from_i32(i32::from(trigger)) {
+                        group.set_trigger(trigger);
+                    }
+                }
+
+                if include_projects {
+                    let project_rows = &conn.query("SELECT * FROM \
+                                                    get_group_projects_for_group_v1($1)",
+                                                   &[&(group_id as i64)])
+                                            .map_err(Error::JobGroupGet)?;
+
+                    assert!(!project_rows.is_empty()); // should at least have one
+                    let projects = self.rows_to_job_group_projects(&project_rows)?;
+                    let in_flight =
+                        projects.iter()
+                                .filter(|p| {
+                                    p.get_state() == jobsrv::JobGroupProjectState::InProgress
+                                })
+                                .count();
+
+                    group.set_projects(projects);
+                    group.set_in_flight(in_flight as u32);
+                }
+
+                Ok(Some(group))
+            })
     }
 
     fn row_to_job_group(&self, row: &postgres::rows::Row) -> Result<jobsrv::JobGroup> {
@@ -517,7 +953,7 @@ impl DataStore {
         group.set_state(group_state);
 
         let created_at = row.get::<&str, DateTime<Utc>>("created_at");
-        group.set_created_at(created_at.to_rfc3339());
+        group.set_created_at(timestamp::to_rfc3339(&created_at));
 
         let project_name: String = row.get("project_name");
         group.set_project_name(project_name);
@@ -525,6 +961,23 @@ impl DataStore {
         let target: String = row.get("target");
         group.set_target(target);
 
+        group.set_no_cache(row.get("no_cache"));
+
+        let max_concurrency: i32 = row.get("max_concurrency");
+        group.set_max_concurrency(max_concurrency as u32);
+
+        let failure_policy: String = row.get("failure_policy");
+        group.set_failure_policy(failure_policy.parse::<jobsrv::JobGroupFailurePolicy>()?);
+
+        if let Some(frozen_until) = row.get::<&str, Option<DateTime<Utc>>>("frozen_until") {
+            group.set_frozen_until(timestamp::to_rfc3339(&frozen_until));
+        }
+        if let Some(Ok(vcs_ref)) = row.get_opt::<&str, String>("vcs_ref") {
+            group.set_vcs_ref(vcs_ref);
+        }
+        let superseded_refs: Vec<String> = row.get("superseded_refs");
+        group.set_superseded_refs(RepeatedField::from_vec(superseded_refs));
+
         Ok(group)
     }
 
@@ -566,12 +1019,13 @@ impl DataStore {
                                group_id: u64,
                                group_state: jobsrv::JobGroupState)
                                -> Result<()> {
-        let conn = self.pool.get()?;
-        let state = group_state.to_string();
-        conn.execute("SELECT set_group_state_v1($1, $2)",
-                     &[&(group_id as i64), &state])
-            .map_err(Error::JobGroupSetState)?;
-        Ok(())
+        self.write(|conn| {
+                let state = group_state.to_string();
+                conn.execute("SELECT set_group_state_v1($1, $2)",
+                             &[&(group_id as i64), &state])
+                    .map_err(Error::JobGroupSetState)?;
+                Ok(())
+            })
     }
 
     pub fn set_job_group_project_state(&self,
@@ -579,105 +1033,264 @@ impl DataStore {
                                        project_name: &str,
                                        project_state: jobsrv::JobGroupProjectState)
                                        -> Result<()> {
-        let conn = self.pool.get()?;
-        let state = project_state.to_string();
-        conn.execute("SELECT set_group_project_name_state_v1($1, $2, $3)",
-                     &[&(group_id as i64), &project_name, &state])
-            .map_err(Error::JobGroupProjectSetState)?;
-        Ok(())
+        self.write(|conn| {
+                let state = project_state.to_string();
+                conn.execute("SELECT set_group_project_name_state_v1($1, $2, $3)",
+                             &[&(group_id as i64), &project_name, &state])
+                    .map_err(Error::JobGroupProjectSetState)?;
+                Ok(())
+            })
+    }
+
+    /// Marks the named projects of the group that have not started yet as skipped by the
+    /// group's failure policy
+    pub fn skip_job_group_projects(&self, group_id: u64, project_names: &[String]) -> Result<()> {
+        self.write(|conn| {
+                conn.execute("SELECT skip_group_projects_v1($1, $2)",
+                             &[&(group_id as i64), &project_names])
+                    .map_err(Error::JobGroupProjectSetState)?;
+                Ok(())
+            })
     }
 
     pub fn set_job_group_job_state(&self, job: &jobsrv::Job) -> Result<()> {
-        let conn = self.pool.get()?;
-        let rows = &conn.query("SELECT * FROM find_group_project_v1($1, $2)",
-                               &[&(job.get_owner_id() as i64), &job.get_project().get_name()])
+        self.write(|conn| {
+                let rows = &conn.query("SELECT * FROM find_group_project_v1($1, $2)",
+                                       &[&(job.get_owner_id() as i64),
+                                         &job.get_project().get_name()])
+                                .map_err(Error::JobGroupProjectSetState)?;
+
+                // No rows means this job might not be one we care about
+                if rows.is_empty() {
+                    warn!("No project found for job id: {}", job.get_id());
+                    return Err(Error::UnknownJobGroupProjectState);
+                }
+
+                assert!(rows.len() == 1); // should never have more than one
+                let pid: i64 = rows.get(0).get("id");
+
+                let state = match job.get_state() {
+                    // The package was built and uploaded, only its promotion was refused
+                    jobsrv::JobState::Complete
+                    | jobsrv::JobState::PostProcessVerificationFailed => "Success",
+                    jobsrv::JobState::CompletedFromCache => "Cached",
+                    jobsrv::JobState::Rejected => "NotStarted", // retry submission
+                    jobsrv::JobState::Failed => "Failure",
+                    jobsrv::JobState::Pending
+                    | jobsrv::JobState::Processing
+                    | jobsrv::JobState::Dispatched => "InProgress",
+                    jobsrv::JobState::CancelPending
+                    | jobsrv::JobState::CancelProcessing
+                    | jobsrv::JobState::CancelComplete => "Canceled",
+                };
+
+                if job.get_state() == jobsrv::JobState::Complete
+                   || job.get_state() == jobsrv::JobState::CompletedFromCache
+                   || job.get_state() == jobsrv::JobState::PostProcessVerificationFailed
+                {
+                    let ident = job.get_package_ident().to_string();
+
+                    conn.execute("SELECT set_group_project_state_ident_v1($1, $2, $3, $4)",
+                                 &[&pid, &(job.get_id() as i64), &state, &ident])
+                        .map_err(Error::JobGroupProjectSetState)?;
+                } else {
+                    conn.execute("SELECT set_group_project_state_v1($1, $2, $3)",
+                                 &[&pid, &(job.get_id() as i64), &state])
                         .map_err(Error::JobGroupProjectSetState)?;
+                };
 
-        // No rows means this job might not be one we care about
-        if rows.is_empty() {
-            warn!("No project found for job id: {}", job.get_id());
-            return Err(Error::UnknownJobGroupProjectState);
-        }
+                Ok(())
+            })
+    }
 
-        assert!(rows.len() == 1); // should never have more than one
-        let pid: i64 = rows.get(0).get("id");
-
-        let state = match job.get_state() {
-            jobsrv::JobState::Complete => "Success",
-            jobsrv::JobState::Rejected => "NotStarted", // retry submission
-            jobsrv::JobState::Failed => "Failure",
-            jobsrv::JobState::Pending
-            | jobsrv::JobState::Processing
-            | jobsrv::JobState::Dispatched => "InProgress",
-            jobsrv::JobState::CancelPending
-            | jobsrv::JobState::CancelProcessing
-            | jobsrv::JobState::CancelComplete => "Canceled",
-        };
+    pub fn pending_job_groups(&self, count: i32) -> Result<Vec<jobsrv::JobGroup>> {
+        self.write(|conn| {
+                let mut groups = Vec::new();
 
-        if job.get_state() == jobsrv::JobState::Complete {
-            let ident = job.get_package_ident().to_string();
+                let group_rows = &conn.query("SELECT * FROM pending_groups_v1($1)", &[&count])
+                                      .map_err(Error::JobGroupPending)?;
 
-            conn.execute("SELECT set_group_project_state_ident_v1($1, $2, $3, $4)",
-                         &[&pid, &(job.get_id() as i64), &state, &ident])
-                .map_err(Error::JobGroupProjectSetState)?;
-        } else {
-            conn.execute("SELECT set_group_project_state_v1($1, $2, $3)",
-                         &[&pid, &(job.get_id() as i64), &state])
-                .map_err(Error::JobGroupProjectSetState)?;
-        };
+                for group_row in group_rows {
+                    let mut group = self.row_to_job_group(&group_row)?;
 
-        Ok(())
+                    let project_rows = &conn.query("SELECT * FROM \
+                                                    get_group_projects_for_group_v1($1)",
+                                                   &[&(group.get_id() as i64)])
+                                            .map_err(Error::JobGroupPending)?;
+                    let projects = self.rows_to_job_group_projects(&project_rows)?;
+
+                    group.set_projects(projects);
+                    groups.push(group);
+                }
+
+                Ok(groups)
+            })
     }
 
-    pub fn pending_job_groups(&self, count: i32) -> Result<Vec<jobsrv::JobGroup>> {
-        let mut groups = Vec::new();
+    pub fn sync_jobs(&self) -> Result<Vec<jobsrv::Job>> {
+        self.read(|conn| {
+                let mut jobs = Vec::new();
+
+                let rows = &conn.query("SELECT * FROM sync_jobs_v2()", &[])
+                                .map_err(Error::SyncJobs)?;
+
+                for row in rows.iter() {
+                    match row_to_job(&row) {
+                        Ok(job) => jobs.push(job),
+                        Err(e) => {
+                            warn!("Failed to convert row to job {}", e);
+                        }
+                    };
+                }
 
-        let conn = self.pool.get()?;
-        let group_rows = &conn.query("SELECT * FROM pending_groups_v1($1)", &[&count])
-                              .map_err(Error::JobGroupPending)?;
+                Ok(jobs)
+            })
+    }
 
-        for group_row in group_rows {
-            let mut group = self.row_to_job_group(&group_row)?;
+    pub fn set_job_sync(&self, job_id: u64) -> Result<()> {
+        self.write(|conn| {
+                conn.query("SELECT * FROM set_jobs_sync_v2($1)", &[&(job_id as i64)])
+                    .map_err(Error::SyncJobs)?;
 
-            let project_rows = &conn.query("SELECT * FROM get_group_projects_for_group_v1($1)",
-                                           &[&(group.get_id() as i64)])
-                                    .map_err(Error::JobGroupPending)?;
-            let projects = self.rows_to_job_group_projects(&project_rows)?;
+                Ok(())
+            })
+    }
 
-            group.set_projects(projects);
-            groups.push(group);
+    /// Run an idempotent read. If the connection it ran on turns out to be lost, the read is
+    /// retried once on a fresh connection; the lost one fails validation on its next checkout
+    /// and is evicted from the pool.
+    fn read<T, F>(&self, f: F) -> Result<T>
+        where F: Fn(&postgres::Connection) -> Result<T>
+    {
+        let result = {
+            let conn = self.checkout()?;
+            bounded(&conn, &f)
+        };
+        match result {
+            Err(ref err) if err.is_connection_error() => {
+                self.health.mark_degraded();
+                warn!("Lost datastore connection, retrying read, err={}", err);
+                let conn = self.checkout()?;
+                self.observe(bounded(&conn, &f))
+            }
+            result => result,
         }
+    }
 
-        Ok(groups)
+    /// Run a write. Writes are never retried, as a lost connection leaves it unknown whether
+    /// they were applied.
+    fn write<T, F>(&self, f: F) -> Result<T>
+        where F: FnOnce(&postgres::Connection) -> Result<T>
+    {
+        let conn = self.checkout()?;
+        self.observe(bounded(&conn, f))
     }
 
-    pub fn sync_jobs(&self) -> Result<Vec<jobsrv::Job>> {
-        let mut jobs = Vec::new();
-        let conn = self.pool.get()?;
+    // Checks out a connection, waiting no longer than the deadline of the RPC being handled
+    fn checkout(&self) -> Result<impl Deref<Target = postgres::Connection>> {
+        match deadline::remaining() {
+            Some(left) => {
+                if deadline::statement_timeout_ms(left) == 0 {
+                    return Err(Error::DeadlineExceeded);
+                }
+                self.pool
+                    .get_within(left)
+                    .map_err(|_| Error::DeadlineExceeded)
+            }
+            None => self.pool.get().map_err(|e| self.unavailable(e)),
+        }
+    }
 
-        let rows = &conn.query("SELECT * FROM sync_jobs_v2()", &[])
-                        .map_err(Error::SyncJobs)?;
+    // No connection could be checked out, either fresh or from the pool
+    fn unavailable(&self, err: db::error::Error) -> Error {
+        self.health.mark_degraded();
+        Error::from(err)
+    }
 
-        for row in rows.iter() {
-            match row_to_job(&row) {
-                Ok(job) => jobs.push(job),
-                Err(e) => {
-                    warn!("Failed to convert row to job {}", e);
-                }
-            };
+    fn observe<T>(&self, result: Result<T>) -> Result<T> {
+        if let Err(ref err) = result {
+            if err.is_connection_error() {
+                self.health.mark_degraded();
+            }
         }
+        result
+    }
+}
 
-        Ok(jobs)
+/// Run `f` with its statements bounded by the deadline of the RPC being handled, if there is one.
+/// The timeout is set on the session for the duration of `f` and reset before the connection
+/// returns to the pool. The pool resets it again as it hands the connection out, and discards
+/// the connection when that fails.
+fn bounded<T, F>(conn: &postgres::Connection, f: F) -> Result<T>
+    where F: FnOnce(&postgres::Connection) -> Result<T>
+{
+    let timeout_ms = match deadline::remaining() {
+        Some(left) => deadline::statement_timeout_ms(left),
+        None => return f(conn),
+    };
+    if timeout_ms == 0 {
+        return Err(Error::DeadlineExceeded);
     }
 
-    pub fn set_job_sync(&self, job_id: u64) -> Result<()> {
-        let conn = self.pool.get()?;
+    conn.batch_execute(&format!("SET statement_timeout = {}", timeout_ms))
+        .map_err(Error::DbTransaction)?;
+    let result = f(conn);
+    if let Err(err) = conn.batch_execute("RESET statement_timeout") {
+        warn!("Unable to reset statement timeout, err={}", err);
+    }
 
-        conn.query("SELECT * FROM set_jobs_sync_v2($1)", &[&(job_id as i64)])
-            .map_err(Error::SyncJobs)?;
+    match result {
+        Err(ref err) if err.is_statement_timeout() => Err(Error::DeadlineExceeded),
+        result => result,
+    }
+}
 
-        Ok(())
+/// Translate a database `feature_flags` row to a `jobsrv::JobFeatureFlag`.
+fn row_to_feature_flag(row: &postgres::rows::Row) -> jobsrv::JobFeatureFlag {
+    let mut flag = jobsrv::JobFeatureFlag::new();
+    let rollout_percent: i16 = row.get("rollout_percent");
+    let updated_at: DateTime<Utc> = row.get("updated_at");
+
+    flag.set_name(row.get("name"));
+    flag.set_enabled(row.get("enabled"));
+    flag.set_rollout_percent(rollout_percent as u32);
+    flag.set_updated_by(row.get("updated_by"));
+    flag.set_updated_at(timestamp::to_rfc3339(&updated_at));
+
+    flag
+}
+
+/// Translate the `origin_freeze_windows` rows of an origin to a `jobsrv::JobFreezeWindows`.
+fn rows_to_freeze_windows(origin: &str,
+                          rows: &postgres::rows::Rows)
+                          -> Result<jobsrv::JobFreezeWindows> {
+    let mut response = jobsrv::JobFreezeWindows::new();
+    response.set_origin(origin.to_string());
+
+    let mut windows = RepeatedField::new();
+    for row in rows {
+        let column = |name: &str| row.get::<&str, Option<String>>(name).unwrap_or_default();
+        let kind: String = row.get("kind");
+
+        let mut window = jobsrv::JobFreezeWindow::new();
+        window.set_kind(kind.parse::<jobsrv::JobFreezeWindowKind>()?);
+        window.set_timezone(row.get("timezone"));
+        window.set_start_day(column("start_day"));
+        window.set_start_time(column("start_time"));
+        window.set_end_day(column("end_day"));
+        window.set_end_time(column("end_time"));
+        window.set_starts_at(column("starts_at"));
+        window.set_ends_at(column("ends_at"));
+        windows.push(window);
+
+        // Every row of the origin is written at once
+        let updated_at: DateTime<Utc> = row.get("updated_at");
+        response.set_updated_by(row.get("updated_by"));
+        response.set_updated_at(timestamp::to_rfc3339(&updated_at));
     }
+    response.set_windows(windows);
+
+    Ok(response)
 }
 
 /// Translate a database `busy_workers` row to a `jobsrv::BusyWorker`.
@@ -712,15 +1325,15 @@ fn row_to_job(row: &postgres::rows::Row) -> Result<jobsrv::Job> {
     job.set_state(job_state);
 
     let created_at = row.get::<&str, DateTime<Utc>>("created_at");
-    job.set_created_at(created_at.to_rfc3339());
+    job.set_created_at(timestamp::to_rfc3339(&created_at));
 
     // Note: these may be null (e.g., a job is scheduled, but hasn't
     // started; a job has started and is currently running)
     if let Some(Ok(start)) = row.get_opt::<&str, DateTime<Utc>>("build_started_at") {
-        job.set_build_started_at(start.to_rfc3339());
+        job.set_build_started_at(timestamp::to_rfc3339(&start));
     }
     if let Some(Ok(stop)) = row.get_opt::<&str, DateTime<Utc>>("build_finished_at") {
-        job.set_build_finished_at(stop.to_rfc3339());
+        job.set_build_finished_at(timestamp::to_rfc3339(&stop));
     }
 
     // package_ident will only be present if the build succeeded
@@ -766,11 +1379,24 @@ fn row_to_job(row: &postgres::rows::Row) -> Result<jobsrv::Job> {
                 }
             }
         }
+        "generic_git" => {
+            let mut vcsa: Vec<Option<String>> = row.get("vcs_arguments");
+            project.set_vcs_type(String::from("generic_git"));
+            project.set_vcs_data(vcsa.remove(0).expect("expected vcs data"));
+            if !vcsa.is_empty() {
+                if let Some(credential) = vcsa.remove(0) {
+                    project.set_vcs_credential(credential);
+                }
+            }
+        }
         e => {
             error!("Unknown VCS, {}", e);
             return Err(Error::UnknownVCS);
         }
     }
+
+    let labels: Vec<String> = row.get("required_worker_labels");
+    project.set_required_worker_labels(RepeatedField::from_vec(labels));
     job.set_project(project);
 
     if let Some(Ok(err_msg)) = row.get_opt::<&str, String>("net_error_msg") {
@@ -797,5 +1423,51 @@ fn row_to_job(row: &postgres::rows::Row) -> Result<jobsrv::Job> {
     let target: String = row.get("target");
     job.set_target(target);
 
+    job.set_no_cache(row.get("no_cache"));
+
+    if let Some(Ok(vcs_ref)) = row.get_opt::<&str, String>("vcs_ref") {
+        job.set_vcs_ref(vcs_ref);
+    }
+
+    if let Some(Ok(bytes)) = row.get_opt::<&str, i64>("peak_workspace_bytes") {
+        job.set_peak_workspace_bytes(bytes as u64);
+    }
+
+    if let Some(Ok(bytes)) = row.get_opt::<&str, i64>("artifact_bytes") {
+        job.set_artifact_bytes(bytes as u64);
+    }
+
+    if job_state == jobsrv::JobState::Failed {
+        if let Some(Ok(excerpt)) = row.get_opt::<&str, String>("failure_excerpt") {
+            job.set_failure_excerpt(excerpt);
+        }
+    }
+
+    if job_state == jobsrv::JobState::Dispatched || job_state == jobsrv::JobState::Processing {
+        if let Some(progress) = row_to_job_progress(row) {
+            job.set_progress(progress);
+        }
+    }
+
     Ok(job)
 }
+
+fn row_to_job_progress(row: &postgres::rows::Row) -> Option<jobsrv::JobProgress> {
+    let phase = match row.get_opt::<&str, String>("progress_phase") {
+        Some(Ok(phase)) => phase,
+        _ => return None,
+    };
+
+    let mut progress = jobsrv::JobProgress::new();
+    progress.set_phase(phase);
+    if let Some(Ok(step)) = row.get_opt::<&str, String>("progress_step") {
+        progress.set_step(step);
+    }
+    if let Some(Ok(secs)) = row.get_opt::<&str, i64>("progress_phase_secs") {
+        progress.set_phase_elapsed_secs(secs as u64);
+    }
+    if let Some(Ok(updated_at)) = row.get_opt::<&str, DateTime<Utc>>("progress_updated_at") {
+        progress.set_updated_at(timestamp::to_rfc3339(&updated_at));
+    }
+    Some(progress)
+}
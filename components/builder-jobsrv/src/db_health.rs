@@ -0,0 +1,133 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Detection of lost datastore connections, such as those cut off by a Postgres primary
+//! failing over. Losing one marks the datastore degraded for a short while, during which
+//! the schedulers stop claiming new work and the status endpoint reports the outage.
+
+use std::{sync::{Arc,
+                 Mutex},
+          time::{Duration,
+                 Instant}};
+
+use postgres;
+
+/// How long the datastore stays degraded after the last lost connection
+pub const DEGRADED_SECS: u64 = 30;
+
+// SQLSTATE codes reporting that the server went away rather than that a statement failed
+const CONNECTION_EXCEPTION_CLASS: &str = "08";
+const ADMIN_SHUTDOWN: &str = "57P01";
+const CRASH_SHUTDOWN: &str = "57P02";
+const CANNOT_CONNECT_NOW: &str = "57P03";
+
+#[derive(Clone, Debug)]
+pub struct DbHealth {
+    window:         Duration,
+    degraded_until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Default for DbHealth {
+    fn default() -> Self { DbHealth::new(Duration::from_secs(DEGRADED_SECS)) }
+}
+
+impl DbHealth {
+    pub fn new(window: Duration) -> Self {
+        DbHealth { window,
+                   degraded_until: Arc::new(Mutex::new(None)) }
+    }
+
+    pub fn mark_degraded(&self) {
+        let mut degraded_until = self.degraded_until.lock().expect("DbHealth lock poisoned");
+        if degraded_until.is_none() {
+            warn!("Lost connection to the datastore, pausing new work");
+        }
+        *degraded_until = Some(Instant::now() + self.window);
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        let mut degraded_until = self.degraded_until.lock().expect("DbHealth lock poisoned");
+        match *degraded_until {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                info!("Datastore connections recovered, resuming new work");
+                *degraded_until = None;
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+/// Whether an error means the connection it happened on is gone, as opposed to a failure of
+/// the statement itself. Operations that fail this way can be retried on a fresh connection.
+pub fn is_connection_error(err: &postgres::error::Error) -> bool {
+    is_connection_failure(err.code().map(|state| state.code()), err.as_io().is_some())
+}
+
+fn is_connection_failure(code: Option<&str>, io: bool) -> bool {
+    if io {
+        return true;
+    }
+    match code {
+        Some(code) => {
+            code.starts_with(CONNECTION_EXCEPTION_CLASS)
+            || code == ADMIN_SHUTDOWN
+            || code == CRASH_SHUTDOWN
+            || code == CANNOT_CONNECT_NOW
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn io_errors_are_connection_errors() {
+        assert!(is_connection_failure(None, true));
+    }
+
+    #[test]
+    fn server_shutdown_is_a_connection_error() {
+        assert!(is_connection_failure(Some("57P01"), false));
+        assert!(is_connection_failure(Some("57P02"), false));
+        assert!(is_connection_failure(Some("57P03"), false));
+        assert!(is_connection_failure(Some("08006"), false));
+        assert!(is_connection_failure(Some("08003"), false));
+    }
+
+    #[test]
+    fn statement_errors_are_not_connection_errors() {
+        assert!(!is_connection_failure(None, false));
+        assert!(!is_connection_failure(Some("23505"), false));
+        assert!(!is_connection_failure(Some("40001"), false));
+        assert!(!is_connection_failure(Some("57014"), false));
+    }
+
+    #[test]
+    fn degraded_until_window_passes() {
+        let health = DbHealth::new(Duration::from_millis(50));
+        assert!(!health.is_degraded());
+
+        health.mark_degraded();
+        assert!(health.is_degraded());
+        assert!(health.clone().is_degraded());
+
+        thread::sleep(Duration::from_millis(80));
+        assert!(!health.is_degraded());
+    }
+}
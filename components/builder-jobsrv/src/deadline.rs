@@ -0,0 +1,148 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deadlines of RPCs. The API sends what is left of its request's deadline with each RPC. While
+//! the RPC is handled, the datastore bounds its statements and connection waits by what remains,
+//! so work for a request nobody waits on any more stops promptly.
+//!
+//! RPCs are handled synchronously on the HTTP worker threads, so the deadline is kept per thread
+//! rather than passed through every handler.
+
+use std::{cell::Cell,
+          time::{Duration,
+                 Instant}};
+
+use diesel::connection::SimpleConnection;
+use postgres;
+
+use crate::{db::{diesel_pool::PgPooledConnection,
+                 DbPool},
+            error::{Error,
+                    Result}};
+
+// SQLSTATE of a statement canceled, as by `statement_timeout`
+const QUERY_CANCELED: &str = "57014";
+
+thread_local! {
+    static CURRENT: Cell<Option<Instant>> = Cell::new(None);
+}
+
+// Restores the deadline of the enclosing work, also when the handler panics
+struct Restore(Option<Instant>);
+
+impl Drop for Restore {
+    fn drop(&mut self) { CURRENT.with(|current| current.set(self.0)); }
+}
+
+/// Runs `f` with `deadline` as the deadline of the work it does on this thread
+pub fn within<T, F>(deadline: Option<Instant>, f: F) -> T
+    where F: FnOnce() -> T
+{
+    let _restore = Restore(CURRENT.with(|current| current.replace(deadline)));
+    f()
+}
+
+/// The time left before the current deadline, none when there is no deadline
+pub fn remaining() -> Option<Duration> {
+    CURRENT.with(Cell::get).map(|deadline| {
+                               let now = Instant::now();
+                               if deadline > now {
+                                   deadline - now
+                               } else {
+                                   Duration::from_secs(0)
+                               }
+                           })
+}
+
+/// Whether the current deadline has passed
+pub fn exceeded() -> bool { remaining().map_or(false, |left| statement_timeout_ms(left) == 0) }
+
+/// The `statement_timeout`, in milliseconds, bounding a statement by the time left. Postgres
+/// takes 0 as no timeout, so the caller has to check for that first.
+pub fn statement_timeout_ms(left: Duration) -> u64 {
+    left.as_secs() * 1000 + u64::from(left.subsec_millis())
+}
+
+/// Checks out a connection of the diesel pool for the work being done on this thread, waiting no
+/// longer than its deadline and bounding its statements by what is left. The pool puts the
+/// timeout back as the connection is next checked out.
+pub fn conn(pool: &DbPool) -> Result<PgPooledConnection> {
+    let left = match remaining() {
+        Some(left) => left,
+        None => return pool.get_conn().map_err(Error::Db),
+    };
+    let timeout_ms = statement_timeout_ms(left);
+    if timeout_ms == 0 {
+        return Err(Error::DeadlineExceeded);
+    }
+
+    let conn = pool.get_conn_within(left)
+                   .map_err(|_| Error::DeadlineExceeded)?;
+    conn.batch_execute(&format!("SET statement_timeout = {}", timeout_ms))
+        .map_err(Error::DieselError)?;
+    Ok(conn)
+}
+
+/// Whether a statement was canceled, as it is when it runs past its `statement_timeout`
+pub fn is_statement_timeout(err: &postgres::error::Error) -> bool {
+    err.code()
+       .map_or(false, |state| state.code() == QUERY_CANCELED)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{panic,
+              thread};
+
+    #[test]
+    fn no_deadline_outside_an_rpc() {
+        assert_eq!(remaining(), None);
+        assert!(!exceeded());
+    }
+
+    #[test]
+    fn deadline_applies_within_and_is_restored() {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        within(Some(deadline), || {
+            let left = remaining().unwrap();
+            assert!(left > Duration::from_secs(9) && left <= Duration::from_secs(10));
+
+            within(None, || assert_eq!(remaining(), None));
+            assert!(remaining().is_some());
+        });
+        assert_eq!(remaining(), None);
+
+        let _ = panic::catch_unwind(|| within(Some(deadline), || panic!("handler failed")));
+        assert_eq!(remaining(), None);
+    }
+
+    #[test]
+    fn deadline_runs_out() {
+        let deadline = Instant::now() + Duration::from_millis(50);
+        within(Some(deadline), || {
+            assert!(!exceeded());
+            thread::sleep(Duration::from_millis(80));
+            assert!(exceeded());
+            assert_eq!(remaining(), Some(Duration::from_secs(0)));
+        });
+    }
+
+    #[test]
+    fn statement_timeout_is_whole_milliseconds() {
+        assert_eq!(statement_timeout_ms(Duration::from_millis(2500)), 2500);
+        assert_eq!(statement_timeout_ms(Duration::from_micros(1999)), 1);
+        assert_eq!(statement_timeout_ms(Duration::from_micros(999)), 0);
+    }
+}
@@ -19,7 +19,8 @@ use std::{error,
           path::PathBuf,
           result};
 
-use actix_web::{http::StatusCode,
+use actix_web::{http::{header,
+                       StatusCode},
                 HttpResponse};
 
 use chrono;
@@ -33,6 +34,8 @@ use zmq;
 
 use crate::{bldr_core,
             db,
+            db_health,
+            deadline,
             hab_core,
             protocol};
 
@@ -49,9 +52,16 @@ pub enum Error {
     DbTransaction(postgres::error::Error),
     DbTransactionStart(postgres::error::Error),
     DbTransactionCommit(postgres::error::Error),
+    DeadlineExceeded,
     DieselError(diesel::result::Error),
+    FeatureFlagSet(postgres::error::Error),
+    FeatureFlagsGet(postgres::error::Error),
+    FreezeWindowsGet(postgres::error::Error),
+    FreezeWindowsSet(postgres::error::Error),
     FromUtf8(std::string::FromUtf8Error),
     HabitatCore(hab_core::Error),
+    InvalidFeatureFlag(String),
+    InvalidFreezeWindow(String),
     InvalidUrl,
     IO(io::Error),
     JobGroupAudit(postgres::error::Error),
@@ -60,15 +70,23 @@ pub enum Error {
     JobGroupGet(postgres::error::Error),
     JobGroupOriginGet(postgres::error::Error),
     JobGroupPending(postgres::error::Error),
+    JobGroupSubmitters(postgres::error::Error),
     JobGroupSetState(postgres::error::Error),
+    JobGroupUpdate(postgres::error::Error),
     JobGraphPackageInsert(postgres::error::Error),
     JobGraphPackageStats(postgres::error::Error),
     JobGraphPackagesGet(postgres::error::Error),
     JobGroupProjectSetState(postgres::error::Error),
     JobCreate(postgres::error::Error),
+    JobFailureExcerpt(postgres::error::Error),
     JobGet(postgres::error::Error),
+    JobEventsPrune(postgres::error::Error),
+    JobLease(postgres::error::Error),
+    JobProgress(postgres::error::Error),
     JobLogArchive(u64, rusoto_core::RusotoError<rusoto_s3::PutObjectError>),
     JobLogRetrieval(u64, rusoto_core::RusotoError<rusoto_s3::GetObjectError>),
+    JobLogDelete(u64, rusoto_core::RusotoError<rusoto_s3::DeleteObjectError>),
+    JobLogList(rusoto_core::RusotoError<rusoto_s3::ListObjectsError>),
     JobMarkArchived(postgres::error::Error),
     JobPending(postgres::error::Error),
     JobReset(postgres::error::Error),
@@ -84,6 +102,7 @@ pub enum Error {
     Protobuf(protobuf::ProtobufError),
     Protocol(protocol::ProtocolError),
     System,
+    UnknownExclusion(String),
     UnknownVCS,
     UnknownJobGroup,
     UnknownJobGroupState,
@@ -96,6 +115,81 @@ pub enum Error {
 
 pub type Result<T> = result::Result<T, Error>;
 
+impl Error {
+    /// The Postgres error behind a failed datastore operation, if there is one.
+    pub fn postgres_error(&self) -> Option<&postgres::error::Error> {
+        match *self {
+            Error::BusyWorkerUpsert(ref e)
+            | Error::BusyWorkerDelete(ref e)
+            | Error::BusyWorkersGet(ref e)
+            | Error::DbTransaction(ref e)
+            | Error::DbTransactionStart(ref e)
+            | Error::DbTransactionCommit(ref e)
+            | Error::FeatureFlagSet(ref e)
+            | Error::FeatureFlagsGet(ref e)
+            | Error::FreezeWindowsGet(ref e)
+            | Error::FreezeWindowsSet(ref e)
+            | Error::JobGroupAudit(ref e)
+            | Error::JobGroupCreate(ref e)
+            | Error::JobGroupCancel(ref e)
+            | Error::JobGroupGet(ref e)
+            | Error::JobGroupOriginGet(ref e)
+            | Error::JobGroupPending(ref e)
+            | Error::JobGroupSubmitters(ref e)
+            | Error::JobGroupSetState(ref e)
+            | Error::JobGroupUpdate(ref e)
+            | Error::JobGraphPackageInsert(ref e)
+            | Error::JobGraphPackageStats(ref e)
+            | Error::JobGraphPackagesGet(ref e)
+            | Error::JobGroupProjectSetState(ref e)
+            | Error::JobCreate(ref e)
+            | Error::JobFailureExcerpt(ref e)
+            | Error::JobGet(ref e)
+            | Error::JobEventsPrune(ref e)
+            | Error::JobLease(ref e)
+            | Error::JobProgress(ref e)
+            | Error::JobMarkArchived(ref e)
+            | Error::JobPending(ref e)
+            | Error::JobReset(ref e)
+            | Error::JobSetLogUrl(ref e)
+            | Error::JobSetState(ref e)
+            | Error::SyncJobs(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Whether the operation failed because its datastore connection was lost.
+    pub fn is_connection_error(&self) -> bool {
+        self.postgres_error()
+            .map_or(false, db_health::is_connection_error)
+    }
+
+    /// Whether the operation was canceled for running past its statement timeout.
+    pub fn is_statement_timeout(&self) -> bool {
+        self.postgres_error()
+            .map_or(false, deadline::is_statement_timeout)
+    }
+
+    /// Whether the operation may succeed if tried again: the datastore, the job queue socket or
+    /// the log store failed, rather than the request itself being wrong.
+    pub fn is_retryable(&self) -> bool {
+        match *self {
+            Error::DbPoolTimeout(_)
+            | Error::DbTransaction(_)
+            | Error::DbTransactionStart(_)
+            | Error::DbTransactionCommit(_)
+            | Error::Db(db::error::Error::ConnectionTimeout(_))
+            | Error::Db(db::error::Error::PostgresConnect(_))
+            | Error::JobLogArchive(..)
+            | Error::JobLogRetrieval(..)
+            | Error::JobLogDelete(..)
+            | Error::JobLogList(_)
+            | Error::Zmq(_) => true,
+            _ => self.is_connection_error() || self.is_statement_timeout(),
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let msg = match *self {
@@ -124,9 +218,20 @@ impl fmt::Display for Error {
             Error::DbTransactionCommit(ref e) => {
                 format!("Failed to commit database transaction, {}", e)
             }
+            Error::DeadlineExceeded => "RPC deadline exceeded".to_string(),
             Error::DieselError(ref e) => format!("{}", e),
+            Error::FeatureFlagSet(ref e) => format!("Database error setting a feature flag, {}", e),
+            Error::FeatureFlagsGet(ref e) => format!("Database error getting feature flags, {}", e),
+            Error::FreezeWindowsGet(ref e) => {
+                format!("Database error getting freeze windows, {}", e)
+            }
+            Error::FreezeWindowsSet(ref e) => {
+                format!("Database error setting freeze windows, {}", e)
+            }
             Error::FromUtf8(ref e) => format!("{}", e),
             Error::HabitatCore(ref e) => format!("{}", e),
+            Error::InvalidFeatureFlag(ref e) => format!("Invalid feature flag, {}", e),
+            Error::InvalidFreezeWindow(ref e) => format!("Invalid freeze window, {}", e),
             Error::InvalidUrl => "Bad URL!".to_string(),
             Error::IO(ref e) => format!("{}", e),
             Error::JobGroupAudit(ref e) => format!("Database error creating audit entry, {}", e),
@@ -137,7 +242,11 @@ impl fmt::Display for Error {
                 format!("Database error getting group data for an origin, {}", e)
             }
             Error::JobGroupPending(ref e) => format!("Database error getting pending group, {}", e),
+            Error::JobGroupSubmitters(ref e) => {
+                format!("Database error getting group submitters, {}", e)
+            }
             Error::JobGroupSetState(ref e) => format!("Database error setting group state, {}", e),
+            Error::JobGroupUpdate(ref e) => format!("Database error updating group, {}", e),
             Error::JobGraphPackageInsert(ref e) => {
                 format!("Database error inserting a new package, {}", e)
             }
@@ -151,13 +260,23 @@ impl fmt::Display for Error {
                 format!("Database error setting project state, {}", e)
             }
             Error::JobCreate(ref e) => format!("Database error creating a new job, {}", e),
+            Error::JobFailureExcerpt(ref e) => {
+                format!("Database error storing job failure excerpt, {}", e)
+            }
             Error::JobGet(ref e) => format!("Database error getting job data, {}", e),
+            Error::JobEventsPrune(ref e) => format!("Database error pruning job events, {}", e),
+            Error::JobLease(ref e) => format!("Database error recording a job lease, {}", e),
+            Error::JobProgress(ref e) => format!("Database error storing job progress, {}", e),
             Error::JobLogArchive(job_id, ref e) => {
                 format!("Log archiving error for job {}, {}", job_id, e)
             }
             Error::JobLogRetrieval(job_id, ref e) => {
                 format!("Log retrieval error for job {}, {}", job_id, e)
             }
+            Error::JobLogDelete(job_id, ref e) => {
+                format!("Log deletion error for job {}, {}", job_id, e)
+            }
+            Error::JobLogList(ref e) => format!("Error listing archived logs, {}", e),
             Error::JobMarkArchived(ref e) => {
                 format!("Database error marking job as archived, {}", e)
             }
@@ -183,6 +302,9 @@ impl fmt::Display for Error {
             Error::Protobuf(ref e) => format!("{}", e),
             Error::Protocol(ref e) => format!("{}", e),
             Error::System => "Internal error".to_string(),
+            Error::UnknownExclusion(ref name) => {
+                format!("Excluded package {} is not in the dependency graph", name)
+            }
             Error::UnknownJobGroup => "Unknown Group".to_string(),
             Error::UnknownJobGroupState => "Unknown Group State".to_string(),
             Error::UnknownJobGraphPackage => "Unknown Package".to_string(),
@@ -210,10 +332,17 @@ impl error::Error for Error {
             Error::DbTransaction(ref err) => err.description(),
             Error::DbTransactionCommit(ref err) => err.description(),
             Error::DbTransactionStart(ref err) => err.description(),
+            Error::DeadlineExceeded => "RPC deadline exceeded",
             Error::DieselError(ref err) => err.description(),
+            Error::FeatureFlagSet(ref err) => err.description(),
+            Error::FeatureFlagsGet(ref err) => err.description(),
+            Error::FreezeWindowsGet(ref err) => err.description(),
+            Error::FreezeWindowsSet(ref err) => err.description(),
             Error::FromUtf8(ref err) => err.description(),
             Error::HabitatCore(ref err) => err.description(),
             Error::IO(ref err) => err.description(),
+            Error::InvalidFeatureFlag(_) => "Invalid feature flag",
+            Error::InvalidFreezeWindow(_) => "Invalid freeze window",
             Error::InvalidUrl => "Bad Url!",
             Error::JobGroupAudit(ref err) => err.description(),
             Error::JobGroupCreate(ref err) => err.description(),
@@ -221,15 +350,23 @@ impl error::Error for Error {
             Error::JobGroupGet(ref err) => err.description(),
             Error::JobGroupOriginGet(ref err) => err.description(),
             Error::JobGroupPending(ref err) => err.description(),
+            Error::JobGroupSubmitters(ref err) => err.description(),
             Error::JobGroupSetState(ref err) => err.description(),
+            Error::JobGroupUpdate(ref err) => err.description(),
             Error::JobGraphPackageInsert(ref err) => err.description(),
             Error::JobGraphPackageStats(ref err) => err.description(),
             Error::JobGraphPackagesGet(ref err) => err.description(),
             Error::JobGroupProjectSetState(ref err) => err.description(),
             Error::JobCreate(ref err) => err.description(),
+            Error::JobFailureExcerpt(ref err) => err.description(),
             Error::JobGet(ref err) => err.description(),
+            Error::JobEventsPrune(ref err) => err.description(),
+            Error::JobLease(ref err) => err.description(),
+            Error::JobProgress(ref err) => err.description(),
             Error::JobLogArchive(_, ref err) => err.description(),
             Error::JobLogRetrieval(_, ref err) => err.description(),
+            Error::JobLogDelete(_, ref err) => err.description(),
+            Error::JobLogList(ref err) => err.description(),
             Error::JobMarkArchived(ref err) => err.description(),
             Error::JobPending(ref err) => err.description(),
             Error::JobReset(ref err) => err.description(),
@@ -245,6 +382,7 @@ impl error::Error for Error {
             Error::Protobuf(ref err) => err.description(),
             Error::Protocol(ref err) => err.description(),
             Error::System => "Internal error",
+            Error::UnknownExclusion(_) => "Excluded package is not in the dependency graph",
             Error::UnknownJobState(ref err) => err.description(),
             Error::UnknownJobGroup => "Unknown Group",
             Error::UnknownJobGroupState => "Unknown Group State",
@@ -255,16 +393,57 @@ impl error::Error for Error {
             Error::Zmq(ref err) => err.description(),
         }
     }
+
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        if let Some(err) = self.postgres_error() {
+            return Some(err);
+        }
+        match *self {
+            Error::BuilderCore(ref err) => Some(err),
+            Error::Db(ref err) => Some(err),
+            Error::DbPoolTimeout(ref err) => Some(err),
+            Error::DieselError(ref err) => Some(err),
+            Error::FromUtf8(ref err) => Some(err),
+            Error::HabitatCore(ref err) => Some(err),
+            Error::IO(ref err) => Some(err),
+            Error::JobLogArchive(_, ref err) => Some(err),
+            Error::JobLogRetrieval(_, ref err) => Some(err),
+            Error::JobLogDelete(_, ref err) => Some(err),
+            Error::JobLogList(ref err) => Some(err),
+            Error::LogDirDoesNotExist(_, ref err) => Some(err),
+            Error::ParseError(ref err) => Some(err),
+            Error::ParseVCSInstallationId(ref err) => Some(err),
+            Error::Protobuf(ref err) => Some(err),
+            Error::Protocol(ref err) => Some(err),
+            Error::UnknownJobState(ref err) => Some(err),
+            Error::Utf8(ref err) => Some(err),
+            Error::Zmq(ref err) => Some(err),
+            _ => None,
+        }
+    }
 }
 
+// Seconds a client is asked to wait before retrying once the database pool had no connection
+// to spare
+const DB_POOL_RETRY_AFTER_SECS: u64 = 5;
+
 impl Into<HttpResponse> for Error {
     fn into(self) -> HttpResponse {
         match self {
             Error::BuilderCore(ref e) => HttpResponse::new(bldr_core_err_to_http(e)),
             Error::Conflict => HttpResponse::new(StatusCode::CONFLICT),
+            Error::DbPoolTimeout(_) => {
+                HttpResponse::build(StatusCode::SERVICE_UNAVAILABLE)
+                    .header(header::RETRY_AFTER, DB_POOL_RETRY_AFTER_SECS.to_string())
+                    .finish()
+            }
+            Error::DeadlineExceeded => HttpResponse::new(StatusCode::GATEWAY_TIMEOUT),
             Error::DieselError(ref e) => HttpResponse::new(diesel_err_to_http(e)),
+            Error::InvalidFeatureFlag(_) => HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY),
+            Error::InvalidFreezeWindow(_) => HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY),
             Error::NotFound => HttpResponse::new(StatusCode::NOT_FOUND),
             Error::System => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
+            Error::UnknownExclusion(_) => HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY),
 
             // Default
             _ => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
@@ -286,6 +465,10 @@ fn diesel_err_to_http(err: &diesel::result::Error) -> StatusCode {
             diesel::result::DatabaseErrorKind::UniqueViolation,
             _,
         ) => StatusCode::CONFLICT,
+        diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::ForeignKeyViolation,
+            _,
+        ) => StatusCode::UNPROCESSABLE_ENTITY,
         _ => StatusCode::INTERNAL_SERVER_ERROR,
     }
 }
@@ -322,6 +505,12 @@ impl From<protocol::ProtocolError> for Error {
     fn from(err: protocol::ProtocolError) -> Self { Error::Protocol(err) }
 }
 
+impl From<rusoto_core::RusotoError<rusoto_s3::ListObjectsError>> for Error {
+    fn from(err: rusoto_core::RusotoError<rusoto_s3::ListObjectsError>) -> Error {
+        Error::JobLogList(err)
+    }
+}
+
 impl From<std::string::FromUtf8Error> for Error {
     fn from(err: std::string::FromUtf8Error) -> Error { Error::FromUtf8(err) }
 }
@@ -333,3 +522,50 @@ impl From<std::str::Utf8Error> for Error {
 impl From<zmq::Error> for Error {
     fn from(err: zmq::Error) -> Error { Error::Zmq(err) }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as StdError;
+
+    #[test]
+    fn wrapped_errors_are_the_source() {
+        let err = Error::IO(io::Error::new(io::ErrorKind::Other, "disk on fire"));
+        assert_eq!(err.source().unwrap().to_string(), "disk on fire");
+
+        let err = Error::from(bldr_core::Error::DeadlineExceeded);
+        assert!(err.source().is_some());
+
+        assert!(Error::NotFound.source().is_none());
+    }
+
+    #[test]
+    fn constraint_violations_are_client_errors() {
+        use diesel::result::{DatabaseErrorKind,
+                             Error as DieselError};
+        let violation = |kind| DieselError::DatabaseError(kind, Box::new("violation".to_string()));
+
+        assert_eq!(diesel_err_to_http(&violation(DatabaseErrorKind::UniqueViolation)),
+                   StatusCode::CONFLICT);
+        assert_eq!(diesel_err_to_http(&violation(DatabaseErrorKind::ForeignKeyViolation)),
+                   StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(diesel_err_to_http(&DieselError::NotFound),
+                   StatusCode::NOT_FOUND);
+        assert_eq!(diesel_err_to_http(&DieselError::RollbackTransaction),
+                   StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn unknown_exclusions_are_client_errors() {
+        let res: HttpResponse = Error::UnknownExclusion("core/nope".to_string()).into();
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[test]
+    fn transient_failures_are_retryable() {
+        assert!(Error::Zmq(zmq::Error::EAGAIN).is_retryable());
+        assert!(!Error::UnknownVCS.is_retryable());
+        assert!(!Error::LogDirIsNotDir(PathBuf::from("/hab/svc")).is_retryable());
+        assert!(!Error::NotFound.is_retryable());
+    }
+}
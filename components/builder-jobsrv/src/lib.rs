@@ -34,6 +34,8 @@ use rusoto_core as rusoto;
 
 pub mod config;
 pub mod data_store;
+pub mod db_health;
+pub mod deadline;
 pub mod error;
 pub mod server;
 
@@ -0,0 +1,38 @@
+ALTER TABLE jobs ADD COLUMN required_worker_labels text[] NOT NULL DEFAULT '{}';
+
+CREATE OR REPLACE FUNCTION insert_job_v4(p_owner_id bigint, p_project_id bigint, p_project_name text, p_project_owner_id bigint, p_project_plan_path text, p_vcs text, p_vcs_arguments text[], p_channel text, p_target text, p_required_worker_labels text[]) RETURNS SETOF jobs
+    LANGUAGE sql
+    AS $$
+      INSERT INTO jobs (owner_id, job_state, project_id, project_name, project_owner_id, project_plan_path, vcs, vcs_arguments, channel, target, required_worker_labels)
+      VALUES (p_owner_id, 'Pending', p_project_id, p_project_name, p_project_owner_id, p_project_plan_path, p_vcs, p_vcs_arguments, p_channel, p_target, p_required_worker_labels)
+      RETURNING *;
+$$;
+
+CREATE OR REPLACE FUNCTION next_pending_job_v3(p_worker text, p_target text, p_labels text[]) RETURNS SETOF jobs
+    LANGUAGE plpgsql
+    AS $$
+DECLARE
+    r jobs % rowtype;
+BEGIN
+    FOR r IN
+        SELECT * FROM jobs
+        WHERE job_state = 'Pending' AND target = p_target AND required_worker_labels <@ p_labels
+        ORDER BY created_at ASC
+        FOR UPDATE SKIP LOCKED
+        LIMIT 1
+    LOOP
+        UPDATE jobs SET job_state='Dispatched', scheduler_sync=false, worker=p_worker, updated_at=now()
+        WHERE id=r.id
+        RETURNING * INTO r;
+        RETURN NEXT r;
+    END LOOP;
+  RETURN;
+END
+$$;
+
+CREATE OR REPLACE FUNCTION get_pending_worker_labels_v1(p_target text) RETURNS TABLE(required_worker_labels text[])
+    LANGUAGE sql STABLE
+    AS $$
+  SELECT DISTINCT required_worker_labels FROM jobs
+  WHERE job_state = 'Pending' AND target = p_target AND cardinality(required_worker_labels) > 0;
+$$;
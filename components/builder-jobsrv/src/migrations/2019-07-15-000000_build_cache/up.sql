@@ -0,0 +1,24 @@
+ALTER TABLE groups ADD COLUMN no_cache bool NOT NULL DEFAULT false;
+ALTER TABLE jobs ADD COLUMN no_cache bool NOT NULL DEFAULT false;
+
+CREATE OR REPLACE FUNCTION insert_group_v4(root_project text, project_names text[], project_idents text[], p_target text, p_no_cache bool) RETURNS SETOF groups
+    LANGUAGE sql
+    AS $$
+  WITH my_group AS (
+          INSERT INTO groups (project_name, group_state, target, no_cache)
+          VALUES (root_project, 'Queued', p_target, p_no_cache) RETURNING *
+      ), my_project AS (
+          INSERT INTO group_projects (owner_id, project_name, project_ident, project_state)
+          SELECT g.id, project_info.name, project_info.ident, 'NotStarted'
+          FROM my_group AS g, unnest(project_names, project_idents) AS project_info(name, ident)
+      )
+  SELECT * FROM my_group;
+$$;
+
+CREATE OR REPLACE FUNCTION insert_job_v5(p_owner_id bigint, p_project_id bigint, p_project_name text, p_project_owner_id bigint, p_project_plan_path text, p_vcs text, p_vcs_arguments text[], p_channel text, p_target text, p_required_worker_labels text[], p_no_cache bool) RETURNS SETOF jobs
+    LANGUAGE sql
+    AS $$
+      INSERT INTO jobs (owner_id, job_state, project_id, project_name, project_owner_id, project_plan_path, vcs, vcs_arguments, channel, target, required_worker_labels, no_cache)
+      VALUES (p_owner_id, 'Pending', p_project_id, p_project_name, p_project_owner_id, p_project_plan_path, p_vcs, p_vcs_arguments, p_channel, p_target, p_required_worker_labels, p_no_cache)
+      RETURNING *;
+$$;
@@ -0,0 +1,22 @@
+ALTER TABLE jobs ADD COLUMN peak_workspace_bytes bigint;
+ALTER TABLE jobs ADD COLUMN artifact_bytes bigint;
+
+CREATE OR REPLACE FUNCTION update_job_v4(p_job_id bigint, p_state text, p_build_started_at timestamp with time zone, p_build_finished_at timestamp with time zone, p_package_ident text, p_err_code integer, p_err_msg text, p_peak_workspace_bytes bigint, p_artifact_bytes bigint) RETURNS void
+    LANGUAGE sql
+    AS $$
+  UPDATE jobs
+  SET job_state = p_state,
+      scheduler_sync = false,
+      sync_count = sync_count + 1,
+      updated_at = now(),
+      build_started_at = p_build_started_at,
+      build_finished_at = p_build_finished_at,
+      package_ident = p_package_ident,
+      net_error_code = p_err_code,
+      net_error_msg = p_err_msg,
+      peak_workspace_bytes = COALESCE(p_peak_workspace_bytes, peak_workspace_bytes),
+      artifact_bytes = COALESCE(p_artifact_bytes, artifact_bytes)
+  WHERE id = p_job_id;
+$$;
+
+CREATE INDEX IF NOT EXISTS jobs_project_disk_usage_v1 ON jobs(project_name, target, id DESC) WHERE (peak_workspace_bytes IS NOT NULL);
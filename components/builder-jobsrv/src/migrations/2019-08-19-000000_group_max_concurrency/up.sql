@@ -0,0 +1,27 @@
+ALTER TABLE groups ADD COLUMN max_concurrency integer NOT NULL DEFAULT 0;
+
+CREATE OR REPLACE FUNCTION insert_group_v5(root_project text, project_names text[], project_idents text[], p_target text, p_no_cache bool, p_max_concurrency integer) RETURNS SETOF groups
+    LANGUAGE sql
+    AS $$
+  WITH my_group AS (
+          INSERT INTO groups (project_name, group_state, target, no_cache, max_concurrency)
+          VALUES (root_project, 'Queued', p_target, p_no_cache, p_max_concurrency) RETURNING *
+      ), my_project AS (
+          INSERT INTO group_projects (owner_id, project_name, project_ident, project_state)
+          SELECT g.id, project_info.name, project_info.ident, 'NotStarted'
+          FROM my_group AS g, unnest(project_names, project_idents) AS project_info(name, ident)
+      )
+  SELECT * FROM my_group;
+$$;
+
+-- A dispatching group goes back to pending so the scheduler re-evaluates it with the new cap
+CREATE OR REPLACE FUNCTION set_group_max_concurrency_v1(p_group_id bigint, p_max_concurrency integer) RETURNS SETOF groups
+    LANGUAGE sql
+    AS $$
+  UPDATE groups
+  SET max_concurrency = p_max_concurrency,
+      group_state = CASE WHEN group_state = 'Dispatching' THEN 'Pending' ELSE group_state END,
+      updated_at = now()
+  WHERE id = p_group_id
+  RETURNING *;
+$$;
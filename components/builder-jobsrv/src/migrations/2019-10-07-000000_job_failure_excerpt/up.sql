@@ -0,0 +1,44 @@
+ALTER TABLE jobs ADD COLUMN failure_excerpt text;
+
+-- The excerpt only stays with a job that ends Failed, a job put back to Pending to be run
+-- again starts without one
+CREATE OR REPLACE FUNCTION update_job_v5(p_job_id bigint, p_state text, p_build_started_at timestamp with time zone, p_build_finished_at timestamp with time zone, p_package_ident text, p_err_code integer, p_err_msg text, p_peak_workspace_bytes bigint, p_artifact_bytes bigint) RETURNS void
+    LANGUAGE sql
+    AS $$
+  UPDATE jobs
+  SET job_state = p_state,
+      scheduler_sync = false,
+      sync_count = sync_count + 1,
+      updated_at = now(),
+      build_started_at = p_build_started_at,
+      build_finished_at = p_build_finished_at,
+      package_ident = p_package_ident,
+      net_error_code = p_err_code,
+      net_error_msg = p_err_msg,
+      peak_workspace_bytes = COALESCE(p_peak_workspace_bytes, peak_workspace_bytes),
+      artifact_bytes = COALESCE(p_artifact_bytes, artifact_bytes),
+      failure_excerpt = CASE WHEN p_state = 'Failed' THEN failure_excerpt ELSE NULL END
+  WHERE id = p_job_id;
+$$;
+
+-- The log can complete before or after the worker reports the job's final state, the excerpt
+-- is kept unless the job is already known to have ended some other way than failing
+CREATE OR REPLACE FUNCTION set_job_failure_excerpt_v1(p_job_id bigint, p_excerpt text) RETURNS void
+    LANGUAGE sql
+    AS $$
+  UPDATE jobs
+  SET failure_excerpt = p_excerpt
+  WHERE id = p_job_id
+  AND job_state NOT IN ('Complete', 'CompletedFromCache', 'CancelComplete', 'Rejected');
+$$;
+
+CREATE OR REPLACE FUNCTION get_group_failure_excerpts_v1(p_group_id bigint) RETURNS TABLE(job_id bigint, failure_excerpt text)
+    LANGUAGE sql STABLE
+    AS $$
+  SELECT j.id, j.failure_excerpt
+  FROM group_projects AS gp
+  INNER JOIN jobs AS j ON j.id = gp.job_id
+  WHERE gp.owner_id = p_group_id
+  AND j.job_state = 'Failed'
+  AND j.failure_excerpt IS NOT NULL;
+$$;
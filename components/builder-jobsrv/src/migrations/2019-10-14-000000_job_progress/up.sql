@@ -0,0 +1,54 @@
+ALTER TABLE jobs ADD COLUMN progress_phase text;
+ALTER TABLE jobs ADD COLUMN progress_step text;
+ALTER TABLE jobs ADD COLUMN progress_phase_secs bigint;
+ALTER TABLE jobs ADD COLUMN progress_updated_at timestamp with time zone;
+
+-- Progress is only kept while a job runs, any other state clears it
+CREATE OR REPLACE FUNCTION update_job_v6(p_job_id bigint, p_state text, p_build_started_at timestamp with time zone, p_build_finished_at timestamp with time zone, p_package_ident text, p_err_code integer, p_err_msg text, p_peak_workspace_bytes bigint, p_artifact_bytes bigint) RETURNS void
+    LANGUAGE sql
+    AS $$
+  UPDATE jobs
+  SET job_state = p_state,
+      scheduler_sync = false,
+      sync_count = sync_count + 1,
+      updated_at = now(),
+      build_started_at = p_build_started_at,
+      build_finished_at = p_build_finished_at,
+      package_ident = p_package_ident,
+      net_error_code = p_err_code,
+      net_error_msg = p_err_msg,
+      peak_workspace_bytes = COALESCE(p_peak_workspace_bytes, peak_workspace_bytes),
+      artifact_bytes = COALESCE(p_artifact_bytes, artifact_bytes),
+      failure_excerpt = CASE WHEN p_state = 'Failed' THEN failure_excerpt ELSE NULL END,
+      progress_phase = CASE WHEN p_state IN ('Dispatched', 'Processing') THEN progress_phase ELSE NULL END,
+      progress_step = CASE WHEN p_state IN ('Dispatched', 'Processing') THEN progress_step ELSE NULL END,
+      progress_phase_secs = CASE WHEN p_state IN ('Dispatched', 'Processing') THEN progress_phase_secs ELSE NULL END,
+      progress_updated_at = CASE WHEN p_state IN ('Dispatched', 'Processing') THEN progress_updated_at ELSE NULL END
+  WHERE id = p_job_id;
+$$;
+
+-- The latest progress overwrites the previous one, at most once every 15 seconds, and only
+-- while the job runs
+CREATE OR REPLACE FUNCTION set_job_progress_v1(p_job_id bigint, p_phase text, p_step text, p_phase_secs bigint) RETURNS void
+    LANGUAGE sql
+    AS $$
+  UPDATE jobs
+  SET progress_phase = p_phase,
+      progress_step = p_step,
+      progress_phase_secs = p_phase_secs,
+      progress_updated_at = now()
+  WHERE id = p_job_id
+  AND job_state IN ('Dispatched', 'Processing')
+  AND (progress_updated_at IS NULL OR progress_updated_at <= now() - interval '15 seconds');
+$$;
+
+CREATE OR REPLACE FUNCTION get_group_progress_v1(p_group_id bigint) RETURNS TABLE(job_id bigint, progress_phase text, progress_step text, progress_phase_secs bigint, progress_updated_at timestamp with time zone)
+    LANGUAGE sql STABLE
+    AS $$
+  SELECT j.id, j.progress_phase, j.progress_step, j.progress_phase_secs, j.progress_updated_at
+  FROM group_projects AS gp
+  INNER JOIN jobs AS j ON j.id = gp.job_id
+  WHERE gp.owner_id = p_group_id
+  AND j.job_state IN ('Dispatched', 'Processing')
+  AND j.progress_phase IS NOT NULL;
+$$;
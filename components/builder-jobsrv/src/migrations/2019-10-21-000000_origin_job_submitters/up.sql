@@ -0,0 +1,13 @@
+CREATE INDEX IF NOT EXISTS audit_jobs_group_id ON audit_jobs (group_id);
+
+-- When each account last submitted a group for a project of the origin
+CREATE OR REPLACE FUNCTION get_origin_group_submitters_v1(p_origin text) RETURNS TABLE(requester_id bigint, last_submitted_at timestamp with time zone)
+    LANGUAGE sql STABLE
+    AS $$
+  SELECT a.requester_id, max(a.created_at)
+  FROM audit_jobs AS a
+  INNER JOIN groups AS g ON g.id = a.group_id
+  WHERE a.operation = 1
+  AND g.project_name LIKE (p_origin || '/%')
+  GROUP BY a.requester_id;
+$$;
@@ -0,0 +1,26 @@
+ALTER TABLE groups ADD COLUMN failure_policy text NOT NULL DEFAULT 'continue';
+
+CREATE OR REPLACE FUNCTION insert_group_v6(root_project text, project_names text[], project_idents text[], p_target text, p_no_cache bool, p_max_concurrency integer, p_failure_policy text) RETURNS SETOF groups
+    LANGUAGE sql
+    AS $$
+  WITH my_group AS (
+          INSERT INTO groups (project_name, group_state, target, no_cache, max_concurrency, failure_policy)
+          VALUES (root_project, 'Queued', p_target, p_no_cache, p_max_concurrency, p_failure_policy) RETURNING *
+      ), my_project AS (
+          INSERT INTO group_projects (owner_id, project_name, project_ident, project_state)
+          SELECT g.id, project_info.name, project_info.ident, 'NotStarted'
+          FROM my_group AS g, unnest(project_names, project_idents) AS project_info(name, ident)
+      )
+  SELECT * FROM my_group;
+$$;
+
+-- Projects the group's failure policy gives up on, as cancel_group_v1 does for a whole group.
+-- Projects that were started or skipped in the meantime keep their state.
+CREATE OR REPLACE FUNCTION skip_group_projects_v1(p_group_id bigint, p_project_names text[]) RETURNS void
+    LANGUAGE sql
+    AS $$
+  UPDATE group_projects SET project_state = 'SkippedGroupPolicy', updated_at = now()
+    WHERE owner_id = p_group_id
+    AND project_name = ANY(p_project_names)
+    AND project_state = 'NotStarted';
+$$;
@@ -0,0 +1,51 @@
+-- Scheduler behaviors operators switch at runtime. A flag missing from the table has the
+-- default the code evaluating it gives it.
+CREATE TABLE IF NOT EXISTS feature_flags (
+    name text PRIMARY KEY,
+    enabled bool NOT NULL,
+    rollout_percent smallint NOT NULL DEFAULT 100 CHECK (rollout_percent BETWEEN 0 AND 100),
+    updated_by text NOT NULL,
+    updated_at timestamp with time zone NOT NULL DEFAULT now()
+);
+
+-- Every change of a flag, with the state it had before (NULL when it was never set)
+CREATE TABLE IF NOT EXISTS feature_flag_audit (
+    id bigserial PRIMARY KEY,
+    name text NOT NULL,
+    requester_id bigint NOT NULL,
+    requester_name text NOT NULL,
+    old_enabled bool,
+    old_rollout_percent smallint,
+    new_enabled bool NOT NULL,
+    new_rollout_percent smallint NOT NULL,
+    created_at timestamp with time zone NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS feature_flag_audit_name ON feature_flag_audit (name, created_at);
+
+CREATE OR REPLACE FUNCTION get_feature_flags_v1() RETURNS SETOF feature_flags
+    LANGUAGE sql STABLE
+    AS $$
+  SELECT * FROM feature_flags ORDER BY name;
+$$;
+
+-- Sets a flag and audits the change in the same statement
+CREATE OR REPLACE FUNCTION set_feature_flag_v1(p_name text, p_enabled bool, p_rollout_percent smallint, p_requester_id bigint, p_requester_name text) RETURNS SETOF feature_flags
+    LANGUAGE sql
+    AS $$
+  WITH old AS (
+          SELECT enabled, rollout_percent FROM feature_flags WHERE name = p_name FOR UPDATE
+      ), audit AS (
+          INSERT INTO feature_flag_audit (name, requester_id, requester_name, old_enabled, old_rollout_percent, new_enabled, new_rollout_percent)
+          SELECT p_name, p_requester_id, p_requester_name, old.enabled, old.rollout_percent, p_enabled, p_rollout_percent
+          FROM (SELECT 1) AS one LEFT JOIN old ON true
+      )
+  INSERT INTO feature_flags (name, enabled, rollout_percent, updated_by, updated_at)
+  VALUES (p_name, p_enabled, p_rollout_percent, p_requester_name, now())
+  ON CONFLICT (name) DO UPDATE
+    SET enabled = EXCLUDED.enabled,
+        rollout_percent = EXCLUDED.rollout_percent,
+        updated_by = EXCLUDED.updated_by,
+        updated_at = EXCLUDED.updated_at
+  RETURNING *;
+$$;
@@ -0,0 +1,69 @@
+-- Periods during which groups are not created for an origin's projects. Weekly windows set
+-- the day and time columns, one-off windows the starts_at and ends_at columns, all local to
+-- the window's timezone.
+CREATE TABLE IF NOT EXISTS origin_freeze_windows (
+    id bigserial PRIMARY KEY,
+    origin text NOT NULL,
+    kind text NOT NULL,
+    timezone text NOT NULL,
+    start_day text,
+    start_time text,
+    end_day text,
+    end_time text,
+    starts_at text,
+    ends_at text,
+    updated_by text NOT NULL,
+    updated_at timestamp with time zone NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS origin_freeze_windows_origin ON origin_freeze_windows (origin);
+
+-- When a group deferred by a build freeze is released
+ALTER TABLE groups ADD COLUMN frozen_until timestamp with time zone;
+
+CREATE OR REPLACE FUNCTION get_origin_freeze_windows_v1(p_origin text) RETURNS SETOF origin_freeze_windows
+    LANGUAGE sql STABLE
+    AS $$
+  SELECT * FROM origin_freeze_windows WHERE origin = p_origin ORDER BY id;
+$$;
+
+-- Replaces every window of the origin in the same statement
+CREATE OR REPLACE FUNCTION set_origin_freeze_windows_v1(p_origin text, p_kinds text[], p_timezones text[], p_start_days text[], p_start_times text[], p_end_days text[], p_end_times text[], p_starts_at text[], p_ends_at text[], p_updated_by text) RETURNS SETOF origin_freeze_windows
+    LANGUAGE sql
+    AS $$
+  WITH removed AS (
+          DELETE FROM origin_freeze_windows WHERE origin = p_origin
+      )
+  INSERT INTO origin_freeze_windows (origin, kind, timezone, start_day, start_time, end_day, end_time, starts_at, ends_at, updated_by)
+  SELECT p_origin, w.kind, w.timezone, w.start_day, w.start_time, w.end_day, w.end_time, w.starts_at, w.ends_at, p_updated_by
+  FROM unnest(p_kinds, p_timezones, p_start_days, p_start_times, p_end_days, p_end_times, p_starts_at, p_ends_at)
+      AS w(kind, timezone, start_day, start_time, end_day, end_time, starts_at, ends_at)
+  RETURNING *;
+$$;
+
+CREATE OR REPLACE FUNCTION insert_group_v7(root_project text, project_names text[], project_idents text[], p_target text, p_no_cache bool, p_max_concurrency integer, p_failure_policy text, p_group_state text, p_frozen_until timestamp with time zone) RETURNS SETOF groups
+    LANGUAGE sql
+    AS $$
+  WITH my_group AS (
+          INSERT INTO groups (project_name, group_state, target, no_cache, max_concurrency, failure_policy, frozen_until)
+          VALUES (root_project, p_group_state, p_target, p_no_cache, p_max_concurrency, p_failure_policy, p_frozen_until) RETURNING *
+      ), my_project AS (
+          INSERT INTO group_projects (owner_id, project_name, project_ident, project_state)
+          SELECT g.id, project_info.name, project_info.ident, 'NotStarted'
+          FROM my_group AS g, unnest(project_names, project_idents) AS project_info(name, ident)
+      )
+  SELECT * FROM my_group;
+$$;
+
+-- Moves a deferred group on to a new release time, or queues it when there is none. Groups
+-- canceled in the meantime are left alone.
+CREATE OR REPLACE FUNCTION set_group_frozen_until_v1(p_group_id bigint, p_frozen_until timestamp with time zone) RETURNS void
+    LANGUAGE sql
+    AS $$
+  UPDATE groups
+    SET frozen_until = p_frozen_until,
+        group_state = CASE WHEN p_frozen_until IS NULL THEN 'Queued' ELSE group_state END,
+        updated_at = now()
+    WHERE id = p_group_id
+    AND group_state = 'DeferredByFreeze';
+$$;
@@ -0,0 +1,64 @@
+ALTER TABLE groups ADD COLUMN vcs_ref text;
+ALTER TABLE groups ADD COLUMN superseded_refs text[] NOT NULL DEFAULT '{}';
+ALTER TABLE groups ADD COLUMN hold_until timestamptz;
+ALTER TABLE jobs ADD COLUMN vcs_ref text;
+
+-- Queues a group for the root project, held for the quiet period, or folds the request into the
+-- group already queued for it. A folded request moves the group to its ref, recording the ref it
+-- replaces; the quiet period keeps running from the first request so that a steady stream of
+-- pushes can't hold builds back for good. Callers for the same project and target take turns,
+-- so concurrent requests can't queue two groups.
+CREATE OR REPLACE FUNCTION queue_group_v1(root_project text, project_names text[], project_idents text[], p_target text, p_no_cache bool, p_max_concurrency integer, p_failure_policy text, p_vcs_ref text, p_quiet_period integer) RETURNS SETOF groups
+    LANGUAGE plpgsql
+    AS $$
+BEGIN
+  PERFORM pg_advisory_xact_lock(hashtext(root_project || ':' || p_target));
+
+  RETURN QUERY
+    UPDATE groups
+    SET superseded_refs = CASE WHEN p_vcs_ref IS NULL OR vcs_ref IS NULL OR vcs_ref = p_vcs_ref
+                               THEN superseded_refs
+                               ELSE array_append(superseded_refs, vcs_ref) END,
+        vcs_ref = COALESCE(p_vcs_ref, vcs_ref),
+        updated_at = now()
+    WHERE project_name = root_project
+    AND target = p_target
+    AND group_state = 'Queued'
+    RETURNING *;
+  IF FOUND THEN
+    RETURN;
+  END IF;
+
+  RETURN QUERY
+    WITH my_group AS (
+            INSERT INTO groups (project_name, group_state, target, no_cache, max_concurrency, failure_policy, vcs_ref, hold_until)
+            VALUES (root_project, 'Queued', p_target, p_no_cache, p_max_concurrency, p_failure_policy, p_vcs_ref, now() + make_interval(secs => p_quiet_period)) RETURNING *
+        ), my_project AS (
+            INSERT INTO group_projects (owner_id, project_name, project_ident, project_state)
+            SELECT g.id, project_info.name, project_info.ident, 'NotStarted'
+            FROM my_group AS g, unnest(project_names, project_idents) AS project_info(name, ident)
+        )
+    SELECT * FROM my_group;
+END
+$$;
+
+-- Moves a queued group to pending once its quiet period is over. The row lock orders this
+-- against queue_group_v1: a request either lands on the group before it leaves the queue or
+-- queues the next one.
+CREATE OR REPLACE FUNCTION release_group_v1(p_group_id bigint) RETURNS SETOF groups
+    LANGUAGE sql
+    AS $$
+  UPDATE groups SET group_state = 'Pending', updated_at = now()
+    WHERE id = p_group_id
+    AND group_state = 'Queued'
+    AND (hold_until IS NULL OR hold_until <= now())
+    RETURNING *;
+$$;
+
+CREATE OR REPLACE FUNCTION insert_job_v6(p_owner_id bigint, p_project_id bigint, p_project_name text, p_project_owner_id bigint, p_project_plan_path text, p_vcs text, p_vcs_arguments text[], p_channel text, p_target text, p_required_worker_labels text[], p_no_cache bool, p_vcs_ref text) RETURNS SETOF jobs
+    LANGUAGE sql
+    AS $$
+      INSERT INTO jobs (owner_id, job_state, project_id, project_name, project_owner_id, project_plan_path, vcs, vcs_arguments, channel, target, required_worker_labels, no_cache, vcs_ref)
+      VALUES (p_owner_id, 'Pending', p_project_id, p_project_name, p_project_owner_id, p_project_plan_path, p_vcs, p_vcs_arguments, p_channel, p_target, p_required_worker_labels, p_no_cache, p_vcs_ref)
+      RETURNING *;
+$$;
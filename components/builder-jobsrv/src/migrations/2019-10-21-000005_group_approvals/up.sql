@@ -0,0 +1,38 @@
+-- Queues a group awaiting approval once an operator approves it, or cancels it along with its
+-- projects. Groups no longer awaiting approval are left alone.
+CREATE OR REPLACE FUNCTION review_group_v1(p_group_id bigint, p_approved bool) RETURNS SETOF groups
+    LANGUAGE plpgsql
+    AS $$
+BEGIN
+  RETURN QUERY
+    UPDATE groups
+    SET group_state = CASE WHEN p_approved THEN 'Queued' ELSE 'Canceled' END,
+        updated_at = now()
+    WHERE id = p_group_id
+    AND group_state = 'PendingApproval'
+    RETURNING *;
+  IF FOUND AND NOT p_approved THEN
+    UPDATE group_projects SET project_state = 'Canceled'
+      WHERE owner_id = p_group_id
+      AND project_state = 'NotStarted';
+  END IF;
+END
+$$;
+
+-- Cancels the groups that have awaited approval since before p_before, along with their
+-- projects
+CREATE OR REPLACE FUNCTION expire_groups_pending_approval_v1(p_before timestamp with time zone) RETURNS SETOF bigint
+    LANGUAGE sql
+    AS $$
+  WITH expired AS (
+          UPDATE groups SET group_state = 'Canceled', updated_at = now()
+          WHERE group_state = 'PendingApproval'
+          AND created_at < p_before
+          RETURNING id
+      ), canceled AS (
+          UPDATE group_projects SET project_state = 'Canceled'
+          WHERE owner_id IN (SELECT id FROM expired)
+          AND project_state = 'NotStarted'
+      )
+  SELECT id FROM expired;
+$$;
@@ -0,0 +1,38 @@
+-- The lease of the worker building a job: when it was granted at dispatch and when it expires
+-- unless the worker renews it
+ALTER TABLE jobs ADD COLUMN leased_at timestamp with time zone;
+ALTER TABLE jobs ADD COLUMN lease_expires_at timestamp with time zone;
+
+-- What happened to a job besides its state changes, such as the renewals of its lease
+CREATE TABLE IF NOT EXISTS job_events (
+    id bigserial PRIMARY KEY,
+    job_id bigint NOT NULL,
+    event text NOT NULL,
+    detail text,
+    created_at timestamp with time zone NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS job_events_job_id ON job_events (job_id, created_at);
+
+-- Grants or renews the lease of a running job and adds the event to the job's timeline. Jobs no
+-- longer running are left alone.
+CREATE OR REPLACE FUNCTION set_job_lease_v1(p_job_id bigint, p_leased_at timestamp with time zone, p_expires_at timestamp with time zone, p_event text) RETURNS void
+    LANGUAGE sql
+    AS $$
+  WITH leased AS (
+          UPDATE jobs
+          SET leased_at = p_leased_at,
+              lease_expires_at = p_expires_at
+          WHERE id = p_job_id
+          AND job_state IN ('Dispatched', 'Processing')
+          RETURNING id
+      )
+  INSERT INTO job_events (job_id, event, detail)
+  SELECT id, p_event, 'lease expires at ' || p_expires_at FROM leased;
+$$;
+
+CREATE OR REPLACE FUNCTION add_job_event_v1(p_job_id bigint, p_event text, p_detail text) RETURNS void
+    LANGUAGE sql
+    AS $$
+  INSERT INTO job_events (job_id, event, detail) VALUES (p_job_id, p_event, p_detail);
+$$;
@@ -0,0 +1,11 @@
+-- What a group was created by, from its creation audit. Groups created before the audit was
+-- kept have none.
+CREATE OR REPLACE FUNCTION get_group_trigger_v1(p_group_id bigint) RETURNS SETOF smallint
+    LANGUAGE sql STABLE
+    AS $$
+  SELECT trigger FROM audit_jobs
+  WHERE group_id = p_group_id
+  AND operation = 1
+  ORDER BY created_at
+  LIMIT 1;
+$$;
@@ -0,0 +1,10 @@
+CREATE INDEX IF NOT EXISTS in_flight_jobs_origin_index_v1 ON jobs(split_part(project_name, '/', 1)) WHERE (job_state IN ('Pending', 'Dispatched', 'Processing'));
+
+-- The origin's jobs counted against its concurrency limit, from creation until they finish
+CREATE OR REPLACE FUNCTION count_origin_jobs_in_flight_v1(p_origin text) RETURNS bigint
+    LANGUAGE sql STABLE
+    AS $$
+  SELECT count(*) FROM jobs
+  WHERE split_part(project_name, '/', 1) = p_origin
+  AND job_state IN ('Pending', 'Dispatched', 'Processing');
+$$;
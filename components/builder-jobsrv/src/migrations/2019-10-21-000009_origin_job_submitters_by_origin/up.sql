@@ -0,0 +1,15 @@
+CREATE INDEX IF NOT EXISTS groups_origin ON groups (split_part(project_name, '/', 1));
+
+-- When each account last submitted a group for a project of the origin. A submission creates a
+-- group, folds into a queued one or overrides a freeze. The origin is compared whole, so that the
+-- wildcards `_` and `%` in origin names match nothing else.
+CREATE OR REPLACE FUNCTION get_origin_group_submitters_v1(p_origin text) RETURNS TABLE(requester_id bigint, last_submitted_at timestamp with time zone)
+    LANGUAGE sql STABLE
+    AS $$
+  SELECT a.requester_id, max(a.created_at)
+  FROM audit_jobs AS a
+  INNER JOIN groups AS g ON g.id = a.group_id
+  WHERE a.operation IN (1, 3, 4)
+  AND split_part(g.project_name, '/', 1) = p_origin
+  GROUP BY a.requester_id;
+$$;
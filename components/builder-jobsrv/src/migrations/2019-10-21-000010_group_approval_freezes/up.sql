@@ -0,0 +1,26 @@
+-- Queues a group awaiting approval once an operator approves it, or defers it until
+-- p_frozen_until when it is set, or cancels it along with its projects. Groups no longer awaiting
+-- approval are left alone.
+CREATE OR REPLACE FUNCTION review_group_v2(p_group_id bigint, p_approved bool, p_frozen_until timestamp with time zone) RETURNS SETOF groups
+    LANGUAGE plpgsql
+    AS $$
+BEGIN
+  RETURN QUERY
+    UPDATE groups
+    SET group_state = CASE
+          WHEN NOT p_approved THEN 'Canceled'
+          WHEN p_frozen_until IS NOT NULL THEN 'DeferredByFreeze'
+          ELSE 'Queued'
+        END,
+        frozen_until = CASE WHEN p_approved THEN p_frozen_until ELSE frozen_until END,
+        updated_at = now()
+    WHERE id = p_group_id
+    AND group_state = 'PendingApproval'
+    RETURNING *;
+  IF FOUND AND NOT p_approved THEN
+    UPDATE group_projects SET project_state = 'Canceled'
+      WHERE owner_id = p_group_id
+      AND project_state = 'NotStarted';
+  END IF;
+END
+$$;
@@ -0,0 +1,11 @@
+-- Deletes the job events older than p_before and returns how many there were
+CREATE OR REPLACE FUNCTION prune_job_events_v1(p_before timestamp with time zone) RETURNS bigint
+    LANGUAGE sql
+    AS $$
+  WITH pruned AS (
+          DELETE FROM job_events
+          WHERE created_at < p_before
+          RETURNING id
+      )
+  SELECT count(*) FROM pruned;
+$$;
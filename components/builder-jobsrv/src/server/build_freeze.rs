@@ -0,0 +1,453 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Build freezes of origins. While one of an origin's freeze windows is open, the groups
+//! created for its projects are deferred and queued once the freeze ends. Windows are written
+//! in the wall time of their timezone, so a window from Friday 18:00 to Monday 06:00 keeps those
+//! hours across DST transitions.
+
+use chrono::{DateTime,
+             Datelike,
+             Duration,
+             LocalResult,
+             NaiveDateTime,
+             NaiveTime,
+             TimeZone,
+             Timelike,
+             Utc,
+             Weekday};
+use chrono_tz::Tz;
+use protobuf::RepeatedField;
+
+use crate::{bldr_core::timestamp,
+            data_store::DataStore,
+            error::{Error,
+                    Result},
+            protocol::jobsrv};
+
+const TIME_FORMAT: &str = "%H:%M";
+const DATE_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M";
+
+const MINUTES_PER_WEEK: i64 = 7 * 24 * 60;
+
+// Most windows an origin can have
+const MAX_WINDOWS: usize = 50;
+
+// Windows that together cover the whole week would otherwise be followed around forever
+const MAX_ADJOINING_WINDOWS: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FreezeWindow {
+    Weekly {
+        start_day:  Weekday,
+        start_time: NaiveTime,
+        end_day:    Weekday,
+        end_time:   NaiveTime,
+        timezone:   Tz,
+    },
+    OneOff {
+        starts_at: NaiveDateTime,
+        ends_at:   NaiveDateTime,
+        timezone:  Tz,
+    },
+}
+
+impl FreezeWindow {
+    /// Checks a window as it was submitted
+    pub fn parse(msg: &jobsrv::JobFreezeWindow) -> Result<Self> {
+        let timezone =
+            msg.get_timezone()
+               .parse::<Tz>()
+               .map_err(|_| invalid(format!("unknown timezone {}", msg.get_timezone())))?;
+
+        match msg.get_kind() {
+            jobsrv::JobFreezeWindowKind::Weekly => {
+                let window = FreezeWindow::Weekly { start_day: day(msg.get_start_day())?,
+                                                    start_time: time(msg.get_start_time())?,
+                                                    end_day: day(msg.get_end_day())?,
+                                                    end_time: time(msg.get_end_time())?,
+                                                    timezone };
+                if window.weekly_length() == Some(Duration::zero()) {
+                    return Err(invalid("weekly window ends when it starts".to_string()));
+                }
+                Ok(window)
+            }
+            jobsrv::JobFreezeWindowKind::OneOff => {
+                let starts_at = date_time(msg.get_starts_at())?;
+                let ends_at = date_time(msg.get_ends_at())?;
+                if ends_at <= starts_at {
+                    return Err(invalid(format!("window ends at {} before it starts",
+                                               msg.get_ends_at())));
+                }
+                Ok(FreezeWindow::OneOff { starts_at,
+                                          ends_at,
+                                          timezone })
+            }
+        }
+    }
+
+    /// The window in the canonical form it is stored and listed in
+    pub fn to_msg(&self) -> jobsrv::JobFreezeWindow {
+        let mut msg = jobsrv::JobFreezeWindow::new();
+        match *self {
+            FreezeWindow::Weekly { start_day,
+                                   start_time,
+                                   end_day,
+                                   end_time,
+                                   timezone, } => {
+                msg.set_kind(jobsrv::JobFreezeWindowKind::Weekly);
+                msg.set_timezone(timezone.name().to_string());
+                msg.set_start_day(day_name(start_day).to_string());
+                msg.set_start_time(start_time.format(TIME_FORMAT).to_string());
+                msg.set_end_day(day_name(end_day).to_string());
+                msg.set_end_time(end_time.format(TIME_FORMAT).to_string());
+            }
+            FreezeWindow::OneOff { starts_at,
+                                   ends_at,
+                                   timezone, } => {
+                msg.set_kind(jobsrv::JobFreezeWindowKind::OneOff);
+                msg.set_timezone(timezone.name().to_string());
+                msg.set_starts_at(starts_at.format(DATE_TIME_FORMAT).to_string());
+                msg.set_ends_at(ends_at.format(DATE_TIME_FORMAT).to_string());
+            }
+        }
+        msg
+    }
+
+    /// When the occurrence of the window open at `now` closes, `None` when none is open
+    pub fn open_until(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match *self {
+            FreezeWindow::Weekly { start_day,
+                                   start_time,
+                                   timezone,
+                                   .. } => {
+                let length = self.weekly_length()?;
+                let today = now.with_timezone(&timezone).naive_local().date();
+                let days_back = (7 + today.weekday().num_days_from_monday()
+                                 - start_day.num_days_from_monday())
+                                % 7;
+                let latest_start = today - Duration::days(i64::from(days_back));
+                // The occurrence starting this week may not have begun yet while last week's
+                // is still open
+                let occurrences = [latest_start - Duration::days(7), latest_start];
+                occurrences.iter()
+                           .filter_map(|date| {
+                               let start = date.and_time(start_time);
+                               open_until(timezone, start, start + length, now)
+                           })
+                           .max()
+            }
+            FreezeWindow::OneOff { starts_at,
+                                   ends_at,
+                                   timezone, } => open_until(timezone, starts_at, ends_at, now),
+        }
+    }
+
+    // The wall time a weekly window stays open for, less than a week
+    fn weekly_length(&self) -> Option<Duration> {
+        match *self {
+            FreezeWindow::Weekly { start_day,
+                                   start_time,
+                                   end_day,
+                                   end_time,
+                                   .. } => {
+                let minutes = |day: Weekday, time: NaiveTime| {
+                    i64::from(day.num_days_from_monday()) * 24 * 60
+                    + i64::from(time.num_seconds_from_midnight() / 60)
+                };
+                // Windows ending earlier in the week than they start wrap around its end
+                let length = minutes(end_day, end_time) - minutes(start_day, start_time);
+                Some(Duration::minutes((length + MINUTES_PER_WEEK) % MINUTES_PER_WEEK))
+            }
+            FreezeWindow::OneOff { .. } => None,
+        }
+    }
+}
+
+/// When the freeze open at `now` ends, `None` when the origin isn't frozen. A window opening as
+/// or before another closes extends the freeze.
+pub fn frozen_until(windows: &[FreezeWindow], now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let mut until = None;
+    let mut at = now;
+    for _ in 0..MAX_ADJOINING_WINDOWS {
+        match windows.iter().filter_map(|w| w.open_until(at)).max() {
+            Some(end) => {
+                until = Some(end);
+                at = end;
+            }
+            None => break,
+        }
+    }
+    until
+}
+
+/// Whether a freeze defers the groups of a trigger. Only automated builds are held back,
+/// those requested by hand go through the freeze.
+pub fn defers(trigger: jobsrv::JobGroupTrigger) -> bool {
+    match trigger {
+        jobsrv::JobGroupTrigger::Webhook | jobsrv::JobGroupTrigger::Upload => true,
+        jobsrv::JobGroupTrigger::Unknown
+        | jobsrv::JobGroupTrigger::HabClient
+        | jobsrv::JobGroupTrigger::BuilderUI => false,
+    }
+}
+
+/// When the build freeze the origin is in ends, `None` when it isn't frozen
+pub fn origin_frozen_until(datastore: &DataStore,
+                           origin: &str,
+                           now: DateTime<Utc>)
+                           -> Result<Option<DateTime<Utc>>> {
+    let list = datastore.get_origin_freeze_windows(origin)?;
+    Ok(frozen_until(&parse_all(list.get_windows())?, now))
+}
+
+/// The windows of an origin, with the end of the freeze it is in
+pub fn list(datastore: &DataStore,
+            origin: &str,
+            now: DateTime<Utc>)
+            -> Result<jobsrv::JobFreezeWindows> {
+    let mut list = datastore.get_origin_freeze_windows(origin)?;
+    set_frozen_until(&mut list, now)?;
+    Ok(list)
+}
+
+/// Replaces the windows of an origin, each checked and stored in canonical form
+pub fn set(datastore: &DataStore,
+           msg: &jobsrv::JobFreezeWindowsSet,
+           now: DateTime<Utc>)
+           -> Result<jobsrv::JobFreezeWindows> {
+    if msg.get_windows().len() > MAX_WINDOWS {
+        return Err(invalid(format!("an origin can have at most {} windows", MAX_WINDOWS)));
+    }
+    let windows = parse_all(msg.get_windows())?.iter()
+                                               .map(FreezeWindow::to_msg)
+                                               .collect();
+    let mut msg = msg.clone();
+    msg.set_windows(RepeatedField::from_vec(windows));
+
+    let mut list = datastore.set_origin_freeze_windows(&msg)?;
+    set_frozen_until(&mut list, now)?;
+    Ok(list)
+}
+
+fn parse_all(windows: &[jobsrv::JobFreezeWindow]) -> Result<Vec<FreezeWindow>> {
+    windows.iter().map(FreezeWindow::parse).collect()
+}
+
+fn set_frozen_until(list: &mut jobsrv::JobFreezeWindows, now: DateTime<Utc>) -> Result<()> {
+    if let Some(until) = frozen_until(&parse_all(list.get_windows())?, now) {
+        list.set_frozen_until(timestamp::to_rfc3339(&until));
+    }
+    Ok(())
+}
+
+// The instant a window with the given local bounds closes at, when it is open at `now`
+fn open_until(timezone: Tz,
+              start: NaiveDateTime,
+              end: NaiveDateTime,
+              now: DateTime<Utc>)
+              -> Option<DateTime<Utc>> {
+    let (start, end) = (instant(timezone, start, true), instant(timezone, end, false));
+    if start <= now && now < end {
+        Some(end)
+    } else {
+        None
+    }
+}
+
+// A local time skipped when clocks go forward stands for the moment they do. One repeated when
+// they go back is its first instant for a window's start and its last for its end, so the
+// window covers every hour its wall times do.
+fn instant(timezone: Tz, local: NaiveDateTime, earliest: bool) -> DateTime<Utc> {
+    let mut local = local;
+    loop {
+        match timezone.from_local_datetime(&local) {
+            LocalResult::Single(at) => return at.with_timezone(&Utc),
+            LocalResult::Ambiguous(first, last) => {
+                let at = if earliest { first } else { last };
+                return at.with_timezone(&Utc);
+            }
+            LocalResult::None => local += Duration::minutes(1),
+        }
+    }
+}
+
+fn day(value: &str) -> Result<Weekday> {
+    value.parse::<Weekday>()
+         .map_err(|_| invalid(format!("unknown day {}", value)))
+}
+
+fn day_name(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "mon",
+        Weekday::Tue => "tue",
+        Weekday::Wed => "wed",
+        Weekday::Thu => "thu",
+        Weekday::Fri => "fri",
+        Weekday::Sat => "sat",
+        Weekday::Sun => "sun",
+    }
+}
+
+fn time(value: &str) -> Result<NaiveTime> {
+    let reason = || format!("time {} is not HH:MM", value);
+    NaiveTime::parse_from_str(value, TIME_FORMAT).map_err(|_| invalid(reason()))
+}
+
+fn date_time(value: &str) -> Result<NaiveDateTime> {
+    let reason = || format!("date-time {} is not YYYY-MM-DDTHH:MM", value);
+    NaiveDateTime::parse_from_str(value, DATE_TIME_FORMAT).map_err(|_| invalid(reason()))
+}
+
+fn invalid(reason: String) -> Error { Error::InvalidFreezeWindow(reason) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weekly(start: (&str, &str), end: (&str, &str), timezone: &str) -> FreezeWindow {
+        let mut msg = jobsrv::JobFreezeWindow::new();
+        msg.set_kind(jobsrv::JobFreezeWindowKind::Weekly);
+        msg.set_timezone(timezone.to_string());
+        msg.set_start_day(start.0.to_string());
+        msg.set_start_time(start.1.to_string());
+        msg.set_end_day(end.0.to_string());
+        msg.set_end_time(end.1.to_string());
+        FreezeWindow::parse(&msg).unwrap()
+    }
+
+    fn one_off(starts_at: &str, ends_at: &str, timezone: &str) -> FreezeWindow {
+        let mut msg = jobsrv::JobFreezeWindow::new();
+        msg.set_kind(jobsrv::JobFreezeWindowKind::OneOff);
+        msg.set_timezone(timezone.to_string());
+        msg.set_starts_at(starts_at.to_string());
+        msg.set_ends_at(ends_at.to_string());
+        FreezeWindow::parse(&msg).unwrap()
+    }
+
+    fn utc(value: &str) -> DateTime<Utc> { value.parse().unwrap() }
+
+    #[test]
+    fn weekend_freeze_spans_the_week_boundary() {
+        // Friday 18:00 to Monday 06:00, Berlin at UTC+2 in October
+        let windows = [weekly(("fri", "18:00"), ("mon", "06:00"), "Europe/Berlin")];
+        let release = Some(utc("2019-10-14T04:00:00Z"));
+
+        assert_eq!(frozen_until(&windows, utc("2019-10-11T15:59:00Z")), None);
+        assert_eq!(frozen_until(&windows, utc("2019-10-11T16:00:00Z")), release);
+        assert_eq!(frozen_until(&windows, utc("2019-10-13T12:00:00Z")), release);
+        assert_eq!(frozen_until(&windows, utc("2019-10-14T03:59:00Z")), release);
+        assert_eq!(frozen_until(&windows, utc("2019-10-14T04:00:00Z")), None);
+        assert_eq!(frozen_until(&windows, utc("2019-10-16T12:00:00Z")), None);
+    }
+
+    #[test]
+    fn weekly_windows_keep_their_wall_times_across_dst() {
+        // Berlin leaves DST on Sunday 2019-10-27, during the weekend freeze
+        let windows = [weekly(("fri", "18:00"), ("mon", "06:00"), "Europe/Berlin")];
+        assert_eq!(frozen_until(&windows, utc("2019-10-25T16:00:00Z")),
+                   Some(utc("2019-10-28T05:00:00Z")));
+        assert_eq!(frozen_until(&windows, utc("2019-10-28T04:30:00Z")),
+                   Some(utc("2019-10-28T05:00:00Z")));
+
+        // The following weekend starts at 18:00 winter time
+        assert_eq!(frozen_until(&windows, utc("2019-11-01T16:30:00Z")), None);
+        assert_eq!(frozen_until(&windows, utc("2019-11-01T17:00:00Z")),
+                   Some(utc("2019-11-04T05:00:00Z")));
+    }
+
+    #[test]
+    fn times_skipped_or_repeated_by_dst_cover_the_whole_window() {
+        // 02:30 is skipped on 2019-03-31 in Berlin, the window opens when clocks go forward
+        let windows = [weekly(("sun", "02:30"), ("sun", "05:00"), "Europe/Berlin")];
+        assert_eq!(frozen_until(&windows, utc("2019-03-31T00:59:00Z")), None);
+        assert_eq!(frozen_until(&windows, utc("2019-03-31T01:00:00Z")),
+                   Some(utc("2019-03-31T03:00:00Z")));
+
+        // 02:30 happens twice on 2019-10-27, the window closes at the second
+        let windows = [weekly(("sun", "00:00"), ("sun", "02:30"), "Europe/Berlin")];
+        assert_eq!(frozen_until(&windows, utc("2019-10-26T23:00:00Z")),
+                   Some(utc("2019-10-27T01:30:00Z")));
+        assert_eq!(frozen_until(&windows, utc("2019-10-27T01:30:00Z")), None);
+    }
+
+    #[test]
+    fn overlapping_and_adjoining_windows_extend_the_freeze() {
+        let windows = [weekly(("fri", "18:00"), ("mon", "06:00"), "UTC"),
+                       one_off("2019-10-13T00:00", "2019-10-15T00:00", "UTC"),
+                       weekly(("tue", "00:00"), ("tue", "12:00"), "UTC")];
+        assert_eq!(frozen_until(&windows, utc("2019-10-12T00:00:00Z")),
+                   Some(utc("2019-10-15T12:00:00Z")));
+        // A window nested in a longer one doesn't end the freeze early, and the weekend freeze
+        // open when the holidays end extends it
+        let windows = [one_off("2019-12-20T00:00", "2020-01-06T00:00", "America/New_York"),
+                       weekly(("fri", "18:00"), ("mon", "06:00"), "America/New_York")];
+        assert_eq!(frozen_until(&windows, utc("2019-12-21T12:00:00Z")),
+                   Some(utc("2020-01-06T11:00:00Z")));
+    }
+
+    #[test]
+    fn windows_in_different_timezones_are_compared_as_instants() {
+        let windows = [weekly(("mon", "09:00"), ("mon", "10:00"), "Asia/Tokyo"),
+                       weekly(("mon", "01:30"), ("mon", "02:30"), "Europe/London")];
+        // 09:00 in Tokyo is 01:00 in London, still on summer time in October, so the London
+        // window opens before the Tokyo one closes
+        assert_eq!(frozen_until(&windows, utc("2019-10-14T00:00:00Z")),
+                   Some(utc("2019-10-14T01:30:00Z")));
+    }
+
+    #[test]
+    fn windows_are_stored_in_canonical_form() {
+        let window = weekly(("Friday", "18:00"), ("MON", "06:00"), "Europe/Berlin");
+        let msg = window.to_msg();
+        assert_eq!(msg.get_start_day(), "fri");
+        assert_eq!(msg.get_end_day(), "mon");
+        assert_eq!(FreezeWindow::parse(&msg).unwrap(), window);
+
+        let window = one_off("2019-12-23T00:00", "2020-01-02T00:00", "UTC");
+        assert_eq!(FreezeWindow::parse(&window.to_msg()).unwrap(), window);
+    }
+
+    #[test]
+    fn invalid_windows_are_refused() {
+        let mut msg = weekly(("fri", "18:00"), ("mon", "06:00"), "UTC").to_msg();
+        msg.set_timezone("Mars/Olympus_Mons".to_string());
+        assert!(FreezeWindow::parse(&msg).is_err());
+
+        let mut msg = weekly(("fri", "18:00"), ("mon", "06:00"), "UTC").to_msg();
+        msg.set_end_time("6am".to_string());
+        assert!(FreezeWindow::parse(&msg).is_err());
+
+        let mut msg = weekly(("fri", "18:00"), ("mon", "06:00"), "UTC").to_msg();
+        msg.set_end_day("fri".to_string());
+        msg.set_end_time("18:00".to_string());
+        assert!(FreezeWindow::parse(&msg).is_err());
+
+        let mut msg = one_off("2019-12-23T00:00", "2020-01-02T00:00", "UTC").to_msg();
+        msg.set_ends_at("2019-12-22T00:00".to_string());
+        match FreezeWindow::parse(&msg) {
+            Err(Error::InvalidFreezeWindow(_)) => (),
+            other => panic!("Expected InvalidFreezeWindow, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn only_automated_groups_are_deferred() {
+        assert!(defers(jobsrv::JobGroupTrigger::Webhook));
+        assert!(defers(jobsrv::JobGroupTrigger::Upload));
+        assert!(!defers(jobsrv::JobGroupTrigger::HabClient));
+        assert!(!defers(jobsrv::JobGroupTrigger::BuilderUI));
+        assert!(!defers(jobsrv::JobGroupTrigger::Unknown));
+    }
+}
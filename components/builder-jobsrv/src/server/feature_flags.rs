@@ -0,0 +1,171 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The scheduler behaviors operators switch at runtime. The flags set in the `feature_flags`
+//! table are reloaded on an interval, so a change made through another jobsrv or in the
+//! database takes effect without a restart; a change made through this jobsrv takes effect at
+//! once.
+
+use std::{collections::HashMap,
+          thread,
+          time::Duration};
+
+use protobuf::RepeatedField;
+
+use crate::{bldr_core::feature_flags::{FeatureFlags,
+                                       Flag,
+                                       FlagState},
+            config::FeatureFlagCfg,
+            data_store::DataStore,
+            error::{Error,
+                    Result},
+            protocol::jobsrv};
+
+use super::supervisor::Supervisor;
+
+/// Caps the jobs of a group dispatched at once at the group's `max_concurrency`
+pub const GROUP_CONCURRENCY_CAP: Flag = Flag { name:    "group_concurrency_cap",
+                                               default: true, };
+
+/// Warns of the deprecated dependencies of a new group's projects
+pub const GROUP_DEPRECATION_WARNINGS: Flag = Flag { name:    "group_deprecation_warnings",
+                                                    default: true, };
+
+/// Every flag the jobsrv evaluates. Only these can be set.
+pub const FLAGS: &[Flag] = &[GROUP_CONCURRENCY_CAP, GROUP_DEPRECATION_WARNINGS];
+
+pub fn known(name: &str) -> Option<Flag> { FLAGS.iter().find(|f| f.name == name).cloned() }
+
+/// Checks that `msg` sets a known flag to a valid state
+pub fn validate(msg: &jobsrv::JobFeatureFlagSet) -> Result<Flag> {
+    let flag = known(msg.get_name()).ok_or(Error::NotFound)?;
+    if msg.get_rollout_percent() > 100 {
+        return Err(Error::InvalidFeatureFlag(format!("rollout_percent {} is over 100",
+                                                     msg.get_rollout_percent())));
+    }
+    Ok(flag)
+}
+
+pub fn state(flag: &jobsrv::JobFeatureFlag) -> FlagState {
+    FlagState { enabled:         flag.get_enabled(),
+                rollout_percent: flag.get_rollout_percent().min(100) as u8, }
+}
+
+/// Replaces the flags with those set in the datastore
+pub fn load(datastore: &DataStore, flags: &FeatureFlags) -> Result<()> {
+    let stored = datastore.get_feature_flags()?;
+    flags.replace(stored.iter()
+                        .map(|flag| (flag.get_name().to_string(), state(flag)))
+                        .collect::<HashMap<_, _>>());
+    Ok(())
+}
+
+/// Every known flag, those never set with their default
+pub fn list(datastore: &DataStore) -> Result<jobsrv::JobFeatureFlagList> {
+    let mut list = jobsrv::JobFeatureFlagList::new();
+    list.set_flags(RepeatedField::from_vec(with_defaults(datastore.get_feature_flags()?)));
+    Ok(list)
+}
+
+fn with_defaults(stored: Vec<jobsrv::JobFeatureFlag>) -> Vec<jobsrv::JobFeatureFlag> {
+    FLAGS.iter()
+         .map(|known| {
+             match stored.iter().find(|f| f.get_name() == known.name) {
+                 Some(flag) => flag.clone(),
+                 None => {
+                     let mut flag = jobsrv::JobFeatureFlag::new();
+                     flag.set_name(known.name.to_string());
+                     flag.set_enabled(known.default);
+                     flag.set_rollout_percent(100);
+                     flag.set_is_default(true);
+                     flag
+                 }
+             }
+         })
+         .collect()
+}
+
+/// Loads the flags, then reloads them every configured interval. Until a load succeeds the
+/// flags have their defaults.
+pub fn start_refresh(cfg: &FeatureFlagCfg,
+                     datastore: &DataStore,
+                     flags: &FeatureFlags,
+                     supervisor: &Supervisor) {
+    let (datastore, flags) = (datastore.clone(), flags.clone());
+    let interval = Duration::from_secs(cfg.refresh_sec.max(1));
+    supervisor.spawn("feature-flags", move |rz| {
+                  rz.send(()).unwrap();
+                  loop {
+                      if let Err(err) = load(&datastore, &flags) {
+                          warn!("Unable to load feature flags, err={}", err);
+                      }
+                      thread::sleep(interval);
+                  }
+              })
+              .expect("Unable to start feature flag refresh thread");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_msg(name: &str, rollout_percent: u32) -> jobsrv::JobFeatureFlagSet {
+        let mut msg = jobsrv::JobFeatureFlagSet::new();
+        msg.set_name(name.to_string());
+        msg.set_enabled(true);
+        msg.set_rollout_percent(rollout_percent);
+        msg
+    }
+
+    #[test]
+    fn only_known_flags_can_be_set() {
+        let flag = validate(&set_msg("group_concurrency_cap", 50)).unwrap();
+        assert_eq!(flag.name, GROUP_CONCURRENCY_CAP.name);
+        match validate(&set_msg("no_such_flag", 50)) {
+            Err(Error::NotFound) => (),
+            other => panic!("Expected NotFound, got {:?}", other.map(|f| f.name)),
+        }
+        match validate(&set_msg("group_concurrency_cap", 101)) {
+            Err(Error::InvalidFeatureFlag(_)) => (),
+            other => {
+                panic!("Expected InvalidFeatureFlag, got {:?}",
+                       other.map(|f| f.name))
+            }
+        }
+    }
+
+    #[test]
+    fn flags_never_set_are_listed_with_their_default() {
+        let mut stored = jobsrv::JobFeatureFlag::new();
+        stored.set_name(GROUP_DEPRECATION_WARNINGS.name.to_string());
+        stored.set_enabled(false);
+        stored.set_rollout_percent(100);
+        stored.set_updated_by("admin".to_string());
+
+        let listed = with_defaults(vec![stored.clone()]);
+        assert_eq!(listed.len(), FLAGS.len());
+
+        let cap = listed.iter()
+                        .find(|f| f.get_name() == GROUP_CONCURRENCY_CAP.name)
+                        .unwrap();
+        assert!(cap.get_enabled());
+        assert!(cap.get_is_default());
+        assert_eq!(cap.get_rollout_percent(), 100);
+
+        let warnings = listed.iter()
+                             .find(|f| f.get_name() == GROUP_DEPRECATION_WARNINGS.name)
+                             .unwrap();
+        assert_eq!(warnings, &stored);
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Keeps the in-memory build graph consistent with the packages table. A random sample of
+//! packages is compared against the graph on a timer, and operators can ask for every package
+//! to be compared. Divergent packages are fed back through `TargetGraph::extend`, the same
+//! update that uploads go through.
+
+use std::{sync::{Arc,
+                 Mutex,
+                 RwLock},
+          thread,
+          time::Duration};
+
+use chrono::Utc;
+
+use crate::{bldr_core::{metrics::CounterMetric,
+                        package_graph::Divergence,
+                        target_graph::TargetGraph},
+            config::GraphCheckCfg,
+            db::{models::package::*,
+                 DbPool},
+            error::{Error,
+                    Result},
+            hab_core::package::PackageIdent,
+            protocol::{jobsrv,
+                       originsrv::OriginPackage}};
+
+use super::{feat,
+            metrics::Counter,
+            supervisor::{self,
+                         Supervisor}};
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    InSync,
+    Repaired,
+    Unrepaired,
+}
+
+#[derive(Clone)]
+pub struct GraphChecker {
+    db:     DbPool,
+    graph:  Arc<RwLock<TargetGraph>>,
+    status: Arc<Mutex<jobsrv::JobGraphVerifyStatus>>,
+}
+
+impl GraphChecker {
+    pub fn new(db: DbPool, graph: &Arc<RwLock<TargetGraph>>) -> Self {
+        GraphChecker { db,
+                       graph: graph.clone(),
+                       status: Arc::new(Mutex::new(jobsrv::JobGraphVerifyStatus::new())) }
+    }
+
+    /// Checks a random sample of packages every configured interval
+    pub fn start(&self, cfg: &GraphCheckCfg, supervisor: &Supervisor) {
+        if cfg.interval_sec == 0 {
+            info!("Build graph sampling disabled");
+            return;
+        }
+
+        let checker = self.clone();
+        let interval = Duration::from_secs(cfg.interval_sec);
+        let sample_size = i64::from(cfg.sample_size);
+        supervisor.spawn("graph-checker", move |rz| {
+                      rz.send(()).unwrap();
+                      loop {
+                          supervisor::checkpoint();
+                          thread::sleep(interval);
+                          if let Err(err) = checker.check_sample(sample_size) {
+                              warn!("Unable to check build graph sample, err={}", err);
+                          }
+                      }
+                  })
+                  .expect("Unable to start graph checker thread");
+    }
+
+    /// Starts comparing every latest package against the graph in the background. Only one
+    /// full check runs at a time.
+    pub fn start_full_check(&self) -> Result<jobsrv::JobGraphVerifyStatus> {
+        {
+            let mut status = self.status.lock().unwrap();
+            if status.get_running() {
+                return Err(Error::Conflict);
+            }
+            *status = jobsrv::JobGraphVerifyStatus::new();
+            status.set_running(true);
+            status.set_started_at(Utc::now().to_rfc3339());
+        }
+
+        let checker = self.clone();
+        let spawned = thread::Builder::new().name("graph-verify".to_string())
+                                            .spawn(move || {
+                                                let result = checker.check_all();
+                                                let mut status = checker.status.lock().unwrap();
+                                                if let Err(err) = result {
+                                                    warn!("Full build graph check failed, err={}",
+                                                          err);
+                                                    status.set_error(err.to_string());
+                                                }
+                                                status.set_running(false);
+                                                status.set_finished_at(Utc::now().to_rfc3339());
+                                            });
+
+        if let Err(err) = spawned {
+            let mut status = self.status.lock().unwrap();
+            status.set_running(false);
+            return Err(Error::IO(err));
+        }
+        Ok(self.status())
+    }
+
+    pub fn status(&self) -> jobsrv::JobGraphVerifyStatus { self.status.lock().unwrap().clone() }
+
+    fn check_sample(&self, sample_size: i64) -> Result<()> {
+        let conn = self.db.get_conn()?;
+        let sample = Package::sample(sample_size, &*conn)?;
+
+        let (mut discrepancies, mut unrepaired) = (0, 0);
+        for package in sample {
+            // The sample may hold any release, the graph only the latest
+            let ident = PackageIdent::new(package.origin.clone(), package.name.clone(), None, None);
+            let latest = GetLatestPackage { ident:      BuilderPackageIdent(ident),
+                                            target:     package.target,
+                                            visibility: PackageVisibility::all(), };
+            let latest: OriginPackage = Package::get_latest(latest, &*conn)?.into();
+            match self.check(&latest) {
+                Outcome::InSync => (),
+                Outcome::Repaired => discrepancies += 1,
+                Outcome::Unrepaired => {
+                    discrepancies += 1;
+                    unrepaired += 1;
+                }
+            }
+        }
+
+        if discrepancies > 0 {
+            warn!("Build graph sample found {} discrepancies, {} unrepaired",
+                  discrepancies, unrepaired);
+        }
+        Ok(())
+    }
+
+    fn check_all(&self) -> Result<()> {
+        let conn = self.db.get_conn()?;
+        let packages = Package::get_all_latest(&*conn)?;
+        self.status.lock().unwrap().set_total(packages.len() as u64);
+
+        for package in packages {
+            let package: OriginPackage = package.into();
+            let outcome = self.check(&package);
+
+            let mut status = self.status.lock().unwrap();
+            let checked = status.get_checked() + 1;
+            status.set_checked(checked);
+            match outcome {
+                Outcome::InSync => (),
+                Outcome::Repaired => {
+                    let discrepancies = status.get_discrepancies() + 1;
+                    status.set_discrepancies(discrepancies);
+                    let repaired = status.get_repaired() + 1;
+                    status.set_repaired(repaired);
+                }
+                Outcome::Unrepaired => {
+                    let discrepancies = status.get_discrepancies() + 1;
+                    status.set_discrepancies(discrepancies);
+                    status.mut_unrepaired()
+                          .push(package.get_ident().to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check(&self, package: &OriginPackage) -> Outcome {
+        let use_build_deps = feat::is_enabled(feat::BuildDeps);
+
+        let divergence = match self.graph.read().unwrap().diff(package, use_build_deps) {
+            Some(divergence) => divergence,
+            None => return Outcome::InSync,
+        };
+        warn!("Build graph diverges from package data for {} ({}): {:?}",
+              package.get_ident(),
+              package.get_target(),
+              divergence);
+        Counter::GraphDiscrepancies.increment();
+
+        // Extending never replaces a newer release, the package it came from is gone
+        if let Divergence::NewerLatest(_) = divergence {
+            return Outcome::Unrepaired;
+        }
+
+        let mut graph = self.graph.write().unwrap();
+        graph.extend(package, use_build_deps);
+        match graph.diff(package, use_build_deps) {
+            None => Outcome::Repaired,
+            Some(divergence) => {
+                warn!("Unable to repair build graph for {} ({}): {:?}",
+                      package.get_ident(),
+                      package.get_target(),
+                      divergence);
+                Outcome::Unrepaired
+            }
+        }
+    }
+}
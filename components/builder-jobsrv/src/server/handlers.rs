@@ -21,6 +21,7 @@ use std::{collections::HashSet,
           path::PathBuf,
           str::FromStr};
 
+use chrono::Utc;
 use diesel::{self,
              result::Error::NotFound};
 use protobuf::RepeatedField;
@@ -38,12 +39,18 @@ use crate::protocol::{jobsrv,
                       net,
                       originsrv};
 
-use crate::server::{feat,
+use crate::server::{build_freeze,
+                    feat,
+                    feature_flags::{self,
+                                    GROUP_DEPRECATION_WARNINGS},
+                    large_groups::{self,
+                                   Review},
                     scheduler::ScheduleClient,
                     worker_manager::WorkerMgrClient};
 
-use crate::error::{Error,
-                   Result};
+use crate::{deadline,
+            error::{Error,
+                    Result}};
 
 pub fn job_get(req: &RpcMessage, state: &AppState) -> Result<RpcMessage> {
     let msg = req.parse::<jobsrv::JobGet>()?;
@@ -219,8 +226,88 @@ pub fn job_group_cancel(req: &RpcMessage, state: &AppState) -> Result<RpcMessage
     RpcMessage::make(&net::NetOk::new()).map_err(Error::BuilderCore)
 }
 
+pub fn job_group_approve(req: &RpcMessage, state: &AppState) -> Result<RpcMessage> {
+    let msg = req.parse::<jobsrv::JobGroupApprove>()?;
+    debug!("job_group_approve message: {:?}", msg);
+
+    // An approved group is deferred when its origin froze in the meantime, as it would have
+    // been had it not needed approval
+    let frozen_until = if msg.get_approved() {
+        large_groups::approved_frozen_until(&state.datastore, msg.get_group_id(), Utc::now())?
+    } else {
+        None
+    };
+
+    let group = match state.datastore
+                           .review_job_group(msg.get_group_id(), msg.get_approved(), frozen_until)?
+    {
+        Some(group) => group,
+        None => {
+            // The group doesn't exist, or was reviewed or expired already
+            let mut jgg = jobsrv::JobGroupGet::new();
+            jgg.set_group_id(msg.get_group_id());
+            return match state.datastore.get_job_group(&jgg)? {
+                Some(_) => Err(Error::Conflict),
+                None => Err(Error::NotFound),
+            };
+        }
+    };
+
+    let operation = if msg.get_approved() {
+        info!("Group {} approved by {}",
+              group.get_id(),
+              msg.get_requester_name());
+        ScheduleClient::default().notify()?;
+        jobsrv::JobGroupOperation::JobGroupOpApprove
+    } else {
+        info!("Group {} rejected by {}",
+              group.get_id(),
+              msg.get_requester_name());
+        jobsrv::JobGroupOperation::JobGroupOpReject
+    };
+
+    let mut jga = jobsrv::JobGroupAudit::new();
+    jga.set_group_id(group.get_id());
+    jga.set_operation(operation);
+    jga.set_requester_id(msg.get_requester_id());
+    jga.set_requester_name(msg.get_requester_name().to_string());
+    if let Err(err) = state.datastore.create_audit_entry(&jga) {
+        warn!("Failed to create audit entry, err={:?}", err);
+    }
+
+    RpcMessage::make(&group).map_err(Error::BuilderCore)
+}
+
+pub fn job_group_update(req: &RpcMessage, state: &AppState) -> Result<RpcMessage> {
+    let msg = req.parse::<jobsrv::JobGroupUpdate>()?;
+    debug!("job_group_update message: {:?}", msg);
+
+    let group = state.datastore
+                     .set_job_group_max_concurrency(msg.get_group_id(), msg.get_max_concurrency())?
+                     .ok_or(Error::NotFound)?;
+
+    // Add audit entry
+    let mut jga = jobsrv::JobGroupAudit::new();
+    jga.set_group_id(group.get_id());
+    jga.set_operation(jobsrv::JobGroupOperation::JobGroupOpUpdate);
+    jga.set_trigger(msg.get_trigger());
+    jga.set_requester_id(msg.get_requester_id());
+    jga.set_requester_name(msg.get_requester_name().to_string());
+
+    match state.datastore.create_audit_entry(&jga) {
+        Ok(_) => (),
+        Err(err) => {
+            warn!("Failed to create audit entry, err={:?}", err);
+        }
+    };
+
+    // A group put back to pending is picked up with the new cap on the next pass
+    ScheduleClient::default().notify()?;
+    RpcMessage::make(&group).map_err(Error::BuilderCore)
+}
+
 fn is_project_buildable(state: &AppState, project_name: &str) -> bool {
-    let conn = match state.db.get_conn().map_err(Error::Db) {
+    let conn = match deadline::conn(&state.db) {
         Ok(conn_ref) => conn_ref,
         Err(_) => return false,
     };
@@ -236,6 +323,23 @@ fn is_project_buildable(state: &AppState, project_name: &str) -> bool {
     }
 }
 
+// How long webhook builds of the project wait in the queue for further pushes, in seconds
+fn build_quiet_period(state: &AppState, project_name: &str) -> u32 {
+    let conn = match deadline::conn(&state.db) {
+        Ok(conn_ref) => conn_ref,
+        Err(_) => return 0,
+    };
+
+    match Project::get(project_name, &*conn) {
+        Ok(project) => project.quiet_period_secs as u32,
+        Err(err) => {
+            debug!("Unable to retrieve project: {:?}, error: {:?}",
+                   project_name, err);
+            0
+        }
+    }
+}
+
 fn populate_build_projects(msg: &jobsrv::JobGroupSpec,
                            state: &AppState,
                            rdeps: &[(String, String)],
@@ -297,6 +401,72 @@ fn populate_build_projects(msg: &jobsrv::JobGroupSpec,
     }
 }
 
+// Projects of a group that directly depend on a deprecated package. The warnings are only
+// informational, so a failed lookup leaves them out rather than failing the group.
+fn deprecation_warnings(state: &AppState,
+                        target: &str,
+                        projects: &[(String, String)],
+                        conn: &diesel::pg::PgConnection)
+                        -> RepeatedField<jobsrv::JobGroupDeprecationWarning> {
+    let project_deps: Vec<(String, Vec<String>)> = {
+        let target_graph = state.graph.read().unwrap();
+        let graph = match target_graph.graph(target) {
+            Some(graph) => graph,
+            None => return RepeatedField::new(),
+        };
+        projects.iter()
+                .filter_map(|(name, _)| graph.deps(name).map(|deps| (name.clone(), deps)))
+                .collect()
+    };
+
+    let mut names: Vec<String> = project_deps.iter()
+                                             .flat_map(|(_, deps)| deps.iter().cloned())
+                                             .collect();
+    names.sort();
+    names.dedup();
+    if names.is_empty() {
+        return RepeatedField::new();
+    }
+
+    let deprecations = match PackageDeprecation::list(&names, conn) {
+        Ok(deprecations) => deprecations,
+        Err(err) => {
+            warn!("Failed to look up package deprecations, err={:?}", err);
+            return RepeatedField::new();
+        }
+    };
+
+    let mut warnings = RepeatedField::new();
+    for (project_name, deps) in &project_deps {
+        for deprecation in &deprecations {
+            let dependency = deprecation.short_name();
+            if !deps.contains(&dependency) {
+                continue;
+            }
+            let mut warning = jobsrv::JobGroupDeprecationWarning::new();
+            warning.set_project_name(project_name.clone());
+            warning.set_dependency(dependency);
+            warning.set_message(deprecation.message.clone());
+            if let Some(ref replacement) = deprecation.replacement {
+                warning.set_replacement(replacement.clone());
+            }
+            warnings.push(warning);
+        }
+    }
+    warnings
+}
+
+// The names of `omitted` that are not projects of `group`
+fn still_omitted(group: &jobsrv::JobGroup, omitted: Vec<String>) -> Vec<String> {
+    let projects: HashSet<&str> = group.get_projects()
+                                       .iter()
+                                       .map(|project| project.get_name())
+                                       .collect();
+    omitted.into_iter()
+           .filter(|name| !projects.contains(name.as_str()))
+           .collect()
+}
+
 pub fn job_group_create(req: &RpcMessage, state: &AppState) -> Result<RpcMessage> {
     let msg = req.parse::<jobsrv::JobGroupSpec>()?;
     debug!("job_group_create message: {:?}", msg);
@@ -364,22 +534,43 @@ pub fn job_group_create(req: &RpcMessage, state: &AppState) -> Result<RpcMessage
     }
 
     // Search the packages graph to find the reverse dependencies
+    let mut omitted = Vec::new();
     if !msg.get_package_only() {
         let rdeps_opt = {
             let target_graph = state.graph.read().unwrap();
             let graph = target_graph.graph(msg.get_target()).unwrap(); // Unwrap OK
+
+            // An exclusion the graph does not know would leave nothing out, it is most likely
+            // a typo
+            if let Some(unknown) = msg.get_exclude()
+                                      .iter()
+                                      .find(|name| graph.resolve(name).is_none())
+            {
+                debug!("JobGroupSpec, unknown exclusion {}", unknown);
+                return Err(Error::UnknownExclusion(unknown.to_string()));
+            }
             start_time = PreciseTime::now();
             let ret = graph.rdeps(&project_name);
+            if !msg.get_exclude().is_empty() {
+                omitted = graph.excluded_rdeps(&project_name, msg.get_exclude())
+                               .unwrap_or_default();
+            }
             end_time = PreciseTime::now();
             ret
         };
 
         match rdeps_opt {
-            Some(rdeps) => {
+            Some(mut rdeps) => {
                 debug!("Graph rdeps: {} items ({} sec)\n",
                        rdeps.len(),
                        start_time.to(end_time));
 
+                if !omitted.is_empty() {
+                    let omitted: HashSet<&String> = omitted.iter().collect();
+                    rdeps.retain(|(name, _)| !omitted.contains(name));
+                    debug!("Graph rdeps: {} items omitted by exclusions", omitted.len());
+                }
+
                 populate_build_projects(&msg, state, &rdeps, &mut projects);
             }
             None => {
@@ -388,6 +579,35 @@ pub fn job_group_create(req: &RpcMessage, state: &AppState) -> Result<RpcMessage
         }
     }
 
+    // Dry runs, and groups the request has yet to confirm, are described rather than created
+    let review = large_groups::review(&state.large_groups, &msg, projects.len());
+    if msg.get_dry_run() || review == Review::ConfirmationRequired {
+        let mut group = jobsrv::JobGroup::new();
+        group.set_id(0);
+        group.set_state(jobsrv::JobGroupState::GroupPending);
+        group.set_project_name(project_name);
+        group.set_target(msg.get_target().to_string());
+        group.set_project_count(projects.len() as u32);
+        if review == Review::ConfirmationRequired {
+            debug!("JobGroupSpec, {} projects need confirming", projects.len());
+            group.set_confirmation_required(true);
+        } else {
+            let projects = projects.into_iter()
+                                   .map(|(name, ident)| {
+                                       let mut project = jobsrv::JobGroupProject::new();
+                                       project.set_name(name);
+                                       project.set_ident(ident);
+                                       project.set_state(jobsrv::JobGroupProjectState::NotStarted);
+                                       project.set_target(msg.get_target().to_string());
+                                       project
+                                   })
+                                   .collect();
+            group.set_projects(projects);
+        }
+        group.set_omitted(RepeatedField::from_vec(omitted));
+        return RpcMessage::make(&group).map_err(Error::BuilderCore);
+    }
+
     let group = if projects.is_empty() {
         debug!("No projects need building - group is complete");
 
@@ -397,22 +617,89 @@ pub fn job_group_create(req: &RpcMessage, state: &AppState) -> Result<RpcMessage
         new_group.set_state(jobsrv::JobGroupState::GroupComplete);
         new_group.set_projects(projects);
         new_group.set_target(msg.get_target().to_string());
+        new_group.set_omitted(RepeatedField::from_vec(omitted));
         new_group
     } else {
+        // A build freeze of the origin defers the groups of webhooks and uploads until it ends.
+        // Groups requested by hand go through it and are audited as overrides.
+        let frozen_until =
+            build_freeze::origin_frozen_until(&state.datastore, msg.get_origin(), Utc::now())?;
+        let overridden = frozen_until.is_some() && !build_freeze::defers(msg.get_trigger());
+        let frozen_until = if overridden { None } else { frozen_until };
+
         // If already have a queued job group (queue length: 1 per project and target),
-        // then return that group, else create a new job group
+        // then return that group, else create a new job group. Deferred groups are reused
+        // the same way while the freeze lasts. Outside a freeze the request is folded into
+        // the queued group, moving it to the request's ref.
         // TODO (SA) - update the group's projects instead of just returning the group
-        let conn = state.db.get_conn().map_err(Error::Db)?;
-
-        let new_group = match Group::get_queued(&project_name, &msg.get_target(), &*conn) {
-            Ok(group) => {
-                debug!("JobGroupSpec, project {} is already queued", project_name);
-                group.into()
+        let conn = deadline::conn(&state.db)?;
+        let mut reused = false;
+        let mut new_group = match frozen_until {
+            // Groups awaiting approval are reused the same way as deferred ones, and only
+            // notify the operators when created. A freeze defers them once they are approved.
+            _ if review == Review::ApprovalRequired => {
+                match Group::get_pending_approval(&project_name, &msg.get_target(), &*conn) {
+                    Ok(group) => {
+                        debug!("JobGroupSpec, project {} already awaits approval",
+                               project_name);
+                        reused = true;
+                        group.into()
+                    }
+                    Err(NotFound) => {
+                        let group =
+                            state.datastore
+                                 .create_job_group(&msg,
+                                                   projects.clone(),
+                                                   jobsrv::JobGroupState::GroupPendingApproval,
+                                                   None)?;
+                        info!("Group {} of {} projects awaits approval",
+                              group.get_id(),
+                              projects.len());
+                        large_groups::notify(&state.large_groups,
+                                             &msg,
+                                             &group,
+                                             projects.len(),
+                                             &*conn);
+                        group
+                    }
+                    Err(err) => {
+                        debug!("Failed to retrieve groups awaiting approval, err = {}", err);
+                        return Err(Error::DieselError(err));
+                    }
+                }
             }
-            Err(NotFound) => state.datastore.create_job_group(&msg, projects)?,
-            Err(err) => {
-                debug!("Failed to retrieve queued groups, err = {}", err);
-                return Err(Error::DieselError(err));
+            Some(_) => {
+                match Group::get_deferred(&project_name, &msg.get_target(), &*conn) {
+                    Ok(group) => {
+                        debug!("JobGroupSpec, project {} is already deferred", project_name);
+                        reused = true;
+                        group.into()
+                    }
+                    Err(NotFound) => {
+                        state.datastore
+                             .create_job_group(&msg,
+                                               projects.clone(),
+                                               jobsrv::JobGroupState::GroupDeferredByFreeze,
+                                               frozen_until)?
+                    }
+                    Err(err) => {
+                        debug!("Failed to retrieve deferred groups, err = {}", err);
+                        return Err(Error::DieselError(err));
+                    }
+                }
+            }
+            None => {
+                let quiet_period = match msg.get_trigger() {
+                    jobsrv::JobGroupTrigger::Webhook => build_quiet_period(state, &project_name),
+                    _ => 0,
+                };
+                let group = state.datastore
+                                 .queue_job_group(&msg, projects.clone(), quiet_period)?;
+                debug!("JobGroupSpec, project {} queued in group {}, superseded refs {:?}",
+                       project_name,
+                       group.get_id(),
+                       group.get_superseded_refs());
+                group
             }
         };
         ScheduleClient::default().notify()?;
@@ -432,6 +719,30 @@ pub fn job_group_create(req: &RpcMessage, state: &AppState) -> Result<RpcMessage
             }
         };
 
+        if overridden {
+            info!("Build freeze of {} overridden by {} for group {}",
+                  msg.get_origin(),
+                  msg.get_requester_name(),
+                  new_group.get_id());
+            jga.set_operation(jobsrv::JobGroupOperation::JobGroupOpFreezeOverride);
+            if let Err(err) = state.datastore.create_audit_entry(&jga) {
+                warn!("Failed to create freeze override audit entry, err={:?}",
+                      err);
+            }
+        }
+
+        if state.flags
+                .is_enabled_for(GROUP_DEPRECATION_WARNINGS, new_group.get_id())
+        {
+            let warnings = deprecation_warnings(state, msg.get_target(), &projects, &*conn);
+            new_group.set_deprecation_warnings(warnings);
+        }
+        // A group the request was folded into keeps the projects it was created with, so only
+        // the exclusions that left a project out of it are reported
+        if !reused {
+            new_group.set_omitted(RepeatedField::from_vec(still_omitted(&new_group, omitted)));
+        }
+        new_group.set_project_count(projects.len() as u32);
         new_group
     };
 
@@ -529,7 +840,7 @@ fn compute_rdep_build_groups(state: &AppState,
 
     debug!("computing redep build groups for: {}", root_ident);
 
-    let conn = state.db.get_conn().map_err(Error::Db)?;
+    let conn = deadline::conn(&state.db)?;
 
     satisfied_deps.insert(root_ident.to_owned());
     assert!(!rdeps.is_empty());
@@ -602,6 +913,18 @@ pub fn job_group_origin_get(req: &RpcMessage, state: &AppState) -> Result<RpcMes
     }
 }
 
+pub fn job_group_submitters_get(req: &RpcMessage, state: &AppState) -> Result<RpcMessage> {
+    let msg = req.parse::<jobsrv::JobGroupSubmittersGet>()?;
+
+    match state.datastore.get_job_group_submitters(&msg) {
+        Ok(ref submitters) => RpcMessage::make(submitters).map_err(Error::BuilderCore),
+        Err(e) => {
+            warn!("job_group_submitters_get error: {:?}", e);
+            Err(Error::System)
+        }
+    }
+}
+
 pub fn job_group_get(req: &RpcMessage, state: &AppState) -> Result<RpcMessage> {
     let msg = req.parse::<jobsrv::JobGroupGet>()?;
     debug!("group_get message: {:?}", msg);
@@ -617,26 +940,168 @@ pub fn job_group_get(req: &RpcMessage, state: &AppState) -> Result<RpcMessage> {
     };
 
     match group_opt {
-        Some(group) => RpcMessage::make(&group).map_err(Error::BuilderCore),
+        Some(mut group) => {
+            set_pending_reasons(&mut group, state);
+            set_failure_excerpts(&mut group, state);
+            set_progress(&mut group, state);
+            RpcMessage::make(&group).map_err(Error::BuilderCore)
+        }
         None => Err(Error::NotFound),
     }
 }
 
+// Flag in-progress projects whose Pending job requires worker labels that no connected worker
+// advertises, so the group status explains why the job is not being dispatched
+fn set_pending_reasons(group: &mut jobsrv::JobGroup, state: &AppState) {
+    let workers = state.workers.read().unwrap();
+
+    for project in group.mut_projects().iter_mut() {
+        if project.get_state() != jobsrv::JobGroupProjectState::InProgress {
+            continue;
+        }
+
+        let mut job_get = jobsrv::JobGet::new();
+        job_get.set_id(project.get_job_id());
+
+        match state.datastore.get_job(&job_get) {
+            Ok(Some(ref job)) if job.get_state() == jobsrv::JobState::Pending => {
+                let labels = job.get_project().get_required_worker_labels();
+                if !labels.is_empty()
+                   && !workers.iter()
+                              .any(|w| w.satisfies(job.get_target(), labels))
+                {
+                    project.set_pending_reason(jobsrv::WAITING_ON_WORKER_LABELS.to_string());
+                }
+            }
+            Ok(_) => (),
+            Err(err) => {
+                warn!("Unable to retrieve job {}, err: {:?}",
+                      job_get.get_id(),
+                      err)
+            }
+        }
+    }
+}
+
+fn set_failure_excerpts(group: &mut jobsrv::JobGroup, state: &AppState) {
+    if !group.get_projects()
+             .iter()
+             .any(|p| p.get_state() == jobsrv::JobGroupProjectState::Failure)
+    {
+        return;
+    }
+
+    let mut excerpts = match state.datastore.get_group_failure_excerpts(group.get_id()) {
+        Ok(excerpts) => excerpts,
+        Err(err) => {
+            warn!("Unable to retrieve failure excerpts of group {}, err: {:?}",
+                  group.get_id(),
+                  err);
+            return;
+        }
+    };
+
+    for project in group.mut_projects().iter_mut() {
+        if let Some(excerpt) = excerpts.remove(&project.get_job_id()) {
+            project.set_failure_excerpt(excerpt);
+        }
+    }
+}
+
+fn set_progress(group: &mut jobsrv::JobGroup, state: &AppState) {
+    if !group.get_projects()
+             .iter()
+             .any(|p| p.get_state() == jobsrv::JobGroupProjectState::InProgress)
+    {
+        return;
+    }
+
+    let mut progress = match state.datastore.get_group_progress(group.get_id()) {
+        Ok(progress) => progress,
+        Err(err) => {
+            warn!("Unable to retrieve progress of group {}, err: {:?}",
+                  group.get_id(),
+                  err);
+            return;
+        }
+    };
+
+    for project in group.mut_projects().iter_mut() {
+        if let Some(p) = progress.remove(&project.get_job_id()) {
+            project.set_progress(p);
+        }
+    }
+}
+
+pub fn worker_list_get(req: &RpcMessage, state: &AppState) -> Result<RpcMessage> {
+    req.parse::<jobsrv::WorkerListGet>()?;
+
+    let mut response = jobsrv::WorkerListResponse::new();
+    response.set_workers(RepeatedField::from_vec(state.workers.read().unwrap().clone()));
+    RpcMessage::make(&response).map_err(Error::BuilderCore)
+}
+
+pub fn feature_flag_list_get(req: &RpcMessage, state: &AppState) -> Result<RpcMessage> {
+    req.parse::<jobsrv::JobFeatureFlagListGet>()?;
+
+    let list = feature_flags::list(&state.datastore)?;
+    RpcMessage::make(&list).map_err(Error::BuilderCore)
+}
+
+pub fn feature_flag_set(req: &RpcMessage, state: &AppState) -> Result<RpcMessage> {
+    let msg = req.parse::<jobsrv::JobFeatureFlagSet>()?;
+    let flag = feature_flags::validate(&msg)?;
+
+    let old = state.flags.get(flag.name);
+    let updated = state.datastore.set_feature_flag(&msg)?;
+    let new = feature_flags::state(&updated);
+    info!("Feature flag {} set by {} ({}), old={:?}, new={:?}",
+          flag.name,
+          msg.get_requester_name(),
+          msg.get_requester_id(),
+          old,
+          new);
+    state.flags.set(flag.name, new);
+
+    RpcMessage::make(&updated).map_err(Error::BuilderCore)
+}
+
+pub fn freeze_windows_get(req: &RpcMessage, state: &AppState) -> Result<RpcMessage> {
+    let msg = req.parse::<jobsrv::JobFreezeWindowsGet>()?;
+
+    let list = build_freeze::list(&state.datastore, msg.get_origin(), Utc::now())?;
+    RpcMessage::make(&list).map_err(Error::BuilderCore)
+}
+
+pub fn freeze_windows_set(req: &RpcMessage, state: &AppState) -> Result<RpcMessage> {
+    let msg = req.parse::<jobsrv::JobFreezeWindowsSet>()?;
+
+    let list = build_freeze::set(&state.datastore, &msg, Utc::now())?;
+    info!("Freeze windows of {} set by {} ({}), windows={}",
+          msg.get_origin(),
+          msg.get_requester_name(),
+          msg.get_requester_id(),
+          list.get_windows().len());
+    // Groups deferred by a freeze that was lifted are released on the scheduler's next pass
+    ScheduleClient::default().notify()?;
+
+    RpcMessage::make(&list).map_err(Error::BuilderCore)
+}
+
 pub fn job_graph_package_create(req: &RpcMessage, state: &AppState) -> Result<RpcMessage> {
     let msg = req.parse::<jobsrv::JobGraphPackageCreate>()?;
     let package = msg.get_package();
     // Extend the graph with new package
     let mut target_graph = state.graph.write().unwrap();
-    let graph = match target_graph.graph_mut(package.get_target()) {
-        Some(g) => g,
+    let start_time = PreciseTime::now();
+    let (ncount, ecount) = match target_graph.extend(package, feat::is_enabled(feat::BuildDeps)) {
+        Some(counts) => counts,
         None => {
             warn!("JobGraphPackageCreate, no graph found for target {}",
                   package.get_target());
             return Err(Error::NotFound);
         }
     };
-    let start_time = PreciseTime::now();
-    let (ncount, ecount) = graph.extend(&package, feat::is_enabled(feat::BuildDeps));
     let end_time = PreciseTime::now();
     debug!("Extended graph, nodes: {}, edges: {} ({} sec)\n",
            ncount,
@@ -682,3 +1147,14 @@ pub fn job_graph_package_precreate(req: &RpcMessage, state: &AppState) -> Result
         Err(Error::Conflict)
     }
 }
+
+pub fn job_graph_verify(req: &RpcMessage, state: &AppState) -> Result<RpcMessage> {
+    req.parse::<jobsrv::JobGraphVerify>()?;
+    let status = state.graph_checker.start_full_check()?;
+    RpcMessage::make(&status).map_err(Error::BuilderCore)
+}
+
+pub fn job_graph_verify_status_get(req: &RpcMessage, state: &AppState) -> Result<RpcMessage> {
+    req.parse::<jobsrv::JobGraphVerifyStatusGet>()?;
+    RpcMessage::make(&state.graph_checker.status()).map_err(Error::BuilderCore)
+}
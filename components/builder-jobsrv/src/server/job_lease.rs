@@ -0,0 +1,244 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Leases of the jobs dispatched to workers. A worker advertising the job lease capability is
+//! granted a lease on its job, which its heartbeats renew while it builds the job, so that long
+//! builds aren't taken for dead ones. The job is reclaimed once its lease expires, or once it
+//! has run for the maximum lifetime however often the lease was renewed. Workers without the
+//! capability get the fixed job timeout instead.
+
+use chrono::{DateTime,
+             Duration,
+             Utc};
+
+use crate::{config::JobLeaseCfg,
+            protocol::jobsrv};
+
+/// Job event of a lease granted at dispatch
+pub const LEASE_GRANTED: &str = "LeaseGranted";
+/// Job event of a lease renewed by the worker's heartbeat
+pub const LEASE_RENEWED: &str = "LeaseRenewed";
+/// Job event of a job reclaimed as its lease expired
+pub const LEASE_EXPIRED: &str = "LeaseExpired";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lease {
+    /// When the job was dispatched
+    pub leased_at:  DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    renewable:      bool,
+}
+
+/// What a heartbeat of the worker building a job does to the job's lease
+#[derive(Debug, PartialEq)]
+pub enum Check {
+    Held,
+    Renewed,
+    Expired,
+}
+
+impl Lease {
+    /// The lease of a job dispatched `now` to a worker renewing leases
+    pub fn grant(cfg: &JobLeaseCfg, now: DateTime<Utc>) -> Self {
+        Lease { leased_at:  now,
+                expires_at: now + lease_duration(cfg),
+                renewable:  true, }.capped(cfg)
+    }
+
+    /// The fixed timeout of a job dispatched `now` to a worker that doesn't renew leases
+    pub fn fixed(job_timeout: u64, now: DateTime<Utc>) -> Self {
+        Lease { leased_at:  now,
+                expires_at: now + Duration::minutes(job_timeout as i64),
+                renewable:  false, }
+    }
+
+    /// The lease recorded with a job before the job server restarted. No renewal was received
+    /// while the job server was down, so a lease that expired in the meantime is held for
+    /// another `grace`, the time a live worker takes to renew it.
+    pub fn restore(cfg: &JobLeaseCfg,
+                   leased_at: DateTime<Utc>,
+                   expires_at: DateTime<Utc>,
+                   now: DateTime<Utc>,
+                   grace: Duration)
+                   -> Self {
+        Lease { leased_at,
+                expires_at: expires_at.max(now + grace),
+                renewable: true }.capped(cfg)
+    }
+
+    pub fn is_renewable(&self) -> bool { self.renewable }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool { self.expires_at <= now }
+
+    /// Renews the lease for another lease duration once half of it is spent, returning whether
+    /// it was. Expired leases are not renewed, nor are leases past the maximum lifetime.
+    pub fn renew(&mut self, cfg: &JobLeaseCfg, now: DateTime<Utc>) -> bool {
+        if !self.renewable
+           || self.is_expired(now)
+           || self.expires_at - now > lease_duration(cfg) / 2
+        {
+            return false;
+        }
+
+        let renewed = Lease { expires_at: now + lease_duration(cfg),
+                              ..*self }.capped(cfg);
+        if renewed.expires_at <= self.expires_at {
+            return false;
+        }
+        *self = renewed;
+        true
+    }
+
+    fn capped(self, cfg: &JobLeaseCfg) -> Self {
+        let end_of_life = self.leased_at + Duration::minutes(cfg.max_lifetime_minutes as i64);
+        Lease { expires_at: self.expires_at.min(end_of_life),
+                ..self }
+    }
+}
+
+/// Checks the lease of `job_id` on a heartbeat of the worker building it, renewing the lease
+/// when the heartbeat asks to
+pub fn check(cfg: &JobLeaseCfg,
+             lease: &mut Lease,
+             heartbeat: &jobsrv::Heartbeat,
+             job_id: u64,
+             now: DateTime<Utc>)
+             -> Check {
+    if heartbeat.get_renew_leases().contains(&job_id) && lease.renew(cfg, now) {
+        Check::Renewed
+    } else if lease.is_expired(now) {
+        Check::Expired
+    } else {
+        Check::Held
+    }
+}
+
+/// Whether the worker sending the heartbeat renews the leases of its jobs
+pub fn renews_leases(heartbeat: &jobsrv::Heartbeat) -> bool {
+    heartbeat.get_capabilities()
+             .iter()
+             .any(|capability| capability == jobsrv::JOB_LEASE_CAPABILITY)
+}
+
+fn lease_duration(cfg: &JobLeaseCfg) -> Duration { Duration::minutes(cfg.lease_minutes as i64) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::RepeatedField;
+
+    fn cfg() -> JobLeaseCfg {
+        JobLeaseCfg { lease_minutes:        10,
+                      max_lifetime_minutes: 60, }
+    }
+
+    fn start() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2019-10-21T07:00:00Z").unwrap()
+                                                            .with_timezone(&Utc)
+    }
+
+    fn at(minutes: i64) -> DateTime<Utc> { start() + Duration::minutes(minutes) }
+
+    fn heartbeat(renewing: &[u64]) -> jobsrv::Heartbeat {
+        let mut heartbeat = jobsrv::Heartbeat::new();
+        heartbeat.set_state(jobsrv::WorkerState::Busy);
+        let capabilities = vec![jobsrv::JOB_LEASE_CAPABILITY.to_string()];
+        heartbeat.set_capabilities(RepeatedField::from_vec(capabilities));
+        heartbeat.set_renew_leases(renewing.to_vec());
+        heartbeat
+    }
+
+    #[test]
+    fn leases_are_renewed_once_half_spent() {
+        let mut lease = Lease::grant(&cfg(), start());
+        assert_eq!(lease.expires_at, at(10));
+
+        assert_eq!(check(&cfg(), &mut lease, &heartbeat(&[7]), 7, at(4)),
+                   Check::Held);
+        assert_eq!(lease.expires_at, at(10));
+        assert_eq!(check(&cfg(), &mut lease, &heartbeat(&[7]), 7, at(5)),
+                   Check::Renewed);
+        assert_eq!(lease.expires_at, at(15));
+    }
+
+    #[test]
+    fn a_worker_that_stops_renewing_a_job_loses_it() {
+        let mut lease = Lease::grant(&cfg(), start());
+        assert_eq!(check(&cfg(), &mut lease, &heartbeat(&[7]), 7, at(6)),
+                   Check::Renewed);
+
+        // The worker keeps heartbeating, for another job
+        assert_eq!(check(&cfg(), &mut lease, &heartbeat(&[8]), 7, at(12)),
+                   Check::Held);
+        assert_eq!(check(&cfg(), &mut lease, &heartbeat(&[8]), 7, at(16)),
+                   Check::Expired);
+        // An expired lease is never renewed again
+        assert_eq!(check(&cfg(), &mut lease, &heartbeat(&[7]), 7, at(17)),
+                   Check::Expired);
+    }
+
+    #[test]
+    fn renewals_stop_at_the_maximum_lifetime() {
+        let mut lease = Lease::grant(&cfg(), start());
+        for minute in (6..56).step_by(6) {
+            assert_eq!(check(&cfg(), &mut lease, &heartbeat(&[7]), 7, at(minute)),
+                       Check::Renewed);
+        }
+        assert_eq!(lease.expires_at, at(60));
+        assert_eq!(check(&cfg(), &mut lease, &heartbeat(&[7]), 7, at(57)),
+                   Check::Held);
+        assert_eq!(check(&cfg(), &mut lease, &heartbeat(&[7]), 7, at(60)),
+                   Check::Expired);
+    }
+
+    #[test]
+    fn leases_expiring_during_a_restart_are_held_for_a_heartbeat() {
+        let grace = Duration::seconds(33);
+        // The lease expired at 10 while the job server was down until 12
+        let mut lease = Lease::restore(&cfg(), start(), at(10), at(12), grace);
+        assert_eq!(lease.expires_at, at(12) + grace);
+        assert_eq!(check(&cfg(), &mut lease, &heartbeat(&[7]), 7, at(12)),
+                   Check::Renewed);
+        assert_eq!(lease.expires_at, at(22));
+
+        // Without a renewal the job is reclaimed once the grace is over
+        let mut lease = Lease::restore(&cfg(), start(), at(10), at(12), grace);
+        assert_eq!(check(&cfg(), &mut lease, &heartbeat(&[]), 7, at(12)),
+                   Check::Held);
+        assert_eq!(check(&cfg(), &mut lease, &heartbeat(&[]), 7, at(13)),
+                   Check::Expired);
+
+        // A lease still running keeps its expiry, and none outlives the maximum lifetime
+        let lease = Lease::restore(&cfg(), start(), at(10), at(3), grace);
+        assert_eq!(lease.expires_at, at(10));
+        let lease = Lease::restore(&cfg(), start(), at(58), at(70), grace);
+        assert!(lease.is_expired(at(70)));
+    }
+
+    #[test]
+    fn fixed_timeouts_are_never_renewed() {
+        let mut lease = Lease::fixed(60, start());
+        assert!(!lease.is_renewable());
+        assert_eq!(check(&cfg(), &mut lease, &heartbeat(&[7]), 7, at(59)),
+                   Check::Held);
+        assert_eq!(check(&cfg(), &mut lease, &heartbeat(&[7]), 7, at(60)),
+                   Check::Expired);
+    }
+
+    #[test]
+    fn only_workers_advertising_the_capability_renew_leases() {
+        assert!(renews_leases(&heartbeat(&[])));
+        assert!(!renews_leases(&jobsrv::Heartbeat::new()));
+    }
+}
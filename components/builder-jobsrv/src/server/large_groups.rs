@@ -0,0 +1,194 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Groups with many projects, such as the rebuild of a package most of the graph depends on.
+//! Above the confirmation threshold a group is only created when its request confirms it, and
+//! above the approval threshold it waits for an operator to approve it. Groups left awaiting
+//! approval are canceled once the approval window is over. The operators are emailed when a
+//! group awaiting approval is created, and an approved group is deferred as any other when its
+//! origin is in a build freeze.
+
+use chrono::{DateTime,
+             Duration,
+             Utc};
+use diesel::pg::PgConnection;
+
+use crate::{config::LargeGroupCfg,
+            data_store::DataStore,
+            db::models::notifications::{EmailNotification,
+                                        NewEmailNotification},
+            error::Result,
+            protocol::jobsrv};
+
+use super::build_freeze;
+
+const GROUP_PENDING_APPROVAL: &str = "group-pending-approval";
+
+#[derive(Debug, PartialEq)]
+pub enum Review {
+    /// The group is created as any other
+    Create,
+    /// The group is not created, the request has to confirm it
+    ConfirmationRequired,
+    /// The group is created and waits for an operator to approve it
+    ApprovalRequired,
+}
+
+/// What becomes of a group of `project_count` projects. Dry runs create nothing and are never
+/// held back. Webhooks and uploads can't confirm a group, so they only go through approval.
+pub fn review(cfg: &LargeGroupCfg, msg: &jobsrv::JobGroupSpec, project_count: usize) -> Review {
+    if msg.get_dry_run() {
+        return Review::Create;
+    }
+
+    let interactive = match msg.get_trigger() {
+        jobsrv::JobGroupTrigger::HabClient | jobsrv::JobGroupTrigger::BuilderUI => true,
+        _ => false,
+    };
+    let above = |threshold: usize| threshold > 0 && project_count > threshold;
+
+    if interactive && above(cfg.confirm_threshold) && !msg.get_confirm_large_group() {
+        Review::ConfirmationRequired
+    } else if above(cfg.approval_threshold) {
+        Review::ApprovalRequired
+    } else {
+        Review::Create
+    }
+}
+
+/// Emails the operators that `group`, of `project_count` projects, awaits their approval. The
+/// group is created whatever becomes of the notifications.
+pub fn notify(cfg: &LargeGroupCfg,
+              msg: &jobsrv::JobGroupSpec,
+              group: &jobsrv::JobGroup,
+              project_count: usize,
+              conn: &PgConnection) {
+    let message = format!("Job group {} rebuilds {} projects for {} ({}), requested by {}. It \
+                           starts once approved with POST /v1/admin/groups/{}/approve and is \
+                           canceled if nobody reviews it within {} hours.",
+                          group.get_id(),
+                          project_count,
+                          group.get_project_name(),
+                          msg.get_target(),
+                          msg.get_requester_name(),
+                          group.get_id(),
+                          cfg.approval_window_hours);
+    for email in &cfg.approval_emails {
+        let notification = NewEmailNotification { email,
+                                                  category: GROUP_PENDING_APPROVAL,
+                                                  message: &message };
+        if let Err(err) = EmailNotification::create(&notification, conn) {
+            warn!("Failed to notify {} of group {}, err={}",
+                  email,
+                  group.get_id(),
+                  err);
+        }
+    }
+}
+
+/// When the group `group_id` is released once approved, `None` to queue it right away. A group
+/// a build freeze would have deferred when it was created is deferred until the freeze ends.
+pub fn approved_frozen_until(datastore: &DataStore,
+                             group_id: u64,
+                             now: DateTime<Utc>)
+                             -> Result<Option<DateTime<Utc>>> {
+    let mut jgg = jobsrv::JobGroupGet::new();
+    jgg.set_group_id(group_id);
+    let group = match datastore.get_job_group(&jgg)? {
+        Some(group) => group,
+        None => return Ok(None),
+    };
+    if !build_freeze::defers(group.get_trigger()) {
+        return Ok(None);
+    }
+
+    let origin = group.get_project_name().split('/').next().unwrap_or("");
+    build_freeze::origin_frozen_until(datastore, origin, now)
+}
+
+/// Cancels the groups that awaited approval for longer than the window, auditing each, and
+/// returns how many there were
+pub fn expire(cfg: &LargeGroupCfg, datastore: &DataStore, now: DateTime<Utc>) -> Result<usize> {
+    let before = now - Duration::hours(cfg.approval_window_hours as i64);
+    let expired = datastore.expire_job_groups_pending_approval(before)?;
+
+    for group_id in &expired {
+        info!("Canceling group {}, it was not approved in time", group_id);
+        let mut jga = jobsrv::JobGroupAudit::new();
+        jga.set_group_id(*group_id);
+        jga.set_operation(jobsrv::JobGroupOperation::JobGroupOpExpire);
+        jga.set_requester_name("builder".to_string());
+        if let Err(err) = datastore.create_audit_entry(&jga) {
+            warn!("Failed to create expiry audit entry, err={:?}", err);
+        }
+    }
+    Ok(expired.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg() -> LargeGroupCfg {
+        LargeGroupCfg { confirm_threshold:     100,
+                        approval_threshold:    1000,
+                        approval_window_hours: 72,
+                        approval_emails:       vec![], }
+    }
+
+    fn spec(trigger: jobsrv::JobGroupTrigger) -> jobsrv::JobGroupSpec {
+        let mut msg = jobsrv::JobGroupSpec::new();
+        msg.set_origin("core".to_string());
+        msg.set_package("glibc".to_string());
+        msg.set_trigger(trigger);
+        msg
+    }
+
+    #[test]
+    fn groups_above_the_confirmation_threshold_must_be_confirmed() {
+        let mut msg = spec(jobsrv::JobGroupTrigger::HabClient);
+        assert_eq!(review(&cfg(), &msg, 100), Review::Create);
+        assert_eq!(review(&cfg(), &msg, 101), Review::ConfirmationRequired);
+        assert_eq!(review(&cfg(), &msg, 11_000), Review::ConfirmationRequired);
+
+        msg.set_confirm_large_group(true);
+        assert_eq!(review(&cfg(), &msg, 101), Review::Create);
+    }
+
+    #[test]
+    fn groups_above_the_approval_threshold_await_an_operator() {
+        let mut msg = spec(jobsrv::JobGroupTrigger::BuilderUI);
+        msg.set_confirm_large_group(true);
+        assert_eq!(review(&cfg(), &msg, 1000), Review::Create);
+        assert_eq!(review(&cfg(), &msg, 1001), Review::ApprovalRequired);
+
+        // Requests that can't confirm a group only go through approval
+        let msg = spec(jobsrv::JobGroupTrigger::Upload);
+        assert_eq!(review(&cfg(), &msg, 500), Review::Create);
+        assert_eq!(review(&cfg(), &msg, 1001), Review::ApprovalRequired);
+    }
+
+    #[test]
+    fn dry_runs_and_disabled_thresholds_are_never_held_back() {
+        let mut msg = spec(jobsrv::JobGroupTrigger::HabClient);
+        msg.set_dry_run(true);
+        assert_eq!(review(&cfg(), &msg, 11_000), Review::Create);
+
+        let off = LargeGroupCfg { confirm_threshold: 0,
+                                  approval_threshold: 0,
+                                  ..cfg() };
+        let msg = spec(jobsrv::JobGroupTrigger::HabClient);
+        assert_eq!(review(&off, &msg, 11_000), Review::Create);
+    }
+}
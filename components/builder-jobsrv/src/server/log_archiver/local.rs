@@ -31,9 +31,11 @@ use sha2::{Digest,
 use std::{fs::{self,
                OpenOptions},
           io::Read,
-          path::PathBuf};
+          path::{Path,
+                 PathBuf}};
 
-use super::LogArchiver;
+use super::{job_id,
+            LogArchiver};
 
 /// Wraps a `PathBuf` representing the root of a local job log archive.
 pub struct LocalArchiver(PathBuf);
@@ -66,6 +68,22 @@ impl LocalArchiver {
 
         new_path
     }
+
+    // The IDs of the logs under `dir`, descending into the checksum directories
+    fn list_dir(dir: &Path, ids: &mut Vec<u64>) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                Self::list_dir(&path, ids)?;
+            } else if let Some(id) = path.file_name()
+                                         .and_then(|name| name.to_str())
+                                         .and_then(job_id)
+            {
+                ids.push(id);
+            }
+        }
+        Ok(())
+    }
 }
 
 impl LogArchiver for LocalArchiver {
@@ -87,6 +105,17 @@ impl LogArchiver for LocalArchiver {
                                                               .collect();
         Ok(lines)
     }
+
+    fn delete(&self, job_id: u64) -> Result<()> {
+        fs::remove_file(self.archive_path(job_id))?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<u64>> {
+        let mut ids = Vec::new();
+        Self::list_dir(&self.0, &mut ids)?;
+        Ok(ids)
+    }
 }
 
 #[cfg(test)]
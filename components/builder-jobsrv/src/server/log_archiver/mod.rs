@@ -43,6 +43,23 @@ pub trait LogArchiver: Send {
     /// Given a `job_id`, retrieves the log output for that job from
     /// long-term storage.
     fn retrieve(&self, job_id: u64) -> Result<Vec<String>>;
+
+    /// Given a `job_id`, removes the log output for that job from
+    /// long-term storage.
+    fn delete(&self, job_id: u64) -> Result<()>;
+
+    /// Returns the IDs of all jobs whose logs are in long-term
+    /// storage.
+    fn list(&self) -> Result<Vec<u64>>;
+}
+
+/// Parses the job ID out of the file name or key of an archived log,
+/// e.g. `722477594578067456.log`.
+fn job_id(name: &str) -> Option<u64> {
+    if !name.ends_with(".log") {
+        return None;
+    }
+    name[..name.len() - ".log".len()].parse().ok()
 }
 
 /// Create appropriate LogArchiver variant based on configuration values.
@@ -52,3 +69,16 @@ pub fn from_config(config: &ArchiveCfg) -> Result<Box<dyn LogArchiver>> {
         ArchiveBackend::S3 => Ok(Box::new(s3::S3Archiver::new(&config))),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job_ids_of_archived_logs() {
+        assert_eq!(job_id("722477594578067456.log"),
+                   Some(722_477_594_578_067_456));
+        assert_eq!(job_id("722477594578067456"), None);
+        assert_eq!(job_id("notes.log"), None);
+    }
+}
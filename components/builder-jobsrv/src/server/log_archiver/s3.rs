@@ -32,7 +32,9 @@ use std::{fs::OpenOptions,
 
 use futures::{Future,
               Stream};
-use rusoto_s3::{GetObjectRequest,
+use rusoto_s3::{DeleteObjectRequest,
+                GetObjectRequest,
+                ListObjectsRequest,
                 PutObjectRequest,
                 S3Client,
                 S3};
@@ -42,7 +44,8 @@ use rusoto_core::HttpClient;
 use crate::rusoto::{credential::StaticProvider,
                     Region};
 
-use super::LogArchiver;
+use super::{job_id,
+            LogArchiver};
 use crate::{config::ArchiveCfg,
             error::{Error,
                     Result}};
@@ -127,4 +130,42 @@ impl LogArchiver for S3Archiver {
 
         Ok(lines)
     }
+
+    fn delete(&self, job_id: u64) -> Result<()> {
+        let mut request = DeleteObjectRequest::default();
+        request.bucket = self.bucket.clone();
+        request.key = Self::key(job_id);
+
+        match self.client.delete_object(request).sync() {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                warn!("Failed to delete job log for {} ({:?})", job_id, e);
+                Err(Error::JobLogDelete(job_id, e))
+            }
+        }
+    }
+
+    fn list(&self) -> Result<Vec<u64>> {
+        let mut ids = Vec::new();
+        let mut marker = None;
+        loop {
+            let mut request = ListObjectsRequest::default();
+            request.bucket = self.bucket.clone();
+            request.marker = marker;
+
+            let output = self.client.list_objects(request).sync()?;
+            let keys: Vec<String> = output.contents
+                                          .unwrap_or_default()
+                                          .into_iter()
+                                          .filter_map(|object| object.key)
+                                          .collect();
+            ids.extend(keys.iter().filter_map(|key| job_id(key)));
+
+            // Without a delimiter, a truncated listing continues after its last key
+            match (output.is_truncated, keys.last()) {
+                (Some(true), Some(last)) => marker = Some(last.clone()),
+                _ => return Ok(ids),
+            }
+        }
+    }
 }
@@ -0,0 +1,145 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The excerpt of a failed job's log shown with the job, so a failure can be triaged without
+//! opening the whole log: the lines matching one of the configured error patterns, followed by
+//! the last lines of the log.
+
+use protobuf::RepeatedField;
+
+use crate::{config::FailureExcerptCfg,
+            protocol::jobsrv::JobLog};
+
+/// Marks lines of the log left out of the excerpt
+const GAP: &str = "...";
+
+/// The excerpt of `log`, None when the log is empty. The log may be cut short or hold invalid
+/// UTF-8, invalid sequences are replaced rather than failing the extraction.
+pub fn extract(log: &[u8], cfg: &FailureExcerptCfg) -> Option<String> {
+    let mut lines = JobLog::new();
+    lines.set_content(RepeatedField::from_vec(String::from_utf8_lossy(log).lines()
+                                                                          .map(str::to_string)
+                                                                          .collect()));
+    lines.strip_ansi();
+    let lines = lines.get_content();
+    if lines.iter().all(|l| l.trim().is_empty()) {
+        return None;
+    }
+
+    let tail_start = lines.len().saturating_sub(cfg.tail_lines);
+    let selected = lines.iter()
+                        .enumerate()
+                        .filter(|&(i, line)| {
+                            i >= tail_start
+                            || cfg.patterns.iter().any(|p| line.contains(p.as_str()))
+                        })
+                        .map(|(i, _)| i)
+                        .collect::<Vec<_>>();
+
+    let mut excerpt = Vec::new();
+    let mut next = 0;
+    for i in selected {
+        if i > next {
+            excerpt.push(GAP);
+        }
+        excerpt.push(lines[i].as_str());
+        next = i + 1;
+    }
+
+    Some(cap(&excerpt, cfg.max_bytes))
+}
+
+// Joins the lines, dropping the earliest ones until the excerpt fits in `max_bytes`. The end of
+// the log is where a build fails, so it is kept over the matches that came before it.
+fn cap(lines: &[&str], max_bytes: usize) -> String {
+    let mut start = 0;
+    let mut len = lines.iter().map(|l| l.len() + 1).sum::<usize>();
+    while len > max_bytes && start + 1 < lines.len() {
+        len -= lines[start].len() + 1;
+        start += 1;
+    }
+
+    let mut excerpt = lines[start..].join("\n");
+    if start > 0 && lines[start] != GAP && excerpt.len() + GAP.len() < max_bytes {
+        excerpt = format!("{}\n{}", GAP, excerpt);
+    }
+    if excerpt.len() > max_bytes {
+        let mut end = max_bytes;
+        while !excerpt.is_char_boundary(end) {
+            end -= 1;
+        }
+        excerpt.truncate(end);
+    }
+    excerpt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg(tail_lines: usize, max_bytes: usize) -> FailureExcerptCfg {
+        FailureExcerptCfg { tail_lines,
+                            max_bytes,
+                            patterns: vec!["ERROR".to_string(),
+                                           "undefined reference".to_string()] }
+    }
+
+    #[test]
+    fn excerpt_is_matches_then_tail() {
+        let log = b"a\nERROR: first\nb\nc\nfoo.c: undefined reference to `bar'\nd\ne\nf\n";
+        assert_eq!(extract(log, &cfg(2, 4096)).unwrap(),
+                   "...\nERROR: first\n...\nfoo.c: undefined reference to `bar'\n...\ne\nf");
+    }
+
+    #[test]
+    fn matches_in_tail_are_not_repeated() {
+        let log = b"a\nb\nERROR: last\n";
+        assert_eq!(extract(log, &cfg(2, 4096)).unwrap(), "...\nb\nERROR: last");
+        assert_eq!(extract(log, &cfg(10, 4096)).unwrap(), "a\nb\nERROR: last");
+    }
+
+    #[test]
+    fn empty_log_has_no_excerpt() {
+        assert_eq!(extract(b"", &cfg(10, 4096)), None);
+        assert_eq!(extract(b"\n  \n", &cfg(10, 4096)), None);
+    }
+
+    #[test]
+    fn color_codes_are_stripped() {
+        let log = b"\x1b[1;31mERROR\x1b[0m: build failed\n";
+        assert_eq!(extract(log, &cfg(0, 4096)).unwrap(), "ERROR: build failed");
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let log = b"ok\nERROR: bad byte \xff\ntruncated \xe2\x82";
+        assert_eq!(extract(log, &cfg(1, 4096)).unwrap(),
+                   "...\nERROR: bad byte \u{fffd}\ntruncated \u{fffd}");
+    }
+
+    #[test]
+    fn earliest_lines_are_dropped_to_fit() {
+        let log = b"ERROR: one\nERROR: two\nlast line\n";
+        assert_eq!(extract(log, &cfg(1, 24)).unwrap(),
+                   "...\nERROR: two\nlast line");
+        assert_eq!(extract(log, &cfg(1, 12)).unwrap(), "last line");
+    }
+
+    #[test]
+    fn long_line_is_cut_on_char_boundary() {
+        let log = "ERROR: é é é é".as_bytes();
+        assert_eq!(extract(log, &cfg(1, 9)).unwrap(), "ERROR: é");
+        assert_eq!(extract(log, &cfg(1, 8)).unwrap(), "ERROR: ");
+    }
+}
@@ -13,28 +13,37 @@
 // limitations under the License.
 
 use crate::{bldr_core::socket::DEFAULT_CONTEXT,
-            config::Config,
+            config::{Config,
+                     FailureExcerptCfg},
             data_store::DataStore,
             error::Result,
-            protocol::jobsrv::{JobLogChunk,
-                               JobLogComplete},
+            protocol::jobsrv::{JobGet,
+                               JobLogChunk,
+                               JobLogComplete,
+                               JobProgress,
+                               JobState},
             server::{log_archiver::{self,
                                     LogArchiver},
-                     log_directory::LogDirectory}};
+                     log_directory::LogDirectory,
+                     log_excerpt,
+                     supervisor::{self,
+                                  Supervisor}}};
 use protobuf::parse_from_bytes;
 use std::{fs::{self,
                OpenOptions},
           io::Write,
+          path::Path,
           str,
           sync::mpsc,
-          thread::{self,
-                   JoinHandle}};
+          thread::JoinHandle};
 use zmq;
 
 /// ZMQ protocol frame to indicate a log line is being sent
 const LOG_LINE: &str = "L";
 /// ZMQ protocol frame to indicate a log has finished
 const LOG_COMPLETE: &str = "C";
+/// ZMQ protocol frame to indicate the progress of a job is being sent
+const LOG_PROGRESS: &str = "P";
 
 /// Listens for log messages from builders and consolidates output for
 /// both streaming to clients and long-term storage.
@@ -45,6 +54,7 @@ pub struct LogIngester {
     log_ingestion_addr: String,
     data_store:         DataStore,
     archiver:           Box<dyn LogArchiver>,
+    failure_excerpt:    FailureExcerptCfg,
 }
 
 impl LogIngester {
@@ -56,24 +66,19 @@ impl LogIngester {
                       log_dir,
                       log_ingestion_addr: config.net.log_ingestion_addr(),
                       data_store,
-                      archiver: log_archiver::from_config(&config.archive).unwrap() }
+                      archiver: log_archiver::from_config(&config.archive).unwrap(),
+                      failure_excerpt: config.failure_excerpt.clone() }
     }
 
     pub fn start(cfg: &Config,
                  log_dir: LogDirectory,
-                 data_store: DataStore)
+                 data_store: DataStore,
+                 supervisor: &Supervisor)
                  -> Result<JoinHandle<()>> {
-        let mut ingester = Self::new(cfg, log_dir, data_store);
-        let (tx, rx) = mpsc::sync_channel(1);
-        let handle = thread::Builder::new().name("log-ingester".to_string())
-                                           .spawn(move || {
-                                               ingester.run(&tx).unwrap();
-                                           })
-                                           .unwrap();
-        match rx.recv() {
-            Ok(()) => Ok(handle),
-            Err(e) => panic!("log-ingester thread startup error, err={}", e),
-        }
+        let cfg = cfg.clone();
+        supervisor.spawn("log-ingester", move |rz| {
+                      Self::new(&cfg, log_dir.clone(), data_store.clone()).run(rz)
+                  })
     }
 
     fn run(&mut self, rz: &mpsc::SyncSender<()>) -> Result<()> {
@@ -81,11 +86,13 @@ impl LogIngester {
         self.intake_sock.bind(&self.log_ingestion_addr)?;
         rz.send(()).unwrap();
         loop {
+            supervisor::checkpoint();
             // Right now we've got 3 frames per message:
             // 1: peer identity (we're using a ROUTER socket)
             // 2: a single-character code indicating message type:
             //    L = a line of log output
             //    C = the log is complete
+            //    P = the progress of the job, sent by workers that report it
             // 3: a protobuf message
             self.intake_sock.recv(&mut self.msg, 0)?; // identity frame
 
@@ -132,8 +139,27 @@ impl LogIngester {
                         }
                     }
                 }
+                LOG_PROGRESS => {
+                    self.intake_sock.recv(&mut self.msg, 0)?; // protobuf message frame
+                    match parse_from_bytes::<JobProgress>(&self.msg) {
+                        Ok(progress) => {
+                            if let Err(e) = self.data_store.set_job_progress(&progress) {
+                                warn!("Unable to store progress for job {}: {}",
+                                      progress.get_job_id(),
+                                      e);
+                            }
+                        }
+                        Err(e) => {
+                            warn!("ERROR parsing JobProgress: {:?}", e);
+                        }
+                    }
+                }
                 other => {
                     warn!("UNRECOGNIZED LOG PROTOCOL CODE: {:?}", other);
+                    // Drop the rest of the message, so its frames aren't read as the next one
+                    while self.intake_sock.get_rcvmore()? {
+                        self.intake_sock.recv(&mut self.msg, 0)?;
+                    }
                 }
             }
         }
@@ -143,6 +169,9 @@ impl LogIngester {
     /// in Result-returning functions to collapse deeply branching
     /// code.
     ///
+    /// The failure excerpt is stored first, while the log is still at hand. Failing to extract
+    /// it is only logged, so it never keeps a log from being archived.
+    ///
     /// # Errors
     ///
     /// This function will return an error in the following scenarios:
@@ -159,6 +188,10 @@ impl LogIngester {
         debug!("Log complete for job {:?}", id);
         let log_file = self.log_dir.log_file_path(id);
 
+        if let Err(e) = self.store_failure_excerpt(id, &log_file) {
+            warn!("Unable to store failure excerpt for job {}: {}", id, e);
+        }
+
         self.archiver.archive(id, &log_file)?;
         debug!("Archived log for job {}", id);
         self.data_store.mark_as_archived(id)?;
@@ -166,4 +199,26 @@ impl LogIngester {
         debug!("Successfully deleted local log file {:?}", log_file);
         Ok(())
     }
+
+    // The worker reports the job's final state apart from the log, which may complete first. So
+    // the excerpt is extracted unless the job is already known to have ended some other way than
+    // failing, and it is only kept with the job if it does fail.
+    fn store_failure_excerpt(&self, job_id: u64, log_file: &Path) -> Result<()> {
+        let mut job_get = JobGet::new();
+        job_get.set_id(job_id);
+        let state = self.data_store
+                        .get_job(&job_get)?
+                        .map(|job| job.get_state());
+        match state {
+            Some(JobState::Dispatched) | Some(JobState::Processing) | Some(JobState::Failed) => (),
+            _ => return Ok(()),
+        }
+
+        let log = fs::read(log_file)?;
+        if let Some(excerpt) = log_excerpt::extract(&log, &self.failure_excerpt) {
+            self.data_store.set_job_failure_excerpt(job_id, &excerpt)?;
+            debug!("Stored failure excerpt for job {}", job_id);
+        }
+        Ok(())
+    }
 }
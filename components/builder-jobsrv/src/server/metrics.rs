@@ -22,7 +22,10 @@ use crate::{bldr_core::metrics,
 
 pub enum Counter {
     CompletedJobs(PackageTarget),
+    CachedJobs(PackageTarget),
     FailedJobs(PackageTarget),
+    GraphDiscrepancies,
+    ThreadRestarts(&'static str),
 }
 
 impl metrics::CounterMetric for Counter {}
@@ -31,7 +34,10 @@ impl metrics::Metric for Counter {
     fn id(&self) -> Cow<'static, str> {
         match *self {
             Counter::CompletedJobs(ref t) => format!("jobsrv.completed.{}", t).into(),
+            Counter::CachedJobs(ref t) => format!("jobsrv.cached.{}", t).into(),
             Counter::FailedJobs(ref t) => format!("jobsrv.failed.{}", t).into(),
+            Counter::GraphDiscrepancies => "jobsrv.graph.discrepancies".into(),
+            Counter::ThreadRestarts(name) => format!("jobsrv.restarts.{}", name).into(),
         }
     }
 }
@@ -12,26 +12,41 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod build_freeze;
+mod feature_flags;
+mod graph_checker;
 mod handlers;
+mod job_lease;
+mod large_groups;
 pub mod log_archiver;
 mod log_directory;
+mod log_excerpt;
 mod log_ingester;
 mod metrics;
 mod scheduler;
+mod supervisor;
 mod worker_manager;
 
-use self::{log_archiver::LogArchiver,
+use self::{graph_checker::GraphChecker,
+           log_archiver::LogArchiver,
            log_directory::LogDirectory,
            log_ingester::LogIngester,
            scheduler::ScheduleMgr,
-           worker_manager::WorkerMgr};
-use crate::{bldr_core::{rpc::RpcMessage,
+           supervisor::{SubsystemHealth,
+                        Supervisor},
+           worker_manager::{WorkerMgr,
+                            WorkerRegistry}};
+use crate::{bldr_core::{build_info::BuildInfo,
+                        feature_flags::FeatureFlags,
+                        rpc::RpcMessage,
                         target_graph::TargetGraph},
             config::{Config,
-                     GatewayCfg},
+                     GatewayCfg,
+                     LargeGroupCfg},
             data_store::DataStore,
             db::{models::package::*,
                  DbPool},
+            deadline,
             error::Result,
             hab_core::package::PackageTarget,
             protocol::originsrv::OriginPackage,
@@ -51,7 +66,9 @@ use std::{collections::{HashMap,
                  Iterator},
           panic,
           sync::{Arc,
-                 RwLock}};
+                 RwLock},
+          time::{Duration,
+                 Instant}};
 use time::PreciseTime;
 
 features! {
@@ -65,50 +82,98 @@ pub struct AppState {
     archiver:      Box<dyn LogArchiver>,
     datastore:     DataStore,
     db:            DbPool,
+    flags:         FeatureFlags,
     graph:         Arc<RwLock<TargetGraph>>,
+    graph_checker: GraphChecker,
     log_dir:       LogDirectory,
     build_targets: HashSet<PackageTarget>,
+    large_groups:  LargeGroupCfg,
+    workers:       WorkerRegistry,
+    health:        SubsystemHealth,
+    build_info:    BuildInfo,
 }
 
 impl AppState {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(cfg: &Config,
                datastore: &DataStore,
                db: DbPool,
-               graph: &Arc<RwLock<TargetGraph>>)
+               flags: &FeatureFlags,
+               graph: &Arc<RwLock<TargetGraph>>,
+               graph_checker: &GraphChecker,
+               workers: &WorkerRegistry,
+               health: &SubsystemHealth,
+               build_info: &BuildInfo)
                -> Self {
         AppState { archiver: log_archiver::from_config(&cfg.archive).unwrap(),
                    datastore: datastore.clone(),
                    db,
+                   flags: flags.clone(),
                    graph: graph.clone(),
+                   graph_checker: graph_checker.clone(),
                    log_dir: LogDirectory::new(&cfg.log_dir),
-                   build_targets: cfg.build_targets.clone() }
+                   build_targets: cfg.build_targets.clone(),
+                   large_groups: cfg.large_groups.clone(),
+                   workers: workers.clone(),
+                   health: health.clone(),
+                   build_info: build_info.clone() }
     }
 }
 
 /// Endpoint for determining availability of builder-jobsrv components.
 ///
 /// Returns a status 200 on success. Any non-200 responses are an outage or a partial outage.
-fn status() -> HttpResponse { HttpResponse::new(StatusCode::OK) }
+#[allow(clippy::needless_pass_by_value)]
+fn status(state: Data<AppState>) -> HttpResponse {
+    if state.datastore.is_degraded() {
+        return HttpResponse::with_body(StatusCode::SERVICE_UNAVAILABLE,
+                                       Body::from_message("datastore degraded"));
+    }
+    let failed = state.health.failed();
+    if !failed.is_empty() {
+        let reason = format!("{} failed", failed.join(", "));
+        return HttpResponse::with_body(StatusCode::SERVICE_UNAVAILABLE,
+                                       Body::from_message(reason));
+    }
+    HttpResponse::new(StatusCode::OK)
+}
+
+/// What this builder-jobsrv was built from, read by builder-api to check that both speak the
+/// same protocol version
+#[allow(clippy::needless_pass_by_value)]
+fn version(state: Data<AppState>) -> HttpResponse { HttpResponse::Ok().json(&state.build_info) }
+
+type RpcHandler = fn(&RpcMessage, &AppState) -> Result<RpcMessage>;
 
 #[allow(clippy::needless_pass_by_value)]
 fn handle_rpc(msg: Json<RpcMessage>, state: Data<AppState>) -> HttpResponse {
     debug!("Got RPC message, body =\n{:?}", msg);
 
-    let result = match msg.id.as_str() {
-        "JobGet" => handlers::job_get(&msg, &state),
-        "JobLogGet" => handlers::job_log_get(&msg, &state),
-        "JobGroupSpec" => handlers::job_group_create(&msg, &state),
-        "JobGroupCancel" => handlers::job_group_cancel(&msg, &state),
-        "JobGroupGet" => handlers::job_group_get(&msg, &state),
-        "JobGroupOriginGet" => handlers::job_group_origin_get(&msg, &state),
-        "JobGraphPackageCreate" => handlers::job_graph_package_create(&msg, &state),
-        "JobGraphPackagePreCreate" => handlers::job_graph_package_precreate(&msg, &state),
+    let handler: RpcHandler = match msg.id.as_str() {
+        "JobGet" => handlers::job_get,
+        "JobLogGet" => handlers::job_log_get,
+        "JobGroupSpec" => handlers::job_group_create,
+        "JobGroupCancel" => handlers::job_group_cancel,
+        "JobGroupApprove" => handlers::job_group_approve,
+        "JobGroupUpdate" => handlers::job_group_update,
+        "JobGroupGet" => handlers::job_group_get,
+        "JobGroupOriginGet" => handlers::job_group_origin_get,
+        "JobGroupSubmittersGet" => handlers::job_group_submitters_get,
+        "JobGraphPackageCreate" => handlers::job_graph_package_create,
+        "JobGraphPackagePreCreate" => handlers::job_graph_package_precreate,
         "JobGraphPackageReverseDependenciesGet" => {
-            handlers::job_graph_package_reverse_dependencies_get(&msg, &state)
+            handlers::job_graph_package_reverse_dependencies_get
         }
         "JobGraphPackageReverseDependenciesGroupedGet" => {
-            handlers::job_graph_package_reverse_dependencies_grouped_get(&msg, &state)
+            handlers::job_graph_package_reverse_dependencies_grouped_get
         }
+        "JobGraphVerify" => handlers::job_graph_verify,
+        "JobGraphVerifyStatusGet" => handlers::job_graph_verify_status_get,
+        "WorkerListGet" => handlers::worker_list_get,
+        "JobFeatureFlagListGet" => handlers::feature_flag_list_get,
+        "JobFeatureFlagSet" => handlers::feature_flag_set,
+        "JobFreezeWindowsGet" => handlers::freeze_windows_get,
+        "JobFreezeWindowsSet" => handlers::freeze_windows_set,
 
         _ => {
             let err = format!("Unknown RPC message received: {}", msg.id);
@@ -118,37 +183,66 @@ fn handle_rpc(msg: Json<RpcMessage>, state: Data<AppState>) -> HttpResponse {
         }
     };
 
+    // The datastore bounds the work done for the message by the deadline the caller sent
+    let deadline = msg.deadline_ms
+                      .map(|ms| Instant::now() + Duration::from_millis(ms));
+    // A statement canceled by its timeout surfaces as whatever error the handler made of it
+    let result = deadline::within(deadline, || {
+        handler(&msg, &state).map_err(|err| {
+                                 if deadline::exceeded() {
+                                     Error::DeadlineExceeded
+                                 } else {
+                                     err
+                                 }
+                             })
+    });
     match result {
         Ok(m) => HttpResponse::Ok().json(m),
+        Err(Error::DeadlineExceeded) => {
+            warn!("Deadline of RPC message {} exceeded", msg.id);
+            Error::DeadlineExceeded.into()
+        }
         Err(e) => e.into(),
     }
 }
 
-fn enable_features_from_config(cfg: &Config) {
+// Returns the names of the features enabled
+fn enable_features_from_config(cfg: &Config) -> Vec<String> {
     let features: HashMap<_, _> = HashMap::from_iter(vec![("BUILDDEPS", feat::BuildDeps)]);
     let features_enabled = cfg.features_enabled
                               .split(',')
                               .map(|f| f.trim().to_uppercase());
 
+    let mut enabled = Vec::new();
     for key in features_enabled {
         if features.contains_key(key.as_str()) {
             info!("Enabling feature: {}", key);
             feat::enable(features[key.as_str()]);
+            enabled.push(key);
         }
     }
+    enabled.sort();
+    enabled.dedup();
 
     if feat::is_enabled(feat::BuildDeps) {
         println!("Listing possible feature flags: {:?}", features.keys());
         println!("Enable features by populating 'features_enabled' in config");
     }
+
+    enabled
 }
 
 pub fn run(config: Config) -> Result<()> {
-    // Set custom panic hook - a panic on the scheduler thread will
-    // cause the builder-jobsrv process to exit (and be re-started
-    // by the supervisor when running under hab)
+    // Set custom panic hook - a panic on a supervised thread is logged
+    // and the thread restarted, a panic anywhere else will cause the
+    // builder-jobsrv process to exit (and be re-started by the
+    // supervisor when running under hab)
     panic::set_hook(Box::new(|panic_info| {
                         let backtrace = backtrace::Backtrace::new();
+                        if supervisor::is_supervised() {
+                            error!("panic info: {:?}\n{:?}", panic_info, backtrace);
+                            return;
+                        }
                         println!("panic info: {:?}", panic_info);
                         println!("{:?}", backtrace);
                         println!("Exiting builder-jobsrv process");
@@ -157,7 +251,8 @@ pub fn run(config: Config) -> Result<()> {
 
     let cfg = Arc::new(config.clone());
 
-    enable_features_from_config(&config);
+    let build_info =
+        builder_core::build_info!("builder-jobsrv", enable_features_from_config(&config));
 
     let datastore = DataStore::new(&config.datastore);
     let db_pool = DbPool::new(&config.datastore.clone());
@@ -178,25 +273,44 @@ pub fn run(config: Config) -> Result<()> {
               stat.target, stat.node_count, stat.edge_count,);
     }
 
+    let health = SubsystemHealth::new(&config.supervisor);
+    let supervisor = Supervisor::new(&config.supervisor, &health);
+
+    let flags = FeatureFlags::new();
+    feature_flags::start_refresh(&config.feature_flags, &datastore, &flags, &supervisor);
+
     let graph_arc = Arc::new(RwLock::new(graph));
+    let graph_checker = GraphChecker::new(db_pool.clone(), &graph_arc);
+    graph_checker.start(&config.graph_check, &supervisor);
+
     LogDirectory::validate(&config.log_dir)?;
     let log_dir = LogDirectory::new(&config.log_dir);
-    LogIngester::start(&config, log_dir, datastore.clone())?;
+    LogIngester::start(&config, log_dir, datastore.clone(), &supervisor)?;
 
-    WorkerMgr::start(&config, &datastore, db_pool.clone())?;
-    ScheduleMgr::start(&config, &datastore, db_pool.clone())?;
+    let workers = WorkerRegistry::default();
+    WorkerMgr::start(&config, &datastore, db_pool.clone(), &workers, &supervisor)?;
+    ScheduleMgr::start(&config, &datastore, db_pool.clone(), &flags, &supervisor)?;
 
     info!("builder-jobsrv listening on {}:{}",
           cfg.listen_addr(),
           cfg.listen_port());
 
     HttpServer::new(move || {
-        let app_state = AppState::new(&config, &datastore, db_pool.clone(), &graph_arc);
+        let app_state = AppState::new(&config,
+                                      &datastore,
+                                      db_pool.clone(),
+                                      &flags,
+                                      &graph_arc,
+                                      &graph_checker,
+                                      &workers,
+                                      &health,
+                                      &build_info);
 
         App::new().data(app_state)
                   .wrap(Logger::default().exclude("/status"))
                   .service(web::resource("/status").route(web::get().to(status))
                                                    .route(web::head().to(status)))
+                  .route("/version", web::get().to(version))
                   .route("/rpc", web::post().to(handle_rpc))
     }).workers(cfg.handler_count())
       .keep_alive(cfg.http.keep_alive)
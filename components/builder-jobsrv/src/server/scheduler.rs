@@ -12,12 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{collections::{HashMap,
+use std::{cmp,
+          collections::{HashMap,
                         HashSet},
           str::FromStr,
           sync::mpsc,
-          thread::{self,
-                   JoinHandle}};
+          thread::JoinHandle};
 
 use chrono::{DateTime,
              Utc};
@@ -25,7 +25,9 @@ use diesel;
 use time::Duration;
 use zmq;
 
-use crate::{config::Config,
+use crate::{config::{Config,
+                     LargeGroupCfg,
+                     OriginLimitsCfg},
             data_store::DataStore,
             db::DbPool,
             error::{Error,
@@ -36,7 +38,8 @@ use crate::db::models::{jobs::*,
                         package::*,
                         projects::*};
 
-use crate::{bldr_core::{logger::Logger,
+use crate::{bldr_core::{feature_flags::FeatureFlags,
+                        logger::Logger,
                         metrics::{CounterMetric,
                                   GaugeMetric,
                                   HistogramMetric},
@@ -45,13 +48,27 @@ use crate::{bldr_core::{logger::Logger,
                                 PackageIdent,
                                 PackageTarget}};
 
-use super::{metrics::{Counter,
+use super::{build_freeze,
+            feature_flags::GROUP_CONCURRENCY_CAP,
+            large_groups,
+            metrics::{Counter,
                       Gauge,
                       Histogram},
+            supervisor::{self,
+                         Supervisor},
             worker_manager::WorkerMgrClient};
 
 const SCHEDULER_ADDR: &str = "inproc://scheduler";
 const SOCKET_TIMEOUT_MS: i64 = 60_000;
+// Shortest wait for a held group, in case the database clock runs behind ours
+const RELEASE_MIN_WAIT_MS: i64 = 1_000;
+// How often the job events past their retention are deleted
+const EVENT_PRUNE_INTERVAL_HOURS: i64 = 1;
+// Retries of a group whose dispatch failed with a retryable error, the first one after
+// DISPATCH_RETRY_MS and each later one after twice the wait of the one before. The retries are
+// counted in memory, so the limit applies per job server process and starts over on a restart.
+const MAX_DISPATCH_RETRIES: u32 = 5;
+const DISPATCH_RETRY_MS: i64 = 2_000;
 
 pub struct ScheduleClient {
     socket: zmq::Socket,
@@ -87,10 +104,22 @@ pub struct ScheduleMgr {
     worker_mgr:    WorkerMgrClient,
     build_targets: HashSet<PackageTarget>,
     job_timeout:   Duration,
+    // Leased jobs are reclaimed by the worker manager once their lease expires, the watchdog
+    // only steps in past their maximum lifetime
+    max_lifetime:  Duration,
+    // How long job events are kept, forever when unset, and when they were last pruned
+    retention:     Option<Duration>,
+    pruned_at:     Option<DateTime<Utc>>,
+    flags:         FeatureFlags,
+    large_groups:  LargeGroupCfg,
+    origin_limits: OriginLimitsCfg,
+    // End of the earliest quiet period among the queued groups
+    next_release:  Option<DateTime<Utc>>,
+    retries:       DispatchRetries,
 }
 
 impl ScheduleMgr {
-    pub fn new(cfg: &Config, datastore: &DataStore, db: DbPool) -> Self {
+    pub fn new(cfg: &Config, datastore: &DataStore, db: DbPool, flags: &FeatureFlags) -> Self {
         let socket = (**DEFAULT_CONTEXT).as_mut().socket(zmq::DEALER).unwrap();
 
         let mut schedule_cli = ScheduleClient::default();
@@ -107,21 +136,30 @@ impl ScheduleMgr {
                       socket,
                       worker_mgr,
                       build_targets: cfg.build_targets.clone(),
-                      job_timeout: Duration::minutes(cfg.job_timeout as i64) }
-    }
-
-    pub fn start(cfg: &Config, datastore: &DataStore, db: DbPool) -> Result<JoinHandle<()>> {
-        let (tx, rx) = mpsc::sync_channel(1);
-        let mut schedule_mgr = Self::new(cfg, datastore, db);
-        let handle = thread::Builder::new().name("scheduler".to_string())
-                                           .spawn(move || {
-                                               schedule_mgr.run(&tx).unwrap();
-                                           })
-                                           .unwrap();
-        match rx.recv() {
-            Ok(()) => Ok(handle),
-            Err(e) => panic!("scheduler thread startup error, err={}", e),
-        }
+                      job_timeout: Duration::minutes(cfg.job_timeout as i64),
+                      max_lifetime: Duration::minutes(cfg.job_leases.max_lifetime_minutes as i64),
+                      retention: match cfg.job_leases.event_retention_days {
+                          0 => None,
+                          days => Some(Duration::days(days as i64)),
+                      },
+                      pruned_at: None,
+                      flags: flags.clone(),
+                      large_groups: cfg.large_groups.clone(),
+                      origin_limits: cfg.origin_limits.clone(),
+                      next_release: None,
+                      retries: DispatchRetries::default() }
+    }
+
+    pub fn start(cfg: &Config,
+                 datastore: &DataStore,
+                 db: DbPool,
+                 flags: &FeatureFlags,
+                 supervisor: &Supervisor)
+                 -> Result<JoinHandle<()>> {
+        let (cfg, datastore, flags) = (cfg.clone(), datastore.clone(), flags.clone());
+        supervisor.spawn("scheduler", move |rz| {
+                      Self::new(&cfg, &datastore, db.clone(), &flags).run(rz)
+                  })
     }
 
     fn run(&mut self, rz: &mpsc::SyncSender<()>) -> Result<()> {
@@ -130,9 +168,11 @@ impl ScheduleMgr {
         let mut socket = false;
         rz.send(()).unwrap();
         loop {
+            supervisor::checkpoint();
             {
+                let timeout = self.poll_timeout_ms();
                 let mut items = [self.socket.as_poll_item(1)];
-                if let Err(err) = zmq::poll(&mut items, SOCKET_TIMEOUT_MS) {
+                if let Err(err) = zmq::poll(&mut items, timeout) {
                     warn!("Scheduler unable to complete ZMQ poll: err {:?}", err);
                 };
 
@@ -141,6 +181,17 @@ impl ScheduleMgr {
                 }
             }
 
+            if let Err(err) = large_groups::expire(&self.large_groups, &self.datastore, Utc::now())
+            {
+                warn!("Scheduler unable to expire groups awaiting approval: err {:?}",
+                      err);
+            }
+
+            if let Err(err) = self.prune_job_events(Utc::now()) {
+                warn!("Scheduler unable to prune job events: err {:?}", err);
+            }
+
+            self.next_release = None;
             for target in PackageTarget::targets() {
                 if self.build_targets.contains(target) {
                     if let Err(err) = self.process_metrics(*target) {
@@ -151,6 +202,10 @@ impl ScheduleMgr {
                         warn!("Scheduler unable to process status: err {:?}", err);
                     }
 
+                    if let Err(err) = self.process_deferred(*target) {
+                        warn!("Scheduler unable to process deferred groups: err {:?}", err);
+                    }
+
                     if let Err(err) = self.process_queue(*target) {
                         warn!("Scheduler unable to process queue: err {:?}", err);
                     }
@@ -174,6 +229,17 @@ impl ScheduleMgr {
         }
     }
 
+    // Wakes the scheduler when the first quiet period ends, if that comes before the next poll
+    fn poll_timeout_ms(&self) -> i64 {
+        match self.next_release {
+            Some(at) => {
+                let wait = at.signed_duration_since(Utc::now()).num_milliseconds();
+                cmp::min(cmp::max(wait, RELEASE_MIN_WAIT_MS), SOCKET_TIMEOUT_MS)
+            }
+            None => SOCKET_TIMEOUT_MS,
+        }
+    }
+
     fn log_error(&mut self, msg: &str) {
         warn!("{}", msg);
         self.logger.log(msg);
@@ -190,6 +256,55 @@ impl ScheduleMgr {
         Ok(())
     }
 
+    // Queues the groups deferred by build freezes that are over, and moves the others on when
+    // their freeze was extended or shortened since they were deferred
+    fn process_deferred(&mut self, target: PackageTarget) -> Result<()> {
+        let conn = self.db.get_conn().map_err(Error::Db)?;
+        let groups = Group::get_all_deferred(target, &*conn)?;
+
+        // An origin whose windows can't be read keeps its groups deferred until the next pass,
+        // the other origins are released as usual
+        let now = Utc::now();
+        let mut freezes: HashMap<String, Option<Option<DateTime<Utc>>>> = HashMap::new();
+        for group in groups.iter() {
+            let origin = group.project_name.split('/').next().unwrap_or_default();
+            let frozen_until = match freezes.get(origin) {
+                Some(until) => *until,
+                None => {
+                    let until =
+                        match build_freeze::origin_frozen_until(&self.datastore, origin, now) {
+                            Ok(until) => Some(until),
+                            Err(err) => {
+                                warn!("Failed to read the freeze windows of {}, err={:?}",
+                                      origin, err);
+                                None
+                            }
+                        };
+                    freezes.insert(origin.to_string(), until);
+                    until
+                }
+            };
+
+            match frozen_until {
+                Some(frozen_until) if frozen_until != group.frozen_until => {
+                    match frozen_until {
+                        Some(until) => debug!("Group {} deferred until {}", group.id, until),
+                        None => debug!("Releasing group {} deferred by a build freeze", group.id),
+                    }
+                    if let Err(err) = self.datastore
+                                          .set_job_group_frozen_until(group.id as u64, frozen_until)
+                    {
+                        warn!("Failed to update deferred group {}, err={:?}",
+                              group.id, err);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
     fn process_queue(&mut self, target: PackageTarget) -> Result<()> {
         let conn = self.db.get_conn().map_err(Error::Db)?;
         let groups = Group::get_all_queued(target, &*conn)?;
@@ -204,10 +319,19 @@ impl ScheduleMgr {
                            target);
                 }
                 Err(diesel::result::Error::NotFound) => {
-                    debug!("Setting group {} from queued to pending",
-                           group.project_name);
-                    self.datastore.set_job_group_state(group.id as u64,
-                                                        jobsrv::JobGroupState::GroupPending)?;
+                    // Requests for the project keep landing on the group until it is released
+                    if self.datastore.release_job_group(group.id as u64)? {
+                        debug!("Setting group {} from queued to pending",
+                               group.project_name);
+                    } else if let Some(hold_until) = group.hold_until {
+                        trace!("Holding queued group {} until {}",
+                               group.project_name,
+                               hold_until);
+                        self.next_release = match self.next_release {
+                            Some(at) if at < hold_until => Some(at),
+                            _ => Some(hold_until),
+                        };
+                    }
                 }
                 Err(err) => {
                     debug!("Failed to get active group, err = {}", err);
@@ -219,11 +343,19 @@ impl ScheduleMgr {
     }
 
     fn process_work(&mut self, target: PackageTarget) -> Result<()> {
+        // Starting groups while the datastore recovers would fail them part way through
+        if self.datastore.is_degraded() {
+            debug!("Datastore degraded, not dispatching {} groups", target);
+            return Ok(());
+        }
+
         let conn = self.db.get_conn().map_err(Error::Db)?;
+        // Groups waiting for their retry, passed over so that the groups after them go ahead
+        let mut waiting = Vec::new();
 
         loop {
             // Take oldest group from the pending list
-            let group = match Group::get_pending(target, &*conn) {
+            let group = match Group::get_pending(target, &waiting, &*conn) {
                 Ok(group) => self.get_group(group.id as u64)?,
                 Err(diesel::result::Error::NotFound) => break,
                 Err(err) => {
@@ -235,6 +367,15 @@ impl ScheduleMgr {
             debug!("Found pending group {:?} for target {}", group, target);
 
             assert!(group.get_state() == jobsrv::JobGroupState::GroupPending);
+            if let Some(retry_at) = self.retries.pending(group.get_id(), Utc::now()) {
+                trace!("Retrying group {} at {}", group.get_id(), retry_at);
+                self.next_release = match self.next_release {
+                    Some(at) if at < retry_at => Some(at),
+                    _ => Some(retry_at),
+                };
+                waiting.push(group.get_id() as i64);
+                continue;
+            }
             self.dispatch_group(&group)?;
             self.update_group_state(group.get_id())?;
         }
@@ -286,6 +427,39 @@ impl ScheduleMgr {
             {
                 self.check_project(&project)?;
             }
+
+            // A group held back by its origin's limit waits while dispatching, and goes back to
+            // pending once the jobs of the origin's other groups free a slot
+            if self.origin_limits.max_concurrency(origin_of(&group)) > 0
+               && self.available_slots(&group)? != Some(0)
+               && !self.dispatchable_projects(&group)?.is_empty()
+            {
+                debug!("Watchdog: group {} has slots again", group.get_id());
+                self.datastore
+                    .set_job_group_state(group.get_id(), jobsrv::JobGroupState::GroupPending)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Deletes the job events past their retention, at most once an hour
+    fn prune_job_events(&mut self, now: DateTime<Utc>) -> Result<()> {
+        let retention = match self.retention {
+            Some(retention) => retention,
+            None => return Ok(()),
+        };
+        if let Some(pruned_at) = self.pruned_at {
+            if now.signed_duration_since(pruned_at) < Duration::hours(EVENT_PRUNE_INTERVAL_HOURS) {
+                return Ok(());
+            }
+        }
+        self.pruned_at = Some(now);
+
+        let pruned = self.datastore.prune_job_events(now - retention)?;
+        if pruned > 0 {
+            debug!("Pruned {} job events older than {} days",
+                   pruned,
+                   retention.num_days());
         }
         Ok(())
     }
@@ -301,11 +475,15 @@ impl ScheduleMgr {
             }
         };
 
+        // A leased job's maximum lifetime starts when it was first leased, not when it was queued
+        let (started_at, timeout) = match job.leased_at {
+            Some(leased_at) => (leased_at, self.max_lifetime),
+            None => (job.created_at.expect("job has a created_at field"), self.job_timeout),
+        };
         let utc: DateTime<Utc> = Utc::now();
-        let duration_since =
-            utc.signed_duration_since(job.created_at.expect("job has a created_at field"));
+        let duration_since = utc.signed_duration_since(started_at);
 
-        if duration_since > self.job_timeout {
+        if duration_since > timeout {
             debug!("Job {} has been running for: {:?}", job.id, duration_since);
             let msg = format!("Watchdog: canceling job {} (exceeded timeout: {} sec)",
                               job.id,
@@ -326,6 +504,7 @@ impl ScheduleMgr {
             .set_job_group_state(group.get_id(), jobsrv::JobGroupState::GroupDispatching)?;
 
         let mut skipped = HashMap::new();
+        let mut slots = self.available_slots(&group)?;
         let dispatchable = self.dispatchable_projects(&group)?;
 
         for project in dispatchable {
@@ -333,15 +512,38 @@ impl ScheduleMgr {
                 continue;
             }
 
+            if slots == Some(0) {
+                debug!("Group {} is at its concurrency cap of {} or its origin's limit",
+                       group.get_id(),
+                       group.get_max_concurrency());
+                break;
+            }
+
             debug!("Dispatching project: {:?}", project.get_name());
             self.logger.log_group_project(&group, &project);
 
             assert!(project.get_state() == jobsrv::JobGroupProjectState::NotStarted);
 
-            match self.schedule_job(group.get_id(), project.get_name(), group.get_target()) {
+            // Only the root project is built at the pushed commit, its dependents are built
+            // from the head of their default branch as before
+            let vcs_ref = if group.has_vcs_ref() && project.get_name() == group.get_project_name() {
+                Some(group.get_vcs_ref())
+            } else {
+                None
+            };
+
+            match self.schedule_job(group.get_id(),
+                                    project.get_name(),
+                                    group.get_target(),
+                                    group.get_no_cache(),
+                                    vcs_ref)
+            {
                 Ok(job_opt) => {
                     match job_opt {
-                        Some(job) => self.datastore.set_job_group_job_state(&job)?,
+                        Some(job) => {
+                            self.datastore.set_job_group_job_state(&job)?;
+                            slots = slots.map(|n| n - 1);
+                        }
                         None => {
                             debug!("Skipping project: {:?}", project.get_name());
                             self.datastore.set_job_group_project_state(
@@ -368,6 +570,20 @@ impl ScheduleMgr {
                     }
                 }
                 Err(err) => {
+                    // The project stays NotStarted and the group goes back to pending, to be
+                    // taken again once the retry is due
+                    if err.is_retryable() {
+                        if let Some(retry_at) = self.retries.retry(group.get_id(), Utc::now()) {
+                            self.log_error(&format!("Failed to schedule job for {} (group: {}), \
+                                                     retrying at {}, err: {:?}",
+                                                    project.get_name(),
+                                                    group.get_id(),
+                                                    retry_at,
+                                                    err));
+                            return Ok(());
+                        }
+                    }
+
                     self.log_error(&format!("Failed to schedule job for {} (group: {}), err: \
                                              {:?}",
                                             project.get_name(),
@@ -391,6 +607,7 @@ impl ScheduleMgr {
                 }
             }
         }
+        self.retries.clear(group.get_id());
         Ok(())
     }
 
@@ -462,6 +679,7 @@ impl ScheduleMgr {
         for project in group.get_projects() {
             if (project.get_name() == name)
                && (project.get_state() != jobsrv::JobGroupProjectState::Success)
+               && (project.get_state() != jobsrv::JobGroupProjectState::Cached)
             {
                 return false;
             }
@@ -532,7 +750,9 @@ impl ScheduleMgr {
     fn schedule_job(&mut self,
                     group_id: u64,
                     project_name: &str,
-                    target: &str)
+                    target: &str,
+                    no_cache: bool,
+                    vcs_ref: Option<&str>)
                     -> Result<Option<jobsrv::Job>> {
         let conn = self.db.get_conn().map_err(Error::Db)?;
 
@@ -556,12 +776,22 @@ impl ScheduleMgr {
         job_spec.set_project(project.into());
         job_spec.set_target(target.to_string());
         job_spec.set_channel(format!("bldr-{}", group_id));
+        job_spec.set_no_cache(no_cache);
+        if let Some(vcs_ref) = vcs_ref {
+            job_spec.set_vcs_ref(vcs_ref.to_string());
+        }
 
         let job: jobsrv::Job = job_spec.into();
         match self.datastore.create_job(&job) {
             Ok(job) => {
                 debug!("Job created: {:?}", job);
-                self.worker_mgr.notify_work()?;
+                // The job exists now, failing here would schedule it twice on a retry. The
+                // worker manager finds it on its next poll without the nudge.
+                if let Err(err) = self.worker_mgr.notify_work() {
+                    warn!("Unable to notify the worker manager of job {}, err: {:?}",
+                          job.get_id(),
+                          err);
+                }
                 Ok(Some(job))
             }
             Err(err) => {
@@ -621,7 +851,7 @@ impl ScheduleMgr {
             };
 
             match job.get_state() {
-                jobsrv::JobState::Complete => {
+                jobsrv::JobState::Complete | jobsrv::JobState::PostProcessVerificationFailed => {
                     Counter::CompletedJobs(target).increment();
                     assert!(job.has_build_started_at());
                     assert!(job.has_build_finished_at());
@@ -632,6 +862,7 @@ impl ScheduleMgr {
                     let build_duration = build_finished_at - build_started_at;
                     Histogram::JobCompletionTime(target).set(build_duration.num_seconds() as f64);
                 }
+                jobsrv::JobState::CompletedFromCache => Counter::CachedJobs(target).increment(),
                 jobsrv::JobState::Failed => Counter::FailedJobs(target).increment(),
                 _ => (),
             }
@@ -649,10 +880,19 @@ impl ScheduleMgr {
                                                         e));
                             }
                         };
+
+                        if let Err(e) = self.apply_failure_policy(job.get_owner_id()) {
+                            self.log_error(&format!("Error applying failure policy (group: {}): \
+                                                     {:?}",
+                                                    job.get_owner_id(),
+                                                    e));
+                        }
                     }
 
                     match job.get_state() {
                         jobsrv::JobState::Complete
+                        | jobsrv::JobState::CompletedFromCache
+                        | jobsrv::JobState::PostProcessVerificationFailed
                         | jobsrv::JobState::Failed
                         | jobsrv::JobState::CancelComplete => {
                             self.update_group_state(job.get_owner_id())?
@@ -681,6 +921,39 @@ impl ScheduleMgr {
         Ok(())
     }
 
+    // Gives up on the projects of a group the failure policy says not to build after one of its
+    // projects failed. Jobs already created are left to finish; the projects that have not
+    // started are marked as skipped by the policy, as canceling a group marks them canceled.
+    //   * fail_fast: all of them
+    //   * fail_fast_same_wave: those that can not be dispatched yet, the current wave (the projects
+    //     whose dependencies in the group are all built) still gets built
+    fn apply_failure_policy(&mut self, group_id: u64) -> Result<()> {
+        let group = self.get_group(group_id)?;
+        let policy = group.get_failure_policy();
+        if policy == jobsrv::JobGroupFailurePolicy::ContinueOnFailure {
+            return Ok(());
+        }
+
+        let wave: Vec<String> = if policy == jobsrv::JobGroupFailurePolicy::FailFastSameWave {
+            self.dispatchable_projects(&group)?
+                .iter()
+                .map(|p| p.get_name().to_string())
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let skipped = given_up(&group, &wave);
+        if !skipped.is_empty() {
+            debug!("Skipping {} projects of group {} by its {} policy",
+                   skipped.len(),
+                   group_id,
+                   policy);
+            self.datastore.skip_job_group_projects(group_id, &skipped)?;
+        }
+        Ok(())
+    }
+
     fn update_group_state(&mut self, group_id: u64) -> Result<()> {
         let group = self.get_group(group_id)?;
 
@@ -691,6 +964,7 @@ impl ScheduleMgr {
         // |     Pending             |     N/A          |        N/A          |
         // |     Dispatching         |   no remaining   |      Complete       |
         // |     Dispatching         |   dispatchable?  |      Pending        |
+        // |                         |   (below cap)    |                     |
         // |     Dispatching         |   otherwise      |      Dispatching    |
         // |     Complete            |     N/A          |        N/A          |
         // |     Failed              |     N/A          |        N/A          |
@@ -704,8 +978,10 @@ impl ScheduleMgr {
             for project in group.get_projects() {
                 match project.get_state() {
                     jobsrv::JobGroupProjectState::Failure => failed += 1,
-                    jobsrv::JobGroupProjectState::Success => succeeded += 1,
-                    jobsrv::JobGroupProjectState::Skipped => skipped += 1,
+                    jobsrv::JobGroupProjectState::Success
+                    | jobsrv::JobGroupProjectState::Cached => succeeded += 1,
+                    jobsrv::JobGroupProjectState::Skipped
+                    | jobsrv::JobGroupProjectState::SkippedGroupPolicy => skipped += 1,
                     jobsrv::JobGroupProjectState::Canceled => canceled += 1,
 
                     jobsrv::JobGroupProjectState::NotStarted
@@ -719,7 +995,7 @@ impl ScheduleMgr {
                 jobsrv::JobGroupState::GroupComplete
             } else if canceled > 0 {
                 jobsrv::JobGroupState::GroupCanceled
-            } else if !dispatchable.is_empty() {
+            } else if !dispatchable.is_empty() && self.available_slots(&group)? != Some(0) {
                 jobsrv::JobGroupState::GroupPending
             } else {
                 jobsrv::JobGroupState::GroupDispatching
@@ -743,6 +1019,90 @@ impl ScheduleMgr {
 
         Ok(())
     }
+
+    // How many more of the group's jobs may be dispatched under both its own cap and the limit
+    // of its origin. The group's cap is only applied to the groups the flag is rolled out to.
+    fn available_slots(&self, group: &jobsrv::JobGroup) -> Result<Option<usize>> {
+        let group_slots = if self.flags
+                                 .is_enabled_for(GROUP_CONCURRENCY_CAP, group.get_id())
+        {
+            available_slots(group)
+        } else {
+            None
+        };
+
+        let origin = origin_of(group);
+        let origin_slots = match self.origin_limits.max_concurrency(origin) {
+            0 => None,
+            limit => {
+                let in_flight = self.datastore.count_origin_jobs_in_flight(origin)?;
+                Some(remaining(limit, in_flight as usize))
+            }
+        };
+
+        Ok(most_restrictive(group_slots, origin_slots))
+    }
+}
+
+fn origin_of(group: &jobsrv::JobGroup) -> &str {
+    group.get_project_name().split('/').next().unwrap_or("")
+}
+
+// The lower of two limits, where `None` is no limit
+fn most_restrictive(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+// The retries of the groups whose dispatch failed with a retryable error, by group id: how many
+// were made and when the next one is due. Only this process's retries are known.
+#[derive(Default)]
+struct DispatchRetries(HashMap<u64, (u32, DateTime<Utc>)>);
+
+impl DispatchRetries {
+    // When the group's retry is due, if that is still to come
+    fn pending(&self, group_id: u64, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self.0.get(&group_id) {
+            Some(&(_, retry_at)) if retry_at > now => Some(retry_at),
+            _ => None,
+        }
+    }
+
+    // Counts another retry of the group and returns when it is due, or `None` once the group
+    // ran out of retries
+    fn retry(&mut self, group_id: u64, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let attempts = self.0.get(&group_id).map_or(0, |&(attempts, _)| attempts);
+        if attempts >= MAX_DISPATCH_RETRIES {
+            self.clear(group_id);
+            return None;
+        }
+        let retry_at = now + Duration::milliseconds(DISPATCH_RETRY_MS << attempts);
+        self.0.insert(group_id, (attempts + 1, retry_at));
+        Some(retry_at)
+    }
+
+    fn clear(&mut self, group_id: u64) { self.0.remove(&group_id); }
+}
+
+fn remaining(limit: u32, in_flight: usize) -> usize { (limit as usize).saturating_sub(in_flight) }
+
+// How many more of the group's jobs may be dispatched, or `None` when the group is not capped.
+// Jobs are counted from the moment they are created until their project finishes.
+fn available_slots(group: &jobsrv::JobGroup) -> Option<usize> {
+    match group.get_max_concurrency() {
+        0 => None,
+        cap => {
+            let in_flight =
+                group.get_projects()
+                     .iter()
+                     .filter(|p| p.get_state() == jobsrv::JobGroupProjectState::InProgress)
+                     .count();
+            Some(remaining(cap, in_flight))
+        }
+    }
 }
 
 fn buildable(project: &jobsrv::JobGroupProject) -> bool {
@@ -751,3 +1111,188 @@ fn buildable(project: &jobsrv::JobGroupProject) -> bool {
         _ => false,
     }
 }
+
+// The projects of the group a failure policy gives up on: those not started yet, apart from
+// the ones of the current `wave`
+fn given_up(group: &jobsrv::JobGroup, wave: &[String]) -> Vec<String> {
+    group.get_projects()
+         .iter()
+         .filter(|p| p.get_state() == jobsrv::JobGroupProjectState::NotStarted)
+         .map(|p| p.get_name().to_string())
+         .filter(|name| !wave.contains(name))
+         .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread,
+              time::{Duration as StdDuration,
+                     Instant}};
+
+    use crate::{config::SupervisorCfg,
+                db::config::DataStoreCfg};
+
+    use crate::server::supervisor::SubsystemHealth;
+
+    fn group(max_concurrency: u32, states: &[jobsrv::JobGroupProjectState]) -> jobsrv::JobGroup {
+        let mut group = jobsrv::JobGroup::new();
+        group.set_project_name("core/nginx".to_string());
+        group.set_max_concurrency(max_concurrency);
+        let projects = states.iter()
+                             .map(|state| {
+                                 let mut project = jobsrv::JobGroupProject::new();
+                                 project.set_state(*state);
+                                 project
+                             })
+                             .collect();
+        group.set_projects(projects);
+        group
+    }
+
+    #[test]
+    fn uncapped_groups_have_no_limit() {
+        let group = group(0, &[jobsrv::JobGroupProjectState::InProgress]);
+        assert_eq!(available_slots(&group), None);
+    }
+
+    #[test]
+    fn capped_groups_count_their_jobs_in_progress() {
+        let group = group(3,
+                          &[jobsrv::JobGroupProjectState::InProgress,
+                            jobsrv::JobGroupProjectState::InProgress,
+                            jobsrv::JobGroupProjectState::Success,
+                            jobsrv::JobGroupProjectState::NotStarted]);
+        assert_eq!(available_slots(&group), Some(1));
+        assert_eq!(remaining(1, 4), 0);
+    }
+
+    #[test]
+    fn the_most_restrictive_limit_wins() {
+        assert_eq!(most_restrictive(Some(5), Some(2)), Some(2));
+        assert_eq!(most_restrictive(Some(2), Some(5)), Some(2));
+        assert_eq!(most_restrictive(Some(0), None), Some(0));
+        assert_eq!(most_restrictive(None, Some(7)), Some(7));
+        assert_eq!(most_restrictive(None, None), None);
+    }
+
+    #[test]
+    fn groups_are_limited_by_their_origin() {
+        let group = group(0, &[]);
+        assert_eq!(origin_of(&group), "core");
+
+        let mut limits = OriginLimitsCfg { max_concurrency: 10,
+                                           ..Default::default() };
+        limits.overrides.insert("core".to_string(), 2);
+        assert_eq!(limits.max_concurrency(origin_of(&group)), 2);
+        assert_eq!(limits.max_concurrency("acme"), 10);
+    }
+
+    fn named(projects: &[(&str, jobsrv::JobGroupProjectState)]) -> jobsrv::JobGroup {
+        let mut group = group(0, &[]);
+        let projects = projects.iter()
+                               .map(|(name, state)| {
+                                   let mut project = jobsrv::JobGroupProject::new();
+                                   project.set_name(name.to_string());
+                                   project.set_state(*state);
+                                   project
+                               })
+                               .collect();
+        group.set_projects(projects);
+        group
+    }
+
+    #[test]
+    fn dispatch_retries_back_off_until_they_run_out() {
+        let mut retries = DispatchRetries::default();
+        let now = Utc::now();
+        let due: Vec<Option<DateTime<Utc>>> =
+            (0..MAX_DISPATCH_RETRIES).map(|_| retries.retry(1, now))
+                                     .collect();
+        let waits = [2, 4, 8, 16, 32];
+        assert_eq!(due,
+                   waits.iter()
+                        .map(|secs| Some(now + Duration::seconds(*secs)))
+                        .collect::<Vec<_>>());
+        assert_eq!(retries.pending(1, now), Some(now + Duration::seconds(32)));
+        assert_eq!(retries.pending(1, now + Duration::seconds(32)), None);
+        assert_eq!(retries.pending(2, now), None);
+
+        // The group fails then and its retries are forgotten
+        assert_eq!(retries.retry(1, now), None);
+        assert_eq!(retries.pending(1, now), None);
+        assert_eq!(retries.retry(2, now), Some(now + Duration::seconds(2)));
+        retries.clear(2);
+        assert_eq!(retries.pending(2, now), None);
+    }
+
+    #[test]
+    fn failing_fast_gives_up_on_every_project_not_started() {
+        let group = named(&[("core/a", jobsrv::JobGroupProjectState::Failure),
+                            ("core/b", jobsrv::JobGroupProjectState::InProgress),
+                            ("core/c", jobsrv::JobGroupProjectState::NotStarted),
+                            ("core/d", jobsrv::JobGroupProjectState::Skipped),
+                            ("core/e", jobsrv::JobGroupProjectState::NotStarted)]);
+        assert_eq!(given_up(&group, &[]), vec!["core/c", "core/e"]);
+    }
+
+    #[test]
+    fn failing_fast_in_the_same_wave_keeps_the_current_wave() {
+        let group = named(&[("core/a", jobsrv::JobGroupProjectState::Failure),
+                            ("core/c", jobsrv::JobGroupProjectState::NotStarted),
+                            ("core/e", jobsrv::JobGroupProjectState::NotStarted)]);
+        assert_eq!(given_up(&group, &["core/c".to_string()]), vec!["core/e"]);
+        assert!(given_up(&group, &["core/c".to_string(), "core/e".to_string()]).is_empty());
+    }
+
+    // Runs against the database of tests/data_store.rs, which creates it
+    fn live_config() -> Config {
+        Config { datastore: DataStoreCfg { password: Some("hab".to_string()),
+                                           database: "builder_jobsrv_test".to_string(),
+                                           ..Default::default() },
+                 supervisor: SupervisorCfg { backoff_initial_ms: 10,
+                                             ..Default::default() },
+                 ..Default::default() }
+    }
+
+    #[test]
+    #[ignore]
+    fn scheduler_dispatches_pending_groups_after_a_panic() {
+        let cfg = live_config();
+        let datastore = DataStore::new(&cfg.datastore);
+        datastore.setup().unwrap();
+
+        let mut spec = jobsrv::JobGroupSpec::new();
+        spec.set_origin("supervised".to_string());
+        spec.set_package("nginx".to_string());
+        spec.set_target(target::X86_64_LINUX.to_string());
+        let projects = vec![("supervised/nginx".to_string(), "supervised/nginx".to_string())];
+        let group =
+            datastore.create_job_group(&spec, projects, jobsrv::JobGroupState::GroupPending, None)
+                     .unwrap();
+
+        // The first attempt panics before its first pass, only the restarted one can dispatch
+        let health = SubsystemHealth::new(&cfg.supervisor);
+        let supervisor = Supervisor::new(&cfg.supervisor, &health);
+        supervisor.inject_panic("scheduler");
+        ScheduleMgr::start(&cfg,
+                           &datastore,
+                           DbPool::new(&cfg.datastore),
+                           &FeatureFlags::new(),
+                           &supervisor).unwrap();
+
+        let mut get = jobsrv::JobGroupGet::new();
+        get.set_group_id(group.get_id());
+        let deadline = Instant::now() + StdDuration::from_secs(30);
+        let state = loop {
+            let state = datastore.get_job_group(&get).unwrap().unwrap().get_state();
+            if state != jobsrv::JobGroupState::GroupPending || Instant::now() > deadline {
+                break state;
+            }
+            thread::sleep(StdDuration::from_millis(100));
+        };
+
+        assert_ne!(state, jobsrv::JobGroupState::GroupPending);
+        assert!(health.failed().is_empty());
+    }
+}
@@ -0,0 +1,315 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Restarts the long running jobsrv threads when they panic or return an error. Every attempt
+//! builds its manager from scratch, so in-memory state is re-read from the database rather than
+//! carried over from the attempt that failed. A thread that keeps failing within the restart
+//! window is reported as failed on the status endpoint until it stays up for a whole window.
+
+use std::{any::Any,
+          cell::Cell,
+          collections::{BTreeMap,
+                        VecDeque},
+          panic::{self,
+                  AssertUnwindSafe},
+          sync::{mpsc,
+                 Arc,
+                 RwLock},
+          thread::{self,
+                   JoinHandle},
+          time::{Duration,
+                 Instant}};
+
+#[cfg(test)]
+use std::{cell::RefCell,
+          collections::HashSet,
+          sync::Mutex};
+
+use crate::{bldr_core::metrics::CounterMetric,
+            config::SupervisorCfg,
+            error::{Error,
+                    Result}};
+
+use super::metrics::Counter;
+
+thread_local!(static SUPERVISED: Cell<bool> = Cell::new(false));
+
+// Names of the threads to panic at their next checkpoint
+#[cfg(test)]
+type InjectedPanics = Arc<Mutex<HashSet<&'static str>>>;
+
+#[cfg(test)]
+thread_local!(static INJECTED: RefCell<Option<(&'static str, InjectedPanics)>> = RefCell::new(None));
+
+/// Whether the calling thread is run by a supervisor. Panics on these threads are recovered
+/// from, so the panic hook must not exit the process.
+pub fn is_supervised() -> bool { SUPERVISED.with(Cell::get) }
+
+/// Called once per pass of the loop of every supervised thread. Panics the thread when its
+/// supervisor was told to with `inject_panic`, a no-op outside of tests.
+#[inline]
+pub fn checkpoint() {
+    #[cfg(test)]
+    INJECTED.with(|injected| {
+                if let Some((ref name, ref panics)) = *injected.borrow() {
+                    if panics.lock().unwrap().remove(name) {
+                        panic!("injected panic in {}", name);
+                    }
+                }
+            });
+}
+
+/// The supervised threads that exceeded their restart budget. A thread is reported until it
+/// has gone a whole restart window without exceeding it again.
+#[derive(Clone)]
+pub struct SubsystemHealth {
+    window: Duration,
+    failed: Arc<RwLock<BTreeMap<&'static str, Instant>>>,
+}
+
+impl SubsystemHealth {
+    pub fn new(cfg: &SupervisorCfg) -> Self {
+        SubsystemHealth { window: Duration::from_secs(cfg.window_sec),
+                          failed: Arc::default(), }
+    }
+
+    pub fn fail(&self, name: &'static str, at: Instant) {
+        self.failed.write().unwrap().insert(name, at);
+    }
+
+    pub fn failed(&self) -> Vec<&'static str> { self.failed_at(Instant::now()) }
+
+    fn failed_at(&self, now: Instant) -> Vec<&'static str> {
+        self.failed
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, at)| **at + self.window > now)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
+
+#[derive(Clone)]
+pub struct Supervisor {
+    cfg:      SupervisorCfg,
+    health:   SubsystemHealth,
+    #[cfg(test)]
+    injected: InjectedPanics,
+}
+
+impl Supervisor {
+    pub fn new(cfg: &SupervisorCfg, health: &SubsystemHealth) -> Self {
+        Supervisor { cfg:                   cfg.clone(),
+                     health:                health.clone(),
+                     #[cfg(test)]
+                     injected:              InjectedPanics::default(), }
+    }
+
+    /// Panics the thread `name` at its next checkpoint
+    #[cfg(test)]
+    pub fn inject_panic(&self, name: &'static str) { self.injected.lock().unwrap().insert(name); }
+
+    /// Runs `body` on a new thread named `name`, calling it again whenever it panics or
+    /// returns. `body` signals readiness on the sender it is given; this returns once the first
+    /// attempt has done so and panics if that attempt fails first, as an unsupervised thread
+    /// would at startup.
+    pub fn spawn<F>(&self, name: &'static str, mut body: F) -> Result<JoinHandle<()>>
+        where F: FnMut(&mpsc::SyncSender<()>) -> Result<()> + Send + 'static
+    {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let supervisor = self.clone();
+        let handle = thread::Builder::new().name(name.to_string())
+                                           .spawn(move || supervisor.run(name, tx, &mut body))?;
+        match rx.recv() {
+            Ok(()) => Ok(handle),
+            Err(e) => panic!("{} thread startup error, err={}", name, e),
+        }
+    }
+
+    fn run<F>(&self, name: &'static str, ready: mpsc::SyncSender<()>, body: &mut F)
+        where F: FnMut(&mpsc::SyncSender<()>) -> Result<()>
+    {
+        SUPERVISED.with(|s| s.set(true));
+        #[cfg(test)]
+        INJECTED.with(|injected| *injected.borrow_mut() = Some((name, self.injected.clone())));
+        let mut ready = Some(ready);
+        let mut window = RestartWindow::new(Duration::from_secs(self.cfg.window_sec));
+
+        loop {
+            // Only the first attempt reports readiness to `spawn`, later ones get a channel of
+            // their own that is kept open until they are done with it
+            let (restart_tx, _restart_rx) = mpsc::sync_channel(1);
+            let tx = ready.take().unwrap_or(restart_tx);
+
+            let err = match panic::catch_unwind(AssertUnwindSafe(|| body(&tx))) {
+                Ok(Ok(())) => {
+                    info!("Supervised thread {} exited", name);
+                    return;
+                }
+                Ok(Err(err)) => err,
+                Err(payload) => Error::CaughtPanic(panic_message(&*payload), name.to_string()),
+            };
+            drop(tx);
+
+            Counter::ThreadRestarts(name).increment();
+            let now = Instant::now();
+            let restarts = window.record(now);
+            if restarts > self.cfg.max_restarts as usize {
+                error!("Thread {} restarted {} times in {} sec, marking it failed",
+                       name, restarts, self.cfg.window_sec);
+                self.health.fail(name, now);
+            }
+
+            let delay = backoff(&self.cfg, restarts);
+            error!("Thread {} stopped, restarting in {} ms, err={}",
+                   name,
+                   delay.as_millis(),
+                   err);
+            thread::sleep(delay);
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+// Delay before the given restart within the window, doubling from the initial backoff
+fn backoff(cfg: &SupervisorCfg, restarts: usize) -> Duration {
+    let exponent = restarts.saturating_sub(1).min(31) as u32;
+    let delay = cfg.backoff_initial_ms.saturating_mul(1 << exponent);
+    Duration::from_millis(delay.min(cfg.backoff_max_ms))
+}
+
+// Restarts seen within the trailing window
+struct RestartWindow {
+    length:   Duration,
+    restarts: VecDeque<Instant>,
+}
+
+impl RestartWindow {
+    fn new(length: Duration) -> Self {
+        RestartWindow { length,
+                        restarts: VecDeque::new() }
+    }
+
+    // Records a restart at `now`, returning the number of restarts within the window
+    fn record(&mut self, now: Instant) -> usize {
+        while let Some(oldest) = self.restarts.front().cloned() {
+            if now.duration_since(oldest) < self.length {
+                break;
+            }
+            self.restarts.pop_front();
+        }
+        self.restarts.push_back(now);
+        self.restarts.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg() -> SupervisorCfg {
+        SupervisorCfg { max_restarts:       2,
+                        window_sec:         600,
+                        backoff_initial_ms: 10,
+                        backoff_max_ms:     40, }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let cfg = cfg();
+        assert_eq!(backoff(&cfg, 1), Duration::from_millis(10));
+        assert_eq!(backoff(&cfg, 2), Duration::from_millis(20));
+        assert_eq!(backoff(&cfg, 3), Duration::from_millis(40));
+        assert_eq!(backoff(&cfg, 4), Duration::from_millis(40));
+        assert_eq!(backoff(&cfg, 100), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn restart_window_forgets_old_restarts() {
+        let start = Instant::now();
+        let mut window = RestartWindow::new(Duration::from_secs(60));
+        assert_eq!(window.record(start), 1);
+        assert_eq!(window.record(start + Duration::from_secs(30)), 2);
+        assert_eq!(window.record(start + Duration::from_secs(61)), 2);
+        assert_eq!(window.record(start + Duration::from_secs(200)), 1);
+    }
+
+    #[test]
+    fn failed_threads_recover_after_a_quiet_window() {
+        let start = Instant::now();
+        let health = SubsystemHealth::new(&cfg());
+        health.fail("scheduler", start);
+        assert_eq!(health.failed_at(start + Duration::from_secs(599)),
+                   vec!["scheduler"]);
+        assert!(health.failed_at(start + Duration::from_secs(600))
+                      .is_empty());
+
+        // Failing again restarts the window
+        health.fail("scheduler", start + Duration::from_secs(300));
+        assert_eq!(health.failed_at(start + Duration::from_secs(600)),
+                   vec!["scheduler"]);
+    }
+
+    #[test]
+    fn injected_panics_restart_the_named_thread_once() {
+        let health = SubsystemHealth::new(&cfg());
+        let supervisor = Supervisor::new(&cfg(), &health);
+        supervisor.inject_panic("test-injected");
+        let (attempts_tx, attempts) = mpsc::channel();
+
+        supervisor.spawn("test-injected", move |rz| {
+                      attempts_tx.send(()).unwrap();
+                      rz.send(()).unwrap();
+                      loop {
+                          checkpoint();
+                          thread::sleep(Duration::from_millis(5));
+                      }
+                  })
+                  .unwrap();
+
+        let timeout = Duration::from_secs(10);
+        assert!(attempts.recv_timeout(timeout).is_ok());
+        assert!(attempts.recv_timeout(timeout).is_ok());
+        assert!(attempts.recv_timeout(Duration::from_millis(200)).is_err());
+        assert!(health.failed().is_empty());
+    }
+
+    #[test]
+    fn thread_failing_past_its_budget_is_marked_failed() {
+        let health = SubsystemHealth::new(&cfg());
+        let supervisor = Supervisor::new(&cfg(), &health);
+        supervisor.spawn("test-failing", |rz| {
+                      rz.send(()).unwrap();
+                      Err(Error::System)
+                  })
+                  .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while health.failed().is_empty() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(health.failed(), vec!["test-failing"]);
+    }
+}
@@ -16,12 +16,17 @@ use std::{collections::HashSet,
           path::PathBuf,
           str::{from_utf8,
                 FromStr},
-          sync::mpsc,
-          thread::{self,
-                   JoinHandle},
+          sync::{mpsc,
+                 Arc,
+                 RwLock},
+          thread::JoinHandle,
           time::{Duration,
                  Instant}};
 
+use chrono::{self,
+             DateTime,
+             Utc};
+
 use crate::{bldr_core::{self,
                         job::Job,
                         metrics::GaugeMetric,
@@ -31,15 +36,16 @@ use crate::{bldr_core::{self,
                                        parse_key_str,
                                        parse_name_with_rev},
                                 BoxKeyPair},
-                       package::{target,
-                                 PackageTarget}}};
+                       package::PackageTarget}};
+use diesel::pg::PgConnection;
 use linked_hash_map::LinkedHashMap;
 use protobuf::{parse_from_bytes,
                Message,
                RepeatedField};
 
 use crate::db::models::{integration::*,
-                        jobs::*,
+                        jobs::{self,
+                               *},
                         keys::*,
                         project_integration::*,
                         secrets::*};
@@ -49,18 +55,28 @@ use crate::protocol::{jobsrv,
 
 use zmq;
 
-use crate::{config::Config,
+use crate::{config::{Config,
+                     JobLeaseCfg},
             data_store::DataStore,
             error::{Error,
                     Result}};
 
-use super::{metrics::Gauge,
-            scheduler::ScheduleClient};
+use super::{job_lease::{self,
+                        Check,
+                        Lease},
+            metrics::Gauge,
+            scheduler::ScheduleClient,
+            supervisor::{self,
+                         Supervisor}};
 
 const WORKER_MGR_ADDR: &str = "inproc://work-manager";
 const WORKER_TIMEOUT_MS: u64 = 33_000; // 33 sec
 const DEFAULT_POLL_TIMEOUT_MS: u64 = 60_000; // 60 secs
-const JOB_TIMEOUT_CONVERT_MS: u64 = 60_000; // Conversion from mins to milli-seconds
+const LABEL_CHECK_INTERVAL_MS: u64 = 300_000; // 5 mins
+const REQUEUE_INTERVAL_MS: u64 = 300_000; // 5 mins
+
+/// Snapshot of the workers known to the WorkerMgr, shared with the RPC handlers
+pub type WorkerRegistry = Arc<RwLock<Vec<jobsrv::WorkerInfo>>>;
 
 pub struct WorkerMgrClient {
     socket: zmq::Socket,
@@ -88,13 +104,17 @@ impl Default for WorkerMgrClient {
 
 #[derive(Debug)]
 pub struct Worker {
-    pub target:     PackageTarget,
-    pub ident:      String,
-    pub state:      jobsrv::WorkerState,
-    pub expiry:     Instant,
-    pub job_id:     Option<u64>,
-    pub job_expiry: Option<Instant>,
-    pub canceling:  bool,
+    pub target:    PackageTarget,
+    pub ident:     String,
+    pub state:     jobsrv::WorkerState,
+    pub expiry:    Instant,
+    pub job_id:    Option<u64>,
+    pub lease:     Option<Lease>,
+    pub canceling: bool,
+    pub labels:    Vec<String>,
+    pub network:   Option<jobsrv::WorkerNetwork>,
+    /// Whether the worker renews the lease of its job
+    pub leasing:   bool,
 }
 
 impl Worker {
@@ -104,27 +124,50 @@ impl Worker {
                  state: jobsrv::WorkerState::Ready,
                  expiry: Instant::now() + Duration::from_millis(WORKER_TIMEOUT_MS),
                  job_id: None,
-                 job_expiry: None,
-                 canceling: false }
+                 lease: None,
+                 canceling: false,
+                 labels: Vec::new(),
+                 network: None,
+                 leasing: false }
+    }
+
+    pub fn set_labels(&mut self, labels: &[String]) {
+        self.labels = labels.to_vec();
+        self.labels.sort();
+        self.labels.dedup();
+    }
+
+    pub fn info(&self) -> jobsrv::WorkerInfo {
+        let mut info = jobsrv::WorkerInfo::new();
+        info.set_ident(self.ident.clone());
+        info.set_target(self.target.to_string());
+        info.set_state(self.state);
+        if let Some(job_id) = self.job_id {
+            info.set_job_id(job_id);
+        }
+        info.set_labels(RepeatedField::from_vec(self.labels.clone()));
+        if let Some(ref network) = self.network {
+            info.set_network(network.clone());
+        }
+        info
     }
 
     pub fn ready(&mut self) {
         self.state = jobsrv::WorkerState::Ready;
         self.expiry = Instant::now() + Duration::from_millis(WORKER_TIMEOUT_MS);
         self.job_id = None;
-        self.job_expiry = None;
+        self.lease = None;
         self.canceling = false;
     }
 
-    pub fn busy(&mut self, job_id: u64, job_timeout: u64) {
+    pub fn busy(&mut self, job_id: u64, lease: Lease) {
         self.state = jobsrv::WorkerState::Busy;
         self.expiry = Instant::now() + Duration::from_millis(WORKER_TIMEOUT_MS);
         self.canceling = false;
 
         if self.job_id.is_none() {
             self.job_id = Some(job_id);
-            self.job_expiry =
-                Some(Instant::now() + Duration::from_millis(job_timeout * JOB_TIMEOUT_CONVERT_MS));
+            self.lease = Some(lease);
         } else {
             assert!(self.job_id.unwrap() == job_id);
         }
@@ -140,15 +183,24 @@ impl Worker {
 
     pub fn is_expired(&self) -> bool { self.expiry < Instant::now() }
 
-    pub fn is_job_expired(&self) -> bool {
-        if self.job_expiry.is_some() {
-            self.job_expiry.unwrap() < Instant::now()
-        } else {
-            false
-        }
+    pub fn is_job_expired(&self, now: DateTime<Utc>) -> bool {
+        self.lease.map_or(false, |lease| lease.is_expired(now))
     }
 }
 
+/// The first Ready worker building `target` whose labels have not yet been exhausted in a
+/// dispatch pass
+fn next_ready_worker<'a, I>(workers: I,
+                            target: PackageTarget,
+                            exhausted: &[Vec<String>])
+                            -> Option<&'a Worker>
+    where I: IntoIterator<Item = &'a Worker>
+{
+    workers.into_iter()
+           .filter(|w| (w.target == target) && (w.state == jobsrv::WorkerState::Ready))
+           .find(|w| !exhausted.contains(&w.labels))
+}
+
 pub struct WorkerMgr {
     datastore:        DataStore,
     db:               DbPool,
@@ -162,11 +214,13 @@ pub struct WorkerMgr {
     worker_heartbeat: String,
     schedule_cli:     ScheduleClient,
     job_timeout:      u64,
+    job_leases:       JobLeaseCfg,
     build_targets:    HashSet<PackageTarget>,
+    registry:         WorkerRegistry,
 }
 
 impl WorkerMgr {
-    pub fn new(cfg: &Config, datastore: &DataStore, db: DbPool) -> Self {
+    pub fn new(cfg: &Config, datastore: &DataStore, db: DbPool, registry: &WorkerRegistry) -> Self {
         let hb_sock = (**DEFAULT_CONTEXT).as_mut().socket(zmq::SUB).unwrap();
         let rq_sock = (**DEFAULT_CONTEXT).as_mut().socket(zmq::ROUTER).unwrap();
         let work_mgr_sock = (**DEFAULT_CONTEXT).as_mut().socket(zmq::DEALER).unwrap();
@@ -188,21 +242,23 @@ impl WorkerMgr {
                     worker_heartbeat: cfg.net.worker_heartbeat_addr(),
                     schedule_cli,
                     job_timeout: cfg.job_timeout,
-                    build_targets: cfg.build_targets.clone() }
-    }
-
-    pub fn start(cfg: &Config, datastore: &DataStore, db: DbPool) -> Result<JoinHandle<()>> {
-        let mut manager = Self::new(cfg, datastore, db);
-        let (tx, rx) = mpsc::sync_channel(1);
-        let handle = thread::Builder::new().name("worker-manager".to_string())
-                                           .spawn(move || {
-                                               manager.run(&tx).unwrap();
-                                           })
-                                           .unwrap();
-        match rx.recv() {
-            Ok(()) => Ok(handle),
-            Err(e) => panic!("worker-manager thread startup error, err={}", e),
-        }
+                    job_leases: cfg.job_leases.clone(),
+                    build_targets: cfg.build_targets.clone(),
+                    registry: registry.clone() }
+    }
+
+    /// Busy workers are reloaded and dispatched jobs without one are requeued every time the
+    /// manager (re)starts, and those jobs are looked for again periodically after that.
+    pub fn start(cfg: &Config,
+                 datastore: &DataStore,
+                 db: DbPool,
+                 registry: &WorkerRegistry,
+                 supervisor: &Supervisor)
+                 -> Result<JoinHandle<()>> {
+        let (cfg, datastore, registry) = (cfg.clone(), datastore.clone(), registry.clone());
+        supervisor.spawn("worker-manager", move |rz| {
+                      Self::new(&cfg, &datastore, db.clone(), &registry).run(rz)
+                  })
     }
 
     #[allow(clippy::cognitive_complexity)]
@@ -217,6 +273,9 @@ impl WorkerMgr {
         let mut work_mgr_sock = false;
         let mut process_work = false;
         let mut last_processed = Instant::now();
+        // Give workers a heartbeat window to register before the first label check
+        let mut next_label_check = Instant::now() + Duration::from_millis(WORKER_TIMEOUT_MS);
+        let mut next_requeue = Instant::now() + Duration::from_millis(REQUEUE_INTERVAL_MS);
 
         rz.send(()).unwrap();
 
@@ -229,6 +288,7 @@ impl WorkerMgr {
         info!("builder-jobsrv is ready to go.");
 
         loop {
+            supervisor::checkpoint();
             {
                 let mut items = [self.hb_sock.as_poll_item(1),
                                  self.rq_sock.as_poll_item(1),
@@ -292,6 +352,27 @@ impl WorkerMgr {
                 last_processed = now;
             }
 
+            if now > next_label_check {
+                for target in PackageTarget::targets() {
+                    if self.build_targets.contains(target) {
+                        if let Err(err) = self.check_worker_labels(*target) {
+                            warn!("Worker-manager unable to check worker labels: err {:?}",
+                                  err);
+                        }
+                    }
+                }
+                next_label_check = now + Duration::from_millis(LABEL_CHECK_INTERVAL_MS);
+            }
+
+            // Jobs claimed by a pass that failed part way through, e.g. while adding secrets,
+            // are left dispatched without a worker
+            if now > next_requeue {
+                if let Err(err) = self.requeue_jobs() {
+                    warn!("Worker-manager unable to requeue jobs: err {:?}", err);
+                }
+                next_requeue = now + Duration::from_millis(REQUEUE_INTERVAL_MS);
+            }
+
             for target in PackageTarget::targets() {
                 if self.build_targets.contains(target) {
                     if let Err(err) = self.process_metrics(*target) {
@@ -299,9 +380,38 @@ impl WorkerMgr {
                     }
                 }
             }
+
+            self.publish_workers();
         }
     }
 
+    fn publish_workers(&self) {
+        let workers = self.workers.values().map(Worker::info).collect();
+        *self.registry.write().unwrap() = workers;
+    }
+
+    fn check_worker_labels(&mut self, target: PackageTarget) -> Result<()> {
+        let target_str = target.to_string();
+        let mut unsatisfiable = Vec::new();
+
+        for labels in self.datastore.get_pending_worker_labels(&target_str)? {
+            if !self.workers
+                    .values()
+                    .any(|w| w.info().satisfies(&target_str, &labels))
+            {
+                unsatisfiable.push(format!("[{}]", labels.join(", ")));
+            }
+        }
+
+        if !unsatisfiable.is_empty() {
+            warn!("Pending {} jobs require worker labels no connected worker advertises: {}",
+                  target,
+                  unsatisfiable.join(" "));
+        }
+
+        Ok(())
+    }
+
     fn load_workers(&mut self) -> Result<()> {
         let conn = self.db.get_conn().map_err(Error::Db)?;
         let workers = BusyWorker::list(&*conn).map_err(Error::DieselError)?;
@@ -310,13 +420,54 @@ impl WorkerMgr {
             debug!("Loading busy worker: {}", worker.ident);
             let target = PackageTarget::from_str(&worker.target)?;
             let mut bw = Worker::new(&worker.ident, target);
-            bw.busy(worker.job_id as u64, self.job_timeout);
+            let lease = self.restore_lease(worker.job_id, &*conn);
+            bw.leasing = lease.is_renewable();
+            bw.busy(worker.job_id as u64, lease);
             self.workers.insert(worker.ident.to_owned(), bw);
         }
 
         Ok(())
     }
 
+    // The lease recorded with a job before a restart. Jobs of workers that don't renew leases
+    // have none and get the fixed timeout again.
+    fn restore_lease(&self, job_id: i64, conn: &PgConnection) -> Lease {
+        let now = Utc::now();
+        match jobs::Job::get(job_id, conn) {
+            Ok(jobs::Job { leased_at: Some(leased_at),
+                           lease_expires_at: Some(expires_at),
+                           .. }) => {
+                let grace = chrono::Duration::milliseconds(WORKER_TIMEOUT_MS as i64);
+                Lease::restore(&self.job_leases, leased_at, expires_at, now, grace)
+            }
+            Ok(_) => Lease::fixed(self.job_timeout, now),
+            Err(err) => {
+                warn!("Unable to read the lease of job {}, err={:?}", job_id, err);
+                Lease::fixed(self.job_timeout, now)
+            }
+        }
+    }
+
+    // The lease of a job dispatched to the worker, recorded with the job when the worker renews
+    // it
+    fn grant_lease(&self, worker: &Worker, job_id: u64) -> Lease {
+        let now = Utc::now();
+        if !worker.leasing {
+            return Lease::fixed(self.job_timeout, now);
+        }
+
+        let lease = Lease::grant(&self.job_leases, now);
+        if let Err(err) = self.datastore.set_job_lease(job_id,
+                                                       lease.leased_at,
+                                                       lease.expires_at,
+                                                       job_lease::LEASE_GRANTED)
+        {
+            warn!("Unable to record the lease of job {}, err={:?}",
+                  job_id, err);
+        }
+        lease
+    }
+
     fn save_worker(&mut self, worker: &Worker) -> Result<()> {
         debug!("Saving busy worker: {}", worker.ident);
         let conn = self.db.get_conn().map_err(Error::Db)?;
@@ -441,26 +592,34 @@ impl WorkerMgr {
     }
 
     fn process_work(&mut self, target: PackageTarget) -> Result<()> {
+        // Leave pending jobs alone until the datastore recovers rather than losing track of
+        // jobs claimed on a connection that goes away
+        if self.datastore.is_degraded() {
+            debug!("Datastore degraded, not claiming {} jobs", target);
+            return Ok(());
+        }
+
+        // Label sets for which no pending job matched during this pass
+        let mut exhausted: Vec<Vec<String>> = Vec::new();
+
         loop {
-            // Exit if we don't have any Ready workers
-            let worker_ident =
-                match self.workers
-                          .iter()
-                          .find(|t| {
-                              (t.1.target == target) && (t.1.state == jobsrv::WorkerState::Ready)
-                          }) {
-                    Some(t) => t.0.clone(),
+            // Exit if we don't have any Ready workers that could still match a job
+            let (worker_ident, labels) =
+                match next_ready_worker(self.workers.values(), target, &exhausted) {
+                    Some(w) => (w.ident.clone(), w.labels.clone()),
                     None => return Ok(()),
                 };
 
-            // Take one job from the pending list
+            // Take one job the worker's labels satisfy from the pending list
             let job_opt = self.datastore
-                              .next_pending_job(&worker_ident, &target.to_string())?;
-            if job_opt.is_none() {
-                break;
-            }
-
-            let mut job = Job::new(job_opt.unwrap()); // unwrap Ok
+                              .next_pending_job(&worker_ident, &target.to_string(), &labels)?;
+            let mut job = match job_opt {
+                Some(job) => Job::new(job),
+                None => {
+                    exhausted.push(labels);
+                    continue;
+                }
+            };
 
             self.add_integrations_to_job(&mut job);
             self.add_project_integrations_to_job(&mut job);
@@ -469,7 +628,8 @@ impl WorkerMgr {
             match self.worker_start_job(&job, &worker_ident) {
                 Ok(()) => {
                     let mut worker = self.workers.remove(&worker_ident).unwrap(); // unwrap Ok
-                    worker.busy(job.get_id(), self.job_timeout);
+                    let lease = self.grant_lease(&worker, job.get_id());
+                    worker.busy(job.get_id(), lease);
                     self.save_worker(&worker)?;
                     self.workers.insert(worker_ident, worker);
                 }
@@ -482,7 +642,6 @@ impl WorkerMgr {
                 }
             }
         }
-        Ok(())
     }
 
     fn worker_start_job(&mut self, job: &Job, worker_ident: &str) -> Result<()> {
@@ -686,6 +845,8 @@ impl WorkerMgr {
                     }
                     jobsrv::JobState::Pending
                     | jobsrv::JobState::Complete
+                    | jobsrv::JobState::CompletedFromCache
+                    | jobsrv::JobState::PostProcessVerificationFailed
                     | jobsrv::JobState::Failed
                     | jobsrv::JobState::CancelComplete
                     | jobsrv::JobState::Rejected => (),
@@ -741,6 +902,8 @@ impl WorkerMgr {
                     | jobsrv::JobState::CancelProcessing => false,
 
                     jobsrv::JobState::Complete
+                    | jobsrv::JobState::CompletedFromCache
+                    | jobsrv::JobState::PostProcessVerificationFailed
                     | jobsrv::JobState::Failed
                     | jobsrv::JobState::CancelComplete
                     | jobsrv::JobState::Rejected => true,
@@ -766,9 +929,17 @@ impl WorkerMgr {
             Some(worker) => worker,
             None => {
                 debug!("New worker detected, heartbeat: {:?}", heartbeat);
+                // A worker advertising a target this job server does not know can not be given
+                // work of any other target, it would build for the wrong platform
                 let worker_target = match PackageTarget::from_str(heartbeat.get_target()) {
                     Ok(t) => t,
-                    Err(_) => target::X86_64_LINUX,
+                    Err(err) => {
+                        warn!("Ignoring worker {} with unknown target {}, err={}",
+                              worker_ident,
+                              heartbeat.get_target(),
+                              err);
+                        return Ok(());
+                    }
                 };
 
                 if heartbeat.get_state() == jobsrv::WorkerState::Ready {
@@ -789,8 +960,36 @@ impl WorkerMgr {
             }
             (jobsrv::WorkerState::Busy, jobsrv::WorkerState::Busy) => {
                 let job_id = worker.job_id.unwrap(); // unwrap Ok
-                if worker.is_job_expired() && !worker.is_canceling() {
-                    debug!("Canceling job due to timeout: {}", job_id);
+                let now = Utc::now();
+                if let Some(ref mut lease) = worker.lease {
+                    if job_lease::check(&self.job_leases, lease, &heartbeat, job_id, now)
+                       == Check::Renewed
+                    {
+                        debug!("Renewed lease of job {} until {}", job_id, lease.expires_at);
+                        if let Err(err) = self.datastore.set_job_lease(job_id,
+                                                                       lease.leased_at,
+                                                                       lease.expires_at,
+                                                                       job_lease::LEASE_RENEWED)
+                        {
+                            warn!("Unable to record the renewed lease of job {}, err={:?}",
+                                  job_id, err);
+                        }
+                    }
+                }
+                if worker.is_job_expired(now) && !worker.is_canceling() {
+                    if worker.lease.map_or(false, |lease| lease.is_renewable()) {
+                        debug!("Canceling job due to expired lease: {}", job_id);
+                        if let Err(err) =
+                            self.datastore.add_job_event(job_id,
+                                                         job_lease::LEASE_EXPIRED,
+                                                         "the worker stopped renewing the lease")
+                        {
+                            warn!("Unable to record the expired lease of job {}, err={:?}",
+                                  job_id, err);
+                        }
+                    } else {
+                        debug!("Canceling job due to timeout: {}", job_id);
+                    }
                     self.cancel_job(job_id, &worker_ident)?;
                     worker.cancel();
                 };
@@ -811,6 +1010,11 @@ impl WorkerMgr {
             _ => worker.ready(),
         };
 
+        worker.set_labels(heartbeat.get_labels());
+        worker.leasing = job_lease::renews_leases(&heartbeat);
+        if heartbeat.has_network() {
+            worker.network = Some(heartbeat.get_network().clone());
+        }
         assert!(!worker.is_expired());
         self.workers.insert(worker_ident, worker);
         Ok(())
@@ -828,3 +1032,118 @@ impl WorkerMgr {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::config::DataStoreCfg,
+                hab_core::package::target};
+    use chrono::TimeZone;
+
+    fn worker(ident: &str, target: PackageTarget, labels: &[&str]) -> Worker {
+        let mut worker = Worker::new(ident, target);
+        worker.set_labels(&labels.iter().map(|l| l.to_string()).collect::<Vec<_>>());
+        worker
+    }
+
+    #[test]
+    fn dispatch_matches_worker_target() {
+        let mut busy = worker("arm-busy", target::AARCH64_LINUX, &[]);
+        busy.busy(1, Lease::fixed(60, Utc::now()));
+        let workers = vec![worker("x86", target::X86_64_LINUX, &[]),
+                           busy,
+                           worker("arm", target::AARCH64_LINUX, &[]),
+                           worker("kernel2", target::X86_64_LINUX_KERNEL2, &[])];
+
+        let next = |target| next_ready_worker(&workers, target, &[]).map(|w| w.ident.as_str());
+        assert_eq!(next(target::AARCH64_LINUX), Some("arm"));
+        assert_eq!(next(target::X86_64_LINUX), Some("x86"));
+        assert_eq!(next(target::X86_64_LINUX_KERNEL2), Some("kernel2"));
+        assert_eq!(next(target::X86_64_WINDOWS), None);
+    }
+
+    #[test]
+    fn dispatch_skips_exhausted_labels() {
+        let workers = vec![worker("arm", target::AARCH64_LINUX, &[]),
+                           worker("arm-gpu", target::AARCH64_LINUX, &["gpu"]),
+                           worker("x86-gpu", target::X86_64_LINUX, &["gpu"])];
+
+        let next = next_ready_worker(&workers, target::AARCH64_LINUX, &[vec![]]);
+        assert_eq!(next.map(|w| w.ident.as_str()), Some("arm-gpu"));
+        let exhausted = vec![vec![], vec!["gpu".to_string()]];
+        assert!(next_ready_worker(&workers, target::AARCH64_LINUX, &exhausted).is_none());
+    }
+
+    // Runs against the database of tests/data_store.rs, which creates it
+    fn live_config() -> Config {
+        Config { datastore: DataStoreCfg { password: Some("hab".to_string()),
+                                           database: "builder_jobsrv_test".to_string(),
+                                           ..Default::default() },
+                 ..Default::default() }
+    }
+
+    // A job of the project as the worker manager dispatched it
+    fn dispatched_job(datastore: &DataStore, project: &str) -> jobsrv::Job {
+        let mut origin_project = originsrv::OriginProject::new();
+        origin_project.set_id(1);
+        origin_project.set_owner_id(1);
+        origin_project.set_name(project.to_string());
+        origin_project.set_plan_path("plan.sh".to_string());
+        origin_project.set_vcs_type("git".to_string());
+        origin_project.set_vcs_data("https://github.com/habitat-sh/core-plans.git".to_string());
+
+        let mut job = jobsrv::Job::new();
+        job.set_owner_id(1);
+        job.set_project(origin_project);
+        job.set_target(target::X86_64_LINUX.to_string());
+        let mut job = datastore.create_job(&job).unwrap();
+        job.set_state(jobsrv::JobState::Dispatched);
+        datastore.update_job(&job).unwrap();
+        job
+    }
+
+    #[test]
+    #[ignore]
+    fn leases_are_restored_after_a_restart() {
+        let cfg = live_config();
+        let datastore = DataStore::new(&cfg.datastore);
+        datastore.setup().unwrap();
+        let db = DbPool::new(&cfg.datastore);
+
+        // The lease expired while the job server was down, its worker hasn't had the chance to
+        // renew it yet
+        let job = dispatched_job(&datastore, "leased/nginx");
+        let now = Utc::now();
+        let leased_at = Utc.timestamp(now.timestamp() - 7_200, 0);
+        let expires_at = Utc.timestamp(now.timestamp() - 60, 0);
+        datastore.set_job_lease(job.get_id(),
+                                leased_at,
+                                expires_at,
+                                job_lease::LEASE_GRANTED)
+                 .unwrap();
+        let ident = format!("leasing-worker-{}", job.get_id());
+        let conn = db.get_conn().unwrap();
+        BusyWorker::create(&NewBusyWorker { target:      &target::X86_64_LINUX.to_string(),
+                                            ident:       &ident,
+                                            job_id:      job.get_id() as i64,
+                                            quarantined: false, },
+                           &*conn).unwrap();
+
+        let mut mgr = WorkerMgr::new(&cfg, &datastore, db.clone(), &WorkerRegistry::default());
+        mgr.load_workers().unwrap();
+        BusyWorker::delete(&ident, job.get_id() as i64, &*conn).unwrap();
+
+        let worker = &mgr.workers[&ident];
+        assert_eq!(worker.job_id, Some(job.get_id()));
+        assert!(worker.leasing);
+        let lease = worker.lease.unwrap();
+        assert!(lease.is_renewable());
+        assert_eq!(lease.leased_at, leased_at);
+        let grace = chrono::Duration::milliseconds(WORKER_TIMEOUT_MS as i64);
+        assert!(lease.expires_at >= now + grace);
+        // The maximum lifetime still counts from the original lease
+        assert!(lease.expires_at
+                <= leased_at
+                   + chrono::Duration::minutes(cfg.job_leases.max_lifetime_minutes as i64));
+    }
+}
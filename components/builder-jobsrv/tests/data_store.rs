@@ -0,0 +1,384 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The jobsrv datastore against a live database. See `components/builder-db/tests/db/README.md`
+//! for running them.
+
+use std::{io::{Read,
+               Write},
+          net::{Shutdown,
+                TcpListener,
+                TcpStream},
+          sync::{atomic::{AtomicBool,
+                          AtomicUsize,
+                          Ordering},
+                 Arc},
+          thread,
+          time::{Duration,
+                 Instant,
+                 SystemTime,
+                 UNIX_EPOCH}};
+
+use chrono::Utc;
+use diesel::RunQueryDsl;
+use postgres::{Connection,
+               TlsMode};
+
+use habitat_builder_db::{config::DataStoreCfg,
+                         pool::Pool,
+                         DbPool};
+use habitat_builder_jobsrv::{data_store::DataStore,
+                             deadline,
+                             error::Error};
+use habitat_builder_protocol::jobsrv;
+
+const DATABASE: &str = "builder_jobsrv_test";
+const NO_SUCH_JOB: u64 = 4_294_967_295;
+
+fn cfg(port: u16) -> DataStoreCfg {
+    DataStoreCfg { port,
+                   password: Some("hab".to_string()),
+                   database: DATABASE.to_string(),
+                   pool_size: 1,
+                   ..Default::default() }
+}
+
+fn datastore(port: u16) -> DataStore {
+    let admin = DataStoreCfg { database: "postgres".to_string(),
+                               ..cfg(5432) };
+    let conn =
+        Connection::connect(admin.to_string(), TlsMode::None).expect("test database is running");
+    let exists = !conn.query("SELECT 1 FROM pg_database WHERE datname = $1", &[&DATABASE])
+                      .unwrap()
+                      .is_empty();
+    if !exists {
+        conn.batch_execute(&format!("CREATE DATABASE {}", DATABASE))
+            .unwrap();
+    }
+
+    let ds = DataStore::new(&cfg(port));
+    ds.setup().unwrap();
+    ds
+}
+
+fn job_get(id: u64) -> jobsrv::JobGet {
+    let mut get = jobsrv::JobGet::new();
+    get.set_id(id);
+    get
+}
+
+// A proxy in front of the test database that severs the connection carrying the next
+// statement containing `needle` once tripped, as a Postgres failover would
+struct FlakyProxy {
+    port:    u16,
+    tripped: Arc<AtomicBool>,
+    severed: Arc<AtomicUsize>,
+}
+
+impl FlakyProxy {
+    fn start(needle: &'static str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let tripped = Arc::new(AtomicBool::new(false));
+        let severed = Arc::new(AtomicUsize::new(0));
+
+        let (t, s) = (tripped.clone(), severed.clone());
+        thread::spawn(move || {
+            for client in listener.incoming() {
+                let client = client.unwrap();
+                let server = TcpStream::connect(("127.0.0.1", 5432)).unwrap();
+                let (tripped, severed) = (t.clone(), s.clone());
+                let (client_rx, server_tx) =
+                    (client.try_clone().unwrap(), server.try_clone().unwrap());
+                thread::spawn(move || pump(server, client));
+                thread::spawn(move || {
+                    let mut buf = [0u8; 8192];
+                    let mut client_rx = client_rx;
+                    let mut server_tx = server_tx;
+                    loop {
+                        let n = match client_rx.read(&mut buf) {
+                            Ok(0) | Err(_) => break,
+                            Ok(n) => n,
+                        };
+                        if contains(&buf[..n], needle.as_bytes())
+                           && tripped.swap(false, Ordering::SeqCst)
+                        {
+                            severed.fetch_add(1, Ordering::SeqCst);
+                            let _ = client_rx.shutdown(Shutdown::Both);
+                            let _ = server_tx.shutdown(Shutdown::Both);
+                            break;
+                        }
+                        if server_tx.write_all(&buf[..n]).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        FlakyProxy { port,
+                     tripped,
+                     severed }
+    }
+
+    fn trip(&self) { self.tripped.store(true, Ordering::SeqCst) }
+
+    fn severed(&self) -> usize { self.severed.load(Ordering::SeqCst) }
+}
+
+fn pump(mut from: TcpStream, mut to: TcpStream) {
+    let mut buf = [0u8; 8192];
+    loop {
+        match from.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                if to.write_all(&buf[..n]).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    let _ = to.shutdown(Shutdown::Both);
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len())
+            .any(|window| window == needle)
+}
+
+#[test]
+#[ignore]
+fn reads_are_retried_after_the_connection_drops() {
+    let proxy = FlakyProxy::start("get_job_v1");
+    let ds = datastore(proxy.port);
+
+    assert!(ds.get_job(&job_get(NO_SUCH_JOB)).unwrap().is_none());
+    assert!(!ds.is_degraded());
+
+    proxy.trip();
+    assert!(ds.get_job(&job_get(NO_SUCH_JOB)).unwrap().is_none());
+    assert_eq!(proxy.severed(), 1);
+    assert!(ds.is_degraded());
+}
+
+#[test]
+#[ignore]
+fn statements_stop_at_the_deadline() {
+    datastore(5432);
+    let pool = DbPool::new(&cfg(5432));
+
+    let started = Instant::now();
+    let result = deadline::within(Some(started + Duration::from_millis(300)), || {
+        let conn = deadline::conn(&pool)?;
+        diesel::sql_query("SELECT pg_sleep(5)").execute(&*conn)
+                                               .map_err(Error::DieselError)
+    });
+    assert!(result.is_err());
+    assert!(started.elapsed() < Duration::from_secs(2),
+            "{:?}",
+            started.elapsed());
+
+    // The pool holds the one connection, handed out again without the timeout
+    let conn = pool.get_conn().unwrap();
+    diesel::sql_query("SELECT pg_sleep(1)").execute(&*conn)
+                                           .unwrap();
+}
+
+#[test]
+#[ignore]
+fn connections_are_checked_out_without_a_statement_timeout() {
+    datastore(5432);
+    let pool = Pool::new(&cfg(5432));
+
+    pool.get()
+        .unwrap()
+        .batch_execute("SET statement_timeout = 100")
+        .unwrap();
+    pool.get()
+        .unwrap()
+        .batch_execute("SELECT pg_sleep(0.5)")
+        .unwrap();
+}
+
+// A group for `project` with an audit entry of `operation` by `requester_id`
+fn audited_group(conn: &Connection, project: &str, operation: i16, requester_id: i64) {
+    let rows = conn.query("INSERT INTO groups (group_state, project_name) VALUES ('Complete', \
+                           $1) RETURNING id",
+                          &[&project])
+                   .unwrap();
+    let group_id: i64 = rows.get(0).get("id");
+    conn.execute("SELECT add_audit_jobs_entry_v1($1, $2, 0, $3, 'tester')",
+                 &[&group_id, &operation, &requester_id])
+        .unwrap();
+}
+
+#[test]
+#[ignore]
+fn group_submitters_are_those_of_the_origin() {
+    let ds = datastore(5432);
+    let conn = Connection::connect(cfg(5432).to_string(), TlsMode::None).unwrap();
+
+    // Origins are unique to the run, the test database is kept between runs
+    let run = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let origin = format!("a_{}", run.as_nanos());
+    let lookalike = origin.replacen("_", "b", 1);
+    audited_group(&conn, &format!("{}/redis", origin), 1, 1);
+    audited_group(&conn, &format!("{}/nginx", origin), 3, 2);
+    audited_group(&conn, &format!("{}/nginx", origin), 4, 3);
+    audited_group(&conn, &format!("{}/nginx", origin), 2, 4);
+    audited_group(&conn, &format!("{}/redis", lookalike), 1, 5);
+    audited_group(&conn, &format!("{}x/redis", origin), 1, 6);
+
+    let mut get = jobsrv::JobGroupSubmittersGet::new();
+    get.set_origin(origin);
+    let mut submitters: Vec<u64> = ds.get_job_group_submitters(&get)
+                                     .unwrap()
+                                     .get_submitters()
+                                     .iter()
+                                     .map(|s| s.get_requester_id())
+                                     .collect();
+    submitters.sort();
+    assert_eq!(submitters, vec![1, 2, 3]);
+}
+
+#[test]
+#[ignore]
+fn policy_skips_only_projects_not_started() {
+    let ds = datastore(5432);
+    let conn = Connection::connect(cfg(5432).to_string(), TlsMode::None).unwrap();
+
+    let names: Vec<String> = vec!["core/a".to_string(),
+                                  "core/b".to_string(),
+                                  "core/c".to_string()];
+    let idents: Vec<String> = names.iter()
+                                   .map(|n| format!("{}/1.0/20191021000000", n))
+                                   .collect();
+    let rows = conn.query("SELECT id FROM insert_group_v6('core/a', $1, $2, 'x86_64-linux', \
+                           false, 0, 'fail_fast')",
+                          &[&names, &idents])
+                   .unwrap();
+    let group_id: i64 = rows.get(0).get("id");
+    conn.execute("UPDATE group_projects SET project_state = 'InProgress' WHERE owner_id = $1 AND \
+                  project_name = 'core/b'",
+                 &[&group_id])
+        .unwrap();
+
+    ds.skip_job_group_projects(group_id as u64, &names).unwrap();
+
+    let mut get = jobsrv::JobGroupGet::new();
+    get.set_group_id(group_id as u64);
+    get.set_include_projects(true);
+    let group = ds.get_job_group(&get).unwrap().unwrap();
+    let mut states: Vec<(String, jobsrv::JobGroupProjectState)> =
+        group.get_projects()
+             .iter()
+             .map(|p| (p.get_name().to_string(), p.get_state()))
+             .collect();
+    states.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(states,
+               vec![("core/a".to_string(), jobsrv::JobGroupProjectState::SkippedGroupPolicy),
+                    ("core/b".to_string(), jobsrv::JobGroupProjectState::InProgress),
+                    ("core/c".to_string(), jobsrv::JobGroupProjectState::SkippedGroupPolicy),]);
+}
+
+// A group of `project` and one project of its own, awaiting approval
+fn pending_group(conn: &Connection, project: &str) -> u64 {
+    let names = vec![project.to_string()];
+    let idents = vec![format!("{}/1.0/20191021000000", project)];
+    let rows = conn.query("SELECT id FROM insert_group_v7($1, $2, $3, 'x86_64-linux', false, 0, \
+                           'fail_fast', 'PendingApproval', NULL)",
+                          &[&project, &names, &idents])
+                   .unwrap();
+    let group_id: i64 = rows.get(0).get("id");
+    group_id as u64
+}
+
+fn group_get(ds: &DataStore, group_id: u64) -> jobsrv::JobGroup {
+    let mut get = jobsrv::JobGroupGet::new();
+    get.set_group_id(group_id);
+    get.set_include_projects(true);
+    ds.get_job_group(&get).unwrap().unwrap()
+}
+
+#[test]
+#[ignore]
+fn groups_awaiting_approval_are_queued_deferred_or_canceled_once() {
+    let ds = datastore(5432);
+    let conn = Connection::connect(cfg(5432).to_string(), TlsMode::None).unwrap();
+
+    let approved = pending_group(&conn, "core/approved");
+    let group = ds.review_job_group(approved, true, None).unwrap().unwrap();
+    assert_eq!(group.get_state(), jobsrv::JobGroupState::GroupQueued);
+    // Reviewed groups no longer await approval
+    assert!(ds.review_job_group(approved, false, None)
+              .unwrap()
+              .is_none());
+    assert_eq!(group_get(&ds, approved).get_state(),
+               jobsrv::JobGroupState::GroupQueued);
+
+    let frozen = pending_group(&conn, "core/frozen");
+    let until = Utc::now() + chrono::Duration::hours(6);
+    let group = ds.review_job_group(frozen, true, Some(until))
+                  .unwrap()
+                  .unwrap();
+    assert_eq!(group.get_state(),
+               jobsrv::JobGroupState::GroupDeferredByFreeze);
+    assert!(!group.get_frozen_until().is_empty());
+
+    let rejected = pending_group(&conn, "core/rejected");
+    let group = ds.review_job_group(rejected, false, Some(until))
+                  .unwrap()
+                  .unwrap();
+    assert_eq!(group.get_state(), jobsrv::JobGroupState::GroupCanceled);
+    let group = group_get(&ds, rejected);
+    assert!(group.get_frozen_until().is_empty());
+    assert!(group.get_projects()
+                 .iter()
+                 .all(|p| p.get_state() == jobsrv::JobGroupProjectState::Canceled));
+
+    assert!(ds.review_job_group(NO_SUCH_JOB, true, None)
+              .unwrap()
+              .is_none());
+}
+
+#[test]
+#[ignore]
+fn groups_left_awaiting_approval_expire() {
+    let ds = datastore(5432);
+    let conn = Connection::connect(cfg(5432).to_string(), TlsMode::None).unwrap();
+
+    let stale = pending_group(&conn, "core/stale");
+    conn.execute("UPDATE groups SET created_at = now() - interval '4 days' WHERE id = $1",
+                 &[&(stale as i64)])
+        .unwrap();
+    let fresh = pending_group(&conn, "core/fresh");
+
+    let expired = ds.expire_job_groups_pending_approval(Utc::now() - chrono::Duration::hours(72))
+                    .unwrap();
+    assert!(expired.contains(&stale));
+    assert!(!expired.contains(&fresh));
+
+    let group = group_get(&ds, stale);
+    assert_eq!(group.get_state(), jobsrv::JobGroupState::GroupCanceled);
+    assert!(group.get_projects()
+                 .iter()
+                 .all(|p| p.get_state() == jobsrv::JobGroupProjectState::Canceled));
+    assert_eq!(group_get(&ds, fresh).get_state(),
+               jobsrv::JobGroupState::GroupPendingApproval);
+
+    // Expired groups are only canceled once
+    let expired = ds.expire_job_groups_pending_approval(Utc::now() - chrono::Duration::hours(72))
+                    .unwrap();
+    assert!(!expired.contains(&stale));
+}
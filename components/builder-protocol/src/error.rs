@@ -21,6 +21,8 @@ use protobuf;
 
 #[derive(Debug)]
 pub enum ProtocolError {
+    BadJobFreezeWindowKind(String),
+    BadJobGroupFailurePolicy(String),
     BadJobGroupProjectState(String),
     BadJobGroupState(String),
     BadJobState(String),
@@ -37,6 +39,12 @@ pub type ProtocolResult<T> = result::Result<T, ProtocolError>;
 impl fmt::Display for ProtocolError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let msg = match *self {
+            ProtocolError::BadJobFreezeWindowKind(ref e) => {
+                format!("Bad Job Freeze Window Kind {}", e)
+            }
+            ProtocolError::BadJobGroupFailurePolicy(ref e) => {
+                format!("Bad Job Group Failure Policy {}", e)
+            }
             ProtocolError::BadJobGroupProjectState(ref e) => {
                 format!("Bad Job Group Project State {}", e)
             }
@@ -62,6 +70,10 @@ impl fmt::Display for ProtocolError {
 impl error::Error for ProtocolError {
     fn description(&self) -> &str {
         match *self {
+            ProtocolError::BadJobFreezeWindowKind(_) => "Job freeze window kind cannot be parsed",
+            ProtocolError::BadJobGroupFailurePolicy(_) => {
+                "Job Group failure policy cannot be parsed"
+            }
             ProtocolError::BadJobGroupProjectState(_) => "Job Group Project state cannot be parsed",
             ProtocolError::BadJobGroupState(_) => "Job Group state cannot be parsed",
             ProtocolError::BadJobState(_) => "Job state cannot be parsed",
@@ -23,13 +23,22 @@ use serde::{ser::SerializeStruct,
             Serializer};
 
 use crate::{error::ProtocolError,
-            message::originsrv::OriginPackage};
+            message::originsrv::OriginPackage,
+            release_to_rfc3339};
 
 pub use crate::message::{jobsrv::*,
                          originsrv};
 
 pub const GITHUB_PUSH_NOTIFY_ID: u64 = 23;
 
+/// Pending reason reported for group projects whose job requires worker labels that no
+/// connected worker advertises
+pub const WAITING_ON_WORKER_LABELS: &str = "WaitingOnWorkerLabels";
+
+/// Capability of workers renewing the lease of their job in their heartbeats. The job server
+/// gives workers without it the fixed job timeout.
+pub const JOB_LEASE_CAPABILITY: &str = "job-lease";
+
 impl Into<Job> for JobSpec {
     fn into(mut self) -> Job {
         let mut job = Job::new();
@@ -40,6 +49,10 @@ impl Into<Job> for JobSpec {
         if self.has_channel() {
             job.set_channel(self.take_channel());
         }
+        job.set_no_cache(self.get_no_cache());
+        if self.has_vcs_ref() {
+            job.set_vcs_ref(self.take_vcs_ref());
+        }
         job
     }
 }
@@ -48,7 +61,7 @@ impl Serialize for Job {
     fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
         where S: Serializer
     {
-        let mut strukt = serializer.serialize_struct("job", 10)?;
+        let mut strukt = serializer.serialize_struct("job", 11)?;
 
         // Technically, an ID is a 64-bit integer, but that can cause
         // issues when processing it in JavaScript on the front-end,
@@ -78,6 +91,10 @@ impl Serialize for Job {
             let ident = self.get_package_ident();
             strukt.serialize_field("version", ident.get_version())?;
             strukt.serialize_field("release", ident.get_release())?;
+            // `release` is kept as-is for the hab client, `release_at` is the RFC 3339 form
+            if let Some(release_at) = release_to_rfc3339(ident.get_release()) {
+                strukt.serialize_field("release_at", &release_at)?;
+            }
         }
 
         if self.has_build_started_at() {
@@ -105,6 +122,22 @@ impl Serialize for Job {
             strukt.serialize_field("target", self.get_target())?;
         }
 
+        if self.has_peak_workspace_bytes() {
+            strukt.serialize_field("peak_workspace_bytes", &self.get_peak_workspace_bytes())?;
+        }
+        if self.has_artifact_bytes() {
+            strukt.serialize_field("artifact_bytes", &self.get_artifact_bytes())?;
+        }
+        if self.has_failure_excerpt() {
+            strukt.serialize_field("failure_excerpt", self.get_failure_excerpt())?;
+        }
+        if self.has_progress() {
+            strukt.serialize_field("progress", self.get_progress())?;
+        }
+        if self.has_vcs_ref() {
+            strukt.serialize_field("vcs_ref", self.get_vcs_ref())?;
+        }
+
         strukt.end()
     }
 }
@@ -130,6 +163,23 @@ impl JobLog {
     }
 }
 
+impl Serialize for JobProgress {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut strukt = serializer.serialize_struct("job_progress", 4)?;
+        strukt.serialize_field("phase", self.get_phase())?;
+        if self.has_step() {
+            strukt.serialize_field("step", self.get_step())?;
+        }
+        strukt.serialize_field("phase_elapsed_secs", &self.get_phase_elapsed_secs())?;
+        if self.has_updated_at() {
+            strukt.serialize_field("updated_at", self.get_updated_at())?;
+        }
+        strukt.end()
+    }
+}
+
 impl Serialize for JobLog {
     fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
         where S: Serializer
@@ -157,6 +207,8 @@ impl Serialize for JobState {
             6 => serializer.serialize_str("CancelPending"),
             7 => serializer.serialize_str("CancelProcessing"),
             8 => serializer.serialize_str("CancelComplete"),
+            9 => serializer.serialize_str("CompletedFromCache"),
+            10 => serializer.serialize_str("PostProcessVerificationFailed"),
             _ => panic!("Unexpected enum value"),
         }
     }
@@ -176,6 +228,8 @@ impl FromStr for JobState {
             "cancelpending" => Ok(JobState::CancelPending),
             "cancelprocessing" => Ok(JobState::CancelProcessing),
             "cancelcomplete" => Ok(JobState::CancelComplete),
+            "completedfromcache" => Ok(JobState::CompletedFromCache),
+            "postprocessverificationfailed" => Ok(JobState::PostProcessVerificationFailed),
             _ => Err(ProtocolError::BadJobState(value.to_string())),
         }
     }
@@ -193,6 +247,8 @@ impl fmt::Display for JobState {
             JobState::CancelPending => "CancelPending",
             JobState::CancelProcessing => "CancelProcessing",
             JobState::CancelComplete => "CancelComplete",
+            JobState::CompletedFromCache => "CompletedFromCache",
+            JobState::PostProcessVerificationFailed => "PostProcessVerificationFailed",
         };
         write!(f, "{}", value)
     }
@@ -231,6 +287,11 @@ impl fmt::Display for JobGroupOperation {
         let value = match *self {
             JobGroupOperation::JobGroupOpCreate => "JobGroupCreate",
             JobGroupOperation::JobGroupOpCancel => "JobGroupCancel",
+            JobGroupOperation::JobGroupOpUpdate => "JobGroupUpdate",
+            JobGroupOperation::JobGroupOpFreezeOverride => "JobGroupFreezeOverride",
+            JobGroupOperation::JobGroupOpApprove => "JobGroupApprove",
+            JobGroupOperation::JobGroupOpReject => "JobGroupReject",
+            JobGroupOperation::JobGroupOpExpire => "JobGroupExpire",
         };
         write!(f, "{}", value)
     }
@@ -243,6 +304,11 @@ impl FromStr for JobGroupOperation {
         match value.to_lowercase().as_ref() {
             "jobgroupcreate" => Ok(JobGroupOperation::JobGroupOpCreate),
             "jobgroupcancel" => Ok(JobGroupOperation::JobGroupOpCancel),
+            "jobgroupupdate" => Ok(JobGroupOperation::JobGroupOpUpdate),
+            "jobgroupfreezeoverride" => Ok(JobGroupOperation::JobGroupOpFreezeOverride),
+            "jobgroupapprove" => Ok(JobGroupOperation::JobGroupOpApprove),
+            "jobgroupreject" => Ok(JobGroupOperation::JobGroupOpReject),
+            "jobgroupexpire" => Ok(JobGroupOperation::JobGroupOpExpire),
             _ => Err(ProtocolError::BadJobGroupState(value.to_string())),
         }
     }
@@ -282,6 +348,8 @@ impl fmt::Display for JobGroupState {
             JobGroupState::GroupFailed => "Failed",
             JobGroupState::GroupQueued => "Queued",
             JobGroupState::GroupCanceled => "Canceled",
+            JobGroupState::GroupDeferredByFreeze => "DeferredByFreeze",
+            JobGroupState::GroupPendingApproval => "PendingApproval",
         };
         write!(f, "{}", value)
     }
@@ -298,6 +366,8 @@ impl FromStr for JobGroupState {
             "failed" => Ok(JobGroupState::GroupFailed),
             "queued" => Ok(JobGroupState::GroupQueued),
             "canceled" => Ok(JobGroupState::GroupCanceled),
+            "deferredbyfreeze" => Ok(JobGroupState::GroupDeferredByFreeze),
+            "pendingapproval" => Ok(JobGroupState::GroupPendingApproval),
             _ => Err(ProtocolError::BadJobGroupState(value.to_string())),
         }
     }
@@ -314,6 +384,8 @@ impl Serialize for JobGroupState {
             3 => serializer.serialize_str("Failed"),
             4 => serializer.serialize_str("Queued"),
             5 => serializer.serialize_str("Canceled"),
+            6 => serializer.serialize_str("DeferredByFreeze"),
+            7 => serializer.serialize_str("PendingApproval"),
             _ => panic!("Unexpected enum value"),
         }
     }
@@ -328,6 +400,8 @@ impl fmt::Display for JobGroupProjectState {
             JobGroupProjectState::Failure => "Failure",
             JobGroupProjectState::Skipped => "Skipped",
             JobGroupProjectState::Canceled => "Canceled",
+            JobGroupProjectState::Cached => "Cached",
+            JobGroupProjectState::SkippedGroupPolicy => "SkippedGroupPolicy",
         };
         write!(f, "{}", value)
     }
@@ -344,6 +418,8 @@ impl FromStr for JobGroupProjectState {
             "failure" => Ok(JobGroupProjectState::Failure),
             "skipped" => Ok(JobGroupProjectState::Skipped),
             "canceled" => Ok(JobGroupProjectState::Canceled),
+            "cached" => Ok(JobGroupProjectState::Cached),
+            "skippedgrouppolicy" => Ok(JobGroupProjectState::SkippedGroupPolicy),
             _ => Err(ProtocolError::BadJobGroupProjectState(value.to_string())),
         }
     }
@@ -360,11 +436,45 @@ impl Serialize for JobGroupProjectState {
             3 => serializer.serialize_str("Failure"),
             4 => serializer.serialize_str("Skipped"),
             5 => serializer.serialize_str("Canceled"),
+            6 => serializer.serialize_str("Cached"),
+            7 => serializer.serialize_str("SkippedGroupPolicy"),
             _ => panic!("Unexpected enum value"),
         }
     }
 }
 
+impl fmt::Display for JobGroupFailurePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = match *self {
+            JobGroupFailurePolicy::ContinueOnFailure => "continue",
+            JobGroupFailurePolicy::FailFast => "fail_fast",
+            JobGroupFailurePolicy::FailFastSameWave => "fail_fast_same_wave",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+impl FromStr for JobGroupFailurePolicy {
+    type Err = ProtocolError;
+
+    fn from_str(value: &str) -> result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_ref() {
+            "continue" => Ok(JobGroupFailurePolicy::ContinueOnFailure),
+            "fail_fast" => Ok(JobGroupFailurePolicy::FailFast),
+            "fail_fast_same_wave" => Ok(JobGroupFailurePolicy::FailFastSameWave),
+            _ => Err(ProtocolError::BadJobGroupFailurePolicy(value.to_string())),
+        }
+    }
+}
+
+impl Serialize for JobGroupFailurePolicy {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
 impl Serialize for JobGroupProject {
     fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
         where S: Serializer
@@ -375,6 +485,15 @@ impl Serialize for JobGroupProject {
         strukt.serialize_field("state", &self.get_state())?;
         strukt.serialize_field("job_id", &self.get_job_id().to_string())?;
         strukt.serialize_field("target", &self.get_target())?;
+        if self.has_pending_reason() {
+            strukt.serialize_field("pending_reason", &self.get_pending_reason())?;
+        }
+        if self.has_failure_excerpt() {
+            strukt.serialize_field("failure_excerpt", &self.get_failure_excerpt())?;
+        }
+        if self.has_progress() {
+            strukt.serialize_field("progress", self.get_progress())?;
+        }
         strukt.end()
     }
 }
@@ -390,6 +509,55 @@ impl Serialize for JobGroup {
         strukt.serialize_field("created_at", &self.get_created_at())?;
         strukt.serialize_field("project_name", &self.get_project_name())?;
         strukt.serialize_field("target", &self.get_target())?;
+        if self.get_no_cache() {
+            strukt.serialize_field("no_cache", &true)?;
+        }
+        if self.get_max_concurrency() > 0 {
+            strukt.serialize_field("max_concurrency", &self.get_max_concurrency())?;
+        }
+        if self.has_in_flight() {
+            strukt.serialize_field("in_flight", &self.get_in_flight())?;
+        }
+        strukt.serialize_field("failure_policy", &self.get_failure_policy())?;
+        if self.has_frozen_until() {
+            strukt.serialize_field("frozen_until", self.get_frozen_until())?;
+        }
+        if self.has_vcs_ref() {
+            strukt.serialize_field("vcs_ref", self.get_vcs_ref())?;
+        }
+        if !self.get_superseded_refs().is_empty() {
+            strukt.serialize_field("superseded_refs", self.get_superseded_refs())?;
+        }
+        if !self.get_deprecation_warnings().is_empty() {
+            strukt.serialize_field("deprecation_warnings", self.get_deprecation_warnings())?;
+        }
+        if !self.get_omitted().is_empty() {
+            strukt.serialize_field("omitted", self.get_omitted())?;
+        }
+        if self.has_project_count() {
+            strukt.serialize_field("project_count", &self.get_project_count())?;
+        }
+        if self.get_confirmation_required() {
+            strukt.serialize_field("confirmation_required", &true)?;
+        }
+        if self.has_trigger() {
+            strukt.serialize_field("trigger", &self.get_trigger().to_string())?;
+        }
+        strukt.end()
+    }
+}
+
+impl Serialize for JobGroupDeprecationWarning {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut strukt = serializer.serialize_struct("job_group_deprecation_warning", 4)?;
+        strukt.serialize_field("project_name", self.get_project_name())?;
+        strukt.serialize_field("dependency", self.get_dependency())?;
+        strukt.serialize_field("message", self.get_message())?;
+        if self.has_replacement() {
+            strukt.serialize_field("replacement", self.get_replacement())?;
+        }
         strukt.end()
     }
 }
@@ -431,6 +599,30 @@ impl Serialize for JobGraphPackageReverseDependenciesGrouped {
     }
 }
 
+impl Serialize for JobGraphVerifyStatus {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut strukt = serializer.serialize_struct("job_graph_verify_status", 9)?;
+        strukt.serialize_field("running", &self.get_running())?;
+        if self.has_started_at() {
+            strukt.serialize_field("started_at", self.get_started_at())?;
+        }
+        if self.has_finished_at() {
+            strukt.serialize_field("finished_at", self.get_finished_at())?;
+        }
+        strukt.serialize_field("total", &self.get_total())?;
+        strukt.serialize_field("checked", &self.get_checked())?;
+        strukt.serialize_field("discrepancies", &self.get_discrepancies())?;
+        strukt.serialize_field("repaired", &self.get_repaired())?;
+        strukt.serialize_field("unrepaired", self.get_unrepaired())?;
+        if self.has_error() {
+            strukt.serialize_field("error", self.get_error())?;
+        }
+        strukt.end()
+    }
+}
+
 impl Serialize for JobGroupOriginResponse {
     fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
         where S: Serializer
@@ -441,6 +633,155 @@ impl Serialize for JobGroupOriginResponse {
     }
 }
 
+impl Serialize for JobFeatureFlag {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut strukt = serializer.serialize_struct("job_feature_flag", 6)?;
+        strukt.serialize_field("name", self.get_name())?;
+        strukt.serialize_field("enabled", &self.get_enabled())?;
+        strukt.serialize_field("rollout_percent", &self.get_rollout_percent())?;
+        strukt.serialize_field("is_default", &self.get_is_default())?;
+        if self.has_updated_by() {
+            strukt.serialize_field("updated_by", self.get_updated_by())?;
+        }
+        if self.has_updated_at() {
+            strukt.serialize_field("updated_at", self.get_updated_at())?;
+        }
+        strukt.end()
+    }
+}
+
+impl Serialize for JobFeatureFlagList {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut strukt = serializer.serialize_struct("job_feature_flag_list", 1)?;
+        strukt.serialize_field("flags", self.get_flags())?;
+        strukt.end()
+    }
+}
+
+impl fmt::Display for JobFreezeWindowKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = match *self {
+            JobFreezeWindowKind::Weekly => "weekly",
+            JobFreezeWindowKind::OneOff => "once",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+impl FromStr for JobFreezeWindowKind {
+    type Err = ProtocolError;
+
+    fn from_str(value: &str) -> result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_ref() {
+            "weekly" => Ok(JobFreezeWindowKind::Weekly),
+            "once" => Ok(JobFreezeWindowKind::OneOff),
+            _ => Err(ProtocolError::BadJobFreezeWindowKind(value.to_string())),
+        }
+    }
+}
+
+impl Serialize for JobFreezeWindow {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut strukt = serializer.serialize_struct("job_freeze_window", 6)?;
+        strukt.serialize_field("kind", &self.get_kind().to_string())?;
+        strukt.serialize_field("timezone", self.get_timezone())?;
+        match self.get_kind() {
+            JobFreezeWindowKind::Weekly => {
+                strukt.serialize_field("start_day", self.get_start_day())?;
+                strukt.serialize_field("start_time", self.get_start_time())?;
+                strukt.serialize_field("end_day", self.get_end_day())?;
+                strukt.serialize_field("end_time", self.get_end_time())?;
+            }
+            JobFreezeWindowKind::OneOff => {
+                strukt.serialize_field("starts_at", self.get_starts_at())?;
+                strukt.serialize_field("ends_at", self.get_ends_at())?;
+            }
+        }
+        strukt.end()
+    }
+}
+
+impl Serialize for JobFreezeWindows {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut strukt = serializer.serialize_struct("job_freeze_windows", 5)?;
+        strukt.serialize_field("origin", self.get_origin())?;
+        strukt.serialize_field("windows", self.get_windows())?;
+        if self.has_frozen_until() {
+            strukt.serialize_field("frozen_until", self.get_frozen_until())?;
+        }
+        if self.has_updated_by() {
+            strukt.serialize_field("updated_by", self.get_updated_by())?;
+        }
+        if self.has_updated_at() {
+            strukt.serialize_field("updated_at", self.get_updated_at())?;
+        }
+        strukt.end()
+    }
+}
+
+impl WorkerInfo {
+    /// Whether this worker can run jobs for the given target that require all of `labels`
+    pub fn satisfies(&self, target: &str, labels: &[String]) -> bool {
+        self.get_target() == target && labels.iter().all(|l| self.get_labels().contains(l))
+    }
+}
+
+impl Serialize for WorkerInfo {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut strukt = serializer.serialize_struct("worker_info", 6)?;
+        strukt.serialize_field("ident", &self.get_ident())?;
+        strukt.serialize_field("target", &self.get_target())?;
+        strukt.serialize_field("state", &format!("{:?}", self.get_state()))?;
+        if self.has_job_id() {
+            strukt.serialize_field("job_id", &self.get_job_id().to_string())?;
+        }
+        strukt.serialize_field("labels", &self.get_labels())?;
+        if self.has_network() {
+            strukt.serialize_field("network", self.get_network())?;
+        }
+        strukt.end()
+    }
+}
+
+impl Serialize for WorkerNetwork {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut strukt = serializer.serialize_struct("worker_network", 4)?;
+        if self.has_ca_bundle() {
+            strukt.serialize_field("ca_bundle", self.get_ca_bundle())?;
+        }
+        if self.has_http_proxy() {
+            strukt.serialize_field("http_proxy", self.get_http_proxy())?;
+        }
+        if self.has_https_proxy() {
+            strukt.serialize_field("https_proxy", self.get_https_proxy())?;
+        }
+        strukt.serialize_field("no_proxy", self.get_no_proxy())?;
+        strukt.end()
+    }
+}
+
+impl Serialize for WorkerListResponse {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut strukt = serializer.serialize_struct("worker_list_response", 1)?;
+        strukt.serialize_field("workers", &self.get_workers())?;
+        strukt.end()
+    }
+}
+
 impl fmt::Display for Os {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let value = match *self {
@@ -498,4 +839,190 @@ mod tests {
                             "↓ Downloading core/hab-backline/0.23.0/20170511220008",];
         assert_eq!(stripped_lines, expected);
     }
+
+    #[test]
+    fn job_serializes_legacy_and_rfc3339_release() {
+        let mut ident = originsrv::OriginPackageIdent::new();
+        ident.set_origin("core".to_string());
+        ident.set_name("redis".to_string());
+        ident.set_version("4.0.14".to_string());
+        ident.set_release("20190701123456".to_string());
+
+        let mut job = Job::new();
+        job.set_id(1);
+        job.set_created_at("2019-07-01T12:30:00.000000Z".to_string());
+        job.set_package_ident(ident);
+
+        let json = serde_json::to_value(&job).unwrap();
+        assert_eq!(json["release"], "20190701123456");
+        assert_eq!(json["release_at"], "2019-07-01T12:34:56.000000Z");
+        assert_eq!(json["created_at"], "2019-07-01T12:30:00.000000Z");
+    }
+
+    #[test]
+    fn job_serializes_progress_when_present() {
+        let mut job = Job::new();
+        job.set_id(1);
+        job.set_state(JobState::Processing);
+        let json = serde_json::to_value(&job).unwrap();
+        assert!(json.get("progress").is_none());
+
+        let mut progress = JobProgress::new();
+        progress.set_phase("build_package".to_string());
+        progress.set_step("Building".to_string());
+        progress.set_phase_elapsed_secs(312);
+        progress.set_updated_at("2019-10-14T09:30:00.000000Z".to_string());
+        job.set_progress(progress);
+
+        let json = serde_json::to_value(&job).unwrap();
+        let progress = &json["progress"];
+        assert_eq!(progress["phase"], "build_package");
+        assert_eq!(progress["step"], "Building");
+        assert_eq!(progress["phase_elapsed_secs"], 312);
+        assert_eq!(progress["updated_at"], "2019-10-14T09:30:00.000000Z");
+    }
+
+    #[test]
+    fn failure_policy_round_trips() {
+        for policy in &[JobGroupFailurePolicy::ContinueOnFailure,
+                        JobGroupFailurePolicy::FailFast,
+                        JobGroupFailurePolicy::FailFastSameWave]
+        {
+            assert_eq!(policy.to_string().parse::<JobGroupFailurePolicy>().unwrap(),
+                       *policy);
+        }
+        assert!("fail-fast".parse::<JobGroupFailurePolicy>().is_err());
+
+        let mut group = JobGroup::new();
+        group.set_failure_policy(JobGroupFailurePolicy::FailFastSameWave);
+        let json = serde_json::to_value(&group).unwrap();
+        assert_eq!(json["failure_policy"], "fail_fast_same_wave");
+    }
+
+    #[test]
+    fn unpromoted_jobs_round_trip() {
+        let state = JobState::PostProcessVerificationFailed;
+        assert_eq!(state.to_string().parse::<JobState>().unwrap(), state);
+        assert_eq!(serde_json::to_value(&state).unwrap(),
+                   "PostProcessVerificationFailed");
+    }
+
+    #[test]
+    fn deferred_groups_serialize_their_release_time() {
+        let state = JobGroupState::GroupDeferredByFreeze;
+        assert_eq!(state.to_string().parse::<JobGroupState>().unwrap(), state);
+
+        let mut group = JobGroup::new();
+        group.set_state(state);
+        group.set_frozen_until("2019-10-28T05:00:00Z".to_string());
+        let json = serde_json::to_value(&group).unwrap();
+        assert_eq!(json["state"], "DeferredByFreeze");
+        assert_eq!(json["frozen_until"], "2019-10-28T05:00:00Z");
+
+        let json = serde_json::to_value(&JobGroup::new()).unwrap();
+        assert!(json.get("frozen_until").is_none());
+    }
+
+    #[test]
+    fn groups_awaiting_confirmation_serialize_their_size() {
+        let state = JobGroupState::GroupPendingApproval;
+        assert_eq!(state.to_string().parse::<JobGroupState>().unwrap(), state);
+        assert_eq!(serde_json::to_value(&state).unwrap(), "PendingApproval");
+
+        let mut group = JobGroup::new();
+        group.set_project_count(11_000);
+        group.set_confirmation_required(true);
+        let json = serde_json::to_value(&group).unwrap();
+        assert_eq!(json["project_count"], 11_000);
+        assert_eq!(json["confirmation_required"], true);
+
+        let json = serde_json::to_value(&JobGroup::new()).unwrap();
+        assert!(json.get("project_count").is_none());
+        assert!(json.get("confirmation_required").is_none());
+
+        for op in &[JobGroupOperation::JobGroupOpApprove,
+                    JobGroupOperation::JobGroupOpReject,
+                    JobGroupOperation::JobGroupOpExpire]
+        {
+            assert_eq!(op.to_string().parse::<JobGroupOperation>().unwrap(), *op);
+        }
+    }
+
+    #[test]
+    fn groups_serialize_their_trigger_when_known() {
+        let mut group = JobGroup::new();
+        group.set_trigger(JobGroupTrigger::HabClient);
+        let json = serde_json::to_value(&group).unwrap();
+        assert_eq!(json["trigger"], "HabClient");
+
+        let json = serde_json::to_value(&JobGroup::new()).unwrap();
+        assert!(json.get("trigger").is_none());
+    }
+
+    #[test]
+    fn freeze_windows_serialize_the_fields_of_their_kind() {
+        let mut window = JobFreezeWindow::new();
+        window.set_kind("once".parse().unwrap());
+        window.set_timezone("Europe/Berlin".to_string());
+        window.set_starts_at("2019-12-23T00:00".to_string());
+        window.set_ends_at("2020-01-02T00:00".to_string());
+        let json = serde_json::to_value(&window).unwrap();
+        assert_eq!(json["kind"], "once");
+        assert_eq!(json["starts_at"], "2019-12-23T00:00");
+        assert_eq!(json["ends_at"], "2020-01-02T00:00");
+        assert!(json.get("start_day").is_none());
+        assert!("daily".parse::<JobFreezeWindowKind>().is_err());
+    }
+
+    #[test]
+    fn group_serializes_coalesced_refs() {
+        let mut group = JobGroup::new();
+        let json = serde_json::to_value(&group).unwrap();
+        assert!(json.get("vcs_ref").is_none());
+        assert!(json.get("superseded_refs").is_none());
+
+        group.set_vcs_ref("c3a1f00".to_string());
+        group.set_superseded_refs(RepeatedField::from_vec(vec!["a1b2c3d".to_string(),
+                                                               "b2c3d4e".to_string()]));
+        let json = serde_json::to_value(&group).unwrap();
+        assert_eq!(json["vcs_ref"], "c3a1f00");
+        assert_eq!(json["superseded_refs"],
+                   serde_json::json!(["a1b2c3d", "b2c3d4e"]));
+    }
+
+    #[test]
+    fn worker_satisfies_required_labels() {
+        let mut worker = WorkerInfo::new();
+        worker.set_target("x86_64-linux".to_string());
+        worker.set_labels(RepeatedField::from_vec(vec!["gpu".to_string(), "secure".to_string()]));
+
+        assert!(worker.satisfies("x86_64-linux", &[]));
+        assert!(worker.satisfies("x86_64-linux", &["secure".to_string()]));
+        assert!(worker.satisfies("x86_64-linux", &["gpu".to_string(), "secure".to_string()]));
+        assert!(!worker.satisfies("x86_64-linux", &["gpu".to_string(), "arm".to_string()]));
+        assert!(!worker.satisfies("x86_64-windows", &["gpu".to_string()]));
+    }
+
+    #[test]
+    fn aarch64_heartbeat_round_trips() {
+        use protobuf::{parse_from_bytes,
+                       Message};
+
+        let mut heartbeat = Heartbeat::new();
+        heartbeat.set_endpoint("worker-arm".to_string());
+        heartbeat.set_os(Os::Linux);
+        heartbeat.set_state(WorkerState::Ready);
+        heartbeat.set_target("aarch64-linux".to_string());
+
+        let bytes = heartbeat.write_to_bytes().unwrap();
+        let heartbeat = parse_from_bytes::<Heartbeat>(&bytes).unwrap();
+        assert_eq!(heartbeat.get_target(), "aarch64-linux");
+
+        let mut worker = WorkerInfo::new();
+        worker.set_target(heartbeat.get_target().to_string());
+        assert!(worker.satisfies("aarch64-linux", &[]));
+        assert!(!worker.satisfies("x86_64-linux", &[]));
+        assert_eq!(serde_json::to_value(&worker).unwrap()["target"],
+                   "aarch64-linux");
+    }
 }
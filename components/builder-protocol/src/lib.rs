@@ -20,6 +20,11 @@ extern crate lazy_static;
 
 use habitat_core as hab_core;
 
+use chrono::{DateTime,
+             NaiveDateTime,
+             SecondsFormat,
+             Utc};
+
 pub mod error;
 pub mod jobsrv;
 pub mod message;
@@ -29,3 +34,15 @@ pub mod originsrv;
 pub use crate::{error::{ProtocolError,
                         ProtocolResult},
                 message::Protocol};
+
+/// The version of the messages the api and jobsrv exchange. Bump it with any change one side
+/// cannot read from the other, so mismatched deployments are reported when they start.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Renders the timestamp embedded in a package release or key revision as RFC 3339 UTC. This
+/// mirrors `builder_core::timestamp`, which can't be used here without a dependency cycle.
+pub fn release_to_rfc3339(release: &str) -> Option<String> {
+    NaiveDateTime::parse_from_str(release, "%Y%m%d%H%M%S").ok().map(|dt| {
+        DateTime::<Utc>::from_utc(dt, Utc).to_rfc3339_opts(SecondsFormat::Micros, true)
+    })
+}
@@ -12,9 +12,10 @@ use serde::{ser::SerializeStruct,
             Serialize,
             Serializer};
 
-use crate::hab_core::{self,
-                      package::{self,
-                                Identifiable}};
+use crate::{hab_core::{self,
+                       package::{self,
+                                 Identifiable}},
+            release_to_rfc3339};
 
 pub use crate::message::originsrv::*;
 
@@ -34,10 +35,15 @@ impl Serialize for OriginKeyIdent {
     fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
         where S: Serializer
     {
-        let mut strukt = serializer.serialize_struct("origin_key", 3)?;
+        let mut strukt = serializer.serialize_struct("origin_key", 5)?;
         strukt.serialize_field("origin", self.get_origin())?;
+        // `revision` is kept as-is for the hab client, `revision_at` is the RFC 3339 form
         strukt.serialize_field("revision", self.get_revision())?;
+        if let Some(revision_at) = release_to_rfc3339(self.get_revision()) {
+            strukt.serialize_field("revision_at", &revision_at)?;
+        }
         strukt.serialize_field("location", self.get_location())?;
+        strukt.serialize_field("expired", &self.get_expired())?;
         strukt.end()
     }
 }
@@ -175,8 +181,15 @@ impl FromStr for OAuthProvider {
             "github" => Ok(OAuthProvider::GitHub),
             "gitlab" => Ok(OAuthProvider::GitLab),
             "bitbucket" => Ok(OAuthProvider::Bitbucket),
+            "bitbucket-server" => Ok(OAuthProvider::BitbucketServer),
             "okta" => Ok(OAuthProvider::Okta),
             "chef-automate" => Ok(OAuthProvider::ChefAutomate),
+            "google" => Ok(OAuthProvider::Google),
+            "oidc" => Ok(OAuthProvider::OpenIdConnect),
+            "auth0" => Ok(OAuthProvider::Auth0),
+            "keycloak" => Ok(OAuthProvider::Keycloak),
+            "cognito" => Ok(OAuthProvider::Cognito),
+            "adfs" => Ok(OAuthProvider::AdFs),
             "none" => Ok(OAuthProvider::None),
             "" => Ok(OAuthProvider::None),
             _ => Err(Error::BadOAuthProvider),
@@ -207,3 +220,21 @@ impl Serialize for Session {
         strukt.end()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origin_key_serializes_legacy_and_rfc3339_revision() {
+        let mut key = OriginKeyIdent::new();
+        key.set_origin("core".to_string());
+        key.set_revision("20190701123456".to_string());
+        key.set_location("/origins/core/keys/20190701123456".to_string());
+
+        let json = serde_json::to_value(&key).unwrap();
+        assert_eq!(json["revision"], "20190701123456");
+        assert_eq!(json["revision_at"], "2019-07-01T12:34:56.000000Z");
+        assert_eq!(json["expired"], false);
+    }
+}
@@ -66,7 +66,21 @@ export function exchangeOAuthCode(code: string, state: string) {
 
     dispatch(signingIn(true));
 
-    fetch(`${authenticateEndpoint}/${code}`).then(response => {
+    // The verifier and nonce are only good for the one exchange
+    const params = [];
+    const verifier = Browser.getCookie('oauthVerifier');
+    if (verifier) {
+      params.push(`code_verifier=${encodeURIComponent(verifier)}`);
+    }
+    const nonce = Browser.getCookie('oauthNonce');
+    if (nonce) {
+      params.push(`nonce=${encodeURIComponent(nonce)}`);
+    }
+    const qs = params.length ? `?${params.join('&')}` : '';
+    Browser.removeCookie('oauthVerifier');
+    Browser.removeCookie('oauthNonce');
+
+    fetch(`${authenticateEndpoint}/${code}${qs}`).then(response => {
       return response.json();
     })
       .then(data => {
@@ -110,6 +124,8 @@ export function loadOAuthState() {
 export function removeSession() {
   return dispatch => {
     Browser.removeCookie('oauthState');
+    Browser.removeCookie('oauthVerifier');
+    Browser.removeCookie('oauthNonce');
     Browser.removeCookie('oauthToken');
     Browser.removeCookie('bldrSessionToken');
   };
@@ -128,19 +144,74 @@ export function setOAuthState() {
 export function loadOAuthProvider() {
   return (dispatch, getState) => {
     dispatch(setOAuthState());
-    dispatch(setOAuthProvider(
-      OAuthProvider.fromConfig(
-        config.oauth_provider,
-        config.oauth_client_id,
-        config.oauth_authorize_url,
-        config.oauth_redirect_url,
-        config.oauth_signup_url,
-        getState().oauth.state
-      )
-    ));
+
+    const provider = OAuthProvider.fromConfig(
+      config.oauth_provider,
+      config.oauth_client_id,
+      config.oauth_authorize_url,
+      config.oauth_redirect_url,
+      config.oauth_signup_url,
+      getState().oauth.state
+    );
+
+    // Scopes the operator configured replace those the provider asks for by default
+    if (provider && config.oauth_scopes && config.oauth_scopes.length) {
+      provider.params = Object.assign({}, provider.params, {
+        scope: config.oauth_scopes.join(' ')
+      });
+    }
+
+    // A nonce of its own for each sign in, so that an ID token can't be replayed into another
+    if (provider && provider.useNonce && window.crypto) {
+      const nonce = Browser.getCookie('oauthNonce') || randomToken();
+      Browser.setCookie('oauthNonce', nonce);
+      provider.params = Object.assign({}, provider.params, { nonce });
+    }
+    dispatch(setOAuthProvider(provider));
+
+    // The challenge is hashed asynchronously, so the authorize URL gets it a moment later.
+    // Browsers without WebCrypto, e.g. on a plain HTTP origin, sign in without PKCE.
+    if (provider && provider.usePkce && window.crypto && window.crypto.subtle) {
+      const verifier = Browser.getCookie('oauthVerifier') || pkceVerifier();
+
+      pkceChallenge(verifier).then(challenge => {
+        Browser.setCookie('oauthVerifier', verifier);
+        provider.params = Object.assign({}, provider.params, {
+          code_challenge: challenge,
+          code_challenge_method: 'S256'
+        });
+        dispatch(setOAuthProvider(provider));
+      });
+    }
   };
 }
 
+// A PKCE code verifier, 32 random bytes as RFC 7636 section 4.1 has it
+function pkceVerifier(): string {
+  return randomToken();
+}
+
+function randomToken(): string {
+  const bytes = new Uint8Array(32);
+  window.crypto.getRandomValues(bytes);
+  return base64Url(bytes);
+}
+
+function pkceChallenge(verifier: string): Promise<string> {
+  const bytes = new Uint8Array(verifier.split('').map(c => c.charCodeAt(0)));
+
+  return window.crypto.subtle.digest('SHA-256', bytes).then(digest => {
+    return base64Url(new Uint8Array(digest));
+  });
+}
+
+function base64Url(bytes: Uint8Array): string {
+  return btoa(String.fromCharCode.apply(null, bytes))
+    .replace(/\+/g, '-')
+    .replace(/\//g, '_')
+    .replace(/=+$/, '');
+}
+
 function setOAuthProvider(payload) {
   return {
     type: SET_OAUTH_PROVIDER,
@@ -236,7 +236,11 @@ export function signingIn(payload) {
 export function signOut(redirectToSignIn: boolean, pathAfterSignIn?: string) {
   return (dispatch, getState) => {
 
-    if (getState().session.token) {
+    const token = getState().session.token;
+
+    if (token) {
+      // Revokes the provider's token too; the session is removed here whatever the answer
+      new BuilderApiClient(token).logout().catch(() => {});
       dispatch(removeSession());
       dispatch(resetAppState());
     }
@@ -859,6 +859,24 @@ export class BuilderApiClient {
     });
   }
 
+  // Signing out must not fail, so unlike the other calls this one never signs the user out
+  public logout() {
+    return new Promise((resolve, reject) => {
+      fetch(`${this.urlPrefix}/logout`, {
+        headers: this.headers,
+        method: 'POST'
+      })
+        .then(response => {
+          if (response.ok) {
+            resolve();
+          } else {
+            reject(new Error(response.statusText));
+          }
+        })
+        .catch(error => reject(error));
+    });
+  }
+
   private handleError(error, reject) {
     const store = this.store;
     const state = store.getState();
@@ -109,6 +109,7 @@ export default Record({
       redirectUrl: undefined,
       signupUrl: undefined,
       useState: undefined,
+      usePkce: undefined,
       params: undefined
     })()
   })(),
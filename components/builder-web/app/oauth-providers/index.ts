@@ -5,13 +5,24 @@ enum OAuthProviderType {
   GitHub = 'github',
   GitLab = 'gitlab',
   Bitbucket = 'bitbucket',
+  BitbucketServer = 'bitbucket-server',
   Okta = 'okta',
+  Google = 'google',
+  OpenIdConnect = 'oidc',
+  Auth0 = 'auth0',
+  Keycloak = 'keycloak',
+  Cognito = 'cognito',
+  AdFs = 'adfs',
 }
 
 export abstract class OAuthProvider {
   static providers = Object.keys(OAuthProviderType).map(key => `'${OAuthProviderType[key]}'`).join(', ');
 
   public name;
+  // Whether the authorize URL carries a PKCE challenge and the code exchange its verifier
+  public usePkce: boolean = false;
+  // Whether the authorize URL carries a nonce, which the ID token must repeat
+  public useNonce: boolean = false;
 
   constructor(
     public type: OAuthProviderType,
@@ -57,8 +68,22 @@ export abstract class OAuthProvider {
         return new GitLabProvider(clientID, authorizeUrl, redirectUrl, signupUrl, state);
       case OAuthProviderType.Bitbucket:
         return new BitbucketProvider(clientID, authorizeUrl, redirectUrl, signupUrl);
+      case OAuthProviderType.BitbucketServer:
+        return new BitbucketServerProvider(clientID, authorizeUrl, redirectUrl, signupUrl, state);
       case OAuthProviderType.Okta:
         return new OktaProvider(clientID, authorizeUrl, redirectUrl, signupUrl, state);
+      case OAuthProviderType.Google:
+        return new GoogleProvider(clientID, authorizeUrl, redirectUrl, signupUrl, state);
+      case OAuthProviderType.OpenIdConnect:
+        return new OpenIdConnectProvider(clientID, authorizeUrl, redirectUrl, signupUrl, state);
+      case OAuthProviderType.Auth0:
+        return new Auth0Provider(clientID, authorizeUrl, redirectUrl, signupUrl, state);
+      case OAuthProviderType.Keycloak:
+        return new KeycloakProvider(clientID, authorizeUrl, redirectUrl, signupUrl, state);
+      case OAuthProviderType.Cognito:
+        return new CognitoProvider(clientID, authorizeUrl, redirectUrl, signupUrl, state);
+      case OAuthProviderType.AdFs:
+        return new AdFsProvider(clientID, authorizeUrl, redirectUrl, signupUrl, state);
       case undefined:
       case '':
         console.error(`Please configure Builder with an OAuth provider. Supported providers are ${OAuthProvider.providers}.`);
@@ -71,6 +96,7 @@ export abstract class OAuthProvider {
 
 class ActiveDirectoryProvider extends OAuthProvider {
   name: string = 'Active Directory';
+  useNonce: boolean = true;
 
   constructor(clientID: string, authorizeUrl: string, redirectUrl: string, signupUrl: string, state: string) {
     super(
@@ -85,8 +111,7 @@ class ActiveDirectoryProvider extends OAuthProvider {
         redirect_uri: redirectUrl,
         response_type: 'code',
         state: state,
-        scope: 'openid',
-        nonce: 0 // Ok since we're not using id_token
+        scope: 'openid'
       }
     );
   }
@@ -94,6 +119,8 @@ class ActiveDirectoryProvider extends OAuthProvider {
 
 class AzureADProvider extends OAuthProvider {
   name: string = 'Azure AD';
+  usePkce: boolean = true;
+  useNonce: boolean = true;
 
   constructor(clientID: string, authorizeUrl: string, redirectUrl: string, signupUrl: string, state: string) {
     super(
@@ -108,8 +135,7 @@ class AzureADProvider extends OAuthProvider {
         redirect_uri: redirectUrl,
         response_type: 'code',
         state: state,
-        scope: 'openid',
-        nonce: 0 // Ok since we're not using id_token
+        scope: 'openid profile email User.Read'
       }
     );
   }
@@ -117,6 +143,7 @@ class AzureADProvider extends OAuthProvider {
 
 class ChefAutomateProvider extends OAuthProvider {
   name: string = 'Chef Automate';
+  useNonce: boolean = true;
 
   constructor(clientID: string, authorizeUrl: string, redirectUrl: string, signupUrl: string, state: string) {
     super(
@@ -131,8 +158,7 @@ class ChefAutomateProvider extends OAuthProvider {
         redirect_uri: redirectUrl,
         response_type: 'code',
         state: state,
-        scope: 'openid profile email',
-        nonce: 0
+        scope: 'openid profile email'
       }
     );
   }
@@ -161,6 +187,7 @@ class GitHubProvider extends OAuthProvider {
 
 class GitLabProvider extends OAuthProvider {
   name: string = 'GitLab';
+  usePkce: boolean = true;
 
   constructor(clientID: string, authorizeUrl: string, redirectUrl: string, signupUrl: string, state: string) {
     super(
@@ -200,8 +227,32 @@ class BitbucketProvider extends OAuthProvider {
   }
 }
 
+class BitbucketServerProvider extends OAuthProvider {
+  name: string = 'Bitbucket Server';
+
+  constructor(clientID: string, authorizeUrl: string, redirectUrl: string, signupUrl: string, state: string) {
+    super(
+      OAuthProviderType.BitbucketServer,
+      clientID,
+      authorizeUrl,
+      redirectUrl,
+      signupUrl,
+      true,
+      {
+        client_id: clientID,
+        redirect_uri: redirectUrl,
+        response_type: 'code',
+        state: state,
+        scope: 'PUBLIC_REPOS'
+      }
+    );
+  }
+}
+
 class OktaProvider extends OAuthProvider {
   name: string = 'Okta';
+  usePkce: boolean = true;
+  useNonce: boolean = true;
 
   constructor(clientID: string, authorizeUrl: string, redirectUrl: string, signupUrl: string, state: string) {
     super(
@@ -216,8 +267,145 @@ class OktaProvider extends OAuthProvider {
         redirect_uri: redirectUrl,
         response_type: 'code',
         state: state,
-        scope: 'openid profile email',
-        nonce: 0
+        scope: 'openid profile email'
+      }
+    );
+  }
+}
+
+class GoogleProvider extends OAuthProvider {
+  name: string = 'Google';
+  usePkce: boolean = true;
+
+  constructor(clientID: string, authorizeUrl: string, redirectUrl: string, signupUrl: string, state: string) {
+    super(
+      OAuthProviderType.Google,
+      clientID,
+      authorizeUrl,
+      redirectUrl,
+      signupUrl,
+      true,
+      {
+        client_id: clientID,
+        redirect_uri: redirectUrl,
+        response_type: 'code',
+        state: state,
+        scope: 'openid email'
+      }
+    );
+  }
+}
+
+class OpenIdConnectProvider extends OAuthProvider {
+  name: string = 'OpenID Connect';
+  usePkce: boolean = true;
+  useNonce: boolean = true;
+
+  constructor(clientID: string, authorizeUrl: string, redirectUrl: string, signupUrl: string, state: string) {
+    super(
+      OAuthProviderType.OpenIdConnect,
+      clientID,
+      authorizeUrl,
+      redirectUrl,
+      signupUrl,
+      true,
+      {
+        client_id: clientID,
+        redirect_uri: redirectUrl,
+        response_type: 'code',
+        state: state,
+        scope: 'openid profile email'
+      }
+    );
+  }
+}
+
+class Auth0Provider extends OAuthProvider {
+  name: string = 'Auth0';
+  usePkce: boolean = true;
+
+  constructor(clientID: string, authorizeUrl: string, redirectUrl: string, signupUrl: string, state: string) {
+    super(
+      OAuthProviderType.Auth0,
+      clientID,
+      authorizeUrl,
+      redirectUrl,
+      signupUrl,
+      true,
+      {
+        client_id: clientID,
+        redirect_uri: redirectUrl,
+        response_type: 'code',
+        state: state,
+        scope: 'openid profile email'
+      }
+    );
+  }
+}
+
+class KeycloakProvider extends OAuthProvider {
+  name: string = 'Keycloak';
+  usePkce: boolean = true;
+
+  constructor(clientID: string, authorizeUrl: string, redirectUrl: string, signupUrl: string, state: string) {
+    super(
+      OAuthProviderType.Keycloak,
+      clientID,
+      authorizeUrl,
+      redirectUrl,
+      signupUrl,
+      true,
+      {
+        client_id: clientID,
+        redirect_uri: redirectUrl,
+        response_type: 'code',
+        state: state,
+        scope: 'openid profile email'
+      }
+    );
+  }
+}
+
+class CognitoProvider extends OAuthProvider {
+  name: string = 'Cognito';
+  usePkce: boolean = true;
+
+  constructor(clientID: string, authorizeUrl: string, redirectUrl: string, signupUrl: string, state: string) {
+    super(
+      OAuthProviderType.Cognito,
+      clientID,
+      authorizeUrl,
+      redirectUrl,
+      signupUrl,
+      true,
+      {
+        client_id: clientID,
+        redirect_uri: redirectUrl,
+        response_type: 'code',
+        state: state,
+        scope: 'openid email profile'
+      }
+    );
+  }
+}
+
+class AdFsProvider extends OAuthProvider {
+  name: string = 'AD FS';
+
+  constructor(clientID: string, authorizeUrl: string, redirectUrl: string, signupUrl: string, state: string) {
+    super(
+      OAuthProviderType.AdFs,
+      clientID,
+      authorizeUrl,
+      redirectUrl,
+      signupUrl,
+      true,
+      {
+        client_id: clientID,
+        redirect_uri: redirectUrl,
+        response_type: 'code',
+        state: state,
+        scope: 'openid'
       }
     );
   }
@@ -32,6 +32,7 @@ export default function oauth(state = initialState['oauth'], action) {
         .setIn(['provider', 'redirectUrl'], action.payload.redirectUrl)
         .setIn(['provider', 'signupUrl'], action.payload.signupUrl)
         .setIn(['provider', 'useState'], action.payload.useState)
+        .setIn(['provider', 'usePkce'], action.payload.usePkce)
         .setIn(['provider', 'params'], action.payload.params);
 
     case actionTypes.SET_OAUTH_STATE:
@@ -173,10 +173,12 @@ export function iconForJobState(state) {
     inprogress: 'loading',
     notstarted: 'pending',
     pending: 'pending',
+    postprocessverificationfailed: 'alert',
     processing: 'loading',
     queued: 'pending',
     rejected: 'alert',
-    skipped: 'no'
+    skipped: 'no',
+    skippedgrouppolicy: 'no'
   }[state.toLowerCase()];
 }
 
@@ -189,6 +191,7 @@ export function labelForJobState(state) {
     cancelcomplete: 'Canceled',
     complete: 'Complete',
     success: 'Complete',
+    deferredbyfreeze: 'Deferred by Freeze',
     dispatching: 'Dispatching',
     dispatched: 'Dispatched',
     failed: 'Failed',
@@ -196,9 +199,11 @@ export function labelForJobState(state) {
     inprogress: 'In Progress',
     notstarted: 'Not Started',
     pending: 'Pending',
+    postprocessverificationfailed: 'Not Promoted',
     processing: 'Processing',
     queued: 'Queued',
     rejected: 'Rejected',
-    skipped: 'Skipped'
+    skipped: 'Skipped',
+    skippedgrouppolicy: 'Skipped (Group Policy)'
   }[state.toLowerCase()];
 }
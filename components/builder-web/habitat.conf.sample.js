@@ -42,6 +42,9 @@ habitatConfig({
     oauth_provider: "github",
     oauth_redirect_url: "http://localhost:3000/",
     oauth_signup_url: "https://github.com/join",
+    // Scopes asked for in place of the provider's own, e.g. ["read_user"] for GitLab. The
+    // builder-api-proxy fills them in from the builder-api [oauth] scopes.
+    oauth_scopes: [],
 
     // oauth_authorize_url: "https://bitbucket.org/site/oauth2/authorize",
     // oauth_client_id: "5U6LKcQf4DvHMRFBeS",
@@ -38,17 +38,25 @@ pub enum Error {
     BuildEnvFile(PathBuf, io::Error),
     BuildFailure(i32),
     BuilderCore(bldr_core::Error),
+    BuildCacheKey(PathBuf, io::Error),
     CannotAddCreds,
     Chown(PathBuf, u32, u32, io::Error),
     ChownWait(io::Error),
     CreateDirectory(PathBuf, io::Error),
+    DependencyNotFound(String, String),
+    DependencyVerificationFailed(String, String),
     Exporter(io::Error),
     ExportFailure(i32),
     Git(git2::Error),
     GithubAppAuthErr(github_api_client::HubError),
     HabitatCore(hab_core::Error),
     InvalidIntegrations(String),
+    MissingDependencies(PathBuf, Vec<String>),
     NotHTTPSCloneUrl(url::Url),
+    OfflineIO(PathBuf, io::Error),
+    OfflineKeyMissing(String, PathBuf),
+    PlanRead(PathBuf, io::Error),
+    PromotionVerificationFailed(String, String, String),
     Protobuf(protobuf::ProtobufError),
     Protocol(protocol::ProtocolError),
     Retry(retry::Error<builder_core::error::Error>),
@@ -56,7 +64,11 @@ pub enum Error {
     StreamTargetSend(zmq::Error),
     StudioBuild(PathBuf, io::Error),
     StudioTeardown(PathBuf, io::Error),
+    TargetArchMismatch(hab_core::package::PackageTarget),
+    UnknownVCS(String),
     UrlParseError(url::ParseError),
+    VcsCredentialMissing(String),
+    VcsKeyFile(PathBuf, io::Error),
     WorkspaceSetup(String, io::Error),
     WorkspaceTeardown(String, io::Error),
     Zmq(zmq::Error),
@@ -77,6 +89,11 @@ impl fmt::Display for Error {
                 format!("Build studio exited with non-zero exit code, {}", e)
             }
             Error::BuilderCore(ref e) => format!("{}", e),
+            Error::BuildCacheKey(ref p, ref e) => {
+                format!("Unable to read plan content for build cache key at {}, err={}",
+                        p.display(),
+                        e)
+            }
             Error::CannotAddCreds => "Cannot add credentials to url".to_string(),
             Error::Chown(ref p, ref u, ref g, ref e) => {
                 format!("Unable to recursively chown path, {} with '{}:{}', {}",
@@ -89,6 +106,13 @@ impl fmt::Display for Error {
             Error::CreateDirectory(ref p, ref e) => {
                 format!("Unable to create directory {}, err={}", p.display(), e)
             }
+            Error::DependencyNotFound(ref i, ref c) => {
+                format!("Dependency {} was not found in the {} or stable channels",
+                        i, c)
+            }
+            Error::DependencyVerificationFailed(ref i, ref e) => {
+                format!("Dependency verification failed for {}, {}", i, e)
+            }
             Error::Exporter(ref e) => {
                 format!("Unable to spawn or pipe data from exporter proc, {}", e)
             }
@@ -99,10 +123,31 @@ impl fmt::Display for Error {
             Error::GithubAppAuthErr(ref e) => format!("{}", e),
             Error::HabitatCore(ref e) => format!("{}", e),
             Error::InvalidIntegrations(ref s) => format!("Invalid integration: {}", s),
+            Error::MissingDependencies(ref p, ref m) => {
+                format!("Missing {} dependencies from the artifact cache {}:\n    {}",
+                        m.len(),
+                        p.display(),
+                        m.join("\n    "))
+            }
             Error::NotHTTPSCloneUrl(ref e) => {
                 format!("Attempted to clone {}. Only HTTPS clone urls are supported",
                         e)
             }
+            Error::OfflineIO(ref p, ref e) => {
+                format!("Unable to read or write {} in offline mode, err={}",
+                        p.display(),
+                        e)
+            }
+            Error::OfflineKeyMissing(ref o, ref p) => {
+                format!("No secret signing key of origin {} in {}", o, p.display())
+            }
+            Error::PlanRead(ref p, ref e) => {
+                format!("Unable to read plan file {}, {}", p.display(), e)
+            }
+            Error::PromotionVerificationFailed(ref i, ref k, ref e) => {
+                format!("Promotion verification failed for {} signed with {}, {}",
+                        i, k, e)
+            }
             Error::Protobuf(ref e) => format!("{}", e),
             Error::Protocol(ref e) => format!("{}", e),
             Error::Retry(ref e) => format!("{}", e),
@@ -123,7 +168,20 @@ impl fmt::Display for Error {
                         p.display(),
                         e)
             }
+            Error::TargetArchMismatch(ref t) => {
+                format!("The {} target can not be built on this host's architecture, set target \
+                         to one that can",
+                        t)
+            }
+            Error::UnknownVCS(ref t) => format!("Unknown VCS type for the project, {}", t),
             Error::UrlParseError(ref e) => format!("{}", e),
+            Error::VcsCredentialMissing(ref n) => {
+                format!("Origin secret {} holding the repository credential was not found",
+                        n)
+            }
+            Error::VcsKeyFile(ref p, ref e) => {
+                format!("Unable to write deploy key to {}, err={}", p.display(), e)
+            }
             Error::WorkspaceSetup(ref p, ref e) => {
                 format!("Error while setting up workspace at {}, err={}", p, e)
             }
@@ -144,17 +202,29 @@ impl error::Error for Error {
             Error::BuildEnvFile(..) => "Unable to read workspace build env file",
             Error::BuildFailure(_) => "Build studio exited with a non-zero exit code",
             Error::BuilderCore(ref err) => err.description(),
+            Error::BuildCacheKey(..) => "Unable to read plan content for build cache key",
             Error::CannotAddCreds => "Cannot add credentials to url",
             Error::Chown(..) => "Unable to recursively chown path",
             Error::ChownWait(_) => "Unable to complete chown process",
             Error::CreateDirectory(..) => "Unable to create directory",
+            Error::DependencyNotFound(..) => "A dependency the plan names was not found",
+            Error::DependencyVerificationFailed(..) => {
+                "Dependency artifact failed verification against its origin public key"
+            }
             Error::Exporter(_) => "IO Error while spawning or piping data from exporter proc",
             Error::ExportFailure(_) => "Docker export exited with a non-zero exit code",
             Error::Git(ref err) => err.description(),
             Error::GithubAppAuthErr(ref err) => err.description(),
             Error::HabitatCore(ref err) => err.description(),
             Error::InvalidIntegrations(_) => "Invalid integrations detected",
+            Error::MissingDependencies(..) => "Dependencies are missing from the artifact cache",
             Error::NotHTTPSCloneUrl(_) => "Only HTTPS clone urls are supported",
+            Error::OfflineIO(..) => "IO Error while reading or writing offline mode files",
+            Error::OfflineKeyMissing(..) => "No secret signing key of the origin in offline mode",
+            Error::PlanRead(..) => "Unable to read the plan file",
+            Error::PromotionVerificationFailed(..) => {
+                "Built artifact is not signed with a key its origin still accepts"
+            }
             Error::Protobuf(ref err) => err.description(),
             Error::Protocol(ref err) => err.description(),
             Error::Retry(ref err) => err.description(),
@@ -162,6 +232,12 @@ impl error::Error for Error {
             Error::StreamLine(_) => "Error while reading a line while consuming an output stream",
             Error::StudioBuild(..) => "IO Error while running studio build",
             Error::StudioTeardown(..) => "IO Error while tearing down studio",
+            Error::TargetArchMismatch(_) => "Target can not be built on this host's architecture",
+            Error::UnknownVCS(_) => "Unknown VCS type for the project",
+            Error::VcsCredentialMissing(_) => {
+                "Origin secret holding the repository credential was not found"
+            }
+            Error::VcsKeyFile(..) => "IO Error while writing deploy key",
             Error::WorkspaceSetup(..) => "IO Error while creating workspace on disk",
             Error::WorkspaceTeardown(..) => "IO Error while destroying workspace on disk",
             Error::Zmq(ref err) => err.description(),
@@ -17,9 +17,11 @@ use std::{sync::mpsc,
                    JoinHandle},
           time::Duration};
 
+use protobuf::RepeatedField;
 use zmq;
 
-use crate::{bldr_core::socket::DEFAULT_CONTEXT,
+use crate::{bldr_core::{http_client::HttpClientCfg,
+                        socket::DEFAULT_CONTEXT},
             protocol::{jobsrv as proto,
                        message}};
 
@@ -44,6 +46,28 @@ fn worker_os() -> proto::Os { proto::Os::Windows }
 #[cfg(target_os = "macos")]
 fn worker_os() -> proto::Os { proto::Os::Darwin }
 
+/// The worker's outbound network settings as reported to the job server, without proxy
+/// credentials
+pub fn worker_network(cfg: &HttpClientCfg) -> proto::WorkerNetwork {
+    let cfg = cfg.redacted();
+    let mut network = proto::WorkerNetwork::new();
+    if let Some(ca_bundle) = cfg.ca_bundle {
+        network.set_ca_bundle(ca_bundle.display().to_string());
+    }
+    if let Some(http_proxy) = cfg.http_proxy {
+        network.set_http_proxy(http_proxy);
+    }
+    if let Some(https_proxy) = cfg.https_proxy {
+        network.set_https_proxy(https_proxy);
+    }
+    network.set_no_proxy(RepeatedField::from_vec(cfg.no_proxy));
+    network
+}
+
+fn capabilities() -> RepeatedField<String> {
+    RepeatedField::from_vec(vec![proto::JOB_LEASE_CAPABILITY.to_string()])
+}
+
 #[derive(PartialEq)]
 enum PulseState {
     Pause,
@@ -72,12 +96,19 @@ pub struct HeartbeatCli {
 
 impl HeartbeatCli {
     /// Create a new HeartbeatMgr client
-    pub fn new(net_ident: String, target: String) -> Self {
+    pub fn new(net_ident: String,
+               target: String,
+               labels: Vec<String>,
+               network: proto::WorkerNetwork)
+               -> Self {
         let sock = (**DEFAULT_CONTEXT).as_mut().socket(zmq::REQ).unwrap();
         let mut state = proto::Heartbeat::new();
         state.set_endpoint(net_ident);
         state.set_os(worker_os());
         state.set_target(target);
+        state.set_labels(RepeatedField::from_vec(labels));
+        state.set_network(network);
+        state.set_capabilities(capabilities());
         HeartbeatCli { msg: zmq::Message::new().unwrap(),
                        sock,
                        state }
@@ -89,9 +120,10 @@ impl HeartbeatCli {
         Ok(())
     }
 
-    /// Set the `HeartbeatMgr` state to busy
-    pub fn set_busy(&mut self) -> Result<()> {
+    /// Set the `HeartbeatMgr` state to busy building `job_id`, renewing the job's lease
+    pub fn set_busy(&mut self, job_id: u64) -> Result<()> {
         self.state.set_state(proto::WorkerState::Busy);
+        self.state.set_renew_leases(vec![job_id]);
         self.sock
             .send_str(PulseState::Pulse.as_ref(), zmq::SNDMORE)?;
         self.sock.send(&message::encode(&self.state)?, 0)?;
@@ -102,6 +134,7 @@ impl HeartbeatCli {
     /// Set the `HeartbeatMgr` state to ready
     pub fn set_ready(&mut self) -> Result<()> {
         self.state.set_state(proto::WorkerState::Ready);
+        self.state.clear_renew_leases();
         self.sock
             .send_str(PulseState::Pulse.as_ref(), zmq::SNDMORE)?;
         self.sock.send(&message::encode(&self.state)?, 0)?;
@@ -132,7 +165,10 @@ impl HeartbeatMgr {
     /// Start the HeartbeatMgr
     pub fn start(config: &Config, net_ident: String) -> Result<JoinHandle<()>> {
         let (tx, rx) = mpsc::sync_channel(0);
-        let mut heartbeat = Self::new(net_ident, config.target.to_string());
+        let mut heartbeat = Self::new(net_ident,
+                                      config.target.to_string(),
+                                      config.labels.clone(),
+                                      worker_network(&config.http_client));
         let jobsrv_addrs = config.jobsrv_addrs();
         let handle = thread::Builder::new().name("heartbeat".to_string())
                                            .spawn(move || {
@@ -145,7 +181,11 @@ impl HeartbeatMgr {
         }
     }
 
-    fn new(net_ident: String, target: String) -> Self {
+    fn new(net_ident: String,
+           target: String,
+           labels: Vec<String>,
+           network: proto::WorkerNetwork)
+           -> Self {
         let pub_sock = (**DEFAULT_CONTEXT).as_mut().socket(zmq::PUB).unwrap();
         let cli_sock = (**DEFAULT_CONTEXT).as_mut().socket(zmq::REP).unwrap();
         pub_sock.set_immediate(true).unwrap();
@@ -156,6 +196,9 @@ impl HeartbeatMgr {
         heartbeat.set_os(worker_os());
         heartbeat.set_state(proto::WorkerState::Ready);
         heartbeat.set_target(target);
+        heartbeat.set_labels(RepeatedField::from_vec(labels));
+        heartbeat.set_network(network);
+        heartbeat.set_capabilities(capabilities());
         HeartbeatMgr { state: PulseState::default(),
                        pub_sock,
                        cli_sock,
@@ -79,4 +79,9 @@ fn exit_with(err: &Error, code: i32) {
 }
 
 /// Starts the builder-worker.
-fn start(config: Config) -> Result<()> { server::run(config) }
+fn start(config: Config) -> Result<()> {
+    // Fail now on a bad CA bundle or proxy rather than on the first job
+    config.http_client.validate()?;
+    config.validate_target()?;
+    server::run(config)
+}
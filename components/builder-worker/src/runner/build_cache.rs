@@ -0,0 +1,348 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Computes the key a build's output is recorded under in the depot build cache. Two jobs share
+//! a key only when they start from the same build root content, the same resolved dependencies
+//! and the same target, in which case the package built by the first can stand in for the
+//! second.
+
+use std::{fs,
+          path::{Path,
+                 PathBuf}};
+
+use sha2::{Digest,
+           Sha256};
+
+use crate::{error::{Error,
+                    Result},
+            hab_core::package::{PackageIdent,
+                                PackageTarget}};
+
+/// Bumped whenever the key definition changes so keys recorded by older workers stop matching
+const KEY_VERSION: &str = "v1";
+
+/// Returns the hex encoded SHA-256 digest of the build root the studio builds from.
+///
+/// Every file below `root` contributes its relative path, type (regular, executable or
+/// symlink) and content, in sorted path order. Modification times, ownership and empty
+/// directories do not. `.git` directories are skipped at any depth, as is the studio's
+/// `results` directory at the top of the build root.
+pub fn plan_digest(root: &Path) -> Result<String> {
+    let mut files = Vec::new();
+    collect_files(root, root, &mut files)?;
+    files.sort();
+
+    let mut hasher = Sha256::default();
+    for (rel_path, path) in files {
+        let metadata =
+            fs::symlink_metadata(&path).map_err(|e| Error::BuildCacheKey(path.clone(), e))?;
+        let (kind, content) = if metadata.file_type().is_symlink() {
+            let target = fs::read_link(&path).map_err(|e| Error::BuildCacheKey(path.clone(), e))?;
+            ("l", target.to_string_lossy().into_owned().into_bytes())
+        } else {
+            let content = fs::read(&path).map_err(|e| Error::BuildCacheKey(path.clone(), e))?;
+            (if is_executable(&metadata) { "x" } else { "f" }, content)
+        };
+
+        // Length prefixes keep content from bleeding across entries
+        hasher.input(rel_path.as_bytes());
+        hasher.input(&[0]);
+        hasher.input(kind.as_bytes());
+        hasher.input(&(content.len() as u64).to_be_bytes());
+        hasher.input(&content);
+    }
+
+    Ok(format!("{:x}", hasher.result()))
+}
+
+/// Combines a plan digest with the project, target and resolved dependency idents into the
+/// cache key sent to the depot. Dependencies are sorted and deduplicated, so only the set of
+/// idents matters and not the order they were resolved in.
+pub fn cache_key(project: &str,
+                 target: PackageTarget,
+                 plan_digest: &str,
+                 deps: &[PackageIdent])
+                 -> String {
+    let mut deps: Vec<String> = deps.iter().map(PackageIdent::to_string).collect();
+    deps.sort();
+    deps.dedup();
+
+    let mut hasher = Sha256::default();
+    hasher.input(format!("bldr-build-cache {}\n", KEY_VERSION));
+    hasher.input(format!("project {}\n", project));
+    hasher.input(format!("target {}\n", target));
+    hasher.input(format!("plan {}\n", plan_digest));
+    for dep in deps {
+        hasher.input(format!("dep {}\n", dep));
+    }
+
+    format!("{:x}", hasher.result())
+}
+
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<(String, PathBuf)>) -> Result<()> {
+    let entries = fs::read_dir(dir).map_err(|e| Error::BuildCacheKey(dir.to_path_buf(), e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| Error::BuildCacheKey(dir.to_path_buf(), e))?;
+        let path = entry.path();
+        let name = entry.file_name();
+
+        if name == ".git" || (dir == root && name == "results") {
+            continue;
+        }
+
+        let file_type = entry.file_type()
+                             .map_err(|e| Error::BuildCacheKey(path.clone(), e))?;
+        if file_type.is_dir() {
+            collect_files(root, &path, files)?;
+        } else {
+            files.push((relative_path(root, &path), path));
+        }
+    }
+    Ok(())
+}
+
+// Separators are normalized so Linux and Windows workers agree on keys for the same tree
+fn relative_path(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &fs::Metadata) -> bool { false }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hab_core::package::target;
+    use std::str::FromStr;
+    use tempfile::TempDir;
+
+    fn plan_tree() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        write(dir.path(),
+              "habitat/plan.sh",
+              "pkg_name=redis\npkg_origin=core\n");
+        write(dir.path(), "habitat/hooks/run", "exec redis-server\n");
+        write(dir.path(), "src/main.c", "int main() { return 0; }\n");
+        dir
+    }
+
+    fn write(root: &Path, rel_path: &str, content: &str) {
+        let path = root.join(rel_path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn digest(dir: &TempDir) -> String { plan_digest(dir.path()).unwrap() }
+
+    fn ident(s: &str) -> PackageIdent { PackageIdent::from_str(s).unwrap() }
+
+    fn deps() -> Vec<PackageIdent> {
+        vec![ident("core/glibc/2.27/20190115002733"),
+             ident("core/openssl/1.0.2r/20190305210149"),]
+    }
+
+    fn key(plan_digest: &str, deps: &[PackageIdent]) -> String {
+        cache_key("core/redis", target::X86_64_LINUX, plan_digest, deps)
+    }
+
+    #[test]
+    fn identical_trees_share_a_digest() {
+        let a = plan_tree();
+        let b = plan_tree();
+        assert_eq!(digest(&a), digest(&b));
+    }
+
+    #[test]
+    fn rewriting_identical_content_keeps_digest() {
+        let dir = plan_tree();
+        let before = digest(&dir);
+        write(dir.path(),
+              "habitat/plan.sh",
+              "pkg_name=redis\npkg_origin=core\n");
+        assert_eq!(before, digest(&dir));
+    }
+
+    #[test]
+    fn git_metadata_and_results_are_ignored() {
+        let dir = plan_tree();
+        let before = digest(&dir);
+        write(dir.path(), ".git/HEAD", "ref: refs/heads/master\n");
+        write(dir.path(),
+              "vendor/lib/.git",
+              "gitdir: ../../.git/modules/lib\n");
+        write(dir.path(), "results/last_build.env", "pkg_origin=core\n");
+        assert_eq!(before, digest(&dir));
+    }
+
+    #[test]
+    fn empty_directories_are_ignored() {
+        let dir = plan_tree();
+        let before = digest(&dir);
+        fs::create_dir_all(dir.path().join("habitat/config")).unwrap();
+        assert_eq!(before, digest(&dir));
+    }
+
+    #[test]
+    fn nested_results_directory_is_content() {
+        let dir = plan_tree();
+        let before = digest(&dir);
+        write(dir.path(), "src/results/fixture.txt", "expected\n");
+        assert_ne!(before, digest(&dir));
+    }
+
+    #[test]
+    fn changed_content_invalidates() {
+        let dir = plan_tree();
+        let before = digest(&dir);
+        write(dir.path(),
+              "habitat/plan.sh",
+              "pkg_name=redis\npkg_origin=core\n# patched\n");
+        assert_ne!(before, digest(&dir));
+    }
+
+    #[test]
+    fn changed_source_outside_plan_directory_invalidates() {
+        let dir = plan_tree();
+        let before = digest(&dir);
+        write(dir.path(), "src/main.c", "int main() { return 1; }\n");
+        assert_ne!(before, digest(&dir));
+    }
+
+    #[test]
+    fn added_file_invalidates() {
+        let dir = plan_tree();
+        let before = digest(&dir);
+        write(dir.path(), "habitat/default.toml", "");
+        assert_ne!(before, digest(&dir));
+    }
+
+    #[test]
+    fn removed_file_invalidates() {
+        let dir = plan_tree();
+        let before = digest(&dir);
+        fs::remove_file(dir.path().join("habitat/hooks/run")).unwrap();
+        assert_ne!(before, digest(&dir));
+    }
+
+    #[test]
+    fn renamed_file_invalidates() {
+        let dir = plan_tree();
+        let before = digest(&dir);
+        fs::rename(dir.path().join("habitat/hooks/run"),
+                   dir.path().join("habitat/hooks/init")).unwrap();
+        assert_ne!(before, digest(&dir));
+    }
+
+    #[test]
+    fn content_moved_between_files_invalidates() {
+        let a = TempDir::new().unwrap();
+        write(a.path(), "a", "xy");
+        write(a.path(), "b", "");
+        let b = TempDir::new().unwrap();
+        write(b.path(), "a", "x");
+        write(b.path(), "b", "y");
+        assert_ne!(digest(&a), digest(&b));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn executable_bit_invalidates() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = plan_tree();
+        let before = digest(&dir);
+        let hook = dir.path().join("habitat/hooks/run");
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_ne!(before, digest(&dir));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_target_invalidates() {
+        use std::os::unix::fs::symlink;
+
+        let dir = plan_tree();
+        symlink("main.c", dir.path().join("src/link.c")).unwrap();
+        let before = digest(&dir);
+        fs::remove_file(dir.path().join("src/link.c")).unwrap();
+        symlink("other.c", dir.path().join("src/link.c")).unwrap();
+        assert_ne!(before, digest(&dir));
+    }
+
+    #[test]
+    fn key_is_hex_sha256() {
+        let k = key("abc", &deps());
+        assert_eq!(k.len(), 64);
+        assert!(k.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn key_is_stable_for_same_inputs() {
+        assert_eq!(key("abc", &deps()), key("abc", &deps()));
+    }
+
+    #[test]
+    fn dependency_order_and_duplicates_do_not_matter() {
+        let mut reordered = deps();
+        reordered.reverse();
+        reordered.push(ident("core/glibc/2.27/20190115002733"));
+        assert_eq!(key("abc", &deps()), key("abc", &reordered));
+    }
+
+    #[test]
+    fn new_dependency_release_invalidates() {
+        let mut updated = deps();
+        updated[1] = ident("core/openssl/1.0.2r/20190601000000");
+        assert_ne!(key("abc", &deps()), key("abc", &updated));
+    }
+
+    #[test]
+    fn added_or_removed_dependency_invalidates() {
+        let mut added = deps();
+        added.push(ident("core/zlib/1.2.11/20190115003728"));
+        assert_ne!(key("abc", &deps()), key("abc", &added));
+        assert_ne!(key("abc", &deps()), key("abc", &deps()[..1]));
+        assert_ne!(key("abc", &deps()), key("abc", &[]));
+    }
+
+    #[test]
+    fn plan_digest_change_invalidates() {
+        assert_ne!(key("abc", &deps()), key("abd", &deps()));
+    }
+
+    #[test]
+    fn target_change_invalidates() {
+        let linux = cache_key("core/redis", target::X86_64_LINUX, "abc", &deps());
+        let windows = cache_key("core/redis", target::X86_64_WINDOWS, "abc", &deps());
+        assert_ne!(linux, windows);
+    }
+
+    #[test]
+    fn project_change_invalidates() {
+        let core = cache_key("core/redis", target::X86_64_LINUX, "abc", &deps());
+        let fork = cache_key("myorigin/redis", target::X86_64_LINUX, "abc", &deps());
+        assert_ne!(core, fork);
+    }
+}
@@ -0,0 +1,263 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tracks how large a job's workspace grows while the studio runs. The workspace is walked on
+//! a timer in a background thread; once it holds more files than a walk should cost, sampling
+//! switches to the growth in usage of the filesystem it lives on, counted from the last walk.
+
+use std::{fs,
+          io,
+          path::{Path,
+                 PathBuf},
+          sync::{atomic::{AtomicU64,
+                          Ordering},
+                 mpsc,
+                 Arc},
+          thread::{self,
+                   JoinHandle},
+          time::Duration};
+
+use fs2;
+
+/// Samples workspace disk usage until `finish` is called and keeps the peak.
+pub struct DiskUsageSampler {
+    peak:   Arc<AtomicU64>,
+    probe:  Option<Probe>,
+    stop:   Option<mpsc::Sender<()>>,
+    handle: Option<JoinHandle<Probe>>,
+}
+
+impl DiskUsageSampler {
+    /// Starts sampling `root` every `interval`. A zero interval takes no samples until
+    /// `finish`.
+    pub fn start<T>(root: T, interval: Duration, max_files: u64) -> Self
+        where T: Into<PathBuf>
+    {
+        let peak = Arc::new(AtomicU64::new(0));
+        let mut probe = Probe::new(root.into(), max_files);
+
+        if interval == Duration::from_secs(0) {
+            return DiskUsageSampler { peak,
+                                      probe: Some(probe),
+                                      stop: None,
+                                      handle: None };
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let shared = peak.clone();
+        let spawned =
+            thread::Builder::new().name("disk-usage".to_string())
+                                  .spawn(move || {
+                                      loop {
+                                          probe.record(&shared);
+                                          match rx.recv_timeout(interval) {
+                                              Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                                              _ => break,
+                                          }
+                                      }
+                                      probe
+                                  });
+
+        match spawned {
+            Ok(handle) => {
+                DiskUsageSampler { peak,
+                                   probe: None,
+                                   stop: Some(tx),
+                                   handle: Some(handle) }
+            }
+            Err(err) => {
+                warn!("Unable to start disk usage sampling, err={}", err);
+                DiskUsageSampler { peak,
+                                   probe: None,
+                                   stop: None,
+                                   handle: None }
+            }
+        }
+    }
+
+    /// Stops sampling, takes a last sample and returns the peak usage in bytes, if any sample
+    /// succeeded.
+    pub fn finish(mut self) -> Option<u64> {
+        if let Some(stop) = self.stop.take() {
+            stop.send(()).ok();
+        }
+        let probe = match self.handle.take() {
+            Some(handle) => handle.join().ok(),
+            None => self.probe.take(),
+        };
+        if let Some(mut probe) = probe {
+            probe.record(&self.peak);
+        }
+
+        match self.peak.load(Ordering::SeqCst) {
+            0 => None,
+            peak => Some(peak),
+        }
+    }
+}
+
+struct Probe {
+    root:      PathBuf,
+    max_files: u64,
+    walked:    u64,
+    // Filesystem usage when the walk was abandoned, growth past it is added to `walked`
+    fs_base:   Option<u64>,
+}
+
+impl Probe {
+    fn new(root: PathBuf, max_files: u64) -> Self {
+        Probe { root,
+                max_files,
+                walked: 0,
+                fs_base: None }
+    }
+
+    fn record(&mut self, peak: &AtomicU64) {
+        match self.sample() {
+            // Only one thread samples at a time, so there is no race between load and store
+            Ok(bytes) if bytes > peak.load(Ordering::SeqCst) => peak.store(bytes, Ordering::SeqCst),
+            Ok(_) => (),
+            Err(err) => {
+                debug!("Unable to sample disk usage of {:?}, err={}",
+                       self.root, err)
+            }
+        }
+    }
+
+    fn sample(&mut self) -> io::Result<u64> {
+        if let Some(base) = self.fs_base {
+            return Ok(self.walked + fs_used(&self.root)?.saturating_sub(base));
+        }
+
+        let mut files = 0;
+        match walk(&self.root, self.max_files, &mut files)? {
+            Some(bytes) => {
+                self.walked = bytes;
+                Ok(bytes)
+            }
+            None => {
+                debug!("Workspace {:?} exceeds {} files, sampling filesystem usage instead",
+                       self.root, self.max_files);
+                self.fs_base = Some(fs_used(&self.root)?);
+                Ok(self.walked)
+            }
+        }
+    }
+}
+
+// Total size of the files below `dir`, or `None` once more than `max_files` have been seen.
+// Entries that vanish mid-walk are skipped since the build is still changing the tree.
+fn walk(dir: &Path, max_files: u64, files: &mut u64) -> io::Result<Option<u64>> {
+    let mut bytes = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        let metadata = match fs::symlink_metadata(entry.path()) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+
+        *files += 1;
+        if *files > max_files {
+            return Ok(None);
+        }
+
+        if metadata.is_dir() {
+            match walk(&entry.path(), max_files, files) {
+                Ok(Some(size)) => bytes += size,
+                Ok(None) => return Ok(None),
+                Err(_) => continue,
+            }
+        } else {
+            bytes += metadata.len();
+        }
+    }
+    Ok(Some(bytes))
+}
+
+fn fs_used(path: &Path) -> io::Result<u64> {
+    Ok(fs2::total_space(path)?.saturating_sub(fs2::free_space(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write(root: &Path, rel_path: &str, len: usize) {
+        let path = root.join(rel_path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, vec![0u8; len]).unwrap();
+    }
+
+    #[test]
+    fn walk_sums_nested_files() {
+        let dir = TempDir::new().unwrap();
+        write(dir.path(), "src/plan.sh", 100);
+        write(dir.path(), "studio/src/big.tar", 4000);
+        write(dir.path(), "out/a/b/c", 20);
+
+        let mut files = 0;
+        assert_eq!(walk(dir.path(), 100, &mut files).unwrap(), Some(4120));
+    }
+
+    #[test]
+    fn walk_gives_up_past_file_threshold() {
+        let dir = TempDir::new().unwrap();
+        for i in 0..5 {
+            write(dir.path(), &format!("src/{}", i), 10);
+        }
+
+        let mut files = 0;
+        assert_eq!(walk(dir.path(), 3, &mut files).unwrap(), None);
+    }
+
+    #[test]
+    fn probe_falls_back_to_last_walk_past_threshold() {
+        let dir = TempDir::new().unwrap();
+        write(dir.path(), "src/a", 500);
+        let mut probe = Probe::new(dir.path().to_path_buf(), 3);
+        assert_eq!(probe.sample().unwrap(), 500);
+
+        for i in 0..5 {
+            write(dir.path(), &format!("out/{}", i), 10);
+        }
+        assert!(probe.sample().unwrap() >= 500);
+        assert!(probe.fs_base.is_some());
+    }
+
+    #[test]
+    fn finish_reports_peak_not_final_size() {
+        let dir = TempDir::new().unwrap();
+        write(dir.path(), "studio/tmp/scratch", 8000);
+        let sampler = DiskUsageSampler::start(dir.path(), Duration::from_secs(3600), 100);
+
+        // Give the sampler's first walk a chance before the scratch file is removed
+        thread::sleep(Duration::from_millis(200));
+        fs::remove_file(dir.path().join("studio/tmp/scratch")).unwrap();
+        write(dir.path(), "out/pkg.hart", 1000);
+
+        assert_eq!(sampler.finish(), Some(8000));
+    }
+
+    #[test]
+    fn zero_interval_samples_once_at_finish() {
+        let dir = TempDir::new().unwrap();
+        let sampler = DiskUsageSampler::start(dir.path(), Duration::from_secs(0), 100);
+        write(dir.path(), "out/pkg.hart", 1000);
+        assert_eq!(sampler.finish(), Some(1000));
+    }
+}
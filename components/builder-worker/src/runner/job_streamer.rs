@@ -18,17 +18,21 @@ const INPROC_ADDR: &str = "inproc://logger";
 const LOG_LINE: &str = "L";
 /// ZMQ protocol frame to indicate a log has finished
 const LOG_COMPLETE: &str = "C";
+/// ZMQ protocol frame to indicate the progress of a job is being sent
+const LOG_PROGRESS: &str = "P";
 /// End-of-line marker
 const EOL_MARKER: &str = "\n";
+/// Size of the reads from a child process' output streams
+const READ_CHUNK_SIZE: usize = 8 * 1024;
 
 use std::{fmt,
-          io::{BufRead,
-               BufReader,
+          io::{self,
                Read},
           process::Child,
           sync::{Arc,
                  Mutex},
-          thread};
+          thread,
+          time::Instant};
 
 use protobuf::Message;
 use zmq;
@@ -36,9 +40,13 @@ use zmq;
 use crate::{bldr_core::{logger::Logger,
                         socket::DEFAULT_CONTEXT},
             protocol::jobsrv::{JobLogChunk,
-                               JobLogComplete}};
+                               JobLogComplete,
+                               JobProgress}};
 
-use super::workspace::Workspace;
+use super::{log_scrubber::{LogScrubber,
+                           StreamScrubber},
+            progress::ProgressTracker,
+            workspace::Workspace};
 use crate::error::{Error,
                    Result};
 
@@ -57,6 +65,8 @@ pub struct JobStreamer {
     target: Arc<Mutex<StreamTarget>>,
     /// Whether or not the stream has been marked as finished
     finished: bool,
+    /// Redacts the job's secrets from the output of child processes
+    scrubber: Arc<LogScrubber>,
 }
 
 impl JobStreamer {
@@ -70,9 +80,12 @@ impl JobStreamer {
     ///
     /// * If the stream target could not be written to
     pub fn new(workspace: &Workspace) -> Self {
-        let streamer = JobStreamer { id:       workspace.job.get_id(),
-                                     target:   Arc::new(Mutex::new(StreamTarget::new(workspace))),
-                                     finished: false, };
+        let scrubber = Arc::new(LogScrubber::from_job(&workspace.job));
+        let target = StreamTarget::new(workspace, scrubber.clone());
+        let streamer = JobStreamer { id: workspace.job.get_id(),
+                                     target: Arc::new(Mutex::new(target)),
+                                     finished: false,
+                                     scrubber };
 
         streamer.target
                 .lock()
@@ -98,12 +111,13 @@ impl JobStreamer {
     /// Consumes the `stdout` and `stderr` ouput streams of a child process and writes their
     /// contents to the stream while attempting to preserve the original output ordering.
     ///
-    /// Each output stream is independently read from a full line at a time before it writes the
-    /// full line to the shared target stream resource. This means that in most cases the output
-    /// ordering will be identical to running the same command in a terminal. The exception to this
-    /// is if a process is writing to both its `stdout` and `stderr` concurrently, without flushing
-    /// their buffers after each newline. Preserving exact ordering did not seem as useful as
-    /// preserving ordering at a line level, hence the current implementation.
+    /// Each output stream is independently read and scrubbed of the job's secrets, then written a
+    /// full line at a time to the shared target stream resource. This means that in most cases
+    /// the output ordering will be identical to running the same command in a terminal. The
+    /// exception to this is if a process is writing to both its `stdout` and `stderr`
+    /// concurrently, without flushing their buffers after each newline. Preserving exact ordering
+    /// did not seem as useful as preserving ordering at a line level, hence the current
+    /// implementation.
     ///
     /// # Panics
     ///
@@ -119,23 +133,41 @@ impl JobStreamer {
         let _stdout_handle = {
             let target = self.target.clone();
             let id = self.id;
+            let scrubber = StreamScrubber::new(self.scrubber.clone());
             let stdout = child.stdout.take().expect("Child stdout was not captured");
             thread::Builder::new().name("stdout-consumer".into())
-                                  .spawn(move || consume_stream(target, id, stdout))
+                                  .spawn(move || consume_stream(target, id, stdout, scrubber))
                                   .expect("Failed to spawn stdout thread")
         };
         let _stderr_handle = {
             let target = self.target.clone();
             let id = self.id;
+            let scrubber = StreamScrubber::new(self.scrubber.clone());
             let stderr = child.stderr.take().expect("Child stderr was not captured");
             thread::Builder::new().name("stderr-consumer".into())
-                                  .spawn(move || consume_stream(target, id, stderr))
+                                  .spawn(move || consume_stream(target, id, stderr, scrubber))
                                   .expect("Failed to spawn stderr thread")
         };
 
         Ok(())
     }
 
+    /// Writes a full line of informational output to the log stream.
+    ///
+    /// # Panics
+    ///
+    /// * If the stream target mutex is poisoned
+    ///
+    /// # Errors
+    ///
+    /// * If the stream target could not be written to
+    pub fn println_stdout<S: Into<String>>(&self, line: S) -> Result<()> {
+        self.target
+            .lock()
+            .expect("Stream target mutex is poisoned!")
+            .stream_line(self.id, line)
+    }
+
     /// Writes a full line from a `stderr` stream to the log stream.
     ///
     /// # Panics
@@ -189,7 +221,7 @@ impl Drop for JobStreamer {
 }
 
 /// The target to which a log stream is written. This struct wraps a remote socket which is log
-/// line aware.
+/// line aware, and which also carries the job's progress as the streamed lines reveal it.
 struct StreamTarget {
     /// A zeromq socket which represents the log stream target
     pub sock: zmq::Socket,
@@ -197,6 +229,10 @@ struct StreamTarget {
     pub line_count: u64,
     /// A local file logger that writes a copy of each line written to the remote socket
     pub local_logger: Logger,
+    /// The phase and build step of the job, reported at most every `progress::REPORT_INTERVAL`
+    progress: ProgressTracker,
+    /// Redacts the job's secrets from every line, including those the worker writes itself
+    scrubber: Arc<LogScrubber>,
 }
 
 impl StreamTarget {
@@ -205,7 +241,7 @@ impl StreamTarget {
     /// # Panics
     ///
     /// * If the zeromq socket cannot be fully set up
-    fn new(workspace: &Workspace) -> Self {
+    fn new(workspace: &Workspace, scrubber: Arc<LogScrubber>) -> Self {
         let sock = (**DEFAULT_CONTEXT).as_mut().socket(zmq::PUSH).unwrap();
         sock.set_immediate(true).unwrap();
         sock.set_linger(5000).unwrap();
@@ -215,9 +251,35 @@ impl StreamTarget {
         let mut local_logger = Logger::init(workspace.root(), format!("local-stream-{}.log", &id));
         local_logger.log_ident(&id);
 
+        let progress = ProgressTracker::new(workspace.job.get_id(),
+                                            workspace.job.get_project().get_name());
+
         StreamTarget { sock,
                        line_count: 0,
-                       local_logger }
+                       local_logger,
+                       progress,
+                       scrubber }
+    }
+
+    /// Marks the start of a phase of the job, which is reported with its progress until the
+    /// phase ends.
+    ///
+    /// # Errors
+    ///
+    /// * If the start line couldn't be sent to the stream target socket
+    fn start_phase(&mut self, id: u64, name: &Section) -> Result<()> {
+        self.progress.start_phase(name.to_string(), Instant::now());
+        self.stream_line(id, format!("builder_log_section::start::{}", name))
+    }
+
+    /// Marks the end of a phase of the job.
+    ///
+    /// # Errors
+    ///
+    /// * If the end line couldn't be sent to the stream target socket
+    fn end_phase(&mut self, id: u64, name: &Section) -> Result<()> {
+        self.progress.end_phase();
+        self.stream_line(id, format!("builder_log_section::end::{}", name))
     }
 
     /// Takes a string, interpreted as a single line, with a job identifier and writes it to the
@@ -231,8 +293,9 @@ impl StreamTarget {
     ///
     /// * If a message couldn't be sent successfully to the stream target socket
     fn stream_line<S: Into<String>>(&mut self, id: u64, line: S) -> Result<()> {
-        let mut line: String = line.into();
+        let mut line = self.scrubber.scrub_str(&line.into());
         self.local_logger.log(&line);
+        self.progress.observe_line(&line);
         line.push_str(EOL_MARKER);
 
         self.line_count += 1;
@@ -249,6 +312,30 @@ impl StreamTarget {
             .send(chunk.write_to_bytes().unwrap().as_slice(), 0)
             .map_err(Error::StreamTargetSend)?;
 
+        if let Some(progress) = self.progress.report(Instant::now()) {
+            self.send_progress(&progress)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends the progress of a job to the stream target socket.
+    ///
+    /// # Panics
+    ///
+    /// * If the protobuf struct cannot be serialized into bytes
+    ///
+    /// # Errors
+    ///
+    /// * If a message couldn't be sent successfully to the stream target socket
+    fn send_progress(&mut self, progress: &JobProgress) -> Result<()> {
+        self.sock
+            .send_str(LOG_PROGRESS, zmq::SNDMORE)
+            .map_err(Error::StreamTargetSend)?;
+        self.sock
+            .send(progress.write_to_bytes().unwrap().as_slice(), 0)
+            .map_err(Error::StreamTargetSend)?;
+
         Ok(())
     }
 
@@ -280,8 +367,10 @@ impl StreamTarget {
 /// Builder user via job output.
 pub enum Section {
     BuildPackage,
+    CheckBuildCache,
     CloneRepository,
     ExportDocker,
+    FetchDependencies,
     FetchOriginKey,
     PublishPackage,
     ValidateIntegrations,
@@ -293,8 +382,10 @@ impl fmt::Display for Section {
         // verb-leading for consistency
         let token = match *self {
             Section::BuildPackage => "build_package",
+            Section::CheckBuildCache => "check_build_cache",
             Section::CloneRepository => "clone_repository",
             Section::ExportDocker => "export_docker",
+            Section::FetchDependencies => "fetch_dependencies",
             Section::FetchOriginKey => "fetch_origin_key",
             Section::PublishPackage => "publish_package",
             Section::ValidateIntegrations => "validate_integrations",
@@ -340,8 +431,7 @@ impl LogSection {
         self.target
             .lock()
             .expect("Stream target mutex is poisoned!")
-            .stream_line(self.id,
-                         format!("builder_log_section::start::{}", self.name))
+            .start_phase(self.id, &self.name)
     }
 
     /// Ends a log section by writing to the log stream. This method can be called multiple times
@@ -365,7 +455,7 @@ impl LogSection {
         self.target
             .lock()
             .expect("Stream target mutex is poisoned!")
-            .stream_line(self.id, format!("builder_log_section::end::{}", self.name))
+            .end_phase(self.id, &self.name)
     }
 }
 
@@ -377,28 +467,52 @@ impl Drop for LogSection {
 }
 
 /// Takes a `Read`er with an identifier and writes its contents to a stream target, one line at a
-/// time.
+/// time. The contents are scrubbed of secrets as they're read, before they're split into lines,
+/// so a secret spanning lines is redacted too.
 ///
 /// # Panics
 ///
-/// * If there is an error reading a single line from the reader stream
 /// * If the stream target mutex is poisoned
 ///
 /// # Errors
 ///
+/// * If there is an error reading from the reader stream
 /// * If the stream target could not be written to
 #[allow(clippy::needless_pass_by_value)]
-fn consume_stream<R: Read>(target: Arc<Mutex<StreamTarget>>, id: u64, reader: R) -> Result<()> {
-    let reader = BufReader::new(reader);
-    for line in reader.lines() {
-        let line = match line {
-            Ok(line) => line,
+fn consume_stream<R: Read>(target: Arc<Mutex<StreamTarget>>,
+                           id: u64,
+                           mut reader: R,
+                           mut scrubber: StreamScrubber)
+                           -> Result<()> {
+    let mut buf = [0; READ_CHUNK_SIZE];
+    let mut pending = Vec::new();
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
             Err(e) => return Err(Error::StreamLine(e)),
         };
-        target.lock()
-              .expect("Stream target mutex is poisoned!")
-              .stream_line(id, line)?;
+        pending.extend(scrubber.push(&buf[..len]));
+
+        while let Some(eol) = pending.iter().position(|b| *b == b'\n') {
+            let line = pending.drain(..=eol).collect::<Vec<_>>();
+            stream_bytes(&target, id, &line)?;
+        }
+    }
+    pending.extend(scrubber.finish());
+    if !pending.is_empty() {
+        stream_bytes(&target, id, &pending)?;
     }
 
     Ok(())
 }
+
+// Writes a line of output, without its line ending, to the stream target
+fn stream_bytes(target: &Arc<Mutex<StreamTarget>>, id: u64, line: &[u8]) -> Result<()> {
+    let line = String::from_utf8_lossy(line);
+    let line = line.trim_end_matches('\n').trim_end_matches('\r');
+    target.lock()
+          .expect("Stream target mutex is poisoned!")
+          .stream_line(id, line)
+}
@@ -0,0 +1,271 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Redacts the values of a job's origin secrets from its build log before the log leaves the
+//! worker. Plans occasionally echo their environment, and the log is readable by every member of
+//! the origin.
+
+use std::sync::Arc;
+
+use base64;
+
+use crate::protocol::jobsrv::Job;
+
+/// Secrets shorter than this are left in the log, as redacting them would mangle ordinary output
+pub const MIN_SECRET_LEN: usize = 6;
+
+// A byte sequence to redact and what it's replaced with
+struct Pattern {
+    needle:      Vec<u8>,
+    replacement: Vec<u8>,
+}
+
+/// The secret values of a job and the way each is redacted. Values are matched as bytes, both as
+/// they are and base64-encoded.
+pub struct LogScrubber {
+    // Longest first, so a secret containing another is redacted whole
+    patterns: Vec<Pattern>,
+}
+
+impl LogScrubber {
+    /// Builds a scrubber from `(name, value)` pairs. Values shorter than `MIN_SECRET_LEN` bytes
+    /// are skipped.
+    pub fn new<I, N, V>(secrets: I) -> Self
+        where I: IntoIterator<Item = (N, V)>,
+              N: AsRef<str>,
+              V: AsRef<[u8]>
+    {
+        let mut patterns = Vec::new();
+        for (name, value) in secrets {
+            let (name, value) = (name.as_ref(), value.as_ref());
+            if value.len() < MIN_SECRET_LEN {
+                warn!("Secret {} is too short to be redacted from the build log",
+                      name);
+                continue;
+            }
+            let replacement = format!("[REDACTED:{}]", name).into_bytes();
+            // Without the padding, which depends on what follows the value when it's encoded
+            // as part of a longer string
+            let encoded = base64::encode(value).trim_end_matches('=')
+                                               .as_bytes()
+                                               .to_vec();
+
+            patterns.push(Pattern { needle:      value.to_vec(),
+                                    replacement: replacement.clone(), });
+            patterns.push(Pattern { needle: encoded,
+                                    replacement });
+        }
+        patterns.sort_by(|a, b| {
+                    b.needle
+                     .len()
+                     .cmp(&a.needle.len())
+                     .then_with(|| a.needle.cmp(&b.needle))
+                });
+        patterns.dedup_by(|a, b| a.needle == b.needle);
+
+        LogScrubber { patterns }
+    }
+
+    /// A scrubber for the decrypted secrets of `job`
+    pub fn from_job(job: &Job) -> Self {
+        let secrets =
+            job.get_secrets()
+               .iter()
+               .map(|secret| secret.get_decrypted_secret())
+               .map(|secret| (secret.get_name().to_string(), secret.get_value().to_string()))
+               .collect::<Vec<_>>();
+        LogScrubber::new(secrets)
+    }
+
+    pub fn is_empty(&self) -> bool { self.patterns.is_empty() }
+
+    /// Redacts the secrets of a complete piece of output, such as a single line
+    pub fn scrub(&self, input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(input.len());
+        self.scrub_into(input, &mut out, true);
+        out
+    }
+
+    /// Redacts the secrets of a line of text
+    pub fn scrub_str(&self, line: &str) -> String {
+        if self.is_empty() {
+            return line.to_string();
+        }
+        String::from_utf8_lossy(&self.scrub(line.as_bytes())).into_owned()
+    }
+
+    // Copies `input` to `out` with every secret replaced and returns how many bytes of `input`
+    // were consumed. Unless `eof` is set, scrubbing stops at the first position where a secret
+    // could still begin, as the rest of it may be in the next chunk.
+    fn scrub_into(&self, input: &[u8], out: &mut Vec<u8>, eof: bool) -> usize {
+        let mut pos = 0;
+        'outer: while pos < input.len() {
+            let rest = &input[pos..];
+            for pattern in &self.patterns {
+                if rest.starts_with(&pattern.needle) {
+                    out.extend_from_slice(&pattern.replacement);
+                    pos += pattern.needle.len();
+                    continue 'outer;
+                }
+                if !eof && rest.len() < pattern.needle.len() && pattern.needle.starts_with(rest) {
+                    break 'outer;
+                }
+            }
+            out.push(input[pos]);
+            pos += 1;
+        }
+        pos
+    }
+}
+
+/// Scrubs one output stream as it's read in chunks of arbitrary size. The tail of a chunk that
+/// could be the start of a secret is held back until the next chunk shows whether it is, so a
+/// value split across two reads, or inside a multi-byte character, is still redacted.
+pub struct StreamScrubber {
+    scrubber: Arc<LogScrubber>,
+    pending:  Vec<u8>,
+}
+
+impl StreamScrubber {
+    pub fn new(scrubber: Arc<LogScrubber>) -> Self {
+        StreamScrubber { scrubber,
+                         pending: Vec::new() }
+    }
+
+    /// Scrubs `chunk` and returns the output that is safe to forward
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(chunk);
+        let mut out = Vec::with_capacity(self.pending.len());
+        let consumed = self.scrubber.scrub_into(&self.pending, &mut out, false);
+        self.pending.drain(..consumed);
+        out
+    }
+
+    /// Returns whatever was held back, once the stream has ended
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.pending.len());
+        self.scrubber.scrub_into(&self.pending, &mut out, true);
+        self.pending.clear();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scrubber() -> Arc<LogScrubber> {
+        Arc::new(LogScrubber::new(vec![("AWS_SECRET", "wJalrXUtnFEMI"),
+                                       ("DB_PASSWORD", "pässwörd✓"),
+                                       ("SHORT", "abc")]))
+    }
+
+    // Streams `input` in chunks of `size` bytes
+    fn stream(scrubber: &Arc<LogScrubber>, input: &[u8], size: usize) -> String {
+        let mut stream = StreamScrubber::new(scrubber.clone());
+        let mut out = Vec::new();
+        for chunk in input.chunks(size) {
+            out.extend(stream.push(chunk));
+        }
+        out.extend(stream.finish());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn secret_values_are_redacted() {
+        let scrubber = scrubber();
+        assert_eq!(scrubber.scrub_str("export AWS_SECRET=wJalrXUtnFEMI; echo done"),
+                   "export AWS_SECRET=[REDACTED:AWS_SECRET]; echo done");
+        assert_eq!(scrubber.scrub_str("wJalrXUtnFEMIwJalrXUtnFEMI"),
+                   "[REDACTED:AWS_SECRET][REDACTED:AWS_SECRET]");
+        assert_eq!(scrubber.scrub_str("password: pässwörd✓ ok"),
+                   "password: [REDACTED:DB_PASSWORD] ok");
+        assert_eq!(scrubber.scrub_str("wJalrXUtnFEM is not the secret"),
+                   "wJalrXUtnFEM is not the secret");
+    }
+
+    #[test]
+    fn base64_encoded_values_are_redacted() {
+        let scrubber = scrubber();
+        let line = format!("Authorization: Basic {}", base64::encode("wJalrXUtnFEMI"));
+        assert_eq!(scrubber.scrub_str(&line),
+                   "Authorization: Basic [REDACTED:AWS_SECRET]");
+
+        let line = format!("echo {} | base64 -d", base64::encode("pässwörd✓"));
+        assert_eq!(scrubber.scrub_str(&line),
+                   "echo [REDACTED:DB_PASSWORD] | base64 -d");
+    }
+
+    #[test]
+    fn short_and_empty_secrets_are_not_redacted() {
+        let scrubber = LogScrubber::new(vec![("SHORT", "abc"), ("EMPTY", "")]);
+        assert!(scrubber.is_empty());
+        assert_eq!(scrubber.scrub_str("abc abc"), "abc abc");
+
+        let scrubber = LogScrubber::new(vec![("EXACT", "abcdef")]);
+        assert_eq!(scrubber.scrub_str("abcdef"), "[REDACTED:EXACT]");
+    }
+
+    #[test]
+    fn the_longest_secret_wins() {
+        let scrubber =
+            LogScrubber::new(vec![("TOKEN", "s3cr3t-t0k3n"), ("TOKEN_PREFIX", "s3cr3t")]);
+        assert_eq!(scrubber.scrub_str("s3cr3t-t0k3n s3cr3t"),
+                   "[REDACTED:TOKEN] [REDACTED:TOKEN_PREFIX]");
+    }
+
+    #[test]
+    fn secrets_split_across_chunks_are_redacted() {
+        let scrubber = scrubber();
+        let input = "start wJalrXUtnFEMI middle pässwörd✓ end\nwJalrXUtnFEMI\n".as_bytes();
+        let expected = "start [REDACTED:AWS_SECRET] middle [REDACTED:DB_PASSWORD] \
+                        end\n[REDACTED:AWS_SECRET]\n";
+        for size in 1..=input.len() {
+            assert_eq!(stream(&scrubber, input, size),
+                       expected,
+                       "chunk size {}",
+                       size);
+        }
+    }
+
+    #[test]
+    fn multi_byte_characters_split_across_chunks_are_kept() {
+        let scrubber = scrubber();
+        // Every boundary, including those inside the multi-byte characters of both the secret
+        // and the text around it
+        let input = "ünïcødé ✓ pässwörd✓✓ pässwör✓".as_bytes();
+        let expected = "ünïcødé ✓ [REDACTED:DB_PASSWORD]✓ pässwör✓";
+        for size in 1..=input.len() {
+            assert_eq!(stream(&scrubber, input, size),
+                       expected,
+                       "chunk size {}",
+                       size);
+        }
+    }
+
+    #[test]
+    fn a_partial_secret_at_the_end_of_the_stream_is_forwarded() {
+        let mut stream = StreamScrubber::new(scrubber());
+        assert_eq!(stream.push(b"done wJalrX"), b"done ".to_vec());
+        assert_eq!(stream.finish(), b"wJalrX".to_vec());
+    }
+
+    #[test]
+    fn output_without_secrets_is_not_held_back() {
+        let mut stream = StreamScrubber::new(scrubber());
+        assert_eq!(stream.push(b"no secrets here\n"),
+                   b"no secrets here\n".to_vec());
+        assert_eq!(stream.finish(), Vec::<u8>::new());
+    }
+}
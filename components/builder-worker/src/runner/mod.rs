@@ -12,34 +12,47 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod build_cache;
+mod disk_usage;
 mod docker;
 mod job_streamer;
+mod log_scrubber;
+mod offline;
 mod postprocessor;
+mod progress;
 mod publisher;
 pub mod studio;
 mod toml_builder;
 mod util;
+mod verifier;
 mod workspace;
 
-use self::{docker::DockerExporter,
+use self::{disk_usage::DiskUsageSampler,
+           docker::DockerExporter,
            job_streamer::{JobStreamer,
                           Section},
            postprocessor::post_process,
            studio::Studio,
+           verifier::DependencyVerifier,
            workspace::Workspace};
 pub use crate::protocol::jobsrv::JobState;
 use crate::{bldr_core::{self,
-                        api_client::ApiClient,
+                        api_client::{ApiClient,
+                                     Package},
                         job::Job,
                         logger::Logger,
-                        socket::DEFAULT_CONTEXT},
-            config::Config,
+                        socket::DEFAULT_CONTEXT,
+                        timestamp},
+            config::{http_clients,
+                     Config},
             error::{Error,
                     Result},
             hab_core::{env,
                        package::{archive::PackageArchive,
                                  target::{self,
-                                          PackageTarget}}},
+                                          PackageTarget},
+                                 PackageIdent},
+                       ChannelIdent},
             protocol::{jobsrv,
                        message,
                        net::{self,
@@ -93,12 +106,13 @@ pub const RETRY_WAIT: Duration = Duration::from_secs(60);
 pub const STUDIO_CHILD_WAIT_SECS: u64 = 10;
 
 pub struct Runner {
-    config:     Arc<Config>,
-    depot_cli:  ApiClient,
-    workspace:  Workspace,
-    logger:     Logger,
-    bldr_token: String,
-    cancel:     Arc<AtomicBool>,
+    config:          Arc<Config>,
+    depot_cli:       ApiClient,
+    workspace:       Workspace,
+    logger:          Logger,
+    bldr_token:      String,
+    cancel:          Arc<AtomicBool>,
+    build_cache_key: Option<String>,
 }
 
 impl Runner {
@@ -108,7 +122,8 @@ impl Runner {
                cancel: Arc<AtomicBool>)
                -> Result<Self> {
         debug!("Creating new Runner with config: {:?}", config);
-        let depot_cli = ApiClient::new(&config.bldr_url)?;
+        let depot_cli =
+            ApiClient::with_factory(&config.bldr_url, &http_clients(&config.http_client)?)?;
 
         let log_path = config.log_path.clone();
         let mut logger = Logger::init(log_path, "builder-worker.log");
@@ -120,7 +135,8 @@ impl Runner {
                     depot_cli,
                     logger,
                     bldr_token,
-                    cancel })
+                    cancel,
+                    build_cache_key: None })
     }
 
     pub fn job(&self) -> &Job { &self.workspace.job }
@@ -210,7 +226,9 @@ impl Runner {
         self.check_cancel(tx)?;
         let mut section = streamer.start_section(Section::CloneRepository)?;
 
-        let vcs = VCS::from_job(&self.job(), self.config.github.clone())?;
+        let vcs = VCS::from_job(&self.job(),
+                                self.config.github.clone(),
+                                &self.config.http_client)?;
         if let Some(err) = vcs.clone(&self.workspace.src()).err() {
             let msg = format!("Failed to clone remote source repository for {}, err={:?}",
                               self.workspace.job.get_project().get_name(),
@@ -228,6 +246,66 @@ impl Runner {
         Ok(())
     }
 
+    fn do_check_cache(&mut self,
+                      tx: &mpsc::Sender<Job>,
+                      streamer: &mut JobStreamer)
+                      -> Result<bool> {
+        self.check_cancel(tx)?;
+
+        // The build cache is kept by the depot
+        if !self.config.build_cache || self.config.offline_mode.enabled {
+            return Ok(false);
+        }
+
+        let mut section = streamer.start_section(Section::CheckBuildCache)?;
+
+        if self.job().get_no_cache() {
+            streamer.println_stdout("Build cache disabled for this job group (no_cache)")?;
+            section.end()?;
+            return Ok(false);
+        }
+
+        // Any failure to consult the cache falls back to a regular build
+        let cached = match self.check_build_cache(streamer) {
+            Ok(cached) => cached,
+            Err(err) => {
+                let msg = format!("Failed to check build cache for {}, err={}",
+                                  self.workspace.job.get_project().get_name(),
+                                  err);
+                warn!("{}", msg);
+                self.logger.log(&msg);
+
+                streamer.println_stderr(msg)?;
+                false
+            }
+        };
+
+        section.end()?;
+        Ok(cached)
+    }
+
+    fn do_fetch_deps(&mut self, tx: &mpsc::Sender<Job>, streamer: &mut JobStreamer) -> Result<()> {
+        self.check_cancel(tx)?;
+        let mut section = streamer.start_section(Section::FetchDependencies)?;
+
+        if let Some(err) = self.fetch_dependencies(streamer).err() {
+            let msg = format!("Failed to verify dependencies for {}, err={}",
+                              self.workspace.job.get_project().get_name(),
+                              err);
+            warn!("{}", msg);
+            self.logger.log(&msg);
+
+            streamer.println_stderr(msg)?;
+            self.fail(net::err(ErrCode::DEPENDENCY_VERIFICATION,
+                               dependency_error_label(&err)));
+            tx.send(self.job().clone()).map_err(Error::Mpsc)?;
+            return Err(err);
+        }
+
+        section.end()?;
+        Ok(())
+    }
+
     fn do_build(&mut self,
                 tx: &mpsc::Sender<Job>,
                 streamer: &mut JobStreamer)
@@ -236,7 +314,7 @@ impl Runner {
 
         self.workspace
             .job
-            .set_build_started_at(Utc::now().to_rfc3339());
+            .set_build_started_at(timestamp::to_rfc3339(&Utc::now()));
 
         let mut section = streamer.start_section(Section::BuildPackage)?;
 
@@ -246,17 +324,30 @@ impl Runner {
         // to "Complete" (or "Failed", etc.). As a result, we won't
         // get the `build_started_at` time set until the job is actually
         // finished.
-        let mut archive = match self.build(self.config.target, streamer, tx) {
+        let interval = Duration::from_secs(self.config.disk_usage_interval_secs);
+        let sampler = DiskUsageSampler::start(self.workspace.root(),
+                                              interval,
+                                              self.config.disk_usage_max_files);
+        let result = self.build(self.config.target, streamer, tx);
+        if let Some(bytes) = sampler.finish() {
+            self.workspace.job.set_peak_workspace_bytes(bytes);
+        }
+
+        let mut archive = match result {
             Ok(archive) => {
                 self.workspace
                     .job
-                    .set_build_finished_at(Utc::now().to_rfc3339());
+                    .set_build_finished_at(timestamp::to_rfc3339(&Utc::now()));
+                match fs::metadata(&archive.path) {
+                    Ok(metadata) => self.workspace.job.set_artifact_bytes(metadata.len()),
+                    Err(err) => warn!("Unable to read size of {:?}, err={}", archive.path, err),
+                }
                 archive
             }
             Err(err) => {
                 self.workspace
                     .job
-                    .set_build_finished_at(Utc::now().to_rfc3339());
+                    .set_build_finished_at(timestamp::to_rfc3339(&Utc::now()));
                 let msg = format!("Failed studio build for {}, err={:?}",
                                   self.workspace.job.get_project().get_name(),
                                   err);
@@ -293,21 +384,29 @@ impl Runner {
         Ok(())
     }
 
+    // Returns whether the package was promoted, false when it was uploaded but its promotion was
+    // refused as its signature failed verification
     fn do_postprocess(&mut self,
                       tx: &mpsc::Sender<Job>,
                       mut archive: PackageArchive,
                       streamer: &mut JobStreamer)
-                      -> Result<()> {
+                      -> Result<bool> {
         self.check_cancel(tx)?;
         let mut section = streamer.start_section(Section::PublishPackage)?;
 
         match post_process(&mut archive,
                            &self.workspace,
                            &self.config,
+                           self.build_cache_key.as_ref().map(String::as_str),
                            &self.bldr_token,
                            &mut self.logger)
         {
             Ok(_) => (),
+            Err(err @ Error::PromotionVerificationFailed(..)) => {
+                streamer.println_stderr(format!("{}", err))?;
+                section.end()?;
+                return Ok(false);
+            }
             Err(err) => {
                 let msg = format!("Failed post processing for {}, err={:?}",
                                   self.workspace.job.get_project().get_name(),
@@ -320,7 +419,7 @@ impl Runner {
         }
 
         section.end()?;
-        Ok(())
+        Ok(true)
     }
 
     fn cleanup(&mut self) {
@@ -342,12 +441,28 @@ impl Runner {
         self.do_install_key(&tx, &mut streamer)?;
         self.do_clone(&tx, &mut streamer)?;
 
+        if self.do_check_cache(&tx, &mut streamer)? {
+            self.cleanup();
+            self.complete_from_cache();
+            tx.send(self.workspace.job).map_err(Error::Mpsc)?;
+
+            streamer.finish()?;
+
+            return Ok(());
+        }
+
+        self.do_fetch_deps(&tx, &mut streamer)?;
+
         let archive = self.do_build(&tx, &mut streamer)?;
         self.do_export(&tx, &mut streamer)?;
-        self.do_postprocess(&tx, archive, &mut streamer)?;
+        let promoted = self.do_postprocess(&tx, archive, &mut streamer)?;
 
         self.cleanup();
-        self.complete();
+        if promoted {
+            self.complete();
+        } else {
+            self.complete_unpromoted();
+        }
         tx.send(self.workspace.job).map_err(Error::Mpsc)?;
 
         streamer.finish()?;
@@ -359,6 +474,15 @@ impl Runner {
         debug!("Installing origin secret key for {} to {:?}",
                self.job().origin(),
                self.workspace.key_path());
+        if self.config.offline_mode.enabled {
+            let dst = offline::install_secret_key(&self.config.offline_mode,
+                                                  self.job().origin(),
+                                                  self.workspace.key_path())?;
+            debug!("Installed origin secret key from the key directory, dst={:?}",
+                   dst);
+            return Ok(());
+        }
+
         match retry(delay::Fixed::from(RETRY_WAIT).take(RETRIES), || {
                   let res = self.depot_cli.fetch_origin_secret_key(self.job().origin(),
                                                                    &self.bldr_token,
@@ -384,6 +508,244 @@ impl Runner {
         }
     }
 
+    /// Downloads the dependencies the plan being built names, and their transitive dependencies,
+    /// into the workspace artifact cache, verifying each against its origin public key. The
+    /// studio installs from this cache, so a tampered artifact fails the job before any of its
+    /// contents are unpacked.
+    fn fetch_dependencies(&self, streamer: &mut JobStreamer) -> Result<()> {
+        if self.config.offline_mode.enabled {
+            return self.install_offline_dependencies(streamer);
+        }
+
+        let plan_path = offline::plan_file(self.workspace.src(),
+                                           self.job().get_project().get_plan_path());
+        let plan = fs::read_to_string(&plan_path).map_err(|e| Error::PlanRead(plan_path, e))?;
+        let channel = if self.job().has_channel() {
+            ChannelIdent::from(self.job().get_channel())
+        } else {
+            ChannelIdent::stable()
+        };
+        let target = self.job().get_target();
+
+        let mut deps: Vec<PackageIdent> = Vec::new();
+        for spec in offline::plan_deps(&plan) {
+            let package = self.resolve_dependency(&spec, &channel, target)?;
+            for dep in Some(package.ident).into_iter().chain(package.tdeps) {
+                let dep: PackageIdent = dep.into();
+                if !deps.contains(&dep) {
+                    deps.push(dep);
+                }
+            }
+        }
+
+        let key_cache = self.config.data_path.join("key-cache");
+        fs::create_dir_all(&key_cache).map_err(|e| Error::CreateDirectory(key_cache.clone(), e))?;
+
+        let mut verifier =
+            DependencyVerifier::new(&self.depot_cli, Some(&self.bldr_token), &key_cache);
+        for dep in deps {
+            let archive = self.depot_cli.fetch_package(&dep,
+                                                       target,
+                                                       self.workspace.artifacts(),
+                                                       Some(&self.bldr_token))?;
+            verifier.verify(&dep.to_string(), &archive.path)?;
+        }
+
+        streamer.println_stdout(format!("Verified {} dependencies against origin public keys \
+                                         ({} key cache hits)",
+                                        verifier.stats().verified,
+                                        verifier.stats().cache_hits))?;
+        Ok(())
+    }
+
+    /// The latest release of a dependency the plan names, from the job's channel or else from
+    /// stable, as the studio resolves them. A dependency found in neither fails the job rather
+    /// than leave it to be installed unverified.
+    fn resolve_dependency(&self,
+                          spec: &str,
+                          channel: &ChannelIdent,
+                          target: &str)
+                          -> Result<Package> {
+        let ident = PackageIdent::from_str(spec)?;
+        let mut channels = vec![channel.clone()];
+        if *channel != ChannelIdent::stable() {
+            channels.push(ChannelIdent::stable());
+        }
+        for channel in channels {
+            match self.depot_cli
+                      .show_package(&ident, &channel, target, Some(&self.bldr_token))
+            {
+                Ok(package) => return Ok(package),
+                Err(bldr_core::Error::ApiError(code, _)) if code.as_u16() == 404 => continue,
+                Err(err) => return Err(Error::BuilderCore(err)),
+            }
+        }
+        Err(Error::DependencyNotFound(spec.to_string(), channel.to_string()))
+    }
+
+    /// Installs the dependencies the plan names, and their transitive dependencies, from the
+    /// artifact cache of offline mode. A job whose dependencies aren't all there fails with the
+    /// list of those missing rather than have the studio fail on the first.
+    fn install_offline_dependencies(&self, streamer: &mut JobStreamer) -> Result<()> {
+        let cfg = &self.config.offline_mode;
+        let target = PackageTarget::from_str(self.job().get_target())?;
+        let plan_path = offline::plan_file(self.workspace.src(),
+                                           self.job().get_project().get_plan_path());
+        let plan =
+            fs::read_to_string(&plan_path).map_err(|e| Error::OfflineIO(plan_path.clone(), e))?;
+
+        let cache = offline::ArtifactCache::open(&cfg.artifact_cache, target)?;
+        let (found, missing) = offline::resolve(&cache, &offline::plan_deps(&plan));
+        if !missing.is_empty() {
+            return Err(Error::MissingDependencies(cfg.artifact_cache.clone(),
+                                                  missing.iter()
+                                                         .map(ToString::to_string)
+                                                         .collect()));
+        }
+
+        for path in &found {
+            offline::install_artifact(cfg,
+                                      path,
+                                      self.workspace.artifacts(),
+                                      self.workspace.key_path())?;
+        }
+        streamer.println_stdout(format!("Installed {} dependencies from the artifact cache {}",
+                                        found.len(),
+                                        cfg.artifact_cache.display()))?;
+        Ok(())
+    }
+
+    /// Computes the job's build cache key and asks the depot for a package already built under
+    /// it. On a hit the job's package ident is set to that package and it is promoted into the
+    /// job's channel so later projects in the group resolve it exactly as if it had been built.
+    fn check_build_cache(&mut self, streamer: &mut JobStreamer) -> Result<bool> {
+        let deps = match self.resolve_direct_dependencies()? {
+            Some(deps) => deps,
+            None => {
+                streamer.println_stdout("Unable to resolve dependencies from a previous \
+                                         release, skipping build cache")?;
+                return Ok(false);
+            }
+        };
+
+        let project = self.job().get_project();
+        let root = self.workspace
+                       .src()
+                       .join(studio::build_path(project.get_plan_path()));
+        let plan_digest = build_cache::plan_digest(&root)?;
+        let target = PackageTarget::from_str(self.job().get_target())?;
+        let key = build_cache::cache_key(project.get_name(), target, &plan_digest, &deps);
+        streamer.println_stdout(format!("Build cache key: {}", key))?;
+
+        let package =
+            self.depot_cli
+                .show_build_cache_package(&key, self.job().get_target(), Some(&self.bldr_token))?;
+        self.build_cache_key = Some(key);
+
+        let ident: PackageIdent = match package {
+            Some(package) => package.ident.into(),
+            None => {
+                streamer.println_stdout("No cached build found, building")?;
+                return Ok(false);
+            }
+        };
+        if ident.origin != project.get_origin_name() || ident.name != project.get_package_name() {
+            warn!("Build cache key {:?} of {} names {}, ignoring it",
+                  self.build_cache_key,
+                  project.get_name(),
+                  ident);
+            streamer.println_stdout(format!("Cached build {} is not a release of {}, building",
+                                            ident,
+                                            project.get_name()))?;
+            return Ok(false);
+        }
+
+        if self.config.auto_publish && self.job().has_channel() {
+            let channel = ChannelIdent::from(self.job().get_channel());
+            if channel != ChannelIdent::stable() && channel != ChannelIdent::unstable() {
+                self.depot_cli
+                    .create_channel(&ident.origin, &channel, &self.bldr_token)?;
+            }
+            self.depot_cli
+                .promote_package((&ident, target), &channel, &self.bldr_token)?;
+        }
+
+        streamer.println_stdout(format!("Found cached build {}, skipping build", ident))?;
+        self.workspace
+            .job
+            .set_package_ident(OriginPackageIdent::from(ident));
+        Ok(true)
+    }
+
+    /// Resolves the project's direct runtime and build dependencies, as recorded on its latest
+    /// release, to the idents a build would install right now. Each dependency is resolved both
+    /// by name and by its recorded version so that a new release matching either a version pin
+    /// or an unpinned dependency changes the result. Returns `None` if the project has no
+    /// release to take dependencies from or a dependency cannot be resolved.
+    fn resolve_direct_dependencies(&self) -> Result<Option<Vec<PackageIdent>>> {
+        let project = self.job().get_project();
+        let ident = PackageIdent::new(project.get_origin_name(),
+                                      project.get_package_name(),
+                                      None,
+                                      None);
+        let channel = if self.job().has_channel() {
+            ChannelIdent::from(self.job().get_channel())
+        } else {
+            ChannelIdent::stable()
+        };
+        let target = self.job().get_target();
+
+        let package = match self.show_package_with_fallback(&ident, &channel)? {
+            Some(package) => package,
+            None => return Ok(None),
+        };
+
+        let mut resolved: Vec<PackageIdent> = Vec::new();
+        for dep in package.deps.into_iter().chain(package.build_deps) {
+            let dep: PackageIdent = dep.into();
+            let by_name = PackageIdent::new(dep.origin.clone(), dep.name.clone(), None, None);
+            let by_version = PackageIdent::new(dep.origin.clone(),
+                                               dep.name.clone(),
+                                               dep.version.clone(),
+                                               None);
+            for spec in &[by_name, by_version] {
+                match self.show_package_with_fallback(spec, &channel)? {
+                    Some(package) => resolved.push(package.ident.into()),
+                    None => {
+                        debug!("Unable to resolve {} for {} in {}", spec, target, channel);
+                        return Ok(None);
+                    }
+                }
+            }
+        }
+
+        Ok(Some(resolved))
+    }
+
+    // Mirrors the studio, which installs from the job channel and falls back to stable
+    fn show_package_with_fallback(&self,
+                                  ident: &PackageIdent,
+                                  channel: &ChannelIdent)
+                                  -> Result<Option<bldr_core::api_client::Package>> {
+        let target = self.job().get_target();
+        let mut channels = vec![channel.clone()];
+        if *channel != ChannelIdent::stable() {
+            channels.push(ChannelIdent::stable());
+        }
+
+        for channel in channels {
+            match self.depot_cli
+                      .show_package(ident, &channel, target, Some(&self.bldr_token))
+            {
+                Ok(package) => return Ok(Some(package)),
+                Err(bldr_core::Error::ApiError(code, _)) if code.as_u16() == 404 => continue,
+                Err(err) => return Err(Error::BuilderCore(err)),
+            }
+        }
+
+        Ok(None)
+    }
+
     fn build(&mut self,
              target: PackageTarget,
              streamer: &mut JobStreamer,
@@ -392,8 +754,11 @@ impl Runner {
         let studio = Studio::new(&self.workspace,
                                  &self.config.bldr_url,
                                  &self.bldr_token,
-                                 target);
-        clean_container();
+                                 target,
+                                 self.config.offline_mode.enabled);
+        if studio::docker_studio(target) {
+            clean_container();
+        }
 
         let mut child = studio.build(streamer)?;
         loop {
@@ -426,7 +791,9 @@ impl Runner {
                 Ok(None) => {
                     if self.is_canceled() {
                         debug!("Canceling job: {}", self.job().get_id());
-                        clean_container();
+                        if studio::docker_studio(target) {
+                            clean_container();
+                        }
                         if let Err(err) = child.kill() {
                             debug!("Failed to kill child, err: {:?}", err);
                         }
@@ -447,6 +814,15 @@ impl Runner {
     }
 
     fn export(&mut self, streamer: &mut JobStreamer) -> Result<()> {
+        // The exporter installs the package from the depot
+        if self.config.offline_mode.enabled {
+            if self.has_docker_integration() {
+                streamer.println_stderr("Skipping the docker export of the origin's docker \
+                                         integration, the exporter can't run in offline mode")?;
+            }
+            return Ok(());
+        }
+
         if self.has_docker_integration() {
             let pkg_target = target::PackageTarget::from_str(self.workspace.job.get_target())?;
             match pkg_target {
@@ -489,6 +865,18 @@ impl Runner {
         self.logger.log_worker_job(&self.workspace.job);
     }
 
+    fn complete_from_cache(&mut self) {
+        self.workspace.job.set_state(JobState::CompletedFromCache);
+        self.logger.log_worker_job(&self.workspace.job);
+    }
+
+    fn complete_unpromoted(&mut self) {
+        self.workspace
+            .job
+            .set_state(JobState::PostProcessVerificationFailed);
+        self.logger.log_worker_job(&self.workspace.job);
+    }
+
     fn fail(&mut self, err: net::NetError) {
         self.teardown();
         self.workspace.job.set_state(JobState::Failed);
@@ -520,6 +908,16 @@ impl Runner {
                                              err));
         }
 
+        debug!("Creating workspace artifacts directory: {}",
+               self.workspace.artifacts().display());
+        if let Some(err) = fs::create_dir_all(self.workspace.artifacts()).err() {
+            return Err(Error::WorkspaceSetup(format!("{}",
+                                                     self.workspace
+                                                         .artifacts()
+                                                         .display()),
+                                             err));
+        }
+
         debug!("Creating workspace keys directory: {}",
                self.workspace.key_path().display());
         if let Some(err) = fs::create_dir_all(self.workspace.key_path()).err() {
@@ -530,6 +928,12 @@ impl Runner {
                                              err));
         }
 
+        // Local secrets are added before the streamer, so they are scrubbed from the log too
+        if self.config.offline_mode.enabled {
+            let secrets = offline::local_secrets(&self.config.offline_mode, self.job().origin())?;
+            offline::merge_secrets(&mut self.workspace.job, secrets);
+        }
+
         Ok(JobStreamer::new(&self.workspace))
     }
 
@@ -556,6 +960,11 @@ impl Runner {
                       self.workspace.src().display(),
                       err);
             }
+            if let Some(err) = fs::remove_dir_all(self.workspace.artifacts()).err() {
+                warn!("Failed to remove artifacts dir {}, err: {:?}",
+                      self.workspace.artifacts().display(),
+                      err);
+            }
         }
     }
 
@@ -569,6 +978,18 @@ impl Runner {
     }
 }
 
+// The error label of a job whose dependencies could not be verified names the dependency at
+// fault, so that it shows in the job status without reading the log
+fn dependency_error_label(err: &Error) -> String {
+    match *err {
+        Error::DependencyNotFound(ref ident, _) => format!("wk:run:deps:missing:{}", ident),
+        Error::DependencyVerificationFailed(ref ident, _) => {
+            format!("wk:run:deps:verify:{}", ident)
+        }
+        _ => "wk:run:deps:fetch".to_string(),
+    }
+}
+
 fn clean_container() {
     let mut cmd = Command::new(&"docker");
     cmd.arg("rm");
@@ -775,4 +1196,20 @@ mod tests {
         let job = Job::new(inner);
         assert_eq!(job.origin(), "core");
     }
+
+    #[test]
+    fn dependency_error_labels_name_the_dependency() {
+        let missing = Error::DependencyNotFound("core/zlib".to_string(), "unstable".to_string());
+        assert_eq!(dependency_error_label(&missing),
+                   "wk:run:deps:missing:core/zlib");
+
+        let tampered =
+            Error::DependencyVerificationFailed("core/zlib/1.2.11/20190115003728".to_string(),
+                                                "bad signature".to_string());
+        assert_eq!(dependency_error_label(&tampered),
+                   "wk:run:deps:verify:core/zlib/1.2.11/20190115003728");
+
+        assert_eq!(dependency_error_label(&Error::JobCanceled),
+                   "wk:run:deps:fetch");
+    }
 }
@@ -0,0 +1,587 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Builds of a worker in offline mode. The dependencies a plan names are resolved against the
+//! artifact cache alone, and a job missing any fails with the list of them before the studio
+//! starts. Origin secret keys and secrets come from local directories, and the artifact built
+//! is written to the outbox with a manifest rather than uploaded, and to the artifact cache for
+//! the jobs that depend on it.
+
+use std::{fmt,
+          fs,
+          path::{Path,
+                 PathBuf},
+          str::FromStr};
+
+use chrono::Utc;
+use protobuf::RepeatedField;
+
+use crate::{bldr_core::job::Job,
+            config::OfflineCfg,
+            error::{Error,
+                    Result},
+            hab_core::{crypto::{artifact,
+                                keys::parse_name_with_rev},
+                       package::{archive::PackageArchive,
+                                 Identifiable,
+                                 PackageIdent,
+                                 PackageTarget}},
+            protocol::originsrv::{OriginSecret,
+                                  OriginSecretDecrypted}};
+
+// The plan arrays naming the dependencies the studio installs
+const DEPS_ARRAYS: &[&str] = &["pkg_deps", "pkg_build_deps"];
+
+/// The dependencies `plan` names in its `pkg_deps` and `pkg_build_deps`, of a plan.sh or a
+/// plan.ps1. Entries built from variables can't be resolved without running the plan and are
+/// left out.
+pub fn plan_deps(plan: &str) -> Vec<String> {
+    let mut deps: Vec<String> = Vec::new();
+    let mut in_array = false;
+    for line in plan.lines() {
+        let mut line = line.split('#').next().unwrap_or("").trim();
+        if !in_array {
+            let assigned = line.trim_start_matches('$');
+            let rest = DEPS_ARRAYS.iter()
+                                  .filter(|name| assigned.starts_with(*name))
+                                  .map(|name| assigned[name.len()..].trim_start())
+                                  .find(|rest| rest.starts_with('='));
+            let rest = match rest {
+                Some(rest) => rest[1..].trim_start().trim_start_matches('@'),
+                None => continue,
+            };
+            if !rest.starts_with('(') {
+                continue;
+            }
+            line = &rest[1..];
+            in_array = true;
+        }
+
+        let (items, closed) = match line.find(')') {
+            Some(end) => (&line[..end], true),
+            None => (line, false),
+        };
+        for item in items.split(|c: char| c.is_whitespace() || c == ',') {
+            let item = item.trim_matches(|c| c == '"' || c == '\'');
+            if !item.is_empty() && !item.contains('$') && !deps.iter().any(|dep| dep == item) {
+                deps.push(item.to_string());
+            }
+        }
+        in_array = !closed;
+    }
+    deps
+}
+
+/// The plan file of `plan_path`, which names either the file or its directory
+pub fn plan_file(src: &Path, plan_path: &str) -> PathBuf {
+    let path = src.join(plan_path);
+    if path.is_dir() {
+        path.join("plan.sh")
+    } else {
+        path
+    }
+}
+
+struct Entry {
+    ident: PackageIdent,
+    path:  PathBuf,
+    tdeps: Vec<PackageIdent>,
+}
+
+/// The artifacts of the job's target in the artifact cache, read once per job
+pub struct ArtifactCache {
+    entries: Vec<Entry>,
+}
+
+impl ArtifactCache {
+    pub fn open(dir: &Path, target: PackageTarget) -> Result<Self> {
+        let read_dir = fs::read_dir(dir).map_err(|e| Error::OfflineIO(dir.to_path_buf(), e))?;
+        let mut entries = Vec::new();
+        for dir_entry in read_dir {
+            let path = dir_entry.map_err(|e| Error::OfflineIO(dir.to_path_buf(), e))?
+                                .path();
+            if path.extension().map_or(true, |ext| ext != "hart") {
+                continue;
+            }
+
+            let mut archive = PackageArchive::new(&path);
+            match (archive.target(), archive.ident(), archive.tdeps()) {
+                (Ok(t), Ok(ident), Ok(tdeps)) if t == target => {
+                    entries.push(Entry { ident, path, tdeps })
+                }
+                (Ok(_), Ok(_), Ok(_)) => (),
+                (t, ident, tdeps) => {
+                    warn!("Skipping unreadable artifact {} in the artifact cache, {:?}",
+                          path.display(),
+                          t.err().or_else(|| ident.err()).or_else(|| tdeps.err()));
+                }
+            }
+        }
+        Ok(ArtifactCache { entries })
+    }
+
+    // The latest artifact satisfying `spec`
+    fn latest(&self, spec: &PackageIdent) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.ident.satisfies(spec))
+            .max_by(|a, b| {
+                a.ident
+                 .partial_cmp(&b.ident)
+                 .unwrap_or(std::cmp::Ordering::Equal)
+            })
+    }
+
+    fn exact(&self, ident: &PackageIdent) -> Option<&Entry> {
+        self.entries.iter().find(|entry| &entry.ident == ident)
+    }
+}
+
+/// A dependency the artifact cache lacks
+#[derive(Debug, PartialEq)]
+pub struct Missing {
+    pub ident:       String,
+    /// The artifact depending on it, none for a dependency the plan names
+    pub required_by: Option<String>,
+}
+
+impl fmt::Display for Missing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.required_by {
+            Some(ref by) => write!(f, "{}, required by {}", self.ident, by),
+            None => write!(f, "{}, required by the plan", self.ident),
+        }
+    }
+}
+
+/// The artifacts to install for the dependencies `specs` names, with their transitive
+/// dependencies, and those missing from the cache
+pub fn resolve(cache: &ArtifactCache, specs: &[String]) -> (Vec<PathBuf>, Vec<Missing>) {
+    let mut found: Vec<PathBuf> = Vec::new();
+    let mut missing: Vec<Missing> = Vec::new();
+    let mut add_missing = |ident: String, required_by: Option<String>| {
+        if !missing.iter().any(|m| m.ident == ident) {
+            missing.push(Missing { ident, required_by });
+        }
+    };
+
+    for spec in specs {
+        let entry = match PackageIdent::from_str(spec) {
+            Ok(ident) => cache.latest(&ident),
+            Err(_) => None,
+        };
+        let entry = match entry {
+            Some(entry) => entry,
+            None => {
+                add_missing(spec.to_string(), None);
+                continue;
+            }
+        };
+        if found.contains(&entry.path) {
+            continue;
+        }
+        found.push(entry.path.clone());
+
+        // The transitive dependencies of an artifact are exact releases
+        for tdep in &entry.tdeps {
+            match cache.exact(tdep) {
+                Some(dep) if !found.contains(&dep.path) => found.push(dep.path.clone()),
+                Some(_) => (),
+                None => add_missing(tdep.to_string(), Some(entry.ident.to_string())),
+            }
+        }
+    }
+    (found, missing)
+}
+
+/// Verifies the artifact at `path` against its origin public key in the artifact cache and
+/// copies both to the workspace, where the studio installs from
+pub fn install_artifact(cfg: &OfflineCfg,
+                        path: &Path,
+                        artifacts: &Path,
+                        key_path: &Path)
+                        -> Result<()> {
+    let failed = |e: String| Error::DependencyVerificationFailed(path.display().to_string(), e);
+    let header = artifact::get_artifact_header(path).map_err(|e| failed(e.to_string()))?;
+    artifact::verify(path, &cfg.artifact_cache).map_err(|e| failed(e.to_string()))?;
+
+    let key_name = format!("{}.pub", header.key_name);
+    copy(&cfg.artifact_cache.join(&key_name),
+         &key_path.join(&key_name))?;
+    copy(path, &artifacts.join(path.file_name().unwrap_or_default()))
+}
+
+/// Copies the latest secret signing key of `origin` in the key directory to `key_path`
+pub fn install_secret_key(cfg: &OfflineCfg, origin: &str, key_path: &Path) -> Result<PathBuf> {
+    let read_dir =
+        fs::read_dir(&cfg.key_dir).map_err(|e| Error::OfflineIO(cfg.key_dir.clone(), e))?;
+    let mut latest: Option<(String, PathBuf)> = None;
+    for dir_entry in read_dir {
+        let path = dir_entry.map_err(|e| Error::OfflineIO(cfg.key_dir.clone(), e))?
+                            .path();
+        let name = path.file_name()
+                       .and_then(|name| name.to_str())
+                       .unwrap_or_default();
+        if !name.ends_with(".sig.key") {
+            continue;
+        }
+        match parse_name_with_rev(&name[..name.len() - ".sig.key".len()]) {
+            Ok((ref key_origin, ref rev)) if key_origin == origin => {
+                if latest.as_ref().map_or(true, |(latest, _)| rev > latest) {
+                    latest = Some((rev.to_string(), path.clone()));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let (_, path) =
+        latest.ok_or_else(|| Error::OfflineKeyMissing(origin.to_string(), cfg.key_dir.clone()))?;
+    let dst = key_path.join(path.file_name().unwrap_or_default());
+    copy(&path, &dst)?;
+    Ok(dst)
+}
+
+/// The secrets of `origin` in the secrets directory, one per file named after the secret
+pub fn local_secrets(cfg: &OfflineCfg, origin: &str) -> Result<Vec<OriginSecretDecrypted>> {
+    let dir = cfg.secrets_dir.join(origin);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut secrets = Vec::new();
+    for dir_entry in fs::read_dir(&dir).map_err(|e| Error::OfflineIO(dir.clone(), e))? {
+        let path = dir_entry.map_err(|e| Error::OfflineIO(dir.clone(), e))?
+                            .path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if path.is_file() && !name.starts_with('.') => name.to_string(),
+            _ => continue,
+        };
+        let value = fs::read_to_string(&path).map_err(|e| Error::OfflineIO(path.clone(), e))?;
+
+        let mut secret = OriginSecret::new();
+        secret.set_origin(origin.to_string());
+        secret.set_name(name);
+        secret.set_value(value.trim_end_matches(|c| c == '\n' || c == '\r')
+                              .to_string());
+        let mut decrypted = OriginSecretDecrypted::new();
+        decrypted.set_decrypted_secret(secret);
+        secrets.push(decrypted);
+    }
+    Ok(secrets)
+}
+
+/// Adds the `local` secrets to the job's, replacing those of the same name
+pub fn merge_secrets(job: &mut Job, local: Vec<OriginSecretDecrypted>) {
+    let mut secrets: Vec<OriginSecretDecrypted> =
+        job.get_secrets()
+           .iter()
+           .filter(|secret| {
+               let name = secret.get_decrypted_secret().get_name();
+               !local.iter()
+                     .any(|l| l.get_decrypted_secret().get_name() == name)
+           })
+           .cloned()
+           .collect();
+    secrets.extend(local);
+    job.set_secrets(RepeatedField::from_vec(secrets));
+}
+
+#[derive(Serialize)]
+struct Manifest<'a> {
+    job_id:          u64,
+    ident:           String,
+    target:          String,
+    channel:         Option<&'a str>,
+    checksum:        String,
+    build_cache_key: Option<&'a str>,
+    built_at:        String,
+}
+
+/// Copies the artifact built to the outbox along with a `<artifact>.json` manifest of the job,
+/// and to the artifact cache with its origin public key, where later jobs install it from. The
+/// manifest is written last, so an artifact with one is complete.
+pub fn write_to_outbox(cfg: &OfflineCfg,
+                       archive: &mut PackageArchive,
+                       job: &Job,
+                       build_cache_key: Option<&str>)
+                       -> Result<PathBuf> {
+    fs::create_dir_all(&cfg.outbox).map_err(|e| Error::CreateDirectory(cfg.outbox.clone(), e))?;
+
+    let file_name = archive.path.file_name().unwrap_or_default().to_os_string();
+    let dst = cfg.outbox.join(&file_name);
+    copy(&archive.path, &dst)?;
+    let header = artifact::get_artifact_header(&archive.path)?;
+    add_to_cache(cfg, &archive.path, &header.key_name)?;
+
+    let channel = if job.has_channel() {
+        Some(job.get_channel())
+    } else {
+        None
+    };
+    let manifest = Manifest { job_id: job.get_id(),
+                              ident: archive.ident()?.to_string(),
+                              target: archive.target()?.to_string(),
+                              channel,
+                              checksum: archive.checksum()?,
+                              build_cache_key,
+                              built_at: Utc::now().to_rfc3339() };
+    let json = serde_json::to_string_pretty(&manifest).expect("Manifest is always serializable");
+
+    let mut manifest_name = file_name;
+    manifest_name.push(".json");
+    let manifest_path = cfg.outbox.join(manifest_name);
+    let tmp = manifest_path.with_extension("json.tmp");
+    fs::write(&tmp, json).map_err(|e| Error::OfflineIO(tmp.clone(), e))?;
+    fs::rename(&tmp, &manifest_path).map_err(|e| Error::OfflineIO(manifest_path.clone(), e))?;
+    Ok(dst)
+}
+
+// Copies the artifact at `path`, signed with the origin key `key_name`, to the artifact cache.
+// The public key is copied from the key directory first unless the cache has it, as an artifact
+// whose key is missing can't be installed.
+fn add_to_cache(cfg: &OfflineCfg, path: &Path, key_name: &str) -> Result<()> {
+    let cache = &cfg.artifact_cache;
+    fs::create_dir_all(cache).map_err(|e| Error::CreateDirectory(cache.clone(), e))?;
+
+    let key_file = format!("{}.pub", key_name);
+    if !cache.join(&key_file).is_file() {
+        copy_into_place(&cfg.key_dir.join(&key_file), &cache.join(&key_file))?;
+    }
+    copy_into_place(path, &cache.join(path.file_name().unwrap_or_default()))
+}
+
+fn copy(src: &Path, dst: &Path) -> Result<()> {
+    fs::copy(src, dst).map_err(|e| Error::OfflineIO(src.to_path_buf(), e))?;
+    Ok(())
+}
+
+// Copies `src` beside `dst` and renames it, so that `dst` is never read partly written
+fn copy_into_place(src: &Path, dst: &Path) -> Result<()> {
+    let mut tmp_name = dst.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp = dst.with_file_name(tmp_name);
+    copy(src, &tmp)?;
+    fs::rename(&tmp, dst).map_err(|e| Error::OfflineIO(dst.to_path_buf(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn ident(s: &str) -> PackageIdent { PackageIdent::from_str(s).unwrap() }
+
+    fn entry(s: &str, tdeps: &[&str]) -> Entry {
+        Entry { ident: ident(s),
+                path:  PathBuf::from(format!("/cache/{}.hart", s.replace('/', "-"))),
+                tdeps: tdeps.iter().map(|d| ident(d)).collect(), }
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> { values.iter().map(|v| v.to_string()).collect() }
+
+    #[test]
+    fn deps_are_read_from_the_plan_arrays() {
+        let plan = r#"
+pkg_name=widget
+pkg_deps=(core/glibc "core/openssl/1.0.2t")
+pkg_build_deps=(
+  core/make   # for the Makefile
+  'core/gcc'
+  "${pkg_deps[@]}"
+)
+pkg_bin_dirs=(bin)
+"#;
+        assert_eq!(plan_deps(plan),
+                   strings(&["core/glibc", "core/openssl/1.0.2t", "core/make", "core/gcc"]));
+
+        let ps1 = r#"
+$pkg_name="widget"
+$pkg_deps=@("core/visual-cpp-redist-2015", "core/zlib")
+$pkg_build_deps=@(
+    "core/visual-cpp-build-tools-2015"
+)
+"#;
+        assert_eq!(plan_deps(ps1),
+                   strings(&["core/visual-cpp-redist-2015",
+                             "core/zlib",
+                             "core/visual-cpp-build-tools-2015"]));
+    }
+
+    #[test]
+    fn deps_resolve_to_the_latest_artifact_with_its_tdeps() {
+        let cache = ArtifactCache { entries: vec![entry("core/zlib/1.2.11/20190115003728", &[]),
+                                                  entry("core/openssl/1.0.2r/20190305210149",
+                                                        &["core/zlib/1.2.11/20190115003728"]),
+                                                  entry("core/openssl/1.0.2t/20191008201012",
+                                                        &["core/zlib/1.2.11/20190115003728"]),], };
+        let (found, missing) = resolve(&cache, &strings(&["core/openssl", "core/zlib"]));
+        assert_eq!(found,
+                   vec![PathBuf::from("/cache/core-openssl-1.0.2t-20191008201012.hart"),
+                        PathBuf::from("/cache/core-zlib-1.2.11-20190115003728.hart")]);
+        assert!(missing.is_empty());
+    }
+
+    #[test]
+    fn missing_deps_are_reported_with_what_requires_them() {
+        let cache =
+            ArtifactCache { entries: vec![entry("core/openssl/1.0.2t/20191008201012",
+                                                &["core/zlib/1.2.11/20190115003728",
+                                                  "core/cacerts/2019.08.28/20190829172945"]),
+                                          entry("core/cacerts/2019.08.28/20190829172945", &[]),], };
+        let (_, missing) = resolve(&cache,
+                                   &strings(&["core/openssl", "core/make", "not an ident"]));
+        let missing: Vec<String> = missing.iter().map(ToString::to_string).collect();
+
+        let err = Error::MissingDependencies(PathBuf::from("/cache"), missing);
+        assert_eq!(err.to_string(),
+                   "Missing 3 dependencies from the artifact cache /cache:\n    \
+                    core/zlib/1.2.11/20190115003728, required by \
+                    core/openssl/1.0.2t/20191008201012\n    core/make, required by the plan\n    \
+                    not an ident, required by the plan");
+    }
+
+    // An offline config of directories in `dir`
+    fn offline_cfg(dir: &TempDir) -> OfflineCfg {
+        OfflineCfg { enabled:        true,
+                     artifact_cache: dir.path().join("artifacts"),
+                     key_dir:        dir.path().join("keys"),
+                     secrets_dir:    dir.path().join("secrets"),
+                     outbox:         dir.path().join("outbox"), }
+    }
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn plans_are_found_in_their_directory() {
+        let dir = TempDir::new().unwrap();
+        write(&dir.path().join("widget/plan.sh"), "pkg_name=widget");
+        write(&dir.path().join("gadget/plan.ps1"), "$pkg_name=\"gadget\"");
+
+        assert_eq!(plan_file(dir.path(), "widget"),
+                   dir.path().join("widget/plan.sh"));
+        assert_eq!(plan_file(dir.path(), "gadget/plan.ps1"),
+                   dir.path().join("gadget/plan.ps1"));
+    }
+
+    #[test]
+    fn built_artifacts_are_cached_with_their_public_key() {
+        let dir = TempDir::new().unwrap();
+        let cfg = offline_cfg(&dir);
+        let built = dir.path()
+                       .join("results/core-widget-0.1.0-20191021000000-x86_64-linux.hart");
+        write(&built, "hart");
+        write(&cfg.key_dir.join("core-20190101000000.pub"), "public");
+        write(&cfg.key_dir.join("core-20190101000000.sig.key"), "secret");
+
+        add_to_cache(&cfg, &built, "core-20190101000000").unwrap();
+        let cached = cfg.artifact_cache
+                        .join("core-widget-0.1.0-20191021000000-x86_64-linux.hart");
+        assert_eq!(fs::read_to_string(cached).unwrap(), "hart");
+        assert_eq!(fs::read_to_string(cfg.artifact_cache.join("core-20190101000000.pub")).unwrap(),
+                   "public");
+        // Only the artifact and the public key
+        assert_eq!(fs::read_dir(&cfg.artifact_cache).unwrap().count(), 2);
+
+        // A key already in the cache is kept
+        fs::remove_dir_all(&cfg.key_dir).unwrap();
+        assert!(add_to_cache(&cfg, &built, "core-20190101000000").is_ok());
+    }
+
+    #[test]
+    fn artifacts_are_not_cached_without_their_public_key() {
+        let dir = TempDir::new().unwrap();
+        let cfg = offline_cfg(&dir);
+        let built = dir.path()
+                       .join("results/core-widget-0.1.0-20191021000000-x86_64-linux.hart");
+        write(&built, "hart");
+
+        match add_to_cache(&cfg, &built, "core-20190101000000") {
+            Err(Error::OfflineIO(path, _)) => {
+                assert_eq!(path, cfg.key_dir.join("core-20190101000000.pub"))
+            }
+            Err(e) => panic!("Expected OfflineIO, got {:?}", e),
+            Ok(()) => panic!("Expected OfflineIO, the artifact was cached"),
+        }
+        assert_eq!(fs::read_dir(&cfg.artifact_cache).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn the_latest_secret_key_of_the_origin_is_installed() {
+        let dir = TempDir::new().unwrap();
+        let cfg = offline_cfg(&dir);
+        let key_path = dir.path().join("studio-keys");
+        fs::create_dir_all(&key_path).unwrap();
+        for key in &["core-20180101000000.sig.key",
+                     "core-20190101000000.sig.key",
+                     "core-20190101000000.pub",
+                     "core-extras-20200101000000.sig.key"]
+        {
+            write(&cfg.key_dir.join(key), key);
+        }
+
+        let dst = install_secret_key(&cfg, "core", &key_path).unwrap();
+        assert_eq!(dst, key_path.join("core-20190101000000.sig.key"));
+        assert_eq!(fs::read_to_string(dst).unwrap(),
+                   "core-20190101000000.sig.key");
+
+        match install_secret_key(&cfg, "acme", &key_path) {
+            Err(Error::OfflineKeyMissing(origin, _)) => assert_eq!(origin, "acme"),
+            Err(e) => panic!("Expected OfflineKeyMissing, got {:?}", e),
+            Ok(dst) => panic!("Expected OfflineKeyMissing, got {}", dst.display()),
+        }
+    }
+
+    #[test]
+    fn local_secrets_replace_the_jobs() {
+        let dir = TempDir::new().unwrap();
+        let cfg = offline_cfg(&dir);
+        write(&cfg.secrets_dir.join("core/API_TOKEN"), "s3cr3t\n");
+        write(&cfg.secrets_dir.join("core/.DS_Store"), "");
+
+        let local = local_secrets(&cfg, "core").unwrap();
+        assert_eq!(local.len(), 1);
+        assert_eq!(local[0].get_decrypted_secret().get_value(), "s3cr3t");
+        assert!(local_secrets(&cfg, "acme").unwrap().is_empty());
+
+        let secret = |name: &str, value: &str| {
+            let mut secret = OriginSecret::new();
+            secret.set_name(name.to_string());
+            secret.set_value(value.to_string());
+            let mut decrypted = OriginSecretDecrypted::new();
+            decrypted.set_decrypted_secret(secret);
+            decrypted
+        };
+        let mut job = Job::new(Default::default());
+        job.set_secrets(RepeatedField::from_vec(vec![secret("API_TOKEN", "depot"),
+                                                     secret("REGION", "us-west-2")]));
+        merge_secrets(&mut job, local);
+
+        let mut secrets: Vec<(String, String)> =
+            job.get_secrets()
+               .iter()
+               .map(|s| {
+                   (s.get_decrypted_secret().get_name().to_string(),
+                    s.get_decrypted_secret().get_value().to_string())
+               })
+               .collect();
+        secrets.sort();
+        assert_eq!(secrets,
+                   vec![("API_TOKEN".to_string(), "s3cr3t".to_string()),
+                        ("REGION".to_string(), "us-west-2".to_string())]);
+    }
+}
@@ -12,21 +12,36 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::fs;
+
 use crate::{bldr_core::logger::Logger,
             config::Config,
-            error::Result,
+            error::{Error,
+                    Result},
             hab_core::{package::archive::PackageArchive,
                        ChannelIdent}};
 
-use super::{publisher::Publisher,
+use super::{offline,
+            publisher::Publisher,
             workspace::Workspace};
 
 pub fn post_process(archive: &mut PackageArchive,
                     workspace: &Workspace,
                     config: &Config,
+                    build_cache_key: Option<&str>,
                     auth_token: &str,
                     logger: &mut Logger)
                     -> Result<()> {
+    // Offline, the artifact leaves the worker by hand
+    if config.offline_mode.enabled {
+        let dst = offline::write_to_outbox(&config.offline_mode,
+                                           archive,
+                                           &workspace.job,
+                                           build_cache_key)?;
+        logger.log(&format!("Wrote {} to the outbox", dst.display()));
+        return Ok(());
+    }
+
     let channel_opt = if workspace.job.has_channel() {
         Some(ChannelIdent::from(workspace.job.get_channel()))
     } else {
@@ -35,10 +50,15 @@ pub fn post_process(archive: &mut PackageArchive,
 
     let url = config.bldr_url.clone();
 
+    let key_cache = config.data_path.join("key-cache");
+    fs::create_dir_all(&key_cache).map_err(|e| Error::CreateDirectory(key_cache.clone(), e))?;
+
     let mut publisher = Publisher { enabled: config.auto_publish,
                                     url,
-                                    channel_opt };
+                                    channel_opt,
+                                    http_cfg: config.http_client.clone(),
+                                    key_cache };
 
     debug!("Starting post processing");
-    publisher.run(archive, auth_token, logger)
+    publisher.run(archive, build_cache_key, auth_token, logger)
 }
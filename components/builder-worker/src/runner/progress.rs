@@ -0,0 +1,193 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tracks the phase and build step a job is in from its log, so that a long running job can
+//! report where it is to the job server.
+
+use std::time::{Duration,
+                Instant};
+
+use crate::protocol::jobsrv::JobProgress;
+
+/// Progress of a job is reported at most this often
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Step headings longer than this, in characters, are truncated
+const MAX_STEP_CHARS: usize = 256;
+
+pub struct ProgressTracker {
+    job_id:      u64,
+    /// The plan build prefixes its step headings with the package name, e.g. `openssl: `
+    step_prefix: String,
+    /// The log section the job is in and when it started
+    phase:       Option<(String, Instant)>,
+    step:        Option<String>,
+    last_report: Option<Instant>,
+}
+
+impl ProgressTracker {
+    /// `project` is the job's project name, e.g. `core/openssl`
+    pub fn new(job_id: u64, project: &str) -> Self {
+        let name = project.rsplit('/').next().unwrap_or(project);
+        ProgressTracker { job_id,
+                          step_prefix: format!("{}: ", name),
+                          phase: None,
+                          step: None,
+                          last_report: None }
+    }
+
+    pub fn start_phase(&mut self, phase: String, now: Instant) {
+        self.phase = Some((phase, now));
+        self.step = None;
+    }
+
+    pub fn end_phase(&mut self) {
+        self.phase = None;
+        self.step = None;
+    }
+
+    /// Remembers the line as the current build step if it is a step heading of the plan build
+    pub fn observe_line(&mut self, line: &str) {
+        if let Some(step) = step_heading(line, &self.step_prefix) {
+            self.step = Some(step);
+        }
+    }
+
+    /// The progress to report at `now`. Nothing is reported outside of a phase or within
+    /// `REPORT_INTERVAL` of the previous report.
+    pub fn report(&mut self, now: Instant) -> Option<JobProgress> {
+        let (phase, started) = self.phase.as_ref()?;
+        if let Some(last) = self.last_report {
+            if now.duration_since(last) < REPORT_INTERVAL {
+                return None;
+            }
+        }
+        self.last_report = Some(now);
+
+        let mut progress = JobProgress::new();
+        progress.set_job_id(self.job_id);
+        progress.set_phase(phase.to_string());
+        if let Some(ref step) = self.step {
+            progress.set_step(step.to_string());
+        }
+        progress.set_phase_elapsed_secs(now.duration_since(*started).as_secs());
+        Some(progress)
+    }
+}
+
+/// The step named by a plan build heading such as `   openssl: Building`
+fn step_heading(line: &str, prefix: &str) -> Option<String> {
+    let line = strip_ansi(line);
+    let line = line.trim_start();
+    if !line.starts_with(prefix) {
+        return None;
+    }
+
+    let step = line[prefix.len()..].trim();
+    if step.is_empty() {
+        None
+    } else {
+        Some(step.chars().take(MAX_STEP_CHARS).collect())
+    }
+}
+
+/// Removes the terminal control sequences used to color studio output
+fn strip_ansi(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // A sequence ends with its first letter
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_phase_step_and_elapsed_time() {
+        let mut tracker = ProgressTracker::new(42, "core/openssl");
+        let started = Instant::now();
+        tracker.start_phase("build_package".to_string(), started);
+        tracker.observe_line("   openssl: Downloading 'openssl-1.0.2t.tar.gz'");
+        tracker.observe_line("\x1b[1;36m   openssl: \x1b[0mBuilding");
+        tracker.observe_line("gcc -O2 -c ssl.c");
+
+        let progress = tracker.report(started + Duration::from_secs(95)).unwrap();
+        assert_eq!(progress.get_job_id(), 42);
+        assert_eq!(progress.get_phase(), "build_package");
+        assert_eq!(progress.get_step(), "Building");
+        assert_eq!(progress.get_phase_elapsed_secs(), 95);
+    }
+
+    #[test]
+    fn reports_at_most_once_per_interval() {
+        let mut tracker = ProgressTracker::new(42, "core/openssl");
+        let started = Instant::now();
+        tracker.start_phase("clone_repository".to_string(), started);
+        assert!(tracker.report(started).is_some());
+        assert!(tracker.report(started + Duration::from_secs(14)).is_none());
+
+        let restarted = started + Duration::from_secs(14);
+        tracker.start_phase("build_package".to_string(), restarted);
+        assert!(tracker.report(restarted).is_none());
+        let progress = tracker.report(started + REPORT_INTERVAL).unwrap();
+        assert_eq!(progress.get_phase(), "build_package");
+        assert_eq!(progress.get_phase_elapsed_secs(), 1);
+    }
+
+    #[test]
+    fn reports_nothing_outside_a_phase() {
+        let mut tracker = ProgressTracker::new(42, "core/openssl");
+        let started = Instant::now();
+        assert!(tracker.report(started).is_none());
+
+        tracker.start_phase("fetch_origin_key".to_string(), started);
+        tracker.end_phase();
+        assert!(tracker.report(started).is_none());
+    }
+
+    #[test]
+    fn a_new_phase_has_no_step() {
+        let mut tracker = ProgressTracker::new(42, "core/openssl");
+        let started = Instant::now();
+        tracker.start_phase("build_package".to_string(), started);
+        tracker.observe_line("   openssl: Installing");
+        tracker.start_phase("publish_package".to_string(), started);
+
+        assert!(!tracker.report(started).unwrap().has_step());
+    }
+
+    #[test]
+    fn only_headings_of_the_plan_are_steps() {
+        assert_eq!(step_heading("   openssl: Unpacking openssl-1.0.2t.tar.gz", "openssl: "),
+                   Some("Unpacking openssl-1.0.2t.tar.gz".to_string()));
+        assert_eq!(step_heading("   zlib: Building", "openssl: "), None);
+        assert_eq!(step_heading("   openssl: ", "openssl: "), None);
+
+        let long = format!("openssl: {}", "x".repeat(300));
+        let step = step_heading(&long, "openssl: ").unwrap();
+        assert_eq!(step.len(), MAX_STEP_CHARS);
+    }
+}
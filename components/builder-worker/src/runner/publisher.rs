@@ -12,10 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{RETRIES,
+use std::path::PathBuf;
+
+use super::{verifier::DependencyVerifier,
+            RETRIES,
             RETRY_WAIT};
 use crate::{bldr_core::{api_client::ApiClient,
+                        http_client::HttpClientCfg,
                         logger::Logger},
+            config::http_clients,
             error::{Error,
                     Result},
             hab_core::{package::archive::PackageArchive,
@@ -28,11 +33,14 @@ pub struct Publisher {
     pub enabled:     bool,
     pub url:         String,
     pub channel_opt: Option<ChannelIdent>,
+    pub http_cfg:    HttpClientCfg,
+    pub key_cache:   PathBuf,
 }
 
 impl Publisher {
     pub fn run(&mut self,
                archive: &mut PackageArchive,
+               build_cache_key: Option<&str>,
                auth_token: &str,
                logger: &mut Logger)
                -> Result<()> {
@@ -43,12 +51,12 @@ impl Publisher {
         debug!("Publisher (url: {}, channel: {:?})",
                self.url, self.channel_opt);
 
-        let client = ApiClient::new(&self.url)?;
+        let client = ApiClient::with_factory(&self.url, &http_clients(&self.http_cfg)?)?;
         let ident = archive.ident().unwrap();
         let target = archive.target().unwrap();
 
         match retry(delay::Fixed::from(RETRY_WAIT).take(RETRIES), || {
-                  let res = client.x_put_package(archive, auth_token);
+                  let res = client.x_put_package(archive, build_cache_key, auth_token);
                   if let Err(ref err) = res {
                       let msg = format!("Upload {}: {:?}", ident, err);
                       debug!("{}", msg);
@@ -67,6 +75,47 @@ impl Publisher {
         }
 
         if let Some(channel) = &self.channel_opt {
+            // A worker signing with a stale or wrong key must not get the package into a channel
+            let keys = match retry(delay::Fixed::from(RETRY_WAIT).take(RETRIES), || {
+                      let res = client.list_origin_keys(&ident.origin, Some(auth_token));
+                      if let Err(ref err) = res {
+                          let msg = format!("List keys of {}: {:?}", ident.origin, err);
+                          debug!("{}", msg);
+                          logger.log(&msg);
+                      }
+
+                      res
+                  }) {
+                Ok(keys) => keys,
+                Err(err) => {
+                    let msg = format!("Failed to list keys of {} after {} retries",
+                                      ident.origin, RETRIES);
+                    warn!("{}", msg);
+                    logger.log(&msg);
+                    return Err(Error::Retry(err));
+                }
+            };
+
+            let mut verifier = DependencyVerifier::new(&client, Some(auth_token), &self.key_cache);
+            if let Err(err) =
+                verifier.verify_for_promotion(&ident.to_string(), &archive.path, &keys)
+            {
+                let msg = format!("Refusing to promote {} to {}, {}", ident, channel, err);
+                warn!("{}", msg);
+                logger.log(&msg);
+                if let Error::PromotionVerificationFailed(_, ref key_name, ref reason) = err {
+                    if let Err(err) = client.report_verification_failure((&ident, target),
+                                                                         key_name,
+                                                                         reason,
+                                                                         auth_token)
+                    {
+                        warn!("Failed to report the refused promotion of {}, err={:?}",
+                              ident, err);
+                    }
+                }
+                return Err(err);
+            }
+
             if channel != &ChannelIdent::stable() && channel != &ChannelIdent::unstable() {
                 match retry(delay::Fixed::from(RETRY_WAIT).take(RETRIES), || {
                           let res = client.create_channel(&ident.origin, &channel, auth_token);
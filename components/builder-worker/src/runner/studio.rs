@@ -13,7 +13,9 @@ use crate::{error::{Error,
                      DEV_MODE,
                      NONINTERACTIVE_ENVVAR,
                      RUNNER_DEBUG_ENVVAR}};
-use std::{path::PathBuf,
+use std::{ffi::OsString,
+          path::{Path,
+                 PathBuf},
           process::{Child,
                     Command,
                     Stdio},
@@ -48,19 +50,23 @@ pub struct Studio<'a> {
     bldr_url:   &'a str,
     auth_token: &'a str,
     target:     PackageTarget,
+    offline:    bool,
 }
 
 impl<'a> Studio<'a> {
-    /// Creates a new Studio runner for a given `Workspace` and Builder URL.
+    /// Creates a new Studio runner for a given `Workspace` and Builder URL. An `offline` studio
+    /// installs dependencies from the workspace artifacts alone.
     pub fn new(workspace: &'a Workspace,
                bldr_url: &'a str,
                auth_token: &'a str,
-               target: PackageTarget)
+               target: PackageTarget,
+               offline: bool)
                -> Self {
         Studio { workspace,
                  bldr_url,
                  auth_token,
-                 target }
+                 target,
+                 offline }
     }
 
     /// Spawns a Studio build command, pipes output streams to the given `LogPipe` and returns the
@@ -91,15 +97,21 @@ impl<'a> Studio<'a> {
         cmd.env("TERM", "xterm-256color"); // Emits ANSI color codes
 
         // Tells workers to ignore any locally-installed dependencies,
-        // and to always use what's in Builder
-        cmd.env("HAB_FEAT_IGNORE_LOCAL", "true");
+        // and to always use what's in Builder. Offline, what's in the
+        // workspace artifacts is all there is.
+        let feature = if self.offline {
+            "HAB_FEAT_OFFLINE_INSTALL"
+        } else {
+            "HAB_FEAT_IGNORE_LOCAL"
+        };
+        cmd.env(feature, "true");
         // Ideally, we would just pass any `HAB_FEAT_*` flags into the
         // studio directly, since we know they're "ours". Until we do,
         // however, we'll need to prefix it with `HAB_STUDIO_SECRET_`.
         //
         // Follow https://github.com/habitat-sh/habitat/issues/5274
         // for progress on this front.
-        cmd.env("HAB_STUDIO_SECRET_HAB_FEAT_IGNORE_LOCAL", "true");
+        cmd.env(format!("HAB_STUDIO_SECRET_{}", feature), "true");
 
         // TODO JB: remove the HAB_STUDIO_SECRET_HAB_LICENSE line after our (n-1) version exceeds
         // 0.81.0
@@ -108,10 +120,15 @@ impl<'a> Studio<'a> {
 
         cmd.env("HAB_DOCKER_OPTS", "--name builder");
 
+        // The repository credential is only needed for the clone, so the plan never sees it
+        let project = self.workspace.job.get_project();
         for secret in self.workspace.job.get_secrets() {
-            cmd.env(format!("HAB_STUDIO_SECRET_{}",
-                            secret.get_decrypted_secret().get_name()),
-                    secret.get_decrypted_secret().get_value());
+            let secret = secret.get_decrypted_secret();
+            if project.has_vcs_credential() && secret.get_name() == project.get_vcs_credential() {
+                continue;
+            }
+            cmd.env(format!("HAB_STUDIO_SECRET_{}", secret.get_name()),
+                    secret.get_value());
         }
 
         cmd.env("HAB_ORIGIN", self.workspace.job.origin());
@@ -144,21 +161,11 @@ impl<'a> Studio<'a> {
             false
         };
 
-        cmd.arg("studio");
-        cmd.arg("build");
-
-        if !dev_mode {
-            cmd.arg("-D"); // Use Docker studio
-        }
-
-        if self.target == target::X86_64_WINDOWS {
-            cmd.arg("-R"); // Work around a bug so studio does not get removed
-                           // Remove when we fix this (hab 0.75.0 or later)
-            cmd.arg("-k"); // Origin key
-            cmd.arg(self.workspace.job.origin());
-        }
-
-        cmd.arg(build_path(self.workspace.job.get_project().get_plan_path()));
+        cmd.args(build_args(self.target,
+                            dev_mode,
+                            self.workspace.studio(),
+                            self.workspace.job.origin(),
+                            self.workspace.job.get_project().get_plan_path()));
         debug!("building studio build command, cmd={:?}", &cmd);
         debug!("setting studio build command env, {}={}",
                ChannelIdent::ENVVAR,
@@ -185,13 +192,51 @@ impl<'a> Studio<'a> {
         }
 
         debug!("HAB_CACHE_KEY_PATH: {:?}", self.workspace.key_path());
-        cmd.env("NO_ARTIFACT_PATH", "true"); // Disables artifact cache mounting
+        // Mount only the dependency artifacts the worker has already verified
+        cmd.env("ARTIFACT_PATH", self.workspace.artifacts());
         cmd.env("HAB_CACHE_KEY_PATH", self.workspace.key_path()); // Sets key cache to build user's home
 
         Ok(cmd)
     }
 }
 
+/// The arguments of the hab command building the plan at `plan_path` for `target`
+pub fn build_args(target: PackageTarget,
+                  dev_mode: bool,
+                  studio_root: &Path,
+                  origin: &str,
+                  plan_path: &str)
+                  -> Vec<OsString> {
+    let mut args: Vec<OsString> = vec!["studio".into()];
+
+    if !dev_mode && !docker_studio(target) {
+        // Root the native studio in the workspace so teardown removes it. The root is an
+        // option of the studio, not of the build subcommand.
+        args.push("-r".into());
+        args.push(studio_root.into());
+    }
+
+    args.push("build".into());
+
+    if !dev_mode && docker_studio(target) {
+        args.push("-D".into()); // Use Docker studio
+    }
+
+    if target == target::X86_64_WINDOWS {
+        args.push("-R".into()); // Work around a bug so studio does not get removed
+                                // Remove when we fix this (hab 0.75.0 or later)
+        args.push("-k".into()); // Origin key
+        args.push(origin.into());
+    }
+
+    args.push(build_path(plan_path).into());
+    args
+}
+
+/// Whether builds for `target` run in a Docker studio. Studio images are only published for
+/// x86_64, aarch64-linux builds run in a native studio on the worker.
+pub fn docker_studio(target: PackageTarget) -> bool { target != target::AARCH64_LINUX }
+
 /// Returns a path argument suitable to pass to a Studio build command.
 pub fn build_path(plan_path: &str) -> String {
     debug!("Creating build_path from plan_path {}", plan_path);
@@ -217,7 +262,60 @@ pub fn build_path(plan_path: &str) -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::build_path;
+    use super::{build_args,
+                build_path,
+                docker_studio};
+    use crate::hab_core::package::target;
+    use std::{ffi::OsString,
+              path::Path};
+
+    fn args(target: target::PackageTarget, dev_mode: bool) -> Vec<OsString> {
+        build_args(target,
+                   dev_mode,
+                   Path::new("/hab/svc/builder-worker/data/studio"),
+                   "core",
+                   "redis/plan.sh")
+    }
+
+    fn expected(args: &[&str]) -> Vec<OsString> { args.iter().map(OsString::from).collect() }
+
+    #[test]
+    fn native_studio_is_rooted_before_the_build_subcommand() {
+        assert_eq!(args(target::AARCH64_LINUX, false),
+                   expected(&["studio",
+                              "-r",
+                              "/hab/svc/builder-worker/data/studio",
+                              "build",
+                              "redis"]));
+    }
+
+    #[test]
+    fn docker_studio_is_requested_for_x86_64_linux() {
+        assert_eq!(args(target::X86_64_LINUX, false),
+                   expected(&["studio", "build", "-D", "redis"]));
+    }
+
+    #[test]
+    fn windows_builds_keep_the_studio_and_sign_with_the_origin() {
+        assert_eq!(args(target::X86_64_WINDOWS, false),
+                   expected(&["studio", "build", "-D", "-R", "-k", "core", "redis"]));
+    }
+
+    #[test]
+    fn dev_mode_uses_the_default_studio() {
+        assert_eq!(args(target::X86_64_LINUX, true),
+                   expected(&["studio", "build", "redis"]));
+        assert_eq!(args(target::AARCH64_LINUX, true),
+                   expected(&["studio", "build", "redis"]));
+    }
+
+    #[test]
+    fn docker_studio_for_x86_64_targets_only() {
+        assert!(docker_studio(target::X86_64_LINUX));
+        assert!(docker_studio(target::X86_64_LINUX_KERNEL2));
+        assert!(docker_studio(target::X86_64_WINDOWS));
+        assert!(!docker_studio(target::AARCH64_LINUX));
+    }
 
     #[test]
     fn build_path_with_plan_sh() {
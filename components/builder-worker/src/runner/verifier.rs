@@ -0,0 +1,254 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Verifies dependency artifacts fetched from the depot against their origin's public signing
+//! key before they are made available to the studio, and the artifact a job built before it is
+//! promoted.
+
+use std::{fs,
+          path::{Path,
+                 PathBuf}};
+
+use crate::{bldr_core::api_client::{ApiClient,
+                                    OriginKey},
+            error::{Error,
+                    Result},
+            hab_core::crypto::{artifact,
+                               keys::parse_name_with_rev}};
+
+/// Counters reported in the build log once verification is complete
+#[derive(Debug, Default)]
+pub struct VerifyStats {
+    pub verified:   usize,
+    pub cache_hits: usize,
+}
+
+pub struct DependencyVerifier<'a> {
+    depot_cli: &'a ApiClient,
+    token:     Option<&'a str>,
+    key_cache: PathBuf,
+    stats:     VerifyStats,
+}
+
+impl<'a> DependencyVerifier<'a> {
+    pub fn new<P>(depot_cli: &'a ApiClient, token: Option<&'a str>, key_cache: P) -> Self
+        where P: AsRef<Path>
+    {
+        DependencyVerifier { depot_cli,
+                             token,
+                             key_cache: key_cache.as_ref().to_path_buf(),
+                             stats: VerifyStats::default() }
+    }
+
+    pub fn stats(&self) -> &VerifyStats { &self.stats }
+
+    /// Verifies the signature of the artifact at `path` against the public key revision named
+    /// in its header. Key revisions are pinned in the cache, but a cached key that fails
+    /// verification is refreshed from the depot once before the artifact is rejected, which
+    /// covers keys that were re-uploaded during a rotation.
+    pub fn verify(&mut self, ident: &str, path: &Path) -> Result<()> {
+        let header = artifact::get_artifact_header(path).map_err(|e| {
+                         Error::DependencyVerificationFailed(ident.to_string(), e.to_string())
+                     })?;
+        let (origin, revision) = parse_name_with_rev(&header.key_name).map_err(|e| {
+                                     Error::DependencyVerificationFailed(ident.to_string(),
+                                                                         e.to_string())
+                                 })?;
+
+        let cached = self.fetch_key(ident, &origin, &revision, &header.key_name, false)?;
+        match artifact::verify(path, &self.key_cache) {
+            Ok(_) => (),
+            Err(err) if cached => {
+                debug!("Failed to verify {} with cached key {}, refreshing, err={}",
+                       ident, header.key_name, err);
+                self.fetch_key(ident, &origin, &revision, &header.key_name, true)
+                    .map_err(|e| {
+                        Error::DependencyVerificationFailed(ident.to_string(),
+                                                            format!("{} ({})", err, e))
+                    })?;
+                artifact::verify(path, &self.key_cache).map_err(|e| {
+                    Error::DependencyVerificationFailed(ident.to_string(), e.to_string())
+                })?;
+            }
+            Err(err) => {
+                return Err(Error::DependencyVerificationFailed(ident.to_string(),
+                                                               err.to_string()));
+            }
+        }
+
+        self.stats.verified += 1;
+        Ok(())
+    }
+
+    /// Verifies the artifact at `path`, built by this worker, before it is promoted. Besides its
+    /// signature, the key that made it must be among `keys`, those of the origin, and not have
+    /// expired: the current key, or one rotated out whose overlap window is still open.
+    pub fn verify_for_promotion(&mut self,
+                                ident: &str,
+                                path: &Path,
+                                keys: &[OriginKey])
+                                -> Result<()> {
+        let failed = |key_name: &str, reason: String| {
+            Error::PromotionVerificationFailed(ident.to_string(), key_name.to_string(), reason)
+        };
+        let key_name = match artifact::get_artifact_header(path) {
+            Ok(header) => header.key_name,
+            Err(err) => return Err(failed("", err.to_string())),
+        };
+        let (origin, revision) =
+            parse_name_with_rev(&key_name).map_err(|e| failed(&key_name, e.to_string()))?;
+
+        let refused = match keys.iter()
+                                .find(|key| key.origin == origin && key.revision == revision)
+        {
+            Some(key) if !key.expired => None,
+            Some(_) => Some("the signing key was rotated out and its overlap window has closed"),
+            None => Some("the signing key is not a key of the origin"),
+        };
+        if let Some(reason) = refused {
+            return Err(failed(&key_name, reason.to_string()));
+        }
+
+        match self.verify(ident, path) {
+            Err(Error::DependencyVerificationFailed(_, reason)) => Err(failed(&key_name, reason)),
+            result => result,
+        }
+    }
+
+    // Ensures the public key is present in the cache, returning whether it was a cache hit
+    fn fetch_key(&mut self,
+                 ident: &str,
+                 origin: &str,
+                 revision: &str,
+                 key_name: &str,
+                 refresh: bool)
+                 -> Result<bool> {
+        let key_path = self.key_cache.join(format!("{}.pub", key_name));
+        if key_path.exists() {
+            if !refresh {
+                self.stats.cache_hits += 1;
+                return Ok(true);
+            }
+            if let Err(err) = fs::remove_file(&key_path) {
+                warn!("Failed to remove cached key {}, err={}",
+                      key_path.display(),
+                      err);
+            }
+        }
+
+        self.depot_cli
+            .fetch_origin_public_key(origin, revision, self.token, &self.key_cache)
+            .map_err(|e| {
+                Error::DependencyVerificationFailed(ident.to_string(),
+                                                    format!("unable to fetch public key {}, {}",
+                                                            key_name, e))
+            })?;
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env,
+              process};
+
+    const CORRUPTED: &str = "core-corrupted-1.0.0-20190101000000-x86_64-linux.hart";
+    const PUBLIC_KEY: &str = "core-20190101000000.pub";
+
+    // Signed with the previous key, which was rotated out for the current one
+    const OVERLAP: &str = "core-overlap-1.0.0-20191002000000-x86_64-linux.hart";
+    // Names the current key in its header but is signed with the previous one
+    const MISSIGNED: &str = "core-missigned-1.0.0-20191002000000-x86_64-linux.hart";
+    const PREVIOUS_REVISION: &str = "20190401000000";
+    const CURRENT_REVISION: &str = "20191001000000";
+
+    fn fixtures() -> PathBuf { PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures") }
+
+    fn origin_keys(previous_expired: bool) -> Vec<OriginKey> {
+        vec![OriginKey { origin:   "core".to_string(),
+                         revision: CURRENT_REVISION.to_string(),
+                         expired:  false, },
+             OriginKey { origin:   "core".to_string(),
+                         revision: PREVIOUS_REVISION.to_string(),
+                         expired:  previous_expired, },]
+    }
+
+    // Verifies the fixture for promotion with both keys of the origin already in the cache
+    fn verify_for_promotion(test: &str, artifact: &str, keys: &[OriginKey]) -> Result<()> {
+        let key_cache = env::temp_dir().join(format!("bldr-worker-{}-{}", test, process::id()));
+        fs::create_dir_all(&key_cache).unwrap();
+        for revision in &[PREVIOUS_REVISION, CURRENT_REVISION] {
+            let key = format!("core-{}.pub", revision);
+            fs::copy(fixtures().join(&key), key_cache.join(&key)).unwrap();
+        }
+
+        let depot_cli = ApiClient::new("http://127.0.0.1:1").unwrap();
+        let mut verifier = DependencyVerifier::new(&depot_cli, None, &key_cache);
+        let result = verifier.verify_for_promotion("core/test/1.0.0/20191002000000",
+                                                   &fixtures().join(artifact),
+                                                   keys);
+        fs::remove_dir_all(&key_cache).unwrap();
+        result
+    }
+
+    fn assert_refused(result: Result<()>, expected_key: &str) {
+        match result {
+            Err(Error::PromotionVerificationFailed(_, key_name, _)) => {
+                assert_eq!(key_name, expected_key)
+            }
+            other => panic!("Expected promotion to be refused, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn corrupted_artifact_fails_verification() {
+        let key_cache = env::temp_dir().join(format!("bldr-worker-key-cache-{}", process::id()));
+        fs::create_dir_all(&key_cache).unwrap();
+        fs::copy(fixtures().join(PUBLIC_KEY), key_cache.join(PUBLIC_KEY)).unwrap();
+
+        // The depot is unreachable so the key refresh after the first failure also fails
+        let depot_cli = ApiClient::new("http://127.0.0.1:1").unwrap();
+        let mut verifier = DependencyVerifier::new(&depot_cli, None, &key_cache);
+        let result = verifier.verify("core/corrupted/1.0.0/20190101000000",
+                                     &fixtures().join(CORRUPTED));
+        fs::remove_dir_all(&key_cache).unwrap();
+
+        match result {
+            Err(Error::DependencyVerificationFailed(ident, _)) => {
+                assert_eq!(ident, "core/corrupted/1.0.0/20190101000000")
+            }
+            other => panic!("Expected verification failure, got {:?}", other),
+        }
+        assert_eq!(verifier.stats().verified, 0);
+        assert_eq!(verifier.stats().cache_hits, 1);
+    }
+
+    #[test]
+    fn artifacts_signed_during_the_overlap_window_are_promoted() {
+        let result = verify_for_promotion("overlap", OVERLAP, &origin_keys(false));
+        assert!(result.is_ok(), "Expected promotion, got {:?}", result);
+
+        assert_refused(verify_for_promotion("overlap-closed", OVERLAP, &origin_keys(true)),
+                       "core-20190401000000");
+        assert_refused(verify_for_promotion("overlap-unknown", OVERLAP, &origin_keys(false)[..1]),
+                       "core-20190401000000");
+    }
+
+    #[test]
+    fn mis_signed_artifacts_are_refused() {
+        assert_refused(verify_for_promotion("missigned", MISSIGNED, &origin_keys(false)),
+                       "core-20191001000000");
+    }
+}
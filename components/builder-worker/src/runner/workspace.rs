@@ -26,12 +26,13 @@ use crate::error::{Error,
                    Result};
 
 pub struct Workspace {
-    pub job:  Job,
-    out:      PathBuf,
-    src:      PathBuf,
-    studio:   PathBuf,
-    key_path: PathBuf,
-    root:     PathBuf,
+    pub job:   Job,
+    artifacts: PathBuf,
+    out:       PathBuf,
+    src:       PathBuf,
+    studio:    PathBuf,
+    key_path:  PathBuf,
+    root:      PathBuf,
 }
 
 impl Workspace {
@@ -41,6 +42,7 @@ impl Workspace {
         let root = data_path.as_ref().join(job.get_id().to_string());
         debug!("New workspace, root = {:?}", root);
         Workspace { job,
+                    artifacts: root.join("artifacts"),
                     out: root.join("out"),
                     src: root.join("src"),
                     studio: root.join("studio"),
@@ -71,6 +73,9 @@ impl Workspace {
         }
     }
 
+    /// Directory containing verified dependency artifacts mounted into the studio
+    pub fn artifacts(&self) -> &Path { &self.artifacts }
+
     /// Directory to the output directory containing built artifacts from studio build
     pub fn out(&self) -> &Path { &self.out }
 
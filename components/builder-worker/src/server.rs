@@ -29,11 +29,13 @@ use crate::{bldr_core::{self,
 use crate::{config::Config,
             error::Result,
             feat,
-            heartbeat::{HeartbeatCli,
+            heartbeat::{worker_network,
+                        HeartbeatCli,
                         HeartbeatMgr},
             log_forwarder::LogForwarder,
             runner::{RunnerCli,
-                     RunnerMgr}};
+                     RunnerMgr},
+            vcs};
 
 /// Interval for main thread to check cancel status
 pub const BUILD_CANCEL_WAIT_SECS: u64 = 15;
@@ -62,7 +64,10 @@ impl Server {
     pub fn new(config: Config) -> Self {
         let net_ident = bldr_core::socket::srv_ident();
         let fe_sock = (**DEFAULT_CONTEXT).as_mut().socket(zmq::DEALER).unwrap();
-        let hb_cli = HeartbeatCli::new(net_ident.clone(), config.target.to_string());
+        let hb_cli = HeartbeatCli::new(net_ident.clone(),
+                                       config.target.to_string(),
+                                       config.labels.clone(),
+                                       worker_network(&config.http_client));
         let runner_cli = RunnerCli::new();
         fe_sock.set_identity(net_ident.as_bytes()).unwrap();
         Server { config: Arc::new(config),
@@ -88,6 +93,10 @@ impl Server {
 
         self.enable_features_from_config();
 
+        if let Some(ref ca_bundle) = self.config.http_client.ca_bundle {
+            vcs::trust_ca_bundle(ca_bundle)?;
+        }
+
         HeartbeatMgr::start(&self.config, (&*self.net_ident).clone())?;
         RunnerMgr::start(self.config.clone(), self.net_ident.clone())?;
         LogForwarder::start(&self.config)?;
@@ -150,12 +159,13 @@ impl Server {
     }
 
     fn start_job(&mut self) -> Result<()> {
+        let job_id = message::decode::<jobsrv::Job>(&self.msg)?.get_id();
         self.runner_cli.start_job(&self.msg)?;
         {
             let reply = self.runner_cli.recv_ack()?;
             self.fe_sock.send(reply, 0)?;
         }
-        self.set_busy()?;
+        self.set_busy(job_id)?;
         Ok(())
     }
 
@@ -177,8 +187,8 @@ impl Server {
         Ok(())
     }
 
-    fn set_busy(&mut self) -> Result<()> {
-        self.hb_cli.set_busy()?;
+    fn set_busy(&mut self, job_id: u64) -> Result<()> {
+        self.hb_cli.set_busy(job_id)?;
         self.state = State::Busy;
         Ok(())
     }
@@ -26,6 +26,7 @@ use reqwest::{header::HeaderMap,
               StatusCode};
 
 use builder_core::{http_client::{HttpClient,
+                                 HttpClientCfg,
                                  ACCEPT_GITHUB_JSON,
                                  CONTENT_TYPE_APPLICATION_JSON,
                                  USER_AGENT_BLDR},
@@ -76,25 +77,43 @@ impl AppToken {
 
 #[derive(Clone)]
 pub struct GitHubClient {
-    inner:              HttpClient,
-    pub api_url:        String,
-    app_id:             u32,
-    app_private_key:    String,
-    pub webhook_secret: String,
+    inner:                HttpClient,
+    pub api_url:          String,
+    app_id:               u32,
+    app_private_key:      String,
+    pub webhook_secret:   String,
+    installation_secrets: HashMap<InstallationId, String>,
 }
 
 impl GitHubClient {
     pub fn new(config: GitHubCfg) -> HubResult<Self> {
+        Self::with_http_cfg(config, &HttpClientCfg::default())
+    }
+
+    pub fn with_http_cfg(config: GitHubCfg, http_cfg: &HttpClientCfg) -> HubResult<Self> {
         let header_values = vec![USER_AGENT_BLDR.clone(),
                                  ACCEPT_GITHUB_JSON.clone(),
                                  CONTENT_TYPE_APPLICATION_JSON.clone()];
         let headers = HeaderMap::from_iter(header_values.into_iter());
 
-        Ok(GitHubClient { inner:           HttpClient::new(&config.api_url, headers)?,
-                          api_url:         config.api_url,
-                          app_id:          config.app_id,
-                          app_private_key: config.app_private_key,
-                          webhook_secret:  config.webhook_secret, })
+        Ok(GitHubClient { inner:                HttpClient::with_http_cfg(&config.api_url,
+                                                                          headers,
+                                                                          http_cfg)?,
+                          api_url:              config.api_url,
+                          app_id:               config.app_id,
+                          app_private_key:      config.app_private_key,
+                          webhook_secret:       config.webhook_secret,
+                          installation_secrets: config.installation_secrets
+                                                      .into_iter()
+                                                      .map(|s| (s.installation_id, s.secret))
+                                                      .collect(), })
+    }
+
+    /// The secret the WebHook payloads of an App installation are signed with, the installation's
+    /// own if it has one
+    pub fn webhook_secret_for(&self, installation_id: Option<InstallationId>) -> &str {
+        installation_id.and_then(|id| self.installation_secrets.get(&id))
+                       .unwrap_or(&self.webhook_secret)
     }
 
     pub fn app(&self) -> HubResult<App> {
@@ -23,20 +23,30 @@ pub const DEV_GITHUB_WEBHOOK_SECRET: &str = "58d4afaf5e5617ab0f8c39e505605e78a05
 #[serde(default)]
 pub struct GitHubCfg {
     /// URL to GitHub API
-    pub api_url: String,
+    pub api_url:              String,
     /// Path to GitHub App private key
-    pub app_private_key: String,
+    pub app_private_key:      String,
     /// App Id used for builder integration
-    pub app_id: u32,
+    pub app_id:               u32,
     /// Secret key for validating payloads sent by a GitHub WebHook
-    pub webhook_secret: String,
+    pub webhook_secret:       String,
+    /// Secret keys of App installations that sign their WebHook payloads with their own,
+    /// installations without one use `webhook_secret`
+    pub installation_secrets: Vec<InstallationWebhookSecret>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct InstallationWebhookSecret {
+    pub installation_id: u32,
+    pub secret:          String,
 }
 
 impl Default for GitHubCfg {
     fn default() -> Self {
-        GitHubCfg { api_url:         DEFAULT_GITHUB_API_URL.to_string(),
-                    app_private_key: "/src/.secrets/builder-github-app.pem".to_string(),
-                    app_id:          DEFAULT_GITHUB_APP_ID,
-                    webhook_secret:  DEV_GITHUB_WEBHOOK_SECRET.to_string(), }
+        GitHubCfg { api_url:              DEFAULT_GITHUB_API_URL.to_string(),
+                    app_private_key:      "/src/.secrets/builder-github-app.pem".to_string(),
+                    app_id:               DEFAULT_GITHUB_APP_ID,
+                    webhook_secret:       DEV_GITHUB_WEBHOOK_SECRET.to_string(),
+                    installation_secrets: Vec::new(), }
     }
 }
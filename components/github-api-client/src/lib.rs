@@ -28,7 +28,8 @@ pub mod metrics;
 pub mod types;
 
 pub use crate::{client::{AppToken,
-                         GitHubClient},
+                         GitHubClient,
+                         InstallationId},
                 config::GitHubCfg,
                 error::{HubError,
                         HubResult}};
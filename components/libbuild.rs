@@ -4,12 +4,20 @@ const VERSION_ENVVAR: &'static str = "PLAN_VERSION";
 #[allow(dead_code)]
 mod builder {
     use std::env;
+    use std::fs;
+    use std::path::Path;
     use std::process::Command;
+    use std::time::{SystemTime, UNIX_EPOCH};
 
     use super::VERSION_ENVVAR;
 
+    const GIT_SHA_ENVVAR: &'static str = "PLAN_GIT_SHA";
+
     pub fn common() {
         super::version::write_file(version());
+        super::util::write_out_dir_file("GIT_SHA", git_sha());
+        super::util::write_out_dir_file("BUILD_TIMESTAMP", build_timestamp());
+        rerun_if_changed();
     }
 
     pub fn version() -> String {
@@ -27,6 +35,53 @@ mod builder {
             .expect("Failed to spawn child");
         String::from_utf8_lossy(&child.stdout).into_owned()
     }
+
+    // Plan builds set the commit they build, other builds have the commit as their version
+    fn git_sha() -> String {
+        match env::var(GIT_SHA_ENVVAR) {
+            Ok(sha) => sha,
+            _ => "unknown".to_string(),
+        }
+    }
+
+    // Seconds since the epoch, SOURCE_DATE_EPOCH when set for a reproducible build
+    fn build_timestamp() -> String {
+        match env::var("SOURCE_DATE_EPOCH") {
+            Ok(secs) => secs,
+            _ => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+                .to_string(),
+        }
+    }
+
+    // The version read from git goes stale when the checkout moves to another commit: HEAD
+    // changes on a checkout, the branch it names on a commit
+    fn rerun_if_changed() {
+        for var in &[VERSION_ENVVAR, GIT_SHA_ENVVAR, "SOURCE_DATE_EPOCH"] {
+            println!("cargo:rerun-if-env-changed={}", var);
+        }
+        let git_dir = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap())
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .join(".git");
+        let head = git_dir.join("HEAD");
+        if !head.exists() {
+            return;
+        }
+        println!("cargo:rerun-if-changed={}", head.display());
+        if let Ok(contents) = fs::read_to_string(&head) {
+            if contents.starts_with("ref: ") {
+                let branch = git_dir.join(contents["ref: ".len()..].trim());
+                if branch.exists() {
+                    println!("cargo:rerun-if-changed={}", branch.display());
+                }
+            }
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -14,32 +14,32 @@
 
 use std::iter::FromIterator;
 
-use serde_json;
+use serde_json::{self,
+                 Value};
 
-use reqwest::{header::HeaderMap,
-              Body};
+use reqwest::header::HeaderMap;
 
 use builder_core::http_client::{HttpClient,
                                 ACCEPT_APPLICATION_JSON,
                                 CONTENT_TYPE_FORM_URL_ENCODED};
 
-use crate::{config::OAuth2Cfg,
+use crate::{claims,
+            config::{ClientAuthMethod,
+                     OAuth2Cfg},
             error::{Error,
                     Result},
+            retry,
+            token::{self,
+                    ClientAuth},
             types::*};
 
 pub struct A2;
 
 #[derive(Deserialize)]
 struct AuthOk {
-    pub access_token: String,
-}
-
-#[derive(Deserialize)]
-struct User {
-    pub sub:                String,
-    pub preferred_username: String,
-    pub email:              Option<String>,
+    pub access_token:  String,
+    pub refresh_token: Option<String>,
+    pub expires_in:    Option<u64>,
 }
 
 impl A2 {
@@ -47,24 +47,18 @@ impl A2 {
         let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),];
         let headers = HeaderMap::from_iter(header_values.into_iter());
 
-        let mut resp = client.get(&config.userinfo_url)
-                             .headers(headers)
-                             .bearer_auth(token)
-                             .send()
-                             .map_err(Error::HttpClient)?;
+        let mut resp = retry::send(config, || {
+            client.get(&config.userinfo_url)
+                  .headers(headers.clone())
+                  .bearer_auth(token)
+        })?;
 
-        let body = resp.text().map_err(Error::HttpClient)?;
+        let body = resp.text().map_err(Error::http_client)?;
         debug!("A2 response body: {}", body);
 
         if resp.status().is_success() {
-            let user = match serde_json::from_str::<User>(&body) {
-                Ok(msg) => msg,
-                Err(e) => return Err(Error::Serialization(e)),
-            };
-
-            Ok(OAuth2User { id:       user.sub,
-                            username: user.preferred_username,
-                            email:    user.email, })
+            let claims = serde_json::from_str::<Value>(&body).map_err(Error::Serialization)?;
+            claims::user(config, &claims, &[claims::DEFAULT_USERNAME_CLAIM])
         } else {
             Err(Error::HttpResponse(resp.status(), body))
         }
@@ -75,38 +69,42 @@ impl OAuth2Provider for A2 {
     fn authenticate(&self,
                     config: &OAuth2Cfg,
                     client: &HttpClient,
-                    code: &str)
-                    -> Result<(String, OAuth2User)> {
+                    code: &str,
+                    verifier: Option<&str>,
+                    _nonce: Option<&str>)
+                    -> Result<(OAuth2Token, OAuth2User)> {
         let url = config.token_url.to_string();
-        let body = format!("client_id={}&client_secret={}&grant_type=authorization_code&code={}&\
-                            redirect_uri={}",
-                           config.client_id, config.client_secret, code, config.redirect_url);
+        let auth = ClientAuth::configured(config, ClientAuthMethod::Body);
+        let body = token::authorization_code(config, code, verifier, &auth);
 
         let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),
                                  CONTENT_TYPE_FORM_URL_ENCODED.clone()];
         let headers = HeaderMap::from_iter(header_values.into_iter());
 
-        let body: Body = body.into();
-
-        let mut resp = client.post(&url)
-                             .headers(headers)
-                             .body(body)
-                             .send()
-                             .map_err(Error::HttpClient)?;
+        let mut resp = retry::send_token_request(config, || {
+            let req = client.post(&url)
+                            .headers(headers.clone())
+                            .body(body.clone());
+            token::authenticated(config, req, &auth)
+        })?;
 
-        let body = resp.text().map_err(Error::HttpClient)?;
+        let body = resp.text().map_err(Error::http_client)?;
         debug!("A2 response body: {}", body);
 
         let token = if resp.status().is_success() {
             match serde_json::from_str::<AuthOk>(&body) {
-                Ok(msg) => msg.access_token,
+                Ok(msg) => {
+                    OAuth2Token { access_token:  msg.access_token,
+                                  refresh_token: msg.refresh_token,
+                                  expires_in:    msg.expires_in, }
+                }
                 Err(e) => return Err(Error::Serialization(e)),
             }
         } else {
             return Err(Error::HttpResponse(resp.status(), body));
         };
 
-        let user = self.user(config, client, &token)?;
+        let user = self.user(config, client, &token.access_token)?;
         Ok((token, user))
     }
 }
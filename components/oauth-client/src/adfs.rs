@@ -0,0 +1,187 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::iter::FromIterator;
+
+use base64;
+use serde_json;
+
+use reqwest::header::HeaderMap;
+
+use builder_core::http_client::{HttpClient,
+                                ACCEPT_APPLICATION_JSON,
+                                CONTENT_TYPE_FORM_URL_ENCODED};
+
+use crate::{config::{ClientAuthMethod,
+                     OAuth2Cfg},
+            error::{Error,
+                    Result},
+            retry,
+            token::{self,
+                    ClientAuth},
+            types::*};
+
+pub struct AdFs;
+
+#[derive(Deserialize)]
+struct AuthOk {
+    pub access_token:  String,
+    pub refresh_token: Option<String>,
+    pub expires_in:    Option<u64>,
+}
+
+// The claims of an AD FS access token Builder reads. Which of them are issued depends on the
+// claim rules of the relying party, so all are optional.
+#[derive(Deserialize)]
+struct Claims {
+    pub upn:         Option<String>,
+    pub unique_name: Option<String>,
+    pub sub:         Option<String>,
+    pub oid:         Option<String>,
+    pub email:       Option<String>,
+}
+
+impl AdFs {
+    // AD FS has no userinfo endpoint, the user is read from the claims of the access token. The
+    // token comes straight from the token endpoint over TLS, its signature is not checked.
+    fn user(&self, token: &str) -> Result<OAuth2User> {
+        let claims = claims(token)?;
+        let id = claims.sub
+                       .or(claims.oid)
+                       .ok_or_else(|| Error::TokenClaims("no sub or oid claim".to_string()))?;
+        let username = match (claims.upn, claims.unique_name) {
+            (Some(upn), _) => upn,
+            // unique_name is DOMAIN\user, only the user is kept
+            (None, Some(name)) => name.rsplit('\\').next().unwrap_or(&name).to_string(),
+            (None, None) => id.clone(),
+        };
+
+        Ok(OAuth2User { id,
+                        username,
+                        email: claims.email,
+                        email_verified: None,
+                        groups: None })
+    }
+}
+
+fn claims(token: &str) -> Result<Claims> {
+    let payload = token.split('.')
+                       .nth(1)
+                       .ok_or_else(|| Error::TokenClaims("not a JWT".to_string()))?;
+    let json = base64::decode_config(payload.trim_end_matches('='), base64::URL_SAFE_NO_PAD)
+        .map_err(|e| Error::TokenClaims(format!("payload is not base64, {}", e)))?;
+    serde_json::from_slice(&json).map_err(Error::Serialization)
+}
+
+// The token request goes to the farm's /adfs/oauth2/token, the configured token URL
+impl OAuth2Provider for AdFs {
+    fn authenticate(&self,
+                    config: &OAuth2Cfg,
+                    client: &HttpClient,
+                    code: &str,
+                    verifier: Option<&str>,
+                    _nonce: Option<&str>)
+                    -> Result<(OAuth2Token, OAuth2User)> {
+        let url = config.token_url.to_string();
+        let auth = ClientAuth::configured(config, ClientAuthMethod::Body);
+        let body = token::authorization_code(config, code, verifier, &auth);
+
+        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),
+                                 CONTENT_TYPE_FORM_URL_ENCODED.clone()];
+        let headers = HeaderMap::from_iter(header_values.into_iter());
+
+        let mut resp = retry::send_token_request(config, || {
+            let req = client.post(&url)
+                            .headers(headers.clone())
+                            .body(body.clone());
+            token::authenticated(config, req, &auth)
+        })?;
+
+        let body = resp.text().map_err(Error::http_client)?;
+        debug!("AdFs response body: {}", body);
+
+        let token = if resp.status().is_success() {
+            match serde_json::from_str::<AuthOk>(&body) {
+                Ok(msg) => {
+                    OAuth2Token { access_token:  msg.access_token,
+                                  refresh_token: msg.refresh_token,
+                                  expires_in:    msg.expires_in, }
+                }
+                Err(e) => return Err(Error::Serialization(e)),
+            }
+        } else {
+            return Err(Error::token_request(resp.status(), body));
+        };
+
+        let user = self.user(&token.access_token)?;
+        Ok((token, user))
+    }
+
+    fn refresh(&self,
+               config: &OAuth2Cfg,
+               client: &HttpClient,
+               refresh_token: &str)
+               -> Result<OAuth2Token> {
+        token::refresh(config,
+                       client,
+                       &config.token_url,
+                       refresh_token,
+                       ClientAuth::configured(config, ClientAuthMethod::Body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs,
+              path::PathBuf};
+
+    fn fixture(name: &str) -> String {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+                                                            .join(name);
+        fs::read_to_string(path).unwrap().trim().to_string()
+    }
+
+    #[test]
+    fn user_is_read_from_the_access_token() {
+        let user = AdFs.user(&fixture("adfs-access-token.jwt")).unwrap();
+        assert_eq!(user.id, "q4MG5BnMj2c1N6Xf4tG2zQfE+6E3hR0zqkq8o7PB8Ew=");
+        assert_eq!(user.username, "jdoe@example.com");
+        assert_eq!(user.email, Some("jane.doe@example.com".to_string()));
+    }
+
+    #[test]
+    fn optional_claims_may_be_missing() {
+        let user = AdFs.user(&fixture("adfs-access-token-no-upn.jwt")).unwrap();
+        assert_eq!(user.id, "5f1d1a7e-4b2a-4c3e-9a61-0d2b6a8c1e44");
+        assert_eq!(user.username, "jdoe");
+        assert_eq!(user.email, None);
+    }
+
+    #[test]
+    fn tokens_without_an_id_are_refused() {
+        match AdFs.user(&fixture("adfs-access-token-no-id.jwt")) {
+            Err(Error::TokenClaims(_)) => (),
+            Err(e) => panic!("Expected a claims error, got {:?}", e),
+            Ok(user) => panic!("Expected a claims error, got user {}", user.username),
+        }
+    }
+
+    #[test]
+    fn malformed_tokens_are_refused() {
+        assert!(AdFs.user("not-a-jwt").is_err());
+        assert!(AdFs.user("header.!!!.signature").is_err());
+        assert!(AdFs.user("header.bm90IGpzb24.signature").is_err());
+    }
+}
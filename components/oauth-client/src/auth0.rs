@@ -0,0 +1,226 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::iter::FromIterator;
+
+use serde_json::{self,
+                 Value};
+
+use reqwest::{header::HeaderMap,
+              StatusCode};
+
+use builder_core::http_client::{HttpClient,
+                                ACCEPT_APPLICATION_JSON,
+                                CONTENT_TYPE_FORM_URL_ENCODED};
+
+use crate::{claims,
+            config::{ClientAuthMethod,
+                     OAuth2Cfg},
+            error::{Error,
+                    Result},
+            retry,
+            token::{self,
+                    ClientAuth},
+            types::*};
+
+pub struct Auth0;
+
+#[derive(Deserialize)]
+struct AuthOk {
+    pub access_token:  String,
+    pub refresh_token: Option<String>,
+    pub expires_in:    Option<u64>,
+}
+
+// Auth0 has no preferred_username, and the nickname and name are picked by the user, so a user
+// is named by their verified email, or their subject without one, unless a claim is configured
+fn user_of(config: &OAuth2Cfg, claims: &Value) -> Result<OAuth2User> {
+    let mut user = claims::user(config, claims, &[claims::DEFAULT_ID_CLAIM])?;
+
+    // Anyone can sign up to a database connection with an address they don't own
+    if user.email_verified == Some(false) {
+        let msg = format!("Auth0 user {} has not verified their email address",
+                          user.id);
+        return Err(Error::HttpResponse(StatusCode::FORBIDDEN, msg));
+    }
+
+    if config.username_claim.is_none() {
+        user.username = claims::handle(&user.id,
+                                       user.email.as_ref().map(String::as_str),
+                                       user.email_verified)?;
+    }
+    Ok(user)
+}
+
+impl Auth0 {
+    fn user(&self, config: &OAuth2Cfg, client: &HttpClient, token: &str) -> Result<OAuth2User> {
+        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),];
+        let headers = HeaderMap::from_iter(header_values.into_iter());
+
+        let mut resp = retry::send(config, || {
+            client.get(&config.userinfo_url)
+                  .headers(headers.clone())
+                  .bearer_auth(token)
+        })?;
+
+        let body = resp.text().map_err(Error::http_client)?;
+        debug!("Auth0 response body: {}", body);
+
+        if resp.status().is_success() {
+            let claims = serde_json::from_str::<Value>(&body).map_err(Error::Serialization)?;
+            user_of(config, &claims)
+        } else {
+            Err(Error::HttpResponse(resp.status(), body))
+        }
+    }
+}
+
+// The token request goes to the tenant's /oauth/token and the user is read from its /userinfo,
+// both taken from the configured token and userinfo URLs
+impl OAuth2Provider for Auth0 {
+    fn authenticate(&self,
+                    config: &OAuth2Cfg,
+                    client: &HttpClient,
+                    code: &str,
+                    verifier: Option<&str>,
+                    _nonce: Option<&str>)
+                    -> Result<(OAuth2Token, OAuth2User)> {
+        let url = config.token_url.to_string();
+        let auth = ClientAuth::configured(config, ClientAuthMethod::Body);
+        let body = token::authorization_code(config, code, verifier, &auth);
+
+        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),
+                                 CONTENT_TYPE_FORM_URL_ENCODED.clone()];
+        let headers = HeaderMap::from_iter(header_values.into_iter());
+
+        let mut resp = retry::send_token_request(config, || {
+            let req = client.post(&url)
+                            .headers(headers.clone())
+                            .body(body.clone());
+            token::authenticated(config, req, &auth)
+        })?;
+
+        let body = resp.text().map_err(Error::http_client)?;
+        debug!("Auth0 response body: {}", body);
+
+        let token = if resp.status().is_success() {
+            match serde_json::from_str::<AuthOk>(&body) {
+                Ok(msg) => {
+                    OAuth2Token { access_token:  msg.access_token,
+                                  refresh_token: msg.refresh_token,
+                                  expires_in:    msg.expires_in, }
+                }
+                Err(e) => return Err(Error::Serialization(e)),
+            }
+        } else {
+            return Err(Error::token_request(resp.status(), body));
+        };
+
+        let user = self.user(config, client, &token.access_token)?;
+        Ok((token, user))
+    }
+
+    fn refresh(&self,
+               config: &OAuth2Cfg,
+               client: &HttpClient,
+               refresh_token: &str)
+               -> Result<OAuth2Token> {
+        token::refresh(config,
+                       client,
+                       &config.token_url,
+                       refresh_token,
+                       ClientAuth::configured(config, ClientAuthMethod::Body))
+    }
+
+    fn revoke(&self, config: &OAuth2Cfg, client: &HttpClient, token: &str) -> Result<()> {
+        token::revoke(config,
+                      client,
+                      token,
+                      ClientAuth::configured(config, ClientAuthMethod::Body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::OAuth2Client,
+                test_support::{self,
+                               Reply}};
+    use builder_core::http_client::HttpClientFactory;
+    use serde_json::json;
+
+    const SUB: &str = "auth0|5d8a6b3c2f1e0d0c9b8a7f6e";
+
+    fn config(username_claim: Option<&str>) -> OAuth2Cfg {
+        OAuth2Cfg { provider: "auth0".to_string(),
+                    username_claim: username_claim.map(str::to_string),
+                    ..Default::default() }
+    }
+
+    #[test]
+    fn users_are_named_by_their_verified_email() {
+        let claims = json!({ "sub": SUB, "nickname": "jdoe", "email": "jane.doe@example.com",
+                             "email_verified": true });
+        let user = user_of(&config(None), &claims).unwrap();
+        assert_eq!(user.id, SUB);
+        assert_eq!(user.username, "jane.doe@example.com");
+    }
+
+    #[test]
+    fn nicknames_are_not_usernames() {
+        let claims = json!({ "sub": SUB, "nickname": "admin", "name": "admin" });
+        assert_eq!(user_of(&config(None), &claims).unwrap().username, SUB);
+    }
+
+    #[test]
+    fn a_configured_claim_names_the_user() {
+        let claims = json!({ "sub": SUB, "login": "jdoe",
+                             "email": "jane.doe@example.com", "email_verified": true });
+        let user = user_of(&config(Some("login")), &claims).unwrap();
+        assert_eq!(user.username, "jdoe");
+    }
+
+    #[test]
+    fn users_with_an_unverified_email_are_refused() {
+        let claims = json!({ "sub": SUB, "nickname": "jdoe", "email": "jane.doe@example.com",
+                             "email_verified": false });
+        for config in &[config(None), config(Some("nickname"))] {
+            match user_of(config, &claims) {
+                Err(Error::HttpResponse(status, _)) => assert_eq!(status, StatusCode::FORBIDDEN),
+                Err(e) => panic!("Expected HttpResponse, got {:?}", e),
+                Ok(user) => panic!("Expected HttpResponse, got user {}", user.username),
+            }
+        }
+    }
+
+    #[test]
+    fn revocation_failures_are_returned() {
+        let provider = test_support::serve(|_, _| {
+            Reply::new(400,
+                       r#"{"error":"invalid_request","error_description":"Invalid token"}"#)
+        });
+        let config = OAuth2Cfg { token_url: format!("{}/oauth/token", provider.url),
+                                 revoke_url: format!("{}/oauth/revoke", provider.url),
+                                 ..config(None) };
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let client = OAuth2Client::new(config, &http).unwrap();
+
+        match client.revoke("v1.MRrT") {
+            Err(Error::HttpResponse(status, _)) => assert_eq!(status, StatusCode::BAD_REQUEST),
+            Err(e) => panic!("Expected HttpResponse, got {:?}", e),
+            Ok(()) => panic!("Expected HttpResponse, got Ok"),
+        }
+        assert_eq!(provider.requests(), 1);
+    }
+}
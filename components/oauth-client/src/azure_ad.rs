@@ -14,32 +14,150 @@
 
 use std::iter::FromIterator;
 
-use serde_json;
+use serde_json::{self,
+                 Value};
 
-use reqwest::{header::HeaderMap,
-              Body};
+use reqwest::header::HeaderMap;
 
 use builder_core::http_client::{HttpClient,
                                 ACCEPT_APPLICATION_JSON,
                                 CONTENT_TYPE_FORM_URL_ENCODED};
 
-use crate::{config::OAuth2Cfg,
+use crate::{claims,
+            config::{ClientAuthMethod,
+                     OAuth2Cfg},
             error::{Error,
                     Result},
+            id_token::JwksCache,
+            retry,
+            token::{self,
+                    ClientAuth},
             types::*};
 
-pub struct AzureAD;
+/// Azure AD, now Microsoft Entra ID, through the v2.0 endpoints of the Microsoft identity
+/// platform. The token URL carries the tenant, e.g.
+/// `https://login.microsoftonline.com/{tenant}/oauth2/v2.0/token`, so it is always taken from
+/// the config, as is the authorize URL the UI sends users to. The tenant is either a single
+/// tenant's ID or domain, or `common` or `organizations` for a multi-tenant app. The userinfo URL
+/// is Graph's `/me`, the default one when unset.
+#[derive(Default)]
+pub struct AzureAD {
+    jwks: JwksCache,
+}
+
+const GRAPH_ME_URL: &str = "https://graph.microsoft.com/v1.0/me";
+
+// Graph leaves otherMails out of `/me` unless it is selected
+const GRAPH_ME_SELECT: &str = "$select=id,userPrincipalName,mail,otherMails";
+
+// The tenant in the issuer URL of a multi-tenant app, as Microsoft's discovery document names
+// it. Tokens are issued by each user's own tenant.
+const TENANT_PLACEHOLDER: &str = "{tenantid}";
 
 #[derive(Deserialize)]
 struct AuthOk {
-    pub access_token: String,
-    pub id_token:     String,
+    pub access_token:  String,
+    pub id_token:      Option<String>,
+    pub refresh_token: Option<String>,
+    pub expires_in:    Option<u64>,
 }
 
+// The fields of the Graph `/me` response Builder reads
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct User {
-    pub sub: String,
-    pub upn: String,
+    pub id:                  String,
+    pub user_principal_name: String,
+    pub mail:                Option<String>,
+    /// Guest accounts often have no `mail`, only the address they were invited with here
+    #[serde(default)]
+    pub other_mails:         Vec<String>,
+}
+
+impl From<User> for OAuth2User {
+    fn from(user: User) -> Self {
+        let email = user.mail
+                        .or_else(|| user.other_mails.into_iter().next())
+                        .filter(|email| !email.is_empty());
+        OAuth2User { id: user.id,
+                     username: user.user_principal_name,
+                     email,
+                     email_verified: None,
+                     groups: None }
+    }
+}
+
+// The user of validated ID token claims. They are the same user Graph would answer with: the
+// object ID is their id, as the subject differs from one application to the next, and the UPN
+// their username. Version 2.0 tokens carry the UPN as the preferred_username. A configured
+// `id_claim` or `username_claim` is read instead.
+fn user_from_claims(config: &OAuth2Cfg, claims: &Value) -> Result<OAuth2User> {
+    claims::user_with_id(config,
+                         claims,
+                         "oid",
+                         &["upn", claims::DEFAULT_USERNAME_CLAIM])
+}
+
+// The user of a Graph `/me` response, named by the configured `username_claim` when there is
+// one, e.g. `mail`
+fn user_from_me(config: &OAuth2Cfg, body: &str) -> Result<OAuth2User> {
+    let me = serde_json::from_str::<Value>(body).map_err(Error::Serialization)?;
+    let user = serde_json::from_value::<User>(me.clone()).map_err(Error::Serialization)?;
+    let mut user = OAuth2User::from(user);
+    if let Some(ref path) = config.username_claim {
+        let missing = || Error::TokenClaims(format!("the {} claim is missing", path));
+        user.username = claims::string(&me, path).ok_or_else(missing)?.to_string();
+    }
+    Ok(user)
+}
+
+// The v2.0 token endpoint refuses a request without a scope, the defaults are sent unless
+// others are configured
+fn token_body(config: &OAuth2Cfg, code: &str, verifier: Option<&str>, auth: &ClientAuth) -> String {
+    let body = token::authorization_code(config, code, verifier, auth);
+    match config.scopes {
+        Some(_) => body,
+        None => {
+            format!("{}&scope={}", body, token::scope_param(&config.scopes()))
+        }
+    }
+}
+
+// Graph's `/me` with the fields Builder reads, unless the configured URL selects its own
+fn me_url(config: &OAuth2Cfg) -> String {
+    let url = config.userinfo_url_override().unwrap_or(GRAPH_ME_URL);
+    if url.contains('?') {
+        url.to_string()
+    } else {
+        format!("{}?{}", url, GRAPH_ME_SELECT)
+    }
+}
+
+// The issuer an ID token must come from. A multi-tenant app's tokens come from the tenant named
+// in their `tid` claim, which only picks the issuer; the token is refused unless its signature
+// and `iss` then check out. Tokens of tenants that aren't allowed are refused outright.
+fn issuer(config: &OAuth2Cfg, id_token: &str) -> Result<String> {
+    if !config.issuer_url.contains(TENANT_PLACEHOLDER) && config.allowed_tenants.is_empty() {
+        return Ok(config.issuer_url.clone());
+    }
+    let token = jsonwebtoken::dangerous_insecure_decode::<Value>(id_token)
+        .map_err(|e| Error::JwtValidation(format!("{}", e)))?;
+    let missing = || Error::TokenClaims("the tid claim is missing".to_string());
+    let tenant = claims::string(&token.claims, "tid").ok_or_else(missing)?;
+    if !config.allowed_tenants.is_empty() && !allowed_tenant(config, tenant) {
+        let msg = format!("Azure AD tenant {} is not one of the tenants {}",
+                          tenant,
+                          config.allowed_tenants.join(", "));
+        return Err(Error::AccessDenied(msg));
+    }
+    Ok(config.issuer_url.replace(TENANT_PLACEHOLDER, tenant))
+}
+
+// Tenant IDs are GUIDs, whatever their case
+fn allowed_tenant(config: &OAuth2Cfg, tenant: &str) -> bool {
+    config.allowed_tenants
+          .iter()
+          .any(|allowed| allowed.eq_ignore_ascii_case(tenant))
 }
 
 impl AzureAD {
@@ -47,24 +165,16 @@ impl AzureAD {
         let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),];
         let headers = HeaderMap::from_iter(header_values.into_iter());
 
-        let mut resp = client.get(&config.userinfo_url)
-                             .headers(headers)
-                             .bearer_auth(token)
-                             .send()
-                             .map_err(Error::HttpClient)?;
+        let url = me_url(config);
+        let mut resp = retry::send(config, || {
+            client.get(&url).headers(headers.clone()).bearer_auth(token)
+        })?;
 
-        let body = resp.text().map_err(Error::HttpClient)?;
+        let body = resp.text().map_err(Error::http_client)?;
         debug!("AzureAD response body: {}", body);
 
         if resp.status().is_success() {
-            let user = match serde_json::from_str::<User>(&body) {
-                Ok(msg) => msg,
-                Err(e) => return Err(Error::Serialization(e)),
-            };
-
-            Ok(OAuth2User { id:       user.sub,
-                            username: user.upn,
-                            email:    None, })
+            user_from_me(config, &body)
         } else {
             Err(Error::HttpResponse(resp.status(), body))
         }
@@ -75,38 +185,227 @@ impl OAuth2Provider for AzureAD {
     fn authenticate(&self,
                     config: &OAuth2Cfg,
                     client: &HttpClient,
-                    code: &str)
-                    -> Result<(String, OAuth2User)> {
+                    code: &str,
+                    verifier: Option<&str>,
+                    nonce: Option<&str>)
+                    -> Result<(OAuth2Token, OAuth2User)> {
         let url = config.token_url.to_string();
-        let body = format!("client_id={}&client_secret={}&grant_type=authorization_code&code={}&\
-                            redirect_uri={}",
-                           config.client_id, config.client_secret, code, config.redirect_url);
+        let auth = ClientAuth::configured(config, ClientAuthMethod::Body);
+        let body = token_body(config, code, verifier, &auth);
 
         let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),
                                  CONTENT_TYPE_FORM_URL_ENCODED.clone()];
         let headers = HeaderMap::from_iter(header_values.into_iter());
 
-        let body: Body = body.into();
+        let mut resp = retry::send_token_request(config, || {
+            let req = client.post(&url)
+                            .headers(headers.clone())
+                            .body(body.clone());
+            token::authenticated(config, req, &auth)
+        })?;
 
-        let mut resp = client.post(&url)
-                             .headers(headers)
-                             .body(body)
-                             .send()
-                             .map_err(Error::HttpClient)?;
-
-        let body = resp.text().map_err(Error::HttpClient)?;
+        let body = resp.text().map_err(Error::http_client)?;
         debug!("AzureAD response body: {}", body);
 
-        let token = if resp.status().is_success() {
+        let msg = if resp.status().is_success() {
             match serde_json::from_str::<AuthOk>(&body) {
-                Ok(msg) => msg.access_token,
+                Ok(msg) => msg,
                 Err(e) => return Err(Error::Serialization(e)),
             }
         } else {
-            return Err(Error::HttpResponse(resp.status(), body));
+            return Err(Error::token_request(resp.status(), body));
         };
 
-        let user = self.user(config, client, &token)?;
+        let user = match msg.id_token {
+            Some(ref id_token) if !config.jwks_url.is_empty() => {
+                let issuer = issuer(config, id_token)?;
+                let claims = self.jwks
+                                 .validate(config, client, &issuer, id_token, nonce)?;
+                user_from_claims(config, &claims)?
+            }
+            _ => self.user(config, client, &msg.access_token)?,
+        };
+        let token = OAuth2Token { access_token:  msg.access_token,
+                                  refresh_token: msg.refresh_token,
+                                  expires_in:    msg.expires_in, };
         Ok((token, user))
     }
+
+    fn refresh(&self,
+               config: &OAuth2Cfg,
+               client: &HttpClient,
+               refresh_token: &str)
+               -> Result<OAuth2Token> {
+        token::refresh(config,
+                       client,
+                       &config.token_url,
+                       refresh_token,
+                       ClientAuth::configured(config, ClientAuthMethod::Body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::{fs,
+              path::PathBuf};
+
+    fn me(name: &str) -> OAuth2User {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+                                                            .join(name);
+        let body = fs::read_to_string(path).unwrap();
+        serde_json::from_str::<User>(&body).unwrap().into()
+    }
+
+    #[test]
+    fn user_is_read_from_graph_me() {
+        let user = me("azure-ad-me.json");
+        assert_eq!(user.id, "87d349ed-44d7-43e1-9a83-5f2406dee5bd");
+        assert_eq!(user.username, "jdoe@contoso.onmicrosoft.com");
+        assert_eq!(user.email, Some("jane.doe@contoso.com".to_string()));
+    }
+
+    #[test]
+    fn mail_may_be_missing() {
+        let user = me("azure-ad-me-no-mail.json");
+        assert_eq!(user.username, "jdoe@contoso.onmicrosoft.com");
+        assert_eq!(user.email, None);
+    }
+
+    #[test]
+    fn guests_are_read_with_their_invited_address() {
+        let user = me("azure-ad-me-guest.json");
+        assert_eq!(user.username,
+                   "jane_example.com#EXT#@contoso.onmicrosoft.com");
+        assert_eq!(user.email, Some("jane@example.com".to_string()));
+    }
+
+    #[test]
+    fn id_token_users_are_the_users_graph_answers_with() {
+        let claims = json!({
+            "sub": "AAAAAAAAAAAAAAAAAAAAAIkzqFVrSaSaFHy782bbtaQ",
+            "oid": "87d349ed-44d7-43e1-9a83-5f2406dee5bd",
+            "preferred_username": "jdoe@contoso.onmicrosoft.com"
+        });
+        let user = user_from_claims(&Default::default(), &claims).unwrap();
+        assert_eq!(user.id, "87d349ed-44d7-43e1-9a83-5f2406dee5bd");
+        assert_eq!(user.username, "jdoe@contoso.onmicrosoft.com");
+        assert_eq!(user.email, None);
+    }
+
+    #[test]
+    fn users_are_named_by_the_configured_claim() {
+        let config = OAuth2Cfg { provider: "azure-ad".to_string(),
+                                 username_claim: Some("mail".to_string()),
+                                 ..Default::default() };
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+                                                            .join("azure-ad-me.json");
+        let body = fs::read_to_string(path).unwrap();
+        let user = user_from_me(&config, &body).unwrap();
+        assert_eq!(user.id, "87d349ed-44d7-43e1-9a83-5f2406dee5bd");
+        assert_eq!(user.username, "jane.doe@contoso.com");
+
+        let claims = json!({
+            "oid": "87d349ed-44d7-43e1-9a83-5f2406dee5bd",
+            "preferred_username": "jdoe@contoso.onmicrosoft.com",
+            "email": "jane.doe@contoso.com"
+        });
+        let config = OAuth2Cfg { username_claim: Some("email".to_string()),
+                                 ..config };
+        let user = user_from_claims(&config, &claims).unwrap();
+        assert_eq!(user.username, "jane.doe@contoso.com");
+
+        let config = OAuth2Cfg { username_claim: Some("upn".to_string()),
+                                 ..config };
+        match user_from_claims(&config, &claims) {
+            Err(Error::TokenClaims(msg)) => assert!(msg.contains("upn"), "{}", msg),
+            Err(e) => panic!("Expected TokenClaims, got {:?}", e),
+            Ok(user) => panic!("Expected TokenClaims, got user {}", user.username),
+        }
+    }
+
+    #[test]
+    fn token_request_asks_for_the_openid_scopes() {
+        let config = OAuth2Cfg { provider: "azure-ad".to_string(),
+                                 ..Default::default() };
+        let scope = "&scope=openid+profile+email+User.Read";
+        assert!(token_body(&config, "code", None, &ClientAuth::Form).ends_with(scope));
+
+        let scopes = vec!["openid", "profile", "email", "offline_access"];
+        let config = OAuth2Cfg { scopes: Some(scopes.into_iter().map(String::from).collect()),
+                                 ..config };
+        let scope = "&scope=openid+profile+email+offline_access";
+        assert!(token_body(&config, "code", None, &ClientAuth::Form).ends_with(scope));
+    }
+
+    #[test]
+    fn graph_me_is_asked_for_the_fields_builder_reads() {
+        let graph_me =
+            "https://graph.microsoft.com/v1.0/me?$select=id,userPrincipalName,mail,otherMails";
+        // The GitHub URL the setting defaults to isn't an override
+        let config = OAuth2Cfg { provider: "azure-ad".to_string(),
+                                 ..Default::default() };
+        assert_eq!(me_url(&config), graph_me);
+        let config = OAuth2Cfg { userinfo_url: "".to_string(),
+                                 ..config };
+        assert_eq!(me_url(&config), graph_me);
+
+        let us_gov = "https://graph.microsoft.us/v1.0/me?$select=id";
+        let config = OAuth2Cfg { userinfo_url: us_gov.to_string(),
+                                 ..config };
+        assert_eq!(me_url(&config), us_gov);
+    }
+
+    #[test]
+    fn multi_tenant_tokens_are_issued_by_the_users_tenant() {
+        // Unsigned, the issuer is picked before the token is validated
+        let claims = base64::encode_config(br#"{"tid":"9188040d-6c67-4c5b-b112-36a304b66dad"}"#,
+                                           base64::URL_SAFE_NO_PAD);
+        let id_token = format!("eyJhbGciOiJSUzI1NiJ9.{}.c2ln", claims);
+
+        let multi_tenant = "https://login.microsoftonline.com/{tenantid}/v2.0";
+        let config = OAuth2Cfg { provider: "azure-ad".to_string(),
+                                 issuer_url: multi_tenant.to_string(),
+                                 ..Default::default() };
+        assert_eq!(issuer(&config, &id_token).unwrap(),
+                   "https://login.microsoftonline.com/9188040d-6c67-4c5b-b112-36a304b66dad/v2.0");
+
+        let single_tenant = "https://login.microsoftonline.com/contoso.com/v2.0";
+        let config = OAuth2Cfg { issuer_url: single_tenant.to_string(),
+                                 ..config };
+        assert_eq!(issuer(&config, &id_token).unwrap(), single_tenant);
+    }
+
+    #[test]
+    fn only_the_allowed_tenants_sign_in() {
+        let claims = base64::encode_config(br#"{"tid":"9188040d-6c67-4c5b-b112-36a304b66dad"}"#,
+                                           base64::URL_SAFE_NO_PAD);
+        let id_token = format!("eyJhbGciOiJSUzI1NiJ9.{}.c2ln", claims);
+
+        let multi_tenant = "https://login.microsoftonline.com/{tenantid}/v2.0";
+        let allowed = vec!["72F988BF-86F1-41AF-91AB-2D7CD011DB47".to_string(),
+                           "9188040D-6C67-4C5B-B112-36A304B66DAD".to_string()];
+        let config = OAuth2Cfg { provider: "azure-ad".to_string(),
+                                 issuer_url: multi_tenant.to_string(),
+                                 allowed_tenants: allowed,
+                                 ..Default::default() };
+        assert_eq!(issuer(&config, &id_token).unwrap(),
+                   "https://login.microsoftonline.com/9188040d-6c67-4c5b-b112-36a304b66dad/v2.0");
+
+        let other = vec!["72f988bf-86f1-41af-91ab-2d7cd011db47".to_string()];
+        let config = OAuth2Cfg { allowed_tenants: other,
+                                 ..config };
+        match issuer(&config, &id_token) {
+            Err(Error::AccessDenied(msg)) => assert!(msg.contains("9188040d"), "{}", msg),
+            Err(e) => panic!("Expected AccessDenied, got {:?}", e),
+            Ok(issuer) => panic!("Expected AccessDenied, got issuer {}", issuer),
+        }
+
+        // Single tenant apps check the tenant too
+        let single_tenant = "https://login.microsoftonline.com/contoso.com/v2.0";
+        let config = OAuth2Cfg { issuer_url: single_tenant.to_string(),
+                                 ..config };
+        assert!(issuer(&config, &id_token).is_err());
+    }
 }
@@ -16,23 +16,28 @@ use std::iter::FromIterator;
 
 use serde_json;
 
-use reqwest::{header::HeaderMap,
-              Body};
+use reqwest::header::HeaderMap;
 
 use builder_core::http_client::{HttpClient,
                                 ACCEPT_APPLICATION_JSON,
                                 CONTENT_TYPE_FORM_URL_ENCODED};
 
-use crate::{config::OAuth2Cfg,
+use crate::{config::{ClientAuthMethod,
+                     OAuth2Cfg},
             error::{Error,
                     Result},
+            retry,
+            token::{self,
+                    ClientAuth},
             types::*};
 
 pub struct Bitbucket;
 
 #[derive(Deserialize)]
 struct AuthOk {
-    pub access_token: String,
+    pub access_token:  String,
+    pub refresh_token: Option<String>,
+    pub expires_in:    Option<u64>,
 }
 
 #[derive(Deserialize)]
@@ -53,13 +58,13 @@ impl Bitbucket {
         let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),];
         let headers = HeaderMap::from_iter(header_values.into_iter());
 
-        let mut resp = client.get(&config.userinfo_url)
-                             .headers(headers)
-                             .bearer_auth(token)
-                             .send()
-                             .map_err(Error::HttpClient)?;
+        let mut resp = retry::send(config, || {
+            client.get(&config.userinfo_url)
+                  .headers(headers.clone())
+                  .bearer_auth(token)
+        })?;
 
-        let body = resp.text().map_err(Error::HttpClient)?;
+        let body = resp.text().map_err(Error::http_client)?;
         debug!("Bitbucket response body: {}", body);
 
         if resp.status().is_success() {
@@ -73,9 +78,11 @@ impl Bitbucket {
                 Utyped::Username(val) => val,
             };
 
-            Ok(OAuth2User { id:       actual_uname.clone(),
-                            username: actual_uname,
-                            email:    None, })
+            Ok(OAuth2User { id:             actual_uname.clone(),
+                            username:       actual_uname,
+                            email:          None,
+                            email_verified: None,
+                            groups:         None, })
         } else {
             Err(Error::HttpResponse(resp.status(), body))
         }
@@ -86,37 +93,54 @@ impl OAuth2Provider for Bitbucket {
     fn authenticate(&self,
                     config: &OAuth2Cfg,
                     client: &HttpClient,
-                    code: &str)
-                    -> Result<(String, OAuth2User)> {
+                    code: &str,
+                    verifier: Option<&str>,
+                    _nonce: Option<&str>)
+                    -> Result<(OAuth2Token, OAuth2User)> {
         let url = config.token_url.to_string();
-        let body = format!("grant_type=authorization_code&code={}", code);
+        let auth = ClientAuth::configured(config, ClientAuthMethod::Basic);
+        let body = token::authorization_code(config, code, verifier, &auth);
 
         let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),
                                  CONTENT_TYPE_FORM_URL_ENCODED.clone()];
         let headers = HeaderMap::from_iter(header_values.into_iter());
 
-        let body: Body = body.into();
+        let mut resp = retry::send_token_request(config, || {
+            let req = client.post(&url)
+                            .headers(headers.clone())
+                            .body(body.clone());
+            token::authenticated(config, req, &auth)
+        })?;
 
-        let mut resp = client.post(&url)
-                             .headers(headers)
-                             .body(body)
-                             .basic_auth(&config.client_id[..], Some(&config.client_secret[..]))
-                             .send()
-                             .map_err(Error::HttpClient)?;
-
-        let body = resp.text().map_err(Error::HttpClient)?;
+        let body = resp.text().map_err(Error::http_client)?;
         debug!("Bitbucket response body: {}", body);
 
         let token = if resp.status().is_success() {
             match serde_json::from_str::<AuthOk>(&body) {
-                Ok(msg) => msg.access_token,
+                Ok(msg) => {
+                    OAuth2Token { access_token:  msg.access_token,
+                                  refresh_token: msg.refresh_token,
+                                  expires_in:    msg.expires_in, }
+                }
                 Err(e) => return Err(Error::Serialization(e)),
             }
         } else {
             return Err(Error::HttpResponse(resp.status(), body));
         };
 
-        let user = self.user(config, client, &token)?;
+        let user = self.user(config, client, &token.access_token)?;
         Ok((token, user))
     }
+
+    fn refresh(&self,
+               config: &OAuth2Cfg,
+               client: &HttpClient,
+               refresh_token: &str)
+               -> Result<OAuth2Token> {
+        token::refresh(config,
+                       client,
+                       &config.token_url,
+                       refresh_token,
+                       ClientAuth::configured(config, ClientAuthMethod::Basic))
+    }
 }
@@ -0,0 +1,238 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bitbucket Server and Data Center, whose REST API has nothing in common with bitbucket.org's.
+//! `userinfo_url` is the base URL of the instance, with its context path if it has one, e.g.
+//! `https://git.corp.example/bitbucket`.
+
+use std::iter::FromIterator;
+
+use serde_json;
+
+use reqwest::header::HeaderMap;
+use url::Url;
+
+use builder_core::http_client::{HttpClient,
+                                ACCEPT_APPLICATION_JSON,
+                                CONTENT_TYPE_FORM_URL_ENCODED};
+
+use crate::{config::{ClientAuthMethod,
+                     OAuth2Cfg},
+            error::{Error,
+                    Result},
+            retry,
+            token::{self,
+                    ClientAuth},
+            types::*};
+
+pub struct BitbucketServer;
+
+#[derive(Deserialize)]
+struct AuthOk {
+    pub access_token:  String,
+    pub refresh_token: Option<String>,
+    pub expires_in:    Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct User {
+    pub id:            u64,
+    pub name:          String,
+    pub email_address: Option<String>,
+}
+
+impl From<User> for OAuth2User {
+    fn from(user: User) -> OAuth2User {
+        OAuth2User { id:             user.id.to_string(),
+                     username:       user.name,
+                     email:          user.email_address,
+                     email_verified: None,
+                     groups:         None, }
+    }
+}
+
+// The instance URL with `segments` appended, each escaped
+fn rest_url(config: &OAuth2Cfg, segments: &[&str]) -> Result<String> {
+    let invalid =
+        || Error::Config(format!("userinfo_url {} is not a base URL", config.userinfo_url));
+    let mut url = Url::parse(&config.userinfo_url).map_err(|_| invalid())?;
+    url.path_segments_mut()
+       .map_err(|_| invalid())?
+       .pop_if_empty()
+       .extend(segments);
+    Ok(url.as_str().to_string())
+}
+
+impl BitbucketServer {
+    fn get(&self,
+           config: &OAuth2Cfg,
+           client: &HttpClient,
+           url: &str,
+           token: &str)
+           -> Result<String> {
+        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),];
+        let headers = HeaderMap::from_iter(header_values.into_iter());
+
+        let mut resp = retry::send(config, || {
+            client.get(url).headers(headers.clone()).bearer_auth(token)
+        })?;
+
+        let body = resp.text().map_err(Error::http_client)?;
+        debug!("BitbucketServer response body: {}", body);
+
+        if resp.status().is_success() {
+            Ok(body)
+        } else {
+            Err(Error::HttpResponse(resp.status(), body))
+        }
+    }
+
+    // whoami answers with the bare username of the token's owner, the user is then read by it
+    fn user(&self, config: &OAuth2Cfg, client: &HttpClient, token: &str) -> Result<OAuth2User> {
+        let whoami = rest_url(config, &["plugins", "servlet", "applinks", "whoami"])?;
+        let name = self.get(config, client, &whoami, token)?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(Error::TokenClaims("whoami answered no user".to_string()));
+        }
+
+        let url = rest_url(config, &["rest", "api", "1.0", "users", name])?;
+        let body = self.get(config, client, &url, token)?;
+        let user = serde_json::from_str::<User>(&body).map_err(Error::Serialization)?;
+        Ok(user.into())
+    }
+}
+
+// The token request goes to the instance's /rest/oauth2/latest/token, the configured token URL
+impl OAuth2Provider for BitbucketServer {
+    fn authenticate(&self,
+                    config: &OAuth2Cfg,
+                    client: &HttpClient,
+                    code: &str,
+                    verifier: Option<&str>,
+                    _nonce: Option<&str>)
+                    -> Result<(OAuth2Token, OAuth2User)> {
+        let url = config.token_url.to_string();
+        let auth = ClientAuth::configured(config, ClientAuthMethod::Body);
+        let body = token::authorization_code(config, code, verifier, &auth);
+
+        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),
+                                 CONTENT_TYPE_FORM_URL_ENCODED.clone()];
+        let headers = HeaderMap::from_iter(header_values.into_iter());
+
+        let mut resp = retry::send_token_request(config, || {
+            let req = client.post(&url)
+                            .headers(headers.clone())
+                            .body(body.clone());
+            token::authenticated(config, req, &auth)
+        })?;
+
+        let body = resp.text().map_err(Error::http_client)?;
+        debug!("BitbucketServer response body: {}", body);
+
+        let token = if resp.status().is_success() {
+            match serde_json::from_str::<AuthOk>(&body) {
+                Ok(msg) => {
+                    OAuth2Token { access_token:  msg.access_token,
+                                  refresh_token: msg.refresh_token,
+                                  expires_in:    msg.expires_in, }
+                }
+                Err(e) => return Err(Error::Serialization(e)),
+            }
+        } else {
+            return Err(Error::token_request(resp.status(), body));
+        };
+
+        let user = self.user(config, client, &token.access_token)?;
+        Ok((token, user))
+    }
+
+    fn refresh(&self,
+               config: &OAuth2Cfg,
+               client: &HttpClient,
+               refresh_token: &str)
+               -> Result<OAuth2Token> {
+        token::refresh(config,
+                       client,
+                       &config.token_url,
+                       refresh_token,
+                       ClientAuth::configured(config, ClientAuthMethod::Body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs,
+              path::PathBuf};
+
+    fn user(name: &str) -> OAuth2User {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+                                                            .join(name);
+        let body = fs::read_to_string(path).unwrap();
+        serde_json::from_str::<User>(&body).unwrap().into()
+    }
+
+    fn config(base: &str) -> OAuth2Cfg {
+        OAuth2Cfg { provider: "bitbucket-server".to_string(),
+                    token_url: format!("{}/rest/oauth2/latest/token", base),
+                    userinfo_url: base.to_string(),
+                    ..Default::default() }
+    }
+
+    #[test]
+    fn user_is_read_from_the_users_resource() {
+        let user = user("bitbucket-server-user.json");
+        assert_eq!(user.id, "101");
+        assert_eq!(user.username, "jdoe");
+        assert_eq!(user.email, Some("jane.doe@example.com".to_string()));
+    }
+
+    #[test]
+    fn service_accounts_may_have_no_email() {
+        let user = user("bitbucket-server-user-no-email.json");
+        assert_eq!(user.id, "2051");
+        assert_eq!(user.username, "ci.bot");
+        assert_eq!(user.email, None);
+    }
+
+    #[test]
+    fn urls_keep_the_context_path() {
+        for base in &["https://git.corp.example/bitbucket",
+                      "https://git.corp.example/bitbucket/"]
+        {
+            assert_eq!(rest_url(&config(base), &["rest", "api", "1.0", "users", "jdoe"]).unwrap(),
+                       "https://git.corp.example/bitbucket/rest/api/1.0/users/jdoe");
+        }
+        assert_eq!(rest_url(&config("https://bitbucket.example.com:7990"),
+                            &["plugins", "servlet", "applinks", "whoami"]).unwrap(),
+                   "https://bitbucket.example.com:7990/plugins/servlet/applinks/whoami");
+    }
+
+    #[test]
+    fn usernames_are_escaped() {
+        assert_eq!(rest_url(&config("https://git.corp.example"),
+                            &["rest", "api", "1.0", "users", "jane doe/admin"]).unwrap(),
+                   "https://git.corp.example/rest/api/1.0/users/jane%20doe%2Fadmin");
+    }
+
+    #[test]
+    fn malformed_instance_urls_are_refused() {
+        match rest_url(&config("git.corp.example/bitbucket"), &["rest"]) {
+            Err(Error::Config(_)) => (),
+            other => panic!("Expected a config error, got {:?}", other),
+        }
+    }
+}
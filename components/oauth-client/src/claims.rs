@@ -0,0 +1,223 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The claims of a userinfo response or an ID token, read by name. Identity providers put the
+//! login name in different claims, `preferred_username`, `nickname`, `upn` or `email`, so the
+//! configured `username_claim` and `id_claim` replace the claims each provider reads by default.
+
+use serde_json::Value;
+
+use crate::{config::OAuth2Cfg,
+            error::{Error,
+                    Result},
+            types::OAuth2User};
+
+/// Claim the id is read from when no `id_claim` is configured
+pub const DEFAULT_ID_CLAIM: &str = "sub";
+
+/// Claim OpenID Connect providers put the username in
+pub const DEFAULT_USERNAME_CLAIM: &str = "preferred_username";
+
+// The value at `path`, a claim name or the dot-separated names leading to a claim of nested
+// objects
+fn value<'a>(claims: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(claims, |value, name| value.get(name))
+}
+
+/// The string claim at `path`, a claim name or the dot-separated names leading to a claim of
+/// nested objects, e.g. `profile.login`. Empty strings count as missing.
+pub fn string<'a>(claims: &'a Value, path: &str) -> Option<&'a str> {
+    value(claims, path).and_then(Value::as_str)
+                       .filter(|s| !s.is_empty())
+}
+
+// The string claim at `path`, or a number claim as its decimal string, as ids often are
+fn id_value(claims: &Value, path: &str) -> Option<String> {
+    match value(claims, path)? {
+        Value::Number(n) => Some(n.to_string()),
+        _ => string(claims, path).map(str::to_string),
+    }
+}
+
+/// The strings of the array claim at `path`, named as with `string`. A string claim is an
+/// array of one, and values that aren't strings are skipped.
+pub fn strings(claims: &Value, path: &str) -> Option<Vec<String>> {
+    match value(claims, path)? {
+        Value::Array(values) => {
+            Some(values.iter()
+                       .filter_map(Value::as_str)
+                       .map(str::to_string)
+                       .collect())
+        }
+        Value::String(s) => Some(vec![s.to_string()]),
+        _ => None,
+    }
+}
+
+/// The user `claims` name. Their id is the configured `id_claim`, `sub` without one, and their
+/// username the configured `username_claim`, the first of the provider's `usernames` claims
+/// present without one. A configured claim that is missing refuses the user rather than
+/// signing them in under another name.
+pub fn user(config: &OAuth2Cfg, claims: &Value, usernames: &[&str]) -> Result<OAuth2User> {
+    user_with_id(config, claims, DEFAULT_ID_CLAIM, usernames)
+}
+
+/// The user `claims` name, as with `user`, for providers whose id is another claim than `sub`
+/// when no `id_claim` is configured
+pub fn user_with_id(config: &OAuth2Cfg,
+                    claims: &Value,
+                    id_claim: &str,
+                    usernames: &[&str])
+                    -> Result<OAuth2User> {
+    let missing = |name: &str| Error::TokenClaims(format!("the {} claim is missing", name));
+
+    let id_claim = config.id_claim.as_ref().map_or(id_claim, String::as_str);
+    let user_id = id_value(claims, id_claim).ok_or_else(|| missing(id_claim))?;
+    let username = match config.username_claim {
+        Some(ref path) => string(claims, path).ok_or_else(|| missing(path))?,
+        None => {
+            usernames.iter()
+                     .filter_map(|name| string(claims, name))
+                     .next()
+                     .ok_or_else(|| missing(&usernames.join(" or ")))?
+        }
+    };
+
+    Ok(OAuth2User { id:             user_id,
+                    username:       username.to_string(),
+                    email:          string(claims, "email").map(str::to_string),
+                    email_verified: claims.get("email_verified").and_then(Value::as_bool),
+                    groups:         None, })
+}
+
+/// A username for providers without a unique login name: the email when the provider says
+/// it's verified, the subject of a user without an email. Names a user can pick, such as a
+/// nickname or the local part of an email, would sign them in to the account of another who
+/// picked the same, so they are never used. An unverified email is refused, as anyone can set
+/// theirs to another's address.
+pub fn handle(subject: &str, email: Option<&str>, email_verified: Option<bool>) -> Result<String> {
+    match (email, email_verified) {
+        (None, _) => Ok(subject.to_string()),
+        (Some(email), Some(true)) => Ok(email.to_string()),
+        (Some(email), _) => {
+            let msg = format!("{} has not verified their email", email);
+            Err(Error::EmailRequired(msg))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config(username_claim: Option<&str>) -> OAuth2Cfg {
+        OAuth2Cfg { provider: "okta".to_string(),
+                    username_claim: username_claim.map(str::to_string),
+                    ..Default::default() }
+    }
+
+    const USERNAMES: &[&str] = &[DEFAULT_USERNAME_CLAIM];
+
+    fn claims() -> Value {
+        json!({
+            "sub": "00u1a2b3c4d5e6f7g8h9",
+            "preferred_username": "jdoe@example.com",
+            "nickname": "jdoe",
+            "email": "jane.doe@example.com",
+            "profile": { "login": "jane", "upn": "", "id": 4021 }
+        })
+    }
+
+    #[test]
+    fn username_is_the_preferred_username_by_default() {
+        let jdoe = user(&config(None), &claims(), USERNAMES).unwrap();
+        assert_eq!(jdoe.id, "00u1a2b3c4d5e6f7g8h9");
+        assert_eq!(jdoe.username, "jdoe@example.com");
+        assert_eq!(jdoe.email, Some("jane.doe@example.com".to_string()));
+
+        let mut claims = claims();
+        claims.as_object_mut().unwrap().remove("preferred_username");
+        let jdoe = user(&config(None), &claims, &[DEFAULT_USERNAME_CLAIM, "email"]).unwrap();
+        assert_eq!(jdoe.username, "jane.doe@example.com");
+    }
+
+    #[test]
+    fn username_is_read_from_the_configured_claim() {
+        let user_of = |claim| {
+            user(&config(Some(claim)), &claims(), USERNAMES).unwrap()
+                                                            .username
+        };
+        assert_eq!(user_of("nickname"), "jdoe");
+        assert_eq!(user_of("profile.login"), "jane");
+    }
+
+    #[test]
+    fn id_is_read_from_the_configured_claim() {
+        let config = OAuth2Cfg { id_claim: Some("profile.id".to_string()),
+                                 ..config(Some("profile.login")) };
+        let jane = user(&config, &claims(), USERNAMES).unwrap();
+        assert_eq!(jane.id, "4021");
+        assert_eq!(jane.username, "jane");
+
+        let config = OAuth2Cfg { id_claim: Some("oid".to_string()),
+                                 ..Default::default() };
+        match user(&config, &claims(), USERNAMES) {
+            Err(Error::TokenClaims(msg)) => assert!(msg.contains("oid"), "{}", msg),
+            Err(e) => panic!("Expected TokenClaims, got {:?}", e),
+            Ok(user) => panic!("Expected TokenClaims, got user {}", user.username),
+        }
+    }
+
+    #[test]
+    fn string_arrays_are_read_by_path() {
+        let claims = json!({
+            "groups": ["builder-admins", 42, "core-maintainers"],
+            "profile": { "team": "release" }
+        });
+        assert_eq!(strings(&claims, "groups"),
+                   Some(vec!["builder-admins".to_string(), "core-maintainers".to_string()]));
+        assert_eq!(strings(&claims, "profile.team"),
+                   Some(vec!["release".to_string()]));
+        assert_eq!(strings(&claims, "profile"), None);
+        assert_eq!(strings(&claims, "roles"), None);
+    }
+
+    #[test]
+    fn a_missing_configured_claim_refuses_the_user() {
+        for claim in &["upn", "profile.upn", "profile.login.name"] {
+            match user(&config(Some(*claim)), &claims(), &["sub"]) {
+                Err(Error::TokenClaims(msg)) => assert!(msg.contains(*claim), "{}", msg),
+                Err(e) => panic!("Expected TokenClaims, got {:?}", e),
+                Ok(user) => panic!("Expected TokenClaims, got user {}", user.username),
+            }
+        }
+    }
+
+    #[test]
+    fn handles_are_verified_emails_or_subjects() {
+        let sub = "110169484474386276334";
+        assert_eq!(handle(sub, Some("jdoe@example.com"), Some(true)).unwrap(),
+                   "jdoe@example.com");
+        assert_eq!(handle(sub, None, None).unwrap(), sub);
+        for verified in &[Some(false), None] {
+            match handle(sub, Some("jdoe@example.com"), *verified) {
+                Err(Error::EmailRequired(msg)) => assert!(msg.contains("jdoe@example.com")),
+                Err(e) => panic!("Expected EmailRequired, got {:?}", e),
+                Ok(name) => panic!("Expected EmailRequired, got {}", name),
+            }
+        }
+    }
+}
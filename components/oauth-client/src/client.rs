@@ -14,20 +14,29 @@
 
 use crate::{a2::A2,
             active_directory::ActiveDirectory,
+            adfs::AdFs,
+            auth0::Auth0,
             azure_ad::AzureAD,
             bitbucket::Bitbucket,
-            config::OAuth2Cfg,
-            error::Result,
+            bitbucket_server::BitbucketServer,
+            cognito::Cognito,
+            config::{default_scopes,
+                     OAuth2Cfg},
+            error::{Error,
+                    Result},
             github::GitHub,
             gitlab::GitLab,
+            google::Google,
+            keycloak::Keycloak,
             metrics::Counter,
+            oidc::OpenIdConnect,
             okta::Okta,
+            pkce,
             types::*};
 use builder_core::{http_client::{HttpClient,
-                                 USER_AGENT_BLDR},
+                                 HttpClientFactory},
                    metrics::CounterMetric};
 use reqwest::header::HeaderMap;
-use std::iter::FromIterator;
 
 pub struct OAuth2Client {
     inner:        HttpClient,
@@ -36,20 +45,53 @@ pub struct OAuth2Client {
 }
 
 impl OAuth2Client {
-    pub fn new(config: OAuth2Cfg) -> Result<Self> {
-        let header_values = vec![USER_AGENT_BLDR.clone(),];
-        let headers = HeaderMap::from_iter(header_values.into_iter());
-
-        let client = HttpClient::new(&config.token_url, headers)?;
+    pub fn new(config: OAuth2Cfg, http: &HttpClientFactory) -> Result<Self> {
+        // ID tokens are only trusted from the configured issuer
+        if !config.jwks_url.is_empty() && config.issuer_url.is_empty() {
+            let msg = "jwks_url is set but issuer_url is not".to_string();
+            return Err(Error::JwtValidation(msg));
+        }
+        check_scopes(&config)?;
+        check_tenants(&config)?;
+        // An OpenID Connect provider's endpoints are only known once its issuer is asked, a
+        // Keycloak realm's may be derived from its base URL
+        let url = match &config.provider[..] {
+            "oidc" => config.issuer_url.clone(),
+            "keycloak" => Keycloak::new(&config).token_url().to_string(),
+            _ => config.token_url.clone(),
+        };
+        let http = if config.http_proxy.is_some() || config.https_proxy.is_some() {
+            http.with_proxies(config.http_proxy.clone(),
+                              config.https_proxy.clone(),
+                              config.no_proxy.clone())?
+        } else {
+            http.clone()
+        };
+        let http = match config.request_timeout_ms {
+            Some(timeout_ms) => http.with_timeout_ms(timeout_ms)?,
+            None => http,
+        };
+        let http = match config.connect_timeout_ms {
+            Some(timeout_ms) => http.with_connect_timeout_ms(timeout_ms)?,
+            None => http,
+        };
+        let client = http.client(url.as_str(), HeaderMap::new())?;
 
         let provider: Box<dyn OAuth2Provider> = match &config.provider[..] {
             "active-directory" => Box::new(ActiveDirectory),
-            "azure-ad" => Box::new(AzureAD),
+            "azure-ad" => Box::new(AzureAD::default()),
             "github" => Box::new(GitHub),
             "gitlab" => Box::new(GitLab),
             "bitbucket" => Box::new(Bitbucket),
-            "okta" => Box::new(Okta),
+            "bitbucket-server" => Box::new(BitbucketServer),
+            "okta" => Box::new(Okta::default()),
+            "auth0" => Box::new(Auth0),
+            "cognito" => Box::new(Cognito),
+            "adfs" => Box::new(AdFs),
+            "google" => Box::new(Google),
+            "keycloak" => Box::new(Keycloak::new(&config)),
             "chef-automate" => Box::new(A2),
+            "oidc" => Box::new(OpenIdConnect::discover(&config, &client)?),
             _ => panic!("Unknown OAuth provider: {}", config.provider),
         };
 
@@ -58,9 +100,263 @@ impl OAuth2Client {
                           provider })
     }
 
-    pub fn authenticate(&self, code: &str) -> Result<(String, OAuth2User)> {
+    /// Exchanges an authorization code, with the PKCE verifier if the authorize URL carried a
+    /// challenge and the nonce if it carried one
+    pub fn authenticate(&self,
+                        code: &str,
+                        verifier: Option<&str>,
+                        nonce: Option<&str>)
+                        -> Result<(OAuth2Token, OAuth2User)> {
         Counter::Authenticate(self.config.provider.clone()).increment();
         debug!("Authenticate called, config: {:?}", self.config);
-        self.provider.authenticate(&self.config, &self.inner, code)
+        if let Some(verifier) = verifier {
+            pkce::check_verifier(verifier)?;
+        }
+        let (token, user) =
+            self.provider
+                .authenticate(&self.config, &self.inner, code, verifier, nonce)?;
+        verified_email(&self.config, &user)?;
+        Ok((token, user))
+    }
+
+    /// Trades a refresh token for a new access token, `Error::NotSupported` when the provider
+    /// does not issue refresh tokens
+    pub fn refresh(&self, refresh_token: &str) -> Result<OAuth2Token> {
+        Counter::Refresh(self.config.provider.clone()).increment();
+        self.provider
+            .refresh(&self.config, &self.inner, refresh_token)
+    }
+
+    /// Reads the user of an access token, failing once the provider no longer takes it.
+    /// `Error::NotSupported` when the provider can't be asked. An `okta` provider's answers are
+    /// kept for `userinfo_cache_secs`.
+    pub fn user(&self, token: &str) -> Result<OAuth2User> {
+        Counter::User(self.config.provider.clone()).increment();
+        self.provider.user(&self.config, &self.inner, token)
+    }
+
+    /// Revokes a token at the provider, doing nothing for providers that can't revoke tokens
+    pub fn revoke(&self, token: &str) -> Result<()> {
+        Counter::Revoke(self.config.provider.clone()).increment();
+        self.provider.revoke(&self.config, &self.inner, token)
+    }
+}
+
+// Refuses configured scopes that leave out one the provider needs to sign users in, so that the
+// misconfiguration is found at startup rather than at the first login
+fn check_scopes(config: &OAuth2Cfg) -> Result<()> {
+    let scopes = match config.scopes {
+        Some(ref scopes) => scopes,
+        None => return Ok(()),
+    };
+    if scopes.is_empty() {
+        return Err(Error::Config("scopes is set but empty".to_string()));
+    }
+
+    let has = |scope: &str| scopes.iter().any(|s| s == scope);
+    let needed = match &config.provider[..] {
+        "github" if !config.allowed_orgs.is_empty() && !has("read:org") => {
+            "read:org to check the allowed_orgs memberships"
+        }
+        "gitlab" if !has("openid") && !has("read_user") && !has("api") => {
+            "openid, read_user or api to read the user"
+        }
+        provider if default_scopes(provider).contains(&"openid") && !has("openid") => {
+            "openid to read the user"
+        }
+        _ => return Ok(()),
+    };
+    let msg = format!("{} scopes must include {}", config.provider, needed);
+    Err(Error::Config(msg))
+}
+
+// Refuses allowed tenants that can't be checked, the tenant is only read from a validated ID
+// token
+fn check_tenants(config: &OAuth2Cfg) -> Result<()> {
+    if config.allowed_tenants.is_empty() || !config.jwks_url.is_empty() {
+        return Ok(());
+    }
+    let msg = format!("{} allowed_tenants needs jwks_url to read the tenants of the ID tokens",
+                      config.provider);
+    Err(Error::Config(msg))
+}
+
+// Refuses a user whose email the provider says is unverified when either `require_email` or
+// `reject_unverified_email` is set, and one it has no email for when `require_email` is. An email
+// of a provider that doesn't say is taken as verified.
+fn verified_email(config: &OAuth2Cfg, user: &OAuth2User) -> Result<()> {
+    let reason = match (&user.email, user.email_verified) {
+        (None, _) if config.require_email => "has no email at the provider",
+        (Some(_), Some(false)) if config.require_email || config.reject_unverified_email => {
+            "has not verified their email"
+        }
+        _ => return Ok(()),
+    };
+    Err(Error::EmailRequired(format!("{} {}", user.username, reason)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self,
+                              Reply};
+    use std::sync::mpsc;
+
+    // Answers every request with a 401 and sends back its method and target
+    fn proxy() -> (String, mpsc::Receiver<(String, String)>) {
+        let (tx, rx) = mpsc::channel();
+        let proxy = test_support::serve(move |request, _| {
+            let _ = tx.send((request.method.clone(), request.target.clone()));
+            Reply::new(401, "")
+        });
+        (proxy.url, rx)
+    }
+
+    #[test]
+    fn provider_requests_go_through_the_configured_proxy() {
+        let (proxy_url, requests) = proxy();
+        let token_url = "http://github.example/login/oauth/access_token";
+        let config = OAuth2Cfg { token_url: token_url.to_string(),
+                                 http_proxy: Some(proxy_url),
+                                 max_retries: 0,
+                                 ..Default::default() };
+        let client = OAuth2Client::new(config, &HttpClientFactory::default()).unwrap();
+
+        match client.authenticate("code", None, None) {
+            Err(Error::HttpResponse(status, _)) => assert_eq!(status.as_u16(), 401),
+            Err(e) => panic!("Expected HttpResponse, got {:?}", e),
+            Ok((_, user)) => panic!("Expected HttpResponse, got user {}", user.username),
+        }
+        let (method, target) = requests.recv().unwrap();
+        assert_eq!(method, "POST");
+        // A proxy is sent the absolute URL
+        assert!(target.starts_with(&format!("{}?", token_url)), "{}", target);
+    }
+
+    #[test]
+    fn unverified_emails_are_refused_by_either_setting() {
+        let user = |email: Option<&str>, email_verified: Option<bool>| {
+            OAuth2User { id: "1".to_string(),
+                         username: "jdoe".to_string(),
+                         email: email.map(str::to_string),
+                         email_verified,
+                         groups: None }
+        };
+        let refused = |config: &OAuth2Cfg, user: &OAuth2User| {
+            match verified_email(config, user) {
+                Err(Error::EmailRequired(_)) => true,
+                Err(e) => panic!("Expected EmailRequired, got {:?}", e),
+                Ok(()) => false,
+            }
+        };
+        let unverified = user(Some("jdoe@example.com"), Some(false));
+        let missing = user(None, None);
+        let unsaid = user(Some("jdoe@example.com"), None);
+
+        let config = OAuth2Cfg::default();
+        assert!(!refused(&config, &unverified));
+        assert!(!refused(&config, &missing));
+
+        let config = OAuth2Cfg { reject_unverified_email: true,
+                                 ..Default::default() };
+        assert!(refused(&config, &unverified));
+        assert!(!refused(&config, &missing));
+        assert!(!refused(&config, &unsaid));
+
+        let config = OAuth2Cfg { require_email: true,
+                                 ..Default::default() };
+        assert!(refused(&config, &unverified));
+        assert!(refused(&config, &missing));
+        assert!(!refused(&config, &unsaid));
+    }
+
+    #[test]
+    fn zero_timeouts_are_refused() {
+        let config = OAuth2Cfg { connect_timeout_ms: Some(0),
+                                 ..Default::default() };
+        assert!(OAuth2Client::new(config, &HttpClientFactory::default()).is_err());
+    }
+
+    #[test]
+    fn invalid_proxies_are_refused() {
+        let config = OAuth2Cfg { https_proxy: Some("proxy.corp:3128".to_string()),
+                                 ..Default::default() };
+        assert!(OAuth2Client::new(config, &HttpClientFactory::default()).is_err());
+    }
+
+    fn scopes(scopes: &[&str]) -> Option<Vec<String>> {
+        Some(scopes.iter().map(|scope| scope.to_string()).collect())
+    }
+
+    #[test]
+    fn configured_scopes_must_let_the_provider_sign_users_in() {
+        let config = OAuth2Cfg { provider: "gitlab".to_string(),
+                                 scopes: scopes(&["read_user"]),
+                                 ..Default::default() };
+        assert!(check_scopes(&config).is_ok());
+        let config = OAuth2Cfg { scopes: scopes(&["openid"]),
+                                 ..config };
+        assert!(check_scopes(&config).is_ok());
+        let config = OAuth2Cfg { scopes: scopes(&["read_repository"]),
+                                 ..config };
+        assert!(check_scopes(&config).is_err());
+        let config = OAuth2Cfg { scopes: scopes(&[]),
+                                 ..config };
+        assert!(check_scopes(&config).is_err());
+
+        let config = OAuth2Cfg { provider: "okta".to_string(),
+                                 scopes: scopes(&["profile", "email"]),
+                                 ..Default::default() };
+        match check_scopes(&config) {
+            Err(Error::Config(msg)) => assert!(msg.contains("openid"), "{}", msg),
+            other => panic!("Expected Config, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn allowed_orgs_need_the_read_org_scope() {
+        let config = OAuth2Cfg { scopes: scopes(&["read:user"]),
+                                 ..Default::default() };
+        assert!(check_scopes(&config).is_ok());
+
+        let config = OAuth2Cfg { allowed_orgs: vec!["habitat-sh".to_string()],
+                                 ..config };
+        assert!(check_scopes(&config).is_err());
+        let config = OAuth2Cfg { scopes: scopes(&["read:user", "read:org"]),
+                                 ..config };
+        assert!(check_scopes(&config).is_ok());
+    }
+
+    #[test]
+    fn allowed_tenants_need_validated_id_tokens() {
+        let config = OAuth2Cfg { provider: "azure-ad".to_string(),
+                                 ..Default::default() };
+        assert!(check_tenants(&config).is_ok());
+
+        let config = OAuth2Cfg { allowed_tenants: vec!["contoso".to_string()],
+                                 ..config };
+        assert!(check_tenants(&config).is_err());
+        let jwks_url = "https://login.microsoftonline.com/common/discovery/v2.0/keys";
+        let config = OAuth2Cfg { jwks_url: jwks_url.to_string(),
+                                 ..config };
+        assert!(check_tenants(&config).is_ok());
+    }
+
+    #[test]
+    fn defaults_are_used_unless_scopes_are_configured() {
+        let config = OAuth2Cfg { provider: "google".to_string(),
+                                 ..Default::default() };
+        assert!(check_scopes(&config).is_ok());
+        assert_eq!(config.scopes(), vec!["openid", "email"]);
+        assert!(OAuth2Cfg::default().scopes().is_empty());
+
+        // The azure-ad scope setting of earlier releases is still read
+        let config = OAuth2Cfg { provider: "azure-ad".to_string(),
+                                 scope: Some("openid  offline_access".to_string()),
+                                 ..Default::default() };
+        assert_eq!(config.scopes(), vec!["openid", "offline_access"]);
+        let config = OAuth2Cfg { scopes: scopes(&["openid"]),
+                                 ..config };
+        assert_eq!(config.scopes(), vec!["openid"]);
     }
 }
@@ -0,0 +1,196 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::iter::FromIterator;
+
+use serde_json::{self,
+                 Value};
+
+use reqwest::{header::HeaderMap,
+              StatusCode};
+
+use builder_core::http_client::{HttpClient,
+                                ACCEPT_APPLICATION_JSON,
+                                CONTENT_TYPE_FORM_URL_ENCODED};
+
+use crate::{claims,
+            config::{ClientAuthMethod,
+                     OAuth2Cfg},
+            error::{Error,
+                    Result},
+            retry,
+            token::{self,
+                    ClientAuth},
+            types::*};
+
+pub struct Cognito;
+
+#[derive(Deserialize)]
+struct AuthOk {
+    pub access_token:  String,
+    pub refresh_token: Option<String>,
+    pub expires_in:    Option<u64>,
+}
+
+impl Cognito {
+    fn user(&self, config: &OAuth2Cfg, client: &HttpClient, token: &str) -> Result<OAuth2User> {
+        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),];
+        let headers = HeaderMap::from_iter(header_values.into_iter());
+
+        let mut resp = retry::send(config, || {
+            client.get(&config.userinfo_url)
+                  .headers(headers.clone())
+                  .bearer_auth(token)
+        })?;
+
+        let body = resp.text().map_err(Error::http_client)?;
+        debug!("Cognito response body: {}", body);
+
+        if resp.status().is_success() {
+            // The username is the user pool's, a preferred_username is an alias users set
+            let claims = serde_json::from_str::<Value>(&body).map_err(Error::Serialization)?;
+            claims::user(config, &claims, &["username"])
+        } else {
+            Err(Error::HttpResponse(resp.status(), body))
+        }
+    }
+}
+
+// Cognito sends its token errors without a description, the ones a misconfigured app client or
+// a stale sign in cause are told apart here
+fn token_error(status: StatusCode, body: String) -> Error {
+    match Error::token_request(status, body) {
+        Error::TokenRequest(status, error, description) => {
+            let description = describe(&error).map_or(description, str::to_string);
+            Error::TokenRequest(status, error, description)
+        }
+        err => err,
+    }
+}
+
+fn describe(error: &str) -> Option<&'static str> {
+    match error {
+        "invalid_grant" => Some("The authorization code is invalid, expired or already used"),
+        "invalid_client" => Some("The client id or secret is not that of a pool app client"),
+        _ => None,
+    }
+}
+
+// The token request goes to the user pool domain's /oauth2/token and the user is read from its
+// /oauth2/userInfo, both taken from the configured token and userinfo URLs
+impl OAuth2Provider for Cognito {
+    fn authenticate(&self,
+                    config: &OAuth2Cfg,
+                    client: &HttpClient,
+                    code: &str,
+                    verifier: Option<&str>,
+                    _nonce: Option<&str>)
+                    -> Result<(OAuth2Token, OAuth2User)> {
+        let url = config.token_url.to_string();
+        let auth = ClientAuth::configured(config, ClientAuthMethod::Basic);
+        let body = token::authorization_code(config, code, verifier, &auth);
+        // Cognito wants the client id in the body as well as in the Authorization header
+        let body = match auth {
+            ClientAuth::Basic => {
+                format!("{}&{}",
+                        token::form(&[("client_id", &config.client_id)]),
+                        body)
+            }
+            ClientAuth::Form => body,
+        };
+
+        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),
+                                 CONTENT_TYPE_FORM_URL_ENCODED.clone()];
+        let headers = HeaderMap::from_iter(header_values.into_iter());
+
+        // Cognito takes the client secret of an app client in the Authorization header unless
+        // the app client is configured for client_secret_post
+        let mut resp = retry::send_token_request(config, || {
+            let req = client.post(&url)
+                            .headers(headers.clone())
+                            .body(body.clone());
+            token::authenticated(config, req, &auth)
+        })?;
+
+        let body = resp.text().map_err(Error::http_client)?;
+        debug!("Cognito response body: {}", body);
+
+        let token = if resp.status().is_success() {
+            match serde_json::from_str::<AuthOk>(&body) {
+                Ok(msg) => {
+                    OAuth2Token { access_token:  msg.access_token,
+                                  refresh_token: msg.refresh_token,
+                                  expires_in:    msg.expires_in, }
+                }
+                Err(e) => return Err(Error::Serialization(e)),
+            }
+        } else {
+            return Err(token_error(resp.status(), body));
+        };
+
+        let user = self.user(config, client, &token.access_token)?;
+        Ok((token, user))
+    }
+
+    fn refresh(&self,
+               config: &OAuth2Cfg,
+               client: &HttpClient,
+               refresh_token: &str)
+               -> Result<OAuth2Token> {
+        token::refresh(config,
+                       client,
+                       &config.token_url,
+                       refresh_token,
+                       ClientAuth::configured(config, ClientAuthMethod::Basic))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expired_codes_are_told_apart() {
+        let err = token_error(StatusCode::BAD_REQUEST,
+                              r#"{"error":"invalid_grant"}"#.to_string());
+        match err {
+            Error::TokenRequest(status, ref error, ref description) => {
+                assert_eq!(status, StatusCode::BAD_REQUEST);
+                assert_eq!(error, "invalid_grant");
+                assert!(description.contains("expired"));
+            }
+            _ => panic!("Expected a token request error, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn bad_client_credentials_are_told_apart() {
+        let err = token_error(StatusCode::BAD_REQUEST,
+                              r#"{"error":"invalid_client"}"#.to_string());
+        assert!(format!("{}", err).contains("error=invalid_client: The client id or secret"));
+    }
+
+    #[test]
+    fn other_errors_keep_their_body() {
+        let page = "<html>Bad Gateway</html>";
+        let err = token_error(StatusCode::BAD_GATEWAY, page.to_string());
+        match err {
+            Error::HttpResponse(status, ref body) => {
+                assert_eq!(status, StatusCode::BAD_GATEWAY);
+                assert_eq!(body, page);
+            }
+            _ => panic!("Expected the raw response, got {:?}", err),
+        }
+    }
+}
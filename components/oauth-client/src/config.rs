@@ -30,21 +30,205 @@ pub const DEV_GITHUB_CLIENT_SECRET: &str = "fc7654ed8c65ccfe014cd339a55e3538f935
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(default)]
 pub struct OAuth2Cfg {
-    pub provider:      String,
-    pub token_url:     String,
-    pub userinfo_url:  String,
-    pub redirect_url:  String,
-    pub client_id:     String,
-    pub client_secret: String,
+    pub provider:                String,
+    pub token_url:               String,
+    pub userinfo_url:            String,
+    pub redirect_url:            String,
+    pub client_id:               String,
+    pub client_secret:           String,
+    /// Issuer of an `oidc` provider, its endpoints are read from the issuer's discovery document
+    pub issuer_url:              String,
+    /// Restricts a `google` provider to the accounts of one G Suite domain
+    pub hosted_domain:           Option<String>,
+    /// Restricts a `github` provider to the members of these organizations, any GitHub account
+    /// may sign in when it is empty. The token must have the `read:org` scope for private
+    /// memberships to count.
+    pub allowed_orgs:            Vec<String>,
+    /// Restricts an `azure-ad` provider to the users of these tenant IDs, any tenant's users may
+    /// sign in to a multi-tenant app when it is empty. The tenant is read from the validated ID
+    /// token, so `jwks_url` must be set.
+    pub allowed_tenants:         Vec<String>,
+    /// Base URL of a `keycloak` server, e.g. `https://sso.example.com/auth`. The token and
+    /// userinfo URLs of its realm are derived from it unless they are set.
+    pub base_url:                String,
+    /// Realm of a `keycloak` provider
+    pub realm:                   String,
+    /// Refuses the users the provider reports with `email_verified: false`. `require_email`
+    /// refuses them too.
+    pub reject_unverified_email: bool,
+    /// Refuses the users the provider has no email for, or reports with `email_verified: false`
+    pub require_email:           bool,
+    /// Timeout of each request made to the provider, the HTTP client's configured timeout when
+    /// unset. A provider that doesn't answer in time fails the login instead of hanging it.
+    pub request_timeout_ms:      Option<u64>,
+    /// Timeout of each connection made to the provider, the HTTP client's configured connect
+    /// timeout when unset
+    pub connect_timeout_ms:      Option<u64>,
+    /// JSON Web Key Set of an `okta`, `azure-ad` or `oidc` provider. When it is set, the ID
+    /// token returned with the access token is validated against it and its claims are used
+    /// instead of a userinfo request. The token must be issued by `issuer_url`.
+    pub jwks_url:                String,
+    /// Seconds the keys at `jwks_url` are kept before they are fetched again. They are fetched
+    /// early for a token signed with a key they lack.
+    pub jwks_cache_secs:         u64,
+    /// Times a request failing with a connection error or a 5xx is sent again. A 4xx is never
+    /// retried.
+    pub max_retries:             u32,
+    /// Delay before the first retry, doubled for each retry after it
+    pub retry_base_ms:           u64,
+    /// Most seconds waited on a provider answering 429 before its request is sent again, a
+    /// longer `Retry-After` is cut short
+    pub max_retry_after_secs:    u64,
+    /// Most milliseconds a request may take with its retries. No retry, nor the retry of a 429,
+    /// is started when its delay would end past it, so a login fails at most one request
+    /// timeout after it.
+    pub max_elapsed_ms:          u64,
+    /// RFC 7009 revocation endpoint of an `okta`, `gitlab` or `auth0` provider, tokens are
+    /// revoked there on logout. Tokens are left to expire when it is unset.
+    pub revoke_url:              String,
+    /// Seconds the user an `okta` provider's userinfo endpoint returns for an access token is
+    /// kept, so that the token's user isn't read from the provider each time. 0 reads it every
+    /// time.
+    pub userinfo_cache_secs:     u64,
+    /// Most access tokens whose users are kept, the least recently used are dropped first
+    pub userinfo_cache_size:     usize,
+    /// Checks the provider token of a signed in session with the provider on each request, so
+    /// that a user deactivated at the provider loses their session. Only `okta` providers can be
+    /// asked, their answers are kept for `userinfo_cache_secs`.
+    pub validate_sessions:       bool,
+    /// Claim the user's id is read from by the OpenID Connect style providers, `a2`, `auth0`,
+    /// `cognito`, `okta` and `oidc`, `sub` when unset. Claims of nested objects are named by
+    /// their path, e.g. `user.id`.
+    pub id_claim:                Option<String>,
+    /// Claim the username is read from by the same providers as `id_claim`, each provider's own
+    /// when unset, e.g. `preferred_username`, or `profile.login` for a nested claim
+    pub username_claim:          Option<String>,
+    /// Claim of an `okta` provider the user's groups are read from, e.g. `groups`. Groups are
+    /// not read when unset.
+    pub groups_claim:            Option<String>,
+    /// Proxies the requests to the provider go through, in place of the `[http]` ones. When
+    /// neither is set, those of the `[http]` settings or the `http_proxy` family of environment
+    /// variables apply.
+    pub http_proxy:              Option<String>,
+    pub https_proxy:             Option<String>,
+    /// Hosts of the provider reached without the proxies above, including their subdomains
+    pub no_proxy:                Vec<String>,
+    /// How the provider's token endpoint is sent the client credentials. Defaults to `basic` for
+    /// `bitbucket` and `cognito`, which refuse them in the body, and to `body` for the others.
+    pub client_auth_method:      Option<ClientAuthMethod>,
+    /// Space separated scopes an `azure-ad` provider asks for when exchanging the code, in
+    /// place of its defaults. `scopes` is used instead when both are set.
+    pub scope:                   Option<String>,
+    /// Scopes asked for in the authorize request and the code exchange, in place of the
+    /// provider's own, see `DEFAULT_SCOPES`. The configured scopes are checked against what
+    /// the provider needs to read the user when the client is made.
+    pub scopes:                  Option<Vec<String>>,
+}
+
+/// The scopes each provider asks for when `scopes` is unset. Providers not listed ask for
+/// none, a `github` app's permissions are set on the app instead.
+pub const DEFAULT_SCOPES: &[(&str, &[&str])] =
+    &[("active-directory", &["openid"]),
+      ("adfs", &["openid"]),
+      ("auth0", &["openid", "profile", "email"]),
+      ("azure-ad", &["openid", "profile", "email", "User.Read"]),
+      ("bitbucket-server", &["PUBLIC_REPOS"]),
+      ("chef-automate", &["openid", "profile", "email"]),
+      ("cognito", &["openid", "email", "profile"]),
+      ("gitlab", &["openid"]),
+      ("google", &["openid", "email"]),
+      ("keycloak", &["openid", "profile", "email"]),
+      ("oidc", &["openid", "profile", "email"]),
+      ("okta", &["openid", "profile", "email"])];
+
+impl OAuth2Cfg {
+    /// The scopes the provider is asked for, the configured ones or else its defaults
+    pub fn scopes(&self) -> Vec<String> {
+        match (&self.scopes, &self.scope) {
+            (Some(scopes), _) => scopes.clone(),
+            (None, Some(scope)) => scope.split_whitespace().map(str::to_string).collect(),
+            (None, None) => {
+                default_scopes(&self.provider).iter()
+                                              .map(|scope| scope.to_string())
+                                              .collect()
+            }
+        }
+    }
+
+    /// The token URL an operator set, for providers whose endpoint is otherwise derived. The
+    /// GitHub URL the setting defaults to is not one.
+    pub fn token_url_override(&self) -> Option<&str> {
+        overridden(&self.token_url, DEFAULT_GITHUB_TOKEN_URL)
+    }
+
+    /// The userinfo URL an operator set, as with `token_url_override`
+    pub fn userinfo_url_override(&self) -> Option<&str> {
+        overridden(&self.userinfo_url, DEFAULT_GITHUB_USERINFO_URL)
+    }
+}
+
+fn overridden<'a>(url: &'a str, default: &str) -> Option<&'a str> {
+    if url.is_empty() || url == default {
+        None
+    } else {
+        Some(url)
+    }
+}
+
+/// The scopes `provider` asks for when none are configured
+pub fn default_scopes(provider: &str) -> &'static [&'static str] {
+    match DEFAULT_SCOPES.iter().find(|&&(name, _)| name == provider) {
+        Some(&(_, scopes)) => scopes,
+        None => &[],
+    }
+}
+
+/// How the client authenticates to the token endpoint, RFC 6749 section 2.3.1
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientAuthMethod {
+    /// `client_id` and `client_secret` in the request body
+    Body,
+    /// HTTP Basic auth, for providers that refuse the credentials in the body
+    Basic,
 }
 
 impl Default for OAuth2Cfg {
     fn default() -> Self {
-        OAuth2Cfg { provider:      "github".to_string(),
-                    token_url:     DEFAULT_GITHUB_TOKEN_URL.to_string(),
-                    userinfo_url:  DEFAULT_GITHUB_USERINFO_URL.to_string(),
-                    redirect_url:  "http://localhost/".to_string(),
-                    client_id:     DEV_GITHUB_CLIENT_ID.to_string(),
-                    client_secret: DEV_GITHUB_CLIENT_SECRET.to_string(), }
+        OAuth2Cfg { provider:                "github".to_string(),
+                    token_url:               DEFAULT_GITHUB_TOKEN_URL.to_string(),
+                    userinfo_url:            DEFAULT_GITHUB_USERINFO_URL.to_string(),
+                    redirect_url:            "http://localhost/".to_string(),
+                    client_id:               DEV_GITHUB_CLIENT_ID.to_string(),
+                    client_secret:           DEV_GITHUB_CLIENT_SECRET.to_string(),
+                    issuer_url:              "".to_string(),
+                    hosted_domain:           None,
+                    allowed_orgs:            vec![],
+                    allowed_tenants:         vec![],
+                    base_url:                "".to_string(),
+                    realm:                   "".to_string(),
+                    reject_unverified_email: false,
+                    require_email:           false,
+                    request_timeout_ms:      None,
+                    connect_timeout_ms:      None,
+                    jwks_url:                "".to_string(),
+                    jwks_cache_secs:         3600,
+                    max_retries:             2,
+                    retry_base_ms:           200,
+                    max_retry_after_secs:    5,
+                    max_elapsed_ms:          10_000,
+                    revoke_url:              "".to_string(),
+                    userinfo_cache_secs:     0,
+                    userinfo_cache_size:     1000,
+                    validate_sessions:       false,
+                    id_claim:                None,
+                    username_claim:          None,
+                    groups_claim:            None,
+                    http_proxy:              None,
+                    https_proxy:             None,
+                    no_proxy:                vec![],
+                    client_auth_method:      None,
+                    scope:                   None,
+                    scopes:                  None, }
     }
 }
@@ -21,10 +21,20 @@ use serde_json;
 
 #[derive(Debug)]
 pub enum Error {
+    AccessDenied(String),
     BuilderCore(builder_core::Error),
+    Config(String),
+    Discovery(String),
+    EmailRequired(String),
     HttpClient(reqwest::Error),
     HttpResponse(reqwest::StatusCode, String),
+    InvalidVerifier(String),
+    JwtValidation(String),
+    NotSupported,
     Serialization(serde_json::Error),
+    Timeout(reqwest::Error),
+    TokenClaims(String),
+    TokenRequest(reqwest::StatusCode, String, String),
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -32,13 +42,26 @@ pub type Result<T> = ::std::result::Result<T, Error>;
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let msg = match *self {
+            Error::AccessDenied(ref e) => format!("Access denied, {}", e),
             Error::BuilderCore(ref e) => format!("{}", e),
+            Error::Config(ref e) => format!("Invalid OAuth config, {}", e),
+            Error::Discovery(ref e) => format!("OpenID Connect discovery failed, {}", e),
+            Error::EmailRequired(ref e) => format!("A verified email is required, {}", e),
             Error::HttpClient(ref e) => format!("{}", e),
             Error::HttpResponse(ref code, ref response) => {
                 format!("Received a non-200 response, status={}, response={}",
                         code, response)
             }
+            Error::InvalidVerifier(ref e) => format!("Invalid PKCE code verifier, {}", e),
+            Error::JwtValidation(ref e) => format!("The ID token is invalid, {}", e),
+            Error::NotSupported => "The OAuth provider does not support the request".to_string(),
             Error::Serialization(ref e) => format!("{}", e),
+            Error::Timeout(ref e) => format!("The OAuth provider did not answer in time, {}", e),
+            Error::TokenClaims(ref e) => format!("Cannot read the user from the token, {}", e),
+            Error::TokenRequest(ref code, ref error, ref description) => {
+                format!("Token request failed, status={}, error={}: {}",
+                        code, error, description)
+            }
         };
         write!(f, "{}", msg)
     }
@@ -47,10 +70,50 @@ impl fmt::Display for Error {
 impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
+            Error::AccessDenied(_) => "The user is not allowed to sign in.",
             Error::BuilderCore(ref err) => err.description(),
+            Error::Config(_) => "Invalid OAuth config.",
+            Error::Discovery(_) => "OpenID Connect discovery failed.",
+            Error::EmailRequired(_) => "The user has no verified email at the OAuth provider.",
             Error::HttpClient(ref err) => err.description(),
             Error::HttpResponse(..) => "Non-200 HTTP response.",
+            Error::InvalidVerifier(_) => "The PKCE code verifier is invalid.",
+            Error::JwtValidation(_) => "ID token validation failed.",
+            Error::NotSupported => "Request not supported by the OAuth provider.",
             Error::Serialization(ref err) => err.description(),
+            Error::Timeout(_) => "The OAuth provider did not answer in time.",
+            Error::TokenClaims(_) => "Token claims could not be read.",
+            Error::TokenRequest(..) => "Token request refused by the OAuth provider.",
+        }
+    }
+}
+
+// The error body of a refused token request, as RFC 6749 section 5.2 has it
+#[derive(Deserialize)]
+struct TokenErrorBody {
+    error:             String,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
+impl Error {
+    /// The error of a failed request to the provider, `Timeout` when it did not answer in time
+    pub fn http_client(err: reqwest::Error) -> Error {
+        if err.is_timeout() {
+            Error::Timeout(err)
+        } else {
+            Error::HttpClient(err)
+        }
+    }
+
+    /// The error of a refused token request, the provider's `error` and `error_description`
+    /// when it sent them, its raw response otherwise
+    pub fn token_request(status: reqwest::StatusCode, body: String) -> Error {
+        match serde_json::from_str::<TokenErrorBody>(&body) {
+            Ok(err) => {
+                Error::TokenRequest(status, err.error, err.error_description.unwrap_or_default())
+            }
+            Err(_) => Error::HttpResponse(status, body),
         }
     }
 }
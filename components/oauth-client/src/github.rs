@@ -16,22 +16,38 @@ use std::iter::FromIterator;
 
 use serde_json;
 
-use reqwest::header::HeaderMap;
+use reqwest::{header::{HeaderMap,
+                       LINK,
+                       RETRY_AFTER},
+              StatusCode};
+
+use url::Url;
 
 use builder_core::http_client::{HttpClient,
                                 ACCEPT_APPLICATION_JSON,
-                                ACCEPT_GITHUB_JSON};
+                                ACCEPT_GITHUB_JSON,
+                                CONTENT_TYPE_FORM_URL_ENCODED};
 
-use crate::{config::OAuth2Cfg,
+use crate::{config::{ClientAuthMethod,
+                     OAuth2Cfg},
             error::{Error,
                     Result},
+            retry,
+            token::{self,
+                    ClientAuth},
             types::*};
 
+// The most organizations GitHub lists a page, and the most pages of them read
+const ORGS_PER_PAGE: u32 = 100;
+const MAX_ORG_PAGES: usize = 50;
+
 pub struct GitHub;
 
 #[derive(Deserialize)]
 struct AuthOk {
-    pub access_token: String,
+    pub access_token:  String,
+    pub refresh_token: Option<String>,
+    pub expires_in:    Option<u64>,
 }
 
 #[derive(Deserialize)]
@@ -41,18 +57,30 @@ struct User {
     pub email: Option<String>,
 }
 
+#[derive(Deserialize)]
+struct Email {
+    pub email:    String,
+    pub primary:  bool,
+    pub verified: bool,
+}
+
+#[derive(Deserialize)]
+struct Org {
+    pub login: String,
+}
+
 impl GitHub {
     fn user(&self, config: &OAuth2Cfg, client: &HttpClient, token: &str) -> Result<OAuth2User> {
         let header_values = vec![ACCEPT_GITHUB_JSON.clone(),];
         let headers = HeaderMap::from_iter(header_values.into_iter());
 
-        let mut resp = client.get(&config.userinfo_url)
-                             .headers(headers)
-                             .bearer_auth(token)
-                             .send()
-                             .map_err(Error::HttpClient)?;
+        let mut resp = retry::send(config, || {
+            client.get(&config.userinfo_url)
+                  .headers(headers.clone())
+                  .bearer_auth(token)
+        })?;
 
-        let body = resp.text().map_err(Error::HttpClient)?;
+        let body = resp.text().map_err(Error::http_client)?;
         debug!("GitHub response body: {}", body);
 
         if resp.status().is_success() {
@@ -61,45 +89,410 @@ impl GitHub {
                 Err(e) => return Err(Error::Serialization(e)),
             };
 
-            Ok(OAuth2User { id:       user.id.to_string(),
+            if !config.allowed_orgs.is_empty() {
+                self.check_orgs(config, client, token, &user.login)?;
+            }
+
+            // Users who keep their email private have none on their profile. Only verified
+            // emails can be made public, and only a verified primary email is read.
+            let email = match user.email {
+                Some(email) => Some(email),
+                None => self.primary_email(config, client, token),
+            };
+
+            Ok(OAuth2User { id: user.id.to_string(),
                             username: user.login,
-                            email:    user.email, })
+                            email_verified: email.as_ref().map(|_| true),
+                            groups: None,
+                            email })
         } else {
             Err(Error::HttpResponse(resp.status(), body))
         }
     }
+
+    // The user's primary email, if they verified it. Not knowing it doesn't fail the login.
+    fn primary_email(&self,
+                     config: &OAuth2Cfg,
+                     client: &HttpClient,
+                     token: &str)
+                     -> Option<String> {
+        match self.emails(config, client, token) {
+            Ok(body) => primary_email(&body),
+            Err(err) => {
+                warn!("Unable to read the GitHub user's emails, err={}", err);
+                None
+            }
+        }
+    }
+
+    fn emails(&self, config: &OAuth2Cfg, client: &HttpClient, token: &str) -> Result<String> {
+        let url = format!("{}/emails", config.userinfo_url.trim_end_matches('/'));
+        let header_values = vec![ACCEPT_GITHUB_JSON.clone(),];
+        let headers = HeaderMap::from_iter(header_values.into_iter());
+
+        let mut resp = retry::send(config, || {
+            client.get(&url).headers(headers.clone()).bearer_auth(token)
+        })?;
+
+        let body = resp.text().map_err(Error::http_client)?;
+        debug!("GitHub emails response body: {}", body);
+
+        if resp.status().is_success() {
+            Ok(body)
+        } else {
+            Err(Error::HttpResponse(resp.status(), body))
+        }
+    }
+
+    // Refuses a user who is a member of none of the allowed organizations. GitHub lists the
+    // user's organizations a page at a time, the next page named by the `Link` header, and they
+    // are read until one of them is allowed.
+    fn check_orgs(&self,
+                  config: &OAuth2Cfg,
+                  client: &HttpClient,
+                  token: &str,
+                  login: &str)
+                  -> Result<()> {
+        let mut next = Some(format!("{}/orgs?per_page={}",
+                                    config.userinfo_url.trim_end_matches('/'),
+                                    ORGS_PER_PAGE));
+        let mut pages = 0;
+        while let Some(url) = next {
+            if pages == MAX_ORG_PAGES {
+                warn!("Stopped reading the organizations of GitHub user {} after {} pages",
+                      login, pages);
+                break;
+            }
+            let (orgs, next_url) = self.orgs(config, client, token, login, &url)?;
+            if orgs.iter().any(|org| allowed_org(config, &org.login)) {
+                return Ok(());
+            }
+            // The token goes with each request, so pages are only read from the API itself
+            next = match next_url {
+                Some(ref url) if !same_origin(&config.userinfo_url, url) => {
+                    warn!("Refused to read the organizations of GitHub user {} from {}",
+                          login, url);
+                    None
+                }
+                next_url => next_url,
+            };
+            pages += 1;
+        }
+
+        let msg = format!("GitHub user {} is not a member of any of the organizations {}",
+                          login,
+                          config.allowed_orgs.join(", "));
+        Err(Error::AccessDenied(msg))
+    }
+
+    // A page of the user's organizations and the URL of the next one
+    fn orgs(&self,
+            config: &OAuth2Cfg,
+            client: &HttpClient,
+            token: &str,
+            login: &str,
+            url: &str)
+            -> Result<(Vec<Org>, Option<String>)> {
+        let header_values = vec![ACCEPT_GITHUB_JSON.clone(),];
+        let headers = HeaderMap::from_iter(header_values.into_iter());
+
+        let mut resp = retry::send(config, || {
+            client.get(url).headers(headers.clone()).bearer_auth(token)
+        })?;
+
+        let body = resp.text().map_err(Error::http_client)?;
+        debug!("GitHub orgs response body: {}", body);
+
+        if !resp.status().is_success() {
+            // Not a denial, the user's organizations are unknown and the sign in fails
+            if rate_limited(resp.status(), resp.headers()) {
+                warn!("GitHub rate limit reached reading the organizations of {}, reset={}",
+                      login,
+                      header(resp.headers(), "x-ratelimit-reset").unwrap_or("unknown"));
+            }
+            return Err(Error::HttpResponse(resp.status(), body));
+        }
+
+        let orgs = serde_json::from_str::<Vec<Org>>(&body).map_err(Error::Serialization)?;
+        Ok((orgs, next_page(resp.headers())))
+    }
+}
+
+// Organization names are case insensitive on GitHub
+fn allowed_org(config: &OAuth2Cfg, org: &str) -> bool {
+    config.allowed_orgs
+          .iter()
+          .any(|allowed| allowed.eq_ignore_ascii_case(org))
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+// GitHub answers a 403 once the rate limit is used up, or a 429 or 403 with a `Retry-After` for
+// its secondary limits
+fn rate_limited(status: StatusCode, headers: &HeaderMap) -> bool {
+    (status == StatusCode::FORBIDDEN || status == StatusCode::TOO_MANY_REQUESTS)
+    && (header(headers, "x-ratelimit-remaining") == Some("0") || headers.contains_key(RETRY_AFTER))
+}
+
+// The URL of the `rel="next"` link of a `Link` header, RFC 8288
+fn next_page(headers: &HeaderMap) -> Option<String> {
+    headers.get(LINK)?
+           .to_str()
+           .ok()?
+           .split(',')
+           .find_map(|link| {
+               let mut params = link.split(';').map(str::trim);
+               let target = params.next()?;
+               let quoted = target.starts_with('<') && target.ends_with('>');
+               if quoted && params.any(|p| p == "rel=\"next\"") {
+                   Some(target[1..target.len() - 1].to_string())
+               } else {
+                   None
+               }
+           })
+}
+
+// Whether `url` has the scheme, host and port of `api_url`
+fn same_origin(api_url: &str, url: &str) -> bool {
+    match (Url::parse(api_url), Url::parse(url)) {
+        (Ok(api_url), Ok(url)) => api_url.origin() == url.origin(),
+        _ => false,
+    }
+}
+
+fn primary_email(body: &str) -> Option<String> {
+    match serde_json::from_str::<Vec<Email>>(body) {
+        Ok(emails) => {
+            emails.into_iter()
+                  .find(|e| e.primary && e.verified)
+                  .map(|e| e.email)
+        }
+        Err(err) => {
+            warn!("Unable to read the GitHub user's emails, err={}", err);
+            None
+        }
+    }
 }
 
 impl OAuth2Provider for GitHub {
     fn authenticate(&self,
                     config: &OAuth2Cfg,
                     client: &HttpClient,
-                    code: &str)
-                    -> Result<(String, OAuth2User)> {
-        let url = format!("{}?client_id={}&client_secret={}&code={}",
-                          config.token_url, config.client_id, config.client_secret, code);
+                    code: &str,
+                    verifier: Option<&str>,
+                    _nonce: Option<&str>)
+                    -> Result<(OAuth2Token, OAuth2User)> {
+        let auth = ClientAuth::configured(config, ClientAuthMethod::Body);
+        let body = token::authorization_code(config, code, verifier, &auth);
 
-        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),];
+        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),
+                                 CONTENT_TYPE_FORM_URL_ENCODED.clone()];
         let headers = HeaderMap::from_iter(header_values.into_iter());
 
-        let mut resp = client.post(&url)
-                             .headers(headers)
-                             .send()
-                             .map_err(Error::HttpClient)?;
+        let mut resp = retry::send_token_request(config, || {
+            let req = client.post(&config.token_url)
+                            .headers(headers.clone())
+                            .body(body.clone());
+            token::authenticated(config, req, &auth)
+        })?;
 
-        let body = resp.text().map_err(Error::HttpClient)?;
+        let body = resp.text().map_err(Error::http_client)?;
         debug!("GitHub response body: {}", body);
 
         let token = if resp.status().is_success() {
             match serde_json::from_str::<AuthOk>(&body) {
-                Ok(msg) => msg.access_token,
+                Ok(msg) => {
+                    OAuth2Token { access_token:  msg.access_token,
+                                  refresh_token: msg.refresh_token,
+                                  expires_in:    msg.expires_in, }
+                }
                 Err(e) => return Err(Error::Serialization(e)),
             }
         } else {
             return Err(Error::HttpResponse(resp.status(), body));
         };
 
-        let user = self.user(config, client, &token)?;
+        let user = self.user(config, client, &token.access_token)?;
         Ok((token, user))
     }
+
+    fn refresh(&self,
+               config: &OAuth2Cfg,
+               client: &HttpClient,
+               refresh_token: &str)
+               -> Result<OAuth2Token> {
+        token::refresh(config,
+                       client,
+                       &config.token_url,
+                       refresh_token,
+                       ClientAuth::configured(config, ClientAuthMethod::Body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::OAuth2Client,
+                test_support::{self,
+                               Reply}};
+    use builder_core::http_client::HttpClientFactory;
+    use serde_json::{json,
+                     Value};
+    use std::{fs,
+              path::PathBuf};
+
+    fn fixture(name: &str) -> String {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+                                                            .join(name);
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn the_primary_email_is_used_once_verified() {
+        assert_eq!(primary_email(&fixture("github-emails.json")),
+                   Some("octocat@example.com".to_string()));
+
+        let unverified =
+            fixture("github-emails.json").replace("\"verified\": true", "\"verified\": false");
+        assert_eq!(primary_email(&unverified), None);
+        assert_eq!(primary_email("[]"), None);
+        assert_eq!(primary_email("{\"message\": \"Not Found\"}"), None);
+    }
+
+    // Signs in a user who keeps their email private and has the `emails` addresses
+    fn sign_in(emails: Value, require_email: bool) -> Result<OAuth2User> {
+        let user = json!({ "id": 583231, "login": "octocat", "email": null });
+        let provider = test_support::provider(vec![("/login/oauth/access_token",
+                                                    json!({ "access_token": "at" })),
+                                                   ("/user", user),
+                                                   ("/user/emails", emails)]);
+        let config = OAuth2Cfg { token_url: format!("{}/login/oauth/access_token", provider.url),
+                                 userinfo_url: format!("{}/user", provider.url),
+                                 require_email,
+                                 ..Default::default() };
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let client = OAuth2Client::new(config, &http).unwrap();
+        client.authenticate("code", None, None)
+              .map(|(_, user)| user)
+    }
+
+    #[test]
+    fn a_verified_email_is_required_when_configured() {
+        let emails: Value = serde_json::from_str(&fixture("github-emails.json")).unwrap();
+        let unverified: Value =
+            serde_json::from_str(&fixture("github-emails.json").replace("\"verified\": true",
+                                                                        "\"verified\": false"))
+                .unwrap();
+
+        let octocat = sign_in(emails, true).unwrap();
+        assert_eq!(octocat.email, Some("octocat@example.com".to_string()));
+
+        for emails in &[unverified, json!([])] {
+            match sign_in(emails.clone(), true) {
+                Err(Error::EmailRequired(_)) => (),
+                Err(e) => panic!("Expected EmailRequired, got {:?}", e),
+                Ok(user) => panic!("Expected EmailRequired, got user {}", user.username),
+            }
+            assert_eq!(sign_in(emails.clone(), false).unwrap().email, None);
+        }
+    }
+
+    #[test]
+    fn the_next_page_is_read_from_the_link_header() {
+        let link = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(LINK, value.parse().unwrap());
+            next_page(&headers)
+        };
+
+        assert_eq!(link("<https://api.github.com/user/orgs?page=2>; rel=\"next\", \
+                         <https://api.github.com/user/orgs?page=4>; rel=\"last\""),
+                   Some("https://api.github.com/user/orgs?page=2".to_string()));
+        assert_eq!(link("<https://api.github.com/user/orgs?page=1>; rel=\"first\", \
+                         <https://api.github.com/user/orgs?page=3>; rel=\"prev\""),
+                   None);
+        assert_eq!(next_page(&HeaderMap::new()), None);
+    }
+
+    // Signs in octocat, a member of the `orgs_routes` organizations, where only `allowed_orgs`
+    // may sign in
+    fn sign_in_to_orgs(orgs_routes: Vec<(&'static str, Reply)>,
+                       allowed_orgs: &[&str])
+                       -> Result<OAuth2User> {
+        let user = json!({ "id": 583231, "login": "octocat", "email": "octocat@example.com" });
+        let mut routes = vec![("/login/oauth/access_token",
+                               Reply::json(200, &json!({ "access_token": "at" }))),
+                              ("/user", Reply::json(200, &user)),];
+        routes.extend(orgs_routes);
+        let provider = test_support::routed(routes);
+        let config =
+            OAuth2Cfg { token_url: format!("{}/login/oauth/access_token", provider.url),
+                        userinfo_url: format!("{}/user", provider.url),
+                        allowed_orgs: allowed_orgs.iter().map(|org| org.to_string()).collect(),
+                        ..Default::default() };
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let client = OAuth2Client::new(config, &http).unwrap();
+        client.authenticate("code", None, None)
+              .map(|(_, user)| user)
+    }
+
+    #[test]
+    fn only_members_of_the_allowed_orgs_sign_in() {
+        let pages = || {
+            vec![("/user/orgs",
+                  Reply::json(200, &json!([{ "login": "github" }, { "login": "octo-org" }]))
+                      .header("Link", "<{url}/user/orgs?per_page=100&page=2>; rel=\"next\"")),
+                 ("/user/orgs?per_page=100&page=2",
+                  Reply::json(200, &json!([{ "login": "Habitat-sh" }]))),]
+        };
+
+        assert_eq!(sign_in_to_orgs(pages(), &["octo-org"]).unwrap().username,
+                   "octocat");
+        // Found on the second page, whatever its case
+        assert!(sign_in_to_orgs(pages(), &["habitat-sh"]).is_ok());
+        assert!(sign_in_to_orgs(vec![], &[]).is_ok());
+
+        match sign_in_to_orgs(pages(), &["chef"]) {
+            Err(Error::AccessDenied(ref msg)) => assert!(msg.contains("chef")),
+            Err(e) => panic!("Expected AccessDenied, got {:?}", e),
+            Ok(user) => panic!("Expected AccessDenied, got user {}", user.username),
+        }
+    }
+
+    #[test]
+    fn org_pages_are_only_read_from_the_api() {
+        let elsewhere =
+            test_support::provider(vec![("/user/orgs", json!([{ "login": "habitat-sh" }]))]);
+        let link = format!("<{}/user/orgs?page=2>; rel=\"next\"", elsewhere.url);
+        let pages = vec![("/user/orgs",
+                          Reply::json(200, &json!([{ "login": "github" }])).header("Link", &link))];
+
+        match sign_in_to_orgs(pages, &["habitat-sh"]) {
+            Err(Error::AccessDenied(_)) => (),
+            Err(e) => panic!("Expected AccessDenied, got {:?}", e),
+            Ok(user) => panic!("Expected AccessDenied, got user {}", user.username),
+        }
+        assert_eq!(elsewhere.requests(), 0);
+        assert!(same_origin("https://api.github.com/user",
+                            "https://api.github.com/user/orgs?page=2"));
+        assert!(!same_origin("https://api.github.com/user",
+                             "https://api.github.com:8443/user/orgs?page=2"));
+        assert!(!same_origin("https://api.github.com/user",
+                             "http://api.github.com/user/orgs"));
+    }
+
+    #[test]
+    fn a_rate_limited_org_check_fails_without_denying() {
+        let limited = vec![("/user/orgs",
+                            Reply::json(403, &json!({ "message": "API rate limit exceeded" }))
+                                .header("X-RateLimit-Remaining", "0")
+                                .header("X-RateLimit-Reset", "1571642880"))];
+        match sign_in_to_orgs(limited, &["octo-org"]) {
+            Err(Error::HttpResponse(status, _)) => assert_eq!(status, StatusCode::FORBIDDEN),
+            Err(e) => panic!("Expected HttpResponse, got {:?}", e),
+            Ok(user) => panic!("Expected HttpResponse, got user {}", user.username),
+        }
+    }
 }
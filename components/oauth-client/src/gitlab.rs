@@ -19,25 +19,77 @@ use serde_json;
 use reqwest::header::HeaderMap;
 
 use builder_core::http_client::{HttpClient,
-                                ACCEPT_APPLICATION_JSON};
+                                ACCEPT_APPLICATION_JSON,
+                                CONTENT_TYPE_FORM_URL_ENCODED};
 
-use crate::{config::OAuth2Cfg,
+use crate::{config::{ClientAuthMethod,
+                     OAuth2Cfg},
             error::{Error,
                     Result},
+            retry,
+            token::{self,
+                    ClientAuth},
             types::*};
 
+/// GitLab, on gitlab.com or a self-hosted instance. Nothing is assumed about the host: the token
+/// URL is the instance's `/oauth/token` and the userinfo URL either its OpenID `/oauth/userinfo`,
+/// with the default `openid` scope, or, for instances without OpenID Connect, its `/api/v4/user`
+/// with the `read_user` scope, e.g. `https://gitlab.example.com/api/v4/user`.
 pub struct GitLab;
 
 #[derive(Deserialize)]
 struct AuthOk {
-    pub access_token: String,
+    pub access_token:  String,
+    pub refresh_token: Option<String>,
+    pub expires_in:    Option<u64>,
 }
 
+// The fields Builder reads of the `/api/v4/user` response, whose id is numeric, or of the
+// OpenID userinfo claims. The subject is the same id as a string, so a user is the same whichever
+// URL is configured. The API has no email_verified, an account's email is verified once the
+// account is confirmed.
 #[derive(Deserialize)]
-struct User {
-    pub sub:      String,
-    pub nickname: String,
-    pub email:    Option<String>,
+#[serde(untagged)]
+enum User {
+    Api {
+        id:           u64,
+        username:     String,
+        email:        Option<String>,
+        confirmed_at: Option<String>,
+    },
+    OpenId {
+        sub:            String,
+        nickname:       String,
+        email:          Option<String>,
+        email_verified: Option<bool>,
+    },
+}
+
+impl From<User> for OAuth2User {
+    fn from(user: User) -> Self {
+        match user {
+            User::Api { id,
+                        username,
+                        email,
+                        confirmed_at, } => {
+                OAuth2User { id: id.to_string(),
+                             username,
+                             email,
+                             email_verified: Some(confirmed_at.is_some()),
+                             groups: None }
+            }
+            User::OpenId { sub,
+                           nickname,
+                           email,
+                           email_verified, } => {
+                OAuth2User { id: sub,
+                             username: nickname,
+                             email,
+                             email_verified,
+                             groups: None }
+            }
+        }
+    }
 }
 
 impl GitLab {
@@ -45,24 +97,20 @@ impl GitLab {
         let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),];
         let headers = HeaderMap::from_iter(header_values.into_iter());
 
-        let mut resp = client.get(&config.userinfo_url)
-                             .headers(headers)
-                             .bearer_auth(token)
-                             .send()
-                             .map_err(Error::HttpClient)?;
+        let mut resp = retry::send(config, || {
+            client.get(&config.userinfo_url)
+                  .headers(headers.clone())
+                  .bearer_auth(token)
+        })?;
 
-        let body = resp.text().map_err(Error::HttpClient)?;
+        let body = resp.text().map_err(Error::http_client)?;
         debug!("GitLab response body: {}", body);
 
         if resp.status().is_success() {
-            let user = match serde_json::from_str::<User>(&body) {
-                Ok(msg) => msg,
-                Err(e) => return Err(Error::Serialization(e)),
-            };
-
-            Ok(OAuth2User { id:       user.sub,
-                            username: user.nickname,
-                            email:    user.email, })
+            match serde_json::from_str::<User>(&body) {
+                Ok(user) => Ok(user.into()),
+                Err(e) => Err(Error::Serialization(e)),
+            }
         } else {
             Err(Error::HttpResponse(resp.status(), body))
         }
@@ -73,37 +121,199 @@ impl OAuth2Provider for GitLab {
     fn authenticate(&self,
                     config: &OAuth2Cfg,
                     client: &HttpClient,
-                    code: &str)
-                    -> Result<(String, OAuth2User)> {
-        let url = format!("{}?client_id={}&client_secret={}&grant_type=authorization_code&\
-                           code={}&redirect_uri={}",
-                          config.token_url,
-                          config.client_id,
-                          config.client_secret,
-                          code,
-                          config.redirect_url);
+                    code: &str,
+                    verifier: Option<&str>,
+                    _nonce: Option<&str>)
+                    -> Result<(OAuth2Token, OAuth2User)> {
+        let auth = ClientAuth::configured(config, ClientAuthMethod::Body);
+        let body = token::authorization_code(config, code, verifier, &auth);
 
-        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),];
+        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),
+                                 CONTENT_TYPE_FORM_URL_ENCODED.clone()];
         let headers = HeaderMap::from_iter(header_values.into_iter());
 
-        let mut resp = client.post(&url)
-                             .headers(headers)
-                             .send()
-                             .map_err(Error::HttpClient)?;
+        let mut resp = retry::send_token_request(config, || {
+            let req = client.post(&config.token_url)
+                            .headers(headers.clone())
+                            .body(body.clone());
+            token::authenticated(config, req, &auth)
+        })?;
 
-        let body = resp.text().map_err(Error::HttpClient)?;
+        let body = resp.text().map_err(Error::http_client)?;
         debug!("GitLab response body: {}", body);
 
         let token = if resp.status().is_success() {
             match serde_json::from_str::<AuthOk>(&body) {
-                Ok(msg) => msg.access_token,
+                Ok(msg) => {
+                    OAuth2Token { access_token:  msg.access_token,
+                                  refresh_token: msg.refresh_token,
+                                  expires_in:    msg.expires_in, }
+                }
                 Err(e) => return Err(Error::Serialization(e)),
             }
         } else {
-            return Err(Error::HttpResponse(resp.status(), body));
+            return Err(Error::token_request(resp.status(), body));
         };
 
-        let user = self.user(config, client, &token)?;
+        let user = self.user(config, client, &token.access_token)?;
         Ok((token, user))
     }
+
+    fn refresh(&self,
+               config: &OAuth2Cfg,
+               client: &HttpClient,
+               refresh_token: &str)
+               -> Result<OAuth2Token> {
+        token::refresh(config,
+                       client,
+                       &config.token_url,
+                       refresh_token,
+                       ClientAuth::configured(config, ClientAuthMethod::Body))
+    }
+
+    fn revoke(&self, config: &OAuth2Cfg, client: &HttpClient, token: &str) -> Result<()> {
+        token::revoke(config,
+                      client,
+                      token,
+                      ClientAuth::configured(config, ClientAuthMethod::Body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::OAuth2Client,
+                test_support::{self,
+                               Reply}};
+    use builder_core::http_client::HttpClientFactory;
+    use std::{fs,
+              path::PathBuf,
+              sync::mpsc};
+
+    fn user(name: &str) -> OAuth2User {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+                                                            .join(name);
+        let body = fs::read_to_string(path).unwrap();
+        serde_json::from_str::<User>(&body).unwrap().into()
+    }
+
+    #[test]
+    fn user_is_read_from_gitlab_com() {
+        let user = user("gitlab-user.json");
+        assert_eq!(user.id, "1843279");
+        assert_eq!(user.username, "jdoe");
+        assert_eq!(user.email, Some("jane.doe@example.com".to_string()));
+        assert_eq!(user.email_verified, Some(true));
+    }
+
+    #[test]
+    fn users_of_unconfirmed_accounts_have_unverified_emails() {
+        let body = r#"{"id": 42, "username": "jdoe", "email": "jdoe@example.com",
+                       "confirmed_at": null}"#;
+        let user: OAuth2User = serde_json::from_str::<User>(body).unwrap().into();
+        assert_eq!(user.email_verified, Some(false));
+    }
+
+    #[test]
+    fn user_is_read_from_a_self_hosted_instance() {
+        let user = user("gitlab-user-self-hosted.json");
+        assert_eq!(user.id, "42");
+        assert_eq!(user.username, "jdoe");
+        assert_eq!(user.email, None);
+    }
+
+    // A self-hosted instance under a relative URL root, without OpenID Connect
+    fn self_hosted() -> test_support::Provider {
+        test_support::serve(|request, _| {
+            match (request.method.as_str(), request.path()) {
+                ("POST", "/gitlab/oauth/token") => {
+                    Reply::new(200,
+                               r#"{"access_token": "glpat-abc", "token_type": "Bearer"}"#)
+                }
+                ("GET", "/gitlab/api/v4/user")
+                    if request.header("Authorization") == Some("Bearer glpat-abc") =>
+                {
+                    Reply::new(200, r#"{"id": 42, "username": "jdoe", "state": "active"}"#)
+                }
+                _ => Reply::new(404, "{}"),
+            }
+        })
+    }
+
+    #[test]
+    fn users_sign_in_on_a_self_hosted_instance() {
+        let provider = self_hosted();
+        let config = OAuth2Cfg { provider: "gitlab".to_string(),
+                                 token_url: format!("{}/gitlab/oauth/token", provider.url),
+                                 userinfo_url: format!("{}/gitlab/api/v4/user", provider.url),
+                                 scopes: Some(vec!["read_user".to_string()]),
+                                 ..Default::default() };
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let client = OAuth2Client::new(config, &http).unwrap();
+
+        let (token, user) = client.authenticate("code", None, None).unwrap();
+        assert_eq!(token.access_token, "glpat-abc");
+        assert_eq!(user.id, "42");
+        assert_eq!(user.username, "jdoe");
+        assert_eq!(provider.requests(), 2);
+    }
+
+    #[test]
+    fn users_are_read_from_the_openid_userinfo_of_existing_configs() {
+        let body = r#"{"sub": "1843279", "nickname": "jdoe", "email": "jane.doe@example.com",
+                       "email_verified": true}"#;
+        let user: OAuth2User = serde_json::from_str::<User>(body).unwrap().into();
+        assert_eq!(user.id, "1843279");
+        assert_eq!(user.username, "jdoe");
+        assert_eq!(user.email_verified, Some(true));
+    }
+
+    #[test]
+    fn tokens_are_revoked_at_the_instances_revoke_url() {
+        let (tx, rx) = mpsc::channel();
+        let provider = test_support::serve(move |request, _| {
+            let _ = tx.send((request.path().to_string(), request.body.clone()));
+            Reply::new(200, "{}")
+        });
+        let config = OAuth2Cfg { provider: "gitlab".to_string(),
+                                 token_url: format!("{}/oauth/token", provider.url),
+                                 revoke_url: format!("{}/oauth/revoke", provider.url),
+                                 client_id: "builder".to_string(),
+                                 client_secret: "s3cret".to_string(),
+                                 ..Default::default() };
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let client = OAuth2Client::new(config, &http).unwrap();
+
+        client.revoke("glrt-abc+1").unwrap();
+        let (path, body) = rx.recv().unwrap();
+        assert_eq!(path, "/oauth/revoke");
+        assert_eq!(body,
+                   "client_id=builder&client_secret=s3cret&token=glrt-abc%2B1");
+    }
+
+    #[test]
+    fn tokens_are_revoked_with_basic_auth_when_configured() {
+        let (tx, rx) = mpsc::channel();
+        let provider = test_support::serve(move |request, _| {
+            let auth = request.header("authorization").map(str::to_string);
+            let _ = tx.send((auth, request.body.clone()));
+            Reply::new(200, "{}")
+        });
+        let config = OAuth2Cfg { provider: "gitlab".to_string(),
+                                 token_url: format!("{}/oauth/token", provider.url),
+                                 revoke_url: format!("{}/oauth/revoke", provider.url),
+                                 client_id: "builder".to_string(),
+                                 client_secret: "s3cret".to_string(),
+                                 client_auth_method: Some(ClientAuthMethod::Basic),
+                                 ..Default::default() };
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let client = OAuth2Client::new(config, &http).unwrap();
+
+        client.revoke("glrt-abc").unwrap();
+        let (auth, body) = rx.recv().unwrap();
+        // base64 of builder:s3cret
+        assert_eq!(auth.as_ref().map(String::as_str),
+                   Some("Basic YnVpbGRlcjpzM2NyZXQ="));
+        assert_eq!(body, "client_id=builder&token=glrt-abc");
+    }
 }
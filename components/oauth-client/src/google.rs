@@ -0,0 +1,252 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::iter::FromIterator;
+
+use serde_json;
+
+use reqwest::header::HeaderMap;
+
+use builder_core::http_client::{HttpClient,
+                                ACCEPT_APPLICATION_JSON,
+                                CONTENT_TYPE_FORM_URL_ENCODED};
+
+use crate::{claims,
+            config::{ClientAuthMethod,
+                     OAuth2Cfg},
+            error::{Error,
+                    Result},
+            retry,
+            token::{self,
+                    ClientAuth},
+            types::*};
+
+pub struct Google;
+
+const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+
+const USERINFO_URL: &str = "https://openidconnect.googleapis.com/v1/userinfo";
+
+#[derive(Deserialize)]
+struct AuthOk {
+    pub access_token:  String,
+    pub refresh_token: Option<String>,
+    pub expires_in:    Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    pub sub:            String,
+    pub email:          Option<String>,
+    pub email_verified: Option<bool>,
+    // The G Suite domain of the account, absent for consumer accounts
+    pub hd:             Option<String>,
+}
+
+impl User {
+    // Google has no username, and local parts of emails of different domains collide
+    fn username(&self) -> Result<String> {
+        claims::handle(&self.sub,
+                       self.email.as_ref().map(String::as_str),
+                       self.email_verified)
+    }
+
+    // Google only sends the hd claim for G Suite accounts, a consumer account is never in the
+    // configured domain
+    fn check_hosted_domain(&self, config: &OAuth2Cfg) -> Result<()> {
+        match config.hosted_domain {
+            Some(ref domain) if self.hd.as_ref() != Some(domain) => {
+                let msg = format!("Google account {} is not in the hosted domain {}",
+                                  self.email.as_ref().unwrap_or(&self.sub),
+                                  domain);
+                Err(Error::AccessDenied(msg))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// The user of a userinfo response, refused when it's outside the configured hosted domain
+fn user_from_userinfo(config: &OAuth2Cfg, body: &str) -> Result<OAuth2User> {
+    let user = serde_json::from_str::<User>(body).map_err(Error::Serialization)?;
+    user.check_hosted_domain(config)?;
+
+    Ok(OAuth2User { username:       user.username()?,
+                    id:             user.sub,
+                    email:          user.email,
+                    email_verified: user.email_verified,
+                    groups:         None, })
+}
+
+// Google's endpoints, unless the config names others. The settings default to GitHub's.
+fn token_url(config: &OAuth2Cfg) -> &str { config.token_url_override().unwrap_or(TOKEN_URL) }
+
+fn userinfo_url(config: &OAuth2Cfg) -> &str {
+    config.userinfo_url_override().unwrap_or(USERINFO_URL)
+}
+
+impl Google {
+    fn user(&self, config: &OAuth2Cfg, client: &HttpClient, token: &str) -> Result<OAuth2User> {
+        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),];
+        let headers = HeaderMap::from_iter(header_values.into_iter());
+
+        let mut resp = retry::send(config, || {
+            client.get(userinfo_url(config))
+                  .headers(headers.clone())
+                  .bearer_auth(token)
+        })?;
+
+        let body = resp.text().map_err(Error::http_client)?;
+        debug!("Google response body: {}", body);
+
+        if resp.status().is_success() {
+            user_from_userinfo(config, &body)
+        } else {
+            Err(Error::HttpResponse(resp.status(), body))
+        }
+    }
+}
+
+impl OAuth2Provider for Google {
+    fn authenticate(&self,
+                    config: &OAuth2Cfg,
+                    client: &HttpClient,
+                    code: &str,
+                    verifier: Option<&str>,
+                    _nonce: Option<&str>)
+                    -> Result<(OAuth2Token, OAuth2User)> {
+        let url = token_url(config);
+        let auth = ClientAuth::configured(config, ClientAuthMethod::Body);
+        let body = token::authorization_code(config, code, verifier, &auth);
+
+        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),
+                                 CONTENT_TYPE_FORM_URL_ENCODED.clone()];
+        let headers = HeaderMap::from_iter(header_values.into_iter());
+
+        let mut resp = retry::send_token_request(config, || {
+            let req = client.post(url).headers(headers.clone()).body(body.clone());
+            token::authenticated(config, req, &auth)
+        })?;
+
+        let body = resp.text().map_err(Error::http_client)?;
+        debug!("Google response body: {}", body);
+
+        let token = if resp.status().is_success() {
+            match serde_json::from_str::<AuthOk>(&body) {
+                Ok(msg) => {
+                    OAuth2Token { access_token:  msg.access_token,
+                                  refresh_token: msg.refresh_token,
+                                  expires_in:    msg.expires_in, }
+                }
+                Err(e) => return Err(Error::Serialization(e)),
+            }
+        } else {
+            return Err(Error::token_request(resp.status(), body));
+        };
+
+        let user = self.user(config, client, &token.access_token)?;
+        Ok((token, user))
+    }
+
+    fn refresh(&self,
+               config: &OAuth2Cfg,
+               client: &HttpClient,
+               refresh_token: &str)
+               -> Result<OAuth2Token> {
+        token::refresh(config,
+                       client,
+                       token_url(config),
+                       refresh_token,
+                       ClientAuth::configured(config, ClientAuthMethod::Body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs,
+              path::PathBuf};
+
+    fn fixture(name: &str) -> String {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+                                                            .join(name);
+        fs::read_to_string(path).unwrap()
+    }
+
+    fn config(hosted_domain: Option<&str>) -> OAuth2Cfg {
+        OAuth2Cfg { provider: "google".to_string(),
+                    hosted_domain: hosted_domain.map(str::to_string),
+                    ..Default::default() }
+    }
+
+    #[test]
+    fn google_endpoints_are_used_unless_others_are_configured() {
+        assert_eq!(token_url(&config(None)),
+                   "https://oauth2.googleapis.com/token");
+        assert_eq!(userinfo_url(&config(None)),
+                   "https://openidconnect.googleapis.com/v1/userinfo");
+
+        let config = OAuth2Cfg { token_url: "https://google.test/token".to_string(),
+                                 userinfo_url: "https://google.test/userinfo".to_string(),
+                                 ..config(None) };
+        assert_eq!(token_url(&config), "https://google.test/token");
+        assert_eq!(userinfo_url(&config), "https://google.test/userinfo");
+    }
+
+    #[test]
+    fn user_is_read_from_userinfo() {
+        let user = user_from_userinfo(&config(None), &fixture("google-user.json")).unwrap();
+        assert_eq!(user.id, "110169484474386276334");
+        assert_eq!(user.username, "jdoe@example.com");
+        assert_eq!(user.email, Some("jdoe@example.com".to_string()));
+    }
+
+    #[test]
+    fn users_of_the_hosted_domain_are_accepted() {
+        let user = user_from_userinfo(&config(Some("example.com")), &fixture("google-user.json"));
+        assert!(user.is_ok());
+    }
+
+    #[test]
+    fn users_outside_the_hosted_domain_are_refused() {
+        for body in &[fixture("google-user.json"),
+                      fixture("google-user-consumer.json")]
+        {
+            match user_from_userinfo(&config(Some("corp.example.com")), body) {
+                Err(Error::AccessDenied(ref msg)) => assert!(msg.contains("corp.example.com")),
+                Err(e) => panic!("Expected AccessDenied, got {:?}", e),
+                Ok(user) => panic!("Expected AccessDenied, got user {}", user.username),
+            }
+        }
+    }
+
+    #[test]
+    fn username_falls_back_to_the_subject() {
+        let body = r#"{"sub": "110169484474386276334"}"#;
+        let user = user_from_userinfo(&config(None), body).unwrap();
+        assert_eq!(user.username, "110169484474386276334");
+        assert_eq!(user.email, None);
+    }
+
+    #[test]
+    fn users_with_an_unverified_email_are_refused() {
+        let body = r#"{"sub": "110169484474386276334", "email": "jdoe@example.com",
+                       "email_verified": false}"#;
+        match user_from_userinfo(&config(None), body) {
+            Err(Error::EmailRequired(_)) => (),
+            Err(e) => panic!("Expected EmailRequired, got {:?}", e),
+            Ok(user) => panic!("Expected EmailRequired, got user {}", user.username),
+        }
+    }
+}
@@ -0,0 +1,345 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The ID token an OpenID Connect provider returns along with the access token. Once its
+//! signature is checked against the provider's published keys, and its issuer, audience, expiry
+//! and nonce against what Builder expects, its claims say who signed in without a userinfo
+//! request. They are read with the `claims` module.
+
+use std::{iter::FromIterator,
+          sync::{Arc,
+                 Mutex,
+                 MutexGuard,
+                 PoisonError},
+          time::{Duration,
+                 Instant}};
+
+use jsonwebtoken::{self,
+                   Algorithm,
+                   DecodingKey,
+                   Validation};
+use reqwest::header::HeaderMap;
+use serde_json::{self,
+                 Value};
+
+use builder_core::http_client::{HttpClient,
+                                ACCEPT_APPLICATION_JSON};
+
+use crate::{config::OAuth2Cfg,
+            error::{Error,
+                    Result},
+            retry};
+
+// Keys fetched this recently aren't fetched again for a token naming a key they lack, so that
+// tokens naming made-up keys can't have every sign in fetch them
+const MIN_REFETCH_SECS: u64 = 10;
+
+// A JSON Web Key Set, RFC 7517 section 5
+#[derive(Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    n:   Option<String>,
+    e:   Option<String>,
+}
+
+/// The keys at the configured `jwks_url`, kept for `jwks_cache_secs` once fetched. They are
+/// fetched again early for a token signed with a key they lack, as the provider may have
+/// rotated its keys.
+#[derive(Default)]
+pub struct JwksCache {
+    cached:   Mutex<Option<(Instant, Arc<Jwks>)>>,
+    // Held while the keys are fetched, so that sign ins missing the cache together fetch them
+    // once. Sign ins the cached keys do for are not held up by it.
+    fetching: Mutex<()>,
+}
+
+impl JwksCache {
+    /// Validates `id_token` with the provider's keys and returns its claims. It must be issued
+    /// by `issuer` to the configured client, not have expired, and carry the `nonce` the code was
+    /// requested with.
+    pub fn validate(&self,
+                    config: &OAuth2Cfg,
+                    client: &HttpClient,
+                    issuer: &str,
+                    id_token: &str,
+                    nonce: Option<&str>)
+                    -> Result<Value> {
+        let header = jsonwebtoken::decode_header(id_token).map_err(invalid)?;
+        let jwks = self.keys(config, client, header.kid.as_ref().map(String::as_str))?;
+        let claims = validate_with_keys(&jwks, issuer, &config.client_id, id_token)?;
+        check_nonce(&claims, nonce)?;
+        Ok(claims)
+    }
+
+    fn keys(&self,
+            config: &OAuth2Cfg,
+            client: &HttpClient,
+            kid: Option<&str>)
+            -> Result<Arc<Jwks>> {
+        if let Some(jwks) = self.cached_keys(config, kid) {
+            return Ok(jwks);
+        }
+
+        let _fetching = self.fetching.lock().unwrap_or_else(PoisonError::into_inner);
+        // Another sign in may have fetched them while this one waited
+        if let Some(jwks) = self.cached_keys(config, kid) {
+            return Ok(jwks);
+        }
+        let jwks = Arc::new(fetch(config, client)?);
+        *self.cached() = Some((Instant::now(), jwks.clone()));
+        Ok(jwks)
+    }
+
+    // The cached keys, unless they expired or lack the token's key and may be fetched again
+    fn cached_keys(&self, config: &OAuth2Cfg, kid: Option<&str>) -> Option<Arc<Jwks>> {
+        let cached = self.cached();
+        let (fetched_at, jwks) = cached.as_ref()?;
+        let age = fetched_at.elapsed();
+        let expired = age >= Duration::from_secs(config.jwks_cache_secs);
+        let refetch =
+            signing_key(jwks, kid).is_err() && age >= Duration::from_secs(MIN_REFETCH_SECS);
+        if expired || refetch {
+            None
+        } else {
+            Some(jwks.clone())
+        }
+    }
+
+    // The keys are replaced whole, so a panic while the lock was held can't leave them torn
+    fn cached(&self) -> MutexGuard<Option<(Instant, Arc<Jwks>)>> {
+        self.cached.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn fetch(config: &OAuth2Cfg, client: &HttpClient) -> Result<Jwks> {
+    let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),];
+    let headers = HeaderMap::from_iter(header_values.into_iter());
+
+    let mut resp = retry::send(config, || {
+        client.get(&config.jwks_url).headers(headers.clone())
+    })?;
+
+    let body = resp.text().map_err(Error::http_client)?;
+    debug!("JWKS response body: {}", body);
+
+    if !resp.status().is_success() {
+        return Err(Error::HttpResponse(resp.status(), body));
+    }
+
+    serde_json::from_str::<Jwks>(&body).map_err(Error::Serialization)
+}
+
+fn invalid(e: jsonwebtoken::errors::Error) -> Error { Error::JwtValidation(format!("{}", e)) }
+
+fn validate_with_keys(jwks: &Jwks, issuer: &str, client_id: &str, id_token: &str) -> Result<Value> {
+    let header = jsonwebtoken::decode_header(id_token).map_err(invalid)?;
+    // Only the provider's keys may sign the token, never a secret the client also knows
+    match header.alg {
+        Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 => (),
+        alg => return Err(Error::JwtValidation(format!("unexpected algorithm {:?}", alg))),
+    }
+
+    let jwk = signing_key(jwks, header.kid.as_ref().map(String::as_str))?;
+    let (n, e) = match (&jwk.n, &jwk.e) {
+        (Some(n), Some(e)) => (n, e),
+        _ => return Err(Error::JwtValidation("the signing key has no modulus".to_string())),
+    };
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[client_id]);
+    validation.iss = Some(issuer.to_string());
+
+    let data = jsonwebtoken::decode::<Value>(id_token,
+                                             &DecodingKey::from_rsa_components(n, e),
+                                             &validation).map_err(invalid)?;
+    Ok(data.claims)
+}
+
+// A token requested with a nonce repeats it, so a token issued to another sign in can't be
+// replayed into this one. Neither has one when the authorize URL carried none.
+fn check_nonce(claims: &Value, nonce: Option<&str>) -> Result<()> {
+    match (claims.get("nonce").and_then(Value::as_str), nonce) {
+        (Some(claim), Some(nonce)) if claim == nonce => Ok(()),
+        (None, None) => Ok(()),
+        (claim, _) => {
+            let claim = claim.unwrap_or("(none)");
+            Err(Error::JwtValidation(format!("unexpected nonce {}", claim)))
+        }
+    }
+}
+
+// The RSA key the token names, the only one when it names none
+fn signing_key<'a>(jwks: &'a Jwks, kid: Option<&str>) -> Result<&'a Jwk> {
+    let mut keys = jwks.keys.iter().filter(|k| k.kty == "RSA");
+    let key = match kid {
+        Some(kid) => keys.find(|k| k.kid.as_ref().map(String::as_str) == Some(kid)),
+        None if jwks.keys.len() == 1 => keys.next(),
+        None => None,
+    };
+    key.ok_or_else(|| {
+           Error::JwtValidation(format!("no signing key {} in the provider's JWKS",
+                                        kid.unwrap_or("(none named)")))
+       })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self,
+                              Provider,
+                              Reply};
+    use builder_core::http_client::HttpClientFactory;
+    use std::{fs,
+              path::PathBuf};
+
+    const ISSUER: &str = "https://example.okta.com/oauth2/default";
+    const CLIENT_ID: &str = "0oa1builderclient";
+
+    fn fixture(name: &str) -> String {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+                                                            .join(name);
+        fs::read_to_string(path).unwrap().trim().to_string()
+    }
+
+    fn keys(body: &str) -> Jwks { serde_json::from_str(body).unwrap() }
+
+    // The JWKS with the key the tokens are signed with renamed, as before a key rotation
+    fn jwks_before_rotation() -> String {
+        fixture("okta-jwks.json").replace("k1-2019-10", "k1-next")
+    }
+
+    fn validate_fixture(name: &str, issuer: &str) -> Result<Value> {
+        validate_with_keys(&keys(&fixture("okta-jwks.json")),
+                           issuer,
+                           CLIENT_ID,
+                           &fixture(name))
+    }
+
+    fn assert_invalid(result: Result<Value>) {
+        match result {
+            Err(Error::JwtValidation(_)) => (),
+            Err(e) => panic!("Expected JwtValidation, got {:?}", e),
+            Ok(claims) => panic!("Expected JwtValidation, got claims of {}", claims["sub"]),
+        }
+    }
+
+    #[test]
+    fn a_valid_token_gives_its_claims() {
+        let claims = validate_fixture("okta-id-token.jwt", ISSUER).unwrap();
+        assert_eq!(claims["sub"], "00u1a2b3c4d5e6f7g8h9");
+        assert_eq!(claims["preferred_username"], "jdoe@example.com");
+        assert_eq!(claims["email"], "jdoe@example.com");
+    }
+
+    #[test]
+    fn tokens_of_another_issuer_or_client_are_refused() {
+        assert_invalid(validate_fixture("okta-id-token.jwt", "https://evil.example.com"));
+        assert_invalid(validate_fixture("okta-id-token-other-audience.jwt", ISSUER));
+    }
+
+    #[test]
+    fn expired_tokens_are_refused() {
+        assert_invalid(validate_fixture("okta-id-token-expired.jwt", ISSUER));
+    }
+
+    #[test]
+    fn tokens_not_signed_by_the_providers_keys_are_refused() {
+        assert_invalid(validate_fixture("okta-id-token-unknown-key.jwt", ISSUER));
+        assert_invalid(validate_fixture("okta-id-token-hs256.jwt", ISSUER));
+
+        // Signed by the key the token names, checked with another key of the set
+        let jwks = fixture("okta-jwks.json").replace("k1-2019-10", "k1-swapped")
+                                            .replace("k0-2019-04", "k1-2019-10");
+        assert_invalid(validate_with_keys(&keys(&jwks),
+                                          ISSUER,
+                                          CLIENT_ID,
+                                          &fixture("okta-id-token.jwt")));
+    }
+
+    #[test]
+    fn tokens_must_repeat_the_nonce_of_the_sign_in() {
+        let claims = serde_json::json!({ "sub": "00u1a2b3c4d5e6f7g8h9", "nonce": "n-0S6_WzA2Mj" });
+        assert!(check_nonce(&claims, Some("n-0S6_WzA2Mj")).is_ok());
+        assert!(check_nonce(&claims, Some("n-replayed")).is_err());
+        assert!(check_nonce(&claims, None).is_err());
+
+        let claims = serde_json::json!({ "sub": "00u1a2b3c4d5e6f7g8h9" });
+        assert!(check_nonce(&claims, None).is_ok());
+        assert!(check_nonce(&claims, Some("n-0S6_WzA2Mj")).is_err());
+    }
+
+    // Answers each request with the next of `bodies`, the last one once they run out
+    fn provider(bodies: Vec<String>) -> Provider {
+        test_support::serve(move |_, n| Reply::new(200, &bodies[n.min(bodies.len() - 1)]))
+    }
+
+    fn validate_cached(cache: &JwksCache, jwks_url: &str, cache_secs: u64) -> Result<Value> {
+        let config = OAuth2Cfg { provider: "okta".to_string(),
+                                 client_id: CLIENT_ID.to_string(),
+                                 jwks_url: jwks_url.to_string(),
+                                 jwks_cache_secs: cache_secs,
+                                 ..Default::default() };
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let client = http.client(jwks_url, Default::default()).unwrap();
+        cache.validate(&config,
+                       &client,
+                       ISSUER,
+                       &fixture("okta-id-token.jwt"),
+                       None)
+    }
+
+    // Makes the cached keys look fetched `secs` ago
+    fn age(cache: &JwksCache, secs: u64) {
+        let mut cached = cache.cached.lock().unwrap();
+        if let Some((ref mut fetched_at, _)) = *cached {
+            *fetched_at = fetched_at.checked_sub(Duration::from_secs(secs)).unwrap();
+        }
+    }
+
+    #[test]
+    fn keys_are_fetched_once_until_they_expire() {
+        let provider = provider(vec![fixture("okta-jwks.json")]);
+        let url = format!("{}/v1/keys", provider.url);
+        let cache = JwksCache::default();
+        validate_cached(&cache, &url, 3600).unwrap();
+        validate_cached(&cache, &url, 3600).unwrap();
+        assert_eq!(provider.requests(), 1);
+
+        age(&cache, 3600);
+        validate_cached(&cache, &url, 3600).unwrap();
+        assert_eq!(provider.requests(), 2);
+    }
+
+    #[test]
+    fn keys_are_fetched_again_for_a_key_they_lack() {
+        let provider = provider(vec![jwks_before_rotation(), fixture("okta-jwks.json")]);
+        let url = format!("{}/v1/keys", provider.url);
+        let cache = JwksCache::default();
+        assert_invalid(validate_cached(&cache, &url, 3600));
+
+        // Not while the keys were only just fetched
+        assert_invalid(validate_cached(&cache, &url, 3600));
+        assert_eq!(provider.requests(), 1);
+
+        age(&cache, MIN_REFETCH_SECS);
+        validate_cached(&cache, &url, 3600).unwrap();
+        assert_eq!(provider.requests(), 2);
+    }
+}
@@ -0,0 +1,248 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::iter::FromIterator;
+
+use serde_json;
+
+use reqwest::header::HeaderMap;
+
+use builder_core::http_client::{HttpClient,
+                                ACCEPT_APPLICATION_JSON,
+                                CONTENT_TYPE_FORM_URL_ENCODED};
+
+use crate::{config::{ClientAuthMethod,
+                     OAuth2Cfg},
+            error::{Error,
+                    Result},
+            retry,
+            token::{self,
+                    ClientAuth},
+            types::*};
+
+/// A Keycloak realm. Its endpoints are those the realm serves under the configured base URL,
+/// unless the token or userinfo URL is set explicitly.
+pub struct Keycloak {
+    token_url:    String,
+    userinfo_url: String,
+}
+
+#[derive(Deserialize)]
+struct AuthOk {
+    pub access_token:  String,
+    pub refresh_token: Option<String>,
+    pub expires_in:    Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    pub sub:                String,
+    pub preferred_username: Option<String>,
+    pub email:              Option<String>,
+    pub email_verified:     Option<bool>,
+}
+
+impl User {
+    // Realms that map usernames away, or clients without the profile scope, leave out the
+    // preferred_username claim
+    fn username(&self) -> String {
+        match self.preferred_username {
+            Some(ref name) if !name.is_empty() => name.clone(),
+            _ => self.sub.clone(),
+        }
+    }
+}
+
+impl Keycloak {
+    pub fn new(config: &OAuth2Cfg) -> Self {
+        Keycloak { token_url:    endpoint(config, config.token_url_override(), "token"),
+                   userinfo_url: endpoint(config, config.userinfo_url_override(), "userinfo"), }
+    }
+
+    pub fn token_url(&self) -> &str { &self.token_url }
+
+    fn user(&self, config: &OAuth2Cfg, client: &HttpClient, token: &str) -> Result<OAuth2User> {
+        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),];
+        let headers = HeaderMap::from_iter(header_values.into_iter());
+
+        let mut resp = retry::send(config, || {
+            client.get(&self.userinfo_url)
+                  .headers(headers.clone())
+                  .bearer_auth(token)
+        })?;
+
+        let body = resp.text().map_err(Error::http_client)?;
+        debug!("Keycloak response body: {}", body);
+
+        if resp.status().is_success() {
+            user_from_userinfo(&body)
+        } else {
+            Err(Error::HttpResponse(resp.status(), body))
+        }
+    }
+}
+
+// An endpoint of the realm's OpenID Connect protocol, the configured URL when there is one
+fn endpoint(config: &OAuth2Cfg, configured: Option<&str>, name: &str) -> String {
+    if let Some(url) = configured {
+        return url.to_string();
+    }
+    format!("{}/realms/{}/protocol/openid-connect/{}",
+            config.base_url.trim_end_matches('/'),
+            config.realm,
+            name)
+}
+
+// The user of a userinfo response. Keycloak sends email_verified: false for addresses nobody
+// confirmed; the client refuses those users when the operator asks for it.
+fn user_from_userinfo(body: &str) -> Result<OAuth2User> {
+    let user = serde_json::from_str::<User>(body).map_err(Error::Serialization)?;
+    Ok(OAuth2User { username:       user.username(),
+                    id:             user.sub,
+                    email:          user.email,
+                    email_verified: user.email_verified,
+                    groups:         None, })
+}
+
+impl OAuth2Provider for Keycloak {
+    fn authenticate(&self,
+                    config: &OAuth2Cfg,
+                    client: &HttpClient,
+                    code: &str,
+                    verifier: Option<&str>,
+                    _nonce: Option<&str>)
+                    -> Result<(OAuth2Token, OAuth2User)> {
+        let auth = ClientAuth::configured(config, ClientAuthMethod::Body);
+        let body = token::authorization_code(config, code, verifier, &auth);
+
+        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),
+                                 CONTENT_TYPE_FORM_URL_ENCODED.clone()];
+        let headers = HeaderMap::from_iter(header_values.into_iter());
+
+        let mut resp = retry::send_token_request(config, || {
+            let req = client.post(&self.token_url)
+                            .headers(headers.clone())
+                            .body(body.clone());
+            token::authenticated(config, req, &auth)
+        })?;
+
+        let body = resp.text().map_err(Error::http_client)?;
+        debug!("Keycloak response body: {}", body);
+
+        let token = if resp.status().is_success() {
+            match serde_json::from_str::<AuthOk>(&body) {
+                Ok(msg) => {
+                    OAuth2Token { access_token:  msg.access_token,
+                                  refresh_token: msg.refresh_token,
+                                  expires_in:    msg.expires_in, }
+                }
+                Err(e) => return Err(Error::Serialization(e)),
+            }
+        } else {
+            return Err(Error::token_request(resp.status(), body));
+        };
+
+        let user = self.user(config, client, &token.access_token)?;
+        Ok((token, user))
+    }
+
+    fn refresh(&self,
+               config: &OAuth2Cfg,
+               client: &HttpClient,
+               refresh_token: &str)
+               -> Result<OAuth2Token> {
+        token::refresh(config,
+                       client,
+                       &self.token_url,
+                       refresh_token,
+                       ClientAuth::configured(config, ClientAuthMethod::Body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DEFAULT_GITHUB_TOKEN_URL;
+    use std::{fs,
+              path::PathBuf};
+
+    fn fixture(name: &str) -> String {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+                                                            .join(name);
+        fs::read_to_string(path).unwrap()
+    }
+
+    fn config() -> OAuth2Cfg {
+        OAuth2Cfg { provider: "keycloak".to_string(),
+                    base_url: "https://sso.example.com/auth/".to_string(),
+                    realm: "builder".to_string(),
+                    ..Default::default() }
+    }
+
+    #[test]
+    fn endpoints_are_built_from_the_realm() {
+        let keycloak = Keycloak::new(&config());
+        assert_eq!(keycloak.token_url,
+                   "https://sso.example.com/auth/realms/builder/protocol/openid-connect/token");
+        assert_eq!(keycloak.userinfo_url,
+                   "https://sso.example.com/auth/realms/builder/protocol/openid-connect/userinfo");
+    }
+
+    #[test]
+    fn the_github_defaults_are_not_overrides() {
+        let default = OAuth2Cfg::default();
+        assert_eq!(default.token_url, DEFAULT_GITHUB_TOKEN_URL);
+        let config = OAuth2Cfg { provider: "keycloak".to_string(),
+                                 base_url: "https://sso.example.com/auth".to_string(),
+                                 realm: "builder".to_string(),
+                                 ..default };
+        let keycloak = Keycloak::new(&config);
+        assert_eq!(keycloak.token_url,
+                   "https://sso.example.com/auth/realms/builder/protocol/openid-connect/token");
+        assert_eq!(keycloak.userinfo_url,
+                   "https://sso.example.com/auth/realms/builder/protocol/openid-connect/userinfo");
+    }
+
+    #[test]
+    fn configured_endpoints_are_kept() {
+        let config = OAuth2Cfg { token_url: "https://proxy.example.com/token".to_string(),
+                                 ..config() };
+        let keycloak = Keycloak::new(&config);
+        assert_eq!(keycloak.token_url, "https://proxy.example.com/token");
+        assert_eq!(keycloak.userinfo_url,
+                   "https://sso.example.com/auth/realms/builder/protocol/openid-connect/userinfo");
+    }
+
+    #[test]
+    fn username_is_the_preferred_username() {
+        let user = user_from_userinfo(&fixture("keycloak-user.json")).unwrap();
+        assert_eq!(user.id, "f7a1c2d4-5b3e-4e6f-9a8b-0c1d2e3f4a5b");
+        assert_eq!(user.username, "jdoe");
+        assert_eq!(user.email, Some("jdoe@example.com".to_string()));
+    }
+
+    #[test]
+    fn username_falls_back_to_the_subject() {
+        let user = user_from_userinfo(&fixture("keycloak-user-minimal.json")).unwrap();
+        assert_eq!(user.id, "0b9e8d7c-6a5f-4e3d-2c1b-a09f8e7d6c5b");
+        assert_eq!(user.username, "0b9e8d7c-6a5f-4e3d-2c1b-a09f8e7d6c5b");
+        assert_eq!(user.email, Some("build-bot@example.com".to_string()));
+    }
+
+    #[test]
+    fn unverified_emails_are_reported() {
+        let user = user_from_userinfo(&fixture("keycloak-user-minimal.json")).unwrap();
+        assert_eq!(user.email_verified, Some(false));
+    }
+}
@@ -20,13 +20,29 @@ extern crate serde_derive;
 
 pub mod a2;
 pub mod active_directory;
+pub mod adfs;
+pub mod auth0;
 pub mod azure_ad;
 pub mod bitbucket;
+pub mod bitbucket_server;
+pub mod claims;
 pub mod client;
+pub mod cognito;
 pub mod config;
 pub mod error;
 pub mod github;
 pub mod gitlab;
+pub mod google;
+pub mod id_token;
+pub mod keycloak;
 pub mod metrics;
+pub mod oidc;
 pub mod okta;
+pub mod pkce;
+pub mod retry;
+pub mod token;
 pub mod types;
+pub mod userinfo_cache;
+
+#[cfg(test)]
+mod test_support;
@@ -20,6 +20,9 @@ use std::borrow::Cow;
 
 pub enum Counter {
     Authenticate(String),
+    Refresh(String),
+    Revoke(String),
+    User(String),
 }
 
 impl metrics::CounterMetric for Counter {}
@@ -28,6 +31,9 @@ impl metrics::Metric for Counter {
     fn id(&self) -> Cow<'static, str> {
         match *self {
             Counter::Authenticate(ref provider) => format!("{}.authenticate", provider).into(),
+            Counter::Refresh(ref provider) => format!("{}.refresh", provider).into(),
+            Counter::Revoke(ref provider) => format!("{}.revoke", provider).into(),
+            Counter::User(ref provider) => format!("{}.user", provider).into(),
         }
     }
 }
@@ -0,0 +1,320 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Any OpenID Connect provider. Its token and userinfo endpoints are read from the discovery
+//! document of the configured `issuer_url` when the client is created, `token_url` and
+//! `userinfo_url` are not used.
+
+use std::iter::FromIterator;
+
+use serde_json::{self,
+                 Value};
+
+use reqwest::header::HeaderMap;
+
+use builder_core::http_client::{HttpClient,
+                                ACCEPT_APPLICATION_JSON,
+                                CONTENT_TYPE_FORM_URL_ENCODED};
+
+use crate::{claims,
+            config::{ClientAuthMethod,
+                     OAuth2Cfg},
+            error::{Error,
+                    Result},
+            id_token::JwksCache,
+            retry,
+            token::{self,
+                    ClientAuth},
+            types::*};
+
+const DISCOVERY_PATH: &str = "/.well-known/openid-configuration";
+// Claim the username is read from when no username_claim is configured
+const USERNAME_CLAIMS: &[&str] = &[claims::DEFAULT_USERNAME_CLAIM];
+
+pub struct OpenIdConnect {
+    issuer:            String,
+    token_endpoint:    String,
+    userinfo_endpoint: String,
+    jwks:              JwksCache,
+}
+
+// The fields of the discovery document Builder reads, see OpenID Connect Discovery 1.0
+#[derive(Deserialize)]
+struct Discovery {
+    pub issuer:            String,
+    pub token_endpoint:    String,
+    pub userinfo_endpoint: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AuthOk {
+    pub access_token:  String,
+    pub refresh_token: Option<String>,
+    pub expires_in:    Option<u64>,
+    pub id_token:      Option<String>,
+}
+
+// The user the claims name. Without a username_claim or a preferred_username, the user is
+// named by their handle, which refuses an unverified email.
+fn user_of(config: &OAuth2Cfg, claims: &Value) -> Result<OAuth2User> {
+    if config.username_claim.is_some()
+       || claims::string(claims, claims::DEFAULT_USERNAME_CLAIM).is_some()
+    {
+        return claims::user(config, claims, USERNAME_CLAIMS);
+    }
+    let mut user = claims::user(config, claims, &[claims::DEFAULT_ID_CLAIM])?;
+    user.username = claims::handle(&user.id,
+                                   user.email.as_ref().map(String::as_str),
+                                   user.email_verified)?;
+    Ok(user)
+}
+
+fn discovery_url(issuer: &str) -> String {
+    format!("{}{}", issuer.trim_end_matches('/'), DISCOVERY_PATH)
+}
+
+impl OpenIdConnect {
+    /// Reads the provider's endpoints from the discovery document of `config.issuer_url`
+    pub fn discover(config: &OAuth2Cfg, client: &HttpClient) -> Result<Self> {
+        if config.issuer_url.is_empty() {
+            return Err(Error::Discovery("no issuer_url is configured".to_string()));
+        }
+        let url = discovery_url(&config.issuer_url);
+
+        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),];
+        let headers = HeaderMap::from_iter(header_values.into_iter());
+
+        let unreachable = |e: Error| {
+            match e {
+                Error::HttpClient(e) | Error::Timeout(e) => {
+                    Error::Discovery(format!("{} is unreachable, {}", url, e))
+                }
+                e => e,
+            }
+        };
+        let mut resp =
+            retry::send(config, || client.get(&url).headers(headers.clone())).map_err(unreachable)?;
+
+        let body = resp.text()
+                       .map_err(|e| Error::Discovery(format!("{} is unreadable, {}", url, e)))?;
+        debug!("OpenIdConnect discovery document: {}", body);
+
+        if !resp.status().is_success() {
+            let msg = format!("{} answered {}", url, resp.status());
+            return Err(Error::Discovery(msg));
+        }
+
+        Self::from_document(&config.issuer_url, &body)
+    }
+
+    fn from_document(issuer: &str, body: &str) -> Result<Self> {
+        let doc = serde_json::from_str::<Discovery>(body).map_err(|e| {
+                      Error::Discovery(format!("the discovery document is malformed, {}", e))
+                  })?;
+
+        // The issuer must be the one the document was fetched for, section 4.3 of the spec
+        if doc.issuer.trim_end_matches('/') != issuer.trim_end_matches('/') {
+            let msg = format!("the discovery document is for issuer {}", doc.issuer);
+            return Err(Error::Discovery(msg));
+        }
+        let userinfo_endpoint = match doc.userinfo_endpoint {
+            Some(endpoint) => endpoint,
+            None => {
+                let msg = "the discovery document has no userinfo_endpoint".to_string();
+                return Err(Error::Discovery(msg));
+            }
+        };
+
+        Ok(OpenIdConnect { issuer: doc.issuer,
+                           token_endpoint: doc.token_endpoint,
+                           userinfo_endpoint,
+                           jwks: JwksCache::default() })
+    }
+
+    fn user(&self, config: &OAuth2Cfg, client: &HttpClient, token: &str) -> Result<OAuth2User> {
+        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),];
+        let headers = HeaderMap::from_iter(header_values.into_iter());
+
+        let mut resp = retry::send(config, || {
+            client.get(&self.userinfo_endpoint)
+                  .headers(headers.clone())
+                  .bearer_auth(token)
+        })?;
+
+        let body = resp.text().map_err(Error::http_client)?;
+        debug!("OpenIdConnect response body: {}", body);
+
+        if resp.status().is_success() {
+            let user = match serde_json::from_str::<Value>(&body) {
+                Ok(msg) => msg,
+                Err(e) => return Err(Error::Serialization(e)),
+            };
+
+            user_of(config, &user)
+        } else {
+            Err(Error::HttpResponse(resp.status(), body))
+        }
+    }
+}
+
+impl OAuth2Provider for OpenIdConnect {
+    fn authenticate(&self,
+                    config: &OAuth2Cfg,
+                    client: &HttpClient,
+                    code: &str,
+                    verifier: Option<&str>,
+                    nonce: Option<&str>)
+                    -> Result<(OAuth2Token, OAuth2User)> {
+        let auth = ClientAuth::configured(config, ClientAuthMethod::Body);
+        let body = token::authorization_code(config, code, verifier, &auth);
+
+        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),
+                                 CONTENT_TYPE_FORM_URL_ENCODED.clone()];
+        let headers = HeaderMap::from_iter(header_values.into_iter());
+
+        let mut resp = retry::send_token_request(config, || {
+            let req = client.post(&self.token_endpoint)
+                            .headers(headers.clone())
+                            .body(body.clone());
+            token::authenticated(config, req, &auth)
+        })?;
+
+        let body = resp.text().map_err(Error::http_client)?;
+        debug!("OpenIdConnect response body: {}", body);
+
+        let msg = if resp.status().is_success() {
+            match serde_json::from_str::<AuthOk>(&body) {
+                Ok(msg) => msg,
+                Err(e) => return Err(Error::Serialization(e)),
+            }
+        } else {
+            return Err(Error::token_request(resp.status(), body));
+        };
+
+        let user = match msg.id_token {
+            Some(ref id_token) if !config.jwks_url.is_empty() => {
+                let claims = self.jwks
+                                 .validate(config, client, &self.issuer, id_token, nonce)?;
+                user_of(config, &claims)?
+            }
+            _ => self.user(config, client, &msg.access_token)?,
+        };
+        let token = OAuth2Token { access_token:  msg.access_token,
+                                  refresh_token: msg.refresh_token,
+                                  expires_in:    msg.expires_in, };
+        Ok((token, user))
+    }
+
+    fn refresh(&self,
+               config: &OAuth2Cfg,
+               client: &HttpClient,
+               refresh_token: &str)
+               -> Result<OAuth2Token> {
+        token::refresh(config,
+                       client,
+                       &self.token_endpoint,
+                       refresh_token,
+                       ClientAuth::configured(config, ClientAuthMethod::Body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::{fs,
+              path::PathBuf};
+
+    const ISSUER: &str = "https://sso.example.com/realms/builder";
+
+    fn fixture(name: &str) -> String {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+                                                            .join(name);
+        fs::read_to_string(path).unwrap()
+    }
+
+    fn assert_discovery_error(result: Result<OpenIdConnect>, expected: &str) {
+        match result {
+            Err(Error::Discovery(ref msg)) => assert!(msg.contains(expected), "{}", msg),
+            Err(e) => panic!("Expected a discovery error, got {:?}", e),
+            Ok(_) => panic!("Expected a discovery error"),
+        }
+    }
+
+    #[test]
+    fn endpoints_are_read_from_the_discovery_document() {
+        let oidc = OpenIdConnect::from_document(ISSUER, &fixture("oidc-discovery.json")).unwrap();
+        assert_eq!(oidc.token_endpoint,
+                   "https://sso.example.com/realms/builder/protocol/openid-connect/token");
+        assert_eq!(oidc.userinfo_endpoint,
+                   "https://sso.example.com/realms/builder/protocol/openid-connect/userinfo");
+
+        let with_slash = format!("{}/", ISSUER);
+        assert!(OpenIdConnect::from_document(&with_slash, &fixture("oidc-discovery.json")).is_ok());
+    }
+
+    #[test]
+    fn discovery_document_is_under_the_issuer() {
+        assert_eq!(discovery_url("https://sso.example.com/realms/builder/"),
+                   "https://sso.example.com/realms/builder/.well-known/openid-configuration");
+        assert_eq!(discovery_url("https://accounts.example.com"),
+                   "https://accounts.example.com/.well-known/openid-configuration");
+    }
+
+    #[test]
+    fn documents_of_another_issuer_are_refused() {
+        assert_discovery_error(OpenIdConnect::from_document("https://sso.example.com/realms/other",
+                                                            &fixture("oidc-discovery.json")),
+                               "for issuer");
+    }
+
+    #[test]
+    fn malformed_documents_are_refused() {
+        assert_discovery_error(OpenIdConnect::from_document(ISSUER, "<html>Not Found</html>"),
+                               "malformed");
+        let no_userinfo = format!(r#"{{"issuer": "{}", "token_endpoint": "{}/token"}}"#,
+                                  ISSUER, ISSUER);
+        assert_discovery_error(OpenIdConnect::from_document(ISSUER, &no_userinfo),
+                               "userinfo_endpoint");
+    }
+
+    const SUB: &str = "f1c2a3e4-5b6d-4e7f-8a9b-0c1d2e3f4a5b";
+
+    #[test]
+    fn username_falls_back_to_the_verified_email() {
+        let claims =
+            json!({ "sub": SUB, "preferred_username": "jdoe", "email": "jane.doe@example.com" });
+        let user = user_of(&Default::default(), &claims).unwrap();
+        assert_eq!(user.id, SUB);
+        assert_eq!(user.username, "jdoe");
+
+        let claims = json!({ "sub": SUB, "email": "jane.doe@example.com", "email_verified": true });
+        let user = user_of(&Default::default(), &claims).unwrap();
+        assert_eq!(user.username, "jane.doe@example.com");
+        assert_eq!(user.email, Some("jane.doe@example.com".to_string()));
+
+        let user = user_of(&Default::default(), &json!({ "sub": SUB })).unwrap();
+        assert_eq!(user.username, SUB);
+    }
+
+    #[test]
+    fn unverified_emails_are_not_usernames() {
+        let claims = json!({ "sub": SUB, "email": "jane.doe@example.com" });
+        match user_of(&Default::default(), &claims) {
+            Err(Error::EmailRequired(_)) => (),
+            Err(e) => panic!("Expected EmailRequired, got {:?}", e),
+            Ok(user) => panic!("Expected EmailRequired, got user {}", user.username),
+        }
+    }
+}
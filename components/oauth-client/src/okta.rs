@@ -14,99 +14,461 @@
 
 use std::iter::FromIterator;
 
-use serde_json;
+use serde_json::{self,
+                 Value};
 
-use reqwest::{header::HeaderMap,
-              Body};
+use reqwest::header::HeaderMap;
 
 use builder_core::http_client::{HttpClient,
                                 ACCEPT_APPLICATION_JSON,
                                 CONTENT_TYPE_FORM_URL_ENCODED};
 
-use crate::{config::OAuth2Cfg,
+use crate::{claims,
+            config::{ClientAuthMethod,
+                     OAuth2Cfg},
             error::{Error,
                     Result},
-            types::*};
+            id_token::JwksCache,
+            retry,
+            token::{self,
+                    ClientAuth},
+            types::*,
+            userinfo_cache::UserinfoCache};
 
-pub struct Okta;
+// Claim the username is read from when no username_claim is configured, whether the user is
+// read from the ID token or from userinfo
+const USERNAME_CLAIMS: &[&str] = &[claims::DEFAULT_USERNAME_CLAIM];
 
-#[derive(Deserialize)]
-struct AuthOk {
-    pub access_token: String,
+#[derive(Default)]
+pub struct Okta {
+    jwks:     JwksCache,
+    userinfo: UserinfoCache,
 }
 
 #[derive(Deserialize)]
-struct User {
-    pub sub:                String,
-    pub preferred_username: String,
-    pub email:              Option<String>,
+struct AuthOk {
+    pub access_token:  String,
+    pub refresh_token: Option<String>,
+    pub expires_in:    Option<u64>,
+    pub id_token:      Option<String>,
 }
 
-impl Okta {
-    fn user(&self, config: &OAuth2Cfg, client: &HttpClient, token: &str) -> Result<OAuth2User> {
-        let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),];
-        let headers = HeaderMap::from_iter(header_values.into_iter());
+fn fetch_user(config: &OAuth2Cfg, client: &HttpClient, token: &str) -> Result<OAuth2User> {
+    let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),];
+    let headers = HeaderMap::from_iter(header_values.into_iter());
 
-        let mut resp = client.get(&config.userinfo_url)
-                             .headers(headers)
-                             .bearer_auth(token)
-                             .send()
-                             .map_err(Error::HttpClient)?;
+    let mut resp = retry::send(config, || {
+        client.get(&config.userinfo_url)
+              .headers(headers.clone())
+              .bearer_auth(token)
+    })?;
 
-        let body = resp.text().map_err(Error::HttpClient)?;
-        debug!("Okta response body: {}", body);
+    let body = resp.text().map_err(Error::http_client)?;
+    debug!("Okta response body: {}", body);
 
-        if resp.status().is_success() {
-            let user = match serde_json::from_str::<User>(&body) {
-                Ok(msg) => msg,
-                Err(e) => return Err(Error::Serialization(e)),
-            };
+    if resp.status().is_success() {
+        let claims = match serde_json::from_str::<Value>(&body) {
+            Ok(msg) => msg,
+            Err(e) => return Err(Error::Serialization(e)),
+        };
 
-            Ok(OAuth2User { id:       user.sub,
-                            username: user.preferred_username,
-                            email:    user.email, })
-        } else {
-            Err(Error::HttpResponse(resp.status(), body))
-        }
+        let user = claims::user(config, &claims, USERNAME_CLAIMS)?;
+        Ok(with_groups(config, &claims, user))
+    } else {
+        Err(Error::HttpResponse(resp.status(), body))
     }
 }
 
+// The user with the groups of the configured `groups_claim`. Okta leaves the claim out for
+// users in none of the groups its filter lets through, they are in no group.
+fn with_groups(config: &OAuth2Cfg, claims: &Value, user: OAuth2User) -> OAuth2User {
+    let groups = config.groups_claim
+                       .as_ref()
+                       .map(|claim| claims::strings(claims, claim).unwrap_or_default());
+    OAuth2User { groups, ..user }
+}
+
 impl OAuth2Provider for Okta {
     fn authenticate(&self,
                     config: &OAuth2Cfg,
                     client: &HttpClient,
-                    code: &str)
-                    -> Result<(String, OAuth2User)> {
+                    code: &str,
+                    verifier: Option<&str>,
+                    nonce: Option<&str>)
+                    -> Result<(OAuth2Token, OAuth2User)> {
         let url = config.token_url.to_string();
-        let body = format!("client_id={}&client_secret={}&grant_type=authorization_code&code={}&\
-                            redirect_uri={}",
-                           config.client_id, config.client_secret, code, config.redirect_url);
+        let auth = ClientAuth::configured(config, ClientAuthMethod::Body);
+        let body = token::authorization_code(config, code, verifier, &auth);
 
         let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),
                                  CONTENT_TYPE_FORM_URL_ENCODED.clone()];
         let headers = HeaderMap::from_iter(header_values.into_iter());
 
-        let body: Body = body.into();
+        let mut resp = retry::send_token_request(config, || {
+            let req = client.post(&url)
+                            .headers(headers.clone())
+                            .body(body.clone());
+            token::authenticated(config, req, &auth)
+        })?;
 
-        let mut resp = client.post(&url)
-                             .headers(headers)
-                             .body(body)
-                             .send()
-                             .map_err(Error::HttpClient)?;
-
-        let body = resp.text().map_err(Error::HttpClient)?;
+        let body = resp.text().map_err(Error::http_client)?;
         debug!("Okta response body: {}", body);
 
-        let token = if resp.status().is_success() {
+        let msg = if resp.status().is_success() {
             match serde_json::from_str::<AuthOk>(&body) {
-                Ok(msg) => msg.access_token,
+                Ok(msg) => msg,
                 Err(e) => return Err(Error::Serialization(e)),
             }
         } else {
-            return Err(Error::HttpResponse(resp.status(), body));
+            return Err(Error::token_request(resp.status(), body));
         };
 
-        let user = self.user(config, client, &token)?;
+        let user = match msg.id_token {
+            Some(ref id_token) if !config.jwks_url.is_empty() => {
+                let claims =
+                    self.jwks
+                        .validate(config, client, &config.issuer_url, id_token, nonce)?;
+                let user = claims::user(config, &claims, USERNAME_CLAIMS)?;
+                with_groups(config, &claims, user)
+            }
+            _ => self.user(config, client, &msg.access_token)?,
+        };
+        let token = OAuth2Token { access_token:  msg.access_token,
+                                  refresh_token: msg.refresh_token,
+                                  expires_in:    msg.expires_in, };
         Ok((token, user))
     }
+
+    fn refresh(&self,
+               config: &OAuth2Cfg,
+               client: &HttpClient,
+               refresh_token: &str)
+               -> Result<OAuth2Token> {
+        token::refresh(config,
+                       client,
+                       &config.token_url,
+                       refresh_token,
+                       ClientAuth::configured(config, ClientAuthMethod::Body))
+    }
+
+    fn user(&self, config: &OAuth2Cfg, client: &HttpClient, token: &str) -> Result<OAuth2User> {
+        self.userinfo
+            .user(config, token, || fetch_user(config, client, token))
+    }
+
+    fn revoke(&self, config: &OAuth2Cfg, client: &HttpClient, token: &str) -> Result<()> {
+        self.userinfo.forget(token);
+        token::revoke(config,
+                      client,
+                      token,
+                      ClientAuth::configured(config, ClientAuthMethod::Body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::OAuth2Client,
+                test_support::{self,
+                               Reply}};
+    use builder_core::http_client::HttpClientFactory;
+    use serde_json::json;
+    use std::{fs,
+              path::PathBuf,
+              sync::mpsc,
+              time::{Duration,
+                     Instant}};
+
+    #[test]
+    fn a_hung_provider_fails_the_login() {
+        let provider = test_support::hung();
+        let config = OAuth2Cfg { provider: "okta".to_string(),
+                                 token_url: format!("{}/v1/token", provider.url),
+                                 request_timeout_ms: Some(200),
+                                 ..Default::default() };
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let client = OAuth2Client::new(config, &http).unwrap();
+
+        let started = Instant::now();
+        match client.authenticate("code", None, None) {
+            Err(Error::Timeout(_)) => (),
+            Err(e) => panic!("Expected Timeout, got {:?}", e),
+            Ok((_, user)) => panic!("Expected Timeout, got user {}", user.username),
+        }
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn an_unreachable_provider_fails_the_login_at_the_connect_timeout() {
+        let provider = test_support::backlogged();
+        let config = OAuth2Cfg { provider: "okta".to_string(),
+                                 token_url: format!("{}/v1/token", provider.url),
+                                 connect_timeout_ms: Some(100),
+                                 max_retries: 0,
+                                 ..Default::default() };
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let client = OAuth2Client::new(config, &http).unwrap();
+
+        let started = Instant::now();
+        match client.authenticate("code", None, None) {
+            Err(Error::Timeout(_)) => (),
+            Err(e) => panic!("Expected Timeout, got {:?}", e),
+            Ok((_, user)) => panic!("Expected Timeout, got user {}", user.username),
+        }
+        // Well before the connect timeout of the [http] settings
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn a_refused_verifier_fails_with_the_providers_reason() {
+        // Answers every request as Okta does a token request missing the PKCE verifier
+        let provider = test_support::serve(|_, _| {
+            let body = json!({ "error": "invalid_grant",
+                               "error_description": "PKCE verification failed." });
+            Reply::json(400, &body)
+        });
+        let config = OAuth2Cfg { provider: "okta".to_string(),
+                                 token_url: format!("{}/v1/token", provider.url),
+                                 ..Default::default() };
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let client = OAuth2Client::new(config, &http).unwrap();
+
+        match client.authenticate("code", None, None) {
+            Err(Error::TokenRequest(status, error, description)) => {
+                assert_eq!(status.as_u16(), 400);
+                assert_eq!(error, "invalid_grant");
+                assert_eq!(description, "PKCE verification failed.");
+            }
+            Err(e) => panic!("Expected TokenRequest, got {:?}", e),
+            Ok((_, user)) => panic!("Expected TokenRequest, got user {}", user.username),
+        }
+    }
+
+    // The token request a login sends with the client credentials sent the `method` way
+    fn token_request(method: ClientAuthMethod) -> test_support::Request {
+        let (tx, rx) = mpsc::channel();
+        let provider = test_support::serve(move |request, _| {
+            let _ = tx.send(request.clone());
+            Reply::new(401, "")
+        });
+
+        let config = OAuth2Cfg { provider: "okta".to_string(),
+                                 token_url: format!("{}/v1/token", provider.url),
+                                 client_id: "0oa1builder".to_string(),
+                                 client_secret: "s3cret".to_string(),
+                                 client_auth_method: Some(method),
+                                 max_retries: 0,
+                                 ..Default::default() };
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let client = OAuth2Client::new(config, &http).unwrap();
+        assert!(client.authenticate("abc123", None, None).is_err());
+        rx.recv_timeout(Duration::from_secs(10)).unwrap()
+    }
+
+    #[test]
+    fn client_credentials_are_sent_the_configured_way() {
+        let request = token_request(ClientAuthMethod::Body);
+        assert!(request.body
+                       .contains("client_id=0oa1builder&client_secret=s3cret&"));
+        assert_eq!(request.header("Authorization"), None);
+
+        let request = token_request(ClientAuthMethod::Basic);
+        // base64 of 0oa1builder:s3cret
+        assert_eq!(request.header("Authorization"),
+                   Some("Basic MG9hMWJ1aWxkZXI6czNjcmV0"));
+        assert!(!request.body.contains("client_id="));
+        assert!(!request.body.contains("client_secret="));
+        assert!(request.body
+                       .contains("grant_type=authorization_code&code=abc123"));
+    }
+
+    fn sign_in(userinfo: &Value, require_email: bool) -> Result<OAuth2User> {
+        sign_in_with(userinfo,
+                     OAuth2Cfg { require_email,
+                                 ..Default::default() })
+    }
+
+    // Signs in the `userinfo` user with the provider's URLs set in `config`
+    fn sign_in_with(userinfo: &Value, config: OAuth2Cfg) -> Result<OAuth2User> {
+        let provider = test_support::provider(vec![("/v1/token", json!({ "access_token": "at" })),
+                                                   ("/v1/userinfo", userinfo.clone())]);
+        let config = OAuth2Cfg { provider: "okta".to_string(),
+                                 token_url: format!("{}/v1/token", provider.url),
+                                 userinfo_url: format!("{}/v1/userinfo", provider.url),
+                                 ..config };
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let client = OAuth2Client::new(config, &http).unwrap();
+        client.authenticate("code", None, None)
+              .map(|(_, user)| user)
+    }
+
+    #[test]
+    fn a_verified_email_is_required_when_configured() {
+        let jdoe = json!({
+            "sub": "00u1a2b3c4d5e6f7g8h9",
+            "preferred_username": "jdoe@example.com",
+            "email": "jdoe@example.com",
+            "email_verified": true
+        });
+        let mut unverified = jdoe.clone();
+        unverified["email_verified"] = json!(false);
+        let mut missing = jdoe.clone();
+        missing.as_object_mut().unwrap().remove("email");
+
+        assert_eq!(sign_in(&jdoe, true).unwrap().email,
+                   Some("jdoe@example.com".to_string()));
+        for userinfo in &[unverified, missing] {
+            match sign_in(userinfo, true) {
+                Err(Error::EmailRequired(_)) => (),
+                Err(e) => panic!("Expected EmailRequired, got {:?}", e),
+                Ok(user) => panic!("Expected EmailRequired, got user {}", user.username),
+            }
+            assert!(sign_in(userinfo, false).is_ok());
+        }
+    }
+
+    #[test]
+    fn groups_are_read_from_the_configured_claim() {
+        let jdoe = json!({
+            "sub": "00u1a2b3c4d5e6f7g8h9",
+            "preferred_username": "jdoe@example.com",
+            "groups": ["Everyone", "builder-core"]
+        });
+        let groups_of = |userinfo: &Value, groups_claim: Option<&str>| {
+            let config = OAuth2Cfg { groups_claim: groups_claim.map(str::to_string),
+                                     ..Default::default() };
+            sign_in_with(userinfo, config).unwrap().groups
+        };
+
+        assert_eq!(groups_of(&jdoe, Some("groups")),
+                   Some(vec!["Everyone".to_string(), "builder-core".to_string()]));
+        assert_eq!(groups_of(&jdoe, None), None);
+
+        // In none of the groups Okta's filter lets through
+        let mut ungrouped = jdoe.clone();
+        ungrouped.as_object_mut().unwrap().remove("groups");
+        assert_eq!(groups_of(&ungrouped, Some("groups")), Some(vec![]));
+    }
+
+    // Signs in with the ID token of the fixtures, there is no userinfo to fall back on
+    fn sign_in_with_id_token(nonce: Option<&str>) -> Result<OAuth2User> {
+        let fixture = |name: &str| {
+            let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+                                                                .join(name);
+            fs::read_to_string(path).unwrap().trim().to_string()
+        };
+        let jwks = serde_json::from_str::<Value>(&fixture("okta-jwks.json")).unwrap();
+        let url = provider(vec![("/v1/token",
+                                 json!({ "access_token": "at",
+                                         "id_token": fixture("okta-id-token.jwt") })),
+                                ("/v1/keys", jwks)]);
+        let config = OAuth2Cfg { provider: "okta".to_string(),
+                                 client_id: "0oa1builderclient".to_string(),
+                                 issuer_url:
+                                     "https://example.okta.com/oauth2/default".to_string(),
+                                 token_url: format!("{}/v1/token", url),
+                                 userinfo_url: format!("{}/v1/userinfo", url),
+                                 jwks_url: format!("{}/v1/keys", url),
+                                 ..Default::default() };
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let client = OAuth2Client::new(config, &http).unwrap();
+        client.authenticate("code", None, nonce)
+              .map(|(_, user)| user)
+    }
+
+    #[test]
+    fn id_token_users_are_named_as_userinfo_users() {
+        let user = sign_in_with_id_token(None).unwrap();
+        assert_eq!(user.id, "00u1a2b3c4d5e6f7g8h9");
+        assert_eq!(user.username, "jdoe@example.com");
+    }
+
+    #[test]
+    fn id_tokens_of_another_sign_in_are_refused() {
+        // The fixture was issued without a nonce
+        match sign_in_with_id_token(Some("n-0S6_WzA2Mj")) {
+            Err(Error::JwtValidation(_)) => (),
+            Err(e) => panic!("Expected JwtValidation, got {:?}", e),
+            Ok(user) => panic!("Expected JwtValidation, got user {}", user.username),
+        }
+    }
+
+    #[test]
+    fn revocation_is_refused_with_the_providers_answer() {
+        // Answers the first request with a 200 and the next with a 401
+        let (tx, rx) = mpsc::channel();
+        let provider = test_support::serve(move |request, n| {
+            let _ = tx.send((request.method.clone(),
+                             request.path().to_string(),
+                             request.header("authorization").is_some(),
+                             request.body.clone()));
+            if n == 0 {
+                Reply::new(200, "")
+            } else {
+                Reply::new(401, r#"{"error":"invalid_client"}"#)
+            }
+        });
+        let url = format!("{}/v1/revoke", provider.url);
+
+        let config = OAuth2Cfg { provider: "okta".to_string(),
+                                 token_url: url.clone(),
+                                 revoke_url: url,
+                                 client_id: "0oa1builder".to_string(),
+                                 client_secret: "s3cr&t".to_string(),
+                                 client_auth_method: Some(ClientAuthMethod::Body),
+                                 ..Default::default() };
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let client = OAuth2Client::new(config, &http).unwrap();
+
+        client.revoke("at+1/2=").unwrap();
+        let (method, path, authorization, body) = rx.recv().unwrap();
+        assert_eq!(method, "POST");
+        assert_eq!(path, "/v1/revoke");
+        assert!(!authorization);
+        assert_eq!(body,
+                   "client_id=0oa1builder&client_secret=s3cr%26t&token=at%2B1%2F2%3D");
+
+        match client.revoke("token") {
+            Err(Error::HttpResponse(status, _)) => assert_eq!(status.as_u16(), 401),
+            Err(e) => panic!("Expected HttpResponse, got {:?}", e),
+            Ok(()) => panic!("Expected HttpResponse, got Ok"),
+        }
+        assert_eq!(provider.requests(), 2);
+    }
+
+    #[test]
+    fn tokens_are_left_to_expire_without_a_revoke_url() {
+        let config = OAuth2Cfg { provider: "okta".to_string(),
+                                 token_url: "http://127.0.0.1:1/v1/token".to_string(),
+                                 ..Default::default() };
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let client = OAuth2Client::new(config, &http).unwrap();
+        client.revoke("token").unwrap();
+    }
+
+    #[test]
+    fn signed_in_tokens_are_validated_from_the_userinfo_cache() {
+        let jdoe = json!({ "sub": "00u1a2b3c4d5e6f7g8h9", "preferred_username": "jdoe" });
+        // The provider's requests once a user signs in and their token is validated twice
+        let requests = |userinfo_cache_secs: u64| {
+            let provider = test_support::provider(vec![("/v1/token",
+                                                        json!({ "access_token": "at" })),
+                                                       ("/v1/userinfo", jdoe.clone())]);
+            let config = OAuth2Cfg { provider: "okta".to_string(),
+                                     token_url: format!("{}/v1/token", provider.url),
+                                     userinfo_url: format!("{}/v1/userinfo", provider.url),
+                                     userinfo_cache_secs,
+                                     ..Default::default() };
+            let http =
+                HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+            let client = OAuth2Client::new(config, &http).unwrap();
+            let (token, _) = client.authenticate("code", None, None).unwrap();
+            assert_eq!(client.user(&token.access_token).unwrap().username, "jdoe");
+            assert_eq!(client.user(&token.access_token).unwrap().username, "jdoe");
+            provider.requests()
+        };
+
+        assert_eq!(requests(60), 2);
+        assert_eq!(requests(0), 4);
+    }
 }
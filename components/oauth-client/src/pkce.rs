@@ -0,0 +1,126 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Proof Key for Code Exchange (RFC 7636), for public clients that cannot keep a client secret.
+//! The challenge goes on the authorize URL and the verifier on the token request, so that a
+//! stolen authorization code is useless without the verifier.
+
+use base64;
+
+use crate::error::{Error,
+                   Result};
+use rand::{distributions::Alphanumeric,
+           thread_rng,
+           Rng};
+use sha2::{Digest,
+           Sha256};
+
+pub const CHALLENGE_METHOD: &str = "S256";
+
+// RFC 7636 allows 43 to 128 characters
+const VERIFIER_LEN: usize = 64;
+const MIN_VERIFIER_LEN: usize = 43;
+const MAX_VERIFIER_LEN: usize = 128;
+
+pub struct Pkce {
+    pub verifier:  String,
+    pub challenge: String,
+}
+
+impl Pkce {
+    pub fn new() -> Self {
+        let verifier = thread_rng().sample_iter(&Alphanumeric)
+                                   .take(VERIFIER_LEN)
+                                   .collect::<String>();
+        let challenge = challenge(&verifier);
+        Pkce { verifier,
+               challenge }
+    }
+
+    /// The params to add to the authorize URL
+    pub fn authorize_params(&self) -> String {
+        format!("code_challenge={}&code_challenge_method={}",
+                self.challenge, CHALLENGE_METHOD)
+    }
+}
+
+impl Default for Pkce {
+    fn default() -> Self { Self::new() }
+}
+
+/// The S256 challenge of a verifier
+pub fn challenge(verifier: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(verifier);
+    base64::encode_config(&hasher.result(), base64::URL_SAFE_NO_PAD)
+}
+
+/// Refuses a verifier that is not the 43 to 128 unreserved characters of RFC 7636 section 4.1,
+/// before it is sent to the provider
+pub fn check_verifier(verifier: &str) -> Result<()> {
+    if verifier.len() < MIN_VERIFIER_LEN || verifier.len() > MAX_VERIFIER_LEN {
+        let msg = format!("it is {} characters long, not {} to {}",
+                          verifier.len(),
+                          MIN_VERIFIER_LEN,
+                          MAX_VERIFIER_LEN);
+        return Err(Error::InvalidVerifier(msg));
+    }
+    let unreserved = |c: char| c.is_ascii_alphanumeric() || "-._~".contains(c);
+    if !verifier.chars().all(unreserved) {
+        let msg = "only letters, digits and -._~ are allowed".to_string();
+        return Err(Error::InvalidVerifier(msg));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn challenge_is_the_rfc_7636_s256() {
+        // Appendix B of RFC 7636
+        assert_eq!(challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+                   "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+    }
+
+    #[test]
+    fn generated_verifiers_match_their_challenge() {
+        let pkce = Pkce::new();
+        assert_eq!(pkce.verifier.len(), VERIFIER_LEN);
+        assert_eq!(pkce.challenge, challenge(&pkce.verifier));
+        assert_ne!(pkce.verifier, Pkce::new().verifier);
+        assert!(pkce.authorize_params()
+                    .ends_with("&code_challenge_method=S256"));
+        assert!(pkce.authorize_params().contains(&pkce.challenge));
+    }
+
+    #[test]
+    fn verifiers_are_the_rfc_7636_characters() {
+        assert!(check_verifier(&Pkce::new().verifier).is_ok());
+        assert!(check_verifier("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk").is_ok());
+        assert!(check_verifier(&"a.b~".repeat(32)).is_ok());
+        for verifier in &["tooshort".to_string(),
+                          "a".repeat(129),
+                          format!("{}&scope=admin", "a".repeat(43)),
+                          format!("{}+", "a".repeat(43)),
+                          format!("{}é", "a".repeat(43))]
+        {
+            match check_verifier(verifier) {
+                Err(Error::InvalidVerifier(_)) => (),
+                other => panic!("Expected InvalidVerifier for {}, got {:?}", verifier, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,371 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Retries of the requests made to a provider. A provider briefly answering 502 or 503, or
+//! dropping a connection, shouldn't fail a login, so those requests are sent again after an
+//! exponential backoff. A 4xx is the provider's answer and is never retried, nor is an error
+//! that would recur on every attempt, such as a redirect loop. The exception is a 429 of a
+//! provider limiting its rate, which is sent again once after the `Retry-After` it asks for.
+//! Token requests may only reach the provider once, a code or refresh token is spent by the
+//! first, so they are only sent again when no connection could be made.
+//! However many retries are left, none is started once `max_elapsed_ms` would be spent.
+
+use std::{thread,
+          time::{Duration,
+                 Instant}};
+
+use chrono::{DateTime,
+             Utc};
+use reqwest::{header::{HeaderMap,
+                       RETRY_AFTER},
+              RequestBuilder,
+              Response,
+              StatusCode};
+
+use crate::{config::OAuth2Cfg,
+            error::{Error,
+                    Result}};
+
+// Caps the backoff at 2^10 times the base delay whatever the configured retries
+const MAX_BACKOFF_SHIFT: u32 = 10;
+
+/// Sends the request `request` builds, building and sending it again while it fails with a
+/// connection error, a timeout or a 5xx, up to the configured `max_retries`. The response of the
+/// last attempt is returned whatever its status, but for a 429 which is sent again once.
+pub fn send<F>(config: &OAuth2Cfg, request: F) -> Result<Response>
+    where F: Fn() -> RequestBuilder
+{
+    send_while(config, request, true)
+}
+
+/// Sends the token request `request` builds, building and sending it again only while no
+/// connection to the provider could be made. A timeout or a 5xx may come after the provider
+/// spent the code or refresh token, so its answer is returned as it is.
+pub fn send_token_request<F>(config: &OAuth2Cfg, request: F) -> Result<Response>
+    where F: Fn() -> RequestBuilder
+{
+    send_while(config, request, false)
+}
+
+// Sends the request, again for a connection error or, if it can be repeated, a timeout or a 5xx
+fn send_while<F>(config: &OAuth2Cfg, request: F, repeatable: bool) -> Result<Response>
+    where F: Fn() -> RequestBuilder
+{
+    let started = Instant::now();
+    let mut attempt = 0;
+    loop {
+        let delay = backoff(config.retry_base_ms, attempt);
+        let retries_left = attempt < config.max_retries && in_time(config, started, delay);
+        let retryable = match request().send() {
+            Ok(ref resp) if repeatable && retries_left && resp.status().is_server_error() => {
+                format!("status={}", resp.status())
+            }
+            Err(ref err) if repeatable && retries_left && transient(err) => format!("{}", err),
+            Err(ref err) if retries_left && unconnected(err) => format!("{}", err),
+            Ok(resp) => return Ok(rate_limited(config, &request, resp, started)),
+            Err(err) => return Err(Error::http_client(err)),
+        };
+
+        warn!("OAuth provider request failed, retrying in {}ms, attempt={} of {}, {}",
+              delay.as_millis(),
+              attempt + 1,
+              config.max_retries,
+              retryable);
+        thread::sleep(delay);
+        attempt += 1;
+    }
+}
+
+// Sends the request of a 429 answer once more after the delay the provider asked for. The 429
+// is returned unless that request succeeds, or when the delay would end past `max_elapsed_ms`.
+fn rate_limited<F>(config: &OAuth2Cfg, request: &F, resp: Response, started: Instant) -> Response
+    where F: Fn() -> RequestBuilder
+{
+    if resp.status() != StatusCode::TOO_MANY_REQUESTS {
+        return resp;
+    }
+
+    let delay = rate_limit_delay(config, resp.headers(), Utc::now());
+    if !in_time(config, started, delay) {
+        warn!("OAuth provider is limiting requests, not retrying after {}ms",
+              started.elapsed().as_millis());
+        return resp;
+    }
+    warn!("OAuth provider is limiting requests, retrying in {}ms",
+          delay.as_millis());
+    thread::sleep(delay);
+
+    match request().send() {
+        Ok(retried) if retried.status().is_success() => retried,
+        Ok(retried) => {
+            warn!("OAuth provider request failed after its rate limit, status={}",
+                  retried.status());
+            resp
+        }
+        Err(err) => {
+            warn!("OAuth provider request failed after its rate limit, {}",
+                  err);
+            resp
+        }
+    }
+}
+
+// The `Retry-After` of a 429, seconds or an HTTP date, capped at `max_retry_after_secs`. The
+// first retry's delay stands in for a missing or unreadable one.
+fn rate_limit_delay(config: &OAuth2Cfg, headers: &HeaderMap, now: DateTime<Utc>) -> Duration {
+    let retry_after = headers.get(RETRY_AFTER)
+                             .and_then(|value| value.to_str().ok())
+                             .and_then(|value| retry_after(value.trim(), now))
+                             .unwrap_or_else(|| backoff(config.retry_base_ms, 0));
+    retry_after.min(Duration::from_secs(config.max_retry_after_secs))
+}
+
+fn retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    // A date already past asks for no delay
+    Some((date.with_timezone(&Utc) - now).to_std()
+                                         .unwrap_or_default())
+}
+
+// Connection failures and timeouts. A request that cannot be built, or that redirects too
+// often, fails the same way when sent again. Only requests that can be repeated are sent again
+// for these, a code exchange that timed out goes through `send_token_request` and is not.
+fn transient(err: &reqwest::Error) -> bool {
+    !(err.is_http() || err.is_redirect() || err.is_serialization())
+}
+
+// No connection to the provider could be made, so it never saw the request
+fn unconnected(err: &reqwest::Error) -> bool {
+    err.get_ref()
+       .and_then(|err| err.downcast_ref::<hyper::Error>())
+       .map_or(false, hyper::Error::is_connect)
+}
+
+// Whether a retry after `delay` would start before `max_elapsed_ms` is spent
+fn in_time(config: &OAuth2Cfg, started: Instant, delay: Duration) -> bool {
+    started.elapsed() + delay < Duration::from_millis(config.max_elapsed_ms)
+}
+
+fn backoff(base_ms: u64, attempt: u32) -> Duration {
+    Duration::from_millis(base_ms.saturating_mul(1 << attempt.min(MAX_BACKOFF_SHIFT)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self,
+                              Provider,
+                              Reply};
+    use builder_core::http_client::HttpClientFactory;
+
+    // Answers each request with the next of `statuses`, the last one once they run out. 429s
+    // ask for no delay.
+    fn provider(statuses: Vec<u16>) -> Provider {
+        test_support::serve(move |_, n| {
+            let status = statuses[n.min(statuses.len() - 1)];
+            let reply = Reply::new(status, &format!("{{\"status\": {}}}", status));
+            if status == 429 {
+                reply.header("Retry-After", "0")
+            } else {
+                reply
+            }
+        })
+    }
+
+    fn userinfo(provider: &Provider) -> String { format!("{}/userinfo", provider.url) }
+
+    fn get(config: &OAuth2Cfg, url: &str) -> Result<Response> {
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let client = http.client(url, Default::default()).unwrap();
+        send(config, || client.get(url))
+    }
+
+    fn config(max_retries: u32) -> OAuth2Cfg {
+        OAuth2Cfg { max_retries,
+                    retry_base_ms: 1,
+                    ..Default::default() }
+    }
+
+    #[test]
+    fn a_briefly_unavailable_provider_is_retried() {
+        let provider = provider(vec![502, 503, 200]);
+        let resp = get(&config(2), &userinfo(&provider)).unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(provider.requests(), 3);
+    }
+
+    #[test]
+    fn the_last_server_error_is_returned_once_retries_run_out() {
+        let provider = provider(vec![503]);
+        let resp = get(&config(2), &userinfo(&provider)).unwrap();
+        assert_eq!(resp.status().as_u16(), 503);
+        assert_eq!(provider.requests(), 3);
+    }
+
+    #[test]
+    fn retries_stop_once_the_elapsed_time_is_spent() {
+        let provider = provider(vec![503]);
+        let config = OAuth2Cfg { max_retries: 10,
+                                 retry_base_ms: 50,
+                                 max_elapsed_ms: 120,
+                                 ..Default::default() };
+        let resp = get(&config, &userinfo(&provider)).unwrap();
+        assert_eq!(resp.status().as_u16(), 503);
+        // The second retry would start 150ms in
+        assert_eq!(provider.requests(), 2);
+    }
+
+    #[test]
+    fn rate_limits_past_the_elapsed_time_are_not_waited_on() {
+        let limited = test_support::serve(|_, n| {
+            let status = if n == 0 { 429 } else { 200 };
+            Reply::new(status, "{}").header("Retry-After", "1")
+        });
+        let config = OAuth2Cfg { max_elapsed_ms: 500,
+                                 ..config(0) };
+        let resp = get(&config, &userinfo(&limited)).unwrap();
+        assert_eq!(resp.status().as_u16(), 429);
+        assert_eq!(limited.requests(), 1);
+    }
+
+    #[test]
+    fn client_errors_are_never_retried() {
+        let provider = provider(vec![401, 200]);
+        let resp = get(&config(2), &userinfo(&provider)).unwrap();
+        assert_eq!(resp.status().as_u16(), 401);
+        assert_eq!(provider.requests(), 1);
+    }
+
+    #[test]
+    fn connection_errors_fail_once_retries_run_out() {
+        let url = format!("{}/userinfo", test_support::unbound());
+        match get(&config(1), &url) {
+            Err(Error::HttpClient(_)) => (),
+            Err(e) => panic!("Expected HttpClient, got {:?}", e),
+            Ok(resp) => panic!("Expected HttpClient, got status {}", resp.status()),
+        }
+    }
+
+    #[test]
+    fn token_requests_are_not_sent_again_once_delivered() {
+        let provider = provider(vec![503, 200]);
+        let url = format!("{}/token", provider.url);
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let client = http.client(&url, Default::default()).unwrap();
+        let resp = send_token_request(&config(2), || client.post(&url)).unwrap();
+        assert_eq!(resp.status().as_u16(), 503);
+        assert_eq!(provider.requests(), 1);
+    }
+
+    #[test]
+    fn token_requests_that_time_out_are_not_sent_again() {
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let http = http.with_timeout_ms(50).unwrap();
+        let post = |provider: &Provider, token_request: bool| {
+            let url = format!("{}/token", provider.url);
+            let client = http.client(&url, Default::default()).unwrap();
+            let sent = if token_request {
+                send_token_request(&config(2), || client.post(&url))
+            } else {
+                send(&config(2), || client.post(&url))
+            };
+            assert!(sent.is_err());
+            // The provider counts a connection once it takes it
+            thread::sleep(Duration::from_millis(100));
+            provider.requests()
+        };
+
+        assert_eq!(post(&test_support::hung(), true), 1);
+        assert_eq!(post(&test_support::hung(), false), 3);
+    }
+
+    #[test]
+    fn refused_connections_never_reached_the_provider() {
+        let url = format!("{}/token", test_support::unbound());
+        let http = HttpClientFactory::new("builder-test", "1.0.0", &Default::default()).unwrap();
+        let client = http.client(&url, Default::default()).unwrap();
+        let err = client.post(&url).send().unwrap_err();
+        assert!(unconnected(&err), "{:?}", err);
+    }
+
+    #[test]
+    fn redirect_loops_are_never_retried() {
+        // Redirects lead back to the provider itself
+        let redirecting =
+            || test_support::serve(|_, _| Reply::new(302, "").header("Location", "/userinfo"));
+
+        let provider = redirecting();
+        let once = match get(&config(0), &userinfo(&provider)) {
+            Err(Error::HttpClient(ref e)) if e.is_redirect() => provider.requests(),
+            Err(e) => panic!("Expected a redirect error, got {:?}", e),
+            Ok(resp) => panic!("Expected a redirect error, got status {}", resp.status()),
+        };
+
+        let provider = redirecting();
+        assert!(get(&config(2), &userinfo(&provider)).is_err());
+        assert_eq!(provider.requests(), once);
+    }
+
+    #[test]
+    fn rate_limited_requests_are_sent_again_once() {
+        let limited = provider(vec![429, 200]);
+        let resp = get(&config(0), &userinfo(&limited)).unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(limited.requests(), 2);
+
+        // The 429 is kept when the request fails again
+        let still_limited = provider(vec![429, 429, 200]);
+        let resp = get(&config(2), &userinfo(&still_limited)).unwrap();
+        assert_eq!(resp.status().as_u16(), 429);
+        assert_eq!(still_limited.requests(), 2);
+
+        let failing = provider(vec![429, 503]);
+        let resp = get(&config(0), &userinfo(&failing)).unwrap();
+        assert_eq!(resp.status().as_u16(), 429);
+    }
+
+    #[test]
+    fn retry_after_is_read_in_seconds_or_as_a_date_and_capped() {
+        let now = DateTime::parse_from_rfc3339("2019-10-21T07:28:00Z").unwrap()
+                                                                      .with_timezone(&Utc);
+        let delay = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, value.parse().unwrap());
+            let config = OAuth2Cfg { retry_base_ms: 100,
+                                     max_retry_after_secs: 30,
+                                     ..Default::default() };
+            rate_limit_delay(&config, &headers, now)
+        };
+
+        assert_eq!(delay("12"), Duration::from_secs(12));
+        assert_eq!(delay("Mon, 21 Oct 2019 07:28:20 GMT"),
+                   Duration::from_secs(20));
+        assert_eq!(delay("Mon, 21 Oct 2019 07:27:00 GMT"),
+                   Duration::from_secs(0));
+        assert_eq!(delay("3600"), Duration::from_secs(30));
+        assert_eq!(delay("soon"), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_a_cap() {
+        assert_eq!(backoff(100, 0), Duration::from_millis(100));
+        assert_eq!(backoff(100, 3), Duration::from_millis(800));
+        assert_eq!(backoff(100, 40), Duration::from_millis(102_400));
+        assert_eq!(backoff(u64::max_value(), 2),
+                   Duration::from_millis(u64::max_value()));
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A fake provider the tests talk to over a local port
+
+use serde_json::Value;
+use std::{io::{Read,
+               Write},
+          net::{TcpListener,
+                TcpStream},
+          sync::{atomic::{AtomicUsize,
+                          Ordering},
+                 Arc},
+          thread,
+          time::Duration};
+
+/// A request as the provider read it
+#[derive(Clone)]
+pub struct Request {
+    pub method:  String,
+    /// The path with its query
+    pub target:  String,
+    pub headers: Vec<(String, String)>,
+    pub body:    String,
+}
+
+impl Request {
+    /// The path without its query
+    pub fn path(&self) -> &str { self.target.split('?').next().unwrap_or("") }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A response of the provider
+#[derive(Clone)]
+pub struct Reply {
+    pub status:  u16,
+    pub headers: Vec<(String, String)>,
+    pub body:    String,
+}
+
+impl Reply {
+    pub fn new(status: u16, body: &str) -> Reply {
+        Reply { status,
+                headers: vec![],
+                body: body.to_string() }
+    }
+
+    pub fn json(status: u16, body: &Value) -> Reply {
+        Reply::new(status, &body.to_string()).header("Content-Type", "application/json")
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Reply {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// A provider listening on a local port
+pub struct Provider {
+    pub url:  String,
+    requests: Arc<AtomicUsize>,
+}
+
+impl Provider {
+    /// The number of requests answered so far
+    pub fn requests(&self) -> usize { self.requests.load(Ordering::SeqCst) }
+}
+
+/// Answers each request with what `answer` makes of it and of the number of requests before it
+pub fn serve<F>(mut answer: F) -> Provider
+    where F: FnMut(&Request, usize) -> Reply + Send + 'static
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counted = requests.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let request = match read_request(&mut stream) {
+                Some(request) => request,
+                None => continue,
+            };
+            let reply = answer(&request, counted.load(Ordering::SeqCst));
+            counted.fetch_add(1, Ordering::SeqCst);
+            let mut head = format!("HTTP/1.1 {} Status\r\n", reply.status);
+            for (name, value) in &reply.headers {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            let _ = write!(stream,
+                           "{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                           head,
+                           reply.body.len(),
+                           reply.body);
+        }
+    });
+    Provider { url, requests }
+}
+
+/// Answers each request with the JSON `routes` has for its path, a 404 for the others
+pub fn provider(routes: Vec<(&'static str, Value)>) -> Provider {
+    routed(routes.into_iter()
+                 .map(|(path, body)| (path, Reply::json(200, &body)))
+                 .collect())
+}
+
+/// Answers each request with the reply `routes` has for its target, or else for its path, a 404
+/// for the others. `{url}` in the headers of a reply is the provider's URL.
+pub fn routed(routes: Vec<(&'static str, Reply)>) -> Provider {
+    serve(move |request, _| {
+        let exact = routes.iter().find(|(target, _)| *target == request.target);
+        let route = exact.or_else(|| routes.iter().find(|(path, _)| *path == request.path()));
+        let mut reply = match route {
+            Some((_, reply)) => reply.clone(),
+            None => return Reply::new(404, ""),
+        };
+        let url = format!("http://{}", request.header("Host").unwrap_or(""));
+        for (_, value) in reply.headers.iter_mut() {
+            *value = value.replace("{url}", &url);
+        }
+        reply
+    })
+}
+
+/// A provider that takes connections and never answers them, counting each as a request
+pub fn hung() -> Provider {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counted = requests.clone();
+    thread::spawn(move || {
+        // Held so that the connections stay open
+        let mut open = Vec::new();
+        for stream in listener.incoming() {
+            counted.fetch_add(1, Ordering::SeqCst);
+            open.push(stream);
+        }
+    });
+    Provider { url, requests }
+}
+
+/// A provider that takes no connections, its listen backlog filled so that connecting to it
+/// hangs until the connect timeout
+pub fn backlogged() -> Provider {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    // Connections complete until the backlog is full, the first one that hangs after it fills it
+    let mut held = Vec::new();
+    while let Ok(stream) = TcpStream::connect_timeout(&addr, Duration::from_millis(100)) {
+        held.push(stream);
+    }
+    thread::spawn(move || {
+        let _held = (listener, held);
+        loop {
+            thread::park();
+        }
+    });
+    Provider { url:      format!("http://{}", addr),
+               requests: Arc::new(AtomicUsize::new(0)), }
+}
+
+/// The URL of a port nothing listens on, one that was bound and released
+pub fn unbound() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+// Reads the request line and headers, then as much body as the Content-Length says
+fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    let head_len = loop {
+        if let Some(at) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break at + 4;
+        }
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+        }
+    };
+
+    let head = String::from_utf8_lossy(&data[..head_len]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or("").split_whitespace();
+    let method = request_line.next().unwrap_or("").to_string();
+    let target = request_line.next().unwrap_or("").to_string();
+    let headers: Vec<(String, String)> = lines.filter_map(header).collect();
+
+    let content_length = headers.iter()
+                                .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                                .and_then(|(_, value)| value.parse::<usize>().ok())
+                                .unwrap_or(0);
+    while data.len() < head_len + content_length {
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+        }
+    }
+    let end = data.len().min(head_len + content_length);
+    let body = String::from_utf8_lossy(&data[head_len..end]).to_string();
+    Some(Request { method,
+                   target,
+                   headers,
+                   body })
+}
+
+fn header(line: &str) -> Option<(String, String)> {
+    let mut parts = line.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(value)) => Some((name.trim().to_string(), value.trim().to_string())),
+        _ => None,
+    }
+}
@@ -0,0 +1,305 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The refresh token grant of RFC 6749 section 6, and the token revocation of RFC 7009. They
+//! are the same requests for every provider that supports them, but for how the client
+//! authenticates.
+
+use std::iter::FromIterator;
+
+use base64;
+use serde_json;
+
+use reqwest::{header::{HeaderMap,
+                       AUTHORIZATION},
+              RequestBuilder};
+use url::form_urlencoded;
+
+use builder_core::http_client::{HttpClient,
+                                ACCEPT_APPLICATION_JSON,
+                                CONTENT_TYPE_FORM_URL_ENCODED};
+
+use crate::{config::{ClientAuthMethod,
+                     OAuth2Cfg},
+            error::{Error,
+                    Result},
+            retry,
+            types::OAuth2Token};
+
+/// How the client authenticates to the token endpoint
+pub enum ClientAuth {
+    /// The client id and secret as form fields
+    Form,
+    /// The client id and secret as HTTP Basic auth
+    Basic,
+}
+
+impl ClientAuth {
+    /// The configured `client_auth_method`, the provider's own `default` when it is unset
+    pub fn configured(config: &OAuth2Cfg, default: ClientAuthMethod) -> Self {
+        ClientAuth::from(config.client_auth_method.unwrap_or(default))
+    }
+}
+
+impl From<ClientAuthMethod> for ClientAuth {
+    fn from(method: ClientAuthMethod) -> Self {
+        match method {
+            ClientAuthMethod::Body => ClientAuth::Form,
+            ClientAuthMethod::Basic => ClientAuth::Basic,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RefreshOk {
+    pub access_token:  String,
+    pub refresh_token: Option<String>,
+    pub expires_in:    Option<u64>,
+}
+
+/// Form encodes `pairs`, as token endpoints take their parameters
+pub fn form(pairs: &[(&str, &str)]) -> String {
+    form_urlencoded::Serializer::new(String::new()).extend_pairs(pairs)
+                                                   .finish()
+}
+
+/// The body of the authorization code grant of RFC 6749 section 4.1.3, with the client
+/// credentials when they are sent as form fields rather than with Basic auth. Only scopes an
+/// operator configured are sent, the exchange is granted those of the authorize request otherwise,
+/// and the PKCE verifier is added when there is one.
+pub fn authorization_code(config: &OAuth2Cfg,
+                          code: &str,
+                          verifier: Option<&str>,
+                          auth: &ClientAuth)
+                          -> String {
+    let mut pairs = vec![];
+    if let ClientAuth::Form = auth {
+        pairs.push(("client_id", &config.client_id[..]));
+        pairs.push(("client_secret", &config.client_secret[..]));
+    }
+    pairs.push(("grant_type", "authorization_code"));
+    pairs.push(("code", code));
+    pairs.push(("redirect_uri", &config.redirect_url[..]));
+
+    let scopes = config.scopes.as_ref().map(|scopes| scopes.join(" "));
+    if let Some(ref scopes) = scopes {
+        pairs.push(("scope", scopes));
+    }
+    if let Some(verifier) = verifier {
+        pairs.push(("code_verifier", verifier));
+    }
+    form(&pairs)
+}
+
+/// Scopes as the value of a form encoded `scope` parameter, each one encoded so that a scope
+/// holding a reserved character, e.g. a resource URI, stays one scope
+pub fn scope_param(scopes: &[String]) -> String {
+    scopes.iter()
+          .map(|scope| form_urlencoded::byte_serialize(scope.as_bytes()).collect::<String>())
+          .collect::<Vec<_>>()
+          .join("+")
+}
+
+/// Adds the client credentials to a token endpoint request the way the provider expects them
+pub fn authenticated(config: &OAuth2Cfg, req: RequestBuilder, auth: &ClientAuth) -> RequestBuilder {
+    match auth {
+        ClientAuth::Basic => req.header(AUTHORIZATION, basic_auth(config)),
+        ClientAuth::Form => req,
+    }
+}
+
+// The Authorization header of the client credentials. RFC 6749 section 2.3.1 has the id and
+// secret form encoded before they are joined, so a secret with a colon in it is still read
+// whole.
+fn basic_auth(config: &OAuth2Cfg) -> String {
+    let encode = |s: &str| form_urlencoded::byte_serialize(s.as_bytes()).collect::<String>();
+    let credentials = format!("{}:{}",
+                              encode(&config.client_id),
+                              encode(&config.client_secret));
+    format!("Basic {}", base64::encode(&credentials))
+}
+
+/// The body of the refresh token grant of RFC 6749 section 6, with the client credentials when
+/// they are sent as form fields
+fn refresh_token_grant(config: &OAuth2Cfg, refresh_token: &str, auth: &ClientAuth) -> String {
+    let mut pairs = vec![("client_id", &config.client_id[..])];
+    if let ClientAuth::Form = auth {
+        pairs.push(("client_secret", &config.client_secret[..]));
+    }
+    pairs.push(("grant_type", "refresh_token"));
+    pairs.push(("refresh_token", refresh_token));
+    form(&pairs)
+}
+
+/// The body of the revocation request of RFC 7009 section 2.1, with the client credentials
+/// when they are sent as form fields
+fn revocation(config: &OAuth2Cfg, token: &str, auth: &ClientAuth) -> String {
+    let mut pairs = vec![("client_id", &config.client_id[..])];
+    if let ClientAuth::Form = auth {
+        pairs.push(("client_secret", &config.client_secret[..]));
+    }
+    pairs.push(("token", token));
+    form(&pairs)
+}
+
+/// Exchanges a refresh token at `token_url` for a new access token, with the refresh token to use
+/// next time when the provider rotates them
+pub fn refresh(config: &OAuth2Cfg,
+               client: &HttpClient,
+               token_url: &str,
+               refresh_token: &str,
+               auth: ClientAuth)
+               -> Result<OAuth2Token> {
+    let body = refresh_token_grant(config, refresh_token, &auth);
+
+    let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),
+                             CONTENT_TYPE_FORM_URL_ENCODED.clone()];
+    let headers = HeaderMap::from_iter(header_values.into_iter());
+
+    let mut resp = retry::send_token_request(config, || {
+        let req = client.post(token_url)
+                        .headers(headers.clone())
+                        .body(body.clone());
+        authenticated(config, req, &auth)
+    })?;
+
+    let body = resp.text().map_err(Error::http_client)?;
+    debug!("Refresh response body: {}", body);
+
+    if resp.status().is_success() {
+        match serde_json::from_str::<RefreshOk>(&body) {
+            Ok(msg) => {
+                Ok(OAuth2Token { access_token:  msg.access_token,
+                                 refresh_token: msg.refresh_token,
+                                 expires_in:    msg.expires_in, })
+            }
+            Err(e) => Err(Error::Serialization(e)),
+        }
+    } else {
+        Err(Error::token_request(resp.status(), body))
+    }
+}
+
+/// Revokes `token` at the configured `revoke_url`, leaving it to expire when none is set.
+/// Providers answer 200 whether or not the token was valid, so any other status is an error.
+pub fn revoke(config: &OAuth2Cfg,
+              client: &HttpClient,
+              token: &str,
+              auth: ClientAuth)
+              -> Result<()> {
+    if config.revoke_url.is_empty() {
+        debug!("No revoke_url for {}, leaving the token to expire",
+               config.provider);
+        return Ok(());
+    }
+
+    let body = revocation(config, token, &auth);
+
+    let header_values = vec![ACCEPT_APPLICATION_JSON.clone(),
+                             CONTENT_TYPE_FORM_URL_ENCODED.clone()];
+    let headers = HeaderMap::from_iter(header_values.into_iter());
+
+    let mut resp = retry::send_token_request(config, || {
+        let req = client.post(&config.revoke_url)
+                        .headers(headers.clone())
+                        .body(body.clone());
+        authenticated(config, req, &auth)
+    })?;
+
+    if resp.status().is_success() {
+        Ok(())
+    } else {
+        let body = resp.text().map_err(Error::http_client)?;
+        debug!("Revoke response body: {}", body);
+        Err(Error::HttpResponse(resp.status(), body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(scopes: Option<Vec<&str>>) -> OAuth2Cfg {
+        OAuth2Cfg { client_id: "builder".to_string(),
+                    client_secret: "s3cr&t=1".to_string(),
+                    redirect_url: "https://bldr.example.com/?a=1".to_string(),
+                    scopes: scopes.map(|s| s.into_iter().map(str::to_string).collect()),
+                    ..Default::default() }
+    }
+
+    #[test]
+    fn code_exchanges_are_form_encoded() {
+        assert_eq!(authorization_code(&config(None), "a+b/c", None, &ClientAuth::Form),
+                   "client_id=builder&client_secret=s3cr%26t%3D1&grant_type=authorization_code&\
+                    code=a%2Bb%2Fc&redirect_uri=https%3A%2F%2Fbldr.example.com%2F%3Fa%3D1");
+    }
+
+    #[test]
+    fn basic_auth_leaves_the_secret_out_of_the_body() {
+        let body = authorization_code(&config(None), "abc", None, &ClientAuth::Basic);
+        assert!(body.starts_with("grant_type=authorization_code&"),
+                "{}",
+                body);
+        assert!(!body.contains("client_"), "{}", body);
+    }
+
+    #[test]
+    fn configured_scopes_and_the_verifier_are_added() {
+        let body = authorization_code(&config(Some(vec!["openid", "email"])),
+                                      "abc",
+                                      Some("xyz"),
+                                      &ClientAuth::Basic);
+        assert!(body.ends_with("&scope=openid+email&code_verifier=xyz"),
+                "{}",
+                body);
+    }
+
+    #[test]
+    fn each_scope_is_form_encoded() {
+        let scopes = vec!["openid".to_string(),
+                          "a+b".to_string(),
+                          "https://graph.microsoft.com/User.Read".to_string()];
+        assert_eq!(scope_param(&scopes),
+                   "openid+a%2Bb+https%3A%2F%2Fgraph.microsoft.com%2FUser.Read");
+    }
+
+    #[test]
+    fn refresh_grants_are_form_encoded() {
+        assert_eq!(refresh_token_grant(&config(None), "1//0g+rt/x=", &ClientAuth::Form),
+                   "client_id=builder&client_secret=s3cr%26t%3D1&grant_type=refresh_token&\
+                    refresh_token=1%2F%2F0g%2Brt%2Fx%3D");
+        assert_eq!(refresh_token_grant(&config(None), "rt", &ClientAuth::Basic),
+                   "client_id=builder&grant_type=refresh_token&refresh_token=rt");
+    }
+
+    #[test]
+    fn revocations_are_form_encoded() {
+        assert_eq!(revocation(&config(None), "1//0g+rt/x=", &ClientAuth::Form),
+                   "client_id=builder&client_secret=s3cr%26t%3D1&token=1%2F%2F0g%2Brt%2Fx%3D");
+        assert_eq!(revocation(&config(None), "at", &ClientAuth::Basic),
+                   "client_id=builder&token=at");
+    }
+
+    #[test]
+    fn basic_auth_form_encodes_the_client_credentials() {
+        // base64 of builder:s3cr%26t%3D1
+        assert_eq!(basic_auth(&config(None)),
+                   "Basic YnVpbGRlcjpzM2NyJTI2dCUzRDE=");
+        let config = OAuth2Cfg { client_id: "app-client".to_string(),
+                                 client_secret: "s3cret".to_string(),
+                                 ..Default::default() };
+        // base64 of app-client:s3cret
+        assert_eq!(basic_auth(&config), "Basic YXBwLWNsaWVudDpzM2NyZXQ=");
+    }
+}
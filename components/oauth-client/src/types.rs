@@ -13,20 +13,64 @@
 // limitations under the License.
 
 use crate::{config::OAuth2Cfg,
-            error::Result};
+            error::{Error,
+                    Result}};
 
 use builder_core::http_client::HttpClient;
 
+#[derive(Clone)]
 pub struct OAuth2User {
-    pub id:       String,
-    pub username: String,
-    pub email:    Option<String>,
+    pub id:             String,
+    pub username:       String,
+    pub email:          Option<String>,
+    /// Whether the provider verified the email, for providers that say
+    pub email_verified: Option<bool>,
+    /// The groups the provider says the user is in, for providers configured to read them
+    pub groups:         Option<Vec<String>>,
+}
+
+/// The token granted for an authorization code. Providers that issue short lived tokens send
+/// a refresh token with them.
+pub struct OAuth2Token {
+    pub access_token:  String,
+    pub refresh_token: Option<String>,
+    /// Seconds the access token is valid for, when the provider says
+    pub expires_in:    Option<u64>,
 }
 
 pub trait OAuth2Provider: Sync + Send {
+    /// Exchanges the authorization code for a token and reads the user it belongs to. The PKCE
+    /// verifier is sent along when the code was requested with a challenge, and an ID token must
+    /// carry the nonce the code was requested with.
     fn authenticate(&self,
                     config: &OAuth2Cfg,
                     client: &HttpClient,
-                    code: &str)
-                    -> Result<(String, OAuth2User)>;
+                    code: &str,
+                    verifier: Option<&str>,
+                    nonce: Option<&str>)
+                    -> Result<(OAuth2Token, OAuth2User)>;
+
+    /// Exchanges a refresh token for a new access token, with the refresh token replacing it
+    /// when the provider rotates them and how long the new access token is valid for when the
+    /// provider says. Providers without refresh tokens keep this default.
+    fn refresh(&self,
+               _config: &OAuth2Cfg,
+               _client: &HttpClient,
+               _refresh_token: &str)
+               -> Result<OAuth2Token> {
+        Err(Error::NotSupported)
+    }
+
+    /// Reads the user an access token belongs to, which fails once the provider no longer takes
+    /// the token. Providers that can't be asked keep this default.
+    fn user(&self, _config: &OAuth2Cfg, _client: &HttpClient, _token: &str) -> Result<OAuth2User> {
+        Err(Error::NotSupported)
+    }
+
+    /// Revokes a token at the provider once the user logs out, so that it can't be used from a
+    /// shared workstation afterwards. Providers without a revocation endpoint keep this default,
+    /// which leaves the token to expire.
+    fn revoke(&self, _config: &OAuth2Cfg, _client: &HttpClient, _token: &str) -> Result<()> {
+        Ok(())
+    }
 }
@@ -0,0 +1,158 @@
+// Copyright (c) 2018 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The users a provider's userinfo endpoint returned, kept by access token for the configured
+//! `userinfo_cache_secs` so that validating the same token again doesn't ask the provider. At
+//! most `userinfo_cache_size` tokens are kept, the least recently used are dropped first. The
+//! tokens themselves aren't kept, only their hashes.
+
+use std::{sync::{Mutex,
+                 MutexGuard,
+                 PoisonError},
+          time::{Duration,
+                 Instant}};
+
+use linked_hash_map::LinkedHashMap;
+use sha2::{Digest,
+           Sha256};
+
+use crate::{config::OAuth2Cfg,
+            error::Result,
+            types::OAuth2User};
+
+#[derive(Default)]
+pub struct UserinfoCache {
+    users: Mutex<LinkedHashMap<Vec<u8>, (Instant, OAuth2User)>>,
+}
+
+impl UserinfoCache {
+    /// The user of `token`, read with `fetch` unless it was read less than `userinfo_cache_secs`
+    /// ago. Failures aren't kept.
+    pub fn user<F>(&self, config: &OAuth2Cfg, token: &str, fetch: F) -> Result<OAuth2User>
+        where F: FnOnce() -> Result<OAuth2User>
+    {
+        if config.userinfo_cache_secs == 0 || config.userinfo_cache_size == 0 {
+            return fetch();
+        }
+
+        let key = Sha256::digest(token.as_bytes()).to_vec();
+        let ttl = Duration::from_secs(config.userinfo_cache_secs);
+        {
+            let mut users = self.users();
+            if let Some((fetched_at, user)) = users.get_refresh(&key) {
+                if fetched_at.elapsed() < ttl {
+                    return Ok(user.clone());
+                }
+            }
+            users.remove(&key);
+        }
+
+        // The provider is asked without holding the lock, so one slow request doesn't hold up
+        // the others
+        let user = fetch()?;
+        let mut users = self.users();
+        users.insert(key, (Instant::now(), user.clone()));
+        while users.len() > config.userinfo_cache_size {
+            users.pop_front();
+        }
+        Ok(user)
+    }
+
+    /// Drops the user of `token`, once it is revoked
+    pub fn forget(&self, token: &str) {
+        let key = Sha256::digest(token.as_bytes()).to_vec();
+        self.users().remove(&key);
+    }
+
+    // A panic while the lock was held leaves at worst a user that expires as any other, so a
+    // poisoned lock is used as is
+    fn users(&self) -> MutexGuard<LinkedHashMap<Vec<u8>, (Instant, OAuth2User)>> {
+        self.users.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use std::cell::Cell;
+
+    fn config(ttl: u64, size: usize) -> OAuth2Cfg {
+        OAuth2Cfg { userinfo_cache_secs: ttl,
+                    userinfo_cache_size: size,
+                    ..Default::default() }
+    }
+
+    // Reads the user of `token` from the cache, counting the reads from the provider
+    fn user(cache: &UserinfoCache, config: &OAuth2Cfg, token: &str, fetches: &Cell<u32>) -> String {
+        cache.user(config, token, || {
+                 fetches.set(fetches.get() + 1);
+                 Ok(OAuth2User { id:             token.to_string(),
+                                 username:       format!("user-of-{}", token),
+                                 email:          None,
+                                 email_verified: None,
+                                 groups:         None, })
+             })
+             .unwrap()
+             .username
+    }
+
+    // Makes the users cached look fetched `secs` ago
+    fn age(cache: &UserinfoCache, secs: u64) {
+        for (_, (fetched_at, _)) in cache.users.lock().unwrap().iter_mut() {
+            *fetched_at = fetched_at.checked_sub(Duration::from_secs(secs)).unwrap();
+        }
+    }
+
+    #[test]
+    fn users_are_kept_until_they_expire() {
+        let (cache, config, fetches) = (UserinfoCache::default(), config(60, 10), Cell::new(0));
+        assert_eq!(user(&cache, &config, "at1", &fetches), "user-of-at1");
+        assert_eq!(user(&cache, &config, "at1", &fetches), "user-of-at1");
+        assert_eq!(fetches.get(), 1);
+
+        age(&cache, 60);
+        user(&cache, &config, "at1", &fetches);
+        assert_eq!(fetches.get(), 2);
+    }
+
+    #[test]
+    fn the_least_recently_used_users_are_dropped() {
+        let (cache, config, fetches) = (UserinfoCache::default(), config(60, 2), Cell::new(0));
+        user(&cache, &config, "at1", &fetches);
+        user(&cache, &config, "at2", &fetches);
+        user(&cache, &config, "at1", &fetches);
+        user(&cache, &config, "at3", &fetches);
+        assert_eq!(fetches.get(), 3);
+        assert_eq!(cache.users.lock().unwrap().len(), 2);
+
+        user(&cache, &config, "at1", &fetches);
+        assert_eq!(fetches.get(), 3);
+        user(&cache, &config, "at2", &fetches);
+        assert_eq!(fetches.get(), 4);
+    }
+
+    #[test]
+    fn nothing_is_kept_without_a_ttl_or_after_a_failure() {
+        let (cache, config, fetches) = (UserinfoCache::default(), config(0, 10), Cell::new(0));
+        user(&cache, &config, "at1", &fetches);
+        user(&cache, &config, "at1", &fetches);
+        assert_eq!(fetches.get(), 2);
+
+        let config = self::config(60, 10);
+        let failed = cache.user(&config, "at1", || Err(Error::NotSupported));
+        assert!(failed.is_err());
+        assert!(cache.users.lock().unwrap().is_empty());
+    }
+}
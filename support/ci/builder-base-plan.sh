@@ -53,6 +53,11 @@ do_builder_prepare() {
   export PLAN_VERSION="${pkg_version}/${pkg_release}"
   build_line "Setting PLAN_VERSION=$PLAN_VERSION"
 
+  # Used by the `build.rs` program to set the commit the binaries report
+  PLAN_GIT_SHA="$(git rev-parse HEAD)"
+  export PLAN_GIT_SHA
+  build_line "Setting PLAN_GIT_SHA=$PLAN_GIT_SHA"
+
   # Used by Cargo to use a pristine, isolated directory for all compilation
   export CARGO_TARGET_DIR="$HAB_CACHE_SRC_PATH/$pkg_dirname"
   build_line "Setting CARGO_TARGET_DIR=$CARGO_TARGET_DIR"
@@ -0,0 +1,93 @@
+const expect = require('chai').expect;
+const supertest = require('supertest');
+const request = supertest('http://localhost:9636/v1');
+
+const announcement = {
+  message: 'Maintenance tonight',
+  severity: 'warning',
+  ends_at: '2099-01-01T00:00:00Z'
+};
+
+describe('Announcements API', function () {
+  describe('Retrieving the announcements', function () {
+    let etag;
+
+    it('returns them with an ETag', function (done) {
+      request.get('/announcements')
+        .accept('application/json')
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.body.announcements).to.be.an('array');
+          expect(res.header['cache-control']).to.equal('private, no-cache');
+          etag = res.header['etag'];
+          expect(etag).to.not.be.empty;
+          done(err);
+        });
+    });
+
+    it('returns a 304 while they are unchanged', function (done) {
+      request.get('/announcements')
+        .accept('application/json')
+        .set('If-None-Match', etag)
+        .expect(304)
+        .end(function (err, res) {
+          expect(res.header['etag']).to.equal(etag);
+          expect(res.text).to.be.empty;
+          done(err);
+        });
+    });
+
+    it('returns them again for another ETag', function (done) {
+      request.get('/announcements')
+        .accept('application/json')
+        .set('If-None-Match', '"stale"')
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.body.announcements).to.be.an('array');
+          done(err);
+        });
+    });
+  });
+
+  describe('Managing announcements', function () {
+    it('requires an admin to list them', function (done) {
+      request.get('/admin/announcements')
+        .set('Authorization', global.boboBearer)
+        .expect(403)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('requires an admin to create one', function (done) {
+      request.post('/admin/announcements')
+        .type('application/json')
+        .set('Authorization', global.boboBearer)
+        .send(announcement)
+        .expect(403)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('requires an admin to change one', function (done) {
+      request.put('/admin/announcements/1234')
+        .type('application/json')
+        .set('Authorization', global.boboBearer)
+        .send(announcement)
+        .expect(403)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('requires an admin to expire one', function (done) {
+      request.delete('/admin/announcements/1234')
+        .set('Authorization', global.boboBearer)
+        .expect(403)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+  });
+});
@@ -16,3 +16,4 @@ require('./projects.js');
 require('./jobs.js');
 require('./ext.js');
 require('./misc.js');
+require('./announcements.js');
@@ -543,4 +543,362 @@ describe('Channels API', function () {
         });
     });
   });
+
+  describe('Channel package pins', function () {
+    it('pins the latest release of a package in a channel', function (done) {
+      request.post('/depot/channels/neurosis/pinned')
+        .set('Authorization', global.boboBearer)
+        .expect(201)
+        .end(function (err, res) {
+          request.put('/depot/channels/neurosis/pinned/pkgs/testapp/0.1.3/20171205003213/promote')
+            .set('Authorization', global.boboBearer)
+            .expect(200)
+            .end(function (err, res) {
+              request.put('/depot/channels/neurosis/pinned/pkgs/testapp/pin')
+                .set('Authorization', global.boboBearer)
+                .expect(201)
+                .end(function (err, res) {
+                  expect(res.body.ident).to.equal('neurosis/testapp/0.1.3/20171205003213');
+                  expect(res.body.pinned_by_name).to.equal('bobo');
+                  done(err);
+                });
+            });
+        });
+    });
+
+    it('returns conflict when the package is already pinned', function (done) {
+      request.put('/depot/channels/neurosis/pinned/pkgs/testapp/pin')
+        .set('Authorization', global.boboBearer)
+        .expect(409)
+        .end(function (err, res) {
+          expect(res.body.ident).to.equal('neurosis/testapp/0.1.3/20171205003213');
+          done(err);
+        });
+    });
+
+    it('marks the pinned release in the channel listing', function (done) {
+      request.get('/depot/channels/neurosis/pinned/pkgs')
+        .set('Authorization', global.boboBearer)
+        .accept('application/json')
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.body.data[0].release).to.equal('20171205003213');
+          expect(res.body.data[0].pinned).to.equal(true);
+          done(err);
+        });
+    });
+
+    it('rejects demoting the pinned release', function (done) {
+      request.put('/depot/channels/neurosis/pinned/pkgs/testapp/0.1.3/20171205003213/demote')
+        .set('Authorization', global.boboBearer)
+        .expect(409)
+        .end(function (err, res) {
+          expect(res.body.ident).to.equal('neurosis/testapp/0.1.3/20171205003213');
+          done(err);
+        });
+    });
+
+    it('rejects promoting a newer release', function (done) {
+      request.put('/depot/channels/neurosis/pinned/pkgs/testapp/0.1.3/20171206004121/promote')
+        .set('Authorization', global.boboBearer)
+        .expect(409)
+        .end(function (err, res) {
+          expect(res.body.ident).to.equal('neurosis/testapp/0.1.3/20171205003213');
+          done(err);
+        });
+    });
+
+    it('rejects deleting the pinned release', function (done) {
+      request.delete('/depot/pkgs/neurosis/testapp/0.1.3/20171205003213')
+        .set('Authorization', global.boboBearer)
+        .expect(409)
+        .end(function (err, res) {
+          expect(res.body.ident).to.equal('neurosis/testapp/0.1.3/20171205003213');
+          done(err);
+        });
+    });
+
+    it('rejects deleting the channel', function (done) {
+      request.delete('/depot/channels/neurosis/pinned')
+        .set('Authorization', global.boboBearer)
+        .expect(409)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('requires origin membership to lift a pin', function (done) {
+      request.delete('/depot/channels/neurosis/pinned/pkgs/testapp/pin')
+        .set('Authorization', global.mystiqueBearer)
+        .expect(401)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('lifts the pin', function (done) {
+      request.delete('/depot/channels/neurosis/pinned/pkgs/testapp/pin')
+        .set('Authorization', global.boboBearer)
+        .expect(204)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('allows demoting the release once the pin is lifted', function (done) {
+      request.put('/depot/channels/neurosis/pinned/pkgs/testapp/0.1.3/20171205003213/demote')
+        .set('Authorization', global.boboBearer)
+        .expect(200)
+        .end(function (err, res) {
+          request.delete('/depot/channels/neurosis/pinned')
+            .set('Authorization', global.boboBearer)
+            .expect(200)
+            .end(function (err, res) {
+              done(err);
+            });
+        });
+    });
+  });
+
+  describe('Rolling channels', function () {
+    it('rejects making the unstable channel rolling', function (done) {
+      request.patch('/depot/channels/neurosis/unstable')
+        .set('Authorization', global.boboBearer)
+        .send({ rolling: true })
+        .expect(403)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('makes a channel rolling', function (done) {
+      request.post('/depot/channels/neurosis/rolling')
+        .set('Authorization', global.boboBearer)
+        .expect(201)
+        .end(function (err, res) {
+          request.patch('/depot/channels/neurosis/rolling')
+            .set('Authorization', global.boboBearer)
+            .send({ rolling: true })
+            .expect(200)
+            .end(function (err, res) {
+              expect(res.body.rolling).to.equal(true);
+              expect(res.body.demoted).to.deep.equal([]);
+              done(err);
+            });
+        });
+    });
+
+    it('keeps a single release of each package on a bulk promotion', function (done) {
+      request.put('/depot/channels/neurosis/unstable/pkgs/promote?channel=rolling')
+        .set('Authorization', global.boboBearer)
+        .expect(200)
+        .end(function (err, res) {
+          request.get('/depot/channels/neurosis/rolling/pkgs/testapp')
+            .set('Authorization', global.boboBearer)
+            .accept('application/json')
+            .expect(200)
+            .end(function (err, res) {
+              expect(res.body.total_count).to.equal(1);
+              expect(res.body.data.length).to.equal(1);
+              done(err);
+            });
+        });
+    });
+
+    it('records the superseded releases in the events feed', function (done) {
+      request.get('/depot/channels/neurosis/rolling/feed.atom')
+        .set('Authorization', global.boboBearer)
+        .buffer(true)
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.text).to.contain('urn:habitat:channel:neurosis:rolling:supersede:');
+          expect(res.text).to.contain('were superseded in rolling by bobo and demoted');
+          request.delete('/depot/channels/neurosis/rolling')
+            .set('Authorization', global.boboBearer)
+            .expect(200)
+            .end(function (err2, res2) {
+              done(err || err2);
+            });
+        });
+    });
+  });
+
+  describe('Ephemeral channels', function () {
+    it('creates an ephemeral channel', function (done) {
+      request.post('/depot/channels/neurosis/feature-a?ephemeral=true&ttl_days=3')
+        .set('Authorization', global.boboBearer)
+        .expect(201)
+        .end(function (err, res) {
+          expect(res.body.name).to.equal('feature-a');
+          expect(res.body.channel_type).to.equal('ephemeral');
+          expect(res.body.expires_at).to.not.be.null;
+          global.channelFeatureA = res.body;
+          done(err);
+        });
+    });
+
+    it('requires authentication to renew a channel', function (done) {
+      request.post('/depot/channels/neurosis/feature-a/renew')
+        .expect(401)
+        .end(function (err, res) {
+          expect(res.text).to.be.empty;
+          done(err);
+        });
+    });
+
+    it('requires origin membership to renew a channel', function (done) {
+      request.post('/depot/channels/neurosis/feature-a/renew')
+        .set('Authorization', global.mystiqueBearer)
+        .expect(401)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('rejects a ttl of zero days', function (done) {
+      request.post('/depot/channels/neurosis/feature-a/renew?ttl_days=0')
+        .set('Authorization', global.boboBearer)
+        .expect(422)
+        .end(function (err, res) {
+          expect(res.body.error).to.equal('ttl_days must be at least 1');
+          done(err);
+        });
+    });
+
+    it('rejects renewing a standard channel', function (done) {
+      request.post('/depot/channels/neurosis/bar/renew')
+        .set('Authorization', global.boboBearer)
+        .expect(422)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('pushes the expiry back', function (done) {
+      request.post('/depot/channels/neurosis/feature-a/renew?ttl_days=7')
+        .set('Authorization', global.boboBearer)
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.body.channel_type).to.equal('ephemeral');
+          expect(new Date(res.body.expires_at)).to.be.above(new Date(global.channelFeatureA.expires_at));
+          done(err);
+        });
+    });
+
+    it('promotes releases no ad-hoc group built into stable without a warning', function (done) {
+      request.put('/depot/channels/neurosis/feature-a/pkgs/testapp/0.1.3/20171205003213/promote')
+        .set('Authorization', global.boboBearer)
+        .expect(200)
+        .end(function (err, res) {
+          request.put('/depot/channels/neurosis/feature-a/pkgs/promote?channel=stable')
+            .set('Authorization', global.boboBearer)
+            .expect(200)
+            .end(function (err2, res2) {
+              expect(res2.headers).to.not.have.property('warning');
+              request.put('/depot/channels/neurosis/stable/pkgs/testapp/0.1.3/20171205003213/demote')
+                .set('Authorization', global.boboBearer)
+                .expect(200)
+                .end(function (err3, res3) {
+                  done(err || err2 || err3);
+                });
+            });
+        });
+    });
+
+    // The group has to promote its builds into the ephemeral channel, and the jobs we submit
+    // don't finish in this environment, as with the job group promotion tests.
+    it('warns when promoting a release an ad-hoc group built into stable', function (done) {
+      this.skip();
+
+      request.post(`/jobs/group/${global.neurosisJobGroup.id}/promote/feature-a`)
+        .set('Authorization', global.boboBearer)
+        .send({ idents: ['neurosis/testapp'] })
+        .expect(204)
+        .end(function (err, res) {
+          request.put('/depot/channels/neurosis/feature-a/pkgs/promote?channel=stable')
+            .set('Authorization', global.boboBearer)
+            .expect(200)
+            .end(function (err2, res2) {
+              expect(res2.headers.warning).to.contain(`built by ad-hoc group ${global.neurosisJobGroup.id}`);
+              done(err || err2);
+            });
+        });
+    });
+
+    it('deletes the channel', function (done) {
+      request.delete('/depot/channels/neurosis/feature-a')
+        .set('Authorization', global.boboBearer)
+        .expect(200)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+  });
+
+  describe('Channel sync', function () {
+    it('returns not found for an unknown channel', function (done) {
+      request.post('/depot/channels/neurosis/nosuchchannel/sync_diff?target=x86_64-linux')
+        .set('Authorization', global.boboBearer)
+        .type('application/json')
+        .send({ inventory: [] })
+        .expect(404)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('rejects an entry outside the chunk', function (done) {
+      request.post('/depot/channels/neurosis/unstable/sync_diff?target=x86_64-linux')
+        .set('Authorization', global.boboBearer)
+        .type('application/json')
+        .send({
+          inventory: [{ ident: 'neurosis/testapp/0.1.3/20171205003213', checksum: 'aa' }],
+          until: 'neurosis/aaa'
+        })
+        .expect(400)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('answers a hash with the channel hash', function (done) {
+      request.post('/depot/channels/neurosis/unstable/sync_diff?target=x86_64-linux')
+        .set('Authorization', global.boboBearer)
+        .type('application/json')
+        .send({ inventory_hash: '0'.repeat(64) })
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.body.unchanged).to.equal(false);
+          expect(res.body.inventory_hash).to.have.lengthOf(64);
+          expect(res.body.missing).to.be.empty;
+          done(err);
+        });
+    });
+
+    it('lists the releases an empty mirror is missing', function (done) {
+      request.post('/depot/channels/neurosis/unstable/sync_diff?target=x86_64-linux')
+        .set('Authorization', global.boboBearer)
+        .type('application/json')
+        .send({ inventory: [] })
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.body.unchanged).to.equal(false);
+          expect(res.body.missing).to.include('neurosis/testapp/0.1.3/20171205003213');
+          expect(res.body).to.not.have.property('inventory_hash');
+          done(err);
+        });
+    });
+
+    it('only compares the releases in the range of a chunk', function (done) {
+      request.post('/depot/channels/neurosis/unstable/sync_diff?target=x86_64-linux')
+        .set('Authorization', global.boboBearer)
+        .type('application/json')
+        .send({ inventory: [], after: 'neurosis/testapp/0.1.3/20171205003213' })
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.body.missing).to.not.include('neurosis/testapp/0.1.3/20171205003213');
+          done(err);
+        });
+    });
+  });
 });
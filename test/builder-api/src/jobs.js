@@ -64,6 +64,55 @@ describe('Jobs API', function () {
     });
   });
 
+  describe('Dry runs and approvals of job groups', function () {
+    it('describes the group of a dry run without creating it', function (done) {
+      request.post('/depot/pkgs/schedule/neurosis/testapp')
+        .query({ dry_run: true })
+        .accept('application/json')
+        .set('Authorization', global.boboBearer)
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.body.project_name).to.equal('neurosis/testapp');
+          expect(res.body.projects[0].name).to.equal('neurosis/testapp');
+          expect(res.body.project_count).to.equal(1);
+          done(err);
+        });
+    });
+
+    it('rejects flags that are not true or false', function (done) {
+      request.post('/depot/pkgs/schedule/neurosis/testapp')
+        .query({ dry_run: 1 })
+        .accept('application/json')
+        .set('Authorization', global.boboBearer)
+        .expect(400)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('requires an admin to approve a group', function (done) {
+      request.post(`/admin/groups/${global.neurosisJobGroup.id}/approve`)
+        .type('application/json')
+        .set('Authorization', global.boboBearer)
+        .send({ approved: true })
+        .expect(403)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('requires a group id that is a u64 to approve', function (done) {
+      request.post('/admin/groups/haha/approve')
+        .type('application/json')
+        .set('Authorization', global.boboBearer)
+        .send({ approved: true })
+        .expect(400)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+  });
+
   describe('Retrieving information about a job group', function () {
     it('requires a group id that is a u64', function (done) {
       request.get('/depot/pkgs/schedule/haha')
@@ -52,4 +52,75 @@ describe('Miscellanenous API', function () {
         });
     });
   });
+
+  describe('Reporting installs', function () {
+    const report = {
+      ident: 'neurosis/testapp/0.1.3/20171205003213',
+      target: 'x86_64-linux',
+      success: true,
+      site_id: '3f1e0c4a9b7d5e26'
+    };
+
+    it('accepts a valid report', function (done) {
+      request.post('/telemetry/install')
+        .type('application/json')
+        .send(report)
+        .expect(202)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('refuses reports with fields beyond those kept', function (done) {
+      request.post('/telemetry/install')
+        .type('application/json')
+        .send(Object.assign({ hostname: 'build-01' }, report))
+        .expect(400)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('refuses site ids that are not opaque', function (done) {
+      request.post('/telemetry/install')
+        .type('application/json')
+        .send(Object.assign({}, report, { site_id: 'build-01.example.com' }))
+        .expect(400)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('refuses bodies over the size cap', function (done) {
+      request.post('/telemetry/install')
+        .type('application/json')
+        .send(Object.assign({}, report, { padding: 'x'.repeat(4096) }))
+        .expect(413)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('shows the install stats to origin members only', function (done) {
+      request.get('/depot/pkgs/neurosis/testapp/stats')
+        .set('Authorization', global.mystiqueBearer)
+        .expect(403)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('returns the install stats of a package', function (done) {
+      request.get('/depot/pkgs/neurosis/testapp/stats?days=7')
+        .set('Authorization', global.boboBearer)
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.body.origin).to.equal('neurosis');
+          expect(res.body.name).to.equal('testapp');
+          expect(res.body.days).to.equal(7);
+          expect(res.body.installs).to.be.an('array');
+          done(err);
+        });
+    });
+  });
 });
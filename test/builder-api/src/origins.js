@@ -127,6 +127,30 @@ describe('Origin API', function () {
         });
     });
 
+    it('reports the field of an invalid value', function (done) {
+      request.put('/depot/origins/neurosis')
+        .set('Authorization', global.boboBearer)
+        .send({ 'default_package_visibility': 5 })
+        .expect(422)
+        .end(function (err, res) {
+          expect(res.body.errors.length).to.equal(1);
+          expect(res.body.errors[0].path).to.equal('default_package_visibility');
+          expect(res.body.errors[0].received).to.equal('5');
+          done(err);
+        });
+    });
+
+    it('warns about unknown fields', function (done) {
+      request.put('/depot/origins/neurosis')
+        .set('Authorization', global.boboBearer)
+        .send({ 'default_package_visibility': 'public', 'colour': 'red' })
+        .expect(204)
+        .end(function (err, res) {
+          expect(res.headers.warning).to.equal('299 - "unknown field colour ignored"');
+          done(err);
+        });
+    });
+
     it('reflects the changes when viewing it again', function (done) {
       request.get('/depot/origins/neurosis')
         .expect(200)
@@ -149,6 +173,23 @@ describe('Origin API', function () {
         });
     });
 
+    it('rejects unknown fields', function (done) {
+      request.post('/depot/origins/neurosis/secret')
+        .set('Authorization', global.boboBearer)
+        .send({ 'name': 'foo', 'valeu': 'bar' })
+        .expect(422)
+        .end(function (err, res) {
+          expect(res.body).to.deep.equal({
+            errors: [{
+              path: 'valeu',
+              received: '"bar"',
+              message: 'unknown field'
+            }]
+          });
+          done(err);
+        });
+    });
+
     // TODO - add a successful creation test
   });
 
@@ -186,6 +227,98 @@ describe('Origin API', function () {
     // TODO - add a successful deletion test
   });
 
+  describe('Origin artifact encryption', function () {
+    it('requires a member of the origin', function (done) {
+      request.get('/depot/origins/neurosis/artifact_encryption')
+        .set('Authorization', global.mystiqueBearer)
+        .expect(403)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('is not set by default', function (done) {
+      request.get('/depot/origins/neurosis/artifact_encryption')
+        .set('Authorization', global.boboBearer)
+        .expect(404)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('rejects a key the key provider does not hold', function (done) {
+      request.put('/depot/origins/neurosis/artifact_encryption')
+        .set('Authorization', global.boboBearer)
+        .send({ 'key_id': 'no-such-key' })
+        .expect(422)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('rejects a malformed key id', function (done) {
+      request.put('/depot/origins/neurosis/artifact_encryption')
+        .set('Authorization', global.boboBearer)
+        .send({ 'key_id': '../../etc/passwd' })
+        .expect(422)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('can be removed when it is not set', function (done) {
+      request.delete('/depot/origins/neurosis/artifact_encryption')
+        .set('Authorization', global.boboBearer)
+        .expect(204)
+        .end(function (err, res) {
+          expect(res.text).to.be.empty;
+          done(err);
+        });
+    });
+  });
+
+  describe('Origin member activity', function () {
+    it('requires the origin owner', function (done) {
+      request.get('/depot/origins/neurosis/members/activity')
+        .set('Authorization', global.mystiqueBearer)
+        .expect(403)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('lists every member of the origin', function (done) {
+      request.get('/depot/origins/neurosis/members/activity')
+        .set('Authorization', global.boboBearer)
+        .accept('application/json')
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.body.origin).to.equal('neurosis');
+          expect(res.body.members.length).to.equal(1);
+          expect(res.body.members[0].account_name).to.equal('bobo');
+          expect(res.body.members[0].role).to.equal('owner');
+          expect(res.body.members[0].last_upload_at).to.equal(null);
+          done(err);
+        });
+    });
+
+    it('returns the report as CSV when asked for it', function (done) {
+      request.get('/depot/origins/neurosis/members/activity')
+        .set('Authorization', global.boboBearer)
+        .accept('text/csv')
+        .expect(200)
+        .expect('Content-Type', /text\/csv/)
+        .end(function (err, res) {
+          const rows = res.text.split('\r\n');
+          expect(rows[0]).to.equal('account_id,account_name,role,member_since,last_authenticated_at,' +
+            'last_upload_at,last_promotion_at,last_job_submitted_at,last_active_at');
+          expect(rows[1]).to.match(/^\d+,bobo,owner,/);
+          expect(rows.length).to.equal(3);
+          done(err);
+        });
+    });
+  });
+
   describe('Origin deletion', function () {
     it('requires authentication', function (done) {
       request.delete('/depot/origins/umbrella')
@@ -121,6 +121,94 @@ describe('Working with packages', function () {
         });
     });
 
+    it('requires a member of the origin to set its upload policy', function (done) {
+      request.put('/depot/origins/neurosis')
+        .set('Authorization', global.mystiqueBearer)
+        .send({ 'upload_policy': { 'blocked_licenses': ['Apache-2.0'] } })
+        .expect(403)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('rejects an upload policy with a malformed license', function (done) {
+      request.put('/depot/origins/neurosis')
+        .set('Authorization', global.boboBearer)
+        .send({ 'upload_policy': { 'blocked_licenses': ['Apache 2.0'] } })
+        .expect(422)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('sets the upload policy of the origin', function (done) {
+      request.put('/depot/origins/neurosis')
+        .set('Authorization', global.boboBearer)
+        .send({ 'upload_policy': { 'blocked_licenses': ['Apache-2.0'] } })
+        .expect(204)
+        .end(function (err, res) {
+          expect(res.text).to.be.empty;
+          done(err);
+        });
+    });
+
+    it('returns the upload policy with the origin', function (done) {
+      request.get('/depot/origins/neurosis')
+        .type('application/json')
+        .accept('application/json')
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.body.upload_policy.blocked_licenses).to.deep.equal(['Apache-2.0']);
+          done(err);
+        });
+    });
+
+    it('requires an admin to list upload policy violations', function (done) {
+      request.get('/depot/origins/neurosis/upload_policy/violations')
+        .set('Authorization', global.boboBearer)
+        .expect(403)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('rejects an upload that violates the upload policy', function (done) {
+      request.post(`/depot/pkgs/neurosis/testapp/0.1.3/${release1}`)
+        .set('Authorization', global.boboBearer)
+        .set('Content-Length', file1.length)
+        .query({ checksum: '3138777020e7bb621a510b19c2f2630deee9b34ac11f1c2a0524a44eb977e4a8' })
+        .send(file1)
+        .expect(422)
+        .end(function (err, res) {
+          expect(res.body.violations[0].policy).to.equal('blocked_licenses');
+          done(err);
+        });
+    });
+
+    it('rejects an upload with an invalid policy override token', function (done) {
+      request.post(`/depot/pkgs/neurosis/testapp/0.1.3/${release1}`)
+        .set('Authorization', global.boboBearer)
+        .set('X-Builder-Policy-Override', 'not-the-token')
+        .set('Content-Length', file1.length)
+        .query({ checksum: '3138777020e7bb621a510b19c2f2630deee9b34ac11f1c2a0524a44eb977e4a8' })
+        .send(file1)
+        .expect(422)
+        .end(function (err, res) {
+          expect(res.body.violations[0].policy).to.equal('blocked_licenses');
+          done(err);
+        });
+    });
+
+    it('clears the upload policy of the origin', function (done) {
+      request.put('/depot/origins/neurosis')
+        .set('Authorization', global.boboBearer)
+        .send({ 'upload_policy': {} })
+        .expect(204)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
     it('allows authenticated users to upload packages', function (done) {
       request.post(`/depot/pkgs/neurosis/testapp/0.1.3/${release1}`)
         .set('Authorization', global.boboBearer)
@@ -173,6 +261,39 @@ describe('Working with packages', function () {
         });
     });
 
+    it('requires dependencies in the depot when the origin is strict about them', function (done) {
+      request.put('/depot/origins/neurosis')
+        .set('Authorization', global.boboBearer)
+        .send({ 'upload_policy': { 'strict_dependency_validation': true } })
+        .expect(204)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('rejects an upload with missing dependencies', function (done) {
+      request.post(`/depot/pkgs/neurosis/testapp/0.1.13/${release10}`)
+        .set('Authorization', global.boboBearer)
+        .set('Content-Length', file10.length)
+        .query({ checksum: '583bf168a02b632af5fce26c06d5f29ae9af011750284595df977160be930db7' })
+        .send(file10)
+        .expect(422)
+        .end(function (err, res) {
+          expect(res.body.missing_dependencies).to.deep.equal(['core/glibc/2.27/20190115002733']);
+          done(err);
+        });
+    });
+
+    it('stops requiring dependencies in the depot', function (done) {
+      request.put('/depot/origins/neurosis')
+        .set('Authorization', global.boboBearer)
+        .send({ 'upload_policy': {} })
+        .expect(204)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
     it('uploads a fifth package', function (done) {
       request.post(`/depot/pkgs/neurosis/testapp/0.1.13/${release10}`)
         .set('Authorization', global.boboBearer)
@@ -482,6 +603,7 @@ describe('Working with packages', function () {
           expect(res.body.ident.name).to.equal('testapp');
           expect(res.body.ident.version).to.equal('0.1.13');
           expect(res.body.ident.release).to.equal(release10);
+          expect(res.body.dependency_report.missing).to.deep.equal(['core/glibc/2.27/20190115002733']);
           done(err);
         });
     });
@@ -705,6 +827,40 @@ describe('Working with packages', function () {
         });
     });
 
+    it('answers a HEAD request with the size of the package', function (done) {
+      request.head(`/depot/pkgs/neurosis/testapp/0.1.3/${release2}/download`)
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.header['content-length']).to.equal('1569');
+          expect(res.header['x-filename']).to.equal(`neurosis-testapp-0.1.3-${release2}-x86_64-linux.hart`);
+          expect(res.text).to.be.undefined;
+          done(err);
+        });
+    });
+
+    it('downloads a range of a package', function (done) {
+      request.get(`/depot/pkgs/neurosis/testapp/0.1.3/${release2}/download`)
+        .set('Range', 'bytes=0-9')
+        .expect(206)
+        .buffer()
+        .parse(binaryParser)
+        .end(function (err, res) {
+          expect(res.header['content-range']).to.equal('bytes 0-9/1569');
+          expect(res.body.length).to.equal(10);
+          done(err);
+        });
+    });
+
+    it('rejects a range past the end of a package', function (done) {
+      request.get(`/depot/pkgs/neurosis/testapp/0.1.3/${release2}/download`)
+        .set('Range', 'bytes=999999999-')
+        .expect(416)
+        .end(function (err, res) {
+          expect(res.header['content-range']).to.equal('bytes */1569');
+          done(err);
+        });
+    });
+
     it('toggles the privacy setting for a package', function (done) {
       request.patch(`/depot/pkgs/neurosis/testapp/0.1.3/${release2}/private`)
         .set('Authorization', global.boboBearer)
@@ -754,6 +910,155 @@ describe('Working with packages', function () {
     });
   });
 
+  describe('Deprecating packages', function () {
+    it('requires authentication to deprecate a package', function (done) {
+      request.put('/depot/pkgs/neurosis/testapp2/deprecation')
+        .type('application/json')
+        .send({ message: 'Use neurosis/testapp instead' })
+        .expect(401)
+        .end(function (err, res) {
+          expect(res.text).to.be.empty;
+          done(err);
+        });
+    });
+
+    it('requires the origin owner to deprecate a package', function (done) {
+      request.put('/depot/pkgs/neurosis/testapp2/deprecation')
+        .set('Authorization', global.mystiqueBearer)
+        .type('application/json')
+        .send({ message: 'Use neurosis/testapp instead' })
+        .expect(403)
+        .end(function (err, res) {
+          expect(res.text).to.be.empty;
+          done(err);
+        });
+    });
+
+    it('rejects an empty message', function (done) {
+      request.put('/depot/pkgs/neurosis/testapp2/deprecation')
+        .set('Authorization', global.boboBearer)
+        .type('application/json')
+        .send({ message: '  ' })
+        .expect(422)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('rejects a replacement that names the package itself', function (done) {
+      request.put('/depot/pkgs/neurosis/testapp2/deprecation')
+        .set('Authorization', global.boboBearer)
+        .type('application/json')
+        .send({ message: 'Use neurosis/testapp instead', replacement: 'neurosis/testapp2' })
+        .expect(422)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('does not deprecate a package that does not exist', function (done) {
+      request.put('/depot/pkgs/neurosis/nosuchapp/deprecation')
+        .set('Authorization', global.boboBearer)
+        .type('application/json')
+        .send({ message: 'Use neurosis/testapp instead' })
+        .expect(404)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('allows the origin owner to deprecate a package', function (done) {
+      request.put('/depot/pkgs/neurosis/testapp2/deprecation')
+        .set('Authorization', global.boboBearer)
+        .type('application/json')
+        .accept('application/json')
+        .send({ message: 'Use neurosis/testapp instead', replacement: 'neurosis/testapp' })
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.body.origin).to.equal('neurosis');
+          expect(res.body.name).to.equal('testapp2');
+          expect(res.body.message).to.equal('Use neurosis/testapp instead');
+          expect(res.body.replacement).to.equal('neurosis/testapp');
+          done(err);
+        });
+    });
+
+    it('includes the notice when showing the package', function (done) {
+      request.get('/depot/pkgs/neurosis/testapp2/latest')
+        .type('application/json')
+        .accept('application/json')
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.body.ident.name).to.equal('testapp2');
+          expect(res.body.deprecation.message).to.equal('Use neurosis/testapp instead');
+          expect(res.body.deprecation.replacement).to.equal('neurosis/testapp');
+          done(err);
+        });
+    });
+
+    it('sends the notice with downloads of the package', function (done) {
+      request.get(`/depot/pkgs/neurosis/testapp2/v1.2.3-master/${release5}/download`)
+        .expect(200)
+        .buffer()
+        .parse(binaryParser)
+        .end(function (err, res) {
+          expect(res.header['x-package-deprecated']).to.equal('Use neurosis/testapp instead; replacement=neurosis/testapp');
+          done(err);
+        });
+    });
+
+    it('does not send the notice with downloads of other packages', function (done) {
+      request.get(`/depot/pkgs/neurosis/testapp/0.1.4/${release3}/download`)
+        .expect(200)
+        .buffer()
+        .parse(binaryParser)
+        .end(function (err, res) {
+          expect(res.header['x-package-deprecated']).to.be.undefined;
+          done(err);
+        });
+    });
+
+    it('requires the origin owner to clear a deprecation', function (done) {
+      request.delete('/depot/pkgs/neurosis/testapp2/deprecation')
+        .set('Authorization', global.mystiqueBearer)
+        .expect(403)
+        .end(function (err, res) {
+          expect(res.text).to.be.empty;
+          done(err);
+        });
+    });
+
+    it('allows the origin owner to clear a deprecation', function (done) {
+      request.delete('/depot/pkgs/neurosis/testapp2/deprecation')
+        .set('Authorization', global.boboBearer)
+        .expect(204)
+        .end(function (err, res) {
+          expect(res.text).to.be.empty;
+          done(err);
+        });
+    });
+
+    it('does not clear a deprecation twice', function (done) {
+      request.delete('/depot/pkgs/neurosis/testapp2/deprecation')
+        .set('Authorization', global.boboBearer)
+        .expect(404)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('no longer shows the notice once cleared', function (done) {
+      request.get('/depot/pkgs/neurosis/testapp2/latest')
+        .type('application/json')
+        .accept('application/json')
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.body.deprecation).to.be.undefined;
+          done(err);
+        });
+    });
+  });
+
   describe('Behavior of non-standard version packages', function () {
     it('Uploads odd version package11', function (done) {
       request.post(`/depot/pkgs/neurosis/oddversion1/R16B/${ov11release}`)
@@ -109,6 +109,100 @@ describe('Profile API', function () {
       });
   });
 
+  describe('Listing security events', function () {
+    it('requires authentication', function (done) {
+      request.get('/user/security_events')
+        .accept('application/json')
+        .expect(401)
+        .end(function (err, res) {
+          expect(res.text).to.be.empty;
+          done(err);
+        });
+    });
+
+    it('lists the token creation with the address it came from', function (done) {
+      request.get('/user/security_events')
+        .set('Authorization', global.boboBearer)
+        .accept('application/json')
+        .expect(200)
+        .end(function (err, res) {
+          let created = res.body.data.filter(e => e.kind === 'token_created');
+          expect(created.length).to.equal(1);
+          expect(created[0].token_id).to.equal(global.boboTokenId);
+          expect(created[0]).to.not.have.property('token');
+          expect(created[0].ip).to.be.oneOf(['127.0.0.1', '::1']);
+          done(err);
+        });
+    });
+
+    it('does not list the events of other accounts', function (done) {
+      request.get('/user/security_events')
+        .set('Authorization', global.mystiqueBearer)
+        .accept('application/json')
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.body.data.filter(e => e.token_id === global.boboTokenId)).to.be.empty;
+          done(err);
+        });
+    });
+  });
+
+  describe('Exporting account data', function () {
+    it('requires authentication', function (done) {
+      request.post('/user/export')
+        .accept('application/json')
+        .expect(401)
+        .end(function (err, res) {
+          expect(res.text).to.be.empty;
+          done(err);
+        });
+    });
+
+    it('starts an export to poll', function (done) {
+      request.post('/user/export')
+        .set('Authorization', global.boboBearer)
+        .accept('application/json')
+        .expect(202)
+        .end(function (err, res) {
+          expect(res.body.state).to.equal('pending');
+          expect(res.body).to.not.have.property('object_key');
+          global.boboExportId = res.body.id;
+          done(err);
+        });
+    });
+
+    it('does not start a second export while one is pending', function (done) {
+      request.post('/user/export')
+        .set('Authorization', global.boboBearer)
+        .accept('application/json')
+        .expect(409)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('shows the export to its account', function (done) {
+      request.get('/user/export/' + global.boboExportId)
+        .set('Authorization', global.boboBearer)
+        .accept('application/json')
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.body.id).to.equal(global.boboExportId);
+          done(err);
+        });
+    });
+
+    it('does not show the export to other accounts', function (done) {
+      request.get('/user/export/' + global.boboExportId)
+        .set('Authorization', global.mystiqueBearer)
+        .accept('application/json')
+        .expect(404)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+  });
+
   describe('Revoking an access token', function () {
     it('requires authentication', function (done) {
       request.delete('/profile/access-tokens/' + global.boboTokenId)
@@ -133,4 +227,92 @@ describe('Profile API', function () {
         done(err);
       });
   });
+
+  describe('Default origin', function () {
+    it('requires authentication to use ~', function (done) {
+      request.get('/depot/pkgs/~/testapp/latest')
+        .expect(401)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('rejects ~ when no default origin is set', function (done) {
+      request.get('/depot/pkgs/~/testapp/latest')
+        .set('Authorization', global.boboBearer)
+        .expect(400)
+        .end(function (err, res) {
+          expect(res.text).to.contain('default_origin');
+          done(err);
+        });
+    });
+
+    it('rejects a default origin the account is not a member of', function (done) {
+      request.patch('/profile')
+        .set('Authorization', global.boboBearer)
+        .type('application/json')
+        .send({ default_origin: 'nosuchorigin' })
+        .expect(422)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('sets the default origin', function (done) {
+      request.patch('/profile')
+        .set('Authorization', global.boboBearer)
+        .type('application/json')
+        .send({ default_origin: 'neurosis' })
+        .expect(200)
+        .end(function (err, res) {
+          request.get('/profile')
+            .set('Authorization', global.boboBearer)
+            .accept('application/json')
+            .expect(200)
+            .end(function (err, res) {
+              expect(res.body.default_origin).to.equal('neurosis');
+              done(err);
+            });
+        });
+    });
+
+    it('resolves ~ to the default origin', function (done) {
+      request.get('/depot/pkgs/~/testapp/latest')
+        .set('Authorization', global.boboBearer)
+        .accept('application/json')
+        .expect(200)
+        .end(function (err, res) {
+          expect(res.body.ident.origin).to.equal('neurosis');
+          expect(res.body.ident.name).to.equal('testapp');
+          done(err);
+        });
+    });
+
+    it('resolves ~ to the caller\'s own default origin', function (done) {
+      request.get('/depot/pkgs/~/testapp/latest')
+        .set('Authorization', global.mystiqueBearer)
+        .expect(400)
+        .end(function (err, res) {
+          done(err);
+        });
+    });
+
+    it('clears the default origin', function (done) {
+      request.patch('/profile')
+        .set('Authorization', global.boboBearer)
+        .type('application/json')
+        .send({ default_origin: '' })
+        .expect(200)
+        .end(function (err, res) {
+          request.get('/profile')
+            .set('Authorization', global.boboBearer)
+            .accept('application/json')
+            .expect(200)
+            .end(function (err, res) {
+              expect(res.body.default_origin).to.equal(null);
+              done(err);
+            });
+        });
+    });
+  });
 });
@@ -24,4 +24,25 @@ describe('Authenticate API', function() {
         });
     });
   });
+
+  describe('Logging out', function() {
+    it('requires authentication', function(done) {
+      request.post('/logout')
+        .expect(401)
+        .end(function(err, res) {
+          expect(res.text).to.be.empty;
+          done(err);
+        });
+    });
+
+    it('ends the session without waiting on the provider', function(done) {
+      request.post('/logout')
+        .set('Authorization', global.weskerBearer)
+        .expect(204)
+        .end(function(err, res) {
+          expect(res.text).to.be.empty;
+          done(err);
+        });
+    });
+  });
 });
@@ -13,7 +13,7 @@ clean_test_artifacts() {
 
   # clean origins
   local origins origin_tables
-  origin_tables=( origin_integrations origin_project_integrations origin_secrets origin_private_encryption_keys origin_public_encryption_keys origin_members origin_channels origin_invitations origin_packages origin_projects origin_public_keys origin_secret_keys audit_package audit_package_group)
+  origin_tables=( origin_integrations origin_project_integrations origin_secrets origin_private_encryption_keys origin_public_encryption_keys origin_members origin_channels origin_invitations origin_packages origin_projects origin_public_keys origin_secret_keys origin_package_deprecations audit_package audit_package_group audit_package_deprecation origin_channel_package_pins audit_package_pin audit_channel)
   sql=
 
   for origin in "${origins[@]}"; do
@@ -34,6 +34,8 @@ clean_test_artifacts() {
   sql=
 
   for user in "${users[@]}"; do
+    sql+="DELETE FROM account_security_events WHERE account_id IN (SELECT id FROM accounts WHERE name='$user');"
+    sql+="DELETE FROM account_known_ips WHERE account_id IN (SELECT id FROM accounts WHERE name='$user');"
     sql+="DELETE FROM accounts WHERE name='$user';"
   done
 